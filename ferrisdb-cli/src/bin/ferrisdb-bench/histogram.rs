@@ -0,0 +1,245 @@
+//! Latency histogram with logarithmic buckets
+//!
+//! Values up to 16 get a bucket each; above that, every power of two is
+//! split into 8 buckets, so a bucket is at most 12.5% wider than its lower
+//! bound. That keeps percentiles accurate to a few percent in 496 counters,
+//! whatever the range of the values.
+
+use std::fmt;
+
+/// Buckets per power of two above the exact range
+const SUB_BUCKETS: usize = 8;
+/// Values below this get a bucket each
+const EXACT: u64 = 2 * SUB_BUCKETS as u64;
+const BUCKETS: usize = EXACT as usize + (64 - 4) * SUB_BUCKETS;
+
+/// Distribution of operation latencies, recorded in nanoseconds
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+    sum_squares: f64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Records one value
+    pub fn record(&mut self, value: u64) {
+        self.counts[bucket(value)] += 1;
+        self.count += 1;
+        self.sum += value as f64;
+        self.sum_squares += value as f64 * value as f64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Adds the values recorded by `other`
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let count = self.count as f64;
+        let variance = (self.sum_squares * count - self.sum * self.sum) / (count * count);
+        variance.max(0.0).sqrt()
+    }
+
+    /// Returns the value below which `p` percent of the values fall,
+    /// interpolated within its bucket
+    pub fn percentile(&self, p: f64) -> f64 {
+        let threshold = self.count as f64 * p / 100.0;
+        let mut cumulative = 0.0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let next = cumulative + count as f64;
+            if next >= threshold {
+                let low = lower_bound(index) as f64;
+                let high = upper_bound(index) as f64;
+                let fraction = (threshold - cumulative) / count as f64;
+                let value = low + (high - low) * fraction;
+                return value.clamp(self.min() as f64, self.max as f64);
+            }
+            cumulative = next;
+        }
+        self.max as f64
+    }
+}
+
+/// Returns the bucket holding `value`
+fn bucket(value: u64) -> usize {
+    if value < EXACT {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros() as usize;
+    let mantissa = (value >> (exponent - 3)) as usize - SUB_BUCKETS;
+    EXACT as usize + (exponent - 4) * SUB_BUCKETS + mantissa
+}
+
+/// Returns the smallest value in bucket `index`
+fn lower_bound(index: usize) -> u64 {
+    if index < EXACT as usize {
+        return index as u64;
+    }
+    let exponent = (index - EXACT as usize) / SUB_BUCKETS + 4;
+    let mantissa = ((index - EXACT as usize) % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    mantissa << (exponent - 3)
+}
+
+/// Returns the smallest value past bucket `index`
+fn upper_bound(index: usize) -> u64 {
+    if index + 1 < BUCKETS {
+        lower_bound(index + 1)
+    } else {
+        u64::MAX
+    }
+}
+
+/// Prints the summary in microseconds, then the non-empty buckets
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let micros = |nanos: f64| nanos / 1000.0;
+        writeln!(
+            f,
+            "Count: {}  Average: {:.4}  StdDev: {:.2}",
+            self.count(),
+            micros(self.mean()),
+            micros(self.std_dev())
+        )?;
+        writeln!(
+            f,
+            "Min: {:.4}  Median: {:.4}  Max: {:.4}",
+            micros(self.min() as f64),
+            micros(self.percentile(50.0)),
+            micros(self.max() as f64)
+        )?;
+        writeln!(
+            f,
+            "Percentiles: P50: {:.2} P75: {:.2} P99: {:.2} P99.9: {:.2} P99.99: {:.2}",
+            micros(self.percentile(50.0)),
+            micros(self.percentile(75.0)),
+            micros(self.percentile(99.0)),
+            micros(self.percentile(99.9)),
+            micros(self.percentile(99.99))
+        )?;
+        writeln!(f, "------------------------------------------------------")?;
+        let mut cumulative = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            cumulative += count;
+            let percent = 100.0 * count as f64 / self.count as f64;
+            writeln!(
+                f,
+                "[ {:>10.3}, {:>10.3} ) {:>8} {:>7.3}% {:>7.3}% {}",
+                micros(lower_bound(index) as f64),
+                micros(upper_bound(index) as f64),
+                count,
+                percent,
+                100.0 * cumulative as f64 / self.count as f64,
+                "#".repeat((percent / 5.0).round() as usize)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_cover_every_value_in_order() {
+        for value in (0..100_000).chain([u64::MAX / 3, u64::MAX]) {
+            let index = bucket(value);
+            assert!(lower_bound(index) <= value, "{value}");
+            assert!(
+                index + 1 == BUCKETS || value < upper_bound(index),
+                "{value}"
+            );
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        // Buckets are at most an eighth wider than where they start
+        for index in EXACT as usize..BUCKETS - 1 {
+            let width = upper_bound(index) - lower_bound(index);
+            assert!(width * 8 <= lower_bound(index), "{index}");
+        }
+    }
+
+    #[test]
+    fn test_percentiles_and_merge() {
+        let mut low = Histogram::new();
+        let mut high = Histogram::new();
+        for value in 1..=1000 {
+            low.record(value * 1000);
+            high.record((value + 1000) * 1000);
+        }
+        low.merge(&high);
+
+        assert_eq!(low.count(), 2000);
+        assert_eq!(low.min(), 1000);
+        assert_eq!(low.max(), 2_000_000);
+        assert!((low.mean() - 1_000_500.0).abs() < 1.0);
+        for (p, expected) in [(25.0, 500_000.0), (50.0, 1_000_000.0), (99.0, 1_980_000.0)] {
+            let value = low.percentile(p);
+            assert!((value - expected).abs() / expected < 0.05, "P{p}: {value}");
+        }
+        assert_eq!(low.percentile(100.0), 2_000_000.0);
+        assert_eq!(Histogram::new().percentile(50.0), 0.0);
+    }
+}
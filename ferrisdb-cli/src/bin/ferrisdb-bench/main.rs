@@ -0,0 +1,455 @@
+//! Measures FerrisDB's throughput and latency under standard workloads
+//!
+//! Modeled on LevelDB's and RocksDB's `db_bench`: each benchmark named in
+//! `--benchmarks` runs in turn against the same database and prints one
+//! line of throughput, followed by a latency histogram with `--histogram`:
+//!
+//! ```text
+//! ferrisdb-bench --data-dir /tmp/bench --benchmarks fillseq,readrandom --num 1000000
+//! ferrisdb-bench --data-dir /tmp/bench --benchmarks fillrandom,readwhilewriting --threads 8 --histogram
+//! ferrisdb-bench --data-dir /tmp/bench --benchmarks fillrandom,seekrandom --value-size 1000 --compression lz4
+//! ```
+//!
+//! Keys are `--num` decimal numbers, zero-padded to `--key-size` bytes.
+//! Values are `--value-size` bytes that compress to about half their size.
+//! Operations are divided among `--threads` threads, and the numbers
+//! reported are totals across them. The database is kept between runs, so
+//! the read benchmarks can measure one filled earlier.
+
+mod histogram;
+
+use ferrisdb_core::{CompressionType, Result, SyncMode};
+use ferrisdb_storage::{Options, ReadOptions, StorageConfig, StorageEngine};
+use histogram::Histogram;
+
+use clap::{Parser, ValueEnum};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Workloads the tool runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Benchmark {
+    /// Writes `--num` keys in ascending order
+    Fillseq,
+    /// Writes `--num` keys in random order
+    Fillrandom,
+    /// Reads `--reads` random keys
+    Readrandom,
+    /// Reads random keys while one more thread keeps writing random keys
+    Readwhilewriting,
+    /// Seeks to random keys and reads `--seek-nexts` entries after each
+    Seekrandom,
+}
+
+/// Block compression of the tables written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Compression {
+    None,
+    Lz4,
+    Snappy,
+}
+
+impl From<Compression> for CompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => CompressionType::None,
+            Compression::Lz4 => CompressionType::Lz4,
+            Compression::Snappy => CompressionType::Snappy,
+        }
+    }
+}
+
+/// Measures FerrisDB's throughput and latency under standard workloads
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Database directory to run against; created if missing
+    #[arg(short, long)]
+    data_dir: PathBuf,
+
+    /// Benchmarks to run, in order
+    #[arg(
+        short,
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "fillseq,readrandom"
+    )]
+    benchmarks: Vec<Benchmark>,
+
+    /// Keys in the database, and writes of the fill benchmarks
+    #[arg(short, long, default_value_t = 1_000_000)]
+    num: u64,
+
+    /// Operations of the read benchmarks; `--num` if omitted
+    #[arg(long)]
+    reads: Option<u64>,
+
+    /// Bytes per key
+    #[arg(long, default_value_t = 16)]
+    key_size: usize,
+
+    /// Bytes per value
+    #[arg(long, default_value_t = 100)]
+    value_size: usize,
+
+    /// Threads sharing the operations of each benchmark
+    #[arg(short, long, default_value_t = 1)]
+    threads: usize,
+
+    /// Entries read after each seek of `seekrandom`
+    #[arg(long, default_value_t = 10)]
+    seek_nexts: usize,
+
+    /// Block compression of the tables written
+    #[arg(long, value_enum, default_value = "none")]
+    compression: Compression,
+
+    /// MemTable size in megabytes; the engine's default if omitted
+    #[arg(long)]
+    memtable_mb: Option<usize>,
+
+    /// Syncs the WAL to disk on every write
+    #[arg(long)]
+    sync: bool,
+
+    /// Prints a latency histogram after each benchmark
+    #[arg(long)]
+    histogram: bool,
+
+    /// Seed of the random keys and values
+    #[arg(long, default_value_t = 301)]
+    seed: u64,
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let engine = open(&cli)?;
+    println!(
+        "Keys: {} bytes each, values: {} bytes each, entries: {}, threads: {}, compression: {:?}",
+        cli.key_size, cli.value_size, cli.num, cli.threads, cli.compression
+    );
+    println!("------------------------------------------------");
+    let bench = Bench::new(&engine, &cli);
+    for &benchmark in &cli.benchmarks {
+        let report = bench.run(benchmark)?;
+        println!("{}", report);
+        if cli.histogram {
+            println!("Microseconds per op:\n{}", report.histogram);
+        }
+    }
+    engine.close()
+}
+
+fn open(cli: &Cli) -> Result<StorageEngine> {
+    let mut config = StorageConfig {
+        data_dir: cli.data_dir.clone(),
+        wal_dir: cli.data_dir.join("wal"),
+        compression: cli.compression.into(),
+        ..Default::default()
+    };
+    if let Some(mb) = cli.memtable_mb {
+        config.memtable_size = mb << 20;
+    }
+    let mut options = Options::from_config(config);
+    if cli.sync {
+        options = options.with_sync_mode(SyncMode::Full);
+    }
+    StorageEngine::open(options)
+}
+
+/// What one benchmark did and how fast
+struct Report {
+    benchmark: Benchmark,
+    /// Operations done by the measured threads
+    ops: u64,
+    /// Reads that found their key
+    found: u64,
+    /// Key and value bytes written or read
+    bytes: u64,
+    /// Writes done alongside the reads of `readwhilewriting`
+    background_writes: u64,
+    elapsed: Duration,
+    threads: usize,
+    histogram: Histogram,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64().max(1e-9);
+        let name = format!("{:?}", self.benchmark).to_lowercase();
+        write!(
+            f,
+            "{:<16} : {:>11.3} micros/op {:>9.0} ops/sec; {:>7.1} MB/s",
+            name,
+            seconds * 1e6 * self.threads as f64 / self.ops.max(1) as f64,
+            self.ops as f64 / seconds,
+            self.bytes as f64 / (1 << 20) as f64 / seconds
+        )?;
+        match self.benchmark {
+            Benchmark::Fillseq | Benchmark::Fillrandom => Ok(()),
+            Benchmark::Readrandom | Benchmark::Seekrandom => {
+                write!(f, " ({} of {} found)", self.found, self.ops)
+            }
+            Benchmark::Readwhilewriting => write!(
+                f,
+                " ({} of {} found, {} writes alongside)",
+                self.found, self.ops, self.background_writes
+            ),
+        }
+    }
+}
+
+/// What one thread did
+#[derive(Default)]
+struct ThreadStats {
+    ops: u64,
+    found: u64,
+    bytes: u64,
+    histogram: Histogram,
+}
+
+impl ThreadStats {
+    /// Runs `op` once, recording its latency
+    fn time<T>(&mut self, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let result = op()?;
+        self.histogram.record(start.elapsed().as_nanos() as u64);
+        self.ops += 1;
+        Ok(result)
+    }
+}
+
+struct Bench<'a> {
+    engine: &'a StorageEngine,
+    cli: &'a Cli,
+    /// Compressible bytes values are sliced from
+    data: Vec<u8>,
+}
+
+impl<'a> Bench<'a> {
+    fn new(engine: &'a StorageEngine, cli: &'a Cli) -> Self {
+        // Random lowercase runs, each repeated once, compress to about half
+        let mut rng = StdRng::seed_from_u64(cli.seed);
+        let len = (1 << 20).max(2 * cli.value_size);
+        let mut data = Vec::with_capacity(len + 100);
+        while data.len() < len {
+            let run: Vec<u8> = (0..50).map(|_| rng.random_range(b'a'..=b'z')).collect();
+            data.extend_from_slice(&run);
+            data.extend_from_slice(&run);
+        }
+        Self { engine, cli, data }
+    }
+
+    fn key(&self, index: u64) -> Vec<u8> {
+        format!("{:0width$}", index, width = self.cli.key_size).into_bytes()
+    }
+
+    fn value(&self, rng: &mut StdRng) -> Vec<u8> {
+        let start = rng.random_range(0..=self.data.len() - self.cli.value_size);
+        self.data[start..start + self.cli.value_size].to_vec()
+    }
+
+    /// Runs `benchmark` and reports on it
+    fn run(&self, benchmark: Benchmark) -> Result<Report> {
+        let threads = self.cli.threads.max(1);
+        let total = match benchmark {
+            Benchmark::Fillseq | Benchmark::Fillrandom => self.cli.num,
+            _ => self.cli.reads.unwrap_or(self.cli.num),
+        };
+        let stop = AtomicBool::new(false);
+        let background_writes = AtomicU64::new(0);
+
+        let start = Instant::now();
+        let results: Vec<Result<ThreadStats>> = std::thread::scope(|scope| {
+            let writer = (benchmark == Benchmark::Readwhilewriting)
+                .then(|| scope.spawn(|| self.write_until(&stop, &background_writes)));
+            let handles: Vec<_> = (0..threads as u64)
+                .map(|thread| {
+                    // Thread `thread` does the operations from `first` to `last`
+                    let first = total * thread / threads as u64;
+                    let last = total * (thread + 1) / threads as u64;
+                    let seed = self.cli.seed + thread + 1;
+                    scope.spawn(move || self.run_thread(benchmark, first..last, seed))
+                })
+                .collect();
+            let mut results: Vec<_> = handles
+                .into_iter()
+                .map(|handle| handle.join().expect("benchmark thread panicked"))
+                .collect();
+            stop.store(true, Ordering::Relaxed);
+            if let Some(writer) = writer {
+                let result = writer.join().expect("writer thread panicked");
+                results.push(result.map(|()| ThreadStats::default()));
+            }
+            results
+        });
+        let elapsed = start.elapsed();
+
+        let mut report = Report {
+            benchmark,
+            ops: 0,
+            found: 0,
+            bytes: 0,
+            background_writes: background_writes.load(Ordering::Relaxed),
+            elapsed,
+            threads,
+            histogram: Histogram::new(),
+        };
+        for result in results {
+            let stats = result?;
+            report.ops += stats.ops;
+            report.found += stats.found;
+            report.bytes += stats.bytes;
+            report.histogram.merge(&stats.histogram);
+        }
+        Ok(report)
+    }
+
+    fn run_thread(
+        &self,
+        benchmark: Benchmark,
+        ops: std::ops::Range<u64>,
+        seed: u64,
+    ) -> Result<ThreadStats> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut stats = ThreadStats::default();
+        let entry_size = (self.cli.key_size + self.cli.value_size) as u64;
+        match benchmark {
+            Benchmark::Fillseq | Benchmark::Fillrandom => {
+                for index in ops {
+                    let index = match benchmark {
+                        Benchmark::Fillseq => index,
+                        _ => rng.random_range(0..self.cli.num),
+                    };
+                    let (key, value) = (self.key(index), self.value(&mut rng));
+                    stats.time(|| self.engine.put(key, value))?;
+                    stats.bytes += entry_size;
+                }
+            }
+            Benchmark::Readrandom | Benchmark::Readwhilewriting => {
+                for _ in ops {
+                    let key = self.key(rng.random_range(0..self.cli.num));
+                    if let Some(value) = stats.time(|| self.engine.get(&key))? {
+                        stats.found += 1;
+                        stats.bytes += (key.len() + value.len()) as u64;
+                    }
+                }
+            }
+            Benchmark::Seekrandom => {
+                let mut iter = self.engine.iter(ReadOptions::new())?;
+                for _ in ops {
+                    let key = self.key(rng.random_range(0..self.cli.num));
+                    let (found, bytes) = stats.time(|| {
+                        iter.seek(&key)?;
+                        let found = iter.key() == Some(&key[..]);
+                        let mut bytes = 0;
+                        for _ in 0..=self.cli.seek_nexts {
+                            let (Some(key), Some(value)) = (iter.key(), iter.value()) else {
+                                break;
+                            };
+                            bytes += (key.len() + value.len()) as u64;
+                            iter.next()?;
+                        }
+                        Ok((found, bytes))
+                    })?;
+                    stats.found += found as u64;
+                    stats.bytes += bytes;
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Writes random keys until `stop` is set, counting them in `writes`
+    fn write_until(&self, stop: &AtomicBool, writes: &AtomicU64) -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(self.cli.seed);
+        while !stop.load(Ordering::Relaxed) {
+            let key = self.key(rng.random_range(0..self.cli.num));
+            self.engine.put(key, self.value(&mut rng))?;
+            writes.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_benchmarks_report_what_they_did() {
+        let dir = TempDir::new().unwrap();
+        let cli = Cli::parse_from([
+            "ferrisdb-bench",
+            "--data-dir",
+            dir.path().to_str().unwrap(),
+            "--benchmarks",
+            "fillseq,readrandom,seekrandom,fillrandom,readwhilewriting",
+            "--num",
+            "500",
+            "--reads",
+            "301",
+            "--threads",
+            "3",
+            "--seek-nexts",
+            "2",
+            "--compression",
+            "lz4",
+        ]);
+        let engine = open(&cli).unwrap();
+        let bench = Bench::new(&engine, &cli);
+
+        let fill = bench.run(Benchmark::Fillseq).unwrap();
+        assert_eq!(fill.ops, 500);
+        assert_eq!(fill.histogram.count(), 500);
+        assert_eq!(fill.bytes, 500 * 116);
+        assert_eq!(engine.get(b"0000000000000499").unwrap().unwrap().len(), 100);
+
+        // Every key was written, so every read finds one
+        let read = bench.run(Benchmark::Readrandom).unwrap();
+        assert_eq!((read.ops, read.found), (301, 301));
+        assert!(read.to_string().contains("(301 of 301 found)"), "{}", read);
+
+        let seek = bench.run(Benchmark::Seekrandom).unwrap();
+        assert_eq!((seek.ops, seek.found), (301, 301));
+        assert!(seek.bytes > 301 * 116);
+
+        let fill = bench.run(Benchmark::Fillrandom).unwrap();
+        assert_eq!(fill.ops, 500);
+        let read = bench.run(Benchmark::Readwhilewriting).unwrap();
+        assert_eq!((read.ops, read.found), (301, 301));
+        assert_eq!(read.histogram.count(), 301);
+    }
+
+    #[test]
+    fn test_values_repeat_runs_of_random_bytes() {
+        let cli = Cli::parse_from(["ferrisdb-bench", "--data-dir", "unused"]);
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let bench = Bench::new(&engine, &cli);
+        let mut rng = StdRng::seed_from_u64(1);
+        let value = bench.value(&mut rng);
+        assert_eq!(value.len(), 100);
+        assert_eq!(bench.key(42), b"0000000000000042");
+
+        assert_eq!(bench.data[..50], bench.data[50..100]);
+        assert_ne!(bench.data[..50], bench.data[100..150]);
+    }
+}
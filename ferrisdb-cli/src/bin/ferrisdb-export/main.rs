@@ -0,0 +1,403 @@
+//! Streams a key range of a FerrisDB database out as CSV, NDJSON or Parquet
+//!
+//! The database is opened read-only (see [`StorageEngine::open_read_only`])
+//! and read through one snapshot, so it may be served at the same time
+//! and the output is consistent as of when the tool started:
+//!
+//! ```text
+//! ferrisdb-export --data-dir ./data --output users.csv --header
+//! ferrisdb-export --data-dir ./data --cf events --prefix 2024- --format ndjson | gzip > events.ndjson.gz
+//! ferrisdb-export --data-dir ./data --start a --end m --jobs 8 --format parquet --output ./dump
+//! ```
+//!
+//! With `--jobs` above 1, the range is split into shards holding about the
+//! same amount of data (see [`StorageEngine::split_keys`]), each written
+//! by its own thread to `part-NNNNN.<format>` in the `--output` directory.
+//! The parts are numbered in key order, so concatenating them gives the
+//! output of a single job. Parquet needs the `parquet` feature.
+
+mod sink;
+
+use ferrisdb_core::keys::prefix_successor;
+use ferrisdb_core::{Error, Key, Result};
+use ferrisdb_storage::storage_engine::DEFAULT_COLUMN_FAMILY;
+use ferrisdb_storage::{ColumnFamily, Options, ReadOptions, Snapshot, StorageEngine};
+#[cfg(feature = "parquet")]
+use sink::ParquetSink;
+use sink::{CsvSink, NdjsonSink, RecordSink};
+
+use clap::{Parser, ValueEnum};
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Output formats the tool writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Comma-separated values
+    Csv,
+    /// Tab-separated values
+    Tsv,
+    /// One JSON object per line
+    Ndjson,
+    /// Apache Parquet with binary key and value columns
+    Parquet,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Ndjson => "ndjson",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+/// Streams a key range of a FerrisDB database out as CSV, NDJSON or Parquet
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Database directory to export from
+    #[arg(short, long)]
+    data_dir: PathBuf,
+
+    /// Column family to export; the default one if omitted
+    #[arg(long = "cf")]
+    column_family: Option<String>,
+
+    /// First key to export; the first in the family if omitted
+    #[arg(long, conflicts_with = "prefix")]
+    start: Option<String>,
+
+    /// Key to stop before; the end of the family if omitted
+    #[arg(long, conflicts_with = "prefix")]
+    end: Option<String>,
+
+    /// Exports only the keys starting with this prefix
+    #[arg(long)]
+    prefix: Option<String>,
+
+    /// Output format; follows the output's extension if omitted
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// File to write, or directory for the parts with more than one job;
+    /// standard output if omitted
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Shards exported in parallel, each to its own part file
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// CSV: writes a `key,value` header row first
+    #[arg(long)]
+    header: bool,
+
+    /// CSV and NDJSON: writes keys and values hex-encoded, for binary data
+    #[arg(long)]
+    hex: bool,
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let format = match (cli.format, &cli.output) {
+        (Some(format), _) => format,
+        (None, Some(path)) if cli.jobs <= 1 => format_of(path)?,
+        _ => {
+            return Err(Error::InvalidOperation(
+                "Can't tell the output format; pass --format".to_string(),
+            ))
+        }
+    };
+    if cli.jobs > 1 && cli.output.is_none() {
+        return Err(Error::InvalidOperation(
+            "--jobs above 1 writes part files; pass an --output directory".to_string(),
+        ));
+    }
+
+    let engine = StorageEngine::open_read_only(Options::new(&cli.data_dir))?;
+    let name = cli
+        .column_family
+        .as_deref()
+        .unwrap_or(DEFAULT_COLUMN_FAMILY);
+    let cf = engine
+        .cf_handle(name)
+        .ok_or_else(|| Error::NotFound(format!("Column family {:?}", name)))?;
+    let records = export(&engine, &cf, &cli, format)?;
+    eprintln!("Exported {} records", records);
+    Ok(())
+}
+
+/// Writes the range out and returns the number of records written
+fn export(engine: &StorageEngine, cf: &ColumnFamily, cli: &Cli, format: Format) -> Result<u64> {
+    let (start, end) = match &cli.prefix {
+        Some(prefix) => (
+            Some(prefix.as_bytes().to_vec()),
+            prefix_successor(prefix.as_bytes()),
+        ),
+        None => (
+            cli.start.as_ref().map(|key| key.as_bytes().to_vec()),
+            cli.end.as_ref().map(|key| key.as_bytes().to_vec()),
+        ),
+    };
+    let snapshot = engine.snapshot();
+
+    if cli.jobs <= 1 {
+        let sink = match &cli.output {
+            Some(path) => sink(format, create(path)?, cli)?,
+            None => sink(format, io::stdout(), cli)?,
+        };
+        let shard = Shard { start, end };
+        return shard.export(engine, cf, &snapshot, cli, sink);
+    }
+
+    let range = (
+        start.clone().map_or(Bound::Unbounded, Bound::Included),
+        end.clone().map_or(Bound::Unbounded, Bound::Excluded),
+    );
+    let splits = engine.split_keys_cf(cf, range, cli.jobs)?;
+    let mut shards = Vec::with_capacity(splits.len() + 1);
+    let mut shard_start = start;
+    for split in splits {
+        shards.push(Shard {
+            start: shard_start,
+            end: Some(split.clone()),
+        });
+        shard_start = Some(split);
+    }
+    shards.push(Shard {
+        start: shard_start,
+        end,
+    });
+
+    let dir = cli.output.as_deref().expect("checked by run");
+    std::fs::create_dir_all(dir)?;
+    let results: Vec<Result<u64>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .enumerate()
+            .map(|(part, shard)| {
+                let path = dir.join(format!("part-{:05}.{}", part, format.extension()));
+                let snapshot = &snapshot;
+                scope.spawn(move || {
+                    let sink = sink(format, create(&path)?, cli)?;
+                    shard.export(engine, cf, snapshot, cli, sink)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("export thread panicked"))
+            .collect()
+    });
+    results.into_iter().sum()
+}
+
+/// A span of keys exported to one output
+struct Shard {
+    /// First key, inclusive
+    start: Option<Key>,
+    /// Last key, exclusive
+    end: Option<Key>,
+}
+
+impl Shard {
+    fn export(
+        self,
+        engine: &StorageEngine,
+        cf: &ColumnFamily,
+        snapshot: &Snapshot,
+        cli: &Cli,
+        mut sink: Box<dyn RecordSink>,
+    ) -> Result<u64> {
+        let mut options = ReadOptions::new().with_snapshot(snapshot);
+        if let Some(start) = self.start {
+            options = options.with_lower_bound(start);
+        }
+        if let Some(end) = self.end {
+            options = options.with_upper_bound(end);
+        }
+        if let Some(prefix) = &cli.prefix {
+            options = options.with_prefix(prefix.as_bytes().to_vec());
+        }
+
+        let mut iter = engine.iter_cf(cf, options)?;
+        iter.seek_to_first()?;
+        let mut records = 0;
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            sink.write(key, value)?;
+            records += 1;
+            iter.next()?;
+        }
+        sink.finish()?;
+        Ok(records)
+    }
+}
+
+fn create(path: &Path) -> Result<File> {
+    File::create(path)
+        .map_err(|e| Error::InvalidOperation(format!("Failed to create {}: {}", path.display(), e)))
+}
+
+/// Returns a sink writing `format` to `out`
+fn sink<W: io::Write + Send + 'static>(
+    format: Format,
+    out: W,
+    cli: &Cli,
+) -> Result<Box<dyn RecordSink>> {
+    let out = BufWriter::new(out);
+    Ok(match format {
+        Format::Csv => Box::new(CsvSink::new(out, b',', cli.header, cli.hex)?),
+        Format::Tsv => Box::new(CsvSink::new(out, b'\t', cli.header, cli.hex)?),
+        Format::Ndjson => Box::new(NdjsonSink::new(out, cli.hex)),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => Box::new(ParquetSink::new(out)?),
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => {
+            return Err(Error::InvalidOperation(
+                "Parquet output needs ferrisdb-cli built with the parquet feature".to_string(),
+            ))
+        }
+    })
+}
+
+fn format_of(path: &Path) -> Result<Format> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => Ok(Format::Csv),
+        Some("tsv") => Ok(Format::Tsv),
+        Some("ndjson" | "jsonl") => Ok(Format::Ndjson),
+        Some("parquet") => Ok(Format::Parquet),
+        _ => Err(Error::InvalidOperation(format!(
+            "Can't tell the format of {}; pass --format",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_storage::ColumnFamilyOptions;
+    use tempfile::TempDir;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::parse_from(std::iter::once("ferrisdb-export").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_export_splits_a_range_into_parts_in_key_order() {
+        let dir = TempDir::new().unwrap();
+        let data_dir = dir.path().join("db");
+        let engine = StorageEngine::open(Options::new(&data_dir)).unwrap();
+        let cf = engine
+            .create_column_family("users", ColumnFamilyOptions::default().with_block_size(256))
+            .unwrap();
+        for i in 0..500 {
+            let key = format!("user{:04}", i);
+            engine
+                .put_cf(&cf, key.into_bytes(), format!("name, {}", i).into_bytes())
+                .unwrap();
+        }
+        engine.flush().unwrap();
+        // Writes after the flush are exported from the MemTable
+        engine
+            .put_cf(&cf, b"user9999".to_vec(), b"last".to_vec())
+            .unwrap();
+
+        let out = dir.path().join("parts");
+        let cli = parse(&[
+            "--data-dir",
+            data_dir.to_str().unwrap(),
+            "--cf",
+            "users",
+            "--start",
+            "user0100",
+            "--format",
+            "csv",
+            "--jobs",
+            "4",
+            "--output",
+            out.to_str().unwrap(),
+        ]);
+        // The export reads through its own read-only engine
+        run(cli).unwrap();
+        drop(engine);
+
+        let mut parts: Vec<_> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        parts.sort();
+        assert_eq!(parts.len(), 4);
+        let csv: String = parts
+            .iter()
+            .map(|part| std::fs::read_to_string(part).unwrap())
+            .collect();
+        let mut expected: String = (100..500)
+            .map(|i| format!("user{:04},\"name, {}\"\n", i, i))
+            .collect();
+        expected.push_str("user9999,last\n");
+        assert_eq!(csv, expected);
+    }
+
+    #[test]
+    fn test_export_writes_a_prefix_as_ndjson() {
+        let dir = TempDir::new().unwrap();
+        let data_dir = dir.path().join("db");
+        let engine = StorageEngine::open(Options::new(&data_dir)).unwrap();
+        engine.put(b"a/1".to_vec(), b"one".to_vec()).unwrap();
+        engine.put(b"b/1".to_vec(), b"two".to_vec()).unwrap();
+        engine.put(b"b/2".to_vec(), vec![0xFF]).unwrap();
+        engine.put(b"c/1".to_vec(), b"three".to_vec()).unwrap();
+
+        let out = dir.path().join("b.ndjson");
+        let cli = parse(&[
+            "--data-dir",
+            data_dir.to_str().unwrap(),
+            "--prefix",
+            "b/",
+            "--hex",
+            "--output",
+            out.to_str().unwrap(),
+        ]);
+        run(cli).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "{\"key\":\"622f31\",\"value\":\"74776f\"}\n{\"key\":\"622f32\",\"value\":\"ff\"}\n"
+        );
+
+        // Without --hex, binary data can't be written as text
+        let cli = parse(&[
+            "--data-dir",
+            data_dir.to_str().unwrap(),
+            "--prefix",
+            "b/",
+            "--output",
+            out.to_str().unwrap(),
+        ]);
+        let err = run(cli).unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg.contains("--hex")));
+    }
+
+    #[test]
+    fn test_format_is_inferred_from_the_extension() {
+        assert_eq!(format_of(Path::new("x.parquet")).unwrap(), Format::Parquet);
+        assert_eq!(format_of(Path::new("x.jsonl")).unwrap(), Format::Ndjson);
+        assert!(format_of(Path::new("x.txt")).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Writers turning key-value records into CSV, NDJSON or Parquet output
+//!
+//! CSV and NDJSON are text, so keys and values must be UTF-8 unless they
+//! are written hex-encoded. Parquet stores them as binary columns as they
+//! are.
+
+use ferrisdb_core::{Error, Result};
+
+use std::borrow::Cow;
+use std::io::Write;
+
+/// Destination of exported records, in the order they are written
+pub trait RecordSink: Send {
+    /// Writes one record
+    fn write(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Writes anything still buffered and closes the output
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Returns `bytes` as text, hex-encoded if `hex` is set
+fn text(bytes: &[u8], hex: bool) -> Result<Cow<'_, str>> {
+    if hex {
+        return Ok(Cow::Owned(
+            bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        ));
+    }
+    std::str::from_utf8(bytes).map(Cow::Borrowed).map_err(|_| {
+        Error::InvalidFormat(format!(
+            "{:?} is not UTF-8 text; pass --hex to export binary data",
+            String::from_utf8_lossy(bytes)
+        ))
+    })
+}
+
+/// Records as CSV rows of a key and a value, quoted where RFC 4180 needs
+pub struct CsvSink<W> {
+    out: W,
+    delimiter: u8,
+    hex: bool,
+}
+
+impl<W: Write + Send> CsvSink<W> {
+    /// Writes rows separated by `delimiter`, with a `key,value` header row
+    /// if `header` is set
+    pub fn new(mut out: W, delimiter: u8, header: bool, hex: bool) -> Result<Self> {
+        if header {
+            out.write_all(b"key")?;
+            out.write_all(&[delimiter])?;
+            out.write_all(b"value\n")?;
+        }
+        Ok(Self {
+            out,
+            delimiter,
+            hex,
+        })
+    }
+
+    fn write_field(&mut self, field: &str) -> Result<()> {
+        let special = |byte: u8| matches!(byte, b'"' | b'\n' | b'\r') || byte == self.delimiter;
+        if field.bytes().any(special) {
+            write!(self.out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            self.out.write_all(field.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> RecordSink for CsvSink<W> {
+    fn write(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_field(&text(key, self.hex)?)?;
+        self.out.write_all(&[self.delimiter])?;
+        self.write_field(&text(value, self.hex)?)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Records as one `{"key": ..., "value": ...}` object per line
+pub struct NdjsonSink<W> {
+    out: W,
+    hex: bool,
+}
+
+impl<W: Write + Send> NdjsonSink<W> {
+    pub fn new(out: W, hex: bool) -> Self {
+        Self { out, hex }
+    }
+}
+
+impl<W: Write + Send> RecordSink for NdjsonSink<W> {
+    fn write(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let key = serde_json::to_string(&text(key, self.hex)?)
+            .map_err(|e| Error::Serialization(e.to_string()))?;
+        let value = serde_json::to_string(&text(value, self.hex)?)
+            .map_err(|e| Error::Serialization(e.to_string()))?;
+        writeln!(self.out, "{{\"key\":{},\"value\":{}}}", key, value)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub use self::parquet_sink::ParquetSink;
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use super::RecordSink;
+    use ferrisdb_core::{Error, Result};
+    use parquet::data_type::{ByteArray, ByteArrayType};
+    use parquet::errors::ParquetError;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    use std::io::Write;
+    use std::sync::Arc;
+
+    /// Columns of the files written
+    const SCHEMA: &str = "message record { required binary key; required binary value; }";
+
+    /// Records buffered before they are written as one row group
+    const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+    fn parquet_error(e: ParquetError) -> Error {
+        Error::Serialization(e.to_string())
+    }
+
+    /// Records as a Parquet file with binary `key` and `value` columns
+    pub struct ParquetSink<W: Write + Send> {
+        writer: SerializedFileWriter<W>,
+        keys: Vec<ByteArray>,
+        values: Vec<ByteArray>,
+    }
+
+    impl<W: Write + Send> ParquetSink<W> {
+        pub fn new(out: W) -> Result<Self> {
+            let schema = Arc::new(parse_message_type(SCHEMA).map_err(parquet_error)?);
+            let properties = Arc::new(WriterProperties::builder().build());
+            Ok(Self {
+                writer: SerializedFileWriter::new(out, schema, properties)
+                    .map_err(parquet_error)?,
+                keys: Vec::new(),
+                values: Vec::new(),
+            })
+        }
+
+        fn write_row_group(&mut self) -> Result<()> {
+            let mut row_group = self.writer.next_row_group().map_err(parquet_error)?;
+            for column in [&self.keys, &self.values] {
+                let mut writer = row_group
+                    .next_column()
+                    .map_err(parquet_error)?
+                    .expect("the schema has two columns");
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(column, None, None)
+                    .map_err(parquet_error)?;
+                writer.close().map_err(parquet_error)?;
+            }
+            row_group.close().map_err(parquet_error)?;
+            self.keys.clear();
+            self.values.clear();
+            Ok(())
+        }
+    }
+
+    impl<W: Write + Send> RecordSink for ParquetSink<W> {
+        fn write(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+            self.keys.push(ByteArray::from(key.to_vec()));
+            self.values.push(ByteArray::from(value.to_vec()));
+            if self.keys.len() >= ROW_GROUP_SIZE {
+                self.write_row_group()?;
+            }
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> Result<()> {
+            if !self.keys.is_empty() {
+                self.write_row_group()?;
+            }
+            self.writer.close().map_err(parquet_error)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(sink: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> String {
+        let mut out = Vec::new();
+        sink(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv_quotes_fields_that_need_it() {
+        let csv = output(|out| {
+            let mut sink = Box::new(CsvSink::new(out, b',', true, false)?);
+            sink.write(b"plain", b"a,b")?;
+            sink.write(b"say \"hi\"", b"two\nlines")?;
+            sink.finish()
+        });
+        assert_eq!(
+            csv,
+            "key,value\nplain,\"a,b\"\n\"say \"\"hi\"\"\",\"two\nlines\"\n"
+        );
+
+        let tsv = output(|out| {
+            let mut sink = Box::new(CsvSink::new(out, b'\t', false, true)?);
+            sink.write(b"a,b", &[0, 255])?;
+            sink.finish()
+        });
+        assert_eq!(tsv, "612c62\t00ff\n");
+    }
+
+    #[test]
+    fn test_ndjson_writes_one_object_per_line() {
+        let ndjson = output(|out| {
+            let mut sink = Box::new(NdjsonSink::new(out, false));
+            sink.write(b"k\"1", b"{\"a\":1}")?;
+            sink.write(b"k2", b"")?;
+            sink.finish()
+        });
+        assert_eq!(
+            ndjson,
+            "{\"key\":\"k\\\"1\",\"value\":\"{\\\"a\\\":1}\"}\n{\"key\":\"k2\",\"value\":\"\"}\n"
+        );
+
+        let mut out = Vec::new();
+        let mut sink = NdjsonSink::new(&mut out, false);
+        let err = sink.write(b"k", &[0xC3, 0x28]).unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg.contains("--hex")));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_stores_binary_columns() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("records.parquet");
+        let mut sink = Box::new(ParquetSink::new(std::fs::File::create(&path).unwrap()).unwrap());
+        sink.write(b"a", &[0, 1]).unwrap();
+        sink.write(b"b", b"text").unwrap();
+        sink.finish().unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].contains("key: [98]"), "{}", rows[1]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Readers turning CSV and NDJSON input into key-value records
+//!
+//! Both read one record at a time, so the input is never held in memory
+//! as a whole. A malformed record fails with `Error::InvalidFormat`,
+//! naming the line it starts on.
+
+use ferrisdb_core::{Error, Key, Result, Value};
+
+use std::io::BufRead;
+
+/// Records from CSV rows, as RFC 4180 describes them
+///
+/// Fields may be quoted, with `""` standing for a quote and line breaks
+/// kept as they are. Blank lines are skipped.
+pub struct CsvRecords<R> {
+    reader: R,
+    delimiter: u8,
+    key_column: usize,
+    value_column: usize,
+    /// Whether the first row names the columns rather than holding a record
+    header: bool,
+    /// Lines read so far
+    line: u64,
+}
+
+impl<R: BufRead> CsvRecords<R> {
+    /// Reads keys from the first column and values from the second
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            delimiter: b',',
+            key_column: 0,
+            value_column: 1,
+            header: false,
+            line: 0,
+        }
+    }
+
+    /// Sets the byte separating fields
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the columns, counted from 0, holding the key and the value
+    pub fn with_columns(mut self, key_column: usize, value_column: usize) -> Self {
+        self.key_column = key_column;
+        self.value_column = value_column;
+        self
+    }
+
+    /// Sets whether the first row is a header to skip
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Reads the fields of the next row that isn't blank
+    fn next_row(&mut self) -> Result<Option<(u64, Vec<Vec<u8>>)>> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !line.iter().all(|&b| b == b'\n' || b == b'\r') {
+                break;
+            }
+        }
+
+        let start = self.line;
+        let mut fields = Vec::new();
+        let mut field = Vec::new();
+        let mut quoted = false;
+        loop {
+            let mut bytes = line.iter().copied().peekable();
+            while let Some(b) = bytes.next() {
+                if quoted {
+                    if b != b'"' {
+                        field.push(b);
+                    } else if bytes.next_if_eq(&b'"').is_some() {
+                        field.push(b'"');
+                    } else {
+                        quoted = false;
+                    }
+                } else if b == b'"' && field.is_empty() {
+                    quoted = true;
+                } else if b == self.delimiter {
+                    fields.push(std::mem::take(&mut field));
+                } else if b != b'\n' && b != b'\r' {
+                    field.push(b);
+                }
+            }
+            if !quoted {
+                fields.push(field);
+                return Ok(Some((start, fields)));
+            }
+
+            // The quoted field goes on in the next line
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Err(Error::InvalidFormat(format!(
+                    "line {}: quoted field is never closed",
+                    start
+                )));
+            }
+            self.line += 1;
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<(Key, Value)>> {
+        if std::mem::take(&mut self.header) && self.next_row()?.is_none() {
+            return Ok(None);
+        }
+        let Some((line, mut fields)) = self.next_row()? else {
+            return Ok(None);
+        };
+        let mut column = |index: usize| {
+            let field = fields.get_mut(index).ok_or_else(|| {
+                Error::InvalidFormat(format!("line {}: no column {}", line, index))
+            })?;
+            Ok::<_, Error>(std::mem::take(field))
+        };
+        if self.key_column == self.value_column {
+            let key = column(self.key_column)?;
+            return Ok(Some((key.clone(), key)));
+        }
+        Ok(Some((column(self.key_column)?, column(self.value_column)?)))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvRecords<R> {
+    type Item = Result<(Key, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Records from newline-delimited JSON objects
+///
+/// The key is a field of each object. The value is another field, or the
+/// whole line if no value field is set. String fields are taken as their
+/// UTF-8 text, any other JSON value as the JSON encoding it. Blank lines
+/// are skipped.
+pub struct NdjsonRecords<R> {
+    reader: R,
+    key_field: String,
+    value_field: Option<String>,
+    /// Lines read so far
+    line: u64,
+}
+
+impl<R: BufRead> NdjsonRecords<R> {
+    /// Reads keys from field `key` and keeps whole lines as values
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            key_field: "key".to_string(),
+            value_field: None,
+            line: 0,
+        }
+    }
+
+    /// Sets the field holding the key
+    pub fn with_key_field(mut self, field: impl Into<String>) -> Self {
+        self.key_field = field.into();
+        self
+    }
+
+    /// Sets the field holding the value, instead of the whole line
+    pub fn with_value_field(mut self, field: impl Into<String>) -> Self {
+        self.value_field = Some(field.into());
+        self
+    }
+
+    fn next_record(&mut self) -> Result<Option<(Key, Value)>> {
+        let mut line = Vec::new();
+        let text = loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            let text = line.trim_ascii();
+            if !text.is_empty() {
+                break text;
+            }
+        };
+
+        let invalid =
+            |message: String| Error::InvalidFormat(format!("line {}: {}", self.line, message));
+        let object = match serde_json::from_slice(text) {
+            Ok(serde_json::Value::Object(object)) => object,
+            Ok(_) => return Err(invalid("not a JSON object".to_string())),
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+        let field = |name: &str| {
+            object
+                .get(name)
+                .filter(|value| !value.is_null())
+                .map(field_bytes)
+                .ok_or_else(|| invalid(format!("no field {:?}", name)))
+        };
+        let key = field(&self.key_field)?;
+        let value = match &self.value_field {
+            Some(name) => field(name)?,
+            None => text.to_vec(),
+        };
+        Ok(Some((key, value)))
+    }
+}
+
+impl<R: BufRead> Iterator for NdjsonRecords<R> {
+    type Item = Result<(Key, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+fn field_bytes(value: &serde_json::Value) -> Vec<u8> {
+    match value {
+        serde_json::Value::String(text) => text.as_bytes().to_vec(),
+        other => other.to_string().into_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(iter: impl Iterator<Item = Result<(Key, Value)>>) -> Vec<(String, String)> {
+        iter.map(|record| {
+            let (key, value) = record.unwrap();
+            (
+                String::from_utf8(key).unwrap(),
+                String::from_utf8(value).unwrap(),
+            )
+        })
+        .collect()
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_csv_reads_quoted_fields_and_picks_columns() {
+        let input =
+            "id,name,note\r\n1,alice,\"says \"\"hi\"\"\"\n\n2,\"b,ob\",\"two\nlines\"\n3,carol,";
+        let csv = CsvRecords::new(input.as_bytes()).with_header(true);
+        assert_eq!(
+            records(csv),
+            vec![pair("1", "alice"), pair("2", "b,ob"), pair("3", "carol")]
+        );
+
+        let csv = CsvRecords::new(input.as_bytes())
+            .with_header(true)
+            .with_columns(1, 2);
+        assert_eq!(
+            records(csv),
+            vec![
+                pair("alice", "says \"hi\""),
+                pair("b,ob", "two\nlines"),
+                pair("carol", "")
+            ]
+        );
+
+        let tsv = CsvRecords::new("a\t1\nb\t2\n".as_bytes()).with_delimiter(b'\t');
+        assert_eq!(records(tsv), vec![pair("a", "1"), pair("b", "2")]);
+    }
+
+    #[test]
+    fn test_csv_reports_the_line_of_malformed_rows() {
+        let mut csv = CsvRecords::new("a,1\nb\nc,\"open\n".as_bytes());
+        assert!(csv.next().unwrap().is_ok());
+        let err = csv.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg == "line 2: no column 1"));
+        let err = csv.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg.starts_with("line 3:")));
+        assert!(csv.next().is_none());
+    }
+
+    #[test]
+    fn test_ndjson_reads_key_and_value_fields() {
+        let input =
+            "{\"id\": \"u1\", \"age\": 30, \"tags\": [\"a\"]}\n\n  {\"id\": 7, \"age\": null}\n";
+        let ndjson = NdjsonRecords::new(input.as_bytes()).with_key_field("id");
+        assert_eq!(
+            records(ndjson),
+            vec![
+                pair("u1", "{\"id\": \"u1\", \"age\": 30, \"tags\": [\"a\"]}"),
+                pair("7", "{\"id\": 7, \"age\": null}")
+            ]
+        );
+
+        let mut ndjson = NdjsonRecords::new(input.as_bytes())
+            .with_key_field("id")
+            .with_value_field("tags");
+        assert_eq!(
+            ndjson.next().unwrap().unwrap(),
+            (b"u1".to_vec(), b"[\"a\"]".to_vec())
+        );
+        let err = ndjson.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg == "line 3: no field \"tags\""));
+
+        let mut ndjson = NdjsonRecords::new("[1]\n{\"key\":".as_bytes());
+        let err = ndjson.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg == "line 1: not a JSON object"));
+        let err = ndjson.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::InvalidFormat(msg) if msg.starts_with("line 2:")));
+    }
+}
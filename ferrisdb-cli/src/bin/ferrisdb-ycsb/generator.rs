@@ -0,0 +1,198 @@
+//! Key choosers of YCSB's request distributions
+//!
+//! These follow YCSB's core generators, so the same workload hits keys
+//! with the same skew as in published results:
+//!
+//! - uniform: every record equally often
+//! - zipfian: a few records very often, scattered over the key space by
+//!   hashing, after Gray et al., "Quickly Generating Billion-Record
+//!   Synthetic Databases", with YCSB's constant of 0.99
+//! - latest: like zipfian, but the most recently inserted records are
+//!   the popular ones
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// Skew of the zipfian distribution YCSB uses
+const ZIPFIAN_CONSTANT: f64 = 0.99;
+
+const FNV_OFFSET_BASIS_64: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME_64: u64 = 1_099_511_628_211;
+
+/// Hashes `value` with 64-bit FNV-1a over its bytes, as YCSB does to turn
+/// record numbers into keys
+pub fn fnv_hash64(mut value: u64) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS_64;
+    for _ in 0..8 {
+        hash ^= value & 0xFF;
+        hash = hash.wrapping_mul(FNV_PRIME_64);
+        value >>= 8;
+    }
+    (hash as i64).wrapping_abs() as u64
+}
+
+/// Picks numbers in `0..items`, small ones far more often than large ones
+///
+/// The normalization constant takes time linear in the number of items to
+/// compute, so it is kept and extended as records are inserted; clone a
+/// generator rather than building one per thread.
+#[derive(Debug, Clone)]
+pub struct Zipfian {
+    items: u64,
+    zetan: f64,
+    zeta2: f64,
+    alpha: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    pub fn new(items: u64) -> Self {
+        let mut zipfian = Self {
+            items: 0,
+            zetan: 0.0,
+            zeta2: zeta(0, 2, 0.0),
+            alpha: 1.0 / (1.0 - ZIPFIAN_CONSTANT),
+            eta: 0.0,
+        };
+        zipfian.resize(items);
+        zipfian
+    }
+
+    /// Adjusts the constants to `items` items
+    fn resize(&mut self, items: u64) {
+        self.zetan = if items >= self.items {
+            zeta(self.items, items, self.zetan)
+        } else {
+            zeta(0, items, 0.0)
+        };
+        self.items = items;
+        self.eta = (1.0 - (2.0 / items as f64).powf(1.0 - ZIPFIAN_CONSTANT))
+            / (1.0 - self.zeta2 / self.zetan);
+    }
+
+    /// Returns a number in `0..items`
+    pub fn next(&mut self, rng: &mut StdRng, items: u64) -> u64 {
+        if items <= 1 {
+            return 0;
+        }
+        if items != self.items {
+            self.resize(items);
+        }
+        let u: f64 = rng.random();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5_f64.powf(ZIPFIAN_CONSTANT) {
+            return 1;
+        }
+        let value = items as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha);
+        (value as u64).min(items - 1)
+    }
+}
+
+/// Adds the terms for items `from..to` to the zeta sum `initial`
+fn zeta(from: u64, to: u64, initial: f64) -> f64 {
+    (from..to).fold(initial, |sum, i| {
+        sum + 1.0 / ((i + 1) as f64).powf(ZIPFIAN_CONSTANT)
+    })
+}
+
+/// Request distributions of the records read, updated and scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Distribution {
+    Uniform,
+    Zipfian,
+    Latest,
+}
+
+/// Picks the records operations go to
+#[derive(Debug, Clone)]
+pub enum KeyChooser {
+    Uniform,
+    Zipfian(Zipfian),
+    Latest(Zipfian),
+}
+
+impl KeyChooser {
+    /// Creates a chooser for a database of `records` records
+    pub fn new(distribution: Distribution, records: u64) -> Self {
+        match distribution {
+            Distribution::Uniform => KeyChooser::Uniform,
+            Distribution::Zipfian => KeyChooser::Zipfian(Zipfian::new(records)),
+            Distribution::Latest => KeyChooser::Latest(Zipfian::new(records)),
+        }
+    }
+
+    /// Returns the number of a record among the first `records`
+    pub fn next(&mut self, rng: &mut StdRng, records: u64) -> u64 {
+        match self {
+            KeyChooser::Uniform => rng.random_range(0..records.max(1)),
+            // Scattered, so the popular records aren't neighbors
+            KeyChooser::Zipfian(zipfian) => fnv_hash64(zipfian.next(rng, records)) % records.max(1),
+            KeyChooser::Latest(zipfian) => records.saturating_sub(1 + zipfian.next(rng, records)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_zipfian_favors_small_numbers_as_items_grow() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut zipfian = Zipfian::new(1000);
+        let mut counts = vec![0u32; 2000];
+        for _ in 0..100_000 {
+            counts[zipfian.next(&mut rng, 1000) as usize] += 1;
+        }
+        // With a constant of 0.99, item 0 gets about 1/zeta(1000) of picks
+        let share = counts[0] as f64 / 100_000.0;
+        assert!((0.12..0.15).contains(&share), "{share}");
+        assert!(counts[0] > counts[1] && counts[1] > counts[10] && counts[10] > counts[500]);
+        assert!(counts[1000..].iter().all(|&count| count == 0));
+
+        // Extending the sum matches computing it afresh
+        zipfian.next(&mut rng, 2000);
+        let fresh = Zipfian::new(2000);
+        assert!((zipfian.zetan - fresh.zetan).abs() < 1e-9);
+        assert!((zipfian.eta - fresh.eta).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_choosers_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for distribution in [
+            Distribution::Uniform,
+            Distribution::Zipfian,
+            Distribution::Latest,
+        ] {
+            let mut chooser = KeyChooser::new(distribution, 100);
+            let mut seen = [false; 100];
+            for _ in 0..10_000 {
+                seen[chooser.next(&mut rng, 100) as usize] = true;
+            }
+            assert!(
+                seen.iter().filter(|&&seen| seen).count() > 50,
+                "{distribution:?}"
+            );
+        }
+
+        // Latest picks the newest record most often
+        let mut latest = KeyChooser::new(Distribution::Latest, 100);
+        let newest = (0..1000)
+            .filter(|_| latest.next(&mut rng, 150) == 149)
+            .count();
+        assert!(newest > 100, "{newest}");
+        assert_eq!(KeyChooser::Uniform.next(&mut rng, 1), 0);
+    }
+
+    #[test]
+    fn test_fnv_hash_matches_ycsb() {
+        // What YCSB's Utils.fnvhash64 returns for 0
+        assert_eq!(fnv_hash64(0), 6_284_781_860_667_377_211);
+        assert_ne!(fnv_hash64(1), fnv_hash64(2));
+    }
+}
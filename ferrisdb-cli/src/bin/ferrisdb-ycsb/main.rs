@@ -0,0 +1,706 @@
+//! Runs YCSB workloads against FerrisDB, embedded or over gRPC
+//!
+//! A YCSB-compatible runner for the core workloads A to F, reporting in
+//! YCSB's own format so results line up with those published for other
+//! stores. As with YCSB, a database is first loaded, then a workload run
+//! against it:
+//!
+//! ```text
+//! ferrisdb-ycsb --data-dir ./ycsb load --record-count 1000000
+//! ferrisdb-ycsb --data-dir ./ycsb run --workload a --operation-count 1000000 --threads 8
+//! ferrisdb-ycsb --server http://127.0.0.1:7070 run --workload e --distribution uniform
+//! ```
+//!
+//! | Workload | Operations                      | Distribution |
+//! |----------|---------------------------------|--------------|
+//! | A        | 50% read, 50% update            | zipfian      |
+//! | B        | 95% read, 5% update             | zipfian      |
+//! | C        | 100% read                       | zipfian      |
+//! | D        | 95% read, 5% insert             | latest       |
+//! | E        | 95% scan, 5% insert             | zipfian      |
+//! | F        | 50% read, 50% read-modify-write | zipfian      |
+//!
+//! Records are `user` followed by a hash of the record number, holding
+//! `--field-count` fields of `--field-length` random printable bytes.
+//! Reads fetch whole records; updates replace one field, reading the
+//! record and writing it back as YCSB's RocksDB binding does. Scans read
+//! up to `--max-scan-length` records, a uniformly chosen number of them.
+//! `run` must be given the `--record-count` the database was loaded with.
+
+mod generator;
+#[path = "../ferrisdb-bench/histogram.rs"]
+mod histogram;
+
+use ferrisdb_client::{Client, ClientOptions, Scan};
+use ferrisdb_core::{Key, Value};
+use ferrisdb_storage::{Options, ReadOptions, StorageEngine};
+use generator::{fnv_hash64, Distribution, KeyChooser};
+use histogram::Histogram;
+
+use clap::{Parser, ValueEnum};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Errors running a workload
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    /// The embedded engine failed
+    #[error(transparent)]
+    Storage(#[from] ferrisdb_core::Error),
+
+    /// A request to the server failed
+    #[error(transparent)]
+    Client(#[from] ferrisdb_client::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Phases of a YCSB benchmark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Phase {
+    /// Inserts the records the workloads run against
+    Load,
+    /// Runs the workload's mix of operations
+    Run,
+}
+
+/// YCSB's core workloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Workload {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+impl Workload {
+    /// Returns the share of each operation, in the order of [`Operation::ALL`]
+    fn mix(self) -> [f64; 5] {
+        match self {
+            Workload::A => [0.0, 0.5, 0.5, 0.0, 0.0],
+            Workload::B => [0.0, 0.95, 0.05, 0.0, 0.0],
+            Workload::C => [0.0, 1.0, 0.0, 0.0, 0.0],
+            Workload::D => [0.05, 0.95, 0.0, 0.0, 0.0],
+            Workload::E => [0.05, 0.0, 0.0, 0.95, 0.0],
+            Workload::F => [0.0, 0.5, 0.0, 0.0, 0.5],
+        }
+    }
+
+    fn distribution(self) -> Distribution {
+        match self {
+            Workload::D => Distribution::Latest,
+            _ => Distribution::Zipfian,
+        }
+    }
+}
+
+/// Operations of the workloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Insert,
+    Read,
+    Update,
+    Scan,
+    ReadModifyWrite,
+}
+
+impl Operation {
+    const ALL: [Operation; 5] = [
+        Operation::Insert,
+        Operation::Read,
+        Operation::Update,
+        Operation::Scan,
+        Operation::ReadModifyWrite,
+    ];
+
+    /// Returns the name YCSB reports the operation under
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Insert => "INSERT",
+            Operation::Read => "READ",
+            Operation::Update => "UPDATE",
+            Operation::Scan => "SCAN",
+            Operation::ReadModifyWrite => "READ-MODIFY-WRITE",
+        }
+    }
+}
+
+/// Runs YCSB workloads against FerrisDB, embedded or over gRPC
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Database directory to open in-process
+    #[arg(short, long, required_unless_present = "server")]
+    data_dir: Option<PathBuf>,
+
+    /// Server to connect to, e.g. http://127.0.0.1:7070
+    #[arg(short, long, conflicts_with = "data_dir")]
+    server: Option<String>,
+
+    /// Bearer token for the server
+    #[arg(
+        long,
+        env = "FERRISDB_TOKEN",
+        hide_env_values = true,
+        requires = "server"
+    )]
+    token: Option<String>,
+
+    /// Phase to run
+    #[arg(value_enum)]
+    phase: Phase,
+
+    /// Workload to run
+    #[arg(short, long, value_enum, default_value = "a")]
+    workload: Workload,
+
+    /// Records loaded, or already loaded when running
+    #[arg(long, default_value_t = 1000)]
+    record_count: u64,
+
+    /// Operations run
+    #[arg(long, default_value_t = 1000)]
+    operation_count: u64,
+
+    /// Request distribution; the workload's if omitted
+    #[arg(long, value_enum)]
+    distribution: Option<Distribution>,
+
+    /// Fields per record
+    #[arg(long, default_value_t = 10)]
+    field_count: usize,
+
+    /// Bytes per field
+    #[arg(long, default_value_t = 100)]
+    field_length: usize,
+
+    /// Most records a scan reads
+    #[arg(long, default_value_t = 1000)]
+    max_scan_length: usize,
+
+    /// Client threads sharing the operations
+    #[arg(short, long, default_value_t = 1)]
+    threads: usize,
+
+    /// Prints a latency histogram of each operation
+    #[arg(long)]
+    histogram: bool,
+
+    /// Seed of the random choices
+    #[arg(long, default_value_t = 301)]
+    seed: u64,
+}
+
+/// Store the workload runs against
+enum Db {
+    /// An engine opened in this process
+    Embedded(StorageEngine),
+    /// A server reached over gRPC, with the runtime driving its requests
+    Remote {
+        client: Client,
+        runtime: tokio::runtime::Handle,
+    },
+}
+
+impl Db {
+    fn read(&self, key: &[u8]) -> Result<Option<Value>> {
+        Ok(match self {
+            Db::Embedded(engine) => engine.get(key)?,
+            Db::Remote { client, runtime } => runtime.block_on(client.get(key))?,
+        })
+    }
+
+    fn write(&self, key: Key, value: Value) -> Result<()> {
+        match self {
+            Db::Embedded(engine) => engine.put(key, value)?,
+            Db::Remote { client, runtime } => runtime.block_on(client.put(key, value))?,
+        }
+        Ok(())
+    }
+
+    /// Reads up to `count` records from `start` on and returns how many
+    /// there were
+    fn scan(&self, start: &[u8], count: usize) -> Result<usize> {
+        match self {
+            Db::Embedded(engine) => {
+                let mut iter = engine.iter(ReadOptions::new())?;
+                iter.seek(start)?;
+                let mut records = 0;
+                while records < count && iter.valid() {
+                    records += 1;
+                    iter.next()?;
+                }
+                Ok(records)
+            }
+            Db::Remote { client, runtime } => {
+                let scan = Scan::starting_at(start.to_vec()).with_limit(count as u32);
+                Ok(runtime.block_on(client.scan(&scan))?.pairs.len())
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match (&cli.data_dir, &cli.server) {
+        (Some(dir), _) => {
+            let engine = StorageEngine::open(Options::new(dir))?;
+            let db = Db::Embedded(engine);
+            let report = Runner::new(&db, &cli).run();
+            // Closing reports write failures that dropping would only log
+            if let Db::Embedded(engine) = db {
+                engine.close()?;
+            }
+            print!("{}", report?);
+        }
+        (None, Some(server)) => {
+            let runtime = tokio::runtime::Runtime::new().map_err(ferrisdb_core::Error::from)?;
+            let mut options = ClientOptions::new(server.as_str());
+            if let Some(token) = &cli.token {
+                options = options.with_bearer_token(token.as_str());
+            }
+            let client = runtime.block_on(Client::connect_with(options))?;
+            let db = Db::Remote {
+                client,
+                runtime: runtime.handle().clone(),
+            };
+            print!("{}", Runner::new(&db, &cli).run()?);
+        }
+        (None, None) => unreachable!("clap requires one of them"),
+    }
+    Ok(())
+}
+
+/// Latencies and outcomes of one kind of operation
+#[derive(Default)]
+struct OperationStats {
+    histogram: Histogram,
+    ok: u64,
+    not_found: u64,
+}
+
+/// What a phase did, per operation in the order of [`Operation::ALL`]
+struct Report {
+    elapsed: Duration,
+    operations: [OperationStats; 5],
+    histograms: bool,
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        for (stats, other) in self.operations.iter_mut().zip(other.operations) {
+            stats.histogram.merge(&other.histogram);
+            stats.ok += other.ok;
+            stats.not_found += other.not_found;
+        }
+    }
+
+    fn stats(&mut self, operation: Operation) -> &mut OperationStats {
+        &mut self.operations[operation as usize]
+    }
+}
+
+/// Prints the report as YCSB does, in microseconds
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: u64 = self
+            .operations
+            .iter()
+            .map(|stats| stats.histogram.count())
+            .sum();
+        let seconds = self.elapsed.as_secs_f64().max(1e-9);
+        writeln!(f, "[OVERALL], RunTime(ms), {}", self.elapsed.as_millis())?;
+        writeln!(
+            f,
+            "[OVERALL], Throughput(ops/sec), {:.1}",
+            total as f64 / seconds
+        )?;
+        for (operation, stats) in Operation::ALL.iter().zip(&self.operations) {
+            let histogram = &stats.histogram;
+            if histogram.count() == 0 {
+                continue;
+            }
+            let name = operation.name();
+            let micros = |nanos: f64| nanos / 1000.0;
+            writeln!(f, "[{}], Operations, {}", name, histogram.count())?;
+            writeln!(
+                f,
+                "[{}], AverageLatency(us), {:.3}",
+                name,
+                micros(histogram.mean())
+            )?;
+            writeln!(
+                f,
+                "[{}], MinLatency(us), {:.3}",
+                name,
+                micros(histogram.min() as f64)
+            )?;
+            writeln!(
+                f,
+                "[{}], MaxLatency(us), {:.3}",
+                name,
+                micros(histogram.max() as f64)
+            )?;
+            for p in [50.0, 95.0, 99.0] {
+                writeln!(
+                    f,
+                    "[{}], {}thPercentileLatency(us), {:.3}",
+                    name,
+                    p,
+                    micros(histogram.percentile(p))
+                )?;
+            }
+            writeln!(f, "[{}], Return=OK, {}", name, stats.ok)?;
+            if stats.not_found > 0 {
+                writeln!(f, "[{}], Return=NOT_FOUND, {}", name, stats.not_found)?;
+            }
+            if self.histograms {
+                writeln!(f, "[{}], Histogram(us):\n{}", name, histogram)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Runner<'a> {
+    db: &'a Db,
+    cli: &'a Cli,
+    /// Number of the next record to insert
+    next_insert: AtomicU64,
+    /// Records known to be inserted, which operations choose from
+    inserted: Acknowledged,
+    /// Chooser each thread starts from a copy of
+    chooser: KeyChooser,
+}
+
+impl<'a> Runner<'a> {
+    fn new(db: &'a Db, cli: &'a Cli) -> Self {
+        let distribution = cli
+            .distribution
+            .unwrap_or_else(|| cli.workload.distribution());
+        Self {
+            db,
+            cli,
+            next_insert: AtomicU64::new(cli.record_count),
+            inserted: Acknowledged::new(cli.record_count),
+            chooser: KeyChooser::new(distribution, cli.record_count),
+        }
+    }
+
+    /// Runs the phase on `--threads` threads and reports on it
+    fn run(&self) -> Result<Report> {
+        let total = match self.cli.phase {
+            Phase::Load => self.cli.record_count,
+            Phase::Run => self.cli.operation_count,
+        };
+        let threads = self.cli.threads.max(1) as u64;
+        let start = Instant::now();
+        let results: Vec<Result<Report>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|thread| {
+                    let ops = total * thread / threads..total * (thread + 1) / threads;
+                    let seed = self.cli.seed + thread;
+                    scope.spawn(move || self.run_thread(ops, seed))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("client thread panicked"))
+                .collect()
+        });
+
+        let mut report = self.empty_report();
+        report.elapsed = start.elapsed();
+        for result in results {
+            report.merge(result?);
+        }
+        Ok(report)
+    }
+
+    fn empty_report(&self) -> Report {
+        Report {
+            elapsed: Duration::ZERO,
+            operations: Default::default(),
+            histograms: self.cli.histogram,
+        }
+    }
+
+    fn run_thread(&self, ops: Range<u64>, seed: u64) -> Result<Report> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut chooser = self.chooser.clone();
+        let mut report = self.empty_report();
+        let mix = self.cli.workload.mix();
+        for index in ops {
+            let operation = match self.cli.phase {
+                Phase::Load => Operation::Insert,
+                Phase::Run => {
+                    let mut choice: f64 = rng.random();
+                    Operation::ALL
+                        .into_iter()
+                        .zip(mix)
+                        .find(|&(_, share)| {
+                            choice -= share;
+                            choice < 0.0
+                        })
+                        .map_or(Operation::Read, |(operation, _)| operation)
+                }
+            };
+            let record = match (self.cli.phase, operation) {
+                (Phase::Load, _) => index,
+                (Phase::Run, Operation::Insert) => self.next_insert.fetch_add(1, Ordering::Relaxed),
+                (Phase::Run, _) => chooser.next(&mut rng, self.inserted.limit()),
+            };
+
+            let start = Instant::now();
+            let found = self.execute(operation, key(record), &mut rng)?;
+            if (self.cli.phase, operation) == (Phase::Run, Operation::Insert) {
+                self.inserted.acknowledge(record);
+            }
+            let stats = report.stats(operation);
+            stats.histogram.record(start.elapsed().as_nanos() as u64);
+            if found {
+                stats.ok += 1;
+            } else {
+                stats.not_found += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Runs one operation on record `key` and returns whether it was there
+    fn execute(&self, operation: Operation, key: Key, rng: &mut StdRng) -> Result<bool> {
+        match operation {
+            Operation::Insert => {
+                let fields: Vec<_> = (0..self.cli.field_count).map(|_| self.field(rng)).collect();
+                self.db.write(key, encode_record(&fields))?;
+                Ok(true)
+            }
+            Operation::Read => Ok(self.db.read(&key)?.is_some()),
+            Operation::Update | Operation::ReadModifyWrite => {
+                let Some(record) = self.db.read(&key)? else {
+                    return Ok(false);
+                };
+                let mut fields = decode_record(&record)?;
+                if !fields.is_empty() {
+                    let index = rng.random_range(0..fields.len());
+                    fields[index] = self.field(rng);
+                }
+                self.db.write(key, encode_record(&fields))?;
+                Ok(true)
+            }
+            Operation::Scan => {
+                let count = rng.random_range(1..=self.cli.max_scan_length.max(1));
+                Ok(self.db.scan(&key, count)? > 0)
+            }
+        }
+    }
+
+    /// Returns a field value of random printable bytes
+    fn field(&self, rng: &mut StdRng) -> Vec<u8> {
+        (0..self.cli.field_length)
+            .map(|_| rng.random_range(b' '..=b'~'))
+            .collect()
+    }
+}
+
+/// Tracks which records inserted concurrently are done
+///
+/// Inserts finish out of order, so operations only go to the records
+/// below the first one still being inserted, as with YCSB's
+/// acknowledged counter.
+struct Acknowledged {
+    limit: AtomicU64,
+    /// Records done above the limit
+    done: Mutex<BTreeSet<u64>>,
+}
+
+impl Acknowledged {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit: AtomicU64::new(limit),
+            done: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the number of records, all of which are inserted
+    fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Acquire)
+    }
+
+    /// Marks `record` inserted
+    fn acknowledge(&self, record: u64) {
+        let mut done = self.done.lock().expect("acknowledged records poisoned");
+        done.insert(record);
+        let mut limit = self.limit.load(Ordering::Relaxed);
+        while done.remove(&limit) {
+            limit += 1;
+        }
+        self.limit.store(limit, Ordering::Release);
+    }
+}
+
+/// Returns the key of record number `record`
+fn key(record: u64) -> Key {
+    format!("user{}", fnv_hash64(record)).into_bytes()
+}
+
+/// Encodes fields `field0`, `field1`, ... as length-prefixed names and
+/// values
+fn encode_record(fields: &[Vec<u8>]) -> Value {
+    let mut record = Vec::new();
+    for (index, value) in fields.iter().enumerate() {
+        let name = format!("field{}", index);
+        record.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        record.extend_from_slice(name.as_bytes());
+        record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        record.extend_from_slice(value);
+    }
+    record
+}
+
+/// Decodes the field values of a record written by [`encode_record`]
+fn decode_record(mut record: &[u8]) -> Result<Vec<Vec<u8>>> {
+    fn take<'a>(record: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = u32::from_le_bytes(record.get(..4)?.try_into().ok()?) as usize;
+        let bytes = record.get(4..4 + len)?;
+        *record = &record[4 + len..];
+        Some(bytes)
+    }
+    let mut fields = Vec::new();
+    while !record.is_empty() {
+        let (Some(_name), Some(value)) = (take(&mut record), take(&mut record)) else {
+            return Err(
+                ferrisdb_core::Error::InvalidFormat("Truncated YCSB record".to_string()).into(),
+            );
+        };
+        fields.push(value.to_vec());
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_server::{Server, ServerConfig};
+    use tempfile::TempDir;
+
+    fn parse(target: &[&str], args: &[&str]) -> Cli {
+        Cli::parse_from(["ferrisdb-ycsb"].iter().chain(target).chain(args).copied())
+    }
+
+    /// Loads 200 records and runs every workload over them
+    fn load_and_run(db: &Db, target: &[&str]) {
+        let load = parse(target, &["load", "--record-count", "200", "--threads", "2"]);
+        let report = Runner::new(db, &load).run().unwrap();
+        assert_eq!(report.operations[0].ok, 200);
+        assert!(report.to_string().contains("[INSERT], Operations, 200\n"));
+
+        for workload in ["a", "b", "c", "d", "e", "f"] {
+            let cli = parse(
+                target,
+                &[
+                    "run",
+                    "--workload",
+                    workload,
+                    "--record-count",
+                    "200",
+                    "--operation-count",
+                    "300",
+                    "--max-scan-length",
+                    "10",
+                    "--threads",
+                    "3",
+                ],
+            );
+            let report = Runner::new(db, &cli).run().unwrap();
+            let total: u64 = report.operations.iter().map(|stats| stats.ok).sum();
+            // Every record chosen was loaded or inserted before
+            assert_eq!(total, 300, "workload {workload}");
+            let text = report.to_string();
+            assert!(text.starts_with("[OVERALL], RunTime(ms), "), "{text}");
+            let expected = match workload {
+                "a" => "[UPDATE], Operations, ",
+                "d" | "e" => "[INSERT], Operations, ",
+                "f" => "[READ-MODIFY-WRITE], Operations, ",
+                _ => "[READ], Operations, ",
+            };
+            assert!(text.contains(expected), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_workloads_run_against_an_embedded_engine() {
+        let dir = TempDir::new().unwrap();
+        let target = ["--data-dir", dir.path().to_str().unwrap()];
+        let db = Db::Embedded(StorageEngine::open(Options::new(dir.path())).unwrap());
+        load_and_run(&db, &target);
+
+        // Updates replace one field and keep the others
+        let Db::Embedded(engine) = &db else {
+            unreachable!()
+        };
+        let fields = decode_record(&engine.get(&key(7)).unwrap().unwrap()).unwrap();
+        assert_eq!(fields.len(), 10);
+        assert!(fields.iter().all(|field| field.len() == 100));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_workloads_run_against_a_server() {
+        let dir = TempDir::new().unwrap();
+        let server = Server::open(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            api_tokens: vec!["t0ken".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve_with_listener(listener, async {
+            let _ = stopped.await;
+        }));
+
+        let client =
+            Client::connect_with(ClientOptions::new(addr.as_str()).with_bearer_token("t0ken"))
+                .await
+                .unwrap();
+        let db = Db::Remote {
+            client,
+            runtime: tokio::runtime::Handle::current(),
+        };
+        let target = ["--server", addr.as_str()];
+        tokio::task::block_in_place(|| load_and_run(&db, &target));
+
+        stop.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let fields = vec![b"a".to_vec(), Vec::new(), b"ccc".to_vec()];
+        let record = encode_record(&fields);
+        assert_eq!(decode_record(&record).unwrap(), fields);
+        assert!(decode_record(&record[..record.len() - 1]).is_err());
+        assert_eq!(key(0), b"user6284781860667377211");
+    }
+}
@@ -0,0 +1,403 @@
+//! The commands the CLI understands and the databases it runs them on
+//!
+//! A command runs against a [`Backend`]: an engine opened in-process
+//! from its data directory, or a server reached over gRPC. Output is
+//! returned as text, one line per key-value pair or statistic, with
+//! bytes that aren't printable ASCII escaped.
+
+use crate::{Error, Result};
+use ferrisdb_client::{Client, Scan, WriteBatch};
+use ferrisdb_core::keys::prefix_successor;
+use ferrisdb_core::{Key, Value};
+use ferrisdb_storage::storage_engine::DEFAULT_COLUMN_FAMILY;
+use ferrisdb_storage::{ColumnFamily, StorageEngine};
+
+use clap::{Args, Subcommand};
+
+use std::fmt::Write;
+use std::ops::Bound;
+
+/// A single database operation
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Print the value of a key
+    Get {
+        key: String,
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Set the value of a key
+    Put {
+        key: String,
+        value: String,
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Delete a key
+    Delete {
+        key: String,
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Print the pairs in [START, END), in key order
+    Scan {
+        /// First key, inclusive; the beginning if omitted
+        start: Option<String>,
+        /// Last key, exclusive; the end if omitted
+        end: Option<String>,
+        /// Only keys starting with these bytes
+        #[arg(short, long)]
+        prefix: Option<String>,
+        /// Most pairs to print
+        #[arg(short, long, default_value_t = 100)]
+        limit: u32,
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Write the MemTables to SSTables
+    Flush,
+    /// Compact the tables holding keys in [START, END)
+    Compact {
+        /// First key, inclusive; the beginning if omitted
+        start: Option<String>,
+        /// Last key, exclusive; the end if omitted
+        end: Option<String>,
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Print engine statistics
+    Stats {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Print the slowest recent operations, newest first
+    SlowLog {
+        /// Most operations to print
+        #[arg(short, long, default_value_t = 20)]
+        limit: u32,
+        /// Empty the log after printing it
+        #[arg(long)]
+        reset: bool,
+    },
+}
+
+/// The column family a command works on
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct Target {
+    /// Column family; the default one if omitted
+    #[arg(long = "cf")]
+    pub column_family: Option<String>,
+}
+
+impl Target {
+    fn name(&self) -> &str {
+        self.column_family.as_deref().unwrap_or("")
+    }
+}
+
+/// The database commands run against
+pub enum Backend {
+    /// An engine opened in this process
+    Embedded(StorageEngine),
+    /// A server reached over gRPC
+    Remote(Client),
+}
+
+impl Backend {
+    /// Runs a command, returning what it prints
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` for maintenance commands a server
+    /// doesn't expose, or the error of the operation itself.
+    pub async fn execute(&self, command: Command) -> Result<String> {
+        match self {
+            Backend::Embedded(engine) => execute_embedded(engine, command),
+            Backend::Remote(client) => execute_remote(client, command).await,
+        }
+    }
+}
+
+fn execute_embedded(engine: &StorageEngine, command: Command) -> Result<String> {
+    let output = match command {
+        Command::Get { key, target } => {
+            let cf = column_family(engine, &target)?;
+            format_value(engine.get_cf(&cf, key.as_bytes())?)
+        }
+        Command::Put { key, value, target } => {
+            let cf = column_family(engine, &target)?;
+            engine.put_cf(&cf, key.into_bytes(), value.into_bytes())?;
+            "OK".to_string()
+        }
+        Command::Delete { key, target } => {
+            let cf = column_family(engine, &target)?;
+            engine.delete_cf(&cf, key.into_bytes())?;
+            "OK".to_string()
+        }
+        Command::Scan {
+            start,
+            end,
+            prefix,
+            limit,
+            target,
+        } => {
+            let cf = column_family(engine, &target)?;
+            let range = scan_range(start, end, prefix);
+            // One pair past the limit tells whether more remain
+            let mut pairs = engine
+                .snapshot()
+                .scan_cf_limit(&cf, range, limit as usize + 1)?;
+            let more = pairs.len() > limit as usize;
+            pairs.truncate(limit as usize);
+            format_pairs(&pairs, more)
+        }
+        Command::Flush => {
+            engine.flush()?;
+            "OK".to_string()
+        }
+        Command::Compact { start, end, target } => {
+            let cf = column_family(engine, &target)?;
+            engine.compact_range_cf(&cf, scan_range(start, end, None))?;
+            "OK".to_string()
+        }
+        Command::Stats { target } => {
+            let cf = column_family(engine, &target)?;
+            engine.statistics_cf(&cf)?.to_string()
+        }
+        Command::SlowLog { limit, reset } => {
+            let log = engine.slow_log();
+            if reset {
+                engine.clear_slow_log();
+            }
+            let mut output = String::new();
+            for operation in log.iter().take(limit as usize) {
+                let _ = writeln!(output, "{}", operation);
+            }
+            let _ = write!(output, "({} operations)", log.len());
+            output
+        }
+    };
+    Ok(output)
+}
+
+async fn execute_remote(client: &Client, command: Command) -> Result<String> {
+    let output = match command {
+        Command::Get { key, target } => {
+            format_value(client.get_cf(target.name(), key.as_bytes()).await?)
+        }
+        Command::Put { key, value, target } => {
+            let mut batch = WriteBatch::new();
+            batch.put_cf(target.name(), key.into_bytes(), value.into_bytes());
+            client.write(batch).await?;
+            "OK".to_string()
+        }
+        Command::Delete { key, target } => {
+            let mut batch = WriteBatch::new();
+            batch.delete_cf(target.name(), key.into_bytes());
+            client.write(batch).await?;
+            "OK".to_string()
+        }
+        Command::Scan {
+            start,
+            end,
+            prefix,
+            limit,
+            target,
+        } => {
+            let mut scan = Scan::range(
+                start.unwrap_or_default().into_bytes(),
+                end.unwrap_or_default().into_bytes(),
+            )
+            .with_column_family(target.name())
+            .with_limit(limit);
+            if let Some(prefix) = prefix {
+                scan = scan.with_prefix(prefix.into_bytes());
+            }
+            let page = client.scan(&scan).await?;
+            format_pairs(&page.pairs, page.continuation_token.is_some())
+        }
+        Command::Flush
+        | Command::Compact { .. }
+        | Command::Stats { .. }
+        | Command::SlowLog { .. } => {
+            return Err(Error::Unsupported(
+                "this command needs an embedded database (--data-dir)".to_string(),
+            ))
+        }
+    };
+    Ok(output)
+}
+
+fn column_family(engine: &StorageEngine, target: &Target) -> Result<ColumnFamily> {
+    let name = target
+        .column_family
+        .as_deref()
+        .unwrap_or(DEFAULT_COLUMN_FAMILY);
+    engine.cf_handle(name).ok_or_else(|| {
+        Error::Storage(ferrisdb_core::Error::InvalidOperation(format!(
+            "Column family {:?} does not exist",
+            name
+        )))
+    })
+}
+
+/// Turns optional bounds and a prefix into a key range
+fn scan_range(
+    start: Option<String>,
+    end: Option<String>,
+    prefix: Option<String>,
+) -> (Bound<Key>, Bound<Key>) {
+    let mut start = start.map(String::into_bytes).unwrap_or_default();
+    let mut end = end.map_or(Bound::Unbounded, |end| Bound::Excluded(end.into_bytes()));
+    if let Some(prefix) = prefix.map(String::into_bytes) {
+        if let Some(successor) = prefix_successor(&prefix) {
+            let narrower = match &end {
+                Bound::Excluded(end) => successor < *end,
+                _ => true,
+            };
+            if narrower {
+                end = Bound::Excluded(successor);
+            }
+        }
+        start = start.max(prefix);
+    }
+    (Bound::Included(start), end)
+}
+
+fn format_value(value: Option<Value>) -> String {
+    match value {
+        Some(value) => escape(&value),
+        None => "(not found)".to_string(),
+    }
+}
+
+fn format_pairs(pairs: &[(Key, Value)], more: bool) -> String {
+    let mut output = String::new();
+    for (key, value) in pairs {
+        let _ = writeln!(output, "{} => {}", escape(key), escape(value));
+    }
+    let _ = write!(
+        output,
+        "({} pairs{})",
+        pairs.len(),
+        if more { ", more" } else { "" }
+    );
+    output
+}
+
+/// Renders bytes as text, escaping anything but printable ASCII
+pub(crate) fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_storage::Options;
+    use tempfile::TempDir;
+
+    use std::time::Duration;
+
+    fn target() -> Target {
+        Target::default()
+    }
+
+    async fn run(backend: &Backend, command: Command) -> String {
+        backend.execute(command).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_commands_on_an_embedded_engine() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path()).with_slow_log_threshold(Duration::from_micros(1));
+        let backend = Backend::Embedded(StorageEngine::open(options).unwrap());
+
+        for (key, value) in [("user:1", "alice"), ("user:2", "bob"), ("order:1", "x")] {
+            let put = Command::Put {
+                key: key.to_string(),
+                value: value.to_string(),
+                target: target(),
+            };
+            assert_eq!(run(&backend, put).await, "OK");
+        }
+        let get = |key: &str| Command::Get {
+            key: key.to_string(),
+            target: target(),
+        };
+        assert_eq!(run(&backend, get("user:1")).await, "alice");
+
+        let scan = Command::Scan {
+            start: None,
+            end: None,
+            prefix: Some("user:".to_string()),
+            limit: 1,
+            target: target(),
+        };
+        assert_eq!(
+            run(&backend, scan).await,
+            "user:1 => alice\n(1 pairs, more)"
+        );
+
+        let delete = Command::Delete {
+            key: "user:1".to_string(),
+            target: target(),
+        };
+        run(&backend, delete).await;
+        assert_eq!(run(&backend, get("user:1")).await, "(not found)");
+
+        assert_eq!(run(&backend, Command::Flush).await, "OK");
+        let compact = Command::Compact {
+            start: None,
+            end: None,
+            target: target(),
+        };
+        assert_eq!(run(&backend, compact).await, "OK");
+        let stats = run(&backend, Command::Stats { target: target() }).await;
+        assert!(stats.contains("compactions: 1,"), "{}", stats);
+
+        let slow_log = |reset| Command::SlowLog { limit: 1, reset };
+        let log = run(&backend, slow_log(true)).await;
+        assert!(log.starts_with("compaction cf=default took"), "{}", log);
+        assert_eq!(log.lines().count(), 2);
+        assert_eq!(run(&backend, slow_log(false)).await, "(0 operations)");
+
+        let missing = Command::Get {
+            key: "a".to_string(),
+            target: Target {
+                column_family: Some("missing".to_string()),
+            },
+        };
+        assert!(backend.execute(missing).await.is_err());
+    }
+
+    #[test]
+    fn test_scan_range_intersects_bounds_with_prefix() {
+        assert_eq!(
+            scan_range(None, None, Some("ab".to_string())),
+            (
+                Bound::Included(b"ab".to_vec()),
+                Bound::Excluded(b"ac".to_vec())
+            )
+        );
+        assert_eq!(
+            scan_range(
+                Some("abc".to_string()),
+                Some("abd".to_string()),
+                Some("ab".to_string())
+            ),
+            (
+                Bound::Included(b"abc".to_vec()),
+                Bound::Excluded(b"abd".to_vec())
+            )
+        );
+        assert_eq!(
+            scan_range(None, None, None),
+            (Bound::Included(Vec::new()), Bound::Unbounded)
+        );
+    }
+
+    #[test]
+    fn test_unprintable_bytes_are_escaped() {
+        assert_eq!(escape(b"a\x00\xff\n"), "a\\x00\\xff\\n");
+    }
+}
@@ -0,0 +1,148 @@
+//! FerrisDB command-line interface
+//!
+//! Runs one command, or starts an interactive session when none is given,
+//! against a database directory or a running server:
+//!
+//! ```text
+//! ferrisdb-cli --data-dir ./data put greeting hello
+//! ferrisdb-cli --data-dir ./data scan --prefix user: --limit 10
+//! ferrisdb-cli --server http://127.0.0.1:7070 --token $TOKEN get greeting
+//! ferrisdb-cli --server https://db:7070 --tls-ca ca.pem get greeting
+//! ferrisdb-cli --data-dir ./data            # interactive
+//! ferrisdb-cli --data-dir ./data --slow-log-threshold-us 1000
+//! ```
+//!
+//! The token can also be passed in `FERRISDB_TOKEN`, which keeps it out
+//! of the shell history.
+//!
+//! A directory is opened in-process, so no server may have it open at the
+//! same time. `flush`, `compact`, `stats` and `slow-log` only work on a
+//! directory. The slow log lives in memory, so it is mostly useful in an
+//! interactive session opened with `--slow-log-threshold-us`.
+
+mod command;
+mod repl;
+
+use command::{Backend, Command};
+use ferrisdb_client::{Client, ClientOptions, TlsOptions};
+use ferrisdb_storage::{Options, StorageEngine};
+
+use clap::Parser;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Errors reported by the CLI
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The embedded engine failed
+    #[error(transparent)]
+    Storage(#[from] ferrisdb_core::Error),
+
+    /// A request to the server failed
+    #[error(transparent)]
+    Client(#[from] ferrisdb_client::Error),
+
+    /// The terminal couldn't be read
+    #[error("Readline error: {0}")]
+    Readline(#[from] rustyline::error::ReadlineError),
+
+    /// The command isn't available on this backend
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+}
+
+/// A specialized Result type for CLI operations
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Reads, writes and maintains a FerrisDB database
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Database directory to open in-process
+    #[arg(short, long, required_unless_present = "server")]
+    data_dir: Option<PathBuf>,
+
+    /// Server to connect to, e.g. http://127.0.0.1:7070
+    #[arg(short, long, conflicts_with = "data_dir")]
+    server: Option<String>,
+
+    /// Bearer token for the server
+    #[arg(
+        long,
+        env = "FERRISDB_TOKEN",
+        hide_env_values = true,
+        requires = "server"
+    )]
+    token: Option<String>,
+
+    /// PEM file with the CA the server's certificate is checked against;
+    /// connects over TLS
+    #[arg(long, requires = "server")]
+    tls_ca: Option<PathBuf>,
+
+    /// Keep operations taking at least this many microseconds in the
+    /// slow log, which `slow-log` prints
+    #[arg(long, requires = "data_dir")]
+    slow_log_threshold_us: Option<u64>,
+
+    /// Command to run; starts an interactive session if omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let backend = match (cli.data_dir, cli.server) {
+        (Some(dir), _) => {
+            let mut options = Options::new(dir);
+            if let Some(threshold) = cli.slow_log_threshold_us {
+                options = options.with_slow_log_threshold(Duration::from_micros(threshold));
+            }
+            Backend::Embedded(StorageEngine::open(options)?)
+        }
+        (None, Some(server)) => {
+            let mut options = ClientOptions::new(server);
+            if let Some(token) = cli.token {
+                options = options.with_bearer_token(token);
+            }
+            if let Some(path) = cli.tls_ca {
+                let ca = std::fs::read(&path).map_err(|e| {
+                    ferrisdb_client::Error::Config(format!(
+                        "Failed to read {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                options = options.with_tls(TlsOptions::new(ca));
+            }
+            Backend::Remote(Client::connect_with(options).await?)
+        }
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+
+    let result = match cli.command {
+        Some(command) => backend
+            .execute(command)
+            .await
+            .map(|output| println!("{}", output)),
+        None => repl::run(&backend).await,
+    };
+
+    // Closing reports write failures that dropping would only log
+    if let Backend::Embedded(engine) = &backend {
+        engine.close()?;
+    }
+    result
+}
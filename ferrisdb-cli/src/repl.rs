@@ -0,0 +1,131 @@
+//! Interactive mode: one command per line, with line editing and history
+//!
+//! Lines are split like a shell would, so keys with spaces can be quoted:
+//!
+//! ```text
+//! ferrisdb> put "user 1" alice
+//! OK
+//! ferrisdb> scan --prefix user
+//! user 1 => alice
+//! (1 pairs)
+//! ```
+//!
+//! History is kept in `~/.ferrisdb_history` across sessions.
+
+use crate::command::{Backend, Command};
+use crate::Result;
+
+use clap::Parser;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use std::path::PathBuf;
+
+const PROMPT: &str = "ferrisdb> ";
+
+/// A command typed at the prompt
+#[derive(Debug, Parser)]
+#[command(no_binary_name = true, disable_version_flag = true)]
+struct Line {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// What a line of input asks for
+#[derive(Debug, PartialEq)]
+enum Input {
+    Command(Command),
+    /// Output to show without running anything, e.g. help or a usage error
+    Message(String),
+    Empty,
+    Quit,
+}
+
+/// Reads and runs commands until the user quits
+///
+/// # Errors
+///
+/// Returns `Error::Readline` if the terminal can't be read. Failing
+/// commands are reported and the loop goes on.
+pub async fn run(backend: &Backend) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // Missing on first use
+        let _ = editor.load_history(path);
+    }
+
+    println!("Type `help` for commands, `quit` to exit");
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            // Ctrl-C abandons the line, Ctrl-D ends the session
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+
+        match parse(&line) {
+            Input::Command(command) => match backend.execute(command).await {
+                Ok(output) => println!("{}", output),
+                Err(e) => eprintln!("error: {}", e),
+            },
+            Input::Message(message) => println!("{}", message.trim_end()),
+            Input::Empty => {}
+            Input::Quit => break,
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("warning: failed to save history: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn parse(line: &str) -> Input {
+    let Some(words) = shlex::split(line) else {
+        return Input::Message("error: unterminated quote".to_string());
+    };
+    match words.first().map(String::as_str) {
+        None => Input::Empty,
+        Some("quit" | "exit") => Input::Quit,
+        Some(_) => match Line::try_parse_from(words) {
+            Ok(line) => Input::Command(line.command),
+            Err(e) => Input::Message(e.render().to_string()),
+        },
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ferrisdb_history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Target;
+
+    #[test]
+    fn test_lines_parse_into_commands() {
+        assert_eq!(
+            parse(r#"put "user 1" 'alice smith' --cf users"#),
+            Input::Command(Command::Put {
+                key: "user 1".to_string(),
+                value: "alice smith".to_string(),
+                target: Target {
+                    column_family: Some("users".to_string()),
+                },
+            })
+        );
+        assert_eq!(parse("   "), Input::Empty);
+        assert_eq!(parse("quit"), Input::Quit);
+        assert!(matches!(parse("get"), Input::Message(_)));
+        assert!(matches!(parse("help"), Input::Message(_)));
+        assert!(matches!(parse("get \"open"), Input::Message(_)));
+    }
+}
@@ -0,0 +1,16 @@
+//! Generates the gRPC client from the server's protobuf schema
+//!
+//! Uses the vendored `protoc` so building doesn't require one installed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::configure()
+        .build_server(false)
+        .compile_protos(
+            &["../ferrisdb-server/proto/ferrisdb.proto"],
+            &["../ferrisdb-server/proto"],
+        )?;
+    Ok(())
+}
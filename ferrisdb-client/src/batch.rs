@@ -0,0 +1,90 @@
+//! Writes sent together and applied atomically
+
+use crate::proto::{mutation, Mutation};
+use ferrisdb_core::{Key, Value};
+
+/// A set of writes the server applies atomically
+///
+/// Column families are named; the unsuffixed methods write to the default
+/// one.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    pub(crate) mutations: Vec<Mutation>,
+}
+
+impl WriteBatch {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a key
+    pub fn put(&mut self, key: Key, value: Value) {
+        self.put_cf("", key, value);
+    }
+
+    /// Sets the value of a key in a column family
+    pub fn put_cf(&mut self, cf: &str, key: Key, value: Value) {
+        self.push(cf, mutation::Op::Put(mutation::Put { key, value }));
+    }
+
+    /// Deletes a key
+    pub fn delete(&mut self, key: Key) {
+        self.delete_cf("", key);
+    }
+
+    /// Deletes a key in a column family
+    pub fn delete_cf(&mut self, cf: &str, key: Key) {
+        self.push(cf, mutation::Op::Delete(mutation::Delete { key }));
+    }
+
+    /// Adds a merge operand to a key
+    pub fn merge(&mut self, key: Key, operand: Value) {
+        self.merge_cf("", key, operand);
+    }
+
+    /// Adds a merge operand to a key in a column family
+    pub fn merge_cf(&mut self, cf: &str, key: Key, operand: Value) {
+        self.push(cf, mutation::Op::Merge(mutation::Merge { key, operand }));
+    }
+
+    /// Deletes the keys in `[start, end)`
+    pub fn delete_range(&mut self, start: Key, end: Key) {
+        self.delete_range_cf("", start, end);
+    }
+
+    /// Deletes the keys in `[start, end)` of a column family
+    pub fn delete_range_cf(&mut self, cf: &str, start: Key, end: Key) {
+        self.push(
+            cf,
+            mutation::Op::DeleteRange(mutation::DeleteRange { start, end }),
+        );
+    }
+
+    /// Returns the number of writes in the batch
+    pub fn len(&self) -> usize {
+        self.mutations.len()
+    }
+
+    /// Returns true if the batch has no writes
+    pub fn is_empty(&self) -> bool {
+        self.mutations.is_empty()
+    }
+
+    /// Returns true if applying the batch twice has the same effect as once
+    ///
+    /// Merge operands accumulate, so a batch with merges isn't retried.
+    pub(crate) fn is_idempotent(&self) -> bool {
+        !self
+            .mutations
+            .iter()
+            .any(|mutation| matches!(mutation.op, Some(mutation::Op::Merge(_))))
+    }
+
+    fn push(&mut self, cf: &str, op: mutation::Op) {
+        self.mutations.push(Mutation {
+            column_family: cf.to_string(),
+            op: Some(op),
+        });
+    }
+}
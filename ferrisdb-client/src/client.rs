@@ -0,0 +1,647 @@
+//! The client and its request loop
+//!
+//! Every request goes through [`Client::call`], which picks the next
+//! pooled connection, bounds the attempt by the request timeout, and
+//! retries transient failures of idempotent requests with backoff:
+//!
+//! ```text
+//! attempt ──ok / permanent error──▶ return
+//!    │
+//!    └─transient─▶ retries left and idempotent? ──no──▶ return
+//!                        │ yes
+//!                        └─▶ sleep(backoff) ──▶ attempt
+//! ```
+
+use crate::proto::key_value_client::KeyValueClient;
+use crate::proto::{BatchWriteRequest, GetRequest, MultiGetRequest, ScanResponse};
+use crate::{ClientOptions, Error, Result, Scan, ScanPage, WriteBatch};
+use ferrisdb_core::{Key, Value};
+
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Status};
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// An async connection pool to a FerrisDB server
+///
+/// Cloning is cheap and clones share the pool, so one client can serve a
+/// whole application.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<ClientInner>,
+}
+
+/// A stub for one attempt of a request
+type Stub = KeyValueClient<InterceptedService<Channel, Authorization>>;
+
+struct ClientInner {
+    options: ClientOptions,
+    authorization: Authorization,
+    channels: Vec<Channel>,
+    /// Round-robin position in `channels`
+    next: AtomicUsize,
+}
+
+impl Client {
+    /// Connects to the server at `endpoint` with default options
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Connect` if the server can't be reached.
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self> {
+        Self::connect_with(ClientOptions::new(endpoint)).await
+    }
+
+    /// Opens the configured number of connections to the server
+    ///
+    /// Connections that drop later are re-established on demand.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` for invalid options and `Error::Connect` if
+    /// the server can't be reached or its certificate isn't trusted.
+    pub async fn connect_with(options: ClientOptions) -> Result<Self> {
+        options.validate()?;
+        let mut endpoint = Endpoint::from_shared(options.endpoint.clone())
+            .map_err(|e| Error::Config(format!("Invalid endpoint {:?}: {}", options.endpoint, e)))?
+            .connect_timeout(options.connect_timeout)
+            .tcp_nodelay(true);
+        if let Some(tls) = &options.tls {
+            let mut config =
+                ClientTlsConfig::new().ca_certificate(Certificate::from_pem(&tls.ca_certificate));
+            if let Some(domain_name) = &tls.domain_name {
+                config = config.domain_name(domain_name);
+            }
+            if let Some((certificate, key)) = &tls.identity {
+                config = config.identity(Identity::from_pem(certificate, key));
+            }
+            endpoint = endpoint
+                .tls_config(config)
+                .map_err(|e| Error::Config(format!("Invalid TLS options: {}", e)))?;
+        }
+        let authorization = Authorization::new(options.bearer_token.as_deref())?;
+
+        let mut channels = Vec::with_capacity(options.pool_size);
+        for _ in 0..options.pool_size {
+            channels.push(endpoint.connect().await.map_err(Error::Connect)?);
+        }
+        log::debug!(
+            "Connected to {} with {} connections",
+            options.endpoint,
+            channels.len()
+        );
+        Ok(Self {
+            inner: Arc::new(ClientInner {
+                options,
+                authorization,
+                channels,
+                next: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    /// Returns the options the client was created with
+    pub fn options(&self) -> &ClientOptions {
+        &self.inner.options
+    }
+
+    /// Returns the value of a key, if it exists
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after all retries.
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        self.get_cf("", key).await
+    }
+
+    /// Returns the value of a key in a column family, if it exists
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if the column family doesn't exist, or
+    /// another error if the request fails after all retries.
+    pub async fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Value>> {
+        let request = GetRequest {
+            column_family: cf.to_string(),
+            key: key.to_vec(),
+        };
+        self.call(true, |mut client| {
+            let request = request.clone();
+            async move { Ok(client.get(request).await?.into_inner().value) }
+        })
+        .await
+    }
+
+    /// Returns the values of several keys, in the order given
+    ///
+    /// The server reads all keys at one point in time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after all retries.
+    pub async fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Value>>> {
+        self.multi_get_cf("", keys).await
+    }
+
+    /// Returns the values of several keys in a column family, in the order
+    /// given
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if the column family doesn't exist, or
+    /// another error if the request fails after all retries.
+    pub async fn multi_get_cf(&self, cf: &str, keys: &[&[u8]]) -> Result<Vec<Option<Value>>> {
+        let request = MultiGetRequest {
+            column_family: cf.to_string(),
+            keys: keys.iter().map(|key| key.to_vec()).collect(),
+        };
+        self.call(true, |mut client| {
+            let request = request.clone();
+            async move {
+                let response = client.multi_get(request).await?.into_inner();
+                Ok(response
+                    .values
+                    .into_iter()
+                    .map(|value| value.value)
+                    .collect())
+            }
+        })
+        .await
+    }
+
+    /// Sets the value of a key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after all retries.
+    pub async fn put(&self, key: Key, value: Value) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(batch).await
+    }
+
+    /// Deletes a key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after all retries.
+    pub async fn delete(&self, key: Key) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch).await
+    }
+
+    /// Applies a batch of writes atomically
+    ///
+    /// Batches with merges aren't retried, as an attempt that timed out may
+    /// still have been applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if a column family doesn't exist,
+    /// `Error::FailedPrecondition` if it has merges but no merge operator,
+    /// or another error if the request fails after all retries.
+    pub async fn write(&self, batch: WriteBatch) -> Result<()> {
+        let idempotent = batch.is_idempotent();
+        let request = BatchWriteRequest {
+            mutations: batch.mutations,
+            sync: self.inner.options.sync_writes,
+        };
+        self.call(idempotent, |mut client| {
+            let request = request.clone();
+            async move {
+                client.batch_write(request).await?;
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    /// Reads one page of a scan
+    ///
+    /// All pairs of a page come from one snapshot of the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` for a malformed continuation token,
+    /// or another error if the request fails after all retries.
+    pub async fn scan(&self, scan: &Scan) -> Result<ScanPage> {
+        self.call(true, |mut client| {
+            let request = scan.request.clone();
+            async move {
+                let mut stream = client.scan(request).await?.into_inner();
+                let mut page = ScanPage::default();
+                while let Some(ScanResponse {
+                    pairs,
+                    continuation_token,
+                }) = stream.message().await?
+                {
+                    page.pairs
+                        .extend(pairs.into_iter().map(|pair| (pair.key, pair.value)));
+                    if !continuation_token.is_empty() {
+                        page.continuation_token = Some(continuation_token);
+                    }
+                }
+                Ok(page)
+            }
+        })
+        .await
+    }
+
+    /// Reads a scan to the end, page by page
+    ///
+    /// Each page comes from its own snapshot, so writes made while the scan
+    /// runs may show up in later pages.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading any page fails.
+    pub async fn scan_all(&self, scan: &Scan) -> Result<Vec<(Key, Value)>> {
+        let mut scan = scan.clone();
+        let mut pairs = Vec::new();
+        loop {
+            let page = self.scan(&scan).await?;
+            pairs.extend(page.pairs);
+            match page.continuation_token {
+                Some(token) => scan = scan.resume(token),
+                None => return Ok(pairs),
+            }
+        }
+    }
+
+    /// Runs a request, retrying transient failures if it is idempotent
+    async fn call<T, F, Fut>(&self, idempotent: bool, mut attempt: F) -> Result<T>
+    where
+        F: FnMut(Stub) -> Fut,
+        Fut: Future<Output = std::result::Result<T, Status>>,
+    {
+        let options = &self.inner.options;
+        let mut retries = 0;
+        loop {
+            let client = KeyValueClient::with_interceptor(
+                self.next_channel(),
+                self.inner.authorization.clone(),
+            );
+            let result = match tokio::time::timeout(options.request_timeout, attempt(client)).await
+            {
+                Ok(result) => result.map_err(Error::from),
+                Err(_) => Err(Error::Timeout(options.request_timeout)),
+            };
+            match result {
+                Err(e) if idempotent && e.is_transient() && retries < options.retry.max_retries => {
+                    let backoff = options.retry.backoff(retries);
+                    log::debug!("Retrying in {:?} after: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn next_channel(&self) -> Channel {
+        let next = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        self.inner.channels[next % self.inner.channels.len()].clone()
+    }
+}
+
+/// Adds the bearer token, if any, to every request
+#[derive(Clone)]
+struct Authorization(Option<MetadataValue<Ascii>>);
+
+impl Authorization {
+    fn new(token: Option<&str>) -> Result<Self> {
+        token
+            .map(|token| {
+                format!("Bearer {}", token)
+                    .parse()
+                    .map_err(|_| Error::Config("Invalid bearer token".to_string()))
+            })
+            .transpose()
+            .map(Self)
+    }
+}
+
+impl Interceptor for Authorization {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        if let Some(value) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", value.clone());
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RetryPolicy, TlsOptions};
+    use ferrisdb_server::proto::key_value_server::{KeyValue, KeyValueServer};
+    use ferrisdb_server::proto::{self as server_proto, BatchWriteResponse, GetResponse};
+    use ferrisdb_server::{Server, ServerConfig};
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{Request, Response};
+
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    /// A server whose requests fail with `Unavailable` until `failures`
+    /// runs out, each after `delay`
+    #[derive(Clone, Default)]
+    struct FlakyServer {
+        failures: Arc<AtomicU32>,
+        calls: Arc<AtomicU32>,
+        delay: Duration,
+    }
+
+    impl FlakyServer {
+        async fn answer<T>(&self, response: T) -> std::result::Result<Response<T>, Status> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            let failed = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if failed {
+                Err(Status::unavailable("try again"))
+            } else {
+                Ok(Response::new(response))
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl KeyValue for FlakyServer {
+        async fn get(
+            &self,
+            _: Request<server_proto::GetRequest>,
+        ) -> std::result::Result<Response<GetResponse>, Status> {
+            self.answer(GetResponse {
+                value: Some(b"v".to_vec()),
+            })
+            .await
+        }
+
+        async fn multi_get(
+            &self,
+            _: Request<server_proto::MultiGetRequest>,
+        ) -> std::result::Result<Response<server_proto::MultiGetResponse>, Status> {
+            self.answer(server_proto::MultiGetResponse { values: Vec::new() })
+                .await
+        }
+
+        async fn put(
+            &self,
+            _: Request<server_proto::PutRequest>,
+        ) -> std::result::Result<Response<server_proto::PutResponse>, Status> {
+            self.answer(server_proto::PutResponse {}).await
+        }
+
+        async fn delete(
+            &self,
+            _: Request<server_proto::DeleteRequest>,
+        ) -> std::result::Result<Response<server_proto::DeleteResponse>, Status> {
+            self.answer(server_proto::DeleteResponse {}).await
+        }
+
+        async fn batch_write(
+            &self,
+            _: Request<server_proto::BatchWriteRequest>,
+        ) -> std::result::Result<Response<BatchWriteResponse>, Status> {
+            self.answer(BatchWriteResponse {}).await
+        }
+
+        type ScanStream =
+            tokio_stream::Empty<std::result::Result<server_proto::ScanResponse, Status>>;
+
+        async fn scan(
+            &self,
+            _: Request<server_proto::ScanRequest>,
+        ) -> std::result::Result<Response<Self::ScanStream>, Status> {
+            self.answer(tokio_stream::empty()).await
+        }
+    }
+
+    async fn serve_flaky(server: FlakyServer) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(KeyValueServer::new(server))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        addr
+    }
+
+    fn options(addr: SocketAddr) -> ClientOptions {
+        ClientOptions::new(format!("http://{}", addr)).with_retry_policy(
+            RetryPolicy::default()
+                .with_max_retries(3)
+                .with_initial_backoff(Duration::from_millis(1)),
+        )
+    }
+
+    /// Serves a real server until the returned sender fires
+    async fn serve(
+        config: ServerConfig,
+    ) -> (
+        SocketAddr,
+        tokio::sync::oneshot::Sender<()>,
+        tokio::task::JoinHandle<ferrisdb_server::Result<()>>,
+    ) {
+        let server = Server::open(config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve_with_listener(listener, async {
+            let _ = stopped.await;
+        }));
+        (addr, stop, task)
+    }
+
+    #[tokio::test]
+    async fn test_reads_writes_and_scans_against_a_server() {
+        let dir = TempDir::new().unwrap();
+        let (addr, stop, task) = serve(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            max_scan_limit: 2,
+            api_tokens: vec!["t0ken".to_string()],
+            ..Default::default()
+        })
+        .await;
+
+        let client =
+            Client::connect_with(options(addr).with_pool_size(3).with_bearer_token("t0ken"))
+                .await
+                .unwrap();
+        client.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        let mut batch = WriteBatch::new();
+        for key in ["b", "c", "d", "e"] {
+            batch.put(format!("k{}", key).into_bytes(), key.as_bytes().to_vec());
+        }
+        batch.delete(b"a".to_vec());
+        client.write(batch).await.unwrap();
+        assert_eq!(client.get(b"a").await.unwrap(), None);
+        assert_eq!(client.get(b"kb").await.unwrap(), Some(b"b".to_vec()));
+        assert_eq!(
+            client.multi_get(&[b"ke", b"a", b"kb"]).await.unwrap(),
+            vec![Some(b"e".to_vec()), None, Some(b"b".to_vec())]
+        );
+
+        // Pages are capped by the server; scan_all follows the tokens
+        let page = client.scan(&Scan::prefix(b"k".to_vec())).await.unwrap();
+        assert_eq!(page.pairs.len(), 2);
+        let rest = client
+            .scan(&Scan::prefix(b"k".to_vec()).resume(page.continuation_token.unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(rest.pairs[0].0, b"kd");
+        let all = client.scan_all(&Scan::all()).await.unwrap();
+        assert_eq!(all.len(), 4);
+
+        assert!(matches!(
+            client.get_cf("missing", b"a").await,
+            Err(Error::NotFound(_))
+        ));
+        let mut merge = WriteBatch::new();
+        merge.merge(b"a".to_vec(), b"1".to_vec());
+        assert!(matches!(
+            client.write(merge).await,
+            Err(Error::FailedPrecondition(_))
+        ));
+
+        stop.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tls_and_bearer_tokens() {
+        let dir = TempDir::new().unwrap();
+        let certs = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../ferrisdb-server/testdata/tls");
+        let (addr, stop, task) = serve(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            api_tokens: vec!["t0ken".to_string()],
+            tls: Some(ferrisdb_server::config::TlsSettings {
+                cert_path: certs.join("server.pem"),
+                key_path: certs.join("server.key"),
+                client_ca_path: None,
+            }),
+            ..Default::default()
+        })
+        .await;
+
+        let tls = TlsOptions::new(std::fs::read(certs.join("ca.pem")).unwrap())
+            .with_domain_name("localhost");
+        let options = options(addr).with_retry_policy(RetryPolicy::none());
+        let options = ClientOptions {
+            endpoint: format!("https://{}", addr),
+            ..options
+        }
+        .with_tls(tls);
+
+        let client = Client::connect_with(options.clone().with_bearer_token("t0ken"))
+            .await
+            .unwrap();
+        client.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+        assert_eq!(client.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+
+        let anonymous = Client::connect_with(options.clone()).await.unwrap();
+        assert!(matches!(
+            anonymous.get(b"a").await,
+            Err(Error::Unauthenticated(_))
+        ));
+        let guessing = Client::connect_with(options.with_bearer_token("guess"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            guessing.get(b"a").await,
+            Err(Error::PermissionDenied(_))
+        ));
+
+        // A CA that didn't sign the server's certificate isn't trusted
+        let untrusted = ClientOptions::new(format!("https://{}", addr)).with_tls(
+            TlsOptions::new(std::fs::read(certs.join("server.pem")).unwrap())
+                .with_domain_name("localhost"),
+        );
+        assert!(matches!(
+            Client::connect_with(untrusted).await,
+            Err(Error::Connect(_))
+        ));
+
+        stop.send(()).unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let server = FlakyServer {
+            failures: Arc::new(AtomicU32::new(2)),
+            ..Default::default()
+        };
+        let client = Client::connect_with(options(serve_flaky(server.clone()).await))
+            .await
+            .unwrap();
+        assert_eq!(client.get(b"k").await.unwrap(), Some(b"v".to_vec()));
+        assert_eq!(server.calls.load(Ordering::SeqCst), 3);
+
+        // Giving up after the last retry
+        server.failures.store(10, Ordering::SeqCst);
+        server.calls.store(0, Ordering::SeqCst);
+        assert!(matches!(client.get(b"k").await, Err(Error::Unavailable(_))));
+        assert_eq!(server.calls.load(Ordering::SeqCst), 4);
+
+        // Merges might be applied twice, so they are never retried
+        server.failures.store(1, Ordering::SeqCst);
+        server.calls.store(0, Ordering::SeqCst);
+        let mut merge = WriteBatch::new();
+        merge.merge(b"k".to_vec(), b"1".to_vec());
+        assert!(matches!(
+            client.write(merge).await,
+            Err(Error::Unavailable(_))
+        ));
+        assert_eq!(server.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_requests_time_out() {
+        let server = FlakyServer {
+            delay: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let addr = serve_flaky(server.clone()).await;
+        let client = Client::connect_with(
+            options(addr)
+                .with_request_timeout(Duration::from_millis(20))
+                .with_retry_policy(RetryPolicy::default().with_max_retries(1)),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(client.get(b"k").await, Err(Error::Timeout(_))));
+        assert_eq!(server.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_connecting_fails_without_a_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(matches!(
+            Client::connect(format!("http://{}", addr)).await,
+            Err(Error::Connect(_))
+        ));
+        assert!(matches!(
+            Client::connect("not a url").await,
+            Err(Error::Config(_))
+        ));
+    }
+}
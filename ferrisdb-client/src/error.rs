@@ -0,0 +1,222 @@
+//! Errors returned by the client
+//!
+//! Failures fall into three groups:
+//!
+//! - **Setup**: [`Error::Config`], [`Error::Connect`]; the client couldn't
+//!   be created
+//! - **Transient**: [`Error::Unavailable`], [`Error::Busy`],
+//!   [`Error::Timeout`]; the request may succeed if sent again, and
+//!   idempotent requests are retried
+//! - **Request**: everything else; the server rejected the request and
+//!   sending it again won't help
+//!
+//! Servers attach the engine's [`ErrorCode`] to failed calls, which picks
+//! the variant where the gRPC status code alone is ambiguous.
+
+use ferrisdb_core::error::{ErrorCode, ERROR_CODE_METADATA};
+use tonic::{Code, Status};
+
+use std::time::Duration;
+
+/// Errors that can occur when talking to a FerrisDB server
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The client options are invalid
+    #[error("Invalid client configuration: {0}")]
+    Config(String),
+
+    /// No connection to the server could be established
+    #[error("Failed to connect: {0}")]
+    Connect(#[source] tonic::transport::Error),
+
+    /// The server didn't answer in time
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// The server couldn't be reached or is shutting down
+    #[error("Server unavailable: {0}")]
+    Unavailable(String),
+
+    /// A column family named in the request doesn't exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// The request is malformed
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// The database isn't in a state that allows the request, e.g. a merge
+    /// without a merge operator
+    #[error("Failed precondition: {0}")]
+    FailedPrecondition(String),
+
+    /// The request carried no bearer token, or the server didn't accept
+    /// the connection's credentials
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    /// The request's bearer token isn't valid for the service
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    /// A transaction conflicted with another one
+    #[error("Aborted: {0}")]
+    Aborted(String),
+
+    /// The server found corrupted data
+    #[error("Data loss: {0}")]
+    DataLoss(String),
+
+    /// The server is temporarily overloaded
+    #[error("Server busy: {0}")]
+    Busy(String),
+
+    /// The server has run out of disk space
+    #[error("Disk full: {0}")]
+    DiskFull(String),
+
+    /// The server doesn't support the request
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// The request reached a limit set on how much it may read or how
+    /// long it may take
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    /// Any other failure reported by the server
+    #[error("Server error ({code:?}): {message}")]
+    Server {
+        /// The gRPC status code
+        code: Code,
+        /// The server's description of the failure
+        message: String,
+    },
+}
+
+impl Error {
+    /// Returns true if sending the same request again may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Unavailable(_) | Error::Busy(_) | Error::Timeout(_)
+        )
+    }
+
+    /// Returns the engine error code this error corresponds to, if it came
+    /// from the database rather than the connection or the client itself
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::NotFound(_) => Some(ErrorCode::NotFound),
+            Error::InvalidArgument(_) => Some(ErrorCode::InvalidArgument),
+            Error::FailedPrecondition(_) => Some(ErrorCode::FailedPrecondition),
+            Error::Aborted(_) => Some(ErrorCode::Aborted),
+            Error::DataLoss(_) => Some(ErrorCode::Corruption),
+            Error::Busy(_) => Some(ErrorCode::Busy),
+            Error::DiskFull(_) => Some(ErrorCode::DiskFull),
+            Error::Unsupported(_) => Some(ErrorCode::Unsupported),
+            Error::LimitExceeded(_) => Some(ErrorCode::LimitExceeded),
+            Error::Server {
+                code: Code::Internal,
+                ..
+            } => Some(ErrorCode::Internal),
+            _ => None,
+        }
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+        let code = status
+            .metadata()
+            .get(ERROR_CODE_METADATA)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .and_then(ErrorCode::from_u16);
+        match code {
+            Some(ErrorCode::Busy) => return Error::Busy(message),
+            Some(ErrorCode::DiskFull) => return Error::DiskFull(message),
+            Some(ErrorCode::Unsupported) => return Error::Unsupported(message),
+            Some(ErrorCode::LimitExceeded) => return Error::LimitExceeded(message),
+            _ => {}
+        }
+        match status.code() {
+            Code::Unavailable => Error::Unavailable(message),
+            Code::NotFound => Error::NotFound(message),
+            Code::InvalidArgument => Error::InvalidArgument(message),
+            Code::FailedPrecondition => Error::FailedPrecondition(message),
+            Code::Unauthenticated => Error::Unauthenticated(message),
+            Code::PermissionDenied => Error::PermissionDenied(message),
+            Code::Aborted => Error::Aborted(message),
+            Code::DataLoss => Error::DataLoss(message),
+            code => Error::Server { code, message },
+        }
+    }
+}
+
+/// A specialized Result type for client operations
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes_map_to_variants() {
+        assert!(matches!(
+            Error::from(Status::unavailable("down")),
+            Error::Unavailable(message) if message == "down"
+        ));
+        assert!(matches!(
+            Error::from(Status::data_loss("bad block")),
+            Error::DataLoss(_)
+        ));
+        assert!(matches!(
+            Error::from(Status::internal("oops")),
+            Error::Server {
+                code: Code::Internal,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_error_code_metadata_picks_the_variant() {
+        let status = |code: ErrorCode| {
+            let mut status = Status::resource_exhausted("full");
+            status
+                .metadata_mut()
+                .insert(ERROR_CODE_METADATA, code.as_u16().into());
+            status
+        };
+
+        let error = Error::from(status(ErrorCode::DiskFull));
+        assert!(matches!(error, Error::DiskFull(_)));
+        assert_eq!(error.code(), Some(ErrorCode::DiskFull));
+        assert!(matches!(
+            Error::from(status(ErrorCode::Busy)),
+            Error::Busy(_)
+        ));
+        assert!(matches!(
+            Error::from(status(ErrorCode::LimitExceeded)),
+            Error::LimitExceeded(_)
+        ));
+        // Without a code, e.g. a quota rejection, the gRPC code decides
+        assert!(matches!(
+            Error::from(Status::resource_exhausted("slow down")),
+            Error::Server {
+                code: Code::ResourceExhausted,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_only_transient_errors_are_retryable() {
+        assert!(Error::Unavailable(String::new()).is_transient());
+        assert!(Error::Busy(String::new()).is_transient());
+        assert!(Error::Timeout(Duration::from_secs(1)).is_transient());
+        assert!(!Error::InvalidArgument(String::new()).is_transient());
+        assert!(!Error::Aborted(String::new()).is_transient());
+    }
+}
@@ -1,8 +1,46 @@
-// FerrisDB client library
-pub struct FerrisDB;
+//! FerrisDB client: an async client for the gRPC API
+//!
+//! A [`Client`] keeps a pool of connections to a server, bounds every
+//! request by a timeout, and retries transient failures with exponential
+//! backoff. Failures come back as a typed [`Error`] rather than raw gRPC
+//! statuses. Servers that require it are reached over TLS, with a bearer
+//! token sent along with every request (see [`ClientOptions`]).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ferrisdb_client::{Client, ClientOptions, Scan, WriteBatch};
+//!
+//! # async fn run() -> ferrisdb_client::Result<()> {
+//! let client = Client::connect_with(ClientOptions::new("http://127.0.0.1:7070").with_pool_size(4))
+//!     .await?;
+//!
+//! client.put(b"user:1".to_vec(), b"alice".to_vec()).await?;
+//! assert_eq!(client.get(b"user:1").await?, Some(b"alice".to_vec()));
+//!
+//! let mut batch = WriteBatch::new();
+//! batch.put(b"user:2".to_vec(), b"bob".to_vec());
+//! batch.delete(b"user:1".to_vec());
+//! client.write(batch).await?;
+//!
+//! let users = client.scan_all(&Scan::prefix(b"user:".to_vec())).await?;
+//! # Ok(())
+//! # }
+//! ```
 
-impl FerrisDB {
-    pub async fn connect(_url: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Self)
-    }
+mod batch;
+mod client;
+pub mod error;
+pub mod options;
+mod scan;
+
+/// Types generated from the protobuf schema
+pub mod proto {
+    tonic::include_proto!("ferrisdb.v1");
 }
+
+pub use batch::WriteBatch;
+pub use client::Client;
+pub use error::{Error, Result};
+pub use options::{ClientOptions, RetryPolicy, TlsOptions};
+pub use scan::{Scan, ScanPage};
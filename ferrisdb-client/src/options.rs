@@ -0,0 +1,270 @@
+//! Client configuration
+//!
+//! ```
+//! use ferrisdb_client::{ClientOptions, RetryPolicy, TlsOptions};
+//! use std::time::Duration;
+//!
+//! # let ca_pem = Vec::new();
+//! let options = ClientOptions::new("https://db.example.com:7070")
+//!     .with_pool_size(4)
+//!     .with_request_timeout(Duration::from_secs(2))
+//!     .with_retry_policy(RetryPolicy::default().with_max_retries(5))
+//!     .with_bearer_token("change-me")
+//!     .with_tls(TlsOptions::new(ca_pem));
+//! ```
+
+use crate::{Error, Result};
+
+use std::time::Duration;
+
+/// How a client connects and how patient it is
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Server address, e.g. `http://127.0.0.1:7070`
+    pub endpoint: String,
+    /// Connections requests are spread over
+    pub pool_size: usize,
+    /// Longest wait for a connection to be established
+    pub connect_timeout: Duration,
+    /// Longest wait for one attempt of a request, including reading a whole
+    /// scan page
+    pub request_timeout: Duration,
+    /// Whether writes wait for the server to sync its WAL
+    pub sync_writes: bool,
+    /// How transient failures are retried
+    pub retry: RetryPolicy,
+    /// Token sent as `authorization: Bearer <token>` with every request
+    pub bearer_token: Option<String>,
+    /// How the server is verified; plaintext if unset
+    pub tls: Option<TlsOptions>,
+}
+
+impl ClientOptions {
+    /// Creates options for a server with default settings
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            pool_size: 1,
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            sync_writes: false,
+            retry: RetryPolicy::default(),
+            bearer_token: None,
+            tls: None,
+        }
+    }
+
+    /// Sets the number of pooled connections
+    ///
+    /// Each connection multiplexes many concurrent requests, so more than
+    /// one only pays off under heavy load.
+    pub fn with_pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size;
+        self
+    }
+
+    /// Sets the connection timeout
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the per-attempt request timeout
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sets whether writes are synced before they're acknowledged
+    pub fn with_sync_writes(mut self, sync: bool) -> Self {
+        self.sync_writes = sync;
+        self
+    }
+
+    /// Sets the retry policy
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the token requests are authorized with
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Connects over TLS; the endpoint should use `https://`
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.pool_size == 0 {
+            return Err(Error::Config("pool_size must be at least 1".to_string()));
+        }
+        if self.request_timeout.is_zero() {
+            return Err(Error::Config(
+                "request_timeout must be positive".to_string(),
+            ));
+        }
+        if self.retry.multiplier < 1.0 {
+            return Err(Error::Config(
+                "retry multiplier must be at least 1".to_string(),
+            ));
+        }
+        if let Some(token) = &self.bearer_token {
+            if token.is_empty() || !token.bytes().all(|b| b.is_ascii_graphic()) {
+                return Err(Error::Config(
+                    "bearer_token must be non-empty printable ASCII".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a client verifies the server, and optionally proves who it is
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    /// PEM certificates of the CAs the server's certificate must chain to
+    pub ca_certificate: Vec<u8>,
+    /// Name the server's certificate must be issued for; the endpoint's
+    /// host if unset
+    pub domain_name: Option<String>,
+    /// PEM certificate and private key presented to servers that require
+    /// client certificates
+    pub identity: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TlsOptions {
+    /// Creates options trusting the CAs in `ca_certificate`
+    pub fn new(ca_certificate: impl Into<Vec<u8>>) -> Self {
+        Self {
+            ca_certificate: ca_certificate.into(),
+            domain_name: None,
+            identity: None,
+        }
+    }
+
+    /// Sets the name the server's certificate is checked against
+    pub fn with_domain_name(mut self, domain_name: impl Into<String>) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    /// Sets the client certificate and key
+    pub fn with_identity(
+        mut self,
+        certificate: impl Into<Vec<u8>>,
+        key: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.identity = Some((certificate.into(), key.into()));
+        self
+    }
+}
+
+/// Exponential backoff between attempts of a request
+///
+/// Only [transient](crate::Error::is_transient) failures of idempotent
+/// requests are retried. The wait before retry `n` (counting from 0) is
+/// `initial_backoff * multiplier^n`, capped at `max_backoff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    /// Wait before the first retry
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts
+    pub max_backoff: Duration,
+    /// Factor the wait grows by after each retry
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the number of retries
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the wait before the first retry
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the longest wait between attempts
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the factor the wait grows by
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Returns the wait before retry `retry`, counting from 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(retry as i32);
+        Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_exponentially_up_to_the_cap() {
+        let policy = RetryPolicy::default()
+            .with_initial_backoff(Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(500));
+        let waits: Vec<_> = (0..5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(waits, [100, 200, 400, 500, 500].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn test_invalid_options_are_rejected() {
+        let options = ClientOptions::new("http://localhost:7070");
+        assert!(options.validate().is_ok());
+        assert!(options.clone().with_pool_size(0).validate().is_err());
+        assert!(options
+            .clone()
+            .with_request_timeout(Duration::ZERO)
+            .validate()
+            .is_err());
+        assert!(options
+            .clone()
+            .with_retry_policy(RetryPolicy::default().with_multiplier(0.5))
+            .validate()
+            .is_err());
+        assert!(options
+            .clone()
+            .with_bearer_token("two words")
+            .validate()
+            .is_err());
+        assert!(options.with_bearer_token("").validate().is_err());
+    }
+}
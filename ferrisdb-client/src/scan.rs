@@ -0,0 +1,77 @@
+//! Range and prefix scans, read a page at a time
+
+use crate::proto::ScanRequest;
+use ferrisdb_core::{Key, Value};
+
+/// What a scan reads
+///
+/// A page holds at most [`limit`](Self::with_limit) pairs, further capped
+/// by the server. [`Client::scan`](crate::Client::scan) reads one page;
+/// [`Client::scan_all`](crate::Client::scan_all) follows continuation
+/// tokens to the end of the range.
+#[derive(Debug, Clone, Default)]
+pub struct Scan {
+    pub(crate) request: ScanRequest,
+}
+
+impl Scan {
+    /// Scans every key
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Scans the keys in `[start, end)`
+    pub fn range(start: Key, end: Key) -> Self {
+        let mut scan = Self::default();
+        scan.request.start = start;
+        scan.request.end = end;
+        scan
+    }
+
+    /// Scans the keys at or after `start`
+    pub fn starting_at(start: Key) -> Self {
+        let mut scan = Self::default();
+        scan.request.start = start;
+        scan
+    }
+
+    /// Scans the keys starting with `prefix`
+    pub fn prefix(prefix: Key) -> Self {
+        let mut scan = Self::default();
+        scan.request.prefix = prefix;
+        scan
+    }
+
+    /// Restricts the scan to keys starting with `prefix`
+    pub fn with_prefix(mut self, prefix: Key) -> Self {
+        self.request.prefix = prefix;
+        self
+    }
+
+    /// Scans a column family rather than the default one
+    pub fn with_column_family(mut self, cf: impl Into<String>) -> Self {
+        self.request.column_family = cf.into();
+        self
+    }
+
+    /// Sets the most pairs a page holds; 0 means the server's limit
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.request.limit = limit;
+        self
+    }
+
+    /// Continues after the page `token` was returned with
+    pub fn resume(mut self, token: Vec<u8>) -> Self {
+        self.request.continuation_token = token;
+        self
+    }
+}
+
+/// One page of a scan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanPage {
+    /// Pairs in key order
+    pub pairs: Vec<(Key, Value)>,
+    /// Set if the range has more pairs; pass it to [`Scan::resume`]
+    pub continuation_token: Option<Vec<u8>>,
+}
@@ -0,0 +1,484 @@
+//! Configuration files shared by FerrisDB components
+//!
+//! A [`Config`] is read from one TOML file and covers the storage engine
+//! and the server. Every setting is optional; settings left out keep the
+//! defaults of the component that uses them, so an empty file is valid.
+//! Sizes are written as a number of bytes or as a string with a unit:
+//!
+//! ```toml
+//! data_dir = "/var/lib/ferrisdb"
+//!
+//! [wal]
+//! sync_mode = "Full"        # None, Normal or Full
+//! size_limit = "64MB"       # rotate WAL files at this size
+//! dir = "/fast/ferrisdb-wal"
+//!
+//! [memtable]
+//! size = "64MB"
+//! max_immutable = 4
+//! write_buffer_budget = "256MB" # all column families together
+//!
+//! [sstable]
+//! block_size = "16KB"
+//! block_cache_size = "1GB"
+//! compression = "Lz4"       # None, Lz4 or Snappy
+//!
+//! [compaction]
+//! level0_file_num_trigger = 4
+//! level0_slowdown_writes_trigger = 20
+//! level0_stop_writes_trigger = 36
+//! max_bytes_for_level_base = "256MB"
+//! max_bytes_for_level_multiplier = 10.0
+//! target_file_size_base = "64MB"
+//! rate_limit = "100MB"      # bytes per second, 0 for unlimited
+//!
+//! [server]
+//! listen_addr = "127.0.0.1:7070"
+//! max_scan_limit = 1000
+//! shutdown_timeout_secs = 30
+//! ```
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_core::config::{ByteSize, Config};
+//!
+//! let config = Config::from_toml_str("[memtable]\nsize = \"64MB\"").unwrap();
+//! assert_eq!(config.memtable.size, Some(ByteSize::mib(64)));
+//! ```
+
+use crate::{CompressionType, Error, Result, SyncMode};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Settings of a FerrisDB deployment, read from TOML
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory holding the database
+    pub data_dir: Option<PathBuf>,
+    /// Write-ahead log settings
+    pub wal: WalConfig,
+    /// MemTable settings
+    pub memtable: MemTableConfig,
+    /// SSTable and block cache settings
+    pub sstable: SSTableConfig,
+    /// Compaction and write stall settings
+    pub compaction: CompactionConfig,
+    /// Settings of the network server
+    pub server: ServerSection,
+}
+
+/// Write-ahead log settings
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalConfig {
+    /// How durably each write is logged before it is acknowledged
+    pub sync_mode: Option<SyncMode>,
+    /// Size at which a WAL file is rotated
+    pub size_limit: Option<ByteSize>,
+    /// Directory holding WAL files, `data_dir/wal` if unset
+    pub dir: Option<PathBuf>,
+    /// Directory flushed WAL files are moved to instead of being deleted
+    pub archive_dir: Option<PathBuf>,
+}
+
+/// MemTable settings
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemTableConfig {
+    /// Size at which the active MemTable is flushed
+    pub size: Option<ByteSize>,
+    /// Immutable MemTables kept waiting for a flush before writes block
+    pub max_immutable: Option<usize>,
+    /// Memory the active MemTables of all column families may use together
+    pub write_buffer_budget: Option<ByteSize>,
+}
+
+/// SSTable and block cache settings
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SSTableConfig {
+    /// Size of each data block
+    pub block_size: Option<ByteSize>,
+    /// Size of the block cache for SSTable reads
+    pub block_cache_size: Option<ByteSize>,
+    /// Compression of SSTable blocks
+    pub compression: Option<CompressionType>,
+}
+
+/// Compaction and write stall settings
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompactionConfig {
+    /// Number of L0 files that trigger a compaction
+    pub level0_file_num_trigger: Option<u32>,
+    /// Number of L0 files at which writes are slowed
+    pub level0_slowdown_writes_trigger: Option<u32>,
+    /// Number of L0 files at which writes stop
+    pub level0_stop_writes_trigger: Option<u32>,
+    /// Target size of L1
+    pub max_bytes_for_level_base: Option<ByteSize>,
+    /// Size multiplier between levels
+    pub max_bytes_for_level_multiplier: Option<f64>,
+    /// Size at which compaction output is split into a new SSTable
+    pub target_file_size_base: Option<ByteSize>,
+    /// Bytes per second background I/O may use, 0 for unlimited
+    pub rate_limit: Option<ByteSize>,
+}
+
+/// Settings of the network server
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// Address the server listens on
+    pub listen_addr: Option<SocketAddr>,
+    /// Most key-value pairs a single scan returns
+    pub max_scan_limit: Option<u32>,
+    /// How long shutdown waits for in-flight requests (in seconds)
+    pub shutdown_timeout_secs: Option<u64>,
+}
+
+impl Config {
+    /// Parses a configuration from TOML text
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if the text isn't valid TOML, has
+    /// unknown settings, or sets an invalid value.
+    pub fn from_toml_str(text: &str) -> Result<Self> {
+        let config: Self =
+            toml::from_str(text).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a configuration from a TOML file
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if the file can't be read, otherwise errors for
+    /// the same reasons as [`from_toml_str`](Self::from_toml_str). Either
+    /// way the error's context names the file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|text| Self::from_toml_str(&text))
+            .map_err(|e| e.with_path(path))
+    }
+
+    /// Checks settings that can't be expressed in their types
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` naming the first invalid setting.
+    pub fn validate(&self) -> Result<()> {
+        let positive = [
+            ("wal.size_limit", self.wal.size_limit),
+            ("memtable.size", self.memtable.size),
+            ("sstable.block_size", self.sstable.block_size),
+            (
+                "compaction.max_bytes_for_level_base",
+                self.compaction.max_bytes_for_level_base,
+            ),
+            (
+                "compaction.target_file_size_base",
+                self.compaction.target_file_size_base,
+            ),
+        ];
+        for (name, size) in positive {
+            if size == Some(ByteSize(0)) {
+                return Err(invalid(format!("{} must be at least 1 byte", name)));
+            }
+        }
+        if self.memtable.max_immutable == Some(0) {
+            return Err(invalid("memtable.max_immutable must be at least 1"));
+        }
+        if self.compaction.level0_file_num_trigger == Some(0) {
+            return Err(invalid(
+                "compaction.level0_file_num_trigger must be at least 1",
+            ));
+        }
+        if let Some(multiplier) = self.compaction.max_bytes_for_level_multiplier {
+            if !(multiplier > 1.0 && multiplier.is_finite()) {
+                return Err(invalid(
+                    "compaction.max_bytes_for_level_multiplier must be above 1",
+                ));
+            }
+        }
+        if let (Some(slowdown), Some(stop)) = (
+            self.compaction.level0_slowdown_writes_trigger,
+            self.compaction.level0_stop_writes_trigger,
+        ) {
+            if slowdown > stop {
+                return Err(invalid(
+                    "compaction.level0_slowdown_writes_trigger must not exceed \
+                     level0_stop_writes_trigger",
+                ));
+            }
+        }
+        if self.server.max_scan_limit == Some(0) {
+            return Err(invalid("server.max_scan_limit must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidArgument(message.into())
+}
+
+/// A number of bytes, written in configuration files as an integer or as
+/// a string with a unit such as `"64MB"`
+///
+/// Units are `B`, `KB`, `MB`, `GB` and `TB`, case-insensitive and
+/// optionally with a space before them. They are powers of 1024, and may
+/// also be written `KiB`, `MiB`, `GiB` and `TiB`. Fractions are allowed
+/// as long as the result is a whole number of bytes, e.g. `"1.5KB"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    /// Returns `n` kibibytes
+    pub const fn kib(n: u64) -> Self {
+        Self(n * 1024)
+    }
+
+    /// Returns `n` mebibytes
+    pub const fn mib(n: u64) -> Self {
+        Self(n * 1024 * 1024)
+    }
+
+    /// Returns `n` gibibytes
+    pub const fn gib(n: u64) -> Self {
+        Self(n * 1024 * 1024 * 1024)
+    }
+
+    /// Returns the number of bytes
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the number of bytes as a `usize`, saturating on 32-bit
+    /// targets
+    pub fn as_usize(self) -> usize {
+        usize::try_from(self.0).unwrap_or(usize::MAX)
+    }
+}
+
+/// Units from largest to smallest, so `"MB"` isn't read as `"B"`
+const UNITS: [(&str, u64); 9] = [
+    ("TIB", 1 << 40),
+    ("GIB", 1 << 30),
+    ("MIB", 1 << 20),
+    ("KIB", 1 << 10),
+    ("TB", 1 << 40),
+    ("GB", 1 << 30),
+    ("MB", 1 << 20),
+    ("KB", 1 << 10),
+    ("B", 1),
+];
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let upper = text.to_ascii_uppercase();
+        let (number, multiplier) = UNITS
+            .iter()
+            .find_map(|(unit, multiplier)| {
+                upper
+                    .strip_suffix(unit)
+                    .map(|number| (number.trim_end(), *multiplier))
+            })
+            .unwrap_or((text, 1));
+        let size = || invalid(format!("Invalid size {:?}, expected e.g. \"64MB\"", text));
+
+        if let Ok(whole) = number.parse::<u64>() {
+            return whole.checked_mul(multiplier).map(Self).ok_or_else(size);
+        }
+        let fraction: f64 = number.parse().map_err(|_| size())?;
+        let bytes = fraction * multiplier as f64;
+        if !(bytes >= 0.0 && bytes.fract() == 0.0 && bytes < u64::MAX as f64) {
+            return Err(size());
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// Writes the size in the largest unit that divides it evenly
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unit, multiplier) = UNITS[4..]
+            .iter()
+            .find(|(_, multiplier)| self.0 != 0 && self.0 % multiplier == 0)
+            .unwrap_or(&("B", 1));
+        write!(f, "{}{}", self.0 / multiplier, unit)
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct ByteSizeVisitor;
+
+        impl Visitor<'_> for ByteSizeVisitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a number of bytes or a size such as \"64MB\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, bytes: u64) -> std::result::Result<ByteSize, E> {
+                Ok(ByteSize(bytes))
+            }
+
+            fn visit_i64<E: de::Error>(self, bytes: i64) -> std::result::Result<ByteSize, E> {
+                u64::try_from(bytes)
+                    .map(ByteSize)
+                    .map_err(|_| E::custom("a size can't be negative"))
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> std::result::Result<ByteSize, E> {
+                text.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ByteSizeVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    #[test]
+    fn test_sizes_parse_with_and_without_units() {
+        for (text, bytes) in [
+            ("0", 0),
+            ("512", 512),
+            ("512B", 512),
+            ("4KB", 4096),
+            ("4 kb", 4096),
+            ("4KiB", 4096),
+            ("64MB", 64 << 20),
+            ("1.5GB", 3 << 29),
+            ("2TiB", 2 << 40),
+        ] {
+            assert_eq!(
+                text.parse::<ByteSize>().unwrap(),
+                ByteSize(bytes),
+                "{}",
+                text
+            );
+        }
+        for text in ["", "MB", "-1KB", "1.1B", "64XB", "99999999999TB"] {
+            assert!(text.parse::<ByteSize>().is_err(), "{}", text);
+        }
+        assert_eq!(ByteSize::mib(64).to_string(), "64MB");
+        assert_eq!(ByteSize(1536).to_string(), "1536B");
+        assert_eq!(ByteSize(0).to_string(), "0B");
+    }
+
+    #[test]
+    fn test_empty_file_sets_nothing() {
+        assert_eq!(Config::from_toml_str("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_every_section_is_parsed() {
+        let config = Config::from_toml_str(
+            r#"
+            data_dir = "/var/lib/ferrisdb"
+
+            [wal]
+            sync_mode = "Full"
+            size_limit = "32MB"
+
+            [memtable]
+            size = 8388608
+            max_immutable = 3
+            write_buffer_budget = "64MB"
+
+            [sstable]
+            block_cache_size = "1GB"
+            compression = "Snappy"
+
+            [compaction]
+            level0_file_num_trigger = 8
+            max_bytes_for_level_multiplier = 8.0
+            rate_limit = "100MB"
+
+            [server]
+            listen_addr = "0.0.0.0:7070"
+            max_scan_limit = 50
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/ferrisdb")));
+        assert_eq!(config.wal.sync_mode, Some(SyncMode::Full));
+        assert_eq!(config.wal.size_limit, Some(ByteSize::mib(32)));
+        assert_eq!(config.memtable.size, Some(ByteSize::mib(8)));
+        assert_eq!(config.memtable.write_buffer_budget, Some(ByteSize::mib(64)));
+        assert_eq!(config.sstable.block_cache_size, Some(ByteSize::gib(1)));
+        assert_eq!(config.sstable.compression, Some(CompressionType::Snappy));
+        assert_eq!(config.compaction.level0_file_num_trigger, Some(8));
+        assert_eq!(config.compaction.rate_limit, Some(ByteSize::mib(100)));
+        assert_eq!(
+            config.server.listen_addr,
+            Some("0.0.0.0:7070".parse().unwrap())
+        );
+        assert_eq!(config.sstable.block_size, None);
+    }
+
+    #[test]
+    fn test_invalid_settings_are_rejected() {
+        for text in [
+            "unknown = 1",
+            "[wal]\nsync_mode = \"Sometimes\"",
+            "[memtable]\nsize = \"lots\"",
+            "[memtable]\nsize = -1",
+            "[memtable]\nsize = 0",
+            "[memtable]\nmax_immutable = 0",
+            "[compaction]\nmax_bytes_for_level_multiplier = 1.0",
+            "[compaction]\nlevel0_slowdown_writes_trigger = 40\nlevel0_stop_writes_trigger = 36",
+            "[server]\nlisten_addr = \"nowhere\"",
+            "[server]\nmax_scan_limit = 0",
+        ] {
+            let error = Config::from_toml_str(text).unwrap_err();
+            assert!(matches!(error, Error::InvalidArgument(_)), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_from_file_names_the_file() {
+        let dir = std::env::temp_dir().join(format!("ferrisdb-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ferrisdb.toml");
+        std::fs::write(&path, "[memtable]\nsize = \"1XB\"").unwrap();
+
+        let error = Config::from_file(&path).unwrap_err();
+        assert!(error.to_string().contains("ferrisdb.toml"), "{}", error);
+        assert_eq!(error.code(), ErrorCode::InvalidArgument);
+        assert_eq!(
+            error.context().unwrap().path.as_deref(),
+            Some(path.as_path())
+        );
+        let error = Config::from_file(dir.join("missing.toml")).unwrap_err();
+        assert!(matches!(error.root(), Error::Io(_)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,42 @@
 //! Error types for FerrisDB
 //!
 //! This module defines the error types used throughout FerrisDB.
+//!
+//! Every error belongs to a category given by its [`ErrorCode`], a stable
+//! number that clients and the gRPC layer can act on without matching
+//! message text. Errors about a particular file can carry its path and the
+//! offset where the problem was found, see [`Error::with_path`] and
+//! [`Error::at_offset`], and checksum mismatches the checksums involved,
+//! see [`Error::with_checksums`].
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_core::error::{Error, ErrorCode};
+//!
+//! let error = Error::Corruption("block checksum mismatch".to_string())
+//!     .with_path("/data/000012.sst")
+//!     .at_offset(4096);
+//!
+//! assert_eq!(error.code(), ErrorCode::Corruption);
+//! assert_eq!(error.context().unwrap().offset, Some(4096));
+//! assert!(matches!(error.root(), Error::Corruption(_)));
+//! ```
 
 use thiserror::Error;
 
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// OS error numbers of a full disk or an exhausted disk quota
+#[cfg(unix)]
+const DISK_FULL_OS_ERRORS: &[i32] = &[libc::ENOSPC, libc::EDQUOT];
+/// `ERROR_HANDLE_DISK_FULL`, `ERROR_DISK_FULL` and `ERROR_DISK_QUOTA_EXCEEDED`
+#[cfg(windows)]
+const DISK_FULL_OS_ERRORS: &[i32] = &[39, 112, 1295];
+#[cfg(not(any(unix, windows)))]
+const DISK_FULL_OS_ERRORS: &[i32] = &[];
+
 /// The main error type for FerrisDB operations
 #[derive(Error, Debug)]
 pub enum Error {
@@ -58,7 +91,361 @@ pub enum Error {
     /// A transaction error occurred
     #[error("Transaction error: {0}")]
     Transaction(String),
+
+    /// A named resource other than a key, such as a file or column family,
+    /// does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// The operation could not run right now and may succeed if retried
+    #[error("Busy: {0}")]
+    Busy(String),
+
+    /// There is no room left to write to
+    #[error("Disk full: {0}")]
+    DiskFull(String),
+
+    /// An argument supplied by the caller is invalid
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// The operation or format is not supported
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// A database directory is already open in another engine
+    #[error("Already locked: {0}")]
+    AlreadyLocked(String),
+
+    /// A scan reached a limit its caller set on keys, bytes or time
+    #[error("Scan limit exceeded: {0}")]
+    ScanLimitExceeded(String),
+
+    /// A file would grow past the size limit it was opened with
+    #[error("Size limit exceeded: {0}")]
+    SizeLimitExceeded(String),
+
+    /// Another error, with the file and offset it happened at
+    #[error("{source} ({context})")]
+    WithContext {
+        source: Box<Error>,
+        context: ErrorContext,
+    },
+}
+
+/// The gRPC metadata key a server reports the [`ErrorCode`] of a failed
+/// call under, as a decimal number
+pub const ERROR_CODE_METADATA: &str = "ferrisdb-error-code";
+
+/// Where in the data files an error happened
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The file being read or written
+    pub path: Option<PathBuf>,
+    /// The byte offset in the file
+    pub offset: Option<u64>,
+    /// The checksums that didn't match, if that was the problem
+    pub checksum: Option<ChecksumMismatch>,
+}
+
+/// A checksum stored with some data and the one computed from its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The checksum stored with the data
+    pub expected: u32,
+    /// The checksum of the bytes read
+    pub actual: u32,
+}
+
+/// The category of an [`Error`]
+///
+/// The numbers are part of the wire protocol and never change; new
+/// categories only get new numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// A bug or unexpected internal state
+    Internal = 1,
+    /// The operating system failed an I/O operation
+    Io = 2,
+    /// Stored data is damaged or in an unknown format
+    Corruption = 3,
+    /// A key or other resource does not exist
+    NotFound = 4,
+    /// The caller passed something invalid
+    InvalidArgument = 5,
+    /// The operation is not allowed in the current state
+    FailedPrecondition = 6,
+    /// A transaction was aborted, for example by a conflict
+    Aborted = 7,
+    /// The database is temporarily overloaded; retry later
+    Busy = 8,
+    /// There is no space left to write to
+    DiskFull = 9,
+    /// The operation or format is not supported
+    Unsupported = 10,
+    /// The operation reached a limit set on it, such as a scan's or a file's
+    LimitExceeded = 11,
+}
+
+impl Error {
+    /// Returns the category of this error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            // Matched by OS error, as the kinds for them need Rust 1.83
+            Error::Io(e)
+                if e.raw_os_error()
+                    .is_some_and(|errno| DISK_FULL_OS_ERRORS.contains(&errno)) =>
+            {
+                ErrorCode::DiskFull
+            }
+            Error::Io(e) => match e.kind() {
+                std::io::ErrorKind::Unsupported => ErrorCode::Unsupported,
+                _ => ErrorCode::Io,
+            },
+            Error::Serialization(_) | Error::StorageEngine(_) => ErrorCode::Internal,
+            Error::KeyNotFound | Error::NotFound(_) => ErrorCode::NotFound,
+            Error::Corruption(_) | Error::InvalidFormat(_) => ErrorCode::Corruption,
+            Error::InvalidOperation(_) | Error::ResourceConsumed(_) | Error::AlreadyLocked(_) => {
+                ErrorCode::FailedPrecondition
+            }
+            Error::MemTableFull
+            | Error::EntrySizeExceeded { .. }
+            | Error::EmptyOperation(_)
+            | Error::KeyOrderingViolation { .. }
+            | Error::InvalidArgument(_) => ErrorCode::InvalidArgument,
+            Error::Transaction(_) => ErrorCode::Aborted,
+            Error::Busy(_) => ErrorCode::Busy,
+            Error::DiskFull(_) => ErrorCode::DiskFull,
+            Error::Unsupported(_) => ErrorCode::Unsupported,
+            Error::ScanLimitExceeded(_) | Error::SizeLimitExceeded(_) => ErrorCode::LimitExceeded,
+            Error::WithContext { source, .. } => source.code(),
+        }
+    }
+
+    /// Returns the error without any file context around it
+    ///
+    /// Match on this rather than on the error itself, since any error may
+    /// have had context added on its way up.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContext { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// Returns the file and offset the error happened at, if known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Records the file the error happened in
+    ///
+    /// An error that already names a file keeps it.
+    pub fn with_path(self, path: impl AsRef<Path>) -> Self {
+        self.add_context(|context| {
+            context
+                .path
+                .get_or_insert_with(|| path.as_ref().to_path_buf());
+        })
+    }
+
+    /// Records the offset in its file the error happened at
+    ///
+    /// An error that already has an offset keeps it.
+    pub fn at_offset(self, offset: u64) -> Self {
+        self.add_context(|context| {
+            context.offset.get_or_insert(offset);
+        })
+    }
+
+    /// Records the checksum stored with the data and the one its bytes
+    /// have, for a checksum mismatch
+    ///
+    /// An error that already has checksums keeps them.
+    pub fn with_checksums(self, expected: u32, actual: u32) -> Self {
+        self.add_context(|context| {
+            context
+                .checksum
+                .get_or_insert(ChecksumMismatch { expected, actual });
+        })
+    }
+
+    /// Returns true if the operation may succeed when retried unchanged
+    pub fn is_retryable(&self) -> bool {
+        self.code() == ErrorCode::Busy
+    }
+
+    fn add_context(self, update: impl FnOnce(&mut ErrorContext)) -> Self {
+        match self {
+            Error::WithContext {
+                source,
+                mut context,
+            } => {
+                update(&mut context);
+                Error::WithContext { source, context }
+            }
+            other => {
+                let mut context = ErrorContext::default();
+                update(&mut context);
+                Error::WithContext {
+                    source: Box::new(other),
+                    context,
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.path, self.offset) {
+            (Some(path), Some(offset)) => write!(f, "file {}, offset {}", path.display(), offset),
+            (Some(path), None) => write!(f, "file {}", path.display()),
+            (None, Some(offset)) => write!(f, "offset {}", offset),
+            (None, None) => write!(f, "no location"),
+        }
+    }
+}
+
+impl ErrorCode {
+    /// Returns the stable number of this code
+    pub fn as_u16(self) -> u16 {
+        self as u16
+    }
+
+    /// Returns the code with number `code`, if there is one
+    pub fn from_u16(code: u16) -> Option<Self> {
+        Some(match code {
+            1 => ErrorCode::Internal,
+            2 => ErrorCode::Io,
+            3 => ErrorCode::Corruption,
+            4 => ErrorCode::NotFound,
+            5 => ErrorCode::InvalidArgument,
+            6 => ErrorCode::FailedPrecondition,
+            7 => ErrorCode::Aborted,
+            8 => ErrorCode::Busy,
+            9 => ErrorCode::DiskFull,
+            10 => ErrorCode::Unsupported,
+            11 => ErrorCode::LimitExceeded,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCode::Internal => "internal",
+            ErrorCode::Io => "io",
+            ErrorCode::Corruption => "corruption",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::InvalidArgument => "invalid_argument",
+            ErrorCode::FailedPrecondition => "failed_precondition",
+            ErrorCode::Aborted => "aborted",
+            ErrorCode::Busy => "busy",
+            ErrorCode::DiskFull => "disk_full",
+            ErrorCode::Unsupported => "unsupported",
+            ErrorCode::LimitExceeded => "limit_exceeded",
+        };
+        f.write_str(name)
+    }
 }
 
 /// A specialized Result type for FerrisDB operations
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_round_trip_through_their_numbers() {
+        for number in 1..=11 {
+            let code = ErrorCode::from_u16(number).unwrap();
+            assert_eq!(code.as_u16(), number);
+        }
+        assert_eq!(ErrorCode::from_u16(0), None);
+        assert_eq!(ErrorCode::from_u16(12), None);
+    }
+
+    #[test]
+    fn test_io_errors_are_categorized_by_kind() {
+        let io = |kind| Error::Io(std::io::Error::new(kind, "test"));
+        assert_eq!(
+            io(std::io::ErrorKind::Unsupported).code(),
+            ErrorCode::Unsupported
+        );
+        assert_eq!(io(std::io::ErrorKind::NotFound).code(), ErrorCode::Io);
+        assert_eq!(io(std::io::ErrorKind::BrokenPipe).code(), ErrorCode::Io);
+    }
+
+    #[test]
+    fn test_full_disks_are_categorized_by_os_error() {
+        for &errno in DISK_FULL_OS_ERRORS {
+            let error = Error::Io(std::io::Error::from_raw_os_error(errno));
+            assert_eq!(error.code(), ErrorCode::DiskFull);
+        }
+    }
+
+    #[test]
+    fn test_context_is_kept_and_displayed() {
+        let error = Error::Corruption("bad block".to_string())
+            .at_offset(128)
+            .with_path("000007.sst")
+            .with_path("other.sst")
+            .at_offset(256);
+
+        assert_eq!(error.code(), ErrorCode::Corruption);
+        assert!(matches!(error.root(), Error::Corruption(msg) if msg == "bad block"));
+        assert_eq!(
+            error.context(),
+            Some(&ErrorContext {
+                path: Some(PathBuf::from("000007.sst")),
+                offset: Some(128),
+                checksum: None,
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "Corruption detected: bad block (file 000007.sst, offset 128)"
+        );
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn test_checksums_are_kept_with_the_location() {
+        let error = Error::Corruption("checksum mismatch".to_string())
+            .with_checksums(0xdead, 0xbeef)
+            .with_path("000003.wal")
+            .with_checksums(1, 2);
+
+        let context = error.context().unwrap();
+        assert_eq!(
+            context.checksum,
+            Some(ChecksumMismatch {
+                expected: 0xdead,
+                actual: 0xbeef,
+            })
+        );
+        assert_eq!(context.path, Some(PathBuf::from("000003.wal")));
+        // The message already gives the checksums
+        assert_eq!(
+            error.to_string(),
+            "Corruption detected: checksum mismatch (file 000003.wal)"
+        );
+    }
+
+    #[test]
+    fn test_only_busy_errors_are_retryable() {
+        assert!(Error::Busy("too many flushes".to_string()).is_retryable());
+        assert!(Error::Busy("stall".to_string())
+            .with_path("wal.log")
+            .is_retryable());
+        assert!(!Error::DiskFull("no space".to_string()).is_retryable());
+        assert!(!Error::KeyNotFound.is_retryable());
+    }
+}
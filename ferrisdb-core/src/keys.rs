@@ -0,0 +1,356 @@
+//! Order-preserving key encoding
+//!
+//! Keys are compared as raw bytes, so a secondary index built on top of
+//! the key-value API only scans correctly if its keys encode values in an
+//! order matching the values themselves. Formatting numbers as text gets
+//! this wrong (`"10" < "9"`), and so does concatenating strings, where
+//! `("ab", "c")` and `("a", "bc")` collide. The encoders in this module
+//! produce bytes that compare exactly like the values they encode
+//! (memcomparable encoding):
+//!
+//! | Type            | Encoding                                               |
+//! | --------------- | ------------------------------------------------------ |
+//! | `u64`           | 8 bytes, big-endian                                    |
+//! | `i64`           | 8 bytes, big-endian, sign bit flipped                  |
+//! | `f64`           | 8 bytes, big-endian, ordered like [`f64::total_cmp`]   |
+//! | `str`, `[u8]`   | `0x00` escaped as `0x00 0xFF`, then `0x00 0x01`        |
+//! | tuples          | components one after another                           |
+//!
+//! Every encoding is self-delimiting, so a tuple compares component by
+//! component, and the encoding of a tuple's first components is a prefix
+//! of the encoding of the whole tuple. That makes prefix scans, e.g. over
+//! every entry of one index value, a matter of scanning from the encoded
+//! prefix up to its [`prefix_successor`].
+//!
+//! The encoding holds no type tags: values must be decoded with the types
+//! they were encoded with.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_core::keys;
+//!
+//! // An index on (city, age) pointing at user ids
+//! let alice = keys::encode(&("Berlin", 34i64, 7u64));
+//! let bob = keys::encode(&("Berlin", -1i64, 9u64));
+//! let carol = keys::encode(&("Bern", 20i64, 3u64));
+//! assert!(bob < alice && alice < carol);
+//!
+//! // Every entry for Berlin lies in [start, end)
+//! let start = keys::encode(&("Berlin",));
+//! let end = keys::prefix_successor(&start).unwrap();
+//! assert!(start <= bob && alice < end && carol >= end);
+//!
+//! let (city, age, id): (String, i64, u64) = keys::decode(&alice)?;
+//! assert_eq!((city.as_str(), age, id), ("Berlin", 34, 7));
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use crate::types::Key;
+
+/// Byte following an escaped `0x00` inside a string
+const ESCAPED_ZERO: u8 = 0xFF;
+
+/// Byte following the `0x00` that ends a string
+const TERMINATOR: u8 = 0x01;
+
+/// A value that can be encoded into an order-preserving key
+pub trait KeyEncode {
+    /// Appends the encoding of `self` to `out`
+    fn encode_key(&self, out: &mut Vec<u8>);
+}
+
+/// A value that can be decoded from an order-preserving key
+pub trait KeyDecode: Sized {
+    /// Decodes a value from the front of `input`, advancing it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `input` doesn't start with a
+    /// valid encoding.
+    fn decode_key(input: &mut &[u8]) -> Result<Self>;
+}
+
+/// Encodes `value` into a key
+pub fn encode<T: KeyEncode + ?Sized>(value: &T) -> Key {
+    let mut out = Vec::new();
+    value.encode_key(&mut out);
+    out
+}
+
+/// Decodes a key holding exactly one `T`
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidArgument`] if `key` isn't a valid encoding of a
+/// `T`, including when bytes are left over.
+pub fn decode<T: KeyDecode>(key: &[u8]) -> Result<T> {
+    let mut input = key;
+    let value = T::decode_key(&mut input)?;
+    if !input.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "{} bytes left over after decoding key",
+            input.len()
+        )));
+    }
+    Ok(value)
+}
+
+/// Returns the smallest key above every key starting with `prefix`
+///
+/// `None` if there is no such key, i.e. the prefix is all `0xFF` bytes.
+pub fn prefix_successor(prefix: &[u8]) -> Option<Key> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < u8::MAX {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+    None
+}
+
+/// Splits `N` bytes off the front of `input`
+fn take<const N: usize>(input: &mut &[u8]) -> Result<[u8; N]> {
+    let (head, rest) = input
+        .split_first_chunk::<N>()
+        .ok_or_else(|| Error::InvalidArgument(format!("Key ends before a {}-byte component", N)))?;
+    *input = rest;
+    Ok(*head)
+}
+
+impl KeyEncode for u64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl KeyDecode for u64 {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        Ok(u64::from_be_bytes(take(input)?))
+    }
+}
+
+impl KeyEncode for i64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        // Flipping the sign bit moves negatives below positives
+        ((*self as u64) ^ (1 << 63)).encode_key(out);
+    }
+}
+
+impl KeyDecode for i64 {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        Ok((u64::decode_key(input)? ^ (1 << 63)) as i64)
+    }
+}
+
+impl KeyEncode for f64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        // Negatives have every bit inverted so larger magnitudes sort
+        // first; positives only get the sign bit set to sort above them
+        let bits = self.to_bits();
+        let ordered = if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits | (1 << 63)
+        };
+        ordered.encode_key(out);
+    }
+}
+
+impl KeyDecode for f64 {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        let ordered = u64::decode_key(input)?;
+        let bits = if ordered >> 63 == 1 {
+            ordered & !(1 << 63)
+        } else {
+            !ordered
+        };
+        Ok(f64::from_bits(bits))
+    }
+}
+
+impl KeyEncode for [u8] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for &byte in self {
+            out.push(byte);
+            if byte == 0 {
+                out.push(ESCAPED_ZERO);
+            }
+        }
+        out.extend_from_slice(&[0, TERMINATOR]);
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_key(out);
+    }
+}
+
+impl KeyDecode for Vec<u8> {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            let [byte] = take(input)?;
+            if byte != 0 {
+                bytes.push(byte);
+                continue;
+            }
+            match take(input)? {
+                [ESCAPED_ZERO] => bytes.push(0),
+                [TERMINATOR] => return Ok(bytes),
+                [other] => {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid byte {:#04x} after 0x00 in a key string",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_bytes().encode_key(out);
+    }
+}
+
+impl KeyDecode for String {
+    fn decode_key(input: &mut &[u8]) -> Result<Self> {
+        String::from_utf8(Vec::decode_key(input)?)
+            .map_err(|_| Error::InvalidArgument("Key string is not UTF-8".to_string()))
+    }
+}
+
+impl<T: KeyEncode + ?Sized> KeyEncode for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out);
+    }
+}
+
+/// Implements the traits for tuples by encoding each component in turn
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: KeyEncode),+> KeyEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(out);)+
+            }
+        }
+
+        impl<$($name: KeyDecode),+> KeyDecode for ($($name,)+) {
+            fn decode_key(input: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::decode_key(input)?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that encoding preserves the order of `values`, which must
+    /// be sorted, and that each one decodes back to itself
+    fn assert_ordered<T>(values: &[T])
+    where
+        T: KeyEncode + KeyDecode + PartialEq + std::fmt::Debug,
+    {
+        let encoded: Vec<Key> = values.iter().map(encode).collect();
+        for (i, pair) in encoded.windows(2).enumerate() {
+            assert!(
+                pair[0] < pair[1],
+                "{:?} should sort before {:?}",
+                values[i],
+                values[i + 1]
+            );
+        }
+        for (value, key) in values.iter().zip(&encoded) {
+            assert_eq!(&decode::<T>(key).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_numbers_keep_their_order() {
+        assert_ordered(&[0u64, 1, 255, 256, u64::MAX]);
+        assert_ordered(&[i64::MIN, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_ordered(&[
+            f64::NEG_INFINITY,
+            -1e300,
+            -1.5,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            2.5,
+            f64::INFINITY,
+        ]);
+    }
+
+    #[test]
+    fn test_strings_keep_their_order_and_zero_bytes() {
+        assert_ordered(&[
+            Vec::new(),
+            vec![0],
+            vec![0, 0],
+            vec![0, 1],
+            vec![1],
+            vec![1, 0],
+            vec![0xFF],
+        ]);
+        assert_ordered(&[
+            "".to_string(),
+            "a".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_tuples_compare_component_by_component() {
+        // Plain concatenation would make both of these "abc"
+        assert_ordered(&[
+            ("a".to_string(), "bc".to_string()),
+            ("ab".to_string(), "c".to_string()),
+        ]);
+        assert_ordered(&[(1u64, -5i64), (1, 3), (2, i64::MIN)]);
+
+        let prefix = encode(&("idx", 1u64));
+        let key = encode(&("idx", 1u64, "pk"));
+        assert!(key.starts_with(&prefix));
+        assert!(key < prefix_successor(&prefix).unwrap());
+    }
+
+    #[test]
+    fn test_malformed_keys_are_rejected() {
+        assert!(decode::<u64>(&[1, 2, 3]).is_err());
+        assert!(decode::<u64>(&[0; 9]).is_err());
+        assert!(decode::<Vec<u8>>(b"abc").is_err());
+        assert!(decode::<Vec<u8>>(&[b'a', 0, 7]).is_err());
+        let err = decode::<String>(&[0xC3, 0, 1]).unwrap_err();
+        assert_eq!(err.code(), crate::error::ErrorCode::InvalidArgument);
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_successor(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_successor(b"\xff"), None);
+    }
+}
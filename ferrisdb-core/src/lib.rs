@@ -5,7 +5,9 @@
 //!
 //! - Common error types with [`Error`] and [`Result`]
 //! - Basic data types like [`Key`], [`Value`], and [`Operation`]
-//! - Configuration types for storage and synchronization
+//! - Configuration types for storage and synchronization, and [`Config`]
+//!   files read from TOML
+//! - Order-preserving encoding of typed values into [`keys`]
 //!
 //! # Example
 //!
@@ -17,8 +19,11 @@
 //! let op = Operation::Put;
 //! ```
 
+pub mod config;
 pub mod error;
+pub mod keys;
 pub mod types;
 
+pub use config::{ByteSize, Config};
 pub use error::{Error, Result};
 pub use types::*;
@@ -24,6 +24,12 @@ pub enum Operation {
     Put,
     /// Delete a key
     Delete,
+    /// Combine an operand with the existing value using a merge operator
+    ///
+    /// The value carries the operand. Operands are stacked on top of the
+    /// newest Put or Delete and resolved lazily, at read time or during
+    /// compaction.
+    Merge,
 }
 
 /// A simple key-value pair
@@ -40,7 +46,7 @@ pub struct KeyValue {
 pub struct TimestampedKeyValue {
     /// The key
     pub key: Key,
-    /// The value (empty for Delete operations)
+    /// The value (empty for Delete operations, the operand for Merge)
     pub value: Value,
     /// The timestamp when this operation occurred
     pub timestamp: Timestamp,
@@ -0,0 +1,112 @@
+//! HTTP endpoint for Prometheus scrapes
+//!
+//! A minimal HTTP/1.1 server: `GET /metrics` returns the registry in the
+//! text exposition format, every other request gets a 404.
+
+use prometheus::Registry;
+
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+
+use std::convert::Infallible;
+
+/// Path answered with the metrics
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Serves `registry` on `listener` until the task is dropped
+///
+/// Each connection is handled on a task of its own; a failed connection is
+/// logged and doesn't stop the server.
+///
+/// # Errors
+///
+/// Returns an error if accepting a connection fails.
+pub async fn serve(listener: TcpListener, registry: Registry) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let response = respond(&registry, &request);
+                async move { Ok::<_, Infallible>(response) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::debug!("Metrics connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn respond(registry: &Registry, request: &Request<Incoming>) -> Response<Full<Bytes>> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        return status(StatusCode::NOT_FOUND, "Not found\n".to_string());
+    }
+    match crate::encode(registry) {
+        Ok(text) => Response::builder()
+            .header(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)
+            .body(Full::new(Bytes::from(text)))
+            .expect("valid response"),
+        Err(e) => status(StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", e)),
+    }
+}
+
+fn status(code: StatusCode, message: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(code)
+        .body(Full::new(Bytes::from(message)))
+        .expect("valid response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_storage::{Options, StorageEngine};
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_scrape_serves_metrics_path_only() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap());
+        engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        let registry = Registry::new();
+        crate::register(&registry, Arc::clone(&engine)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, registry));
+
+        let response = get(addr, METRICS_PATH).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("ferrisdb_storage_estimated_keys 1"));
+        assert!(response.contains("ferrisdb_wal_writes_total 1"));
+
+        let response = get(addr, "/").await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        server.abort();
+    }
+}
@@ -0,0 +1,565 @@
+//! Prometheus metrics for FerrisDB
+//!
+//! [`StorageCollector`] reads a [`StorageEngine`]'s statistics, WAL metrics
+//! and write stall counters each time a registry is gathered, so one scrape
+//! covers the whole storage engine:
+//!
+//! ```text
+//! Registry::gather()
+//!      │
+//!      ▼
+//! StorageCollector ──▶ StorageEngine::statistics()   keys, levels, MemTables,
+//!                  │                                 block cache, flushes,
+//!                  │                                 compactions, latencies
+//!                  ├─▶ StorageEngine::wal_metrics()  writes, bytes, syncs,
+//!                  │                                 latencies
+//!                  └─▶ WriteController::metrics()    delayed and stopped writes
+//! ```
+//!
+//! Latencies are exported as summaries with the 0.5, 0.95, 0.99 and 0.999
+//! quantiles of everything since the engine opened, in seconds.
+//!
+//! With the `http` feature, [`http::serve`] answers `GET /metrics` with the
+//! registry in the Prometheus text format.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::{Options, StorageEngine};
+//! use prometheus::Registry;
+//! use std::sync::Arc;
+//!
+//! let dir = tempfile::tempdir().unwrap();
+//! let engine = Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap());
+//! engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+//!
+//! let registry = Registry::new();
+//! ferrisdb_metrics::register(&registry, Arc::clone(&engine)).unwrap();
+//! let text = ferrisdb_metrics::encode(&registry).unwrap();
+//! assert!(text.contains("ferrisdb_storage_estimated_keys 1"));
+//! ```
+
+#[cfg(feature = "http")]
+pub mod http;
+
+use ferrisdb_storage::histogram::HistogramSnapshot;
+use ferrisdb_storage::StorageEngine;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{self, MetricFamily, MetricType, Quantile};
+use prometheus::{
+    Counter, Encoder, IntCounter, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Prefix of every metric name
+const NAMESPACE: &str = "ferrisdb";
+
+/// Exports the state of a storage engine as Prometheus metrics
+///
+/// Values are read from the engine on every gather rather than pushed by
+/// it, so the engine pays nothing between scrapes. Counters mirror the
+/// engine's own counters, which start at zero when it opens.
+pub struct StorageCollector {
+    engine: Arc<StorageEngine>,
+    /// Serializes gathers, which move the mirrored counters forward
+    lock: Mutex<()>,
+    storage: StorageMetrics,
+    wal: WalMetrics,
+    write_stall: WriteStallMetrics,
+}
+
+struct StorageMetrics {
+    estimated_keys: IntGauge,
+    level_files: IntGaugeVec,
+    level_bytes: IntGaugeVec,
+    pending_compaction_bytes: IntGauge,
+    memtable_bytes: IntGaugeVec,
+    immutable_memtables: IntGauge,
+    block_cache_hits: IntCounter,
+    block_cache_misses: IntCounter,
+    flushes: IntCounter,
+    flush_bytes_written: IntCounter,
+    compactions: IntCounter,
+    compaction_bytes_read: IntCounter,
+    compaction_bytes_written: IntCounter,
+    read_latency: LatencySummary,
+    write_latency: LatencySummary,
+    flush_latency: LatencySummary,
+    compaction_latency: LatencySummary,
+}
+
+struct WalMetrics {
+    writes: IntCounter,
+    write_failures: IntCounter,
+    bytes_written: IntCounter,
+    syncs: IntCounter,
+    sync_seconds: Counter,
+    current_file_bytes: IntGauge,
+    write_latency: LatencySummary,
+    sync_latency: LatencySummary,
+}
+
+struct WriteStallMetrics {
+    delayed_writes: IntCounter,
+    stopped_writes: IntCounter,
+    stall_seconds: Counter,
+}
+
+impl StorageCollector {
+    /// Creates a collector reading from `engine`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a metric definition is invalid, which would be
+    /// a bug in this crate.
+    pub fn new(engine: Arc<StorageEngine>) -> prometheus::Result<Self> {
+        Ok(Self {
+            engine,
+            lock: Mutex::new(()),
+            storage: StorageMetrics {
+                estimated_keys: IntGauge::with_opts(opts(
+                    "storage",
+                    "estimated_keys",
+                    "Entries in MemTables and tables, counting versions and tombstones",
+                ))?,
+                level_files: IntGaugeVec::new(
+                    opts("storage", "level_files", "Tables in each level"),
+                    &["level"],
+                )?,
+                level_bytes: IntGaugeVec::new(
+                    opts(
+                        "storage",
+                        "level_bytes",
+                        "Bytes of the tables in each level",
+                    ),
+                    &["level"],
+                )?,
+                pending_compaction_bytes: IntGauge::with_opts(opts(
+                    "storage",
+                    "pending_compaction_bytes",
+                    "Bytes compaction has to rewrite to reach its targets",
+                ))?,
+                memtable_bytes: IntGaugeVec::new(
+                    opts("storage", "memtable_bytes", "Bytes held by MemTables"),
+                    &["state"],
+                )?,
+                immutable_memtables: IntGauge::with_opts(opts(
+                    "storage",
+                    "immutable_memtables",
+                    "MemTables waiting for a flush",
+                ))?,
+                block_cache_hits: IntCounter::with_opts(opts(
+                    "storage",
+                    "block_cache_hits_total",
+                    "Block loads served from the block cache",
+                ))?,
+                block_cache_misses: IntCounter::with_opts(opts(
+                    "storage",
+                    "block_cache_misses_total",
+                    "Block loads that read the table file",
+                ))?,
+                flushes: IntCounter::with_opts(opts(
+                    "storage",
+                    "flushes_total",
+                    "MemTable flushes, one per column family",
+                ))?,
+                flush_bytes_written: IntCounter::with_opts(opts(
+                    "storage",
+                    "flush_bytes_written_total",
+                    "Bytes of the tables written by flushes",
+                ))?,
+                compactions: IntCounter::with_opts(opts(
+                    "storage",
+                    "compactions_total",
+                    "Finished compactions",
+                ))?,
+                compaction_bytes_read: IntCounter::with_opts(opts(
+                    "storage",
+                    "compaction_bytes_read_total",
+                    "Bytes of the tables read by compactions",
+                ))?,
+                compaction_bytes_written: IntCounter::with_opts(opts(
+                    "storage",
+                    "compaction_bytes_written_total",
+                    "Bytes of the tables written by compactions",
+                ))?,
+                read_latency: LatencySummary::new(
+                    "storage",
+                    "read_latency_seconds",
+                    "Time point reads took",
+                )?,
+                write_latency: LatencySummary::new(
+                    "storage",
+                    "write_latency_seconds",
+                    "Time writes took, stalls included",
+                )?,
+                flush_latency: LatencySummary::new(
+                    "storage",
+                    "flush_latency_seconds",
+                    "Time MemTable flushes took",
+                )?,
+                compaction_latency: LatencySummary::new(
+                    "storage",
+                    "compaction_latency_seconds",
+                    "Time compactions took",
+                )?,
+            },
+            wal: WalMetrics {
+                writes: IntCounter::with_opts(opts("wal", "writes_total", "WAL records written"))?,
+                write_failures: IntCounter::with_opts(opts(
+                    "wal",
+                    "write_failures_total",
+                    "WAL writes that failed",
+                ))?,
+                bytes_written: IntCounter::with_opts(opts(
+                    "wal",
+                    "bytes_written_total",
+                    "Bytes written to the WAL",
+                ))?,
+                syncs: IntCounter::with_opts(opts("wal", "syncs_total", "WAL syncs to disk"))?,
+                sync_seconds: Counter::with_opts(opts(
+                    "wal",
+                    "sync_seconds_total",
+                    "Time spent syncing the WAL",
+                ))?,
+                current_file_bytes: IntGauge::with_opts(opts(
+                    "wal",
+                    "current_file_bytes",
+                    "Size of the active WAL segment",
+                ))?,
+                write_latency: LatencySummary::new(
+                    "wal",
+                    "write_latency_seconds",
+                    "Time writing a WAL record took, syncs included",
+                )?,
+                sync_latency: LatencySummary::new(
+                    "wal",
+                    "sync_latency_seconds",
+                    "Time WAL syncs took",
+                )?,
+            },
+            write_stall: WriteStallMetrics {
+                delayed_writes: IntCounter::with_opts(opts(
+                    "write_stall",
+                    "delayed_writes_total",
+                    "Writes slowed down by a compaction backlog",
+                ))?,
+                stopped_writes: IntCounter::with_opts(opts(
+                    "write_stall",
+                    "stopped_writes_total",
+                    "Writes stopped by a compaction backlog",
+                ))?,
+                stall_seconds: Counter::with_opts(opts(
+                    "write_stall",
+                    "seconds_total",
+                    "Time writers spent delayed or stopped",
+                ))?,
+            },
+        })
+    }
+
+    /// Copies the engine's current values into the metrics
+    fn update(&self) {
+        let stats = self.engine.statistics();
+        let storage = &self.storage;
+        storage.estimated_keys.set(stats.estimated_num_keys as i64);
+        for (level, (files, bytes)) in stats.level_files.iter().zip(&stats.level_bytes).enumerate()
+        {
+            let level = level.to_string();
+            storage
+                .level_files
+                .with_label_values(&[level.as_str()])
+                .set(*files as i64);
+            storage
+                .level_bytes
+                .with_label_values(&[level.as_str()])
+                .set(*bytes as i64);
+        }
+        storage
+            .pending_compaction_bytes
+            .set(stats.pending_compaction_bytes as i64);
+        storage
+            .memtable_bytes
+            .with_label_values(&["active"])
+            .set(stats.active_memtable_bytes as i64);
+        storage
+            .memtable_bytes
+            .with_label_values(&["immutable"])
+            .set(stats.immutable_memtable_bytes as i64);
+        storage
+            .immutable_memtables
+            .set(stats.immutable_memtables as i64);
+        advance(&storage.block_cache_hits, stats.block_cache_hits);
+        advance(&storage.block_cache_misses, stats.block_cache_misses);
+        advance(&storage.flushes, stats.flushes);
+        advance(&storage.flush_bytes_written, stats.flush_bytes_written);
+        advance(&storage.compactions, stats.compactions);
+        advance(&storage.compaction_bytes_read, stats.compaction_bytes_read);
+        advance(
+            &storage.compaction_bytes_written,
+            stats.compaction_bytes_written,
+        );
+        storage.read_latency.set(stats.read_latency);
+        storage.write_latency.set(stats.write_latency);
+        storage.flush_latency.set(stats.flush_latency);
+        storage.compaction_latency.set(stats.compaction_latency);
+
+        let wal_metrics = self.engine.wal_metrics();
+        let wal = &self.wal;
+        advance(&wal.writes, wal_metrics.writes_total());
+        advance(&wal.write_failures, wal_metrics.writes_failed());
+        advance(&wal.bytes_written, wal_metrics.bytes_written());
+        advance(&wal.syncs, wal_metrics.sync_total());
+        advance_seconds(
+            &wal.sync_seconds,
+            wal_metrics.sync_duration_ms() as f64 / 1000.0,
+        );
+        wal.current_file_bytes
+            .set(wal_metrics.current_file_size() as i64);
+        wal.write_latency.set(wal_metrics.write_latency());
+        wal.sync_latency.set(wal_metrics.sync_latency());
+
+        let stall_metrics = self.engine.write_controller().metrics();
+        let write_stall = &self.write_stall;
+        advance(&write_stall.delayed_writes, stall_metrics.delayed_writes());
+        advance(&write_stall.stopped_writes, stall_metrics.stopped_writes());
+        advance_seconds(
+            &write_stall.stall_seconds,
+            stall_metrics.stall_time().as_secs_f64(),
+        );
+    }
+
+    fn collectors(&self) -> Vec<&dyn Collector> {
+        let storage = &self.storage;
+        let wal = &self.wal;
+        let write_stall = &self.write_stall;
+        vec![
+            &storage.estimated_keys,
+            &storage.level_files,
+            &storage.level_bytes,
+            &storage.pending_compaction_bytes,
+            &storage.memtable_bytes,
+            &storage.immutable_memtables,
+            &storage.block_cache_hits,
+            &storage.block_cache_misses,
+            &storage.flushes,
+            &storage.flush_bytes_written,
+            &storage.compactions,
+            &storage.compaction_bytes_read,
+            &storage.compaction_bytes_written,
+            &storage.read_latency,
+            &storage.write_latency,
+            &storage.flush_latency,
+            &storage.compaction_latency,
+            &wal.writes,
+            &wal.write_failures,
+            &wal.bytes_written,
+            &wal.syncs,
+            &wal.sync_seconds,
+            &wal.current_file_bytes,
+            &wal.write_latency,
+            &wal.sync_latency,
+            &write_stall.delayed_writes,
+            &write_stall.stopped_writes,
+            &write_stall.stall_seconds,
+        ]
+    }
+}
+
+impl Collector for StorageCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.update();
+        self.collectors()
+            .into_iter()
+            .flat_map(|collector| collector.collect())
+            .collect()
+    }
+}
+
+/// Registers a [`StorageCollector`] for `engine` with `registry`
+///
+/// # Errors
+///
+/// Returns an error if the registry already holds metrics of the same
+/// names, such as those of another engine.
+pub fn register(registry: &Registry, engine: Arc<StorageEngine>) -> prometheus::Result<()> {
+    registry.register(Box::new(StorageCollector::new(engine)?))
+}
+
+/// Gathers `registry` into the Prometheus text exposition format
+///
+/// # Errors
+///
+/// Returns an error if a gathered metric can't be encoded.
+pub fn encode(registry: &Registry) -> prometheus::Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&registry.gather(), &mut buffer)?;
+    String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+}
+
+fn opts(subsystem: &str, name: &str, help: &str) -> Opts {
+    Opts::new(name, help)
+        .namespace(NAMESPACE)
+        .subsystem(subsystem)
+}
+
+/// Moves a counter forward to a value read from the engine
+fn advance(counter: &IntCounter, value: u64) {
+    let current = counter.get();
+    if value > current {
+        counter.inc_by(value - current);
+    }
+}
+
+fn advance_seconds(counter: &Counter, seconds: f64) {
+    let current = counter.get();
+    if seconds > current {
+        counter.inc_by(seconds - current);
+    }
+}
+
+/// A latency distribution read from the engine, exported as a summary
+struct LatencySummary {
+    desc: Desc,
+    snapshot: Mutex<HistogramSnapshot>,
+}
+
+impl LatencySummary {
+    fn new(subsystem: &str, name: &str, help: &str) -> prometheus::Result<Self> {
+        Ok(Self {
+            desc: Desc::new(
+                format!("{NAMESPACE}_{subsystem}_{name}"),
+                help.to_string(),
+                Vec::new(),
+                HashMap::new(),
+            )?,
+            snapshot: Mutex::new(HistogramSnapshot::default()),
+        })
+    }
+
+    fn set(&self, snapshot: HistogramSnapshot) {
+        *self.snapshot.lock().unwrap_or_else(|e| e.into_inner()) = snapshot;
+    }
+}
+
+impl Collector for LatencySummary {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let snapshot = *self.snapshot.lock().unwrap_or_else(|e| e.into_inner());
+        let quantiles = [
+            (0.5, snapshot.p50),
+            (0.95, snapshot.p95),
+            (0.99, snapshot.p99),
+            (0.999, snapshot.p999),
+        ]
+        .into_iter()
+        .map(|(quantile, value)| {
+            let mut q = Quantile::default();
+            q.set_quantile(quantile);
+            q.set_value(value.as_secs_f64());
+            q
+        })
+        .collect();
+
+        let mut summary = proto::Summary::default();
+        summary.set_sample_count(snapshot.count);
+        summary.set_sample_sum(snapshot.sum.as_secs_f64());
+        summary.set_quantile(quantiles);
+        let mut metric = proto::Metric::default();
+        metric.set_summary(summary);
+
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(MetricType::SUMMARY);
+        family.set_metric(vec![metric]);
+        vec![family]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_storage::Options;
+    use tempfile::TempDir;
+
+    fn engine(dir: &TempDir) -> Arc<StorageEngine> {
+        Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap())
+    }
+
+    #[test]
+    fn test_gather_reflects_engine_state() {
+        let dir = TempDir::new().unwrap();
+        let engine = engine(&dir);
+        let registry = Registry::new();
+        register(&registry, Arc::clone(&engine)).unwrap();
+
+        for i in 0..10u8 {
+            engine.put(vec![i], vec![b'v'; 16]).unwrap();
+        }
+        let text = encode(&registry).unwrap();
+        assert!(text.contains("ferrisdb_storage_estimated_keys 10"));
+        assert!(text.contains("ferrisdb_wal_writes_total 10"));
+        assert!(text.contains("ferrisdb_storage_flushes_total 0"));
+        assert!(text.contains("ferrisdb_storage_level_files{level=\"0\"} 0"));
+
+        engine.flush().unwrap();
+        assert_eq!(engine.get(&[3]).unwrap(), Some(vec![b'v'; 16]));
+        let text = encode(&registry).unwrap();
+        assert!(text.contains("ferrisdb_storage_flushes_total 1"));
+        assert!(text.contains("ferrisdb_storage_level_files{level=\"0\"} 1"));
+        assert!(text.contains("ferrisdb_storage_block_cache_misses_total 1"));
+        assert!(text.contains("ferrisdb_storage_memtable_bytes{state=\"active\"} 0"));
+        // Counters keep their value across gathers
+        let text = encode(&registry).unwrap();
+        assert!(text.contains("ferrisdb_storage_flushes_total 1"));
+    }
+
+    #[test]
+    fn test_latencies_are_exported_as_summaries() {
+        let dir = TempDir::new().unwrap();
+        let engine = engine(&dir);
+        let registry = Registry::new();
+        register(&registry, Arc::clone(&engine)).unwrap();
+
+        let text = encode(&registry).unwrap();
+        assert!(text.contains("# TYPE ferrisdb_storage_read_latency_seconds summary"));
+        assert!(text.contains("ferrisdb_storage_read_latency_seconds_count 0"));
+
+        for i in 0..10u8 {
+            engine.put(vec![i], vec![b'v'; 16]).unwrap();
+            engine.get(&[i]).unwrap();
+        }
+        engine.flush().unwrap();
+        let text = encode(&registry).unwrap();
+        assert!(text.contains("ferrisdb_storage_read_latency_seconds_count 10"));
+        assert!(text.contains("ferrisdb_storage_write_latency_seconds_count 10"));
+        assert!(text.contains("ferrisdb_storage_flush_latency_seconds_count 1"));
+        assert!(text.contains("ferrisdb_wal_write_latency_seconds_count 10"));
+        assert!(text.contains("ferrisdb_storage_read_latency_seconds{quantile=\"0.999\"}"));
+        assert!(text.contains("ferrisdb_wal_sync_latency_seconds{quantile=\"0.5\"}"));
+    }
+
+    #[test]
+    fn test_second_engine_in_one_registry_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::new();
+        register(&registry, engine(&dir)).unwrap();
+
+        let other = TempDir::new().unwrap();
+        assert!(register(&registry, engine(&other)).is_err());
+    }
+}
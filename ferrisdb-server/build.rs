@@ -0,0 +1,11 @@
+//! Generates the gRPC service from the protobuf schema
+//!
+//! Uses the vendored `protoc` so building doesn't require one installed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::compile_protos("proto/ferrisdb.proto")?;
+    Ok(())
+}
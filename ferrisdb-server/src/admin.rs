@@ -0,0 +1,206 @@
+//! The `Admin` gRPC service: maintenance operations on the engine
+//!
+//! Flushes, compactions and checkpoints run for as long as the disk work
+//! takes, so like key-value calls they run on the blocking thread pool.
+//! Checkpoints are only written inside the configured checkpoint
+//! directory; clients choose a name, never a path.
+
+use crate::proto::admin_server::Admin;
+use crate::proto::{
+    CompactRangeRequest, CompactRangeResponse, CreateCheckpointRequest, CreateCheckpointResponse,
+    FlushRequest, FlushResponse, GetMetricsRequest, GetMetricsResponse, GetPropertyRequest,
+    GetPropertyResponse, GetSlowLogRequest, GetSlowLogResponse, GetStatisticsRequest,
+    GetStatisticsResponse, SlowOperation,
+};
+use crate::service::{resolve_column_family, run_blocking};
+use ferrisdb_storage::storage_engine::SlowOperation as EngineSlowOperation;
+use ferrisdb_storage::{Statistics, StorageEngine};
+use prometheus::Registry;
+
+use tonic::{Request, Response, Status};
+
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Serves maintenance operations of one storage engine
+#[derive(Clone)]
+pub struct AdminService {
+    engine: Arc<StorageEngine>,
+    checkpoint_dir: PathBuf,
+    registry: Registry,
+}
+
+impl AdminService {
+    /// Creates a service writing checkpoints into `checkpoint_dir`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine's metrics can't be registered.
+    pub fn new(
+        engine: Arc<StorageEngine>,
+        checkpoint_dir: impl Into<PathBuf>,
+    ) -> prometheus::Result<Self> {
+        let registry = Registry::new();
+        ferrisdb_metrics::register(&registry, Arc::clone(&engine))?;
+        Ok(Self {
+            engine,
+            checkpoint_dir: checkpoint_dir.into(),
+            registry,
+        })
+    }
+
+    /// Returns the registry `GetMetrics` reports, so the server can add
+    /// metrics of its own
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Returns where a checkpoint called `name` is written
+    // Handlers return `Status` by value, so this does too
+    #[allow(clippy::result_large_err)]
+    fn checkpoint_path(&self, name: &str) -> Result<PathBuf, Status> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(self.checkpoint_dir.join(name)),
+            _ => Err(Status::invalid_argument(format!(
+                "Checkpoint name {:?} must be a single path component",
+                name
+            ))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn flush(&self, _: Request<FlushRequest>) -> Result<Response<FlushResponse>, Status> {
+        run_blocking(&self.engine, |engine| engine.flush()).await?;
+        Ok(Response::new(FlushResponse {}))
+    }
+
+    async fn compact_range(
+        &self,
+        request: Request<CompactRangeRequest>,
+    ) -> Result<Response<CompactRangeResponse>, Status> {
+        let request = request.into_inner();
+        let cf = resolve_column_family(&self.engine, &request.column_family)?;
+        let end = if request.end.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(request.end)
+        };
+        run_blocking(&self.engine, move |engine| {
+            engine.compact_range_cf(&cf, (Bound::Included(request.start), end))
+        })
+        .await?;
+        Ok(Response::new(CompactRangeResponse {}))
+    }
+
+    async fn create_checkpoint(
+        &self,
+        request: Request<CreateCheckpointRequest>,
+    ) -> Result<Response<CreateCheckpointResponse>, Status> {
+        let path = self.checkpoint_path(&request.into_inner().name)?;
+        let dir = self.checkpoint_dir.clone();
+        let target = path.clone();
+        run_blocking(&self.engine, move |engine| {
+            std::fs::create_dir_all(&dir)?;
+            engine.create_checkpoint(&target)
+        })
+        .await?;
+        log::info!("Created checkpoint {}", path.display());
+        Ok(Response::new(CreateCheckpointResponse {
+            path: path.display().to_string(),
+        }))
+    }
+
+    async fn get_property(
+        &self,
+        request: Request<GetPropertyRequest>,
+    ) -> Result<Response<GetPropertyResponse>, Status> {
+        let request = request.into_inner();
+        let cf = resolve_column_family(&self.engine, &request.column_family)?;
+        let value = run_blocking(&self.engine, move |engine| {
+            engine.property_cf(&cf, &request.name)
+        })
+        .await?;
+        Ok(Response::new(GetPropertyResponse { value }))
+    }
+
+    async fn get_statistics(
+        &self,
+        request: Request<GetStatisticsRequest>,
+    ) -> Result<Response<GetStatisticsResponse>, Status> {
+        let name = request.into_inner().column_family;
+        let statistics = if name.is_empty() {
+            run_blocking(&self.engine, |engine| Ok(engine.statistics())).await?
+        } else {
+            let cf = resolve_column_family(&self.engine, &name)?;
+            run_blocking(&self.engine, move |engine| engine.statistics_cf(&cf)).await?
+        };
+        Ok(Response::new(statistics_response(statistics)))
+    }
+
+    async fn get_metrics(
+        &self,
+        _: Request<GetMetricsRequest>,
+    ) -> Result<Response<GetMetricsResponse>, Status> {
+        let registry = self.registry.clone();
+        let text = tokio::task::spawn_blocking(move || ferrisdb_metrics::encode(&registry))
+            .await
+            .map_err(|e| Status::internal(format!("Request task failed: {}", e)))?
+            .map_err(|e| Status::internal(format!("Failed to encode metrics: {}", e)))?;
+        Ok(Response::new(GetMetricsResponse { text }))
+    }
+
+    async fn get_slow_log(
+        &self,
+        request: Request<GetSlowLogRequest>,
+    ) -> Result<Response<GetSlowLogResponse>, Status> {
+        let request = request.into_inner();
+        let mut log = self.engine.slow_log();
+        if request.reset {
+            self.engine.clear_slow_log();
+        }
+        if request.limit > 0 {
+            log.truncate(request.limit as usize);
+        }
+        Ok(Response::new(GetSlowLogResponse {
+            operations: log.into_iter().map(slow_operation).collect(),
+        }))
+    }
+}
+
+fn slow_operation(operation: EngineSlowOperation) -> SlowOperation {
+    SlowOperation {
+        kind: operation.kind.to_string(),
+        column_family: operation.column_family.unwrap_or_default(),
+        key: operation.key,
+        key_len: operation.key_len as u64,
+        started_at_us: operation.started_at.as_micros() as u64,
+        duration_us: operation.duration.as_micros() as u64,
+        wal_sync_us: operation.wal_sync_time.as_micros() as u64,
+        block_reads: operation.block_reads,
+        block_cache_hits: operation.block_cache_hits,
+    }
+}
+
+fn statistics_response(statistics: Statistics) -> GetStatisticsResponse {
+    GetStatisticsResponse {
+        estimated_num_keys: statistics.estimated_num_keys,
+        level_files: statistics.level_files.iter().map(|&n| n as u64).collect(),
+        level_bytes: statistics.level_bytes,
+        pending_compaction_bytes: statistics.pending_compaction_bytes,
+        active_memtable_bytes: statistics.active_memtable_bytes as u64,
+        immutable_memtable_bytes: statistics.immutable_memtable_bytes as u64,
+        immutable_memtables: statistics.immutable_memtables as u64,
+        block_cache_hits: statistics.block_cache_hits,
+        block_cache_misses: statistics.block_cache_misses,
+        flushes: statistics.flushes,
+        flush_bytes_written: statistics.flush_bytes_written,
+        compactions: statistics.compactions,
+        compaction_bytes_read: statistics.compaction_bytes_read,
+        compaction_bytes_written: statistics.compaction_bytes_written,
+        corruption_reports: statistics.corruption_reports,
+    }
+}
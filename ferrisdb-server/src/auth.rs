@@ -0,0 +1,152 @@
+//! Bearer-token authorization of gRPC calls
+//!
+//! Each protected service is wrapped in a [`TokenAuth`] interceptor for
+//! its scope (the HTTP gateway checks its requests with one too), which
+//! admits a call only if it carries
+//!
+//! ```text
+//! authorization: Bearer <token>
+//! ```
+//!
+//! with one of the scope's tokens. A scope without tokens rejects every
+//! call, so forgetting to configure one leaves the service closed rather
+//! than open; serving without authorization takes an explicit
+//! [`TokenAuth::open`].
+//!
+//! | Scope         | Service       | Tokens from                |
+//! |---------------|---------------|----------------------------|
+//! | `api`         | `KeyValue`    | `api_tokens`, or generated |
+//! | `api`         | REST gateway  | `api_tokens`, or generated |
+//! | `admin`       | `Admin`       | `admin_token`              |
+//! | `replication` | `Replication` | `replication_token`        |
+//! | `replication` | `Raft`        | `replication_token`        |
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use std::sync::Arc;
+
+/// Checks calls against the tokens of one authorization scope
+#[derive(Clone)]
+pub struct TokenAuth {
+    scope: &'static str,
+    tokens: Arc<[String]>,
+    open: bool,
+}
+
+impl TokenAuth {
+    /// Creates an interceptor admitting calls that present any of `tokens`
+    pub fn new<I, T>(scope: &'static str, tokens: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        Self {
+            scope,
+            tokens: tokens.into_iter().map(Into::into).collect(),
+            open: false,
+        }
+    }
+
+    /// Creates an interceptor admitting every call
+    pub fn open(scope: &'static str) -> Self {
+        Self {
+            scope,
+            tokens: Arc::from([]),
+            open: true,
+        }
+    }
+
+    /// Checks the value of a call's `authorization` header
+    // Interceptors return `Status` by value, so this does too
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn check(&self, header: Option<&str>) -> Result<(), Status> {
+        if self.open {
+            return Ok(());
+        }
+        if self.tokens.is_empty() {
+            return Err(Status::permission_denied(format!(
+                "The {} scope has no token configured",
+                self.scope
+            )));
+        }
+        let presented = header
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| {
+                Status::unauthenticated(format!(
+                    "Missing bearer token for the {} scope",
+                    self.scope
+                ))
+            })?;
+        // Every token is compared, so timing doesn't tell which one matched
+        let matched = self.tokens.iter().fold(false, |matched, token| {
+            constant_time_eq(presented.as_bytes(), token.as_bytes()) | matched
+        });
+        if !matched {
+            return Err(Status::permission_denied(format!(
+                "Invalid token for the {} scope",
+                self.scope
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Interceptor for TokenAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let header = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        self.check(header)?;
+        Ok(request)
+    }
+}
+
+/// Compares without exiting early, so timing doesn't leak the token
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn call(auth: &mut TokenAuth, header: Option<&str>) -> Result<(), Code> {
+        let mut request = Request::new(());
+        if let Some(header) = header {
+            request
+                .metadata_mut()
+                .insert("authorization", header.parse().unwrap());
+        }
+        auth.call(request)
+            .map(|_| ())
+            .map_err(|status| status.code())
+    }
+
+    #[test]
+    fn test_only_the_scope_token_is_admitted() {
+        let mut auth = TokenAuth::new("admin", ["s3cret", "rotated"]);
+        assert_eq!(call(&mut auth, Some("Bearer s3cret")), Ok(()));
+        assert_eq!(call(&mut auth, Some("Bearer rotated")), Ok(()));
+        assert_eq!(
+            call(&mut auth, Some("Bearer s3cre")),
+            Err(Code::PermissionDenied)
+        );
+        assert_eq!(call(&mut auth, Some("s3cret")), Err(Code::Unauthenticated));
+        assert_eq!(call(&mut auth, None), Err(Code::Unauthenticated));
+    }
+
+    #[test]
+    fn test_scope_without_token_rejects_everything() {
+        let mut auth = TokenAuth::new("admin", None::<String>);
+        assert_eq!(
+            call(&mut auth, Some("Bearer anything")),
+            Err(Code::PermissionDenied)
+        );
+
+        let mut auth = TokenAuth::open("api");
+        assert_eq!(call(&mut auth, None), Ok(()));
+    }
+}
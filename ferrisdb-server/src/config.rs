@@ -0,0 +1,683 @@
+//! Server configuration loaded from TOML
+//!
+//! Every setting has a default, so an empty file is a valid configuration:
+//!
+//! ```toml
+//! listen_addr = "127.0.0.1:7070"
+//! data_dir = "./data"
+//! max_scan_limit = 1000
+//! shutdown_timeout_secs = 30
+//! api_tokens = ["change-me"]    # one is generated and logged if empty
+//! allow_unauthenticated = false # serve the key-value API without tokens
+//! admin_token = "change-me-too" # Admin service disabled if unset
+//! checkpoint_dir = "./checkpoints"
+//! replication_token = "secret"  # Replication service disabled if unset
+//! allow_plaintext = false       # listen without TLS beyond loopback
+//!
+//! [tls]                         # plaintext if omitted
+//! cert_path = "server.pem"
+//! key_path = "server.key"
+//! client_ca_path = "ca.pem"     # optional, requires client certificates
+//!
+//! [http]                        # HTTP/JSON gateway, off if omitted
+//! listen_addr = "127.0.0.1:8080"
+//!
+//! [resp]                        # Redis protocol frontend, off if omitted
+//! listen_addr = "127.0.0.1:6379"
+//!
+//! [quotas]                      # unlimited if omitted, any may be left out
+//! requests_per_sec = 50000      # across all clients
+//! bytes_per_sec = 268435456     # request and response payloads
+//! connection_requests_per_sec = 5000
+//! connection_bytes_per_sec = 33554432
+//!
+//! [replica]                     # serves as a read-only replica if set
+//! primary = "https://primary:7070"
+//! token = "secret"              # the primary's replication_token
+//! tls_ca_path = "ca.pem"        # optional, verifies the primary over TLS
+//! replica_id = "replica-1"      # optional, the listen address if unset
+//!
+//! [raft]                        # replicates writes by consensus if set
+//! node_id = 1                   # unique in the cluster, not 0
+//! tls_ca_path = "ca.pem"        # optional, verifies the peers over TLS
+//! election_timeout_ms = 1000    # silence before an election starts
+//! heartbeat_interval_ms = 100
+//! max_log_entries = 10000       # applied entries kept past a snapshot
+//!
+//! [[raft.peers]]                # every other server of the cluster
+//! id = 2
+//! addr = "https://node-2:7070"
+//!
+//! [[raft.peers]]
+//! id = 3
+//! addr = "https://node-3:7070"
+//!
+//! [storage]
+//! sync_mode = "Normal"          # None, Normal or Full
+//! memtable_size = "64MB"        # bytes, or a size with a unit
+//! wal_archive_dir = "./archive" # keeps flushed WAL for lagging replicas
+//! slow_log_threshold_us = 10000 # logs operations taking 10ms or more
+//! slow_log_redact_keys = true   # keeps keys out of the slow log
+//! ```
+//!
+//! A server can also be configured from the file format shared by all
+//! components, see [`ServerConfig::from_core`].
+
+use crate::{raft, resp, Error, Result};
+use ferrisdb_core::{ByteSize, Config, SyncMode};
+use ferrisdb_storage::{ColumnFamilyOptions, Options, StorageConfig};
+use serde::Deserialize;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Settings of a FerrisDB server
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the gRPC server listens on
+    pub listen_addr: SocketAddr,
+    /// Directory holding the database
+    pub data_dir: PathBuf,
+    /// Most key-value pairs a single scan returns
+    pub max_scan_limit: u32,
+    /// How long shutdown waits for in-flight requests before giving up
+    pub shutdown_timeout_secs: u64,
+    /// Bearer tokens authorizing calls to the KeyValue service
+    ///
+    /// If empty, a random token is generated at startup and logged,
+    /// unless `allow_unauthenticated` is set.
+    pub api_tokens: Vec<String>,
+    /// Serves the KeyValue service to anyone when no API token is set
+    pub allow_unauthenticated: bool,
+    /// Bearer token authorizing calls to the Admin service, which rejects
+    /// every call if unset
+    pub admin_token: Option<String>,
+    /// Directory the Admin service writes checkpoints into
+    pub checkpoint_dir: PathBuf,
+    /// Bearer token replicas stream the WAL with; the Replication service
+    /// rejects every call if unset
+    pub replication_token: Option<String>,
+    /// The primary this server replicates; unset for a primary
+    pub replica: Option<ReplicaSettings>,
+    /// The Raft cluster this server's writes are replicated across; unset
+    /// for a server on its own
+    pub raft: Option<RaftSettings>,
+    /// Where the HTTP gateway listens; not served if unset
+    pub http: Option<HttpSettings>,
+    /// Where the Redis protocol frontend listens; not served if unset
+    pub resp: Option<RespSettings>,
+    /// Request and byte quotas of client traffic; unlimited if unset
+    pub quotas: Option<QuotaSettings>,
+    /// Certificates to serve TLS with; plaintext if unset
+    pub tls: Option<TlsSettings>,
+    /// Allows listening without TLS on addresses other than loopback
+    pub allow_plaintext: bool,
+    /// Settings passed on to the storage engine
+    pub storage: StorageSettings,
+    /// Engine settings of a shared configuration file, which `storage`
+    /// and `data_dir` take precedence over
+    #[serde(skip)]
+    pub engine: Config,
+}
+
+/// Certificates the server terminates TLS with
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    /// PEM file with the server's certificate chain
+    pub cert_path: PathBuf,
+    /// PEM file with the server's private key
+    pub key_path: PathBuf,
+    /// PEM file with the CAs client certificates must be signed by; client
+    /// certificates aren't requested if unset
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// Where a replica streams its writes from
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicaSettings {
+    /// Address of the primary, e.g. `https://primary:7070`
+    pub primary: String,
+    /// The primary's replication token
+    #[serde(default)]
+    pub token: Option<String>,
+    /// PEM file with the CA the primary's certificate is checked against;
+    /// connects in plaintext if unset
+    #[serde(default)]
+    pub tls_ca_path: Option<PathBuf>,
+    /// Name the primary reports the replica under
+    #[serde(default)]
+    pub replica_id: Option<String>,
+}
+
+/// Membership and timing of a Raft cluster, see [`crate::raft`]
+///
+/// The servers of a cluster authenticate to each other with the
+/// `replication_token`, which they must share.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RaftSettings {
+    /// Id of this server in the cluster, unique and not 0
+    pub node_id: u64,
+    /// The other servers of the cluster
+    pub peers: Vec<RaftPeer>,
+    /// PEM file with the CA the peers' certificates are checked against;
+    /// connects in plaintext if unset
+    #[serde(default)]
+    pub tls_ca_path: Option<PathBuf>,
+    /// Milliseconds without hearing from a leader before a server starts
+    /// an election, 1000 if unset
+    #[serde(default)]
+    pub election_timeout_ms: Option<u64>,
+    /// Milliseconds between a leader's heartbeats, 100 if unset
+    #[serde(default)]
+    pub heartbeat_interval_ms: Option<u64>,
+    /// Applied entries the log keeps when it is compacted, which it is
+    /// once twice as many piled up; 10000 if unset
+    #[serde(default)]
+    pub max_log_entries: Option<u64>,
+}
+
+/// Another server of a Raft cluster
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RaftPeer {
+    /// The server's `node_id`
+    pub id: u64,
+    /// Address of its gRPC server, e.g. `https://node-2:7070`
+    pub addr: String,
+}
+
+impl RaftSettings {
+    /// Returns how long a server waits for a leader before an election
+    pub fn election_timeout(&self) -> Duration {
+        Duration::from_millis(self.election_timeout_ms.unwrap_or(1000))
+    }
+
+    /// Returns how often a leader sends heartbeats
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval_ms.unwrap_or(100))
+    }
+
+    /// Returns how many applied entries the log keeps when compacted
+    pub fn max_log_entries(&self) -> u64 {
+        self.max_log_entries.unwrap_or(10000)
+    }
+}
+
+/// Settings of the HTTP/JSON gateway
+///
+/// Requests authenticate with the API tokens, as `KeyValue` calls do.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpSettings {
+    /// Address the gateway listens on, always in plaintext
+    pub listen_addr: SocketAddr,
+}
+
+/// Settings of the Redis protocol frontend
+///
+/// Clients authenticate with `AUTH` and one of the API tokens.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RespSettings {
+    /// Address the frontend listens on, always in plaintext
+    pub listen_addr: SocketAddr,
+}
+
+/// Rates client requests are held to, see [`crate::quota`]
+///
+/// Unset quotas are unlimited. Requests over a quota fail with
+/// `RESOURCE_EXHAUSTED`, or 429 from the HTTP gateway.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaSettings {
+    /// Requests per second admitted across all connections
+    pub requests_per_sec: Option<u64>,
+    /// Request and response bytes per second across all connections
+    pub bytes_per_sec: Option<u64>,
+    /// Requests per second admitted from one connection
+    pub connection_requests_per_sec: Option<u64>,
+    /// Request and response bytes per second of one connection
+    pub connection_bytes_per_sec: Option<u64>,
+}
+
+/// Storage engine settings a server can override
+///
+/// Unset settings keep the engine's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    /// Directory holding WAL segments, `data_dir/wal` if unset
+    pub wal_dir: Option<PathBuf>,
+    /// How durably each write is logged before it is acknowledged
+    pub sync_mode: Option<SyncMode>,
+    /// Size at which the active MemTable is flushed
+    pub memtable_size: Option<ByteSize>,
+    /// Directory flushed WAL segments are moved to instead of being
+    /// deleted, so replicas that fall behind can still catch up
+    pub wal_archive_dir: Option<PathBuf>,
+    /// Microseconds from which operations are kept in the slow log,
+    /// which `GetSlowLog` returns; 0 turns the log off
+    pub slow_log_threshold_us: Option<u64>,
+    /// Whether the slow log leaves out keys, keeping only their lengths
+    pub slow_log_redact_keys: Option<bool>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: SocketAddr::from(([127, 0, 0, 1], 7070)),
+            data_dir: PathBuf::from("./data"),
+            max_scan_limit: 1000,
+            shutdown_timeout_secs: 30,
+            api_tokens: Vec::new(),
+            allow_unauthenticated: false,
+            admin_token: None,
+            checkpoint_dir: PathBuf::from("./checkpoints"),
+            replication_token: None,
+            replica: None,
+            raft: None,
+            http: None,
+            resp: None,
+            quotas: None,
+            tls: None,
+            allow_plaintext: false,
+            storage: StorageSettings::default(),
+            engine: Config::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parses a configuration from TOML text
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if the text isn't valid TOML, has unknown
+    /// settings, or sets an invalid value.
+    pub fn from_toml_str(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text).map_err(|e| Error::Config(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a configuration from a TOML file
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if the file can't be read, otherwise errors for
+    /// the same reasons as [`from_toml_str`](Self::from_toml_str).
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml_str(&text).map_err(|e| match e {
+            Error::Config(message) => Error::Config(format!("{}: {}", path.display(), message)),
+            e => e,
+        })
+    }
+
+    /// Builds a configuration from the file format shared by all
+    /// components, see [`ferrisdb_core::config`]
+    ///
+    /// Its `[server]` section and `data_dir` set the matching settings
+    /// here, and its engine sections are used when opening the engine.
+    /// Settings only a server file has, such as tokens, keep their
+    /// defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if the resulting configuration is invalid.
+    pub fn from_core(config: &Config) -> Result<Self> {
+        let defaults = Self::default();
+        let server = &config.server;
+        let config = Self {
+            listen_addr: server.listen_addr.unwrap_or(defaults.listen_addr),
+            data_dir: config.data_dir.clone().unwrap_or(defaults.data_dir),
+            max_scan_limit: server.max_scan_limit.unwrap_or(defaults.max_scan_limit),
+            shutdown_timeout_secs: server
+                .shutdown_timeout_secs
+                .unwrap_or(defaults.shutdown_timeout_secs),
+            engine: config.clone(),
+            ..defaults
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Returns how long shutdown waits for in-flight requests
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+
+    /// Returns the options the storage engine is opened with
+    pub fn storage_options(&self) -> Options {
+        let mut engine = self.engine.clone();
+        engine.data_dir = Some(self.data_dir.clone());
+        let mut options = Options::from_config(StorageConfig::from(&engine));
+        if let Some(wal_dir) = &self.storage.wal_dir {
+            options = options.with_wal_dir(wal_dir);
+        }
+        if let Some(sync_mode) = self.storage.sync_mode {
+            options = options.with_sync_mode(sync_mode);
+        }
+        if let Some(memtable_size) = self.storage.memtable_size {
+            options = options.with_memtable_size(memtable_size.as_usize());
+        }
+        if let Some(archive) = &self.storage.wal_archive_dir {
+            options = options.with_wal_archive_dir(archive);
+        }
+        if let Some(threshold) = self.storage.slow_log_threshold_us {
+            options = options.with_slow_log_threshold(Duration::from_micros(threshold));
+        }
+        if let Some(redact) = self.storage.slow_log_redact_keys {
+            options = options.with_slow_log_redact_keys(redact);
+        }
+        if self.resp.is_some() {
+            options =
+                options.with_column_family(resp::COLUMN_FAMILY, resp::column_family_options());
+        }
+        if self.raft.is_some() {
+            options =
+                options.with_column_family(raft::COLUMN_FAMILY, ColumnFamilyOptions::default());
+        }
+        options.with_replica(self.replica.is_some())
+    }
+
+    /// Checks settings that can't be expressed in their types
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` naming the first invalid setting.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_scan_limit == 0 {
+            return Err(Error::Config(
+                "max_scan_limit must be at least 1".to_string(),
+            ));
+        }
+        if self.admin_token.as_deref() == Some("") {
+            return Err(Error::Config("admin_token must not be empty".to_string()));
+        }
+        if self.replication_token.as_deref() == Some("") {
+            return Err(Error::Config(
+                "replication_token must not be empty".to_string(),
+            ));
+        }
+        if self.api_tokens.iter().any(String::is_empty) {
+            return Err(Error::Config("api_tokens must not be empty".to_string()));
+        }
+        if self.tls.is_none() && !self.listen_addr.ip().is_loopback() && !self.allow_plaintext {
+            return Err(Error::Config(format!(
+                "listening on {} without TLS needs allow_plaintext = true",
+                self.listen_addr
+            )));
+        }
+        let plaintext = [
+            (
+                "the HTTP gateway",
+                self.http.as_ref().map(|http| http.listen_addr),
+            ),
+            (
+                "the Redis protocol frontend",
+                self.resp.as_ref().map(|resp| resp.listen_addr),
+            ),
+        ];
+        for (frontend, addr) in plaintext {
+            if let Some(addr) = addr.filter(|addr| !addr.ip().is_loopback()) {
+                if !self.allow_plaintext {
+                    return Err(Error::Config(format!(
+                        "{} has no TLS, listening on {} needs allow_plaintext = true",
+                        frontend, addr
+                    )));
+                }
+            }
+        }
+        if let Some(quotas) = &self.quotas {
+            let rates = [
+                ("requests_per_sec", quotas.requests_per_sec),
+                ("bytes_per_sec", quotas.bytes_per_sec),
+                (
+                    "connection_requests_per_sec",
+                    quotas.connection_requests_per_sec,
+                ),
+                ("connection_bytes_per_sec", quotas.connection_bytes_per_sec),
+            ];
+            if let Some((name, _)) = rates.iter().find(|(_, rate)| *rate == Some(0)) {
+                return Err(Error::Config(format!("quotas.{} must be at least 1", name)));
+            }
+        }
+        if self.storage.memtable_size == Some(ByteSize(0)) {
+            return Err(Error::Config(
+                "storage.memtable_size must be at least 1".to_string(),
+            ));
+        }
+        if let Some(raft) = &self.raft {
+            self.validate_raft(raft)?;
+        }
+        Ok(())
+    }
+
+    fn validate_raft(&self, raft: &RaftSettings) -> Result<()> {
+        if raft.node_id == 0 {
+            return Err(Error::Config("raft.node_id must not be 0".to_string()));
+        }
+        let mut ids = std::collections::BTreeSet::from([raft.node_id]);
+        if let Some(peer) = raft
+            .peers
+            .iter()
+            .find(|peer| !ids.insert(peer.id) || peer.id == 0)
+        {
+            return Err(Error::Config(format!(
+                "raft.peers has id {}, which is 0 or taken",
+                peer.id
+            )));
+        }
+        if self.replication_token.is_none() {
+            return Err(Error::Config(
+                "[raft] needs the replication_token the servers authenticate to each other with"
+                    .to_string(),
+            ));
+        }
+        // These write to the engine directly, around the Raft log
+        let direct = [
+            ("[replica]", self.replica.is_some()),
+            ("[http]", self.http.is_some()),
+            ("[resp]", self.resp.is_some()),
+        ];
+        if let Some((section, _)) = direct.iter().find(|(_, set)| *set) {
+            return Err(Error::Config(format!(
+                "{} can't be combined with [raft]",
+                section
+            )));
+        }
+        let tick = raft::TICK_INTERVAL;
+        if raft.heartbeat_interval() < tick
+            || raft.heartbeat_interval() * 2 > raft.election_timeout()
+        {
+            return Err(Error::Config(format!(
+                "raft.heartbeat_interval_ms must be at least {} and at most half of raft.election_timeout_ms",
+                tick.as_millis()
+            )));
+        }
+        if raft.max_log_entries() == 0 {
+            return Err(Error::Config(
+                "raft.max_log_entries must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_file_uses_defaults() {
+        assert_eq!(
+            ServerConfig::from_toml_str("").unwrap(),
+            ServerConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_settings_are_parsed_and_applied() {
+        let config = ServerConfig::from_toml_str(
+            r#"
+            listen_addr = "0.0.0.0:9000"
+            data_dir = "/var/lib/ferrisdb"
+            max_scan_limit = 50
+            api_tokens = ["a", "b"]
+            admin_token = "s3cret"
+
+            [tls]
+            cert_path = "server.pem"
+            key_path = "server.key"
+
+            [replica]
+            primary = "https://primary:7070"
+
+            [resp]
+            listen_addr = "127.0.0.1:6380"
+
+            [http]
+            listen_addr = "127.0.0.1:8081"
+
+            [quotas]
+            connection_requests_per_sec = 100
+
+            [storage]
+            sync_mode = "Full"
+            memtable_size = "1MB"
+            wal_archive_dir = "/var/lib/ferrisdb-archive"
+            slow_log_threshold_us = 5000
+            slow_log_redact_keys = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen_addr, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.max_scan_limit, 50);
+        assert_eq!(config.admin_token.as_deref(), Some("s3cret"));
+        assert_eq!(config.api_tokens, ["a", "b"]);
+        let tls = config.tls.as_ref().unwrap();
+        assert_eq!(tls.key_path, Path::new("server.key"));
+        assert_eq!(tls.client_ca_path, None);
+        assert_eq!(config.shutdown_timeout(), Duration::from_secs(30));
+
+        let options = config.storage_options();
+        let storage = options.config();
+        assert_eq!(storage.data_dir, Path::new("/var/lib/ferrisdb"));
+        assert_eq!(storage.wal_dir, Path::new("/var/lib/ferrisdb/wal"));
+        assert_eq!(storage.wal_sync_mode, SyncMode::Full);
+        assert_eq!(storage.memtable_size, 1048576);
+        assert_eq!(
+            storage.wal_archive_dir.as_deref(),
+            Some(Path::new("/var/lib/ferrisdb-archive"))
+        );
+        assert_eq!(storage.slow_log_threshold_us, 5000);
+        assert!(storage.slow_log_redact_keys);
+        let replica = config.replica.as_ref().unwrap();
+        assert_eq!(replica.primary, "https://primary:7070");
+        assert_eq!(replica.token, None);
+        assert_eq!(
+            config.resp.as_ref().unwrap().listen_addr,
+            "127.0.0.1:6380".parse().unwrap()
+        );
+        assert_eq!(
+            config.http.as_ref().unwrap().listen_addr,
+            "127.0.0.1:8081".parse().unwrap()
+        );
+        let quotas = config.quotas.as_ref().unwrap();
+        assert_eq!(quotas.connection_requests_per_sec, Some(100));
+        assert_eq!(quotas.bytes_per_sec, None);
+    }
+
+    #[test]
+    fn test_raft_settings_are_parsed() {
+        let config = ServerConfig::from_toml_str(
+            r#"
+            replication_token = "secret"
+
+            [raft]
+            node_id = 1
+            election_timeout_ms = 500
+            max_log_entries = 50
+
+            [[raft.peers]]
+            id = 2
+            addr = "http://node-2:7070"
+            "#,
+        )
+        .unwrap();
+        let raft = config.raft.as_ref().unwrap();
+        assert_eq!(raft.node_id, 1);
+        assert_eq!(raft.peers[0].addr, "http://node-2:7070");
+        assert_eq!(raft.election_timeout(), Duration::from_millis(500));
+        assert_eq!(raft.heartbeat_interval(), Duration::from_millis(100));
+        assert_eq!(raft.max_log_entries(), 50);
+    }
+
+    #[test]
+    fn test_shared_config_files_configure_server_and_engine() {
+        let core = Config::from_toml_str(
+            r#"
+            data_dir = "/srv/ferrisdb"
+
+            [memtable]
+            size = "16MB"
+
+            [compaction]
+            target_file_size_base = "8MB"
+
+            [server]
+            max_scan_limit = 10
+            "#,
+        )
+        .unwrap();
+        let config = ServerConfig::from_core(&core).unwrap();
+        assert_eq!(config.data_dir, Path::new("/srv/ferrisdb"));
+        assert_eq!(config.max_scan_limit, 10);
+        assert_eq!(config.listen_addr, ServerConfig::default().listen_addr);
+
+        let options = config.storage_options();
+        let storage = options.config();
+        assert_eq!(storage.wal_dir, Path::new("/srv/ferrisdb/wal"));
+        assert_eq!(storage.memtable_size, 16 << 20);
+        assert_eq!(storage.target_file_size_base, 8 << 20);
+    }
+
+    #[test]
+    fn test_invalid_settings_are_rejected() {
+        for text in [
+            "listen_addr = \"not an address\"",
+            "unknown = 1",
+            "max_scan_limit = 0",
+            "admin_token = \"\"",
+            "api_tokens = [\"\"]",
+            "replication_token = \"\"",
+            "[replica]\ntoken = \"t\"",
+            "listen_addr = \"0.0.0.0:7070\"",
+            "[resp]\nlisten_addr = \"0.0.0.0:6379\"",
+            "[quotas]\nbytes_per_sec = 0",
+            "[http]\nlisten_addr = \"0.0.0.0:8080\"",
+            "[tls]\ncert_path = \"server.pem\"",
+            "[storage]\nmemtable_size = 0",
+            "[storage]\nsync_mode = \"Sometimes\"",
+            "replication_token = \"t\"\n[raft]\nnode_id = 0\npeers = []",
+            "replication_token = \"t\"\n[raft]\nnode_id = 1\npeers = [{ id = 1, addr = \"http://a\" }]",
+            "[raft]\nnode_id = 1\npeers = []",
+            "replication_token = \"t\"\n[raft]\nnode_id = 1\npeers = []\n[resp]\nlisten_addr = \"127.0.0.1:6379\"",
+            "replication_token = \"t\"\n[raft]\nnode_id = 1\npeers = []\nheartbeat_interval_ms = 600",
+            "replication_token = \"t\"\n[raft]\nnode_id = 1\npeers = []\nmax_log_entries = 0",
+        ] {
+            assert!(
+                matches!(ServerConfig::from_toml_str(text), Err(Error::Config(_))),
+                "accepted {:?}",
+                text
+            );
+        }
+    }
+}
@@ -1 +1,1447 @@
-// FerrisDB server library
+//! FerrisDB server: the storage engine behind a gRPC API
+//!
+//! A [`Server`] opens a [`StorageEngine`] and serves the `KeyValue`,
+//! `Admin` and `Replication` services defined in `proto/ferrisdb.proto`
+//! over it:
+//!
+//! ```text
+//!   client ──gRPC/TLS──▶ tonic ─┬─▶ TokenAuth ──▶ KeyValueService ────┬─spawn_blocking─▶ StorageEngine
+//!                               ├─▶ TokenAuth ──▶ AdminService ───────┤
+//!   replica ◀─────WAL──────────────── TokenAuth ◀── ReplicationService ─┘
+//! ```
+//!
+//! No service is open by default. `KeyValue` calls must present one of
+//! the configured API tokens, and one is generated and logged at startup
+//! if none is configured; `Admin` and `Replication` calls must present the
+//! admin and replication tokens (see [`auth`]). With a `[tls]` section the server terminates TLS
+//! itself, optionally requiring client certificates, and it refuses to
+//! listen in plaintext beyond loopback unless told to.
+//!
+//! With a `[quotas]` section, client requests are held to request and
+//! byte rates, globally and per connection (see [`quota`]).
+//!
+//! With an `[http]` section the `KeyValue` API is also served as HTTP and
+//! JSON (see [`rest`]), authorized by the same API tokens.
+//!
+//! With a `[resp]` section the server also speaks a subset of the Redis
+//! protocol on a second address (see [`resp`]), for Redis clients and
+//! benchmarks; its keys live in a column family of their own.
+//!
+//! With a `[replica]` section the server is a read-only replica: it
+//! streams the WAL of its primary into its own engine (see
+//! [`replication`]) and rejects writes with `FAILED_PRECONDITION`. A
+//! replica starts from a checkpoint of the primary's data directory.
+//!
+//! With a `[raft]` section the server is one node of a Raft cluster (see
+//! [`raft`]): writes go through the elected leader and return once a
+//! majority of the servers has them, and the nodes talk to each other
+//! over the `Raft` service, authorized by the replication token.
+//!
+//! Shutdown is graceful: once the shutdown signal fires, the server stops
+//! accepting connections, lets in-flight requests finish for up to the
+//! configured timeout, and then closes the engine so every acknowledged
+//! write is in the WAL.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ferrisdb_server::{Server, ServerConfig};
+//!
+//! # async fn run() -> ferrisdb_server::Result<()> {
+//! let config = ServerConfig::from_file("ferrisdb.toml")?;
+//! let server = Server::open(config)?;
+//! server
+//!     .serve(async {
+//!         let _ = tokio::signal::ctrl_c().await;
+//!     })
+//!     .await
+//! # }
+//! ```
+
+mod admin;
+pub mod auth;
+pub mod config;
+pub mod quota;
+pub mod raft;
+pub mod replication;
+pub mod resp;
+pub mod rest;
+mod scan;
+mod service;
+
+/// Types generated from the protobuf schema
+pub mod proto {
+    tonic::include_proto!("ferrisdb.v1");
+}
+
+pub use admin::AdminService;
+pub use config::ServerConfig;
+pub use quota::Quotas;
+pub use raft::{RaftNode, RaftService};
+pub use replication::{ReplicationService, Replicator};
+pub use resp::RespServer;
+pub use rest::RestGateway;
+pub use service::KeyValueService;
+
+use auth::TokenAuth;
+use ferrisdb_storage::StorageEngine;
+use proto::admin_server::AdminServer;
+use proto::key_value_server::KeyValueServer;
+use proto::raft_server::RaftServer;
+use proto::replication_server::ReplicationServer;
+
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Errors starting or running a server
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The configuration is invalid
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// The storage engine failed
+    #[error(transparent)]
+    Storage(#[from] ferrisdb_core::Error),
+
+    /// Binding or accepting connections failed
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The gRPC transport failed
+    #[error("Transport error: {0}")]
+    Transport(#[from] tonic::transport::Error),
+
+    /// The engine's metrics couldn't be registered
+    #[error("Metrics error: {0}")]
+    Metrics(#[from] prometheus::Error),
+}
+
+/// A specialized Result type for server operations
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A storage engine served over gRPC
+pub struct Server {
+    config: ServerConfig,
+    engine: Arc<StorageEngine>,
+    api_tokens: Vec<String>,
+    tls: Option<ServerTlsConfig>,
+    replicator: Option<Replicator>,
+    raft: Option<Arc<RaftNode>>,
+    resp: Option<RespServer>,
+    quotas: Arc<Quotas>,
+}
+
+impl Server {
+    /// Opens the database in the configured data directory
+    ///
+    /// Generates an API token if none is configured and unauthenticated
+    /// access isn't allowed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if the configuration is invalid or a TLS
+    /// file can't be read, or `Error::Storage` if the engine can't be
+    /// opened.
+    pub fn open(config: ServerConfig) -> Result<Self> {
+        config.validate()?;
+        let tls = config.tls.as_ref().map(load_tls).transpose()?;
+        let mut api_tokens = config.api_tokens.clone();
+        if api_tokens.is_empty() && !config.allow_unauthenticated {
+            let token = generate_token();
+            log::warn!(
+                "No api_tokens configured, generated one for this run: {}",
+                token
+            );
+            api_tokens.push(token);
+        }
+        let engine = Arc::new(StorageEngine::open(config.storage_options())?);
+        let quotas = Arc::new(match &config.quotas {
+            Some(settings) => Quotas::new(settings),
+            None => Quotas::unlimited(),
+        });
+        let replicator = config
+            .replica
+            .as_ref()
+            .map(|settings| {
+                Replicator::new(
+                    Arc::clone(&engine),
+                    settings,
+                    config.listen_addr.to_string(),
+                )
+            })
+            .transpose()?;
+        let raft = match (&config.raft, &config.replication_token) {
+            (Some(settings), Some(token)) => {
+                Some(RaftNode::open(Arc::clone(&engine), settings, token)?)
+            }
+            _ => None,
+        };
+        let resp = config
+            .resp
+            .is_some()
+            .then(|| RespServer::new(Arc::clone(&engine), api_tokens.clone()))
+            .transpose()?
+            .map(|resp| resp.with_quotas(Arc::clone(&quotas)));
+        Ok(Self {
+            config,
+            engine,
+            api_tokens,
+            tls,
+            replicator,
+            raft,
+            resp,
+            quotas,
+        })
+    }
+
+    /// Returns the engine requests are served from
+    pub fn engine(&self) -> &Arc<StorageEngine> {
+        &self.engine
+    }
+
+    /// Returns the node of the Raft cluster, if the server is in one
+    pub fn raft(&self) -> Option<&Arc<RaftNode>> {
+        self.raft.as_ref()
+    }
+
+    /// Returns the tokens admitted by the KeyValue service, including a
+    /// generated one
+    ///
+    /// Empty if the service is served without authentication.
+    pub fn api_tokens(&self) -> &[String] {
+        &self.api_tokens
+    }
+
+    /// Serves on the configured address until `shutdown` completes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address can't be bound, the transport
+    /// fails, or the engine fails to close.
+    pub async fn serve(self, shutdown: impl Future<Output = ()> + Send) -> Result<()> {
+        let listener = TcpListener::bind(self.config.listen_addr).await?;
+        self.serve_with_listener(listener, shutdown).await
+    }
+
+    /// Serves on an already bound listener until `shutdown` completes
+    ///
+    /// Useful to listen on an ephemeral port, as tests do. The Redis
+    /// protocol frontend and the HTTP gateway, if configured, still bind
+    /// their own addresses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a frontend's address can't be bound, the
+    /// transport fails, or the engine fails to close.
+    pub async fn serve_with_listener(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()> + Send,
+    ) -> Result<()> {
+        log::info!(
+            "Serving {} on {}",
+            self.config.data_dir.display(),
+            listener.local_addr()?
+        );
+        let mut service =
+            KeyValueService::new(Arc::clone(&self.engine), self.config.max_scan_limit)
+                .with_quotas(Arc::clone(&self.quotas));
+        if let Some(raft) = &self.raft {
+            service = service.with_raft(Arc::clone(raft));
+        }
+        let admin = AdminService::new(Arc::clone(&self.engine), &self.config.checkpoint_dir)?;
+        self.quotas.register(admin.registry())?;
+        if self.config.admin_token.is_none() {
+            log::warn!("No admin_token configured, the Admin service rejects all calls");
+        }
+        let admin_auth = TokenAuth::new("admin", self.config.admin_token.clone());
+        let replication = ReplicationService::new(Arc::clone(&self.engine));
+        let replication_auth = TokenAuth::new("replication", self.config.replication_token.clone());
+        let api_auth = if self.api_tokens.is_empty() {
+            log::warn!("allow_unauthenticated is set, the KeyValue service is open to anyone");
+            TokenAuth::open("api")
+        } else {
+            TokenAuth::new("api", self.api_tokens.iter().cloned())
+        };
+
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(tls) = self.tls.clone() {
+            builder = builder.tls_config(tls)?;
+        } else if !listener.local_addr()?.ip().is_loopback() {
+            log::warn!("Serving without TLS, requests and tokens travel in plaintext");
+        }
+
+        // The frontends stop accepting along with the gRPC server
+        let (stopping, _) = tokio::sync::watch::channel(false);
+        let stopped = |stopping: &tokio::sync::watch::Sender<bool>| {
+            let mut stopping = stopping.subscribe();
+            async move {
+                let _ = stopping.wait_for(|stopping| *stopping).await;
+            }
+        };
+        let resp = match (self.resp, &self.config.resp) {
+            (Some(resp), Some(settings)) => {
+                let listener = TcpListener::bind(settings.listen_addr).await?;
+                let stopped = stopped(&stopping);
+                Some(tokio::spawn(async move {
+                    if let Err(e) = resp.serve(listener, stopped).await {
+                        log::error!("Redis protocol frontend failed: {}", e);
+                    }
+                }))
+            }
+            _ => None,
+        };
+        let http = match &self.config.http {
+            Some(settings) => {
+                let listener = TcpListener::bind(settings.listen_addr).await?;
+                let gateway = RestGateway::new(
+                    Arc::clone(&self.engine),
+                    self.config.max_scan_limit,
+                    api_auth.clone(),
+                )
+                .with_quotas(Arc::clone(&self.quotas));
+                let stopped = stopped(&stopping);
+                Some(tokio::spawn(async move {
+                    if let Err(e) = gateway.serve(listener, stopped).await {
+                        log::error!("HTTP gateway failed: {}", e);
+                    }
+                }))
+            }
+            None => None,
+        };
+
+        // Stop accepting on the signal, then give requests the timeout
+        let mut timer = stopping.subscribe();
+        let timeout = self.config.shutdown_timeout();
+        // Without TCP_NODELAY, small responses wait on the client's delayed
+        // ACK, adding tens of milliseconds to every request
+        let incoming = TcpIncoming::from(listener).with_nodelay(Some(true));
+        let raft = self.raft.as_ref().map(|raft| {
+            RaftServer::with_interceptor(
+                RaftService::new(Arc::clone(raft)),
+                replication_auth.clone(),
+            )
+        });
+        let server = builder
+            .add_service(KeyValueServer::with_interceptor(service, api_auth))
+            .add_service(AdminServer::with_interceptor(admin, admin_auth))
+            .add_service(ReplicationServer::with_interceptor(
+                replication,
+                replication_auth,
+            ))
+            .add_optional_service(raft)
+            .serve_with_incoming_shutdown(incoming, async {
+                shutdown.await;
+                log::info!("Shutting down, waiting up to {:?} for requests", timeout);
+                stopping.send_replace(true);
+            });
+        tokio::pin!(server);
+        let replicator = self
+            .replicator
+            .map(|replicator| tokio::spawn(replicator.run()));
+        let raft = self.raft.clone().map(|raft| tokio::spawn(raft.run()));
+
+        let result = tokio::select! {
+            result = &mut server => result,
+            _ = async {
+                let _ = timer.wait_for(|stopping| *stopping).await;
+                tokio::time::sleep(timeout).await;
+            } => {
+                log::warn!("Requests still running after {:?}, closing anyway", timeout);
+                Ok(())
+            }
+        };
+        stopping.send_replace(true);
+
+        if let Some(replicator) = replicator {
+            replicator.abort();
+            let _ = replicator.await;
+        }
+        if let (Some(task), Some(raft)) = (raft, &self.raft) {
+            raft.stop();
+            task.abort();
+            let _ = task.await;
+        }
+        if let Some(mut http) = http {
+            if tokio::time::timeout(timeout, &mut http).await.is_err() {
+                log::warn!(
+                    "HTTP requests still running after {:?}, closing anyway",
+                    timeout
+                );
+                http.abort();
+            }
+        }
+        if let Some(resp) = resp {
+            resp.abort();
+            let _ = resp.await;
+        }
+        self.engine.close()?;
+        log::info!("Server stopped");
+        Ok(result?)
+    }
+}
+
+/// Reads the certificates named by the TLS settings
+fn load_tls(settings: &config::TlsSettings) -> Result<ServerTlsConfig> {
+    let read = |path: &Path| {
+        std::fs::read(path)
+            .map_err(|e| Error::Config(format!("Failed to read {}: {}", path.display(), e)))
+    };
+    let identity = Identity::from_pem(read(&settings.cert_path)?, read(&settings.key_path)?);
+    let mut tls = ServerTlsConfig::new().identity(identity);
+    if let Some(ca_path) = &settings.client_ca_path {
+        tls = tls.client_ca_root(Certificate::from_pem(read(ca_path)?));
+    }
+    Ok(tls)
+}
+
+/// Returns a random token of 32 hex digits
+fn generate_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_core::error::{ErrorCode, ERROR_CODE_METADATA};
+    use ferrisdb_storage::{ColumnFamilyOptions, WriteBatch};
+    use proto::admin_client::AdminClient;
+    use proto::key_value_client::KeyValueClient;
+    use proto::{
+        mutation, BatchWriteRequest, DeleteRequest, GetRequest, MultiGetRequest, Mutation,
+        PutRequest, ScanRequest, ScanResponse,
+    };
+    use tempfile::TempDir;
+    use tonic::transport::Channel;
+    use tonic::Code;
+
+    use std::net::SocketAddr;
+    use std::path::Path;
+
+    struct TestServer {
+        addr: SocketAddr,
+        engine: Arc<StorageEngine>,
+        raft: Option<Arc<RaftNode>>,
+        stop: tokio::sync::oneshot::Sender<()>,
+        task: tokio::task::JoinHandle<Result<()>>,
+    }
+
+    async fn start(dir: &TempDir, max_scan_limit: u32) -> TestServer {
+        start_with(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            max_scan_limit,
+            allow_unauthenticated: true,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn start_with(config: ServerConfig) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        start_on(config, listener).await
+    }
+
+    async fn start_on(config: ServerConfig, listener: TcpListener) -> TestServer {
+        let server = Server::open(config).unwrap();
+        let engine = Arc::clone(server.engine());
+        let raft = server.raft().cloned();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(server.serve_with_listener(listener, async {
+            let _ = stopped.await;
+        }));
+        TestServer {
+            addr,
+            engine,
+            raft,
+            stop,
+            task,
+        }
+    }
+
+    async fn connect(addr: SocketAddr) -> KeyValueClient<Channel> {
+        KeyValueClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+
+    fn put(key: &[u8], value: &[u8]) -> Mutation {
+        Mutation {
+            column_family: String::new(),
+            op: Some(mutation::Op::Put(mutation::Put {
+                key: key.to_vec(),
+                value: value.to_vec(),
+            })),
+        }
+    }
+
+    async fn get(client: &mut KeyValueClient<Channel>, key: &[u8]) -> Option<Vec<u8>> {
+        client
+            .get(GetRequest {
+                column_family: String::new(),
+                key: key.to_vec(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .value
+    }
+
+    /// Runs a scan, returning its pairs and continuation token
+    async fn scan(
+        client: &mut KeyValueClient<Channel>,
+        request: ScanRequest,
+    ) -> (Vec<(Vec<u8>, Vec<u8>)>, Vec<u8>) {
+        let mut stream = client.scan(request).await.unwrap().into_inner();
+        let mut pairs = Vec::new();
+        let mut token = Vec::new();
+        while let Some(ScanResponse {
+            pairs: chunk,
+            continuation_token,
+        }) = stream.message().await.unwrap()
+        {
+            assert!(token.is_empty(), "only the last response has a token");
+            pairs.extend(chunk.into_iter().map(|pair| (pair.key, pair.value)));
+            token = continuation_token;
+        }
+        (pairs, token)
+    }
+
+    fn keys(pairs: &[(Vec<u8>, Vec<u8>)]) -> Vec<&[u8]> {
+        pairs.iter().map(|(key, _)| key.as_slice()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_reads_and_writes_over_grpc() {
+        let dir = TempDir::new().unwrap();
+        let server = start(&dir, 2).await;
+        let mut client = connect(server.addr).await;
+
+        client
+            .put(PutRequest {
+                column_family: String::new(),
+                key: b"a".to_vec(),
+                value: b"1".to_vec(),
+                sync: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(get(&mut client, b"a").await, Some(b"1".to_vec()));
+
+        client
+            .batch_write(BatchWriteRequest {
+                mutations: vec![put(b"b", b"2"), put(b"c", b"3"), put(b"d", b"4")],
+                sync: false,
+            })
+            .await
+            .unwrap();
+        client
+            .delete(DeleteRequest {
+                column_family: String::new(),
+                key: b"a".to_vec(),
+                sync: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(get(&mut client, b"a").await, None);
+
+        let values: Vec<_> = client
+            .multi_get(MultiGetRequest {
+                column_family: String::new(),
+                keys: vec![b"d".to_vec(), b"a".to_vec(), b"b".to_vec()],
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .values
+            .into_iter()
+            .map(|response| response.value)
+            .collect();
+        assert_eq!(values, vec![Some(b"4".to_vec()), None, Some(b"2".to_vec())]);
+
+        // Scans are capped by the server's limit
+        let (pairs, token) = scan(
+            &mut client,
+            ScanRequest {
+                start: b"a".to_vec(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(keys(&pairs), vec![b"b", b"c"]);
+        assert!(!token.is_empty());
+
+        let (pairs, token) = scan(
+            &mut client,
+            ScanRequest {
+                start: b"c".to_vec(),
+                end: b"d".to_vec(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(keys(&pairs), vec![b"c"]);
+        assert!(token.is_empty());
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+
+        // Everything acknowledged survives the shutdown
+        let engine = StorageEngine::open(ferrisdb_storage::Options::new(dir.path())).unwrap();
+        assert_eq!(engine.get(b"d").unwrap(), Some(b"4".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_scans_resume_from_continuation_tokens() {
+        let dir = TempDir::new().unwrap();
+        let server = start(&dir, 1000).await;
+        let mut client = connect(server.addr).await;
+
+        let mut batch = WriteBatch::new();
+        for i in 0..700 {
+            batch.put(format!("user:{:04}", i).into_bytes(), b"x".to_vec());
+        }
+        batch.put(b"users".to_vec(), b"x".to_vec());
+        batch.put(b"user".to_vec(), b"x".to_vec());
+        server.engine.write(batch, false).unwrap();
+
+        // Pages of 300 within the prefix, spanning several responses each
+        let mut request = ScanRequest {
+            prefix: b"user:".to_vec(),
+            limit: 300,
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        let mut pages = 0;
+        loop {
+            let (pairs, token) = scan(&mut client, request.clone()).await;
+            seen.extend(pairs.into_iter().map(|(key, _)| key));
+            pages += 1;
+            if token.is_empty() {
+                break;
+            }
+            request.continuation_token = token;
+        }
+        assert_eq!(pages, 3);
+        let expected: Vec<_> = (0..700)
+            .map(|i| format!("user:{:04}", i).into_bytes())
+            .collect();
+        assert_eq!(seen, expected);
+
+        // Bounds narrow the prefix further
+        let (pairs, _) = scan(
+            &mut client,
+            ScanRequest {
+                start: b"user:0100".to_vec(),
+                end: b"user:0103".to_vec(),
+                prefix: b"user:".to_vec(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(keys(&pairs), vec![b"user:0100", b"user:0101", b"user:0102"]);
+
+        let status = client
+            .scan(ScanRequest {
+                continuation_token: b"bogus".to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scans_ignore_concurrent_writes() {
+        let dir = TempDir::new().unwrap();
+        let server = start(&dir, 10_000).await;
+        let mut client = connect(server.addr).await;
+
+        let mut batch = WriteBatch::new();
+        for i in 0..5000 {
+            batch.put(format!("{:05}", i).into_bytes(), b"old".to_vec());
+        }
+        server.engine.write(batch, false).unwrap();
+
+        let mut stream = client
+            .scan(ScanRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        let mut pairs = stream.message().await.unwrap().unwrap().pairs;
+
+        // Overwrite, delete and insert while the scan is under way
+        let mut batch = WriteBatch::new();
+        for i in 0..5000 {
+            let key = format!("{:05}", i).into_bytes();
+            if i % 2 == 0 {
+                batch.put(key, b"new".to_vec());
+            } else {
+                batch.delete(key);
+            }
+            batch.put(format!("{:05}a", i).into_bytes(), b"new".to_vec());
+        }
+        server.engine.write(batch, false).unwrap();
+        server.engine.flush().unwrap();
+
+        while let Some(response) = stream.message().await.unwrap() {
+            pairs.extend(response.pairs);
+        }
+        assert_eq!(pairs.len(), 5000);
+        assert!(pairs.iter().all(|pair| pair.value == b"old"));
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_status_codes() {
+        let dir = TempDir::new().unwrap();
+        let server = start(&dir, 100).await;
+        let mut client = connect(server.addr).await;
+
+        let status = client
+            .get(GetRequest {
+                column_family: "missing".to_string(),
+                key: b"a".to_vec(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // No merge operator is configured
+        let merge = Mutation {
+            column_family: String::new(),
+            op: Some(mutation::Op::Merge(mutation::Merge {
+                key: b"a".to_vec(),
+                operand: b"1".to_vec(),
+            })),
+        };
+        let status = client
+            .batch_write(BatchWriteRequest {
+                mutations: vec![merge],
+                sync: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.metadata().get(ERROR_CODE_METADATA).unwrap(),
+            &ErrorCode::FailedPrecondition.as_u16().to_string()
+        );
+
+        let status = client
+            .batch_write(BatchWriteRequest {
+                mutations: vec![Mutation::default()],
+                sync: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    /// Builds a request, with `token` as bearer token if given
+    fn with_token<T>(message: T, token: Option<&str>) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(token) = token {
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_admin_rpcs_need_the_admin_token() {
+        let dir = TempDir::new().unwrap();
+        let checkpoints = TempDir::new().unwrap();
+        let server = start_with(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            allow_unauthenticated: true,
+            admin_token: Some("s3cret".to_string()),
+            checkpoint_dir: checkpoints.path().to_path_buf(),
+            storage: config::StorageSettings {
+                slow_log_threshold_us: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let mut client = connect(server.addr).await;
+        let mut admin = AdminClient::connect(format!("http://{}", server.addr))
+            .await
+            .unwrap();
+
+        let mut batch = BatchWriteRequest {
+            mutations: (0..10)
+                .map(|i| put(format!("key{}", i).as_bytes(), b"v"))
+                .collect(),
+            sync: false,
+        };
+        client.batch_write(batch.clone()).await.unwrap();
+
+        // Key-value calls need no token, admin calls need the right one
+        let status = admin
+            .flush(with_token(proto::FlushRequest {}, None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let status = admin
+            .flush(with_token(proto::FlushRequest {}, Some("guess")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let token = Some("s3cret");
+        admin
+            .flush(with_token(proto::FlushRequest {}, token))
+            .await
+            .unwrap();
+        batch.mutations.truncate(5);
+        client.batch_write(batch).await.unwrap();
+        admin
+            .compact_range(with_token(proto::CompactRangeRequest::default(), token))
+            .await
+            .unwrap();
+
+        let statistics = admin
+            .get_statistics(with_token(proto::GetStatisticsRequest::default(), token))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(statistics.flushes, 2);
+        assert_eq!(statistics.compactions, 1);
+        assert_eq!(statistics.estimated_num_keys, 10);
+        assert_eq!(statistics.level_files[0], 0);
+
+        let property = admin
+            .get_property(with_token(
+                proto::GetPropertyRequest {
+                    column_family: String::new(),
+                    name: "ferrisdb.estimate-num-keys".to_string(),
+                },
+                token,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(property.value.as_deref(), Some("10"));
+
+        let metrics = admin
+            .get_metrics(with_token(proto::GetMetricsRequest {}, token))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(metrics.text.contains("ferrisdb_storage_estimated_keys 10"));
+
+        let slow_log = |reset| proto::GetSlowLogRequest { limit: 1, reset };
+        let operations = admin
+            .get_slow_log(with_token(slow_log(true), token))
+            .await
+            .unwrap()
+            .into_inner()
+            .operations;
+        // The compaction was the last operation
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].kind, "compaction");
+        assert_eq!(operations[0].column_family, "default");
+        assert!(operations[0].duration_us >= 1);
+        let operations = admin
+            .get_slow_log(with_token(slow_log(false), token))
+            .await
+            .unwrap()
+            .into_inner()
+            .operations;
+        assert!(operations.is_empty());
+
+        let checkpoint = admin
+            .create_checkpoint(with_token(
+                proto::CreateCheckpointRequest {
+                    name: "nightly".to_string(),
+                },
+                token,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            Path::new(&checkpoint.path),
+            checkpoints.path().join("nightly")
+        );
+        for name in ["../escape", "/tmp/abs", "", "a/b"] {
+            let status = admin
+                .create_checkpoint(with_token(
+                    proto::CreateCheckpointRequest {
+                        name: name.to_string(),
+                    },
+                    token,
+                ))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{:?}", name);
+        }
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+
+        let copy = StorageEngine::open(ferrisdb_storage::Options::new(&checkpoint.path)).unwrap();
+        assert_eq!(copy.get(b"key9").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_admin_service_is_closed_without_a_token() {
+        let dir = TempDir::new().unwrap();
+        let server = start(&dir, 100).await;
+        let mut admin = AdminClient::connect(format!("http://{}", server.addr))
+            .await
+            .unwrap();
+        let status = admin
+            .flush(with_token(proto::FlushRequest {}, Some("anything")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_key_value_rpcs_need_an_api_token() {
+        let dir = TempDir::new().unwrap();
+        let server = start_with(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            api_tokens: vec!["old".to_string(), "new".to_string()],
+            ..Default::default()
+        })
+        .await;
+        let mut client = connect(server.addr).await;
+        let request = |token| {
+            with_token(
+                GetRequest {
+                    column_family: String::new(),
+                    key: b"a".to_vec(),
+                },
+                token,
+            )
+        };
+
+        let status = client.get(request(None)).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let status = client.get(request(Some("guess"))).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        for token in ["old", "new"] {
+            client.get(request(Some(token))).await.unwrap();
+        }
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_a_token_is_generated_unless_unauthenticated_access_is_allowed() {
+        let dir = TempDir::new().unwrap();
+        let config = ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let server = Server::open(config.clone()).unwrap();
+        assert_eq!(server.api_tokens().len(), 1);
+        assert_eq!(server.api_tokens()[0].len(), 32);
+        server.engine().close().unwrap();
+        drop(server);
+
+        let server = Server::open(ServerConfig {
+            allow_unauthenticated: true,
+            ..config.clone()
+        })
+        .unwrap();
+        assert!(server.api_tokens().is_empty());
+        server.engine().close().unwrap();
+        drop(server);
+
+        // Public addresses need TLS or an explicit opt-out
+        let public = ServerConfig {
+            listen_addr: "0.0.0.0:7070".parse().unwrap(),
+            ..config
+        };
+        assert!(matches!(
+            Server::open(public.clone()),
+            Err(Error::Config(_))
+        ));
+        let server = Server::open(ServerConfig {
+            allow_plaintext: true,
+            ..public
+        })
+        .unwrap();
+        server.engine().close().unwrap();
+    }
+
+    fn tls_file(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(name)
+    }
+
+    async fn connect_tls(
+        addr: SocketAddr,
+        identity: Option<Identity>,
+    ) -> std::result::Result<KeyValueClient<Channel>, tonic::transport::Error> {
+        let ca = std::fs::read(tls_file("ca.pem")).unwrap();
+        let mut tls = tonic::transport::ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca))
+            .domain_name("localhost");
+        if let Some(identity) = identity {
+            tls = tls.identity(identity);
+        }
+        let channel = Channel::from_shared(format!("https://{}", addr))
+            .unwrap()
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(KeyValueClient::new(channel))
+    }
+
+    #[tokio::test]
+    async fn test_connections_over_their_quota_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let server = start_with(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            allow_unauthenticated: true,
+            admin_token: Some("s3cret".to_string()),
+            quotas: Some(config::QuotaSettings {
+                connection_requests_per_sec: Some(3),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await;
+
+        let mut client = connect(server.addr).await;
+        for _ in 0..3 {
+            get(&mut client, b"a").await;
+        }
+        let status = client
+            .get(GetRequest {
+                column_family: String::new(),
+                key: b"a".to_vec(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        // Another connection has a quota of its own, and admin calls have none
+        let mut other = connect(server.addr).await;
+        assert_eq!(get(&mut other, b"a").await, None);
+        let mut admin = AdminClient::connect(format!("http://{}", server.addr))
+            .await
+            .unwrap();
+        let metrics = admin
+            .get_metrics(with_token(proto::GetMetricsRequest {}, Some("s3cret")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(metrics.text.contains(
+            "ferrisdb_server_quota_rejected_requests_total{quota=\"request\",scope=\"connection\"} 1"
+        ));
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serves_over_tls_with_client_certificates() {
+        let dir = TempDir::new().unwrap();
+        let server = start_with(ServerConfig {
+            data_dir: dir.path().to_path_buf(),
+            allow_unauthenticated: true,
+            tls: Some(config::TlsSettings {
+                cert_path: tls_file("server.pem"),
+                key_path: tls_file("server.key"),
+                client_ca_path: Some(tls_file("ca.pem")),
+            }),
+            ..Default::default()
+        })
+        .await;
+
+        let identity = Identity::from_pem(
+            std::fs::read(tls_file("client.pem")).unwrap(),
+            std::fs::read(tls_file("client.key")).unwrap(),
+        );
+        let mut client = connect_tls(server.addr, Some(identity)).await.unwrap();
+        client
+            .put(PutRequest {
+                column_family: String::new(),
+                key: b"a".to_vec(),
+                value: b"1".to_vec(),
+                sync: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(get(&mut client, b"a").await, Some(b"1".to_vec()));
+
+        // Neither plaintext nor TLS without a client certificate gets in
+        let mut plaintext = connect(server.addr).await;
+        let get_a = || GetRequest {
+            column_family: String::new(),
+            key: b"a".to_vec(),
+        };
+        assert!(plaintext.get(get_a()).await.is_err());
+        if let Ok(mut anonymous) = connect_tls(server.addr, None).await {
+            assert!(anonymous.get(get_a()).await.is_err());
+        }
+
+        server.stop.send(()).unwrap();
+        server.task.await.unwrap().unwrap();
+    }
+
+    /// Waits up to ten seconds for `condition` to hold
+    async fn eventually(mut condition: impl FnMut() -> bool) {
+        for _ in 0..1000 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("condition not met within ten seconds");
+    }
+
+    #[tokio::test]
+    async fn test_replica_streams_writes_from_the_primary() {
+        let dir = TempDir::new().unwrap();
+        let primary = start_with(ServerConfig {
+            data_dir: dir.path().join("primary"),
+            allow_unauthenticated: true,
+            replication_token: Some("repl".to_string()),
+            ..Default::default()
+        })
+        .await;
+        let mut client = connect(primary.addr).await;
+        let batch = |range: std::ops::Range<usize>| BatchWriteRequest {
+            mutations: range
+                .map(|i| put(format!("key{:03}", i).as_bytes(), b"v"))
+                .collect(),
+            sync: false,
+        };
+        client.batch_write(batch(0..10)).await.unwrap();
+        primary
+            .engine
+            .create_checkpoint(dir.path().join("replica"))
+            .unwrap();
+
+        let replica_config = ServerConfig {
+            data_dir: dir.path().join("replica"),
+            allow_unauthenticated: true,
+            replica: Some(config::ReplicaSettings {
+                primary: format!("http://{}", primary.addr),
+                token: Some("repl".to_string()),
+                tls_ca_path: None,
+                replica_id: Some("replica-1".to_string()),
+            }),
+            ..Default::default()
+        };
+        let replica = start_with(replica_config.clone()).await;
+        for i in 10..20 {
+            client.batch_write(batch(i..i + 1)).await.unwrap();
+        }
+        client.batch_write(batch(20..100)).await.unwrap();
+        eventually(|| replica.engine.last_timestamp() == primary.engine.last_timestamp()).await;
+
+        let mut replica_client = connect(replica.addr).await;
+        assert_eq!(
+            get(&mut replica_client, b"key099").await,
+            Some(b"v".to_vec())
+        );
+        let status = replica_client.batch_write(batch(0..1)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        // The primary reports the replica's acknowledgements
+        let mut replication = proto::replication_client::ReplicationClient::connect(format!(
+            "http://{}",
+            primary.addr
+        ))
+        .await
+        .unwrap();
+        let status = replication
+            .get_replication_status(with_token(proto::GetReplicationStatusRequest {}, None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let last = primary.engine.last_timestamp();
+        let mut applied = 0;
+        for _ in 0..1000 {
+            let status = replication
+                .get_replication_status(with_token(
+                    proto::GetReplicationStatusRequest {},
+                    Some("repl"),
+                ))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(status.last_timestamp, last);
+            assert_eq!(status.replicas.len(), 1);
+            assert_eq!(status.replicas[0].replica_id, "replica-1");
+            applied = status.replicas[0].applied_timestamp;
+            if applied == last {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(applied, last);
+
+        // A restarted replica resumes where it stopped
+        replica.stop.send(()).unwrap();
+        replica.task.await.unwrap().unwrap();
+        client.batch_write(batch(100..150)).await.unwrap();
+        let replica = start_with(replica_config).await;
+        eventually(|| replica.engine.last_timestamp() == primary.engine.last_timestamp()).await;
+        assert_eq!(
+            replica.engine.scan::<[u8], _>(..).unwrap(),
+            primary.engine.scan::<[u8], _>(..).unwrap()
+        );
+
+        replica.stop.send(()).unwrap();
+        replica.task.await.unwrap().unwrap();
+        primary.stop.send(()).unwrap();
+        primary.task.await.unwrap().unwrap();
+    }
+
+    /// Configures node `id` of a cluster listening on `addrs`, node 1 first
+    fn raft_config(dir: &TempDir, id: u64, addrs: &[SocketAddr]) -> ServerConfig {
+        let peers = (1..)
+            .zip(addrs)
+            .filter(|&(peer, _)| peer != id)
+            .map(|(peer, addr)| config::RaftPeer {
+                id: peer,
+                addr: format!("http://{}", addr),
+            })
+            .collect();
+        ServerConfig {
+            data_dir: dir.path().join(format!("node-{}", id)),
+            allow_unauthenticated: true,
+            replication_token: Some("repl".to_string()),
+            raft: Some(config::RaftSettings {
+                node_id: id,
+                peers,
+                tls_ca_path: None,
+                election_timeout_ms: Some(300),
+                heartbeat_interval_ms: Some(50),
+                max_log_entries: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Waits until every server follows the same leader, returning its
+    /// position in `servers`
+    async fn raft_leader(servers: &[TestServer]) -> usize {
+        let mut leader = None;
+        eventually(|| {
+            let statuses: Vec<_> = servers
+                .iter()
+                .map(|server| server.raft.as_ref().unwrap().status())
+                .collect();
+            leader = statuses.iter().position(|status| status.role == "leader");
+            leader.is_some_and(|leader| {
+                let leader = &statuses[leader];
+                statuses
+                    .iter()
+                    .all(|status| status.term == leader.term && status.leader_id == leader.node_id)
+            })
+        })
+        .await;
+        leader.unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_raft_cluster_replicates_writes_and_fails_over() {
+        let dir = TempDir::new().unwrap();
+        let mut listeners = Vec::new();
+        for _ in 0..3 {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        let addrs: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        let mut servers = Vec::new();
+        for (id, listener) in (1..).zip(listeners) {
+            servers.push(start_on(raft_config(&dir, id, &addrs), listener).await);
+        }
+        let leader = raft_leader(&servers).await;
+
+        // A write through the leader reaches every server
+        let mut client = connect(servers[leader].addr).await;
+        let request = BatchWriteRequest {
+            mutations: vec![put(b"a", b"1"), put(b"b", b"2")],
+            sync: false,
+        };
+        client.batch_write(request).await.unwrap();
+        assert_eq!(get(&mut client, b"a").await, Some(b"1".to_vec()));
+        for server in &servers {
+            eventually(|| server.engine.get(b"b").unwrap() == Some(b"2".to_vec())).await;
+        }
+
+        // Followers turn writes away, naming the leader
+        let follower = (leader + 1) % servers.len();
+        let status = connect(servers[follower].addr)
+            .await
+            .put(PutRequest {
+                column_family: String::new(),
+                key: b"c".to_vec(),
+                value: b"3".to_vec(),
+                sync: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(status.message().contains(&servers[leader].addr.to_string()));
+
+        // The log's family is out of reach, and merges no node could
+        // apply take no entry
+        let status = connect(servers[leader].addr)
+            .await
+            .put(PutRequest {
+                column_family: raft::COLUMN_FAMILY.to_string(),
+                key: b"state".to_vec(),
+                value: b"3".to_vec(),
+                sync: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let request = BatchWriteRequest {
+            mutations: vec![Mutation {
+                column_family: String::new(),
+                op: Some(mutation::Op::Merge(mutation::Merge {
+                    key: b"a".to_vec(),
+                    operand: b"1".to_vec(),
+                })),
+            }],
+            sync: false,
+        };
+        let status = client.batch_write(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        // The others elect a new leader without the old one
+        let old = servers.remove(leader);
+        old.stop.send(()).unwrap();
+        old.task.await.unwrap().unwrap();
+        let new = raft_leader(&servers).await;
+        let mut client = connect(servers[new].addr).await;
+        client
+            .put(PutRequest {
+                column_family: String::new(),
+                key: b"c".to_vec(),
+                value: b"3".to_vec(),
+                sync: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(get(&mut client, b"a").await, Some(b"1".to_vec()));
+
+        // The old leader rejoins as a follower and catches up
+        let listener = TcpListener::bind(old.addr).await.unwrap();
+        let id = leader as u64 + 1;
+        servers.push(start_on(raft_config(&dir, id, &addrs), listener).await);
+        raft_leader(&servers).await;
+        let rejoined = servers.last().unwrap();
+        eventually(|| rejoined.engine.get(b"c").unwrap() == Some(b"3".to_vec())).await;
+        assert_eq!(rejoined.engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+        for server in servers {
+            server.stop.send(()).unwrap();
+            server.task.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_raft_node_behind_the_compacted_log_catches_up_from_a_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let mut listeners = Vec::new();
+        for _ in 0..3 {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        let addrs: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        let config = |id| {
+            let mut config = raft_config(&dir, id, &addrs);
+            config.raft.as_mut().unwrap().max_log_entries = Some(5);
+            config
+        };
+        let mut servers = Vec::new();
+        for (id, listener) in (1..).zip(listeners) {
+            servers.push(start_on(config(id), listener).await);
+        }
+        let leader = raft_leader(&servers).await;
+
+        // A follower is lost with its data while the log is compacted
+        let lost = servers.remove((leader + 1) % servers.len());
+        let id = addrs.iter().position(|addr| *addr == lost.addr).unwrap() as u64 + 1;
+        lost.stop.send(()).unwrap();
+        lost.task.await.unwrap().unwrap();
+        std::fs::remove_dir_all(dir.path().join(format!("node-{}", id))).unwrap();
+
+        let leader = raft_leader(&servers).await;
+        // A family only the leader has reaches the others through the log
+        servers[leader]
+            .engine
+            .create_column_family("users", ColumnFamilyOptions::default())
+            .unwrap();
+        let mut client = connect(servers[leader].addr).await;
+        for i in 0..30u32 {
+            let request = BatchWriteRequest {
+                mutations: vec![Mutation {
+                    column_family: "users".to_string(),
+                    op: Some(mutation::Op::Put(mutation::Put {
+                        key: i.to_be_bytes().to_vec(),
+                        value: b"v".to_vec(),
+                    })),
+                }],
+                sync: false,
+            };
+            client.batch_write(request).await.unwrap();
+        }
+        let status = servers[leader].raft.as_ref().unwrap().status();
+        assert!(status.snapshot_index >= 20, "{:?}", status);
+
+        // It rejoins empty and is sent a snapshot
+        let listener = TcpListener::bind(lost.addr).await.unwrap();
+        servers.push(start_on(config(id), listener).await);
+        let rejoined = servers.last().unwrap();
+        eventually(|| {
+            let status = rejoined.raft.as_ref().unwrap().status();
+            status.applied_index >= 30 && status.snapshot_index > 0
+        })
+        .await;
+        let users = rejoined.engine.cf_handle("users").unwrap();
+        let keys = rejoined.engine.scan_cf::<[u8], _>(&users, ..).unwrap();
+        assert_eq!(keys.len(), 30);
+        assert!(rejoined
+            .raft
+            .as_ref()
+            .unwrap()
+            .status()
+            .apply_error
+            .is_empty());
+
+        for server in servers {
+            server.stop.send(()).unwrap();
+            server.task.await.unwrap().unwrap();
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! FerrisDB server binary
+//!
+//! ```text
+//! ferrisdb-server --config ferrisdb.toml
+//! ferrisdb-server --shared-config ferrisdb-shared.toml
+//! ferrisdb-server --data-dir ./data --listen 0.0.0.0:7070
+//! ```
+//!
+//! Command-line flags override the configuration file. The server shuts
+//! down gracefully on Ctrl-C.
+
+use clap::Parser;
+use ferrisdb_server::{Server, ServerConfig};
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Serves a FerrisDB database over gRPC
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// TOML configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Configuration file in the format shared by all FerrisDB components
+    #[arg(long, conflicts_with = "config")]
+    shared_config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(short, long)]
+    listen: Option<SocketAddr>,
+
+    /// Directory holding the database
+    #[arg(short, long)]
+    data_dir: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> ferrisdb_server::Result<()> {
+    let mut config = match (&args.config, &args.shared_config) {
+        (Some(path), _) => ServerConfig::from_file(path)?,
+        (None, Some(path)) => ServerConfig::from_core(&ferrisdb_core::Config::from_file(path)?)?,
+        (None, None) => ServerConfig::default(),
+    };
+    if let Some(listen) = args.listen {
+        config.listen_addr = listen;
+    }
+    if let Some(data_dir) = args.data_dir {
+        config.data_dir = data_dir;
+    }
+
+    Server::open(config)?
+        .serve(async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                log::error!("Failed to listen for Ctrl-C: {}", e);
+                std::future::pending::<()>().await;
+            }
+        })
+        .await
+}
@@ -0,0 +1,349 @@
+//! Request and byte quotas protecting a shared server
+//!
+//! Each quota is a token bucket refilled at its configured rate that holds
+//! at most one second's worth. A request is admitted while the request
+//! buckets it draws from have a token left and its byte buckets aren't
+//! empty. It takes one request token, and bytes for its payload as it
+//! arrives and for the data sent back once known.
+//! Bytes can overdraw a bucket, so one large request still gets through,
+//! after which the client waits until the debt is paid off.
+//!
+//! ```text
+//!                       ┌─ global: requests/s, bytes/s ─────────┐
+//!   request ──admit────▶│                                       │──▶ handler ──charge──▶ response
+//!     │                 └─ its connection: requests/s, bytes/s ─┘
+//!     └── rejected with RESOURCE_EXHAUSTED (HTTP 429) if any bucket is empty
+//! ```
+//!
+//! The global quotas cap the whole server; the per-connection ones keep a
+//! single runaway client from using all of it. Connections are told apart
+//! by their peer address. Quotas cover the `KeyValue` service, the HTTP
+//! gateway and the Redis frontend; `Admin` and `Replication` calls are
+//! never throttled.
+//!
+//! Admitted and rejected requests are counted in the server's metrics, as
+//! `ferrisdb_server_quota_*`.
+
+use crate::config::QuotaSettings;
+
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry};
+use tonic::Status;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often buckets of connections that went quiet are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Enforces the request and byte quotas of a server
+pub struct Quotas {
+    settings: QuotaSettings,
+    state: Mutex<QuotaState>,
+    admitted: IntCounter,
+    rejected: IntCounterVec,
+    bytes: IntCounter,
+}
+
+struct QuotaState {
+    global: Limits,
+    connections: HashMap<SocketAddr, Limits>,
+    swept: Instant,
+}
+
+/// The quotas one request is charged against
+#[derive(Clone)]
+pub(crate) struct Meter {
+    quotas: Arc<Quotas>,
+    connection: Option<SocketAddr>,
+}
+
+/// A bucket of tokens refilled at a fixed rate
+#[derive(Debug)]
+struct Bucket {
+    /// Tokens added per second, also the most the bucket holds
+    rate: f64,
+    /// May be negative after a charge larger than what was left
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// The request and byte buckets of one scope
+#[derive(Debug, Default)]
+struct Limits {
+    requests: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl Quotas {
+    /// Creates quotas enforcing `settings`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric definitions are invalid, which they never are.
+    pub fn new(settings: &QuotaSettings) -> Self {
+        let now = Instant::now();
+        let counter = |name: &str, help: &str| {
+            IntCounter::with_opts(Opts::new(name, help).namespace("ferrisdb_server"))
+                .expect("metric definition is valid")
+        };
+        Self {
+            settings: settings.clone(),
+            state: Mutex::new(QuotaState {
+                global: Limits::new(settings.requests_per_sec, settings.bytes_per_sec, now),
+                connections: HashMap::new(),
+                swept: now,
+            }),
+            admitted: counter(
+                "quota_admitted_requests_total",
+                "Requests admitted by the quotas",
+            ),
+            rejected: IntCounterVec::new(
+                Opts::new(
+                    "quota_rejected_requests_total",
+                    "Requests rejected for exceeding a quota",
+                )
+                .namespace("ferrisdb_server"),
+                &["scope", "quota"],
+            )
+            .expect("metric definition is valid"),
+            bytes: counter(
+                "quota_bytes_total",
+                "Request and response bytes charged to the quotas",
+            ),
+        }
+    }
+
+    /// Creates quotas that admit everything, still counting requests
+    pub fn unlimited() -> Self {
+        Self::new(&QuotaSettings::default())
+    }
+
+    /// Returns the quotas being enforced
+    pub fn settings(&self) -> &QuotaSettings {
+        &self.settings
+    }
+
+    /// Adds the quota metrics to `registry`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry already has metrics of these names.
+    pub fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.admitted.clone()))?;
+        registry.register(Box::new(self.rejected.clone()))?;
+        registry.register(Box::new(self.bytes.clone()))
+    }
+
+    /// Admits a request of `bytes` from `connection`, or rejects it with
+    /// `RESOURCE_EXHAUSTED` if a quota it draws from is used up
+    ///
+    /// Requests without a known connection only count against the global
+    /// quotas.
+    // Handlers return `Status` by value, so this does too
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn admit(
+        self: &Arc<Self>,
+        connection: Option<SocketAddr>,
+        bytes: usize,
+    ) -> Result<Meter, Status> {
+        let now = Instant::now();
+        let mut guard = self.state.lock().expect("quota state poisoned");
+        let state = &mut *guard;
+        if now.duration_since(state.swept) >= SWEEP_INTERVAL {
+            // A full bucket is no different from a new one
+            state.connections.retain(|_, limits| !limits.is_full(now));
+            state.swept = now;
+        }
+
+        let per_connection = self.settings.connection_requests_per_sec.is_some()
+            || self.settings.connection_bytes_per_sec.is_some();
+        let mut local = match connection.filter(|_| per_connection) {
+            Some(addr) => Some(state.connections.entry(addr).or_insert_with(|| {
+                Limits::new(
+                    self.settings.connection_requests_per_sec,
+                    self.settings.connection_bytes_per_sec,
+                    now,
+                )
+            })),
+            None => None,
+        };
+
+        let scopes = [
+            ("global", Some(&mut state.global)),
+            ("connection", local.as_deref_mut()),
+        ];
+        for (scope, limits) in scopes {
+            let Some(limits) = limits else { continue };
+            limits.refill(now);
+            if let Some(quota) = limits.exhausted() {
+                self.rejected.with_label_values(&[scope, quota]).inc();
+                return Err(Status::resource_exhausted(format!(
+                    "The {} {} quota is exhausted, retry later",
+                    scope, quota
+                )));
+            }
+        }
+
+        state.global.take(1.0, bytes as f64);
+        if let Some(limits) = local {
+            limits.take(1.0, bytes as f64);
+        }
+        drop(guard);
+
+        self.admitted.inc();
+        self.bytes.inc_by(bytes as u64);
+        Ok(Meter {
+            quotas: Arc::clone(self),
+            connection,
+        })
+    }
+
+    /// Takes `bytes` from the byte quotas of `connection`
+    fn charge(&self, connection: Option<SocketAddr>, bytes: usize) {
+        let mut state = self.state.lock().expect("quota state poisoned");
+        state.global.take(0.0, bytes as f64);
+        if let Some(limits) = connection.and_then(|addr| state.connections.get_mut(&addr)) {
+            limits.take(0.0, bytes as f64);
+        }
+        drop(state);
+        self.bytes.inc_by(bytes as u64);
+    }
+}
+
+impl Meter {
+    /// Charges `bytes` sent back for the request
+    pub(crate) fn charge(&self, bytes: usize) {
+        if bytes > 0 {
+            self.quotas.charge(self.connection, bytes);
+        }
+    }
+}
+
+impl Bucket {
+    /// Creates a full bucket refilled at `rate` per second
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+    }
+}
+
+impl Limits {
+    fn new(requests_per_sec: Option<u64>, bytes_per_sec: Option<u64>, now: Instant) -> Self {
+        Self {
+            requests: requests_per_sec.map(|rate| Bucket::new(rate, now)),
+            bytes: bytes_per_sec.map(|rate| Bucket::new(rate, now)),
+        }
+    }
+
+    fn buckets(&mut self) -> impl Iterator<Item = &mut Bucket> {
+        self.requests.iter_mut().chain(self.bytes.iter_mut())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.buckets().for_each(|bucket| bucket.refill(now));
+    }
+
+    /// Returns the name of a quota that is used up, if any
+    fn exhausted(&self) -> Option<&'static str> {
+        // A request needs a whole token, bytes only a bucket not overdrawn
+        if !self
+            .requests
+            .as_ref()
+            .map_or(true, |bucket| bucket.tokens >= 1.0)
+        {
+            Some("request")
+        } else if !self
+            .bytes
+            .as_ref()
+            .map_or(true, |bucket| bucket.tokens > 0.0)
+        {
+            Some("byte")
+        } else {
+            None
+        }
+    }
+
+    fn take(&mut self, requests: f64, bytes: f64) {
+        if let Some(bucket) = &mut self.requests {
+            bucket.tokens -= requests;
+        }
+        if let Some(bucket) = &mut self.bytes {
+            bucket.tokens -= bytes;
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.buckets().all(|bucket| bucket.tokens >= bucket.rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> Option<SocketAddr> {
+        Some(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    #[test]
+    fn test_connections_are_limited_separately() {
+        let quotas = Arc::new(Quotas::new(&QuotaSettings {
+            connection_requests_per_sec: Some(2),
+            ..Default::default()
+        }));
+
+        assert!(quotas.admit(addr(1), 0).is_ok());
+        assert!(quotas.admit(addr(1), 0).is_ok());
+        let status = quotas.admit(addr(1), 0).err().unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        // Other connections and requests without one are unaffected
+        assert!(quotas.admit(addr(2), 0).is_ok());
+        assert!(quotas.admit(None, 0).is_ok());
+
+        let registry = Registry::new();
+        quotas.register(&registry).unwrap();
+        let text = ferrisdb_metrics::encode(&registry).unwrap();
+        assert!(text.contains("ferrisdb_server_quota_admitted_requests_total 4"));
+        assert!(text.contains(
+            "ferrisdb_server_quota_rejected_requests_total{quota=\"request\",scope=\"connection\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_bytes_can_overdraw_until_the_debt_is_paid() {
+        let quotas = Arc::new(Quotas::new(&QuotaSettings {
+            bytes_per_sec: Some(1000),
+            ..Default::default()
+        }));
+
+        // One request larger than the whole quota still gets through
+        let meter = quotas.admit(None, 10).unwrap();
+        meter.charge(1500);
+        assert!(quotas.admit(addr(1), 0).is_err());
+
+        std::thread::sleep(Duration::from_millis(800));
+        assert!(quotas.admit(addr(1), 0).is_ok());
+    }
+
+    #[test]
+    fn test_unlimited_quotas_admit_everything() {
+        let quotas = Arc::new(Quotas::unlimited());
+        for _ in 0..10_000 {
+            quotas.admit(addr(1), 1 << 20).unwrap().charge(1 << 20);
+        }
+        assert!(quotas.state.lock().unwrap().connections.is_empty());
+    }
+}
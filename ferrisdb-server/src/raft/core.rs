@@ -0,0 +1,1343 @@
+//! The Raft state machine, free of I/O and timers
+//!
+//! [`RaftCore`] follows the algorithm of the Raft paper for one node of a
+//! fixed cluster. It is driven from outside: the caller ticks it at a
+//! steady interval, hands it the requests and responses other nodes send,
+//! and sends the requests it queues in its outbox. Every change to the
+//! term, the vote or the log goes through the [`LogStore`] before the
+//! core answers or sends anything that depends on it, so a node that
+//! crashes and restarts from its store keeps every promise it made.
+//!
+//! ```text
+//!   tick ──────────▶ ┌──────────┐ ──▶ outbox: RequestVote / AppendEntries
+//!   requests ──────▶ │ RaftCore │         / InstallSnapshot
+//!   responses ─────▶ └──────────┘ ──▶ responses, commit index
+//!                         │
+//!                     LogStore: term, vote, entries, snapshot
+//! ```
+//!
+//! A node starts as a follower. If it hears from no leader for an
+//! election timeout, randomized so nodes rarely time out together, it
+//! becomes a candidate in a new term and asks the others for their votes.
+//! Each node votes once per term, and only for a candidate whose log is at
+//! least as up to date as its own, so a candidate winning a majority has
+//! every committed entry. The leader appends proposals to its log and
+//! replicates them; an entry of its own term is committed once a majority
+//! has it, and every entry before it with it. A new leader commits the
+//! entries of earlier terms by appending an empty entry of its own.
+//!
+//! Once applied, the start of the log can be [compacted](RaftCore::compact)
+//! away, leaving the state it built as the snapshot the log starts from. A
+//! follower needing compacted entries can't be sent them anymore; the
+//! leader queues [`Request::Snapshot`] instead, and the caller sends the
+//! follower a copy of its state for the follower to
+//! [install](RaftCore::handle_snapshot) in place of its own.
+
+use ferrisdb_core::Result;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Most entries sent in one `AppendEntries` request
+const MAX_ENTRIES_PER_APPEND: usize = 256;
+
+/// Bytes of entry data after which an `AppendEntries` request is sent
+/// without adding more
+const MAX_APPEND_BYTES: usize = 4 * 1024 * 1024;
+
+/// One entry of the replicated log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Position in the log, from 1
+    pub index: u64,
+    /// Term of the leader that appended it
+    pub term: u64,
+    /// The command, empty for the entry a new leader starts its term with
+    pub data: Vec<u8>,
+}
+
+/// What a node must remember across restarts besides its log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HardState {
+    /// Newest term the node has seen
+    pub term: u64,
+    /// Candidate the node voted for in `term`, if any
+    pub voted_for: Option<u64>,
+}
+
+/// Durable storage of a node's log and hard state
+///
+/// Every method returns once the change is durable. The log starts after
+/// the snapshot it was compacted into: [`term`](Self::term) still knows
+/// the term of the snapshot's last entry, but no entry before it. Index 0
+/// stands for the empty log, the snapshot of a log never compacted, and
+/// has term 0.
+pub trait LogStore {
+    /// Returns the hard state last saved
+    fn hard_state(&self) -> HardState;
+
+    /// Saves the term and vote
+    fn save_hard_state(&mut self, state: HardState) -> Result<()>;
+
+    /// Returns the index of the last entry, 0 if the log is empty
+    fn last_index(&self) -> u64;
+
+    /// Returns the index of the last entry of the snapshot the log
+    /// starts from, 0 if it was never compacted
+    fn snapshot_index(&self) -> u64;
+
+    /// Returns the term of the entry at `index`, if the log has it or it
+    /// is the snapshot's last
+    fn term(&self, index: u64) -> Option<u64>;
+
+    /// Returns the entries from `from` to `to` (inclusive), which must be
+    /// after the snapshot, stopping early once they hold `max_bytes` of
+    /// data, but never empty if the range isn't
+    fn entries(&self, from: u64, to: u64, max_bytes: usize) -> Result<Vec<Entry>>;
+
+    /// Appends consecutive entries after the snapshot, first removing
+    /// every entry at or after the first one's index
+    fn append(&mut self, entries: &[Entry]) -> Result<()>;
+
+    /// Discards the entries up to `index`, which must be applied, making
+    /// it the snapshot's last
+    fn compact(&mut self, index: u64) -> Result<()>;
+
+    /// Starts the log from a snapshot whose last entry is `index` of
+    /// `term`, installed in place of the state the log built
+    ///
+    /// Entries after `index` are kept if the log has that entry, as they
+    /// continue the snapshot; otherwise the whole log is discarded.
+    fn install_snapshot(&mut self, index: u64, term: u64) -> Result<()>;
+}
+
+/// A node's part in its term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Follows the leader of the term, if it knows one
+    Follower,
+    /// Asks for votes to become the leader
+    Candidate,
+    /// Accepts proposals and replicates the log
+    Leader,
+}
+
+/// A candidate asking for a vote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate: u64,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+}
+
+/// A node's answer to a [`VoteRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoteResponse {
+    pub term: u64,
+    pub granted: bool,
+}
+
+/// The leader replicating its log, or just asserting its leadership if
+/// there are no entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppendRequest {
+    pub term: u64,
+    pub leader: u64,
+    /// Index and term of the entry just before `entries`
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<Entry>,
+    pub leader_commit: u64,
+}
+
+/// A node's answer to an [`AppendRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendResponse {
+    pub term: u64,
+    pub success: bool,
+    /// On success the index up to which the log matches the leader's; on
+    /// failure an index the leader may retry from the entry after
+    pub match_index: u64,
+}
+
+/// The leader replacing a follower's state with a snapshot of its own,
+/// whose last entry is `index` of `last_term`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotRequest {
+    pub term: u64,
+    pub leader: u64,
+    pub index: u64,
+    pub last_term: u64,
+}
+
+/// A node's answer to a [`SnapshotRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotResponse {
+    pub term: u64,
+    /// The index up to which the log matches the leader's, the snapshot's
+    /// last
+    pub match_index: u64,
+}
+
+/// A request for another node of the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Vote(VoteRequest),
+    Append(AppendRequest),
+    /// Send the node a snapshot of the state this leader of `term` has
+    /// applied, as it needs entries compacted away; see
+    /// [`RaftCore::handle_snapshot`]
+    Snapshot {
+        term: u64,
+    },
+}
+
+/// Where a follower's log stands, as the leader knows it
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    /// Index of the next entry to send
+    next: u64,
+    /// Highest index known to match the leader's log
+    matched: u64,
+    /// Whether an append was sent and not answered yet; heartbeats are
+    /// sent regardless
+    in_flight: bool,
+    /// Whether a snapshot is being sent, which appends wait for
+    snapshot: bool,
+    /// Ticks to wait before sending another snapshot after one failed
+    snapshot_wait: u32,
+}
+
+/// Timing of a node, in ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Ticks without a leader before a node starts an election; each
+    /// election waits a random number of ticks from this to twice it
+    pub election_ticks: u32,
+    /// Ticks between a leader's heartbeats, well below `election_ticks`
+    pub heartbeat_ticks: u32,
+}
+
+/// A snapshot of a node's state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeStatus {
+    pub id: u64,
+    pub term: u64,
+    pub role: Role,
+    /// The leader of the current term, if the node knows it
+    pub leader: Option<u64>,
+    pub commit_index: u64,
+    pub last_index: u64,
+    pub snapshot_index: u64,
+}
+
+/// One node's Raft state machine, see the [module documentation](self)
+pub struct RaftCore<S> {
+    id: u64,
+    /// The other nodes of the cluster
+    peers: Vec<u64>,
+    store: S,
+    state: HardState,
+    role: Role,
+    leader: Option<u64>,
+    commit: u64,
+    timing: Timing,
+    /// Ticks since the election timer was reset, or since the last
+    /// heartbeat of a leader
+    elapsed: u32,
+    /// Ticks the current election timer runs for
+    timeout: u32,
+    /// Nodes that voted for this candidate in the current term
+    votes: BTreeSet<u64>,
+    /// The followers' logs, kept by a leader
+    progress: BTreeMap<u64, Progress>,
+    /// Requests waiting to be sent, with the node each is for
+    outbox: Vec<(u64, Request)>,
+    rng: StdRng,
+}
+
+impl<S: LogStore> RaftCore<S> {
+    /// Restarts node `id` of a cluster with `peers` from its store
+    ///
+    /// Entries up to `applied` were committed before, so the node starts
+    /// with them committed. `seed` randomizes the election timeouts.
+    pub fn new(
+        id: u64,
+        peers: Vec<u64>,
+        store: S,
+        timing: Timing,
+        applied: u64,
+        seed: u64,
+    ) -> Self {
+        let state = store.hard_state();
+        let mut core = Self {
+            id,
+            peers,
+            store,
+            state,
+            role: Role::Follower,
+            leader: None,
+            commit: applied,
+            timing,
+            elapsed: 0,
+            timeout: 0,
+            votes: BTreeSet::new(),
+            progress: BTreeMap::new(),
+            outbox: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+        };
+        core.reset_election_timer();
+        core
+    }
+
+    /// Returns the node's state
+    pub fn status(&self) -> NodeStatus {
+        NodeStatus {
+            id: self.id,
+            term: self.state.term,
+            role: self.role,
+            leader: self.leader,
+            commit_index: self.commit,
+            last_index: self.store.last_index(),
+            snapshot_index: self.store.snapshot_index(),
+        }
+    }
+
+    /// Returns the store holding the log
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the requests queued since the last call
+    pub fn take_outbox(&mut self) -> Vec<(u64, Request)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Advances the timers by one tick, starting an election or sending
+    /// heartbeats when due
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save a new term.
+    pub fn tick(&mut self) -> Result<()> {
+        self.elapsed += 1;
+        match self.role {
+            Role::Leader => {
+                for progress in self.progress.values_mut() {
+                    progress.snapshot_wait = progress.snapshot_wait.saturating_sub(1);
+                }
+                if self.elapsed >= self.timing.heartbeat_ticks {
+                    self.elapsed = 0;
+                    for peer in self.peers.clone() {
+                        self.send_append(peer, true)?;
+                    }
+                }
+                Ok(())
+            }
+            Role::Follower | Role::Candidate if self.elapsed >= self.timeout => self.campaign(),
+            Role::Follower | Role::Candidate => Ok(()),
+        }
+    }
+
+    /// Appends a command to the log if the node is the leader, returning
+    /// its index and term
+    ///
+    /// The command is committed once the commit index reaches the index
+    /// while the entry there still has the term; an entry of another term
+    /// there means the command was dropped. Returns `Ok(None)` if the node
+    /// isn't the leader, see [`NodeStatus::leader`] for the one to ask.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to append the entry.
+    pub fn propose(&mut self, data: Vec<u8>) -> Result<Option<(u64, u64)>> {
+        if self.role != Role::Leader {
+            return Ok(None);
+        }
+        let entry = Entry {
+            index: self.store.last_index() + 1,
+            term: self.state.term,
+            data,
+        };
+        let position = (entry.index, entry.term);
+        self.store.append(&[entry])?;
+        self.advance_commit();
+        for peer in self.peers.clone() {
+            self.send_append(peer, false)?;
+        }
+        Ok(Some(position))
+    }
+
+    /// Answers a candidate's request for a vote
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save the vote.
+    pub fn handle_vote_request(&mut self, request: &VoteRequest) -> Result<VoteResponse> {
+        if request.term > self.state.term {
+            self.become_follower(request.term, None)?;
+        }
+        let last_index = self.store.last_index();
+        let last_term = self.last_term();
+        let up_to_date = (request.last_log_term, request.last_log_index) >= (last_term, last_index);
+        let can_vote =
+            self.state.voted_for.is_none() || self.state.voted_for == Some(request.candidate);
+        let granted = request.term == self.state.term && can_vote && up_to_date;
+        if granted && self.state.voted_for.is_none() {
+            self.state.voted_for = Some(request.candidate);
+            self.store.save_hard_state(self.state)?;
+        }
+        if granted {
+            self.reset_election_timer();
+        }
+        Ok(VoteResponse {
+            term: self.state.term,
+            granted,
+        })
+    }
+
+    /// Counts the answer of `from` to this node's request for a vote
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save a newer term, or to
+    /// append the entry the node starts its leadership with.
+    pub fn handle_vote_response(&mut self, from: u64, response: VoteResponse) -> Result<()> {
+        if response.term > self.state.term {
+            return self.become_follower(response.term, None);
+        }
+        if self.role != Role::Candidate || response.term != self.state.term || !response.granted {
+            return Ok(());
+        }
+        self.votes.insert(from);
+        if self.votes.len() >= self.quorum() {
+            self.become_leader()?;
+        }
+        Ok(())
+    }
+
+    /// Answers the leader's request to append entries
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save a newer term or the
+    /// entries.
+    pub fn handle_append_request(&mut self, request: &AppendRequest) -> Result<AppendResponse> {
+        let reject = |term, match_index| AppendResponse {
+            term,
+            success: false,
+            match_index,
+        };
+        if request.term < self.state.term {
+            return Ok(reject(self.state.term, 0));
+        }
+        if request.term > self.state.term || self.role != Role::Follower {
+            self.become_follower(request.term, Some(request.leader))?;
+        }
+        self.leader = Some(request.leader);
+        self.reset_election_timer();
+
+        // Entries up to the snapshot are committed, so they match the
+        // leader's; it goes on from the commit index
+        if request.prev_log_index < self.store.snapshot_index() {
+            return Ok(AppendResponse {
+                term: self.state.term,
+                success: true,
+                match_index: self.commit,
+            });
+        }
+
+        // The log must hold the entry the new ones continue from
+        let last_index = self.store.last_index();
+        if request.prev_log_index > last_index {
+            return Ok(reject(self.state.term, last_index));
+        }
+        if self.store.term(request.prev_log_index) != Some(request.prev_log_term) {
+            return Ok(reject(self.state.term, request.prev_log_index - 1));
+        }
+
+        // Entries the log already has are kept; from the first that
+        // differs, the leader's replace the rest
+        let new = request
+            .entries
+            .iter()
+            .position(|entry| self.store.term(entry.index) != Some(entry.term));
+        if let Some(first) = new {
+            self.store.append(&request.entries[first..])?;
+        }
+
+        let matched = request.prev_log_index + request.entries.len() as u64;
+        // Only what is known to match the leader's log can be committed
+        let commit = request.leader_commit.min(matched);
+        if commit > self.commit {
+            self.commit = commit;
+        }
+        Ok(AppendResponse {
+            term: self.state.term,
+            success: true,
+            match_index: matched,
+        })
+    }
+
+    /// Takes in the answer of `from` to an append, advancing the commit
+    /// index or backing up to where the logs match
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save a newer term or to
+    /// read the entries to send next.
+    pub fn handle_append_response(&mut self, from: u64, response: AppendResponse) -> Result<()> {
+        if response.term > self.state.term {
+            return self.become_follower(response.term, None);
+        }
+        if self.role != Role::Leader || response.term != self.state.term {
+            return Ok(());
+        }
+        let Some(progress) = self.progress.get_mut(&from) else {
+            return Ok(());
+        };
+        progress.in_flight = false;
+        if response.success {
+            progress.matched = progress.matched.max(response.match_index);
+            progress.next = progress.matched + 1;
+            self.advance_commit();
+        } else {
+            // Never below what is known to match, in case answers to
+            // earlier appends arrive late
+            progress.next = (response.match_index + 1)
+                .min(progress.next.saturating_sub(1))
+                .max(progress.matched + 1);
+        }
+        if self.progress[&from].next <= self.store.last_index() {
+            self.send_append(from, false)?;
+        }
+        Ok(())
+    }
+
+    /// Notes that a request for `peer` couldn't be delivered, so the next
+    /// append isn't held back waiting for its answer
+    pub fn handle_unreachable(&mut self, peer: u64) {
+        if let Some(progress) = self.progress.get_mut(&peer) {
+            progress.in_flight = false;
+        }
+    }
+
+    /// Answers the leader's request to install a snapshot
+    ///
+    /// Unless the node already committed the snapshot's last entry,
+    /// `install` replaces the node's state with the snapshot, and the log
+    /// then starts from it, see [`LogStore::install_snapshot`]. The store
+    /// is passed along for `install` to record progress in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save a newer term or the
+    /// snapshot, or `install` fails.
+    pub fn handle_snapshot(
+        &mut self,
+        request: &SnapshotRequest,
+        install: impl FnOnce(&mut S) -> Result<()>,
+    ) -> Result<SnapshotResponse> {
+        if request.term < self.state.term {
+            return Ok(SnapshotResponse {
+                term: self.state.term,
+                match_index: 0,
+            });
+        }
+        if request.term > self.state.term || self.role != Role::Follower {
+            self.become_follower(request.term, Some(request.leader))?;
+        }
+        self.leader = Some(request.leader);
+        self.reset_election_timer();
+
+        if request.index > self.commit {
+            log::info!(
+                "Raft node {} installing a snapshot up to entry {}",
+                self.id,
+                request.index
+            );
+            install(&mut self.store)?;
+            self.store
+                .install_snapshot(request.index, request.last_term)?;
+            self.commit = request.index;
+        }
+        Ok(SnapshotResponse {
+            term: self.state.term,
+            match_index: request.index,
+        })
+    }
+
+    /// Takes in the answer of `from` to a snapshot, resuming appends
+    /// after it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to save a newer term or to
+    /// read the entries to send next.
+    pub fn handle_snapshot_response(
+        &mut self,
+        from: u64,
+        response: SnapshotResponse,
+    ) -> Result<()> {
+        if response.term > self.state.term {
+            return self.become_follower(response.term, None);
+        }
+        if self.role != Role::Leader || response.term != self.state.term {
+            return Ok(());
+        }
+        let Some(progress) = self.progress.get_mut(&from) else {
+            return Ok(());
+        };
+        progress.snapshot = false;
+        progress.matched = progress.matched.max(response.match_index);
+        progress.next = progress.matched + 1;
+        self.advance_commit();
+        self.send_append(from, false)
+    }
+
+    /// Notes that a snapshot couldn't be sent to `peer`, waiting an
+    /// election timeout before trying again
+    pub fn handle_snapshot_failure(&mut self, peer: u64) {
+        if let Some(progress) = self.progress.get_mut(&peer) {
+            progress.snapshot = false;
+            progress.snapshot_wait = self.timing.election_ticks;
+        }
+    }
+
+    /// Discards the log up to `index`, which the caller applied
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store fails to compact the log.
+    pub fn compact(&mut self, index: u64) -> Result<()> {
+        if index <= self.store.snapshot_index() {
+            return Ok(());
+        }
+        self.store.compact(index.min(self.commit))
+    }
+
+    /// Returns how many nodes, this one included, make a majority
+    fn quorum(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    fn last_term(&self) -> u64 {
+        self.store.term(self.store.last_index()).unwrap_or(0)
+    }
+
+    fn reset_election_timer(&mut self) {
+        let ticks = self.timing.election_ticks.max(1);
+        self.elapsed = 0;
+        self.timeout = self.rng.random_range(ticks..ticks * 2);
+    }
+
+    /// Starts an election in the next term
+    fn campaign(&mut self) -> Result<()> {
+        self.state = HardState {
+            term: self.state.term + 1,
+            voted_for: Some(self.id),
+        };
+        self.store.save_hard_state(self.state)?;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.votes = BTreeSet::from([self.id]);
+        self.progress.clear();
+        self.reset_election_timer();
+        log::info!(
+            "Raft node {} starting an election in term {}",
+            self.id,
+            self.state.term
+        );
+        if self.votes.len() >= self.quorum() {
+            return self.become_leader();
+        }
+        let request = VoteRequest {
+            term: self.state.term,
+            candidate: self.id,
+            last_log_index: self.store.last_index(),
+            last_log_term: self.last_term(),
+        };
+        for &peer in &self.peers {
+            self.outbox.push((peer, Request::Vote(request.clone())));
+        }
+        Ok(())
+    }
+
+    /// Follows `leader`, if known, in `term`
+    fn become_follower(&mut self, term: u64, leader: Option<u64>) -> Result<()> {
+        if term > self.state.term {
+            self.state = HardState {
+                term,
+                voted_for: None,
+            };
+            self.store.save_hard_state(self.state)?;
+        }
+        if self.role == Role::Leader {
+            log::info!(
+                "Raft node {} stepping down in term {}",
+                self.id,
+                self.state.term
+            );
+        }
+        // A follower's timer only restarts on hearing from the leader or
+        // granting a vote, so candidates that can't win don't hold off
+        // those that can
+        if self.role != Role::Follower {
+            self.reset_election_timer();
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.votes.clear();
+        self.progress.clear();
+        Ok(())
+    }
+
+    /// Takes over the term's leadership, starting it with an empty entry
+    fn become_leader(&mut self) -> Result<()> {
+        log::info!(
+            "Raft node {} became the leader of term {}",
+            self.id,
+            self.state.term
+        );
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.elapsed = 0;
+        let next = self.store.last_index() + 1;
+        self.progress = self
+            .peers
+            .iter()
+            .map(|&peer| {
+                let progress = Progress {
+                    next,
+                    matched: 0,
+                    in_flight: false,
+                    snapshot: false,
+                    snapshot_wait: 0,
+                };
+                (peer, progress)
+            })
+            .collect();
+        // Entries of earlier terms only commit along with one of this term
+        self.propose(Vec::new())?;
+        Ok(())
+    }
+
+    /// Moves the commit index to the newest entry of this term a majority
+    /// has
+    fn advance_commit(&mut self) {
+        let mut matched: Vec<u64> = self
+            .progress
+            .values()
+            .map(|progress| progress.matched)
+            .chain([self.store.last_index()])
+            .collect();
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let majority = matched[self.quorum() - 1];
+        if majority > self.commit && self.store.term(majority) == Some(self.state.term) {
+            self.commit = majority;
+        }
+    }
+
+    /// Queues an append for `peer` with the entries it lacks, unless one
+    /// is still unanswered; a heartbeat goes out regardless
+    ///
+    /// If the entries were compacted away, a snapshot is queued instead,
+    /// and heartbeats go out without entries until it is installed.
+    fn send_append(&mut self, peer: u64, heartbeat: bool) -> Result<()> {
+        let Some(progress) = self.progress.get(&peer).copied() else {
+            return Ok(());
+        };
+        let compacted = progress.next <= self.store.snapshot_index();
+        if compacted && !progress.snapshot && progress.snapshot_wait == 0 {
+            let progress = self
+                .progress
+                .get_mut(&peer)
+                .expect("progress looked up above");
+            progress.snapshot = true;
+            let term = self.state.term;
+            self.outbox.push((peer, Request::Snapshot { term }));
+            return Ok(());
+        }
+        if (progress.in_flight || compacted) && !heartbeat {
+            return Ok(());
+        }
+        let last_index = self.store.last_index();
+        let mut entries = if !compacted && progress.next <= last_index {
+            self.store
+                .entries(progress.next, last_index, MAX_APPEND_BYTES)?
+        } else {
+            Vec::new()
+        };
+        entries.truncate(MAX_ENTRIES_PER_APPEND);
+        let prev_log_index = progress.next - 1;
+        let request = AppendRequest {
+            term: self.state.term,
+            leader: self.id,
+            prev_log_index,
+            prev_log_term: self.store.term(prev_log_index).unwrap_or(0),
+            entries,
+            leader_commit: self.commit,
+        };
+        self.progress
+            .get_mut(&peer)
+            .expect("progress looked up above")
+            .in_flight = true;
+        self.outbox.push((peer, Request::Append(request)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps everything in memory, as a store that survives restarts
+    #[derive(Clone, Default)]
+    struct MemStore {
+        state: HardState,
+        /// Index and term of the snapshot's last entry
+        snapshot: (u64, u64),
+        /// The commands of the snapshot, standing in for the state they
+        /// built
+        compacted: Vec<Vec<u8>>,
+        /// The entries after the snapshot
+        log: Vec<Entry>,
+    }
+
+    impl MemStore {
+        /// Returns the position of `index` in `log`
+        fn offset(&self, index: u64) -> usize {
+            (index - self.snapshot.0 - 1) as usize
+        }
+    }
+
+    impl LogStore for MemStore {
+        fn hard_state(&self) -> HardState {
+            self.state
+        }
+
+        fn save_hard_state(&mut self, state: HardState) -> Result<()> {
+            self.state = state;
+            Ok(())
+        }
+
+        fn last_index(&self) -> u64 {
+            self.snapshot.0 + self.log.len() as u64
+        }
+
+        fn snapshot_index(&self) -> u64 {
+            self.snapshot.0
+        }
+
+        fn term(&self, index: u64) -> Option<u64> {
+            match index {
+                _ if index == self.snapshot.0 => Some(self.snapshot.1),
+                _ if index < self.snapshot.0 => None,
+                _ => self.log.get(self.offset(index)).map(|entry| entry.term),
+            }
+        }
+
+        fn entries(&self, from: u64, to: u64, _max_bytes: usize) -> Result<Vec<Entry>> {
+            Ok(self.log[self.offset(from)..=self.offset(to)].to_vec())
+        }
+
+        fn append(&mut self, entries: &[Entry]) -> Result<()> {
+            self.log.truncate(self.offset(entries[0].index));
+            self.log.extend_from_slice(entries);
+            Ok(())
+        }
+
+        fn compact(&mut self, index: u64) -> Result<()> {
+            let term = self.term(index).unwrap();
+            let compacted = self.log.drain(..=self.offset(index));
+            self.compacted.extend(
+                compacted
+                    .filter(|entry| !entry.data.is_empty())
+                    .map(|entry| entry.data),
+            );
+            self.snapshot = (index, term);
+            Ok(())
+        }
+
+        fn install_snapshot(&mut self, index: u64, term: u64) -> Result<()> {
+            if self.term(index) == Some(term) {
+                self.log.drain(..=self.offset(index));
+            } else {
+                self.log.clear();
+            }
+            self.snapshot = (index, term);
+            Ok(())
+        }
+    }
+
+    const TIMING: Timing = Timing {
+        election_ticks: 10,
+        heartbeat_ticks: 3,
+    };
+
+    /// Nodes 1 to `n`, exchanging requests and responses in memory
+    struct Cluster {
+        nodes: BTreeMap<u64, RaftCore<MemStore>>,
+        /// Nodes cut off from all others
+        isolated: BTreeSet<u64>,
+    }
+
+    impl Cluster {
+        fn new(n: u64) -> Self {
+            let nodes = (1..=n)
+                .map(|id| {
+                    let peers = (1..=n).filter(|&peer| peer != id).collect();
+                    let core = RaftCore::new(id, peers, MemStore::default(), TIMING, 0, id);
+                    (id, core)
+                })
+                .collect();
+            Self {
+                nodes,
+                isolated: BTreeSet::new(),
+            }
+        }
+
+        fn node(&mut self, id: u64) -> &mut RaftCore<MemStore> {
+            self.nodes.get_mut(&id).unwrap()
+        }
+
+        fn connected(&self, a: u64, b: u64) -> bool {
+            !self.isolated.contains(&a) && !self.isolated.contains(&b)
+        }
+
+        /// Delivers requests and their responses until none are left
+        fn deliver(&mut self) {
+            loop {
+                let mut sent = Vec::new();
+                for (&from, node) in &mut self.nodes {
+                    sent.extend(node.take_outbox().into_iter().map(|(to, r)| (from, to, r)));
+                }
+                if sent.is_empty() {
+                    return;
+                }
+                for (from, to, request) in sent {
+                    if !self.connected(from, to) {
+                        match request {
+                            Request::Snapshot { .. } => self.node(from).handle_snapshot_failure(to),
+                            _ => self.node(from).handle_unreachable(to),
+                        }
+                        continue;
+                    }
+                    match request {
+                        Request::Vote(request) => {
+                            let response = self.node(to).handle_vote_request(&request).unwrap();
+                            self.node(from).handle_vote_response(to, response).unwrap();
+                        }
+                        Request::Append(request) => {
+                            let response = self.node(to).handle_append_request(&request).unwrap();
+                            self.node(from)
+                                .handle_append_response(to, response)
+                                .unwrap();
+                        }
+                        Request::Snapshot { term } => {
+                            // The leader's state up to its commit index
+                            let leader = self.node(from);
+                            let index = leader.commit;
+                            leader.compact(index).unwrap();
+                            let request = SnapshotRequest {
+                                term,
+                                leader: from,
+                                index,
+                                last_term: leader.store.snapshot.1,
+                            };
+                            let state = leader.store.compacted.clone();
+                            let response = self
+                                .node(to)
+                                .handle_snapshot(&request, |store| {
+                                    store.compacted = state;
+                                    Ok(())
+                                })
+                                .unwrap();
+                            self.node(from)
+                                .handle_snapshot_response(to, response)
+                                .unwrap();
+                        }
+                    }
+                }
+            }
+        }
+
+        /// Ticks every node and delivers the traffic, `ticks` times
+        fn run(&mut self, ticks: usize) {
+            for _ in 0..ticks {
+                for node in self.nodes.values_mut() {
+                    node.tick().unwrap();
+                }
+                self.deliver();
+            }
+        }
+
+        /// Returns the leaders, by the nodes' own account
+        fn leaders(&self) -> Vec<u64> {
+            self.nodes
+                .values()
+                .filter(|node| node.role == Role::Leader)
+                .map(|node| node.id)
+                .collect()
+        }
+
+        /// Runs until a single node reachable from a majority leads the
+        /// newest term among them; a leader cut off before may still
+        /// believe it leads an older one
+        fn elect(&mut self) -> u64 {
+            for _ in 0..100 {
+                self.run(1);
+                let connected = |node: &&RaftCore<MemStore>| !self.isolated.contains(&node.id);
+                let term = self
+                    .nodes
+                    .values()
+                    .filter(connected)
+                    .map(|node| node.state.term)
+                    .max();
+                let leaders: Vec<_> = self
+                    .nodes
+                    .values()
+                    .filter(connected)
+                    .filter(|node| node.role == Role::Leader && Some(node.state.term) == term)
+                    .map(|node| node.id)
+                    .collect();
+                if let [leader] = leaders[..] {
+                    return leader;
+                }
+            }
+            panic!("no leader elected");
+        }
+
+        fn propose(&mut self, id: u64, data: &[u8]) -> (u64, u64) {
+            let position = self.node(id).propose(data.to_vec()).unwrap().unwrap();
+            self.deliver();
+            position
+        }
+
+        /// Returns the commands of a node's snapshot and log, without the
+        /// empty entries
+        fn commands(&self, id: u64) -> Vec<Vec<u8>> {
+            let store = &self.nodes[&id].store;
+            let logged = store
+                .log
+                .iter()
+                .filter(|entry| !entry.data.is_empty())
+                .map(|entry| entry.data.clone());
+            store.compacted.iter().cloned().chain(logged).collect()
+        }
+    }
+
+    #[test]
+    fn test_a_single_leader_is_elected() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        cluster.run(50);
+
+        // The heartbeats keep the others from starting elections
+        assert_eq!(cluster.leaders(), vec![leader]);
+        let term = cluster.nodes[&leader].state.term;
+        for node in cluster.nodes.values() {
+            assert_eq!(node.state.term, term);
+            assert_eq!(node.leader, Some(leader));
+        }
+        // The leader's empty entry is committed everywhere
+        for node in cluster.nodes.values() {
+            assert_eq!(node.commit, 1);
+        }
+    }
+
+    #[test]
+    fn test_a_lone_node_leads_itself() {
+        let mut cluster = Cluster::new(1);
+        let leader = cluster.elect();
+        let (index, _) = cluster.propose(leader, b"a");
+        assert_eq!(cluster.nodes[&leader].commit, index);
+    }
+
+    #[test]
+    fn test_entries_commit_once_a_majority_has_them() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        let follower = (1..=3).find(|&id| id != leader).unwrap();
+        cluster.isolated.insert(follower);
+
+        // One follower is enough for a majority of three
+        let (index, term) = cluster.propose(leader, b"a");
+        assert_eq!(cluster.nodes[&leader].commit, index);
+        assert_eq!(cluster.nodes[&leader].store.term(index), Some(term));
+
+        // The isolated follower catches up once it is reachable again
+        cluster.isolated.clear();
+        cluster.run(TIMING.heartbeat_ticks as usize);
+        assert_eq!(cluster.nodes[&follower].commit, index);
+        assert_eq!(cluster.commands(follower), vec![b"a".to_vec()]);
+    }
+
+    #[test]
+    fn test_a_leader_without_a_majority_commits_nothing() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        let committed = cluster.nodes[&leader].commit;
+        for id in 1..=3 {
+            if id != leader {
+                cluster.isolated.insert(id);
+            }
+        }
+
+        let (index, _) = cluster.propose(leader, b"lost");
+        cluster.run(5);
+        assert_eq!(cluster.nodes[&leader].commit, committed);
+        assert!(index > committed);
+    }
+
+    #[test]
+    fn test_a_new_leader_takes_over_with_every_committed_entry() {
+        let mut cluster = Cluster::new(3);
+        let old = cluster.elect();
+        cluster.propose(old, b"a");
+        cluster.propose(old, b"b");
+
+        cluster.isolated.insert(old);
+        let new = cluster.elect();
+        assert_ne!(new, old);
+        assert!(cluster.nodes[&new].state.term > cluster.nodes[&old].state.term);
+        assert_eq!(cluster.commands(new), vec![b"a".to_vec(), b"b".to_vec()]);
+        let (index, _) = cluster.propose(new, b"c");
+        assert_eq!(cluster.nodes[&new].commit, index);
+
+        // The old leader steps down on hearing from the new one
+        cluster.isolated.clear();
+        cluster.run(TIMING.heartbeat_ticks as usize);
+        assert_eq!(cluster.leaders(), vec![new]);
+        assert_eq!(cluster.nodes[&old].commit, index);
+        assert_eq!(
+            cluster.commands(old),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_uncommitted_entries_of_a_deposed_leader_are_replaced() {
+        let mut cluster = Cluster::new(3);
+        let old = cluster.elect();
+        cluster.propose(old, b"a");
+
+        // Appended by the old leader alone, so never committed
+        cluster.isolated.insert(old);
+        let (lost, lost_term) = cluster.propose(old, b"lost");
+        let new = cluster.elect();
+        let (index, term) = cluster.propose(new, b"b");
+
+        cluster.isolated.clear();
+        cluster.run(TIMING.heartbeat_ticks as usize);
+        for id in 1..=3 {
+            assert_eq!(cluster.commands(id), vec![b"a".to_vec(), b"b".to_vec()]);
+            assert_eq!(cluster.nodes[&id].store.term(index), Some(term));
+            assert_ne!(cluster.nodes[&id].store.term(lost), Some(lost_term));
+        }
+    }
+
+    #[test]
+    fn test_votes_go_only_to_candidates_with_up_to_date_logs() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        let stale = (1..=3).find(|&id| id != leader).unwrap();
+        cluster.isolated.insert(stale);
+        cluster.propose(leader, b"a");
+        let current = cluster.nodes[&leader].state.term;
+
+        let request = VoteRequest {
+            term: current + 1,
+            candidate: stale,
+            last_log_index: cluster.nodes[&stale].store.last_index(),
+            last_log_term: cluster.nodes[&stale].last_term(),
+        };
+        let response = cluster.node(leader).handle_vote_request(&request).unwrap();
+        assert!(!response.granted);
+        // The newer term still deposes the leader
+        assert_eq!(response.term, current + 1);
+        assert_eq!(cluster.nodes[&leader].role, Role::Follower);
+
+        // A candidate with the whole log gets the vote, once per term
+        let mut request = VoteRequest {
+            term: current + 1,
+            candidate: 99,
+            last_log_index: cluster.nodes[&leader].store.last_index(),
+            last_log_term: current,
+        };
+        assert!(
+            cluster
+                .node(leader)
+                .handle_vote_request(&request)
+                .unwrap()
+                .granted
+        );
+        assert!(
+            cluster
+                .node(leader)
+                .handle_vote_request(&request)
+                .unwrap()
+                .granted
+        );
+        request.candidate = 98;
+        assert!(
+            !cluster
+                .node(leader)
+                .handle_vote_request(&request)
+                .unwrap()
+                .granted
+        );
+    }
+
+    #[test]
+    fn test_a_restarted_node_keeps_its_term_vote_and_log() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        cluster.propose(leader, b"a");
+        let follower = (1..=3).find(|&id| id != leader).unwrap();
+        let before = cluster.nodes[&follower].status();
+
+        let store = cluster.nodes[&follower].store.clone();
+        let peers = cluster.nodes[&follower].peers.clone();
+        let restarted = RaftCore::new(follower, peers, store, TIMING, before.commit_index, 7);
+        let after = restarted.status();
+        assert_eq!(after.term, before.term);
+        assert_eq!(after.last_index, before.last_index);
+        assert_eq!(after.commit_index, before.commit_index);
+        assert_eq!(after.role, Role::Follower);
+        assert_eq!(
+            restarted.state.voted_for,
+            cluster.nodes[&follower].state.voted_for
+        );
+
+        // Having voted in the term, it doesn't vote for anyone else
+        cluster.nodes.insert(follower, restarted);
+        let voted = cluster.nodes[&follower].state.voted_for;
+        let request = VoteRequest {
+            term: before.term,
+            candidate: 99,
+            last_log_index: u64::MAX,
+            last_log_term: u64::MAX,
+        };
+        let granted = cluster
+            .node(follower)
+            .handle_vote_request(&request)
+            .unwrap()
+            .granted;
+        assert_eq!(granted, voted.is_none());
+    }
+
+    #[test]
+    fn test_elections_settle_despite_failures() {
+        let mut cluster = Cluster::new(5);
+        let mut committed = Vec::new();
+        for round in 0..10u8 {
+            let leader = cluster.elect();
+            let (index, _) = cluster.propose(leader, &[round]);
+            assert_eq!(cluster.nodes[&leader].commit, index);
+            committed.push(vec![round]);
+            // Cut off the leader and one other node, leaving a majority
+            cluster.isolated = BTreeSet::from([leader, leader % 5 + 1]);
+        }
+        cluster.isolated.clear();
+        cluster.run(TIMING.election_ticks as usize * 4);
+        assert_eq!(cluster.leaders().len(), 1);
+        for id in 1..=5 {
+            assert_eq!(cluster.commands(id), committed);
+        }
+    }
+
+    #[test]
+    fn test_a_follower_behind_the_compacted_log_installs_a_snapshot() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        let follower = (1..=3).find(|&id| id != leader).unwrap();
+        cluster.propose(leader, b"a");
+        cluster.isolated.insert(follower);
+        cluster.propose(leader, b"b");
+        let (index, _) = cluster.propose(leader, b"c");
+
+        // The follower lacks entries the leader no longer has
+        cluster.node(leader).compact(index - 1).unwrap();
+        assert_eq!(cluster.nodes[&leader].store.snapshot_index(), index - 1);
+        assert_eq!(cluster.nodes[&leader].store.term(index - 2), None);
+        assert_eq!(
+            cluster.commands(leader),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+
+        cluster.isolated.clear();
+        cluster.run(TIMING.heartbeat_ticks as usize);
+        let store = &cluster.nodes[&follower].store;
+        assert_eq!(store.snapshot_index(), index);
+        assert_eq!(cluster.nodes[&follower].commit, index);
+        assert_eq!(
+            cluster.commands(follower),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+
+        // Appends continue after the snapshot
+        let (next, _) = cluster.propose(leader, b"d");
+        cluster.run(TIMING.heartbeat_ticks as usize);
+        assert_eq!(cluster.nodes[&follower].commit, next);
+        assert_eq!(cluster.commands(follower).len(), 4);
+    }
+
+    #[test]
+    fn test_a_failed_snapshot_is_retried_after_an_election_timeout() {
+        let mut cluster = Cluster::new(3);
+        let leader = cluster.elect();
+        let follower = (1..=3).find(|&id| id != leader).unwrap();
+        cluster.isolated.insert(follower);
+        let (index, _) = cluster.propose(leader, b"a");
+        cluster.node(leader).compact(index).unwrap();
+
+        // Sending the snapshot fails while the follower is cut off
+        cluster.node(leader).send_append(follower, true).unwrap();
+        let outbox = cluster.node(leader).take_outbox();
+        assert_eq!(outbox.len(), 1);
+        assert!(matches!(outbox[0], (to, Request::Snapshot { .. }) if to == follower));
+        cluster.node(leader).handle_snapshot_failure(follower);
+
+        // Heartbeats still go out, but no other snapshot until the wait
+        // is over
+        for _ in 0..TIMING.election_ticks - 1 {
+            cluster.node(leader).tick().unwrap();
+            let outbox = cluster.node(leader).take_outbox();
+            assert!(outbox
+                .iter()
+                .all(|(_, request)| matches!(request, Request::Append(_))));
+        }
+        cluster.isolated.clear();
+        cluster.run(TIMING.heartbeat_ticks as usize * 2);
+        assert_eq!(cluster.nodes[&follower].store.snapshot_index(), index);
+        assert_eq!(cluster.commands(follower), vec![b"a".to_vec()]);
+    }
+
+    #[test]
+    fn test_installing_a_snapshot_keeps_the_entries_continuing_it() {
+        let mut store = MemStore::default();
+        let entries: Vec<_> = (1..=4)
+            .map(|index| Entry {
+                index,
+                term: 1,
+                data: vec![index as u8],
+            })
+            .collect();
+        store.append(&entries).unwrap();
+        store.install_snapshot(2, 1).unwrap();
+        assert_eq!(store.snapshot_index(), 2);
+        assert_eq!(store.last_index(), 4);
+        assert_eq!(store.term(2), Some(1));
+        assert_eq!(store.term(1), None);
+
+        // A snapshot at an entry of another term replaces the whole log
+        store.install_snapshot(3, 2).unwrap();
+        assert_eq!(store.last_index(), 3);
+        assert_eq!(store.term(3), Some(2));
+    }
+}
@@ -0,0 +1,956 @@
+//! Raft consensus for the writes of a cluster of servers
+//!
+//! With a `[raft]` section a server is one node of a fixed cluster. Every
+//! write is encoded into an entry of a replicated log: the leader appends
+//! it, sends it to the others over the `Raft` service, and once a majority
+//! has it the entry is committed and each node applies it to its engine,
+//! in log order:
+//!
+//! ```text
+//!   client ──write──▶ leader ──AppendEntries──▶ followers
+//!                       │  log in the "raft" column family  │
+//!                       ▼                                   ▼
+//!                    applier ──WriteBatch──▶ engine      applier
+//! ```
+//!
+//! A write returns once it is committed and applied on the leader, so it
+//! survives the loss of any minority of the nodes, and writes are
+//! linearizable: each takes effect in log order, between its call and its
+//! answer. Since a majority has the log synced before a write returns,
+//! every write is durable, whatever its `sync` flag. Followers reject
+//! writes with `FAILED_PRECONDITION` naming the leader's address, or
+//! `UNAVAILABLE` while they know of no leader. Reads are served from each
+//! server's own engine, so a follower may not see the newest writes yet.
+//!
+//! Each entry names the column families it writes to. A write is only
+//! proposed if the leader has its families, and a node applying an entry
+//! creates those it lacks, so families made outside the log, such as by a
+//! tool run on the leader's data directory, reach every node. The log's
+//! own family, `raft`, is out of reach of clients.
+//!
+//! Once `max_log_entries` entries past the last snapshot are applied, a
+//! node compacts its log down to that many, the snapshot standing in for
+//! the rest. A follower needing entries the leader compacted away, such as
+//! one that lost its data directory, is sent a
+//! [snapshot](snapshot) of the leader's engine instead, then catches up
+//! from there.
+//!
+//! An entry the engine fails to write is retried, backing off, as long as
+//! the failure may be this node's alone; after `MAX_APPLY_ATTEMPTS` the
+//! node stops for good, reporting the error in `GetRaftStatus` and
+//! answering every request with `UNAVAILABLE`, until it is restarted.
+//!
+//! The protocol lives in a `RaftCore`, free of I/O, which a [`RaftNode`]
+//! drives: a ticker advances its timers, the `Raft` service hands it the requests of
+//! the other nodes, and the requests it queues go out on tasks of their
+//! own. The membership is fixed by the configuration.
+
+mod core;
+mod snapshot;
+mod store;
+
+pub use self::core::{NodeStatus, Role};
+pub use self::store::COLUMN_FAMILY;
+
+use self::core::{
+    AppendRequest, AppendResponse, Entry, LogStore, RaftCore, Request, SnapshotRequest,
+    SnapshotResponse, Timing, VoteRequest, VoteResponse,
+};
+use self::store::EngineLogStore;
+use crate::config::RaftSettings;
+use crate::proto::raft_client::RaftClient;
+use crate::proto::raft_server::Raft;
+use crate::proto::{
+    AppendEntriesRequest, AppendEntriesResponse, BatchWriteRequest, GetRaftStatusRequest,
+    GetRaftStatusResponse, InstallSnapshotRequest, InstallSnapshotResponse, Mutation, RaftEntry,
+    RequestVoteRequest, RequestVoteResponse,
+};
+use crate::service::{status_from_error, write_batch};
+use crate::{Error, Result};
+use ferrisdb_core::error::ErrorCode;
+use ferrisdb_storage::{ColumnFamily, ColumnFamilyOptions, StorageEngine, WriteBatch};
+
+use prost::Message;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tonic::{Status, Streaming};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often a node's timers advance
+pub const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a write waits to be committed before failing
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the applier first waits before retrying after the engine
+/// failed, doubling with each failure up to `MAX_APPLY_RETRY_INTERVAL`
+const APPLY_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+const MAX_APPLY_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Times the applier tries to apply an entry before the node stops
+const MAX_APPLY_ATTEMPTS: u32 = 10;
+
+/// Snapshot chunks read ahead of the stream sending them
+const SNAPSHOT_CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Bytes of entries read from the log at a time for applying
+const MAX_APPLY_BYTES: usize = 4 * 1024 * 1024;
+
+type PeerClient = RaftClient<InterceptedService<Channel, Bearer>>;
+
+/// Adds the replication token to requests for other nodes
+#[derive(Clone)]
+struct Bearer(MetadataValue<Ascii>);
+
+impl Interceptor for Bearer {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> std::result::Result<tonic::Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert("authorization", self.0.clone());
+        Ok(request)
+    }
+}
+
+/// A write waiting for its entry to be applied
+struct Waiter {
+    /// Term the entry was proposed in; another term at its index means
+    /// the write was dropped
+    term: u64,
+    done: oneshot::Sender<std::result::Result<(), Status>>,
+}
+
+/// The result of a request to another node
+enum Reply {
+    Vote(VoteResponse),
+    Append(AppendResponse),
+    Snapshot(SnapshotResponse),
+}
+
+/// One server's node of a Raft cluster, see the [module documentation](self)
+pub struct RaftNode {
+    id: u64,
+    engine: Arc<StorageEngine>,
+    core: Mutex<RaftCore<EngineLogStore>>,
+    /// Column family of the log, which applied entries are recorded in
+    log_cf: ColumnFamily,
+    /// The peers' addresses, without the timeout of `request_timeout`,
+    /// which snapshots aren't held to
+    peers: BTreeMap<u64, Endpoint>,
+    request_timeout: Duration,
+    /// Connections to the peers, made on first use
+    clients: Mutex<BTreeMap<u64, PeerClient>>,
+    authorization: Bearer,
+    /// Entries applied past the last snapshot before the log is compacted
+    /// down to this many
+    max_log_entries: u64,
+    /// Where snapshots are kept while they're sent or installed
+    snapshots: PathBuf,
+    /// Held while entries are applied or a snapshot is taken or
+    /// installed, before `core`
+    applying: Mutex<()>,
+    /// Held while a snapshot is received
+    receiving: tokio::sync::Mutex<()>,
+    /// Index of the last entry applied to the engine
+    applied: AtomicU64,
+    /// Writes waiting for their entries, by index
+    waiters: Mutex<BTreeMap<u64, Waiter>>,
+    /// Wakes the applier when the commit index moves
+    commits: Notify,
+    stopped: AtomicBool,
+    /// Why the node stopped applying entries, after which it answers no
+    /// request
+    apply_error: Mutex<Option<String>>,
+}
+
+impl RaftNode {
+    /// Loads the node of `settings` from `engine`, which holds its log
+    /// along with the data
+    ///
+    /// `token` is the replication token the nodes share.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if a peer's address or the token is
+    /// invalid or the CA file can't be read, `Error::Storage` if the log
+    /// can't be loaded or a snapshot whose install was cut short can't be
+    /// installed, or `Error::Io` if old snapshots can't be removed.
+    pub fn open(
+        engine: Arc<StorageEngine>,
+        settings: &RaftSettings,
+        token: &str,
+    ) -> Result<Arc<Self>> {
+        let ca = settings
+            .tls_ca_path
+            .as_ref()
+            .map(|path| {
+                std::fs::read(path)
+                    .map_err(|e| Error::Config(format!("Failed to read {}: {}", path.display(), e)))
+            })
+            .transpose()?;
+        let mut peers = BTreeMap::new();
+        for peer in &settings.peers {
+            let mut endpoint = Endpoint::from_shared(peer.addr.clone())
+                .map_err(|e| Error::Config(format!("Invalid Raft peer {:?}: {}", peer.addr, e)))?
+                .connect_timeout(settings.election_timeout());
+            if let Some(ca) = &ca {
+                endpoint = endpoint.tls_config(
+                    ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca.clone())),
+                )?;
+            }
+            peers.insert(peer.id, endpoint);
+        }
+        let authorization = format!("Bearer {}", token)
+            .parse()
+            .map_err(|_| Error::Config("Invalid replication token".to_string()))?;
+
+        let mut store = EngineLogStore::open(Arc::clone(&engine))?;
+        let snapshots = snapshot::directory(&engine);
+        if let Some((index, term)) = store.interrupted_install()? {
+            log::warn!(
+                "Resuming the install of a Raft snapshot up to entry {}",
+                index
+            );
+            snapshot::restore(&engine, &snapshots.join(snapshot::RECEIVED))?;
+            store.install_snapshot(index, term)?;
+        }
+        snapshot::remove_dir(&snapshots)?;
+        let log_cf = store.column_family().clone();
+        let applied = store.applied()?;
+        let ticks =
+            |interval: Duration| (interval.as_millis() / TICK_INTERVAL.as_millis()).max(1) as u32;
+        let timing = Timing {
+            election_ticks: ticks(settings.election_timeout()),
+            heartbeat_ticks: ticks(settings.heartbeat_interval()),
+        };
+        let core = RaftCore::new(
+            settings.node_id,
+            peers.keys().copied().collect(),
+            store,
+            timing,
+            applied,
+            rand::random(),
+        );
+        Ok(Arc::new(Self {
+            id: settings.node_id,
+            engine,
+            core: Mutex::new(core),
+            log_cf,
+            peers,
+            request_timeout: settings.election_timeout(),
+            clients: Mutex::new(BTreeMap::new()),
+            authorization: Bearer(authorization),
+            max_log_entries: settings.max_log_entries(),
+            snapshots,
+            applying: Mutex::new(()),
+            receiving: tokio::sync::Mutex::new(()),
+            applied: AtomicU64::new(applied),
+            waiters: Mutex::new(BTreeMap::new()),
+            commits: Notify::new(),
+            stopped: AtomicBool::new(false),
+            apply_error: Mutex::new(None),
+        }))
+    }
+
+    /// Returns the node's state
+    pub fn status(&self) -> GetRaftStatusResponse {
+        let status = self.core.lock().expect("Raft core poisoned").status();
+        let role = match status.role {
+            Role::Follower => "follower",
+            Role::Candidate => "candidate",
+            Role::Leader => "leader",
+        };
+        GetRaftStatusResponse {
+            node_id: status.id,
+            term: status.term,
+            role: role.to_string(),
+            leader_id: status.leader.unwrap_or(0),
+            commit_index: status.commit_index,
+            applied_index: self.applied.load(Ordering::Acquire),
+            last_index: status.last_index,
+            snapshot_index: status.snapshot_index,
+            apply_error: self
+                .apply_error
+                .lock()
+                .expect("Raft apply error poisoned")
+                .clone()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Runs the node's timers and applies committed entries until the
+    /// task is aborted, or the node stops after failing to apply an entry
+    pub async fn run(self: Arc<Self>) {
+        // Entries committed before a restart are applied right away
+        self.commits.notify_one();
+        tokio::join!(self.tick_forever(), self.apply_forever());
+    }
+
+    /// Stops sending requests to the other nodes, before the engine closes
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+
+    /// Replicates `request` and applies it, returning once this node
+    /// applied it
+    ///
+    /// # Errors
+    ///
+    /// Fails with `FAILED_PRECONDITION` or `UNAVAILABLE` if this node isn't
+    /// the leader, `ABORTED` if it lost its leadership and the write was
+    /// dropped, `UNAVAILABLE` if the write wasn't committed in time (it
+    /// may still be) or the node stopped, or the status the write failed
+    /// with on every node.
+    pub async fn write(
+        self: &Arc<Self>,
+        request: BatchWriteRequest,
+    ) -> std::result::Result<(), Status> {
+        self.check_running()?;
+        let data = request.encode_to_vec();
+        let (done, result) = oneshot::channel();
+        let node = Arc::clone(self);
+        let proposed = self
+            .step_blocking(move |core| {
+                let position = core.propose(data)?;
+                if let Some((index, term)) = position {
+                    let waiter = Waiter { term, done };
+                    node.waiters
+                        .lock()
+                        .expect("Raft waiters poisoned")
+                        .insert(index, waiter);
+                }
+                Ok(position.is_some())
+            })
+            .await?;
+        if !proposed {
+            return Err(self.not_leader());
+        }
+        match tokio::time::timeout(WRITE_TIMEOUT, result).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Status::unavailable("The Raft node stopped")),
+            Err(_) => Err(Status::unavailable(
+                "The write wasn't committed in time, it may still be",
+            )),
+        }
+    }
+
+    /// Returns the status a write fails with on a node that isn't the leader
+    fn not_leader(&self) -> Status {
+        let leader = self
+            .core
+            .lock()
+            .expect("Raft core poisoned")
+            .status()
+            .leader;
+        match leader.and_then(|id| self.peers.get(&id)) {
+            Some(endpoint) => Status::failed_precondition(format!(
+                "This server is a Raft follower, writes go to the leader at {}",
+                endpoint.uri()
+            )),
+            None => Status::unavailable("No Raft leader is known yet"),
+        }
+    }
+
+    /// Fails with `UNAVAILABLE` once the node stopped applying entries
+    #[allow(clippy::result_large_err)]
+    fn check_running(&self) -> std::result::Result<(), Status> {
+        match &*self.apply_error.lock().expect("Raft apply error poisoned") {
+            Some(error) => Err(Status::unavailable(format!(
+                "The Raft node stopped applying entries: {}",
+                error
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the node for good after it failed to apply an entry, failing
+    /// the writes waiting with `error`
+    fn halt(&self, error: String) {
+        *self.apply_error.lock().expect("Raft apply error poisoned") = Some(error);
+        self.stopped.store(true, Ordering::Release);
+        let waiters = std::mem::take(&mut *self.waiters.lock().expect("Raft waiters poisoned"));
+        if let Err(status) = self.check_running() {
+            for waiter in waiters.into_values() {
+                let _ = waiter.done.send(Err(status.clone()));
+            }
+        }
+    }
+
+    /// Runs `f` on the core, then sends the requests it queued and wakes
+    /// the applier if entries were committed
+    fn step<T>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&mut RaftCore<EngineLogStore>) -> ferrisdb_core::Result<T>,
+    ) -> ferrisdb_core::Result<T> {
+        let mut core = self.core.lock().expect("Raft core poisoned");
+        let result = f(&mut core);
+        let outbox = core.take_outbox();
+        let commit = core.status().commit_index;
+        drop(core);
+        if commit > self.applied.load(Ordering::Acquire) {
+            self.commits.notify_one();
+        }
+        if !self.stopped.load(Ordering::Acquire) {
+            for (peer, request) in outbox {
+                self.send(peer, request);
+            }
+        }
+        result
+    }
+
+    /// Runs [`step`](Self::step) on the blocking thread pool, as the core
+    /// writes to the engine
+    async fn step_blocking<T, F>(self: &Arc<Self>, f: F) -> std::result::Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut RaftCore<EngineLogStore>) -> ferrisdb_core::Result<T> + Send + 'static,
+    {
+        let node = Arc::clone(self);
+        tokio::task::spawn_blocking(move || node.step(f))
+            .await
+            .map_err(|e| Status::internal(format!("Raft task failed: {}", e)))?
+            .map_err(status_from_error)
+    }
+
+    /// Sends `request` to `peer` and hands the core the reply
+    fn send(self: &Arc<Self>, peer: u64, request: Request) {
+        let node = Arc::clone(self);
+        tokio::spawn(async move {
+            let snapshot = matches!(request, Request::Snapshot { .. });
+            let reply = match request {
+                Request::Vote(request) => node
+                    .client(peer)
+                    .request_vote(vote_request_to_proto(request))
+                    .await
+                    .map(|response| Reply::Vote(vote_response_from_proto(response.into_inner()))),
+                Request::Append(request) => node
+                    .client(peer)
+                    .append_entries(append_request_to_proto(request))
+                    .await
+                    .map(|response| {
+                        Reply::Append(append_response_from_proto(response.into_inner()))
+                    }),
+                Request::Snapshot { term } => {
+                    node.send_snapshot(peer, term).await.map(Reply::Snapshot)
+                }
+            };
+            if node.stopped.load(Ordering::Acquire) {
+                return;
+            }
+            let result = node
+                .step_blocking(move |core| match reply {
+                    Ok(Reply::Vote(response)) => core.handle_vote_response(peer, response),
+                    Ok(Reply::Append(response)) => core.handle_append_response(peer, response),
+                    Ok(Reply::Snapshot(response)) => core.handle_snapshot_response(peer, response),
+                    Err(status) if snapshot => {
+                        log::warn!(
+                            "Failed to send a Raft snapshot to node {}: {}",
+                            peer,
+                            status.message()
+                        );
+                        core.handle_snapshot_failure(peer);
+                        Ok(())
+                    }
+                    Err(status) => {
+                        log::debug!("Raft request to node {} failed: {}", peer, status.message());
+                        core.handle_unreachable(peer);
+                        Ok(())
+                    }
+                })
+                .await;
+            if let Err(status) = result {
+                log::error!("Raft node failed to handle a reply: {}", status.message());
+            }
+        });
+    }
+
+    /// Returns the client for `peer`, connecting lazily the first time
+    fn client(&self, peer: u64) -> PeerClient {
+        self.clients
+            .lock()
+            .expect("Raft clients poisoned")
+            .entry(peer)
+            .or_insert_with(|| {
+                let channel = self.peers[&peer]
+                    .clone()
+                    .timeout(self.request_timeout)
+                    .connect_lazy();
+                RaftClient::with_interceptor(channel, self.authorization.clone())
+            })
+            .clone()
+    }
+
+    /// Sends `peer` a snapshot of what this node, the leader of `term`,
+    /// has applied, returning the peer's answer
+    ///
+    /// The snapshot goes over a connection of its own, without the
+    /// timeout of the other requests, as it may take long to send.
+    async fn send_snapshot(
+        self: &Arc<Self>,
+        peer: u64,
+        term: u64,
+    ) -> std::result::Result<SnapshotResponse, Status> {
+        let dir = self.snapshots.join(format!("to-{}", peer));
+        let node = Arc::clone(self);
+        let checkpoint = dir.clone();
+        let (index, last_term, files) =
+            tokio::task::spawn_blocking(move || node.checkpoint(&checkpoint))
+                .await
+                .map_err(|e| Status::internal(format!("Raft task failed: {}", e)))?
+                .map_err(status_from_error)?;
+        log::info!(
+            "Sending node {} a Raft snapshot up to entry {}",
+            peer,
+            index
+        );
+
+        let header = InstallSnapshotRequest {
+            term,
+            leader_id: self.id,
+            last_included_index: index,
+            last_included_term: last_term,
+            ..Default::default()
+        };
+        let (chunks, stream) = mpsc::channel(SNAPSHOT_CHUNKS_IN_FLIGHT);
+        let reader = tokio::task::spawn_blocking(move || {
+            let read = snapshot::read_chunks(&dir, &files, |file, data| {
+                let chunk = InstallSnapshotRequest {
+                    file,
+                    data,
+                    ..header.clone()
+                };
+                chunks.blocking_send(chunk).is_ok()
+            });
+            snapshot::remove_dir(&dir)?;
+            // Marking the end only once every file was read, so a failed
+            // read doesn't leave the peer a partial snapshot
+            read?;
+            let _ = chunks.blocking_send(InstallSnapshotRequest {
+                done: true,
+                ..header
+            });
+            ferrisdb_core::Result::Ok(())
+        });
+        let channel = self.peers[&peer].connect_lazy();
+        let response = RaftClient::with_interceptor(channel, self.authorization.clone())
+            .install_snapshot(ReceiverStream::new(stream))
+            .await;
+        match reader.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Failed to read the Raft snapshot for node {}: {}", peer, e),
+            Err(e) => log::warn!("Raft snapshot task failed: {}", e),
+        }
+        Ok(SnapshotResponse {
+            term: response?.into_inner().term,
+            match_index: index,
+        })
+    }
+
+    /// Checkpoints the engine into `dir` between entries being applied,
+    /// returning the index and term of the last entry applied and the
+    /// checkpoint's files
+    fn checkpoint(&self, dir: &Path) -> ferrisdb_core::Result<(u64, u64, Vec<String>)> {
+        let _applying = self.applying.lock().expect("Raft applier poisoned");
+        let index = self.applied.load(Ordering::Acquire);
+        let term = self
+            .core
+            .lock()
+            .expect("Raft core poisoned")
+            .store()
+            .term(index)
+            .ok_or_else(|| {
+                ferrisdb_core::Error::InvalidOperation(format!(
+                    "The term of applied Raft log entry {} is unknown",
+                    index
+                ))
+            })?;
+        let files = snapshot::checkpoint(&self.engine, dir)?;
+        Ok((index, term, files))
+    }
+
+    /// Installs the snapshot received in place of this node's data,
+    /// between entries being applied
+    ///
+    /// A snapshot that fails to install leaves the data part way replaced,
+    /// so the node stops; reopening it finishes the install.
+    fn install(
+        self: &Arc<Self>,
+        request: SnapshotRequest,
+    ) -> ferrisdb_core::Result<SnapshotResponse> {
+        let _applying = self.applying.lock().expect("Raft applier poisoned");
+        let dir = self.snapshots.join(snapshot::RECEIVED);
+        let mut begun = false;
+        let result = self.step(|core| {
+            core.handle_snapshot(&request, |store| {
+                store.begin_install(request.index, request.last_term)?;
+                begun = true;
+                snapshot::restore(&self.engine, &dir)
+            })
+        });
+        match result {
+            Err(e) if begun => {
+                log::error!(
+                    "Failed to install a Raft snapshot, stopping the node: {}",
+                    e
+                );
+                self.halt(format!("Failed to install a Raft snapshot: {}", e));
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+            Ok(_) if begun => {
+                self.applied.store(request.index, Ordering::Release);
+                let mut waiters = self.waiters.lock().expect("Raft waiters poisoned");
+                let later = waiters.split_off(&(request.index + 1));
+                for waiter in std::mem::replace(&mut *waiters, later).into_values() {
+                    let _ = waiter.done.send(Err(Status::unavailable(
+                        "A Raft snapshot replaced the write's entry, which may have been applied",
+                    )));
+                }
+            }
+            Ok(_) => {}
+        }
+        snapshot::remove_dir(&dir)?;
+        result
+    }
+
+    async fn tick_forever(self: &Arc<Self>) {
+        let mut ticks = tokio::time::interval(TICK_INTERVAL);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        while !self.stopped.load(Ordering::Acquire) {
+            ticks.tick().await;
+            if let Err(status) = self.step_blocking(|core| core.tick()).await {
+                log::error!("Raft node failed to tick: {}", status.message());
+            }
+        }
+    }
+
+    /// Applies entries as they're committed, stopping the node once one
+    /// failed `MAX_APPLY_ATTEMPTS` times
+    async fn apply_forever(self: &Arc<Self>) {
+        let mut failures = 0;
+        let mut failed_after = 0;
+        loop {
+            self.commits.notified().await;
+            let node = Arc::clone(self);
+            let error = match tokio::task::spawn_blocking(move || node.apply_committed()).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => format!("Raft applier task failed: {}", e),
+            };
+            let applied = self.applied.load(Ordering::Acquire);
+            if applied != failed_after {
+                failures = 0;
+                failed_after = applied;
+            }
+            failures += 1;
+            if failures >= MAX_APPLY_ATTEMPTS {
+                log::error!(
+                    "Failed to apply Raft log entry {} {} times, stopping the node: {}",
+                    applied + 1,
+                    failures,
+                    error
+                );
+                self.halt(error);
+                return;
+            }
+            log::error!(
+                "Failed to apply Raft log entry {}, retrying: {}",
+                applied + 1,
+                error
+            );
+            let backoff = APPLY_RETRY_INTERVAL * 2u32.pow(failures - 1);
+            tokio::time::sleep(backoff.min(MAX_APPLY_RETRY_INTERVAL)).await;
+            self.commits.notify_one();
+        }
+    }
+
+    /// Applies the committed entries not applied yet, answering the
+    /// writes waiting for them, then compacts the log if it grew long
+    fn apply_committed(&self) -> ferrisdb_core::Result<()> {
+        let _applying = self.applying.lock().expect("Raft applier poisoned");
+        loop {
+            let applied = self.applied.load(Ordering::Acquire);
+            let entries = {
+                let mut core = self.core.lock().expect("Raft core poisoned");
+                let commit = core.status().commit_index;
+                if applied >= commit {
+                    // Compacting only once twice the entries kept piled
+                    // up, so each compaction discards many at once
+                    let snapshot = core.store().snapshot_index();
+                    if applied - snapshot >= 2 * self.max_log_entries {
+                        core.compact(applied - self.max_log_entries)?;
+                    }
+                    return Ok(());
+                }
+                core.store().entries(applied + 1, commit, MAX_APPLY_BYTES)?
+            };
+            for entry in entries {
+                let result = self.apply(&entry)?;
+                self.applied.store(entry.index, Ordering::Release);
+                let waiter = self
+                    .waiters
+                    .lock()
+                    .expect("Raft waiters poisoned")
+                    .remove(&entry.index);
+                if let Some(waiter) = waiter {
+                    let result = if waiter.term == entry.term {
+                        result
+                    } else {
+                        Err(Status::aborted(
+                            "The Raft leader changed and the write was dropped",
+                        ))
+                    };
+                    let _ = waiter.done.send(result);
+                }
+            }
+        }
+    }
+
+    /// Applies one entry, returning what its write returns
+    ///
+    /// A write that fails the same way on every node, such as one whose
+    /// entry is too large, is recorded as applied and its error returned to
+    /// the writer. Other failures are returned as errors, leaving the
+    /// entry to be applied again.
+    fn apply(&self, entry: &Entry) -> ferrisdb_core::Result<std::result::Result<(), Status>> {
+        let mut batch = WriteBatch::new();
+        let mut result = Ok(());
+        if !entry.data.is_empty() {
+            match BatchWriteRequest::decode(entry.data.as_slice()) {
+                Ok(request) => {
+                    self.create_column_families(&request.mutations)?;
+                    match write_batch(&self.engine, request.mutations) {
+                        Ok(writes) => batch = writes,
+                        Err(status) => result = Err(status),
+                    }
+                }
+                Err(e) => {
+                    log::error!(
+                        "Skipping Raft log entry {}, it can't be decoded: {}",
+                        entry.index,
+                        e
+                    );
+                    result = Err(Status::data_loss("The write's Raft log entry is corrupt"));
+                }
+            }
+        }
+        store::record_applied(&mut batch, &self.log_cf, entry.index);
+        match self.engine.write(batch, false) {
+            Ok(()) => Ok(result),
+            Err(e) if deterministic(e.code()) => {
+                let mut batch = WriteBatch::new();
+                store::record_applied(&mut batch, &self.log_cf, entry.index);
+                self.engine.write(batch, false)?;
+                Ok(Err(status_from_error(e)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Creates the column families `mutations` write to that this node
+    /// lacks, as the leader has them
+    fn create_column_families(&self, mutations: &[Mutation]) -> ferrisdb_core::Result<()> {
+        for mutation in mutations {
+            let name = mutation.column_family.as_str();
+            if name.is_empty() || name == COLUMN_FAMILY || self.engine.cf_handle(name).is_some() {
+                continue;
+            }
+            log::info!("Creating column family {:?} for a Raft log entry", name);
+            self.engine
+                .create_column_family(name, ColumnFamilyOptions::default())?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether an error writing an entry's batch would occur on every node
+///
+/// Only a batch the engine can't take at all qualifies: any other failure
+/// may be this node's alone, such as a full disk, and is retried rather
+/// than letting the nodes' data diverge.
+fn deterministic(code: ErrorCode) -> bool {
+    code == ErrorCode::InvalidArgument
+}
+
+/// Serves the requests of the other nodes of the cluster
+#[derive(Clone)]
+pub struct RaftService {
+    node: Arc<RaftNode>,
+}
+
+impl RaftService {
+    /// Creates a service handing requests to `node`
+    pub fn new(node: Arc<RaftNode>) -> Self {
+        Self { node }
+    }
+}
+
+#[tonic::async_trait]
+impl Raft for RaftService {
+    async fn request_vote(
+        &self,
+        request: tonic::Request<RequestVoteRequest>,
+    ) -> std::result::Result<tonic::Response<RequestVoteResponse>, Status> {
+        self.node.check_running()?;
+        let request = vote_request_from_proto(request.into_inner());
+        let response = self
+            .node
+            .step_blocking(move |core| core.handle_vote_request(&request))
+            .await?;
+        Ok(tonic::Response::new(RequestVoteResponse {
+            term: response.term,
+            vote_granted: response.granted,
+        }))
+    }
+
+    async fn append_entries(
+        &self,
+        request: tonic::Request<AppendEntriesRequest>,
+    ) -> std::result::Result<tonic::Response<AppendEntriesResponse>, Status> {
+        self.node.check_running()?;
+        let request = append_request_from_proto(request.into_inner());
+        let response = self
+            .node
+            .step_blocking(move |core| core.handle_append_request(&request))
+            .await?;
+        Ok(tonic::Response::new(AppendEntriesResponse {
+            term: response.term,
+            success: response.success,
+            match_index: response.match_index,
+        }))
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: tonic::Request<Streaming<InstallSnapshotRequest>>,
+    ) -> std::result::Result<tonic::Response<InstallSnapshotResponse>, Status> {
+        self.node.check_running()?;
+        let mut stream = request.into_inner();
+        let first = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("The snapshot stream is empty"))?;
+        let term = self
+            .node
+            .core
+            .lock()
+            .expect("Raft core poisoned")
+            .status()
+            .term;
+        if first.term < term {
+            return Ok(tonic::Response::new(InstallSnapshotResponse { term }));
+        }
+        let _receiving = self
+            .node
+            .receiving
+            .try_lock()
+            .map_err(|_| Status::unavailable("Another Raft snapshot is being received"))?;
+        let request = SnapshotRequest {
+            term: first.term,
+            leader: first.leader_id,
+            index: first.last_included_index,
+            last_term: first.last_included_term,
+        };
+        snapshot::receive(&self.node.snapshots, first, &mut stream).await?;
+        let node = Arc::clone(&self.node);
+        let response = tokio::task::spawn_blocking(move || node.install(request))
+            .await
+            .map_err(|e| Status::internal(format!("Raft task failed: {}", e)))?
+            .map_err(status_from_error)?;
+        Ok(tonic::Response::new(InstallSnapshotResponse {
+            term: response.term,
+        }))
+    }
+
+    async fn get_raft_status(
+        &self,
+        _request: tonic::Request<GetRaftStatusRequest>,
+    ) -> std::result::Result<tonic::Response<GetRaftStatusResponse>, Status> {
+        Ok(tonic::Response::new(self.node.status()))
+    }
+}
+
+fn vote_request_to_proto(request: VoteRequest) -> RequestVoteRequest {
+    RequestVoteRequest {
+        term: request.term,
+        candidate_id: request.candidate,
+        last_log_index: request.last_log_index,
+        last_log_term: request.last_log_term,
+    }
+}
+
+fn vote_request_from_proto(request: RequestVoteRequest) -> VoteRequest {
+    VoteRequest {
+        term: request.term,
+        candidate: request.candidate_id,
+        last_log_index: request.last_log_index,
+        last_log_term: request.last_log_term,
+    }
+}
+
+fn vote_response_from_proto(response: RequestVoteResponse) -> VoteResponse {
+    VoteResponse {
+        term: response.term,
+        granted: response.vote_granted,
+    }
+}
+
+fn append_request_to_proto(request: AppendRequest) -> AppendEntriesRequest {
+    AppendEntriesRequest {
+        term: request.term,
+        leader_id: request.leader,
+        prev_log_index: request.prev_log_index,
+        prev_log_term: request.prev_log_term,
+        entries: request
+            .entries
+            .into_iter()
+            .map(|entry| RaftEntry {
+                term: entry.term,
+                data: entry.data,
+            })
+            .collect(),
+        leader_commit: request.leader_commit,
+    }
+}
+
+/// Converts a request, numbering its entries on from `prev_log_index`
+fn append_request_from_proto(request: AppendEntriesRequest) -> AppendRequest {
+    let first = request.prev_log_index + 1;
+    AppendRequest {
+        term: request.term,
+        leader: request.leader_id,
+        prev_log_index: request.prev_log_index,
+        prev_log_term: request.prev_log_term,
+        entries: request
+            .entries
+            .into_iter()
+            .zip(first..)
+            .map(|(entry, index)| Entry {
+                index,
+                term: entry.term,
+                data: entry.data,
+            })
+            .collect(),
+        leader_commit: request.leader_commit,
+    }
+}
+
+fn append_response_from_proto(response: AppendEntriesResponse) -> AppendResponse {
+    AppendResponse {
+        term: response.term,
+        success: response.success,
+        match_index: response.match_index,
+    }
+}
@@ -0,0 +1,344 @@
+//! Snapshots a leader sends to followers behind its compacted log
+//!
+//! A snapshot is a [checkpoint](StorageEngine::create_checkpoint) of the
+//! leader's engine, taken while no entry is being applied, so it holds
+//! exactly the entries up to the last one applied. Its files are streamed
+//! to the follower, which opens them as an engine of their own and copies
+//! every column family but the log's over its own data.
+//!
+//! Both ends keep the files under `raft-snapshots` in the data directory,
+//! which is cleared whenever the node opens:
+//!
+//! ```text
+//!   raft-snapshots/to-<node>    checkpoint being sent to a node
+//!   raft-snapshots/receiving    files of a snapshot still arriving
+//!   raft-snapshots/received     a complete snapshot, being installed
+//! ```
+
+use super::store::COLUMN_FAMILY;
+use crate::proto::InstallSnapshotRequest;
+use ferrisdb_core::{Error, Key, Result, Value};
+use ferrisdb_storage::{ColumnFamily, ColumnFamilyOptions, Options, StorageEngine, WriteBatch};
+
+use tokio::io::AsyncWriteExt;
+use tonic::{Status, Streaming};
+
+use std::io::Read;
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+
+/// Directory of a complete snapshot, see the [module documentation](self)
+pub const RECEIVED: &str = "received";
+
+/// Directory a snapshot's files are written to as they arrive
+const RECEIVING: &str = "receiving";
+
+/// Bytes of a file sent in one message
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Key-value pairs copied or deleted in one batch when installing
+const BATCH_KEYS: usize = 1024;
+
+/// Returns the directory snapshots of `engine` are kept in
+pub fn directory(engine: &StorageEngine) -> PathBuf {
+    engine.config().data_dir.join("raft-snapshots")
+}
+
+/// Checkpoints `engine` into `dir`, replacing what was there, and
+/// returns the paths of its files relative to `dir`
+///
+/// # Errors
+///
+/// Returns an error if the checkpoint fails or its files can't be listed.
+pub fn checkpoint(engine: &StorageEngine, dir: &Path) -> Result<Vec<String>> {
+    remove_dir(dir)?;
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    engine.create_checkpoint(dir)?;
+    let mut files = Vec::new();
+    list_files(dir, "", &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn list_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            Error::InvalidOperation(format!("Checkpoint file {:?} isn't UTF-8", name))
+        })?;
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &format!("{}/", path), files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `files` of the checkpoint in `dir` in chunks, passing each
+/// to `send` until it returns false
+///
+/// A file's first chunk carries its path, and every file has at least
+/// one chunk.
+///
+/// # Errors
+///
+/// Returns an error if a file can't be read.
+pub fn read_chunks(
+    dir: &Path,
+    files: &[String],
+    mut send: impl FnMut(String, Vec<u8>) -> bool,
+) -> Result<()> {
+    for name in files {
+        let mut file = std::fs::File::open(dir.join(name))?;
+        let mut path = name.clone();
+        loop {
+            let mut data = Vec::with_capacity(CHUNK_SIZE);
+            (&mut file).take(CHUNK_SIZE as u64).read_to_end(&mut data)?;
+            let last = data.len() < CHUNK_SIZE;
+            if !send(std::mem::take(&mut path), data) {
+                return Ok(());
+            }
+            if last {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the snapshot `stream` sends, starting with `first`, into
+/// `dir`/`received`, replacing what was there
+///
+/// # Errors
+///
+/// Fails with `INVALID_ARGUMENT` if a path leaves the directory or data
+/// comes before any file, `ABORTED` if the stream ends before its last
+/// message, or `INTERNAL` if the files can't be written.
+#[allow(clippy::result_large_err)]
+pub async fn receive(
+    dir: &Path,
+    first: InstallSnapshotRequest,
+    stream: &mut Streaming<InstallSnapshotRequest>,
+) -> std::result::Result<(), Status> {
+    let receiving = dir.join(RECEIVING);
+    remove_dir(&receiving).map_err(write_failed)?;
+    tokio::fs::create_dir_all(&receiving)
+        .await
+        .map_err(write_failed)?;
+    let mut file: Option<tokio::fs::File> = None;
+    let mut message = Some(first);
+    while let Some(chunk) = message {
+        if chunk.done {
+            finish(file).await?;
+            let received = dir.join(RECEIVED);
+            remove_dir(&received).map_err(write_failed)?;
+            return tokio::fs::rename(&receiving, &received)
+                .await
+                .map_err(write_failed);
+        }
+        if !chunk.file.is_empty() {
+            finish(file.take()).await?;
+            let path = receiving.join(relative_path(&chunk.file)?);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(write_failed)?;
+            }
+            file = Some(tokio::fs::File::create(&path).await.map_err(write_failed)?);
+        }
+        let Some(output) = file.as_mut() else {
+            return Err(Status::invalid_argument(
+                "The snapshot's data comes before any file",
+            ));
+        };
+        output.write_all(&chunk.data).await.map_err(write_failed)?;
+        message = stream.message().await?;
+    }
+    Err(Status::aborted(
+        "The snapshot stream ended before its last file",
+    ))
+}
+
+/// Syncs a received file, so the snapshot is durable before it's installed
+async fn finish(file: Option<tokio::fs::File>) -> std::result::Result<(), Status> {
+    match file {
+        Some(file) => file.sync_all().await.map_err(write_failed),
+        None => Ok(()),
+    }
+}
+
+fn write_failed(error: impl std::fmt::Display) -> Status {
+    Status::internal(format!("Failed to write the snapshot: {}", error))
+}
+
+/// Checks that `path` names a file inside the snapshot's directory
+#[allow(clippy::result_large_err)]
+fn relative_path(path: &str) -> std::result::Result<PathBuf, Status> {
+    let relative = PathBuf::from(path);
+    let inside = path
+        .split('/')
+        .all(|part| !part.is_empty() && part != "." && part != "..")
+        && relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !inside {
+        return Err(Status::invalid_argument(format!(
+            "Invalid snapshot file {:?}",
+            path
+        )));
+    }
+    Ok(relative)
+}
+
+/// Replaces the data of `engine` with that of the checkpoint in `dir`
+///
+/// Every column family but the log's is cleared, then the checkpoint's
+/// families are copied over, creating those `engine` lacks. The writes
+/// aren't synced; the caller syncs the write recording the snapshot.
+///
+/// # Errors
+///
+/// Returns an error if the checkpoint can't be opened or read, or the
+/// engine fails to write.
+pub fn restore(engine: &StorageEngine, dir: &Path) -> Result<()> {
+    let snapshot = StorageEngine::open(Options::new(dir))?;
+    for name in engine.column_family_names() {
+        match engine.cf_handle(&name) {
+            Some(cf) if name != COLUMN_FAMILY => for_each_page(engine, &cf, |page| {
+                let (Some((first, _)), Some((last, _))) = (page.first(), page.last()) else {
+                    return Ok(());
+                };
+                let mut end = last.clone();
+                end.push(0);
+                let mut batch = WriteBatch::new();
+                batch.delete_range_cf(&cf, first.as_slice()..end.as_slice());
+                engine.write(batch, false)
+            })?,
+            _ => {}
+        }
+    }
+    for name in snapshot.column_family_names() {
+        let Some(from) = snapshot.cf_handle(&name) else {
+            continue;
+        };
+        if name == COLUMN_FAMILY {
+            continue;
+        }
+        let to = match engine.cf_handle(&name) {
+            Some(cf) => cf,
+            None => engine.create_column_family(&name, ColumnFamilyOptions::default())?,
+        };
+        for_each_page(&snapshot, &from, |page| {
+            let mut batch = WriteBatch::new();
+            for (key, value) in page {
+                batch.put_cf(&to, key, value);
+            }
+            engine.write(batch, false)
+        })?;
+    }
+    snapshot.close()
+}
+
+/// Calls `f` with the key-value pairs of `cf`, a batch at a time
+fn for_each_page(
+    engine: &StorageEngine,
+    cf: &ColumnFamily,
+    mut f: impl FnMut(Vec<(Key, Value)>) -> Result<()>,
+) -> Result<()> {
+    let snapshot = engine.snapshot();
+    let mut start = Bound::Unbounded;
+    loop {
+        let page = snapshot.scan_cf_limit(cf, (start, Bound::Unbounded), BATCH_KEYS)?;
+        let Some((last, _)) = page.last() else {
+            return Ok(());
+        };
+        start = Bound::Excluded(last.clone());
+        let done = page.len() < BATCH_KEYS;
+        f(page)?;
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Removes `dir` with what it holds, if it exists
+pub fn remove_dir(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_restore_replaces_the_data_but_keeps_the_log() {
+        let dir = TempDir::new().unwrap();
+        let open = |name: &str| {
+            let engine = StorageEngine::open(Options::new(dir.path().join(name))).unwrap();
+            engine
+                .create_column_family(COLUMN_FAMILY, ColumnFamilyOptions::default())
+                .unwrap();
+            engine
+        };
+        let leader = open("leader");
+        let users = leader
+            .create_column_family("users", ColumnFamilyOptions::default())
+            .unwrap();
+        for i in 0..BATCH_KEYS as u32 * 2 + 10 {
+            leader
+                .put(i.to_be_bytes().to_vec(), b"leader".to_vec())
+                .unwrap();
+        }
+        leader
+            .put_cf(&users, b"ada".to_vec(), b"1".to_vec())
+            .unwrap();
+        let raft = leader.cf_handle(COLUMN_FAMILY).unwrap();
+        leader
+            .put_cf(&raft, b"state".to_vec(), b"leader".to_vec())
+            .unwrap();
+        let checkpoint = dir.path().join("checkpoint");
+        let files = super::checkpoint(&leader, &checkpoint).unwrap();
+        assert!(files.iter().any(|file| file.contains('/')));
+
+        let follower = open("follower");
+        for i in 0..BATCH_KEYS as u32 * 3 {
+            follower
+                .put(format!("stale-{}", i).into_bytes(), b"follower".to_vec())
+                .unwrap();
+        }
+        let raft = follower.cf_handle(COLUMN_FAMILY).unwrap();
+        follower
+            .put_cf(&raft, b"state".to_vec(), b"follower".to_vec())
+            .unwrap();
+        restore(&follower, &checkpoint).unwrap();
+
+        let data = follower.scan::<[u8], _>(..).unwrap();
+        assert_eq!(data.len(), BATCH_KEYS * 2 + 10);
+        assert!(data.iter().all(|(_, value)| value == b"leader"));
+        let users = follower.cf_handle("users").unwrap();
+        assert_eq!(
+            follower.get_cf(&users, b"ada").unwrap(),
+            Some(b"1".to_vec())
+        );
+        assert_eq!(
+            follower.get_cf(&raft, b"state").unwrap(),
+            Some(b"follower".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_snapshot_paths_stay_inside_the_directory() {
+        assert!(relative_path("cf/users/000001.sst").is_ok());
+        for path in ["", "../MANIFEST", "/etc/passwd", "a//b", "a/./b", "a/"] {
+            assert!(relative_path(path).is_err(), "{:?}", path);
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! The Raft log and hard state, kept in a column family of the engine
+//!
+//! The log lives next to the data it replicates, in [`COLUMN_FAMILY`], so
+//! it shares the engine's WAL and recovery. Every change is written with
+//! a sync, as Raft requires it to be durable before the node answers:
+//!
+//! ```text
+//!   state              term (8 bytes) │ voted for (8 bytes, 0 for none)
+//!   applied            index of the last entry applied (8 bytes)
+//!   snapshot           index │ term of the last entry compacted away
+//!   installing         index │ term of a snapshot being installed
+//!   log/<index>        term (8 bytes) │ command
+//! ```
+//!
+//! Indexes are big-endian, so the entries sort in log order. `applied` is
+//! written in the same batch as the writes of the entry it names, so after
+//! a crash an entry is either applied and recorded, or neither. The log
+//! starts after `snapshot`, which compaction moves up along with deleting
+//! the entries. `installing` is set while a snapshot from the leader
+//! replaces the data, and cleared in the batch that starts the log from
+//! it, so a crash part way through is noticed on restart.
+
+use super::core::{Entry, HardState, LogStore};
+use ferrisdb_core::{Error, Result};
+use ferrisdb_storage::{ColumnFamily, ColumnFamilyOptions, StorageEngine, WriteBatch};
+
+use std::sync::Arc;
+
+/// Column family holding the Raft log of a server
+pub const COLUMN_FAMILY: &str = "raft";
+
+const STATE_KEY: &[u8] = b"state";
+const APPLIED_KEY: &[u8] = b"applied";
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+const INSTALLING_KEY: &[u8] = b"installing";
+const LOG_PREFIX: &[u8] = b"log/";
+/// The first key after every log key
+const LOG_END: &[u8] = b"log0";
+
+/// A [`LogStore`] in a column family of the engine
+pub struct EngineLogStore {
+    engine: Arc<StorageEngine>,
+    cf: ColumnFamily,
+    state: HardState,
+    /// Index and term of the last entry compacted away
+    snapshot: (u64, u64),
+    /// Term of each entry after the snapshot, so lookups don't read the
+    /// engine
+    terms: Vec<u64>,
+}
+
+impl EngineLogStore {
+    /// Loads the log of `engine`, creating [`COLUMN_FAMILY`] if the engine
+    /// doesn't have it yet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the column family can't be created or read, or
+    /// `Error::Corruption` if the log isn't in the format above.
+    pub fn open(engine: Arc<StorageEngine>) -> Result<Self> {
+        let cf = match engine.cf_handle(COLUMN_FAMILY) {
+            Some(cf) => cf,
+            None => engine.create_column_family(COLUMN_FAMILY, ColumnFamilyOptions::default())?,
+        };
+        let state = match engine.get_cf(&cf, STATE_KEY)? {
+            Some(value) => {
+                let [term, voted_for] = decode_u64s(&value, "hard state")?;
+                HardState {
+                    term,
+                    voted_for: (voted_for != 0).then_some(voted_for),
+                }
+            }
+            None => HardState::default(),
+        };
+
+        let snapshot = match engine.get_cf(&cf, SNAPSHOT_KEY)? {
+            Some(value) => {
+                let [index, term] = decode_u64s(&value, "snapshot")?;
+                (index, term)
+            }
+            None => (0, 0),
+        };
+
+        // Compaction keeps the log short, so it is read in one go
+        let mut terms = Vec::new();
+        for (key, value) in engine.scan_cf(&cf, LOG_PREFIX..LOG_END)? {
+            let [index] = decode_u64s(&key[LOG_PREFIX.len()..], "log key")?;
+            let expected = snapshot.0 + terms.len() as u64 + 1;
+            if index != expected {
+                return Err(Error::Corruption(format!(
+                    "Raft log entry {} follows entry {}",
+                    index,
+                    expected - 1
+                )));
+            }
+            terms.push(decode_entry(index, &value)?.term);
+        }
+        Ok(Self {
+            engine,
+            cf,
+            state,
+            snapshot,
+            terms,
+        })
+    }
+
+    /// Returns the column family holding the log
+    pub fn column_family(&self) -> &ColumnFamily {
+        &self.cf
+    }
+
+    /// Returns the index of the last entry applied to the engine
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the index can't be read.
+    pub fn applied(&self) -> Result<u64> {
+        match self.engine.get_cf(&self.cf, APPLIED_KEY)? {
+            Some(value) => Ok(decode_u64s::<1>(&value, "applied index")?[0]),
+            None => Ok(0),
+        }
+    }
+
+    /// Notes that a snapshot whose last entry is `index` of `term` is
+    /// about to replace the data, until
+    /// [`install_snapshot`](LogStore::install_snapshot) finishes it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the note can't be written.
+    pub fn begin_install(&self, index: u64, term: u64) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put_cf(
+            &self.cf,
+            INSTALLING_KEY.to_vec(),
+            encode_u64s([index, term]),
+        );
+        self.engine.write(batch, true)
+    }
+
+    /// Returns the index and term of a snapshot whose install was cut
+    /// short, leaving the data part way replaced
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the note can't be read.
+    pub fn interrupted_install(&self) -> Result<Option<(u64, u64)>> {
+        match self.engine.get_cf(&self.cf, INSTALLING_KEY)? {
+            Some(value) => {
+                let [index, term] = decode_u64s(&value, "snapshot being installed")?;
+                Ok(Some((index, term)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the position of `index`, after the snapshot, in `terms`
+    fn offset(&self, index: u64) -> usize {
+        (index - self.snapshot.0 - 1) as usize
+    }
+
+    /// Adds deleting the entries from the snapshot up to `last` to `batch`
+    fn delete_entries(&self, batch: &mut WriteBatch, last: u64) {
+        if last > self.snapshot.0 {
+            let (start, end) = (log_key(self.snapshot.0 + 1), log_key(last + 1));
+            batch.delete_range_cf(&self.cf, start.as_slice()..end.as_slice());
+        }
+    }
+}
+
+/// Adds recording `index` as the last entry applied to `batch`, for the
+/// log in `cf`
+pub fn record_applied(batch: &mut WriteBatch, cf: &ColumnFamily, index: u64) {
+    batch.put_cf(cf, APPLIED_KEY.to_vec(), index.to_be_bytes().to_vec());
+}
+
+impl LogStore for EngineLogStore {
+    fn hard_state(&self) -> HardState {
+        self.state
+    }
+
+    fn save_hard_state(&mut self, state: HardState) -> Result<()> {
+        let value = encode_u64s([state.term, state.voted_for.unwrap_or(0)]);
+        let mut batch = WriteBatch::new();
+        batch.put_cf(&self.cf, STATE_KEY.to_vec(), value);
+        self.engine.write(batch, true)?;
+        self.state = state;
+        Ok(())
+    }
+
+    fn last_index(&self) -> u64 {
+        self.snapshot.0 + self.terms.len() as u64
+    }
+
+    fn snapshot_index(&self) -> u64 {
+        self.snapshot.0
+    }
+
+    fn term(&self, index: u64) -> Option<u64> {
+        match index {
+            _ if index == self.snapshot.0 => Some(self.snapshot.1),
+            _ if index < self.snapshot.0 => None,
+            _ => self.terms.get(self.offset(index)).copied(),
+        }
+    }
+
+    fn entries(&self, from: u64, to: u64, max_bytes: usize) -> Result<Vec<Entry>> {
+        if from <= self.snapshot.0 {
+            return Err(Error::InvalidArgument(format!(
+                "Raft log entry {} was compacted away",
+                from
+            )));
+        }
+        let mut entries = Vec::new();
+        let mut bytes = 0;
+        for index in from..=to.min(self.last_index()) {
+            if !entries.is_empty() && bytes >= max_bytes {
+                break;
+            }
+            let value = self
+                .engine
+                .get_cf(&self.cf, &log_key(index))?
+                .ok_or_else(|| Error::Corruption(format!("Raft log entry {} is missing", index)))?;
+            let entry = decode_entry(index, &value)?;
+            bytes += entry.data.len();
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        let Some(first) = entries.first().map(|entry| entry.index) else {
+            return Ok(());
+        };
+        let mut batch = WriteBatch::new();
+        for index in first..=self.last_index() {
+            batch.delete_cf(&self.cf, log_key(index));
+        }
+        for entry in entries {
+            let mut value = entry.term.to_be_bytes().to_vec();
+            value.extend_from_slice(&entry.data);
+            batch.put_cf(&self.cf, log_key(entry.index), value);
+        }
+        self.engine.write(batch, true)?;
+        self.terms.truncate(self.offset(first));
+        self.terms.extend(entries.iter().map(|entry| entry.term));
+        Ok(())
+    }
+
+    fn compact(&mut self, index: u64) -> Result<()> {
+        let Some(term) = self.term(index).filter(|_| index > self.snapshot.0) else {
+            return Ok(());
+        };
+        let mut batch = WriteBatch::new();
+        self.delete_entries(&mut batch, index);
+        batch.put_cf(&self.cf, SNAPSHOT_KEY.to_vec(), encode_u64s([index, term]));
+        self.engine.write(batch, true)?;
+        self.terms.drain(..=self.offset(index));
+        self.snapshot = (index, term);
+        Ok(())
+    }
+
+    /// Also records the snapshot's last entry as applied, as the caller
+    /// installed the state up to it, and clears the note of
+    /// [`begin_install`](EngineLogStore::begin_install)
+    fn install_snapshot(&mut self, index: u64, term: u64) -> Result<()> {
+        let keep = index > self.snapshot.0 && self.term(index) == Some(term);
+        let last = if keep { index } else { self.last_index() };
+        let mut batch = WriteBatch::new();
+        self.delete_entries(&mut batch, last);
+        batch.put_cf(&self.cf, SNAPSHOT_KEY.to_vec(), encode_u64s([index, term]));
+        record_applied(&mut batch, &self.cf, index);
+        batch.delete_cf(&self.cf, INSTALLING_KEY.to_vec());
+        self.engine.write(batch, true)?;
+        if keep {
+            self.terms.drain(..=self.offset(index));
+        } else {
+            self.terms.clear();
+        }
+        self.snapshot = (index, term);
+        Ok(())
+    }
+}
+
+fn log_key(index: u64) -> Vec<u8> {
+    let mut key = LOG_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn decode_entry(index: u64, value: &[u8]) -> Result<Entry> {
+    if value.len() < 8 {
+        return Err(Error::Corruption(format!(
+            "Raft log entry {} is truncated",
+            index
+        )));
+    }
+    let (term, data) = value.split_at(8);
+    Ok(Entry {
+        index,
+        term: u64::from_be_bytes(term.try_into().expect("split at 8 bytes")),
+        data: data.to_vec(),
+    })
+}
+
+fn encode_u64s<const N: usize>(values: [u64; N]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+/// Decodes `N` big-endian integers filling `bytes`
+fn decode_u64s<const N: usize>(bytes: &[u8], what: &str) -> Result<[u64; N]> {
+    if bytes.len() != N * 8 {
+        return Err(Error::Corruption(format!(
+            "Raft {} has {} bytes, expected {}",
+            what,
+            bytes.len(),
+            N * 8
+        )));
+    }
+    let mut values = [0; N];
+    for (value, chunk) in values.iter_mut().zip(bytes.chunks_exact(8)) {
+        *value = u64::from_be_bytes(chunk.try_into().expect("chunks of 8 bytes"));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_storage::Options;
+    use tempfile::TempDir;
+
+    fn entry(index: u64, term: u64, data: &[u8]) -> Entry {
+        Entry {
+            index,
+            term,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_log_and_hard_state_survive_reopening() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap());
+        let mut store = EngineLogStore::open(Arc::clone(&engine)).unwrap();
+        assert_eq!(store.hard_state(), HardState::default());
+        assert_eq!(store.last_index(), 0);
+        assert_eq!(store.term(0), Some(0));
+
+        let state = HardState {
+            term: 3,
+            voted_for: Some(2),
+        };
+        store.save_hard_state(state).unwrap();
+        store
+            .append(&[entry(1, 1, b""), entry(2, 1, b"a"), entry(3, 2, b"b")])
+            .unwrap();
+        // Appending at 3 replaces the entry there and everything after
+        store.append(&[entry(3, 3, b"c")]).unwrap();
+        let mut batch = WriteBatch::new();
+        record_applied(&mut batch, store.column_family(), 2);
+        engine.write(batch, false).unwrap();
+        drop(store);
+        engine.close().unwrap();
+        drop(engine);
+
+        let engine = Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap());
+        let store = EngineLogStore::open(engine).unwrap();
+        assert_eq!(store.hard_state(), state);
+        assert_eq!(store.last_index(), 3);
+        assert_eq!(store.term(3), Some(3));
+        assert_eq!(store.term(4), None);
+        assert_eq!(store.applied().unwrap(), 2);
+        assert_eq!(
+            store.entries(2, 3, usize::MAX).unwrap(),
+            vec![entry(2, 1, b"a"), entry(3, 3, b"c")]
+        );
+        // A limit still returns the first entry
+        assert_eq!(store.entries(2, 3, 0).unwrap(), vec![entry(2, 1, b"a")]);
+    }
+
+    #[test]
+    fn test_compaction_and_snapshots_trim_the_log() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap());
+        let mut store = EngineLogStore::open(Arc::clone(&engine)).unwrap();
+        store
+            .append(&[
+                entry(1, 1, b"a"),
+                entry(2, 1, b"b"),
+                entry(3, 2, b"c"),
+                entry(4, 2, b"d"),
+            ])
+            .unwrap();
+        store.compact(2).unwrap();
+        assert_eq!(store.snapshot_index(), 2);
+        assert_eq!(store.term(1), None);
+        assert_eq!(store.term(2), Some(1));
+        assert!(store.entries(2, 4, usize::MAX).is_err());
+
+        // A snapshot the log continues keeps the entries after it
+        store.install_snapshot(3, 2).unwrap();
+        assert_eq!(store.applied().unwrap(), 3);
+        drop(store);
+        engine.close().unwrap();
+        drop(engine);
+
+        let engine = Arc::new(StorageEngine::open(Options::new(dir.path())).unwrap());
+        let mut store = EngineLogStore::open(engine).unwrap();
+        assert_eq!(store.snapshot_index(), 3);
+        assert_eq!(store.term(3), Some(2));
+        assert_eq!(store.last_index(), 4);
+        assert_eq!(
+            store.entries(4, 4, usize::MAX).unwrap(),
+            vec![entry(4, 2, b"d")]
+        );
+
+        // Any other discards the whole log, and ends the install begun
+        store.begin_install(6, 3).unwrap();
+        assert_eq!(store.interrupted_install().unwrap(), Some((6, 3)));
+        store.install_snapshot(6, 3).unwrap();
+        assert_eq!(store.interrupted_install().unwrap(), None);
+        assert_eq!(store.last_index(), 6);
+        assert_eq!(store.term(6), Some(3));
+        assert_eq!(store.term(4), None);
+    }
+}
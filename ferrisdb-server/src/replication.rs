@@ -0,0 +1,375 @@
+//! WAL shipping between servers: the `Replication` service and the
+//! replica side streaming from it
+//!
+//! A replica keeps one `Replicate` call open to its primary. The primary
+//! follows its WAL with a tailer and sends the records as they are
+//! written; the replica applies each response and acknowledges the newest
+//! timestamp it applied:
+//!
+//! ```text
+//!   primary                                       replica
+//!   WALTailer ──poll──▶ ReplicateResponse ──────▶ write_replicated
+//!                        { records }                    │
+//!   status ◀──────────── ReplicateRequest ◀─────────────┘
+//!                        { applied_timestamp }
+//! ```
+//!
+//! When the stream breaks, the replica reconnects and asks for everything
+//! after its own last timestamp, so nothing is skipped or applied twice.
+//! A replica serves reads like any server, but its engine is opened as a
+//! replica and rejects writes other than replicated ones.
+//!
+//! # Consistency
+//!
+//! Replication is asynchronous: the primary acknowledges a write once it
+//! is in its own WAL, so replicas may lag, and a write the primary loses
+//! before shipping it is lost. Nothing elects a new primary; failing over
+//! means reconfiguring a replica as a primary by hand. For writes that
+//! survive the loss of a server and a leader elected automatically, run
+//! the servers as a Raft cluster instead, see [`crate::raft`].
+
+use crate::config::ReplicaSettings;
+use crate::proto::replication_client::ReplicationClient;
+use crate::proto::replication_server::Replication;
+use crate::proto::{
+    GetReplicationStatusRequest, GetReplicationStatusResponse, ReplicaStatus, ReplicateRequest,
+    ReplicateResponse,
+};
+use crate::service::{run_blocking, status_from_error};
+use crate::{Error, Result};
+use ferrisdb_storage::wal::{WALEntry, WALTailer};
+use ferrisdb_storage::StorageEngine;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint};
+use tonic::{Request, Response, Status, Streaming};
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Most records sent in one response
+const MAX_RECORDS_PER_RESPONSE: usize = 256;
+
+/// Bytes of records after which a response is sent without waiting for more
+const MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+/// How often an idle stream checks the WAL for new records
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a replica waits before reconnecting to its primary
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+type ReplicateItem = std::result::Result<ReplicateResponse, Status>;
+
+/// Progress of one connected replica
+#[derive(Debug, Default)]
+struct Progress {
+    applied: AtomicU64,
+    sent: AtomicU64,
+}
+
+/// Streams the WAL of one storage engine to replicas
+#[derive(Clone)]
+pub struct ReplicationService {
+    engine: Arc<StorageEngine>,
+    replicas: Arc<Mutex<BTreeMap<String, Arc<Progress>>>>,
+}
+
+impl ReplicationService {
+    /// Creates a service shipping the writes of `engine`
+    pub fn new(engine: Arc<StorageEngine>) -> Self {
+        Self {
+            engine,
+            replicas: Arc::default(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Replication for ReplicationService {
+    type ReplicateStream = ReceiverStream<ReplicateItem>;
+
+    async fn replicate(
+        &self,
+        request: Request<Streaming<ReplicateRequest>>,
+    ) -> std::result::Result<Response<Self::ReplicateStream>, Status> {
+        let mut requests = request.into_inner();
+        let start = requests
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Replication stream ended before starting"))?;
+        if start.replica_id.is_empty() {
+            return Err(Status::invalid_argument("replica_id must not be empty"));
+        }
+        let tailer = self
+            .engine
+            .tail_wal(start.from_timestamp)
+            .map_err(status_from_error)?;
+        log::info!(
+            "Replica {:?} streaming from timestamp {}",
+            start.replica_id,
+            start.from_timestamp
+        );
+
+        let progress = Arc::new(Progress::default());
+        let before = start.from_timestamp.saturating_sub(1);
+        progress.applied.store(before, Ordering::Relaxed);
+        progress.sent.store(before, Ordering::Relaxed);
+        self.replicas
+            .lock()
+            .expect("replica registry poisoned")
+            .insert(start.replica_id.clone(), Arc::clone(&progress));
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(ship(
+            Arc::clone(&self.engine),
+            tailer,
+            tx,
+            Arc::clone(&progress),
+        ));
+
+        // Record acknowledgements until the replica goes away
+        let replicas = Arc::clone(&self.replicas);
+        tokio::spawn(async move {
+            while let Ok(Some(ack)) = requests.message().await {
+                progress
+                    .applied
+                    .fetch_max(ack.applied_timestamp, Ordering::Relaxed);
+            }
+            let mut replicas = replicas.lock().expect("replica registry poisoned");
+            // A reconnected replica has registered anew
+            if replicas
+                .get(&start.replica_id)
+                .is_some_and(|current| Arc::ptr_eq(current, &progress))
+            {
+                replicas.remove(&start.replica_id);
+            }
+            log::info!("Replica {:?} disconnected", start.replica_id);
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_replication_status(
+        &self,
+        _: Request<GetReplicationStatusRequest>,
+    ) -> std::result::Result<Response<GetReplicationStatusResponse>, Status> {
+        let replicas = self
+            .replicas
+            .lock()
+            .expect("replica registry poisoned")
+            .iter()
+            .map(|(id, progress)| ReplicaStatus {
+                replica_id: id.clone(),
+                applied_timestamp: progress.applied.load(Ordering::Relaxed),
+                sent_timestamp: progress.sent.load(Ordering::Relaxed),
+            })
+            .collect();
+        Ok(Response::new(GetReplicationStatusResponse {
+            last_timestamp: self.engine.last_timestamp(),
+            replicas,
+        }))
+    }
+}
+
+/// Sends WAL records to one replica until it disconnects or the WAL fails
+async fn ship(
+    engine: Arc<StorageEngine>,
+    mut tailer: WALTailer,
+    tx: mpsc::Sender<ReplicateItem>,
+    progress: Arc<Progress>,
+) {
+    loop {
+        let engine = Arc::clone(&engine);
+        let polled = tokio::task::spawn_blocking(move || {
+            let response = next_response(&engine, &mut tailer);
+            (tailer, response)
+        })
+        .await;
+        let response = match polled {
+            Ok((returned, response)) => {
+                tailer = returned;
+                response
+            }
+            Err(e) => {
+                let status = Status::internal(format!("Replication task failed: {}", e));
+                let _ = tx.send(Err(status)).await;
+                return;
+            }
+        };
+
+        match response {
+            Ok(Some(response)) => {
+                progress
+                    .sent
+                    .store(tailer.next_timestamp() - 1, Ordering::Relaxed);
+                if tx.send(Ok(response)).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+            Err(status) => {
+                log::warn!("Replication stream failed: {}", status.message());
+                let _ = tx.send(Err(status)).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Collects the records written since the last poll, if any
+// Handlers return `Status` by value, so this does too
+#[allow(clippy::result_large_err)]
+fn next_response(
+    engine: &StorageEngine,
+    tailer: &mut WALTailer,
+) -> std::result::Result<Option<ReplicateResponse>, Status> {
+    let mut records = Vec::new();
+    let mut bytes = 0;
+    while records.len() < MAX_RECORDS_PER_RESPONSE && bytes < MAX_RESPONSE_BYTES {
+        let Some(entries) = tailer.poll().map_err(status_from_error)? else {
+            break;
+        };
+        let record = WALEntry::encode_batch(&entries).map_err(status_from_error)?;
+        bytes += record.len();
+        records.push(record);
+    }
+    if records.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ReplicateResponse {
+        records,
+        primary_timestamp: engine.last_timestamp(),
+    }))
+}
+
+/// Keeps a replica's engine up to date with its primary
+pub struct Replicator {
+    engine: Arc<StorageEngine>,
+    endpoint: Endpoint,
+    authorization: Option<MetadataValue<Ascii>>,
+    replica_id: String,
+}
+
+impl Replicator {
+    /// Prepares to stream from the primary in `settings`
+    ///
+    /// `default_id` names the replica if the settings don't.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if the primary's address or the token is
+    /// invalid, or the CA file can't be read.
+    pub fn new(
+        engine: Arc<StorageEngine>,
+        settings: &ReplicaSettings,
+        default_id: String,
+    ) -> Result<Self> {
+        let mut endpoint = Endpoint::from_shared(settings.primary.clone())
+            .map_err(|e| Error::Config(format!("Invalid primary {:?}: {}", settings.primary, e)))?
+            .connect_timeout(Duration::from_secs(5));
+        if let Some(path) = &settings.tls_ca_path {
+            let ca = std::fs::read(path)
+                .map_err(|e| Error::Config(format!("Failed to read {}: {}", path.display(), e)))?;
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca)))?;
+        }
+        let authorization = settings
+            .token
+            .as_ref()
+            .map(|token| {
+                format!("Bearer {}", token)
+                    .parse()
+                    .map_err(|_| Error::Config("Invalid replica token".to_string()))
+            })
+            .transpose()?;
+        Ok(Self {
+            engine,
+            endpoint,
+            authorization,
+            replica_id: settings.replica_id.clone().unwrap_or(default_id),
+        })
+    }
+
+    /// Streams from the primary forever, reconnecting whenever the stream
+    /// breaks
+    pub async fn run(self) {
+        loop {
+            match self.stream().await {
+                Ok(()) => log::info!("Primary ended the replication stream"),
+                Err(status) if status.code() == tonic::Code::FailedPrecondition => log::error!(
+                    "Replication failed, the replica may need seeding from a new checkpoint: {}",
+                    status.message()
+                ),
+                Err(status) => log::warn!("Replication interrupted: {}", status.message()),
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+
+    /// Applies responses from one stream until it ends or fails
+    // Interceptors return `Status` by value, so the closure below does too
+    #[allow(clippy::result_large_err)]
+    async fn stream(&self) -> std::result::Result<(), Status> {
+        let channel = self
+            .endpoint
+            .connect()
+            .await
+            .map_err(|e| Status::unavailable(format!("Failed to connect to primary: {}", e)))?;
+        let authorization = self.authorization.clone();
+        let mut client =
+            ReplicationClient::with_interceptor(channel, move |mut request: Request<()>| {
+                if let Some(value) = &authorization {
+                    request
+                        .metadata_mut()
+                        .insert("authorization", value.clone());
+                }
+                Ok(request)
+            });
+
+        let (acks, requests) = mpsc::channel(16);
+        let applied = self.engine.last_timestamp();
+        let start = ReplicateRequest {
+            replica_id: self.replica_id.clone(),
+            from_timestamp: applied + 1,
+            applied_timestamp: applied,
+        };
+        acks.send(start)
+            .await
+            .expect("the receiver is alive until the call starts");
+        let mut responses = client
+            .replicate(ReceiverStream::new(requests))
+            .await?
+            .into_inner();
+        log::info!("Replicating from timestamp {}", applied + 1);
+
+        while let Some(response) = responses.message().await? {
+            let records = response.records;
+            let applied = run_blocking(&self.engine, move |engine| {
+                let mut applied = engine.last_timestamp();
+                for record in records {
+                    applied = engine.write_replicated(WALEntry::decode_batch(&record)?, false)?;
+                }
+                Ok(applied)
+            })
+            .await?;
+            let ack = ReplicateRequest {
+                applied_timestamp: applied,
+                ..Default::default()
+            };
+            if acks.send(ack).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1079 @@
+//! A Redis protocol frontend over the storage engine
+//!
+//! Speaks enough of RESP2 for Redis clients and load generators such as
+//! `redis-benchmark` and `memtier_benchmark` to talk to FerrisDB:
+//!
+//! | Command                                 | Notes                                |
+//! |-----------------------------------------|--------------------------------------|
+//! | `GET key`                               |                                      |
+//! | `SET key value [NX\|XX] [EX s\|PX ms]`  |                                      |
+//! | `DEL key [key ...]`, `EXISTS key [...]` |                                      |
+//! | `EXPIRE key seconds`                    | no `NX`/`XX`/`GT`/`LT`               |
+//! | `TTL key`, `PTTL key`                   |                                      |
+//! | `SCAN cursor [MATCH pattern] [COUNT n]` | no `TYPE`, cursors expire            |
+//! | `AUTH [user] token`                     | one of the server's API tokens       |
+//! | `PING`, `ECHO`, `SELECT 0`, `QUIT`      |                                      |
+//! | `COMMAND`, `CONFIG GET`                 | empty replies, for client handshakes |
+//!
+//! Keys live in their own column family, [`COLUMN_FAMILY`], which has a
+//! TTL so `EX`, `PX` and `EXPIRE` map onto the engine's expiry; values
+//! written without one never expire. Every value is a plain string.
+//!
+//! Commands needing a read before their write (`SET NX`, `DEL`'s count,
+//! `EXPIRE`) run under one lock shared by all writes of the frontend, so
+//! they are atomic with respect to each other, though not to writes
+//! through the gRPC API to the same family.
+//!
+//! RESP has no TLS here: like the gRPC listener without a `[tls]` section,
+//! the frontend only listens beyond loopback with `allow_plaintext`.
+//!
+//! Commands count against the server's quotas like gRPC calls, each one
+//! as a request; one over a quota fails with `ERR` and the reason.
+//!
+//! `SCAN` cursors are numbers, as clients expect, naming a key the server
+//! remembers. The most recent [`MAX_CURSORS`] are kept, shared by all
+//! connections; resuming an older one fails with `ERR invalid cursor`.
+
+use crate::auth::constant_time_eq;
+use crate::quota::Quotas;
+use crate::Result;
+use ferrisdb_core::keys::prefix_successor;
+use ferrisdb_core::Key;
+use ferrisdb_storage::{ColumnFamily, ColumnFamilyOptions, StorageEngine, WriteBatch};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::io;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Column family holding the keys of the frontend
+pub const COLUMN_FAMILY: &str = "redis";
+
+/// Most `SCAN` cursors remembered at once
+pub const MAX_CURSORS: usize = 4096;
+
+/// Longest inline command or RESP header line (in bytes)
+const MAX_LINE: u64 = 64 * 1024;
+
+/// Largest bulk string accepted (in bytes)
+const MAX_BULK_LEN: usize = 64 * 1024 * 1024;
+
+/// Most arguments of one command
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// Most keys one `SCAN` call looks at
+const MAX_SCAN_COUNT: usize = 10_000;
+
+/// Options the frontend's column family is created and opened with
+///
+/// Values without a TTL of their own never expire.
+pub fn column_family_options() -> ColumnFamilyOptions {
+    ColumnFamilyOptions::default().with_ttl(Duration::MAX)
+}
+
+/// Serves the Redis protocol on a listener
+pub struct RespServer {
+    state: State,
+}
+
+struct State {
+    engine: Arc<StorageEngine>,
+    cf: ColumnFamily,
+    /// Tokens `AUTH` admits; empty if no authentication is needed
+    tokens: Vec<String>,
+    cursors: Mutex<Cursors>,
+    /// Serializes the writes of the frontend
+    write_lock: Mutex<()>,
+    quotas: Arc<Quotas>,
+}
+
+/// Per-connection state
+struct Session {
+    authenticated: bool,
+}
+
+impl RespServer {
+    /// Creates a frontend over `engine` admitting clients that `AUTH` with
+    /// one of `tokens`, or every client if `tokens` is empty
+    ///
+    /// Creates [`COLUMN_FAMILY`] if the engine doesn't have it yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Storage` if the column family can't be created.
+    pub fn new(engine: Arc<StorageEngine>, tokens: Vec<String>) -> Result<Self> {
+        let cf = match engine.cf_handle(COLUMN_FAMILY) {
+            Some(cf) => cf,
+            None => engine.create_column_family(COLUMN_FAMILY, column_family_options())?,
+        };
+        Ok(Self {
+            state: State {
+                engine,
+                cf,
+                tokens,
+                cursors: Mutex::new(Cursors::default()),
+                write_lock: Mutex::new(()),
+                quotas: Arc::new(Quotas::unlimited()),
+            },
+        })
+    }
+
+    /// Holds commands to `quotas`, which are unlimited by default
+    pub fn with_quotas(mut self, quotas: Arc<Quotas>) -> Self {
+        self.state.quotas = quotas;
+        self
+    }
+
+    /// Accepts connections until `shutdown` completes, then drops them
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if accepting connections fails.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()> + Send,
+    ) -> Result<()> {
+        log::info!("Serving the Redis protocol on {}", listener.local_addr()?);
+        let state = Arc::new(self.state);
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+        loop {
+            let stream = tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => accepted?.0,
+                // Reap finished connections as they go
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
+            let _ = stream.set_nodelay(true);
+            let state = Arc::clone(&state);
+            connections.spawn(async move {
+                if let Err(e) = state.handle(stream).await {
+                    log::debug!("Redis protocol connection failed: {}", e);
+                }
+            });
+        }
+        connections.shutdown().await;
+        Ok(())
+    }
+}
+
+impl State {
+    /// Serves one connection until the client leaves or breaks protocol
+    async fn handle(self: &Arc<Self>, stream: TcpStream) -> io::Result<()> {
+        let connection = stream.peer_addr().ok();
+        let (read, write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let mut writer = BufWriter::new(write);
+        let mut session = Session {
+            authenticated: self.tokens.is_empty(),
+        };
+        let mut out = Vec::new();
+        loop {
+            let args = match read_command(&mut reader).await {
+                Ok(Some(args)) => args,
+                Ok(None) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    out.clear();
+                    Reply::Error(format!("ERR Protocol error: {}", e)).encode(&mut out);
+                    writer.write_all(&out).await?;
+                    return writer.flush().await;
+                }
+                Err(e) => return Err(e),
+            };
+            if args.is_empty() {
+                if reader.buffer().is_empty() {
+                    writer.flush().await?;
+                }
+                continue;
+            }
+            let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+            let size = args.iter().map(Vec::len).sum();
+            let meter = self.quotas.admit(connection, size);
+            let reply = match &meter {
+                Ok(_) => self.execute(&mut session, args).await,
+                Err(status) => Reply::Error(format!("ERR {}", status.message())),
+            };
+            out.clear();
+            reply.encode(&mut out);
+            if let Ok(meter) = meter {
+                meter.charge(out.len());
+            }
+            writer.write_all(&out).await?;
+            // Pipelined commands are answered together
+            if quit || reader.buffer().is_empty() {
+                writer.flush().await?;
+            }
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs one command
+    async fn execute(self: &Arc<Self>, session: &mut Session, args: Vec<Vec<u8>>) -> Reply {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        let mut args = args.into_iter().skip(1).collect::<Vec<_>>();
+        match name.as_str() {
+            "auth" => return self.auth(session, &args),
+            "quit" => return Reply::ok(),
+            _ if !session.authenticated => {
+                return Reply::Error("NOAUTH Authentication required.".to_string())
+            }
+            _ => {}
+        }
+
+        let arity = |min: usize, max: usize| {
+            if args.len() < min || args.len() > max {
+                Err(Reply::Error(format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    name
+                )))
+            } else {
+                Ok(())
+            }
+        };
+        let checked = match name.as_str() {
+            "ping" | "command" => arity(0, usize::MAX),
+            "echo" | "select" | "get" | "ttl" | "pttl" => arity(1, 1),
+            "del" | "exists" | "scan" | "config" => arity(1, usize::MAX),
+            "expire" => arity(2, 2),
+            "set" => arity(2, usize::MAX),
+            _ => return Reply::Error(format!("ERR unknown command '{}'", name)),
+        };
+        if let Err(reply) = checked {
+            return reply;
+        }
+
+        match name.as_str() {
+            "ping" => match args.pop() {
+                Some(message) => Reply::Bulk(Some(message)),
+                None => Reply::Simple("PONG"),
+            },
+            "echo" => Reply::Bulk(args.pop()),
+            "select" => match parse_int(&args[0]) {
+                Ok(0) => Reply::ok(),
+                Ok(_) => Reply::Error("ERR DB index is out of range".to_string()),
+                Err(reply) => reply,
+            },
+            "command" | "config" => Reply::Array(Vec::new()),
+            "get" => {
+                let key = args.pop().expect("arity checked");
+                self.blocking(move |state| Ok(Reply::Bulk(state.engine.get_cf(&state.cf, &key)?)))
+                    .await
+            }
+            "set" => self.set(args).await,
+            "del" => self.blocking(move |state| state.del(args)).await,
+            "exists" => {
+                self.blocking(move |state| {
+                    let mut count = 0;
+                    for key in &args {
+                        if state.engine.get_cf(&state.cf, key)?.is_some() {
+                            count += 1;
+                        }
+                    }
+                    Ok(Reply::Integer(count))
+                })
+                .await
+            }
+            "expire" => {
+                let seconds = match parse_int(&args[1]) {
+                    Ok(seconds) => seconds,
+                    Err(reply) => return reply,
+                };
+                let key = args.swap_remove(0);
+                self.blocking(move |state| state.expire(key, seconds)).await
+            }
+            "ttl" | "pttl" => {
+                let key = args.pop().expect("arity checked");
+                let millis = name == "pttl";
+                self.blocking(move |state| {
+                    let reply = match state.engine.get_cf_with_ttl(&state.cf, &key)? {
+                        None => -2,
+                        Some((_, None)) => -1,
+                        Some((_, Some(ttl))) if millis => ttl.as_millis() as i64,
+                        Some((_, Some(ttl))) => ((ttl.as_millis() + 500) / 1000) as i64,
+                    };
+                    Ok(Reply::Integer(reply))
+                })
+                .await
+            }
+            "scan" => self.scan(args).await,
+            _ => unreachable!("unknown commands are rejected above"),
+        }
+    }
+
+    fn auth(&self, session: &mut Session, args: &[Vec<u8>]) -> Reply {
+        // `AUTH token` or `AUTH user token`; the user name is ignored
+        let Some(token) = args.last().filter(|_| matches!(args.len(), 1 | 2)) else {
+            return Reply::Error("ERR wrong number of arguments for 'auth' command".to_string());
+        };
+        if self.tokens.is_empty() {
+            return Reply::Error(
+                "ERR AUTH called without any password configured for the default user".to_string(),
+            );
+        }
+        // Every token is compared, so timing doesn't tell which one matched
+        let matched = self.tokens.iter().fold(false, |matched, t| {
+            constant_time_eq(token, t.as_bytes()) | matched
+        });
+        if matched {
+            session.authenticated = true;
+            Reply::ok()
+        } else {
+            Reply::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            )
+        }
+    }
+
+    /// Parses the options of `SET` and applies it
+    async fn set(self: &Arc<Self>, args: Vec<Vec<u8>>) -> Reply {
+        let mut args = args.into_iter();
+        let key = args.next().expect("arity checked");
+        let value = args.next().expect("arity checked");
+        let mut condition = None;
+        let mut ttl = None;
+        while let Some(option) = args.next() {
+            let option = option.to_ascii_uppercase();
+            match option.as_slice() {
+                b"NX" | b"XX" if condition.is_none() => condition = Some(option == b"NX"),
+                b"EX" | b"PX" if ttl.is_none() => {
+                    let Some(amount) = args.next() else {
+                        return Reply::syntax_error();
+                    };
+                    let amount = match parse_int(&amount) {
+                        Ok(amount) if amount > 0 => amount as u64,
+                        Ok(_) => {
+                            return Reply::Error(
+                                "ERR invalid expire time in 'set' command".to_string(),
+                            )
+                        }
+                        Err(reply) => return reply,
+                    };
+                    ttl = Some(if option == b"EX" {
+                        Duration::from_secs(amount)
+                    } else {
+                        Duration::from_millis(amount)
+                    });
+                }
+                _ => return Reply::syntax_error(),
+            }
+        }
+
+        self.blocking(move |state| {
+            let _guard = state.write_lock.lock().expect("write lock poisoned");
+            if let Some(absent) = condition {
+                let exists = state.engine.get_cf(&state.cf, &key)?.is_some();
+                if exists == absent {
+                    return Ok(Reply::Bulk(None));
+                }
+            }
+            match ttl {
+                Some(ttl) => state.engine.put_cf_with_ttl(&state.cf, key, value, ttl)?,
+                None => state.engine.put_cf(&state.cf, key, value)?,
+            }
+            Ok(Reply::ok())
+        })
+        .await
+    }
+
+    /// Deletes keys, returning how many existed
+    fn del(&self, keys: Vec<Vec<u8>>) -> ferrisdb_core::Result<Reply> {
+        let keys: BTreeSet<_> = keys.into_iter().collect();
+        let _guard = self.write_lock.lock().expect("write lock poisoned");
+        let mut batch = WriteBatch::new();
+        let mut count = 0;
+        for key in keys {
+            if self.engine.get_cf(&self.cf, &key)?.is_some() {
+                batch.delete_cf(&self.cf, key);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.engine.write(batch, false)?;
+        }
+        Ok(Reply::Integer(count))
+    }
+
+    /// Sets a key to expire in `seconds`, deleting it if that isn't positive
+    fn expire(&self, key: Key, seconds: i64) -> ferrisdb_core::Result<Reply> {
+        let _guard = self.write_lock.lock().expect("write lock poisoned");
+        let Some(value) = self.engine.get_cf(&self.cf, &key)? else {
+            return Ok(Reply::Integer(0));
+        };
+        if seconds <= 0 {
+            self.engine.delete_cf(&self.cf, key)?;
+        } else {
+            let ttl = Duration::from_secs(seconds as u64);
+            self.engine.put_cf_with_ttl(&self.cf, key, value, ttl)?;
+        }
+        Ok(Reply::Integer(1))
+    }
+
+    /// Parses the options of `SCAN` and returns the next page of keys
+    async fn scan(self: &Arc<Self>, args: Vec<Vec<u8>>) -> Reply {
+        let cursor = match std::str::from_utf8(&args[0])
+            .ok()
+            .and_then(|c| c.parse().ok())
+        {
+            Some(cursor) => cursor,
+            None => return Reply::Error("ERR invalid cursor".to_string()),
+        };
+        let mut pattern = None;
+        let mut count = 10;
+        let mut options = args.into_iter().skip(1);
+        while let Some(option) = options.next() {
+            let Some(argument) = options.next() else {
+                return Reply::syntax_error();
+            };
+            match option.to_ascii_uppercase().as_slice() {
+                b"MATCH" => pattern = Some(argument),
+                b"COUNT" => match parse_int(&argument) {
+                    Ok(n) if n >= 1 => count = (n as usize).min(MAX_SCAN_COUNT),
+                    Ok(_) => return Reply::syntax_error(),
+                    Err(reply) => return reply,
+                },
+                _ => return Reply::syntax_error(),
+            }
+        }
+
+        let after = if cursor == 0 {
+            None
+        } else {
+            match self.cursors.lock().expect("cursors poisoned").get(cursor) {
+                Some(key) => Some(key),
+                None => return Reply::Error("ERR invalid cursor".to_string()),
+            }
+        };
+        self.blocking(move |state| state.scan_page(after, pattern, count))
+            .await
+    }
+
+    /// Reads up to `count` keys after `after`, keeping those matching
+    /// `pattern`
+    fn scan_page(
+        &self,
+        after: Option<Key>,
+        pattern: Option<Vec<u8>>,
+        count: usize,
+    ) -> ferrisdb_core::Result<Reply> {
+        // Only keys starting with the pattern's literal prefix can match
+        let prefix = pattern.as_deref().map(literal_prefix).unwrap_or_default();
+        let start = match after {
+            Some(after) if after.as_slice() >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix.to_vec()),
+        };
+        let end = match prefix_successor(prefix) {
+            Some(successor) if !prefix.is_empty() => Bound::Excluded(successor),
+            _ => Bound::Unbounded,
+        };
+
+        let mut pairs = self
+            .engine
+            .snapshot()
+            .scan_cf_limit(&self.cf, (start, end), count + 1)?;
+        let more = pairs.len() > count;
+        pairs.truncate(count);
+        let next = match pairs.last() {
+            Some((last, _)) if more => self
+                .cursors
+                .lock()
+                .expect("cursors poisoned")
+                .insert(last.clone()),
+            _ => 0,
+        };
+        let keys = pairs
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| pattern.as_deref().map_or(true, |p| glob_match(p, key)))
+            .map(|key| Reply::Bulk(Some(key)))
+            .collect();
+        Ok(Reply::Array(vec![
+            Reply::Bulk(Some(next.to_string().into_bytes())),
+            Reply::Array(keys),
+        ]))
+    }
+
+    /// Runs `f` on the blocking thread pool, turning engine errors into
+    /// error replies
+    async fn blocking<F>(self: &Arc<Self>, f: F) -> Reply
+    where
+        F: FnOnce(&State) -> ferrisdb_core::Result<Reply> + Send + 'static,
+    {
+        let state = Arc::clone(self);
+        match tokio::task::spawn_blocking(move || f(&state)).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(e)) => Reply::Error(format!("ERR {}", e)),
+            Err(e) => Reply::Error(format!("ERR Request task failed: {}", e)),
+        }
+    }
+}
+
+/// `SCAN` positions handed out as cursors, oldest evicted first
+#[derive(Debug, Default)]
+struct Cursors {
+    next: u64,
+    positions: BTreeMap<u64, Key>,
+}
+
+impl Cursors {
+    /// Remembers `key`, returning the cursor naming it
+    fn insert(&mut self, key: Key) -> u64 {
+        self.next += 1;
+        self.positions.insert(self.next, key);
+        while self.positions.len() > MAX_CURSORS {
+            self.positions.pop_first();
+        }
+        self.next
+    }
+
+    fn get(&self, cursor: u64) -> Option<Key> {
+        self.positions.get(&cursor).cloned()
+    }
+}
+
+/// A reply to a command
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Simple("OK")
+    }
+
+    fn syntax_error() -> Self {
+        Reply::Error("ERR syntax error".to_string())
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(text) => {
+                out.push(b'+');
+                out.extend_from_slice(text.as_bytes());
+            }
+            Reply::Error(message) => {
+                out.push(b'-');
+                // A line break would end the reply early
+                out.extend(
+                    message
+                        .bytes()
+                        .map(|b| if b == b'\r' || b == b'\n' { b' ' } else { b }),
+                );
+            }
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1"),
+            Reply::Bulk(Some(data)) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+                return;
+            }
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Parses an integer argument
+#[allow(clippy::result_large_err)]
+fn parse_int(arg: &[u8]) -> std::result::Result<i64, Reply> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| Reply::Error("ERR value is not an integer or out of range".to_string()))
+}
+
+fn protocol_error(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a line without its line ending, `None` at the end of the stream
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(protocol_error("line too long or cut short"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+/// Reads the `<count>` of a `*<count>` or `$<len>` header
+fn parse_header(line: &[u8], max: usize) -> io::Result<Option<usize>> {
+    let text = std::str::from_utf8(&line[1..]).map_err(|_| protocol_error("invalid length"))?;
+    let n: i64 = text.parse().map_err(|_| protocol_error("invalid length"))?;
+    match n {
+        n if n < 0 => Ok(None),
+        n if n as u64 > max as u64 => Err(protocol_error("length exceeds the limit")),
+        n => Ok(Some(n as usize)),
+    }
+}
+
+/// Reads one command, as a RESP array of bulk strings or an inline line
+///
+/// Returns `None` at the end of the stream and an `InvalidData` error for
+/// malformed input.
+async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    if line.first() != Some(&b'*') {
+        let args = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        return Ok(Some(args));
+    }
+
+    let count = parse_header(&line, MAX_ARGS)?.unwrap_or(0);
+    let mut args = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let header = read_line(reader)
+            .await?
+            .ok_or_else(|| protocol_error("command cut short"))?;
+        if header.first() != Some(&b'$') {
+            return Err(protocol_error(format!(
+                "expected '$', got '{}'",
+                header.first().map_or(' ', |&b| b as char)
+            )));
+        }
+        let len = parse_header(&header, MAX_BULK_LEN)?
+            .ok_or_else(|| protocol_error("invalid bulk length"))?;
+        let mut arg = vec![0u8; len + 2];
+        reader.read_exact(&mut arg).await?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not terminated by CRLF"));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Returns the part of a glob pattern before its first special character
+fn literal_prefix(pattern: &[u8]) -> &[u8] {
+    let end = pattern
+        .iter()
+        .position(|b| matches!(b, b'*' | b'?' | b'[' | b'\\'))
+        .unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// Matches `text` against a Redis glob pattern
+///
+/// Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` escapes. A `*`
+/// backtracks to the last star only, so matching takes at most
+/// `O(pattern * text)` steps.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Pattern position after the last star, and the text it was tried at
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() {
+            if pattern[p] == b'*' {
+                p += 1;
+                star = Some((p, t));
+                continue;
+            }
+            if let Some(len) = match_one(&pattern[p..], text[t]) {
+                p += len;
+                t += 1;
+                continue;
+            }
+        }
+        match star {
+            // Let the star swallow one more byte
+            Some((after_star, tried)) => {
+                p = after_star;
+                t = tried + 1;
+                star = Some((after_star, tried + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches one byte against the element at the start of `pattern`,
+/// returning the element's length if it matches
+fn match_one(pattern: &[u8], byte: u8) -> Option<usize> {
+    match pattern[0] {
+        b'?' => Some(1),
+        b'\\' if pattern.len() > 1 => (pattern[1] == byte).then_some(2),
+        b'[' => match class_end(pattern) {
+            Some(end) => class_matches(&pattern[1..end], byte).then_some(end + 1),
+            // An unclosed bracket is literal
+            None => (byte == b'[').then_some(1),
+        },
+        literal => (literal == byte).then_some(1),
+    }
+}
+
+/// Returns the index of the `]` closing the class at the start of `pattern`
+fn class_end(pattern: &[u8]) -> Option<usize> {
+    let mut i = 1;
+    if pattern.get(i) == Some(&b'^') {
+        i += 1;
+    }
+    // A `]` right at the start is a member
+    if pattern.get(i) == Some(&b']') {
+        i += 1;
+    }
+    while i < pattern.len() {
+        match pattern[i] {
+            b'\\' => i += 2,
+            b']' => return Some(i),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Matches a byte against the members of a class, without its brackets
+fn class_matches(class: &[u8], byte: u8) -> bool {
+    let (negated, mut class) = match class.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    while let Some((&first, rest)) = class.split_first() {
+        let (low, rest) = match (first, rest) {
+            (b'\\', [escaped, rest @ ..]) => (*escaped, rest),
+            _ => (first, rest),
+        };
+        let (high, rest) = match rest {
+            [b'-', high, rest @ ..] => (*high, rest),
+            _ => (low, rest),
+        };
+        let (low, high) = (low.min(high), low.max(high));
+        matched |= (low..=high).contains(&byte);
+        class = rest;
+    }
+    matched != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_glob_match() {
+        for (pattern, text) in [
+            (&b"*"[..], &b""[..]),
+            (b"user:*", b"user:42"),
+            (b"h?llo", b"hello"),
+            (b"h[ae]llo", b"hallo"),
+            (b"h[^e]llo", b"hallo"),
+            (b"h[a-b]llo", b"hbllo"),
+            (b"h[b-a]llo", b"hallo"),
+            (b"a*b*c", b"axxbyyc"),
+            (b"\\*x", b"*x"),
+            (b"[]]", b"]"),
+            (b"[", b"["),
+        ] {
+            assert!(
+                glob_match(pattern, text),
+                "{:?} should match {:?}",
+                pattern,
+                text
+            );
+        }
+        for (pattern, text) in [
+            (&b"user:*"[..], &b"users"[..]),
+            (b"h?llo", b"hllo"),
+            (b"h[^e]llo", b"hello"),
+            (b"a*b*c", b"axxbyy"),
+            (b"\\*x", b"ax"),
+        ] {
+            assert!(
+                !glob_match(pattern, text),
+                "{:?} matched {:?}",
+                pattern,
+                text
+            );
+        }
+        // Many stars don't make matching blow up
+        let pattern = b"*a".repeat(50);
+        assert!(!glob_match(&pattern, &[b'a'; 40]));
+        assert_eq!(literal_prefix(b"user:*:name"), b"user:");
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_parsed_in_both_forms() {
+        let mut input: &[u8] =
+            b"*2\r\n$3\r\nGET\r\n$3\r\nk\r\n\r\nPING  hi\r\n*1\r\n$4\r\nPING\r\n";
+        let mut reader = BufReader::new(&mut input);
+        assert_eq!(
+            read_command(&mut reader).await.unwrap().unwrap(),
+            vec![b"GET".to_vec(), b"k\r\n".to_vec()]
+        );
+        assert_eq!(
+            read_command(&mut reader).await.unwrap().unwrap(),
+            vec![b"PING".to_vec(), b"hi".to_vec()]
+        );
+        assert_eq!(
+            read_command(&mut reader).await.unwrap().unwrap(),
+            vec![b"PING".to_vec()]
+        );
+        assert!(read_command(&mut reader).await.unwrap().is_none());
+
+        for malformed in [&b"*1\r\n+PING\r\n"[..], b"*1\r\n$4\r\nPINGxx", b"*x\r\n"] {
+            let mut input = malformed;
+            let error = read_command(&mut BufReader::new(&mut input))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ),
+                "{:?}",
+                malformed
+            );
+        }
+    }
+
+    /// A minimal RESP client sending commands and reading raw replies
+    struct Client {
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        async fn connect(addr: std::net::SocketAddr) -> Self {
+            Self {
+                reader: BufReader::new(TcpStream::connect(addr).await.unwrap()),
+            }
+        }
+
+        async fn call(&mut self, args: &[&str]) -> Reply {
+            let mut request = format!("*{}\r\n", args.len());
+            for arg in args {
+                request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+            }
+            self.reader
+                .get_mut()
+                .write_all(request.as_bytes())
+                .await
+                .unwrap();
+            self.read_reply().await
+        }
+
+        fn read_reply(&mut self) -> std::pin::Pin<Box<dyn Future<Output = Reply> + '_>> {
+            Box::pin(async move {
+                let line = read_line(&mut self.reader).await.unwrap().unwrap();
+                let text = String::from_utf8(line[1..].to_vec()).unwrap();
+                match line[0] {
+                    b'+' => Reply::Simple(if text == "OK" { "OK" } else { "PONG" }),
+                    b'-' => Reply::Error(text),
+                    b':' => Reply::Integer(text.parse().unwrap()),
+                    b'$' if text == "-1" => Reply::Bulk(None),
+                    b'$' => {
+                        let mut data = vec![0u8; text.parse::<usize>().unwrap() + 2];
+                        self.reader.read_exact(&mut data).await.unwrap();
+                        data.truncate(data.len() - 2);
+                        Reply::Bulk(Some(data))
+                    }
+                    b'*' => {
+                        let mut items = Vec::new();
+                        for _ in 0..text.parse::<usize>().unwrap() {
+                            items.push(self.read_reply().await);
+                        }
+                        Reply::Array(items)
+                    }
+                    other => panic!("unexpected reply type {:?}", other as char),
+                }
+            })
+        }
+    }
+
+    fn bulk(data: &str) -> Reply {
+        Reply::Bulk(Some(data.as_bytes().to_vec()))
+    }
+
+    async fn start(
+        engine: Arc<StorageEngine>,
+        tokens: Vec<String>,
+    ) -> (std::net::SocketAddr, tokio::sync::oneshot::Sender<()>) {
+        let server = RespServer::new(engine, tokens).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(server.serve(listener, async {
+            let _ = stopped.await;
+        }));
+        (addr, stop)
+    }
+
+    #[tokio::test]
+    async fn test_redis_commands_map_onto_the_engine() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            Arc::new(StorageEngine::open(ferrisdb_storage::Options::new(dir.path())).unwrap());
+        let (addr, stop) = start(Arc::clone(&engine), Vec::new()).await;
+        let mut client = Client::connect(addr).await;
+
+        assert_eq!(client.call(&["PING"]).await, Reply::Simple("PONG"));
+        assert_eq!(client.call(&["set", "a", "1"]).await, Reply::ok());
+        assert_eq!(client.call(&["GET", "a"]).await, bulk("1"));
+        assert_eq!(client.call(&["GET", "missing"]).await, Reply::Bulk(None));
+        assert_eq!(
+            client.call(&["SET", "a", "2", "NX"]).await,
+            Reply::Bulk(None)
+        );
+        assert_eq!(
+            client.call(&["SET", "b", "2", "XX"]).await,
+            Reply::Bulk(None)
+        );
+        assert_eq!(
+            client.call(&["SET", "b", "2", "NX", "EX", "100"]).await,
+            Reply::ok()
+        );
+        assert_eq!(
+            client.call(&["EXISTS", "a", "b", "c", "a"]).await,
+            Reply::Integer(3)
+        );
+
+        assert_eq!(client.call(&["TTL", "a"]).await, Reply::Integer(-1));
+        assert_eq!(client.call(&["TTL", "b"]).await, Reply::Integer(100));
+        assert_eq!(client.call(&["TTL", "c"]).await, Reply::Integer(-2));
+        assert_eq!(client.call(&["EXPIRE", "a", "50"]).await, Reply::Integer(1));
+        assert_eq!(client.call(&["GET", "a"]).await, bulk("1"));
+        assert!(matches!(
+            client.call(&["PTTL", "a"]).await,
+            Reply::Integer(ms) if ms > 49_000 && ms <= 50_000
+        ));
+        assert_eq!(client.call(&["EXPIRE", "c", "50"]).await, Reply::Integer(0));
+        assert_eq!(client.call(&["EXPIRE", "a", "0"]).await, Reply::Integer(1));
+        assert_eq!(client.call(&["GET", "a"]).await, Reply::Bulk(None));
+        assert_eq!(
+            client.call(&["SET", "c", "3", "PX", "1"]).await,
+            Reply::ok()
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(client.call(&["GET", "c"]).await, Reply::Bulk(None));
+        assert_eq!(
+            client.call(&["DEL", "b", "b", "c"]).await,
+            Reply::Integer(1)
+        );
+
+        // Errors keep the connection usable
+        for (command, error) in [
+            (
+                &["GET"][..],
+                "ERR wrong number of arguments for 'get' command",
+            ),
+            (
+                &["SET", "a", "1", "EX", "0"],
+                "ERR invalid expire time in 'set' command",
+            ),
+            (&["SET", "a", "1", "NX", "XX"], "ERR syntax error"),
+            (
+                &["EXPIRE", "a", "soon"],
+                "ERR value is not an integer or out of range",
+            ),
+            (&["FLUSHALL"], "ERR unknown command 'flushall'"),
+            (&["SCAN", "12345"], "ERR invalid cursor"),
+        ] {
+            assert_eq!(client.call(command).await, Reply::Error(error.to_string()));
+        }
+
+        // Scanning pages through every key, MATCH narrowing them down
+        for i in 0..25 {
+            let key = format!("user:{:02}", i);
+            client.call(&["SET", &key, "v"]).await;
+        }
+        client.call(&["SET", "other", "v"]).await;
+        let mut cursor = "0".to_string();
+        let mut keys = Vec::new();
+        loop {
+            let reply = client
+                .call(&["SCAN", &cursor, "MATCH", "user:1*", "COUNT", "4"])
+                .await;
+            let Reply::Array(reply) = reply else {
+                panic!("unexpected reply {:?}", reply);
+            };
+            let [Reply::Bulk(Some(next)), Reply::Array(page)] = &reply[..] else {
+                panic!("unexpected reply {:?}", reply);
+            };
+            keys.extend(page.iter().cloned());
+            cursor = String::from_utf8(next.clone()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        let expected: Vec<_> = (10..20).map(|i| bulk(&format!("user:{}", i))).collect();
+        assert_eq!(keys, expected);
+
+        // The keys are in their own column family of the engine
+        let cf = engine.cf_handle(COLUMN_FAMILY).unwrap();
+        assert_eq!(engine.get_cf(&cf, b"user:07").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"user:07").unwrap(), None);
+
+        assert_eq!(client.call(&["QUIT"]).await, Reply::ok());
+        stop.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clients_authenticate_with_api_tokens() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            Arc::new(StorageEngine::open(ferrisdb_storage::Options::new(dir.path())).unwrap());
+        let (addr, stop) = start(engine, vec!["s3cret".to_string()]).await;
+        let mut client = Client::connect(addr).await;
+
+        assert_eq!(
+            client.call(&["GET", "a"]).await,
+            Reply::Error("NOAUTH Authentication required.".to_string())
+        );
+        assert!(matches!(
+            client.call(&["AUTH", "guess"]).await,
+            Reply::Error(e) if e.starts_with("WRONGPASS")
+        ));
+        assert_eq!(
+            client.call(&["AUTH", "default", "s3cret"]).await,
+            Reply::ok()
+        );
+        assert_eq!(client.call(&["GET", "a"]).await, Reply::Bulk(None));
+
+        // Pipelined inline commands are answered in order
+        let mut pipelined = Client::connect(addr).await;
+        pipelined
+            .reader
+            .get_mut()
+            .write_all(b"AUTH s3cret\r\nSET k v\r\nGET k\r\n")
+            .await
+            .unwrap();
+        assert_eq!(pipelined.read_reply().await, Reply::ok());
+        assert_eq!(pipelined.read_reply().await, Reply::ok());
+        assert_eq!(pipelined.read_reply().await, bulk("v"));
+
+        stop.send(()).unwrap();
+    }
+}
@@ -0,0 +1,561 @@
+//! An HTTP/JSON gateway to the key-value API
+//!
+//! For clients that can't speak gRPC easily, such as `curl`, browsers or
+//! serverless functions:
+//!
+//! | Request                   | Body                | Response                            |
+//! |---------------------------|---------------------|-------------------------------------|
+//! | `GET /v1/keys/{key}`      |                     | `{"key": .., "value": ..}`, or 404  |
+//! | `PUT /v1/keys/{key}`      | the value, raw      | 204                                 |
+//! | `DELETE /v1/keys/{key}`   |                     | 204                                 |
+//! | `GET /v1/keys`            |                     | `{"pairs": [..], "continuation": ..}` |
+//!
+//! Query parameters:
+//!
+//! - `cf`: column family, the default one if unset
+//! - `encoding`: `utf8` (default) or `base64`, how keys in paths and
+//!   parameters and keys and values in responses are written. Binary data
+//!   needs `base64`, the URL-safe alphabet with optional padding; with
+//!   `utf8` a response that isn't valid UTF-8 fails with 422
+//! - `sync`: `true` to sync the WAL before a write is acknowledged
+//! - for scans, `start`, `end`, `prefix` and `limit` as in the gRPC
+//!   `Scan` call, and `continuation` from the previous page
+//!
+//! Requests authenticate with `Authorization: Bearer <token>` and one of
+//! the API tokens, like `KeyValue` calls. Errors come back as
+//! `{"error": message}` with the status code matching the gRPC status of
+//! the same failure, so requests over the server's quotas get 429. The
+//! gateway speaks plaintext HTTP/1.1 only, so like
+//! the Redis frontend it listens beyond loopback only with
+//! `allow_plaintext`.
+
+use crate::auth::TokenAuth;
+use crate::proto::ScanRequest;
+use crate::quota::{Meter, Quotas};
+use crate::scan::{self, ScanPlan};
+use crate::service::{resolve_column_family, run_blocking};
+use crate::Result;
+use ferrisdb_storage::{StorageEngine, WriteBatch};
+
+use axum::body::Bytes;
+use axum::extract::rejection::{PathRejection, QueryRejection};
+use axum::extract::{ConnectInfo, Extension, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use base64::alphabet::URL_SAFE;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tonic::{Code, Status};
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// URL-safe base64, which fits in paths and query strings unescaped
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Serves the HTTP gateway over a storage engine
+pub struct RestGateway {
+    gateway: Gateway,
+    auth: TokenAuth,
+}
+
+#[derive(Clone)]
+struct Gateway {
+    engine: Arc<StorageEngine>,
+    max_scan_limit: u32,
+    quotas: Arc<Quotas>,
+}
+
+impl RestGateway {
+    /// Creates a gateway whose scans return at most `max_scan_limit` pairs
+    /// per page, admitting requests as `auth` does
+    pub fn new(engine: Arc<StorageEngine>, max_scan_limit: u32, auth: TokenAuth) -> Self {
+        Self {
+            gateway: Gateway {
+                engine,
+                max_scan_limit,
+                quotas: Arc::new(Quotas::unlimited()),
+            },
+            auth,
+        }
+    }
+
+    /// Holds requests to `quotas`, which are unlimited by default
+    pub fn with_quotas(mut self, quotas: Arc<Quotas>) -> Self {
+        self.gateway.quotas = quotas;
+        self
+    }
+
+    /// Returns the routes of the gateway
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/v1/keys", get(scan_keys))
+            .route(
+                "/v1/keys/{*key}",
+                get(get_key).put(put_key).delete(delete_key),
+            )
+            .layer(middleware::from_fn_with_state(self.gateway.clone(), admit))
+            .with_state(self.gateway.clone())
+            .layer(middleware::from_fn_with_state(self.auth.clone(), authorize))
+    }
+
+    /// Serves on `listener` until `shutdown` completes and in-flight
+    /// requests finish
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if accepting connections fails.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<()> {
+        log::info!("Serving HTTP on {}", listener.local_addr()?);
+        let router = self.router();
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A failed request, answered as `{"error": message}`
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        let code = match status.code() {
+            Code::Ok => StatusCode::OK,
+            Code::InvalidArgument | Code::OutOfRange => StatusCode::BAD_REQUEST,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::AlreadyExists | Code::Aborted | Code::FailedPrecondition => StatusCode::CONFLICT,
+            Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+            Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+            Code::Cancelled | Code::Unknown | Code::Internal | Code::DataLoss => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        Self {
+            status: code,
+            message: status.message().to_string(),
+        }
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Status::invalid_argument(rejection.body_text()).into()
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Status::invalid_argument(rejection.body_text()).into()
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: self.message,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+/// How binary keys and values are written as text
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
+impl Encoding {
+    fn decode(self, name: &str, text: &str) -> ApiResult<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Base64 => BASE64.decode(text).map_err(|_| {
+                Status::invalid_argument(format!("{} is not valid base64", name)).into()
+            }),
+        }
+    }
+
+    fn encode(self, data: Vec<u8>) -> ApiResult<String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(data).map_err(|_| ApiError {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                message: "Data is not valid UTF-8, request it with encoding=base64".to_string(),
+            }),
+            Encoding::Base64 => Ok(BASE64.encode(data)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KeyParams {
+    cf: String,
+    encoding: Encoding,
+    sync: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScanParams {
+    cf: String,
+    encoding: Encoding,
+    start: String,
+    end: String,
+    prefix: String,
+    limit: u32,
+    continuation: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Pair {
+    key: String,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ScanPage {
+    pairs: Vec<Pair>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuation: Option<String>,
+}
+
+/// Rejects requests without one of the scope's tokens
+async fn authorize(State(auth): State<TokenAuth>, request: Request, next: Next) -> Response {
+    let header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    match auth.check(header) {
+        Ok(()) => next.run(request).await,
+        Err(status) => ApiError::from(status).into_response(),
+    }
+}
+
+/// Admits requests within the quotas, passing their [`Meter`] on
+///
+/// A request is charged its path, query and declared body length.
+async fn admit(State(gateway): State<Gateway>, mut request: Request, next: Next) -> Response {
+    let connection = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let body = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<usize>().ok())
+        .unwrap_or(0);
+    let bytes = request
+        .uri()
+        .path_and_query()
+        .map_or(0, |uri| uri.as_str().len())
+        + body;
+    match gateway.quotas.admit(connection, bytes) {
+        Ok(meter) => {
+            request.extensions_mut().insert(meter);
+            next.run(request).await
+        }
+        Err(status) => ApiError::from(status).into_response(),
+    }
+}
+
+async fn get_key(
+    State(gateway): State<Gateway>,
+    Extension(meter): Extension<Meter>,
+    key: std::result::Result<Path<String>, PathRejection>,
+    params: std::result::Result<Query<KeyParams>, QueryRejection>,
+) -> ApiResult<Json<Pair>> {
+    let (Path(key), Query(params)) = (key?, params?);
+    let cf = resolve_column_family(&gateway.engine, &params.cf)?;
+    let key = params.encoding.decode("key", &key)?;
+    let lookup = key.clone();
+    let value = run_blocking(&gateway.engine, move |engine| engine.get_cf(&cf, &lookup))
+        .await?
+        .ok_or_else(|| Status::not_found("Key not found"))?;
+    meter.charge(value.len());
+    Ok(Json(Pair {
+        key: params.encoding.encode(key)?,
+        value: params.encoding.encode(value)?,
+    }))
+}
+
+async fn put_key(
+    State(gateway): State<Gateway>,
+    key: std::result::Result<Path<String>, PathRejection>,
+    params: std::result::Result<Query<KeyParams>, QueryRejection>,
+    value: Bytes,
+) -> ApiResult<StatusCode> {
+    let (Path(key), Query(params)) = (key?, params?);
+    let mut batch = WriteBatch::new();
+    batch.put_cf(
+        &resolve_column_family(&gateway.engine, &params.cf)?,
+        params.encoding.decode("key", &key)?,
+        value.to_vec(),
+    );
+    run_blocking(&gateway.engine, move |engine| {
+        engine.write(batch, params.sync)
+    })
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_key(
+    State(gateway): State<Gateway>,
+    key: std::result::Result<Path<String>, PathRejection>,
+    params: std::result::Result<Query<KeyParams>, QueryRejection>,
+) -> ApiResult<StatusCode> {
+    let (Path(key), Query(params)) = (key?, params?);
+    let mut batch = WriteBatch::new();
+    batch.delete_cf(
+        &resolve_column_family(&gateway.engine, &params.cf)?,
+        params.encoding.decode("key", &key)?,
+    );
+    run_blocking(&gateway.engine, move |engine| {
+        engine.write(batch, params.sync)
+    })
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn scan_keys(
+    State(gateway): State<Gateway>,
+    Extension(meter): Extension<Meter>,
+    params: std::result::Result<Query<ScanParams>, QueryRejection>,
+) -> ApiResult<Json<ScanPage>> {
+    let Query(params) = params?;
+    let cf = resolve_column_family(&gateway.engine, &params.cf)?;
+    let encoding = params.encoding;
+    let request = ScanRequest {
+        column_family: params.cf,
+        start: encoding.decode("start", &params.start)?,
+        end: encoding.decode("end", &params.end)?,
+        prefix: encoding.decode("prefix", &params.prefix)?,
+        limit: params.limit,
+        continuation_token: BASE64
+            .decode(&params.continuation)
+            .map_err(|_| Status::invalid_argument("Malformed continuation token"))?,
+    };
+    let plan = ScanPlan::new(&request, gateway.max_scan_limit)?;
+
+    // The page is collected from the same chunked scan gRPC streams
+    let snapshot = gateway.engine.snapshot();
+    let (responses, mut chunks) = scan::channel();
+    tokio::task::spawn_blocking(move || scan::run(snapshot, cf, plan, meter, responses));
+    let mut pairs = Vec::new();
+    let mut continuation = None;
+    while let Some(chunk) = chunks.recv().await {
+        let chunk = chunk?;
+        for pair in chunk.pairs {
+            pairs.push(Pair {
+                key: encoding.encode(pair.key)?,
+                value: encoding.encode(pair.value)?,
+            });
+        }
+        if !chunk.continuation_token.is_empty() {
+            continuation = Some(BASE64.encode(chunk.continuation_token));
+        }
+    }
+    Ok(Json(ScanPage {
+        pairs,
+        continuation,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Method;
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    /// Sends a request, returning the status and the JSON body if any
+    async fn send(
+        router: &Router,
+        method: Method,
+        uri: &str,
+        body: &[u8],
+        token: Option<&str>,
+    ) -> (StatusCode, Value) {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request.body(Body::from(body.to_vec())).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&body).unwrap()
+        };
+        (status, body)
+    }
+
+    fn gateway(dir: &TempDir, auth: TokenAuth) -> (Arc<StorageEngine>, Router) {
+        let engine =
+            Arc::new(StorageEngine::open(ferrisdb_storage::Options::new(dir.path())).unwrap());
+        let gateway = RestGateway::new(Arc::clone(&engine), 3, auth);
+        (engine, gateway.router())
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_read_written_and_scanned_over_http() {
+        let dir = TempDir::new().unwrap();
+        let (engine, router) = gateway(&dir, TokenAuth::open("api"));
+        let token = None;
+
+        let (status, _) = send(&router, Method::PUT, "/v1/keys/user/1", b"alice", token).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(engine.get(b"user/1").unwrap(), Some(b"alice".to_vec()));
+        let (status, body) = send(&router, Method::GET, "/v1/keys/user/1", b"", token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"key": "user/1", "value": "alice"}));
+
+        // Percent-encoded and base64 keys reach the engine as bytes
+        send(
+            &router,
+            Method::PUT,
+            "/v1/keys/a%20b?sync=true",
+            b"1",
+            token,
+        )
+        .await;
+        assert_eq!(engine.get(b"a b").unwrap(), Some(b"1".to_vec()));
+        engine.put(vec![0xff, 0x00], vec![0x80]).unwrap();
+        let (status, _) = send(&router, Method::GET, "/v1/keys/_wA", b"", token).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = send(
+            &router,
+            Method::GET,
+            "/v1/keys/_wA?encoding=base64",
+            b"",
+            token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"key": "_wA", "value": "gA"}));
+
+        let (status, body) = send(&router, Method::GET, "/v1/keys", b"", token).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].as_str().unwrap().contains("base64"));
+
+        // Pages of three continue where the last one stopped
+        for i in 0..5 {
+            let uri = format!("/v1/keys/item{}", i);
+            send(&router, Method::PUT, &uri, b"v", token).await;
+        }
+        let (_, page) = send(&router, Method::GET, "/v1/keys?prefix=item", b"", token).await;
+        let keys: Vec<_> = page["pairs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["key"].clone())
+            .collect();
+        assert_eq!(keys, [json!("item0"), json!("item1"), json!("item2")]);
+        let uri = format!(
+            "/v1/keys?prefix=item&continuation={}",
+            page["continuation"].as_str().unwrap()
+        );
+        let (_, page) = send(&router, Method::GET, &uri, b"", token).await;
+        let keys: Vec<_> = page["pairs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["key"].clone())
+            .collect();
+        assert_eq!(keys, [json!("item3"), json!("item4")]);
+        assert!(page.get("continuation").is_none());
+
+        let (status, _) = send(&router, Method::DELETE, "/v1/keys/item0", b"", token).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(engine.get(b"item0").unwrap(), None);
+
+        // Bad requests fail with JSON errors
+        for (uri, expected) in [
+            ("/v1/keys/a?cf=missing", StatusCode::NOT_FOUND),
+            ("/v1/keys/a?unknown=1", StatusCode::BAD_REQUEST),
+            ("/v1/keys/!?encoding=base64", StatusCode::BAD_REQUEST),
+            ("/v1/keys?continuation=*", StatusCode::BAD_REQUEST),
+        ] {
+            let (status, body) = send(&router, Method::GET, uri, b"", token).await;
+            assert_eq!(status, expected, "{}", uri);
+            assert!(body["error"].is_string(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_need_an_api_token() {
+        let dir = TempDir::new().unwrap();
+        let (_engine, router) = gateway(&dir, TokenAuth::new("api", ["s3cret"]));
+
+        let (status, body) = send(&router, Method::GET, "/v1/keys/a", b"", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body["error"].as_str().unwrap().contains("bearer token"));
+        let (status, _) = send(&router, Method::PUT, "/v1/keys/a", b"1", Some("guess")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&router, Method::PUT, "/v1/keys/a", b"1", Some("s3cret")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_requests_over_the_quota_get_429() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            Arc::new(StorageEngine::open(ferrisdb_storage::Options::new(dir.path())).unwrap());
+        let quotas = Quotas::new(&crate::config::QuotaSettings {
+            requests_per_sec: Some(2),
+            ..Default::default()
+        });
+        let router = RestGateway::new(engine, 3, TokenAuth::open("api"))
+            .with_quotas(Arc::new(quotas))
+            .router();
+
+        let (status, _) = send(&router, Method::PUT, "/v1/keys/a", b"1", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&router, Method::GET, "/v1/keys/a", b"", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&router, Method::GET, "/v1/keys/a", b"", None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(body["error"].as_str().unwrap().contains("quota"));
+    }
+}
@@ -0,0 +1,217 @@
+//! Streaming scans with prefixes, limits and continuation tokens
+//!
+//! A scan reads its range from one engine snapshot in chunks of
+//! [`CHUNK_SIZE`] pairs, each sent as its own response, so neither the
+//! server nor the client holds the whole range in memory and concurrent
+//! writes never show up half way through a call.
+//!
+//! A call that stops at its limit hands out a continuation token naming
+//! the last key sent:
+//!
+//! ```text
+//! [version: 1 byte][last key]
+//! ```
+//!
+//! Resuming with the token continues right after that key. The resumed
+//! call reads from a fresh snapshot, since the snapshot of the first one
+//! is released when it ends.
+
+use crate::proto::{KeyValuePair, ScanRequest, ScanResponse};
+use crate::quota::Meter;
+use crate::service::status_from_error;
+use ferrisdb_core::keys::prefix_successor;
+use ferrisdb_core::Key;
+use ferrisdb_storage::{ColumnFamily, Snapshot};
+
+use tokio::sync::mpsc;
+use tonic::Status;
+
+use std::ops::Bound;
+
+/// Pairs sent per response
+pub(crate) const CHUNK_SIZE: usize = 256;
+
+/// Responses buffered ahead of a slow client
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Leading byte of the current token format
+const TOKEN_VERSION: u8 = 1;
+
+/// One message of a scan stream
+pub(crate) type ScanItem = Result<ScanResponse, Status>;
+
+/// The key range and limit of a scan call
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ScanPlan {
+    start: Bound<Key>,
+    end: Bound<Key>,
+    limit: usize,
+}
+
+impl ScanPlan {
+    /// Works out what a request reads, given the server's limit
+    // Handlers return `Status` by value, so this does too
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn new(request: &ScanRequest, max_limit: u32) -> Result<Self, Status> {
+        let mut start = Bound::Included(request.start.clone());
+        let mut end = if request.end.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(request.end.clone())
+        };
+
+        if !request.prefix.is_empty() {
+            if request.prefix > request.start {
+                start = Bound::Included(request.prefix.clone());
+            }
+            if let Some(successor) = prefix_successor(&request.prefix) {
+                if request.end.is_empty() || successor < request.end {
+                    end = Bound::Excluded(successor);
+                }
+            }
+        }
+
+        if !request.continuation_token.is_empty() {
+            let last = decode_token(&request.continuation_token)?;
+            let after_start = match &start {
+                Bound::Included(start) => last >= *start,
+                _ => true,
+            };
+            if !after_start {
+                return Err(Status::invalid_argument(
+                    "Continuation token is outside the scanned range",
+                ));
+            }
+            start = Bound::Excluded(last);
+        }
+
+        let limit = match request.limit {
+            0 => max_limit,
+            limit => limit.min(max_limit),
+        };
+        Ok(Self {
+            start,
+            end,
+            limit: limit as usize,
+        })
+    }
+}
+
+/// Streams the pairs of `plan` from `snapshot` into `responses`
+///
+/// Runs on a blocking thread until the scan is done, fails, or the client
+/// goes away. The pairs sent are charged to `meter`.
+pub(crate) fn run(
+    snapshot: Snapshot,
+    cf: ColumnFamily,
+    plan: ScanPlan,
+    meter: Meter,
+    responses: mpsc::Sender<ScanItem>,
+) {
+    let mut start = plan.start;
+    let mut remaining = plan.limit;
+    loop {
+        // Reading one pair past the chunk tells whether more remain
+        let wanted = remaining.min(CHUNK_SIZE);
+        let mut pairs =
+            match snapshot.scan_cf_limit(&cf, (start.clone(), plan.end.clone()), wanted + 1) {
+                Ok(pairs) => pairs,
+                Err(e) => {
+                    let _ = responses.blocking_send(Err(status_from_error(e)));
+                    return;
+                }
+            };
+        let more = pairs.len() > wanted;
+        pairs.truncate(wanted);
+        remaining -= pairs.len();
+        meter.charge(
+            pairs
+                .iter()
+                .map(|(key, value)| key.len() + value.len())
+                .sum(),
+        );
+
+        let last = pairs.last().map(|(key, _)| key.clone());
+        let done = !more || remaining == 0;
+        let continuation_token = match &last {
+            Some(last) if more && remaining == 0 => encode_token(last),
+            _ => Vec::new(),
+        };
+        let response = ScanResponse {
+            pairs: pairs
+                .into_iter()
+                .map(|(key, value)| KeyValuePair { key, value })
+                .collect(),
+            continuation_token,
+        };
+        // An empty range still gets one response
+        if responses.blocking_send(Ok(response)).is_err() || done {
+            return;
+        }
+        start = Bound::Excluded(last.expect("a chunk with more to come has pairs"));
+    }
+}
+
+/// Creates the channel responses are streamed through
+pub(crate) fn channel() -> (mpsc::Sender<ScanItem>, mpsc::Receiver<ScanItem>) {
+    mpsc::channel(CHANNEL_CAPACITY)
+}
+
+fn encode_token(last_key: &[u8]) -> Vec<u8> {
+    let mut token = Vec::with_capacity(1 + last_key.len());
+    token.push(TOKEN_VERSION);
+    token.extend_from_slice(last_key);
+    token
+}
+
+#[allow(clippy::result_large_err)]
+fn decode_token(token: &[u8]) -> Result<Key, Status> {
+    match token.split_first() {
+        Some((&TOKEN_VERSION, last_key)) => Ok(last_key.to_vec()),
+        _ => Err(Status::invalid_argument("Malformed continuation token")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(start: &[u8], end: &[u8], prefix: &[u8]) -> ScanRequest {
+        ScanRequest {
+            start: start.to_vec(),
+            end: end.to_vec(),
+            prefix: prefix.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_intersects_bounds_with_prefix() {
+        let plan = ScanPlan::new(&request(b"", b"", b"user:"), 10).unwrap();
+        assert_eq!(plan.start, Bound::Included(b"user:".to_vec()));
+        assert_eq!(plan.end, Bound::Excluded(b"user;".to_vec()));
+        assert_eq!(plan.limit, 10);
+
+        let plan = ScanPlan::new(&request(b"user:5", b"user:7", b"user:"), 10).unwrap();
+        assert_eq!(plan.start, Bound::Included(b"user:5".to_vec()));
+        assert_eq!(plan.end, Bound::Excluded(b"user:7".to_vec()));
+
+        let plan = ScanPlan::new(&request(b"", b"", b"\xff"), 10).unwrap();
+        assert_eq!(plan.end, Bound::Unbounded);
+    }
+
+    #[test]
+    fn test_plan_resumes_after_token() {
+        let mut resumed = request(b"a", b"", b"");
+        resumed.continuation_token = encode_token(b"m");
+        resumed.limit = 50;
+        let plan = ScanPlan::new(&resumed, 10).unwrap();
+        assert_eq!(plan.start, Bound::Excluded(b"m".to_vec()));
+        assert_eq!(plan.limit, 10);
+
+        resumed.continuation_token = encode_token(b"0");
+        assert!(ScanPlan::new(&resumed, 10).is_err());
+        resumed.continuation_token = vec![99, b'm'];
+        assert!(ScanPlan::new(&resumed, 10).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! The `KeyValue` gRPC service backed by a storage engine
+//!
+//! Engine calls block on disk I/O and locks, so each runs on Tokio's
+//! blocking thread pool rather than on the async workers serving
+//! connections. Scans stream from a snapshot, see [`crate::scan`].
+//! Every call is admitted by the server's [`Quotas`] first.
+//!
+//! On a server of a Raft cluster, writes are proposed to the
+//! [`RaftNode`] instead, and return once a majority committed them and
+//! this server applied them; reads are still served from the local
+//! engine.
+
+use crate::proto::key_value_server::KeyValue;
+use crate::proto::{
+    mutation, BatchWriteRequest, BatchWriteResponse, DeleteRequest, DeleteResponse, GetRequest,
+    GetResponse, MultiGetRequest, MultiGetResponse, Mutation, PutRequest, PutResponse, ScanRequest,
+};
+use crate::quota::{Meter, Quotas};
+use crate::raft::{self, RaftNode};
+use crate::scan::{self, ScanItem, ScanPlan};
+use ferrisdb_core::error::{ErrorCode, ERROR_CODE_METADATA};
+use ferrisdb_core::Error;
+use ferrisdb_storage::storage_engine::DEFAULT_COLUMN_FAMILY;
+use ferrisdb_storage::{ColumnFamily, StorageEngine, WriteBatch};
+
+use prost::Message;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use std::sync::Arc;
+
+/// Serves reads and writes of one storage engine
+#[derive(Clone)]
+pub struct KeyValueService {
+    engine: Arc<StorageEngine>,
+    max_scan_limit: u32,
+    quotas: Arc<Quotas>,
+    raft: Option<Arc<RaftNode>>,
+}
+
+impl KeyValueService {
+    /// Creates a service whose scans return at most `max_scan_limit` pairs
+    pub fn new(engine: Arc<StorageEngine>, max_scan_limit: u32) -> Self {
+        Self {
+            engine,
+            max_scan_limit,
+            quotas: Arc::new(Quotas::unlimited()),
+            raft: None,
+        }
+    }
+
+    /// Holds calls to `quotas`, which are unlimited by default
+    pub fn with_quotas(mut self, quotas: Arc<Quotas>) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Replicates writes through `raft` rather than writing to the engine
+    pub fn with_raft(mut self, raft: Arc<RaftNode>) -> Self {
+        self.raft = Some(raft);
+        self
+    }
+
+    /// Admits a call from its connection and size
+    // Handlers return `Status` by value, so this does too
+    #[allow(clippy::result_large_err)]
+    fn admit<T: Message>(&self, request: &Request<T>) -> Result<Meter, Status> {
+        self.quotas
+            .admit(request.remote_addr(), request.get_ref().encoded_len())
+    }
+
+    /// Resolves a column family name from a request, empty meaning the default
+    // Handlers return `Status` by value, so this does too
+    #[allow(clippy::result_large_err)]
+    fn column_family(&self, name: &str) -> Result<ColumnFamily, Status> {
+        resolve_column_family(&self.engine, name)
+    }
+
+    /// Runs `f` with the engine on the blocking thread pool
+    async fn blocking<T, F>(&self, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&StorageEngine) -> ferrisdb_core::Result<T> + Send + 'static,
+    {
+        run_blocking(&self.engine, f).await
+    }
+
+    /// Writes `mutations` atomically, through the Raft log if there is one
+    async fn write(&self, mutations: Vec<Mutation>, sync: bool) -> Result<(), Status> {
+        match &self.raft {
+            Some(raft) => {
+                // Checked up front, so a bad request takes no log entry
+                check_mutations(&self.engine, &mutations)?;
+                raft.write(BatchWriteRequest { mutations, sync }).await
+            }
+            None => {
+                let batch = write_batch(&self.engine, mutations)?;
+                self.blocking(move |engine| engine.write(batch, sync)).await
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl KeyValue for KeyValueService {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let meter = self.admit(&request)?;
+        let request = request.into_inner();
+        let cf = self.column_family(&request.column_family)?;
+        let value = self
+            .blocking(move |engine| engine.get_cf(&cf, &request.key))
+            .await?;
+        meter.charge(value.as_ref().map_or(0, Vec::len));
+        Ok(Response::new(GetResponse { value }))
+    }
+
+    async fn multi_get(
+        &self,
+        request: Request<MultiGetRequest>,
+    ) -> Result<Response<MultiGetResponse>, Status> {
+        let meter = self.admit(&request)?;
+        let request = request.into_inner();
+        let cf = self.column_family(&request.column_family)?;
+        let values = self
+            .blocking(move |engine| engine.multi_get_cf(&cf, &request.keys))
+            .await?;
+        meter.charge(values.iter().flatten().map(Vec::len).sum());
+        let values = values
+            .into_iter()
+            .map(|value| GetResponse { value })
+            .collect();
+        Ok(Response::new(MultiGetResponse { values }))
+    }
+
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        let put = mutation::Put {
+            key: request.key,
+            value: request.value,
+        };
+        let mutation = Mutation {
+            column_family: request.column_family,
+            op: Some(mutation::Op::Put(put)),
+        };
+        self.write(vec![mutation], request.sync).await?;
+        Ok(Response::new(PutResponse {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        let mutation = Mutation {
+            column_family: request.column_family,
+            op: Some(mutation::Op::Delete(mutation::Delete { key: request.key })),
+        };
+        self.write(vec![mutation], request.sync).await?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    async fn batch_write(
+        &self,
+        request: Request<BatchWriteRequest>,
+    ) -> Result<Response<BatchWriteResponse>, Status> {
+        self.admit(&request)?;
+        let request = request.into_inner();
+        self.write(request.mutations, request.sync).await?;
+        Ok(Response::new(BatchWriteResponse {}))
+    }
+
+    type ScanStream = ReceiverStream<ScanItem>;
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        let meter = self.admit(&request)?;
+        let request = request.into_inner();
+        let cf = self.column_family(&request.column_family)?;
+        let plan = ScanPlan::new(&request, self.max_scan_limit)?;
+
+        // Taken before returning so the scan sees the writes acknowledged
+        // before the call
+        let snapshot = self.engine.snapshot();
+        let (responses, stream) = scan::channel();
+        tokio::task::spawn_blocking(move || scan::run(snapshot, cf, plan, meter, responses));
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+/// Resolves a column family name from a request, empty meaning the default
+///
+/// The Raft log's family is reserved, so requests can't reach it.
+#[allow(clippy::result_large_err)]
+pub(crate) fn resolve_column_family(
+    engine: &StorageEngine,
+    name: &str,
+) -> Result<ColumnFamily, Status> {
+    let name = if name.is_empty() {
+        DEFAULT_COLUMN_FAMILY
+    } else {
+        name
+    };
+    if name == raft::COLUMN_FAMILY {
+        return Err(Status::invalid_argument(format!(
+            "Column family {:?} is reserved for the Raft log",
+            name
+        )));
+    }
+    engine
+        .cf_handle(name)
+        .ok_or_else(|| Status::not_found(format!("Column family {:?} does not exist", name)))
+}
+
+/// Checks that every mutation names an existing column family and an
+/// operation, as [`write_batch`] does, and that merges go to families
+/// with a merge operator, as the engine does on writing
+#[allow(clippy::result_large_err)]
+pub(crate) fn check_mutations(
+    engine: &StorageEngine,
+    mutations: &[Mutation],
+) -> Result<(), Status> {
+    for mutation in mutations {
+        let cf = resolve_column_family(engine, &mutation.column_family)?;
+        match mutation.op {
+            None => return Err(Status::invalid_argument("Mutation has no operation")),
+            Some(mutation::Op::Merge(_)) if !cf.has_merge_operator() => {
+                return Err(Status::failed_precondition(format!(
+                    "Column family {:?} has no merge operator",
+                    cf.name()
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Builds the batch writing `mutations`
+#[allow(clippy::result_large_err)]
+pub(crate) fn write_batch(
+    engine: &StorageEngine,
+    mutations: Vec<Mutation>,
+) -> Result<WriteBatch, Status> {
+    let mut batch = WriteBatch::new();
+    for mutation in mutations {
+        let cf = resolve_column_family(engine, &mutation.column_family)?;
+        match mutation.op {
+            Some(mutation::Op::Put(put)) => batch.put_cf(&cf, put.key, put.value),
+            Some(mutation::Op::Delete(delete)) => batch.delete_cf(&cf, delete.key),
+            Some(mutation::Op::Merge(merge)) => batch.merge_cf(&cf, merge.key, merge.operand),
+            Some(mutation::Op::DeleteRange(range)) => {
+                batch.delete_range_cf(&cf, range.start.as_slice()..range.end.as_slice())
+            }
+            None => return Err(Status::invalid_argument("Mutation has no operation")),
+        }
+    }
+    Ok(batch)
+}
+
+/// Runs `f` with the engine on the blocking thread pool
+pub(crate) async fn run_blocking<T, F>(engine: &Arc<StorageEngine>, f: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce(&StorageEngine) -> ferrisdb_core::Result<T> + Send + 'static,
+{
+    let engine = Arc::clone(engine);
+    tokio::task::spawn_blocking(move || f(&engine))
+        .await
+        .map_err(|e| Status::internal(format!("Request task failed: {}", e)))?
+        .map_err(status_from_error)
+}
+
+/// Maps an engine error to the gRPC status a client sees
+///
+/// The status also carries the error's stable code under
+/// [`ERROR_CODE_METADATA`], which is finer grained than the gRPC code.
+pub(crate) fn status_from_error(error: Error) -> Status {
+    let message = error.to_string();
+    let code = error.code();
+    let mut status = match code {
+        ErrorCode::NotFound => Status::not_found(message),
+        ErrorCode::Corruption => Status::data_loss(message),
+        ErrorCode::Aborted => Status::aborted(message),
+        ErrorCode::FailedPrecondition => Status::failed_precondition(message),
+        ErrorCode::InvalidArgument => Status::invalid_argument(message),
+        ErrorCode::Busy => Status::unavailable(message),
+        ErrorCode::DiskFull | ErrorCode::LimitExceeded => Status::resource_exhausted(message),
+        ErrorCode::Unsupported => Status::unimplemented(message),
+        ErrorCode::Internal | ErrorCode::Io => Status::internal(message),
+    };
+    status
+        .metadata_mut()
+        .insert(ERROR_CODE_METADATA, code.as_u16().into());
+    status
+}
@@ -0,0 +1,29 @@
+#!/usr/bin/env bash
+# Regenerates the certificates the server tests use: a CA, a server
+# certificate for localhost and a client certificate, all signed by the
+# CA and valid for 100 years. Never use these outside tests.
+set -euo pipefail
+cd "$(dirname "$0")"
+
+days=36500
+ec() { openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1"; }
+
+ec ca.key
+openssl req -x509 -new -key ca.key -days "$days" -subj "/CN=FerrisDB Test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" -out ca.pem
+
+sign() {
+    local name=$1 cn=$2 ext=$3
+    ec "$name.key"
+    openssl req -new -key "$name.key" -subj "/CN=$cn" -out "$name.csr"
+    openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial \
+        -days "$days" -extfile <(printf '%s\n' "basicConstraints=critical,CA:FALSE" "$ext") \
+        -out "$name.pem"
+    rm "$name.csr"
+}
+
+sign server localhost "subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=serverAuth"
+sign client ferrisdb-test-client "extendedKeyUsage=clientAuth"
+rm -f ca.key ca.srl
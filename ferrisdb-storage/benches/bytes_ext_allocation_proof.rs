@@ -5,10 +5,11 @@
 //! avoids memory allocations after initial buffer creation.
 
 use bytes::BytesMut;
-use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use ferrisdb_storage::utils::BytesMutExt;
 use stats_alloc::{Region, StatsAlloc, INSTRUMENTED_SYSTEM};
 use std::alloc::System;
+use std::hint::black_box;
 use std::io::{Cursor, Read};
 
 #[global_allocator]
@@ -21,7 +22,7 @@ const LARGE_SIZE: usize = 1024 * 1024; // 1MB
 
 /// Standard approach: pre-allocate and zero-fill buffer
 fn read_with_standard_approach(data: &[u8], size: usize) -> (BytesMut, usize) {
-    let region = Region::new(&GLOBAL);
+    let region = Region::new(GLOBAL);
 
     let mut buf = BytesMut::new();
     buf.resize(size, 0); // This zeros the memory
@@ -35,7 +36,7 @@ fn read_with_standard_approach(data: &[u8], size: usize) -> (BytesMut, usize) {
 
 /// Optimized approach: use BytesMutExt
 fn read_with_bytes_mut_ext(data: &[u8], size: usize) -> (BytesMut, usize) {
-    let region = Region::new(&GLOBAL);
+    let region = Region::new(GLOBAL);
 
     let mut buf = BytesMut::new();
     let mut reader = Cursor::new(data);
@@ -63,7 +64,7 @@ fn benchmark_zero_allocation_proof(c: &mut Criterion) {
             },
             |(mut buf, data)| {
                 // This should not allocate since capacity is sufficient
-                let region = Region::new(&GLOBAL);
+                let region = Region::new(GLOBAL);
                 let mut reader = Cursor::new(&data);
                 buf.read_exact_from(&mut reader, MEDIUM_SIZE).unwrap();
                 let stats = region.change();
@@ -96,7 +97,7 @@ fn benchmark_allocation_comparison(c: &mut Criterion) {
             _ => "unknown",
         };
 
-        group.bench_function(&format!("standard_approach_{}", name), |b| {
+        group.bench_function(format!("standard_approach_{}", name), |b| {
             let data = vec![42u8; size];
             b.iter(|| {
                 let (buf, allocs) = read_with_standard_approach(&data, size);
@@ -105,7 +106,7 @@ fn benchmark_allocation_comparison(c: &mut Criterion) {
             });
         });
 
-        group.bench_function(&format!("bytes_mut_ext_{}", name), |b| {
+        group.bench_function(format!("bytes_mut_ext_{}", name), |b| {
             let data = vec![42u8; size];
             b.iter(|| {
                 let (buf, allocs) = read_with_bytes_mut_ext(&data, size);
@@ -141,7 +142,7 @@ fn benchmark_sequential_zero_allocations(c: &mut Criterion) {
                 buf
             },
             |mut buf| {
-                let region = Region::new(&GLOBAL);
+                let region = Region::new(GLOBAL);
                 let mut reader = Cursor::new(&data);
 
                 // Read chunks sequentially - should not allocate
@@ -177,7 +178,7 @@ fn benchmark_capacity_growth_allocations(c: &mut Criterion) {
         b.iter_batched(
             || data.clone(),
             |data| {
-                let region = Region::new(&GLOBAL);
+                let region = Region::new(GLOBAL);
 
                 // Start with small buffer that will need to grow
                 let mut buf = BytesMut::with_capacity(64);
@@ -217,14 +218,14 @@ fn benchmark_allocation_efficiency_proof(c: &mut Criterion) {
             || data.clone(),
             |data| {
                 // Test standard approach
-                let region1 = Region::new(&GLOBAL);
+                let region1 = Region::new(GLOBAL);
                 let mut buf1 = BytesMut::new();
                 buf1.resize(MEDIUM_SIZE, 0);
                 Cursor::new(&data).read_exact(&mut buf1[..]).unwrap();
                 let std_stats = region1.change();
 
                 // Test BytesMutExt approach
-                let region2 = Region::new(&GLOBAL);
+                let region2 = Region::new(GLOBAL);
                 let mut buf2 = BytesMut::new();
                 buf2.read_exact_from(&mut Cursor::new(&data), MEDIUM_SIZE)
                     .unwrap();
@@ -4,8 +4,9 @@
 //! versus the standard approach of pre-allocating and zeroing a buffer.
 
 use bytes::BytesMut;
-use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use ferrisdb_storage::utils::BytesMutExt;
+use std::hint::black_box;
 use std::io::{Cursor, Read};
 
 // Benchmark data sizes
@@ -107,7 +108,7 @@ fn benchmark_sequential_reads(c: &mut Criterion) {
 
     group.bench_function("standard_approach", |b| {
         b.iter_batched(
-            || BytesMut::new(),
+            BytesMut::new,
             |mut buf| {
                 let mut reader = Cursor::new(&data);
                 for _ in 0..num_chunks {
@@ -123,7 +124,7 @@ fn benchmark_sequential_reads(c: &mut Criterion) {
 
     group.bench_function("bytes_mut_ext", |b| {
         b.iter_batched(
-            || BytesMut::new(),
+            BytesMut::new,
             |mut buf| {
                 let mut reader = Cursor::new(&data);
                 for _ in 0..num_chunks {
@@ -0,0 +1,106 @@
+//! Benchmarks comparing MemTable index implementations
+//!
+//! The hash index claims faster point operations than the skip list in
+//! exchange for expensive range scans. These benchmarks measure both sides
+//! of that trade-off.
+
+use ferrisdb_storage::memtable::MemTable;
+use ferrisdb_storage::MemTableKind;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use std::hint::black_box;
+
+const ENTRY_COUNT: usize = 10_000;
+
+fn kinds() -> [(&'static str, MemTableKind); 2] {
+    [
+        ("skip_list", MemTableKind::SkipList),
+        ("hash_index", MemTableKind::HashIndex),
+    ]
+}
+
+fn filled(kind: MemTableKind) -> MemTable {
+    let memtable = MemTable::with_kind(64 * 1024 * 1024, kind);
+    for i in 0..ENTRY_COUNT {
+        let key = format!("key_{:06}", i).into_bytes();
+        memtable.put(key, vec![b'v'; 100], i as u64).unwrap();
+    }
+    memtable
+}
+
+/// Benchmarks point lookups against a table holding 10,000 keys.
+///
+/// Measures:
+/// - Skip list search cost, O(log n)
+/// - Hash index probe cost, O(1) expected
+fn bench_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memtable_get");
+
+    for (name, kind) in kinds() {
+        let memtable = filled(kind);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                let key = format!("key_{:06}", i % ENTRY_COUNT).into_bytes();
+                i += 7919;
+                black_box(memtable.get(&key, u64::MAX))
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Benchmarks filling an empty table with 10,000 keys.
+///
+/// Measures:
+/// - Per-insert search and link cost of the skip list
+/// - Hash probe and log append cost of the hash index
+fn bench_put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memtable_put_10000");
+
+    for (name, kind) in kinds() {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter_batched(
+                || MemTable::with_kind(64 * 1024 * 1024, kind),
+                |memtable| {
+                    for i in 0..ENTRY_COUNT {
+                        let key = format!("key_{:06}", i).into_bytes();
+                        memtable.put(key, vec![b'v'; 100], 1).unwrap();
+                    }
+                    memtable
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+/// Benchmarks a scan over 1,000 of the 10,000 keys.
+///
+/// Measures:
+/// - Ordered skip list iteration
+/// - Hash index snapshot-and-sort cost, the price paid for faster gets
+fn bench_range(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memtable_range_1000");
+
+    for (name, kind) in kinds() {
+        let memtable = filled(kind);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                black_box(
+                    memtable
+                        .range(b"key_004000".as_slice()..b"key_005000".as_slice(), u64::MAX)
+                        .count(),
+                )
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_get, bench_put, bench_range);
+criterion_main!(benches);
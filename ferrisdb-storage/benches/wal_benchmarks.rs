@@ -1,9 +1,8 @@
 use ferrisdb_core::SyncMode;
 use ferrisdb_storage::wal::{WALEntry, WALReader, WALWriter};
 
-use criterion::{
-    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
-};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
 use tempfile::TempDir;
 
 use std::sync::Arc;
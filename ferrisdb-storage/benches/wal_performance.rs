@@ -1,5 +1,7 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ferrisdb_storage::utils::crc;
 use ferrisdb_storage::wal::WALEntry;
+use std::hint::black_box;
 
 /// Benchmarks encoding performance for small entries.
 ///
@@ -95,12 +97,37 @@ fn bench_checksum_calculation(c: &mut Criterion) {
     });
 }
 
+/// Benchmarks CRC32C against crc32fast's CRC32 across record sizes.
+///
+/// Measures:
+/// - Throughput of the hardware-accelerated CRC32C path
+/// - Per-call overhead on small records, which dominate WAL recovery
+/// - Whether switching checksums would speed up the read path
+fn bench_crc32c_vs_crc32fast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("checksum_comparison");
+
+    for size in [64, 1024, 64 * 1024] {
+        let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("crc32fast", size), &data, |b, data| {
+            b.iter(|| black_box(crc32fast::hash(data)))
+        });
+        group.bench_with_input(BenchmarkId::new("crc32c", size), &data, |b, data| {
+            b.iter(|| black_box(crc::crc32c(data)))
+        });
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_encode_small_entry,
     bench_encode_large_entry,
     bench_decode_small_entry,
     bench_decode_large_entry,
-    bench_checksum_calculation
+    bench_checksum_calculation,
+    bench_crc32c_vs_crc32fast
 );
 criterion_main!(benches);
@@ -7,9 +7,9 @@ use ferrisdb_core::SyncMode;
 use ferrisdb_storage::wal::{WALEntry, WALReader, WALWriter};
 
 use criterion::{
-    black_box, criterion_group, criterion_main, BenchmarkId, Criterion, PlotConfiguration,
-    Throughput,
+    criterion_group, criterion_main, BenchmarkId, Criterion, PlotConfiguration, Throughput,
 };
+use std::hint::black_box;
 use tempfile::TempDir;
 
 /// Prove that append is O(1) - constant time regardless of file size
@@ -0,0 +1,44 @@
+//! Writes the format compatibility fixtures into `tests/fixtures/`
+//!
+//! Only fixtures that don't exist yet are written: those of older format
+//! versions must stay exactly as they were, since they stand in for files
+//! written by older releases. Run after bumping a format version:
+//!
+//! ```text
+//! cargo run --package ferrisdb-storage --bin generate_fixtures
+//! ```
+
+#[path = "../../tests/fixtures/mod.rs"]
+mod fixtures;
+
+use ferrisdb_core::Result;
+
+use std::fs;
+use std::path::Path;
+
+fn main() -> Result<()> {
+    for &version in fixtures::WAL_VERSIONS {
+        write_missing(&fixtures::wal_fixture(version), || {
+            fixtures::encode_wal(version)
+        })?;
+    }
+    for &version in fixtures::SSTABLE_VERSIONS {
+        write_missing(&fixtures::sstable_fixture(version), || {
+            fixtures::encode_sstable(version)
+        })?;
+    }
+    Ok(())
+}
+
+fn write_missing(path: &Path, encode: impl FnOnce() -> Result<Vec<u8>>) -> Result<()> {
+    if path.exists() {
+        println!("kept    {}", path.display());
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, encode()?)?;
+    println!("created {}", path.display());
+    Ok(())
+}
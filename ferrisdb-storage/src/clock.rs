@@ -0,0 +1,93 @@
+//! Sources of the current time
+//!
+//! Time-dependent behavior, such as expiring values with a TTL, asks a
+//! [`Clock`] for the time instead of the operating system. Production code
+//! uses [`SystemClock`]; tests use a [`ManualClock`] and move time forward
+//! explicitly, so expiry is deterministic instead of depending on sleeps.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::clock::{Clock, ManualClock};
+//! use std::time::Duration;
+//!
+//! let clock = ManualClock::new(Duration::from_secs(1_000));
+//! clock.advance(Duration::from_millis(1_500));
+//! assert_eq!(clock.now(), Duration::from_millis(1_001_500));
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A source of the current wall-clock time
+pub trait Clock: Send + Sync {
+    /// Returns the time elapsed since the Unix epoch
+    fn now(&self) -> Duration;
+}
+
+/// The operating system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        // A clock set before 1970 is treated as the epoch itself
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// A clock that only moves when told to, with millisecond resolution
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    /// Creates a clock showing `now` (since the Unix epoch)
+    pub fn new(now: Duration) -> Self {
+        Self {
+            millis: AtomicU64::new(now.as_millis() as u64),
+        }
+    }
+
+    /// Moves the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Sets the clock to `now` (since the Unix epoch), possibly backwards
+    pub fn set(&self, now: Duration) {
+        self.millis.store(now.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.millis.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new(Duration::from_secs(10));
+        assert_eq!(clock.now(), Duration::from_secs(10));
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(clock.now(), Duration::from_millis(10_250));
+
+        clock.set(Duration::from_secs(5));
+        assert_eq!(clock.now(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_system_clock_is_past_the_epoch() {
+        assert!(SystemClock.now() > Duration::from_secs(1_600_000_000));
+    }
+}
@@ -0,0 +1,650 @@
+//! Executes compaction tasks against a version set
+
+use super::{CompactionFilter, CompactionIterator, CompactionTask, MergingIterator};
+use crate::config::StorageConfig;
+use crate::manifest::{SSTableMeta, VersionEdit};
+use crate::merge::MergeOperator;
+use crate::prefix::PrefixExtractor;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::sstable::{SSTableEntry, SSTableWriter};
+use crate::version::VersionSet;
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::{Key, Result, Timestamp};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Bytes accumulated before charging the rate limiter
+const THROTTLE_CHUNK: u64 = 64 * 1024;
+
+/// Summary of a finished compaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Number of input tables
+    pub input_files: usize,
+    /// Number of tables written
+    pub output_files: usize,
+    /// Total size of the input tables in bytes
+    pub bytes_read: u64,
+    /// Total size of the output tables in bytes
+    pub bytes_written: u64,
+}
+
+/// How far a running compaction has got
+///
+/// Passed to [`Compactor::run_tracked`], which counts entries as it reads
+/// and writes them, so another thread can watch the compaction progress.
+/// A trivial move reads and writes nothing.
+#[derive(Debug, Default)]
+pub struct CompactionProgress {
+    entries_read: AtomicU64,
+    entries_written: AtomicU64,
+}
+
+impl CompactionProgress {
+    /// Returns the number of input entries read so far
+    pub fn entries_read(&self) -> u64 {
+        self.entries_read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of entries written to output tables so far
+    pub fn entries_written(&self) -> u64 {
+        self.entries_written.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs compaction tasks picked by a [`CompactionStrategy`](super::CompactionStrategy)
+///
+/// A run merges the input tables, rewrites their entries through a
+/// [`CompactionIterator`], writes the result into new tables of the output
+/// level, and installs the change with a single version edit. Output is
+/// split into tables of about `target_file_size_base` bytes, but never
+/// between two versions of the same key.
+///
+/// A task with a single input table and a different output level is a
+/// trivial move: the table is relinked into the output level without being
+/// rewritten.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use ferrisdb_storage::compaction::{strategy_from_config, Compactor};
+/// use ferrisdb_storage::version::VersionSet;
+/// use ferrisdb_storage::StorageConfig;
+///
+/// let config = StorageConfig::default();
+/// let versions = Arc::new(VersionSet::open(&config.data_dir)?);
+/// let strategy = strategy_from_config(&config);
+/// let compactor = Compactor::new(Arc::clone(&versions), &config);
+///
+/// while let Some(task) = strategy.pick_compaction(&versions.current()) {
+///     compactor.run(&task, u64::MAX)?;
+/// }
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct Compactor {
+    versions: Arc<VersionSet>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    filter: Option<Arc<dyn CompactionFilter>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    target_file_size: u64,
+    block_size: usize,
+    /// Extractor of the prefixes in the outputs' bloom filters
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    bloom_bits_per_key: usize,
+    /// Filesystem of the inputs and outputs, with direct I/O if configured
+    vfs: Arc<dyn Vfs>,
+    /// Whether input blocks are checked against their checksums and
+    /// outputs read back before they are installed
+    paranoid_checks: bool,
+    /// Versions of each key kept at least, whatever the snapshots
+    min_versions: usize,
+}
+
+impl Compactor {
+    /// Creates a compactor writing tables sized according to `config`
+    ///
+    /// Inputs are read and outputs written through the version set's
+    /// filesystem, with direct I/O if `use_direct_io_for_compaction` is
+    /// set. With `paranoid_checks`,
+    /// input blocks are checked against their checksums and every output
+    /// is read back in full before the edit installing it is logged.
+    pub fn new(versions: Arc<VersionSet>, config: &StorageConfig) -> Self {
+        Self {
+            merge_operator: None,
+            filter: None,
+            rate_limiter: None,
+            target_file_size: config.target_file_size_base,
+            block_size: config.block_size,
+            prefix_extractor: None,
+            bloom_bits_per_key: config.bloom_filter_bits_per_key.max(1) as usize,
+            vfs: vfs::with_direct_io(versions.vfs(), config.use_direct_io_for_compaction),
+            versions,
+            paranoid_checks: config.paranoid_checks,
+            min_versions: config.min_versions_to_keep,
+        }
+    }
+
+    /// Sets the operator used to fold merge operand chains
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    /// Sets the filter applied to every version the compaction writes
+    ///
+    /// Trivial moves don't rewrite their table, so the filter only sees
+    /// its versions once a later compaction merges it.
+    pub fn with_compaction_filter(mut self, filter: Arc<dyn CompactionFilter>) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Builds the output tables' bloom filters over the prefixes
+    /// `extractor` finds, with `bloom_filter_bits_per_key` bits per prefix
+    pub fn with_prefix_extractor(mut self, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+
+    /// Throttles the compaction's reads and writes at low priority
+    ///
+    /// The limiter is usually shared with every other background job, so
+    /// their combined I/O stays within one budget.
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Runs a compaction task and installs its result
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - Tables to compact, usually from a compaction strategy
+    /// * `oldest_snapshot` - Oldest timestamp any reader may still read at;
+    ///   `Timestamp::MAX` when there are no open snapshots
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an input cannot be read, an output cannot be
+    /// written, or the edit cannot be installed, e.g. because another
+    /// compaction already removed an input. Output tables written before
+    /// the error are deleted and the current version is left unchanged.
+    pub fn run(
+        &self,
+        task: &CompactionTask,
+        oldest_snapshot: Timestamp,
+    ) -> Result<CompactionStats> {
+        self.run_with_snapshots(task, &[oldest_snapshot])
+    }
+
+    /// Runs a compaction task like [`run`](Self::run), dropping every
+    /// version none of `snapshots` reads
+    ///
+    /// `snapshots` holds every timestamp a reader may still read at, in any
+    /// order; readers may also use any timestamp above the newest of them.
+    /// Versions between two snapshots that the newer one doesn't read are
+    /// dropped instead of kept for the oldest snapshot's sake. An empty
+    /// list is taken as `Timestamp::MAX`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`run`](Self::run).
+    pub fn run_with_snapshots(
+        &self,
+        task: &CompactionTask,
+        snapshots: &[Timestamp],
+    ) -> Result<CompactionStats> {
+        self.run_tracked(task, snapshots, &CompactionProgress::default())
+    }
+
+    /// Runs a compaction task like [`run_with_snapshots`](Self::run_with_snapshots),
+    /// counting the entries it reads and writes in `progress`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`run`](Self::run).
+    pub fn run_tracked(
+        &self,
+        task: &CompactionTask,
+        snapshots: &[Timestamp],
+        progress: &CompactionProgress,
+    ) -> Result<CompactionStats> {
+        let mut edit = VersionEdit::default();
+        for (level, table) in &task.inputs {
+            edit.delete_file(*level, table.meta().number);
+        }
+
+        let mut stats = CompactionStats {
+            input_files: task.inputs.len(),
+            bytes_read: task.input_size(),
+            ..Default::default()
+        };
+
+        if let [(level, table)] = task.inputs.as_slice() {
+            if *level != task.output_level {
+                edit.add_file(task.output_level, table.meta().clone());
+                self.versions.log_and_apply(edit)?;
+                return Ok(stats);
+            }
+        }
+
+        let outputs = self.write_outputs(task, snapshots, progress)?;
+        if self.paranoid_checks {
+            if let Err(e) = self.verify_outputs(&outputs) {
+                self.remove_outputs(&outputs);
+                return Err(e);
+            }
+        }
+        for meta in &outputs {
+            stats.output_files += 1;
+            stats.bytes_written += meta.file_size;
+            edit.add_file(task.output_level, meta.clone());
+        }
+
+        if let Err(e) = self.versions.log_and_apply(edit) {
+            self.remove_outputs(&outputs);
+            return Err(e);
+        }
+
+        Ok(stats)
+    }
+
+    /// Writes the rewritten input entries into new tables
+    fn write_outputs(
+        &self,
+        task: &CompactionTask,
+        snapshots: &[Timestamp],
+        progress: &CompactionProgress,
+    ) -> Result<Vec<SSTableMeta>> {
+        let mut outputs = Vec::new();
+        // Every file number used, including tables that failed to finish
+        let mut allocated = Vec::new();
+
+        let comparator = self.versions.comparator();
+        let result = (|| {
+            let mut sources = Vec::with_capacity(task.inputs.len());
+            for (_, table) in &task.inputs {
+                sources.push(Throttled {
+                    inner: table
+                        .open_reader_in(&self.vfs)?
+                        .with_verify_checksums(self.paranoid_checks)
+                        .into_iter(),
+                    throttle: Throttle::new(self.rate_limiter.clone()),
+                    read: &progress.entries_read,
+                });
+            }
+
+            let merged = MergingIterator::with_comparator(sources, Arc::clone(&comparator));
+            let oldest_snapshot = snapshots.iter().copied().min().unwrap_or(Timestamp::MAX);
+            let mut entries = CompactionIterator::new(merged, oldest_snapshot)
+                .with_snapshots(snapshots.iter().copied())
+                .with_bottommost(task.bottommost)
+                .with_min_versions(self.min_versions);
+            if let Some(operator) = &self.merge_operator {
+                entries = entries.with_merge_operator(Arc::clone(operator));
+            }
+            if let Some(filter) = &self.filter {
+                entries = entries.with_filter(Arc::clone(filter));
+            }
+
+            let mut write_throttle = Throttle::new(self.rate_limiter.clone());
+            let mut current: Option<(u64, SSTableWriter)> = None;
+            let mut written = 0u64;
+            let mut last_user_key: Option<Key> = None;
+            for entry in entries {
+                let entry = entry?;
+
+                // Only split between user keys, so no key spans two tables
+                let full = written >= self.target_file_size;
+                if full && last_user_key.as_ref() != Some(&entry.key.user_key) {
+                    if let Some((number, writer)) = current.take() {
+                        outputs.push(SSTableMeta::new(number, &writer.finish()?));
+                    }
+                    written = 0;
+                }
+
+                let (_, writer) = match &mut current {
+                    Some(current) => current,
+                    None => {
+                        let number = self.versions.new_file_number();
+                        allocated.push(number);
+                        let path = self.versions.table_path(number);
+                        let mut writer =
+                            SSTableWriter::with_block_size_in(&self.vfs, path, self.block_size)?
+                                .with_comparator(Arc::clone(&comparator));
+                        if let Some(extractor) = &self.prefix_extractor {
+                            writer = writer.with_prefix_extractor(
+                                Arc::clone(extractor),
+                                self.bloom_bits_per_key,
+                            );
+                        }
+                        current.insert((number, writer))
+                    }
+                };
+
+                written += entry.serialized_size() as u64;
+                write_throttle.charge(entry.serialized_size() as u64);
+                let SSTableEntry {
+                    key,
+                    value,
+                    operation,
+                } = entry;
+                last_user_key = Some(key.user_key.clone());
+                writer.add(key, value, operation)?;
+                progress.entries_written.fetch_add(1, Ordering::Relaxed);
+            }
+
+            if let Some((number, writer)) = current.take() {
+                outputs.push(SSTableMeta::new(number, &writer.finish()?));
+            }
+            write_throttle.flush();
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => Ok(outputs),
+            Err(e) => {
+                for number in allocated {
+                    // A table may have failed before its file was created
+                    let _ = self.vfs.remove_file(&self.versions.table_path(number));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads every output table back, checking it holds what was written
+    fn verify_outputs(&self, outputs: &[SSTableMeta]) -> Result<()> {
+        for meta in outputs {
+            self.versions.verify_table(&self.vfs, meta)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes output tables that were never installed
+    fn remove_outputs(&self, outputs: &[SSTableMeta]) {
+        for meta in outputs {
+            let path = self.versions.table_path(meta.number);
+            if let Err(e) = self.vfs.remove_file(&path) {
+                log::warn!(
+                    "Failed to delete unused compaction output {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Charges bytes to a rate limiter at low priority in batches
+///
+/// Batching keeps the limiter's lock off the per-entry path.
+struct Throttle {
+    limiter: Option<Arc<RateLimiter>>,
+    pending: u64,
+}
+
+impl Throttle {
+    fn new(limiter: Option<Arc<RateLimiter>>) -> Self {
+        Self {
+            limiter,
+            pending: 0,
+        }
+    }
+
+    /// Records `bytes` of I/O, blocking once a full chunk has accumulated
+    fn charge(&mut self, bytes: u64) {
+        self.pending += bytes;
+        if self.pending >= THROTTLE_CHUNK {
+            self.flush();
+        }
+    }
+
+    /// Charges whatever has accumulated
+    fn flush(&mut self) {
+        if let (Some(limiter), pending @ 1..) = (&self.limiter, self.pending) {
+            limiter.request(pending, IoPriority::Low);
+        }
+        self.pending = 0;
+    }
+}
+
+/// Charges the entries read from an input table to a [`Throttle`], and
+/// counts them in `read`
+struct Throttled<'a, I> {
+    inner: I,
+    throttle: Throttle,
+    read: &'a AtomicU64,
+}
+
+impl<I> Iterator for Throttled<'_, I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    type Item = Result<SSTableEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.inner.next();
+        match &next {
+            Some(Ok(entry)) => {
+                self.read.fetch_add(1, Ordering::Relaxed);
+                self.throttle.charge(entry.serialized_size() as u64)
+            }
+            Some(Err(_)) => {}
+            None => self.throttle.flush(),
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::{CompactionStrategy, LeveledStrategy};
+    use crate::sstable::InternalKey;
+    use crate::version::VersionSet;
+    use ferrisdb_core::{Error, Operation};
+    use tempfile::TempDir;
+
+    /// Writes a table of `(key, timestamp, value)` entries into `level`
+    fn add_table(versions: &VersionSet, level: usize, entries: &[(&str, u64, &str)]) -> u64 {
+        let number = versions.new_file_number();
+        let mut writer = SSTableWriter::new(versions.table_path(number)).unwrap();
+        for &(key, timestamp, value) in entries {
+            writer
+                .add(
+                    InternalKey::new(key.as_bytes().to_vec(), timestamp),
+                    value.as_bytes().to_vec(),
+                    Operation::Put,
+                )
+                .unwrap();
+        }
+        let mut edit = VersionEdit::default();
+        edit.add_file(level, SSTableMeta::new(number, &writer.finish().unwrap()));
+        versions.log_and_apply(edit).unwrap();
+        number
+    }
+
+    fn read_all(versions: &VersionSet, level: usize) -> Vec<(String, u64, String)> {
+        let mut entries = Vec::new();
+        for table in versions.current().files(level) {
+            for entry in table.open_reader().unwrap() {
+                let entry = entry.unwrap();
+                entries.push((
+                    String::from_utf8(entry.key.user_key).unwrap(),
+                    entry.key.timestamp,
+                    String::from_utf8(entry.value).unwrap(),
+                ));
+            }
+        }
+        entries
+    }
+
+    #[test]
+    fn test_run_merges_level0_into_level1_and_drops_shadowed_versions() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        let older = add_table(&versions, 0, &[("a", 1, "a1"), ("b", 2, "b2")]);
+        let newer = add_table(&versions, 0, &[("b", 5, "b5"), ("c", 6, "c6")]);
+
+        let config = StorageConfig::default();
+        let task = LeveledStrategy::new(2, 1 << 20, 10.0)
+            .pick_compaction(&versions.current())
+            .unwrap();
+        let progress = CompactionProgress::default();
+        let stats = Compactor::new(Arc::clone(&versions), &config)
+            .run_tracked(&task, &[u64::MAX], &progress)
+            .unwrap();
+
+        assert_eq!(stats.input_files, 2);
+        assert_eq!(stats.output_files, 1);
+        assert_eq!(progress.entries_read(), 4);
+        assert_eq!(progress.entries_written(), 3);
+        assert!(versions.current().files(0).is_empty());
+        assert_eq!(
+            read_all(&versions, 1),
+            vec![
+                ("a".to_string(), 1, "a1".to_string()),
+                ("b".to_string(), 5, "b5".to_string()),
+                ("c".to_string(), 6, "c6".to_string()),
+            ]
+        );
+        // The task pins its inputs until it is dropped
+        assert!(versions.table_path(older).exists());
+        drop(task);
+        assert!(!versions.table_path(older).exists());
+        assert!(!versions.table_path(newer).exists());
+    }
+
+    #[test]
+    fn test_run_splits_output_between_user_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        add_table(
+            &versions,
+            0,
+            &[("a", 2, "x"), ("b", 4, "x"), ("b", 3, "x"), ("c", 1, "x")],
+        );
+        add_table(&versions, 0, &[("d", 5, "x")]);
+
+        let config = StorageConfig {
+            target_file_size_base: 1,
+            ..Default::default()
+        };
+        let task = CompactionTask::new(
+            &versions.current(),
+            versions
+                .current()
+                .files(0)
+                .iter()
+                .map(|t| (0, Arc::clone(t)))
+                .collect(),
+            1,
+        );
+        // Keep every version, so "b" has two entries to keep together
+        let stats = Compactor::new(Arc::clone(&versions), &config)
+            .run(&task, 0)
+            .unwrap();
+
+        assert_eq!(stats.output_files, 4);
+        let current = versions.current();
+        let keys: Vec<_> = current
+            .files(1)
+            .iter()
+            .map(|t| (t.meta().smallest.user_key.clone(), t.meta().entry_count))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (b"a".to_vec(), 1),
+                (b"b".to_vec(), 2),
+                (b"c".to_vec(), 1),
+                (b"d".to_vec(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_single_input_into_another_level_is_moved_without_rewrite() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        let number = add_table(&versions, 1, &[("a", 1, "x")]);
+
+        let current = versions.current();
+        let task = CompactionTask::new(&current, vec![(1, Arc::clone(&current.files(1)[0]))], 2);
+        let stats = Compactor::new(Arc::clone(&versions), &StorageConfig::default())
+            .run(&task, u64::MAX)
+            .unwrap();
+
+        assert_eq!(stats.output_files, 0);
+        assert_eq!(versions.current().files(2)[0].meta().number, number);
+        assert!(versions.table_path(number).exists());
+    }
+
+    #[test]
+    fn test_failed_install_removes_outputs() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        add_table(&versions, 0, &[("a", 1, "x")]);
+        add_table(&versions, 0, &[("a", 2, "y")]);
+
+        let current = versions.current();
+        let inputs: Vec<_> = current
+            .files(0)
+            .iter()
+            .map(|t| (0, Arc::clone(t)))
+            .collect();
+        let task = CompactionTask::new(&current, inputs, 1);
+        let compactor = Compactor::new(Arc::clone(&versions), &StorageConfig::default());
+        compactor.run(&task, u64::MAX).unwrap();
+        let tables_before = std::fs::read_dir(temp_dir.path()).unwrap().count();
+
+        // The inputs are gone, so the same task can no longer be installed
+        assert!(matches!(
+            compactor.run(&task, u64::MAX),
+            Err(Error::InvalidOperation(_))
+        ));
+        assert_eq!(
+            std::fs::read_dir(temp_dir.path()).unwrap().count(),
+            tables_before
+        );
+    }
+
+    #[test]
+    fn test_run_charges_reads_and_writes_to_rate_limiter() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        add_table(&versions, 0, &[("a", 1, "old"), ("b", 1, "x")]);
+        add_table(&versions, 0, &[("a", 2, "new")]);
+
+        let current = versions.current();
+        let inputs: Vec<_> = current
+            .files(0)
+            .iter()
+            .map(|t| (0, Arc::clone(t)))
+            .collect();
+        let task = CompactionTask::new(&current, inputs, 1);
+        let limiter = Arc::new(RateLimiter::new(0));
+        Compactor::new(Arc::clone(&versions), &StorageConfig::default())
+            .with_rate_limiter(Arc::clone(&limiter))
+            .run(&task, u64::MAX)
+            .unwrap();
+
+        // Three entries read, the two newest versions written
+        let entry_size = |key: &str, value: &str| {
+            SSTableEntry::new(
+                InternalKey::new(key.as_bytes().to_vec(), 1),
+                value.as_bytes().to_vec(),
+                Operation::Put,
+            )
+            .serialized_size() as u64
+        };
+        let read = entry_size("a", "old") + entry_size("b", "x") + entry_size("a", "new");
+        let written = entry_size("a", "new") + entry_size("b", "x");
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.bytes_through(IoPriority::Low), read + written);
+        assert_eq!(metrics.requests(IoPriority::High), 0);
+    }
+}
@@ -0,0 +1,78 @@
+//! User hooks deciding the fate of individual versions during compaction
+
+use ferrisdb_core::{Operation, Timestamp, Value};
+
+/// What a [`CompactionFilter`] wants done with one version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Write the version unchanged
+    Keep,
+    /// Remove the version, so readers see the key as deleted
+    Remove,
+    /// Write the version with a new value
+    ChangeValue(Value),
+}
+
+/// User-provided logic to keep, drop, or rewrite versions during compaction
+///
+/// Compaction already reads and rewrites every entry of its inputs, so a
+/// filter can expire TTL'd data, purge a user's keys, or migrate values to
+/// a new schema as a side effect, without a separate full rewrite driven
+/// from the client.
+///
+/// The filter sees each Put and Merge version that the compaction writes,
+/// after obsolete versions were dropped and merge chains folded:
+///
+/// - Versions newer than the oldest snapshot are never filtered, so open
+///   snapshots keep reading what they read before
+/// - Tombstones are never filtered
+/// - Removing a Put writes a tombstone in its place, so older versions in
+///   levels outside the compaction cannot resurface; in a bottommost
+///   compaction the version is dropped outright
+/// - Removing a Merge version drops just that operand
+///
+/// A key may be compacted many times, so decisions must not depend on how
+/// often the filter has seen it, and readers may observe a version until
+/// the compaction covering it runs.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_core::{Operation, Timestamp};
+/// use ferrisdb_storage::compaction::{CompactionFilter, FilterDecision};
+///
+/// /// Expires values whose first 8 bytes hold an expiry time before `now`
+/// struct Ttl {
+///     now: u64,
+/// }
+///
+/// impl CompactionFilter for Ttl {
+///     fn name(&self) -> &str {
+///         "ttl"
+///     }
+///
+///     fn filter(&self, _key: &[u8], _ts: Timestamp, op: Operation, value: &[u8]) -> FilterDecision {
+///         let expiry = value.get(..8).map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+///         match (op, expiry) {
+///             (Operation::Put, Some(expiry)) if expiry < self.now => FilterDecision::Remove,
+///             _ => FilterDecision::Keep,
+///         }
+///     }
+/// }
+/// ```
+pub trait CompactionFilter: Send + Sync {
+    /// Returns a name identifying the filter in logs
+    fn name(&self) -> &str;
+
+    /// Decides what to do with one version of a key
+    ///
+    /// `operation` is [`Operation::Put`] for a value or [`Operation::Merge`]
+    /// for a merge operand that could not be folded into a value yet.
+    fn filter(
+        &self,
+        key: &[u8],
+        timestamp: Timestamp,
+        operation: Operation,
+        value: &[u8],
+    ) -> FilterDecision;
+}
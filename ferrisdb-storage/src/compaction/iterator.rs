@@ -0,0 +1,631 @@
+//! Iterator that rewrites sorted entries for a compaction output
+
+use super::{CompactionFilter, FilterDecision};
+use crate::merge::MergeOperator;
+use crate::sstable::{InternalKey, SSTableEntry};
+use ferrisdb_core::{Operation, Result, Timestamp};
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::sync::Arc;
+
+/// Rewrites the versions of each key for a compaction output
+///
+/// The input must yield entries in internal key order, for example a single
+/// [`SSTableIterator`](crate::sstable::SSTableIterator) or a merge of
+/// several. For each user key, versions newer than the newest snapshot are
+/// passed through unchanged. Of the versions at or below the oldest
+/// snapshot only the newest is kept; if it is a chain of merge operands,
+/// the chain is folded with the merge operator:
+///
+/// - With a Put or Delete below the chain, `full_merge` produces a Put
+/// - Without one, in the bottommost level, `full_merge` runs with no
+///   existing value, because no older version can exist anywhere
+/// - Otherwise `partial_merge` may collapse the chain into a single operand,
+///   and the operands are kept as they are if it declines
+///
+/// Without a merge operator, operand chains and their base are kept intact.
+/// Between two snapshots given with [`with_snapshots`](Self::with_snapshots),
+/// only the versions the newer one reads are kept: the newest, and below a
+/// merge operand the rest of its chain down to the first Put or Delete.
+/// A [`CompactionFilter`] may then keep, remove, or rewrite each version
+/// written at or below the oldest snapshot.
+/// [`with_min_versions`](Self::with_min_versions) keeps a few more of the
+/// newest versions whatever the snapshots, for reads by timestamp.
+///
+/// In the bottommost level, a tombstone with no version kept below it
+/// hides nothing from any snapshot, so it is dropped along with the key.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use ferrisdb_core::Operation;
+/// use ferrisdb_storage::compaction::CompactionIterator;
+/// use ferrisdb_storage::merge::U64AddOperator;
+/// use ferrisdb_storage::sstable::{InternalKey, SSTableEntry};
+///
+/// let input = vec![
+///     SSTableEntry::new(InternalKey::new(b"hits".to_vec(), 3), 2u64.to_le_bytes().to_vec(), Operation::Merge),
+///     SSTableEntry::new(InternalKey::new(b"hits".to_vec(), 1), 40u64.to_le_bytes().to_vec(), Operation::Put),
+/// ];
+///
+/// let output: Vec<_> = CompactionIterator::new(input.into_iter().map(Ok), u64::MAX)
+///     .with_merge_operator(Arc::new(U64AddOperator))
+///     .collect::<Result<_, _>>()?;
+///
+/// assert_eq!(output.len(), 1);
+/// assert_eq!(output[0].operation, Operation::Put);
+/// assert_eq!(output[0].value, 42u64.to_le_bytes().to_vec());
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct CompactionIterator<I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    /// Entries being compacted, in internal key order
+    input: Peekable<I>,
+    /// Timestamps readers may still read at, oldest first and never empty
+    snapshots: Vec<Timestamp>,
+    /// Whether the output is the last level, with nothing older below it
+    bottommost: bool,
+    /// Versions of each key kept at least, whichever snapshots read them
+    min_versions: usize,
+    /// Operator used to fold merge operand chains
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// User hook applied to versions below the oldest snapshot
+    filter: Option<Arc<dyn CompactionFilter>>,
+    /// Rewritten entries of the current key, ready to be yielded
+    output: VecDeque<SSTableEntry>,
+}
+
+impl<I> CompactionIterator<I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    /// Creates a compaction iterator over sorted input
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - Entries in internal key order
+    /// * `oldest_snapshot` - Oldest timestamp any reader may still read at;
+    ///   `Timestamp::MAX` when there are no open snapshots
+    pub fn new(input: I, oldest_snapshot: Timestamp) -> Self {
+        Self {
+            input: input.peekable(),
+            snapshots: vec![oldest_snapshot],
+            bottommost: false,
+            min_versions: 0,
+            merge_operator: None,
+            filter: None,
+            output: VecDeque::new(),
+        }
+    }
+
+    /// Adds timestamps readers may still read at besides the oldest one
+    ///
+    /// Without them, every version newer than the oldest snapshot is kept,
+    /// as a reader may use any timestamp in between. Knowing each snapshot
+    /// lets versions no snapshot reads be dropped as well.
+    pub fn with_snapshots(mut self, snapshots: impl IntoIterator<Item = Timestamp>) -> Self {
+        self.snapshots.extend(snapshots);
+        self.snapshots.sort_unstable();
+        self.snapshots.dedup();
+        self
+    }
+
+    /// Sets the operator used to fold merge operand chains
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    /// Sets the filter deciding the fate of versions below the oldest snapshot
+    pub fn with_filter(mut self, filter: Arc<dyn CompactionFilter>) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Marks the output as the bottommost level
+    ///
+    /// Operand chains without a base version are then fully merged against
+    /// a missing value instead of being kept for a later compaction.
+    pub fn with_bottommost(mut self, bottommost: bool) -> Self {
+        self.bottommost = bottommost;
+        self
+    }
+
+    /// Keeps at least the `count` newest versions of every key, whichever
+    /// snapshots read them
+    ///
+    /// All but the oldest of them are kept as they are, without running
+    /// the filter or folding merge operands; the oldest is the version
+    /// compaction would keep below them anyway.
+    pub fn with_min_versions(mut self, count: usize) -> Self {
+        self.min_versions = count;
+        self
+    }
+
+    /// Reads all versions of the next user key, newest first
+    fn next_key_versions(&mut self) -> Option<Result<Vec<SSTableEntry>>> {
+        let first = match self.input.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let mut versions = vec![first];
+        // An error ends the group here and is reported by the next call
+        while let Some(Ok(entry)) = self.input.peek() {
+            if entry.key.user_key != versions[0].key.user_key {
+                break;
+            }
+            versions.push(self.input.next()?.ok()?);
+        }
+
+        Some(Ok(versions))
+    }
+
+    /// Rewrites the versions of one key into the output queue
+    fn compact_key(&mut self, versions: Vec<SSTableEntry>) -> Result<()> {
+        let mut versions = versions.into_iter().peekable();
+        let mut kept = Vec::new();
+
+        // A reader may still use any timestamp above the newest snapshot
+        let newest = self.snapshots[self.snapshots.len() - 1];
+        while let Some(entry) =
+            versions.next_if(|e| e.key.timestamp > newest || kept.len() + 1 < self.min_versions)
+        {
+            kept.push(entry);
+        }
+
+        // Between two snapshots, the newer one reads the newest version and
+        // the chain of operands below it
+        for pair in self.snapshots.windows(2).rev() {
+            let older = pair[0];
+            let mut visible = true;
+            while let Some(entry) = versions.next_if(|e| e.key.timestamp > older) {
+                if visible {
+                    visible = entry.operation == Operation::Merge;
+                    kept.push(entry);
+                }
+            }
+        }
+
+        for entry in self.resolve(versions)? {
+            if let Some(entry) = self.apply_filter(entry) {
+                kept.push(entry);
+            }
+        }
+
+        if self.bottommost {
+            while kept
+                .last()
+                .is_some_and(|entry| entry.operation == Operation::Delete)
+            {
+                kept.pop();
+            }
+        }
+        self.output.extend(kept);
+        Ok(())
+    }
+
+    /// Keeps the newest of the versions below the oldest snapshot, folding
+    /// a merge chain on top of it where possible
+    fn resolve(&self, versions: impl Iterator<Item = SSTableEntry>) -> Result<Vec<SSTableEntry>> {
+        // Readers at or above the oldest snapshot only see the newest of the
+        // remaining versions; everything below its base is shadowed
+        let mut operands = Vec::new();
+        let mut base = None;
+        for entry in versions {
+            if entry.operation == Operation::Merge {
+                operands.push(entry);
+            } else {
+                base = Some(entry);
+                break;
+            }
+        }
+
+        let Some(newest) = operands.first() else {
+            return Ok(base.into_iter().collect());
+        };
+        let Some(operator) = &self.merge_operator else {
+            operands.extend(base);
+            return Ok(operands);
+        };
+
+        let user_key = &newest.key.user_key;
+        let key = InternalKey::new(user_key.clone(), newest.key.timestamp);
+        let chain: Vec<&[u8]> = operands.iter().rev().map(|e| e.value.as_slice()).collect();
+
+        let folded = match base {
+            Some(base) => {
+                let existing = (base.operation == Operation::Put).then_some(base.value.as_slice());
+                let value = operator.full_merge(user_key, existing, &chain)?;
+                SSTableEntry::new(key, value, Operation::Put)
+            }
+            None if self.bottommost => {
+                let value = operator.full_merge(user_key, None, &chain)?;
+                SSTableEntry::new(key, value, Operation::Put)
+            }
+            None => match operator.partial_merge(user_key, &chain) {
+                Some(value) => SSTableEntry::new(key, value, Operation::Merge),
+                None => return Ok(operands),
+            },
+        };
+
+        Ok(vec![folded])
+    }
+
+    /// Runs the compaction filter on a version, returning what to write
+    fn apply_filter(&self, mut entry: SSTableEntry) -> Option<SSTableEntry> {
+        let Some(filter) = &self.filter else {
+            return Some(entry);
+        };
+        if entry.operation == Operation::Delete {
+            return Some(entry);
+        }
+
+        let decision = filter.filter(
+            &entry.key.user_key,
+            entry.key.timestamp,
+            entry.operation,
+            &entry.value,
+        );
+        match decision {
+            FilterDecision::Keep => Some(entry),
+            FilterDecision::ChangeValue(value) => {
+                entry.value = value;
+                Some(entry)
+            }
+            // Dropping an operand leaves the rest of the chain intact, and
+            // nothing older than a bottommost compaction can resurface
+            FilterDecision::Remove if entry.operation == Operation::Merge || self.bottommost => {
+                None
+            }
+            FilterDecision::Remove => {
+                Some(SSTableEntry::new(entry.key, Vec::new(), Operation::Delete))
+            }
+        }
+    }
+}
+
+impl<I> Iterator for CompactionIterator<I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    type Item = Result<SSTableEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.output.is_empty() {
+            let versions = match self.next_key_versions()? {
+                Ok(versions) => versions,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Err(e) = self.compact_key(versions) {
+                return Some(Err(e));
+            }
+        }
+
+        self.output.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use ferrisdb_core::Value;
+
+    fn entry(key: &[u8], timestamp: Timestamp, value: u64, operation: Operation) -> SSTableEntry {
+        SSTableEntry::new(
+            InternalKey::new(key.to_vec(), timestamp),
+            value.to_le_bytes().to_vec(),
+            operation,
+        )
+    }
+
+    fn compact(
+        input: Vec<SSTableEntry>,
+        oldest_snapshot: Timestamp,
+        bottommost: bool,
+    ) -> Vec<(Timestamp, Operation, Value)> {
+        CompactionIterator::new(input.into_iter().map(Ok), oldest_snapshot)
+            .with_merge_operator(Arc::new(U64AddOperator))
+            .with_bottommost(bottommost)
+            .map(|e| {
+                let e = e.unwrap();
+                (e.key.timestamp, e.operation, e.value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compaction_folds_operands_onto_base() {
+        let input = vec![
+            entry(b"k", 4, 3, Operation::Merge),
+            entry(b"k", 3, 2, Operation::Merge),
+            entry(b"k", 2, 10, Operation::Put),
+            entry(b"k", 1, 99, Operation::Put),
+        ];
+
+        assert_eq!(
+            compact(input, u64::MAX, false),
+            vec![(4, Operation::Put, 15u64.to_le_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_compaction_tombstone_base_counts_as_missing_value() {
+        let input = vec![
+            entry(b"k", 3, 2, Operation::Merge),
+            SSTableEntry::new(
+                InternalKey::new(b"k".to_vec(), 2),
+                Vec::new(),
+                Operation::Delete,
+            ),
+            entry(b"k", 1, 99, Operation::Put),
+        ];
+
+        assert_eq!(
+            compact(input, u64::MAX, false),
+            vec![(3, Operation::Put, 2u64.to_le_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_compaction_keeps_versions_newer_than_oldest_snapshot() {
+        let input = vec![
+            entry(b"k", 9, 1, Operation::Merge),
+            entry(b"k", 7, 2, Operation::Merge),
+            entry(b"k", 5, 3, Operation::Merge),
+            entry(b"k", 2, 10, Operation::Put),
+        ];
+
+        assert_eq!(
+            compact(input, 6, false),
+            vec![
+                (9, Operation::Merge, 1u64.to_le_bytes().to_vec()),
+                (7, Operation::Merge, 2u64.to_le_bytes().to_vec()),
+                (5, Operation::Put, 13u64.to_le_bytes().to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compaction_without_base_uses_partial_or_bottommost_merge() {
+        let input = || {
+            vec![
+                entry(b"a", 3, 1, Operation::Merge),
+                entry(b"a", 2, 2, Operation::Merge),
+                entry(b"b", 1, 5, Operation::Put),
+            ]
+        };
+
+        // Older levels may still hold a base for "a"
+        assert_eq!(
+            compact(input(), u64::MAX, false),
+            vec![
+                (3, Operation::Merge, 3u64.to_le_bytes().to_vec()),
+                (1, Operation::Put, 5u64.to_le_bytes().to_vec()),
+            ]
+        );
+
+        // Nothing exists below the bottommost level
+        assert_eq!(
+            compact(input(), u64::MAX, true),
+            vec![
+                (3, Operation::Put, 3u64.to_le_bytes().to_vec()),
+                (1, Operation::Put, 5u64.to_le_bytes().to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compaction_without_operator_keeps_operand_chain() {
+        let input = vec![
+            entry(b"k", 3, 1, Operation::Merge),
+            entry(b"k", 2, 10, Operation::Put),
+            entry(b"k", 1, 99, Operation::Put),
+        ];
+
+        let output: Vec<_> = CompactionIterator::new(input.into_iter().map(Ok), u64::MAX)
+            .map(|e| e.unwrap().key.timestamp)
+            .collect();
+
+        assert_eq!(output, vec![3, 2]);
+    }
+
+    #[test]
+    fn test_compaction_keeps_only_the_versions_each_snapshot_reads() {
+        let input = vec![
+            entry(b"k", 8, 8, Operation::Put),
+            entry(b"k", 7, 7, Operation::Put),
+            entry(b"k", 6, 1, Operation::Merge),
+            entry(b"k", 5, 5, Operation::Put),
+            entry(b"k", 4, 4, Operation::Put),
+            entry(b"k", 2, 2, Operation::Put),
+            entry(b"k", 1, 1, Operation::Put),
+        ];
+
+        // Readers above 6 may use any timestamp; the snapshot at 6 reads the
+        // operand and its base, the one at 3 only the version at 2
+        let output: Vec<_> = CompactionIterator::new(input.into_iter().map(Ok), 3)
+            .with_snapshots([6])
+            .with_merge_operator(Arc::new(U64AddOperator))
+            .map(|e| e.unwrap().key.timestamp)
+            .collect();
+
+        assert_eq!(output, vec![8, 7, 6, 5, 2]);
+    }
+
+    #[test]
+    fn test_compaction_keeps_min_versions_whatever_the_snapshots() {
+        let input = || {
+            vec![
+                entry(b"k", 4, 4, Operation::Put),
+                entry(b"k", 3, 1, Operation::Merge),
+                entry(b"k", 2, 2, Operation::Put),
+                entry(b"k", 1, 1, Operation::Put),
+            ]
+        };
+        let compact = |count| -> Vec<Timestamp> {
+            CompactionIterator::new(input().into_iter().map(Ok), u64::MAX)
+                .with_merge_operator(Arc::new(U64AddOperator))
+                .with_min_versions(count)
+                .map(|e| e.unwrap().key.timestamp)
+                .collect()
+        };
+
+        assert_eq!(compact(0), vec![4]);
+        assert_eq!(compact(1), vec![4]);
+        // The oldest kept version folds the operand onto its base
+        assert_eq!(compact(2), vec![4, 3]);
+        assert_eq!(compact(3), vec![4, 3, 2]);
+        assert_eq!(compact(5), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_bottommost_compaction_drops_tombstones_no_snapshot_reads_below() {
+        let tombstone = |key: &[u8], timestamp| {
+            SSTableEntry::new(
+                InternalKey::new(key.to_vec(), timestamp),
+                Vec::new(),
+                Operation::Delete,
+            )
+        };
+        let input = || {
+            vec![
+                tombstone(b"a", 5),
+                entry(b"a", 3, 1, Operation::Put),
+                tombstone(b"b", 9),
+                entry(b"c", 2, 2, Operation::Put),
+            ]
+        };
+
+        // Older levels may still hold values the tombstones hide
+        assert_eq!(
+            compact(input(), u64::MAX, false),
+            vec![
+                (5, Operation::Delete, Vec::new()),
+                (9, Operation::Delete, Vec::new()),
+                (2, Operation::Put, 2u64.to_le_bytes().to_vec()),
+            ]
+        );
+
+        // Nothing is below the bottommost level for them to hide
+        assert_eq!(
+            compact(input(), u64::MAX, true),
+            vec![(2, Operation::Put, 2u64.to_le_bytes().to_vec())]
+        );
+
+        // A snapshot at 4 still reads "a"@3, so its tombstone stays
+        assert_eq!(
+            compact(input(), 4, true),
+            vec![
+                (5, Operation::Delete, Vec::new()),
+                (3, Operation::Put, 1u64.to_le_bytes().to_vec()),
+                (2, Operation::Put, 2u64.to_le_bytes().to_vec()),
+            ]
+        );
+    }
+
+    /// Removes versions whose value starts with 0, rewrites those starting with 1
+    struct ByFirstByte;
+
+    impl CompactionFilter for ByFirstByte {
+        fn name(&self) -> &str {
+            "by-first-byte"
+        }
+
+        fn filter(&self, _: &[u8], _: Timestamp, _: Operation, value: &[u8]) -> FilterDecision {
+            match value.first() {
+                Some(0) => FilterDecision::Remove,
+                Some(1) => FilterDecision::ChangeValue(b"rewritten".to_vec()),
+                _ => FilterDecision::Keep,
+            }
+        }
+    }
+
+    fn filtered(
+        input: Vec<SSTableEntry>,
+        oldest_snapshot: Timestamp,
+        bottommost: bool,
+    ) -> Vec<(Timestamp, Operation, Value)> {
+        CompactionIterator::new(input.into_iter().map(Ok), oldest_snapshot)
+            .with_filter(Arc::new(ByFirstByte))
+            .with_bottommost(bottommost)
+            .map(|e| {
+                let e = e.unwrap();
+                (e.key.timestamp, e.operation, e.value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_removes_and_rewrites_versions_below_snapshot() {
+        let input = || {
+            vec![
+                entry(b"a", 9, 0, Operation::Put),
+                entry(b"a", 3, 0, Operation::Put),
+                entry(b"b", 4, 1, Operation::Put),
+                entry(b"c", 5, 2, Operation::Merge),
+                entry(b"c", 4, 0, Operation::Merge),
+                entry(b"c", 2, 7, Operation::Put),
+            ]
+        };
+
+        // "a"@9 is newer than the snapshot and escapes the filter; "a"@3
+        // leaves a tombstone so older levels cannot resurface a value
+        assert_eq!(
+            filtered(input(), 8, false),
+            vec![
+                (9, Operation::Put, 0u64.to_le_bytes().to_vec()),
+                (3, Operation::Delete, Vec::new()),
+                (4, Operation::Put, b"rewritten".to_vec()),
+                (5, Operation::Merge, 2u64.to_le_bytes().to_vec()),
+                (2, Operation::Put, 7u64.to_le_bytes().to_vec()),
+            ]
+        );
+
+        // Nothing lies below a bottommost compaction, so removal drops outright
+        assert_eq!(
+            filtered(input(), 8, true),
+            vec![
+                (9, Operation::Put, 0u64.to_le_bytes().to_vec()),
+                (4, Operation::Put, b"rewritten".to_vec()),
+                (5, Operation::Merge, 2u64.to_le_bytes().to_vec()),
+                (2, Operation::Put, 7u64.to_le_bytes().to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter_sees_folded_merge_result_and_skips_tombstones() {
+        let input = vec![
+            entry(b"k", 3, 1, Operation::Merge),
+            entry(b"k", 2, 0, Operation::Put),
+            SSTableEntry::new(
+                InternalKey::new(b"t".to_vec(), 1),
+                Vec::new(),
+                Operation::Delete,
+            ),
+        ];
+
+        // 0 + 1 folds to 1, which the filter then rewrites
+        let output: Vec<_> = CompactionIterator::new(input.into_iter().map(Ok), u64::MAX)
+            .with_merge_operator(Arc::new(U64AddOperator))
+            .with_filter(Arc::new(ByFirstByte))
+            .map(|e| {
+                let e = e.unwrap();
+                (e.key.timestamp, e.operation, e.value)
+            })
+            .collect();
+
+        assert_eq!(
+            output,
+            vec![
+                (3, Operation::Put, b"rewritten".to_vec()),
+                (1, Operation::Delete, Vec::new()),
+            ]
+        );
+    }
+}
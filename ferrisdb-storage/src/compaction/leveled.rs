@@ -0,0 +1,245 @@
+//! Leveled compaction strategy
+
+use super::strategy::{key_range, overlaps};
+use super::{CompactionStrategy, CompactionTask};
+use crate::config::StorageConfig;
+use crate::manifest::NUM_LEVELS;
+use crate::version::Version;
+
+use std::sync::Arc;
+
+/// Keeps every level below level 0 sorted, non-overlapping and bounded
+///
+/// Each level is given a score: level 0 by its file count relative to the
+/// trigger, deeper levels by their size relative to their target size. The
+/// level with the highest score of at least 1.0 is compacted into the next:
+///
+/// - Level 0: all its tables, since they may overlap each other
+/// - Deeper levels: the oldest table of the level
+///
+/// together with every table of the next level that overlaps them.
+///
+/// ```text
+/// L1 target = max_bytes_for_level_base
+/// Ln target = L1 target * multiplier^(n-1)
+/// ```
+///
+/// The last level has no target and is never compacted further.
+#[derive(Debug, Clone)]
+pub struct LeveledStrategy {
+    level0_file_trigger: usize,
+    max_bytes_for_level_base: u64,
+    level_multiplier: f64,
+}
+
+impl LeveledStrategy {
+    /// Creates a leveled strategy
+    ///
+    /// # Arguments
+    ///
+    /// * `level0_file_trigger` - Number of level 0 tables that triggers a compaction
+    /// * `max_bytes_for_level_base` - Target size of level 1 in bytes
+    /// * `level_multiplier` - Growth factor of the target size per level
+    pub fn new(
+        level0_file_trigger: usize,
+        max_bytes_for_level_base: u64,
+        level_multiplier: f64,
+    ) -> Self {
+        Self {
+            level0_file_trigger: level0_file_trigger.max(1),
+            max_bytes_for_level_base,
+            level_multiplier,
+        }
+    }
+
+    /// Creates a leveled strategy from the engine configuration
+    pub fn from_config(config: &StorageConfig) -> Self {
+        Self::new(
+            config.level0_file_num_compaction_trigger.max(1) as usize,
+            config.max_bytes_for_level_base,
+            config.max_bytes_for_level_multiplier,
+        )
+    }
+
+    /// Returns the target size in bytes of a level below level 0
+    pub fn max_bytes_for_level(&self, level: usize) -> u64 {
+        let exponent = level.saturating_sub(1) as i32;
+        (self.max_bytes_for_level_base as f64 * self.level_multiplier.powi(exponent)) as u64
+    }
+
+    /// Returns how urgently a level needs compaction; 1.0 or more triggers one
+    pub fn score(&self, version: &Version, level: usize) -> f64 {
+        if level == 0 {
+            version.files(0).len() as f64 / self.level0_file_trigger as f64
+        } else {
+            version.level_size(level) as f64 / self.max_bytes_for_level(level).max(1) as f64
+        }
+    }
+}
+
+impl CompactionStrategy for LeveledStrategy {
+    fn name(&self) -> &str {
+        "leveled"
+    }
+
+    fn pick_compaction(&self, version: &Version) -> Option<CompactionTask> {
+        let (level, score) = (0..NUM_LEVELS - 1)
+            .map(|level| (level, self.score(version, level)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        if score < 1.0 {
+            return None;
+        }
+
+        let mut inputs: Vec<_> = if level == 0 {
+            version
+                .files(0)
+                .iter()
+                .map(|t| (0, Arc::clone(t)))
+                .collect()
+        } else {
+            let oldest = version
+                .files(level)
+                .iter()
+                .min_by_key(|t| t.meta().number)?;
+            vec![(level, Arc::clone(oldest))]
+        };
+
+        let comparator = &**version.comparator();
+        let (smallest, largest) = key_range(comparator, inputs.iter().map(|(_, t)| t.as_ref()))?;
+        let next: Vec<_> = version
+            .files(level + 1)
+            .iter()
+            .filter(|t| overlaps(comparator, t, smallest, largest))
+            .map(|t| (level + 1, Arc::clone(t)))
+            .collect();
+        inputs.extend(next);
+
+        Some(CompactionTask::new(version, inputs, level + 1))
+    }
+
+    /// Sums level 0 once it reaches the trigger and every deeper level's
+    /// excess over its target, each of which has to be merged down
+    fn pending_compaction_bytes(&self, version: &Version) -> u64 {
+        let mut pending = 0;
+        if version.files(0).len() >= self.level0_file_trigger {
+            pending += version.level_size(0);
+        }
+        for level in 1..NUM_LEVELS - 1 {
+            pending += version
+                .level_size(level)
+                .saturating_sub(self.max_bytes_for_level(level));
+        }
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::strategy::tests::{version_with, Table};
+
+    const MB: u64 = 1024 * 1024;
+
+    fn table(level: usize, number: u64, range: (&'static [u8], &'static [u8]), size: u64) -> Table {
+        Table {
+            level,
+            number,
+            smallest: range.0,
+            largest: range.1,
+            size,
+        }
+    }
+
+    fn numbers(task: &CompactionTask) -> Vec<(usize, u64)> {
+        task.inputs
+            .iter()
+            .map(|(level, t)| (*level, t.meta().number))
+            .collect()
+    }
+
+    #[test]
+    fn test_level0_compaction_takes_all_level0_and_overlapping_level1() {
+        let strategy = LeveledStrategy::new(2, 10 * MB, 10.0);
+        let mut tables = vec![
+            table(0, 5, (b"c", b"f"), MB),
+            table(1, 1, (b"a", b"b"), MB),
+            table(1, 2, (b"e", b"h"), MB),
+            table(1, 3, (b"x", b"z"), MB),
+        ];
+
+        let (_dir, version) = version_with(&tables);
+        assert!(strategy.pick_compaction(&version).is_none());
+
+        tables.push(table(0, 6, (b"b", b"d"), MB));
+        let (_dir, version) = version_with(&tables);
+        let task = strategy.pick_compaction(&version).unwrap();
+
+        assert_eq!(task.output_level, 1);
+        assert_eq!(numbers(&task), vec![(0, 6), (0, 5), (1, 1), (1, 2)]);
+        // Table 3 in level 1 lies outside the compacted range
+        assert!(task.bottommost);
+    }
+
+    #[test]
+    fn test_oversized_level_compacts_oldest_table_into_next_level() {
+        let strategy = LeveledStrategy::new(4, 10 * MB, 10.0);
+        let (_dir, version) = version_with(&[
+            table(1, 7, (b"a", b"f"), 6 * MB),
+            table(1, 4, (b"g", b"m"), 6 * MB),
+            table(2, 2, (b"h", b"k"), 50 * MB),
+            table(2, 3, (b"n", b"p"), 50 * MB),
+            table(3, 1, (b"i", b"j"), 50 * MB),
+        ]);
+
+        let task = strategy.pick_compaction(&version).unwrap();
+
+        assert_eq!(task.output_level, 2);
+        assert_eq!(numbers(&task), vec![(1, 4), (2, 2)]);
+        // Level 3 still holds older data for part of the range
+        assert!(!task.bottommost);
+    }
+
+    #[test]
+    fn test_highest_score_wins_and_last_level_is_never_picked() {
+        let strategy = LeveledStrategy::new(4, 10 * MB, 10.0);
+        let (_dir, version) = version_with(&[
+            table(0, 9, (b"a", b"b"), MB),
+            table(0, 8, (b"a", b"b"), MB),
+            table(0, 7, (b"a", b"b"), MB),
+            table(0, 6, (b"a", b"b"), MB),
+            table(2, 1, (b"a", b"z"), 300 * MB),
+            table(NUM_LEVELS - 1, 2, (b"a", b"z"), u64::MAX / 2),
+        ]);
+
+        assert_eq!(strategy.score(&version, 0), 1.0);
+        assert_eq!(strategy.score(&version, 2), 3.0);
+        assert_eq!(strategy.pick_compaction(&version).unwrap().output_level, 3);
+    }
+
+    #[test]
+    fn test_pending_bytes_count_level0_and_excess_of_deeper_levels() {
+        let strategy = LeveledStrategy::new(2, 10 * MB, 10.0);
+        let (_dir, version) = version_with(&[
+            table(0, 9, (b"a", b"b"), MB),
+            table(1, 1, (b"a", b"z"), 8 * MB),
+            table(2, 2, (b"a", b"z"), 130 * MB),
+        ]);
+        assert_eq!(strategy.pending_compaction_bytes(&version), 30 * MB);
+
+        let (_dir, version) = version_with(&[
+            table(0, 9, (b"a", b"b"), MB),
+            table(0, 8, (b"a", b"b"), 2 * MB),
+            table(1, 1, (b"a", b"z"), 8 * MB),
+        ]);
+        assert_eq!(strategy.pending_compaction_bytes(&version), 3 * MB);
+    }
+
+    #[test]
+    fn test_level_targets_grow_by_multiplier() {
+        let strategy = LeveledStrategy::new(4, 10 * MB, 10.0);
+
+        assert_eq!(strategy.max_bytes_for_level(1), 10 * MB);
+        assert_eq!(strategy.max_bytes_for_level(2), 100 * MB);
+        assert_eq!(strategy.max_bytes_for_level(4), 10_000 * MB);
+    }
+}
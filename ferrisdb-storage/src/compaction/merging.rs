@@ -0,0 +1,227 @@
+//! K-way merge of sorted entry streams
+
+use crate::comparator::{self, Comparator};
+use crate::sstable::SSTableEntry;
+use ferrisdb_core::Result;
+
+use std::sync::Arc;
+
+/// Merges several sorted entry streams into one
+///
+/// Each source must yield entries in internal key order (user_key ASC,
+/// timestamp DESC); the merged stream is in the same order. Sources are
+/// given newest first: if two sources hold the exact same internal key, the
+/// entry from the earlier source wins and the others are skipped.
+///
+/// User keys are compared bytewise unless the iterator is created
+/// [`with_comparator`](Self::with_comparator). Picking the next entry
+/// compares the head of every source, which for the handful of sources of
+/// a scan or compaction is cheaper than maintaining a heap.
+///
+/// The first error from any source is yielded and ends the stream.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_core::Operation;
+/// use ferrisdb_storage::compaction::MergingIterator;
+/// use ferrisdb_storage::sstable::{InternalKey, SSTableEntry};
+///
+/// let entry = |key: &[u8], ts| {
+///     Ok(SSTableEntry::new(InternalKey::new(key.to_vec(), ts), Vec::new(), Operation::Put))
+/// };
+///
+/// let newer = vec![entry(b"b", 5)];
+/// let older = vec![entry(b"a", 1), entry(b"b", 2)];
+///
+/// let merged: Vec<_> = MergingIterator::new(vec![newer.into_iter(), older.into_iter()])
+///     .map(|e| e.unwrap().key)
+///     .collect();
+///
+/// assert_eq!(merged, vec![
+///     InternalKey::new(b"a".to_vec(), 1),
+///     InternalKey::new(b"b".to_vec(), 5),
+///     InternalKey::new(b"b".to_vec(), 2),
+/// ]);
+/// ```
+pub struct MergingIterator<I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    sources: Vec<I>,
+    /// The next entry of every source, `None` once it is exhausted
+    heads: Vec<Option<SSTableEntry>>,
+    /// Ordering of user keys
+    comparator: Arc<dyn Comparator>,
+    /// Error from a source, reported before anything else
+    error: Option<ferrisdb_core::Error>,
+    /// Set once an error was yielded
+    done: bool,
+}
+
+impl<I> MergingIterator<I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    /// Creates a merging iterator over sources ordered newest first
+    pub fn new(sources: Vec<I>) -> Self {
+        Self::with_comparator(sources, comparator::bytewise())
+    }
+
+    /// Creates a merging iterator over sources sorted by `comparator`
+    pub fn with_comparator(sources: Vec<I>, comparator: Arc<dyn Comparator>) -> Self {
+        let mut iter = Self {
+            heads: (0..sources.len()).map(|_| None).collect(),
+            sources,
+            comparator,
+            error: None,
+            done: false,
+        };
+        for source in 0..iter.sources.len() {
+            iter.advance(source);
+        }
+        iter
+    }
+
+    /// Pulls the next entry of a source into its head slot
+    fn advance(&mut self, source: usize) {
+        self.heads[source] = match self.sources[source].next() {
+            Some(Ok(entry)) => Some(entry),
+            Some(Err(e)) => {
+                self.error.get_or_insert(e);
+                None
+            }
+            None => None,
+        };
+    }
+
+    /// Returns the source holding the smallest key, the newest on ties
+    fn smallest(&self) -> Option<usize> {
+        let mut smallest: Option<(usize, &SSTableEntry)> = None;
+        for (source, head) in self.heads.iter().enumerate() {
+            let Some(entry) = head else { continue };
+            let is_smaller = smallest.map_or(true, |(_, best)| {
+                comparator::compare_internal(&*self.comparator, &entry.key, &best.key).is_lt()
+            });
+            if is_smaller {
+                smallest = Some((source, entry));
+            }
+        }
+        smallest.map(|(source, _)| source)
+    }
+}
+
+impl<I> Iterator for MergingIterator<I>
+where
+    I: Iterator<Item = Result<SSTableEntry>>,
+{
+    type Item = Result<SSTableEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(e) = self.error.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+
+        let source = self.smallest()?;
+        let entry = self.heads[source]
+            .take()
+            .expect("smallest source has a head");
+        self.advance(source);
+
+        // Drop the same internal key from older sources
+        for older in source + 1..self.heads.len() {
+            if self.heads[older]
+                .as_ref()
+                .is_some_and(|head| head.key == entry.key)
+            {
+                self.advance(older);
+            }
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::InternalKey;
+    use ferrisdb_core::{Error, Operation};
+
+    fn entry(key: &[u8], timestamp: u64, value: &[u8]) -> Result<SSTableEntry> {
+        Ok(SSTableEntry::new(
+            InternalKey::new(key.to_vec(), timestamp),
+            value.to_vec(),
+            Operation::Put,
+        ))
+    }
+
+    #[test]
+    fn test_merging_interleaves_sources_in_key_order() {
+        let sources = vec![
+            vec![entry(b"b", 9, b"1"), entry(b"d", 1, b"2")].into_iter(),
+            vec![entry(b"a", 3, b"3"), entry(b"b", 4, b"4")].into_iter(),
+            vec![].into_iter(),
+            vec![entry(b"b", 7, b"5"), entry(b"c", 1, b"6")].into_iter(),
+        ];
+
+        let merged: Vec<_> = MergingIterator::new(sources)
+            .map(|e| e.unwrap().value)
+            .collect();
+
+        assert_eq!(merged, vec![b"3", b"1", b"5", b"4", b"6", b"2"]);
+    }
+
+    #[test]
+    fn test_merging_prefers_newest_source_for_duplicate_keys() {
+        let sources = vec![
+            vec![entry(b"k", 5, b"newer")].into_iter(),
+            vec![entry(b"k", 5, b"older"), entry(b"k", 4, b"kept")].into_iter(),
+        ];
+
+        let merged: Vec<_> = MergingIterator::new(sources)
+            .map(|e| e.unwrap().value)
+            .collect();
+
+        assert_eq!(merged, vec![b"newer".to_vec(), b"kept".to_vec()]);
+    }
+
+    #[test]
+    fn test_merging_stops_after_source_error() {
+        let sources = vec![
+            vec![entry(b"a", 1, b"1"), entry(b"c", 1, b"3")].into_iter(),
+            vec![
+                entry(b"b", 1, b"2"),
+                Err(Error::Corruption("bad block".to_string())),
+            ]
+            .into_iter(),
+        ];
+
+        let mut merged = MergingIterator::new(sources);
+        assert!(merged.next().unwrap().is_ok());
+        assert!(merged.next().unwrap().is_ok());
+        assert!(matches!(merged.next(), Some(Err(Error::Corruption(_)))));
+        assert!(merged.next().is_none());
+    }
+
+    #[test]
+    fn test_merging_follows_the_comparator() {
+        let sources = vec![
+            vec![entry(b"c", 1, b"1"), entry(b"a", 1, b"2")].into_iter(),
+            vec![entry(b"d", 1, b"3"), entry(b"b", 1, b"4")].into_iter(),
+        ];
+
+        let merged: Vec<_> = MergingIterator::with_comparator(
+            sources,
+            Arc::new(comparator::ReverseBytewiseComparator),
+        )
+        .map(|e| e.unwrap().value)
+        .collect();
+
+        assert_eq!(merged, vec![b"3", b"1", b"4", b"2"]);
+    }
+}
@@ -0,0 +1,64 @@
+//! Compaction of SSTables
+//!
+//! Compaction rewrites a set of SSTables into new ones, dropping versions
+//! that no reader can observe anymore and folding merge operand chains into
+//! plain values. It is split into three parts:
+//!
+//! - A [`CompactionStrategy`] inspects the current
+//!   [`Version`](crate::version::Version) and picks a [`CompactionTask`]:
+//!   which tables to merge and which level receives the output
+//! - The [`Compactor`] runs a task: it merges the inputs with a
+//!   [`MergingIterator`], rewrites them, and installs the new tables
+//! - The [`CompactionIterator`] holds the rewriting logic, consuming entries
+//!   in internal key order (user_key ASC, timestamp DESC) and yielding the
+//!   entries to write to the output tables
+//! - An optional [`CompactionFilter`] lets applications drop or rewrite
+//!   versions as they are compacted, e.g. to enforce a TTL
+//!
+//! # Strategies
+//!
+//! The strategy is selected per engine with
+//! [`StorageConfig::compaction_strategy`](crate::StorageConfig::compaction_strategy):
+//!
+//! - [`LeveledStrategy`] keeps each level below L0 sorted and
+//!   non-overlapping, bounding how many tables a read consults
+//! - [`SizeTieredStrategy`] merges tables of similar size, rewriting data
+//!   less often, which suits write-heavy workloads
+//!
+//! # Snapshots
+//!
+//! Every version with a timestamp newer than the oldest live snapshot is
+//! preserved verbatim, since some reader may still be pinned between those
+//! versions. At or below the oldest snapshot, readers only ever observe the
+//! newest version of each key, so older ones are garbage:
+//!
+//! ```text
+//! oldest_snapshot = 5
+//!
+//! key@9 Merge +1   ──────────────▶ key@9 Merge +1   (kept: newer than snapshot)
+//! key@5 Merge +2   ┐
+//! key@4 Merge +3   ├─ full_merge ─▶ key@5 Put 15
+//! key@2 Put   10   ┘
+//! key@1 Put   7    ──────────────▶ (dropped: shadowed)
+//! ```
+//!
+//! Given every live snapshot, the versions between two of them that the
+//! newer one doesn't read are dropped as well. In the bottommost level, a
+//! tombstone with no version left below it is dropped too, since it no
+//! longer hides anything from any snapshot.
+
+mod compactor;
+mod filter;
+mod iterator;
+mod leveled;
+mod merging;
+mod size_tiered;
+mod strategy;
+
+pub use compactor::{CompactionProgress, CompactionStats, Compactor};
+pub use filter::{CompactionFilter, FilterDecision};
+pub use iterator::CompactionIterator;
+pub use leveled::LeveledStrategy;
+pub use merging::MergingIterator;
+pub use size_tiered::{SizeTieredStrategy, DEFAULT_MAX_THRESHOLD, DEFAULT_MIN_TABLE_SIZE};
+pub use strategy::{strategy_from_config, tables_in_range, CompactionStrategy, CompactionTask};
@@ -0,0 +1,250 @@
+//! Size-tiered compaction strategy
+
+use super::{tables_in_range, CompactionStrategy, CompactionTask};
+use crate::config::StorageConfig;
+use crate::version::{TableHandle, Version};
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Default maximum number of tables merged by one compaction
+pub const DEFAULT_MAX_THRESHOLD: usize = 32;
+
+/// Default size below which all tables share one tier (1MB)
+pub const DEFAULT_MIN_TABLE_SIZE: u64 = 1024 * 1024;
+
+/// Merges tables of similar size, keeping everything in level 0
+///
+/// Tables are grouped into tiers whose members are within
+/// `[bucket_low, bucket_high]` times the tier's average size; tables below
+/// `min_table_size` all share one tier. Once a tier holds `min_threshold`
+/// tables, up to `max_threshold` of its smallest tables are merged into
+/// one larger table, which then joins the next tier up:
+///
+/// ```text
+/// 4 x 4MB flushes ──▶ 16MB ─┐
+/// 4 x 4MB flushes ──▶ 16MB ─┤
+/// 4 x 4MB flushes ──▶ 16MB ─┼─▶ 64MB
+/// 4 x 4MB flushes ──▶ 16MB ─┘
+/// ```
+///
+/// Every entry is rewritten roughly once per tier, so write amplification
+/// grows logarithmically with the data size instead of with the level
+/// multiplier. In exchange, tables overlap, so reads may consult several
+/// per key, and space is only reclaimed once a whole tier is merged.
+#[derive(Debug, Clone)]
+pub struct SizeTieredStrategy {
+    min_threshold: usize,
+    max_threshold: usize,
+    bucket_low: f64,
+    bucket_high: f64,
+    min_table_size: u64,
+}
+
+impl SizeTieredStrategy {
+    /// Creates a size-tiered strategy merging `min_threshold` similar tables
+    ///
+    /// Uses bucket bounds of 0.5 and 1.5, at most [`DEFAULT_MAX_THRESHOLD`]
+    /// tables per compaction, and [`DEFAULT_MIN_TABLE_SIZE`].
+    pub fn new(min_threshold: usize) -> Self {
+        let min_threshold = min_threshold.max(2);
+        Self {
+            min_threshold,
+            max_threshold: DEFAULT_MAX_THRESHOLD.max(min_threshold),
+            bucket_low: 0.5,
+            bucket_high: 1.5,
+            min_table_size: DEFAULT_MIN_TABLE_SIZE,
+        }
+    }
+
+    /// Creates a size-tiered strategy from the engine configuration
+    ///
+    /// `level0_file_num_compaction_trigger` is the number of similar tables
+    /// that triggers a compaction.
+    pub fn from_config(config: &StorageConfig) -> Self {
+        Self::new(config.level0_file_num_compaction_trigger.max(1) as usize)
+    }
+
+    /// Sets the maximum number of tables merged by one compaction
+    pub fn with_max_threshold(mut self, max_threshold: usize) -> Self {
+        self.max_threshold = max_threshold.max(self.min_threshold);
+        self
+    }
+
+    /// Sets how far a table's size may deviate from its tier's average
+    pub fn with_bucket_bounds(mut self, low: f64, high: f64) -> Self {
+        self.bucket_low = low;
+        self.bucket_high = high;
+        self
+    }
+
+    /// Sets the size below which all tables share one tier
+    pub fn with_min_table_size(mut self, min_table_size: u64) -> Self {
+        self.min_table_size = min_table_size;
+        self
+    }
+
+    /// Groups tables into tiers of similar size, each sorted by size
+    fn buckets<'a>(&self, tables: &'a [Arc<TableHandle>]) -> Vec<Vec<&'a Arc<TableHandle>>> {
+        let mut by_size: Vec<_> = tables.iter().collect();
+        by_size.sort_by_key(|t| t.meta().file_size);
+
+        let mut buckets: Vec<(f64, Vec<&Arc<TableHandle>>)> = Vec::new();
+        for table in by_size {
+            let size = table.meta().file_size as f64;
+            let fits = |average: f64| {
+                (size >= average * self.bucket_low && size <= average * self.bucket_high)
+                    || (size < self.min_table_size as f64 && average < self.min_table_size as f64)
+            };
+
+            match buckets.iter_mut().find(|(average, _)| fits(*average)) {
+                Some((average, members)) => {
+                    let total = *average * members.len() as f64 + size;
+                    members.push(table);
+                    *average = total / members.len() as f64;
+                }
+                None => buckets.push((size, vec![table])),
+            }
+        }
+
+        buckets.into_iter().map(|(_, members)| members).collect()
+    }
+}
+
+impl CompactionStrategy for SizeTieredStrategy {
+    fn name(&self) -> &str {
+        "size-tiered"
+    }
+
+    fn pick_compaction(&self, version: &Version) -> Option<CompactionTask> {
+        // Prefer the tier of smallest tables: cheapest to merge, and it
+        // removes the most tables per byte rewritten
+        let mut bucket = self
+            .buckets(version.files(0))
+            .into_iter()
+            .find(|members| members.len() >= self.min_threshold)?;
+        bucket.truncate(self.max_threshold);
+
+        // Inputs are ordered newest data first
+        bucket.sort_by_key(|t| std::cmp::Reverse(t.meta().number));
+        let inputs = bucket.into_iter().map(|t| (0, Arc::clone(t))).collect();
+
+        Some(CompactionTask::new(version, inputs, 0))
+    }
+
+    /// Sums the tables of every tier that is due for a merge
+    fn pending_compaction_bytes(&self, version: &Version) -> u64 {
+        self.buckets(version.files(0))
+            .iter()
+            .filter(|members| members.len() >= self.min_threshold)
+            .flatten()
+            .map(|t| t.meta().file_size)
+            .sum()
+    }
+
+    /// Merges the range's tables into one, kept in level 0 like every table
+    fn pick_range_compaction(
+        &self,
+        version: &Version,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+    ) -> Option<CompactionTask> {
+        let inputs = tables_in_range(version, range);
+        if inputs.is_empty() {
+            return None;
+        }
+        Some(CompactionTask::new(version, inputs, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::strategy::tests::{version_with, Table};
+
+    const MB: u64 = 1024 * 1024;
+
+    fn level0(sizes: &[(u64, u64)]) -> Vec<Table> {
+        sizes
+            .iter()
+            .map(|&(number, size)| Table {
+                level: 0,
+                number,
+                smallest: b"a",
+                largest: b"z",
+                size,
+            })
+            .collect()
+    }
+
+    fn numbers(task: &CompactionTask) -> Vec<u64> {
+        task.inputs.iter().map(|(_, t)| t.meta().number).collect()
+    }
+
+    #[test]
+    fn test_similar_sized_tables_form_a_tier() {
+        let strategy = SizeTieredStrategy::new(4);
+
+        // Two large tables and three flushes: no tier is full yet
+        let mut tables = level0(&[
+            (1, 64 * MB),
+            (2, 60 * MB),
+            (3, 4 * MB),
+            (5, 5 * MB),
+            (6, 3 * MB),
+        ]);
+        let (_dir, version) = version_with(&tables);
+        assert!(strategy.pick_compaction(&version).is_none());
+
+        tables.extend(level0(&[(7, 4 * MB)]));
+        let (_dir, version) = version_with(&tables);
+        let task = strategy.pick_compaction(&version).unwrap();
+
+        assert_eq!(task.output_level, 0);
+        assert_eq!(numbers(&task), vec![7, 6, 5, 3]);
+        // The large tables overlap the merged ones and may hold older data
+        assert!(!task.bottommost);
+    }
+
+    #[test]
+    fn test_tiny_tables_share_a_tier_and_merge_at_most_max_threshold() {
+        let strategy = SizeTieredStrategy::new(2)
+            .with_max_threshold(3)
+            .with_min_table_size(MB);
+        let (_dir, version) = version_with(&level0(&[
+            (1, 1024),
+            (2, 500 * 1024),
+            (3, 10),
+            (4, 64 * 1024),
+        ]));
+
+        let task = strategy.pick_compaction(&version).unwrap();
+
+        // The three smallest of the four tiny tables
+        assert_eq!(numbers(&task), vec![4, 3, 1]);
+    }
+
+    #[test]
+    fn test_pending_bytes_count_only_full_tiers() {
+        let strategy = SizeTieredStrategy::new(3);
+        let (_dir, version) = version_with(&level0(&[
+            (1, 64 * MB),
+            (2, 60 * MB),
+            (3, 4 * MB),
+            (4, 4 * MB),
+            (5, 5 * MB),
+        ]));
+
+        assert_eq!(strategy.pending_compaction_bytes(&version), 13 * MB);
+    }
+
+    #[test]
+    fn test_merging_every_table_is_bottommost() {
+        let strategy = SizeTieredStrategy::new(2);
+        let (_dir, version) = version_with(&level0(&[(1, 8 * MB), (2, 9 * MB)]));
+
+        let task = strategy.pick_compaction(&version).unwrap();
+
+        assert_eq!(numbers(&task), vec![2, 1]);
+        assert!(task.bottommost);
+    }
+}
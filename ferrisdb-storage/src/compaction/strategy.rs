@@ -0,0 +1,327 @@
+//! Pluggable policies deciding which tables to compact next
+
+use super::{LeveledStrategy, SizeTieredStrategy};
+use crate::comparator::{self, Comparator};
+use crate::config::{CompactionStrategyKind, StorageConfig};
+use crate::manifest::NUM_LEVELS;
+use crate::version::{TableHandle, Version};
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Decides which tables to compact next and where the output goes
+///
+/// A strategy only inspects the current [`Version`]; running the
+/// compaction it picks is left to the [`Compactor`](super::Compactor), so
+/// new strategies can be added without touching the rewrite path.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::compaction::{CompactionStrategy, CompactionTask};
+/// use ferrisdb_storage::version::Version;
+///
+/// /// Never compacts anything
+/// struct Disabled;
+///
+/// impl CompactionStrategy for Disabled {
+///     fn name(&self) -> &str {
+///         "disabled"
+///     }
+///
+///     fn pick_compaction(&self, _version: &Version) -> Option<CompactionTask> {
+///         None
+///     }
+/// }
+/// ```
+pub trait CompactionStrategy: Send + Sync {
+    /// Returns a name identifying the strategy in logs
+    fn name(&self) -> &str;
+
+    /// Picks the next compaction to run, or `None` if the tree is in shape
+    fn pick_compaction(&self, version: &Version) -> Option<CompactionTask>;
+
+    /// Estimates how many bytes must be compacted to bring the tree in shape
+    ///
+    /// Used to slow down writes before compaction debt grows unbounded.
+    /// The default reports no debt.
+    fn pending_compaction_bytes(&self, _version: &Version) -> u64 {
+        0
+    }
+
+    /// Picks a manual compaction of every table overlapping `range`
+    ///
+    /// The default merges them into the deepest level they reach, level 1
+    /// at the least, leaving the range in one sorted run. Returns `None` if
+    /// no table overlaps the range.
+    fn pick_range_compaction(
+        &self,
+        version: &Version,
+        range: (Bound<&[u8]>, Bound<&[u8]>),
+    ) -> Option<CompactionTask> {
+        let inputs = tables_in_range(version, range);
+        let deepest = inputs.iter().map(|(level, _)| *level).max()?;
+        Some(CompactionTask::new(version, inputs, deepest.max(1)))
+    }
+}
+
+/// Creates the strategy selected by `config.compaction_strategy`
+pub fn strategy_from_config(config: &StorageConfig) -> Arc<dyn CompactionStrategy> {
+    match config.compaction_strategy {
+        CompactionStrategyKind::Leveled => Arc::new(LeveledStrategy::from_config(config)),
+        CompactionStrategyKind::SizeTiered => Arc::new(SizeTieredStrategy::from_config(config)),
+    }
+}
+
+/// A set of tables to merge into new tables in one output level
+#[derive(Debug, Clone)]
+pub struct CompactionTask {
+    /// Input tables with their levels, newest data first
+    pub inputs: Vec<(usize, Arc<TableHandle>)>,
+    /// Level receiving the output tables
+    pub output_level: usize,
+    /// Whether no table outside the inputs overlaps their key range
+    ///
+    /// Nothing older than the inputs can exist for their keys then, so
+    /// merge operand chains can be fully resolved and tombstones no
+    /// snapshot reads below can be dropped.
+    pub bottommost: bool,
+}
+
+impl CompactionTask {
+    /// Creates a task, working out whether it is bottommost in `version`
+    ///
+    /// `inputs` must be ordered newest data first: level 0 tables newest
+    /// first, followed by deeper levels in order.
+    pub fn new(
+        version: &Version,
+        inputs: Vec<(usize, Arc<TableHandle>)>,
+        output_level: usize,
+    ) -> Self {
+        let comparator = &**version.comparator();
+        let bottommost = match key_range(comparator, inputs.iter().map(|(_, t)| t.as_ref())) {
+            Some((smallest, largest)) => (0..NUM_LEVELS).all(|level| {
+                version.files(level).iter().all(|table| {
+                    inputs.iter().any(|(_, input)| Arc::ptr_eq(input, table))
+                        || !overlaps(comparator, table, smallest, largest)
+                })
+            }),
+            None => true,
+        };
+
+        Self {
+            inputs,
+            output_level,
+            bottommost,
+        }
+    }
+
+    /// Returns the total size in bytes of the input tables
+    pub fn input_size(&self) -> u64 {
+        self.inputs.iter().map(|(_, t)| t.meta().file_size).sum()
+    }
+}
+
+/// Returns the tables overlapping `range`, newest data first
+///
+/// Tables overlapping the selected ones are added until none is left out,
+/// so merging the result into any level keeps that level's tables
+/// disjoint.
+pub fn tables_in_range(
+    version: &Version,
+    range: (Bound<&[u8]>, Bound<&[u8]>),
+) -> Vec<(usize, Arc<TableHandle>)> {
+    let comparator = &**version.comparator();
+    let in_range = |table: &TableHandle| {
+        comparator::above_start(comparator, range.0, &table.meta().largest.user_key)
+            && comparator::below_end(comparator, range.1, &table.meta().smallest.user_key)
+    };
+
+    let mut selected: Vec<Vec<&Arc<TableHandle>>> = (0..NUM_LEVELS)
+        .map(|level| {
+            version
+                .files(level)
+                .iter()
+                .filter(|t| in_range(t))
+                .collect()
+        })
+        .collect();
+    loop {
+        let Some((smallest, largest)) = key_range(
+            comparator,
+            selected.iter().flatten().copied().map(|t| t.as_ref()),
+        ) else {
+            return Vec::new();
+        };
+        let mut grew = false;
+        for (level, tables) in selected.iter_mut().enumerate() {
+            for table in version.files(level) {
+                if overlaps(comparator, table, smallest, largest)
+                    && !tables.iter().any(|t| Arc::ptr_eq(t, table))
+                {
+                    tables.push(table);
+                    grew = true;
+                }
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+
+    // Level 0 tables newest first, then deeper levels in order
+    selected[0].sort_by_key(|t| std::cmp::Reverse(t.meta().number));
+    selected
+        .into_iter()
+        .enumerate()
+        .flat_map(|(level, tables)| tables.into_iter().map(move |t| (level, Arc::clone(t))))
+        .collect()
+}
+
+/// Returns the smallest and largest user key covered by `tables`
+pub(super) fn key_range<'a>(
+    comparator: &dyn Comparator,
+    tables: impl IntoIterator<Item = &'a TableHandle>,
+) -> Option<(&'a [u8], &'a [u8])> {
+    tables.into_iter().fold(None, |range, table| {
+        let smallest = table.meta().smallest.user_key.as_slice();
+        let largest = table.meta().largest.user_key.as_slice();
+        Some(match range {
+            None => (smallest, largest),
+            Some((low, high)) => (
+                std::cmp::min_by(low, smallest, |a, b| comparator.compare(a, b)),
+                std::cmp::max_by(high, largest, |a, b| comparator.compare(a, b)),
+            ),
+        })
+    })
+}
+
+/// Returns true if the table's key range intersects `smallest..=largest`
+pub(super) fn overlaps(
+    comparator: &dyn Comparator,
+    table: &TableHandle,
+    smallest: &[u8],
+    largest: &[u8],
+) -> bool {
+    comparator
+        .compare(&table.meta().smallest.user_key, largest)
+        .is_le()
+        && comparator
+            .compare(smallest, &table.meta().largest.user_key)
+            .is_le()
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use crate::manifest::{SSTableMeta, VersionEdit};
+    use crate::sstable::InternalKey;
+    use crate::version::{Version, VersionSet};
+
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// A table placed in a version without a backing file
+    pub(in crate::compaction) struct Table {
+        pub level: usize,
+        pub number: u64,
+        pub smallest: &'static [u8],
+        pub largest: &'static [u8],
+        pub size: u64,
+    }
+
+    /// Builds a version containing `tables`, for strategies to inspect
+    pub(in crate::compaction) fn version_with(tables: &[Table]) -> (TempDir, Arc<Version>) {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+
+        let mut edit = VersionEdit::default();
+        for table in tables {
+            edit.add_file(
+                table.level,
+                SSTableMeta {
+                    number: table.number,
+                    file_size: table.size,
+                    entry_count: 1,
+                    smallest: InternalKey::new(table.smallest.to_vec(), 1),
+                    largest: InternalKey::new(table.largest.to_vec(), 1),
+                },
+            );
+        }
+        versions.log_and_apply(edit).unwrap();
+
+        (temp_dir, versions.current())
+    }
+
+    use super::{tables_in_range, CompactionStrategy};
+    use crate::compaction::LeveledStrategy;
+    use crate::config::StorageConfig;
+    use std::ops::Bound;
+
+    fn table(level: usize, number: u64, smallest: &'static [u8], largest: &'static [u8]) -> Table {
+        Table {
+            level,
+            number,
+            smallest,
+            largest,
+            size: 1024,
+        }
+    }
+
+    #[test]
+    fn test_tables_in_range_pulls_in_overlapping_tables() {
+        let (_dir, version) = version_with(&[
+            table(0, 7, b"c", b"d"),
+            table(0, 9, b"a", b"b"),
+            table(1, 3, b"a", b"c"),
+            table(1, 4, b"e", b"f"),
+            table(2, 1, b"b", b"e"),
+            table(2, 2, b"x", b"z"),
+        ]);
+
+        // "a".."b" overlaps table 3, which reaches "c", and so on
+        let numbers = |range| -> Vec<(usize, u64)> {
+            tables_in_range(&version, range)
+                .iter()
+                .map(|(level, t)| (*level, t.meta().number))
+                .collect()
+        };
+        assert_eq!(
+            numbers((
+                Bound::Included(b"a".as_slice()),
+                Bound::Excluded(b"b".as_slice())
+            )),
+            vec![(0, 9), (0, 7), (1, 3), (1, 4), (2, 1)]
+        );
+        assert_eq!(
+            numbers((Bound::Excluded(b"z".as_slice()), Bound::Unbounded)),
+            vec![]
+        );
+        assert_eq!(
+            numbers((Bound::Included(b"y".as_slice()), Bound::Unbounded)),
+            vec![(2, 2)]
+        );
+    }
+
+    #[test]
+    fn test_range_compaction_targets_the_deepest_level() {
+        let strategy = LeveledStrategy::from_config(&StorageConfig::default());
+        let (_dir, version) = version_with(&[table(0, 5, b"a", b"c"), table(3, 1, b"b", b"d")]);
+        let task = strategy
+            .pick_range_compaction(&version, (Bound::Unbounded, Bound::Unbounded))
+            .unwrap();
+        assert_eq!(task.output_level, 3);
+        assert!(task.bottommost);
+
+        let (_dir, version) = version_with(&[table(0, 5, b"a", b"c")]);
+        let task = strategy
+            .pick_range_compaction(&version, (Bound::Unbounded, Bound::Unbounded))
+            .unwrap();
+        assert_eq!(task.output_level, 1);
+        assert!(strategy
+            .pick_range_compaction(
+                &version,
+                (Bound::Included(b"x".as_slice()), Bound::Unbounded)
+            )
+            .is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! Orderings of user keys
+//!
+//! Every sorted structure of the engine, from MemTables and SSTables to
+//! the key ranges of compaction, orders user keys through a [`Comparator`].
+//! The default [`BytewiseComparator`] sorts keys as byte strings;
+//! applications that need keys in another order, such as descending,
+//! case-insensitive or by a numeric component, supply their own instead
+//! of re-encoding keys so that bytewise order matches.
+//!
+//! Range bounds passed to scans and range deletes are interpreted in the
+//! comparator's order: `start..end` covers the keys sorting at or after
+//! `start` and before `end`.
+//!
+//! # Persistence
+//!
+//! A comparator's [`name`](Comparator::name) is recorded in every SSTable
+//! written with it. Opening a table with a comparator of another name
+//! fails, since its data would be read in the wrong order. Changing how a
+//! comparator orders keys requires a new name.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::comparator::Comparator;
+//! use std::cmp::Ordering;
+//!
+//! /// Sorts ASCII letters without regard to case
+//! struct CaseInsensitive;
+//!
+//! impl Comparator for CaseInsensitive {
+//!     fn name(&self) -> &str {
+//!         "example.CaseInsensitive"
+//!     }
+//!
+//!     fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+//!         let fold = |key: &[u8]| key.to_ascii_lowercase();
+//!         // Keys differing only in case must not compare equal
+//!         fold(a).cmp(&fold(b)).then_with(|| a.cmp(b))
+//!     }
+//! }
+//!
+//! assert_eq!(CaseInsensitive.compare(b"Apple", b"banana"), Ordering::Less);
+//! ```
+
+use crate::sstable::InternalKey;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// A total order on user keys
+///
+/// Implementations must be consistent: the order may never change for a
+/// given name, and two keys may only compare equal if they are the same
+/// bytes. Point lookups, bloom filters and hash-indexed MemTables find
+/// keys by their bytes, so keys that are different but equal under the
+/// comparator would be treated inconsistently.
+pub trait Comparator: Send + Sync {
+    /// Returns a stable name identifying this ordering
+    fn name(&self) -> &str;
+
+    /// Compares two user keys
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+impl fmt::Debug for dyn Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Comparator").field(&self.name()).finish()
+    }
+}
+
+/// Orders keys as byte strings, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "ferrisdb.BytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Orders keys as byte strings, largest first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "ferrisdb.ReverseBytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Returns the default comparator
+pub fn bytewise() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+/// Compares internal keys: by user key, then newest version first
+pub fn compare_internal(comparator: &dyn Comparator, a: &InternalKey, b: &InternalKey) -> Ordering {
+    comparator
+        .compare(&a.user_key, &b.user_key)
+        .then_with(|| b.timestamp.cmp(&a.timestamp))
+}
+
+/// Returns true if `key` sorts at or after an inclusive `start`, or
+/// strictly after an exclusive one
+pub fn above_start(comparator: &dyn Comparator, start: Bound<&[u8]>, key: &[u8]) -> bool {
+    match start {
+        Bound::Included(start) => comparator.compare(key, start).is_ge(),
+        Bound::Excluded(start) => comparator.compare(key, start).is_gt(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Returns true if `key` sorts at or before an inclusive `end`, or
+/// strictly before an exclusive one
+pub fn below_end(comparator: &dyn Comparator, end: Bound<&[u8]>, key: &[u8]) -> bool {
+    match end {
+        Bound::Included(end) => comparator.compare(key, end).is_le(),
+        Bound::Excluded(end) => comparator.compare(key, end).is_lt(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Returns true if `key` lies within `start` and `end`
+pub fn contains(
+    comparator: &dyn Comparator,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+    key: &[u8],
+) -> bool {
+    above_start(comparator, start, key) && below_end(comparator, end, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounds_follow_the_comparator() {
+        let reverse = ReverseBytewiseComparator;
+        // In reverse order "c" comes first, so c..a holds c and b
+        let (start, end) = (
+            Bound::Included(b"c".as_slice()),
+            Bound::Excluded(b"a".as_slice()),
+        );
+        assert!(contains(&reverse, start, end, b"c"));
+        assert!(contains(&reverse, start, end, b"b"));
+        assert!(!contains(&reverse, start, end, b"a"));
+        assert!(!contains(&reverse, start, end, b"d"));
+        assert!(!contains(&BytewiseComparator, start, end, b"b"));
+    }
+
+    #[test]
+    fn test_internal_keys_order_versions_newest_first() {
+        let reverse = ReverseBytewiseComparator;
+        let key = |user_key: &[u8], timestamp| InternalKey::new(user_key.to_vec(), timestamp);
+        assert_eq!(
+            compare_internal(&reverse, &key(b"b", 1), &key(b"a", 9)),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_internal(&reverse, &key(b"a", 9), &key(b"a", 1)),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_internal(&reverse, &key(b"a", 1), &key(b"a", 1)),
+            Ordering::Equal
+        );
+    }
+}
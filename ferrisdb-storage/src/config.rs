@@ -1,6 +1,6 @@
 //! Configuration for the storage engine
 
-use ferrisdb_core::{CompressionType, SyncMode};
+use ferrisdb_core::{CompressionType, Config, SyncMode};
 use std::path::PathBuf;
 
 /// Configuration options for the storage engine
@@ -40,32 +40,148 @@ pub struct StorageConfig {
     /// Maximum size of a single WAL file before rotation (in bytes)
     pub wal_size_limit: usize,
 
+    /// Whether the file of the next WAL segment is created ahead of time,
+    /// so rotating the WAL only renames it
+    pub wal_preallocate: bool,
+
+    /// Directory flushed WAL segments are moved to instead of being deleted,
+    /// for point-in-time recovery (`None` = delete them)
+    pub wal_archive_dir: Option<PathBuf>,
+
+    /// Memory kept for the most recently appended WAL records, so WAL
+    /// tailers read them without going to the files (in bytes, 0 = none)
+    pub wal_cache_size: usize,
+
     /// Maximum size of active MemTable before flush (in bytes)
     pub memtable_size: usize,
 
     /// Maximum number of immutable MemTables to keep before blocking writes
     pub max_immutable_memtables: usize,
 
+    /// Memory the active MemTables of all column families may use together
+    /// before they are switched for a flush (in bytes, 0 = no budget)
+    pub write_buffer_budget: usize,
+
+    /// Memory the engine may hold in MemTables, cached blocks, table
+    /// indexes and iterators together before it flushes early and stops
+    /// caching blocks (in bytes, 0 = no limit)
+    pub memory_limit: usize,
+
+    /// In-memory index used by MemTables
+    /// - `SkipList`: Ordered, supports efficient range scans (default)
+    /// - `HashIndex`: Faster gets and inserts, range scans sort on demand
+    pub memtable_kind: MemTableKind,
+
     /// Size of each data block in SSTable files (in bytes)
     pub block_size: usize,
 
     /// Compression algorithm for SSTable blocks
     pub compression: CompressionType,
 
+    /// How SSTables are organized and picked for compaction
+    /// - `Leveled`: Non-overlapping levels, low read and space amplification (default)
+    /// - `SizeTiered`: Merges similar-sized tables, low write amplification
+    pub compaction_strategy: CompactionStrategyKind,
+
     /// Number of L0 files that trigger compaction
     pub level0_file_num_compaction_trigger: i32,
 
+    /// Number of L0 files at which writes are slowed to `delayed_write_rate`
+    pub level0_slowdown_writes_trigger: i32,
+
+    /// Number of L0 files at which writes stop until compaction catches up
+    pub level0_stop_writes_trigger: i32,
+
+    /// Estimated compaction debt at which writes are slowed (in bytes)
+    pub soft_pending_compaction_bytes_limit: u64,
+
+    /// Estimated compaction debt at which writes stop (in bytes)
+    pub hard_pending_compaction_bytes_limit: u64,
+
+    /// Write rate allowed while writes are slowed (in bytes per second)
+    pub delayed_write_rate: u64,
+
     /// Target size for L1 (in bytes)
     pub max_bytes_for_level_base: u64,
 
     /// Size multiplier between levels (L2 = L1 * multiplier)
     pub max_bytes_for_level_multiplier: f64,
 
+    /// Size at which compaction output is split into a new SSTable (in bytes)
+    pub target_file_size_base: u64,
+
+    /// Combined read and write rate of background I/O such as compaction
+    /// (in bytes per second, 0 = unlimited)
+    pub rate_limiter_bytes_per_sec: u64,
+
     /// Size of the block cache for SSTable reads (in bytes)
     pub block_cache_size: usize,
 
     /// Bits per key for bloom filters (10 = ~1% false positive rate)
     pub bloom_filter_bits_per_key: i32,
+
+    /// Read SSTables with direct I/O, bypassing the OS page cache
+    pub use_direct_reads: bool,
+
+    /// Read and write compaction input and output with direct I/O, so
+    /// large compactions don't evict the hot working set from the OS cache
+    pub use_direct_io_for_compaction: bool,
+
+    /// Only log the orphaned files found after recovery instead of
+    /// deleting them
+    pub orphan_files_dry_run: bool,
+
+    /// Check every SSTable block read against its checksum, re-read the
+    /// tables compactions and flushes write before installing them, and
+    /// check that tables exist with their recorded sizes before the
+    /// MANIFEST records them
+    pub paranoid_checks: bool,
+
+    /// How often the WAL is synced to disk in the background, covering
+    /// writes the sync mode didn't sync (in milliseconds, 0 = never)
+    pub wal_sync_interval_ms: u64,
+
+    /// How long every version stays readable by timestamp before
+    /// compaction may drop it (in milliseconds, 0 = only while snapshots
+    /// read it)
+    pub version_retention_ms: u64,
+
+    /// Versions of each key compaction keeps whatever their age, the
+    /// newest first
+    pub min_versions_to_keep: usize,
+
+    /// How often the scrubber reads cold SSTables back to verify their
+    /// checksums (in milliseconds, 0 = never)
+    pub scrub_interval_ms: u64,
+
+    /// Most bytes per second the scrubber reads (0 = unlimited)
+    pub scrub_bytes_per_sec: u64,
+
+    /// How often a secondary engine catches up with its primary (in
+    /// milliseconds, 0 = only when asked to)
+    pub catch_up_interval_ms: u64,
+
+    /// How long a pessimistic transaction waits for a lock before failing
+    /// (in milliseconds)
+    pub lock_timeout_ms: u64,
+
+    /// Operations taking at least this long are kept in the slow log (in
+    /// microseconds, 0 = no slow log)
+    pub slow_log_threshold_us: u64,
+
+    /// Most operations the slow log keeps, the oldest dropped first
+    pub slow_log_capacity: usize,
+
+    /// Whether the slow log leaves out the keys of operations, keeping
+    /// only their lengths
+    pub slow_log_redact_keys: bool,
+
+    /// Shallowest level whose SSTables are offloaded to remote storage,
+    /// when the engine has one
+    pub remote_level: usize,
+
+    /// Size of the local cache of remote SSTable chunks (in bytes)
+    pub remote_cache_size: usize,
 }
 
 impl Default for StorageConfig {
@@ -75,15 +191,156 @@ impl Default for StorageConfig {
             wal_dir: PathBuf::from("./data/wal"),
             wal_sync_mode: SyncMode::Normal,
             wal_size_limit: 64 * 1024 * 1024, // 64MB
-            memtable_size: 4 * 1024 * 1024,   // 4MB
+            wal_preallocate: true,
+            wal_archive_dir: None,
+            wal_cache_size: 0,
+            memtable_size: 4 * 1024 * 1024, // 4MB
             max_immutable_memtables: 2,
+            write_buffer_budget: 0,
+            memory_limit: 0,
+            memtable_kind: MemTableKind::SkipList,
             block_size: 4 * 1024, // 4KB
             compression: CompressionType::Lz4,
+            compaction_strategy: CompactionStrategyKind::Leveled,
             level0_file_num_compaction_trigger: 4,
-            max_bytes_for_level_base: 10 * 1024 * 1024, // 10MB
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            soft_pending_compaction_bytes_limit: 64 * 1024 * 1024 * 1024, // 64GB
+            hard_pending_compaction_bytes_limit: 256 * 1024 * 1024 * 1024, // 256GB
+            delayed_write_rate: 16 * 1024 * 1024,                         // 16MB/s
+            max_bytes_for_level_base: 10 * 1024 * 1024,                   // 10MB
             max_bytes_for_level_multiplier: 10.0,
+            target_file_size_base: 2 * 1024 * 1024, // 2MB
+            rate_limiter_bytes_per_sec: 0,
             block_cache_size: 128 * 1024 * 1024, // 128MB
             bloom_filter_bits_per_key: 10,
+            use_direct_reads: false,
+            use_direct_io_for_compaction: false,
+            orphan_files_dry_run: false,
+            paranoid_checks: false,
+            wal_sync_interval_ms: 0,
+            version_retention_ms: 0,
+            min_versions_to_keep: 1,
+            scrub_interval_ms: 0,
+            scrub_bytes_per_sec: 4 * 1024 * 1024, // 4MB/s
+            catch_up_interval_ms: 0,
+            lock_timeout_ms: 1000, // 1s
+            slow_log_threshold_us: 0,
+            slow_log_capacity: 128,
+            slow_log_redact_keys: false,
+            remote_level: crate::manifest::NUM_LEVELS - 1,
+            remote_cache_size: 64 * 1024 * 1024, // 64MB
+        }
+    }
+}
+
+impl From<&Config> for StorageConfig {
+    /// Applies the settings of a configuration file over the defaults
+    ///
+    /// WAL files go to `wal.dir` if set, otherwise to the `wal`
+    /// subdirectory of the data directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_core::Config;
+    /// use ferrisdb_storage::StorageConfig;
+    ///
+    /// let config = Config::from_toml_str("[sstable]\nblock_cache_size = \"1GB\"").unwrap();
+    /// let storage = StorageConfig::from(&config);
+    /// assert_eq!(storage.block_cache_size, 1 << 30);
+    /// assert_eq!(storage.wal_dir, std::path::Path::new("./data/wal"));
+    /// ```
+    fn from(config: &Config) -> Self {
+        let defaults = Self::default();
+        let data_dir = config.data_dir.clone().unwrap_or(defaults.data_dir);
+        let level0 = |trigger: Option<u32>, default: i32| {
+            trigger.map_or(default, |n| i32::try_from(n).unwrap_or(i32::MAX))
+        };
+        let (wal, memtable, sstable, compaction) = (
+            &config.wal,
+            &config.memtable,
+            &config.sstable,
+            &config.compaction,
+        );
+        Self {
+            wal_dir: wal.dir.clone().unwrap_or_else(|| data_dir.join("wal")),
+            data_dir,
+            wal_sync_mode: wal.sync_mode.unwrap_or(defaults.wal_sync_mode),
+            wal_size_limit: wal
+                .size_limit
+                .map_or(defaults.wal_size_limit, |size| size.as_usize()),
+            wal_archive_dir: wal.archive_dir.clone(),
+            memtable_size: memtable
+                .size
+                .map_or(defaults.memtable_size, |size| size.as_usize()),
+            max_immutable_memtables: memtable
+                .max_immutable
+                .unwrap_or(defaults.max_immutable_memtables),
+            write_buffer_budget: memtable
+                .write_buffer_budget
+                .map_or(defaults.write_buffer_budget, |size| size.as_usize()),
+            block_size: sstable
+                .block_size
+                .map_or(defaults.block_size, |size| size.as_usize()),
+            block_cache_size: sstable
+                .block_cache_size
+                .map_or(defaults.block_cache_size, |size| size.as_usize()),
+            compression: sstable.compression.unwrap_or(defaults.compression),
+            level0_file_num_compaction_trigger: level0(
+                compaction.level0_file_num_trigger,
+                defaults.level0_file_num_compaction_trigger,
+            ),
+            level0_slowdown_writes_trigger: level0(
+                compaction.level0_slowdown_writes_trigger,
+                defaults.level0_slowdown_writes_trigger,
+            ),
+            level0_stop_writes_trigger: level0(
+                compaction.level0_stop_writes_trigger,
+                defaults.level0_stop_writes_trigger,
+            ),
+            max_bytes_for_level_base: compaction
+                .max_bytes_for_level_base
+                .map_or(defaults.max_bytes_for_level_base, |size| size.as_u64()),
+            max_bytes_for_level_multiplier: compaction
+                .max_bytes_for_level_multiplier
+                .unwrap_or(defaults.max_bytes_for_level_multiplier),
+            target_file_size_base: compaction
+                .target_file_size_base
+                .map_or(defaults.target_file_size_base, |size| size.as_u64()),
+            rate_limiter_bytes_per_sec: compaction
+                .rate_limit
+                .map_or(defaults.rate_limiter_bytes_per_sec, |rate| rate.as_u64()),
+            ..defaults
         }
     }
 }
+
+/// In-memory index implementations available for MemTables
+///
+/// The skip list suits general workloads. Workloads that only ever issue
+/// point lookups can choose the hash index, which trades cheap ordered
+/// iteration for faster gets and inserts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemTableKind {
+    /// Concurrent skip list ordered by key
+    #[default]
+    SkipList,
+    /// Sharded hash map plus insertion log; scans sort a snapshot
+    HashIndex,
+}
+
+/// Compaction strategies available for organizing SSTables
+///
+/// Leveled compaction keeps read and space amplification low at the cost of
+/// rewriting data many times. Size-tiered compaction rewrites data far less
+/// often, which suits write-heavy workloads, but reads may have to consult
+/// more tables and space amplification is higher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompactionStrategyKind {
+    /// Sorted, non-overlapping levels growing by a fixed multiplier
+    #[default]
+    Leveled,
+    /// Tiers of similar-sized, possibly overlapping tables in level 0
+    SizeTiered,
+}
@@ -0,0 +1,283 @@
+//! Names of the files in data and WAL directories
+//!
+//! Tables and WAL segments are named after a number from one counter, so
+//! their names sort in the order they were created:
+//!
+//! | File | Name | Lives in |
+//! |------|------|----------|
+//! | SSTable | `000042.sst` | a column family's directory |
+//! | WAL segment | `000043.wal` | the WAL directory |
+//! | MANIFEST | `MANIFEST` | a column family's directory |
+//! | Next WAL segment | `PREALLOCATED.wal.tmp` | the WAL directory |
+//! | Temporary file | `<name>.tmp` | next to the file being replaced |
+//!
+//! [`parse_file_name`] maps a name back to its [`FileType`]. Names that
+//! don't parse belong to someone else, such as the directory lock, and are
+//! never touched.
+//!
+//! # Garbage Collection
+//!
+//! A crash can leave files no MANIFEST refers to: a table written by a
+//! flush or compaction that never got installed, or the temporary file of
+//! an interrupted [`write_atomic`](crate::utils::atomic_file::write_atomic).
+//! Once recovery has installed everything it replayed, such orphans can't
+//! become live anymore, and [`collect_garbage`] deletes them, or only
+//! lists them in a dry run.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::files::{self, FileType};
+//!
+//! assert_eq!(files::table_file_name(42), "000042.sst");
+//! assert_eq!(files::parse_file_name("000042.sst"), Some(FileType::Table(42)));
+//! assert_eq!(files::parse_file_name("MANIFEST"), Some(FileType::Manifest));
+//! assert_eq!(files::parse_file_name("LOCK"), None);
+//! ```
+
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::Result;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File name of the MANIFEST within the data directory
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST";
+
+/// File name of the file the next WAL segment is renamed from
+///
+/// It is a temporary file, so garbage collection removes one left behind.
+pub const PREALLOCATED_WAL_FILE_NAME: &str = "PREALLOCATED.wal.tmp";
+
+/// Suffix of SSTable file names
+const TABLE_SUFFIX: &str = ".sst";
+
+/// Suffix of WAL segment file names
+const WAL_SUFFIX: &str = ".wal";
+
+/// Suffix of temporary files, as written by `write_atomic`
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Returns the file name of the SSTable with the given number
+pub fn table_file_name(number: u64) -> String {
+    format!("{:06}{}", number, TABLE_SUFFIX)
+}
+
+/// Returns the file name of the WAL segment with the given number
+///
+/// WAL segments share the table number space, so the MANIFEST's log number
+/// orders them relative to the tables flushed from them.
+pub fn wal_file_name(number: u64) -> String {
+    format!("{:06}{}", number, WAL_SUFFIX)
+}
+
+/// The kind of a file the engine creates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    /// An SSTable with its number
+    Table(u64),
+    /// A WAL segment with its number
+    Wal(u64),
+    /// The MANIFEST of a column family
+    Manifest,
+    /// A temporary file replacing another one
+    Temp,
+}
+
+/// Returns the type of the file named `name`, or `None` if the engine
+/// doesn't create files by that name
+///
+/// Numbers are parsed whatever their width, so names written before the
+/// number outgrew six digits still parse.
+pub fn parse_file_name(name: &str) -> Option<FileType> {
+    let number = |digits: &str| -> Option<u64> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+
+    if name == MANIFEST_FILE_NAME {
+        Some(FileType::Manifest)
+    } else if let Some(digits) = name.strip_suffix(TABLE_SUFFIX) {
+        number(digits).map(FileType::Table)
+    } else if let Some(digits) = name.strip_suffix(WAL_SUFFIX) {
+        number(digits).map(FileType::Wal)
+    } else if name.len() > TEMP_SUFFIX.len() && name.ends_with(TEMP_SUFFIX) {
+        Some(FileType::Temp)
+    } else {
+        None
+    }
+}
+
+/// Lists the files of the engine's types in `dir`, sorted by path
+///
+/// Subdirectories and files with other names are left out.
+pub fn list_files(dir: &Path) -> Result<Vec<(FileType, PathBuf)>> {
+    list_files_in(&vfs::os(), dir)
+}
+
+/// Lists the files of the engine's types in `dir` on `vfs`, sorted by path
+///
+/// See [`list_files`].
+pub fn list_files_in(vfs: &Arc<dyn Vfs>, dir: &Path) -> Result<Vec<(FileType, PathBuf)>> {
+    let mut files = Vec::new();
+    for path in vfs.read_dir(dir)? {
+        let file_type = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_file_name);
+        // Only directories can be listed
+        if let (Some(file_type), false) = (file_type, vfs.read_dir(&path).is_ok()) {
+            files.push((file_type, path));
+        }
+    }
+    Ok(files)
+}
+
+/// Files found by [`collect_garbage`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GarbageReport {
+    /// Orphaned files, deleted unless it was a dry run
+    pub files: Vec<PathBuf>,
+    /// Total size of the orphaned files in bytes
+    pub bytes: u64,
+}
+
+impl GarbageReport {
+    /// Adds the files of another report
+    pub fn extend(&mut self, other: GarbageReport) {
+        self.files.extend(other.files);
+        self.bytes += other.bytes;
+    }
+}
+
+/// Deletes the files in `dir` that `is_live` rejects
+///
+/// Only files whose names [`parse_file_name`] recognizes are considered.
+/// With `dry_run`, nothing is deleted and the report lists what would
+/// have been. Must only run while nothing is writing to `dir`, as a table
+/// being written isn't live yet.
+///
+/// # Errors
+///
+/// Returns an error if the directory can't be listed or a file can't be
+/// deleted. Files deleted before the error stay deleted.
+pub fn collect_garbage(
+    dir: &Path,
+    is_live: impl Fn(FileType) -> bool,
+    dry_run: bool,
+) -> Result<GarbageReport> {
+    collect_garbage_in(&vfs::os(), dir, is_live, dry_run)
+}
+
+/// Deletes the files in `dir` on `vfs` that `is_live` rejects
+///
+/// See [`collect_garbage`].
+///
+/// # Errors
+///
+/// Returns an error if the directory can't be listed or a file can't be
+/// deleted.
+pub fn collect_garbage_in(
+    vfs: &Arc<dyn Vfs>,
+    dir: &Path,
+    is_live: impl Fn(FileType) -> bool,
+    dry_run: bool,
+) -> Result<GarbageReport> {
+    let mut report = GarbageReport::default();
+    for (file_type, path) in list_files_in(vfs, dir)? {
+        if is_live(file_type) {
+            continue;
+        }
+        let size = vfs.file_size(&path)?;
+        if dry_run {
+            log::info!("Orphaned file {} would be deleted", path.display());
+        } else {
+            log::info!("Deleting orphaned file {}", path.display());
+            vfs.remove_file(&path)?;
+        }
+        report.files.push(path);
+        report.bytes += size;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_names_round_trip_through_the_parser() {
+        for number in [0, 42, 999_999, 1_000_000, u64::MAX] {
+            assert_eq!(
+                parse_file_name(&table_file_name(number)),
+                Some(FileType::Table(number))
+            );
+            assert_eq!(
+                parse_file_name(&wal_file_name(number)),
+                Some(FileType::Wal(number))
+            );
+        }
+        assert_eq!(parse_file_name("MANIFEST"), Some(FileType::Manifest));
+        assert_eq!(parse_file_name("backup.json.tmp"), Some(FileType::Temp));
+        assert_eq!(
+            parse_file_name(PREALLOCATED_WAL_FILE_NAME),
+            Some(FileType::Temp)
+        );
+
+        for name in [
+            "LOCK",
+            ".sst",
+            "12a.sst",
+            "+1.wal",
+            "000001.sst.bak",
+            ".tmp",
+            "MANIFEST2",
+        ] {
+            assert_eq!(parse_file_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_collect_garbage_spares_live_and_unknown_files() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "000001.sst",
+            "000002.sst",
+            "000003.wal",
+            "MANIFEST",
+            "LOCK",
+            "x.tmp",
+        ] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        std::fs::create_dir(dir.path().join("000004.sst")).unwrap();
+        let is_live = |file_type| match file_type {
+            FileType::Table(number) => number == 1,
+            FileType::Wal(_) | FileType::Manifest => true,
+            FileType::Temp => false,
+        };
+
+        let dry_run = collect_garbage(dir.path(), is_live, true).unwrap();
+        assert_eq!(
+            dry_run.files,
+            vec![dir.path().join("000002.sst"), dir.path().join("x.tmp")]
+        );
+        assert_eq!(dry_run.bytes, 10 + 5);
+        assert!(dir.path().join("000002.sst").exists());
+
+        let report = collect_garbage(dir.path(), is_live, false).unwrap();
+        assert_eq!(report, dry_run);
+        let mut left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            ["000001.sst", "000003.wal", "000004.sst", "LOCK", "MANIFEST"]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Lock-free latency histograms
+//!
+//! A [`LatencyHistogram`] counts durations in logarithmic buckets: up to
+//! 16ns each nanosecond gets a bucket, and above that every power of two is
+//! split into 8 buckets, so a bucket is at most 12.5% wider than its lower
+//! bound. Percentiles are interpolated within their bucket and are accurate
+//! to a few percent whatever the range of the durations.
+//!
+//! Recording is a handful of relaxed atomic additions. Threads are spread
+//! over cache-padded shards, so threads recording at once rarely touch the
+//! same cache line; [`snapshot`](LatencyHistogram::snapshot) sums the
+//! shards. A snapshot taken while others record may miss their latest
+//! durations, which is fine for monitoring.
+//!
+//! ```
+//! use ferrisdb_storage::histogram::LatencyHistogram;
+//! use std::time::Duration;
+//!
+//! let histogram = LatencyHistogram::new();
+//! for micros in 1..=100 {
+//!     histogram.record(Duration::from_micros(micros));
+//! }
+//! let snapshot = histogram.snapshot();
+//! assert_eq!(snapshot.count, 100);
+//! assert!(snapshot.p99 >= Duration::from_micros(95));
+//! ```
+
+use crossbeam::utils::CachePadded;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Buckets per power of two above the exact range
+const SUB_BUCKETS: usize = 8;
+/// Nanoseconds below this get a bucket each
+const EXACT: u64 = 2 * SUB_BUCKETS as u64;
+const BUCKETS: usize = EXACT as usize + (64 - 4) * SUB_BUCKETS;
+/// Shards threads are spread over
+const SHARDS: usize = 8;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Shard the current thread records into, assigned round-robin
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+/// Distribution of the durations of an operation
+///
+/// Safe to record into from any number of threads; see the
+/// [module documentation](self).
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    shards: Box<[CachePadded<Shard>]>,
+}
+
+#[derive(Debug)]
+struct Shard {
+    buckets: Box<[AtomicU64]>,
+    sum_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl Shard {
+    fn new() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            sum_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    /// Creates an empty histogram
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| CachePadded::new(Shard::new()))
+                .collect(),
+        }
+    }
+
+    /// Records one duration
+    pub fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let shard = &self.shards[SHARD.with(|shard| *shard)];
+        shard.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        shard.sum_ns.fetch_add(nanos, Ordering::Relaxed);
+        shard.max_ns.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Forgets every duration recorded
+    pub fn reset(&self) {
+        for shard in self.shards.iter() {
+            for bucket in shard.buckets.iter() {
+                bucket.store(0, Ordering::Relaxed);
+            }
+            shard.sum_ns.store(0, Ordering::Relaxed);
+            shard.max_ns.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the number of durations and their percentiles so far
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut counts = vec![0u64; BUCKETS];
+        let mut sum = 0u64;
+        let mut max = 0u64;
+        for shard in self.shards.iter() {
+            for (count, bucket) in counts.iter_mut().zip(shard.buckets.iter()) {
+                *count += bucket.load(Ordering::Relaxed);
+            }
+            sum = sum.saturating_add(shard.sum_ns.load(Ordering::Relaxed));
+            max = max.max(shard.max_ns.load(Ordering::Relaxed));
+        }
+        // Counted from the buckets, so the percentiles agree with the count
+        // even if durations were recorded while the shards were read
+        let count = counts.iter().sum();
+        let percentile = |p: f64| Duration::from_nanos(percentile(&counts, count, max, p));
+        HistogramSnapshot {
+            count,
+            sum: Duration::from_nanos(sum),
+            max: Duration::from_nanos(max),
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            p999: percentile(99.9),
+        }
+    }
+}
+
+/// Durations recorded by a [`LatencyHistogram`] up to some moment
+///
+/// All durations are zero without any recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Number of durations recorded
+    pub count: u64,
+    /// Total of the durations
+    pub sum: Duration,
+    /// Longest duration
+    pub max: Duration,
+    /// Median duration
+    pub p50: Duration,
+    /// Duration 95% of the others were at most
+    pub p95: Duration,
+    /// Duration 99% of the others were at most
+    pub p99: Duration,
+    /// Duration 99.9% of the others were at most
+    pub p999: Duration,
+}
+
+impl HistogramSnapshot {
+    /// Returns the average duration
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.sum.as_nanos() / u128::from(self.count)) as u64)
+    }
+}
+
+impl fmt::Display for HistogramSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ops, mean {:?}, p50 {:?}, p95 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            self.count,
+            self.mean(),
+            self.p50,
+            self.p95,
+            self.p99,
+            self.p999,
+            self.max
+        )
+    }
+}
+
+/// Returns the value below which `p` percent of the `count` values in
+/// `counts` fall, interpolated within its bucket
+fn percentile(counts: &[u64], count: u64, max: u64, p: f64) -> u64 {
+    let threshold = count as f64 * p / 100.0;
+    let mut cumulative = 0.0;
+    for (index, &bucket_count) in counts.iter().enumerate() {
+        if bucket_count == 0 {
+            continue;
+        }
+        let next = cumulative + bucket_count as f64;
+        if next >= threshold {
+            let low = lower_bound(index) as f64;
+            let high = upper_bound(index) as f64;
+            let fraction = (threshold - cumulative) / bucket_count as f64;
+            let value = (low + (high - low) * fraction) as u64;
+            return value.min(max);
+        }
+        cumulative = next;
+    }
+    max
+}
+
+/// Returns the bucket holding `value`
+fn bucket(value: u64) -> usize {
+    if value < EXACT {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros() as usize;
+    let mantissa = (value >> (exponent - 3)) as usize - SUB_BUCKETS;
+    EXACT as usize + (exponent - 4) * SUB_BUCKETS + mantissa
+}
+
+/// Returns the smallest value in bucket `index`
+fn lower_bound(index: usize) -> u64 {
+    if index < EXACT as usize {
+        return index as u64;
+    }
+    let exponent = (index - EXACT as usize) / SUB_BUCKETS + 4;
+    let mantissa = ((index - EXACT as usize) % SUB_BUCKETS + SUB_BUCKETS) as u64;
+    mantissa << (exponent - 3)
+}
+
+/// Returns the smallest value past bucket `index`
+fn upper_bound(index: usize) -> u64 {
+    if index + 1 < BUCKETS {
+        lower_bound(index + 1)
+    } else {
+        u64::MAX
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_buckets_cover_every_value_in_order() {
+        for value in (0..100_000).chain([u64::MAX / 3, u64::MAX]) {
+            let index = bucket(value);
+            assert!(lower_bound(index) <= value, "{value}");
+            assert!(
+                index + 1 == BUCKETS || value < upper_bound(index),
+                "{value}"
+            );
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_snapshot_percentiles() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(histogram.snapshot(), HistogramSnapshot::default());
+
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 1000);
+        assert_eq!(snapshot.max, Duration::from_millis(1));
+        assert_eq!(snapshot.sum, Duration::from_micros(500_500));
+        assert_eq!(snapshot.mean(), Duration::from_nanos(500_500));
+        for (value, expected) in [
+            (snapshot.p50, 500.0),
+            (snapshot.p95, 950.0),
+            (snapshot.p99, 990.0),
+            (snapshot.p999, 999.0),
+        ] {
+            let micros = value.as_nanos() as f64 / 1000.0;
+            assert!((micros - expected).abs() / expected < 0.05, "{value:?}");
+        }
+        assert!(snapshot.to_string().starts_with("1000 ops, mean 500.5µs"));
+
+        histogram.reset();
+        assert_eq!(histogram.snapshot(), HistogramSnapshot::default());
+    }
+
+    #[test]
+    fn test_threads_record_into_one_histogram() {
+        let histogram = Arc::new(LatencyHistogram::new());
+        let threads: Vec<_> = (0..16)
+            .map(|_| {
+                let histogram = Arc::clone(&histogram);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        histogram.record(Duration::from_micros(10));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 16_000);
+        assert_eq!(snapshot.sum, Duration::from_millis(160));
+        assert_eq!(snapshot.max, Duration::from_micros(10));
+        assert!(snapshot.p999 <= Duration::from_micros(10));
+        assert!(snapshot.p50 >= Duration::from_micros(9));
+    }
+}
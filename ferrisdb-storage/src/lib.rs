@@ -6,7 +6,24 @@
 //! - **Write-Ahead Log (WAL)**: Ensures durability of writes
 //! - **MemTable**: In-memory write buffer using a skip list
 //! - **SSTable**: Sorted String Table for persistent storage
+//! - **Manifest**: Persistent log of which SSTables are live in each level
+//! - **Versions**: Reference-counted snapshots of the live SSTable set
+//! - **Files**: Names of tables, WAL segments and MANIFESTs, and removal of orphans
 //! - **Compaction**: Background process to merge and optimize SSTables
+//! - **Bulk import**: Externally sorted records written straight to SSTables, bypassing the WAL
+//! - **Merge operators**: Read-modify-write updates resolved lazily, with counters,
+//!   list appends and max/min ready-made
+//! - **Rate limiter**: Caps background I/O so it doesn't starve foreground writes
+//! - **Scheduler**: Runs flushes, compactions and other background jobs
+//! - **Write stalls**: Slow or stop writes while compaction falls behind
+//! - **Histograms**: Lock-free latency distributions with percentile snapshots
+//! - **Clocks**: Pluggable time source for TTL expiry and timestamps
+//! - **VFS**: Pluggable filesystem, with a simulated one for crash testing
+//! - **Remote storage**: Object stores holding cold SSTables, read through a local cache
+//! - **Timestamp oracle**: Hybrid logical clock issuing write timestamps
+//! - **Comparators**: Pluggable ordering of user keys
+//! - **Prefix extractors**: Key groups that bloom filters let scans skip tables by
+//! - **Typed tables**: Serde-encoded keys and values over a column family (feature `typed`)
 //!
 //! # Architecture
 //!
@@ -21,19 +38,46 @@
 //! # Example
 //!
 //! ```no_run
-//! use ferrisdb_storage::{StorageEngine, StorageConfig};
+//! use ferrisdb_storage::storage_engine::Options;
+//! use ferrisdb_storage::StorageEngine;
 //!
-//! let config = StorageConfig::default();
-//! let engine = StorageEngine::new(config);
+//! let engine = StorageEngine::open(Options::new("./data"))?;
+//! engine.put(b"key".to_vec(), b"value".to_vec())?;
+//! assert_eq!(engine.get(b"key")?, Some(b"value".to_vec()));
+//! # Ok::<(), ferrisdb_core::Error>(())
 //! ```
 
+pub mod clock;
+pub mod compaction;
+pub mod comparator;
 pub mod config;
+pub mod files;
 pub mod format;
+pub mod histogram;
+pub mod lock_manager;
+pub mod manifest;
 pub mod memtable;
+pub mod merge;
+pub mod merge_operators;
+pub mod oracle;
+pub mod prefix;
+pub mod rate_limiter;
+pub mod remote;
+pub mod scheduler;
 pub mod sstable;
 pub mod storage_engine;
+#[cfg(feature = "typed")]
+pub mod typed;
 pub mod utils;
+pub mod version;
+pub mod vfs;
 pub mod wal;
+pub mod write_stall;
 
-pub use config::StorageConfig;
-pub use storage_engine::StorageEngine;
+pub use config::{CompactionStrategyKind, MemTableKind, StorageConfig};
+pub use storage_engine::{
+    BackupEngine, BackupInfo, ColumnFamily, ColumnFamilyOptions, EngineIterator, FlushOptions,
+    ImportOptions, IndexExtractor, Options, PessimisticTransaction, PinnedSlice, ReadOptions,
+    SecondaryIndex, Snapshot, Statistics, StorageEngine, Transaction, WatchEvent, Watcher,
+    WriteBatch, WriteBatchWithIndex, WriteOptions,
+};
@@ -0,0 +1,431 @@
+//! Key-range locks for pessimistic transactions
+//!
+//! Interactive transactions ported from RDBMS-style clients expect a read
+//! or write to block conflicting transactions until they finish, rather
+//! than failing at commit. The [`LockManager`] provides that with
+//! two-phase locking: each transaction acquires locks as it goes and
+//! releases all of them at once when it commits or aborts.
+//!
+//! Locks cover key ranges; a single key is the range `[key, key]`. Shared
+//! locks are compatible with each other, an exclusive lock with nothing:
+//!
+//! ```text
+//!             held: Shared   Exclusive
+//! requested
+//! Shared            grant    wait
+//! Exclusive         wait     wait
+//! ```
+//!
+//! Locks held by the requester itself never conflict, so a transaction can
+//! upgrade a shared lock to an exclusive one.
+//!
+//! # Deadlocks
+//!
+//! A waiting transaction records which transactions it waits for. Before
+//! blocking, the manager follows this wait-for graph and fails the request
+//! that would close a cycle. Waits are also bounded by a timeout, which
+//! catches anything the graph can't see, such as a transaction that is
+//! simply never finished.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::lock_manager::{LockManager, LockMode};
+//! use std::time::Duration;
+//!
+//! let locks = LockManager::new();
+//! let (t1, t2) = (locks.new_owner(), locks.new_owner());
+//! let timeout = Duration::from_millis(10);
+//!
+//! locks.lock_key(t1, b"k", LockMode::Shared, timeout)?;
+//! locks.lock_key(t2, b"k", LockMode::Shared, timeout)?;
+//! assert!(locks.lock_key(t2, b"k", LockMode::Exclusive, timeout).is_err());
+//!
+//! locks.release_all(t1);
+//! locks.lock_key(t2, b"k", LockMode::Exclusive, timeout)?;
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use crate::comparator::{self, Comparator};
+use ferrisdb_core::{Error, Key, Result};
+
+use parking_lot::{Condvar, Mutex};
+
+use std::collections::{BTreeSet, HashMap};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Identifies the transaction holding or waiting for locks
+pub type LockOwner = u64;
+
+/// How a lock may be shared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockMode {
+    /// Held by any number of readers at once
+    Shared,
+    /// Held by a single writer, excluding everyone else
+    Exclusive,
+}
+
+/// Grants shared and exclusive locks on key ranges
+///
+/// Held locks are kept in a list that every request scans, which suits
+/// the modest number of locks interactive transactions hold at a time.
+/// Ranges are compared bytewise unless the manager is created
+/// [`with_comparator`](Self::with_comparator).
+#[derive(Debug)]
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    /// Signalled whenever locks are released
+    released: Condvar,
+    next_owner: AtomicU64,
+    /// Ordering of the keys in locked ranges
+    comparator: Arc<dyn Comparator>,
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    held: Vec<HeldLock>,
+    /// Wait-for graph: waiting owner -> owners holding conflicting locks
+    waiting: HashMap<LockOwner, BTreeSet<LockOwner>>,
+}
+
+#[derive(Debug)]
+struct HeldLock {
+    owner: LockOwner,
+    start: Bound<Key>,
+    end: Bound<Key>,
+    mode: LockMode,
+}
+
+impl LockManager {
+    /// Creates a lock manager with no locks held
+    pub fn new() -> Self {
+        Self::with_comparator(comparator::bytewise())
+    }
+
+    /// Creates a lock manager for keys ordered by `comparator`
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            table: Mutex::default(),
+            released: Condvar::new(),
+            next_owner: AtomicU64::new(0),
+            comparator,
+        }
+    }
+
+    /// Returns a fresh owner id for a new transaction
+    pub fn new_owner(&self) -> LockOwner {
+        self.next_owner.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Locks a single key, waiting up to `timeout` for conflicting locks
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Transaction` if waiting would deadlock or the
+    /// timeout expires first.
+    pub fn lock_key(
+        &self,
+        owner: LockOwner,
+        key: &[u8],
+        mode: LockMode,
+        timeout: Duration,
+    ) -> Result<()> {
+        let key = key.to_vec();
+        self.lock(
+            owner,
+            (Bound::Included(key.clone()), Bound::Included(key)),
+            mode,
+            timeout,
+        )
+    }
+
+    /// Locks every key in `range`, waiting up to `timeout` for conflicts
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Transaction` if waiting would deadlock or the
+    /// timeout expires first.
+    pub fn lock<K, R>(
+        &self,
+        owner: LockOwner,
+        range: R,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> Result<()>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let start = range.start_bound().map(|key| key.as_ref().to_vec());
+        let end = range.end_bound().map(|key| key.as_ref().to_vec());
+        let deadline = Instant::now() + timeout;
+
+        let mut table = self.table.lock();
+        loop {
+            if table.holds(owner, &start, &end, mode) {
+                return Ok(());
+            }
+
+            let blockers = table.blockers(&*self.comparator, owner, &start, &end, mode);
+            if blockers.is_empty() {
+                table.waiting.remove(&owner);
+                table.held.push(HeldLock {
+                    owner,
+                    start,
+                    end,
+                    mode,
+                });
+                return Ok(());
+            }
+
+            table.waiting.insert(owner, blockers);
+            if table.waits_for_itself(owner) {
+                table.waiting.remove(&owner);
+                return Err(Error::Transaction(format!(
+                    "Deadlock detected: transaction {} would wait for itself",
+                    owner
+                )));
+            }
+            if self.released.wait_until(&mut table, deadline).timed_out() {
+                table.waiting.remove(&owner);
+                if table
+                    .blockers(&*self.comparator, owner, &start, &end, mode)
+                    .is_empty()
+                {
+                    continue;
+                }
+                return Err(Error::Transaction(format!(
+                    "Transaction {} timed out after {:?} waiting for a lock",
+                    owner, timeout
+                )));
+            }
+        }
+    }
+
+    /// Releases every lock held by `owner` and wakes waiting requests
+    pub fn release_all(&self, owner: LockOwner) {
+        let mut table = self.table.lock();
+        table.held.retain(|lock| lock.owner != owner);
+        table.waiting.remove(&owner);
+        // Nobody waits for an owner without locks
+        for blockers in table.waiting.values_mut() {
+            blockers.remove(&owner);
+        }
+        self.released.notify_all();
+    }
+
+    /// Returns the number of locks currently held by `owner`
+    pub fn held_by(&self, owner: LockOwner) -> usize {
+        let table = self.table.lock();
+        table.held.iter().filter(|lock| lock.owner == owner).count()
+    }
+}
+
+impl LockTable {
+    /// Returns true if `owner` already holds exactly this lock, or stronger
+    fn holds(
+        &self,
+        owner: LockOwner,
+        start: &Bound<Key>,
+        end: &Bound<Key>,
+        mode: LockMode,
+    ) -> bool {
+        self.held.iter().any(|lock| {
+            lock.owner == owner && lock.mode >= mode && lock.start == *start && lock.end == *end
+        })
+    }
+
+    /// Returns the other owners holding locks that conflict with the request
+    fn blockers(
+        &self,
+        comparator: &dyn Comparator,
+        owner: LockOwner,
+        start: &Bound<Key>,
+        end: &Bound<Key>,
+        mode: LockMode,
+    ) -> BTreeSet<LockOwner> {
+        self.held
+            .iter()
+            .filter(|lock| lock.owner != owner)
+            .filter(|lock| mode == LockMode::Exclusive || lock.mode == LockMode::Exclusive)
+            .filter(|lock| overlaps(comparator, (start, end), (&lock.start, &lock.end)))
+            .map(|lock| lock.owner)
+            .collect()
+    }
+
+    /// Returns true if the wait-for graph leads from `owner` back to itself
+    fn waits_for_itself(&self, owner: LockOwner) -> bool {
+        let mut visited = BTreeSet::new();
+        let mut stack: Vec<LockOwner> = self.waiting[&owner].iter().copied().collect();
+        while let Some(next) = stack.pop() {
+            if next == owner {
+                return true;
+            }
+            if visited.insert(next) {
+                if let Some(blockers) = self.waiting.get(&next) {
+                    stack.extend(blockers);
+                }
+            }
+        }
+        false
+    }
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns true if two key ranges may share a key
+fn overlaps(
+    comparator: &dyn Comparator,
+    a: (&Bound<Key>, &Bound<Key>),
+    b: (&Bound<Key>, &Bound<Key>),
+) -> bool {
+    !ends_before(comparator, a.1, b.0) && !ends_before(comparator, b.1, a.0)
+}
+
+/// Returns true if every key up to `end` sorts before `start`
+fn ends_before(comparator: &dyn Comparator, end: &Bound<Key>, start: &Bound<Key>) -> bool {
+    match (end, start) {
+        (Bound::Unbounded, _) | (_, Bound::Unbounded) => false,
+        (Bound::Included(end), Bound::Included(start)) => comparator.compare(end, start).is_lt(),
+        (Bound::Included(end), Bound::Excluded(start))
+        | (Bound::Excluded(end), Bound::Included(start))
+        | (Bound::Excluded(end), Bound::Excluded(start)) => comparator.compare(end, start).is_le(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    const SHORT: Duration = Duration::from_millis(20);
+    const LONG: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_shared_locks_are_compatible_exclusive_are_not() {
+        let locks = LockManager::new();
+        let (t1, t2) = (locks.new_owner(), locks.new_owner());
+
+        locks.lock_key(t1, b"a", LockMode::Shared, SHORT).unwrap();
+        locks.lock_key(t2, b"a", LockMode::Shared, SHORT).unwrap();
+        assert!(locks
+            .lock_key(t2, b"a", LockMode::Exclusive, SHORT)
+            .is_err());
+        locks
+            .lock_key(t2, b"b", LockMode::Exclusive, SHORT)
+            .unwrap();
+        assert!(locks.lock_key(t1, b"b", LockMode::Shared, SHORT).is_err());
+
+        // Re-locking what is already held is a no-op
+        locks.lock_key(t1, b"a", LockMode::Shared, SHORT).unwrap();
+        assert_eq!(locks.held_by(t1), 1);
+    }
+
+    #[test]
+    fn test_own_locks_never_conflict() {
+        let locks = LockManager::new();
+        let t1 = locks.new_owner();
+
+        locks.lock_key(t1, b"a", LockMode::Shared, SHORT).unwrap();
+        locks
+            .lock_key(t1, b"a", LockMode::Exclusive, SHORT)
+            .unwrap();
+        locks
+            .lock(t1, b"a".as_slice().., LockMode::Exclusive, SHORT)
+            .unwrap();
+        assert_eq!(locks.held_by(t1), 3);
+
+        locks.release_all(t1);
+        assert_eq!(locks.held_by(t1), 0);
+    }
+
+    #[test]
+    fn test_range_locks_conflict_only_when_overlapping() {
+        let locks = LockManager::new();
+        let (t1, t2) = (locks.new_owner(), locks.new_owner());
+
+        locks
+            .lock(
+                t1,
+                b"b".as_slice()..b"d".as_slice(),
+                LockMode::Exclusive,
+                SHORT,
+            )
+            .unwrap();
+        locks
+            .lock_key(t2, b"a", LockMode::Exclusive, SHORT)
+            .unwrap();
+        locks
+            .lock_key(t2, b"d", LockMode::Exclusive, SHORT)
+            .unwrap();
+        assert!(locks.lock_key(t2, b"c", LockMode::Shared, SHORT).is_err());
+        assert!(locks
+            .lock::<[u8], _>(t2, .., LockMode::Shared, SHORT)
+            .is_err());
+    }
+
+    #[test]
+    fn test_waiter_proceeds_once_lock_is_released() {
+        let locks = Arc::new(LockManager::new());
+        let (t1, t2) = (locks.new_owner(), locks.new_owner());
+        locks
+            .lock_key(t1, b"a", LockMode::Exclusive, SHORT)
+            .unwrap();
+
+        let waiter = {
+            let locks = Arc::clone(&locks);
+            thread::spawn(move || locks.lock_key(t2, b"a", LockMode::Exclusive, LONG))
+        };
+        thread::sleep(SHORT);
+        locks.release_all(t1);
+
+        waiter.join().unwrap().unwrap();
+        assert_eq!(locks.held_by(t2), 1);
+    }
+
+    #[test]
+    fn test_deadlock_is_detected() {
+        let locks = Arc::new(LockManager::new());
+        let (t1, t2) = (locks.new_owner(), locks.new_owner());
+        locks
+            .lock_key(t1, b"a", LockMode::Exclusive, SHORT)
+            .unwrap();
+        locks
+            .lock_key(t2, b"b", LockMode::Exclusive, SHORT)
+            .unwrap();
+
+        let barrier = Arc::new(Barrier::new(2));
+        let first = {
+            let locks = Arc::clone(&locks);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                let result = locks.lock_key(t1, b"b", LockMode::Exclusive, LONG);
+                if result.is_err() {
+                    locks.release_all(t1);
+                }
+                result
+            })
+        };
+        barrier.wait();
+        let second = locks.lock_key(t2, b"a", LockMode::Exclusive, LONG);
+        if second.is_err() {
+            locks.release_all(t2);
+        }
+        let first = first.join().unwrap();
+
+        // Exactly one side is chosen as the victim; the other proceeds
+        assert!(first.is_err() != second.is_err());
+        let err = first.err().or(second.err()).unwrap();
+        assert!(err.to_string().contains("Deadlock"), "{}", err);
+    }
+}
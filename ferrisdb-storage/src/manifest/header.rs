@@ -0,0 +1,265 @@
+//! MANIFEST file header implementation
+//!
+//! The manifest header uses the same 64-byte layout as the WAL header so
+//! that both files can be identified and validated the same way.
+
+use crate::format::{ChecksummedHeader, FileFormat, FileHeader, FileMetadata, ValidateFile};
+use ferrisdb_core::{Error, Result};
+
+use crc32fast::Hasher;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Magic number identifying MANIFEST files
+/// Format: "FDB_MAN\0" (7 chars + null terminator)
+pub const MANIFEST_MAGIC: &[u8; 8] = b"FDB_MAN\0";
+
+/// Current MANIFEST format version (1.0)
+pub const MANIFEST_CURRENT_VERSION: u16 = 0x0100;
+
+/// Size of MANIFEST header in bytes
+pub const MANIFEST_HEADER_SIZE: usize = 64;
+
+/// MANIFEST file header
+///
+/// ## Binary Layout
+///
+/// ```text
+/// struct ManifestHeader {
+///     magic: [u8; 8],           // offset 0:  "FDB_MAN\0"
+///     version: u16,             // offset 8:  0x0100 (v1.0)
+///     flags: u16,               // offset 10: 0x0000 (reserved)
+///     header_size: u32,         // offset 12: 64
+///     header_checksum: u32,     // offset 16: CRC32 of bytes 0-15,20-63
+///     record_start_offset: u32, // offset 20: 64
+///     created_at: u64,          // offset 24: microseconds since epoch
+///     file_sequence: u64,       // offset 32: unique file ID
+///     reserved: [u8; 24],       // offset 40: zeros (future use)
+/// }  // Total: 64 bytes
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManifestHeader {
+    /// Magic bytes identifying this as a MANIFEST file
+    pub magic: [u8; 8],
+    /// Version number (major.minor in high.low bytes)
+    pub version: u16,
+    /// Feature flags (currently unused, must be 0)
+    pub flags: u16,
+    /// Total size of header (64 for v1.0)
+    pub header_size: u32,
+    /// CRC32 checksum of header (excluding this field)
+    pub header_checksum: u32,
+    /// Offset where records begin (64 for v1.0)
+    pub record_start_offset: u32,
+    /// Creation timestamp in microseconds since Unix epoch
+    pub created_at: u64,
+    /// Unique sequence number for this file
+    pub file_sequence: u64,
+    /// Reserved for future use (must be zero)
+    pub reserved: [u8; 24],
+}
+
+impl ManifestHeader {
+    /// Create a new MANIFEST header with the given file sequence
+    pub fn new(file_sequence: u64) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+
+        let mut header = Self {
+            magic: *MANIFEST_MAGIC,
+            version: MANIFEST_CURRENT_VERSION,
+            flags: 0,
+            header_size: MANIFEST_HEADER_SIZE as u32,
+            header_checksum: 0,
+            record_start_offset: MANIFEST_HEADER_SIZE as u32,
+            created_at,
+            file_sequence,
+            reserved: [0; 24],
+        };
+
+        header.header_checksum = header.calculate_checksum();
+        header
+    }
+}
+
+impl FileFormat for ManifestHeader {
+    const MAGIC: &'static [u8; 8] = MANIFEST_MAGIC;
+    const FORMAT_NAME: &'static str = "MANIFEST";
+    const CURRENT_VERSION: u16 = MANIFEST_CURRENT_VERSION;
+    const MIN_SUPPORTED_VERSION: u16 = 0x0100; // v1.0
+}
+
+impl FileHeader for ManifestHeader {
+    const HEADER_SIZE: usize = MANIFEST_HEADER_SIZE;
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; Self::HEADER_SIZE];
+
+        buf[0..8].copy_from_slice(&self.magic);
+        buf[8..10].copy_from_slice(&self.version.to_le_bytes());
+        buf[10..12].copy_from_slice(&self.flags.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.header_size.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.header_checksum.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.record_start_offset.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.created_at.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.file_sequence.to_le_bytes());
+        buf[40..64].copy_from_slice(&self.reserved);
+
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(Error::Corruption(format!(
+                "MANIFEST header too small: {} bytes (expected {})",
+                data.len(),
+                Self::HEADER_SIZE
+            )));
+        }
+
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(data[offset..offset + 8].try_into().expect("8 bytes"))
+        };
+
+        let mut magic = [0u8; 8];
+        magic.copy_from_slice(&data[0..8]);
+        let mut reserved = [0u8; 24];
+        reserved.copy_from_slice(&data[40..64]);
+
+        let header = Self {
+            magic,
+            version: u16::from_le_bytes([data[8], data[9]]),
+            flags: u16::from_le_bytes([data[10], data[11]]),
+            header_size: u32_at(12),
+            header_checksum: u32_at(16),
+            record_start_offset: u32_at(20),
+            created_at: u64_at(24),
+            file_sequence: u64_at(32),
+            reserved,
+        };
+
+        // Validate immediately after decoding
+        header.validate()?;
+
+        Ok(header)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if &self.magic != Self::MAGIC {
+            return Err(Error::Corruption(format!(
+                "Invalid MANIFEST magic: expected {:?}, found {:?}",
+                Self::MAGIC,
+                self.magic
+            )));
+        }
+
+        if !self.is_version_supported() {
+            return Err(Error::Corruption(format!(
+                "Unsupported MANIFEST version: {}.{} (supported: {}.x)",
+                self.version >> 8,
+                self.version & 0xFF,
+                Self::CURRENT_VERSION >> 8
+            )));
+        }
+
+        if self.header_size != Self::HEADER_SIZE as u32 {
+            return Err(Error::Corruption(format!(
+                "Invalid MANIFEST header size: {} (expected {})",
+                self.header_size,
+                Self::HEADER_SIZE
+            )));
+        }
+
+        if self.flags != 0 {
+            return Err(Error::Corruption(format!(
+                "Invalid MANIFEST flags: {:#x} (must be 0)",
+                self.flags
+            )));
+        }
+
+        self.verify_checksum()?;
+
+        Ok(())
+    }
+
+    fn magic(&self) -> &[u8; 8] {
+        &self.magic
+    }
+
+    fn version(&self) -> u16 {
+        self.version
+    }
+}
+
+impl ValidateFile for ManifestHeader {}
+
+impl ChecksummedHeader for ManifestHeader {
+    fn calculate_checksum(&self) -> u32 {
+        let mut hasher = Hasher::new();
+
+        // Hash all fields except the checksum itself
+        hasher.update(&self.magic);
+        hasher.update(&self.version.to_le_bytes());
+        hasher.update(&self.flags.to_le_bytes());
+        hasher.update(&self.header_size.to_le_bytes());
+        hasher.update(&self.record_start_offset.to_le_bytes());
+        hasher.update(&self.created_at.to_le_bytes());
+        hasher.update(&self.file_sequence.to_le_bytes());
+        hasher.update(&self.reserved);
+
+        hasher.finalize()
+    }
+
+    fn stored_checksum(&self) -> u32 {
+        self.header_checksum
+    }
+}
+
+impl FileMetadata for ManifestHeader {
+    fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    fn file_id(&self) -> u64 {
+        self.file_sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that MANIFEST headers preserve all data through encode/decode.
+    #[test]
+    fn encode_decode_preserves_all_header_fields() {
+        let header = ManifestHeader::new(42);
+        let decoded = ManifestHeader::decode(&header.encode()).unwrap();
+
+        assert_eq!(header, decoded);
+        assert_eq!(decoded.file_id(), 42);
+    }
+
+    /// Tests that a WAL header is not mistaken for a MANIFEST header.
+    #[test]
+    fn decode_rejects_wal_magic() {
+        let wal_header = crate::wal::WALHeader::new(42).encode();
+
+        let err = ManifestHeader::decode(&wal_header).unwrap_err();
+        assert!(matches!(err, Error::Corruption(msg) if msg.contains("Invalid MANIFEST magic")));
+    }
+
+    /// Tests that header decoding detects checksum corruption.
+    #[test]
+    fn decode_returns_error_when_checksum_corrupted() {
+        let mut encoded = ManifestHeader::new(42).encode();
+        encoded[33] ^= 0xFF;
+
+        let err = ManifestHeader::decode(&encoded).unwrap_err();
+        assert!(matches!(err, Error::Corruption(msg) if msg.contains("checksum")));
+    }
+}
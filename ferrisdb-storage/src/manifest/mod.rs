@@ -0,0 +1,95 @@
+//! MANIFEST: the persistent log of changes to the LSM tree's file set
+//!
+//! SSTables are immutable, but the set of *live* SSTables changes with
+//! every flush and compaction. The MANIFEST records each such change as a
+//! [`VersionEdit`], so that after a restart the engine can replay the edits
+//! and know exactly which files exist in which level, which WAL files are
+//! still needed, and which file number to allocate next.
+//!
+//! ## File Format Overview
+//!
+//! A MANIFEST file consists of:
+//! 1. A 64-byte header (see [`ManifestHeader`])
+//! 2. Zero or more edit records (see [`VersionEdit::encode`])
+//!
+//! ```text
+//! +-------------------+
+//! |  MANIFEST Header  |  64 bytes - File identification and metadata
+//! +-------------------+
+//! |   Edit Record     |  Variable size - e.g. flush adds table 5 to L0
+//! +-------------------+
+//! |   Edit Record     |  Variable size - e.g. compaction moves 5 to L1
+//! +-------------------+
+//! |       ...         |
+//! +-------------------+
+//! ```
+//!
+//! ## Header Format (64 bytes)
+//!
+//! ```text
+//! Offset  Size  Field               Description
+//! ------  ----  -----               -----------
+//! 0       8     magic               Magic bytes: "FDB_MAN\0"
+//! 8       2     version             Format version (major.minor)
+//! 10      2     flags               Feature flags (must be 0)
+//! 12      4     header_size         Size of header (64)
+//! 16      4     header_checksum     CRC32 of header (excluding this field)
+//! 20      4     record_start_offset Where records begin (64)
+//! 24      8     created_at          Creation time (µs since Unix epoch)
+//! 32      8     file_sequence       Unique file identifier
+//! 40      24    reserved            Reserved for future use (zeros)
+//! ```
+//!
+//! ## Record Format (Variable size)
+//!
+//! ```text
+//! Offset  Size  Field     Description
+//! ------  ----  -----     -----------
+//! 0       4     length    Record size excluding this field
+//! 4       4     checksum  CRC32 of the tagged fields
+//! 8       var   fields    Sequence of [tag:1][field] pairs
+//! ```
+//!
+//! ## Recovery
+//!
+//! Records are appended and synced one at a time, so a crash can leave at
+//! most one torn record at the end of the file. Recovery stops there and
+//! [`ManifestWriter::open`] truncates it before appending. A complete
+//! record with a bad checksum, or an edit that contradicts the edits before
+//! it, is reported as corruption.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use ferrisdb_storage::manifest::{ManifestWriter, SSTableMeta, VersionEdit};
+//! use ferrisdb_storage::sstable::SSTableWriter;
+//!
+//! let mut manifest = ManifestWriter::open("data/MANIFEST")?;
+//!
+//! // Flush: write table 12 and register it in L0
+//! let info = SSTableWriter::new("data/000012.sst")?.finish()?;
+//! let mut edit = VersionEdit::default();
+//! edit.add_file(0, SSTableMeta::new(12, &info));
+//! edit.set_next_file_number(13);
+//! manifest.log_and_apply(edit)?;
+//!
+//! for meta in manifest.state().files(0) {
+//!     println!("L0 table {} ({} bytes)", meta.number, meta.file_size);
+//! }
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+mod edit;
+mod header;
+mod reader;
+mod state;
+mod writer;
+
+pub use edit::{SSTableMeta, VersionEdit};
+pub use header::{ManifestHeader, MANIFEST_CURRENT_VERSION, MANIFEST_HEADER_SIZE, MANIFEST_MAGIC};
+pub use reader::ManifestReader;
+pub use state::ManifestState;
+pub use writer::ManifestWriter;
+
+/// Number of levels in the LSM tree (L0 through L6)
+pub const NUM_LEVELS: usize = 7;
@@ -0,0 +1,145 @@
+use super::edit::{MAX_RECORD_SIZE, RECORD_HEADER_SIZE};
+use super::{ManifestHeader, ManifestState, VersionEdit, MANIFEST_HEADER_SIZE};
+use crate::format::FileHeader;
+use crate::vfs::{self, Vfs, VfsFile};
+use ferrisdb_core::{Error, Result};
+
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Reader for MANIFEST files
+///
+/// Reads version edits sequentially after validating the file header. A
+/// record cut short by a crash during its write is treated as the end of
+/// the file, since the edit it carried was never acknowledged; a complete
+/// record that fails its checksum is reported as corruption.
+///
+/// # Example
+///
+/// ```no_run
+/// use ferrisdb_storage::manifest::ManifestReader;
+///
+/// let mut reader = ManifestReader::new("path/to/MANIFEST")?;
+/// let state = reader.recover()?;
+/// println!("{} live tables", state.file_count());
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct ManifestReader {
+    reader: BufReader<Box<dyn VfsFile>>,
+    header: ManifestHeader,
+    /// Offset just past the last complete record read so far
+    valid_len: u64,
+}
+
+impl ManifestReader {
+    /// Opens a MANIFEST file and validates its header
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or its header is
+    /// missing or invalid.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new_in(&vfs::os(), path)
+    }
+
+    /// Opens a MANIFEST file through `vfs` and validates its header
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`new`](Self::new).
+    pub fn new_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = vfs.open(path.as_ref())?;
+
+        let mut header_data = [0u8; MANIFEST_HEADER_SIZE];
+        file.read_exact(&mut header_data)?;
+        let header = ManifestHeader::decode(&header_data)?;
+
+        let valid_len = header.record_start_offset as u64;
+        file.seek(SeekFrom::Start(valid_len))?;
+
+        Ok(Self {
+            reader: BufReader::new(file),
+            header,
+            valid_len,
+        })
+    }
+
+    /// Get the MANIFEST file header
+    pub fn header(&self) -> &ManifestHeader {
+        &self.header
+    }
+
+    /// Returns the offset just past the last complete record read
+    ///
+    /// After reading to the end, anything beyond this offset is a torn
+    /// write and can be truncated before appending new records.
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
+
+    /// Reads the next version edit
+    ///
+    /// Returns `Ok(None)` at the end of the file or at a torn final record.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if a complete record is invalid.
+    pub fn read_edit(&mut self) -> Result<Option<VersionEdit>> {
+        let mut length_buf = [0u8; 4];
+        if !self.read_or_eof(&mut length_buf)? {
+            return Ok(None);
+        }
+
+        let length = u32::from_le_bytes(length_buf) as usize;
+        if !(RECORD_HEADER_SIZE - 4..=MAX_RECORD_SIZE).contains(&length) {
+            return Err(Error::Corruption(format!(
+                "MANIFEST record at offset {} has invalid length {}",
+                self.valid_len, length
+            )));
+        }
+
+        let mut record = vec![0u8; length + 4];
+        record[..4].copy_from_slice(&length_buf);
+        if !self.read_or_eof(&mut record[4..])? {
+            return Ok(None);
+        }
+
+        let edit = VersionEdit::decode(&record)?;
+        self.valid_len += record.len() as u64;
+        Ok(Some(edit))
+    }
+
+    /// Reads all remaining edits and applies them to an empty state
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if a record is invalid or an edit cannot
+    /// be applied to the state built from the edits before it.
+    pub fn recover(&mut self) -> Result<ManifestState> {
+        let mut state = ManifestState::default();
+        while let Some(edit) = self.read_edit()? {
+            state.apply(&edit).map_err(|e| {
+                Error::Corruption(format!("MANIFEST edit cannot be applied: {}", e))
+            })?;
+        }
+        Ok(state)
+    }
+
+    /// Fills `buf`, returning false if the file ends first
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> Result<bool> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Iterator for ManifestReader {
+    type Item = Result<VersionEdit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_edit().transpose()
+    }
+}
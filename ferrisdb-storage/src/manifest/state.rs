@@ -0,0 +1,346 @@
+//! The live file set reconstructed from version edits
+
+use super::{SSTableMeta, VersionEdit, NUM_LEVELS};
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::collections::BTreeMap;
+
+/// The set of live SSTables per level, plus persisted counters
+///
+/// This is the result of applying every edit in a MANIFEST, in order,
+/// starting from an empty database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestState {
+    /// Live tables of each level, keyed by file number
+    levels: [BTreeMap<u64, SSTableMeta>; NUM_LEVELS],
+    /// WAL files numbered below this are fully flushed
+    log_number: u64,
+    /// Next file number to allocate
+    next_file_number: u64,
+    /// Highest timestamp persisted in any SSTable
+    last_timestamp: Timestamp,
+    /// Names of the live column families other than the default, by id
+    column_families: BTreeMap<u32, String>,
+    /// Next column family id to allocate; ids are never reused
+    next_column_family_id: u32,
+}
+
+impl ManifestState {
+    /// Applies an edit, validating it against the current file set
+    ///
+    /// The edit is applied completely or not at all.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the edit names a level beyond
+    /// [`NUM_LEVELS`], deletes a table that isn't live in that level, or
+    /// adds a table number that is already live.
+    pub fn apply(&mut self, edit: &VersionEdit) -> Result<()> {
+        self.validate(edit)?;
+
+        for (level, number) in &edit.deleted_files {
+            self.levels[*level].remove(number);
+        }
+        for (level, meta) in &edit.new_files {
+            self.levels[*level].insert(meta.number, meta.clone());
+        }
+
+        if let Some(number) = edit.log_number {
+            self.log_number = number;
+        }
+        if let Some(number) = edit.next_file_number {
+            self.next_file_number = number;
+        }
+        if let Some(timestamp) = edit.last_timestamp {
+            self.last_timestamp = timestamp;
+        }
+
+        for id in &edit.dropped_column_families {
+            self.column_families.remove(id);
+        }
+        for (id, name) in &edit.added_column_families {
+            self.column_families.insert(*id, name.clone());
+            self.next_column_family_id = self.next_column_family_id.max(id + 1);
+        }
+        if let Some(id) = edit.next_column_family_id {
+            self.next_column_family_id = self.next_column_family_id.max(id);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that an edit can be applied without modifying the state
+    fn validate(&self, edit: &VersionEdit) -> Result<()> {
+        let check_level = |level: usize| {
+            if level >= NUM_LEVELS {
+                return Err(Error::InvalidOperation(format!(
+                    "Level {} out of range (max {})",
+                    level,
+                    NUM_LEVELS - 1
+                )));
+            }
+            Ok(())
+        };
+
+        let mut deleted = Vec::with_capacity(edit.deleted_files.len());
+        for &(level, number) in &edit.deleted_files {
+            check_level(level)?;
+            if !self.levels[level].contains_key(&number) || deleted.contains(&number) {
+                return Err(Error::InvalidOperation(format!(
+                    "Cannot delete table {} from level {}: not live",
+                    number, level
+                )));
+            }
+            deleted.push(number);
+        }
+
+        let mut added = Vec::with_capacity(edit.new_files.len());
+        for (level, meta) in &edit.new_files {
+            check_level(*level)?;
+            let live = self.live_level_of(meta.number).is_some() && !deleted.contains(&meta.number);
+            if live || added.contains(&meta.number) {
+                return Err(Error::InvalidOperation(format!(
+                    "Cannot add table {} to level {}: already live",
+                    meta.number, level
+                )));
+            }
+            added.push(meta.number);
+        }
+
+        let mut dropped = Vec::with_capacity(edit.dropped_column_families.len());
+        for &id in &edit.dropped_column_families {
+            if !self.column_families.contains_key(&id) || dropped.contains(&id) {
+                return Err(Error::InvalidOperation(format!(
+                    "Cannot drop column family {}: not live",
+                    id
+                )));
+            }
+            dropped.push(id);
+        }
+        for (i, (id, name)) in edit.added_column_families.iter().enumerate() {
+            let taken = *id == 0
+                || *id < self.next_column_family_id
+                || edit.added_column_families[..i]
+                    .iter()
+                    .any(|(other, _)| other == id);
+            let name_live = self
+                .column_families
+                .iter()
+                .any(|(live, live_name)| live_name == name && !dropped.contains(live))
+                || edit.added_column_families[..i]
+                    .iter()
+                    .any(|(_, other)| other == name);
+            if taken || name_live {
+                return Err(Error::InvalidOperation(format!(
+                    "Cannot add column family {} ({:?}): id or name already used",
+                    id, name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the level a live table belongs to
+    pub fn live_level_of(&self, number: u64) -> Option<usize> {
+        self.levels
+            .iter()
+            .position(|files| files.contains_key(&number))
+    }
+
+    /// Returns the live tables of a level, ordered by file number
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level >= NUM_LEVELS`.
+    pub fn files(&self, level: usize) -> impl Iterator<Item = &SSTableMeta> {
+        self.levels[level].values()
+    }
+
+    /// Returns the total number of live tables across all levels
+    pub fn file_count(&self) -> usize {
+        self.levels.iter().map(BTreeMap::len).sum()
+    }
+
+    /// Returns the total size in bytes of the live tables of a level
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.files(level).map(|meta| meta.file_size).sum()
+    }
+
+    /// Returns the oldest WAL file number still needed for recovery
+    pub fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    /// Returns the next file number to allocate
+    pub fn next_file_number(&self) -> u64 {
+        self.next_file_number
+    }
+
+    /// Returns the highest timestamp persisted in any SSTable
+    pub fn last_timestamp(&self) -> Timestamp {
+        self.last_timestamp
+    }
+
+    /// Returns the live column families other than the default, by id
+    pub fn column_families(&self) -> &BTreeMap<u32, String> {
+        &self.column_families
+    }
+
+    /// Returns the next column family id to allocate, never below 1
+    pub fn next_column_family_id(&self) -> u32 {
+        self.next_column_family_id.max(1)
+    }
+
+    /// Returns a single edit that recreates this state from scratch
+    ///
+    /// Used as the first record when starting a new MANIFEST file, so the
+    /// old one can be discarded.
+    pub fn snapshot(&self) -> VersionEdit {
+        let mut edit = VersionEdit::default();
+        edit.set_log_number(self.log_number);
+        edit.set_next_file_number(self.next_file_number);
+        edit.set_last_timestamp(self.last_timestamp);
+        for (level, files) in self.levels.iter().enumerate() {
+            for meta in files.values() {
+                edit.add_file(level, meta.clone());
+            }
+        }
+        for (id, name) in &self.column_families {
+            edit.add_column_family(*id, name.clone());
+        }
+        edit.set_next_column_family_id(self.next_column_family_id);
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::InternalKey;
+
+    fn meta(number: u64) -> SSTableMeta {
+        SSTableMeta {
+            number,
+            file_size: 100 * number,
+            entry_count: 1,
+            smallest: InternalKey::new(b"a".to_vec(), 1),
+            largest: InternalKey::new(b"z".to_vec(), 1),
+        }
+    }
+
+    #[test]
+    fn test_apply_moves_table_between_levels() {
+        let mut state = ManifestState::default();
+
+        let mut flush = VersionEdit::default();
+        flush.add_file(0, meta(1));
+        flush.add_file(0, meta(2));
+        flush.set_next_file_number(3);
+        state.apply(&flush).unwrap();
+
+        let mut compaction = VersionEdit::default();
+        compaction.delete_file(0, 1);
+        compaction.delete_file(0, 2);
+        compaction.add_file(1, meta(2));
+        compaction.add_file(1, meta(3));
+        compaction.set_next_file_number(4);
+        state.apply(&compaction).unwrap();
+
+        assert_eq!(state.files(0).count(), 0);
+        let level1: Vec<_> = state.files(1).map(|meta| meta.number).collect();
+        assert_eq!(level1, vec![2, 3]);
+        assert_eq!(state.level_size(1), 500);
+        assert_eq!(state.live_level_of(2), Some(1));
+        assert_eq!(state.next_file_number(), 4);
+    }
+
+    #[test]
+    fn test_apply_rejects_invalid_edits_atomically() {
+        let mut state = ManifestState::default();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, meta(1));
+        state.apply(&edit).unwrap();
+        let before = state.clone();
+
+        // Table already live
+        let mut edit = VersionEdit::default();
+        edit.set_next_file_number(9);
+        edit.add_file(1, meta(1));
+        assert!(matches!(
+            state.apply(&edit),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        // Table not live in that level
+        let mut edit = VersionEdit::default();
+        edit.delete_file(1, 1);
+        assert!(matches!(
+            state.apply(&edit),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        // Level out of range
+        let mut edit = VersionEdit::default();
+        edit.add_file(NUM_LEVELS, meta(2));
+        assert!(matches!(
+            state.apply(&edit),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn test_column_family_ids_are_never_reused() {
+        let mut state = ManifestState::default();
+        let mut edit = VersionEdit::default();
+        edit.add_column_family(1, "index");
+        edit.add_column_family(2, "meta");
+        state.apply(&edit).unwrap();
+
+        let mut edit = VersionEdit::default();
+        edit.drop_column_family(1);
+        state.apply(&edit).unwrap();
+        assert_eq!(state.next_column_family_id(), 3);
+
+        // A dropped id stays taken, a dropped name is free again
+        let mut edit = VersionEdit::default();
+        edit.add_column_family(1, "index");
+        assert!(state.apply(&edit).is_err());
+        let mut edit = VersionEdit::default();
+        edit.add_column_family(3, "meta");
+        assert!(state.apply(&edit).is_err());
+        let mut edit = VersionEdit::default();
+        edit.drop_column_family(1);
+        assert!(state.apply(&edit).is_err());
+
+        let mut edit = VersionEdit::default();
+        edit.add_column_family(3, "index");
+        state.apply(&edit).unwrap();
+        let names: Vec<_> = state.column_families().values().cloned().collect();
+        assert_eq!(names, vec!["meta".to_string(), "index".to_string()]);
+
+        let mut rebuilt = ManifestState::default();
+        rebuilt.apply(&state.snapshot()).unwrap();
+        assert_eq!(rebuilt, state);
+        assert_eq!(rebuilt.next_column_family_id(), 4);
+    }
+
+    #[test]
+    fn test_snapshot_recreates_state() {
+        let mut state = ManifestState::default();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, meta(4));
+        edit.add_file(2, meta(5));
+        edit.set_log_number(7);
+        edit.set_next_file_number(6);
+        edit.set_last_timestamp(99);
+        state.apply(&edit).unwrap();
+
+        let mut rebuilt = ManifestState::default();
+        rebuilt.apply(&state.snapshot()).unwrap();
+
+        assert_eq!(rebuilt, state);
+    }
+}
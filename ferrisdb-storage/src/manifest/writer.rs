@@ -0,0 +1,152 @@
+use super::{ManifestHeader, ManifestReader, ManifestState, VersionEdit};
+use crate::format::FileHeader;
+use crate::vfs::{self, Vfs, VfsFile};
+use ferrisdb_core::Result;
+
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Writer for MANIFEST files
+///
+/// Owns the current [`ManifestState`] and keeps it in sync with the file:
+/// an edit only becomes part of the state once its record is durably on
+/// disk, so the state never runs ahead of what recovery would rebuild.
+///
+/// # Example
+///
+/// ```no_run
+/// use ferrisdb_storage::manifest::{ManifestWriter, VersionEdit};
+///
+/// let mut manifest = ManifestWriter::create("path/to/MANIFEST")?;
+///
+/// let mut edit = VersionEdit::default();
+/// edit.set_next_file_number(2);
+/// manifest.log_and_apply(edit)?;
+///
+/// // After a restart, the same state is recovered
+/// let manifest = ManifestWriter::open("path/to/MANIFEST")?;
+/// assert_eq!(manifest.state().next_file_number(), 2);
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct ManifestWriter {
+    file: BufWriter<Box<dyn VfsFile>>,
+    path: PathBuf,
+    state: ManifestState,
+}
+
+impl ManifestWriter {
+    /// Creates a new, empty MANIFEST file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file already exists or cannot be written.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::create_in(&vfs::os(), path)
+    }
+
+    /// Creates a new, empty MANIFEST file through `vfs`, with the clock
+    /// for its sequence number provided by `vfs`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`create`](Self::create).
+    pub fn create_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        if let Some(parent) = path.parent() {
+            vfs.create_dir_all(parent)?;
+        }
+        if vfs.exists(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            )
+            .into());
+        }
+        let mut file = vfs.create(&path)?;
+
+        // Generate file sequence based on timestamp
+        let file_sequence = vfs.clock().now().as_micros() as u64;
+
+        file.write_all(&ManifestHeader::new(file_sequence).encode())?;
+        file.sync_all()?;
+        // Without this, a crash could lose the new file along with its edits
+        vfs::sync_parent_dir(vfs.as_ref(), &path)?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            path,
+            state: ManifestState::default(),
+        })
+    }
+
+    /// Opens an existing MANIFEST file, recovering its state
+    ///
+    /// A torn final record left by a crash is truncated so that new records
+    /// are appended directly after the last complete one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened, its header is invalid,
+    /// or a complete record is corrupted.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_in(&vfs::os(), path)
+    }
+
+    /// Opens an existing MANIFEST file through `vfs`, recovering its state
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`open`](Self::open).
+    pub fn open_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut reader = ManifestReader::new_in(vfs, &path)?;
+        let state = reader.recover()?;
+
+        let mut file = vfs.open_or_create(&path)?;
+        if file.size()? > reader.valid_len() {
+            file.set_len(reader.valid_len())?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+            path,
+            state,
+        })
+    }
+
+    /// Durably records an edit, then applies it to the in-memory state
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the edit is inconsistent with
+    /// the current state (nothing is written in that case), or an I/O error
+    /// if the record cannot be written and synced. After an I/O error the
+    /// file may end in a torn record, so the manifest must be reopened
+    /// before logging further edits.
+    pub fn log_and_apply(&mut self, edit: VersionEdit) -> Result<()> {
+        let mut next = self.state.clone();
+        next.apply(&edit)?;
+
+        self.file.write_all(&edit.encode())?;
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+
+        self.state = next;
+        Ok(())
+    }
+
+    /// Returns the state as of the last applied edit
+    pub fn state(&self) -> &ManifestState {
+        &self.state
+    }
+
+    /// Returns the path to the MANIFEST file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
@@ -0,0 +1,245 @@
+//! Hash-indexed MemTable representation for point-lookup workloads
+//!
+//! This representation stores entries in an append-only insertion log and
+//! keeps a hash index from user key to the positions of that key's versions
+//! in the log. Compared to the skip list it:
+//!
+//! - Answers gets in O(1) expected time instead of O(log n)
+//! - Inserts with one hash probe and an append instead of a list search
+//! - Gives up cheap ordered iteration: a range scan must sort a snapshot
+//!
+//! Range scans still return correct results so that the table can always be
+//! flushed, but they cost O(n log n) and are meant for the flush path only.
+//!
+//! # Concurrency
+//!
+//! Keys are spread over a fixed number of shards, each guarded by its own
+//! `RwLock`. Writers to different shards never contend, and readers only
+//! block behind a writer to the same shard for the duration of an append.
+
+use crate::comparator::{self, Comparator};
+use ferrisdb_core::{Key, Operation, Timestamp, Value};
+use parking_lot::RwLock;
+use std::collections::hash_map::{DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Number of independently locked shards (power of two)
+const SHARD_COUNT: usize = 16;
+
+/// A single version stored in the insertion log
+#[derive(Debug, Clone)]
+pub struct HashEntry {
+    /// The user-provided key
+    pub user_key: Key,
+    /// Timestamp for MVCC versioning
+    pub timestamp: Timestamp,
+    /// Operation type (Put, Delete or Merge)
+    pub operation: Operation,
+    /// The value (empty for Delete operations, the operand for Merge)
+    pub value: Value,
+}
+
+/// One shard of the hash index
+#[derive(Default)]
+struct Shard {
+    /// Every version inserted into this shard, in arrival order
+    log: Vec<HashEntry>,
+    /// Positions in `log` of each key's versions, newest timestamp first
+    index: HashMap<Key, Vec<usize>>,
+}
+
+/// Hash map plus insertion log keyed by user key
+pub struct HashIndex {
+    shards: Box<[RwLock<Shard>]>,
+    /// Ordering of user keys in snapshots
+    comparator: Arc<dyn Comparator>,
+}
+
+impl HashIndex {
+    /// Creates a new empty hash index ordering keys bytewise
+    pub fn new() -> Self {
+        Self::with_comparator(comparator::bytewise())
+    }
+
+    /// Creates a new empty hash index ordering keys by `comparator`
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            shards: (0..SHARD_COUNT)
+                .map(|_| RwLock::new(Shard::default()))
+                .collect(),
+            comparator,
+        }
+    }
+
+    /// Returns the shard responsible for `user_key`
+    fn shard(&self, user_key: &[u8]) -> &RwLock<Shard> {
+        let mut hasher = DefaultHasher::new();
+        user_key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize & (SHARD_COUNT - 1)]
+    }
+
+    /// Inserts a new version of a key
+    ///
+    /// If the same key with the same timestamp already exists, it will not
+    /// be updated, matching the skip list's immutability of versions.
+    pub fn insert(&self, user_key: Key, value: Value, timestamp: Timestamp, operation: Operation) {
+        let mut shard = self.shard(&user_key).write();
+        let Shard { log, index } = &mut *shard;
+
+        let position = log.len();
+        let versions = index.entry(user_key.clone()).or_default();
+
+        // Versions are kept newest first; writes usually arrive in timestamp
+        // order, so the insertion point is almost always the front
+        let slot = versions.partition_point(|&pos| log[pos].timestamp > timestamp);
+        if versions
+            .get(slot)
+            .is_some_and(|&pos| log[pos].timestamp == timestamp)
+        {
+            return;
+        }
+        versions.insert(slot, position);
+
+        log.push(HashEntry {
+            user_key,
+            timestamp,
+            operation,
+            value,
+        });
+    }
+
+    /// Retrieves the newest version of a key visible at `timestamp`
+    pub fn get(&self, user_key: &[u8], timestamp: Timestamp) -> Option<(Value, Operation)> {
+        let shard = self.shard(user_key).read();
+        let versions = shard.index.get(user_key)?;
+
+        versions
+            .iter()
+            .map(|&pos| &shard.log[pos])
+            .find(|entry| entry.timestamp <= timestamp)
+            .map(|entry| (entry.value.clone(), entry.operation))
+    }
+
+    /// Returns every version of a key visible at `timestamp`, newest first
+    pub fn versions(
+        &self,
+        user_key: &[u8],
+        timestamp: Timestamp,
+    ) -> Vec<(Value, Timestamp, Operation)> {
+        let shard = self.shard(user_key).read();
+        let Some(versions) = shard.index.get(user_key) else {
+            return Vec::new();
+        };
+
+        versions
+            .iter()
+            .map(|&pos| &shard.log[pos])
+            .filter(|entry| entry.timestamp <= timestamp)
+            .map(|entry| (entry.value.clone(), entry.timestamp, entry.operation))
+            .collect()
+    }
+
+    /// Returns every version with a user key within the bounds, sorted
+    ///
+    /// Entries are ordered by user key ascending by the index's comparator,
+    /// then timestamp descending,
+    /// the same order the skip list iterates in. This copies and sorts the
+    /// matching part of the log, so it is O(n log n).
+    pub fn sorted_snapshot(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<HashEntry> {
+        let mut entries = Vec::new();
+        let comparator = &*self.comparator;
+
+        for shard in self.shards.iter() {
+            let shard = shard.read();
+            entries.extend(
+                shard
+                    .log
+                    .iter()
+                    .filter(|entry| comparator::contains(comparator, start, end, &entry.user_key))
+                    .cloned(),
+            );
+        }
+
+        entries.sort_unstable_by(|a, b| {
+            comparator
+                .compare(&a.user_key, &b.user_key)
+                .then_with(|| b.timestamp.cmp(&a.timestamp))
+        });
+        entries
+    }
+
+    /// Returns the number of entries across all shards
+    ///
+    /// Note: This counts all versions of all keys, not just unique keys.
+    pub fn size(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().log.len()).sum()
+    }
+}
+
+impl Default for HashIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_index_versions() {
+        let index = HashIndex::new();
+
+        // Insert out of timestamp order to exercise the sorted insert
+        index.insert(b"key1".to_vec(), b"value3".to_vec(), 5, Operation::Put);
+        index.insert(b"key1".to_vec(), b"value1".to_vec(), 1, Operation::Put);
+        index.insert(b"key1".to_vec(), b"value2".to_vec(), 3, Operation::Put);
+
+        assert_eq!(index.get(b"key1", 0), None);
+        assert_eq!(index.get(b"key1", 2).unwrap().0, b"value1");
+        assert_eq!(index.get(b"key1", 4).unwrap().0, b"value2");
+        assert_eq!(index.get(b"key1", 6).unwrap().0, b"value3");
+        assert_eq!(index.get(b"missing", 6), None);
+    }
+
+    #[test]
+    fn test_hash_index_duplicate_version_is_ignored() {
+        let index = HashIndex::new();
+
+        index.insert(b"key1".to_vec(), b"first".to_vec(), 1, Operation::Put);
+        index.insert(b"key1".to_vec(), b"second".to_vec(), 1, Operation::Put);
+
+        assert_eq!(index.size(), 1);
+        assert_eq!(index.get(b"key1", 1).unwrap().0, b"first");
+    }
+
+    #[test]
+    fn test_hash_index_sorted_snapshot_matches_skip_list_order() {
+        let index = HashIndex::new();
+
+        for (key, ts) in [(b"c", 1), (b"a", 1), (b"b", 1), (b"a", 4), (b"d", 2)] {
+            index.insert(key.to_vec(), Vec::new(), ts, Operation::Put);
+        }
+
+        let snapshot = index.sorted_snapshot(
+            Bound::Included(b"a".as_slice()),
+            Bound::Excluded(b"d".as_slice()),
+        );
+        let order: Vec<_> = snapshot
+            .iter()
+            .map(|entry| (entry.user_key.clone(), entry.timestamp))
+            .collect();
+
+        assert_eq!(
+            order,
+            vec![
+                (b"a".to_vec(), 4),
+                (b"a".to_vec(), 1),
+                (b"b".to_vec(), 1),
+                (b"c".to_vec(), 1)
+            ]
+        );
+    }
+}
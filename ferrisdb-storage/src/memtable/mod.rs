@@ -5,7 +5,8 @@
 //! skip list implementation that provides:
 //!
 //! - O(log n) insert, delete, and lookup operations
-//! - Lock-free reads with epoch-based memory reclamation
+//! - Lock-free reads and writes, so concurrent writers never serialize
+//!   on a mutex after the WAL has accepted their entries
 //! - Support for multiple versions of the same key (MVCC)
 //! - Efficient range scans
 //!
@@ -26,9 +27,14 @@
 //! # Ok::<(), ferrisdb_core::Error>(())
 //! ```
 
-use self::skip_list::SkipList;
+use self::hash_index::{HashEntry, HashIndex};
+use self::skip_list::{SkipList, SkipListIter};
+use self::sync::{AtomicUsize, Ordering};
+use crate::comparator::{self, Comparator};
+use crate::config::MemTableKind;
+use crate::sstable::{InternalKey as SSTableKey, SSTableEntry};
 use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
 
 /// In-memory write buffer using a concurrent skip list
@@ -40,10 +46,16 @@ use std::sync::Arc;
 /// - Efficient range scans
 /// - Memory usage tracking
 ///
+/// The skip list is the default index. Tables created with
+/// [`MemTableKind::HashIndex`] use a hash map plus insertion log instead,
+/// which speeds up gets and inserts at the cost of O(n log n) range scans.
+///
 /// # Thread Safety
 ///
 /// MemTable is designed to be shared across multiple threads safely.
-/// It uses a lock-free skip list internally for optimal performance.
+/// It uses a lock-free skip list internally for optimal performance, and
+/// capacity is reserved with a compare-and-swap so concurrent writers can
+/// never push the table past `max_size` between them.
 ///
 /// # Memory Management & LSM-Tree Integration
 ///
@@ -56,13 +68,13 @@ use std::sync::Arc;
 /// This sharing pattern requires `Arc<SkipList>` for zero-copy access across
 /// multiple components without expensive cloning of the entire data structure.
 pub struct MemTable {
-    /// The underlying skip list data structure
+    /// The underlying index data structure
     ///
     /// Uses Arc for shared ownership in LSM-tree scenarios:
     /// - Storage engine keeps immutable MemTables for reads during flush
     /// - Background threads flush MemTable to SSTable
     /// - Iterators need concurrent access without blocking writes
-    skiplist: Arc<SkipList>,
+    index: Index,
     /// Current memory usage in bytes (approximate)
     memory_usage: AtomicUsize,
     /// Maximum memory capacity before flush is needed
@@ -84,13 +96,91 @@ impl MemTable {
     /// let memtable = MemTable::new(4 * 1024 * 1024); // 4MB
     /// ```
     pub fn new(max_size: usize) -> Self {
+        Self::with_kind(max_size, MemTableKind::SkipList)
+    }
+
+    /// Creates a new MemTable backed by the given index implementation
+    ///
+    /// # Arguments
+    ///
+    /// * `max_size` - Maximum memory usage in bytes before flush is required
+    /// * `kind` - Which in-memory index to use
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::memtable::MemTable;
+    /// use ferrisdb_storage::MemTableKind;
+    ///
+    /// // Point-lookup workload: trade scan speed for faster gets
+    /// let memtable = MemTable::with_kind(4 * 1024 * 1024, MemTableKind::HashIndex);
+    /// ```
+    pub fn with_kind(max_size: usize, kind: MemTableKind) -> Self {
+        Self::with_comparator(max_size, kind, comparator::bytewise())
+    }
+
+    /// Creates a new MemTable ordering keys by `comparator`
+    ///
+    /// Scans and flushes yield keys in the comparator's order, which must
+    /// match the order of the SSTables the table is flushed into.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::comparator::ReverseBytewiseComparator;
+    /// use ferrisdb_storage::memtable::MemTable;
+    /// use ferrisdb_storage::MemTableKind;
+    /// use std::sync::Arc;
+    ///
+    /// let memtable = MemTable::with_comparator(
+    ///     1024,
+    ///     MemTableKind::SkipList,
+    ///     Arc::new(ReverseBytewiseComparator),
+    /// );
+    /// memtable.put(b"a".to_vec(), b"1".to_vec(), 1)?;
+    /// memtable.put(b"b".to_vec(), b"2".to_vec(), 1)?;
+    ///
+    /// let keys: Vec<_> = memtable.range::<[u8], _>(.., 1).map(|(key, _)| key).collect();
+    /// assert_eq!(keys, vec![b"b".to_vec(), b"a".to_vec()]);
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    pub fn with_comparator(
+        max_size: usize,
+        kind: MemTableKind,
+        comparator: Arc<dyn Comparator>,
+    ) -> Self {
+        let index = match kind {
+            MemTableKind::SkipList => {
+                Index::SkipList(Arc::new(SkipList::with_comparator(comparator)))
+            }
+            MemTableKind::HashIndex => {
+                Index::Hash(Arc::new(HashIndex::with_comparator(comparator)))
+            }
+        };
+
         Self {
-            skiplist: Arc::new(SkipList::new()),
+            index,
             memory_usage: AtomicUsize::new(0),
             max_size,
         }
     }
 
+    /// Returns which index implementation backs this MemTable
+    pub fn kind(&self) -> MemTableKind {
+        match self.index {
+            Index::SkipList(_) => MemTableKind::SkipList,
+            Index::Hash(_) => MemTableKind::HashIndex,
+        }
+    }
+
+    /// Inserts a version into whichever index backs this table
+    fn insert(&self, key: Key, value: Value, timestamp: Timestamp, operation: Operation) {
+        match &self.index {
+            Index::SkipList(skiplist) => skiplist.insert(key, value, timestamp, operation),
+            Index::Hash(hash) => hash.insert(key, value, timestamp, operation),
+        }
+    }
+
     /// Inserts a key-value pair into the MemTable
     ///
     /// This operation is atomic and thread-safe. The timestamp is used
@@ -107,17 +197,9 @@ impl MemTable {
     /// Returns an error if the MemTable is over capacity after the insert.
     /// Callers should flush the MemTable to disk when this occurs.
     pub fn put(&self, key: Key, value: Value, timestamp: Timestamp) -> Result<()> {
-        let size_estimate = key.len() + value.len() + 64; // 64 bytes overhead estimate
+        self.reserve(entry_size_estimate(&key, &value))?;
 
-        let current_usage = self.memory_usage.load(Ordering::Relaxed);
-        if current_usage + size_estimate > self.max_size {
-            return Err(Error::MemTableFull);
-        }
-
-        self.skiplist.insert(key, value, timestamp, Operation::Put);
-
-        self.memory_usage
-            .fetch_add(size_estimate, Ordering::Relaxed);
+        self.insert(key, value, timestamp, Operation::Put);
 
         Ok(())
     }
@@ -132,18 +214,32 @@ impl MemTable {
     /// * `key` - The key to delete
     /// * `timestamp` - MVCC timestamp for this delete operation
     pub fn delete(&self, key: Key, timestamp: Timestamp) -> Result<()> {
-        let size_estimate = key.len() + 64; // 64 bytes overhead estimate
+        self.reserve(entry_size_estimate(&key, &[]))?;
 
-        let current_usage = self.memory_usage.load(Ordering::Relaxed);
-        if current_usage + size_estimate > self.max_size {
-            return Err(Error::MemTableFull);
-        }
+        self.insert(key, Vec::new(), timestamp, Operation::Delete);
 
-        self.skiplist
-            .insert(key, Vec::new(), timestamp, Operation::Delete);
+        Ok(())
+    }
 
-        self.memory_usage
-            .fetch_add(size_estimate, Ordering::Relaxed);
+    /// Records a merge operand for a key
+    ///
+    /// The operand is stored as its own version and combined with the
+    /// key's older versions by a [`MergeOperator`](crate::merge::MergeOperator)
+    /// at read time (see [`versions`](Self::versions)) or during compaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to merge into
+    /// * `operand` - The operand to apply to the existing value
+    /// * `timestamp` - MVCC timestamp for this merge operation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the MemTable is over capacity after the insert.
+    pub fn merge(&self, key: Key, operand: Value, timestamp: Timestamp) -> Result<()> {
+        self.reserve(entry_size_estimate(&key, &operand))?;
+
+        self.insert(key, operand, timestamp, Operation::Merge);
 
         Ok(())
     }
@@ -162,9 +258,29 @@ impl MemTable {
     ///
     /// - `Some((value, Operation::Put))` if the key exists and is not deleted
     /// - `Some((_, Operation::Delete))` if the key has been deleted
+    /// - `Some((operand, Operation::Merge))` if the newest version is a merge
+    ///   operand; use [`versions`](Self::versions) to resolve it
     /// - `None` if the key doesn't exist or all versions are newer
     pub fn get(&self, key: &[u8], timestamp: Timestamp) -> Option<(Value, Operation)> {
-        self.skiplist.get(key, timestamp)
+        match &self.index {
+            Index::SkipList(skiplist) => skiplist.get(key, timestamp),
+            Index::Hash(hash) => hash.get(key, timestamp),
+        }
+    }
+
+    /// Returns every version of a key visible at `timestamp`, newest first
+    ///
+    /// Each item is `(value, timestamp, operation)`. This is the input for
+    /// [`merge::resolve`](crate::merge::resolve) when the newest version is
+    /// a merge operand that needs older versions to be applied to.
+    pub fn versions(&self, key: &[u8], timestamp: Timestamp) -> Vec<(Value, Timestamp, Operation)> {
+        match &self.index {
+            Index::SkipList(skiplist) => skiplist
+                .range_iter(Bound::Included(key), Bound::Included(key), timestamp)
+                .map(|(key, value)| (value.clone(), key.timestamp, key.operation))
+                .collect(),
+            Index::Hash(hash) => hash.versions(key, timestamp),
+        }
     }
 
     /// Performs a range scan over keys at a specific timestamp
@@ -189,7 +305,123 @@ impl MemTable {
         end_key: &[u8],
         timestamp: Timestamp,
     ) -> Vec<(Key, Value)> {
-        self.skiplist.scan(start_key, end_key, timestamp)
+        self.range(start_key..end_key, timestamp).collect()
+    }
+
+    /// Reserves `size` bytes of capacity for an insert
+    ///
+    /// The check and the increment happen in one atomic step, so two
+    /// writers racing for the last bytes cannot both succeed.
+    fn reserve(&self, size: usize) -> Result<()> {
+        self.memory_usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                let new_usage = current.checked_add(size)?;
+                (new_usage <= self.max_size).then_some(new_usage)
+            })
+            .map(|_| ())
+            .map_err(|_| Error::MemTableFull)
+    }
+
+    /// Returns an iterator over the keys in `range` as of `read_ts`
+    ///
+    /// For every user key in the range, only the newest version with a
+    /// timestamp at or below `read_ts` is considered; if that version is a
+    /// tombstone the key is skipped entirely. Keys whose visible version is
+    /// a merge operand are skipped as well, since resolving them needs a
+    /// merge operator and possibly versions outside this table. Keys are
+    /// yielded in ascending order by the table's comparator, which makes this iterator a direct input
+    /// to merged scans.
+    ///
+    /// With the skip list index the iterator reads the live list without
+    /// copying it; with the hash index it sorts a snapshot of the matching
+    /// entries up front, costing O(n log n). Writes
+    /// with timestamps above `read_ts` are never visible, so as long as the
+    /// caller only reads at timestamps that are already committed, the
+    /// result is a consistent snapshot even while other threads insert.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - Bounds on user keys, e.g. `b"a".as_slice()..b"m".as_slice()`
+    /// * `read_ts` - The snapshot timestamp to read at
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::memtable::MemTable;
+    ///
+    /// let memtable = MemTable::new(1024);
+    /// memtable.put(b"a".to_vec(), b"1".to_vec(), 1)?;
+    /// memtable.put(b"a".to_vec(), b"2".to_vec(), 3)?;
+    /// memtable.delete(b"b".to_vec(), 2)?;
+    ///
+    /// let visible: Vec<_> = memtable.range(b"a".as_slice()..b"z".as_slice(), 2).collect();
+    /// assert_eq!(visible, vec![(b"a".to_vec(), b"1".to_vec())]);
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    pub fn range<K, R>(&self, range: R, read_ts: Timestamp) -> MemTableIterator<'_>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let start = range.start_bound().map(AsRef::as_ref);
+        let end = range.end_bound().map(AsRef::as_ref);
+
+        let source = match &self.index {
+            Index::SkipList(skiplist) => Source::SkipList(skiplist.range_iter(start, end, read_ts)),
+            Index::Hash(hash) => Source::Sorted(hash.sorted_snapshot(start, end).into_iter()),
+        };
+
+        MemTableIterator {
+            source,
+            read_ts,
+            last_key: None,
+        }
+    }
+
+    /// Returns an iterator over every version of the keys in `range`
+    ///
+    /// Unlike [`range`](Self::range), nothing is resolved: all versions of
+    /// all keys are yielded in internal key order, tombstones and merge
+    /// operands included. Entries come in SSTable form, so a flush can write
+    /// them out directly and scans can merge them with SSTable iterators.
+    pub fn entries<K, R>(&self, range: R) -> MemTableEntries<'_>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let start = range.start_bound().map(AsRef::as_ref);
+        let end = range.end_bound().map(AsRef::as_ref);
+
+        let source = match &self.index {
+            Index::SkipList(skiplist) => {
+                Source::SkipList(skiplist.range_iter(start, end, Timestamp::MAX))
+            }
+            Index::Hash(hash) => Source::Sorted(hash.sorted_snapshot(start, end).into_iter()),
+        };
+
+        MemTableEntries { source }
+    }
+
+    /// Returns true if a version of `key` with `value` fits without a flush
+    ///
+    /// Uses the same estimate as [`put`](Self::put), so a writer that
+    /// checks first and is the only one inserting never sees
+    /// `Error::MemTableFull`.
+    pub fn has_room_for(&self, key: &[u8], value: &[u8]) -> bool {
+        self.has_room_for_all([(key, value)])
+    }
+
+    /// Returns true if versions for all `(key, value)` pairs fit together
+    pub fn has_room_for_all<'a, I>(&self, entries: I) -> bool
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    {
+        entries
+            .into_iter()
+            .try_fold(self.memory_usage(), |usage, (key, value)| {
+                usage.checked_add(entry_size_estimate(key, value))
+            })
+            .is_some_and(|usage| usage <= self.max_size)
     }
 
     /// Returns the approximate memory usage in bytes
@@ -212,11 +444,132 @@ impl MemTable {
     ///
     /// Note: This counts all versions of all keys, including tombstones.
     pub fn entry_count(&self) -> usize {
-        self.skiplist.size()
+        match &self.index {
+            Index::SkipList(skiplist) => skiplist.size(),
+            Index::Hash(hash) => hash.size(),
+        }
     }
 }
 
+/// Approximate memory taken by one version, including 64 bytes of overhead
+fn entry_size_estimate(key: &[u8], value: &[u8]) -> usize {
+    key.len() + value.len() + 64
+}
+
+/// The index backing a [`MemTable`]
+enum Index {
+    SkipList(Arc<SkipList>),
+    Hash(Arc<HashIndex>),
+}
+
+/// Raw entries feeding a [`MemTableIterator`], in internal key order
+enum Source<'a> {
+    /// Live iteration over the skip list
+    SkipList(SkipListIter<'a>),
+    /// Sorted copy of the hash index's matching entries
+    Sorted(std::vec::IntoIter<HashEntry>),
+}
+
+/// Iterator over the visible keys of a [`MemTable`] at a read timestamp
+///
+/// Created by [`MemTable::range`]. Yields `(key, value)` pairs in ascending
+/// key order, one per user key, with tombstoned and unresolved merged keys
+/// omitted.
+pub struct MemTableIterator<'a> {
+    /// Raw entries in internal key order
+    source: Source<'a>,
+    /// Snapshot timestamp; newer versions are invisible
+    read_ts: Timestamp,
+    /// User key whose visible version was already resolved
+    last_key: Option<Key>,
+}
+
+impl MemTableIterator<'_> {
+    /// Decides whether a raw entry is the visible version of a new key
+    ///
+    /// Versions are ordered newest first, so the first version at or below
+    /// the read timestamp resolves its key and every older one is shadowed.
+    fn resolves_key(&mut self, user_key: &[u8], timestamp: Timestamp) -> bool {
+        if timestamp > self.read_ts || self.last_key.as_deref() == Some(user_key) {
+            return false;
+        }
+
+        self.last_key = Some(user_key.to_vec());
+        true
+    }
+}
+
+impl Iterator for MemTableIterator<'_> {
+    type Item = (Key, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match &mut self.source {
+                Source::SkipList(iter) => {
+                    let (key, value) = iter.next()?;
+                    if self.resolves_key(&key.user_key, key.timestamp)
+                        && key.operation == Operation::Put
+                    {
+                        return Some((key.user_key.clone(), value.clone()));
+                    }
+                }
+                Source::Sorted(iter) => {
+                    let entry = iter.next()?;
+                    if self.resolves_key(&entry.user_key, entry.timestamp)
+                        && entry.operation == Operation::Put
+                    {
+                        return Some((entry.user_key, entry.value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the raw versions of a [`MemTable`]
+///
+/// Created by [`MemTable::entries`]. Yields every version in internal key
+/// order (user_key ASC, timestamp DESC).
+pub struct MemTableEntries<'a> {
+    source: Source<'a>,
+}
+
+impl Iterator for MemTableEntries<'_> {
+    type Item = SSTableEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (user_key, timestamp, operation, value) = match &mut self.source {
+            Source::SkipList(iter) => {
+                let (key, value) = iter.next()?;
+                (
+                    key.user_key.clone(),
+                    key.timestamp,
+                    key.operation,
+                    value.clone(),
+                )
+            }
+            Source::Sorted(iter) => {
+                let entry = iter.next()?;
+                (
+                    entry.user_key,
+                    entry.timestamp,
+                    entry.operation,
+                    entry.value,
+                )
+            }
+        };
+
+        Some(SSTableEntry::new(
+            SSTableKey::new(user_key, timestamp),
+            value,
+            operation,
+        ))
+    }
+}
+
+mod hash_index;
 mod skip_list;
+mod sync;
 
 #[cfg(test)]
 mod tests {
@@ -296,4 +649,160 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_memtable_range_returns_newest_visible_version() {
+        let memtable = MemTable::new(4096);
+
+        memtable.put(b"a".to_vec(), b"a1".to_vec(), 1).unwrap();
+        memtable.put(b"a".to_vec(), b"a5".to_vec(), 5).unwrap();
+        memtable.put(b"b".to_vec(), b"b2".to_vec(), 2).unwrap();
+        memtable.put(b"c".to_vec(), b"c6".to_vec(), 6).unwrap();
+
+        let results: Vec<_> = memtable
+            .range(b"a".as_slice()..b"z".as_slice(), 4)
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (b"a".to_vec(), b"a1".to_vec()),
+                (b"b".to_vec(), b"b2".to_vec())
+            ]
+        );
+
+        let results: Vec<_> = memtable
+            .range(b"a".as_slice()..b"z".as_slice(), 10)
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (b"a".to_vec(), b"a5".to_vec()),
+                (b"b".to_vec(), b"b2".to_vec()),
+                (b"c".to_vec(), b"c6".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memtable_range_skips_tombstones() {
+        let memtable = MemTable::new(4096);
+
+        memtable.put(b"a".to_vec(), b"a1".to_vec(), 1).unwrap();
+        memtable.delete(b"a".to_vec(), 3).unwrap();
+        memtable.put(b"b".to_vec(), b"b1".to_vec(), 1).unwrap();
+
+        // Before the delete the old value is visible
+        let results: Vec<_> = memtable
+            .range(b"a".as_slice()..b"c".as_slice(), 2)
+            .collect();
+        assert_eq!(results.len(), 2);
+
+        // After the delete the key disappears instead of exposing a stale value
+        let results: Vec<_> = memtable
+            .range(b"a".as_slice()..b"c".as_slice(), 3)
+            .collect();
+        assert_eq!(results, vec![(b"b".to_vec(), b"b1".to_vec())]);
+    }
+
+    #[test]
+    fn test_memtable_range_respects_bounds() {
+        let memtable = MemTable::new(4096);
+
+        for key in [b"a", b"b", b"c", b"d"] {
+            memtable.put(key.to_vec(), key.to_vec(), 1).unwrap();
+        }
+
+        let keys = |results: Vec<(Key, Value)>| -> Vec<Key> {
+            results.into_iter().map(|(key, _)| key).collect()
+        };
+
+        assert_eq!(
+            keys(
+                memtable
+                    .range(b"b".as_slice()..=b"c".as_slice(), 1)
+                    .collect()
+            ),
+            vec![b"b".to_vec(), b"c".to_vec()]
+        );
+        assert_eq!(
+            keys(memtable.range(b"c".to_vec().., 1).collect()),
+            vec![b"c".to_vec(), b"d".to_vec()]
+        );
+        assert_eq!(
+            keys(memtable.range::<[u8], _>(.., 1).collect()),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_memtable_hash_index_matches_skip_list() {
+        let skiplist = MemTable::new(4096);
+        let hash = MemTable::with_kind(4096, MemTableKind::HashIndex);
+        assert_eq!(skiplist.kind(), MemTableKind::SkipList);
+        assert_eq!(hash.kind(), MemTableKind::HashIndex);
+
+        for memtable in [&skiplist, &hash] {
+            memtable.put(b"b".to_vec(), b"b1".to_vec(), 1).unwrap();
+            memtable.put(b"a".to_vec(), b"a2".to_vec(), 2).unwrap();
+            memtable.put(b"a".to_vec(), b"a1".to_vec(), 1).unwrap();
+            memtable.delete(b"b".to_vec(), 3).unwrap();
+            memtable.put(b"c".to_vec(), b"c1".to_vec(), 1).unwrap();
+        }
+
+        for ts in 0..4 {
+            for key in [b"a", b"b", b"c", b"d"] {
+                assert_eq!(skiplist.get(key, ts), hash.get(key, ts));
+            }
+            assert_eq!(
+                skiplist.range::<[u8], _>(.., ts).collect::<Vec<_>>(),
+                hash.range::<[u8], _>(.., ts).collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(skiplist.entry_count(), hash.entry_count());
+        assert_eq!(skiplist.memory_usage(), hash.memory_usage());
+    }
+
+    #[test]
+    fn test_memtable_entries_yield_every_version_in_order() {
+        for kind in [MemTableKind::SkipList, MemTableKind::HashIndex] {
+            let memtable = MemTable::with_kind(4096, kind);
+            memtable.put(b"b".to_vec(), b"b1".to_vec(), 1).unwrap();
+            memtable.delete(b"b".to_vec(), 3).unwrap();
+            memtable.merge(b"a".to_vec(), b"+1".to_vec(), 2).unwrap();
+            memtable.put(b"c".to_vec(), b"c1".to_vec(), 4).unwrap();
+
+            let entries: Vec<_> = memtable
+                .entries(b"a".as_slice()..b"c".as_slice())
+                .map(|e| (e.key.user_key, e.key.timestamp, e.operation))
+                .collect();
+
+            assert_eq!(
+                entries,
+                vec![
+                    (b"a".to_vec(), 2, Operation::Merge),
+                    (b"b".to_vec(), 3, Operation::Delete),
+                    (b"b".to_vec(), 1, Operation::Put),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_memtable_has_room_for_matches_put() {
+        let memtable = MemTable::new(200);
+        assert!(memtable.has_room_for(b"key", &[0; 100]));
+        memtable.put(b"key".to_vec(), vec![0; 100], 1).unwrap();
+
+        assert!(!memtable.has_room_for(b"key", &[0; 100]));
+        assert!(memtable.put(b"key".to_vec(), vec![0; 100], 2).is_err());
+    }
+
+    #[test]
+    fn test_memtable_has_room_for_all_sums_entries() {
+        let memtable = MemTable::new(400);
+        let entry: (&[u8], &[u8]) = (b"key", &[0; 100]);
+        assert!(memtable.has_room_for_all([entry, entry]));
+        assert!(!memtable.has_room_for_all([entry, entry, entry]));
+        assert!(memtable.has_room_for_all([]));
+    }
 }
@@ -1,29 +1,37 @@
 //! Lock-free skip list implementation for the MemTable
 //!
 //! This module implements a concurrent skip list that supports:
-//! - Lock-free reads using epoch-based memory reclamation
-//! - Concurrent writes with fine-grained locking
+//! - Lock-free reads and writes using compare-and-swap on node links
 //! - Multiple versions of the same key (MVCC)
 //! - Efficient range scans
-
-use crossbeam::epoch::{self, Atomic, Guard, Owned, Shared};
+//!
+//! # Why no memory reclamation?
+//!
+//! A MemTable is append-only: nodes are linked in once and never unlinked
+//! until the whole table is dropped after its flush. Because no node is
+//! ever removed while readers are active, there is no ABA problem and no
+//! need for epoch or hazard-pointer reclamation. Every node is freed in
+//! [`Drop`], which runs only once no other thread holds a reference.
+
+use super::sync::{AtomicPtr, AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
+use crate::comparator::{self, Comparator};
 use ferrisdb_core::{Key, Operation, Timestamp, Value};
-use parking_lot::Mutex;
-use rand::{Rng, SeedableRng};
 use std::cmp::Ordering;
-use std::ops::Deref;
-use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::ptr;
+use std::sync::Arc;
 
 /// Maximum height of the skip list (affects memory usage and performance)
 const MAX_HEIGHT: usize = 12;
 
 /// Probability factor for determining node height (1/4 chance of increasing height)
-const BRANCHING_FACTOR: u32 = 4;
+const BRANCHING_FACTOR: u64 = 4;
 
 /// Internal key representation that includes metadata for MVCC
 ///
-/// Keys in the skip list are ordered first by user key (ascending),
-/// then by timestamp (descending). This ensures that:
+/// Keys in the skip list are ordered first by user key (ascending, by the
+/// list's comparator), then by timestamp (descending). This ensures that:
 /// - Keys are grouped together
 /// - Newer versions appear before older versions
 /// - Range scans are efficient
@@ -33,7 +41,7 @@ pub struct InternalKey {
     pub user_key: Key,
     /// Timestamp for MVCC versioning
     pub timestamp: Timestamp,
-    /// Operation type (Put or Delete)
+    /// Operation type (Put, Delete or Merge)
     pub operation: Operation,
 }
 
@@ -50,10 +58,10 @@ impl InternalKey {
     /// Compares two internal keys for ordering
     ///
     /// Keys are ordered by:
-    /// 1. User key (ascending)
+    /// 1. User key (ascending by `comparator`)
     /// 2. Timestamp (descending) - newer versions first
-    fn compare(&self, other: &Self) -> Ordering {
-        match self.user_key.cmp(&other.user_key) {
+    fn compare(&self, other: &Self, comparator: &dyn Comparator) -> Ordering {
+        match comparator.compare(&self.user_key, &other.user_key) {
             Ordering::Equal => {
                 // Newer timestamps come first (descending order)
                 match other.timestamp.cmp(&self.timestamp) {
@@ -76,17 +84,16 @@ struct Node {
     key: InternalKey,
     /// The value associated with this key version
     value: Value,
-    /// Next pointers for each level (height determines the vector length)
-    next: Vec<Atomic<Node>>,
+    /// Next pointers for each level (height determines the slice length)
+    next: Box<[AtomicPtr<Node>]>,
 }
 
 impl Node {
     /// Creates a new node with the specified height
     fn new(key: InternalKey, value: Value, height: usize) -> Self {
-        let mut next = Vec::with_capacity(height);
-        for _ in 0..height {
-            next.push(Atomic::null());
-        }
+        let next = (0..height)
+            .map(|_| AtomicPtr::new(ptr::null_mut()))
+            .collect();
 
         Self { key, value, next }
     }
@@ -106,42 +113,60 @@ impl Node {
 /// A concurrent skip list for storing versioned key-value pairs
 ///
 /// This skip list implementation provides:
-/// - O(log n) expected time for search, insert, and delete
-/// - Lock-free reads using epoch-based memory reclamation
+/// - O(log n) expected time for search and insert
+/// - Lock-free reads and writes; no operation ever blocks on a mutex
 /// - Support for multiple versions of the same key
 /// - Efficient range scans
 ///
 /// # Thread Safety
 ///
-/// Multiple threads can read concurrently without locking. Writes use
-/// fine-grained locking to allow concurrent modifications to different
-/// parts of the list.
+/// Any number of threads may insert and read concurrently. An insert
+/// becomes visible atomically when it is spliced into level 0 with a
+/// single compare-and-swap; the upper levels are only search shortcuts
+/// and are linked afterwards, so a reader may briefly find a new node at
+/// level 0 only, which is still a correct (if slightly slower) search.
 ///
 /// # Memory Management
 ///
-/// Uses crossbeam's epoch-based memory reclamation to safely free
-/// nodes that are no longer reachable, avoiding the ABA problem.
+/// Nodes are never removed while the list is alive, so raw pointers into
+/// the list stay valid for as long as `&self` does. All nodes are freed
+/// together when the skip list is dropped.
 pub struct SkipList {
     /// Sentinel head node
-    head: Atomic<Node>,
+    head: Box<Node>,
     /// Current height of the skip list
     height: AtomicUsize,
     /// Number of entries in the skip list
     size: AtomicUsize,
-    /// Random number generator for determining node heights
-    rng: Mutex<rand::rngs::StdRng>,
+    /// State of the SplitMix64 generator used for node heights
+    ///
+    /// A single `fetch_add` per insert replaces the mutex-guarded RNG so that
+    /// concurrent writers never serialize on height selection.
+    rng_state: AtomicU64,
+    /// Ordering of user keys
+    comparator: Arc<dyn Comparator>,
 }
 
 impl SkipList {
-    /// Creates a new empty skip list
+    /// Creates a new empty skip list ordering keys bytewise
     pub fn new() -> Self {
-        let head = Node::head(MAX_HEIGHT);
+        Self::with_comparator(comparator::bytewise())
+    }
+
+    /// Creates a new empty skip list ordering keys by `comparator`
+    pub fn with_comparator(comparator: Arc<dyn Comparator>) -> Self {
+        // Loom requires every execution of a model to be deterministic
+        #[cfg(loom)]
+        let seed = 0;
+        #[cfg(not(loom))]
+        let seed = rand::random::<u64>();
 
         Self {
-            head: Atomic::new(head),
+            head: Box::new(Node::head(MAX_HEIGHT)),
             height: AtomicUsize::new(1),
             size: AtomicUsize::new(0),
-            rng: Mutex::new(rand::rngs::StdRng::from_os_rng()),
+            rng_state: AtomicU64::new(seed),
+            comparator,
         }
     }
 
@@ -150,11 +175,21 @@ impl SkipList {
     /// Uses geometric distribution with p = 1/4 to determine height.
     /// This gives expected height of 1.33 and keeps the skip list balanced.
     fn random_height(&self) -> usize {
-        let mut height = 1;
-        let mut rng = self.rng.lock();
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+        // SplitMix64: advance the shared state, then mix the result
+        let mut z = self
+            .rng_state
+            .fetch_add(GAMMA, AtomicOrdering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
 
-        while height < MAX_HEIGHT && rng.random_ratio(1, BRANCHING_FACTOR) {
+        let mut height = 1;
+        while height < MAX_HEIGHT && z % BRANCHING_FACTOR == 0 {
             height += 1;
+            z /= BRANCHING_FACTOR;
         }
 
         height
@@ -162,149 +197,145 @@ impl SkipList {
 
     /// Inserts a new key-value pair with version information
     ///
-    /// This operation is thread-safe and uses compare-and-swap operations
-    /// to ensure consistency. If the same key with the same timestamp already
-    /// exists, it will not be updated (preserving immutability).
+    /// This operation is lock-free: the node is published with a single
+    /// compare-and-swap at level 0 and retried on contention. If the same
+    /// key with the same timestamp already exists, it will not be updated
+    /// (preserving immutability).
     ///
     /// # Arguments
     ///
     /// * `user_key` - The key to insert
     /// * `value` - The value to associate with the key
     /// * `timestamp` - Version timestamp for MVCC
-    /// * `operation` - Type of operation (Put or Delete)
+    /// * `operation` - Type of operation (Put, Delete or Merge)
     pub fn insert(&self, user_key: Key, value: Value, timestamp: Timestamp, operation: Operation) {
-        let guard = &epoch::pin();
         let key = InternalKey::new(user_key, timestamp, operation);
         let height = self.random_height();
 
-        // Update max height if necessary
+        // Raise the list height first so searches cover every level we link
+        self.height.fetch_max(height, AtomicOrdering::AcqRel);
+
+        let mut preds = [ptr::null(); MAX_HEIGHT];
+        let mut succs = [ptr::null_mut(); MAX_HEIGHT];
+
+        if self.find(&key, &mut preds, &mut succs) {
+            // Key already exists, we don't update in skip list
+            // (newer version should be inserted as separate entry)
+            return;
+        }
+
+        let node = Box::into_raw(Box::new(Node::new(key, value, height)));
+        // SAFETY: `node` was just allocated and is not yet shared
+        let node_ref = unsafe { &*node };
+
+        // Splice into level 0; success here is the linearization point
         loop {
-            let current_height = self.height.load(AtomicOrdering::Acquire);
-            if height <= current_height {
-                break;
-            }
+            node_ref.next[0].store(succs[0], AtomicOrdering::Relaxed);
 
-            if self
-                .height
+            // SAFETY: preds always point at the head or a live node
+            let pred = unsafe { &*preds[0] };
+            if pred.next[0]
                 .compare_exchange(
-                    current_height,
-                    height,
-                    AtomicOrdering::Release,
+                    succs[0],
+                    node,
+                    AtomicOrdering::AcqRel,
                     AtomicOrdering::Acquire,
                 )
                 .is_ok()
             {
                 break;
             }
-        }
-
-        let mut preds: Vec<Shared<Node>> = vec![Shared::null(); height];
-        let mut succs: Vec<Shared<Node>> = vec![Shared::null(); height];
 
-        loop {
-            if self.find(&key, &mut preds, &mut succs, guard) {
-                // Key already exists, we don't update in skip list
-                // (newer version should be inserted as separate entry)
-                break;
-            }
-
-            let new_node = Owned::new(Node::new(key.clone(), value.clone(), height));
-
-            // Set next pointers of new node
-            for (i, &succ) in succs.iter().enumerate().take(height) {
-                new_node.deref().next[i].store(succ, AtomicOrdering::Relaxed);
+            if self.find(&node_ref.key, &mut preds, &mut succs) {
+                // A concurrent writer inserted the same version first
+                // SAFETY: `node` was never published, so we still own it
+                drop(unsafe { Box::from_raw(node) });
+                return;
             }
+        }
 
-            let new_node_shared = new_node.into_shared(guard);
-
-            // Try to link the new node
-            match unsafe { preds[0].as_ref() }.unwrap().next[0].compare_exchange(
-                succs[0],
-                new_node_shared,
-                AtomicOrdering::Release,
-                AtomicOrdering::Acquire,
-                guard,
-            ) {
-                Ok(_) => {
-                    // Successfully linked at bottom level, link other levels
-                    for i in 1..height {
-                        loop {
-                            match unsafe { preds[i].as_ref() }.unwrap().next[i].compare_exchange(
-                                succs[i],
-                                new_node_shared,
-                                AtomicOrdering::Release,
-                                AtomicOrdering::Acquire,
-                                guard,
-                            ) {
-                                Ok(_) => break,
-                                Err(_) => {
-                                    // Re-find predecessors for this level
-                                    self.find(&key, &mut preds, &mut succs, guard);
-                                }
-                            }
-                        }
-                    }
-
-                    self.size.fetch_add(1, AtomicOrdering::Relaxed);
+        // Link the upper levels; these only speed up searches
+        for level in 1..height {
+            loop {
+                node_ref.next[level].store(succs[level], AtomicOrdering::Relaxed);
+
+                // SAFETY: preds always point at the head or a live node
+                let pred = unsafe { &*preds[level] };
+                if pred.next[level]
+                    .compare_exchange(
+                        succs[level],
+                        node,
+                        AtomicOrdering::AcqRel,
+                        AtomicOrdering::Acquire,
+                    )
+                    .is_ok()
+                {
                     break;
                 }
-                Err(_) => {
-                    // CAS failed, retry
-                    continue;
-                }
+
+                // Re-find predecessors for this level
+                self.find(&node_ref.key, &mut preds, &mut succs);
             }
         }
+
+        self.size.fetch_add(1, AtomicOrdering::Relaxed);
     }
 
     /// Finds the predecessors and successors for a key at each level
     ///
-    /// This is the core search operation used by insert and delete.
+    /// This is the core search operation used by insert and lookups.
     /// It populates the `preds` and `succs` arrays with the nodes
     /// before and after where the key would be inserted at each level.
+    /// Levels at or above the current list height are left untouched.
     ///
     /// # Arguments
     ///
     /// * `key` - The key to search for
     /// * `preds` - Array to fill with predecessor nodes at each level
     /// * `succs` - Array to fill with successor nodes at each level
-    /// * `guard` - Epoch guard for safe memory access
     ///
     /// # Returns
     ///
     /// `true` if an exact match for the key is found, `false` otherwise
-    fn find<'g>(
+    fn find(
         &self,
         key: &InternalKey,
-        preds: &mut [Shared<'g, Node>],
-        succs: &mut [Shared<'g, Node>],
-        guard: &'g Guard,
+        preds: &mut [*const Node; MAX_HEIGHT],
+        succs: &mut [*mut Node; MAX_HEIGHT],
     ) -> bool {
-        let mut pred = self.head.load(AtomicOrdering::Acquire, guard);
+        let mut pred: *const Node = &*self.head;
 
         for level in (0..self.height.load(AtomicOrdering::Acquire)).rev() {
-            let mut curr =
-                unsafe { pred.as_ref() }.unwrap().next[level].load(AtomicOrdering::Acquire, guard);
+            // SAFETY: `pred` is the head or a node reached through the list
+            let mut curr = unsafe { &*pred }.next[level].load(AtomicOrdering::Acquire);
 
-            while !curr.is_null() {
-                let curr_ref = unsafe { curr.as_ref() }.unwrap();
-
-                match key.compare(&curr_ref.key) {
+            // SAFETY: non-null links always point at live nodes
+            while let Some(curr_ref) = unsafe { curr.as_ref() } {
+                match key.compare(&curr_ref.key, &*self.comparator) {
                     Ordering::Greater => {
                         pred = curr;
-                        curr = curr_ref.next[level].load(AtomicOrdering::Acquire, guard);
+                        curr = curr_ref.next[level].load(AtomicOrdering::Acquire);
                     }
                     _ => break,
                 }
             }
 
-            if level < preds.len() {
-                preds[level] = pred;
-                succs[level] = curr;
-            }
+            preds[level] = pred;
+            succs[level] = curr;
         }
 
-        !succs[0].is_null()
-            && unsafe { succs[0].as_ref() }.unwrap().key.compare(key) == Ordering::Equal
+        // SAFETY: non-null links always point at live nodes
+        unsafe { succs[0].as_ref() }
+            .is_some_and(|node| node.key.compare(key, &*self.comparator) == Ordering::Equal)
+    }
+
+    /// Returns the first node whose key is not less than `key`
+    fn seek(&self, key: &InternalKey) -> *mut Node {
+        let mut preds = [ptr::null(); MAX_HEIGHT];
+        let mut succs = [ptr::null_mut(); MAX_HEIGHT];
+
+        self.find(key, &mut preds, &mut succs);
+        succs[0]
     }
 
     /// Retrieves the value for a key at a specific timestamp
@@ -321,106 +352,120 @@ impl SkipList {
     /// # Returns
     ///
     /// `Some((value, operation))` if the key exists at the given timestamp,
-    /// where operation indicates if this is a Put or Delete.
+    /// where operation indicates if this is a Put, Delete or Merge.
     /// `None` if the key doesn't exist or all versions are newer than the timestamp.
     pub fn get(&self, user_key: &[u8], timestamp: Timestamp) -> Option<(Value, Operation)> {
-        let guard = &epoch::pin();
-
-        // First, find the position where this key would be
-        let search_key = InternalKey::new(user_key.to_vec(), u64::MAX, Operation::Put);
-        let mut preds = vec![Shared::null(); 1];
-        let mut succs = vec![Shared::null(); 1];
-
-        self.find(&search_key, &mut preds, &mut succs, guard);
-
-        // Now scan from this position to find the right version
-        let mut curr = succs[0];
-
-        while !curr.is_null() {
-            let curr_ref = unsafe { curr.as_ref() }.unwrap();
-
-            if curr_ref.key.user_key != user_key {
-                break;
-            }
-
-            if curr_ref.key.timestamp <= timestamp {
-                return Some((curr_ref.value.clone(), curr_ref.key.operation));
-            }
-
-            curr = curr_ref.next[0].load(AtomicOrdering::Acquire, guard);
+        // Versions are ordered newest first, so seeking to (key, timestamp)
+        // lands directly on the newest version visible at `timestamp`
+        let search_key = InternalKey::new(user_key.to_vec(), timestamp, Operation::Put);
+
+        // SAFETY: non-null links always point at live nodes
+        let node = unsafe { self.seek(&search_key).as_ref() }?;
+        if node.key.user_key != user_key {
+            return None;
         }
 
-        None
+        Some((node.value.clone(), node.key.operation))
     }
 
-    /// Performs a range scan between start_key and end_key at a specific timestamp
+    /// Returns an iterator over every entry from `start` up to `end`
     ///
-    /// Returns all key-value pairs where the key is in the range [start_key, end_key)
-    /// and the timestamp is less than or equal to the given timestamp. For keys with
-    /// multiple versions, only the most recent valid version is returned.
+    /// The iterator yields raw entries in internal key order: all versions
+    /// of all keys, tombstones included. For an inclusive start bound,
+    /// versions of the start key newer than `timestamp` are skipped since
+    /// no reader at `timestamp` may see them.
     ///
-    /// Delete operations (tombstones) are filtered out from the results.
-    ///
-    /// # Arguments
-    ///
-    /// * `start_key` - The inclusive lower bound of the range
-    /// * `end_key` - The exclusive upper bound of the range
-    /// * `timestamp` - The timestamp to read at
-    ///
-    /// # Returns
-    ///
-    /// A vector of (key, value) pairs in ascending key order
-    pub fn scan(
+    /// Entries inserted concurrently may or may not be observed, depending
+    /// on whether they are linked before the iterator reaches their position.
+    pub fn range_iter(
         &self,
-        start_key: &[u8],
-        end_key: &[u8],
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
         timestamp: Timestamp,
-    ) -> Vec<(Key, Value)> {
-        let guard = &epoch::pin();
-        let mut result = Vec::new();
-        let mut seen_keys = std::collections::HashSet::new();
-
-        let search_key = InternalKey::new(start_key.to_vec(), timestamp, Operation::Put);
-        let mut preds = vec![Shared::null(); 1];
-        let mut succs = vec![Shared::null(); 1];
-
-        self.find(&search_key, &mut preds, &mut succs, guard);
-
-        let mut curr = succs[0];
-
-        while !curr.is_null() {
-            let curr_ref = unsafe { curr.as_ref() }.unwrap();
-
-            if curr_ref.key.user_key.as_slice() >= end_key {
-                break;
+    ) -> SkipListIter<'_> {
+        let next = match start {
+            Bound::Included(key) => {
+                self.seek(&InternalKey::new(key.to_vec(), timestamp, Operation::Put))
             }
-
-            if curr_ref.key.timestamp <= timestamp && !seen_keys.contains(&curr_ref.key.user_key) {
-                if curr_ref.key.operation == Operation::Put {
-                    result.push((curr_ref.key.user_key.clone(), curr_ref.value.clone()));
+            Bound::Excluded(key) => {
+                // (key, 0) sorts after every other version of `key`
+                let mut node = self.seek(&InternalKey::new(key.to_vec(), 0, Operation::Put));
+                // SAFETY: non-null links always point at live nodes
+                while let Some(node_ref) = unsafe { node.as_ref() } {
+                    if node_ref.key.user_key != key {
+                        break;
+                    }
+                    node = node_ref.next[0].load(AtomicOrdering::Acquire);
                 }
-                seen_keys.insert(curr_ref.key.user_key.clone());
+                node
             }
-
-            curr = curr_ref.next[0].load(AtomicOrdering::Acquire, guard);
+            Bound::Unbounded => self.head.next[0].load(AtomicOrdering::Acquire),
+        };
+
+        SkipListIter {
+            next,
+            end: end.map(<[u8]>::to_vec),
+            comparator: &*self.comparator,
+            _list: PhantomData,
         }
-
-        result
     }
 
     /// Returns the number of entries in the skip list
     ///
     /// Note: This counts all versions of all keys, not just unique keys.
-    #[allow(dead_code)]
     pub fn size(&self) -> usize {
         self.size.load(AtomicOrdering::Relaxed)
     }
 }
 
+/// Iterator over the raw entries of a [`SkipList`]
+///
+/// Created by [`SkipList::range_iter`]. Borrows the list, so every node it
+/// hands out stays alive for the iterator's lifetime.
+pub struct SkipListIter<'a> {
+    /// Next node to yield, or null when exhausted
+    next: *const Node,
+    /// Upper bound on user keys
+    end: Bound<Key>,
+    /// Ordering the bound is checked with
+    comparator: &'a dyn Comparator,
+    /// Ties yielded references to the borrowed list
+    _list: PhantomData<&'a SkipList>,
+}
+
+// SAFETY: the iterator only hands out shared references into a list it
+// borrows, exactly like `&SkipList`, which is Send + Sync
+unsafe impl Send for SkipListIter<'_> {}
+unsafe impl Sync for SkipListIter<'_> {}
+
+impl<'a> Iterator for SkipListIter<'a> {
+    type Item = (&'a InternalKey, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: nodes live as long as the list borrowed for 'a
+        let node: &'a Node = unsafe { self.next.as_ref() }?;
+
+        let end = self.end.as_ref().map(Vec::as_slice);
+        if !comparator::below_end(self.comparator, end, &node.key.user_key) {
+            self.next = ptr::null();
+            return None;
+        }
+
+        self.next = node.next[0].load(AtomicOrdering::Acquire);
+        Some((&node.key, &node.value))
+    }
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // SkipList automatically implements Send + Sync because:
-// - Atomic<Node> is Send + Sync (crossbeam atomics)
-// - AtomicUsize is Send + Sync (standard library)
-// - Mutex<StdRng> is Send + Sync (StdRng implements Send + Sync)
+// - AtomicPtr<Node> is Send + Sync regardless of the pointee
+// - AtomicUsize and AtomicU64 are Send + Sync
+// - Box<Node> only holds keys, values, and atomics
 //
 // Send + Sync are required because SkipList is used within Arc<SkipList>
 // for sharing between storage engine components:
@@ -428,21 +473,18 @@ impl SkipList {
 // - Background flush threads writing MemTable contents to SSTables
 // - Iterator support that outlives individual method calls
 //
-// Using StdRng instead of ThreadRng avoids unsafe impl and leverages
-// Rust's type system to automatically prove thread safety.
+// This is sound because nodes are immutable once published (only their
+// atomic links change) and are never freed before the list itself.
 
 impl Drop for SkipList {
     fn drop(&mut self) {
-        let guard = &epoch::pin();
-
-        let mut curr = self.head.load(AtomicOrdering::Acquire, guard);
+        // `&mut self` guarantees no concurrent readers or writers remain
+        let mut curr = self.head.next[0].load(AtomicOrdering::Relaxed);
         while !curr.is_null() {
-            let next =
-                unsafe { curr.as_ref() }.unwrap().next[0].load(AtomicOrdering::Acquire, guard);
-            unsafe {
-                guard.defer_destroy(curr);
-            }
-            curr = next;
+            // SAFETY: every linked node was created by Box::into_raw and is
+            // reachable exactly once through level 0
+            let node = unsafe { Box::from_raw(curr) };
+            curr = node.next[0].load(AtomicOrdering::Relaxed);
         }
     }
 }
@@ -503,4 +545,103 @@ mod tests {
         let result = sl.get(b"key1", 4);
         assert_eq!(result.unwrap().1, Operation::Delete);
     }
+
+    #[test]
+    fn test_skiplist_concurrent_inserts() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let sl = Arc::new(SkipList::new());
+
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let sl = Arc::clone(&sl);
+                thread::spawn(move || {
+                    for i in 0..250u64 {
+                        let key = format!("key{:04}", i * 4 + t).into_bytes();
+                        sl.insert(key, b"v".to_vec(), 1, Operation::Put);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(sl.size(), 1000);
+        let keys: Vec<_> = sl
+            .range_iter(Bound::Unbounded, Bound::Unbounded, 1)
+            .map(|(key, _)| key.user_key.clone())
+            .collect();
+        assert_eq!(keys.len(), 1000);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_skiplist_range_iter_bounds() {
+        let sl = SkipList::new();
+
+        sl.insert(b"a".to_vec(), b"a1".to_vec(), 1, Operation::Put);
+        sl.insert(b"b".to_vec(), b"b1".to_vec(), 1, Operation::Put);
+        sl.insert(b"b".to_vec(), b"b3".to_vec(), 3, Operation::Put);
+        sl.insert(b"c".to_vec(), b"c1".to_vec(), 1, Operation::Put);
+
+        let collect = |start: Bound<&[u8]>, end: Bound<&[u8]>, ts| -> Vec<(Vec<u8>, u64)> {
+            sl.range_iter(start, end, ts)
+                .map(|(key, _)| (key.user_key.clone(), key.timestamp))
+                .collect()
+        };
+
+        // Inclusive start skips versions of the start key newer than ts
+        assert_eq!(
+            collect(Bound::Included(b"b".as_slice()), Bound::Unbounded, 2),
+            vec![(b"b".to_vec(), 1), (b"c".to_vec(), 1)]
+        );
+        // Exclusive start skips every version of the start key
+        assert_eq!(
+            collect(Bound::Excluded(b"b".as_slice()), Bound::Unbounded, 5),
+            vec![(b"c".to_vec(), 1)]
+        );
+        // Inclusive end keeps every version of the end key
+        assert_eq!(
+            collect(Bound::Unbounded, Bound::Included(b"b".as_slice()), 5),
+            vec![(b"a".to_vec(), 1), (b"b".to_vec(), 3), (b"b".to_vec(), 1)]
+        );
+        assert_eq!(
+            collect(Bound::Unbounded, Bound::Excluded(b"b".as_slice()), 5),
+            vec![(b"a".to_vec(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_skiplist_duplicate_version_is_ignored() {
+        let sl = SkipList::new();
+
+        sl.insert(b"key1".to_vec(), b"first".to_vec(), 1, Operation::Put);
+        sl.insert(b"key1".to_vec(), b"second".to_vec(), 1, Operation::Put);
+
+        assert_eq!(sl.size(), 1);
+        assert_eq!(sl.get(b"key1", 1).unwrap().0, b"first");
+    }
+
+    #[test]
+    fn test_skiplist_orders_keys_by_its_comparator() {
+        let sl = SkipList::with_comparator(Arc::new(comparator::ReverseBytewiseComparator));
+
+        for key in [b"a", b"c", b"b"] {
+            sl.insert(key.to_vec(), key.to_vec(), 1, Operation::Put);
+        }
+        sl.insert(b"b".to_vec(), b"b2".to_vec(), 2, Operation::Put);
+
+        let keys: Vec<(Vec<u8>, u64)> = sl
+            .range_iter(Bound::Unbounded, Bound::Excluded(b"a".as_slice()), 5)
+            .map(|(key, _)| (key.user_key.clone(), key.timestamp))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![(b"c".to_vec(), 1), (b"b".to_vec(), 2), (b"b".to_vec(), 1)]
+        );
+        assert_eq!(sl.get(b"b", 1).unwrap().0, b"b");
+    }
 }
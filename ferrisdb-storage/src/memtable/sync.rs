@@ -0,0 +1,16 @@
+//! Atomic primitives used by the MemTable
+//!
+//! The skip list and the memory accounting in [`MemTable`](super::MemTable)
+//! only synchronize through the atomics re-exported here. Building with
+//! `--cfg loom` swaps them for loom's model-checked versions so that the
+//! ordering-sensitive insert path can be explored exhaustively:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test -p ferrisdb-storage --release --test memtable_loom_tests
+//! ```
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
@@ -0,0 +1,194 @@
+//! Merge operators for read-modify-write updates
+//!
+//! A merge records an *operand* for a key instead of a full value. Operands
+//! stack on top of the key's newest Put or Delete and are combined by a
+//! user-provided [`MergeOperator`] only when the key is read or compacted.
+//! This turns updates such as "increment this counter" or "append to this
+//! list" into a single blind write, avoiding the race in a get-then-put.
+//!
+//! # Resolution
+//!
+//! Versions of a key are visited newest first. Merge operands are collected
+//! until the first Put (whose value becomes the existing value) or Delete
+//! (no existing value), and the operator folds the operands onto it oldest
+//! first:
+//!
+//! ```text
+//! ts=9  Merge +5   ┐
+//! ts=7  Merge +2   ├─ full_merge(existing = 10, operands = [+2, +5]) = 17
+//! ts=4  Put   10   ┘
+//! ts=1  Put   3      (shadowed)
+//! ```
+//!
+//! Common operators, such as counters and list appends, are ready-made in
+//! [`merge_operators`](crate::merge_operators).
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::memtable::MemTable;
+//! use ferrisdb_storage::merge::{self, U64AddOperator};
+//!
+//! let memtable = MemTable::new(1024);
+//! memtable.put(b"hits".to_vec(), 10u64.to_le_bytes().to_vec(), 1)?;
+//! memtable.merge(b"hits".to_vec(), 2u64.to_le_bytes().to_vec(), 2)?;
+//! memtable.merge(b"hits".to_vec(), 5u64.to_le_bytes().to_vec(), 3)?;
+//!
+//! let value = merge::resolve(&U64AddOperator, b"hits", memtable.versions(b"hits", 10))?;
+//! assert_eq!(value, Some(17u64.to_le_bytes().to_vec()));
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use ferrisdb_core::{Operation, Result, Timestamp, Value};
+
+pub use crate::merge_operators::U64AddOperator;
+
+/// User-provided logic for combining merge operands
+///
+/// Implementations must be deterministic: the same inputs have to produce
+/// the same output whether they are merged at read time or during
+/// compaction, otherwise readers would observe values change after a
+/// compaction.
+pub trait MergeOperator: Send + Sync {
+    /// Returns a stable name identifying this operator
+    fn name(&self) -> &str;
+
+    /// Applies `operands` (oldest first) on top of the existing value
+    ///
+    /// `existing` is `None` when the key has no value, either because it
+    /// was never written or because its newest base version is a tombstone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an operand or the existing value is malformed.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Value>;
+
+    /// Combines consecutive operands (oldest first) into a single operand
+    ///
+    /// Used by compaction when a chain of operands has no base version in
+    /// the files being compacted. Returning `None` keeps the operands as
+    /// they are, which is always correct; the default does exactly that.
+    fn partial_merge(&self, _key: &[u8], _operands: &[&[u8]]) -> Option<Value> {
+        None
+    }
+}
+
+/// Resolves the value of a key from its versions, newest first
+///
+/// `versions` is typically the concatenation of
+/// [`MemTable::versions`](crate::memtable::MemTable::versions) and
+/// [`SSTableReader::get_versions`](crate::sstable::reader::SSTableReader::get_versions)
+/// for each source, newest source first. Iteration stops at the first Put
+/// or Delete.
+///
+/// # Returns
+///
+/// - `Some(value)` if the key has a value, after applying any operands
+/// - `None` if the key doesn't exist or its newest base is a tombstone
+///   with no operands on top
+///
+/// # Errors
+///
+/// Returns any error reported by the operator's `full_merge`.
+pub fn resolve<I>(operator: &dyn MergeOperator, key: &[u8], versions: I) -> Result<Option<Value>>
+where
+    I: IntoIterator<Item = (Value, Timestamp, Operation)>,
+{
+    let mut operands = Vec::new();
+    let mut existing = None;
+
+    for (value, _, operation) in versions {
+        match operation {
+            Operation::Merge => operands.push(value),
+            Operation::Put => {
+                existing = Some(value);
+                break;
+            }
+            Operation::Delete => break,
+        }
+    }
+
+    if operands.is_empty() {
+        return Ok(existing);
+    }
+
+    // Operands were collected newest first; operators apply them oldest first
+    let operands: Vec<&[u8]> = operands.iter().rev().map(Vec::as_slice).collect();
+    operator
+        .full_merge(key, existing.as_deref(), &operands)
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(n: u64) -> Value {
+        n.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_resolve_applies_operands_oldest_first() {
+        /// Appends operands so the application order is observable
+        struct Append;
+
+        impl MergeOperator for Append {
+            fn name(&self) -> &str {
+                "append"
+            }
+
+            fn full_merge(
+                &self,
+                _key: &[u8],
+                existing: Option<&[u8]>,
+                operands: &[&[u8]],
+            ) -> Result<Value> {
+                let mut value = existing.unwrap_or_default().to_vec();
+                for operand in operands {
+                    value.extend_from_slice(operand);
+                }
+                Ok(value)
+            }
+        }
+
+        let versions = vec![
+            (b"c".to_vec(), 4, Operation::Merge),
+            (b"b".to_vec(), 3, Operation::Merge),
+            (b"a".to_vec(), 2, Operation::Put),
+            (b"old".to_vec(), 1, Operation::Put),
+        ];
+
+        let value = resolve(&Append, b"key", versions).unwrap();
+        assert_eq!(value, Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn test_resolve_stops_at_tombstone() {
+        let versions = vec![
+            (counter(2), 3, Operation::Merge),
+            (Vec::new(), 2, Operation::Delete),
+            (counter(40), 1, Operation::Put),
+        ];
+        assert_eq!(
+            resolve(&U64AddOperator, b"key", versions).unwrap(),
+            Some(counter(2))
+        );
+
+        let versions = vec![
+            (Vec::new(), 2, Operation::Delete),
+            (counter(40), 1, Operation::Put),
+        ];
+        assert_eq!(resolve(&U64AddOperator, b"key", versions).unwrap(), None);
+    }
+
+    #[test]
+    fn test_resolve_without_base_or_operands() {
+        assert_eq!(resolve(&U64AddOperator, b"key", Vec::new()).unwrap(), None);
+
+        let versions = vec![(counter(7), 1, Operation::Put)];
+        assert_eq!(
+            resolve(&U64AddOperator, b"key", versions).unwrap(),
+            Some(counter(7))
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Ready-made merge operators for common read-modify-write updates
+//!
+//! | Operator               | Value                           | Operand           | Missing value  |
+//! |------------------------|---------------------------------|-------------------|----------------|
+//! | [`U64AddOperator`]     | little-endian `u64`             | `u64` to add      | zero           |
+//! | [`ListAppendOperator`] | length-prefixed elements        | element to append | empty list     |
+//! | [`MaxOperator`]        | any bytes                       | candidate         | first operand  |
+//! | [`MinOperator`]        | any bytes                       | candidate         | first operand  |
+//!
+//! [`MaxOperator`] and [`MinOperator`] compare bytewise, which orders
+//! big-endian integers numerically and keys encoded with
+//! [`typed::encode_key`](crate::typed::encode_key) like the keys.
+//!
+//! Operators are identified by [`name`](MergeOperator::name), so a column
+//! family has to keep the same one for as long as its data lives.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::merge_operators::ListAppendOperator;
+//! use ferrisdb_storage::storage_engine::Options;
+//! use ferrisdb_storage::StorageEngine;
+//!
+//! use std::sync::Arc;
+//!
+//! # let dir = tempfile::TempDir::new()?;
+//! let engine = StorageEngine::open(
+//!     Options::new(dir.path()).with_merge_operator(Arc::new(ListAppendOperator)),
+//! )?;
+//! engine.merge(b"tags".to_vec(), b"rust".to_vec())?;
+//! engine.merge(b"tags".to_vec(), b"db".to_vec())?;
+//!
+//! let tags = engine.get(b"tags")?.unwrap();
+//! assert_eq!(ListAppendOperator::elements(&tags)?, vec![&b"rust"[..], &b"db"[..]]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::merge::MergeOperator;
+use crate::utils::coding::{get_length_prefixed_slice, put_length_prefixed_slice};
+use ferrisdb_core::{Error, Result, Value};
+
+/// Counter operator treating values and operands as little-endian `u64`s
+///
+/// A missing existing value counts as zero and additions wrap on overflow.
+/// Operands can always be pre-added, so compaction collapses long chains
+/// of increments even without a base value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    /// Decodes one counter value or operand
+    fn decode(bytes: &[u8]) -> Result<u64> {
+        let bytes: [u8; 8] = bytes.try_into().map_err(|_| {
+            Error::InvalidOperation(format!(
+                "u64 add operand must be 8 bytes, got {}",
+                bytes.len()
+            ))
+        })?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "ferrisdb.u64add"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Value> {
+        let mut total = existing.map(Self::decode).transpose()?.unwrap_or(0);
+        for operand in operands {
+            total = total.wrapping_add(Self::decode(operand)?);
+        }
+        Ok(total.to_le_bytes().to_vec())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Value> {
+        self.full_merge(key, None, operands).ok()
+    }
+}
+
+/// List operator appending each operand to the value as one element
+///
+/// Values are a sequence of elements, each prefixed with its length as a
+/// varint, so elements may hold any bytes; [`elements`](Self::elements)
+/// splits a value back up. A Put sets the whole list and must be encoded
+/// the same way, for example with [`encode`](Self::encode).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListAppendOperator;
+
+impl ListAppendOperator {
+    /// Encodes `elements` into a list value
+    pub fn encode<E: AsRef<[u8]>>(elements: &[E]) -> Value {
+        let mut value = Vec::new();
+        for element in elements {
+            put_length_prefixed_slice(&mut value, element.as_ref());
+        }
+        value
+    }
+
+    /// Splits a list value into its elements
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if `value` isn't a list.
+    pub fn elements(mut value: &[u8]) -> Result<Vec<&[u8]>> {
+        let mut elements = Vec::new();
+        while !value.is_empty() {
+            elements.push(get_length_prefixed_slice(&mut value)?);
+        }
+        Ok(elements)
+    }
+}
+
+impl MergeOperator for ListAppendOperator {
+    fn name(&self) -> &str {
+        "ferrisdb.listappend"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Value> {
+        let mut value = existing.unwrap_or_default().to_vec();
+        for operand in operands {
+            if u32::try_from(operand.len()).is_err() {
+                return Err(Error::InvalidOperation(format!(
+                    "List element of {} bytes is too large",
+                    operand.len()
+                )));
+            }
+            put_length_prefixed_slice(&mut value, operand);
+        }
+        Ok(value)
+    }
+}
+
+/// Operator keeping the bytewise greatest of the value and its operands
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MaxOperator;
+
+impl MergeOperator for MaxOperator {
+    fn name(&self) -> &str {
+        "ferrisdb.max"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Value> {
+        let max = existing.into_iter().chain(operands.iter().copied()).max();
+        Ok(max.unwrap_or_default().to_vec())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Value> {
+        self.full_merge(key, None, operands).ok()
+    }
+}
+
+/// Operator keeping the bytewise least of the value and its operands
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinOperator;
+
+impl MergeOperator for MinOperator {
+    fn name(&self) -> &str {
+        "ferrisdb.min"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Result<Value> {
+        let min = existing.into_iter().chain(operands.iter().copied()).min();
+        Ok(min.unwrap_or_default().to_vec())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Value> {
+        self.full_merge(key, None, operands).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::resolve;
+    use crate::storage_engine::{ColumnFamilyOptions, Options, StorageEngine};
+    use ferrisdb_core::Operation;
+
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn counter(n: u64) -> Value {
+        n.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_u64_add_rejects_malformed_operand() {
+        let versions = vec![(b"xyz".to_vec(), 1, Operation::Merge)];
+        let err = resolve(&U64AddOperator, b"key", versions).unwrap_err();
+        assert!(matches!(err, Error::InvalidOperation(msg) if msg.contains("8 bytes")));
+    }
+
+    #[test]
+    fn test_u64_add_wraps_and_pre_adds() {
+        let operands: [&[u8]; 2] = [&counter(u64::MAX), &counter(3)];
+        assert_eq!(
+            U64AddOperator
+                .full_merge(b"k", Some(&counter(1)), &operands)
+                .unwrap(),
+            counter(3)
+        );
+        assert_eq!(
+            U64AddOperator.partial_merge(b"k", &operands),
+            Some(counter(2))
+        );
+    }
+
+    #[test]
+    fn test_list_append_keeps_elements_in_order() {
+        let existing = ListAppendOperator::encode(&[b"a".as_slice(), b""]);
+        let operands: [&[u8]; 2] = [b"\x00\x01", b"bc"];
+        let value = ListAppendOperator
+            .full_merge(b"k", Some(&existing), &operands)
+            .unwrap();
+        assert_eq!(
+            ListAppendOperator::elements(&value).unwrap(),
+            vec![&b"a"[..], b"", b"\x00\x01", b"bc"]
+        );
+
+        let value = ListAppendOperator
+            .full_merge(b"k", None, &operands)
+            .unwrap();
+        assert_eq!(value, ListAppendOperator::encode(&operands));
+        assert!(ListAppendOperator.partial_merge(b"k", &operands).is_none());
+        assert!(matches!(
+            ListAppendOperator::elements(&[5, b'a']),
+            Err(Error::Corruption(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_and_min_compare_bytewise() {
+        let operands: [&[u8]; 3] = [b"b", b"ab", b"ba"];
+        assert_eq!(
+            MaxOperator.full_merge(b"k", None, &operands).unwrap(),
+            b"ba"
+        );
+        assert_eq!(
+            MaxOperator.full_merge(b"k", Some(b"c"), &operands).unwrap(),
+            b"c"
+        );
+        assert_eq!(
+            MinOperator.full_merge(b"k", None, &operands).unwrap(),
+            b"ab"
+        );
+        assert_eq!(
+            MinOperator.full_merge(b"k", Some(b"a"), &operands).unwrap(),
+            b"a"
+        );
+        assert_eq!(
+            MinOperator.partial_merge(b"k", &operands),
+            Some(b"ab".to_vec())
+        );
+        assert_eq!(MaxOperator.full_merge(b"k", None, &[]).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_operators_resolve_through_the_engine() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path()).with_merge_operator(Arc::new(ListAppendOperator));
+        let engine = StorageEngine::open(options).unwrap();
+        let high = engine
+            .create_column_family(
+                "high",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(MaxOperator)),
+            )
+            .unwrap();
+        let low = engine
+            .create_column_family(
+                "low",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(MinOperator)),
+            )
+            .unwrap();
+
+        for (i, reading) in [40u32, 75, 12, 60].into_iter().enumerate() {
+            let reading = reading.to_be_bytes().to_vec();
+            engine.merge(b"log".to_vec(), reading.clone()).unwrap();
+            engine
+                .merge_cf(&high, b"t".to_vec(), reading.clone())
+                .unwrap();
+            engine.merge_cf(&low, b"t".to_vec(), reading).unwrap();
+            if i == 1 {
+                engine.flush().unwrap();
+            }
+        }
+        engine.compact_range::<[u8], _>(..).unwrap();
+        engine.compact_range_cf::<[u8], _>(&high, ..).unwrap();
+
+        let log = engine.get(b"log").unwrap().unwrap();
+        let readings: Vec<u32> = ListAppendOperator::elements(&log)
+            .unwrap()
+            .into_iter()
+            .map(|element| u32::from_be_bytes(element.try_into().unwrap()))
+            .collect();
+        assert_eq!(readings, vec![40, 75, 12, 60]);
+        assert_eq!(
+            engine.get_cf(&high, b"t").unwrap(),
+            Some(75u32.to_be_bytes().to_vec())
+        );
+        assert_eq!(
+            engine.get_cf(&low, b"t").unwrap(),
+            Some(12u32.to_be_bytes().to_vec())
+        );
+    }
+}
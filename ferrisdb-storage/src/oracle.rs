@@ -0,0 +1,187 @@
+//! Issuing timestamps
+//!
+//! Every write is stamped with a timestamp that orders it in the WAL, the
+//! MemTable and SSTables, and snapshots read as of one. The
+//! [`TimestampOracle`] hands them out as a hybrid logical clock (HLC): the
+//! upper bits hold wall-clock milliseconds from a [`Clock`], the lower
+//! [`LOGICAL_BITS`] a counter for writes within the same millisecond.
+//!
+//! ```text
+//!  63                                    16 15              0
+//!  ┌─────────────────────────────────────┬─────────────────┐
+//!  │  physical: ms since the Unix epoch  │  logical count  │
+//!  └─────────────────────────────────────┴─────────────────┘
+//! ```
+//!
+//! A timestamp is never smaller than the last one issued, even if the wall
+//! clock jumps backwards: the oracle keeps counting from the last
+//! timestamp until the clock catches up. Timestamps therefore track the
+//! time of their write closely without relying on the clock being
+//! monotonic, but they aren't consecutive; when the clock moves on, the
+//! next timestamp skips ahead to it.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::clock::ManualClock;
+//! use ferrisdb_storage::oracle::{self, TimestampOracle};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let clock = Arc::new(ManualClock::new(Duration::from_secs(100)));
+//! let oracle = TimestampOracle::new(clock.clone(), 0);
+//!
+//! let first = oracle.next();
+//! assert_eq!(oracle::physical_time(first), Duration::from_secs(100));
+//! oracle.publish(first + 1);
+//!
+//! // The clock going backwards doesn't move timestamps back
+//! clock.set(Duration::from_secs(50));
+//! assert_eq!(oracle.next(), first + 2);
+//! ```
+
+use crate::clock::Clock;
+use ferrisdb_core::Timestamp;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Number of low bits of a timestamp holding the logical counter
+pub const LOGICAL_BITS: u32 = 16;
+
+/// Returns the smallest timestamp issued at or after wall-clock `time`
+/// (since the Unix epoch)
+pub fn timestamp_at(time: Duration) -> Timestamp {
+    (time.as_millis() as u64) << LOGICAL_BITS
+}
+
+/// Returns the wall-clock time (since the Unix epoch) a timestamp was
+/// issued at, with millisecond resolution
+pub fn physical_time(timestamp: Timestamp) -> Duration {
+    Duration::from_millis(timestamp >> LOGICAL_BITS)
+}
+
+/// Returns the logical counter of a timestamp
+pub fn logical(timestamp: Timestamp) -> u64 {
+    timestamp & ((1 << LOGICAL_BITS) - 1)
+}
+
+/// Hands out strictly increasing hybrid logical timestamps
+///
+/// Issuing and publishing are separate steps: a writer takes timestamps
+/// with [`next`](Self::next), applies its writes and only then
+/// makes them visible with [`publish`](Self::publish), which is what
+/// [`last`](Self::last) and thus new snapshots see. Writers must be
+/// serialized from taking their timestamps until they publish, since
+/// timestamps that are never published are handed out again.
+pub struct TimestampOracle {
+    clock: Arc<dyn Clock>,
+    /// Newest published timestamp
+    last: AtomicU64,
+}
+
+impl TimestampOracle {
+    /// Creates an oracle continuing after timestamp `last`
+    pub fn new(clock: Arc<dyn Clock>, last: Timestamp) -> Self {
+        Self {
+            clock,
+            last: AtomicU64::new(last),
+        }
+    }
+
+    /// Returns the newest published timestamp
+    pub fn last(&self) -> Timestamp {
+        self.last.load(Ordering::Acquire)
+    }
+
+    /// Returns the timestamp following the last published one
+    ///
+    /// A batch of writes takes consecutive timestamps starting at this one.
+    /// More than 2^[`LOGICAL_BITS`] timestamps in one millisecond carry
+    /// into the physical part, running ahead of the clock until it catches
+    /// up.
+    pub fn next(&self) -> Timestamp {
+        let now = timestamp_at(self.clock.now());
+        (self.last.load(Ordering::Relaxed) + 1).max(now)
+    }
+
+    /// Returns the smallest timestamp issued within `age` of now, but no
+    /// newer than the last published one
+    pub fn issued_within(&self, age: Duration) -> Timestamp {
+        timestamp_at(self.clock.now().saturating_sub(age)).min(self.last())
+    }
+
+    /// Makes every timestamp up to `timestamp` visible
+    ///
+    /// Publishing an older timestamp than the last one has no effect, so
+    /// timestamps received from elsewhere, such as replicated writes, can
+    /// be published as they are.
+    pub fn publish(&self, timestamp: Timestamp) {
+        self.last.fetch_max(timestamp, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for TimestampOracle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimestampOracle")
+            .field("last", &self.last())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_timestamps_follow_the_clock() {
+        let clock = Arc::new(ManualClock::new(Duration::from_millis(1_000)));
+        let oracle = TimestampOracle::new(clock.clone(), 0);
+
+        let first = oracle.next();
+        assert_eq!(first, timestamp_at(Duration::from_millis(1_000)));
+        assert_eq!(logical(first), 0);
+        oracle.publish(first + 2);
+
+        // Within the same millisecond the counter goes on
+        let second = oracle.next();
+        assert_eq!(second, first + 3);
+        assert_eq!(logical(second), 3);
+        oracle.publish(second);
+
+        clock.advance(Duration::from_millis(5));
+        let third = oracle.next();
+        assert_eq!(physical_time(third), Duration::from_millis(1_005));
+        assert_eq!(logical(third), 0);
+    }
+
+    #[test]
+    fn test_timestamps_never_go_back() {
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(60)));
+        let oracle = TimestampOracle::new(clock.clone(), 0);
+        let before = oracle.next();
+        oracle.publish(before);
+
+        clock.set(Duration::from_secs(10));
+        let after = oracle.next();
+        assert_eq!(after, before + 1);
+        assert_eq!(physical_time(after), Duration::from_secs(60));
+
+        // Unpublished timestamps are handed out again
+        assert_eq!(oracle.next(), after);
+        oracle.publish(after);
+        oracle.publish(before);
+        assert_eq!(oracle.last(), after);
+    }
+
+    #[test]
+    fn test_oracle_continues_after_a_recovered_timestamp() {
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let oracle = TimestampOracle::new(clock, 41);
+        assert_eq!(oracle.last(), 41);
+        assert_eq!(oracle.next(), 42);
+    }
+}
@@ -0,0 +1,162 @@
+//! Key prefixes for prefix scans
+//!
+//! Keys are often built from segments, such as `user:123:name`, and read
+//! a group at a time: everything under `user:123:`. A [`PrefixExtractor`]
+//! names that group for each key. SSTables written with one store a bloom
+//! filter over the prefixes of their keys, so a scan of one prefix, set
+//! through [`ReadOptions::with_prefix`](crate::ReadOptions::with_prefix),
+//! skips every table that holds none of it.
+//!
+//! # Persistence
+//!
+//! An extractor's [`name`](PrefixExtractor::name) is recorded in every
+//! SSTable written with it. Filters are only consulted when the table's
+//! extractor has the same name as the column family's, so changing the
+//! extractor is safe: tables written before stop being filtered until
+//! compaction rewrites them. Changing what an extractor returns requires
+//! a new name.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::prefix::{DelimitedPrefix, FixedPrefix, PrefixExtractor};
+//!
+//! let by_user = DelimitedPrefix::new(b':', 2);
+//! assert_eq!(by_user.prefix(b"user:123:name"), Some(&b"user:123:"[..]));
+//! assert_eq!(by_user.prefix(b"user:123"), None);
+//!
+//! assert_eq!(FixedPrefix::new(4).prefix(b"2024-06-01"), Some(&b"2024"[..]));
+//! ```
+
+use ferrisdb_core::Key;
+
+use std::fmt;
+
+/// Maps keys to the prefix they are scanned by
+///
+/// Implementations must be consistent: a key's prefix may never change
+/// for a given name, and a prefix must be a prefix of its key, so that
+/// the keys sharing one sort next to each other in bytewise order.
+pub trait PrefixExtractor: Send + Sync {
+    /// Returns a stable name identifying the mapping
+    fn name(&self) -> &str;
+
+    /// Returns the prefix of `key`, or `None` for keys without one
+    ///
+    /// Keys without a prefix are never excluded by a filter.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+impl fmt::Debug for dyn PrefixExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrefixExtractor({})", self.name())
+    }
+}
+
+/// Takes the first `len` bytes of each key
+#[derive(Debug, Clone)]
+pub struct FixedPrefix {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefix {
+    /// Creates an extractor of `len`-byte prefixes; shorter keys have none
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("ferrisdb.FixedPrefix.{}", len),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
+
+/// Takes each key up to and including its `count`th `delimiter`
+///
+/// With `:` and 2, the prefix of `user:123:name` is `user:123:`.
+#[derive(Debug, Clone)]
+pub struct DelimitedPrefix {
+    delimiter: u8,
+    count: usize,
+    name: String,
+}
+
+impl DelimitedPrefix {
+    /// Creates an extractor of prefixes ending in the `count`th
+    /// `delimiter`; keys with fewer delimiters have none
+    pub fn new(delimiter: u8, count: usize) -> Self {
+        Self {
+            delimiter,
+            count,
+            name: format!("ferrisdb.DelimitedPrefix.{}.{}", delimiter, count),
+        }
+    }
+}
+
+impl PrefixExtractor for DelimitedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let end = key
+            .iter()
+            .enumerate()
+            .filter(|&(_, &byte)| byte == self.delimiter)
+            .nth(self.count.checked_sub(1)?)?
+            .0;
+        Some(&key[..=end])
+    }
+}
+
+/// Returns the smallest key after every key starting with `prefix` in
+/// bytewise order, or `None` if no key is
+pub fn prefix_end(prefix: &[u8]) -> Option<Key> {
+    let last = prefix.iter().rposition(|&byte| byte != u8::MAX)?;
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extractors_return_prefixes_of_keys_in_their_domain() {
+        let fixed = FixedPrefix::new(3);
+        assert_eq!(fixed.prefix(b"abcdef"), Some(&b"abc"[..]));
+        assert_eq!(fixed.prefix(b"abc"), Some(&b"abc"[..]));
+        assert_eq!(fixed.prefix(b"ab"), None);
+
+        let delimited = DelimitedPrefix::new(b'/', 1);
+        assert_eq!(delimited.prefix(b"a/b/c"), Some(&b"a/"[..]));
+        assert_eq!(delimited.prefix(b"/"), Some(&b"/"[..]));
+        assert_eq!(delimited.prefix(b"abc"), None);
+        assert_eq!(DelimitedPrefix::new(b'/', 0).prefix(b"a/b"), None);
+        assert_ne!(delimited.name(), DelimitedPrefix::new(b'/', 2).name());
+    }
+
+    #[test]
+    fn test_prefix_end_follows_every_key_with_the_prefix() {
+        assert_eq!(prefix_end(b"user:"), Some(b"user;".to_vec()));
+        assert_eq!(prefix_end(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_end(b"\xff\xff"), None);
+        assert_eq!(prefix_end(b""), None);
+
+        let end = prefix_end(b"ab\xff").unwrap();
+        for key in [&b"ab\xff"[..], b"ab\xff\xff\xff", b"ab\xff\x00"] {
+            assert!(key < end.as_slice());
+        }
+        assert!(b"ac".as_slice() >= end.as_slice());
+    }
+}
@@ -0,0 +1,188 @@
+//! Object storage for cold SSTables
+//!
+//! Datasets larger than the local disk can keep their bottom level, which
+//! holds most of the data and is read least, in an object store such as S3
+//! or GCS. A [`RemoteStorage`] is the little the engine needs of one:
+//! whole-object puts, ranged gets, listing by prefix and deletes. Objects
+//! are never changed once put, as tables never are.
+//!
+//! A [`TieredVfs`] puts the objects behind the [`Vfs`](crate::vfs::Vfs)
+//! tables are read through. A table [offloaded](TieredVfs::offload) is
+//! uploaded and removed locally, and opening its path reads the object
+//! instead, a chunk at a time, keeping recently read chunks in a local
+//! cache so hot blocks aren't fetched again. Listing, sizing and deleting
+//! files see remote tables as if they were still in their directory, so
+//! garbage collection and dropping column families need no changes.
+//!
+//! [`MemoryRemoteStorage`] keeps its objects in memory, for tests and for
+//! trying tiering out.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::remote::{MemoryRemoteStorage, TieredVfs};
+//! use ferrisdb_storage::vfs::{SimVfs, Vfs};
+//! use std::io::{Read, Write};
+//! use std::path::Path;
+//! use std::sync::Arc;
+//!
+//! let remote = Arc::new(MemoryRemoteStorage::new());
+//! let tiered = TieredVfs::new(Arc::new(SimVfs::new(0)), remote.clone(), "/db");
+//! let table = Path::new("/db/000007.sst");
+//!
+//! tiered.create_dir_all(Path::new("/db"))?;
+//! tiered.create(table)?.write_all(b"cold data")?;
+//! tiered.offload(table)?;
+//! assert!(!tiered.is_local(table));
+//! assert_eq!(remote.object("000007.sst").as_deref(), Some(&b"cold data"[..]));
+//!
+//! let mut contents = Vec::new();
+//! tiered.open(table)?.read_to_end(&mut contents)?;
+//! assert_eq!(contents, b"cold data");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+mod tiered;
+
+pub use tiered::{TieredVfs, DEFAULT_CACHE_SIZE};
+
+use parking_lot::RwLock;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// An object in remote storage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteObject {
+    /// Name of the object, with `/` separating the directories of the
+    /// file it holds
+    pub name: String,
+    /// Size of the object in bytes
+    pub size: u64,
+}
+
+/// An object store, such as a bucket of S3 or GCS
+///
+/// Objects are put whole and never changed afterwards. Methods are called
+/// from the threads reading, compacting and offloading tables, so a slow
+/// store slows those down.
+pub trait RemoteStorage: Send + Sync {
+    /// Stores `data` as the object `name`, replacing any object by that
+    /// name
+    fn put(&self, name: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Reads up to `len` bytes of the object `name` from `offset` on,
+    /// fewer if the object ends first
+    ///
+    /// Returns an error of kind `NotFound` if there is no such object.
+    fn get(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+
+    /// Lists the objects whose names start with `prefix`, sorted by name
+    fn list(&self, prefix: &str) -> io::Result<Vec<RemoteObject>>;
+
+    /// Deletes the object `name`, succeeding if there is none
+    fn delete(&self, name: &str) -> io::Result<()>;
+}
+
+/// A remote storage keeping its objects in memory
+#[derive(Debug, Default)]
+pub struct MemoryRemoteStorage {
+    objects: RwLock<BTreeMap<String, Arc<Vec<u8>>>>,
+    /// Calls to `get` so far
+    gets: AtomicU64,
+}
+
+impl MemoryRemoteStorage {
+    /// Creates an empty storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the contents of the object `name`
+    pub fn object(&self, name: &str) -> Option<Vec<u8>> {
+        self.objects
+            .read()
+            .get(name)
+            .map(|data| data.as_ref().clone())
+    }
+
+    /// Returns how many times objects were read, to tell cached reads
+    /// from fetched ones
+    pub fn gets(&self) -> u64 {
+        self.gets.load(Ordering::Relaxed)
+    }
+}
+
+impl RemoteStorage for MemoryRemoteStorage {
+    fn put(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.objects
+            .write()
+            .insert(name.to_string(), Arc::new(data.to_vec()));
+        Ok(())
+    }
+
+    fn get(&self, name: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        let data = self.objects.read().get(name).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("No object {}", name))
+        })?;
+        let start = offset.min(data.len() as u64) as usize;
+        let end = offset.saturating_add(len).min(data.len() as u64) as usize;
+        Ok(data[start..end].to_vec())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<RemoteObject>> {
+        Ok(self
+            .objects
+            .read()
+            .range(prefix.to_string()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, data)| RemoteObject {
+                name: name.clone(),
+                size: data.len() as u64,
+            })
+            .collect())
+    }
+
+    fn delete(&self, name: &str) -> io::Result<()> {
+        self.objects.write().remove(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_storage_lists_by_prefix_and_reads_ranges() {
+        let storage = MemoryRemoteStorage::new();
+        storage.put("a/1.sst", b"hello world").unwrap();
+        storage.put("a/2.sst", b"x").unwrap();
+        storage.put("b/1.sst", b"y").unwrap();
+
+        let names: Vec<_> = storage
+            .list("a/")
+            .unwrap()
+            .into_iter()
+            .map(|object| (object.name, object.size))
+            .collect();
+        assert_eq!(
+            names,
+            [("a/1.sst".to_string(), 11), ("a/2.sst".to_string(), 1)]
+        );
+        assert_eq!(storage.get("a/1.sst", 6, 100).unwrap(), b"world");
+        assert_eq!(storage.get("a/1.sst", 20, 5).unwrap(), b"");
+        assert_eq!(
+            storage.get("c", 0, 1).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        storage.delete("a/1.sst").unwrap();
+        storage.delete("a/1.sst").unwrap();
+        assert_eq!(storage.list("a/").unwrap().len(), 1);
+        assert_eq!(storage.gets(), 3);
+    }
+}
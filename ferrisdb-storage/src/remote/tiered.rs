@@ -0,0 +1,458 @@
+//! A filesystem whose tables may live in remote storage
+
+use super::RemoteStorage;
+use crate::clock::Clock;
+use crate::files::{self, FileType};
+use crate::utils::cache::{Cache, CacheStats};
+use crate::vfs::{self, FileLock, Vfs, VfsFile};
+
+use parking_lot::Mutex;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Default capacity of the cache of remote table chunks (64MB)
+pub const DEFAULT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Bytes of a remote table fetched with one get and cached together
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Cached chunks of remote tables, by object name and offset
+type ChunkCache = Cache<(String, u64), Vec<u8>>;
+
+/// A filesystem keeping offloaded tables in remote storage
+///
+/// Wraps the local filesystem. Tables under the root directory that are
+/// missing locally are looked up in the remote storage, where their
+/// object names are their paths relative to the root. Every other file,
+/// and every table still on local disk, goes to the local filesystem.
+///
+/// Remote tables are read-only: they can be opened, listed, sized and
+/// deleted, but not written or renamed.
+pub struct TieredVfs {
+    local: Arc<dyn Vfs>,
+    remote: Arc<dyn RemoteStorage>,
+    /// Directory whose tables may be offloaded
+    root: PathBuf,
+    /// Sizes of the remote tables seen so far, by object name
+    sizes: Mutex<HashMap<String, u64>>,
+    cache: Arc<ChunkCache>,
+}
+
+impl TieredVfs {
+    /// Creates a filesystem offloading the tables under `root` from
+    /// `local` to `remote`
+    pub fn new(
+        local: Arc<dyn Vfs>,
+        remote: Arc<dyn RemoteStorage>,
+        root: impl AsRef<Path>,
+    ) -> Self {
+        Self {
+            local,
+            remote,
+            root: root.as_ref().to_path_buf(),
+            sizes: Mutex::new(HashMap::new()),
+            cache: Arc::new(Cache::new(DEFAULT_CACHE_SIZE)),
+        }
+    }
+
+    /// Sets the bytes of remote tables kept in the local cache,
+    /// [`DEFAULT_CACHE_SIZE`] by default
+    pub fn with_cache_size(mut self, bytes: usize) -> Self {
+        self.cache = Arc::new(Cache::new(bytes));
+        self
+    }
+
+    /// Returns true if the file at `path` is on local disk
+    pub fn is_local(&self, path: &Path) -> bool {
+        self.local.exists(path)
+    }
+
+    /// Uploads the table at `path` to remote storage and removes the local
+    /// file, returning its size
+    ///
+    /// Readers that opened the table before keep reading the local file,
+    /// where the platform lets an open file be removed; later ones read
+    /// the remote copy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `path` isn't a table
+    /// under the root, or the error of reading, uploading or removing the
+    /// table. The local file is only removed once the upload succeeded.
+    pub fn offload(&self, path: &Path) -> io::Result<u64> {
+        let name = self.object_name(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is not a table under {}",
+                    path.display(),
+                    self.root.display()
+                ),
+            )
+        })?;
+        let mut data = Vec::new();
+        self.local.open(path)?.read_to_end(&mut data)?;
+        self.remote.put(&name, &data)?;
+        self.sizes.lock().insert(name, data.len() as u64);
+        self.local.remove_file(path)?;
+        vfs::sync_parent_dir(self.local.as_ref(), path)?;
+        Ok(data.len() as u64)
+    }
+
+    /// Returns the hits, misses and evictions of the cache of remote chunks
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Returns the object name of the table at `path`, or `None` if it
+    /// isn't a table under the root
+    fn object_name(&self, path: &Path) -> Option<String> {
+        let name = path.file_name()?.to_str()?;
+        if !matches!(files::parse_file_name(name), Some(FileType::Table(_))) {
+            return None;
+        }
+        Self::relative_name(path.strip_prefix(&self.root).ok()?)
+    }
+
+    /// Joins the components of a relative path with `/`
+    fn relative_name(path: &Path) -> Option<String> {
+        let mut parts = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_str()?),
+                _ => return None,
+            }
+        }
+        Some(parts.join("/"))
+    }
+
+    /// Returns the size of the remote table at `path`, or `None` if it
+    /// isn't one
+    fn remote_size(&self, path: &Path) -> io::Result<Option<(String, u64)>> {
+        let Some(name) = self.object_name(path) else {
+            return Ok(None);
+        };
+        if let Some(&size) = self.sizes.lock().get(&name) {
+            return Ok(Some((name, size)));
+        }
+        let found = self
+            .remote
+            .list(&name)?
+            .into_iter()
+            .find(|object| object.name == name);
+        Ok(found.map(|object| {
+            self.sizes.lock().insert(name.clone(), object.size);
+            (name, object.size)
+        }))
+    }
+
+    /// Returns the paths of the remote tables directly in `dir`
+    fn remote_entries(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let Some(prefix) = dir
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(Self::relative_name)
+        else {
+            return Ok(Vec::new());
+        };
+        let prefix = if prefix.is_empty() {
+            prefix
+        } else {
+            prefix + "/"
+        };
+        let mut sizes = self.sizes.lock();
+        let mut paths = Vec::new();
+        for object in self.remote.list(&prefix)? {
+            let name = &object.name[prefix.len()..];
+            if name.contains('/') {
+                continue;
+            }
+            paths.push(dir.join(name));
+            sizes.insert(object.name, object.size);
+        }
+        Ok(paths)
+    }
+}
+
+impl fmt::Debug for TieredVfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TieredVfs")
+            .field("root", &self.root)
+            .field("remote_tables", &self.sizes.lock().len())
+            .field("cache", &self.cache.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Vfs for TieredVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        if !self.local.exists(path) {
+            if let Some((name, size)) = self.remote_size(path)? {
+                return Ok(Box::new(RemoteFile {
+                    remote: Arc::clone(&self.remote),
+                    cache: Arc::clone(&self.cache),
+                    name,
+                    size,
+                    position: 0,
+                }));
+            }
+        }
+        self.local.open(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.local.create(path)
+    }
+
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.local.open_or_create(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.local.exists(path) || matches!(self.remote_size(path), Ok(Some(_)))
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        if !self.local.exists(path) {
+            if let Some((_, size)) = self.remote_size(path)? {
+                return Ok(size);
+            }
+        }
+        self.local.file_size(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.local.create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = self.local.read_dir(path)?;
+        paths.extend(self.remote_entries(path)?);
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let local = self.local.remove_file(path);
+        match self.remote_size(path)? {
+            Some((name, _)) => {
+                self.remote.delete(&name)?;
+                self.sizes.lock().remove(&name);
+                match local {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                    _ => Ok(()),
+                }
+            }
+            None => local,
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        for table in self.remote_entries(path)? {
+            self.remove_file(&table)?;
+        }
+        self.local.remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.local.rename(from, to)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.local.hard_link(from, to)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.local.sync_dir(dir)
+    }
+
+    fn try_lock_file(&self, path: &Path) -> io::Result<FileLock> {
+        self.local.try_lock_file(path)
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.local.clock()
+    }
+}
+
+/// A remote table opened for reading
+struct RemoteFile {
+    remote: Arc<dyn RemoteStorage>,
+    cache: Arc<ChunkCache>,
+    name: String,
+    size: u64,
+    position: u64,
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let offset = self.position - self.position % CHUNK_SIZE;
+        let key = (self.name.clone(), offset);
+        let chunk = match self.cache.get(&key) {
+            Some(chunk) => chunk,
+            None => {
+                let chunk = self.remote.get(&self.name, offset, CHUNK_SIZE)?;
+                let charge = chunk.len();
+                self.cache.insert(key, chunk, charge)
+            }
+        };
+        let start = (self.position - offset) as usize;
+        let Some(available) = chunk.get(start..).filter(|rest| !rest.is_empty()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Object {} is shorter than {} bytes", self.name, self.size),
+            ));
+        };
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Write for RemoteFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VfsFile for RemoteFile {
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    fn set_len(&self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Remote tables are read-only",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MemoryRemoteStorage;
+    use super::*;
+    use crate::vfs::SimVfs;
+
+    fn tiered() -> (TieredVfs, Arc<MemoryRemoteStorage>) {
+        let remote = Arc::new(MemoryRemoteStorage::new());
+        let tiered = TieredVfs::new(Arc::new(SimVfs::new(0)), remote.clone(), "/db");
+        tiered.create_dir_all(Path::new("/db/cf-1")).unwrap();
+        (tiered, remote)
+    }
+
+    fn write(vfs: &dyn Vfs, path: &str, data: &[u8]) {
+        let mut file = vfs.create(Path::new(path)).unwrap();
+        file.write_all(data).unwrap();
+        file.sync_all().unwrap();
+    }
+
+    #[test]
+    fn test_offloaded_tables_read_through_the_cache() {
+        let (tiered, remote) = tiered();
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| i as u8).collect();
+        write(&tiered, "/db/cf-1/000003.sst", &data);
+        let path = Path::new("/db/cf-1/000003.sst");
+
+        assert_eq!(tiered.offload(path).unwrap(), data.len() as u64);
+        assert!(!tiered.is_local(path));
+        assert!(tiered.exists(path));
+        assert_eq!(tiered.file_size(path).unwrap(), data.len() as u64);
+        assert_eq!(remote.object("cf-1/000003.sst").unwrap(), data);
+
+        let mut file = tiered.open(path).unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, data);
+        assert_eq!(remote.gets(), 3);
+
+        // A read across a chunk boundary, both chunks cached
+        let mut buf = vec![0; 100];
+        file.seek(SeekFrom::Start(CHUNK_SIZE - 50)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[CHUNK_SIZE as usize - 50..][..100]);
+        assert_eq!(remote.gets(), 3);
+        assert!(tiered.cache_stats().hits >= 2);
+        assert!(file.write_all(b"x").is_err());
+
+        // Other files and paths outside the root never go remote
+        write(&tiered, "/db/MANIFEST", b"manifest");
+        assert!(tiered.offload(Path::new("/db/MANIFEST")).is_err());
+        assert!(tiered.offload(Path::new("/elsewhere/000001.sst")).is_err());
+    }
+
+    #[test]
+    fn test_remote_tables_are_listed_and_removed_with_their_directory() {
+        let (tiered, remote) = tiered();
+        write(&tiered, "/db/cf-1/000004.sst", b"remote");
+        write(&tiered, "/db/cf-1/000005.sst", b"local");
+        write(&tiered, "/db/000006.sst", b"root");
+        tiered.offload(Path::new("/db/cf-1/000004.sst")).unwrap();
+        tiered.offload(Path::new("/db/000006.sst")).unwrap();
+
+        assert_eq!(
+            tiered.read_dir(Path::new("/db/cf-1")).unwrap(),
+            [
+                PathBuf::from("/db/cf-1/000004.sst"),
+                PathBuf::from("/db/cf-1/000005.sst")
+            ]
+        );
+        let root = tiered.read_dir(Path::new("/db")).unwrap();
+        assert!(root.contains(&PathBuf::from("/db/000006.sst")));
+        assert!(!root.contains(&PathBuf::from("/db/cf-1/000004.sst")));
+        // A remote table isn't mistaken for a directory
+        assert!(tiered.read_dir(Path::new("/db/000006.sst")).is_err());
+
+        tiered.remove_file(Path::new("/db/000006.sst")).unwrap();
+        assert!(remote.object("000006.sst").is_none());
+        assert_eq!(
+            tiered
+                .remove_file(Path::new("/db/000006.sst"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        tiered.remove_dir_all(Path::new("/db/cf-1")).unwrap();
+        assert!(remote.list("").unwrap().is_empty());
+        assert!(!tiered.exists(Path::new("/db/cf-1/000005.sst")));
+    }
+}
@@ -0,0 +1,572 @@
+//! Running background jobs
+//!
+//! Flushes, compactions and periodic housekeeping all run off the write
+//! path. Rather than each spawning and tracking threads of its own, they
+//! are registered with a [`Scheduler`] as named jobs. Each job gets a
+//! thread that runs it whenever it is [triggered](Scheduler::trigger) and,
+//! for periodic jobs, once its interval has passed since the last run. A
+//! trigger while the job is running makes it run again right after, so
+//! work signalled mid-run is never missed, but triggers don't queue up.
+//!
+//! A job that returns an error or panics stops. The failure is kept, shown
+//! by [`jobs`](Scheduler::jobs), passed to the failure handler set with
+//! [`with_failure_handler`](Scheduler::with_failure_handler), and returned
+//! by [`stop`](Scheduler::stop) and [`shutdown`](Scheduler::shutdown).
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::scheduler::{JobState, Schedule, Scheduler};
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//! use std::sync::Arc;
+//!
+//! let scheduler = Scheduler::new();
+//! let flushed = Arc::new(AtomicU64::new(0));
+//! scheduler.spawn("flush", Schedule::OnDemand, {
+//!     let flushed = Arc::clone(&flushed);
+//!     move || {
+//!         flushed.fetch_add(1, Ordering::Relaxed);
+//!         Ok(())
+//!     }
+//! })?;
+//!
+//! scheduler.trigger("flush");
+//! scheduler.shutdown()?;
+//! assert!(flushed.load(Ordering::Relaxed) <= 1);
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use ferrisdb_core::{Error, Result};
+
+use parking_lot::{Condvar, Mutex};
+
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// When a job runs, besides when triggered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Only when triggered
+    OnDemand,
+    /// Also once the interval has passed since the last run ended
+    Every(Duration),
+}
+
+/// What a job is doing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    /// Waiting for a trigger or its next run
+    Idle,
+    /// Running now
+    Running,
+    /// Stopped after returning an error or panicking, with the message
+    Failed(String),
+}
+
+/// A snapshot of a job, from [`Scheduler::jobs`]
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    /// Name the job was spawned with
+    pub name: String,
+    /// When the job runs besides when triggered
+    pub schedule: Schedule,
+    /// What the job is doing
+    pub state: JobState,
+    /// Set if the job was triggered and hasn't started running since
+    pub triggered: bool,
+    /// Runs completed, successful or not
+    pub runs: u64,
+    /// When the last run started
+    pub last_run: Option<Instant>,
+    /// How long the last run took
+    pub last_duration: Option<Duration>,
+    /// When a periodic job runs next, unless triggered earlier
+    pub next_run: Option<Instant>,
+}
+
+/// Called with a job's name and error when it fails
+type FailureHandler = Arc<dyn Fn(&str, &Error) + Send + Sync>;
+
+/// Owns named background jobs, each running on a thread of its own
+///
+/// Dropping the scheduler stops its jobs and waits for running ones to
+/// finish.
+pub struct Scheduler {
+    jobs: Mutex<Vec<Job>>,
+    on_failure: Option<FailureHandler>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler without jobs
+    pub fn new() -> Self {
+        Self {
+            jobs: Mutex::new(Vec::new()),
+            on_failure: None,
+        }
+    }
+
+    /// Calls `handler` on the job's thread whenever a job fails, before
+    /// the job stops
+    pub fn with_failure_handler(
+        mut self,
+        handler: impl Fn(&str, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_failure = Some(Arc::new(handler));
+        self
+    }
+
+    /// Starts a job running `work` on its own thread
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if a job with the same name exists,
+    /// or an I/O error if the thread cannot be spawned.
+    pub fn spawn(
+        &self,
+        name: impl Into<String>,
+        schedule: Schedule,
+        work: impl FnMut() -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        let name = name.into();
+        let mut jobs = self.jobs.lock();
+        if jobs.iter().any(|job| job.shared.name == name) {
+            return Err(Error::InvalidArgument(format!(
+                "Background job {:?} already exists",
+                name
+            )));
+        }
+
+        let shared = Arc::new(JobShared {
+            name: name.clone(),
+            schedule,
+            status: Mutex::new(JobStatus::new(schedule)),
+            wake: Condvar::new(),
+            idle: Condvar::new(),
+        });
+        let thread = thread::Builder::new()
+            .name(format!("ferrisdb-{}", name))
+            .spawn({
+                let shared = Arc::clone(&shared);
+                let on_failure = self.on_failure.clone();
+                move || run_job(&shared, work, on_failure)
+            })?;
+        jobs.push(Job {
+            shared,
+            thread: Some(thread),
+        });
+        Ok(())
+    }
+
+    /// Makes a job run as soon as possible
+    ///
+    /// Returns false if there is no such job or it has failed.
+    pub fn trigger(&self, name: &str) -> bool {
+        let jobs = self.jobs.lock();
+        let Some(job) = jobs.iter().find(|job| job.shared.name == name) else {
+            return false;
+        };
+        let mut status = job.shared.status.lock();
+        if status.failure.is_some() {
+            return false;
+        }
+        status.triggered = true;
+        job.shared.wake.notify_one();
+        true
+    }
+
+    /// Stops a job, waiting for a running one to finish, and removes it
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if there is no such job, or
+    /// `Error::StorageEngine` if it had failed.
+    pub fn stop(&self, name: &str) -> Result<()> {
+        let job = {
+            let mut jobs = self.jobs.lock();
+            let index = jobs
+                .iter()
+                .position(|job| job.shared.name == name)
+                .ok_or_else(|| Error::NotFound(format!("Background job {:?}", name)))?;
+            jobs.remove(index)
+        };
+        job.stop()
+    }
+
+    /// Stops every job, waiting for running ones to finish
+    ///
+    /// # Errors
+    ///
+    /// Returns the failure of the first job that had failed.
+    pub fn shutdown(&self) -> Result<()> {
+        let jobs = std::mem::take(&mut *self.jobs.lock());
+        for job in &jobs {
+            job.shared.request_stop();
+        }
+        // Every job is stopped even after one reports a failure
+        let mut result = Ok(());
+        for job in jobs {
+            result = result.and(job.stop());
+        }
+        result
+    }
+
+    /// Waits until a job has finished its run and any run it was
+    /// triggered for
+    ///
+    /// Returns false if there is no such job, or it failed or stopped.
+    pub fn wait_idle(&self, name: &str) -> bool {
+        let Some(shared) = self
+            .jobs
+            .lock()
+            .iter()
+            .find(|job| job.shared.name == name)
+            .map(|job| Arc::clone(&job.shared))
+        else {
+            return false;
+        };
+        let mut status = shared.status.lock();
+        while (status.running || status.triggered) && status.failure.is_none() && !status.stopping {
+            shared.idle.wait(&mut status);
+        }
+        status.failure.is_none() && !status.stopping
+    }
+
+    /// Returns a snapshot of every job, in the order they were spawned
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.jobs
+            .lock()
+            .iter()
+            .map(|job| job.shared.info())
+            .collect()
+    }
+
+    /// Returns a snapshot of the named job
+    pub fn job(&self, name: &str) -> Option<JobInfo> {
+        self.jobs
+            .lock()
+            .iter()
+            .find(|job| job.shared.name == name)
+            .map(|job| job.shared.info())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("jobs", &self.jobs())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// A spawned job and its thread
+struct Job {
+    shared: Arc<JobShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Job {
+    /// Stops the job and joins its thread, returning its failure
+    fn stop(mut self) -> Result<()> {
+        self.shared.request_stop();
+        if let Some(thread) = self.thread.take() {
+            // A job dropping the last reference to its own scheduler can't
+            // wait for itself; it exits once its run returns
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+        match &self.shared.status.lock().failure {
+            Some(failure) => Err(Error::StorageEngine(format!(
+                "Background job {:?} failed: {}",
+                self.shared.name, failure
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State shared between a job's thread and the scheduler
+struct JobShared {
+    name: String,
+    schedule: Schedule,
+    status: Mutex<JobStatus>,
+    /// Wakes the job's thread on a trigger or stop
+    wake: Condvar,
+    /// Wakes [`Scheduler::wait_idle`] callers when a run ends or the job
+    /// stops
+    idle: Condvar,
+}
+
+impl JobShared {
+    fn request_stop(&self) {
+        self.status.lock().stopping = true;
+        self.wake.notify_one();
+        self.idle.notify_all();
+    }
+
+    fn info(&self) -> JobInfo {
+        let status = self.status.lock();
+        let state = match (&status.failure, status.running) {
+            (Some(failure), _) => JobState::Failed(failure.clone()),
+            (None, true) => JobState::Running,
+            (None, false) => JobState::Idle,
+        };
+        JobInfo {
+            name: self.name.clone(),
+            schedule: self.schedule,
+            state,
+            triggered: status.triggered,
+            runs: status.runs,
+            last_run: status.last_run,
+            last_duration: status.last_duration,
+            next_run: status.next_run,
+        }
+    }
+}
+
+struct JobStatus {
+    triggered: bool,
+    stopping: bool,
+    running: bool,
+    runs: u64,
+    last_run: Option<Instant>,
+    last_duration: Option<Duration>,
+    next_run: Option<Instant>,
+    failure: Option<String>,
+}
+
+impl JobStatus {
+    fn new(schedule: Schedule) -> Self {
+        Self {
+            triggered: false,
+            stopping: false,
+            running: false,
+            runs: 0,
+            last_run: None,
+            last_duration: None,
+            next_run: next_run(schedule),
+            failure: None,
+        }
+    }
+}
+
+fn next_run(schedule: Schedule) -> Option<Instant> {
+    match schedule {
+        Schedule::OnDemand => None,
+        Schedule::Every(interval) => Some(Instant::now() + interval),
+    }
+}
+
+/// Body of a job's thread
+fn run_job(
+    shared: &JobShared,
+    mut work: impl FnMut() -> Result<()>,
+    on_failure: Option<FailureHandler>,
+) {
+    loop {
+        {
+            let mut status = shared.status.lock();
+            loop {
+                if status.stopping {
+                    return;
+                }
+                if status.triggered || status.next_run.is_some_and(|at| at <= Instant::now()) {
+                    break;
+                }
+                match status.next_run {
+                    Some(at) => {
+                        shared.wake.wait_until(&mut status, at);
+                    }
+                    None => shared.wake.wait(&mut status),
+                }
+            }
+            status.triggered = false;
+            status.running = true;
+        }
+
+        let started = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(&mut work)).unwrap_or_else(|payload| {
+            Err(Error::StorageEngine(format!(
+                "panicked: {}",
+                panic_message(&*payload)
+            )))
+        });
+
+        let mut status = shared.status.lock();
+        status.running = false;
+        status.runs += 1;
+        status.last_run = Some(started);
+        status.last_duration = Some(started.elapsed());
+        status.next_run = next_run(shared.schedule);
+        shared.idle.notify_all();
+        if let Err(e) = result {
+            status.failure = Some(e.to_string());
+            status.next_run = None;
+            drop(status);
+            if let Some(on_failure) = &on_failure {
+                on_failure(&shared.name, &e);
+            }
+            return;
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc;
+
+    /// Polls `condition` until it holds or a generous timeout passes
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    #[test]
+    fn test_triggered_jobs_run_and_report_runs() {
+        let scheduler = Scheduler::new();
+        let (sender, receiver) = mpsc::channel();
+        scheduler
+            .spawn("flush", Schedule::OnDemand, move || {
+                sender.send(()).unwrap();
+                Ok(())
+            })
+            .unwrap();
+
+        let info = scheduler.job("flush").unwrap();
+        assert_eq!(info.state, JobState::Idle);
+        assert_eq!((info.runs, info.last_run, info.next_run), (0, None, None));
+
+        assert!(scheduler.trigger("flush"));
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(eventually(|| scheduler.job("flush").unwrap().runs == 1));
+        let info = scheduler.job("flush").unwrap();
+        assert!(info.last_run.is_some() && info.last_duration.is_some());
+
+        assert!(!scheduler.trigger("missing"));
+        scheduler.shutdown().unwrap();
+        assert!(scheduler.jobs().is_empty());
+    }
+
+    #[test]
+    fn test_wait_idle_returns_once_triggered_runs_are_done() {
+        let scheduler = Scheduler::new();
+        let (sender, receiver) = mpsc::channel();
+        let done = Arc::new(AtomicU64::new(0));
+        scheduler
+            .spawn("compaction", Schedule::OnDemand, {
+                let done = Arc::clone(&done);
+                move || {
+                    receiver.recv().unwrap();
+                    done.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+            })
+            .unwrap();
+        assert!(scheduler.wait_idle("compaction"));
+
+        // Whether or not the run has started, the wait covers it
+        scheduler.trigger("compaction");
+        sender.send(()).unwrap();
+        assert!(scheduler.wait_idle("compaction"));
+        assert_eq!(done.load(Ordering::Relaxed), 1);
+
+        assert!(!scheduler.wait_idle("missing"));
+        scheduler.stop("compaction").unwrap();
+        assert!(!scheduler.wait_idle("compaction"));
+    }
+
+    #[test]
+    fn test_periodic_jobs_run_on_their_interval() {
+        let scheduler = Scheduler::new();
+        let runs = Arc::new(AtomicU64::new(0));
+        scheduler
+            .spawn("sync", Schedule::Every(Duration::from_millis(5)), {
+                let runs = Arc::clone(&runs);
+                move || {
+                    runs.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+            })
+            .unwrap();
+
+        assert!(scheduler.job("sync").unwrap().next_run.is_some());
+        assert!(eventually(|| runs.load(Ordering::Relaxed) >= 3));
+        scheduler.stop("sync").unwrap();
+        assert!(scheduler.job("sync").is_none());
+    }
+
+    #[test]
+    fn test_panics_and_errors_are_surfaced() {
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let scheduler = Scheduler::new().with_failure_handler({
+            let failures = Arc::clone(&failures);
+            move |name, error| failures.lock().push(format!("{}: {}", name, error))
+        });
+        scheduler
+            .spawn("compaction", Schedule::OnDemand, || panic!("bad table"))
+            .unwrap();
+        scheduler
+            .spawn("metrics", Schedule::OnDemand, || {
+                Err(Error::DiskFull("no space".to_string()))
+            })
+            .unwrap();
+
+        scheduler.trigger("compaction");
+        scheduler.trigger("metrics");
+        assert!(eventually(|| failures.lock().len() == 2));
+
+        let info = scheduler.job("compaction").unwrap();
+        assert!(matches!(info.state, JobState::Failed(ref msg) if msg.contains("bad table")));
+        // Failed jobs no longer accept triggers
+        assert!(!scheduler.trigger("metrics"));
+
+        let error = scheduler.stop("metrics").unwrap_err();
+        assert!(error.to_string().contains("no space"));
+        assert!(scheduler.shutdown().is_err());
+    }
+
+    #[test]
+    fn test_duplicate_names_are_rejected() {
+        let scheduler = Scheduler::new();
+        scheduler
+            .spawn("flush", Schedule::OnDemand, || Ok(()))
+            .unwrap();
+        let error = scheduler
+            .spawn("flush", Schedule::OnDemand, || Ok(()))
+            .unwrap_err();
+        assert!(matches!(error, Error::InvalidArgument(_)));
+        assert!(matches!(scheduler.stop("missing"), Err(Error::NotFound(_))));
+    }
+}
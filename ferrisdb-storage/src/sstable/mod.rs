@@ -20,6 +20,8 @@
 //! ├─────────────────┤
 //! │  Bloom Filter   │ ← Probabilistic existence filter
 //! ├─────────────────┤
+//! │   Properties    │ ← Named table properties, e.g. the comparator
+//! ├─────────────────┤
 //! │     Footer      │ ← Metadata and magic number
 //! └─────────────────┘
 //! ```
@@ -38,10 +40,12 @@
 //! ```text
 //! ┌──────────┬─────────────┬───────────┬──────────────┬────────────┬──────────┐
 //! │ Key Len  │ Value Len   │ Timestamp │  Operation   │    Key     │  Value   │
-//! │(4 bytes) │ (4 bytes)   │ (8 bytes) │   (1 byte)   │ (var len)  │(var len) │
+//! │ (varint) │  (varint)   │ (8 bytes) │   (1 byte)   │ (var len)  │(var len) │
 //! └──────────┴─────────────┴───────────┴──────────────┴────────────┴──────────┘
 //! ```
 //!
+//! The operation byte is 0 for Put, 1 for Delete and 2 for Merge.
+//!
 //! ## Index Block Format
 //!
 //! ```text
@@ -56,7 +60,7 @@
 //! ```text
 //! ┌─────────────┬─────────────┬────────────┐
 //! │ Block Offset│  Key Len    │    Key     │
-//! │  (8 bytes)  │ (4 bytes)   │ (var len)  │
+//! │  (varint)   │  (varint)   │ (var len)  │
 //! └─────────────┴─────────────┴────────────┘
 //! ```
 //!
@@ -69,6 +73,47 @@
 //! └─────────────────┴─────────────────┴─────────────┘
 //! ```
 //!
+//! The filter holds the prefixes of the table's keys under the extractor
+//! named by the `ferrisdb.prefix_extractor` property (see
+//! [`BloomFilter`](crate::utils::bloom::BloomFilter)). Tables written
+//! without an extractor store an empty filter with a hash count of 0.
+//!
+//! ## Properties Block Format
+//!
+//! ```text
+//! ┌─────────────────┬─────────────────┬─────────────┐
+//! │   Entry Count   │     Entries     │  Checksum   │
+//! │    (4 bytes)    │   (variable)    │  (4 bytes)  │
+//! └─────────────────┴─────────────────┴─────────────┘
+//! ```
+//!
+//! Each entry is a name and a value, both prefixed with a 4-byte length.
+//! The block fills the space between the bloom filter and the footer, so
+//! the footer doesn't need to locate it. Files written before the block
+//! existed end the bloom filter right at the footer; they have no
+//! properties and were written with the bytewise comparator. Unknown
+//! property names are skipped.
+//!
+//! From version 4 on, the `ferrisdb.timestamp_range` property holds the
+//! smallest and largest timestamp of the table's entries, so reads
+//! restricted to a range of timestamps skip tables outside it without
+//! reading a block.
+//!
+//! ## Format Versions
+//!
+//! The `ferrisdb.format_version` property records how data and index
+//! entries are encoded. Version 2 stores lengths and block offsets as
+//! varints (see [`coding`](crate::utils::coding)). Tables without the
+//! property are version 1, which stored them as fixed 4-byte lengths and
+//! 8-byte offsets; they remain readable.
+//!
+//! Version 3 fills in the checksums of data, index and properties blocks:
+//! a CRC32C of the block's bytes before the checksum. Earlier versions
+//! wrote 0 there, which is never checked. Index and properties blocks are
+//! checked whenever a table is opened; data blocks only by readers asked
+//! to with [`SSTableReader::with_verify_checksums`], as checking them
+//! costs a pass over every block read.
+//!
 //! ## Footer Format (40 bytes)
 //!
 //! The SSTable footer contains metadata about the file's structure and is written
@@ -91,10 +136,11 @@
 //!
 //! # Key Invariants
 //!
-//! 1. **Sorting**: Entries sorted by (user_key ASC, timestamp DESC)
+//! 1. **Sorting**: Entries sorted by (user_key ASC, timestamp DESC), user
+//!    keys ordered by the comparator named in the properties
 //! 2. **Immutability**: SSTables are never modified after creation
-//! 3. **Checksums**: All blocks include CRC32 checksums
-//! 4. **Little Endian**: All multi-byte integers in little-endian format
+//! 3. **Checksums**: All blocks include CRC32C checksums (version 3)
+//! 4. **Little Endian**: All fixed-width integers in little-endian format
 //! 5. **Magic Number**: `0x46455252_49534442` ("FERRISDB" in ASCII)
 //!
 //! # Features
@@ -104,7 +150,9 @@
 //! - Checksums for corruption detection
 //! - Bloom filters for existence checks
 
-use ferrisdb_core::{Key, Operation, Result, Timestamp, Value};
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::utils::{coding, crc};
+use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
 use std::fmt;
 
 /// Magic number for SSTable files ("FERRISDB" in ASCII)
@@ -119,10 +167,36 @@ pub const FOOTER_SIZE: usize = 40;
 /// Maximum key or value size (16MB)
 pub const MAX_ENTRY_SIZE: usize = 16 * 1024 * 1024;
 
+/// Name of the property holding the comparator's name
+pub const COMPARATOR_PROPERTY: &str = "ferrisdb.comparator";
+
+/// Name of the property holding the format version, as a 4-byte integer
+pub const FORMAT_VERSION_PROPERTY: &str = "ferrisdb.format_version";
+
+/// Name of the property holding the name of the prefix extractor whose
+/// prefixes the bloom filter holds; absent in tables without a filter
+pub const PREFIX_EXTRACTOR_PROPERTY: &str = "ferrisdb.prefix_extractor";
+
+/// Name of the property holding the smallest and largest timestamp of the
+/// entries, as two 8-byte integers; absent in tables written before it
+pub const TIMESTAMP_RANGE_PROPERTY: &str = "ferrisdb.timestamp_range";
+
+/// Format version written by [`SSTableWriter`]
+///
+/// Version 1 tables, which predate the property, used fixed-width lengths
+/// and offsets in data and index blocks; version 2 uses varints, version 3
+/// adds block checksums, and version 4 records the timestamp range.
+pub const FORMAT_VERSION: u32 = 4;
+
+/// First format version whose blocks carry checksums
+const CHECKSUM_VERSION: u32 = 3;
+
 /// Internal key representation for SSTable entries
 ///
 /// Combines user key with MVCC timestamp for versioning.
-/// Keys are ordered by (user_key ASC, timestamp DESC).
+/// Keys are ordered by (user_key ASC, timestamp DESC), comparing user keys
+/// bytewise; tables with another comparator are ordered by
+/// [`compare_internal`](crate::comparator::compare_internal).
 /// Operation metadata is stored separately in SSTableEntry.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InternalKey {
@@ -141,7 +215,8 @@ impl InternalKey {
 
     /// Returns the total serialized size of this internal key
     pub fn serialized_size(&self) -> usize {
-        4 + 8 + self.user_key.len() // key_len + timestamp + key
+        // key_len + timestamp + key
+        coding::varint_length(self.user_key.len() as u64) + 8 + self.user_key.len()
     }
 }
 
@@ -183,7 +258,7 @@ pub struct SSTableEntry {
     pub key: InternalKey,
     /// The value associated with this key version
     pub value: Value,
-    /// The operation type (Put/Delete) for this entry
+    /// The operation type (Put/Delete/Merge) for this entry
     pub operation: Operation,
 }
 
@@ -199,7 +274,52 @@ impl SSTableEntry {
 
     /// Returns the total serialized size of this entry
     pub fn serialized_size(&self) -> usize {
-        self.key.serialized_size() + 4 + self.value.len() + 1 // key + value_len + value + operation
+        // key + value_len + value + operation
+        self.key.serialized_size()
+            + coding::varint_length(self.value.len() as u64)
+            + self.value.len()
+            + 1
+    }
+
+    /// Appends the entry in the current format version
+    pub(crate) fn encode_to(&self, buf: &mut Vec<u8>) {
+        // MAX_ENTRY_SIZE keeps both lengths within a varint32
+        coding::put_varint32(buf, self.key.user_key.len() as u32);
+        coding::put_varint32(buf, self.value.len() as u32);
+        coding::put_fixed64(buf, self.key.timestamp);
+        buf.push(match self.operation {
+            Operation::Put => 0,
+            Operation::Delete => 1,
+            Operation::Merge => 2,
+        });
+        buf.extend_from_slice(&self.key.user_key);
+        buf.extend_from_slice(&self.value);
+    }
+
+    /// Decodes an entry of a table in `format_version` from the front of
+    /// `input`
+    pub(crate) fn decode_from(input: &mut &[u8], format_version: u32) -> Result<Self> {
+        let key_len = get_length(input, format_version)?;
+        let value_len = get_length(input, format_version)?;
+        let timestamp = coding::get_fixed64(input)?;
+        let operation = match coding::get_bytes(input, 1)?[0] {
+            0 => Operation::Put,
+            1 => Operation::Delete,
+            2 => Operation::Merge,
+            op => {
+                return Err(Error::InvalidFormat(format!(
+                    "Invalid operation byte: {}",
+                    op
+                )))
+            }
+        };
+        let user_key = coding::get_bytes(input, key_len)?.to_vec();
+        let value = coding::get_bytes(input, value_len)?.to_vec();
+        Ok(Self::new(
+            InternalKey::new(user_key, timestamp),
+            value,
+            operation,
+        ))
     }
 }
 
@@ -223,8 +343,154 @@ impl IndexEntry {
 
     /// Returns the serialized size of this index entry
     pub fn serialized_size(&self) -> usize {
-        8 + 4 + self.first_key.len() // offset + key_len + key
+        // offset + key_len + key
+        coding::varint_length(self.block_offset)
+            + coding::varint_length(self.first_key.len() as u64)
+            + self.first_key.len()
+    }
+
+    /// Appends the entry in the current format version
+    pub(crate) fn encode_to(&self, buf: &mut Vec<u8>) {
+        coding::put_varint64(buf, self.block_offset);
+        coding::put_length_prefixed_slice(buf, &self.first_key);
+    }
+
+    /// Decodes an entry of a table in `format_version` from the front of
+    /// `input`
+    pub(crate) fn decode_from(input: &mut &[u8], format_version: u32) -> Result<Self> {
+        let block_offset = if format_version < 2 {
+            coding::get_fixed64(input)?
+        } else {
+            coding::get_varint64(input)?
+        };
+        let key_len = get_length(input, format_version)?;
+        let first_key = coding::get_bytes(input, key_len)?.to_vec();
+        Ok(Self::new(block_offset, first_key))
+    }
+}
+
+/// Encodes a data block: the entry count, the entries and a checksum
+pub fn encode_data_block(entries: &[SSTableEntry]) -> Vec<u8> {
+    let size = entries
+        .iter()
+        .map(SSTableEntry::serialized_size)
+        .sum::<usize>();
+    encode_block(entries, size, SSTableEntry::encode_to)
+}
+
+/// Decodes a data block of a table in `format_version`
+///
+/// # Errors
+///
+/// Returns an error if the block is truncated or an entry is malformed.
+pub fn decode_data_block(block: &[u8], format_version: u32) -> Result<Vec<SSTableEntry>> {
+    decode_block(block, |input| {
+        SSTableEntry::decode_from(input, format_version)
+    })
+}
+
+/// Encodes an index block: the entry count, the entries and a checksum
+pub fn encode_index_block(entries: &[IndexEntry]) -> Vec<u8> {
+    let size = entries
+        .iter()
+        .map(IndexEntry::serialized_size)
+        .sum::<usize>();
+    encode_block(entries, size, IndexEntry::encode_to)
+}
+
+/// Decodes the index block of a table in `format_version`
+///
+/// # Errors
+///
+/// Returns an error if the block is truncated or an entry is malformed.
+pub fn decode_index_block(block: &[u8], format_version: u32) -> Result<Vec<IndexEntry>> {
+    decode_block(block, |input| {
+        IndexEntry::decode_from(input, format_version)
+    })
+}
+
+fn encode_block<T>(entries: &[T], size: usize, encode: fn(&T, &mut Vec<u8>)) -> Vec<u8> {
+    let mut block = Vec::with_capacity(size + 8);
+    coding::put_fixed32(&mut block, entries.len() as u32);
+    for entry in entries {
+        encode(entry, &mut block);
     }
+
+    let checksum = crc::crc32c(&block);
+    coding::put_fixed32(&mut block, checksum);
+    block
+}
+
+fn decode_block<T>(block: &[u8], decode: impl Fn(&mut &[u8]) -> Result<T>) -> Result<Vec<T>> {
+    let mut cursor = block;
+    let entry_count = coding::get_fixed32(&mut cursor)? as usize;
+    // A corrupted count must not reserve more than the block could hold
+    let mut entries = Vec::with_capacity(entry_count.min(cursor.len()));
+    for _ in 0..entry_count {
+        entries.push(decode(&mut cursor)?);
+    }
+
+    // Checked separately by `verify_block_checksum`
+    coding::get_fixed32(&mut cursor)?;
+
+    Ok(entries)
+}
+
+/// Checks the checksum ending a data or index block of a table in
+/// `format_version`
+///
+/// Blocks written before version 3 end in a 0 placeholder and always pass.
+///
+/// # Errors
+///
+/// Returns [`Error::Corruption`] if the block is too short to hold a
+/// checksum or its bytes don't match it.
+pub fn verify_block_checksum(block: &[u8], format_version: u32) -> Result<()> {
+    if format_version < CHECKSUM_VERSION {
+        return Ok(());
+    }
+    verify_checksum(block, "Block")
+}
+
+/// Checks that the last 4 bytes of `block` are the CRC32C of the others
+fn verify_checksum(block: &[u8], what: &str) -> Result<()> {
+    let Some(body_len) = block.len().checked_sub(4) else {
+        return Err(Error::Corruption(format!(
+            "{} of {} bytes is too short for a checksum",
+            what,
+            block.len()
+        )));
+    };
+    let (body, mut stored) = block.split_at(body_len);
+    let stored = coding::get_fixed32(&mut stored)?;
+    let actual = crc::crc32c(body);
+    if stored != actual {
+        return Err(Error::Corruption(format!(
+            "{} checksum mismatch: stored {:#010x}, computed {:#010x}",
+            what, stored, actual
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the checksum stored at the end of `block` and the CRC32C of its
+/// other bytes, if they differ
+pub(crate) fn checksum_mismatch(block: &[u8]) -> Option<(u32, u32)> {
+    let body_len = block.len().checked_sub(4)?;
+    let (body, mut stored) = block.split_at(body_len);
+    let stored = coding::get_fixed32(&mut stored).ok()?;
+    let actual = crc::crc32c(body);
+    (stored != actual).then_some((stored, actual))
+}
+
+/// Decodes a key or value length, a fixed 4-byte integer in version 1
+fn get_length(input: &mut &[u8], format_version: u32) -> Result<usize> {
+    let len = if format_version < 2 {
+        coding::get_fixed32(input)?
+    } else {
+        coding::get_varint32(input)?
+    };
+    Ok(len as usize)
 }
 
 /// SSTable metadata stored in the footer
@@ -298,10 +564,139 @@ impl Footer {
     }
 }
 
+/// Properties recorded about an SSTable when it was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Name of the comparator the keys are sorted by
+    pub comparator: String,
+    /// Encoding of the data and index blocks
+    pub format_version: u32,
+    /// Name of the prefix extractor the bloom filter was built with
+    pub prefix_extractor: Option<String>,
+    /// Smallest and largest timestamp of the entries, or `None` if the
+    /// table didn't record them
+    pub timestamp_range: Option<(Timestamp, Timestamp)>,
+}
+
+impl TableProperties {
+    /// Returns the properties of a table written now with `comparator`
+    pub fn new(comparator: &dyn Comparator) -> Self {
+        Self {
+            comparator: comparator.name().to_string(),
+            format_version: FORMAT_VERSION,
+            prefix_extractor: None,
+            timestamp_range: None,
+        }
+    }
+
+    /// Returns whether the table may hold entries with a timestamp in
+    /// `min..=max`, which tables without a recorded range always may
+    pub fn may_contain_timestamps(&self, min: Timestamp, max: Timestamp) -> bool {
+        self.timestamp_range.map_or(true, |(smallest, largest)| {
+            smallest <= max && min <= largest
+        })
+    }
+
+    /// Serializes the properties block, including its checksum
+    ///
+    /// The block keeps fixed 4-byte lengths in every format version, since
+    /// it is read before the version is known.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let format_version = self.format_version.to_le_bytes();
+        let mut properties = vec![
+            (COMPARATOR_PROPERTY, self.comparator.as_bytes()),
+            (FORMAT_VERSION_PROPERTY, format_version.as_slice()),
+        ];
+        if let Some(extractor) = &self.prefix_extractor {
+            properties.push((PREFIX_EXTRACTOR_PROPERTY, extractor.as_bytes()));
+        }
+        let mut timestamp_range = Vec::new();
+        if let Some((smallest, largest)) = self.timestamp_range {
+            timestamp_range.extend_from_slice(&smallest.to_le_bytes());
+            timestamp_range.extend_from_slice(&largest.to_le_bytes());
+            properties.push((TIMESTAMP_RANGE_PROPERTY, timestamp_range.as_slice()));
+        }
+
+        let mut bytes = Vec::new();
+        coding::put_fixed32(&mut bytes, properties.len() as u32);
+        for (name, value) in properties {
+            coding::put_fixed32(&mut bytes, name.len() as u32);
+            bytes.extend_from_slice(name.as_bytes());
+            coding::put_fixed32(&mut bytes, value.len() as u32);
+            bytes.extend_from_slice(value);
+        }
+
+        let checksum = if self.format_version < CHECKSUM_VERSION {
+            0
+        } else {
+            crc::crc32c(&bytes)
+        };
+        coding::put_fixed32(&mut bytes, checksum);
+        bytes
+    }
+
+    /// Deserializes a properties block; an empty one gives the defaults
+    ///
+    /// Blocks of version 3 and later must match their checksum.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut properties = Self::default();
+        if bytes.is_empty() {
+            return Ok(properties);
+        }
+
+        let mut cursor = bytes;
+        let count = coding::get_fixed32(&mut cursor)?;
+        for _ in 0..count {
+            let name_len = coding::get_fixed32(&mut cursor)?;
+            let name = coding::get_bytes(&mut cursor, name_len as usize)?;
+            let value_len = coding::get_fixed32(&mut cursor)?;
+            let mut value = coding::get_bytes(&mut cursor, value_len as usize)?;
+            if name == COMPARATOR_PROPERTY.as_bytes() {
+                properties.comparator = String::from_utf8(value.to_vec()).map_err(|_| {
+                    Error::InvalidFormat("Comparator name is not UTF-8".to_string())
+                })?;
+            } else if name == FORMAT_VERSION_PROPERTY.as_bytes() {
+                properties.format_version = coding::get_fixed32(&mut value)?;
+            } else if name == PREFIX_EXTRACTOR_PROPERTY.as_bytes() {
+                let extractor = String::from_utf8(value.to_vec()).map_err(|_| {
+                    Error::InvalidFormat("Prefix extractor name is not UTF-8".to_string())
+                })?;
+                properties.prefix_extractor = Some(extractor);
+            } else if name == TIMESTAMP_RANGE_PROPERTY.as_bytes() {
+                let smallest = coding::get_fixed64(&mut value)?;
+                let largest = coding::get_fixed64(&mut value)?;
+                properties.timestamp_range = Some((smallest, largest));
+            }
+        }
+        coding::get_fixed32(&mut cursor)?;
+        if properties.format_version >= CHECKSUM_VERSION {
+            verify_checksum(&bytes[..bytes.len() - cursor.len()], "Properties block")?;
+        }
+
+        Ok(properties)
+    }
+}
+
+impl Default for TableProperties {
+    /// Properties of a table written before the properties block existed
+    fn default() -> Self {
+        Self {
+            comparator: BytewiseComparator.name().to_string(),
+            format_version: 1,
+            prefix_extractor: None,
+            timestamp_range: None,
+        }
+    }
+}
+
 pub mod reader;
+pub mod split;
 pub mod writer;
 
-pub use reader::{SSTableIterator, SSTableReader, SSTableReaderInfo};
+pub use reader::{
+    PinnedEntry, SSTableCursor, SSTableIntoIter, SSTableIterator, SSTableReader, SSTableReaderInfo,
+};
+pub use split::TableSplit;
 pub use writer::{SSTableInfo, SSTableWriter};
 
 #[cfg(test)]
@@ -325,7 +720,7 @@ mod tests {
     #[test]
     fn test_internal_key_serialized_size() {
         let key = InternalKey::new(b"test_key".to_vec(), 12345);
-        let expected_size = 4 + 8 + 8; // key_len + timestamp + key
+        let expected_size = 1 + 8 + 8; // key_len + timestamp + key
         assert_eq!(key.serialized_size(), expected_size);
     }
 
@@ -369,10 +764,109 @@ mod tests {
             .contains("Invalid footer size"));
     }
 
+    #[test]
+    fn test_table_properties_round_trip() {
+        let mut properties = TableProperties {
+            comparator: "example.Reverse".to_string(),
+            format_version: FORMAT_VERSION,
+            prefix_extractor: None,
+            timestamp_range: None,
+        };
+        let bytes = properties.to_bytes();
+        assert_eq!(TableProperties::from_bytes(&bytes).unwrap(), properties);
+        properties.prefix_extractor = Some("example.Prefix".to_string());
+        let with_extractor = properties.to_bytes();
+        assert_eq!(
+            TableProperties::from_bytes(&with_extractor).unwrap(),
+            properties
+        );
+        properties.timestamp_range = Some((7, 42));
+        let with_range = properties.to_bytes();
+        assert_eq!(
+            TableProperties::from_bytes(&with_range).unwrap(),
+            properties
+        );
+        assert!(properties.may_contain_timestamps(42, 50));
+        assert!(!properties.may_contain_timestamps(43, 50));
+        assert!(!properties.may_contain_timestamps(0, 6));
+        properties.timestamp_range = None;
+        assert!(properties.may_contain_timestamps(0, 6));
+
+        // Files without a properties block use the defaults
+        assert_eq!(
+            TableProperties::from_bytes(&[]).unwrap(),
+            TableProperties::default()
+        );
+        assert!(TableProperties::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // Damage is caught from version 3 on
+        let mut damaged = bytes.clone();
+        damaged[10] ^= 0x20;
+        assert!(matches!(
+            TableProperties::from_bytes(&damaged),
+            Err(Error::Corruption(_))
+        ));
+        properties.format_version = 2;
+        let unchecked = properties.to_bytes();
+        assert_eq!(unchecked[unchecked.len() - 4..], [0; 4]);
+        assert_eq!(TableProperties::from_bytes(&unchecked).unwrap(), properties);
+    }
+
+    #[test]
+    fn test_block_checksums_are_checked_from_version_3() {
+        let entries = vec![SSTableEntry::new(
+            InternalKey::new(b"a".to_vec(), 1),
+            b"x".to_vec(),
+            Operation::Put,
+        )];
+        let mut block = encode_data_block(&entries);
+        verify_block_checksum(&block, FORMAT_VERSION).unwrap();
+
+        let last = block.len() - 5;
+        block[last] = b'y';
+        assert!(matches!(
+            verify_block_checksum(&block, FORMAT_VERSION),
+            Err(Error::Corruption(_))
+        ));
+        assert!(verify_block_checksum(&block, 2).is_ok());
+        assert!(verify_block_checksum(&[0; 3], FORMAT_VERSION).is_err());
+    }
+
+    #[test]
+    fn test_blocks_round_trip_and_reject_truncation() {
+        let entries = vec![
+            SSTableEntry::new(
+                InternalKey::new(b"a".to_vec(), 2),
+                b"x".to_vec(),
+                Operation::Put,
+            ),
+            SSTableEntry::new(
+                InternalKey::new(b"b".to_vec(), 1),
+                vec![],
+                Operation::Delete,
+            ),
+        ];
+        let block = encode_data_block(&entries);
+        assert_eq!(decode_data_block(&block, FORMAT_VERSION).unwrap(), entries);
+
+        let index = vec![
+            IndexEntry::new(0, b"a".to_vec()),
+            IndexEntry::new(4096, b"m".to_vec()),
+        ];
+        let block = encode_index_block(&index);
+        let decoded = decode_index_block(&block, FORMAT_VERSION).unwrap();
+        assert_eq!(decoded[1].block_offset, 4096);
+        assert_eq!(decoded[1].first_key, b"m");
+
+        for len in 0..block.len() {
+            assert!(decode_index_block(&block[..len], FORMAT_VERSION).is_err());
+        }
+    }
+
     #[test]
     fn test_index_entry_serialized_size() {
         let entry = IndexEntry::new(1000, b"first_key".to_vec());
-        let expected_size = 8 + 4 + 9; // offset + key_len + key
+        let expected_size = 2 + 1 + 9; // offset + key_len + key
         assert_eq!(entry.serialized_size(), expected_size);
     }
 
@@ -422,8 +916,8 @@ mod tests {
         let value = b"test_value".to_vec();
         let entry = SSTableEntry::new(key, value, Operation::Put);
 
-        // key_serialized_size + value_len(4) + value + operation(1)
-        let expected_size = (4 + 8 + 8) + 4 + 10 + 1;
+        // key_serialized_size + value_len(1) + value + operation(1)
+        let expected_size = (1 + 8 + 8) + 1 + 10 + 1;
         assert_eq!(entry.serialized_size(), expected_size);
     }
 
@@ -503,11 +997,11 @@ mod tests {
             assert_eq!(missing, None);
 
             // Test iterator
-            let mut iter = reader.iter().unwrap();
+            let iter = reader.iter().unwrap();
             let mut count = 0;
             let mut last_key: Option<InternalKey> = None;
 
-            while let Some(entry_result) = iter.next() {
+            for entry_result in iter {
                 let entry = entry_result.unwrap();
 
                 // Verify ordering
@@ -522,10 +1016,10 @@ mod tests {
             // Test range iterator
             let start_key = b"banana".to_vec();
             let end_key = b"date".to_vec();
-            let mut range_iter = reader.range_iter(Some(&start_key), Some(&end_key)).unwrap();
+            let range_iter = reader.range_iter(Some(&start_key), Some(&end_key)).unwrap();
 
             let mut range_entries = Vec::new();
-            while let Some(entry_result) = range_iter.next() {
+            for entry_result in range_iter {
                 let entry = entry_result.unwrap();
                 assert!(entry.key.user_key >= start_key);
                 assert!(entry.key.user_key < end_key);
@@ -536,4 +1030,104 @@ mod tests {
             assert!(range_entries.len() >= 3);
         }
     }
+
+    /// Writes a table the way format version 1 did: fixed-width lengths
+    /// and offsets, and no properties block
+    fn write_version_1_table(path: &std::path::Path, entries: &[SSTableEntry]) {
+        let mut file = Vec::new();
+        coding::put_fixed32(&mut file, entries.len() as u32);
+        for entry in entries {
+            coding::put_fixed32(&mut file, entry.key.user_key.len() as u32);
+            coding::put_fixed32(&mut file, entry.value.len() as u32);
+            coding::put_fixed64(&mut file, entry.key.timestamp);
+            file.push(0);
+            file.extend_from_slice(&entry.key.user_key);
+            file.extend_from_slice(&entry.value);
+        }
+        coding::put_fixed32(&mut file, 0);
+
+        let index_offset = file.len() as u64;
+        coding::put_fixed32(&mut file, 1);
+        coding::put_fixed64(&mut file, 0);
+        coding::put_fixed32(&mut file, entries[0].key.user_key.len() as u32);
+        file.extend_from_slice(&entries[0].key.user_key);
+        coding::put_fixed32(&mut file, 0);
+
+        let bloom_offset = file.len() as u64;
+        file.extend_from_slice(&[0; 16]);
+        let footer = Footer::new(index_offset, bloom_offset - index_offset, bloom_offset, 16);
+        file.extend_from_slice(&footer.to_bytes());
+        std::fs::write(path, file).unwrap();
+    }
+
+    #[test]
+    fn test_version_1_tables_remain_readable() {
+        use crate::sstable::{SSTableReader, SSTableWriter};
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let entries: Vec<_> = (0..20u64)
+            .map(|i| {
+                SSTableEntry::new(
+                    InternalKey::new(format!("key{:02}", i).into_bytes(), 100 - i),
+                    vec![b'v'; i as usize],
+                    Operation::Put,
+                )
+            })
+            .collect();
+
+        let legacy = temp_dir.path().join("legacy.sst");
+        write_version_1_table(&legacy, &entries);
+        let mut reader = SSTableReader::open(&legacy).unwrap();
+        assert_eq!(reader.properties().format_version, 1);
+        let read: Vec<_> = reader.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, entries);
+
+        let current = temp_dir.path().join("current.sst");
+        let mut writer = SSTableWriter::new(&current).unwrap();
+        for entry in &entries {
+            writer
+                .add(entry.key.clone(), entry.value.clone(), entry.operation)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let mut reader = SSTableReader::open(&current).unwrap();
+        assert_eq!(reader.properties().format_version, FORMAT_VERSION);
+        let read: Vec<_> = reader.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn test_newer_format_versions_are_rejected() {
+        use crate::sstable::{SSTableReader, SSTableWriter};
+        use ferrisdb_core::error::ErrorCode;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("future.sst");
+        let mut writer = SSTableWriter::new(&path).unwrap();
+        writer
+            .add(
+                InternalKey::new(b"k".to_vec(), 1),
+                b"v".to_vec(),
+                Operation::Put,
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        // Rewrite the version property as if a newer build wrote the table
+        let mut file = std::fs::read(&path).unwrap();
+        let name = FORMAT_VERSION_PROPERTY.as_bytes();
+        let at = file.windows(name.len()).position(|w| w == name).unwrap() + name.len() + 4;
+        file[at..at + 4].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let footer_at = file.len() - FOOTER_SIZE;
+        let footer = Footer::from_bytes(&file[footer_at..]).unwrap();
+        let start = (footer.bloom_offset + footer.bloom_length) as usize;
+        let checksum = crc::crc32c(&file[start..footer_at - 4]);
+        file[footer_at - 4..footer_at].copy_from_slice(&checksum.to_le_bytes());
+        std::fs::write(&path, file).unwrap();
+
+        let error = SSTableReader::open(&path).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::Unsupported);
+    }
 }
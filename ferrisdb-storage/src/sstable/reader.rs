@@ -1,21 +1,56 @@
 //! SSTable reader implementation
 
-use crate::sstable::{Footer, IndexEntry, InternalKey, SSTableEntry, FOOTER_SIZE};
+use crate::comparator::{self, Comparator};
+use crate::prefix::PrefixExtractor;
+use crate::sstable::{
+    checksum_mismatch, decode_data_block, decode_index_block, verify_block_checksum, Footer,
+    IndexEntry, InternalKey, SSTableEntry, TableProperties, FOOTER_SIZE, FORMAT_VERSION,
+};
+use crate::utils::bloom::BloomFilter;
+use crate::utils::cache::{Cache, CacheHandle};
+use crate::utils::memory::{MemoryKind, MemoryReservation, MemoryTracker};
+use crate::utils::{BufferPool, BytesMutExt, PooledBuffer};
+use crate::vfs::{self, Vfs, VfsFile};
 use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
-use std::collections::BTreeMap;
-use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[cfg(test)]
 use crate::sstable::SSTABLE_MAGIC;
 
+/// Total size of the encoded blocks a reader keeps decoded in its cache
+const BLOCK_CACHE_CAPACITY: usize = 8 * 1024 * 1024;
+
+/// A decoded data block pinned in a reader's block cache
+type BlockHandle = CacheHandle<u64, Vec<SSTableEntry>>;
+
+/// Buffered reader over the table's file
+type FileReader = BufReader<Box<dyn VfsFile>>;
+
+/// What a reader holds charged to a memory tracker
+struct ReaderMemory {
+    /// The index and, once read, the bloom filter
+    metadata: MemoryReservation,
+    /// The blocks in the reader's cache
+    blocks: MemoryReservation,
+}
+
 /// Reader for querying SSTable files
 ///
 /// The SSTableReader provides efficient point lookups and range scans over
 /// immutable SSTable files. It uses the index to locate data blocks and
 /// supports both exact key matches and range queries.
 ///
+/// Blocks are read into buffers checked out of the shared
+/// [`BufferPool`](crate::utils::BufferPool) rather than freshly allocated.
+///
+/// Errors name the file, and for a damaged data block its offset.
+///
+/// The index and properties are checked against their checksums when the
+/// table is opened. Data blocks are only checked once
+/// [`with_verify_checksums`](Self::with_verify_checksums) asks for it.
+///
 /// # Example
 ///
 /// ```ignore
@@ -34,21 +69,42 @@ use crate::sstable::SSTABLE_MAGIC;
 /// }
 /// ```
 pub struct SSTableReader {
+    /// Path of the file, for error context
+    path: PathBuf,
     /// Buffered reader for the file
-    reader: BufReader<File>,
+    reader: FileReader,
     /// SSTable metadata from footer
     footer: Footer,
     /// Index entries for efficient block lookup
     index: Vec<IndexEntry>,
-    /// Cached data blocks (block_offset -> entries)
-    block_cache: BTreeMap<u64, Vec<SSTableEntry>>,
+    /// Properties recorded by the writer
+    properties: TableProperties,
+    /// Bloom filter over the keys' prefixes, read on first use
+    prefix_filter: Option<BloomFilter>,
+    /// Ordering of user keys, matching the recorded comparator
+    comparator: Arc<dyn Comparator>,
+    /// Decoded data blocks by offset, charged by their encoded size and
+    /// evicted least recently used first
+    block_cache: Cache<u64, Vec<SSTableEntry>>,
+    /// Block loads served from the cache
+    cache_hits: u64,
+    /// Block loads that had to read the file
+    cache_misses: u64,
+    /// Blocks iterators read from the file, past the cache
+    blocks_scanned: u64,
+    /// Whether data blocks are checked against their checksums when read
+    verify_checksums: bool,
+    /// Charges of the reader's memory, if it is tracked
+    memory: Option<ReaderMemory>,
 }
 
 impl std::fmt::Debug for SSTableReader {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SSTableReader")
+            .field("path", &self.path)
             .field("footer", &self.footer)
             .field("index_count", &self.index.len())
+            .field("comparator", &self.properties.comparator)
             .field("cached_blocks", &self.block_cache.len())
             .finish()
     }
@@ -60,8 +116,9 @@ impl SSTableReader {
     /// This method:
     /// 1. Opens the file and reads the footer
     /// 2. Validates the magic number
-    /// 3. Reads and parses the index block
-    /// 4. Prepares the reader for queries
+    /// 3. Reads the properties block, which gives the format version
+    /// 4. Reads and parses the index block
+    /// 5. Prepares the reader for queries
     ///
     /// # Arguments
     ///
@@ -74,24 +131,161 @@ impl SSTableReader {
     /// - The file format is invalid
     /// - The magic number doesn't match
     /// - Index data is corrupted
+    /// - The table was written in a newer format version
+    /// - The table was not written with the bytewise comparator
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
+        Self::open_with_comparator(path, comparator::bytewise())
+    }
+
+    /// Opens an SSTable whose keys are sorted by `comparator`
+    ///
+    /// # Errors
+    ///
+    /// Besides the errors of [`open`](Self::open), returns
+    /// [`Error::InvalidArgument`] if the table records a comparator with a
+    /// different name.
+    pub fn open_with_comparator(
+        path: impl AsRef<Path>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        Self::open_in(&vfs::os(), path, comparator)
+    }
+
+    /// Opens an SSTable through `vfs` whose keys are sorted by `comparator`
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`open_with_comparator`](Self::open_with_comparator).
+    pub fn open_in(
+        vfs: &Arc<dyn Vfs>,
+        path: impl AsRef<Path>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let open = || -> Result<_> {
+            let file = vfs.open(path)?;
+            let mut reader = BufReader::new(file);
+
+            // Read and parse footer
+            let footer = Self::read_footer(&mut reader)?;
+
+            let properties = Self::read_properties(&mut reader, &footer)?;
+            if properties.format_version > FORMAT_VERSION {
+                return Err(Error::Unsupported(format!(
+                    "SSTable format version {} is newer than the supported {}",
+                    properties.format_version, FORMAT_VERSION
+                )));
+            }
 
-        // Read and parse footer
-        let footer = Self::read_footer(&mut reader)?;
+            // Read and parse index
+            let index = Self::read_index(&mut reader, &footer, properties.format_version)?;
 
-        // Read and parse index
-        let index = Self::read_index(&mut reader, &footer)?;
+            if properties.comparator != comparator.name() {
+                return Err(Error::InvalidArgument(format!(
+                    "SSTable was written with comparator {:?}, not {:?}",
+                    properties.comparator,
+                    comparator.name()
+                )));
+            }
+            Ok((reader, footer, index, properties))
+        };
+        let (reader, footer, index, properties) = open().map_err(|e| e.with_path(path))?;
 
         Ok(Self {
+            path: path.to_path_buf(),
             reader,
             footer,
             index,
-            block_cache: BTreeMap::new(),
+            properties,
+            prefix_filter: None,
+            comparator,
+            block_cache: Cache::new(BLOCK_CACHE_CAPACITY).with_shards(1),
+            cache_hits: 0,
+            cache_misses: 0,
+            blocks_scanned: 0,
+            verify_checksums: false,
+            memory: None,
         })
     }
 
+    /// Checks every data block read from the file against its checksum
+    ///
+    /// Blocks already in the cache were read before and aren't checked
+    /// again. Tables written before format version 3 have no checksums
+    /// to check.
+    pub fn with_verify_checksums(mut self, verify: bool) -> Self {
+        self.verify_checksums = verify;
+        self
+    }
+
+    /// Charges the index, bloom filter and cached blocks to `tracker`
+    /// while the reader is open
+    ///
+    /// While the tracker is over its limit, the reader keeps no blocks
+    /// cached beyond those in use.
+    pub fn with_memory_tracker(mut self, tracker: &Arc<MemoryTracker>) -> Self {
+        self.memory = Some(ReaderMemory {
+            metadata: tracker.reserve(MemoryKind::TableReaders, self.metadata_size()),
+            blocks: tracker.reserve(MemoryKind::BlockCache, self.block_cache.usage()),
+        });
+        self
+    }
+
+    /// Returns roughly the bytes the index and bloom filter take in memory
+    fn metadata_size(&self) -> usize {
+        let index: usize = self
+            .index
+            .iter()
+            .map(|entry| std::mem::size_of::<IndexEntry>() + entry.first_key.len())
+            .sum();
+        let filter = match self.prefix_filter {
+            Some(_) => self.footer.bloom_length as usize,
+            None => 0,
+        };
+        index + filter
+    }
+
+    /// Reads every data block, checking its checksum and that its entries
+    /// are in order, and returns the number of entries
+    ///
+    /// Blocks are read from the file even if they are cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Corruption`] if a block doesn't match its checksum
+    /// or holds entries out of order, or the error of a block that can't
+    /// be read or decoded.
+    pub fn verify(&mut self) -> Result<u64> {
+        let verify_checksums = std::mem::replace(&mut self.verify_checksums, true);
+        let result = self.verify_blocks();
+        self.verify_checksums = verify_checksums;
+        result
+    }
+
+    fn verify_blocks(&mut self) -> Result<u64> {
+        let mut count = 0;
+        let mut last: Option<InternalKey> = None;
+        for block_idx in 0..self.index.len() {
+            let block_offset = self.index[block_idx].block_offset;
+            let entries = self.read_block(block_offset)?;
+            for entry in entries {
+                if let Some(last) = &last {
+                    if comparator::compare_internal(&*self.comparator, last, &entry.key).is_ge() {
+                        return Err(Error::Corruption(format!(
+                            "Key {} follows {}",
+                            entry.key, last
+                        ))
+                        .with_path(&self.path)
+                        .at_offset(block_offset));
+                    }
+                }
+                last = Some(entry.key);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     /// Looks up a specific key at a specific timestamp in the SSTable
     ///
     /// Returns the value associated with the exact key-timestamp combination,
@@ -113,29 +307,34 @@ impl SSTableReader {
     ///
     /// Returns an error if an I/O error occurs during lookup
     pub fn get(&mut self, user_key: &Key, timestamp: Timestamp) -> Result<Option<Value>> {
-        // Find the block that might contain this key
-        let block_offset = match self.find_block_for_key(user_key) {
-            Some(offset) => offset,
+        // Find the first block that might contain this key
+        let mut block_idx = match self.find_block_index(user_key) {
+            Some(idx) => idx,
             None => return Ok(None), // Key is outside the range of this SSTable
         };
 
-        // Load the block (from cache or disk)
-        let entries = self.load_block(block_offset)?;
-
         // Create target key for binary search
         let target_key = InternalKey::new(user_key.clone(), timestamp);
 
-        // Use binary search to find exact key match
-        match entries.binary_search_by(|entry| entry.key.cmp(&target_key)) {
-            Ok(index) => {
+        while block_idx < self.index.len() {
+            // Load the block (from cache or disk)
+            let block_offset = self.index[block_idx].block_offset;
+            let (entries, comparator) = self.load_block(block_offset)?;
+
+            // Use binary search to find exact key match
+            match entries.binary_search_by(|entry| {
+                comparator::compare_internal(comparator, &entry.key, &target_key)
+            }) {
                 // Found exact match
-                Ok(Some(entries[index].value.clone()))
-            }
-            Err(_) => {
-                // No exact match found
-                Ok(None)
+                Ok(index) => return Ok(Some(entries[index].value.clone())),
+                // The target would sort inside this block, so it isn't present
+                Err(index) if index < entries.len() => return Ok(None),
+                // The target sorts after this block; its versions may continue
+                Err(_) => block_idx += 1,
             }
         }
+
+        Ok(None)
     }
 
     /// Finds the latest version of a user key
@@ -162,44 +361,129 @@ impl SSTableReader {
         user_key: &Key,
         max_timestamp: Timestamp,
     ) -> Result<Option<(Value, Timestamp, Operation)>> {
-        // Find the block that might contain this key
-        let block_offset = match self.find_block_for_key(user_key) {
-            Some(offset) => offset,
-            None => return Ok(None),
-        };
-
-        // Load the block
-        let entries = self.load_block(block_offset)?;
+        let mut latest = None;
+        self.scan_versions(user_key, |entry| {
+            // Versions are ordered timestamp DESC, so the first one within
+            // our timestamp limit is the latest valid version
+            if entry.key.timestamp <= max_timestamp {
+                latest = Some((entry.value.clone(), entry.key.timestamp, entry.operation));
+                return false;
+            }
+            true
+        })?;
 
-        // Use binary search to find the first entry with matching user_key
-        let start_index = entries.partition_point(|entry| entry.key.user_key < *user_key);
+        Ok(latest)
+    }
 
-        // Linear search through versions (timestamp DESC) for the latest valid version
-        for i in start_index..entries.len() {
-            let entry = &entries[i];
+    /// Finds the latest version of a user key without copying it
+    ///
+    /// Like [`get_latest`](Self::get_latest), but the entry stays in its
+    /// decoded block, which is kept in memory while the returned
+    /// [`PinnedEntry`] is alive, even after the reader is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_key` - The user key to search for
+    /// * `max_timestamp` - Maximum timestamp to consider (for snapshot isolation)
+    pub fn get_latest_pinned(
+        &mut self,
+        user_key: &[u8],
+        max_timestamp: Timestamp,
+    ) -> Result<Option<PinnedEntry>> {
+        let Some(first_block) = self.find_block_index(user_key) else {
+            return Ok(None);
+        };
 
-            // Stop if we've moved to a different user_key
-            if entry.key.user_key != *user_key {
-                break;
+        for block_idx in first_block..self.index.len() {
+            let block_offset = self.index[block_idx].block_offset;
+            let (block, comparator) = self.load_block(block_offset)?;
+            let start_index = block
+                .partition_point(|entry| comparator.compare(&entry.key.user_key, user_key).is_lt());
+
+            // Versions are ordered timestamp DESC, and may continue into
+            // the next block if none of this one's is visible
+            let found = block[start_index..]
+                .iter()
+                .position(|entry| {
+                    entry.key.user_key != user_key || entry.key.timestamp <= max_timestamp
+                })
+                .map(|offset| start_index + offset);
+            match found {
+                Some(index) if block[index].key.user_key == user_key => {
+                    return Ok(Some(PinnedEntry { block, index }));
+                }
+                Some(_) => return Ok(None),
+                None => {}
             }
+        }
 
-            // Check if this version is within our timestamp limit
+        Ok(None)
+    }
+
+    /// Returns every version of a user key visible at `max_timestamp`
+    ///
+    /// Versions are returned newest first as (value, timestamp, operation),
+    /// matching [`MemTable::versions`](crate::memtable::MemTable::versions),
+    /// so they can be chained after newer sources and passed to
+    /// [`merge::resolve`](crate::merge::resolve).
+    ///
+    /// # Arguments
+    ///
+    /// * `user_key` - The user key to search for
+    /// * `max_timestamp` - Maximum timestamp to consider (for snapshot isolation)
+    pub fn get_versions(
+        &mut self,
+        user_key: &Key,
+        max_timestamp: Timestamp,
+    ) -> Result<Vec<(Value, Timestamp, Operation)>> {
+        let mut versions = Vec::new();
+        self.scan_versions(user_key, |entry| {
             if entry.key.timestamp <= max_timestamp {
-                return Ok(Some((
-                    entry.value.clone(),
-                    entry.key.timestamp,
-                    entry.operation,
-                )));
+                versions.push((entry.value.clone(), entry.key.timestamp, entry.operation));
+            }
+            true
+        })?;
+
+        Ok(versions)
+    }
+
+    /// Visits the versions of a user key in order (timestamp DESC)
+    ///
+    /// The versions of one key may span several blocks, so this continues
+    /// into following blocks until it reaches a different key. Visiting
+    /// stops early when `visit` returns false.
+    fn scan_versions(
+        &mut self,
+        user_key: &Key,
+        mut visit: impl FnMut(&SSTableEntry) -> bool,
+    ) -> Result<()> {
+        let Some(first_block) = self.find_block_index(user_key) else {
+            return Ok(());
+        };
+
+        for block_idx in first_block..self.index.len() {
+            let block_offset = self.index[block_idx].block_offset;
+            let (entries, comparator) = self.load_block(block_offset)?;
+
+            // Use binary search to find the first entry with matching user_key
+            let start_index = entries
+                .partition_point(|entry| comparator.compare(&entry.key.user_key, user_key).is_lt());
+
+            for entry in &entries[start_index..] {
+                // Stop if we've moved to a different user_key
+                if entry.key.user_key != *user_key || !visit(entry) {
+                    return Ok(());
+                }
             }
         }
 
-        Ok(None)
+        Ok(())
     }
 
     /// Creates an iterator over all entries in the SSTable
     ///
     /// The iterator yields entries in sorted order (user_key ASC, timestamp DESC).
-    pub fn iter(&mut self) -> Result<SSTableIterator> {
+    pub fn iter(&mut self) -> Result<SSTableIterator<'_>> {
         SSTableIterator::new(self)
     }
 
@@ -213,10 +497,102 @@ impl SSTableReader {
         &mut self,
         start_key: Option<&Key>,
         end_key: Option<&Key>,
-    ) -> Result<SSTableIterator> {
+    ) -> Result<SSTableIterator<'_>> {
         SSTableIterator::new_range(self, start_key, end_key)
     }
 
+    /// Consumes the reader into a cursor that moves in both directions
+    ///
+    /// See [`SSTableCursor`]; it starts out unpositioned.
+    pub fn into_cursor(self) -> SSTableCursor {
+        SSTableCursor {
+            reader: self,
+            block: None,
+            entry_idx: 0,
+        }
+    }
+
+    /// Returns the properties recorded when the table was written
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// Returns false only if no key in the table has `prefix` as the
+    /// prefix `extractor` gives it
+    ///
+    /// Tables written with a differently named extractor, or none, may
+    /// always contain the prefix, as may prefixes the extractor would not
+    /// return for themselves.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bloom filter can't be read or is corrupted.
+    pub fn may_contain_prefix(
+        &mut self,
+        extractor: &dyn PrefixExtractor,
+        prefix: &[u8],
+    ) -> Result<bool> {
+        if self.properties.prefix_extractor.as_deref() != Some(extractor.name())
+            || extractor.prefix(prefix) != Some(prefix)
+        {
+            return Ok(true);
+        }
+        let filter = match self.prefix_filter.take() {
+            Some(filter) => filter,
+            None => self
+                .read_prefix_filter()
+                .map_err(|e| e.with_path(&self.path))?,
+        };
+        let may_contain = filter.may_contain(prefix);
+        self.prefix_filter = Some(filter);
+        let size = self.metadata_size();
+        if let Some(memory) = &mut self.memory {
+            memory.metadata.resize(size);
+        }
+        Ok(may_contain)
+    }
+
+    /// Returns the offsets of the first data block and of the end of the
+    /// last, where the index block begins
+    pub fn data_range(&self) -> std::ops::Range<u64> {
+        let end = self.footer.index_offset;
+        self.index.first().map_or(end, |entry| entry.block_offset)..end
+    }
+
+    /// Returns roughly where in the file the entries of keys from
+    /// `user_key` on begin
+    ///
+    /// Only the index is consulted: this is the offset of the first data
+    /// block whose first key is `user_key` or after it, or the end of the
+    /// data if there is none. The block before it is counted wholly as
+    /// keys before `user_key`.
+    pub fn approximate_offset_of(&self, user_key: &[u8]) -> u64 {
+        let blocks_before = self
+            .index
+            .partition_point(|entry| self.comparator.compare(&entry.first_key, user_key).is_lt());
+        self.index
+            .get(blocks_before)
+            .map_or(self.footer.index_offset, |entry| entry.block_offset)
+    }
+
+    /// Returns the first user key and the size in bytes of each data
+    /// block, in key order
+    ///
+    /// Like [`approximate_offset_of`](Self::approximate_offset_of), this
+    /// only consults the index.
+    pub fn data_blocks(&self) -> impl Iterator<Item = (&[u8], u64)> + '_ {
+        let ends = self
+            .index
+            .iter()
+            .skip(1)
+            .map(|entry| entry.block_offset)
+            .chain(std::iter::once(self.footer.index_offset));
+        self.index
+            .iter()
+            .zip(ends)
+            .map(|(entry, end)| (entry.first_key.as_slice(), end - entry.block_offset))
+    }
+
     /// Returns metadata about the SSTable
     pub fn info(&self) -> SSTableReaderInfo {
         SSTableReaderInfo {
@@ -226,7 +602,7 @@ impl SSTableReader {
     }
 
     /// Reads the footer from the end of the file
-    fn read_footer(reader: &mut BufReader<File>) -> Result<Footer> {
+    fn read_footer(reader: &mut FileReader) -> Result<Footer> {
         // Seek to the start of the footer (file_size - FOOTER_SIZE)
         let file_size = reader.seek(SeekFrom::End(0))?;
         if file_size < FOOTER_SIZE as u64 {
@@ -246,146 +622,190 @@ impl SSTableReader {
     }
 
     /// Reads and parses the index block
-    fn read_index(reader: &mut BufReader<File>, footer: &Footer) -> Result<Vec<IndexEntry>> {
-        // Seek to index block
+    fn read_index(
+        reader: &mut FileReader,
+        footer: &Footer,
+        format_version: u32,
+    ) -> Result<Vec<IndexEntry>> {
+        let len = footer.index_length as usize;
+        let mut block = BufferPool::shared().checkout(len);
         reader.seek(SeekFrom::Start(footer.index_offset))?;
+        block.read_exact_from(reader, len)?;
 
-        // Read entry count
-        let mut count_bytes = [0u8; 4];
-        reader.read_exact(&mut count_bytes)?;
-        let entry_count = u32::from_le_bytes(count_bytes) as usize;
-
-        let mut index_entries = Vec::with_capacity(entry_count);
-
-        // Read each index entry
-        for _ in 0..entry_count {
-            // Read block offset
-            let mut offset_bytes = [0u8; 8];
-            reader.read_exact(&mut offset_bytes)?;
-            let block_offset = u64::from_le_bytes(offset_bytes);
-
-            // Read key length
-            let mut key_len_bytes = [0u8; 4];
-            reader.read_exact(&mut key_len_bytes)?;
-            let key_len = u32::from_le_bytes(key_len_bytes) as usize;
+        verify_block(&block, format_version)?;
+        decode_index_block(&block, format_version)
+    }
 
-            // Read key
-            let mut key = vec![0u8; key_len];
-            reader.read_exact(&mut key)?;
+    /// Reads and decodes the bloom filter
+    fn read_prefix_filter(&mut self) -> Result<BloomFilter> {
+        let len = self.footer.bloom_length as usize;
+        let mut bytes = BufferPool::shared().checkout(len);
+        self.reader
+            .seek(SeekFrom::Start(self.footer.bloom_offset))?;
+        bytes.read_exact_from(&mut self.reader, len)?;
+        BloomFilter::decode(&bytes)
+    }
 
-            index_entries.push(IndexEntry::new(block_offset, key));
+    /// Reads the properties block between the bloom filter and the footer
+    fn read_properties(reader: &mut FileReader, footer: &Footer) -> Result<TableProperties> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        let start = footer.bloom_offset.saturating_add(footer.bloom_length);
+        let end = file_size - FOOTER_SIZE as u64;
+        if start > end {
+            return Err(Error::InvalidFormat(
+                "Bloom filter overlaps the footer".to_string(),
+            ));
         }
 
-        // Read and verify checksum (placeholder for now)
-        let mut checksum_bytes = [0u8; 4];
-        reader.read_exact(&mut checksum_bytes)?;
-        let _checksum = u32::from_le_bytes(checksum_bytes);
-        // TODO: Verify checksum
-
-        Ok(index_entries)
+        reader.seek(SeekFrom::Start(start))?;
+        let len = (end - start) as usize;
+        let mut bytes = BufferPool::shared().checkout(len);
+        bytes.read_exact_from(reader, len)?;
+        TableProperties::from_bytes(&bytes)
     }
 
-    /// Finds the block offset that might contain the given user key
-    fn find_block_for_key(&self, user_key: &Key) -> Option<u64> {
+    /// Finds the index of the first block that might contain the given user key
+    ///
+    /// A block's index key is its first user key, and the versions of one
+    /// key may continue from the previous block. The first candidate is
+    /// therefore the last block whose first key is strictly smaller than
+    /// `user_key`, or the first block if there is none.
+    fn find_block_index(&self, user_key: &[u8]) -> Option<usize> {
         if self.index.is_empty() {
             return None;
         }
 
-        // Find the last block whose first key is <= user_key
-        let mut result = None;
-        for entry in &self.index {
-            if entry.first_key <= *user_key {
-                result = Some(entry.block_offset);
-            } else {
-                break;
-            }
-        }
+        let blocks_before = self
+            .index
+            .partition_point(|entry| self.comparator.compare(&entry.first_key, user_key).is_lt());
+        Some(blocks_before.saturating_sub(1))
+    }
 
-        // If no block's first key is <= user_key, the key might be in the first block
-        // (if it's smaller than the first key in the file)
-        result.or(Some(self.index[0].block_offset))
+    /// Returns how many block loads for lookups were served from the cache
+    pub fn block_cache_hits(&self) -> u64 {
+        self.cache_hits
     }
 
-    /// Loads a data block, using cache if available
-    fn load_block(&mut self, block_offset: u64) -> Result<&Vec<SSTableEntry>> {
-        if !self.block_cache.contains_key(&block_offset) {
-            let entries = self.read_block(block_offset)?;
-            self.block_cache.insert(block_offset, entries);
-        }
-        Ok(self.block_cache.get(&block_offset).unwrap())
+    /// Returns how many block loads for lookups had to read the file
+    pub fn block_cache_misses(&self) -> u64 {
+        self.cache_misses
     }
 
-    /// Reads a data block from disk
-    fn read_block(&mut self, block_offset: u64) -> Result<Vec<SSTableEntry>> {
-        // Seek to block
-        self.reader.seek(SeekFrom::Start(block_offset))?;
+    /// Returns how many blocks iterators over the table read from the
+    /// file, which they do without the cache
+    pub fn blocks_scanned(&self) -> u64 {
+        self.blocks_scanned
+    }
 
-        // Read entry count
-        let mut count_bytes = [0u8; 4];
-        self.reader.read_exact(&mut count_bytes)?;
-        let entry_count = u32::from_le_bytes(count_bytes) as usize;
-
-        let mut entries = Vec::with_capacity(entry_count);
-
-        // Read each entry
-        for _ in 0..entry_count {
-            let entry = self.read_entry()?;
-            entries.push(entry);
-        }
-
-        // Read and verify checksum (placeholder for now)
-        let mut checksum_bytes = [0u8; 4];
-        self.reader.read_exact(&mut checksum_bytes)?;
-        let _checksum = u32::from_le_bytes(checksum_bytes);
-        // TODO: Verify checksum
-
-        Ok(entries)
-    }
-
-    /// Reads a single entry from the current position
-    fn read_entry(&mut self) -> Result<SSTableEntry> {
-        // Read key length
-        let mut key_len_bytes = [0u8; 4];
-        self.reader.read_exact(&mut key_len_bytes)?;
-        let key_len = u32::from_le_bytes(key_len_bytes) as usize;
-
-        // Read value length
-        let mut value_len_bytes = [0u8; 4];
-        self.reader.read_exact(&mut value_len_bytes)?;
-        let value_len = u32::from_le_bytes(value_len_bytes) as usize;
-
-        // Read timestamp
-        let mut timestamp_bytes = [0u8; 8];
-        self.reader.read_exact(&mut timestamp_bytes)?;
-        let timestamp = u64::from_le_bytes(timestamp_bytes);
-
-        // Read operation
-        let mut op_byte = [0u8; 1];
-        self.reader.read_exact(&mut op_byte)?;
-        let operation = match op_byte[0] {
-            0 => Operation::Put,
-            1 => Operation::Delete,
-            _ => {
-                return Err(Error::InvalidFormat(format!(
-                    "Invalid operation byte: {}",
-                    op_byte[0]
-                )))
+    /// Reads a data block for an iterator
+    fn scan_block(&mut self, block_offset: u64) -> Result<Vec<SSTableEntry>> {
+        self.blocks_scanned += 1;
+        self.read_block(block_offset)
+    }
+
+    /// Loads a data block, using cache if available
+    ///
+    /// Returns the comparator alongside for convenience; the block stays
+    /// pinned in the cache until its handle is dropped.
+    fn load_block(&mut self, block_offset: u64) -> Result<(BlockHandle, &dyn Comparator)> {
+        let block = match self.block_cache.get(&block_offset) {
+            Some(block) => {
+                self.cache_hits += 1;
+                block
+            }
+            None => {
+                self.cache_misses += 1;
+                let entries = self.read_block(block_offset)?;
+                let charge = self.block_len(block_offset)? as usize;
+                let block = self.block_cache.insert(block_offset, entries, charge);
+                if let Some(memory) = &mut self.memory {
+                    if memory.blocks.tracker().is_over_limit() {
+                        self.block_cache.clear();
+                    }
+                    memory.blocks.resize(self.block_cache.usage());
+                }
+                block
             }
         };
+        Ok((block, &*self.comparator))
+    }
 
-        // Read key
-        let mut user_key = vec![0u8; key_len];
-        self.reader.read_exact(&mut user_key)?;
+    /// Returns the index entries, one per data block in file order
+    pub(super) fn index(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    /// Returns the comparator the keys are ordered by
+    pub(super) fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Returns the encoded length of the data block at `block_offset`
+    fn block_len(&self, block_offset: u64) -> Result<u64> {
+        // A block ends where the next one, or the index, begins
+        let next = self
+            .index
+            .partition_point(|entry| entry.block_offset <= block_offset);
+        let end = self
+            .index
+            .get(next)
+            .map_or(self.footer.index_offset, |entry| entry.block_offset);
+        end.checked_sub(block_offset).ok_or_else(|| {
+            Error::InvalidFormat(format!("Data block at {} overlaps the index", block_offset))
+        })
+    }
+
+    /// Reads `len` bytes at `offset` as they are in the file
+    pub(super) fn read_bytes(&mut self, offset: u64, len: u64) -> Result<PooledBuffer> {
+        let read = |reader: &mut FileReader| -> Result<PooledBuffer> {
+            let mut bytes = BufferPool::shared().checkout(len as usize);
+            reader.seek(SeekFrom::Start(offset))?;
+            bytes.read_exact_from(reader, len as usize)?;
+            Ok(bytes)
+        };
+        read(&mut self.reader).map_err(|e| e.with_path(&self.path).at_offset(offset))
+    }
+
+    /// Reads the data block at `block_offset` as it is in the file,
+    /// checked against its checksum
+    pub(super) fn read_block_bytes(&mut self, block_offset: u64) -> Result<PooledBuffer> {
+        let len = self
+            .block_len(block_offset)
+            .map_err(|e| e.with_path(&self.path))?;
+        let block = self.read_bytes(block_offset, len)?;
+        verify_block(&block, self.properties.format_version)
+            .map_err(|e| e.with_path(&self.path).at_offset(block_offset))?;
+        Ok(block)
+    }
 
-        // Read value
-        let mut value = vec![0u8; value_len];
-        self.reader.read_exact(&mut value)?;
+    /// Reads a data block from disk
+    pub(super) fn read_block(&mut self, block_offset: u64) -> Result<Vec<SSTableEntry>> {
+        self.read_block_entries(block_offset)
+            .map_err(|e| e.with_path(&self.path).at_offset(block_offset))
+    }
+
+    fn read_block_entries(&mut self, block_offset: u64) -> Result<Vec<SSTableEntry>> {
+        let len = self.block_len(block_offset)?;
+        let mut block = BufferPool::shared().checkout(len as usize);
+        self.reader.seek(SeekFrom::Start(block_offset))?;
+        block.read_exact_from(&mut self.reader, len as usize)?;
 
-        let internal_key = InternalKey::new(user_key, timestamp);
-        Ok(SSTableEntry::new(internal_key, value, operation))
+        if self.verify_checksums {
+            verify_block(&block, self.properties.format_version)?;
+        }
+        decode_data_block(&block, self.properties.format_version)
     }
 }
 
+/// Checks `block` against its checksum, reporting both checksums if they
+/// don't match
+fn verify_block(block: &[u8], format_version: u32) -> Result<()> {
+    verify_block_checksum(block, format_version).map_err(|e| match checksum_mismatch(block) {
+        Some((stored, actual)) => e.with_checksums(stored, actual),
+        None => e,
+    })
+}
+
 /// Iterator over SSTable entries
 pub struct SSTableIterator<'a> {
     reader: &'a mut SSTableReader,
@@ -421,14 +841,8 @@ impl<'a> SSTableIterator<'a> {
 
         // Find the starting block if we have a start key
         if let Some(start) = start_key {
-            if let Some(block_offset) = iter.reader.find_block_for_key(start) {
-                // Find the index of this block
-                for (idx, entry) in iter.reader.index.iter().enumerate() {
-                    if entry.block_offset == block_offset {
-                        iter.current_block_idx = idx;
-                        break;
-                    }
-                }
+            if let Some(block_idx) = iter.reader.find_block_index(start) {
+                iter.current_block_idx = block_idx;
             }
         }
 
@@ -443,7 +857,7 @@ impl<'a> SSTableIterator<'a> {
 
         if self.current_block_entries.is_none() {
             let block_offset = self.reader.index[self.current_block_idx].block_offset;
-            let entries = self.reader.read_block(block_offset)?;
+            let entries = self.reader.scan_block(block_offset)?;
             self.current_block_entries = Some(entries);
             self.current_entry_idx = 0;
         }
@@ -483,14 +897,15 @@ impl<'a> Iterator for SSTableIterator<'a> {
             self.current_entry_idx += 1;
 
             // Check range constraints
+            let comparator = &*self.reader.comparator;
             if let Some(ref start) = self.start_key {
-                if entry.key.user_key < *start {
+                if comparator.compare(&entry.key.user_key, start).is_lt() {
                     continue;
                 }
             }
 
             if let Some(ref end) = self.end_key {
-                if entry.key.user_key >= *end {
+                if comparator.compare(&entry.key.user_key, end).is_ge() {
                     return None; // Reached end of range
                 }
             }
@@ -500,6 +915,265 @@ impl<'a> Iterator for SSTableIterator<'a> {
     }
 }
 
+impl IntoIterator for SSTableReader {
+    type Item = Result<SSTableEntry>;
+    type IntoIter = SSTableIntoIter;
+
+    /// Consumes the reader, iterating over all of its entries
+    ///
+    /// Unlike [`SSTableReader::iter`], the iterator owns the reader, so many
+    /// tables can be iterated side by side, e.g. when merging them during
+    /// compaction. Blocks are read one at a time and bypass the block cache.
+    fn into_iter(self) -> SSTableIntoIter {
+        SSTableIntoIter {
+            reader: self,
+            next_block_idx: 0,
+            current_block: Vec::new().into_iter(),
+        }
+    }
+}
+
+/// Owning iterator over all entries of an SSTable
+///
+/// Created by [`SSTableReader::into_iter`]. Yields entries in sorted order
+/// (user_key ASC, timestamp DESC).
+pub struct SSTableIntoIter {
+    reader: SSTableReader,
+    next_block_idx: usize,
+    current_block: std::vec::IntoIter<SSTableEntry>,
+}
+
+impl Iterator for SSTableIntoIter {
+    type Item = Result<SSTableEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.current_block.next() {
+                return Some(Ok(entry));
+            }
+
+            let block_offset = self.reader.index.get(self.next_block_idx)?.block_offset;
+            self.next_block_idx += 1;
+            match self.reader.scan_block(block_offset) {
+                Ok(entries) => self.current_block = entries.into_iter(),
+                Err(e) => {
+                    // Stop after reporting the error
+                    self.next_block_idx = self.reader.index.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// An SSTable entry read in place from its decoded block
+///
+/// Returned by [`SSTableReader::get_latest_pinned`]. The whole block stays
+/// in memory while the entry is held.
+pub struct PinnedEntry {
+    block: BlockHandle,
+    index: usize,
+}
+
+impl PinnedEntry {
+    /// Returns the entry
+    pub fn entry(&self) -> &SSTableEntry {
+        &self.block[self.index]
+    }
+}
+
+impl std::fmt::Debug for PinnedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PinnedEntry")
+            .field("block_offset", self.block.key())
+            .field("key", &self.entry().key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Cursor over the entries of an SSTable that seeks and moves both ways
+///
+/// Created by [`SSTableReader::into_cursor`]. Entries are in internal key
+/// order (user_key ASC, timestamp DESC), and blocks are loaded through the
+/// reader's block cache, the current one staying pinned while the cursor
+/// is in it. After a seek or a move the cursor is either at an entry or,
+/// past either end, at none.
+pub struct SSTableCursor {
+    reader: SSTableReader,
+    /// Index and entries of the block the cursor is in, or `None` when it
+    /// is not at an entry
+    block: Option<(usize, BlockHandle)>,
+    /// Position of the current entry within the block
+    entry_idx: usize,
+}
+
+impl SSTableCursor {
+    /// Returns the reader, e.g. for its block cache counters
+    pub fn reader(&self) -> &SSTableReader {
+        &self.reader
+    }
+
+    /// Returns the entry the cursor is at, if any
+    pub fn entry(&self) -> Option<&SSTableEntry> {
+        self.block
+            .as_ref()
+            .map(|(_, entries)| &entries[self.entry_idx])
+    }
+
+    /// Moves to the first entry
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        if self.reader.index.is_empty() {
+            self.block = None;
+            return Ok(());
+        }
+        let block = self.load(0)?;
+        self.position_at_or_after(0, block, 0)
+    }
+
+    /// Moves to the last entry
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let Some(block_idx) = self.reader.index.len().checked_sub(1) else {
+            self.block = None;
+            return Ok(());
+        };
+        let block = self.load(block_idx)?;
+        let end = block.len();
+        self.position_before(block_idx, block, end)
+    }
+
+    /// Moves to the first entry at or after `target`
+    pub fn seek(&mut self, target: &InternalKey) -> Result<()> {
+        let Some(mut block_idx) = self.reader.find_block_index(&target.user_key) else {
+            self.block = None;
+            return Ok(());
+        };
+        // The candidate block may end before the target, when the versions
+        // of its key reach into the blocks after it
+        while block_idx < self.reader.index.len() {
+            let block = self.load(block_idx)?;
+            let comparator = &*self.reader.comparator;
+            let start = block.partition_point(|entry| {
+                comparator::compare_internal(comparator, &entry.key, target).is_lt()
+            });
+            if start < block.len() {
+                self.block = Some((block_idx, block));
+                self.entry_idx = start;
+                return Ok(());
+            }
+            block_idx += 1;
+        }
+        self.block = None;
+        Ok(())
+    }
+
+    /// Moves to the last entry at or before `target`
+    pub fn seek_for_prev(&mut self, target: &InternalKey) -> Result<()> {
+        // Blocks after the last one starting at or before the target's user
+        // key only hold larger keys
+        let comparator = &*self.reader.comparator;
+        let blocks = self.reader.index.partition_point(|entry| {
+            comparator
+                .compare(&entry.first_key, &target.user_key)
+                .is_le()
+        });
+        // Versions of the target's key after it may reach back into
+        // earlier blocks
+        for block_idx in (0..blocks).rev() {
+            let block = self.load(block_idx)?;
+            let comparator = &*self.reader.comparator;
+            let end = block.partition_point(|entry| {
+                comparator::compare_internal(comparator, &entry.key, target).is_le()
+            });
+            if end > 0 {
+                self.block = Some((block_idx, block));
+                self.entry_idx = end - 1;
+                return Ok(());
+            }
+        }
+        self.block = None;
+        Ok(())
+    }
+
+    /// Moves to the next entry, or past the end after the last one
+    ///
+    /// Does nothing when the cursor is not at an entry.
+    #[allow(clippy::should_implement_trait)] // Moves the cursor, yields nothing
+    pub fn next(&mut self) -> Result<()> {
+        if let Some((block_idx, block)) = self.block.take() {
+            self.position_at_or_after(block_idx, block, self.entry_idx + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Moves to the previous entry, or past the start before the first one
+    ///
+    /// Does nothing when the cursor is not at an entry.
+    pub fn prev(&mut self) -> Result<()> {
+        if let Some((block_idx, block)) = self.block.take() {
+            self.position_before(block_idx, block, self.entry_idx)?;
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, block_idx: usize) -> Result<BlockHandle> {
+        let block_offset = self.reader.index[block_idx].block_offset;
+        Ok(self.reader.load_block(block_offset)?.0)
+    }
+
+    /// Positions at entry `start` of a block, or at the first entry of a
+    /// later block if the block has fewer entries
+    fn position_at_or_after(
+        &mut self,
+        mut block_idx: usize,
+        mut block: BlockHandle,
+        mut start: usize,
+    ) -> Result<()> {
+        while start >= block.len() {
+            block_idx += 1;
+            if block_idx >= self.reader.index.len() {
+                self.block = None;
+                return Ok(());
+            }
+            block = self.load(block_idx)?;
+            start = 0;
+        }
+        self.block = Some((block_idx, block));
+        self.entry_idx = start;
+        Ok(())
+    }
+
+    /// Positions at the entry before entry `end` of a block, or at the last
+    /// entry of an earlier block if `end` is 0
+    fn position_before(
+        &mut self,
+        mut block_idx: usize,
+        mut block: BlockHandle,
+        mut end: usize,
+    ) -> Result<()> {
+        while end == 0 {
+            if block_idx == 0 {
+                self.block = None;
+                return Ok(());
+            }
+            block_idx -= 1;
+            block = self.load(block_idx)?;
+            end = block.len();
+        }
+        self.block = Some((block_idx, block));
+        self.entry_idx = end - 1;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SSTableCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SSTableCursor")
+            .field("path", &self.reader.path)
+            .field("entry", &self.entry().map(|entry| &entry.key))
+            .finish()
+    }
+}
+
 /// Metadata about an SSTable from reader perspective
 #[derive(Debug, Clone)]
 pub struct SSTableReaderInfo {
@@ -614,11 +1288,11 @@ mod tests {
         let (_temp_dir, path, test_data) = create_test_sstable();
 
         let mut reader = SSTableReader::open(&path).unwrap();
-        let mut iter = reader.iter().unwrap();
+        let iter = reader.iter().unwrap();
 
         // Collect all entries
         let mut entries = Vec::new();
-        while let Some(entry_result) = iter.next() {
+        for entry_result in iter {
             entries.push(entry_result.unwrap());
         }
 
@@ -640,10 +1314,10 @@ mod tests {
         // Test range from key1 to key3 (exclusive)
         let start_key = b"key1".to_vec();
         let end_key = b"key3".to_vec();
-        let mut iter = reader.range_iter(Some(&start_key), Some(&end_key)).unwrap();
+        let iter = reader.range_iter(Some(&start_key), Some(&end_key)).unwrap();
 
         let mut entries = Vec::new();
-        while let Some(entry_result) = iter.next() {
+        for entry_result in iter {
             entries.push(entry_result.unwrap());
         }
 
@@ -700,6 +1374,51 @@ mod tests {
             .contains("Invalid magic number"));
     }
 
+    #[test]
+    fn test_sstable_reader_verifies_data_checksums_on_request() {
+        let (_temp_dir, path, _) = create_test_sstable();
+        let mut file = std::fs::read(&path).unwrap();
+        let at = file
+            .windows(6)
+            .position(|window| window == b"value3")
+            .unwrap();
+        file[at] = b'V';
+        std::fs::write(&path, &file).unwrap();
+        let key = b"key3".to_vec();
+
+        // Unchecked reads return the damaged value
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.get(&key, 150).unwrap(), Some(b"Value3".to_vec()));
+
+        let mut reader = SSTableReader::open(&path)
+            .unwrap()
+            .with_verify_checksums(true);
+        let err = reader.get(&key, 150).unwrap_err();
+        assert!(matches!(err.root(), Error::Corruption(_)), "{}", err);
+        assert_eq!(err.context().unwrap().offset, Some(0));
+
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert!(reader.get(&key, 150).is_ok());
+        assert!(matches!(
+            reader.verify().unwrap_err().root(),
+            Error::Corruption(_)
+        ));
+    }
+
+    #[test]
+    fn test_sstable_reader_rejects_a_damaged_index() {
+        let (_temp_dir, path, test_data) = create_test_sstable();
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.verify().unwrap(), test_data.len() as u64);
+
+        let index_offset = reader.info().footer.index_offset as usize;
+        let mut file = std::fs::read(&path).unwrap();
+        file[index_offset + 4] ^= 0x01;
+        std::fs::write(&path, &file).unwrap();
+        let err = SSTableReader::open(&path).unwrap_err();
+        assert!(matches!(err.root(), Error::Corruption(_)), "{}", err);
+    }
+
     #[test]
     fn test_sstable_reader_binary_search_performance() {
         let temp_dir = TempDir::new().unwrap();
@@ -740,4 +1459,325 @@ mod tests {
         let result = reader.get(&b"key_999999".to_vec(), 100).unwrap();
         assert_eq!(result, None);
     }
+
+    #[test]
+    fn test_sstable_reader_charges_its_memory_to_a_tracker() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("tracked.sst");
+        let mut writer = SSTableWriter::with_block_size(&path, 256).unwrap();
+        for i in 0..100u64 {
+            let key = InternalKey::new(format!("key_{:04}", i).into_bytes(), i);
+            writer.add(key, vec![b'v'; 32], Operation::Put).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let tracker = Arc::new(MemoryTracker::default());
+        let mut reader = SSTableReader::open(&path)
+            .unwrap()
+            .with_memory_tracker(&tracker);
+        assert!(tracker.usage(MemoryKind::TableReaders) > 0);
+        assert_eq!(tracker.usage(MemoryKind::BlockCache), 0);
+
+        reader.get(&b"key_0000".to_vec(), 0).unwrap();
+        reader.get(&b"key_0099".to_vec(), 99).unwrap();
+        let cached = tracker.usage(MemoryKind::BlockCache);
+        assert!(cached > 0);
+        assert_eq!(cached, reader.block_cache.usage());
+
+        drop(reader);
+        assert_eq!(tracker.total(), 0);
+
+        // Over the limit, blocks are read but not kept
+        let tracker = Arc::new(MemoryTracker::new(1));
+        let mut reader = SSTableReader::open(&path)
+            .unwrap()
+            .with_memory_tracker(&tracker);
+        assert_eq!(
+            reader.get(&b"key_0050".to_vec(), 50).unwrap(),
+            Some(vec![b'v'; 32])
+        );
+        assert!(reader.block_cache.is_empty());
+        assert_eq!(tracker.usage(MemoryKind::BlockCache), 0);
+    }
+
+    #[test]
+    fn test_sstable_reader_versions_spanning_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("spanning.sst");
+
+        // Tiny blocks so that the versions of "key" are split across blocks
+        let mut writer = SSTableWriter::with_block_size(&path, 64).unwrap();
+        writer
+            .add(
+                InternalKey::new(b"a".to_vec(), 1),
+                b"a".to_vec(),
+                Operation::Put,
+            )
+            .unwrap();
+        for ts in (1..=20u64).rev() {
+            let operation = if ts == 1 {
+                Operation::Put
+            } else {
+                Operation::Merge
+            };
+            writer
+                .add(
+                    InternalKey::new(b"key".to_vec(), ts),
+                    ts.to_le_bytes().to_vec(),
+                    operation,
+                )
+                .unwrap();
+        }
+        writer
+            .add(
+                InternalKey::new(b"z".to_vec(), 1),
+                b"z".to_vec(),
+                Operation::Put,
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert!(reader.info().index_entries > 2);
+
+        // Newest version lives in an earlier block than the older ones
+        let (value, timestamp, operation) =
+            reader.get_latest(&b"key".to_vec(), 1000).unwrap().unwrap();
+        assert_eq!(value, 20u64.to_le_bytes().to_vec());
+        assert_eq!(timestamp, 20);
+        assert_eq!(operation, Operation::Merge);
+
+        for ts in 1..=20u64 {
+            let value = reader.get(&b"key".to_vec(), ts).unwrap();
+            assert_eq!(value, Some(ts.to_le_bytes().to_vec()));
+        }
+
+        let versions = reader.get_versions(&b"key".to_vec(), 10).unwrap();
+        let timestamps: Vec<_> = versions.iter().map(|(_, ts, _)| *ts).collect();
+        assert_eq!(timestamps, (1..=10).rev().collect::<Vec<_>>());
+        assert_eq!(versions.last().unwrap().2, Operation::Put);
+
+        assert!(reader
+            .get_versions(&b"missing".to_vec(), 1000)
+            .unwrap()
+            .is_empty());
+
+        // Pinned lookups find the same versions, in whichever block
+        for ts in 1..=20u64 {
+            let pinned = reader.get_latest_pinned(b"key", ts).unwrap().unwrap();
+            assert_eq!(pinned.entry().key.timestamp, ts);
+            assert_eq!(pinned.entry().value, ts.to_le_bytes().to_vec());
+        }
+        assert!(reader.get_latest_pinned(b"key", 0).unwrap().is_none());
+        assert!(reader
+            .get_latest_pinned(b"missing", 1000)
+            .unwrap()
+            .is_none());
+        let z = reader.get_latest_pinned(b"z", 1000).unwrap().unwrap();
+        assert_eq!(z.entry().value, b"z".to_vec());
+    }
+
+    #[test]
+    fn test_sstable_reader_into_iter_matches_iter() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("owned.sst");
+
+        let mut writer = SSTableWriter::with_block_size(&path, 128).unwrap();
+        for i in 0..100u64 {
+            let key = InternalKey::new(format!("key_{:03}", i).into_bytes(), i);
+            writer
+                .add(key, i.to_le_bytes().to_vec(), Operation::Put)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut reader = SSTableReader::open(&path).unwrap();
+        let borrowed: Vec<_> = reader.iter().unwrap().map(|e| e.unwrap()).collect();
+        let owned: Vec<_> = reader.into_iter().map(|e| e.unwrap()).collect();
+
+        assert_eq!(owned.len(), 100);
+        assert_eq!(owned, borrowed);
+    }
+
+    #[test]
+    fn test_sstable_cursor_seeks_and_moves_both_ways() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("cursor.sst");
+
+        // Small blocks, and keys with several versions that span them
+        let mut writer = SSTableWriter::with_block_size(&path, 96).unwrap();
+        let mut expected = Vec::new();
+        for i in 0..40u64 {
+            for ts in (1..=i % 4 + 1).rev() {
+                let key = InternalKey::new(format!("key_{:03}", i * 2).into_bytes(), ts * 10);
+                writer
+                    .add(key.clone(), ts.to_le_bytes().to_vec(), Operation::Put)
+                    .unwrap();
+                expected.push(key);
+            }
+        }
+        writer.finish().unwrap();
+
+        let mut cursor = SSTableReader::open(&path).unwrap().into_cursor();
+        assert!(cursor.reader().info().index_entries > 10);
+        let key_at = |cursor: &SSTableCursor| cursor.entry().map(|entry| entry.key.clone());
+        assert_eq!(key_at(&cursor), None);
+
+        // Walk forward and back over everything
+        cursor.seek_to_first().unwrap();
+        let mut forward = Vec::new();
+        while let Some(key) = key_at(&cursor) {
+            forward.push(key);
+            cursor.next().unwrap();
+        }
+        assert_eq!(forward, expected);
+        cursor.seek_to_last().unwrap();
+        let mut backward = Vec::new();
+        while let Some(key) = key_at(&cursor) {
+            backward.push(key);
+            cursor.prev().unwrap();
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        // Seek to keys present, between and beyond the entries, at several
+        // timestamps each
+        for i in 0..82u64 {
+            for ts in [0, 15, 25, u64::MAX] {
+                let target = InternalKey::new(format!("key_{:03}", i).into_bytes(), ts);
+                let at = expected.partition_point(|key| key < &target);
+                cursor.seek(&target).unwrap();
+                assert_eq!(key_at(&cursor), expected.get(at).cloned(), "{:?}", target);
+
+                let before = expected.partition_point(|key| key <= &target);
+                cursor.seek_for_prev(&target).unwrap();
+                let want = before.checked_sub(1).map(|i| expected[i].clone());
+                assert_eq!(key_at(&cursor), want, "{:?}", target);
+
+                // Steps from a seek land on the neighbouring entries
+                if let Some(i) = before.checked_sub(1).filter(|i| i + 1 < expected.len()) {
+                    cursor.next().unwrap();
+                    assert_eq!(key_at(&cursor), Some(expected[i + 1].clone()));
+                    cursor.prev().unwrap();
+                    assert_eq!(key_at(&cursor), Some(expected[i].clone()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sstable_reader_requires_the_recorded_comparator() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("reverse.sst");
+        let reverse: Arc<dyn Comparator> = Arc::new(comparator::ReverseBytewiseComparator);
+
+        let mut writer = SSTableWriter::with_block_size(&path, 64)
+            .unwrap()
+            .with_comparator(Arc::clone(&reverse));
+        for i in (0..50u64).rev() {
+            let key = InternalKey::new(format!("key_{:02}", i).into_bytes(), 1);
+            writer.add(key, vec![i as u8], Operation::Put).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let err = SSTableReader::open(&path).unwrap_err();
+        assert!(matches!(err.root(), Error::InvalidArgument(_)), "{}", err);
+        assert_eq!(err.context().unwrap().path.as_deref(), Some(path.as_path()));
+
+        let mut reader = SSTableReader::open_with_comparator(&path, reverse).unwrap();
+        assert_eq!(
+            reader.properties().comparator,
+            "ferrisdb.ReverseBytewiseComparator"
+        );
+        assert_eq!(reader.get(&b"key_07".to_vec(), 1).unwrap(), Some(vec![7]));
+
+        let start = b"key_30".to_vec();
+        let end = b"key_27".to_vec();
+        let keys: Vec<_> = reader
+            .range_iter(Some(&start), Some(&end))
+            .unwrap()
+            .map(|e| e.unwrap().key.user_key)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![b"key_30".to_vec(), b"key_29".to_vec(), b"key_28".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_sstable_reader_skips_prefixes_its_filter_excludes() {
+        use crate::prefix::{DelimitedPrefix, FixedPrefix};
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("prefixed.sst");
+        let extractor = Arc::new(DelimitedPrefix::new(b':', 2));
+        let mut writer = SSTableWriter::new(&path)
+            .unwrap()
+            .with_prefix_extractor(extractor.clone(), 10);
+        for user in (0..100).step_by(2) {
+            for field in ["age", "name"] {
+                let key = InternalKey::new(format!("user:{:03}:{}", user, field).into_bytes(), 1);
+                writer.add(key, b"v".to_vec(), Operation::Put).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let mut reader = SSTableReader::open(&path).unwrap();
+        assert_eq!(
+            reader.properties().prefix_extractor.as_deref(),
+            Some(extractor.name())
+        );
+        for user in (0..100).step_by(2) {
+            let prefix = format!("user:{:03}:", user).into_bytes();
+            assert!(reader.may_contain_prefix(&*extractor, &prefix).unwrap());
+        }
+        let skipped = (0..100)
+            .skip(1)
+            .step_by(2)
+            .filter(|user| {
+                let prefix = format!("user:{:03}:", user).into_bytes();
+                !reader.may_contain_prefix(&*extractor, &prefix).unwrap()
+            })
+            .count();
+        assert!(skipped >= 45, "only {} of 50 skipped", skipped);
+
+        // Partial prefixes and other extractors are never ruled out
+        assert!(reader.may_contain_prefix(&*extractor, b"user:").unwrap());
+        let other = FixedPrefix::new(9);
+        assert!(reader.may_contain_prefix(&other, b"user:001:").unwrap());
+    }
+
+    #[test]
+    fn test_approximate_offsets_follow_the_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("offsets.sst");
+
+        let mut writer = SSTableWriter::with_block_size(&path, 128).unwrap();
+        for i in 0..100u32 {
+            writer
+                .add(
+                    InternalKey::new(format!("key{i:03}").into_bytes(), 1),
+                    vec![b'v'; 32],
+                    Operation::Put,
+                )
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SSTableReader::open(&path).unwrap();
+        let data = reader.data_range();
+        assert_eq!(data.start, 0);
+        assert_eq!(data.end, reader.footer.index_offset);
+        assert!(reader.index.len() > 10);
+
+        assert_eq!(reader.approximate_offset_of(b"a"), data.start);
+        assert_eq!(reader.approximate_offset_of(b"z"), data.end);
+        let offsets: Vec<u64> = (0..100u32)
+            .map(|i| reader.approximate_offset_of(format!("key{i:03}").as_bytes()))
+            .collect();
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Halfway through the keys is about halfway through the data
+        let middle = offsets[50] as f64 / data.end as f64;
+        assert!((0.4..0.6).contains(&middle), "{middle}");
+    }
 }
@@ -0,0 +1,393 @@
+//! Splitting an SSTable in two at a key
+//!
+//! Moving a key range to another shard hands over the tables holding it,
+//! and a table straddling the boundary must first be cut in two.
+//! [`SSTableReader::split_at`] does that without decoding whole blocks:
+//! the index tells which data blocks lie wholly on one side, and those
+//! are copied byte for byte. Only the block the split key falls in is
+//! decoded and written again, as the last block of the left half and the
+//! first of the right.
+//!
+//! The halves keep the source's properties and bloom filter. The filter
+//! and the timestamp range still describe the whole source, so they may
+//! let through reads a half has nothing for, but never hide its keys.
+//!
+//! Tables of format version 1 encode their entries differently from the
+//! blocks written now, so they are rewritten entry by entry instead.
+
+use super::reader::SSTableReader;
+use super::writer::{SSTableInfo, SSTableWriter};
+use super::{encode_data_block, encode_index_block, Footer, IndexEntry, InternalKey, SSTableEntry};
+use crate::utils::coding;
+use crate::vfs::{self, Vfs, VfsFile};
+use ferrisdb_core::{Error, Key, Result};
+
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The tables an SSTable was split into
+#[derive(Debug, Clone)]
+pub struct TableSplit {
+    /// Table of the keys before the split key, `None` if there were none
+    pub left: Option<SSTableInfo>,
+    /// Table of the split key and the keys after it, `None` if there were
+    /// none
+    pub right: Option<SSTableInfo>,
+    /// Data blocks copied without decoding them
+    pub blocks_copied: usize,
+    /// Data blocks decoded and written again
+    pub blocks_rewritten: usize,
+}
+
+impl SSTableReader {
+    /// Splits the table into one at `left` holding the keys before
+    /// `user_key`, and one at `right` holding the rest
+    ///
+    /// Every version of a key goes to the same half. A half that would
+    /// hold nothing isn't created. The source is left as it is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a half cannot be written, or a block of the
+    /// source cannot be read or doesn't match its checksum. Halves already
+    /// written are left behind.
+    pub fn split_at(
+        &mut self,
+        user_key: &[u8],
+        left: impl AsRef<Path>,
+        right: impl AsRef<Path>,
+    ) -> Result<TableSplit> {
+        self.split_at_in(&vfs::os(), user_key, left, right)
+    }
+
+    /// Splits the table like [`split_at`](Self::split_at), writing the
+    /// halves through `vfs`
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`split_at`](Self::split_at).
+    pub fn split_at_in(
+        &mut self,
+        vfs: &Arc<dyn Vfs>,
+        user_key: &[u8],
+        left: impl AsRef<Path>,
+        right: impl AsRef<Path>,
+    ) -> Result<TableSplit> {
+        let (left, right) = (left.as_ref(), right.as_ref());
+        if self.properties().format_version < 2 {
+            return self.rewrite_split(vfs, user_key, left, right);
+        }
+
+        let index = self.index().to_vec();
+        let comparator = Arc::clone(self.comparator());
+        let is_left = |key: &[u8]| comparator.compare(key, user_key).is_lt();
+        // Blocks from `first_right` on start at the split key or after it,
+        // so only the block before them can hold keys of both halves
+        let first_right = index.partition_point(|block| is_left(&block.first_key));
+        let (before, after) = match first_right.checked_sub(1) {
+            Some(boundary) => {
+                let mut before = self.read_block(index[boundary].block_offset)?;
+                let at = before.partition_point(|entry| is_left(&entry.key.user_key));
+                let after = before.split_off(at);
+                (before, after)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        let mut split = TableSplit {
+            left: None,
+            right: None,
+            blocks_copied: index.len() - first_right.min(1),
+            blocks_rewritten: first_right.min(1),
+        };
+
+        if let Some(largest) = before.last() {
+            let mut half = HalfTable::create(vfs, left)?;
+            let copied = &index[..first_right - 1];
+            for block in copied {
+                half.copy_block(self, block)?;
+            }
+            let smallest = match copied.first() {
+                Some(block) => self.first_key(block)?,
+                None => before[0].key.clone(),
+            };
+            half.write_block(&before)?;
+            split.left = Some(half.finish(self, smallest, largest.key.clone())?);
+        }
+
+        let copied = &index[first_right..];
+        if !after.is_empty() || !copied.is_empty() {
+            let mut half = HalfTable::create(vfs, right)?;
+            half.write_block(&after)?;
+            for block in copied {
+                half.copy_block(self, block)?;
+            }
+            let smallest = match after.first() {
+                Some(entry) => entry.key.clone(),
+                None => self.first_key(&copied[0])?,
+            };
+            let largest = match copied.last() {
+                Some(block) => self.last_key(block)?,
+                None => after[after.len() - 1].key.clone(),
+            };
+            split.right = Some(half.finish(self, smallest, largest)?);
+        }
+        Ok(split)
+    }
+
+    /// Splits a table whose blocks can't be copied by writing every entry
+    /// again
+    fn rewrite_split(
+        &mut self,
+        vfs: &Arc<dyn Vfs>,
+        user_key: &[u8],
+        left: &Path,
+        right: &Path,
+    ) -> Result<TableSplit> {
+        let comparator = Arc::clone(self.comparator());
+        let blocks_rewritten = self.index().len();
+        let mut halves: [Option<SSTableWriter>; 2] = [None, None];
+        for entry in self.iter()? {
+            let entry = entry?;
+            let side = usize::from(comparator.compare(&entry.key.user_key, user_key).is_ge());
+            let writer = match &mut halves[side] {
+                Some(writer) => writer,
+                slot @ None => slot.insert(
+                    SSTableWriter::new_in(vfs, [left, right][side])?
+                        .with_comparator(Arc::clone(&comparator)),
+                ),
+            };
+            writer.add(entry.key, entry.value, entry.operation)?;
+        }
+
+        let [left, right] = halves;
+        Ok(TableSplit {
+            left: left.map(SSTableWriter::finish).transpose()?,
+            right: right.map(SSTableWriter::finish).transpose()?,
+            blocks_copied: 0,
+            blocks_rewritten,
+        })
+    }
+
+    /// Returns the first key of a data block
+    fn first_key(&mut self, block: &IndexEntry) -> Result<InternalKey> {
+        let entries = self.read_block(block.block_offset)?;
+        entries
+            .into_iter()
+            .next()
+            .map(|entry| entry.key)
+            .ok_or_else(|| empty_block(block))
+    }
+
+    /// Returns the last key of a data block
+    fn last_key(&mut self, block: &IndexEntry) -> Result<InternalKey> {
+        let entries = self.read_block(block.block_offset)?;
+        entries
+            .into_iter()
+            .next_back()
+            .map(|entry| entry.key)
+            .ok_or_else(|| empty_block(block))
+    }
+}
+
+fn empty_block(block: &IndexEntry) -> Error {
+    Error::Corruption(format!("Data block at {} is empty", block.block_offset))
+}
+
+/// One half of a split being written
+struct HalfTable {
+    writer: BufWriter<Box<dyn VfsFile>>,
+    vfs: Arc<dyn Vfs>,
+    path: PathBuf,
+    /// Current position in the file
+    offset: u64,
+    /// Index entries of the blocks written so far
+    index: Vec<IndexEntry>,
+    entry_count: usize,
+}
+
+impl HalfTable {
+    fn create(vfs: &Arc<dyn Vfs>, path: &Path) -> Result<Self> {
+        let file = vfs.create(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            vfs: Arc::clone(vfs),
+            path: path.to_path_buf(),
+            offset: 0,
+            index: Vec::new(),
+            entry_count: 0,
+        })
+    }
+
+    /// Copies a data block of `source` without decoding its entries
+    fn copy_block(&mut self, source: &mut SSTableReader, block: &IndexEntry) -> Result<()> {
+        let bytes = source.read_block_bytes(block.block_offset)?;
+        // The entry count leads the block
+        self.entry_count += coding::get_fixed32(&mut &bytes[..])? as usize;
+        self.append(block.first_key.clone(), &bytes)
+    }
+
+    /// Writes a data block of `entries`, if there are any
+    fn write_block(&mut self, entries: &[SSTableEntry]) -> Result<()> {
+        let Some(first) = entries.first() else {
+            return Ok(());
+        };
+        self.entry_count += entries.len();
+        self.append(first.key.user_key.clone(), &encode_data_block(entries))
+    }
+
+    fn append(&mut self, first_key: Key, block: &[u8]) -> Result<()> {
+        self.index.push(IndexEntry::new(self.offset, first_key));
+        self.writer.write_all(block)?;
+        self.offset += block.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the index, the source's bloom filter and properties, and
+    /// the footer, then syncs the file
+    fn finish(
+        mut self,
+        source: &mut SSTableReader,
+        smallest_key: InternalKey,
+        largest_key: InternalKey,
+    ) -> Result<SSTableInfo> {
+        let index_offset = self.offset;
+        let index = encode_index_block(&self.index);
+        self.writer.write_all(&index)?;
+        self.offset += index.len() as u64;
+
+        let source_footer = source.info().footer;
+        let bloom_offset = self.offset;
+        let bloom = source.read_bytes(source_footer.bloom_offset, source_footer.bloom_length)?;
+        self.writer.write_all(&bloom)?;
+        self.offset += bloom.len() as u64;
+
+        let properties = source.properties().to_bytes();
+        self.writer.write_all(&properties)?;
+        self.offset += properties.len() as u64;
+
+        let footer = Footer::new(
+            index_offset,
+            index.len() as u64,
+            bloom_offset,
+            bloom.len() as u64,
+        );
+        self.writer.write_all(&footer.to_bytes())?;
+        self.offset += footer.to_bytes().len() as u64;
+
+        self.writer.flush()?;
+        let file = self
+            .writer
+            .into_inner()
+            .map_err(|e| Error::Io(e.into_parts().0))?;
+        file.sync_all()?;
+        vfs::sync_parent_dir(self.vfs.as_ref(), &self.path)
+            .map_err(|e| Error::from(e).with_path(&self.path))?;
+
+        Ok(SSTableInfo {
+            path: self.path,
+            file_size: self.offset,
+            entry_count: self.entry_count,
+            smallest_key,
+            largest_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_core::Operation;
+    use tempfile::TempDir;
+
+    fn key(i: usize) -> Key {
+        format!("key{:04}", i).into_bytes()
+    }
+
+    /// Writes keys `0..count` in small blocks, with three versions of
+    /// every tenth key
+    fn write_table(path: &Path, count: usize) -> Vec<SSTableEntry> {
+        let mut entries = Vec::new();
+        for i in 0..count {
+            let versions = if i % 10 == 0 { 3 } else { 1 };
+            for ts in (1..=versions).rev() {
+                entries.push(SSTableEntry::new(
+                    InternalKey::new(key(i), ts),
+                    format!("value{i}@{ts}").into_bytes(),
+                    Operation::Put,
+                ));
+            }
+        }
+        let mut writer = SSTableWriter::with_block_size(path, 256).unwrap();
+        for entry in &entries {
+            writer
+                .add(entry.key.clone(), entry.value.clone(), entry.operation)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        entries
+    }
+
+    fn read_all(path: &Path) -> Vec<SSTableEntry> {
+        let mut reader = SSTableReader::open(path).unwrap();
+        assert!(reader.verify().is_ok());
+        reader.iter().unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_split_copies_blocks_on_either_side() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source.sst");
+        let entries = write_table(&source, 200);
+        let mut reader = SSTableReader::open(&source).unwrap();
+        let blocks = reader.index().len();
+
+        for split_at in [50, 55, 123, 1] {
+            let (left, right) = (dir.path().join("left.sst"), dir.path().join("right.sst"));
+            let split = reader.split_at(&key(split_at), &left, &right).unwrap();
+            assert_eq!(split.blocks_rewritten, 1);
+            assert_eq!(split.blocks_copied, blocks - 1);
+
+            let at = entries.partition_point(|entry| entry.key.user_key < key(split_at));
+            let (left_info, right_info) = (split.left.unwrap(), split.right.unwrap());
+            assert_eq!(read_all(&left), entries[..at]);
+            assert_eq!(read_all(&right), entries[at..]);
+            assert_eq!(left_info.entry_count, at);
+            assert_eq!(right_info.entry_count, entries.len() - at);
+            assert_eq!(left_info.smallest_key, entries[0].key);
+            assert_eq!(left_info.largest_key, entries[at - 1].key);
+            assert_eq!(right_info.smallest_key, entries[at].key);
+            assert_eq!(right_info.largest_key, entries[entries.len() - 1].key);
+            assert_eq!(left_info.file_size, std::fs::metadata(&left).unwrap().len());
+
+            let mut half = SSTableReader::open(&right).unwrap();
+            assert_eq!(half.properties(), reader.properties());
+            let newest = if split_at % 10 == 0 { 3 } else { 1 };
+            assert_eq!(
+                half.get_latest(&key(split_at), u64::MAX)
+                    .unwrap()
+                    .map(|(value, ..)| value),
+                Some(format!("value{split_at}@{newest}").into_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_outside_the_keys_leaves_one_half() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source.sst");
+        let entries = write_table(&source, 50);
+        let mut reader = SSTableReader::open(&source).unwrap();
+        let (left, right) = (dir.path().join("left.sst"), dir.path().join("right.sst"));
+
+        let split = reader.split_at(b"a", &left, &right).unwrap();
+        assert!(split.left.is_none() && !left.exists());
+        assert_eq!(split.blocks_rewritten, 0);
+        assert_eq!(read_all(&right), entries);
+
+        std::fs::remove_file(&right).unwrap();
+        let split = reader.split_at(b"z", &left, &right).unwrap();
+        assert!(split.right.is_none() && !right.exists());
+        assert_eq!(read_all(&left), entries);
+    }
+}
@@ -1,12 +1,18 @@
 //! SSTable writer implementation
 
+use crate::comparator::{self, Comparator};
+use crate::prefix::PrefixExtractor;
+use crate::rate_limiter::{IoPriority, RateLimiter};
 use crate::sstable::{
-    Footer, IndexEntry, InternalKey, SSTableEntry, DEFAULT_BLOCK_SIZE, MAX_ENTRY_SIZE,
+    encode_data_block, encode_index_block, Footer, IndexEntry, InternalKey, SSTableEntry,
+    TableProperties, DEFAULT_BLOCK_SIZE, MAX_ENTRY_SIZE,
 };
-use ferrisdb_core::{Error, Operation, Result, Value};
-use std::fs::File;
+use crate::utils::bloom::{self, BloomFilter};
+use crate::vfs::{self, Vfs, VfsFile};
+use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Metadata about a written SSTable file
 #[derive(Debug, Clone)]
@@ -45,7 +51,9 @@ pub struct SSTableInfo {
 /// ```
 pub struct SSTableWriter {
     /// Buffered writer for the file
-    writer: BufWriter<File>,
+    writer: BufWriter<Box<dyn VfsFile>>,
+    /// Filesystem the file is written to
+    vfs: Arc<dyn Vfs>,
     /// Path to the file being written
     path: PathBuf,
     /// Current position in the file
@@ -66,6 +74,19 @@ pub struct SSTableWriter {
     largest_key: Option<InternalKey>,
     /// Last key written (for ordering verification)
     last_key: Option<InternalKey>,
+    /// Smallest and largest timestamp seen, recorded in the properties block
+    timestamp_range: Option<(Timestamp, Timestamp)>,
+    /// Ordering of user keys, recorded in the properties block
+    comparator: Arc<dyn Comparator>,
+    /// Extractor of the prefixes the bloom filter holds, with the bits
+    /// spent on each
+    prefix_filter: Option<(Arc<dyn PrefixExtractor>, usize)>,
+    /// Hashes of the distinct prefixes added so far
+    prefix_hashes: Vec<u32>,
+    /// Limiter charged for every block written, at the given priority
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
+    /// Prefix of the last key added, to hash each prefix once
+    last_prefix: Option<Key>,
     /// Whether finish() has been called
     finished: bool,
 }
@@ -81,12 +102,22 @@ impl SSTableWriter {
     ///
     /// Returns an error if the file cannot be created
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new_in(&vfs::os(), path)
+    }
+
+    /// Creates a new SSTable writer for a file created through `vfs`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created
+    pub fn new_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
-        let file = File::create(&path)?;
+        let file = vfs.create(&path)?;
         let writer = BufWriter::new(file);
 
         Ok(Self {
             writer,
+            vfs: Arc::clone(vfs),
             path,
             file_offset: 0,
             current_block: Vec::new(),
@@ -97,6 +128,12 @@ impl SSTableWriter {
             smallest_key: None,
             largest_key: None,
             last_key: None,
+            timestamp_range: None,
+            comparator: comparator::bytewise(),
+            prefix_filter: None,
+            prefix_hashes: Vec::new(),
+            rate_limiter: None,
+            last_prefix: None,
             finished: false,
         })
     }
@@ -108,15 +145,56 @@ impl SSTableWriter {
     /// * `path` - Path where the SSTable file will be created
     /// * `block_size` - Target size for data blocks in bytes
     pub fn with_block_size(path: impl AsRef<Path>, block_size: usize) -> Result<Self> {
-        let mut writer = Self::new(path)?;
+        Self::with_block_size_in(&vfs::os(), path, block_size)
+    }
+
+    /// Creates a new SSTable writer with a custom block size for a file
+    /// created through `vfs`
+    pub fn with_block_size_in(
+        vfs: &Arc<dyn Vfs>,
+        path: impl AsRef<Path>,
+        block_size: usize,
+    ) -> Result<Self> {
+        let mut writer = Self::new_in(vfs, path)?;
         writer.block_size = block_size;
         Ok(writer)
     }
 
+    /// Sets the comparator keys are sorted by, bytewise by default
+    ///
+    /// Its name is recorded in the table, and readers must open the table
+    /// with a comparator of the same name.
+    pub fn with_comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
+    /// Builds a bloom filter over the prefixes `extractor` finds in the
+    /// keys, spending `bits_per_key` bits on each distinct prefix
+    ///
+    /// The extractor's name is recorded in the table; without one the
+    /// table gets an empty filter.
+    pub fn with_prefix_extractor(
+        mut self,
+        extractor: Arc<dyn PrefixExtractor>,
+        bits_per_key: usize,
+    ) -> Self {
+        self.prefix_filter = Some((extractor, bits_per_key));
+        self
+    }
+
+    /// Charges every block written to `limiter` at `priority`, blocking
+    /// the writer while the limiter holds it back
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>, priority: IoPriority) -> Self {
+        self.rate_limiter = Some((limiter, priority));
+        self
+    }
+
     /// Adds a key-value pair with operation to the SSTable
     ///
     /// Keys must be added in sorted order according to InternalKey ordering
-    /// (user_key ascending, then timestamp descending). The writer verifies
+    /// (user_key ascending by the writer's comparator, then timestamp
+    /// descending). The writer verifies
     /// ordering to prevent creating invalid SSTables.
     ///
     /// # Arguments
@@ -157,7 +235,7 @@ impl SSTableWriter {
 
         // Verify ordering
         if let Some(ref last) = self.last_key {
-            if key <= *last {
+            if comparator::compare_internal(&*self.comparator, &key, last).is_le() {
                 return Err(Error::KeyOrderingViolation {
                     last_key: last.to_string(),
                     new_key: key.to_string(),
@@ -165,6 +243,16 @@ impl SSTableWriter {
             }
         }
 
+        if let Some((extractor, _)) = &self.prefix_filter {
+            if let Some(prefix) = extractor.prefix(&key.user_key) {
+                // Keys sharing a prefix are added one after another
+                if self.last_prefix.as_deref() != Some(prefix) {
+                    self.prefix_hashes.push(bloom::hash(prefix));
+                    self.last_prefix = Some(prefix.to_vec());
+                }
+            }
+        }
+
         // Create entry with the provided operation
         let entry = SSTableEntry::new(key.clone(), value, operation);
         let entry_size = entry.serialized_size();
@@ -174,6 +262,11 @@ impl SSTableWriter {
             self.smallest_key = Some(key.clone());
         }
         self.largest_key = Some(key.clone());
+        let ts = key.timestamp;
+        self.timestamp_range = Some(match self.timestamp_range {
+            Some((smallest, largest)) => (smallest.min(ts), largest.max(ts)),
+            None => (ts, ts),
+        });
 
         // Check if we need to flush the current block
         if !self.current_block.is_empty() && self.current_block_size + entry_size > self.block_size
@@ -197,9 +290,10 @@ impl SSTableWriter {
     /// This method:
     /// 1. Flushes any remaining data block
     /// 2. Writes the index block
-    /// 3. Writes the bloom filter (placeholder for now)
-    /// 4. Writes the footer
-    /// 5. Syncs the file to disk
+    /// 3. Writes the bloom filter over the keys' prefixes
+    /// 4. Writes the properties block
+    /// 5. Writes the footer
+    /// 6. Syncs the file to disk
     ///
     /// After calling finish(), the writer cannot be used again.
     pub fn finish(mut self) -> Result<SSTableInfo> {
@@ -218,14 +312,26 @@ impl SSTableWriter {
         let index_offset = self.file_offset;
         let index_length = self.write_index_block()?;
 
-        // Write bloom filter (placeholder for now)
+        // Write bloom filter
         let bloom_offset = self.file_offset;
         let bloom_length = self.write_bloom_filter()?;
 
+        // Write properties, found by readers between the bloom filter and footer
+        let mut properties = TableProperties::new(&*self.comparator);
+        properties.prefix_extractor = self
+            .prefix_filter
+            .as_ref()
+            .map(|(extractor, _)| extractor.name().to_string());
+        properties.timestamp_range = self.timestamp_range;
+        let properties = properties.to_bytes();
+        self.writer.write_all(&properties)?;
+        self.file_offset += properties.len() as u64;
+
         // Write footer
         let footer = Footer::new(index_offset, index_length, bloom_offset, bloom_length);
         self.writer.write_all(&footer.to_bytes())?;
         self.file_offset += footer.to_bytes().len() as u64;
+        self.throttle(self.file_offset - index_offset);
 
         // Sync to disk
         self.writer.flush()?;
@@ -234,6 +340,10 @@ impl SSTableWriter {
             .into_inner()
             .map_err(|e| Error::Io(e.into_parts().0))?;
         file.sync_all()?;
+        // The table is registered in the MANIFEST next, which must never
+        // name a file whose directory entry a crash could lose
+        vfs::sync_parent_dir(self.vfs.as_ref(), &self.path)
+            .map_err(|e| Error::from(e).with_path(&self.path))?;
 
         self.finished = true;
 
@@ -260,20 +370,10 @@ impl SSTableWriter {
         let first_key = self.current_block[0].key.user_key.clone();
         let block_offset = self.file_offset;
 
-        // Write block header (entry count - u32 supports up to 4B entries per block)
-        let entry_count = self.current_block.len() as u32;
-        self.writer.write_all(&entry_count.to_le_bytes())?;
-        self.file_offset += 4;
-
-        // Write entries
-        for entry in &self.current_block {
-            Self::write_entry(&mut self.writer, &mut self.file_offset, entry)?;
-        }
-
-        // Calculate and write checksum (placeholder - just use 0 for now)
-        let checksum: u32 = 0; // TODO: Implement actual CRC32
-        self.writer.write_all(&checksum.to_le_bytes())?;
-        self.file_offset += 4;
+        let block = encode_data_block(&self.current_block);
+        self.writer.write_all(&block)?;
+        self.file_offset += block.len() as u64;
+        self.throttle(block.len() as u64);
 
         // Add index entry
         self.index_entries
@@ -286,99 +386,33 @@ impl SSTableWriter {
         Ok(())
     }
 
-    /// Writes a single entry to the current block
-    fn write_entry(
-        writer: &mut BufWriter<File>,
-        file_offset: &mut u64,
-        entry: &SSTableEntry,
-    ) -> Result<()> {
-        // Write key length (safe cast: MAX_ENTRY_SIZE is 16MB, well within u32)
-        let key_len = entry.key.user_key.len() as u32;
-        writer.write_all(&key_len.to_le_bytes())?;
-        *file_offset += 4;
-
-        // Write value length (safe cast: MAX_ENTRY_SIZE is 16MB, well within u32)
-        let value_len = entry.value.len() as u32;
-        writer.write_all(&value_len.to_le_bytes())?;
-        *file_offset += 4;
-
-        // Write timestamp
-        writer.write_all(&entry.key.timestamp.to_le_bytes())?;
-        *file_offset += 8;
-
-        // Write operation
-        let op_byte = match entry.operation {
-            Operation::Put => 0u8,
-            Operation::Delete => 1u8,
-        };
-        writer.write_all(&[op_byte])?;
-        *file_offset += 1;
-
-        // Write key
-        writer.write_all(&entry.key.user_key)?;
-        *file_offset += entry.key.user_key.len() as u64;
-
-        // Write value
-        writer.write_all(&entry.value)?;
-        *file_offset += entry.value.len() as u64;
-
-        Ok(())
+    /// Charges `bytes` written to the rate limiter, if any
+    fn throttle(&self, bytes: u64) {
+        if let Some((limiter, priority)) = &self.rate_limiter {
+            limiter.request(bytes, *priority);
+        }
     }
 
     /// Writes the index block and returns its length
     fn write_index_block(&mut self) -> Result<u64> {
-        let start_offset = self.file_offset;
-
-        // Write entry count
-        let entry_count = self.index_entries.len() as u32;
-        self.writer.write_all(&entry_count.to_le_bytes())?;
-        self.file_offset += 4;
-
-        // Write each index entry
-        for entry in &self.index_entries {
-            // Write block offset
-            self.writer.write_all(&entry.block_offset.to_le_bytes())?;
-            self.file_offset += 8;
-
-            // Write key length
-            let key_len = entry.first_key.len() as u32;
-            self.writer.write_all(&key_len.to_le_bytes())?;
-            self.file_offset += 4;
-
-            // Write key
-            self.writer.write_all(&entry.first_key)?;
-            self.file_offset += entry.first_key.len() as u64;
-        }
-
-        // Write checksum (placeholder)
-        let checksum: u32 = 0; // TODO: Implement actual CRC32
-        self.writer.write_all(&checksum.to_le_bytes())?;
-        self.file_offset += 4;
-
-        Ok(self.file_offset - start_offset)
+        let block = encode_index_block(&self.index_entries);
+        self.writer.write_all(&block)?;
+        self.file_offset += block.len() as u64;
+        Ok(block.len() as u64)
     }
 
-    /// Writes a placeholder bloom filter and returns its length
+    /// Writes the bloom filter and returns its length
     fn write_bloom_filter(&mut self) -> Result<u64> {
-        let start_offset = self.file_offset;
-
-        // For now, just write a minimal bloom filter structure
-        // TODO: Implement actual bloom filter
-
-        // Write empty bit array (just 8 bytes of zeros)
-        self.writer.write_all(&[0u8; 8])?;
-        self.file_offset += 8;
-
-        // Write hash count (0 for placeholder)
-        self.writer.write_all(&0u32.to_le_bytes())?;
-        self.file_offset += 4;
-
-        // Write checksum (placeholder)
-        let checksum: u32 = 0;
-        self.writer.write_all(&checksum.to_le_bytes())?;
-        self.file_offset += 4;
-
-        Ok(self.file_offset - start_offset)
+        let filter = match &self.prefix_filter {
+            Some((_, bits_per_key)) => {
+                BloomFilter::from_hashes(self.prefix_hashes.drain(..), *bits_per_key)
+            }
+            None => BloomFilter::empty(),
+        };
+        let bytes = filter.encode();
+        self.writer.write_all(&bytes)?;
+        self.file_offset += bytes.len() as u64;
+        Ok(bytes.len() as u64)
     }
 }
 
@@ -459,6 +493,28 @@ mod tests {
         assert!(info.file_size > 128); // Should be larger than one block
     }
 
+    #[test]
+    fn test_sstable_writer_charges_every_byte_to_rate_limiter() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("limited.sst");
+        let limiter = Arc::new(RateLimiter::new(0));
+
+        let mut writer = SSTableWriter::with_block_size(&path, 128)
+            .unwrap()
+            .with_rate_limiter(Arc::clone(&limiter), IoPriority::High);
+        for i in 0..20 {
+            let key = InternalKey::new(format!("key_{:04}", i).into_bytes(), i as u64);
+            writer.add(key, b"value".to_vec(), Operation::Put).unwrap();
+        }
+        let info = writer.finish().unwrap();
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.bytes_through(IoPriority::High), info.file_size);
+        // One request per data block and one for the rest
+        assert!(metrics.requests(IoPriority::High) > 2);
+        assert_eq!(metrics.requests(IoPriority::Low), 0);
+    }
+
     #[test]
     fn test_sstable_writer_large_entries() {
         let temp_dir = TempDir::new().unwrap();
@@ -589,6 +645,9 @@ mod tests {
         assert_eq!(info.entry_count, 3);
         assert_eq!(info.smallest_key, key1);
         assert_eq!(info.largest_key, key3);
+
+        let reader = crate::sstable::SSTableReader::open(&path).unwrap();
+        assert_eq!(reader.properties().timestamp_range, Some((100, 300)));
     }
 
     #[test]
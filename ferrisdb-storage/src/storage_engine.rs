@@ -1,53 +0,0 @@
-//! Main storage engine implementation
-
-use crate::StorageConfig;
-
-/// The main storage engine for FerrisDB
-///
-/// This struct coordinates all storage components including WAL, MemTable,
-/// SSTables, and compaction. It provides the primary interface for
-/// reading and writing data.
-///
-/// # Architecture
-///
-/// The storage engine implements an LSM-tree (Log-Structured Merge-tree) with:
-/// - Write-ahead logging for durability
-/// - In-memory MemTable for recent writes
-/// - On-disk SSTables organized in levels
-/// - Background compaction to optimize read performance
-///
-/// # Example
-///
-/// ```no_run
-/// use ferrisdb_storage::{StorageEngine, StorageConfig};
-///
-/// let config = StorageConfig::default();
-/// let engine = StorageEngine::new(config);
-///
-/// // TODO: Add methods for get/put/delete operations
-/// ```
-pub struct StorageEngine {
-    #[allow(dead_code)] // TODO: Remove when implementing engine
-    config: StorageConfig,
-}
-
-impl StorageEngine {
-    /// Creates a new storage engine with the given configuration
-    ///
-    /// This will:
-    /// 1. Create necessary directories
-    /// 2. Recover from existing WAL if present
-    /// 3. Load existing SSTables
-    /// 4. Start background compaction threads
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if:
-    /// - Directory creation fails
-    /// - WAL recovery fails
-    /// - Corruption is detected during recovery
-    pub fn new(config: StorageConfig) -> Self {
-        // TODO: Implement full initialization
-        Self { config }
-    }
-}
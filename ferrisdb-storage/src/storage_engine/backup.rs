@@ -0,0 +1,695 @@
+//! Backups and restore, including point-in-time recovery
+//!
+//! A [`BackupEngine`] keeps numbered backups in a directory of its own.
+//! Each backup is a [checkpoint](super::StorageEngine::create_checkpoint)
+//! plus a `BACKUP` file listing the size and CRC32 of every file in it:
+//!
+//! ```text
+//!   backups/
+//!   ├── 000001/
+//!   │   ├── BACKUP        timestamp, then (path, size, crc32) per file
+//!   │   ├── MANIFEST
+//!   │   ├── 000012.sst
+//!   │   └── wal/000017.wal
+//!   └── 000002/
+//! ```
+//!
+//! A backup is written to a temporary directory and renamed once its
+//! `BACKUP` file is complete, so a crash never leaves half a backup behind.
+//! Restoring copies the files into a new database directory and checks
+//! every copy against the recorded checksums.
+//!
+//! Backups live on a filesystem, the operating system's unless opened with
+//! [`BackupEngine::open_in`], and only engines on the same filesystem can
+//! be backed up, as the checkpoint is written through the engine's.
+//!
+//! # Point-in-Time Recovery
+//!
+//! An engine opened with [`Options::with_wal_archive_dir`](super::Options::with_wal_archive_dir)
+//! moves flushed WAL segments into the archive instead of deleting them.
+//! [`BackupEngine::restore_to_timestamp`] restores a backup and then adds
+//! the archived segments written after it, cut off before the first batch
+//! holding a write newer than the target timestamp. Batches are restored
+//! whole or not at all. Writes still in the engine's active segment are
+//! not archived yet; flush the engine to archive them.
+
+use super::checkpoint::create_checkpoint;
+use super::recovery::{truncate, wal_segments_in};
+use super::StorageEngine;
+use crate::utils::atomic_file;
+use crate::vfs::{self, Vfs};
+use crate::wal::WALReader;
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File describing the contents of a backup
+const BACKUP_FILE_NAME: &str = "BACKUP";
+
+/// Identifies a backup description file
+const BACKUP_MAGIC: &[u8; 8] = b"FDB_BAK\0";
+
+/// Summary of a stored backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// Id of the backup, increasing with every backup taken
+    pub id: u64,
+    /// Timestamp of the newest write in the backup
+    pub timestamp: Timestamp,
+    /// Number of files in the backup
+    pub file_count: usize,
+    /// Total size of the backup's files (in bytes)
+    pub size: u64,
+}
+
+/// One file of a backup, relative to the backup directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BackupFile {
+    path: String,
+    size: u64,
+    crc32: u32,
+}
+
+/// Creates, lists, and restores backups kept in one directory
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{BackupEngine, Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path().join("db")))?;
+/// engine.put(b"key".to_vec(), b"value".to_vec())?;
+///
+/// let backups = BackupEngine::open(dir.path().join("backups"))?;
+/// let info = backups.create_backup(&engine)?;
+///
+/// let restored = dir.path().join("restored");
+/// backups.restore(info.id, &restored)?;
+/// let copy = StorageEngine::open(Options::new(&restored))?;
+/// assert_eq!(copy.get(b"key")?, Some(b"value".to_vec()));
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct BackupEngine {
+    dir: PathBuf,
+    /// Filesystem holding the backups
+    vfs: Arc<dyn Vfs>,
+}
+
+impl BackupEngine {
+    /// Opens the backups stored in `dir`, creating it if needed
+    ///
+    /// Leftovers of backups interrupted by a crash are removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or read.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_in(vfs::os(), dir)
+    }
+
+    /// Opens the backups stored in `dir` on `vfs`, creating it if needed
+    ///
+    /// Only engines opened on the same filesystem, with
+    /// [`Options::with_vfs`](super::Options::with_vfs), can be backed up
+    /// here. Restored databases are written to it as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`open`](Self::open).
+    pub fn open_in(vfs: Arc<dyn Vfs>, dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        vfs.create_dir_all(&dir)?;
+        for path in vfs.read_dir(&dir)? {
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                log::warn!("Removing incomplete backup {}", path.display());
+                vfs.remove_dir_all(&path)?;
+            }
+        }
+        Ok(Self { dir, vfs })
+    }
+
+    /// Takes a backup of every write acknowledged before the call
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` for an engine on another filesystem
+    /// than the backups, `Error::Unsupported` for one with
+    /// [remote storage](super::Options::with_remote_storage), or an error
+    /// if the engine is closed or the checkpoint or the backup description
+    /// cannot be written.
+    pub fn create_backup(&self, engine: &StorageEngine) -> Result<BackupInfo> {
+        engine.inner.check_open()?;
+        if engine.inner.tiered().is_none() && !vfs::is_same(&engine.inner.options.vfs, &self.vfs) {
+            return Err(Error::InvalidArgument(
+                "The engine and its backups must be on the same filesystem".to_string(),
+            ));
+        }
+        let id = self.backup_ids()?.last().map_or(1, |id| id + 1);
+        let tmp_dir = self.dir.join(format!("{:06}.tmp", id));
+
+        let result = (|| {
+            let timestamp = create_checkpoint(&engine.inner, &tmp_dir)?;
+            let mut files = Vec::new();
+            for path in list_files(&self.vfs, &tmp_dir)? {
+                let (size, crc32) = checksum(&self.vfs, &tmp_dir.join(&path))?;
+                files.push(BackupFile { path, size, crc32 });
+            }
+
+            let description = tmp_dir.join(BACKUP_FILE_NAME);
+            atomic_file::write_atomic_in(
+                self.vfs.as_ref(),
+                &description,
+                &encode(timestamp, &files),
+            )?;
+            self.vfs.rename(&tmp_dir, &self.backup_dir(id))?;
+            self.vfs.sync_dir(&self.dir)?;
+            Ok(info(id, timestamp, &files))
+        })();
+
+        if result.is_err() {
+            let _ = self.vfs.remove_dir_all(&tmp_dir);
+        }
+        result
+    }
+
+    /// Returns the stored backups, oldest first
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or a backup description cannot be
+    /// read, or `Error::Corruption` if a description is damaged.
+    pub fn backups(&self) -> Result<Vec<BackupInfo>> {
+        self.backup_ids()?
+            .into_iter()
+            .map(|id| {
+                let (timestamp, files) = self.read_description(id)?;
+                Ok(info(id, timestamp, &files))
+            })
+            .collect()
+    }
+
+    /// Deletes a backup
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the backup doesn't exist, or an
+    /// error if its files cannot be removed.
+    pub fn delete_backup(&self, backup_id: u64) -> Result<()> {
+        self.check_exists(backup_id)?;
+        self.vfs.remove_dir_all(&self.backup_dir(backup_id))?;
+        Ok(())
+    }
+
+    /// Checks every file of a backup against its recorded size and checksum
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` naming the first damaged or missing
+    /// file, or `Error::InvalidOperation` if the backup doesn't exist.
+    pub fn verify_backup(&self, backup_id: u64) -> Result<()> {
+        let (_, files) = self.read_description(backup_id)?;
+        let dir = self.backup_dir(backup_id);
+        for file in &files {
+            verify(&self.vfs, &dir.join(&file.path), file)?;
+        }
+        Ok(())
+    }
+
+    /// Restores a backup into `target_dir`, which must not exist
+    ///
+    /// The result opens through `Options::new(target_dir)`, with the column
+    /// families' options passed again. Each restored file is checked
+    /// against the checksum recorded when the backup was taken.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the backup doesn't exist or
+    /// `target_dir` does, `Error::Corruption` if a restored file doesn't
+    /// match its checksum, or an error if copying fails. A partially
+    /// restored directory is removed.
+    pub fn restore(&self, backup_id: u64, target_dir: impl AsRef<Path>) -> Result<()> {
+        self.restore_files(backup_id, target_dir.as_ref())?;
+        Ok(())
+    }
+
+    /// Restores a backup and replays archived writes up to `timestamp`
+    ///
+    /// The backup is restored as by [`restore`](Self::restore), then the
+    /// segments in `wal_archive_dir` logged after the backup are added up
+    /// to the last batch whose writes are all at or below `timestamp`.
+    /// Opening the result replays them; a segment missing from the archive
+    /// makes that fail, since the next one continues from a write that
+    /// wasn't replayed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if `timestamp` is older than the
+    /// backup, and otherwise errors like [`restore`](Self::restore) or if
+    /// an archived segment cannot be read or copied.
+    pub fn restore_to_timestamp(
+        &self,
+        backup_id: u64,
+        target_dir: impl AsRef<Path>,
+        wal_archive_dir: impl AsRef<Path>,
+        timestamp: Timestamp,
+    ) -> Result<()> {
+        let target_dir = target_dir.as_ref();
+        let (backup_timestamp, _) = self.read_description(backup_id)?;
+        if timestamp < backup_timestamp {
+            return Err(Error::InvalidOperation(format!(
+                "Backup {} is at timestamp {}, after the requested {}",
+                backup_id, backup_timestamp, timestamp
+            )));
+        }
+
+        let wal_dir = self.restore_files(backup_id, target_dir)?;
+        let result = (|| {
+            // The backup's oldest segment is the first one it may miss writes of
+            let first = match wal_segments_in(&self.vfs, &wal_dir)?.first() {
+                Some(&(number, _)) => number,
+                None => return Ok(()),
+            };
+            for (number, path) in wal_segments_in(&self.vfs, wal_archive_dir.as_ref())? {
+                if number < first {
+                    continue;
+                }
+                let target = wal_dir.join(path.file_name().expect("segment has a name"));
+                vfs::copy(self.vfs.as_ref(), &path, &target)?;
+                if cut_after(&self.vfs, &target, timestamp)? {
+                    break;
+                }
+            }
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = self.vfs.remove_dir_all(target_dir);
+        }
+        result
+    }
+
+    /// Copies a backup's files into `target_dir`, returning its WAL directory
+    fn restore_files(&self, backup_id: u64, target_dir: &Path) -> Result<PathBuf> {
+        if self.vfs.exists(target_dir) {
+            return Err(Error::InvalidOperation(format!(
+                "Restore target {} already exists",
+                target_dir.display()
+            )));
+        }
+        let (_, files) = self.read_description(backup_id)?;
+        let dir = self.backup_dir(backup_id);
+
+        let result = (|| {
+            self.vfs.create_dir_all(&target_dir.join("wal"))?;
+            for file in &files {
+                let target = target_dir.join(&file.path);
+                if let Some(parent) = target.parent() {
+                    self.vfs.create_dir_all(parent)?;
+                }
+                vfs::copy(self.vfs.as_ref(), &dir.join(&file.path), &target)?;
+                verify(&self.vfs, &target, file)?;
+            }
+            // Sync deepest directories first, so each parent records a
+            // child whose entries are already durable
+            let mut dirs: Vec<_> = files
+                .iter()
+                .filter_map(|file| target_dir.join(&file.path).parent().map(Path::to_path_buf))
+                .collect();
+            dirs.push(target_dir.join("wal"));
+            dirs.sort();
+            dirs.dedup();
+            for dir in dirs.iter().rev() {
+                self.vfs.sync_dir(dir)?;
+            }
+            vfs::sync_parent_dir(self.vfs.as_ref(), target_dir)?;
+            Ok(target_dir.join("wal"))
+        })();
+
+        if result.is_err() {
+            let _ = self.vfs.remove_dir_all(target_dir);
+        }
+        result
+    }
+
+    fn backup_dir(&self, backup_id: u64) -> PathBuf {
+        self.dir.join(format!("{:06}", backup_id))
+    }
+
+    /// Returns the ids of complete backups in ascending order
+    fn backup_ids(&self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        for path in self.vfs.read_dir(&self.dir)? {
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse().ok());
+            if let Some(id) = id {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn check_exists(&self, backup_id: u64) -> Result<()> {
+        if !self
+            .vfs
+            .exists(&self.backup_dir(backup_id).join(BACKUP_FILE_NAME))
+        {
+            return Err(Error::InvalidOperation(format!(
+                "Backup {} does not exist",
+                backup_id
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_description(&self, backup_id: u64) -> Result<(Timestamp, Vec<BackupFile>)> {
+        self.check_exists(backup_id)?;
+        let mut data = Vec::new();
+        self.vfs
+            .open(&self.backup_dir(backup_id).join(BACKUP_FILE_NAME))?
+            .read_to_end(&mut data)?;
+        decode(&data)
+    }
+}
+
+fn info(id: u64, timestamp: Timestamp, files: &[BackupFile]) -> BackupInfo {
+    BackupInfo {
+        id,
+        timestamp,
+        file_count: files.len(),
+        size: files.iter().map(|file| file.size).sum(),
+    }
+}
+
+/// Lists the files below `dir` as `/`-separated relative paths, sorted
+fn list_files(vfs: &Arc<dyn Vfs>, dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut pending = vec![(dir.to_path_buf(), String::new())];
+    while let Some((path, prefix)) = pending.pop() {
+        for entry in vfs.read_dir(&path)? {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            let relative = format!("{}{}", prefix, name);
+            // Only directories can be listed
+            if vfs.read_dir(&entry).is_ok() {
+                pending.push((entry, format!("{}/", relative)));
+            } else {
+                files.push(relative);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the size and CRC32 of a file
+fn checksum(vfs: &Arc<dyn Vfs>, path: &Path) -> Result<(u64, u32)> {
+    let mut file = vfs.open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        size += n as u64;
+    }
+    Ok((size, hasher.finalize()))
+}
+
+fn verify(vfs: &Arc<dyn Vfs>, path: &Path, expected: &BackupFile) -> Result<()> {
+    let (size, crc32) = checksum(vfs, path).map_err(|e| {
+        Error::Corruption(format!("Backup file {} unreadable: {}", expected.path, e))
+    })?;
+    if size != expected.size || crc32 != expected.crc32 {
+        return Err(Error::Corruption(format!(
+            "Backup file {} has size {} and checksum {:#010x}, expected {} and {:#010x}",
+            expected.path, size, crc32, expected.size, expected.crc32
+        )));
+    }
+    Ok(())
+}
+
+/// Cuts a segment before the first record with a write newer than `until`
+///
+/// Returns true if anything was cut, so later segments are not needed.
+fn cut_after(vfs: &Arc<dyn Vfs>, path: &Path, until: Timestamp) -> Result<bool> {
+    let mut reader = WALReader::new_in(vfs, path)?;
+    let mut record_start = reader.valid_len();
+    loop {
+        let before = reader.valid_len();
+        let Some(entry) = reader.read_entry()? else {
+            return Ok(false);
+        };
+        // The offset only moves when a new record, or batch, is read
+        if reader.valid_len() != before {
+            record_start = before;
+        }
+        if entry.timestamp > until {
+            drop(reader);
+            truncate(vfs.as_ref(), path, record_start)?;
+            return Ok(true);
+        }
+    }
+}
+
+/// Encodes a backup description
+///
+/// ```text
+/// [magic:8][timestamp:8][count:4]
+/// count × [path_len:4][path][size:8][crc32:4]
+/// [checksum:4]   CRC32 of everything before it
+/// ```
+fn encode(timestamp: Timestamp, files: &[BackupFile]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(BACKUP_MAGIC);
+    data.extend_from_slice(&timestamp.to_le_bytes());
+    data.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    for file in files {
+        data.extend_from_slice(&(file.path.len() as u32).to_le_bytes());
+        data.extend_from_slice(file.path.as_bytes());
+        data.extend_from_slice(&file.size.to_le_bytes());
+        data.extend_from_slice(&file.crc32.to_le_bytes());
+    }
+    let checksum = crc32fast::hash(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+fn decode(data: &[u8]) -> Result<(Timestamp, Vec<BackupFile>)> {
+    let corrupt = |reason: &str| Error::Corruption(format!("Backup description {}", reason));
+    if data.len() < BACKUP_MAGIC.len() + 16 || &data[..BACKUP_MAGIC.len()] != BACKUP_MAGIC {
+        return Err(corrupt("has an invalid header"));
+    }
+    let (body, stored) = data.split_at(data.len() - 4);
+    if crc32fast::hash(body) != u32::from_le_bytes(stored.try_into().unwrap()) {
+        return Err(corrupt("checksum mismatch"));
+    }
+
+    let mut rest = &body[BACKUP_MAGIC.len()..];
+    let mut take = |len: usize| -> Result<&[u8]> {
+        if rest.len() < len {
+            return Err(corrupt("is truncated"));
+        }
+        let (head, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(head)
+    };
+    let timestamp = u64::from_le_bytes(take(8)?.try_into().unwrap());
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut files = Vec::new();
+    for _ in 0..count {
+        let path_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let path = String::from_utf8(take(path_len)?.to_vec())
+            .map_err(|_| corrupt("has a path that is not UTF-8"))?;
+        let size = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let crc32 = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        files.push(BackupFile { path, size, crc32 });
+    }
+    Ok((timestamp, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, WriteBatch};
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_description_roundtrips_and_detects_damage() {
+        let files = vec![
+            BackupFile {
+                path: "MANIFEST".to_string(),
+                size: 120,
+                crc32: 7,
+            },
+            BackupFile {
+                path: "wal/000004.wal".to_string(),
+                size: 64,
+                crc32: 9,
+            },
+        ];
+        let mut data = encode(42, &files);
+        assert_eq!(decode(&data).unwrap(), (42, files));
+
+        data[12] ^= 1;
+        assert!(matches!(decode(&data), Err(Error::Corruption(_))));
+        assert!(matches!(decode(&data[..10]), Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn test_backup_restores_every_column_family() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path().join("db"))).unwrap();
+        let index = engine
+            .create_column_family("index", ColumnFamilyOptions::default())
+            .unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put_cf(&index, b"x".to_vec(), b"1".to_vec()).unwrap();
+
+        let backups = BackupEngine::open(dir.path().join("backups")).unwrap();
+        let first = backups.create_backup(&engine).unwrap();
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        let second = backups.create_backup(&engine).unwrap();
+        assert_eq!((first.id, second.id), (1, 2));
+        assert!(second.timestamp > first.timestamp);
+        assert_eq!(backups.backups().unwrap(), vec![first.clone(), second]);
+        backups.verify_backup(1).unwrap();
+
+        let restored = dir.path().join("restored");
+        backups.restore(1, &restored).unwrap();
+        assert!(matches!(
+            backups.restore(1, &restored),
+            Err(Error::InvalidOperation(_))
+        ));
+        {
+            let copy = StorageEngine::open(Options::new(&restored)).unwrap();
+            let copied_index = copy.cf_handle("index").unwrap();
+            assert_eq!(copy.get(b"a").unwrap(), Some(b"1".to_vec()));
+            assert_eq!(
+                copy.get_cf(&copied_index, b"x").unwrap(),
+                Some(b"1".to_vec())
+            );
+        }
+
+        backups.delete_backup(2).unwrap();
+        assert_eq!(backups.backups().unwrap(), vec![first]);
+        assert!(matches!(
+            backups.restore(2, dir.path().join("gone")),
+            Err(Error::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_damaged_backup_is_not_restored() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path().join("db"))).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        let backups = BackupEngine::open(dir.path().join("backups")).unwrap();
+        let info = backups.create_backup(&engine).unwrap();
+
+        let manifest = backups.backup_dir(info.id).join("MANIFEST");
+        let mut data = std::fs::read(&manifest).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(&manifest, data).unwrap();
+
+        assert!(matches!(
+            backups.verify_backup(info.id),
+            Err(Error::Corruption(_))
+        ));
+        let restored = dir.path().join("restored");
+        assert!(matches!(
+            backups.restore(info.id, &restored),
+            Err(Error::Corruption(_))
+        ));
+        assert!(!restored.exists());
+    }
+
+    #[test]
+    fn test_restore_to_timestamp_replays_archived_batches() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("archive");
+        let engine = StorageEngine::open(
+            Options::new(dir.path().join("db"))
+                .with_memtable_size(4 * 1024)
+                .with_wal_archive_dir(&archive),
+        )
+        .unwrap();
+        let backups = BackupEngine::open(dir.path().join("backups")).unwrap();
+        engine.put(key(0), b"in backup".to_vec()).unwrap();
+        let backup = backups.create_backup(&engine).unwrap();
+
+        // Enough batches of three to span several archived segments
+        let mut timestamps = Vec::new();
+        for i in 1..100 {
+            let mut batch = WriteBatch::new();
+            for j in 0..3 {
+                batch.put(key(i * 3 + j), vec![b'v'; 32]);
+            }
+            engine.write(batch, false).unwrap();
+            timestamps.push(engine.snapshot().timestamp());
+        }
+        engine.flush().unwrap();
+
+        let target = timestamps[49];
+        let restored = dir.path().join("restored");
+        // One past the end of batch 50 cuts into batch 51
+        backups
+            .restore_to_timestamp(backup.id, &restored, &archive, target + 1)
+            .unwrap();
+        let copy = StorageEngine::open(Options::new(&restored)).unwrap();
+        assert_eq!(copy.get(&key(0)).unwrap(), Some(b"in backup".to_vec()));
+        let keys = copy.scan::<[u8], _>(..).unwrap();
+        assert_eq!(keys.len(), 1 + 50 * 3);
+        assert_eq!(keys.last().unwrap().0, key(50 * 3 + 2));
+        assert_eq!(copy.recovery_report().last_timestamp, target);
+
+        assert!(matches!(
+            backups.restore_to_timestamp(
+                backup.id,
+                dir.path().join("too-early"),
+                &archive,
+                backup.timestamp - 1
+            ),
+            Err(Error::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_backups_on_the_engine_vfs() {
+        let options = Options::in_memory();
+        let vfs = Arc::clone(options.vfs());
+        let engine = StorageEngine::open(options).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+
+        let backups = BackupEngine::open_in(Arc::clone(&vfs), "/backups").unwrap();
+        let info = backups.create_backup(&engine).unwrap();
+        backups.verify_backup(info.id).unwrap();
+        backups.restore(info.id, "/restored").unwrap();
+        assert!(!Path::new("/backups").exists());
+
+        let copy = StorageEngine::open(Options::new("/restored").with_vfs(vfs)).unwrap();
+        assert_eq!(copy.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+        // A checkpoint can't reach backups on another filesystem
+        let dir = TempDir::new().unwrap();
+        let on_disk = BackupEngine::open(dir.path()).unwrap();
+        assert!(matches!(
+            on_disk.create_backup(&engine),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(on_disk.backups().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+//! Writes applied to the engine as one unit
+
+use super::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use super::{KeyRange, WAL_ENTRY_OVERHEAD};
+use crate::wal::WALEntry;
+use ferrisdb_core::{Key, Operation, Result, Timestamp, Value};
+
+use std::ops::{Bound, RangeBounds};
+use std::time::Duration;
+
+/// Writes that become durable and visible together
+///
+/// Collect puts, deletes, merges, and range deletions, then hand the batch
+/// to [`StorageEngine::write`](super::StorageEngine::write). The engine
+/// logs it as one WAL batch record and publishes it to readers only after
+/// every write is in the MemTable, so a batch is never seen or recovered
+/// in part. Later writes in a batch shadow earlier ones to the same key.
+///
+/// The plain methods write to the default column family; the `*_cf`
+/// variants write to the given one, and a batch may span several.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, StorageEngine, WriteBatch};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// engine.put(b"session:1".to_vec(), b"alice".to_vec())?;
+/// engine.put(b"session:2".to_vec(), b"bob".to_vec())?;
+///
+/// let mut batch = WriteBatch::new();
+/// batch.delete_range(b"session:".as_slice()..b"session;".as_slice());
+/// batch.put(b"session:3".to_vec(), b"carol".to_vec());
+/// engine.write(batch, true)?;
+///
+/// assert_eq!(
+///     engine.scan(b"session:".as_slice()..b"session;".as_slice())?,
+///     vec![(b"session:3".to_vec(), b"carol".to_vec())]
+/// );
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+    /// Estimated bytes the batch adds to the WAL
+    size: usize,
+}
+
+/// One write of a [`WriteBatch`]
+#[derive(Debug, Clone)]
+pub(super) struct BatchEntry {
+    pub(super) column_family: u32,
+    pub(super) operation: Operation,
+    pub(super) key: Key,
+    pub(super) value: Value,
+    /// Lifetime of a put overriding its family's TTL
+    pub(super) ttl: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum BatchOp {
+    Write(BatchEntry),
+    /// Deletes every key in the range that exists when the batch is written
+    DeleteRange(u32, KeyRange),
+}
+
+impl WriteBatch {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a key
+    pub fn put(&mut self, key: Key, value: Value) {
+        self.push(Operation::Put, key, value);
+    }
+
+    /// Sets the value of a key in a column family
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: Key, value: Value) {
+        self.push_cf(cf.id(), Operation::Put, key, value);
+    }
+
+    /// Sets the value of a key that expires after `ttl`
+    ///
+    /// Writing the batch fails unless the default column family has a TTL.
+    pub fn put_with_ttl(&mut self, key: Key, value: Value, ttl: Duration) {
+        self.push_entry(
+            DEFAULT_COLUMN_FAMILY_ID,
+            Operation::Put,
+            key,
+            value,
+            Some(ttl),
+        );
+    }
+
+    /// Sets the value of a key in a column family that expires after `ttl`
+    ///
+    /// Writing the batch fails unless the family has a TTL.
+    pub fn put_cf_with_ttl(&mut self, cf: &ColumnFamily, key: Key, value: Value, ttl: Duration) {
+        self.push_entry(cf.id(), Operation::Put, key, value, Some(ttl));
+    }
+
+    /// Deletes a key
+    pub fn delete(&mut self, key: Key) {
+        self.push(Operation::Delete, key, Vec::new());
+    }
+
+    /// Deletes a key from a column family
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: Key) {
+        self.push_cf(cf.id(), Operation::Delete, key, Vec::new());
+    }
+
+    /// Records a merge operand for a key
+    ///
+    /// Writing the batch fails unless the engine has a merge operator.
+    pub fn merge(&mut self, key: Key, operand: Value) {
+        self.push(Operation::Merge, key, operand);
+    }
+
+    /// Records a merge operand for a key in a column family
+    ///
+    /// Writing the batch fails unless the family has a merge operator.
+    pub fn merge_cf(&mut self, cf: &ColumnFamily, key: Key, operand: Value) {
+        self.push_cf(cf.id(), Operation::Merge, key, operand);
+    }
+
+    /// Deletes every key in `range`
+    ///
+    /// This covers keys written earlier in the batch and keys existing in
+    /// the engine when the batch is written; writes later in the batch
+    /// survive. The engine turns the range into a delete per covered key,
+    /// so the cost grows with the number of keys in the range.
+    pub fn delete_range<K, R>(&mut self, range: R)
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.push_range_delete(DEFAULT_COLUMN_FAMILY_ID, range);
+    }
+
+    /// Deletes every key in `range` from a column family
+    ///
+    /// See [`delete_range`](Self::delete_range).
+    pub fn delete_range_cf<K, R>(&mut self, cf: &ColumnFamily, range: R)
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.push_range_delete(cf.id(), range);
+    }
+
+    pub(super) fn push_range_delete<K, R>(&mut self, column_family: u32, range: R)
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let range = super::snapshot::owned_range(range);
+        self.size += bound_len(&range.0) + bound_len(&range.1) + WAL_ENTRY_OVERHEAD;
+        self.ops.push(BatchOp::DeleteRange(column_family, range));
+    }
+
+    /// Returns the number of writes and range deletions in the batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns true if the batch holds no writes
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Removes all writes so the batch can be reused
+    pub fn clear(&mut self) {
+        self.ops.clear();
+        self.size = 0;
+    }
+
+    /// Appends a write to the default column family
+    pub(super) fn push(&mut self, operation: Operation, key: Key, value: Value) {
+        self.push_cf(DEFAULT_COLUMN_FAMILY_ID, operation, key, value);
+    }
+
+    fn push_cf(&mut self, column_family: u32, operation: Operation, key: Key, value: Value) {
+        self.push_entry(column_family, operation, key, value, None);
+    }
+
+    /// Appends a write; later writes to a key shadow earlier ones
+    pub(super) fn push_entry(
+        &mut self,
+        column_family: u32,
+        operation: Operation,
+        key: Key,
+        value: Value,
+        ttl: Option<Duration>,
+    ) {
+        self.size += key.len() + value.len() + WAL_ENTRY_OVERHEAD;
+        self.ops.push(BatchOp::Write(BatchEntry {
+            column_family,
+            operation,
+            key,
+            value,
+            ttl,
+        }));
+    }
+
+    /// Returns the estimated bytes the batch adds to the WAL
+    pub(super) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the point writes in the order they were added
+    pub(super) fn entries(&self) -> impl Iterator<Item = &BatchEntry> {
+        self.ops.iter().filter_map(|op| match op {
+            BatchOp::Write(entry) => Some(entry),
+            BatchOp::DeleteRange(..) => None,
+        })
+    }
+
+    /// Returns the write at `position` in the order writes were added, if
+    /// it is a point write
+    pub(super) fn entry(&self, position: usize) -> Option<&BatchEntry> {
+        match self.ops.get(position)? {
+            BatchOp::Write(entry) => Some(entry),
+            BatchOp::DeleteRange(..) => None,
+        }
+    }
+
+    /// Returns the column families the batch's merges go to
+    pub(super) fn merge_column_families(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entries()
+            .filter(|entry| entry.operation == Operation::Merge)
+            .map(|entry| entry.column_family)
+    }
+
+    /// Returns the writes and range deletions in the order they were added
+    pub(super) fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+}
+
+/// Converts point writes into WAL entries timestamped from `first`
+pub(super) fn into_wal_entries(
+    entries: Vec<BatchEntry>,
+    first: Timestamp,
+) -> Result<Vec<WALEntry>> {
+    entries
+        .into_iter()
+        .zip(first..)
+        .map(|(entry, timestamp)| {
+            let wal_entry = match entry.operation {
+                Operation::Put => WALEntry::new_put(entry.key, entry.value, timestamp),
+                Operation::Delete => WALEntry::new_delete(entry.key, timestamp),
+                Operation::Merge => WALEntry::new_merge(entry.key, entry.value, timestamp),
+            };
+            Ok(wal_entry?.with_column_family(entry.column_family))
+        })
+        .collect()
+}
+
+fn bound_len(bound: &Bound<Key>) -> usize {
+    match bound {
+        Bound::Included(key) | Bound::Excluded(key) => key.len(),
+        Bound::Unbounded => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_tracks_writes_in_order() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"a".to_vec(), b"1".to_vec());
+        batch.delete_range(b"b".as_slice()..b"c".as_slice());
+        batch.merge(b"a".to_vec(), b"+".to_vec());
+        batch.delete(b"a".to_vec());
+
+        assert_eq!(batch.len(), 4);
+        assert_eq!(batch.merge_column_families().collect::<Vec<_>>(), vec![0]);
+        let operations: Vec<_> = batch.entries().map(|entry| entry.operation).collect();
+        assert_eq!(
+            operations,
+            vec![Operation::Put, Operation::Merge, Operation::Delete]
+        );
+        assert_eq!(batch.entry(2).unwrap().operation, Operation::Merge);
+        assert!(batch.entry(1).is_none());
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!(batch.size(), 0);
+    }
+}
@@ -0,0 +1,201 @@
+//! Consistent point-in-time copies of a database
+//!
+//! A checkpoint is a directory that opens as a database of its own, holding
+//! exactly the writes acknowledged before it was taken:
+//!
+//! ```text
+//!   checkpoint/
+//!   ├── MANIFEST          fresh, holding only the current file set
+//!   ├── 000012.sst        hard links to the live tables
+//!   ├── cf-1/             the same for every other column family
+//!   │   ├── MANIFEST
+//!   │   └── 000015.sst
+//!   └── wal/
+//!       └── 000017.wal    copy of the WAL tail
+//! ```
+//!
+//! SSTables never change once written, so linking them costs no space
+//! until the source compacts them away. The MANIFESTs and the active WAL
+//! segment keep growing in the source and are written or copied instead.
+//! The MemTables are flushed first, which keeps the copied tail short.
+//!
+//! Checkpoints are written through the engine's filesystem, and copy the
+//! tables instead where it can't link them.
+
+use super::column_family::{column_family_dir, DEFAULT_COLUMN_FAMILY_ID};
+use super::recovery::wal_segments_in;
+use super::EngineInner;
+use crate::files::wal_file_name;
+use crate::vfs;
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::path::Path;
+
+/// Name of the WAL directory inside a checkpoint, where `Options::new` looks
+const WAL_DIR_NAME: &str = "wal";
+
+/// Writes a checkpoint of the engine to `dir`, which must not exist
+///
+/// Writes are blocked while tables are linked and the WAL tail copied, so
+/// every family's MANIFEST and the tail describe the same moment. Returns
+/// the timestamp of the newest write in the checkpoint. A partially
+/// written checkpoint is removed on error.
+pub(super) fn create_checkpoint(inner: &EngineInner, dir: &Path) -> Result<Timestamp> {
+    if inner.tiered().is_some() {
+        return Err(Error::Unsupported(
+            "Databases with remote tables can't be checkpointed".to_string(),
+        ));
+    }
+    let vfs = &inner.options.vfs;
+    if vfs.exists(dir) {
+        return Err(Error::InvalidOperation(format!(
+            "Checkpoint directory {} already exists",
+            dir.display()
+        )));
+    }
+    inner.flush()?;
+
+    let result = (|| {
+        let wal = inner.lock_writer()?;
+        // No MemTable can be retired now; wait for those retired before
+        inner.wait_for_flush(u64::MAX)?;
+        wal.sync()?;
+
+        let mut log_number = u64::MAX;
+        for cf in inner.families() {
+            let cf_dir = if cf.id == DEFAULT_COLUMN_FAMILY_ID {
+                dir.to_path_buf()
+            } else {
+                column_family_dir(dir, cf.id)
+            };
+            cf.versions.checkpoint(&cf_dir)?;
+            log_number = log_number.min(cf.versions.manifest_state().log_number());
+        }
+
+        let wal_dir = dir.join(WAL_DIR_NAME);
+        vfs.create_dir_all(&wal_dir)?;
+        for (number, path) in wal_segments_in(vfs, &inner.options.config.wal_dir)? {
+            if number >= log_number {
+                vfs::copy(vfs.as_ref(), &path, &wal_dir.join(wal_file_name(number)))?;
+            }
+        }
+        vfs.sync_dir(&wal_dir)?;
+        vfs.sync_dir(dir)?;
+        vfs::sync_parent_dir(vfs.as_ref(), dir)?;
+        Ok(inner.oracle.last())
+    })();
+
+    if result.is_err() {
+        let _ = vfs.remove_dir_all(dir);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine, WriteBatch};
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    fn options(dir: &Path) -> Options {
+        Options::new(dir)
+            .with_memtable_size(4 * 1024)
+            .with_column_family(
+                "counters",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+            )
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_checkpoint_opens_as_independent_copy() {
+        let dir = TempDir::new().unwrap();
+        let checkpoint_dir = dir.path().join("checkpoint");
+        let engine = StorageEngine::open(options(&dir.path().join("db"))).unwrap();
+        let counters = engine
+            .create_column_family(
+                "counters",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+            )
+            .unwrap();
+        for i in 0..200 {
+            engine.put(key(i), b"before".to_vec()).unwrap();
+        }
+        engine
+            .merge_cf(&counters, b"n".to_vec(), 5u64.to_le_bytes().to_vec())
+            .unwrap();
+
+        engine.create_checkpoint(&checkpoint_dir).unwrap();
+        assert!(matches!(
+            engine.create_checkpoint(&checkpoint_dir),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        // Later writes and compactions of the source don't reach the copy
+        for i in 0..200 {
+            engine.put(key(i), b"after".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.drop_column_family(&counters).unwrap();
+
+        let copy = StorageEngine::open(options(&checkpoint_dir)).unwrap();
+        assert_eq!(copy.get(&key(7)).unwrap(), Some(b"before".to_vec()));
+        assert_eq!(copy.scan::<[u8], _>(..).unwrap().len(), 200);
+        let copied_counters = copy.cf_handle("counters").unwrap();
+        assert_eq!(
+            copy.get_cf(&copied_counters, b"n").unwrap(),
+            Some(5u64.to_le_bytes().to_vec())
+        );
+
+        copy.put(key(7), b"copy".to_vec()).unwrap();
+        assert_eq!(engine.get(&key(7)).unwrap(), Some(b"after".to_vec()));
+    }
+
+    #[test]
+    fn test_checkpoints_under_concurrent_writes_are_consistent() {
+        let dir = TempDir::new().unwrap();
+        let engine = Arc::new(StorageEngine::open(options(&dir.path().join("db"))).unwrap());
+        let done = Arc::new(AtomicBool::new(false));
+
+        // Each batch writes the next key and moves the "last" marker to it
+        let writer = {
+            let engine = Arc::clone(&engine);
+            let done = Arc::clone(&done);
+            std::thread::spawn(move || {
+                let mut i = 0;
+                while !done.load(Ordering::Acquire) {
+                    let mut batch = WriteBatch::new();
+                    batch.put(key(i), vec![b'v'; 64]);
+                    batch.put(b"last".to_vec(), key(i));
+                    engine.write(batch, false).unwrap();
+                    i += 1;
+                }
+            })
+        };
+
+        for n in 0..5 {
+            let checkpoint_dir = dir.path().join(format!("checkpoint{}", n));
+            engine.create_checkpoint(&checkpoint_dir).unwrap();
+
+            let copy = StorageEngine::open(Options::new(&checkpoint_dir)).unwrap();
+            let keys = copy.scan(key(0)..key(99999)).unwrap();
+            match copy.get(b"last").unwrap() {
+                Some(last) => assert_eq!(keys.last().unwrap().0, last),
+                None => assert!(keys.is_empty()),
+            }
+            for (i, (k, _)) in keys.iter().enumerate() {
+                assert_eq!(k, &key(i));
+            }
+        }
+
+        done.store(true, Ordering::Release);
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,703 @@
+//! Column families: independent key spaces sharing one WAL
+//!
+//! Every column family has MemTables and SSTables of its own, so keys in
+//! one never shadow keys in another, and each can be tuned separately
+//! through [`ColumnFamilyOptions`]. All families log to the same WAL, so a
+//! [`WriteBatch`](super::WriteBatch) spanning several of them still commits
+//! atomically.
+//!
+//! ```text
+//!                      shared WAL segment
+//!                              │
+//!            ┌─────────────────┼─────────────────┐
+//!            ▼                 ▼                 ▼
+//!   "default" MemTable   "index" MemTable   "meta" MemTable
+//!            │                 │                 │
+//!   data_dir/MANIFEST    cf-1/MANIFEST      cf-2/MANIFEST
+//!   data_dir/*.sst       cf-1/*.sst         cf-2/*.sst
+//! ```
+//!
+//! The default family lives directly in the data directory, and its
+//! MANIFEST also records which other families exist. Each other family
+//! keeps its MANIFEST and tables in a subdirectory named after its id.
+//!
+//! # Flushing
+//!
+//! Families switch MemTables together: when any active MemTable is full,
+//! all of them are retired along with the WAL segment logging their
+//! writes, and a flush writes each family's MemTable into that family's
+//! level 0 before the segment is deleted. Each family's MANIFEST records
+//! the newest timestamp it has persisted, so recovery replays only the
+//! entries a family is still missing, even after a crash in the middle of
+//! a flush.
+//!
+//! # Dropping
+//!
+//! Dropping a family removes it from the engine right away. Its files are
+//! deleted once the last [`ColumnFamily`] handle to it is gone; until then
+//! the handle only returns errors.
+
+use super::options::Options;
+use super::ttl::{Ttl, TtlFilter, TtlMergeOperator};
+use crate::compaction::{strategy_from_config, CompactionFilter, CompactionStrategy, Compactor};
+use crate::comparator::Comparator;
+use crate::config::{CompactionStrategyKind, MemTableKind, StorageConfig};
+use crate::memtable::MemTable;
+use crate::merge::MergeOperator;
+use crate::prefix::PrefixExtractor;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::sstable::{SSTableReader, SSTableWriter};
+use crate::utils::memory::MemoryTracker;
+use crate::version::{TableHandle, VersionSet};
+use crate::vfs;
+use ferrisdb_core::{CompressionType, Error, Result};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Name of the column family every engine has
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+/// Id of the default column family
+pub(super) const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// Returns the directory of a non-default column family
+pub(super) fn column_family_dir(data_dir: &Path, id: u32) -> PathBuf {
+    data_dir.join(format!("cf-{}", id))
+}
+
+/// Parses the id out of a column family directory name
+pub(super) fn parse_column_family_dir(name: &str) -> Option<u32> {
+    name.strip_prefix("cf-")?.parse().ok()
+}
+
+/// Settings of a single column family
+///
+/// The default column family takes these settings from the engine's
+/// [`Options`]. Other families get them when created through
+/// [`StorageEngine::create_column_family`](super::StorageEngine::create_column_family);
+/// they are not persisted, so pass them again through
+/// [`Options::with_column_family`] when reopening the engine. Families
+/// reopened without options use the defaults.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_core::CompressionType;
+/// use ferrisdb_storage::merge::U64AddOperator;
+/// use ferrisdb_storage::storage_engine::ColumnFamilyOptions;
+/// use std::sync::Arc;
+///
+/// let options = ColumnFamilyOptions::default()
+///     .with_memtable_size(1024 * 1024)
+///     .with_compression(CompressionType::None)
+///     .with_merge_operator(Arc::new(U64AddOperator));
+/// ```
+#[derive(Clone)]
+pub struct ColumnFamilyOptions {
+    memtable_size: usize,
+    memtable_kind: MemTableKind,
+    block_size: usize,
+    compression: CompressionType,
+    compaction_strategy: CompactionStrategyKind,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    ttl: Option<Duration>,
+    comparator: Arc<dyn Comparator>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+}
+
+impl ColumnFamilyOptions {
+    /// Takes the default column family's settings from the engine options
+    pub(super) fn from_options(options: &Options) -> Self {
+        let config = &options.config;
+        Self {
+            memtable_size: config.memtable_size,
+            memtable_kind: config.memtable_kind,
+            block_size: config.block_size,
+            compression: config.compression,
+            compaction_strategy: config.compaction_strategy,
+            merge_operator: options.merge_operator.clone(),
+            compaction_filter: options.compaction_filter.clone(),
+            ttl: options.ttl,
+            comparator: Arc::clone(&options.comparator),
+            prefix_extractor: options.prefix_extractor.clone(),
+        }
+    }
+
+    /// Returns the ordering of the family's keys
+    pub(super) fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Sets the size at which the family's active MemTable is flushed (in bytes)
+    pub fn with_memtable_size(mut self, memtable_size: usize) -> Self {
+        self.memtable_size = memtable_size;
+        self
+    }
+
+    /// Sets the in-memory index used by the family's MemTables
+    pub fn with_memtable_kind(mut self, kind: MemTableKind) -> Self {
+        self.memtable_kind = kind;
+        self
+    }
+
+    /// Sets the size of each data block in the family's SSTables (in bytes)
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets the compression algorithm for the family's SSTable blocks
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets how the family's SSTables are organized and picked for compaction
+    pub fn with_compaction_strategy(mut self, strategy: CompactionStrategyKind) -> Self {
+        self.compaction_strategy = strategy;
+        self
+    }
+
+    /// Sets the operator resolving merge operands in this family
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    /// Sets the filter applied to versions rewritten by compaction
+    pub fn with_compaction_filter(mut self, filter: Arc<dyn CompactionFilter>) -> Self {
+        self.compaction_filter = Some(filter);
+        self
+    }
+
+    /// Makes the family's values expire `ttl` after being written
+    ///
+    /// Like the other settings it isn't persisted, and a family's values
+    /// are stored differently with a TTL, so a family must be reopened
+    /// with a TTL exactly when it was created with one.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets how keys in this family are ordered
+    ///
+    /// The comparator is recorded in the family's SSTables, so the family
+    /// must always be reopened with a comparator of the same name.
+    pub fn with_comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
+    /// Builds the family's bloom filters over the prefixes `extractor` finds
+    ///
+    /// See [`Options::with_prefix_extractor`].
+    pub fn with_prefix_extractor(mut self, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+}
+
+impl Default for ColumnFamilyOptions {
+    fn default() -> Self {
+        Self::from_options(&Options::default())
+    }
+}
+
+impl fmt::Debug for ColumnFamilyOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColumnFamilyOptions")
+            .field("memtable_size", &self.memtable_size)
+            .field("memtable_kind", &self.memtable_kind)
+            .field("block_size", &self.block_size)
+            .field("compression", &self.compression)
+            .field("compaction_strategy", &self.compaction_strategy)
+            .field(
+                "merge_operator",
+                &self.merge_operator.as_ref().map(|op| op.name()),
+            )
+            .field(
+                "compaction_filter",
+                &self.compaction_filter.as_ref().map(|filter| filter.name()),
+            )
+            .field("ttl", &self.ttl)
+            .field("comparator", &self.comparator.name())
+            .field("prefix_extractor", &self.prefix_extractor)
+            .finish()
+    }
+}
+
+/// Handle to a column family of an engine
+///
+/// Obtained from [`StorageEngine::cf_handle`](super::StorageEngine::cf_handle)
+/// or [`StorageEngine::create_column_family`](super::StorageEngine::create_column_family),
+/// and passed to the `*_cf` methods of the engine, [`WriteBatch`](super::WriteBatch)
+/// and [`Snapshot`](super::Snapshot). Handles are cheap to clone.
+#[derive(Clone)]
+pub struct ColumnFamily {
+    pub(super) data: Arc<ColumnFamilyData>,
+}
+
+impl ColumnFamily {
+    /// Returns the family's name
+    pub fn name(&self) -> &str {
+        &self.data.name
+    }
+
+    /// Returns the family's id, unique for the lifetime of the database
+    pub fn id(&self) -> u32 {
+        self.data.id
+    }
+
+    /// Returns true once the family has been dropped
+    pub fn is_dropped(&self) -> bool {
+        self.data.dropped.load(Ordering::Acquire)
+    }
+
+    /// Returns true if the family has a merge operator, which merges into
+    /// it need
+    pub fn has_merge_operator(&self) -> bool {
+        self.data.merge_operator.is_some()
+    }
+}
+
+impl fmt::Debug for ColumnFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColumnFamily")
+            .field("id", &self.data.id)
+            .field("name", &self.data.name)
+            .field("dropped", &self.is_dropped())
+            .finish()
+    }
+}
+
+/// The state of one column family shared by the engine and its handles
+pub(super) struct ColumnFamilyData {
+    pub(super) id: u32,
+    pub(super) name: String,
+    /// The engine configuration with the family's settings applied
+    pub(super) config: StorageConfig,
+    pub(super) merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Ordering of the family's keys
+    pub(super) comparator: Arc<dyn Comparator>,
+    /// Prefixes of the family's bloom filters
+    pub(super) prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    pub(super) versions: Arc<VersionSet>,
+    pub(super) strategy: Arc<dyn CompactionStrategy>,
+    pub(super) compactor: Compactor,
+    /// Limiter shared by the engine's flushes and compactions
+    rate_limiter: Arc<RateLimiter>,
+    /// The engine's memory accounting, charged by the family's readers
+    memory: Arc<MemoryTracker>,
+    /// Set in families whose values expire
+    pub(super) ttl: Option<Ttl>,
+    dropped: AtomicBool,
+}
+
+impl ColumnFamilyData {
+    /// Opens the family's tables in `dir`, creating it if needed
+    pub(super) fn open(
+        id: u32,
+        name: &str,
+        dir: &Path,
+        engine: &Options,
+        options: ColumnFamilyOptions,
+        rate_limiter: &Arc<RateLimiter>,
+    ) -> Result<Self> {
+        let versions = VersionSet::open_in(&engine.vfs, dir, Arc::clone(&options.comparator))?;
+        Ok(Self::new(id, name, versions, engine, options, rate_limiter))
+    }
+
+    /// Opens the family's tables in `dir` without writing to it
+    ///
+    /// Edits to the family fail, and dropping it leaves its files behind.
+    pub(super) fn open_read_only(
+        id: u32,
+        name: &str,
+        dir: &Path,
+        engine: &Options,
+        options: ColumnFamilyOptions,
+        rate_limiter: &Arc<RateLimiter>,
+    ) -> Result<Self> {
+        let versions =
+            VersionSet::open_read_only_in(&engine.vfs, dir, Arc::clone(&options.comparator))?;
+        Ok(Self::new(id, name, versions, engine, options, rate_limiter))
+    }
+
+    fn new(
+        id: u32,
+        name: &str,
+        versions: VersionSet,
+        engine: &Options,
+        options: ColumnFamilyOptions,
+        rate_limiter: &Arc<RateLimiter>,
+    ) -> Self {
+        let mut config = engine.config.clone();
+        config.data_dir = versions.dir().to_path_buf();
+        config.memtable_size = options.memtable_size;
+        config.memtable_kind = options.memtable_kind;
+        config.block_size = options.block_size;
+        config.compression = options.compression;
+        config.compaction_strategy = options.compaction_strategy;
+
+        let ttl = options
+            .ttl
+            .map(|default| Ttl::new(default, Arc::clone(&engine.clock)));
+        let mut merge_operator = options.merge_operator;
+        let mut compaction_filter = options.compaction_filter;
+        if let Some(ttl) = &ttl {
+            merge_operator = merge_operator.map(|operator| {
+                Arc::new(TtlMergeOperator::new(operator, ttl.clone())) as Arc<dyn MergeOperator>
+            });
+            compaction_filter = Some(Arc::new(TtlFilter::new(compaction_filter, ttl.clone())));
+        }
+
+        let versions = Arc::new(versions.with_paranoid_checks(config.paranoid_checks));
+        let mut compactor = Compactor::new(Arc::clone(&versions), &config)
+            .with_rate_limiter(Arc::clone(rate_limiter));
+        if let Some(operator) = &merge_operator {
+            compactor = compactor.with_merge_operator(Arc::clone(operator));
+        }
+        if let Some(filter) = compaction_filter {
+            compactor = compactor.with_compaction_filter(filter);
+        }
+        if let Some(extractor) = &options.prefix_extractor {
+            compactor = compactor.with_prefix_extractor(Arc::clone(extractor));
+        }
+
+        Self {
+            id,
+            name: name.to_string(),
+            strategy: strategy_from_config(&config),
+            merge_operator,
+            comparator: options.comparator,
+            prefix_extractor: options.prefix_extractor,
+            config,
+            versions,
+            compactor,
+            rate_limiter: Arc::clone(rate_limiter),
+            memory: Arc::clone(&engine.memory),
+            ttl,
+            dropped: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a fresh, empty MemTable with the family's settings
+    pub(super) fn new_memtable(&self) -> Arc<MemTable> {
+        Arc::new(MemTable::with_comparator(
+            self.config.memtable_size,
+            self.config.memtable_kind,
+            Arc::clone(&self.comparator),
+        ))
+    }
+
+    /// Creates a writer of a table at `path` with the family's settings,
+    /// its writes charged to the rate limiter at `priority`
+    ///
+    /// Flushes write at high priority, ahead of compactions, while bulk
+    /// imports write at low priority like compactions.
+    pub(super) fn table_writer(&self, path: &Path, priority: IoPriority) -> Result<SSTableWriter> {
+        let mut writer =
+            SSTableWriter::with_block_size_in(self.versions.vfs(), path, self.config.block_size)?
+                .with_comparator(Arc::clone(&self.comparator))
+                .with_rate_limiter(Arc::clone(&self.rate_limiter), priority);
+        if let Some(extractor) = &self.prefix_extractor {
+            let bits_per_key = self.config.bloom_filter_bits_per_key.max(1) as usize;
+            writer = writer.with_prefix_extractor(Arc::clone(extractor), bits_per_key);
+        }
+        Ok(writer)
+    }
+
+    /// Opens a reader over one of the family's tables, with direct I/O if
+    /// `use_direct_reads` is set and checking block checksums with
+    /// `paranoid_checks`, its memory charged to the engine
+    pub(super) fn open_table(&self, table: &TableHandle) -> Result<SSTableReader> {
+        let vfs = vfs::with_direct_io(self.versions.vfs(), self.config.use_direct_reads);
+        let reader = table.open_reader_in(&vfs)?;
+        Ok(reader
+            .with_verify_checksums(self.config.paranoid_checks)
+            .with_memory_tracker(&self.memory))
+    }
+
+    /// Fails for a family that has been dropped
+    pub(super) fn check_live(&self) -> Result<()> {
+        if self.dropped.load(Ordering::Acquire) {
+            return Err(Error::InvalidOperation(format!(
+                "Column family {:?} has been dropped",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Marks the family dropped; its files go with the last handle
+    pub(super) fn mark_dropped(&self) {
+        self.dropped.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for ColumnFamilyData {
+    fn drop(&mut self) {
+        if self.dropped.load(Ordering::Acquire) && !self.versions.is_read_only() {
+            let dir = self.versions.dir();
+            if let Err(e) = self.versions.vfs().remove_dir_all(dir) {
+                log::warn!(
+                    "Failed to delete dropped column family {}: {}",
+                    dir.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::recovery::wal_segments_in;
+    use super::super::{Options, StorageEngine, WriteBatch, COMPACTION_JOB, FLUSH_JOB};
+    use super::*;
+    use crate::manifest::{SSTableMeta, VersionEdit};
+    use crate::merge::U64AddOperator;
+    use crate::sstable::InternalKey;
+    use ferrisdb_core::Operation;
+    use tempfile::TempDir;
+
+    fn options(dir: &TempDir) -> Options {
+        Options::new(dir.path()).with_column_family(
+            "index",
+            ColumnFamilyOptions::default()
+                .with_memtable_size(4 * 1024)
+                .with_merge_operator(Arc::new(U64AddOperator)),
+        )
+    }
+
+    fn index_options(options: &Options) -> ColumnFamilyOptions {
+        options.column_families["index"].clone()
+    }
+
+    fn counter(n: u64) -> Vec<u8> {
+        n.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_column_families_are_separate_key_spaces() {
+        let dir = TempDir::new().unwrap();
+        let options = options(&dir);
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        let default = engine.cf_handle(DEFAULT_COLUMN_FAMILY).unwrap();
+        assert_eq!(default.id(), DEFAULT_COLUMN_FAMILY_ID);
+
+        let index = engine
+            .create_column_family("index", index_options(&options))
+            .unwrap();
+        assert_eq!(index.id(), 1);
+        assert_eq!(engine.column_family_names(), vec!["default", "index"]);
+        assert!(matches!(
+            engine.create_column_family("index", ColumnFamilyOptions::default()),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        engine.put(b"k".to_vec(), b"default".to_vec()).unwrap();
+        engine
+            .put_cf(&index, b"k".to_vec(), b"index".to_vec())
+            .unwrap();
+        engine
+            .put_cf(&index, b"only".to_vec(), b"index".to_vec())
+            .unwrap();
+        assert_eq!(engine.get(b"k").unwrap(), Some(b"default".to_vec()));
+        assert_eq!(
+            engine.get_cf(&index, b"k").unwrap(),
+            Some(b"index".to_vec())
+        );
+        assert_eq!(engine.get(b"only").unwrap(), None);
+        assert_eq!(engine.scan_cf::<[u8], _>(&index, ..).unwrap().len(), 2);
+
+        engine.delete_cf(&index, b"k".to_vec()).unwrap();
+        assert_eq!(engine.get(b"k").unwrap(), Some(b"default".to_vec()));
+        assert_eq!(engine.get_cf(&index, b"k").unwrap(), None);
+
+        // Only the index family has a merge operator
+        engine.merge_cf(&index, b"n".to_vec(), counter(2)).unwrap();
+        engine.merge_cf(&index, b"n".to_vec(), counter(3)).unwrap();
+        assert_eq!(engine.get_cf(&index, b"n").unwrap(), Some(counter(5)));
+        assert!(matches!(
+            engine.merge(b"n".to_vec(), counter(1)),
+            Err(Error::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_batch_spanning_families_survives_crash() {
+        let dir = TempDir::new().unwrap();
+        let options = options(&dir);
+        {
+            let engine = StorageEngine::open(options.clone()).unwrap();
+            let index = engine
+                .create_column_family("index", index_options(&options))
+                .unwrap();
+            engine
+                .put_cf(&index, b"a".to_vec(), b"stale".to_vec())
+                .unwrap();
+
+            let mut batch = WriteBatch::new();
+            batch.put(b"a".to_vec(), b"default".to_vec());
+            batch.delete_range_cf(&index, b"a".as_slice()..b"b".as_slice());
+            batch.put_cf(&index, b"b".to_vec(), b"index".to_vec());
+            batch.merge_cf(&index, b"n".to_vec(), counter(7));
+            engine.write(batch, true).unwrap();
+            // Leave the writes in the WAL instead of flushing them on close
+            engine.crash();
+        }
+
+        let engine = StorageEngine::open(options).unwrap();
+        let index = engine.cf_handle("index").unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 5);
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"default".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+        assert_eq!(
+            engine.scan_cf::<[u8], _>(&index, ..).unwrap(),
+            vec![
+                (b"b".to_vec(), b"index".to_vec()),
+                (b"n".to_vec(), counter(7))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flushed_families_persist_across_reopen() {
+        let dir = TempDir::new().unwrap();
+        let options = options(&dir);
+        {
+            let engine = StorageEngine::open(options.clone()).unwrap();
+            let index = engine
+                .create_column_family("index", index_options(&options))
+                .unwrap();
+            for i in 0..100u64 {
+                let key = format!("key{:03}", i).into_bytes();
+                engine.put(key.clone(), b"default".to_vec()).unwrap();
+                engine.put_cf(&index, key, counter(i)).unwrap();
+            }
+            engine.flush().unwrap();
+
+            // The index family's small MemTable switched every family
+            assert!(!index.data.versions.current().files(0).is_empty());
+            assert!(!engine.inner.default.versions.current().files(0).is_empty());
+            assert!(index.data.versions.dir().ends_with("cf-1"));
+            engine.close().unwrap();
+        }
+
+        let engine = StorageEngine::open(options).unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 0);
+        let index = engine.cf_handle("index").unwrap();
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap().len(), 100);
+        assert_eq!(engine.get_cf(&index, b"key042").unwrap(), Some(counter(42)));
+        engine
+            .merge_cf(&index, b"key042".to_vec(), counter(1))
+            .unwrap();
+        assert_eq!(engine.get_cf(&index, b"key042").unwrap(), Some(counter(43)));
+    }
+
+    #[test]
+    fn test_recovery_replays_only_what_each_family_missed() {
+        let dir = TempDir::new().unwrap();
+        let options = options(&dir);
+        let (written, last) = {
+            let engine = StorageEngine::open(options.clone()).unwrap();
+            let index = engine
+                .create_column_family("index", index_options(&options))
+                .unwrap();
+            engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+            let written = engine.last_timestamp();
+            engine.put_cf(&index, b"x".to_vec(), b"1".to_vec()).unwrap();
+            let last = engine.last_timestamp();
+            engine.crash();
+            (written, last)
+        };
+
+        // Crash in the middle of a flush: the default family persisted
+        // the segment, the index family did not
+        {
+            let (wal_number, _) = wal_segments_in(&options.vfs, &options.config.wal_dir)
+                .unwrap()
+                .pop()
+                .unwrap();
+            let versions = VersionSet::open(dir.path()).unwrap();
+            let number = versions.new_file_number();
+            let mut writer = SSTableWriter::new(versions.table_path(number)).unwrap();
+            writer
+                .add(
+                    InternalKey::new(b"a".to_vec(), written),
+                    b"1".to_vec(),
+                    Operation::Put,
+                )
+                .unwrap();
+            let meta = SSTableMeta::new(number, &writer.finish().unwrap());
+            let mut edit = VersionEdit::default();
+            edit.add_file(0, meta);
+            edit.set_last_timestamp(last);
+            edit.set_log_number(wal_number + 1);
+            versions.log_and_apply(edit).unwrap();
+        }
+
+        let engine = StorageEngine::open(options).unwrap();
+        let index = engine.cf_handle("index").unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 1);
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get_cf(&index, b"x").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.inner.default.versions.current().files(0).len(), 1);
+    }
+
+    #[test]
+    fn test_dropped_family_is_deleted_with_its_last_handle() {
+        let dir = TempDir::new().unwrap();
+        let options = options(&dir);
+        let cf_dir = column_family_dir(dir.path(), 1);
+        {
+            let engine = StorageEngine::open(options.clone()).unwrap();
+            let default = engine.cf_handle(DEFAULT_COLUMN_FAMILY).unwrap();
+            assert!(matches!(
+                engine.drop_column_family(&default),
+                Err(Error::InvalidOperation(_))
+            ));
+
+            let index = engine
+                .create_column_family("index", index_options(&options))
+                .unwrap();
+            engine.put_cf(&index, b"a".to_vec(), b"1".to_vec()).unwrap();
+            engine.flush().unwrap();
+            engine.put_cf(&index, b"b".to_vec(), b"1".to_vec()).unwrap();
+
+            engine.drop_column_family(&index).unwrap();
+            assert!(index.is_dropped());
+            assert!(engine.cf_handle("index").is_none());
+            assert!(engine.get_cf(&index, b"a").is_err());
+            assert!(engine.put_cf(&index, b"c".to_vec(), b"1".to_vec()).is_err());
+            assert!(engine.drop_column_family(&index).is_err());
+            assert!(cf_dir.exists());
+
+            // The flush job, and the compaction job it wakes, may still
+            // hold the family; whichever lets go last deletes it
+            drop(index);
+            assert!(engine.inner.scheduler.wait_idle(FLUSH_JOB));
+            assert!(engine.inner.scheduler.wait_idle(COMPACTION_JOB));
+            assert!(!cf_dir.exists());
+            engine.crash();
+        }
+
+        // The unflushed write to the dropped family is not replayed, and
+        // its id is not reused
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 0);
+        assert_eq!(engine.column_family_names(), vec!["default"]);
+        let index = engine
+            .create_column_family("index", index_options(&options))
+            .unwrap();
+        assert_eq!(index.id(), 2);
+        assert_eq!(engine.get_cf(&index, b"a").unwrap(), None);
+    }
+}
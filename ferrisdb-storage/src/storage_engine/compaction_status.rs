@@ -0,0 +1,310 @@
+//! What compaction is doing and what it has left to do
+//!
+//! When disk I/O is saturated, [`StorageEngine::compaction_status`](super::StorageEngine::compaction_status)
+//! tells whether compaction is behind it: the compactions running, with
+//! the tables they read and how far they got, and the compaction each
+//! column family's strategy would pick next. Their numbers also show up in
+//! the [`Statistics`](super::Statistics) and as the
+//! `ferrisdb.num-running-compactions` and `ferrisdb.compaction-pending`
+//! properties.
+
+use super::column_family::ColumnFamilyData;
+use super::secondary::OpenMode;
+use super::EngineInner;
+use crate::compaction::{CompactionProgress, CompactionTask};
+
+use parking_lot::Mutex;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A table a compaction reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionInput {
+    /// Level the table is in
+    pub level: usize,
+    /// File number of the table
+    pub number: u64,
+    /// Size of the table file in bytes
+    pub file_size: u64,
+    /// Entries in the table, counting every version and tombstone
+    pub entry_count: u64,
+}
+
+/// How far a running compaction got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionProgressInfo {
+    /// Input entries read so far
+    pub entries_read: u64,
+    /// Entries written to output tables so far
+    pub entries_written: u64,
+    /// Time since the compaction started
+    pub elapsed: Duration,
+}
+
+/// A compaction running or due to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionJob {
+    /// Name of the column family compacted
+    pub column_family: String,
+    /// Tables read, newest data first
+    pub inputs: Vec<CompactionInput>,
+    /// Level receiving the output tables
+    pub output_level: usize,
+    /// Whether nothing older than the inputs exists for their keys
+    pub bottommost: bool,
+    /// Whether [`compact_range`](super::StorageEngine::compact_range) asked
+    /// for the compaction, rather than the strategy picking it
+    pub manual: bool,
+    /// How far the compaction got, or `None` if it hasn't started
+    pub progress: Option<CompactionProgressInfo>,
+}
+
+impl CompactionJob {
+    fn new(cf: &ColumnFamilyData, task: &CompactionTask, manual: bool) -> Self {
+        Self {
+            column_family: cf.name.clone(),
+            inputs: task
+                .inputs
+                .iter()
+                .map(|(level, table)| CompactionInput {
+                    level: *level,
+                    number: table.meta().number,
+                    file_size: table.meta().file_size,
+                    entry_count: table.meta().entry_count,
+                })
+                .collect(),
+            output_level: task.output_level,
+            bottommost: task.bottommost,
+            manual,
+            progress: None,
+        }
+    }
+
+    /// Returns the bytes of the input tables, all of which the compaction reads
+    pub fn input_bytes(&self) -> u64 {
+        self.inputs.iter().map(|input| input.file_size).sum()
+    }
+
+    /// Returns the fraction of the input entries read so far, 0 before
+    /// the compaction starts
+    pub fn fraction_done(&self) -> f64 {
+        let total: u64 = self.inputs.iter().map(|input| input.entry_count).sum();
+        match self.progress {
+            Some(progress) if total > 0 => (progress.entries_read as f64 / total as f64).min(1.0),
+            _ => 0.0,
+        }
+    }
+
+    /// Returns the estimated bytes of the input read so far
+    pub fn estimated_bytes_read(&self) -> u64 {
+        (self.input_bytes() as f64 * self.fraction_done()) as u64
+    }
+
+    /// Returns whether the compaction reads the table `number` of `column_family`
+    fn reads(&self, column_family: &str, number: u64) -> bool {
+        self.column_family == column_family
+            && self.inputs.iter().any(|input| input.number == number)
+    }
+}
+
+/// Compactions running in an engine and those due next
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStatus {
+    /// Compactions running, oldest first
+    pub running: Vec<CompactionJob>,
+    /// The compaction each column family's strategy would pick next, for
+    /// the families with one due that isn't already running
+    pub pending: Vec<CompactionJob>,
+    /// Bytes compaction has to rewrite to reach the strategies' targets
+    pub pending_compaction_bytes: u64,
+}
+
+/// A compaction being run, with the counters its compactor updates
+struct Running {
+    job: CompactionJob,
+    started: Instant,
+    progress: CompactionProgress,
+}
+
+/// Compactions the engine is running
+#[derive(Default)]
+pub(super) struct RunningCompactions {
+    jobs: Mutex<Vec<Arc<Running>>>,
+}
+
+impl RunningCompactions {
+    /// Registers a compaction of `task` until the returned guard is dropped
+    pub(super) fn start(
+        &self,
+        cf: &ColumnFamilyData,
+        task: &CompactionTask,
+        manual: bool,
+    ) -> RunningCompaction<'_> {
+        let running = Arc::new(Running {
+            job: CompactionJob::new(cf, task, manual),
+            started: Instant::now(),
+            progress: CompactionProgress::default(),
+        });
+        self.jobs.lock().push(Arc::clone(&running));
+        RunningCompaction {
+            list: self,
+            running,
+        }
+    }
+
+    /// Returns the running compactions with their progress so far
+    pub(super) fn jobs(&self) -> Vec<CompactionJob> {
+        self.jobs
+            .lock()
+            .iter()
+            .map(|running| running.job())
+            .collect()
+    }
+}
+
+impl Running {
+    /// Returns the job with its progress so far
+    fn job(&self) -> CompactionJob {
+        CompactionJob {
+            progress: Some(CompactionProgressInfo {
+                entries_read: self.progress.entries_read(),
+                entries_written: self.progress.entries_written(),
+                elapsed: self.started.elapsed(),
+            }),
+            ..self.job.clone()
+        }
+    }
+}
+
+/// A registered running compaction, unregistered when dropped
+pub(super) struct RunningCompaction<'a> {
+    list: &'a RunningCompactions,
+    running: Arc<Running>,
+}
+
+impl RunningCompaction<'_> {
+    /// Returns the counters to pass to the compactor
+    pub(super) fn progress(&self) -> &CompactionProgress {
+        &self.running.progress
+    }
+
+    /// Returns the job with its progress so far
+    pub(super) fn job(&self) -> CompactionJob {
+        self.running.job()
+    }
+}
+
+impl Drop for RunningCompaction<'_> {
+    fn drop(&mut self) {
+        self.list
+            .jobs
+            .lock()
+            .retain(|running| !Arc::ptr_eq(running, &self.running));
+    }
+}
+
+impl EngineInner {
+    /// Returns the compactions running and due
+    pub(super) fn compaction_status(&self) -> CompactionStatus {
+        let running = self.running_compactions.jobs();
+        let mut status = CompactionStatus::default();
+        for cf in self.families() {
+            let version = cf.versions.current();
+            status.pending_compaction_bytes += cf.strategy.pending_compaction_bytes(&version);
+            status
+                .pending
+                .extend(self.pending_compaction(&cf, &running));
+        }
+        status.running = running;
+        status
+    }
+
+    /// Returns the compaction the strategy of `cf` would pick next, unless
+    /// it reads a table one of `running` already does
+    ///
+    /// Only primary engines compact, so nothing is due in others.
+    pub(super) fn pending_compaction(
+        &self,
+        cf: &ColumnFamilyData,
+        running: &[CompactionJob],
+    ) -> Option<CompactionJob> {
+        if self.mode != OpenMode::Primary {
+            return None;
+        }
+        let task = cf.strategy.pick_compaction(&cf.versions.current())?;
+        let busy = task.inputs.iter().any(|(_, table)| {
+            running
+                .iter()
+                .any(|job| job.reads(&cf.name, table.meta().number))
+        });
+        (!busy).then(|| CompactionJob::new(cf, &task, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_status_shows_pending_and_running_compactions() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        // Holding the lock keeps the compaction job from running any
+        let held = engine.inner.compaction.lock();
+        for table in 0..4u64 {
+            for i in 0..5u64 {
+                engine
+                    .put((table * 5 + i).to_be_bytes().to_vec(), b"v".to_vec())
+                    .unwrap();
+            }
+            engine.flush().unwrap();
+        }
+
+        let status = engine.compaction_status();
+        assert!(status.running.is_empty());
+        assert_eq!(status.pending.len(), 1);
+        let pending = &status.pending[0];
+        assert_eq!(pending.column_family, "default");
+        assert_eq!(pending.inputs.len(), 4);
+        assert!(pending.inputs.iter().all(|input| input.level == 0));
+        assert_eq!(pending.output_level, 1);
+        assert!(!pending.manual);
+        assert_eq!(pending.progress, None);
+        assert_eq!(pending.fraction_done(), 0.0);
+        assert_eq!(engine.statistics().pending_compactions, 1);
+
+        // A compaction registered as running hides the same one as pending
+        let cf = Arc::clone(&engine.inner.default);
+        let task = cf.strategy.pick_compaction(&cf.versions.current()).unwrap();
+        let running = engine.inner.running_compactions.start(&cf, &task, true);
+        let status = engine.compaction_status();
+        assert!(status.pending.is_empty());
+        assert_eq!(status.running.len(), 1);
+        assert!(status.running[0].manual);
+        assert_eq!(status.running[0].input_bytes(), pending.input_bytes());
+        assert_eq!(
+            engine.property(super::super::properties::NUM_RUNNING_COMPACTIONS),
+            Some("1".to_string())
+        );
+
+        let snapshots = [u64::MAX];
+        cf.compactor
+            .run_tracked(&task, &snapshots, running.progress())
+            .unwrap();
+        let job = &engine.compaction_status().running[0];
+        assert_eq!(job.progress.unwrap().entries_read, 20);
+        assert_eq!(job.progress.unwrap().entries_written, 20);
+        assert_eq!(job.fraction_done(), 1.0);
+        assert_eq!(job.estimated_bytes_read(), job.input_bytes());
+
+        drop(running);
+        drop(held);
+        let status = engine.compaction_status();
+        assert!(status.running.is_empty());
+        assert!(status.pending.is_empty());
+        assert_eq!(engine.statistics().running_compactions, 0);
+    }
+}
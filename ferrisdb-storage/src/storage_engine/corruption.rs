@@ -0,0 +1,317 @@
+//! Reports of the corrupt data an engine ran into
+//!
+//! Whenever a read, a scrub, background work or WAL replay finds damaged
+//! data, the engine records a [`CorruptionReport`]: the file and offset of
+//! the damage and, for a checksum mismatch, the checksum stored with the
+//! data and the one its bytes have. Reports are appended as JSON lines to
+//! the `CORRUPTION` file of the data directory and loaded back when the
+//! engine opens, so damage found before a restart stays known:
+//!
+//! ```text
+//! {"detected_at_micros":1700000000000000,"detected_by":"get","column_family":"default","path":"./data/000007.sst","offset":4096,"expected_checksum":3735928559,"actual_checksum":3405691582,"error":"Corruption detected: Block checksum mismatch: ..."}
+//! ```
+//!
+//! [`StorageEngine::corruption_reports`](super::StorageEngine::corruption_reports)
+//! returns them, and the statistics count them. The same damage is
+//! reported once however often it is read, while
+//! [listeners](super::EventListener::on_corruption_detected) hear of every
+//! operation that fails on it. Engines opened read-only keep new reports
+//! in memory and leave the file as it is.
+
+use crate::clock::{Clock, SystemClock};
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::error::{ChecksumMismatch, ErrorCode};
+use ferrisdb_core::{Error, Result};
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Name of the file in the data directory reports are appended to
+pub(super) const CORRUPTION_FILE_NAME: &str = "CORRUPTION";
+
+/// Damage the engine found, with where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionReport {
+    /// When the damage was found, since the Unix epoch by the engine's
+    /// clock
+    pub detected_at: Duration,
+    /// What ran into the damage, as in
+    /// [`CorruptionInfo::detected_by`](super::CorruptionInfo::detected_by),
+    /// or `"recovery"` for WAL replay
+    pub detected_by: String,
+    /// Name of the column family read, if known
+    pub column_family: Option<String>,
+    /// Path of the damaged file, if known
+    pub path: Option<PathBuf>,
+    /// Offset of the damage in the file, if known
+    pub offset: Option<u64>,
+    /// The checksums that didn't match, if that was the damage
+    pub checksum: Option<ChecksumMismatch>,
+    /// What the operation failed with
+    pub error: String,
+}
+
+impl CorruptionReport {
+    fn to_json(&self) -> Value {
+        json!({
+            "detected_at_micros": self.detected_at.as_micros() as u64,
+            "detected_by": self.detected_by,
+            "column_family": self.column_family,
+            "path": self.path,
+            "offset": self.offset,
+            "expected_checksum": self.checksum.map(|checksum| checksum.expected),
+            "actual_checksum": self.checksum.map(|checksum| checksum.actual),
+            "error": self.error,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let checksum = |name: &str| value[name].as_u64().map(|checksum| checksum as u32);
+        Some(Self {
+            detected_at: Duration::from_micros(value["detected_at_micros"].as_u64()?),
+            detected_by: value["detected_by"].as_str()?.to_string(),
+            column_family: value["column_family"].as_str().map(str::to_string),
+            path: value["path"].as_str().map(PathBuf::from),
+            offset: value["offset"].as_u64(),
+            checksum: checksum("expected_checksum")
+                .zip(checksum("actual_checksum"))
+                .map(|(expected, actual)| ChecksumMismatch { expected, actual }),
+            error: value["error"].as_str()?.to_string(),
+        })
+    }
+
+    /// Returns true if both report the same damage
+    fn same_damage(&self, other: &CorruptionReport) -> bool {
+        self.path == other.path && self.offset == other.offset && self.error == other.error
+    }
+}
+
+/// The reports of an engine, kept in memory and in its `CORRUPTION` file
+pub(super) struct CorruptionLog {
+    /// Filesystem the file lives on
+    vfs: Arc<dyn Vfs>,
+    /// Path of the file, `None` to keep reports in memory only
+    path: Option<PathBuf>,
+    /// Gives the reports their time
+    clock: Arc<dyn Clock>,
+    /// Every report so far, oldest first
+    reports: Mutex<Vec<CorruptionReport>>,
+}
+
+impl CorruptionLog {
+    /// Loads the reports of the `CORRUPTION` file in `dir`, and appends
+    /// new ones to it unless `read_only`
+    ///
+    /// Lines that don't parse, such as one cut short by a crash, are
+    /// skipped.
+    pub(super) fn open(
+        vfs: &Arc<dyn Vfs>,
+        dir: &Path,
+        clock: &Arc<dyn Clock>,
+        read_only: bool,
+    ) -> Result<Self> {
+        let path = dir.join(CORRUPTION_FILE_NAME);
+        let mut contents = String::new();
+        match vfs.open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut contents)
+                    .map_err(|e| Error::from(e).with_path(&path))?;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::from(e).with_path(&path)),
+        }
+        let reports = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .filter_map(|value| CorruptionReport::from_json(&value))
+            .collect();
+        Ok(Self {
+            vfs: Arc::clone(vfs),
+            path: (!read_only).then_some(path),
+            clock: Arc::clone(clock),
+            reports: Mutex::new(reports),
+        })
+    }
+
+    /// Returns every report, oldest first
+    pub(super) fn reports(&self) -> Vec<CorruptionReport> {
+        self.reports.lock().clone()
+    }
+
+    /// Returns the number of reports
+    pub(super) fn len(&self) -> usize {
+        self.reports.lock().len()
+    }
+
+    /// Records the damage `error` reports, if it reports corrupt data
+    /// that wasn't reported before
+    pub(super) fn record(&self, error: &Error, column_family: Option<&str>, detected_by: &str) {
+        if error.code() != ErrorCode::Corruption {
+            return;
+        }
+        let context = error.context();
+        let report = CorruptionReport {
+            detected_at: self.clock.now(),
+            detected_by: detected_by.to_string(),
+            column_family: column_family.map(str::to_string),
+            path: context.and_then(|context| context.path.clone()),
+            offset: context.and_then(|context| context.offset),
+            checksum: context.and_then(|context| context.checksum),
+            error: error.to_string(),
+        };
+
+        let mut reports = self.reports.lock();
+        if reports.iter().any(|known| known.same_damage(&report)) {
+            return;
+        }
+        log::error!("Corrupt data found by {}: {}", detected_by, report.error);
+        if let Some(path) = &self.path {
+            if let Err(e) = self.append(path, &report) {
+                log::warn!("Failed to write to {}: {}", path.display(), e);
+            }
+        }
+        reports.push(report);
+    }
+
+    /// Appends `report` to the file as a line of JSON
+    fn append(&self, path: &Path, report: &CorruptionReport) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(&report.to_json())?;
+        line.push(b'\n');
+        let mut file = self.vfs.open_or_create(path)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&line)?;
+        file.sync_all()
+    }
+}
+
+impl Default for CorruptionLog {
+    /// A log keeping its reports in memory only
+    fn default() -> Self {
+        Self {
+            vfs: vfs::os(),
+            path: None,
+            clock: Arc::new(SystemClock),
+            reports: Mutex::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{properties, Options, StorageEngine};
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::files::wal_file_name;
+    use crate::wal::{WALEntry, WALWriter};
+    use ferrisdb_core::SyncMode;
+    use tempfile::TempDir;
+
+    /// Flips a bit of the only table's value, returning the table's path
+    fn damage_table(engine: &StorageEngine) -> PathBuf {
+        let path = engine.inner.default.versions.current().files(0)[0]
+            .path()
+            .to_path_buf();
+        let mut file = std::fs::read(&path).unwrap();
+        let at = file
+            .windows(b"damaged".len())
+            .position(|window| window == b"damaged")
+            .unwrap();
+        file[at] ^= 0x01;
+        std::fs::write(&path, file).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_damage_is_reported_once_and_kept_across_restarts() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(1_700_000_000)));
+        let options = Options::new(dir.path())
+            .with_clock(clock)
+            .with_paranoid_checks(true);
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        engine
+            .put(b"key".to_vec(), b"damaged value".to_vec())
+            .unwrap();
+        engine.flush().unwrap();
+        assert!(engine.corruption_reports().is_empty());
+
+        let path = damage_table(&engine);
+        for _ in 0..3 {
+            assert!(engine.get(b"key").is_err());
+        }
+        let reports = engine.corruption_reports();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.detected_at, Duration::from_secs(1_700_000_000));
+        assert_eq!(report.detected_by, "get");
+        assert_eq!(report.column_family.as_deref(), Some("default"));
+        assert_eq!(report.path.as_ref(), Some(&path));
+        assert!(report.offset.is_some());
+        let checksum = report.checksum.unwrap();
+        assert_ne!(checksum.expected, checksum.actual);
+        let stats = engine.statistics();
+        assert_eq!(stats.corruption_reports, 1);
+        assert_eq!(
+            stats.property(properties::CORRUPTION_REPORTS),
+            Some("1".to_string())
+        );
+
+        drop(engine);
+        let file = std::fs::read_to_string(dir.path().join(CORRUPTION_FILE_NAME)).unwrap();
+        assert_eq!(file.lines().count(), 1);
+        let engine = StorageEngine::open(options).unwrap();
+        assert_eq!(engine.corruption_reports(), reports);
+    }
+
+    #[test]
+    fn test_damaged_wal_is_reported_by_recovery() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path());
+        let path = options.config().wal_dir.join(wal_file_name(1));
+        let writer = WALWriter::new_after(&path, SyncMode::Full, 1024 * 1024, 0).unwrap();
+        for ts in 1..=3 {
+            let entry = WALEntry::new_put(vec![ts as u8], b"damaged".to_vec(), ts);
+            writer.append(&entry.unwrap()).unwrap();
+        }
+        drop(writer);
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        std::fs::write(&path, data).unwrap();
+
+        // The damaged last entry is cut off the newest segment
+        let engine = StorageEngine::open(options).unwrap();
+        assert!(engine.recovery_report().truncated_tail());
+        let reports = engine.corruption_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].detected_by, "recovery");
+        assert_eq!(reports[0].path.as_ref(), Some(&path));
+        assert!(reports[0].checksum.is_some());
+        assert_eq!(engine.get(&[3]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unreadable_lines_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        let report = CorruptionReport {
+            detected_at: Duration::from_micros(42),
+            detected_by: "scrub".to_string(),
+            column_family: Some("default".to_string()),
+            path: Some(dir.path().join("000007.sst")),
+            offset: None,
+            checksum: None,
+            error: "Corruption detected: bad block".to_string(),
+        };
+        let contents = format!("{}\n{{\"detected_by\":\n", report.to_json());
+        std::fs::write(dir.path().join(CORRUPTION_FILE_NAME), contents).unwrap();
+
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let log = CorruptionLog::open(&vfs::os(), dir.path(), &clock, true).unwrap();
+        assert_eq!(log.reports(), [report]);
+    }
+}
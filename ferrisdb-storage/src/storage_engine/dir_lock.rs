@@ -0,0 +1,78 @@
+//! Exclusive ownership of a database directory
+//!
+//! Two engines writing the same directory would interleave their WAL
+//! records and MANIFEST edits and corrupt both. On open, an engine takes
+//! a lock on a `LOCK` file in its data directory (and in its WAL
+//! directory, if that lives elsewhere) through its [`Vfs`], and a second
+//! open fails with `Error::AlreadyLocked` until the first engine is closed.
+//! On the operating system's filesystem the lock is tied to the open file,
+//! so the operating system releases it when the process dies; the file
+//! itself is left behind and reused.
+
+use crate::vfs::{FileLock, Vfs};
+use ferrisdb_core::{Error, Result};
+
+use std::io;
+use std::path::Path;
+
+/// Name of the lock file in a locked directory
+pub(super) const LOCK_FILE_NAME: &str = "LOCK";
+
+/// A held lock on a directory, released when dropped
+#[derive(Debug)]
+pub(super) struct DirLock {
+    _lock: FileLock,
+}
+
+impl DirLock {
+    /// Locks `dir`, which must exist on `vfs`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::AlreadyLocked` if another engine, in this process or
+    /// another, holds the lock, or an I/O error if the lock file cannot be
+    /// opened or locked.
+    pub(super) fn acquire(vfs: &dyn Vfs, dir: &Path) -> Result<Self> {
+        let path = dir.join(LOCK_FILE_NAME);
+        match vfs.try_lock_file(&path) {
+            Ok(lock) => Ok(Self { _lock: lock }),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(Error::AlreadyLocked(format!(
+                "{} is in use by another engine",
+                dir.display()
+            ))),
+            Err(e) => Err(Error::from(e).with_path(&path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{self, SimVfs};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let dir = TempDir::new().unwrap();
+        let vfs = vfs::os();
+        let lock = DirLock::acquire(&*vfs, dir.path()).unwrap();
+        assert!(dir.path().join(LOCK_FILE_NAME).exists());
+
+        let error = DirLock::acquire(&*vfs, dir.path()).unwrap_err();
+        assert!(matches!(error, Error::AlreadyLocked(_)));
+
+        drop(lock);
+        DirLock::acquire(&*vfs, dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_lock_goes_through_the_vfs() {
+        let vfs = SimVfs::new(3);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+        let _lock = DirLock::acquire(&vfs, Path::new("/db")).unwrap();
+        assert!(vfs.exists(&Path::new("/db").join(LOCK_FILE_NAME)));
+
+        let error = DirLock::acquire(&vfs, Path::new("/db")).unwrap_err();
+        assert!(matches!(error, Error::AlreadyLocked(_)));
+    }
+}
@@ -0,0 +1,304 @@
+//! Callbacks on the engine's background work
+//!
+//! An [`EventListener`] registered with [`Options::with_event_listener`](super::Options::with_event_listener)
+//! is told when flushes begin and complete, compactions complete, the WAL
+//! moves on to a new segment, the write stall condition changes, and data
+//! turns out to be corrupt. That is enough to log background work, export
+//! metrics or warm a cache with fresh tables without patching the engine.
+//!
+//! Callbacks run on the thread doing the work, often with locks held: the
+//! WAL rotation callback holds up every write. They should return quickly
+//! and must not call back into the engine; anything slow or reentrant is
+//! better handed off to a thread of the listener's own.
+
+use super::column_family::ColumnFamilyData;
+use super::compaction_status::CompactionJob;
+use super::EngineInner;
+use crate::compaction::CompactionStats;
+use crate::write_stall::{WriteStallCondition, WriteStallInfo};
+use ferrisdb_core::error::ErrorCode;
+use ferrisdb_core::Error;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A MemTable flush of one column family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushJobInfo {
+    /// Name of the column family flushed
+    pub column_family: String,
+    /// Number of the WAL segment whose writes the MemTable holds
+    pub wal_number: u64,
+    /// Entries in the MemTable, counting every version and tombstone
+    pub entry_count: usize,
+    /// Path of the level 0 table written, `None` until the flush completes
+    pub table_path: Option<PathBuf>,
+    /// Size of the table in bytes, 0 until the flush completes
+    pub file_size: u64,
+    /// Time the flush took, zero until it completes
+    pub elapsed: Duration,
+}
+
+/// A finished compaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionJobInfo {
+    /// What was compacted, with the progress at the end
+    pub job: CompactionJob,
+    /// Tables and bytes read and written
+    pub stats: CompactionStats,
+}
+
+/// The WAL moving on to a new segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalRotationInfo {
+    /// Path of the segment closed, which is flushed next
+    pub closed_path: PathBuf,
+    /// Size of the closed segment in bytes, not counting its seal
+    pub closed_size: u64,
+    /// Path of the segment new writes go to
+    pub path: PathBuf,
+}
+
+/// A change of the write stall condition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteStallChange {
+    /// Condition before the change
+    pub previous: WriteStallCondition,
+    /// State after the change, with what caused it
+    pub current: WriteStallInfo,
+}
+
+/// Corrupt data an operation ran into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionInfo {
+    /// Name of the column family read, if known
+    pub column_family: Option<String>,
+    /// Path of the damaged file, if known
+    pub path: Option<PathBuf>,
+    /// Offset of the damage in the file, if known
+    pub offset: Option<u64>,
+    /// What the operation failed with
+    pub error: String,
+    /// What ran into the damage: `"get"`, `"scan"`, `"write"` or `"sync"`
+    /// for user operations, `"scrub"` or `"background"` for the engine's
+    /// own work
+    pub detected_by: &'static str,
+}
+
+/// Receives events of an engine's background work
+///
+/// Every method does nothing by default, so a listener implements only
+/// those it cares about. See the [module documentation](self) for the
+/// threads the methods run on.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::storage_engine::{EventListener, FlushJobInfo, Options, StorageEngine};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct FlushedBytes(AtomicU64);
+///
+/// impl EventListener for FlushedBytes {
+///     fn on_flush_completed(&self, info: &FlushJobInfo) {
+///         self.0.fetch_add(info.file_size, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let dir = tempfile::tempdir()?;
+/// let listener = Arc::new(FlushedBytes::default());
+/// let engine = StorageEngine::open(Options::new(dir.path()).with_event_listener(listener.clone()))?;
+/// engine.put(b"key".to_vec(), b"value".to_vec())?;
+/// engine.flush()?;
+/// assert!(listener.0.load(Ordering::Relaxed) > 0);
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub trait EventListener: Send + Sync {
+    /// Called before a column family's MemTable is written to level 0
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    /// Called once the table a flush wrote is installed
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    /// Called once the tables a compaction wrote are installed
+    ///
+    /// Failed compactions aren't reported; they fail the engine's writes.
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    /// Called after the WAL switched to a new segment, with every write
+    /// waiting
+    fn on_wal_rotated(&self, _info: &WalRotationInfo) {}
+
+    /// Called when writes get delayed, stopped or released
+    fn on_write_stall(&self, _info: &WriteStallChange) {}
+
+    /// Called when a read, a scrub or background work finds a checksum
+    /// mismatch or otherwise damaged data
+    ///
+    /// Runs on the thread that found the damage, before its operation
+    /// returns the error.
+    fn on_corruption_detected(&self, _info: &CorruptionInfo) {}
+}
+
+impl EngineInner {
+    /// Calls `event` on every registered listener, in order
+    pub(super) fn notify_listeners(&self, event: impl Fn(&dyn EventListener)) {
+        for listener in &self.options.event_listeners {
+            event(listener.as_ref());
+        }
+    }
+
+    /// Records `error` and tells the listeners about it if it reports
+    /// corrupt data
+    pub(super) fn check_corruption(
+        &self,
+        error: &Error,
+        cf: Option<&ColumnFamilyData>,
+        detected_by: &'static str,
+    ) {
+        if error.code() != ErrorCode::Corruption {
+            return;
+        }
+        self.options
+            .corruption
+            .record(error, cf.map(|cf| cf.name.as_str()), detected_by);
+        let context = error.context();
+        let info = CorruptionInfo {
+            column_family: cf.map(|cf| cf.name.clone()),
+            path: context.and_then(|context| context.path.clone()),
+            offset: context.and_then(|context| context.offset),
+            error: error.to_string(),
+            detected_by,
+        };
+        self.notify_listeners(|listener| listener.on_corruption_detected(&info));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use super::*;
+    use tempfile::TempDir;
+
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// Remembers every event, by the name of its callback
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+        flushes: Mutex<Vec<FlushJobInfo>>,
+        compactions: Mutex<Vec<CompactionJobInfo>>,
+        rotations: Mutex<Vec<WalRotationInfo>>,
+        stalls: Mutex<Vec<WriteStallChange>>,
+    }
+
+    impl EventListener for Recorder {
+        fn on_flush_begin(&self, info: &FlushJobInfo) {
+            self.events.lock().push("flush_begin".to_string());
+            self.flushes.lock().push(info.clone());
+        }
+
+        fn on_flush_completed(&self, info: &FlushJobInfo) {
+            self.events.lock().push("flush_completed".to_string());
+            self.flushes.lock().push(info.clone());
+        }
+
+        fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+            self.events.lock().push("compaction_completed".to_string());
+            self.compactions.lock().push(info.clone());
+        }
+
+        fn on_wal_rotated(&self, info: &WalRotationInfo) {
+            self.events.lock().push("wal_rotated".to_string());
+            self.rotations.lock().push(info.clone());
+        }
+
+        fn on_write_stall(&self, info: &WriteStallChange) {
+            self.stalls.lock().push(info.clone());
+        }
+    }
+
+    #[test]
+    fn test_listeners_hear_about_flushes_and_wal_rotations() {
+        let dir = TempDir::new().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_event_listener(recorder.clone()))
+                .unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        engine.flush().unwrap();
+
+        assert_eq!(
+            *recorder.events.lock(),
+            ["wal_rotated", "flush_begin", "flush_completed"]
+        );
+        let rotation = recorder.rotations.lock()[0].clone();
+        assert!(rotation.closed_size > 0);
+        assert_ne!(rotation.closed_path, rotation.path);
+
+        let flushes = recorder.flushes.lock();
+        let (begin, completed) = (&flushes[0], &flushes[1]);
+        assert_eq!(begin.column_family, "default");
+        assert_eq!(begin.entry_count, 2);
+        assert_eq!(begin.table_path, None);
+        assert_eq!(begin.file_size, 0);
+        assert_eq!(completed.wal_number, begin.wal_number);
+        let version = engine.inner.default.versions.current();
+        assert_eq!(
+            completed.table_path.as_deref(),
+            Some(version.files(0)[0].path())
+        );
+        assert_eq!(completed.file_size, version.files(0)[0].meta().file_size);
+    }
+
+    #[test]
+    fn test_listeners_hear_about_compactions_and_stalls() {
+        let dir = TempDir::new().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let mut options = Options::new(dir.path())
+            .with_event_listener(recorder.clone())
+            .with_event_listener(Arc::new(Recorder::default()));
+        options.config.level0_slowdown_writes_trigger = 2;
+        let engine = StorageEngine::open(options).unwrap();
+
+        // Holding the lock keeps the compaction job from running any
+        let held = engine.inner.compaction.lock();
+        for table in 0..2u64 {
+            engine
+                .put(table.to_be_bytes().to_vec(), b"v".to_vec())
+                .unwrap();
+            engine.flush().unwrap();
+        }
+        // The flush job may not have updated the stall yet, and a repeated
+        // update reports nothing new
+        engine.inner.update_write_stall();
+        {
+            let stalls = recorder.stalls.lock();
+            assert_eq!(stalls.len(), 1);
+            assert_eq!(stalls[0].previous, WriteStallCondition::Normal);
+            assert_eq!(stalls[0].current.condition, WriteStallCondition::Delayed);
+            assert_eq!(stalls[0].current.level0_files, 2);
+        }
+        drop(held);
+
+        engine.compact_range::<&[u8], _>(..).unwrap();
+        let compactions = recorder.compactions.lock().clone();
+        assert_eq!(compactions.len(), 1);
+        let compaction = &compactions[0];
+        assert!(compaction.job.manual);
+        assert_eq!(compaction.job.inputs.len(), 2);
+        assert_eq!(compaction.stats.input_files, 2);
+        assert_eq!(compaction.job.progress.unwrap().entries_read, 2);
+        assert_eq!(compaction.job.fraction_done(), 1.0);
+
+        engine.inner.update_write_stall();
+        let stalls = recorder.stalls.lock();
+        assert_eq!(stalls.len(), 2);
+        assert_eq!(stalls[1].previous, WriteStallCondition::Delayed);
+        assert_eq!(stalls[1].current.condition, WriteStallCondition::Normal);
+    }
+}
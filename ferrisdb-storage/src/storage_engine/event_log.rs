@@ -0,0 +1,287 @@
+//! Structured event log of the engine's lifecycle
+//!
+//! With [`Options::with_event_log`](super::Options::with_event_log) the
+//! engine writes one JSON object per line for every flush, compaction,
+//! WAL rotation, write stall change and detected corruption. The log goes
+//! straight to the handle it was given, not through the `log` crate, so
+//! tools can follow it whatever logger the application set up:
+//!
+//! ```text
+//! {"event":"flush_started","time_micros":1700000000000000,"column_family":"default","wal_number":3,"entry_count":1042}
+//! {"event":"flush_finished","time_micros":1700000000012000,"column_family":"default","wal_number":3,"entry_count":1042,"table_path":"./data/000007.sst","file_size":65536,"elapsed_micros":11873}
+//! ```
+//!
+//! Every event has `event`, its name, and `time_micros`, the engine's
+//! clock in microseconds since the Unix epoch. The other fields are those
+//! of the matching [`EventListener`] callback's info. The log hears each
+//! event after the listeners added with
+//! [`Options::with_event_listener`](super::Options::with_event_listener),
+//! and the handle is flushed after every line.
+
+use super::event_listener::{
+    CompactionJobInfo, CorruptionInfo, EventListener, FlushJobInfo, WalRotationInfo,
+    WriteStallChange,
+};
+use crate::clock::Clock;
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+
+use std::io::Write;
+use std::sync::Arc;
+
+/// Where events are written; shared by clones of the options
+pub(super) type EventLogWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Listener writing every event as a line of JSON
+pub(super) struct JsonEventLog {
+    writer: EventLogWriter,
+    clock: Arc<dyn Clock>,
+}
+
+impl JsonEventLog {
+    pub(super) fn new(writer: EventLogWriter, clock: Arc<dyn Clock>) -> Self {
+        Self { writer, clock }
+    }
+
+    /// Writes `fields` as the event `name`
+    fn write(&self, name: &str, fields: Value) {
+        let mut event = json!({
+            "event": name,
+            "time_micros": self.clock.now().as_micros() as u64,
+        });
+        if let (Value::Object(event), Value::Object(fields)) = (&mut event, fields) {
+            event.extend(fields);
+        }
+        let mut writer = self.writer.lock();
+        let result = serde_json::to_writer(&mut *writer, &event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+            .and_then(|()| writer.flush());
+        if let Err(e) = result {
+            log::warn!("Failed to write {} to the event log: {}", name, e);
+        }
+    }
+
+    fn flush_fields(info: &FlushJobInfo) -> Value {
+        json!({
+            "column_family": info.column_family,
+            "wal_number": info.wal_number,
+            "entry_count": info.entry_count,
+        })
+    }
+}
+
+impl EventListener for JsonEventLog {
+    fn on_flush_begin(&self, info: &FlushJobInfo) {
+        self.write("flush_started", Self::flush_fields(info));
+    }
+
+    fn on_flush_completed(&self, info: &FlushJobInfo) {
+        let mut fields = Self::flush_fields(info);
+        if let Value::Object(fields) = &mut fields {
+            fields.extend([
+                ("table_path".to_string(), json!(info.table_path)),
+                ("file_size".to_string(), json!(info.file_size)),
+                (
+                    "elapsed_micros".to_string(),
+                    json!(info.elapsed.as_micros() as u64),
+                ),
+            ]);
+        }
+        self.write("flush_finished", fields);
+    }
+
+    fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+        let job = &info.job;
+        let inputs: Vec<_> = job
+            .inputs
+            .iter()
+            .map(|input| {
+                json!({
+                    "level": input.level,
+                    "number": input.number,
+                    "file_size": input.file_size,
+                })
+            })
+            .collect();
+        self.write(
+            "compaction_finished",
+            json!({
+                "column_family": job.column_family,
+                "inputs": inputs,
+                "output_level": job.output_level,
+                "bottommost": job.bottommost,
+                "manual": job.manual,
+                "input_files": info.stats.input_files,
+                "output_files": info.stats.output_files,
+                "bytes_read": info.stats.bytes_read,
+                "bytes_written": info.stats.bytes_written,
+                "entries_read": job.progress.map(|progress| progress.entries_read),
+                "entries_written": job.progress.map(|progress| progress.entries_written),
+                "elapsed_micros": job
+                    .progress
+                    .map(|progress| progress.elapsed.as_micros() as u64),
+            }),
+        );
+    }
+
+    fn on_wal_rotated(&self, info: &WalRotationInfo) {
+        self.write(
+            "wal_rotated",
+            json!({
+                "closed_path": info.closed_path,
+                "closed_size": info.closed_size,
+                "path": info.path,
+            }),
+        );
+    }
+
+    fn on_write_stall(&self, info: &WriteStallChange) {
+        self.write(
+            "write_stall_changed",
+            json!({
+                "previous": format!("{:?}", info.previous),
+                "condition": format!("{:?}", info.current.condition),
+                "cause": info.current.cause.map(|cause| format!("{:?}", cause)),
+                "level0_files": info.current.level0_files,
+                "pending_compaction_bytes": info.current.pending_compaction_bytes,
+            }),
+        );
+    }
+
+    fn on_corruption_detected(&self, info: &CorruptionInfo) {
+        self.write(
+            "corruption_detected",
+            json!({
+                "column_family": info.column_family,
+                "path": info.path,
+                "offset": info.offset,
+                "error": info.error,
+                "detected_by": info.detected_by,
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use crate::clock::ManualClock;
+    use ferrisdb_core::Error;
+    use tempfile::TempDir;
+
+    use serde_json::Value;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// A handle whose lines the test can read back
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn events(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_events_are_written_as_json_lines() {
+        let dir = TempDir::new().unwrap();
+        let buffer = SharedBuffer::default();
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(1_700_000_000)));
+        let options = Options::new(dir.path())
+            .with_clock(clock)
+            .with_event_log(buffer.clone());
+        let engine = StorageEngine::open(options).unwrap();
+
+        for table in 0..2u8 {
+            engine.put(vec![table], b"v".to_vec()).unwrap();
+            engine.flush().unwrap();
+        }
+        engine.compact_range::<&[u8], _>(..).unwrap();
+
+        let events = buffer.events();
+        let names: Vec<_> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "wal_rotated",
+                "flush_started",
+                "flush_finished",
+                "wal_rotated",
+                "flush_started",
+                "flush_finished",
+                "compaction_finished",
+            ]
+        );
+        assert!(events
+            .iter()
+            .all(|event| event["time_micros"] == 1_700_000_000_000_000u64));
+
+        let flushed = &events[2];
+        assert_eq!(flushed["column_family"], "default");
+        assert_eq!(flushed["entry_count"], 1);
+        assert!(flushed["file_size"].as_u64().unwrap() > 0);
+        assert!(flushed["table_path"].as_str().unwrap().ends_with(".sst"));
+
+        let compaction = &events[6];
+        assert_eq!(compaction["manual"], true);
+        assert_eq!(compaction["input_files"], 2);
+        assert_eq!(compaction["inputs"].as_array().unwrap().len(), 2);
+        assert_eq!(compaction["entries_read"], 2);
+    }
+
+    #[test]
+    fn test_corruption_found_by_a_read_is_logged() {
+        let dir = TempDir::new().unwrap();
+        let buffer = SharedBuffer::default();
+        let options = Options::new(dir.path())
+            .with_paranoid_checks(true)
+            .with_event_log(buffer.clone());
+        let engine = StorageEngine::open(options).unwrap();
+        engine
+            .put(b"key".to_vec(), b"damaged value".to_vec())
+            .unwrap();
+        engine.flush().unwrap();
+
+        let path = engine.inner.default.versions.current().files(0)[0]
+            .path()
+            .to_path_buf();
+        let mut file = std::fs::read(&path).unwrap();
+        let at = file
+            .windows(b"damaged".len())
+            .position(|window| window == b"damaged")
+            .unwrap();
+        file[at] ^= 0x01;
+        std::fs::write(&path, file).unwrap();
+
+        let error = engine.get(b"key").unwrap_err();
+        assert!(matches!(error.root(), Error::Corruption(_)), "{error}");
+        let events = buffer.events();
+        let corruption = events.last().unwrap();
+        assert_eq!(corruption["event"], "corruption_detected");
+        assert_eq!(corruption["detected_by"], "get");
+        assert_eq!(corruption["column_family"], "default");
+        assert_eq!(corruption["path"], path.to_str().unwrap());
+    }
+}
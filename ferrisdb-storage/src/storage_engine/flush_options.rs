@@ -0,0 +1,49 @@
+//! Options for flushing a storage engine
+
+/// Settings for a flush through [`StorageEngine::flush_with_options`](super::StorageEngine::flush_with_options)
+///
+/// By default a flush waits until the MemTables are written to level 0.
+/// Settings not changed through a `with_*` method keep their defaults.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{FlushOptions, Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// engine.put(b"key".to_vec(), b"value".to_vec())?;
+///
+/// // Hand the MemTable to the flush job and carry on writing
+/// engine.flush_with_options(&FlushOptions::new().with_wait(false))?;
+/// engine.put(b"other".to_vec(), b"value".to_vec())?;
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushOptions {
+    /// Whether the flush returns only once the MemTables are written
+    pub(super) wait: bool,
+}
+
+impl FlushOptions {
+    /// Creates options for a flush that waits to finish
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the flush waits for the MemTables to be written
+    ///
+    /// Without waiting, the active MemTables are only switched for new
+    /// ones and handed to the flush job, so writes acknowledged before
+    /// reach level 0 some time after the call returns.
+    pub fn with_wait(mut self, wait: bool) -> Self {
+        self.wait = wait;
+        self
+    }
+}
+
+impl Default for FlushOptions {
+    fn default() -> Self {
+        Self { wait: true }
+    }
+}
@@ -0,0 +1,666 @@
+//! Bulk import of records straight into tables
+//!
+//! Loading a large data set with ordinary writes costs a WAL record and a
+//! MemTable insert per key, then a flush and compactions rewriting every
+//! table. [`import`](super::StorageEngine::import) writes the tables
+//! itself instead:
+//!
+//! 1. Records are sorted in memory in chunks of the
+//!    [sort buffer size](ImportOptions::with_sort_buffer_size). Each full
+//!    chunk is written to a temporary run file, so the input can be far
+//!    larger than memory. Nothing is locked yet
+//! 2. The MemTables are flushed. With writes held off, the runs are
+//!    merged into tables of about `target_file_size_base` bytes, all at
+//!    one new timestamp
+//! 3. The tables are added to the deepest level that no table in it or
+//!    above it overlaps, level 0 if level 1 already does, in one MANIFEST
+//!    edit. The edit also records the timestamp, so the engine keeps
+//!    issuing newer ones after a restart
+//!
+//! Among records with the same key, the last one read wins. Nothing is
+//! logged to the WAL, so [`tail_wal`](super::StorageEngine::tail_wal),
+//! replicas and [`Watcher`](super::Watcher)s never see the records.
+
+use super::column_family::ColumnFamilyData;
+use super::{EngineInner, COMPACTION_JOB};
+use crate::comparator::Comparator;
+use crate::manifest::{SSTableMeta, VersionEdit, NUM_LEVELS};
+use crate::rate_limiter::IoPriority;
+use crate::sstable::{InternalKey, SSTableWriter, MAX_ENTRY_SIZE};
+use crate::version::Version;
+use crate::wal::WALWriter;
+use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
+
+use parking_lot::MappedMutexGuard;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Bytes sorted in memory before a run is written out, by default
+pub const DEFAULT_SORT_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+/// Bytes a buffered record takes up besides its key and value
+const RECORD_OVERHEAD: usize = 2 * std::mem::size_of::<Vec<u8>>();
+
+/// Flushes an import tries before giving up on writes that keep coming
+const IMPORT_FLUSH_ATTEMPTS: usize = 3;
+
+/// Settings for an import through [`StorageEngine::import`](super::StorageEngine::import)
+///
+/// Settings not changed through a `with_*` method keep their defaults.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{ImportOptions, Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+///
+/// let records = (0..1000u32).rev().map(|i| Ok((i.to_be_bytes().to_vec(), b"v".to_vec())));
+/// let options = ImportOptions::new().with_sort_buffer_size(16 * 1024);
+/// let report = engine.import(records, &options)?;
+/// assert_eq!(report.records, 1000);
+/// assert_eq!(engine.get(&7u32.to_be_bytes())?, Some(b"v".to_vec()));
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOptions {
+    /// Bytes of records sorted in memory at a time
+    pub(super) sort_buffer_size: usize,
+    /// Directory of the run files, the system's if unset
+    pub(super) temp_dir: Option<PathBuf>,
+}
+
+impl ImportOptions {
+    /// Creates options with the default sort buffer and temporary directory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many bytes of records are sorted in memory before they
+    /// are written to a run file
+    ///
+    /// Larger buffers make fewer runs to merge, at the cost of memory.
+    pub fn with_sort_buffer_size(mut self, size: usize) -> Self {
+        self.sort_buffer_size = size;
+        self
+    }
+
+    /// Sets the directory run files are written to
+    ///
+    /// The files are deleted as soon as the import is done with them, and
+    /// by the operating system if the process dies first.
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            sort_buffer_size: DEFAULT_SORT_BUFFER_SIZE,
+            temp_dir: None,
+        }
+    }
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Keys written to the tables
+    pub records: u64,
+    /// Records dropped because a later one had the same key
+    pub duplicates: u64,
+    /// Sorted runs written to temporary files
+    pub runs: usize,
+    /// Tables added to the column family
+    pub tables: usize,
+    /// Total size of the tables in bytes
+    pub bytes: u64,
+    /// Level the tables were added to
+    pub level: usize,
+    /// Timestamp of the imported records, `None` if there were none
+    pub timestamp: Option<Timestamp>,
+}
+
+impl EngineInner {
+    /// Sorts `records` and adds them to `cf` as tables
+    pub(super) fn import<I>(
+        &self,
+        cf: &ColumnFamilyData,
+        records: I,
+        options: &ImportOptions,
+    ) -> Result<ImportReport>
+    where
+        I: IntoIterator<Item = Result<(Key, Value)>>,
+    {
+        self.check_writable()?;
+        if self.options.replica {
+            return Err(Error::InvalidOperation(
+                "The engine is a replica and only accepts replicated writes".to_string(),
+            ));
+        }
+        if self.is_indexed(cf.id) {
+            return Err(Error::InvalidOperation(format!(
+                "Column family {:?} has an index, which an import wouldn't update",
+                cf.name
+            )));
+        }
+
+        let mut sorter = Sorter::new(&*cf.comparator, options);
+        for record in records {
+            let (key, value) = record?;
+            sorter.add(key, value)?;
+        }
+        let mut report = ImportReport {
+            runs: sorter.runs.len(),
+            ..ImportReport::default()
+        };
+        if sorter.is_empty() {
+            return Ok(report);
+        }
+        let mut merge = sorter.finish()?;
+
+        let wal = self.lock_for_import(cf)?;
+        self.background_error(&self.background.lock())?;
+        let timestamp = self.oracle.next();
+        let tables = write_tables(cf, &mut merge, timestamp)?;
+        let installed = (|| {
+            // No compaction may add tables the level is picked around
+            let _compaction = self.compaction.lock();
+            let level = import_level(&cf.versions.current(), &tables);
+            let mut edit = VersionEdit::default();
+            for meta in &tables {
+                edit.add_file(level, meta.clone());
+            }
+            edit.set_last_timestamp(timestamp);
+            cf.versions.log_and_apply(edit)?;
+            Ok(level)
+        })();
+        let level = match installed {
+            Ok(level) => level,
+            Err(e) => {
+                for meta in &tables {
+                    let _ = cf
+                        .versions
+                        .vfs()
+                        .remove_file(&cf.versions.table_path(meta.number));
+                }
+                return Err(e);
+            }
+        };
+        self.oracle.publish(timestamp);
+        drop(wal);
+
+        self.update_write_stall();
+        self.scheduler.trigger(COMPACTION_JOB);
+        report.records = tables.iter().map(|meta| meta.entry_count).sum();
+        report.duplicates = merge.duplicates;
+        report.tables = tables.len();
+        report.bytes = tables.iter().map(|meta| meta.file_size).sum();
+        report.level = level;
+        report.timestamp = Some(timestamp);
+        Ok(report)
+    }
+
+    /// Flushes, then locks the writer once `cf` has no data in the
+    /// MemTables and no retired MemTable is left
+    ///
+    /// Recovery skips a family's logged writes up to the timestamp its
+    /// MANIFEST records, so none older than the import's may still be
+    /// unflushed. A retired MemTable's flush would also record its older
+    /// timestamp over the import's.
+    fn lock_for_import(&self, cf: &ColumnFamilyData) -> Result<MappedMutexGuard<'_, WALWriter>> {
+        for _ in 0..IMPORT_FLUSH_ATTEMPTS {
+            self.flush()?;
+            let wal = self.lock_writer()?;
+            let flushed = {
+                let memtables = self.memtables.read();
+                memtables.immutable.is_empty()
+                    && memtables
+                        .active
+                        .get(&cf.id)
+                        .map_or(true, |memtable| memtable.entry_count() == 0)
+            };
+            if flushed {
+                return Ok(wal);
+            }
+        }
+        Err(Error::Busy(format!(
+            "Writes to column family {:?} kept coming while an import flushed",
+            cf.name
+        )))
+    }
+}
+
+/// Writes the merged records to tables of `cf` at `timestamp`, removing
+/// them again if one fails
+fn write_tables(
+    cf: &ColumnFamilyData,
+    records: &mut Merge<'_>,
+    timestamp: Timestamp,
+) -> Result<Vec<SSTableMeta>> {
+    let mut allocated = Vec::new();
+    let result = (|| {
+        let mut tables = Vec::new();
+        let mut current: Option<(u64, SSTableWriter)> = None;
+        let mut written = 0u64;
+        for record in records {
+            let (key, mut value) = record?;
+            if written >= cf.config.target_file_size_base {
+                if let Some((number, writer)) = current.take() {
+                    tables.push(SSTableMeta::new(number, &writer.finish()?));
+                }
+                written = 0;
+            }
+            let (_, writer) = match &mut current {
+                Some(current) => current,
+                None => {
+                    let number = cf.versions.new_file_number();
+                    allocated.push(number);
+                    // Imports can wait for flushes, and aren't counted as them
+                    let path = cf.versions.table_path(number);
+                    let writer = cf.table_writer(&path, IoPriority::Low)?;
+                    current.insert((number, writer))
+                }
+            };
+            if let Some(ttl) = &cf.ttl {
+                ttl.stamp(&mut value, None);
+            }
+            written += (key.len() + value.len()) as u64;
+            writer.add(InternalKey::new(key, timestamp), value, Operation::Put)?;
+        }
+        if let Some((number, writer)) = current {
+            tables.push(SSTableMeta::new(number, &writer.finish()?));
+        }
+        if cf.config.paranoid_checks {
+            for meta in &tables {
+                cf.versions.verify_table(cf.versions.vfs(), meta)?;
+            }
+        }
+        Ok(tables)
+    })();
+
+    if result.is_err() {
+        for number in allocated {
+            let _ = cf
+                .versions
+                .vfs()
+                .remove_file(&cf.versions.table_path(number));
+        }
+    }
+    result
+}
+
+/// Returns the deepest level no table in it or above overlaps the keys
+/// of `tables`, or level 0 if level 1 already does
+fn import_level(version: &Version, tables: &[SSTableMeta]) -> usize {
+    let comparator = &**version.comparator();
+    let (Some(first), Some(last)) = (tables.first(), tables.last()) else {
+        return 0;
+    };
+    let (smallest, largest) = (&first.smallest.user_key, &last.largest.user_key);
+    let overlaps = |level: usize| {
+        version.files(level).iter().any(|table| {
+            comparator
+                .compare(&table.meta().smallest.user_key, largest)
+                .is_le()
+                && comparator
+                    .compare(smallest, &table.meta().largest.user_key)
+                    .is_le()
+        })
+    };
+    (0..NUM_LEVELS)
+        .take_while(|&level| !overlaps(level))
+        .last()
+        .unwrap_or(0)
+}
+
+/// Sorts records in memory, writing each full buffer to a run file
+struct Sorter<'a> {
+    comparator: &'a dyn Comparator,
+    buffer: Vec<(Key, Value)>,
+    /// Bytes the buffered records take up
+    buffered: usize,
+    buffer_size: usize,
+    temp_dir: PathBuf,
+    /// Full run files, oldest records first, positioned at their start
+    runs: Vec<File>,
+    /// Records replaced within a buffer
+    duplicates: u64,
+}
+
+impl<'a> Sorter<'a> {
+    fn new(comparator: &'a dyn Comparator, options: &ImportOptions) -> Self {
+        Self {
+            comparator,
+            buffer: Vec::new(),
+            buffered: 0,
+            buffer_size: options.sort_buffer_size,
+            temp_dir: options.temp_dir.clone().unwrap_or_else(std::env::temp_dir),
+            runs: Vec::new(),
+            duplicates: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty() && self.runs.is_empty()
+    }
+
+    fn add(&mut self, key: Key, value: Value) -> Result<()> {
+        for size in [key.len(), value.len()] {
+            if size > MAX_ENTRY_SIZE {
+                return Err(Error::EntrySizeExceeded {
+                    size,
+                    max_size: MAX_ENTRY_SIZE,
+                });
+            }
+        }
+        self.buffered += key.len() + value.len() + RECORD_OVERHEAD;
+        self.buffer.push((key, value));
+        if self.buffered >= self.buffer_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sorts the buffer, keeping the last record of each key
+    fn sort(&mut self) -> Vec<(Key, Value)> {
+        let mut records = std::mem::take(&mut self.buffer);
+        self.buffered = 0;
+        // The sort is stable, so records of a key stay in input order
+        records.sort_by(|a, b| self.comparator.compare(&a.0, &b.0));
+        let mut sorted: Vec<(Key, Value)> = Vec::with_capacity(records.len());
+        for record in records {
+            match sorted.last_mut() {
+                Some(last) if self.comparator.compare(&last.0, &record.0).is_eq() => {
+                    *last = record;
+                    self.duplicates += 1;
+                }
+                _ => sorted.push(record),
+            }
+        }
+        sorted
+    }
+
+    /// Writes the sorted buffer to a new run file
+    ///
+    /// Keys and values are each prefixed with their length as a
+    /// little-endian `u32`, which [`MAX_ENTRY_SIZE`] keeps them within.
+    fn spill(&mut self) -> Result<()> {
+        let records = self.sort();
+        let mut file = BufWriter::new(tempfile::tempfile_in(&self.temp_dir)?);
+        for (key, value) in &records {
+            for field in [key, value] {
+                file.write_all(&(field.len() as u32).to_le_bytes())?;
+                file.write_all(field)?;
+            }
+        }
+        let mut file = file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        self.runs.push(file);
+        Ok(())
+    }
+
+    /// Merges the runs with the records still in memory
+    fn finish(mut self) -> Result<Merge<'a>> {
+        let memory = self.sort();
+        let mut runs: Vec<Run> = std::mem::take(&mut self.runs)
+            .into_iter()
+            .map(|file| Run::File(BufReader::new(file)))
+            .collect();
+        runs.push(Run::Memory(memory.into_iter()));
+        let heads = runs
+            .iter_mut()
+            .map(Run::next_record)
+            .collect::<Result<_>>()?;
+        Ok(Merge {
+            comparator: self.comparator,
+            runs,
+            heads,
+            duplicates: self.duplicates,
+        })
+    }
+}
+
+/// Sorted records without duplicate keys
+enum Run {
+    Memory(std::vec::IntoIter<(Key, Value)>),
+    File(BufReader<File>),
+}
+
+impl Run {
+    fn next_record(&mut self) -> Result<Option<(Key, Value)>> {
+        match self {
+            Run::Memory(records) => Ok(records.next()),
+            Run::File(file) => {
+                if file.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some((read_field(file)?, read_field(file)?)))
+            }
+        }
+    }
+}
+
+fn read_field(file: &mut BufReader<File>) -> Result<Vec<u8>> {
+    let mut len = [0; 4];
+    file.read_exact(&mut len)?;
+    let mut field = vec![0; u32::from_le_bytes(len) as usize];
+    file.read_exact(&mut field)?;
+    Ok(field)
+}
+
+/// The records of all runs in key order, the newest run's winning a key
+struct Merge<'a> {
+    comparator: &'a dyn Comparator,
+    /// Runs holding older records first
+    runs: Vec<Run>,
+    /// Next record of each run
+    heads: Vec<Option<(Key, Value)>>,
+    /// Records replaced, within a run or by a newer run
+    duplicates: u64,
+}
+
+impl Merge<'_> {
+    fn next_record(&mut self) -> Result<Option<(Key, Value)>> {
+        // The last run holding the smallest key is the newest
+        let mut newest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let Some((key, _)) = head else {
+                continue;
+            };
+            let smallest = newest.map_or(true, |j| {
+                let (best, _) = self.heads[j].as_ref().expect("picked runs have a head");
+                self.comparator.compare(key, best).is_le()
+            });
+            if smallest {
+                newest = Some(i);
+            }
+        }
+        let Some(newest) = newest else {
+            return Ok(None);
+        };
+
+        let record = self.heads[newest].take().expect("picked runs have a head");
+        self.heads[newest] = self.runs[newest].next_record()?;
+        for i in 0..newest {
+            let replaced = self.heads[i]
+                .as_ref()
+                .is_some_and(|(key, _)| self.comparator.compare(key, &record.0).is_eq());
+            if replaced {
+                self.heads[i] = self.runs[i].next_record()?;
+                self.duplicates += 1;
+            }
+        }
+        Ok(Some(record))
+    }
+}
+
+impl Iterator for Merge<'_> {
+    type Item = Result<(Key, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage_engine::{ColumnFamilyOptions, IndexExtractor, Options, StorageEngine};
+
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn record(key: &str, value: &str) -> Result<(Key, Value)> {
+        Ok((key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn test_import_merges_spilled_runs_and_keeps_the_last_duplicate() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        // Written before the import, so it flushes the key first
+        engine.put(b"k07".to_vec(), b"old".to_vec()).unwrap();
+        let before = engine.snapshot();
+
+        let mut records: Vec<_> = (0..50)
+            .map(|i| record(&format!("k{:02}", (i * 7) % 50), &format!("v{}", i)))
+            .collect();
+        records.push(record("k07", "first"));
+        records.push(record("k30", "x"));
+        records.push(record("k07", "last"));
+        let temp = TempDir::new().unwrap();
+        let options = ImportOptions::new()
+            .with_sort_buffer_size(256)
+            .with_temp_dir(temp.path());
+        let report = engine.import(records, &options).unwrap();
+
+        assert_eq!(report.records, 50);
+        assert_eq!(report.duplicates, 3);
+        assert!(report.runs > 1);
+        assert_eq!(report.level, 0);
+        assert_eq!(engine.get(b"k07").unwrap(), Some(b"last".to_vec()));
+        assert_eq!(engine.get(b"k30").unwrap(), Some(b"x".to_vec()));
+        assert_eq!(before.get(b"k07").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(before.get(b"k30").unwrap(), None);
+        let keys: Vec<Key> = engine
+            .scan::<[u8], _>(..)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let expected: Vec<Key> = (0..50).map(|i| format!("k{:02}", i).into_bytes()).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(std::fs::read_dir(temp.path()).unwrap().count(), 0);
+        drop(before);
+        drop(engine);
+
+        // Writes after a restart still take newer timestamps
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert_eq!(engine.get(b"k07").unwrap(), Some(b"last".to_vec()));
+        engine.put(b"k07".to_vec(), b"new".to_vec()).unwrap();
+        assert_eq!(engine.get(b"k07").unwrap(), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_import_goes_to_the_deepest_level_clear_of_overlaps() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let cf = engine
+            .create_column_family("bulk", ColumnFamilyOptions::default())
+            .unwrap();
+
+        let report = engine
+            .import_cf(
+                &cf,
+                vec![record("m", "1"), record("a", "1")],
+                &ImportOptions::new(),
+            )
+            .unwrap();
+        assert_eq!(report.level, NUM_LEVELS - 1);
+        let report = engine
+            .import_cf(
+                &cf,
+                vec![record("x", "2"), record("z", "2")],
+                &ImportOptions::new(),
+            )
+            .unwrap();
+        assert_eq!(report.level, NUM_LEVELS - 1);
+
+        engine.put_cf(&cf, b"c".to_vec(), b"3".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let report = engine
+            .import_cf(&cf, vec![record("e", "4")], &ImportOptions::new())
+            .unwrap();
+        assert_eq!(report.level, NUM_LEVELS - 2);
+        let report = engine
+            .import_cf(
+                &cf,
+                vec![record("b", "5"), record("m", "5")],
+                &ImportOptions::new(),
+            )
+            .unwrap();
+        assert_eq!(report.level, 0);
+
+        let records = engine.scan_cf::<[u8], _>(&cf, ..).unwrap();
+        let expected: Vec<(Key, Value)> = [
+            ("a", "1"),
+            ("b", "5"),
+            ("c", "3"),
+            ("e", "4"),
+            ("m", "5"),
+            ("x", "2"),
+            ("z", "2"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+        .collect();
+        assert_eq!(records, expected);
+
+        let report = engine
+            .import_cf(&cf, Vec::new(), &ImportOptions::new())
+            .unwrap();
+        assert_eq!(report, ImportReport::default());
+    }
+
+    #[test]
+    fn test_failed_imports_leave_nothing_behind() {
+        struct WholeValue;
+
+        impl IndexExtractor for WholeValue {
+            fn extract(&self, value: &[u8]) -> Option<Vec<u8>> {
+                Some(value.to_vec())
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let tables = |engine: &StorageEngine| engine.inner.default.versions.current().file_count();
+
+        let records = vec![
+            record("a", "1"),
+            Err(Error::Serialization("bad row".to_string())),
+        ];
+        let err = engine.import(records, &ImportOptions::new()).unwrap_err();
+        assert!(matches!(err, Error::Serialization(_)));
+        let huge = vec![Ok((b"k".to_vec(), vec![0; MAX_ENTRY_SIZE + 1]))];
+        let err = engine.import(huge, &ImportOptions::new()).unwrap_err();
+        assert!(matches!(err, Error::EntrySizeExceeded { .. }));
+        assert_eq!(tables(&engine), 0);
+        assert_eq!(engine.get(b"a").unwrap(), None);
+
+        engine
+            .create_index("by_value", Arc::new(WholeValue))
+            .unwrap();
+        let err = engine
+            .import(vec![record("a", "1")], &ImportOptions::new())
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidOperation(_)));
+        assert_eq!(tables(&engine), 0);
+    }
+}
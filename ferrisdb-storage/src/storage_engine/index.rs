@@ -0,0 +1,568 @@
+//! Secondary indexes maintained along with the data they index
+//!
+//! A [`SecondaryIndex`] maps a value extracted from each record of a base
+//! column family back to the record's key. Its entries live in a column
+//! family of their own, keyed by the extracted value followed by the
+//! record's key, so several records may share a value:
+//!
+//! ```text
+//!  base "users"                         index "users_by_city"
+//!  user:1 ─▶ {city: Oslo, ..}           Oslo\0\x01 user:1 ─▶ ""
+//!  user:2 ─▶ {city: Bern, ..}   ──▶     Bern\0\x01 user:2 ─▶ ""
+//!  user:3 ─▶ {city: Oslo, ..}           Oslo\0\x01 user:3 ─▶ ""
+//! ```
+//!
+//! Zero bytes in the extracted value are escaped and the value ends in
+//! `\0\x01`, so the index family orders entries by value, whatever their
+//! lengths, and a value's entries form one contiguous range.
+//!
+//! Every write to the base family, batches and transactions included,
+//! reads the record's current value under the write lock and adds the
+//! index entries to remove and insert to the same WAL record: the index
+//! never disagrees with its base, not even after a crash. Reads through
+//! [`get_by_index`](super::StorageEngine::get_by_index) and
+//! [`scan_index`](super::StorageEngine::scan_index) read the index and the
+//! base at one snapshot.
+
+use super::batch::{self, BatchEntry};
+use super::column_family::{ColumnFamily, ColumnFamilyOptions};
+use super::snapshot::Snapshot;
+use super::write_options::WriteOptions;
+use super::{EngineInner, KeyRange};
+use crate::wal::WALWriter;
+use ferrisdb_core::{Error, Key, Operation, Result, Value};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Bytes ending the extracted value in an index entry's key
+const TERMINATOR: [u8; 2] = [0x00, 0x01];
+/// Bytes a zero byte of the extracted value is escaped to
+const ESCAPED_ZERO: [u8; 2] = [0x00, 0xFF];
+
+/// Extracts the value a record is indexed by
+///
+/// Implemented for closures, so `Arc::new(|value: &[u8]| ...)` makes an
+/// extractor.
+pub trait IndexExtractor: Send + Sync {
+    /// Returns the value a record with value `value` is indexed by, or
+    /// `None` to leave the record out of the index
+    fn extract(&self, value: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl<F> IndexExtractor for F
+where
+    F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync,
+{
+    fn extract(&self, value: &[u8]) -> Option<Vec<u8>> {
+        self(value)
+    }
+}
+
+/// A secondary index over a column family
+///
+/// Created by [`StorageEngine::create_index`](super::StorageEngine::create_index).
+/// Extractors are code, so indexes aren't persisted: an engine must create
+/// its indexes again each time it opens, before writing to their base
+/// families. Handles are cheap to clone.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, StorageEngine};
+/// use std::sync::Arc;
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// // Values are "city,name"; the index is by city
+/// let by_city = engine.create_index(
+///     "by_city",
+///     Arc::new(|value: &[u8]| value.split(|&b| b == b',').next().map(<[u8]>::to_vec)),
+/// )?;
+///
+/// engine.put(b"user:1".to_vec(), b"Oslo,alice".to_vec())?;
+/// engine.put(b"user:2".to_vec(), b"Bern,bob".to_vec())?;
+/// engine.put(b"user:1".to_vec(), b"Bern,alice".to_vec())?;
+///
+/// let keys: Vec<_> = engine
+///     .get_by_index(&by_city, b"Bern")?
+///     .into_iter()
+///     .map(|(key, _)| key)
+///     .collect();
+/// assert_eq!(keys, vec![b"user:1".to_vec(), b"user:2".to_vec()]);
+/// assert!(engine.get_by_index(&by_city, b"Oslo")?.is_empty());
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct SecondaryIndex {
+    pub(super) data: Arc<IndexData>,
+}
+
+/// The state of one index shared by the engine and its handles
+pub(super) struct IndexData {
+    /// The family whose records are indexed
+    base: ColumnFamily,
+    /// The family holding the index entries, named like the index
+    column_family: ColumnFamily,
+    extractor: Arc<dyn IndexExtractor>,
+}
+
+impl SecondaryIndex {
+    /// Returns the index's name, which its column family goes by
+    pub fn name(&self) -> &str {
+        self.data.column_family.name()
+    }
+
+    /// Returns the column family whose records are indexed
+    pub fn base(&self) -> &ColumnFamily {
+        &self.data.base
+    }
+
+    /// Returns the column family holding the index entries
+    pub fn column_family(&self) -> &ColumnFamily {
+        &self.data.column_family
+    }
+}
+
+impl fmt::Debug for SecondaryIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecondaryIndex")
+            .field("name", &self.name())
+            .field("base", &self.data.base)
+            .finish()
+    }
+}
+
+impl IndexData {
+    /// Returns the index entries to delete and insert for a record whose
+    /// value changes from `old` to `new`
+    fn updates(&self, key: &[u8], old: Option<&[u8]>, new: Option<&[u8]>) -> Vec<BatchEntry> {
+        let old = old.and_then(|value| self.extractor.extract(value));
+        let new = new.and_then(|value| self.extractor.extract(value));
+        if old == new {
+            return Vec::new();
+        }
+        let column_family = self.column_family.id();
+        let entry = |operation, indexed: &[u8]| BatchEntry {
+            column_family,
+            operation,
+            key: index_key(indexed, key),
+            value: Vec::new(),
+            ttl: None,
+        };
+        old.map(|old| entry(Operation::Delete, &old))
+            .into_iter()
+            .chain(new.map(|new| entry(Operation::Put, &new)))
+            .collect()
+    }
+}
+
+impl EngineInner {
+    /// Registers an index over `base` named `name`, creating its column
+    /// family and indexing the records already in `base` unless the
+    /// family exists
+    pub(super) fn create_index(
+        &self,
+        base: &ColumnFamily,
+        name: &str,
+        extractor: Arc<dyn IndexExtractor>,
+    ) -> Result<SecondaryIndex> {
+        let base_data = self.column_family(base)?;
+        if base_data.ttl.is_some() {
+            return Err(Error::InvalidOperation(format!(
+                "Column family {:?} has a TTL and can't be indexed",
+                base.name()
+            )));
+        }
+        if self.index_named(name).is_some() {
+            return Err(Error::InvalidOperation(format!(
+                "An index named {:?} already exists",
+                name
+            )));
+        }
+        let existing = self
+            .column_families
+            .read()
+            .values()
+            .find(|cf| cf.name == name)
+            .map(|cf| ColumnFamily {
+                data: Arc::clone(cf),
+            });
+        if existing.as_ref().is_some_and(|cf| cf.id() == base.id()) {
+            return Err(Error::InvalidOperation(format!(
+                "Column family {:?} can't index itself",
+                name
+            )));
+        }
+        let fresh = existing.is_none();
+        let column_family = match existing {
+            Some(cf) => cf,
+            None => self.create_column_family(name, ColumnFamilyOptions::default())?,
+        };
+        let index = Arc::new(IndexData {
+            base: base.clone(),
+            column_family,
+            extractor,
+        });
+
+        let result = (|| {
+            let mut wal = self.lock_writer()?;
+            self.background_error(&self.background.lock())?;
+            if fresh {
+                self.backfill(&mut wal, &index)?;
+            }
+            let mut indexes = self.indexes.write();
+            if indexes
+                .iter()
+                .any(|other| other.column_family.name() == name)
+            {
+                return Err(Error::InvalidOperation(format!(
+                    "An index named {:?} already exists",
+                    name
+                )));
+            }
+            indexes.push(Arc::clone(&index));
+            Ok(())
+        })();
+        if let Err(e) = result {
+            // A family left half filled would be taken for a complete index
+            if fresh {
+                let _ = self.drop_column_family(&index.column_family);
+            }
+            return Err(e);
+        }
+        log::info!(
+            "Created index {:?} over column family {:?}",
+            name,
+            base.name()
+        );
+        Ok(SecondaryIndex { data: index })
+    }
+
+    /// Unregisters an index and drops its column family
+    pub(super) fn drop_index(&self, index: &SecondaryIndex) -> Result<()> {
+        {
+            let _wal = self.lock_writer()?;
+            let mut indexes = self.indexes.write();
+            let before = indexes.len();
+            indexes.retain(|other| !Arc::ptr_eq(other, &index.data));
+            if indexes.len() == before {
+                return Err(Error::InvalidOperation(format!(
+                    "Index {:?} was dropped already",
+                    index.name()
+                )));
+            }
+        }
+        self.drop_column_family(&index.data.column_family)
+    }
+
+    /// Returns the registered index named `name`
+    fn index_named(&self, name: &str) -> Option<Arc<IndexData>> {
+        self.indexes
+            .read()
+            .iter()
+            .find(|index| index.column_family.name() == name)
+            .cloned()
+    }
+
+    /// Returns true if a registered index reads or writes the family
+    pub(super) fn is_indexed(&self, column_family: u32) -> bool {
+        self.indexes.read().iter().any(|index| {
+            index.base.id() == column_family || index.column_family.id() == column_family
+        })
+    }
+
+    /// Returns the index entries to write along with `writes`, reading
+    /// the records' current values
+    ///
+    /// Must be called with the write lock held, so the values read are
+    /// still current when the entries are applied. Writes later in
+    /// `writes` see the values of earlier ones to the same key.
+    pub(super) fn index_updates<'a>(
+        &self,
+        writes: impl IntoIterator<Item = (u32, Operation, &'a Key, &'a Value)>,
+    ) -> Result<Vec<BatchEntry>> {
+        let indexes = self.indexes.read();
+        if indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let read_ts = self.oracle.last();
+        let mut current: HashMap<(u32, &Key), Option<&Value>> = HashMap::new();
+        let mut updates = Vec::new();
+        for (column_family, operation, key, value) in writes {
+            let covering: Vec<_> = indexes
+                .iter()
+                .filter(|index| index.base.id() == column_family)
+                .collect();
+            if covering.is_empty() {
+                continue;
+            }
+            if operation == Operation::Merge {
+                return Err(Error::InvalidOperation(
+                    "Indexed column families don't accept merges".to_string(),
+                ));
+            }
+            let stored;
+            let old = match current.get(&(column_family, key)) {
+                Some(old) => old.map(Vec::as_slice),
+                None => {
+                    stored = self.get_at(&covering[0].base.data, key, read_ts)?;
+                    stored.as_deref()
+                }
+            };
+            let new = (operation == Operation::Put).then_some(value);
+            for index in covering {
+                updates.extend(index.updates(key, old, new.map(Vec::as_slice)));
+            }
+            current.insert((column_family, key), new);
+        }
+        Ok(updates)
+    }
+
+    /// Writes the entries of a new index for the records in its base
+    ///
+    /// Must be called with the write lock held. The entries are written in
+    /// batches of a quarter of the index family's MemTable.
+    fn backfill(&self, wal: &mut WALWriter, index: &IndexData) -> Result<()> {
+        let records = self.scan_at(
+            &index.base.data,
+            &(Bound::Unbounded, Bound::Unbounded),
+            self.oracle.last(),
+        )?;
+        let limit = index.column_family.data.config.memtable_size / 4;
+        let mut pending = Vec::new();
+        let mut size = 0;
+        for (i, (key, value)) in records.iter().enumerate() {
+            for entry in index.updates(key, None, Some(value)) {
+                size += entry.key.len();
+                pending.push(entry);
+            }
+            if !pending.is_empty() && (size >= limit || i + 1 == records.len()) {
+                let first = self.oracle.next();
+                let entries = batch::into_wal_entries(std::mem::take(&mut pending), first)?;
+                self.log_and_apply(wal, entries, &WriteOptions::default(), None)?;
+                size = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the records whose indexed value is in `range`, ordered by the
+/// value and then their key, as of `snapshot`
+pub(super) fn scan_index(
+    snapshot: &Snapshot,
+    index: &SecondaryIndex,
+    range: KeyRange,
+) -> Result<Vec<(Key, Value)>> {
+    let start = match range.0 {
+        Bound::Included(value) => Bound::Included(encode_value(&value)),
+        Bound::Excluded(value) => Bound::Included(encode_successor(&value)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match range.1 {
+        Bound::Included(value) => Bound::Excluded(encode_successor(&value)),
+        Bound::Excluded(value) => Bound::Excluded(encode_value(&value)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let entries = snapshot.scan_cf(&index.data.column_family, (start, end))?;
+    let keys = entries
+        .iter()
+        .map(|(entry, _)| {
+            split_index_key(entry).ok_or_else(|| {
+                Error::Corruption(format!(
+                    "Invalid entry in index {:?}: {:?}",
+                    index.name(),
+                    entry
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let values = snapshot.multi_get_cf(&index.data.base, &keys)?;
+    Ok(keys
+        .into_iter()
+        .zip(values)
+        .filter_map(|(key, value)| Some((key.to_vec(), value?)))
+        .collect())
+}
+
+/// Returns the key of the index entry for record `key` indexed by `value`
+fn index_key(value: &[u8], key: &[u8]) -> Key {
+    let mut encoded = encode_value(value);
+    encoded.extend_from_slice(key);
+    encoded
+}
+
+/// Escapes the zero bytes of `value` and appends the terminator, so
+/// encoded values order like the values and none is a prefix of another
+fn encode_value(value: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(value.len() + TERMINATOR.len());
+    for &byte in value {
+        match byte {
+            0 => encoded.extend_from_slice(&ESCAPED_ZERO),
+            byte => encoded.push(byte),
+        }
+    }
+    encoded.extend_from_slice(&TERMINATOR);
+    encoded
+}
+
+/// Returns the smallest key after every index entry for `value`
+fn encode_successor(value: &[u8]) -> Vec<u8> {
+    let mut encoded = encode_value(value);
+    *encoded.last_mut().expect("terminated") += 1;
+    encoded
+}
+
+/// Returns the record key of an index entry
+fn split_index_key(entry: &[u8]) -> Option<&[u8]> {
+    let mut i = 0;
+    while i + 1 < entry.len() {
+        match (entry[i], entry[i + 1]) {
+            (0x00, 0x01) => return Some(&entry[i + 2..]),
+            (0x00, 0xFF) => i += 2,
+            (0x00, _) => return None,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine, WriteBatch};
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Indexes "city,name" values by city
+    fn by_city() -> Arc<dyn IndexExtractor> {
+        Arc::new(|value: &[u8]| {
+            value
+                .split(|&byte| byte == b',')
+                .next()
+                .filter(|city| !city.is_empty())
+                .map(<[u8]>::to_vec)
+        })
+    }
+
+    fn keys(records: Vec<(Key, Value)>) -> Vec<Key> {
+        records.into_iter().map(|(key, _)| key).collect()
+    }
+
+    #[test]
+    fn test_encoded_values_order_like_the_values() {
+        let values: [&[u8]; 6] = [b"", b"\x00", b"\x00\x00", b"\x00a", b"a", b"a\x00"];
+        let encoded: Vec<_> = values.iter().map(|value| index_key(value, b"zz")).collect();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (value, key) in values.iter().zip(&encoded) {
+            assert_eq!(split_index_key(key), Some(b"zz".as_slice()));
+            // Every entry for the value falls before the successor
+            assert!(key.as_slice() < encode_successor(value).as_slice());
+            assert!(index_key(value, b"") >= encode_value(value));
+        }
+        assert_eq!(split_index_key(b"a\x00\x02"), None);
+    }
+
+    #[test]
+    fn test_writes_keep_the_index_up_to_date() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let index = engine.create_index("by_city", by_city()).unwrap();
+
+        engine.put(b"u1".to_vec(), b"Oslo,a".to_vec()).unwrap();
+        engine.put(b"u2".to_vec(), b"Bern,b".to_vec()).unwrap();
+        engine.put(b"u3".to_vec(), b",c".to_vec()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"u4".to_vec(), b"Oslo,d".to_vec());
+        batch.put(b"u4".to_vec(), b"Rome,d".to_vec());
+        batch.put(b"u2".to_vec(), b"Oslo,b".to_vec());
+        engine.write(batch, false).unwrap();
+        assert_eq!(
+            keys(engine.get_by_index(&index, b"Oslo").unwrap()),
+            vec![b"u1".to_vec(), b"u2".to_vec()]
+        );
+        assert!(engine.get_by_index(&index, b"Bern").unwrap().is_empty());
+
+        // Scans are ordered by city, then key
+        let records = engine
+            .scan_index(&index, b"Bern".as_slice()..=b"Rome".as_slice())
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![
+                (b"u1".to_vec(), b"Oslo,a".to_vec()),
+                (b"u2".to_vec(), b"Oslo,b".to_vec()),
+                (b"u4".to_vec(), b"Rome,d".to_vec()),
+            ]
+        );
+        let after_oslo = (Bound::Excluded(b"Oslo".as_slice()), Bound::Unbounded);
+        assert_eq!(
+            keys(engine.scan_index::<[u8], _>(&index, after_oslo).unwrap()),
+            vec![b"u4".to_vec()]
+        );
+
+        // Deletes, range deletes and transactions all go through the index
+        engine.delete(b"u1".to_vec()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"u4".as_slice()..b"u5".as_slice());
+        engine.write(batch, false).unwrap();
+        let mut txn = engine.begin_transaction();
+        txn.put(b"u3".to_vec(), b"Oslo,c".to_vec());
+        txn.commit().unwrap();
+        let mut txn = engine.begin_pessimistic_transaction();
+        txn.put(b"u5".to_vec(), b"Rome,e".to_vec()).unwrap();
+        txn.prepare("xid").unwrap();
+        assert!(engine.get_by_index(&index, b"Rome").unwrap().is_empty());
+        txn.commit().unwrap();
+        assert_eq!(
+            keys(engine.scan_index::<[u8], _>(&index, ..).unwrap()),
+            vec![b"u2".to_vec(), b"u3".to_vec(), b"u5".to_vec()]
+        );
+        assert_eq!(
+            engine
+                .scan_cf::<[u8], _>(index.column_family(), ..)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        assert!(matches!(
+            engine.merge(b"u2".to_vec(), b"x".to_vec()),
+            Err(Error::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_indexes_are_filled_and_reattached_on_create() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_memtable_size(4096)).unwrap();
+        for i in 0..200 {
+            let city = if i % 2 == 0 { "Oslo" } else { "Bern" };
+            let value = format!("{},{}", city, i).into_bytes();
+            engine
+                .put(format!("u{:03}", i).into_bytes(), value)
+                .unwrap();
+        }
+        let index = engine.create_index("by_city", by_city()).unwrap();
+        assert_eq!(engine.get_by_index(&index, b"Oslo").unwrap().len(), 100);
+        assert!(engine.create_index("by_city", by_city()).is_err());
+        assert!(engine.create_index("default", by_city()).is_err());
+        assert!(engine.drop_column_family(index.column_family()).is_err());
+        drop(engine);
+
+        // Reopened, the index family is taken as it is
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let index = engine.create_index("by_city", by_city()).unwrap();
+        engine.put(b"u000".to_vec(), b"Bern,0".to_vec()).unwrap();
+        assert_eq!(engine.get_by_index(&index, b"Oslo").unwrap().len(), 99);
+        assert_eq!(engine.get_by_index(&index, b"Bern").unwrap().len(), 101);
+
+        engine.drop_index(&index).unwrap();
+        assert!(engine.drop_index(&index).is_err());
+        assert!(engine.cf_handle("by_city").is_none());
+        engine.merge(b"u000".to_vec(), b"x".to_vec()).unwrap_err();
+    }
+}
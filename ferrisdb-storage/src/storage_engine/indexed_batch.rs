@@ -0,0 +1,502 @@
+//! Write batches that can be read before they are written
+//!
+//! A [`WriteBatchWithIndex`] keeps an ordered index from each key to its
+//! writes in the batch, so reads through the batch find its own writes
+//! without scanning it, and lay them over the engine's data:
+//!
+//! ```text
+//!  batch writes ──▶ index (cf, key) ─▶ positions ──┐
+//!                                                  ├─▶ resolve ─▶ value
+//!  MemTables, SSTables at the read timestamp ──────┘
+//! ```
+//!
+//! The batch's writes are newer than anything the engine holds, so reads
+//! stamp them with timestamps above every committed one: a put or delete
+//! in the batch hides the engine's versions of its key, and merges in the
+//! batch apply on top of them. [`Transaction`](super::Transaction) and
+//! [`PessimisticTransaction`](super::PessimisticTransaction) buffer their
+//! writes this way.
+
+use super::batch::{BatchEntry, WriteBatch};
+use super::column_family::{ColumnFamily, ColumnFamilyData, DEFAULT_COLUMN_FAMILY_ID};
+use super::iterator::EngineIterator;
+use super::read_options::ReadOptions;
+use super::{range_contains, EngineInner, KeyRange, StorageEngine};
+use crate::comparator;
+use crate::sstable::{InternalKey, SSTableEntry};
+use ferrisdb_core::{Key, Operation, Result, Timestamp, Value};
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A [`WriteBatch`] whose writes can be read before it is written
+///
+/// Collect puts, deletes and merges as in a `WriteBatch`, read keys with
+/// [`get`](Self::get) or iterate with [`iter`](Self::iter) to see the
+/// engine's data with the batch's writes applied, then hand
+/// [`into_batch`](Self::into_batch) to
+/// [`StorageEngine::write`](super::StorageEngine::write). Nothing is
+/// visible to other readers until then.
+///
+/// Range deletions are not supported: what they delete depends on the
+/// data when the batch is written.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, ReadOptions, StorageEngine, WriteBatchWithIndex};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// engine.put(b"a".to_vec(), b"1".to_vec())?;
+/// engine.put(b"b".to_vec(), b"1".to_vec())?;
+///
+/// let mut batch = WriteBatchWithIndex::new();
+/// batch.put(b"c".to_vec(), b"2".to_vec());
+/// batch.delete(b"a".to_vec());
+/// assert_eq!(batch.get(&engine, b"a")?, None);
+///
+/// let mut iter = batch.iter(&engine, ReadOptions::new())?;
+/// iter.seek_to_first()?;
+/// assert_eq!(iter.key(), Some(&b"b"[..]));
+/// iter.next()?;
+/// assert_eq!(iter.key(), Some(&b"c"[..]));
+///
+/// engine.write(batch.into_batch(), false)?;
+/// assert_eq!(engine.get(b"c")?, Some(b"2".to_vec()));
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatchWithIndex {
+    batch: WriteBatch,
+    /// Positions of the writes to each key of each family, oldest first
+    index: BTreeMap<(u32, Key), Vec<usize>>,
+}
+
+impl WriteBatchWithIndex {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of a key
+    pub fn put(&mut self, key: Key, value: Value) {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, Operation::Put, key, value, None);
+    }
+
+    /// Sets the value of a key in a column family
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: Key, value: Value) {
+        self.push(cf.id(), Operation::Put, key, value, None);
+    }
+
+    /// Sets the value of a key that expires after `ttl`
+    ///
+    /// Writing the batch fails unless the default column family has a TTL.
+    pub fn put_with_ttl(&mut self, key: Key, value: Value, ttl: Duration) {
+        self.push(
+            DEFAULT_COLUMN_FAMILY_ID,
+            Operation::Put,
+            key,
+            value,
+            Some(ttl),
+        );
+    }
+
+    /// Deletes a key
+    pub fn delete(&mut self, key: Key) {
+        self.push(
+            DEFAULT_COLUMN_FAMILY_ID,
+            Operation::Delete,
+            key,
+            Vec::new(),
+            None,
+        );
+    }
+
+    /// Deletes a key from a column family
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: Key) {
+        self.push(cf.id(), Operation::Delete, key, Vec::new(), None);
+    }
+
+    /// Records a merge operand for a key
+    ///
+    /// Reading the key or writing the batch fails unless the engine has a
+    /// merge operator.
+    pub fn merge(&mut self, key: Key, operand: Value) {
+        self.push(
+            DEFAULT_COLUMN_FAMILY_ID,
+            Operation::Merge,
+            key,
+            operand,
+            None,
+        );
+    }
+
+    /// Records a merge operand for a key in a column family
+    ///
+    /// Reading the key or writing the batch fails unless the family has a
+    /// merge operator.
+    pub fn merge_cf(&mut self, cf: &ColumnFamily, key: Key, operand: Value) {
+        self.push(cf.id(), Operation::Merge, key, operand, None);
+    }
+
+    /// Returns the number of writes in the batch
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    /// Returns true if the batch holds no writes
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    /// Removes all writes so the batch can be reused
+    pub fn clear(&mut self) {
+        self.batch.clear();
+        self.index.clear();
+    }
+
+    /// Returns the writes as a plain batch
+    pub fn batch(&self) -> &WriteBatch {
+        &self.batch
+    }
+
+    /// Turns the batch into a plain one, ready to be written
+    pub fn into_batch(self) -> WriteBatch {
+        self.batch
+    }
+
+    /// Returns the current value of a key with the batch's writes applied
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed, the read fails, or the
+    /// merge operator fails.
+    pub fn get(&self, engine: &StorageEngine, key: &[u8]) -> Result<Option<Value>> {
+        let inner = &engine.inner;
+        inner.check_open()?;
+        let pin = inner.snapshots.pin(&inner.oracle);
+        self.get_at(inner, &inner.default, key, pin.timestamp())
+    }
+
+    /// Returns the current value of a key in a column family with the
+    /// batch's writes applied
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped,
+    /// otherwise errors for the same reasons as [`get`](Self::get).
+    pub fn get_cf(
+        &self,
+        engine: &StorageEngine,
+        cf: &ColumnFamily,
+        key: &[u8],
+    ) -> Result<Option<Value>> {
+        let inner = &engine.inner;
+        inner.check_open()?;
+        let cf = inner.column_family(cf)?;
+        let pin = inner.snapshots.pin(&inner.oracle);
+        self.get_at(inner, &cf, key, pin.timestamp())
+    }
+
+    /// Returns an iterator over the default column family with the
+    /// batch's writes applied
+    ///
+    /// The iterator holds a copy of the batch's writes within its bounds;
+    /// writes added to the batch later don't show up in it. Otherwise it
+    /// behaves as one from [`StorageEngine::iter`](super::StorageEngine::iter).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as
+    /// [`StorageEngine::iter`](super::StorageEngine::iter).
+    pub fn iter(&self, engine: &StorageEngine, options: ReadOptions<'_>) -> Result<EngineIterator> {
+        let inner = &engine.inner;
+        inner.check_open()?;
+        EngineIterator::new(
+            Arc::clone(inner),
+            Arc::clone(&inner.default),
+            options,
+            Some(self),
+        )
+    }
+
+    /// Returns an iterator over a column family with the batch's writes
+    /// applied
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped,
+    /// otherwise errors for the same reasons as [`iter`](Self::iter).
+    pub fn iter_cf(
+        &self,
+        engine: &StorageEngine,
+        cf: &ColumnFamily,
+        options: ReadOptions<'_>,
+    ) -> Result<EngineIterator> {
+        let inner = &engine.inner;
+        inner.check_open()?;
+        let cf = inner.column_family(cf)?;
+        EngineIterator::new(Arc::clone(inner), cf, options, Some(self))
+    }
+
+    fn push(
+        &mut self,
+        column_family: u32,
+        operation: Operation,
+        key: Key,
+        value: Value,
+        ttl: Option<Duration>,
+    ) {
+        let position = self.batch.len();
+        self.index
+            .entry((column_family, key.clone()))
+            .or_default()
+            .push(position);
+        self.batch
+            .push_entry(column_family, operation, key, value, ttl);
+    }
+
+    /// Returns the write at `position`; the index only refers to point
+    /// writes
+    fn entry(&self, position: usize) -> &BatchEntry {
+        self.batch
+            .entry(position)
+            .expect("indexed batches hold only point writes")
+    }
+
+    /// Returns the timestamp the write at `position` has in reads through
+    /// the batch
+    ///
+    /// The batch's writes take the largest timestamps, which no committed
+    /// write ever gets, in the order they were added.
+    fn own_timestamp(&self, position: usize) -> Timestamp {
+        Timestamp::MAX - (self.batch.len() - position) as Timestamp
+    }
+
+    /// Returns the oldest timestamp of the batch's writes in reads through
+    /// the batch
+    pub(super) fn own_writes_from(&self) -> Timestamp {
+        self.own_timestamp(0)
+    }
+
+    /// Returns the value a write stores, stamped with the family's TTL as
+    /// it will be when written
+    fn stored_value(&self, cf: &ColumnFamilyData, entry: &BatchEntry) -> Value {
+        let mut value = entry.value.clone();
+        if let Some(ttl) = &cf.ttl {
+            if entry.operation != Operation::Delete {
+                ttl.stamp(&mut value, entry.ttl);
+            }
+        }
+        value
+    }
+
+    /// Returns the batch's versions of a key, newest first
+    pub(super) fn versions(
+        &self,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+    ) -> Vec<(Value, Timestamp, Operation)> {
+        let Some(positions) = self.index.get(&(cf.id, key.to_vec())) else {
+            return Vec::new();
+        };
+        positions
+            .iter()
+            .rev()
+            .map(|&position| {
+                let entry = self.entry(position);
+                (
+                    self.stored_value(cf, entry),
+                    self.own_timestamp(position),
+                    entry.operation,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the keys the batch writes to in a family, each once
+    pub(super) fn keys(&self, column_family: u32) -> impl Iterator<Item = &Key> {
+        self.index
+            .range((column_family, Vec::new())..)
+            .take_while(move |((id, _), _)| *id == column_family)
+            .map(|((_, key), _)| key)
+    }
+
+    /// Returns the batch's versions of the keys of a family in `range`,
+    /// sorted as the family's MemTables are
+    pub(super) fn entries_in(&self, cf: &ColumnFamilyData, range: &KeyRange) -> Vec<SSTableEntry> {
+        let comparator = &*cf.comparator;
+        let mut entries: Vec<_> = self
+            .keys(cf.id)
+            .filter(|key| range_contains(comparator, range, key))
+            .flat_map(|key| {
+                self.versions(cf, key)
+                    .into_iter()
+                    .map(|(value, timestamp, operation)| {
+                        SSTableEntry::new(
+                            InternalKey::new(key.clone(), timestamp),
+                            value,
+                            operation,
+                        )
+                    })
+            })
+            .collect();
+        // The index is in bytewise order, which the family may not use
+        entries.sort_by(|a, b| comparator::compare_internal(comparator, &a.key, &b.key));
+        entries
+    }
+
+    /// Reads a key as of `read_ts` with the batch's writes on top
+    pub(super) fn get_at(
+        &self,
+        inner: &EngineInner,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        read_ts: Timestamp,
+    ) -> Result<Option<Value>> {
+        let mut versions = self.versions(cf, key);
+        if !has_base(&versions) {
+            versions.extend(inner.versions_at(cf, key, read_ts)?);
+        }
+        inner.resolve(cf, key, versions)
+    }
+}
+
+/// Returns true if a put or delete among a key's versions hides the older
+/// ones
+pub(super) fn has_base(versions: &[(Value, Timestamp, Operation)]) -> bool {
+    versions
+        .iter()
+        .any(|(_, _, operation)| *operation != Operation::Merge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, ReadOptions, StorageEngine};
+    use super::*;
+    use crate::comparator::ReverseBytewiseComparator;
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    fn counter(n: u64) -> Vec<u8> {
+        n.to_le_bytes().to_vec()
+    }
+
+    fn collect(mut iter: EngineIterator) -> Vec<(Key, Value)> {
+        let mut pairs = Vec::new();
+        iter.seek_to_first().unwrap();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            pairs.push((key.to_vec(), value.to_vec()));
+            iter.next().unwrap();
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_reads_see_the_batch_over_the_engine() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path()).with_merge_operator(Arc::new(U64AddOperator)),
+        )
+        .unwrap();
+        engine.put(b"a".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"n".to_vec(), counter(1)).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"d".to_vec(), b"old".to_vec()).unwrap();
+
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put(b"a".to_vec(), b"first".to_vec());
+        batch.delete(b"b".to_vec());
+        batch.merge(b"n".to_vec(), counter(2));
+        batch.put(b"c".to_vec(), b"new".to_vec());
+        batch.put(b"a".to_vec(), b"new".to_vec());
+        batch.merge(b"n".to_vec(), counter(3));
+        assert_eq!(batch.len(), 6);
+
+        assert_eq!(batch.get(&engine, b"a").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(batch.get(&engine, b"b").unwrap(), None);
+        assert_eq!(batch.get(&engine, b"n").unwrap(), Some(counter(6)));
+        assert_eq!(batch.get(&engine, b"d").unwrap(), Some(b"old".to_vec()));
+
+        let expected = vec![
+            (b"a".to_vec(), b"new".to_vec()),
+            (b"c".to_vec(), b"new".to_vec()),
+            (b"d".to_vec(), b"old".to_vec()),
+            (b"n".to_vec(), counter(6)),
+        ];
+        assert_eq!(
+            collect(batch.iter(&engine, ReadOptions::new()).unwrap()),
+            expected
+        );
+
+        // Backwards, and within bounds
+        let mut iter = batch
+            .iter(
+                &engine,
+                ReadOptions::new()
+                    .with_lower_bound(b"b".to_vec())
+                    .with_upper_bound(b"n".to_vec()),
+            )
+            .unwrap();
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key(), Some(&b"d"[..]));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), Some(&b"c"[..]));
+        iter.prev().unwrap();
+        assert!(!iter.valid());
+
+        // A snapshot from before the batch's keys were written keeps
+        // hiding them, but never the batch's own writes
+        let snapshot = engine.snapshot();
+        engine.put(b"e".to_vec(), b"later".to_vec()).unwrap();
+        assert_eq!(
+            collect(
+                batch
+                    .iter(&engine, ReadOptions::new().with_snapshot(&snapshot))
+                    .unwrap()
+            ),
+            expected
+        );
+
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"old".to_vec()));
+        engine.write(batch.into_batch(), false).unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(engine.get(b"n").unwrap(), Some(counter(6)));
+    }
+
+    #[test]
+    fn test_batch_follows_the_family_order() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let cf = engine
+            .create_column_family(
+                "reversed",
+                ColumnFamilyOptions::default().with_comparator(Arc::new(ReverseBytewiseComparator)),
+            )
+            .unwrap();
+        engine.put_cf(&cf, b"b".to_vec(), b"1".to_vec()).unwrap();
+
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put_cf(&cf, b"a".to_vec(), b"2".to_vec());
+        batch.put_cf(&cf, b"c".to_vec(), b"2".to_vec());
+        batch.put(b"b".to_vec(), b"default".to_vec());
+
+        assert_eq!(
+            batch.get_cf(&engine, &cf, b"b").unwrap(),
+            Some(b"1".to_vec())
+        );
+        assert_eq!(batch.get(&engine, b"a").unwrap(), None);
+        let keys: Vec<_> = collect(batch.iter_cf(&engine, &cf, ReadOptions::new()).unwrap())
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]);
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!(batch.get_cf(&engine, &cf, b"a").unwrap(), None);
+    }
+}
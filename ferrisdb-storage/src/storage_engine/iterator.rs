@@ -0,0 +1,1143 @@
+//! Long-lived iterators over a column family
+//!
+//! An [`EngineIterator`] follows the classic LevelDB iterator contract: it
+//! starts out unpositioned, the `seek*` methods move it to a key,
+//! [`next`](EngineIterator::next) and [`prev`](EngineIterator::prev) step
+//! to the neighbouring keys, and [`valid`](EngineIterator::valid) tells
+//! whether it is at a key at all.
+//!
+//! ```text
+//!  MemTables (copied) ──┐
+//!                       ├─▶ merged cursor ─▶ versions of ─▶ resolve ─▶ key, value
+//!  SSTables (pinned) ───┘   (internal keys)   one key
+//! ```
+//!
+//! On creation the iterator copies the visible part of each MemTable and
+//! pins the current [`Version`], so it reads one consistent state however
+//! long it lives: flushes and compactions install new versions, but the
+//! tables of the pinned one stay on disk until the iterator is dropped.
+//! SSTables are read a block at a time through cursors that move in both
+//! directions. The merged cursor only ever moves one way; changing
+//! direction seeks every source again around the current key.
+
+use super::column_family::ColumnFamilyData;
+use super::indexed_batch::WriteBatchWithIndex;
+use super::read_options::ReadOptions;
+use super::{overlaps_range, EngineInner, KeyRange};
+use crate::clock::Clock;
+use crate::comparator::{self, BytewiseComparator, Comparator};
+use crate::prefix;
+use crate::sstable::{InternalKey, SSTableCursor, SSTableEntry, SSTableReader};
+use crate::utils::memory::{MemoryKind, MemoryReservation};
+use crate::version::Version;
+use ferrisdb_core::{Error, Key, Result, Timestamp, Value};
+
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Iterator over the keys of a column family, in both directions
+///
+/// Created by [`StorageEngine::iter`](super::StorageEngine::iter) or
+/// [`iter_cf`](super::StorageEngine::iter_cf). Reads as of a snapshot or
+/// the last write committed when it was created, within the bounds of its
+/// [`ReadOptions`]. Deleted and expired keys are skipped and merge
+/// operands resolved, exactly as [`get`](super::StorageEngine::get) would.
+///
+/// Moving fails when a table cannot be read, a merge fails, or the
+/// iterator reaches a limit set in its [`ReadOptions`]; the iterator is
+/// then no longer [`valid`](Self::valid) until the next seek.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, ReadOptions, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// for key in [b"a", b"b", b"c"] {
+///     engine.put(key.to_vec(), b"v".to_vec())?;
+/// }
+///
+/// let mut iter = engine.iter(ReadOptions::new())?;
+/// iter.seek(b"b")?;
+/// assert_eq!(iter.key(), Some(&b"b"[..]));
+/// iter.prev()?;
+/// assert_eq!(iter.key(), Some(&b"a"[..]));
+/// iter.seek_for_prev(b"bb")?;
+/// assert_eq!(iter.key(), Some(&b"b"[..]));
+/// iter.next()?;
+/// iter.next()?;
+/// assert!(!iter.valid());
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct EngineIterator {
+    inner: Arc<EngineInner>,
+    cf: Arc<ColumnFamilyData>,
+    /// Keeps the tables the cursors read from alive
+    _version: Arc<Version>,
+    /// Charges the entries copied out of MemTables to the engine
+    _memory: MemoryReservation,
+    read_ts: Timestamp,
+    /// Oldest timestamp of the versions the iterator sees
+    min_ts: Timestamp,
+    /// Oldest timestamp of the writes of a batch read through, which the
+    /// iterator sees whatever it reads at
+    own_writes_from: Option<Timestamp>,
+    lower_bound: Option<Key>,
+    upper_bound: Option<Key>,
+    sources: MergedCursor,
+    /// Which way the merged cursor last moved; moving forward leaves it
+    /// after the current key's versions, moving backward before them
+    direction: Direction,
+    /// Key the iterator is at, with its resolved value
+    current: Option<(Key, Value)>,
+    limits: ScanLimits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+impl EngineIterator {
+    /// Pins the family's MemTables and tables for reading, with the writes
+    /// of `batch` on top
+    pub(super) fn new(
+        inner: Arc<EngineInner>,
+        cf: Arc<ColumnFamilyData>,
+        options: ReadOptions<'_>,
+        batch: Option<&WriteBatchWithIndex>,
+    ) -> Result<Self> {
+        let (lower_bound, upper_bound) = prefix_bounds(&cf, &options)?;
+        let (min_ts, read_ts, version, sources, copied) = {
+            // Compaction keeps the versions visible at a pinned timestamp,
+            // and once the version is pinned its tables no longer change
+            let pin;
+            let read_ts = match options.snapshot {
+                Some(snapshot) => snapshot.timestamp(),
+                None => {
+                    pin = inner.snapshots.pin(&inner.oracle);
+                    pin.timestamp()
+                }
+            };
+            let (min_ts, read_ts) = match options.timestamp_range {
+                Some((min, max)) => (min, read_ts.min(max)),
+                None => (0, read_ts),
+            };
+
+            let range: KeyRange = (
+                lower_bound
+                    .clone()
+                    .map_or(Bound::Unbounded, Bound::Included),
+                upper_bound
+                    .clone()
+                    .map_or(Bound::Unbounded, Bound::Excluded),
+            );
+            // MemTables before the version, as in `versions_at`
+            let memtables = inner.memtables.read().newest_first(cf.id);
+            let version = cf.versions.current();
+
+            let mut sources: Vec<Box<dyn Cursor>> = Vec::new();
+            let mut copied = 0;
+            if let Some(batch) = batch {
+                let entries = batch.entries_in(&cf, &range);
+                copied += copied_size(&entries);
+                sources.push(Box::new(MemTableCursor {
+                    entries,
+                    comparator: Arc::clone(&cf.comparator),
+                    position: None,
+                }));
+            }
+            for memtable in &memtables {
+                let entries = memtable
+                    .entries(range.clone())
+                    .filter(|entry| (min_ts..=read_ts).contains(&entry.key.timestamp))
+                    .collect::<Vec<_>>();
+                copied += copied_size(&entries);
+                sources.push(Box::new(MemTableCursor {
+                    entries,
+                    comparator: Arc::clone(&cf.comparator),
+                    position: None,
+                }));
+            }
+            for level in 0..crate::manifest::NUM_LEVELS {
+                for table in version.files(level) {
+                    if !overlaps_range(&*cf.comparator, table, &range) {
+                        continue;
+                    }
+                    let mut reader = cf.open_table(table)?;
+                    if options.timestamp_range.is_some()
+                        && !reader.properties().may_contain_timestamps(min_ts, read_ts)
+                    {
+                        inner.counters.record_timestamp_filter_skip();
+                        continue;
+                    }
+                    if let (Some(prefix), Some(extractor)) = (&options.prefix, &cf.prefix_extractor)
+                    {
+                        if !reader.may_contain_prefix(&**extractor, prefix)? {
+                            inner.counters.record_prefix_filter_skip();
+                            continue;
+                        }
+                    }
+                    sources.push(Box::new(reader.into_cursor()));
+                }
+            }
+            (min_ts, read_ts, version, sources, copied)
+        };
+        let memory = inner.options.memory.reserve(MemoryKind::Iterators, copied);
+        let limits = ScanLimits::new(&options, Arc::clone(&inner.options.clock));
+
+        Ok(Self {
+            sources: MergedCursor {
+                sources,
+                comparator: Arc::clone(&cf.comparator),
+                current: None,
+            },
+            inner,
+            cf,
+            _version: version,
+            _memory: memory,
+            read_ts,
+            min_ts,
+            own_writes_from: batch.map(WriteBatchWithIndex::own_writes_from),
+            lower_bound,
+            upper_bound,
+            direction: Direction::Forward,
+            current: None,
+            limits,
+        })
+    }
+
+    /// Returns true if the iterator is at a key
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Returns the key the iterator is at
+    pub fn key(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(key, _)| key.as_slice())
+    }
+
+    /// Returns the value of the key the iterator is at
+    pub fn value(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(_, value)| value.as_slice())
+    }
+
+    /// Returns the timestamp the iterator reads at
+    pub fn timestamp(&self) -> Timestamp {
+        self.read_ts
+    }
+
+    /// Moves to the first key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a table cannot be read or a merge fails.
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        let result = match self.lower_bound.clone() {
+            Some(lower) => self.sources.seek(&InternalKey::new(lower, Timestamp::MAX)),
+            None => self.sources.seek_to_first(),
+        };
+        self.settle_forward(result)
+    }
+
+    /// Moves to the last key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a table cannot be read or a merge fails.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let result = match self.upper_bound.clone() {
+            Some(upper) => self.seek_before(upper),
+            None => self.sources.seek_to_last(),
+        };
+        self.settle_backward(result)
+    }
+
+    /// Moves to the first key at or after `key`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a table cannot be read or a merge fails.
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let target = match &self.lower_bound {
+            Some(lower) if self.cf.comparator.compare(key, lower).is_lt() => lower.clone(),
+            _ => key.to_vec(),
+        };
+        let result = self.sources.seek(&InternalKey::new(target, Timestamp::MAX));
+        self.settle_forward(result)
+    }
+
+    /// Moves to the last key at or before `key`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a table cannot be read or a merge fails.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let result = match &self.upper_bound {
+            Some(upper) if self.cf.comparator.compare(key, upper).is_ge() => {
+                self.seek_before(upper.clone())
+            }
+            // Timestamp 0 sorts after every version of the key
+            _ => self
+                .sources
+                .seek_for_prev(&InternalKey::new(key.to_vec(), 0)),
+        };
+        self.settle_backward(result)
+    }
+
+    /// Moves to the next key, or past the last one
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the iterator is not at a key,
+    /// or an error if a table cannot be read or a merge fails.
+    #[allow(clippy::should_implement_trait)] // Moves a cursor, yields nothing
+    pub fn next(&mut self) -> Result<()> {
+        let (key, _) = self.current.take().ok_or_else(not_positioned)?;
+        let mut result = Ok(());
+        if self.direction == Direction::Backward {
+            result = self
+                .sources
+                .seek(&InternalKey::new(key.clone(), 0))
+                .and_then(|()| self.skip_forward_over(&key));
+        }
+        self.settle_forward(result)
+    }
+
+    /// Moves to the previous key, or before the first one
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the iterator is not at a key,
+    /// or an error if a table cannot be read or a merge fails.
+    pub fn prev(&mut self) -> Result<()> {
+        let (key, _) = self.current.take().ok_or_else(not_positioned)?;
+        let mut result = Ok(());
+        if self.direction == Direction::Forward {
+            result = self.seek_before(key);
+        }
+        self.settle_backward(result)
+    }
+
+    /// Positions the merged cursor at the last entry of a key before `key`
+    fn seek_before(&mut self, key: Key) -> Result<()> {
+        // Timestamp MAX sorts before every version of the key
+        self.sources
+            .seek_for_prev(&InternalKey::new(key.clone(), Timestamp::MAX))?;
+        while self
+            .sources
+            .entry()
+            .is_some_and(|entry| entry.key.user_key == key)
+        {
+            self.sources.prev()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the merged cursor forward past the versions of `key`
+    fn skip_forward_over(&mut self, key: &[u8]) -> Result<()> {
+        while self
+            .sources
+            .entry()
+            .is_some_and(|entry| entry.key.user_key == key)
+        {
+            self.sources.next()?;
+        }
+        Ok(())
+    }
+
+    /// Finds the first visible key from the merged cursor on, after it
+    /// moved forward with `result`
+    fn settle_forward(&mut self, result: Result<()>) -> Result<()> {
+        self.direction = Direction::Forward;
+        self.current = None;
+        let result = result.and_then(|()| self.find_forward());
+        if result.is_err() {
+            self.current = None;
+        }
+        result
+    }
+
+    /// Finds the last visible key from the merged cursor back, after it
+    /// moved backward with `result`
+    fn settle_backward(&mut self, result: Result<()>) -> Result<()> {
+        self.direction = Direction::Backward;
+        self.current = None;
+        let result = result.and_then(|()| self.find_backward());
+        if result.is_err() {
+            self.current = None;
+        }
+        result
+    }
+
+    /// Returns whether a version written at `ts` is visible to the iterator
+    fn sees(&self, ts: Timestamp) -> bool {
+        (self.min_ts..=self.read_ts).contains(&ts)
+            || self.own_writes_from.is_some_and(|from| ts >= from)
+    }
+
+    fn find_forward(&mut self) -> Result<()> {
+        while let Some(entry) = self.sources.entry() {
+            if let Some(upper) = &self.upper_bound {
+                if self
+                    .cf
+                    .comparator
+                    .compare(&entry.key.user_key, upper)
+                    .is_ge()
+                {
+                    return Ok(());
+                }
+            }
+
+            // Versions come newest first
+            let user_key = entry.key.user_key.clone();
+            let mut versions = Vec::new();
+            while let Some(entry) = self.sources.entry() {
+                if entry.key.user_key != user_key {
+                    break;
+                }
+                if self.sees(entry.key.timestamp) {
+                    versions.push((entry.value.clone(), entry.key.timestamp, entry.operation));
+                }
+                self.limits.charge(entry)?;
+                self.sources.next()?;
+            }
+
+            if versions.is_empty() {
+                continue;
+            }
+            if let Some(value) = self.inner.resolve(&self.cf, &user_key, versions)? {
+                self.current = Some((user_key, value));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn find_backward(&mut self) -> Result<()> {
+        while let Some(entry) = self.sources.entry() {
+            if let Some(lower) = &self.lower_bound {
+                if self
+                    .cf
+                    .comparator
+                    .compare(&entry.key.user_key, lower)
+                    .is_lt()
+                {
+                    return Ok(());
+                }
+            }
+
+            // Versions come oldest first
+            let user_key = entry.key.user_key.clone();
+            let mut versions = Vec::new();
+            while let Some(entry) = self.sources.entry() {
+                if entry.key.user_key != user_key {
+                    break;
+                }
+                if self.sees(entry.key.timestamp) {
+                    versions.push((entry.value.clone(), entry.key.timestamp, entry.operation));
+                }
+                self.limits.charge(entry)?;
+                self.sources.prev()?;
+            }
+
+            if versions.is_empty() {
+                continue;
+            }
+            versions.reverse();
+            if let Some(value) = self.inner.resolve(&self.cf, &user_key, versions)? {
+                self.current = Some((user_key, value));
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EngineIterator {
+    fn drop(&mut self) {
+        for source in &self.sources.sources {
+            if let Some(reader) = source.table_reader() {
+                self.inner.counters.record_reads(reader);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for EngineIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EngineIterator")
+            .field("column_family", &self.cf.name)
+            .field("timestamp", &self.read_ts)
+            .field("key", &self.key())
+            .finish_non_exhaustive()
+    }
+}
+
+/// What an iterator may still step over before it fails
+struct ScanLimits {
+    max_keys_scanned: Option<u64>,
+    max_bytes_read: Option<u64>,
+    /// Time since the Unix epoch on `clock` at which the iterator stops
+    deadline: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    keys_scanned: u64,
+    bytes_read: u64,
+}
+
+impl ScanLimits {
+    fn new(options: &ReadOptions<'_>, clock: Arc<dyn Clock>) -> Self {
+        let deadline = match (options.deadline, options.timeout) {
+            (deadline, Some(timeout)) => {
+                let after_timeout = clock.now().saturating_add(timeout);
+                Some(deadline.map_or(after_timeout, |deadline| deadline.min(after_timeout)))
+            }
+            (deadline, None) => deadline,
+        };
+        Self {
+            max_keys_scanned: options.max_keys_scanned,
+            max_bytes_read: options.max_bytes_read,
+            deadline,
+            clock,
+            keys_scanned: 0,
+            bytes_read: 0,
+        }
+    }
+
+    /// Counts an entry stepped over, failing once a limit is passed
+    fn charge(&mut self, entry: &SSTableEntry) -> Result<()> {
+        self.keys_scanned += 1;
+        self.bytes_read += (entry.key.user_key.len() + entry.value.len()) as u64;
+        if let Some(max) = self.max_keys_scanned.filter(|&max| self.keys_scanned > max) {
+            return Err(Error::ScanLimitExceeded(format!(
+                "iterator stepped over more than {} entries",
+                max
+            )));
+        }
+        if let Some(max) = self.max_bytes_read.filter(|&max| self.bytes_read > max) {
+            return Err(Error::ScanLimitExceeded(format!(
+                "iterator read more than {} bytes",
+                max
+            )));
+        }
+        if let Some(deadline) = self
+            .deadline
+            .filter(|&deadline| self.clock.now() >= deadline)
+        {
+            return Err(Error::ScanLimitExceeded(format!(
+                "iterator passed its deadline of {}ms since the epoch",
+                deadline.as_millis()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn not_positioned() -> Error {
+    Error::InvalidOperation("Iterator is not positioned at a key".to_string())
+}
+
+/// Returns the bounds of a read, narrowed to the keys with its prefix
+fn prefix_bounds(
+    cf: &ColumnFamilyData,
+    options: &ReadOptions<'_>,
+) -> Result<(Option<Key>, Option<Key>)> {
+    let (mut lower, mut upper) = (options.lower_bound.clone(), options.upper_bound.clone());
+    let Some(prefix) = &options.prefix else {
+        return Ok((lower, upper));
+    };
+    if cf.comparator.name() != BytewiseComparator.name() {
+        return Err(Error::InvalidArgument(format!(
+            "Prefix reads need the bytewise comparator, not {:?}",
+            cf.comparator.name()
+        )));
+    }
+
+    if lower.as_ref().map_or(true, |lower| lower < prefix) {
+        lower = Some(prefix.clone());
+    }
+    if let Some(end) = prefix::prefix_end(prefix) {
+        if upper.as_ref().map_or(true, |upper| *upper > end) {
+            upper = Some(end);
+        }
+    }
+    Ok((lower, upper))
+}
+
+/// A position in a sorted run of entries that moves both ways
+trait Cursor: Send {
+    fn entry(&self) -> Option<&SSTableEntry>;
+    fn seek_to_first(&mut self) -> Result<()>;
+    fn seek_to_last(&mut self) -> Result<()>;
+    fn seek(&mut self, target: &InternalKey) -> Result<()>;
+    fn seek_for_prev(&mut self, target: &InternalKey) -> Result<()>;
+    fn next(&mut self) -> Result<()>;
+    fn prev(&mut self) -> Result<()>;
+
+    /// Returns the table read from, for the engine's statistics
+    fn table_reader(&self) -> Option<&SSTableReader> {
+        None
+    }
+}
+
+impl Cursor for SSTableCursor {
+    fn entry(&self) -> Option<&SSTableEntry> {
+        SSTableCursor::entry(self)
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        SSTableCursor::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        SSTableCursor::seek_to_last(self)
+    }
+
+    fn seek(&mut self, target: &InternalKey) -> Result<()> {
+        SSTableCursor::seek(self, target)
+    }
+
+    fn seek_for_prev(&mut self, target: &InternalKey) -> Result<()> {
+        SSTableCursor::seek_for_prev(self, target)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        SSTableCursor::next(self)
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        SSTableCursor::prev(self)
+    }
+
+    fn table_reader(&self) -> Option<&SSTableReader> {
+        Some(self.reader())
+    }
+}
+
+/// Returns roughly the bytes `entries` take in memory
+fn copied_size(entries: &[SSTableEntry]) -> usize {
+    entries
+        .iter()
+        .map(|entry| {
+            std::mem::size_of::<SSTableEntry>() + entry.key.user_key.len() + entry.value.len()
+        })
+        .sum()
+}
+
+/// Cursor over the visible entries copied out of a MemTable
+struct MemTableCursor {
+    entries: Vec<SSTableEntry>,
+    comparator: Arc<dyn Comparator>,
+    position: Option<usize>,
+}
+
+impl Cursor for MemTableCursor {
+    fn entry(&self) -> Option<&SSTableEntry> {
+        self.position.map(|i| &self.entries[i])
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.position = (!self.entries.is_empty()).then_some(0);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.position = self.entries.len().checked_sub(1);
+        Ok(())
+    }
+
+    fn seek(&mut self, target: &InternalKey) -> Result<()> {
+        let comparator = &*self.comparator;
+        let start = self.entries.partition_point(|entry| {
+            comparator::compare_internal(comparator, &entry.key, target).is_lt()
+        });
+        self.position = (start < self.entries.len()).then_some(start);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, target: &InternalKey) -> Result<()> {
+        let comparator = &*self.comparator;
+        let end = self.entries.partition_point(|entry| {
+            comparator::compare_internal(comparator, &entry.key, target).is_le()
+        });
+        self.position = end.checked_sub(1);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.position = self
+            .position
+            .map(|i| i + 1)
+            .filter(|&i| i < self.entries.len());
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.position = self.position.and_then(|i| i.checked_sub(1));
+        Ok(())
+    }
+}
+
+/// Merges cursors, newest data first, into one over internal keys
+///
+/// All sources are moved the same way, so the current entry is the
+/// smallest of theirs after moving forward and the largest after moving
+/// backward. An entry in several sources, as when a MemTable being
+/// flushed also shows up as a table, is returned once.
+struct MergedCursor {
+    sources: Vec<Box<dyn Cursor>>,
+    comparator: Arc<dyn Comparator>,
+    /// Source holding the current entry
+    current: Option<usize>,
+}
+
+impl MergedCursor {
+    fn entry(&self) -> Option<&SSTableEntry> {
+        self.current.and_then(|i| self.sources[i].entry())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek_to_first()?;
+        }
+        self.pick(Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek_to_last()?;
+        }
+        self.pick(Direction::Backward);
+        Ok(())
+    }
+
+    fn seek(&mut self, target: &InternalKey) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek(target)?;
+        }
+        self.pick(Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, target: &InternalKey) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek_for_prev(target)?;
+        }
+        self.pick(Direction::Backward);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.step(Direction::Forward)
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.step(Direction::Backward)
+    }
+
+    /// Moves every source at the current entry one step
+    fn step(&mut self, direction: Direction) -> Result<()> {
+        let Some(key) = self.entry().map(|entry| entry.key.clone()) else {
+            return Ok(());
+        };
+        for source in &mut self.sources {
+            if source.entry().is_some_and(|entry| entry.key == key) {
+                match direction {
+                    Direction::Forward => source.next()?,
+                    Direction::Backward => source.prev()?,
+                }
+            }
+        }
+        self.pick(direction);
+        Ok(())
+    }
+
+    /// Makes the source with the next entry in `direction` current,
+    /// preferring newer sources on ties
+    fn pick(&mut self, direction: Direction) {
+        let comparator = &*self.comparator;
+        let mut best: Option<(usize, &InternalKey)> = None;
+        for (i, source) in self.sources.iter().enumerate() {
+            let Some(entry) = source.entry() else {
+                continue;
+            };
+            let better = best.map_or(true, |(_, best)| {
+                let order = comparator::compare_internal(comparator, &entry.key, best);
+                match direction {
+                    Direction::Forward => order.is_lt(),
+                    Direction::Backward => order.is_gt(),
+                }
+            });
+            if better {
+                best = Some((i, &entry.key));
+            }
+        }
+        self.current = best.map(|(i, _)| i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine};
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    use std::collections::BTreeMap;
+
+    fn key(i: usize) -> Key {
+        format!("key{:04}", i).into_bytes()
+    }
+
+    /// Collects the keys and values from the current position to the end
+    fn forward(iter: &mut EngineIterator) -> Vec<(Key, Value)> {
+        let mut pairs = Vec::new();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            pairs.push((key.to_vec(), value.to_vec()));
+            iter.next().unwrap();
+        }
+        pairs
+    }
+
+    /// Collects the keys and values from the current position to the start
+    fn backward(iter: &mut EngineIterator) -> Vec<(Key, Value)> {
+        let mut pairs = Vec::new();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            pairs.push((key.to_vec(), value.to_vec()));
+            iter.prev().unwrap();
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_iterator_matches_scan_across_memtables_and_tables() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_memtable_size(4 * 1024)).unwrap();
+        let mut model = BTreeMap::new();
+        for round in 0..4 {
+            for i in (round..200).step_by(round + 1) {
+                let value = format!("value{}-{}", i, round).into_bytes();
+                engine.put(key(i), value.clone()).unwrap();
+                model.insert(key(i), value);
+            }
+            for i in (0..200).step_by(7 + round) {
+                engine.delete(key(i)).unwrap();
+                model.remove(&key(i));
+            }
+        }
+        let expected: Vec<_> = model.into_iter().collect();
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap(), expected);
+
+        let mut iter = engine.iter(ReadOptions::new()).unwrap();
+        assert!(!iter.valid());
+        assert!(matches!(iter.next(), Err(Error::InvalidOperation(_))));
+
+        iter.seek_to_first().unwrap();
+        assert_eq!(forward(&mut iter), expected);
+        iter.seek_to_last().unwrap();
+        let mut reversed = backward(&mut iter);
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+    }
+
+    #[test]
+    fn test_direction_changes_visit_neighbours() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_memtable_size(4 * 1024)).unwrap();
+        for i in (0..100).step_by(2) {
+            for version in 0..3 {
+                engine
+                    .put(key(i), format!("v{}", version).into_bytes())
+                    .unwrap();
+            }
+        }
+        engine.flush().unwrap();
+        engine.put(key(51), b"new".to_vec()).unwrap();
+
+        let mut iter = engine.iter(ReadOptions::new()).unwrap();
+        iter.seek(&key(49)).unwrap();
+        assert_eq!(iter.key(), Some(&key(50)[..]));
+        assert_eq!(iter.value(), Some(&b"v2"[..]));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), Some(&key(51)[..]));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), Some(&key(50)[..]));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), Some(&key(48)[..]));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), Some(&key(50)[..]));
+
+        iter.seek_for_prev(&key(49)).unwrap();
+        assert_eq!(iter.key(), Some(&key(48)[..]));
+        iter.seek_for_prev(&key(48)).unwrap();
+        assert_eq!(iter.key(), Some(&key(48)[..]));
+        iter.seek(&key(99)).unwrap();
+        assert!(!iter.valid());
+        iter.seek_for_prev(b"a").unwrap();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_bounds_limit_every_movement() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        for i in 0..20 {
+            engine.put(key(i), b"v".to_vec()).unwrap();
+        }
+
+        let options = ReadOptions::new()
+            .with_lower_bound(key(5))
+            .with_upper_bound(key(10));
+        let mut iter = engine.iter(options).unwrap();
+        let keys = |pairs: Vec<(Key, Value)>| pairs.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+
+        iter.seek_to_first().unwrap();
+        assert_eq!(
+            keys(forward(&mut iter)),
+            (5..10).map(key).collect::<Vec<_>>()
+        );
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key(), Some(&key(9)[..]));
+        assert_eq!(keys(backward(&mut iter)).len(), 5);
+
+        iter.seek(&key(0)).unwrap();
+        assert_eq!(iter.key(), Some(&key(5)[..]));
+        iter.seek(&key(10)).unwrap();
+        assert!(!iter.valid());
+        iter.seek_for_prev(&key(15)).unwrap();
+        assert_eq!(iter.key(), Some(&key(9)[..]));
+        iter.seek_for_prev(&key(4)).unwrap();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_timestamp_range_hides_versions_and_skips_tables_outside_it() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"a".to_vec(), b"a1".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"b1".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let first = engine.snapshot().timestamp();
+        engine.put(b"a".to_vec(), b"a2".to_vec()).unwrap();
+        engine.delete(b"b".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let second = engine.snapshot().timestamp();
+        engine.put(b"c".to_vec(), b"c1".to_vec()).unwrap();
+
+        let read = |options: ReadOptions<'_>| {
+            let mut iter = engine.iter(options).unwrap();
+            iter.seek_to_first().unwrap();
+            forward(&mut iter)
+        };
+        let skips = || engine.statistics().timestamp_filter_skips;
+        let pair = |key: &[u8], value: &[u8]| (key.to_vec(), value.to_vec());
+
+        assert_eq!(
+            read(ReadOptions::new().with_timestamp_range(first + 1..=second)),
+            vec![pair(b"a", b"a2")]
+        );
+        assert_eq!(skips(), 1);
+        assert_eq!(
+            read(ReadOptions::new().with_timestamp_range(first + 1..)),
+            vec![pair(b"a", b"a2"), pair(b"c", b"c1")]
+        );
+        assert_eq!(skips(), 2);
+        let mut iter = engine
+            .iter(ReadOptions::new().with_timestamp_range(..=first))
+            .unwrap();
+        assert_eq!(iter.timestamp(), first);
+        iter.seek_to_last().unwrap();
+        let mut older = backward(&mut iter);
+        older.reverse();
+        assert_eq!(older, vec![pair(b"a", b"a1"), pair(b"b", b"b1")]);
+        assert_eq!(skips(), 3);
+
+        assert!(read(ReadOptions::new().with_timestamp_range(0..0)).is_empty());
+        assert_eq!(skips(), 5);
+    }
+
+    #[test]
+    fn test_prefix_narrows_the_bounds_in_bytewise_families_only() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        for key in [&b"a:1"[..], b"b:1", b"b:2", b"b:3", b"b;", b"c:1"] {
+            engine.put(key.to_vec(), b"v".to_vec()).unwrap();
+        }
+        let keys = |iter: &mut EngineIterator| {
+            iter.seek_to_first().unwrap();
+            forward(iter)
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>()
+        };
+
+        let mut iter = engine
+            .iter(ReadOptions::new().with_prefix(b"b:".to_vec()))
+            .unwrap();
+        assert_eq!(
+            keys(&mut iter),
+            vec![b"b:1".to_vec(), b"b:2".to_vec(), b"b:3".to_vec()]
+        );
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key(), Some(&b"b:3"[..]));
+        iter.seek(b"a").unwrap();
+        assert_eq!(iter.key(), Some(&b"b:1"[..]));
+
+        // Explicit bounds narrow the prefix further
+        let options = ReadOptions::new()
+            .with_prefix(b"b:".to_vec())
+            .with_lower_bound(b"b:2".to_vec())
+            .with_upper_bound(b"z".to_vec());
+        let mut iter = engine.iter(options).unwrap();
+        assert_eq!(keys(&mut iter), vec![b"b:2".to_vec(), b"b:3".to_vec()]);
+
+        let reversed = engine
+            .create_column_family(
+                "reversed",
+                ColumnFamilyOptions::default()
+                    .with_comparator(Arc::new(comparator::ReverseBytewiseComparator)),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.iter_cf(&reversed, ReadOptions::new().with_prefix(b"b:".to_vec())),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_iterator_keeps_its_view_through_writes_and_flushes() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path()).with_merge_operator(Arc::new(U64AddOperator)),
+        )
+        .unwrap();
+        engine.put(b"a".to_vec(), b"old".to_vec()).unwrap();
+        engine
+            .merge(b"n".to_vec(), 1u64.to_le_bytes().to_vec())
+            .unwrap();
+        engine
+            .merge(b"n".to_vec(), 2u64.to_le_bytes().to_vec())
+            .unwrap();
+        let snapshot = engine.snapshot();
+        engine.put(b"b".to_vec(), b"new".to_vec()).unwrap();
+
+        let mut latest = engine.iter(ReadOptions::new()).unwrap();
+        let mut pinned = engine
+            .iter(ReadOptions::new().with_snapshot(&snapshot))
+            .unwrap();
+        assert_eq!(pinned.timestamp(), snapshot.timestamp());
+        drop(snapshot);
+
+        engine.put(b"a".to_vec(), b"newer".to_vec()).unwrap();
+        engine.delete(b"n".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.compact_range::<[u8], _>(..).unwrap();
+
+        latest.seek_to_first().unwrap();
+        assert_eq!(
+            forward(&mut latest),
+            vec![
+                (b"a".to_vec(), b"old".to_vec()),
+                (b"b".to_vec(), b"new".to_vec()),
+                (b"n".to_vec(), 3u64.to_le_bytes().to_vec()),
+            ]
+        );
+        pinned.seek_to_last().unwrap();
+        assert_eq!(
+            backward(&mut pinned),
+            vec![
+                (b"n".to_vec(), 3u64.to_le_bytes().to_vec()),
+                (b"a".to_vec(), b"old".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scans_stop_at_their_limits() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(crate::clock::ManualClock::new(Duration::from_secs(1_000)));
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_clock(Arc::clone(&clock) as _))
+                .unwrap();
+        // Deleted keys return nothing but still cost a scan
+        for i in 0..100 {
+            engine.put(key(i), vec![b'v'; 10]).unwrap();
+            engine.delete(key(i)).unwrap();
+        }
+        engine.put(key(100), b"live".to_vec()).unwrap();
+
+        let mut iter = engine
+            .iter(ReadOptions::new().with_max_keys_scanned(150))
+            .unwrap();
+        let error = iter.seek_to_first().unwrap_err();
+        assert!(matches!(error, Error::ScanLimitExceeded(_)), "{error}");
+        assert_eq!(error.code(), ferrisdb_core::error::ErrorCode::LimitExceeded);
+        assert!(!iter.valid());
+
+        let mut iter = engine
+            .iter(ReadOptions::new().with_max_keys_scanned(201))
+            .unwrap();
+        iter.seek_to_first().unwrap();
+        assert_eq!(iter.key(), Some(&key(100)[..]));
+
+        // Each deleted key's two versions take 24 bytes
+        let mut iter = engine
+            .iter(ReadOptions::new().with_max_bytes_read(1000))
+            .unwrap();
+        assert!(matches!(
+            iter.seek_to_first(),
+            Err(Error::ScanLimitExceeded(_))
+        ));
+
+        // Counts add up over seeks
+        let mut iter = engine
+            .iter(ReadOptions::new().with_max_keys_scanned(2))
+            .unwrap();
+        iter.seek(&key(100)).unwrap();
+        iter.seek(&key(100)).unwrap();
+        assert!(matches!(
+            iter.seek(&key(100)),
+            Err(Error::ScanLimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_scans_stop_at_their_deadline() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(crate::clock::ManualClock::new(Duration::from_secs(1_000)));
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_clock(Arc::clone(&clock) as _))
+                .unwrap();
+        for i in 0..10 {
+            engine.put(key(i), b"v".to_vec()).unwrap();
+        }
+
+        let mut iter = engine
+            .iter(ReadOptions::new().with_timeout(Duration::from_secs(5)))
+            .unwrap();
+        iter.seek_to_first().unwrap();
+        iter.next().unwrap();
+        clock.advance(Duration::from_secs(5));
+        assert!(matches!(iter.next(), Err(Error::ScanLimitExceeded(_))));
+        assert!(!iter.valid());
+
+        // The earlier of a deadline and a timeout applies
+        let options = ReadOptions::new()
+            .with_deadline(clock.now() + Duration::from_secs(1))
+            .with_timeout(Duration::from_secs(60));
+        let mut iter = engine.iter(options).unwrap();
+        iter.seek_to_first().unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert!(matches!(
+            iter.seek_to_last(),
+            Err(Error::ScanLimitExceeded(_))
+        ));
+    }
+}
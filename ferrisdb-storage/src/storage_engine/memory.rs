@@ -0,0 +1,181 @@
+//! Memory usage of a running engine
+//!
+//! [`StorageEngine::memory_usage`](super::StorageEngine::memory_usage)
+//! breaks down what the engine holds in memory:
+//!
+//! ```text
+//! active MemTables       written to, switched for a flush when full
+//! immutable MemTables    waiting for a flush
+//! block cache            decoded blocks table readers keep cached
+//! table readers          index blocks and bloom filters of open tables
+//! iterators              data iterators copied out of MemTables
+//! ```
+//!
+//! MemTables are measured directly; the rest is charged to the engine's
+//! [`MemoryTracker`](crate::utils::memory::MemoryTracker) by readers and
+//! iterators while they are open. With a
+//! [memory limit](super::Options::with_memory_limit) the engine flushes
+//! early and caches fewer blocks as usage approaches it.
+
+use super::EngineInner;
+use crate::utils::memory::{MemoryKind, MemoryTracker};
+
+use std::fmt;
+
+/// What an engine holds in memory, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Held by the active MemTables of all column families
+    pub active_memtables: usize,
+    /// Held by MemTables waiting for a flush
+    pub immutable_memtables: usize,
+    /// Held by decoded blocks in table readers' caches
+    pub block_cache: usize,
+    /// Held by the index blocks and bloom filters of open tables
+    pub table_readers: usize,
+    /// Held by data iterators copied out of MemTables
+    pub iterators: usize,
+    /// Limit of the total, 0 if there is none
+    pub limit: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the bytes held by all MemTables
+    pub fn memtables(&self) -> usize {
+        self.active_memtables + self.immutable_memtables
+    }
+
+    /// Returns the bytes held altogether
+    pub fn total(&self) -> usize {
+        self.memtables() + self.block_cache + self.table_readers + self.iterators
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "memory: {} bytes (memtables {} active, {} immutable; block cache {}; \
+             table readers {}; iterators {})",
+            self.total(),
+            self.active_memtables,
+            self.immutable_memtables,
+            self.block_cache,
+            self.table_readers,
+            self.iterators
+        )?;
+        if self.limit > 0 {
+            write!(f, " of {} allowed", self.limit)?;
+        }
+        Ok(())
+    }
+}
+
+impl EngineInner {
+    /// Returns what the engine holds in memory
+    pub(super) fn memory_usage(&self) -> MemoryUsage {
+        let (active_memtables, immutable_memtables) = self.memtables.read().memory_usage();
+        let memory = &self.options.memory;
+        MemoryUsage {
+            active_memtables,
+            immutable_memtables,
+            block_cache: memory.usage(MemoryKind::BlockCache),
+            table_readers: memory.usage(MemoryKind::TableReaders),
+            iterators: memory.usage(MemoryKind::Iterators),
+            limit: memory.limit(),
+        }
+    }
+
+    /// Records the MemTables' current usage with the memory tracker
+    pub(super) fn record_memtable_memory(&self) {
+        let (active, immutable) = self.memtables.read().memory_usage();
+        self.options
+            .memory
+            .record(MemoryKind::MemTables, active + immutable);
+    }
+}
+
+/// Returns whether a write of `size` bytes keeps the engine within the
+/// limit of `memory`, given what the MemTables hold
+///
+/// Switching only helps once the active MemTables hold a good part of the
+/// limit, so otherwise the write is let through rather than flushing
+/// tiny tables while readers and iterators hold the memory.
+pub(super) fn within_memory_limit(
+    memory: &MemoryTracker,
+    active: usize,
+    immutable: usize,
+    size: usize,
+) -> bool {
+    memory.record(MemoryKind::MemTables, active + immutable);
+    let limit = memory.limit();
+    limit == 0 || active < limit / 2 || memory.total() + size <= limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, ReadOptions, StorageEngine};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_usage_is_broken_down_by_holder() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        for i in 0..100u32 {
+            engine
+                .put(format!("key{:03}", i).into_bytes(), vec![b'v'; 64])
+                .unwrap();
+        }
+        let usage = engine.memory_usage();
+        assert!(usage.active_memtables > 100 * 64);
+        assert_eq!(usage.immutable_memtables, 0);
+        assert_eq!(usage.limit, 0);
+
+        engine.flush().unwrap();
+        engine.put(b"key050".to_vec(), b"new".to_vec()).unwrap();
+        let mut iter = engine.iter(ReadOptions::new()).unwrap();
+        iter.seek_to_first().unwrap();
+        let usage = engine.memory_usage();
+        assert!(usage.table_readers > 0);
+        assert!(usage.iterators > 0);
+        assert_eq!(
+            usage.total(),
+            usage.memtables() + usage.block_cache + usage.table_readers + usage.iterators
+        );
+
+        drop(iter);
+        let usage = engine.memory_usage();
+        assert_eq!((usage.table_readers, usage.iterators), (0, 0));
+    }
+
+    #[test]
+    fn test_limit_flushes_memtables_early() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path())
+            .with_memtable_size(1024 * 1024)
+            .with_memory_limit(64 * 1024);
+        let engine = StorageEngine::open(options).unwrap();
+
+        for i in 0..1000u32 {
+            engine
+                .put(format!("key{:04}", i).into_bytes(), vec![b'v'; 100])
+                .unwrap();
+            assert!(engine.memory_usage().active_memtables <= 64 * 1024);
+        }
+        engine.flush().unwrap();
+
+        // Far below the MemTable size, only the limit could have switched
+        // MemTables before the final flush
+        assert!(engine.statistics().flushes > 1);
+        assert!(engine
+            .memory_usage()
+            .to_string()
+            .ends_with("of 65536 allowed"));
+        for i in (0..1000u32).step_by(97) {
+            assert_eq!(
+                engine.get(format!("key{:04}", i).as_bytes()).unwrap(),
+                Some(vec![b'v'; 100])
+            );
+        }
+    }
+}
@@ -0,0 +1,4156 @@
+//! Main storage engine implementation
+//!
+//! [`StorageEngine`] composes the WAL, MemTables, SSTables, the MANIFEST and
+//! compaction into a single key-value store:
+//!
+//! ```text
+//!  put/delete/merge                       get/scan
+//!        │                                    │
+//!        ▼                                    ▼
+//!   WAL segment ──▶ active MemTable ◀──── newest first
+//!                        │ full               │
+//!                        ▼                    │
+//!                 immutable MemTables ◀───────┤
+//!                        │ background flush   │
+//!                        ▼                    │
+//!                 L0 SSTables ◀───────────────┤
+//!                        │ compaction         │
+//!                        ▼                    │
+//!                 L1..L6 SSTables ◀───────────┘
+//! ```
+//!
+//! # Timestamps
+//!
+//! Every write is assigned the next timestamp by the [`TimestampOracle`]
+//! under the write lock, logged to the current WAL segment, inserted into
+//! the active MemTable, and only then published as the last committed
+//! timestamp. Timestamps are hybrid logical clock readings: they follow
+//! the wall clock of [`Options::with_clock`] but keep increasing when it
+//! goes backwards. Reads pin the last
+//! committed timestamp and ignore anything newer, so they see a consistent
+//! view while writes, flushes, and compactions continue.
+//! [`get_at`](StorageEngine::get_at) and [`scan_at`](StorageEngine::scan_at)
+//! read at an earlier timestamp instead, as far back as the
+//! [version retention](Options::with_version_retention) reaches.
+//!
+//! A [`Transaction`] or [`PessimisticTransaction`] commits its writes as
+//! one batch: consecutive timestamps, a single WAL batch record, and one
+//! publish of the last timestamp, so the batch is never seen or recovered
+//! in part. Until then it buffers them in a [`WriteBatchWithIndex`], whose
+//! reads see the pending writes on top of the engine's data. A
+//! [`PessimisticTransaction`] can also take part in a distributed
+//! transaction, preparing durably before an external coordinator decides
+//! whether it commits.
+//!
+//! # Column Families
+//!
+//! Keys live in [`ColumnFamily`]s, each with its own MemTables, SSTables
+//! and settings but all sharing the WAL and the timestamp sequence. Plain
+//! methods such as [`put`](StorageEngine::put) use the default family;
+//! the `*_cf` variants take a family handle. Families configured with a
+//! TTL treat values as deleted once they expire, and compaction drops them.
+//!
+//! # Background Work
+//!
+//! Two jobs of the engine's [`Scheduler`] work in the background: the
+//! `flush` job writes full MemTables to level 0 and then triggers the
+//! `compaction` job, which runs compactions picked by the configured
+//! strategy until none is due. Flushes therefore never wait behind a long
+//! compaction. After each step the [`WriteController`] is updated, which
+//! slows or stops writers while compaction falls behind. Writers also
+//! block while `max_immutable_memtables` MemTables are waiting to be
+//! flushed. Besides each family's MemTable size, an optional
+//! [write buffer budget](Options::with_write_buffer_budget) caps the
+//! memory of all active MemTables together, and a
+//! [memory limit](Options::with_memory_limit) everything the engine holds
+//! (see [`memory_usage`](StorageEngine::memory_usage)). If either job
+//! fails or panics, all further writes fail.
+//! [`compaction_status`](StorageEngine::compaction_status) shows the
+//! compaction running and those due next, and an [`EventListener`] hears
+//! about each flush and compaction as it happens; an
+//! [event log](Options::with_event_log) writes them to a file as JSON
+//! lines. Changes to the data itself reach a [`Watcher`] as each write
+//! commits.
+//!
+//! With a scrub interval set, a third job, `scrub`, periodically reads
+//! cold tables back to catch damage before a read does (see
+//! [`scrub`](StorageEngine::scrub)). Damage found by the scrub, a read or
+//! WAL replay is kept in the `CORRUPTION` file of the data directory, see
+//! [`corruption_reports`](StorageEngine::corruption_reports).
+//!
+//! With a [WAL sync interval](Options::with_wal_sync_interval) set, the
+//! `wal_sync` job periodically syncs the WAL to disk.
+//!
+//! # Replication
+//!
+//! Since timestamps are consecutive, a replica's last timestamp says
+//! exactly which writes it has. [`tail_wal`](StorageEngine::tail_wal)
+//! follows a primary's WAL from any timestamp still in it, and a replica
+//! opened with [`Options::with_replica`] applies the records through
+//! [`write_replicated`](StorageEngine::write_replicated), rejecting
+//! ordinary writes.
+//!
+//! Without copying anything, [`open_read_only`](StorageEngine::open_read_only)
+//! and [`open_as_secondary`](StorageEngine::open_as_secondary) read a
+//! database directory another engine has open; a secondary follows the
+//! primary by re-reading its MANIFESTs and WAL (see
+//! [`catch_up_with_primary`](StorageEngine::catch_up_with_primary)).
+//!
+//! # Durability
+//!
+//! Each MemTable's writes go to a WAL segment of its own. Unless
+//! [turned off](Options::with_wal_preallocation), the flush job creates
+//! the next segment's file ahead of time, so switching segments only
+//! renames it. A flush records
+//! the next segment as the MANIFEST's log number and deletes the flushed
+//! one. On open, segments at or above the log number are replayed into
+//! level 0 tables before the engine accepts writes (see [`RecoveryReport`]).
+//! The configured sync mode decides whether a write's WAL record is synced
+//! before it returns, unless [`WriteOptions::with_sync`] overrides it for
+//! that write. Writes made with [`WriteOptions::with_disable_wal`] skip
+//! the WAL and only become durable once their MemTable is flushed.
+//! Records loaded with [`import`](StorageEngine::import) skip both the WAL
+//! and the MemTables and are durable once their tables are added.
+//! A database whose MANIFEST is lost or corrupt can be rebuilt from its
+//! tables and WAL with [`repair`](StorageEngine::repair).
+
+mod backup;
+mod batch;
+mod checkpoint;
+mod column_family;
+mod compaction_status;
+mod corruption;
+mod dir_lock;
+mod event_listener;
+mod event_log;
+mod flush_options;
+mod import;
+mod index;
+mod indexed_batch;
+mod iterator;
+mod memory;
+mod offload;
+mod options;
+mod pessimistic;
+mod pinned;
+mod prepared;
+mod read_options;
+mod recovery;
+mod repair;
+mod replication;
+mod scrub;
+mod secondary;
+mod size_estimate;
+mod slow_log;
+mod snapshot;
+mod statistics;
+mod transaction;
+mod ttl;
+mod watch;
+mod write_options;
+
+pub use backup::{BackupEngine, BackupInfo};
+pub use batch::WriteBatch;
+pub use column_family::{ColumnFamily, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY};
+pub use compaction_status::{
+    CompactionInput, CompactionJob, CompactionProgressInfo, CompactionStatus,
+};
+pub use corruption::CorruptionReport;
+pub use event_listener::{
+    CompactionJobInfo, CorruptionInfo, EventListener, FlushJobInfo, WalRotationInfo,
+    WriteStallChange,
+};
+pub use flush_options::FlushOptions;
+pub use import::{ImportOptions, ImportReport, DEFAULT_SORT_BUFFER_SIZE};
+pub use index::{IndexExtractor, SecondaryIndex};
+pub use indexed_batch::WriteBatchWithIndex;
+pub use iterator::EngineIterator;
+pub use memory::MemoryUsage;
+pub use options::Options;
+pub use pessimistic::PessimisticTransaction;
+pub use pinned::PinnedSlice;
+pub use read_options::ReadOptions;
+pub use recovery::{RecoveryObserver, RecoveryProgress, RecoveryReport};
+pub use repair::{RepairReport, LOST_DIR_NAME};
+pub use scrub::{CorruptTable, ScrubReport};
+pub use slow_log::{SlowOperation, SlowOperationKind};
+pub use snapshot::Snapshot;
+pub use statistics::{properties, Statistics};
+pub use transaction::Transaction;
+pub use watch::{WatchEvent, Watcher, DEFAULT_WATCH_CAPACITY};
+pub use write_options::WriteOptions;
+
+use self::batch::{BatchEntry, BatchOp};
+use self::column_family::{
+    column_family_dir, parse_column_family_dir, ColumnFamilyData, DEFAULT_COLUMN_FAMILY_ID,
+};
+use self::compaction_status::RunningCompactions;
+use self::corruption::CorruptionLog;
+use self::dir_lock::DirLock;
+use self::event_log::JsonEventLog;
+use self::index::IndexData;
+use self::offload::OFFLOAD_JOB;
+use self::pinned::copy_into;
+use self::prepared::{Prepared, PreparedWrites};
+use self::recovery::{recover, wal_segments_in};
+use self::scrub::{Scrubber, SCRUB_JOB};
+use self::secondary::OpenMode;
+use self::slow_log::SlowLog;
+use self::snapshot::{owned_range, owned_ranges, SnapshotList};
+use self::statistics::Counters;
+use self::watch::WatchQueue;
+use crate::compaction::{CompactionStats, MergingIterator};
+use crate::comparator::{self, Comparator};
+use crate::files::{wal_file_name, PREALLOCATED_WAL_FILE_NAME};
+use crate::lock_manager::LockManager;
+use crate::manifest::{SSTableMeta, VersionEdit};
+use crate::memtable::MemTable;
+use crate::merge;
+use crate::oracle::TimestampOracle;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::remote::TieredVfs;
+use crate::scheduler::{JobInfo, Schedule, Scheduler};
+use crate::sstable::SSTableEntry;
+use crate::utils::cache::CacheStats;
+use crate::utils::memory::MemoryTracker;
+use crate::version::TableHandle;
+use crate::vfs;
+use crate::wal::{WALCache, WALEntry, WALMetrics, WALTailer, WALWriter};
+use crate::write_stall::{WriteController, WriteStallCondition};
+use crate::StorageConfig;
+use ferrisdb_core::{Error, Key, Operation, Result, SyncMode, Timestamp, Value};
+
+use parking_lot::{Condvar, MappedMutexGuard, Mutex, MutexGuard, RwLock};
+
+use std::collections::{btree_map, hash_map, BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// Upper bound on the bytes a WAL entry adds beyond its key and value
+const WAL_ENTRY_OVERHEAD: usize = 64;
+
+/// Key bounds of a scan, owned so they can outlive the caller's range
+type KeyRange = (Bound<Key>, Bound<Key>);
+
+/// Name of the background job flushing retired MemTables
+const FLUSH_JOB: &str = "flush";
+
+/// Name of the background job running compactions
+const COMPACTION_JOB: &str = "compaction";
+
+/// Name of the background job syncing the WAL
+const WAL_SYNC_JOB: &str = "wal_sync";
+
+/// The main storage engine for FerrisDB
+///
+/// This struct coordinates all storage components including WAL, MemTable,
+/// SSTables, and compaction. It provides the primary interface for
+/// reading and writing data.
+///
+/// # Architecture
+///
+/// The storage engine implements an LSM-tree (Log-Structured Merge-tree) with:
+/// - Write-ahead logging for durability
+/// - In-memory MemTable for recent writes
+/// - On-disk SSTables organized in levels
+/// - Background compaction to optimize read performance
+///
+/// # Thread Safety
+///
+/// `StorageEngine` is `Send + Sync`; share it through an `Arc`. Writes are
+/// serialized internally, reads never wait for writes.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::storage_engine::{Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+///
+/// engine.put(b"user:1".to_vec(), b"alice".to_vec())?;
+/// engine.put(b"user:2".to_vec(), b"bob".to_vec())?;
+/// engine.delete(b"user:1".to_vec())?;
+///
+/// assert_eq!(engine.get(b"user:1")?, None);
+/// assert_eq!(
+///     engine.scan(b"user:".as_slice()..b"user;".as_slice())?,
+///     vec![(b"user:2".to_vec(), b"bob".to_vec())]
+/// );
+///
+/// engine.close()?;
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct StorageEngine {
+    inner: Arc<EngineInner>,
+    /// Locks on the data and WAL directories, released on close
+    dir_locks: Mutex<Vec<DirLock>>,
+    recovery: RecoveryReport,
+}
+
+impl StorageEngine {
+    /// Opens the database described by `options`, creating it if needed
+    ///
+    /// This will:
+    /// 1. Create necessary directories and lock them against other engines
+    /// 2. Load the column families and their live SSTables from the MANIFESTs
+    /// 3. Replay unflushed WAL segments into level 0 tables, truncating a
+    ///    torn tail left by a crash (see [`recovery_report`](Self::recovery_report))
+    /// 4. Start the background flush and compaction jobs
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - Directory creation fails
+    /// - Another engine has the data or WAL directory open
+    ///   (`Error::AlreadyLocked`)
+    /// - The MANIFEST or a WAL segment cannot be read
+    /// - Corruption is detected during recovery, including a segment
+    ///   missing between the replayed ones
+    pub fn open(options: Options) -> Result<Self> {
+        Self::open_in_mode(options, OpenMode::Primary)
+    }
+
+    /// Opens the database described by `options` for reading only
+    ///
+    /// The engine reads the database as it was when opened. It neither
+    /// locks nor changes any file: unflushed WAL segments are replayed
+    /// into MemTables only, and a partly written entry at the end of the
+    /// newest one is skipped. It can therefore open a database another
+    /// engine has open, for example to run analytics next to it. Writes,
+    /// flushes, compactions and column family changes fail, and no
+    /// background job runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if there is no database in the data
+    /// directory, or an error if a MANIFEST or WAL segment cannot be read.
+    pub fn open_read_only(options: Options) -> Result<Self> {
+        Self::open_in_mode(options, OpenMode::ReadOnly)
+    }
+
+    /// Opens the database described by `options` as a secondary of the
+    /// engine writing it
+    ///
+    /// A secondary reads like an engine opened with
+    /// [`open_read_only`](Self::open_read_only), but follows the primary
+    /// through [`catch_up_with_primary`](Self::catch_up_with_primary), and
+    /// on its own every [catch-up interval](Options::with_catch_up_interval).
+    ///
+    /// # Errors
+    ///
+    /// See [`open_read_only`](Self::open_read_only).
+    pub fn open_as_secondary(options: Options) -> Result<Self> {
+        Self::open_in_mode(options, OpenMode::Secondary)
+    }
+
+    /// Opens a fresh database kept entirely in memory
+    ///
+    /// Shorthand for opening [`Options::in_memory`]: the engine writes,
+    /// flushes and compacts through the same code paths as on disk, but
+    /// into an in-memory filesystem that is gone once the engine is
+    /// dropped. Useful for unit tests and experiments that shouldn't leave
+    /// files behind.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::storage_engine::StorageEngine;
+    ///
+    /// let engine = StorageEngine::open_in_memory()?;
+    /// engine.put(b"key".to_vec(), b"value".to_vec())?;
+    /// engine.flush()?;
+    /// assert_eq!(engine.get(b"key")?, Some(b"value".to_vec()));
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error only if setting up the empty database fails.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::open(Options::in_memory())
+    }
+
+    fn open_in_mode(mut options: Options, mode: OpenMode) -> Result<Self> {
+        let read_only = mode != OpenMode::Primary;
+        let dir_locks = if read_only {
+            Vec::new()
+        } else {
+            lock_dirs(&options)?
+        };
+        // Everything after the locks goes through the tiers, so tables are
+        // found wherever they are
+        let tiered = options.remote_storage.clone().map(|remote| {
+            let tiered = Arc::new(
+                TieredVfs::new(Arc::clone(&options.vfs), remote, &options.config.data_dir)
+                    .with_cache_size(options.config.remote_cache_size),
+            );
+            options.vfs = tiered.clone();
+            tiered
+        });
+        // Clones of the options don't share what engines hold
+        options.memory = Arc::new(MemoryTracker::new(options.config.memory_limit));
+        options.corruption = Arc::new(CorruptionLog::open(
+            &options.vfs,
+            &options.config.data_dir,
+            &options.clock,
+            read_only,
+        )?);
+        if let Some(writer) = options.event_log.clone() {
+            let log = JsonEventLog::new(writer, Arc::clone(&options.clock));
+            options.event_listeners.push(Arc::new(log));
+        }
+        let config = &options.config;
+        if let (Some(archive), false) = (&config.wal_archive_dir, read_only) {
+            options.vfs.create_dir_all(archive)?;
+        }
+
+        let rate_limiter = Arc::new(RateLimiter::new(config.rate_limiter_bytes_per_sec));
+        let open_family = if read_only {
+            ColumnFamilyData::open_read_only
+        } else {
+            ColumnFamilyData::open
+        };
+        let default = Arc::new(open_family(
+            DEFAULT_COLUMN_FAMILY_ID,
+            DEFAULT_COLUMN_FAMILY,
+            &config.data_dir,
+            &options,
+            ColumnFamilyOptions::from_options(&options),
+            &rate_limiter,
+        )?);
+        let families = open_column_families(&options, &default, &rate_limiter)?;
+
+        let wal_metrics = Arc::new(WALMetrics::new());
+        let (recovery, prepared, wal, memtables) = if read_only {
+            let (recovery, memtables) = secondary::replay_wal(&options, &families)?;
+            (recovery, PreparedWrites::new(), None, memtables)
+        } else {
+            let segments = wal_segments_in(&options.vfs, &config.wal_dir)?;
+            if let Some(&(number, _)) = segments.last() {
+                default.versions.mark_file_number_used(number);
+            }
+
+            let wal_number = default.versions.new_file_number();
+            let (recovery, prepared) = recover(&options, &families, &segments, wal_number)?;
+            let wal = WALWriter::new_in(
+                &options.vfs,
+                config.wal_dir.join(wal_file_name(wal_number)),
+                config.wal_sync_mode,
+                config.wal_size_limit as u64,
+                recovery.last_timestamp,
+            )?
+            .with_metrics(Arc::clone(&wal_metrics));
+
+            let memtables = MemTables {
+                active: families
+                    .values()
+                    .map(|cf| (cf.id, cf.new_memtable()))
+                    .collect(),
+                active_wal: wal_number,
+                immutable: VecDeque::new(),
+                flushed_wals: VecDeque::new(),
+            };
+            (recovery, prepared, Some(wal), memtables)
+        };
+
+        let wal_cache = (!read_only && config.wal_cache_size > 0).then(|| {
+            Arc::new(WALCache::new(
+                config.wal_cache_size,
+                recovery.last_timestamp,
+            ))
+        });
+        let write_controller = WriteController::new(config);
+        let scrubber = Scrubber::new(&options);
+        let oracle = TimestampOracle::new(Arc::clone(&options.clock), recovery.last_timestamp);
+        let snapshots =
+            SnapshotList::new(&oracle, Duration::from_millis(config.version_retention_ms));
+        let inner = Arc::new_cyclic(|weak: &Weak<EngineInner>| EngineInner {
+            scheduler: Scheduler::new().with_failure_handler({
+                let weak = weak.clone();
+                move |_, error| {
+                    if let Some(inner) = weak.upgrade() {
+                        inner.background_failed(error);
+                    }
+                }
+            }),
+            write_controller,
+            reported_stall: Mutex::new(WriteStallCondition::Normal),
+            memtables: RwLock::new(memtables),
+            writer: Mutex::new(wal),
+            wal_preallocated: Mutex::new(false),
+            wal_cache,
+            oracle,
+            snapshots,
+            locks: LockManager::with_comparator(Arc::clone(&default.comparator)),
+            prepared: Mutex::default(),
+            indexes: RwLock::default(),
+            watchers: Mutex::default(),
+            counters: Counters::default(),
+            slow_log: SlowLog::new(&options.config),
+            scrubber,
+            tiered,
+            wal_metrics,
+            compaction: Mutex::new(()),
+            running_compactions: RunningCompactions::default(),
+            closed: AtomicBool::new(false),
+            background: Mutex::new(BackgroundState::default()),
+            flushed: Condvar::new(),
+            rate_limiter,
+            column_families: RwLock::new(families),
+            default,
+            options,
+            mode,
+        });
+
+        match mode {
+            OpenMode::Primary => {
+                inner.restore_prepared(prepared)?;
+                inner.update_write_stall();
+                EngineInner::start_background_jobs(&inner)?;
+                // Recovered tables may already be due for compaction, or
+                // for offloading if the remote level was lowered
+                inner.schedule_background_work();
+                inner.scheduler.trigger(OFFLOAD_JOB);
+            }
+            OpenMode::ReadOnly => {}
+            OpenMode::Secondary => EngineInner::start_catch_up_job(&inner)?,
+        }
+
+        Ok(Self {
+            inner,
+            dir_locks: Mutex::new(dir_locks),
+            recovery,
+        })
+    }
+
+    /// Rebuilds the MANIFESTs of a database from the files on disk
+    ///
+    /// A last resort for a database that fails to open because a MANIFEST
+    /// is lost or corrupt: every readable table is registered in level 0,
+    /// what can still be read of the WAL is written to new tables, and
+    /// unreadable tables, damaged WAL segments and the old MANIFESTs are
+    /// moved to the [`LOST_DIR_NAME`] directory. Pass the options the
+    /// database is opened with, column families included, since tables are
+    /// read with their families' comparators. Open the database as usual
+    /// afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::AlreadyLocked` if an engine has the database open,
+    /// `Error::Unsupported` for options with
+    /// [remote storage](Options::with_remote_storage),
+    /// `Error::InvalidArgument` if a table was written with a different
+    /// comparator than its family's options give, or an error if a file
+    /// can't be listed, moved or written.
+    pub fn repair(options: Options) -> Result<RepairReport> {
+        repair::repair(&options)
+    }
+
+    /// Returns the configuration the engine was opened with
+    pub fn config(&self) -> &StorageConfig {
+        &self.inner.options.config
+    }
+
+    /// Returns what was recovered from the WAL when the engine was opened
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Returns the metrics of the WAL, summed over all segments since open
+    pub fn wal_metrics(&self) -> &WALMetrics {
+        &self.inner.wal_metrics
+    }
+
+    /// Returns the counters of the WAL cache, if the engine keeps one
+    ///
+    /// Hits are records tailers read from memory, misses are polls that
+    /// had to read the files.
+    pub fn wal_cache_stats(&self) -> Option<CacheStats> {
+        self.inner.wal_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Returns the state of the background jobs
+    pub fn background_jobs(&self) -> Vec<JobInfo> {
+        self.inner.scheduler.jobs()
+    }
+
+    /// Reads every live table back in full, verifying its checksums
+    ///
+    /// Unlike the background job enabled by
+    /// [`Options::with_scrub_interval`], this reads hot tables as well as
+    /// cold ones, and tables found damaged before. Reads are throttled to
+    /// the scrub rate, and a scrub in the background is waited for.
+    /// Tables found damaged for the first time are counted in the
+    /// [`statistics`](Self::statistics) and passed to the
+    /// [corruption handler](Options::with_corruption_handler).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a table can't be read for another reason than
+    /// damage, such as an I/O error. Damaged tables are listed in the
+    /// report instead.
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        self.inner.check_open()?;
+        self.inner.scrub(false)
+    }
+
+    /// Offloads the local tables of the levels from the
+    /// [remote level](Options::with_remote_level) down to remote storage,
+    /// returning how many were offloaded
+    ///
+    /// A job does the same in the background after every compaction;
+    /// this is for offloading right away, such as after lowering the
+    /// remote level. Engines without
+    /// [remote storage](Options::with_remote_storage) have nothing to
+    /// offload.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` for an engine open for reading
+    /// only, or an error if a table can't be read or uploaded. The tables
+    /// offloaded before the error stay remote.
+    pub fn offload_tables(&self) -> Result<usize> {
+        self.inner.check_open()?;
+        self.inner.check_writable()?;
+        self.inner.offload_tables()
+    }
+
+    /// Returns the compactions running and the compactions due next
+    ///
+    /// Shows which tables compaction is reading and how far it got, to
+    /// tell whether it is what keeps the disks busy. Engines opened for
+    /// reading only never have compactions due.
+    pub fn compaction_status(&self) -> CompactionStatus {
+        self.inner.compaction_status()
+    }
+
+    /// Returns the controller slowing or stopping writes on compaction backlog
+    pub fn write_controller(&self) -> &WriteController {
+        &self.inner.write_controller
+    }
+
+    /// Returns statistics summed over all column families
+    pub fn statistics(&self) -> Statistics {
+        let families = self.inner.families();
+        let families: Vec<_> = families.iter().map(Arc::as_ref).collect();
+        statistics::collect(&self.inner, &families)
+    }
+
+    /// Returns statistics of one column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped.
+    pub fn statistics_cf(&self, cf: &ColumnFamily) -> Result<Statistics> {
+        let cf = self.inner.column_family(cf)?;
+        Ok(statistics::collect(&self.inner, &[&cf]))
+    }
+
+    /// Returns the corrupt data the engine found, oldest first
+    ///
+    /// Includes what earlier runs found, as the reports are kept in the
+    /// `CORRUPTION` file of the data directory; see [`CorruptionReport`].
+    pub fn corruption_reports(&self) -> Vec<CorruptionReport> {
+        self.inner.options.corruption.reports()
+    }
+
+    /// Returns what the engine holds in memory, broken down by holder
+    ///
+    /// See [`MemoryUsage`]; with a
+    /// [memory limit](Options::with_memory_limit) it also gives the limit.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.inner.memory_usage()
+    }
+
+    /// Returns the operations that took at least the
+    /// [slow log threshold](Options::with_slow_log_threshold), newest
+    /// first
+    ///
+    /// The log keeps the latest operations up to its
+    /// [capacity](Options::with_slow_log_capacity) and is empty unless a
+    /// threshold is set. See [`SlowOperation`] for what each entry holds.
+    pub fn slow_log(&self) -> Vec<SlowOperation> {
+        self.inner.slow_log.entries()
+    }
+
+    /// Empties the slow log
+    pub fn clear_slow_log(&self) {
+        self.inner.slow_log.clear();
+    }
+
+    /// Returns a named property of the default column family
+    ///
+    /// Returns `None` for names not listed in [`properties`].
+    pub fn property(&self, name: &str) -> Option<String> {
+        statistics::collect(&self.inner, &[&self.inner.default]).property(name)
+    }
+
+    /// Returns a named property of a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped.
+    pub fn property_cf(&self, cf: &ColumnFamily, name: &str) -> Result<Option<String>> {
+        Ok(self.statistics_cf(cf)?.property(name))
+    }
+
+    /// Returns the column family with the given name, if it exists
+    ///
+    /// `cf_handle("default")` always returns the default column family.
+    pub fn cf_handle(&self, name: &str) -> Option<ColumnFamily> {
+        self.inner
+            .column_families
+            .read()
+            .values()
+            .find(|cf| cf.name == name)
+            .map(|cf| ColumnFamily {
+                data: Arc::clone(cf),
+            })
+    }
+
+    /// Returns the names of all column families, the default first
+    pub fn column_family_names(&self) -> Vec<String> {
+        self.inner
+            .column_families
+            .read()
+            .values()
+            .map(|cf| cf.name.clone())
+            .collect()
+    }
+
+    /// Creates a new, empty column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the name is empty or already
+    /// taken, or an error if the engine is closed or the family's files
+    /// cannot be created.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily> {
+        self.inner.check_open()?;
+        self.inner.create_column_family(name, options)
+    }
+
+    /// Drops a column family and all of its data
+    ///
+    /// The family disappears from the engine right away; its files are
+    /// deleted once every handle to it is dropped. Unflushed writes to it
+    /// are discarded, even by recovery.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` for the default column family or
+    /// a family that was already dropped, or an error if the engine is
+    /// closed or the MANIFEST write fails.
+    pub fn drop_column_family(&self, cf: &ColumnFamily) -> Result<()> {
+        self.inner.check_open()?;
+        self.inner.drop_column_family(cf)
+    }
+
+    /// Creates a secondary index over the default column family, named
+    /// `name` and indexing each record by what `extractor` returns for it
+    ///
+    /// The index entries go to a column family named like the index. If
+    /// it doesn't exist, it is created and filled from the records there
+    /// are, with writes held back until it is; if it does, it is taken to
+    /// hold the index already, as after reopening the engine. From then
+    /// on, every write keeps the index up to date in the same WAL record.
+    /// Indexes aren't persisted, so create them again after each open
+    /// before writing. See [`SecondaryIndex`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if an index of that name exists
+    /// or the name is the base family's, or an error if the engine is
+    /// closed or the index can't be filled.
+    pub fn create_index(
+        &self,
+        name: &str,
+        extractor: Arc<dyn IndexExtractor>,
+    ) -> Result<SecondaryIndex> {
+        self.inner.check_open()?;
+        let base = ColumnFamily {
+            data: Arc::clone(&self.inner.default),
+        };
+        self.inner.create_index(&base, name, extractor)
+    }
+
+    /// Creates a secondary index over a column family
+    ///
+    /// Families with a TTL can't be indexed, and indexed families don't
+    /// accept merges.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped or has
+    /// a TTL, otherwise errors for the same reasons as
+    /// [`create_index`](Self::create_index).
+    pub fn create_index_cf(
+        &self,
+        cf: &ColumnFamily,
+        name: &str,
+        extractor: Arc<dyn IndexExtractor>,
+    ) -> Result<SecondaryIndex> {
+        self.inner.check_open()?;
+        self.inner.create_index(cf, name, extractor)
+    }
+
+    /// Stops maintaining an index and drops its column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the index was dropped already,
+    /// or an error if the engine is closed or the MANIFEST write fails.
+    pub fn drop_index(&self, index: &SecondaryIndex) -> Result<()> {
+        self.inner.check_open()?;
+        self.inner.drop_index(index)
+    }
+
+    /// Returns the records an index maps `value` to, ordered by key
+    ///
+    /// The index and its base family are read at one snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed, either family was
+    /// dropped, or the read fails.
+    pub fn get_by_index(&self, index: &SecondaryIndex, value: &[u8]) -> Result<Vec<(Key, Value)>> {
+        self.scan_index(index, value..=value)
+    }
+
+    /// Returns the records whose indexed value is in `range`, ordered by
+    /// that value and then by key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_by_index`](Self::get_by_index).
+    pub fn scan_index<K, R>(&self, index: &SecondaryIndex, range: R) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        index::scan_index(&self.snapshot(), index, owned_range(range))
+    }
+
+    /// Sets the value of a key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed, background work failed,
+    /// the entry doesn't fit into an empty MemTable, or the WAL write fails.
+    pub fn put(&self, key: Key, value: Value) -> Result<()> {
+        self.inner.write(key, value, Operation::Put)
+    }
+
+    /// Sets the value of a key in a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped,
+    /// otherwise errors for the same reasons as [`put`](Self::put).
+    pub fn put_cf(&self, cf: &ColumnFamily, key: Key, value: Value) -> Result<()> {
+        self.inner.column_family(cf)?;
+        let mut batch = WriteBatch::new();
+        batch.put_cf(cf, key, value);
+        self.inner
+            .write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Sets the value of a key that expires after `ttl`
+    ///
+    /// `ttl` overrides the default set with [`Options::with_ttl`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the default column family has
+    /// no TTL, otherwise errors for the same reasons as [`put`](Self::put).
+    pub fn put_with_ttl(&self, key: Key, value: Value, ttl: Duration) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl(key, value, ttl);
+        self.inner
+            .write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Sets the value of a key in a column family that expires after `ttl`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped or has
+    /// no TTL, otherwise errors for the same reasons as [`put`](Self::put).
+    pub fn put_cf_with_ttl(
+        &self,
+        cf: &ColumnFamily,
+        key: Key,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.inner.column_family(cf)?;
+        let mut batch = WriteBatch::new();
+        batch.put_cf_with_ttl(cf, key, value, ttl);
+        self.inner
+            .write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Deletes a key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`put`](Self::put).
+    pub fn delete(&self, key: Key) -> Result<()> {
+        self.inner.write(key, Vec::new(), Operation::Delete)
+    }
+
+    /// Deletes a key from a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`put_cf`](Self::put_cf).
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: Key) -> Result<()> {
+        self.inner.column_family(cf)?;
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(cf, key);
+        self.inner
+            .write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Records a merge operand for a key
+    ///
+    /// The operand is combined with the key's existing value by the merge
+    /// operator configured in [`Options::with_merge_operator`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if no merge operator is configured,
+    /// otherwise errors for the same reasons as [`put`](Self::put).
+    pub fn merge(&self, key: Key, operand: Value) -> Result<()> {
+        self.inner.check_merge_operator(DEFAULT_COLUMN_FAMILY_ID)?;
+        self.inner.write(key, operand, Operation::Merge)
+    }
+
+    /// Records a merge operand for a key in a column family
+    ///
+    /// The operand is combined by the family's merge operator, set through
+    /// [`ColumnFamilyOptions::with_merge_operator`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family has no merge
+    /// operator, otherwise errors for the same reasons as [`put_cf`](Self::put_cf).
+    pub fn merge_cf(&self, cf: &ColumnFamily, key: Key, operand: Value) -> Result<()> {
+        self.inner.column_family(cf)?;
+        self.inner.check_merge_operator(cf.id())?;
+        let mut batch = WriteBatch::new();
+        batch.merge_cf(cf, key, operand);
+        self.inner
+            .write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Atomically applies every write in `batch`
+    ///
+    /// Readers see all of the batch or none of it, and recovery replays
+    /// all of it or none of it. With `sync`, the WAL is synced to disk
+    /// before returning, whatever the configured sync mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the batch writes to a dropped
+    /// column family or holds merges for a family without a merge
+    /// operator, otherwise errors for the same reasons as [`put`](Self::put).
+    /// Nothing of the batch is applied on error.
+    pub fn write(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.write_with_options(
+            batch,
+            &WriteOptions {
+                sync: sync.then_some(true),
+                ..WriteOptions::default()
+            },
+        )
+    }
+
+    /// Atomically applies every write in `batch` as `options` say
+    ///
+    /// See [`write`](Self::write) and [`WriteOptions`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `options` ask to sync writes
+    /// that skip the WAL, otherwise errors for the same reasons as
+    /// [`write`](Self::write).
+    pub fn write_with_options(&self, batch: WriteBatch, options: &WriteOptions) -> Result<()> {
+        if options.sync == Some(true) && options.disable_wal {
+            return Err(Error::InvalidArgument(
+                "Writes that skip the WAL can't be synced".to_string(),
+            ));
+        }
+        for column_family in batch.merge_column_families() {
+            self.inner.check_merge_operator(column_family)?;
+        }
+        self.inner.write_batch(batch, options, || Ok(()))
+    }
+
+    /// Returns the current value of a key, or `None` if it doesn't exist
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed, an SSTable cannot be read,
+    /// or the merge operator fails.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        self.inner.check_open()?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        let default = &self.inner.default;
+        self.inner
+            .timed(SlowOperationKind::Get, Some(default), Some(key), || {
+                self.inner.get_at(default, key, pin.timestamp())
+            })
+    }
+
+    /// Returns the current value of a key in a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped,
+    /// otherwise errors for the same reasons as [`get`](Self::get).
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Value>> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        self.inner
+            .timed(SlowOperationKind::Get, Some(&cf), Some(key), || {
+                self.inner.get_at(&cf, key, pin.timestamp())
+            })
+    }
+
+    /// Returns the current value of a key without copying it out of the
+    /// table it was read from
+    ///
+    /// The returned [`PinnedSlice`] borrows the value from its decoded
+    /// block, keeping the block in memory while it is held. Values in
+    /// MemTables, merged values and values of families with a TTL are
+    /// copied as by [`get`](Self::get).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get).
+    pub fn get_pinned(&self, key: &[u8]) -> Result<Option<PinnedSlice>> {
+        self.inner.check_open()?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        let default = &self.inner.default;
+        self.inner
+            .timed(SlowOperationKind::Get, Some(default), Some(key), || {
+                self.inner.get_pinned_at(default, key, pin.timestamp())
+            })
+    }
+
+    /// Returns the current value of a key in a column family without
+    /// copying it out of the table it was read from
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn get_pinned_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<PinnedSlice>> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        self.inner
+            .timed(SlowOperationKind::Get, Some(&cf), Some(key), || {
+                self.inner.get_pinned_at(&cf, key, pin.timestamp())
+            })
+    }
+
+    /// Reads the current value of a key into `buffer`, reusing its
+    /// allocation
+    ///
+    /// Returns false, leaving `buffer` empty, if the key doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get).
+    pub fn get_into(&self, key: &[u8], buffer: &mut Vec<u8>) -> Result<bool> {
+        let value = self.get_pinned(key)?;
+        Ok(copy_into(value, buffer))
+    }
+
+    /// Reads the current value of a key in a column family into `buffer`,
+    /// reusing its allocation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn get_into_cf(&self, cf: &ColumnFamily, key: &[u8], buffer: &mut Vec<u8>) -> Result<bool> {
+        let value = self.get_pinned_cf(cf, key)?;
+        Ok(copy_into(value, buffer))
+    }
+
+    /// Returns the current values of several keys, in the order given
+    ///
+    /// All keys are read at one point in time. Lookups run in key order and
+    /// open each table once, which is cheaper than calling
+    /// [`get`](Self::get) per key when keys share tables or blocks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get).
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>> {
+        self.inner.check_open()?;
+        let keys: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        let default = &self.inner.default;
+        self.inner.timed(
+            SlowOperationKind::Get,
+            Some(default),
+            keys.first().copied(),
+            || self.inner.multi_get_at(default, &keys, pin.timestamp()),
+        )
+    }
+
+    /// Returns the current values of several keys in a column family, in
+    /// the order given
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn multi_get_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        keys: &[K],
+    ) -> Result<Vec<Option<Value>>> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        let keys: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        self.inner.timed(
+            SlowOperationKind::Get,
+            Some(&cf),
+            keys.first().copied(),
+            || self.inner.multi_get_at(&cf, &keys, pin.timestamp()),
+        )
+    }
+
+    /// Returns the current value of a key in a column family together with
+    /// the time it has left to live
+    ///
+    /// The time is `None` for values that never expire, which includes
+    /// every value in a family without a TTL.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn get_cf_with_ttl(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+    ) -> Result<Option<(Value, Option<Duration>)>> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        let versions = self.inner.versions_at(&cf, key, pin.timestamp())?;
+        self.inner.resolve_with_ttl(&cf, key, versions)
+    }
+
+    /// Returns the current key-value pairs in `range`, in key order
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get).
+    pub fn scan<K, R>(&self, range: R) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let range = owned_range(range);
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        self.inner
+            .scan_at(&self.inner.default, &range, pin.timestamp())
+    }
+
+    /// Returns the current key-value pairs in `range` of a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn scan_cf<K, R>(&self, cf: &ColumnFamily, range: R) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        let range = owned_range(range);
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        self.inner.scan_at(&cf, &range, pin.timestamp())
+    }
+
+    /// Returns an iterator over the default column family
+    ///
+    /// The iterator starts out unpositioned and keeps reading the data
+    /// as of its creation, or of the snapshot in `options`; see
+    /// [`EngineIterator`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or an SSTable cannot be
+    /// opened.
+    pub fn iter(&self, options: ReadOptions<'_>) -> Result<EngineIterator> {
+        self.inner.check_open()?;
+        EngineIterator::new(
+            Arc::clone(&self.inner),
+            Arc::clone(&self.inner.default),
+            options,
+            None,
+        )
+    }
+
+    /// Returns an iterator over a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped,
+    /// otherwise errors for the same reasons as [`iter`](Self::iter).
+    pub fn iter_cf(&self, cf: &ColumnFamily, options: ReadOptions<'_>) -> Result<EngineIterator> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        EngineIterator::new(Arc::clone(&self.inner), cf, options, None)
+    }
+
+    /// Returns a consistent view of all writes committed so far
+    ///
+    /// Versions visible to the snapshot are protected from compaction
+    /// until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(Arc::clone(&self.inner))
+    }
+
+    /// Returns a consistent view of the writes committed up to `timestamp`
+    ///
+    /// Like [`snapshot`](Self::snapshot), the view protects the versions it
+    /// reads until it is dropped. How far back it may go depends on the
+    /// [version retention](Options::with_version_retention); see
+    /// [`oldest_readable_timestamp`](Self::oldest_readable_timestamp).
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidArgument` if `timestamp` is newer than the last
+    /// committed write, or older than the versions compaction kept.
+    pub fn snapshot_at(&self, timestamp: Timestamp) -> Result<Snapshot> {
+        self.inner.check_open()?;
+        Snapshot::at(Arc::clone(&self.inner), timestamp)
+    }
+
+    /// Returns the value `key` had at `timestamp`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get) and
+    /// [`snapshot_at`](Self::snapshot_at).
+    pub fn get_at(&self, key: &[u8], timestamp: Timestamp) -> Result<Option<Value>> {
+        self.snapshot_at(timestamp)?.get(key)
+    }
+
+    /// Returns the key-value pairs in `range` at `timestamp`, in key order
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`scan`](Self::scan) and
+    /// [`snapshot_at`](Self::snapshot_at).
+    pub fn scan_at<K, R>(&self, range: R, timestamp: Timestamp) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.snapshot_at(timestamp)?.scan(range)
+    }
+
+    /// Returns the oldest timestamp [`snapshot_at`](Self::snapshot_at)
+    /// accepts
+    ///
+    /// Versions older than the retention window are dropped as compaction
+    /// gets to them, so the timestamp moves forward as compactions run;
+    /// without a window, it is the last timestamp committed before the
+    /// latest compaction started.
+    pub fn oldest_readable_timestamp(&self) -> Timestamp {
+        self.inner.snapshots.oldest_readable()
+    }
+
+    /// Starts an optimistic transaction reading at the current timestamp
+    ///
+    /// See [`Transaction`] for how conflicts are detected on commit.
+    pub fn begin_transaction(&self) -> Transaction {
+        Transaction::new(Arc::clone(&self.inner))
+    }
+
+    /// Starts a transaction that locks the keys it reads and writes
+    ///
+    /// See [`PessimisticTransaction`] for the locking rules.
+    pub fn begin_pessimistic_transaction(&self) -> PessimisticTransaction {
+        PessimisticTransaction::new(Arc::clone(&self.inner))
+    }
+
+    /// Returns the transactions prepared for a two-phase commit that no
+    /// [`PessimisticTransaction`] stands for
+    ///
+    /// After a restart, these are the transactions recovered from the WAL,
+    /// holding their locks again; the coordinator commits or rolls back
+    /// each according to its decision. A prepared transaction that is
+    /// dropped is returned again by the next call.
+    pub fn prepared_transactions(&self) -> Vec<PessimisticTransaction> {
+        self.inner
+            .claim_prepared()
+            .into_iter()
+            .map(|(name, owner)| {
+                PessimisticTransaction::recovered(Arc::clone(&self.inner), owner, name)
+            })
+            .collect()
+    }
+
+    /// Writes the active MemTable to level 0 and waits for the flush
+    ///
+    /// MemTables that were already waiting for a flush are written first.
+    /// Afterwards every write acknowledged before the call is in an SSTable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or the flush fails.
+    pub fn flush(&self) -> Result<()> {
+        self.flush_with_options(&FlushOptions::new())
+    }
+
+    /// Writes the active MemTable to level 0 as `options` say
+    ///
+    /// See [`flush`](Self::flush) and [`FlushOptions`]. A flush that
+    /// doesn't wait may still wait for an earlier one to free room for
+    /// the MemTable it retires.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or read-only, or the
+    /// flush fails; one that doesn't wait only reports failures of
+    /// earlier flushes.
+    pub fn flush_with_options(&self, options: &FlushOptions) -> Result<()> {
+        self.inner.check_open()?;
+        let target = self.inner.retire_memtables()?;
+        if options.wait {
+            self.inner.wait_for_flush(target)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the WAL to disk, making every write acknowledged so far
+    /// durable
+    ///
+    /// A barrier for writes made with a sync mode, or
+    /// [`WriteOptions`], that didn't sync them: once it returns they
+    /// survive a power loss, without waiting for a flush. A failed sync
+    /// fails all further writes, as the writes it should have made
+    /// durable may be lost.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or read-only, or the WAL
+    /// cannot be synced.
+    pub fn sync_wal(&self) -> Result<()> {
+        self.inner.check_open()?;
+        self.inner.timed(SlowOperationKind::Sync, None, None, || {
+            let wal = self.inner.lock_writer()?;
+            wal.sync()
+                .inspect_err(|e| self.inner.background_failed(e))?;
+            slow_log::note_wal_sync(wal.last_sync_duration());
+            Ok(())
+        })
+    }
+
+    /// Compacts the tables holding keys in `range` and waits for it
+    ///
+    /// The MemTables are flushed first, so the range ends up in a single
+    /// sorted run without the versions shadowed by newer writes, except
+    /// those an open snapshot can still read. Compaction picks
+    /// tables, so keys just outside the range may be rewritten too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed, or the flush or the
+    /// compaction fails.
+    pub fn compact_range<K, R>(&self, range: R) -> Result<()>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        self.inner
+            .compact_range(&self.inner.default, &owned_range(range))
+    }
+
+    /// Compacts the tables of a column family holding keys in `range`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped, or an
+    /// error for the same reasons as [`compact_range`](Self::compact_range).
+    pub fn compact_range_cf<K, R>(&self, cf: &ColumnFamily, range: R) -> Result<()>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.compact_range(&cf, &owned_range(range))
+    }
+
+    /// Deletes every key in `range`, dropping the tables that hold only
+    /// keys in it without reading or rewriting them
+    ///
+    /// Tables wholly inside the range are removed from the MANIFEST at any
+    /// level, which costs the same however much data they hold. The keys
+    /// left in the range, in the MemTables and in tables reaching past
+    /// either end, are then deleted as by [`WriteBatch::delete_range`].
+    /// Returns the number of tables dropped.
+    ///
+    /// Dropping tables isn't a write: snapshots and reads running during
+    /// the call may lose keys of the range, and may briefly see older
+    /// versions the dropped tables shadowed until those are deleted too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or read-only, or the
+    /// MANIFEST or the deletes cannot be written.
+    pub fn delete_files_in_range<K, R>(&self, range: R) -> Result<usize>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner
+            .delete_files_in_range(&self.inner.default, owned_range(range))
+    }
+
+    /// Deletes every key in `range` from a column family, dropping the
+    /// tables that hold only keys in it
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped, or an
+    /// error for the same reasons as [`delete_files_in_range`](Self::delete_files_in_range).
+    pub fn delete_files_in_range_cf<K, R>(&self, cf: &ColumnFamily, range: R) -> Result<usize>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let cf = self.inner.column_family(cf)?;
+        self.inner.delete_files_in_range(&cf, owned_range(range))
+    }
+
+    /// Loads records into the default column family by writing them as
+    /// tables, bypassing the WAL and the MemTables
+    ///
+    /// For large initial loads this is far faster than writing the
+    /// records: they are sorted externally, spilling to temporary files
+    /// under `options`, merged into tables and added to the deepest level
+    /// they fit into without compacting anything. Among records with the
+    /// same key, the last one wins. The records are durable once the call
+    /// returns and all take one new timestamp, so they replace older
+    /// values of their keys and snapshots taken before don't see them.
+    ///
+    /// The MemTables are flushed first, and writes wait while the tables
+    /// are written. Nothing is logged, so [`tail_wal`](Self::tail_wal),
+    /// replicas and [watchers](Self::watch) never see the records.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error `records` yields, `Error::EntrySizeExceeded`
+    /// for a key or value too large for a table, `Error::InvalidOperation`
+    /// if the engine is read-only or a replica or the family is indexed,
+    /// `Error::Busy` if writes keep the MemTables from being flushed, or
+    /// an error if a run file, a table or the MANIFEST cannot be written.
+    /// Tables written by a failed import are removed.
+    pub fn import<I>(&self, records: I, options: &ImportOptions) -> Result<ImportReport>
+    where
+        I: IntoIterator<Item = Result<(Key, Value)>>,
+    {
+        self.inner.check_open()?;
+        self.inner.import(&self.inner.default, records, options)
+    }
+
+    /// Loads records into a column family by writing them as tables
+    ///
+    /// Values of a family with a TTL expire as if written when the tables
+    /// are.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped, or an
+    /// error for the same reasons as [`import`](Self::import).
+    pub fn import_cf<I>(
+        &self,
+        cf: &ColumnFamily,
+        records: I,
+        options: &ImportOptions,
+    ) -> Result<ImportReport>
+    where
+        I: IntoIterator<Item = Result<(Key, Value)>>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.import(&cf, records, options)
+    }
+
+    /// Returns roughly how many bytes of SSTable data each range holds
+    ///
+    /// The sizes come from the tables' index blocks without reading any
+    /// data, so they are off by up to a block at either end of each table
+    /// a range cuts through, and count every version and tombstone. Writes
+    /// still in the MemTables aren't counted; [`estimate_num_keys`](Self::estimate_num_keys)
+    /// counts those too.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or a table cannot be
+    /// opened.
+    pub fn approximate_sizes<K, R>(&self, ranges: &[R]) -> Result<Vec<u64>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        self.inner
+            .approximate_sizes(&self.inner.default, &owned_ranges(ranges))
+    }
+
+    /// Returns roughly how many bytes of SSTable data each range of a
+    /// column family holds
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped, or an
+    /// error for the same reasons as [`approximate_sizes`](Self::approximate_sizes).
+    pub fn approximate_sizes_cf<K, R>(&self, cf: &ColumnFamily, ranges: &[R]) -> Result<Vec<u64>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.approximate_sizes(&cf, &owned_ranges(ranges))
+    }
+
+    /// Returns roughly how many entries `range` holds
+    ///
+    /// Each table's entry count is scaled by the share of its data in the
+    /// range, as [`approximate_sizes`](Self::approximate_sizes) measures
+    /// it, and the MemTables' entries are counted exactly. Every version
+    /// and tombstone counts, so keys often overwritten or deleted are
+    /// counted high until compaction drops their old versions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or a table cannot be
+    /// opened.
+    pub fn estimate_num_keys<K, R>(&self, range: R) -> Result<u64>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        self.inner
+            .estimate_num_keys(&self.inner.default, &owned_range(range))
+    }
+
+    /// Returns roughly how many entries `range` holds in a column family
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped, or an
+    /// error for the same reasons as [`estimate_num_keys`](Self::estimate_num_keys).
+    pub fn estimate_num_keys_cf<K, R>(&self, cf: &ColumnFamily, range: R) -> Result<u64>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.estimate_num_keys(&cf, &owned_range(range))
+    }
+
+    /// Returns up to `shards - 1` keys, in order, that split `range` into
+    /// spans holding about the same amount of data
+    ///
+    /// Each key starts a span, so the spans are `range`'s start to the
+    /// first key, each key to the next, and the last key to `range`'s end.
+    /// Like [`approximate_sizes`](Self::approximate_sizes), only the
+    /// SSTables' index blocks are read, so a range with little data in
+    /// the tables gets fewer keys, and one held only in the MemTables none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine is closed or a table cannot be
+    /// opened.
+    pub fn split_keys<K, R>(&self, range: R, shards: usize) -> Result<Vec<Key>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        self.inner
+            .split_keys(&self.inner.default, &owned_range(range), shards)
+    }
+
+    /// Returns up to `shards - 1` keys splitting `range` of a column family
+    /// into spans holding about the same amount of data
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the family was dropped, or an
+    /// error for the same reasons as [`split_keys`](Self::split_keys).
+    pub fn split_keys_cf<K, R>(
+        &self,
+        cf: &ColumnFamily,
+        range: R,
+        shards: usize,
+    ) -> Result<Vec<Key>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.split_keys(&cf, &owned_range(range), shards)
+    }
+
+    /// Writes a consistent copy of the database to `dir`
+    ///
+    /// The MemTables are flushed, the live SSTables hard-linked, and the
+    /// MANIFESTs and the rest of the WAL copied, so the checkpoint holds
+    /// every write acknowledged before the call and costs little space
+    /// until the engine compacts the linked tables away. It is written
+    /// through the engine's [filesystem](Options::with_vfs) and opens like
+    /// any database on it through `Options::new(dir)`, with the column
+    /// families' options passed again. Writes wait only while files are linked and
+    /// copied, not during the flush.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if `dir` already exists,
+    /// `Error::Unsupported` for an engine with
+    /// [remote storage](Options::with_remote_storage), or an error if the
+    /// engine is closed, the flush fails, or a
+    /// file cannot be linked or copied. A partially written checkpoint is
+    /// removed.
+    pub fn create_checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        self.inner.check_open()?;
+        checkpoint::create_checkpoint(&self.inner, dir.as_ref())?;
+        Ok(())
+    }
+
+    /// Returns the timestamp of the newest write readers can see
+    ///
+    /// Timestamps are consecutive, so this is also the number of writes
+    /// the database has seen, and a replica's position in its primary's
+    /// history.
+    pub fn last_timestamp(&self) -> Timestamp {
+        self.inner.oracle.last()
+    }
+
+    /// Follows the WAL from timestamp `from` on, as written
+    ///
+    /// The tailer reads the WAL directory and archive, so it can start as
+    /// far back as the oldest segment still in either. With a
+    /// [WAL cache](Options::with_wal_cache_size), records still in it are
+    /// read from memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if `from` is beyond the next
+    /// timestamp.
+    pub fn tail_wal(&self, from: Timestamp) -> Result<WALTailer> {
+        self.inner.check_open()?;
+        replication::tail_wal(&self.inner, from)
+    }
+
+    /// Watches the keys of the default column family starting with
+    /// `prefix` for changes
+    ///
+    /// The [`Watcher`] receives every write to them committed from now
+    /// on; an empty prefix watches the whole family.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the engine is closed or open
+    /// for reading only.
+    pub fn watch(&self, prefix: &[u8]) -> Result<Watcher> {
+        self.inner.check_open()?;
+        self.inner.watch(DEFAULT_COLUMN_FAMILY_ID, prefix)
+    }
+
+    /// Watches the keys of a column family starting with `prefix` for
+    /// changes
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the engine is closed or open
+    /// for reading only, or the family was dropped.
+    pub fn watch_cf(&self, cf: &ColumnFamily, prefix: &[u8]) -> Result<Watcher> {
+        self.inner.check_open()?;
+        let data = self.inner.column_family(cf)?;
+        self.inner.watch(data.id, prefix)
+    }
+
+    /// Applies the entries of a record read from a primary's WAL
+    ///
+    /// Entries at or below [`last_timestamp`](Self::last_timestamp) were
+    /// applied before and are skipped; the rest must be in timestamp order.
+    /// They are logged as one batch and keep their timestamps.
+    /// Returns the new last timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the engine isn't a
+    /// [replica](Options::with_replica), the entries are out of order, or one
+    /// writes to a column family the replica doesn't have, or an error if
+    /// logging fails.
+    pub fn write_replicated(&self, entries: Vec<WALEntry>, sync: bool) -> Result<Timestamp> {
+        replication::write_replicated(&self.inner, entries, sync)
+    }
+
+    /// Makes everything the primary has logged so far visible to reads
+    /// of a secondary, returning the new last timestamp
+    ///
+    /// The secondary re-reads the MANIFESTs, opens the column families the
+    /// primary created and drops those it dropped, and replays the WAL the
+    /// MANIFESTs still need.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the engine wasn't opened with
+    /// [`open_as_secondary`](Self::open_as_secondary), or an error if a
+    /// MANIFEST or WAL segment cannot be read. Reads keep seeing what they
+    /// saw before the call then.
+    pub fn catch_up_with_primary(&self) -> Result<Timestamp> {
+        self.inner.catch_up_with_primary()
+    }
+
+    /// Flushes all MemTables, stops background work and seals the WAL
+    ///
+    /// Further operations return an error. Closing twice is a no-op, and
+    /// dropping the engine closes it. The sealed segment tells the next
+    /// open it was closed cleanly, so recovery reads it without verifying
+    /// checksums.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the final flush or WAL sync fails. The engine is
+    /// closed regardless; unflushed writes are recovered from the WAL on
+    /// the next open.
+    pub fn close(&self) -> Result<()> {
+        if self.inner.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let flushed = if self.inner.mode == OpenMode::Primary {
+            self.inner.flush()
+        } else {
+            Ok(())
+        };
+
+        self.inner.write_controller.close();
+        self.inner.close_watchers();
+        self.inner.background.lock().shutdown = true;
+        self.inner.flushed.notify_all();
+        // Failed jobs were reported to writers when they failed
+        let _ = self.inner.scheduler.shutdown();
+        if std::mem::take(&mut *self.inner.wal_preallocated.lock()) {
+            let path = self.config().wal_dir.join(PREALLOCATED_WAL_FILE_NAME);
+            let _ = self.inner.options.vfs.remove_file(&path);
+        }
+
+        // Writers that passed the closed check before close may have
+        // logged entries after the final flush; sealing the segment syncs
+        // them too
+        let synced = self
+            .inner
+            .writer
+            .lock()
+            .take()
+            .map_or(Ok(()), WALWriter::close);
+        self.dir_locks.lock().clear();
+        flushed.and(synced)
+    }
+
+    /// Abandons the engine without closing it, as if the process had
+    /// crashed, but releases its directory locks so it can be reopened
+    #[cfg(test)]
+    fn crash(self) {
+        self.dir_locks.lock().clear();
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for StorageEngine {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::warn!("Failed to close storage engine cleanly: {}", e);
+        }
+    }
+}
+
+/// State shared between the engine handle and its background jobs
+struct EngineInner {
+    options: Options,
+    /// The default column family, whose MANIFEST registers the others
+    default: Arc<ColumnFamilyData>,
+    /// Every live column family by id, the default included
+    column_families: RwLock<BTreeMap<u32, Arc<ColumnFamilyData>>>,
+    /// Shared by the compactions of every column family
+    rate_limiter: Arc<RateLimiter>,
+    write_controller: WriteController,
+    /// Stall condition last reported to the event listeners
+    reported_stall: Mutex<WriteStallCondition>,
+    /// Serializes writes and owns the active WAL segment, which engines
+    /// opened for reading only have none of
+    writer: Mutex<Option<WALWriter>>,
+    /// Whether the file the next WAL segment is renamed from is ready,
+    /// which only the flush job creates. Held while the file is created
+    /// or renamed, so the job cannot recreate it under a rotation
+    wal_preallocated: Mutex<bool>,
+    /// The most recent WAL records, for tailers, if the engine keeps them
+    wal_cache: Option<Arc<WALCache>>,
+    memtables: RwLock<MemTables>,
+    /// Issues write timestamps and holds the newest one visible to readers
+    oracle: TimestampOracle,
+    snapshots: SnapshotList,
+    /// Locks held by pessimistic transactions
+    locks: LockManager,
+    /// Transactions prepared for a two-phase commit, by name
+    prepared: Mutex<BTreeMap<String, Prepared>>,
+    /// Secondary indexes kept up to date by every write
+    indexes: RwLock<Vec<Arc<IndexData>>>,
+    /// Queues of the watchers of committed changes, dropped ones included
+    /// until they are pruned
+    watchers: Mutex<Vec<Weak<WatchQueue>>>,
+    counters: Counters,
+    slow_log: SlowLog,
+    /// Shared by the writers of all WAL segments
+    wal_metrics: Arc<WALMetrics>,
+    /// Held while picking and running a compaction, so background and
+    /// manual compactions never pick the same tables
+    compaction: Mutex<()>,
+    /// Compactions being run, with their progress
+    running_compactions: RunningCompactions,
+    closed: AtomicBool,
+    /// Runs the flush, compaction, scrub and offload jobs
+    scheduler: Scheduler,
+    scrubber: Scrubber,
+    /// Filesystem offloaded tables are read through, if the engine has
+    /// remote storage
+    tiered: Option<Arc<TieredVfs>>,
+    background: Mutex<BackgroundState>,
+    /// Signalled when a flush finishes or background work fails
+    flushed: Condvar,
+    mode: OpenMode,
+}
+
+/// Coordination between foreground callers and the background jobs
+#[derive(Debug, Default)]
+struct BackgroundState {
+    /// Set on close; compaction stops once it sees it
+    shutdown: bool,
+    /// First background failure; all further writes fail with it
+    error: Option<String>,
+}
+
+/// The active MemTables and those waiting to be flushed
+struct MemTables {
+    /// Active MemTable of each column family, by id
+    active: BTreeMap<u32, Arc<MemTable>>,
+    /// WAL segment logging the active MemTables' writes
+    active_wal: u64,
+    /// Full MemTables waiting for a flush, oldest first
+    immutable: VecDeque<ImmutableMemTable>,
+    /// WAL segments whose MemTables are flushed but that still hold
+    /// writes of a family left active, oldest first
+    flushed_wals: VecDeque<u64>,
+}
+
+impl MemTables {
+    /// Returns a column family's MemTables, newest data first
+    fn newest_first(&self, column_family: u32) -> Vec<Arc<MemTable>> {
+        self.active
+            .get(&column_family)
+            .into_iter()
+            .chain(
+                self.immutable
+                    .iter()
+                    .rev()
+                    .filter_map(|imm| imm.memtables.get(&column_family)),
+            )
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if any active MemTable holds data
+    fn has_data(&self) -> bool {
+        self.active
+            .values()
+            .any(|memtable| memtable.entry_count() > 0)
+    }
+
+    /// Returns the bytes held by the active MemTables and by those waiting
+    /// for a flush
+    fn memory_usage(&self) -> (usize, usize) {
+        let active = self
+            .active
+            .values()
+            .map(|memtable| memtable.memory_usage())
+            .sum();
+        let immutable = self
+            .immutable
+            .iter()
+            .flat_map(|imm| imm.memtables.values())
+            .map(|memtable| memtable.memory_usage())
+            .sum();
+        (active, immutable)
+    }
+
+    /// Returns whether `size` more bytes keep the engine within the limit
+    /// of `memory`, which empty MemTables always are so a large batch
+    /// can't get stuck
+    fn within_memory_limit(&self, size: usize, memory: &MemoryTracker) -> bool {
+        let (active, immutable) = self.memory_usage();
+        memory::within_memory_limit(memory, active, immutable, size) || !self.has_data()
+    }
+
+    /// Returns whether `size` more bytes keep the active MemTables within
+    /// `budget`, which empty ones always are
+    fn within_budget(&self, size: usize, budget: usize) -> bool {
+        budget == 0 || !self.has_data() || self.memory_usage().0 + size <= budget
+    }
+
+    /// Returns the column families whose active MemTables, largest first,
+    /// make room within `budget` for `size` more bytes once retired
+    ///
+    /// Empty MemTables are retired along with them, so a family without
+    /// writes doesn't keep the older WAL segments around.
+    fn over_budget(&self, size: usize, budget: usize) -> BTreeSet<u32> {
+        let mut by_size: Vec<_> = self
+            .active
+            .iter()
+            .map(|(id, memtable)| (memtable.memory_usage(), *id))
+            .collect();
+        by_size.sort_unstable_by(|a, b| b.cmp(a));
+        let mut usage: usize = by_size.iter().map(|(bytes, _)| bytes).sum();
+        let mut retired = BTreeSet::new();
+        for (bytes, id) in by_size {
+            if usage + size > budget || self.active[&id].entry_count() == 0 {
+                usage -= bytes;
+                retired.insert(id);
+            }
+        }
+        retired
+    }
+}
+
+/// Retired MemTables together with the WAL segment holding their writes
+#[derive(Clone)]
+struct ImmutableMemTable {
+    /// Each column family's MemTable, by id
+    memtables: BTreeMap<u32, Arc<MemTable>>,
+    wal_number: u64,
+    /// Timestamp of the newest write logged to the segment
+    last_timestamp: Timestamp,
+}
+
+impl EngineInner {
+    fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::StorageEngine("Storage engine is closed".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns the sticky background error, if any
+    fn background_error(&self, state: &BackgroundState) -> Result<()> {
+        match &state.error {
+            Some(e) => Err(Error::StorageEngine(format!(
+                "Background work failed: {}",
+                e
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails for an engine opened for reading only
+    fn check_writable(&self) -> Result<()> {
+        if self.mode != OpenMode::Primary {
+            return Err(Error::InvalidOperation(
+                "The engine is open for reading only".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Locks the WAL writer, which serializes all changes to the database
+    fn lock_writer(&self) -> Result<MappedMutexGuard<'_, WALWriter>> {
+        self.check_writable()?;
+        MutexGuard::try_map(self.writer.lock(), Option::as_mut)
+            .map_err(|_| Error::StorageEngine("The engine has no WAL writer".to_string()))
+    }
+
+    fn wal_path(&self, number: u64) -> PathBuf {
+        self.options.config.wal_dir.join(wal_file_name(number))
+    }
+
+    /// Returns the live column families, ordered by id
+    fn families(&self) -> Vec<Arc<ColumnFamilyData>> {
+        self.column_families.read().values().cloned().collect()
+    }
+
+    /// Resolves a handle to a live column family of this engine
+    fn column_family(&self, cf: &ColumnFamily) -> Result<Arc<ColumnFamilyData>> {
+        cf.data.check_live()?;
+        match self.column_families.read().get(&cf.id()) {
+            Some(data) if Arc::ptr_eq(data, &cf.data) => Ok(Arc::clone(data)),
+            _ => Err(Error::InvalidOperation(format!(
+                "Column family {:?} does not belong to this engine",
+                cf.name()
+            ))),
+        }
+    }
+
+    /// Fails unless a column family has a merge operator
+    fn check_merge_operator(&self, column_family: u32) -> Result<()> {
+        let families = self.column_families.read();
+        let cf = families
+            .get(&column_family)
+            .ok_or_else(|| missing_column_family(column_family))?;
+        if cf.merge_operator.is_none() {
+            return Err(Error::InvalidOperation(
+                "Merge requires a merge operator".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Creates a column family and gives it an active MemTable
+    ///
+    /// Runs under the write lock, so the family's MANIFEST starts out
+    /// consistent with the WAL: nothing logged before it has data for it.
+    fn create_column_family(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily> {
+        if name.is_empty() {
+            return Err(Error::InvalidOperation(
+                "Column family name must not be empty".to_string(),
+            ));
+        }
+
+        let _wal = self.lock_writer()?;
+        if self
+            .column_families
+            .read()
+            .values()
+            .any(|cf| cf.name == name)
+        {
+            return Err(Error::InvalidOperation(format!(
+                "Column family {:?} already exists",
+                name
+            )));
+        }
+
+        let id = self
+            .default
+            .versions
+            .manifest_state()
+            .next_column_family_id();
+        let vfs = &self.options.vfs;
+        let dir = column_family_dir(&self.options.config.data_dir, id);
+        if vfs.exists(&dir) {
+            vfs.remove_dir_all(&dir)?;
+        }
+
+        let result = (|| {
+            let cf =
+                ColumnFamilyData::open(id, name, &dir, &self.options, options, &self.rate_limiter)?;
+            let mut edit = VersionEdit::default();
+            edit.set_log_number(self.memtables.read().active_wal);
+            edit.set_last_timestamp(self.oracle.last());
+            cf.versions.log_and_apply(edit)?;
+
+            let mut edit = VersionEdit::default();
+            edit.add_column_family(id, name);
+            edit.set_next_column_family_id(id + 1);
+            self.default.versions.log_and_apply(edit)?;
+            Ok(Arc::new(cf))
+        })();
+        let cf = match result {
+            Ok(cf) => cf,
+            Err(e) => {
+                let _ = vfs.remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+
+        self.column_families.write().insert(id, Arc::clone(&cf));
+        self.memtables.write().active.insert(id, cf.new_memtable());
+        log::info!("Created column family {:?} with id {}", name, id);
+        Ok(ColumnFamily { data: cf })
+    }
+
+    /// Unregisters a column family and stops writes to it
+    fn drop_column_family(&self, cf: &ColumnFamily) -> Result<()> {
+        if cf.id() == DEFAULT_COLUMN_FAMILY_ID {
+            return Err(Error::InvalidOperation(
+                "The default column family cannot be dropped".to_string(),
+            ));
+        }
+
+        let _wal = self.lock_writer()?;
+        let data = self.column_family(cf)?;
+        if self.is_indexed(data.id) {
+            return Err(Error::InvalidOperation(format!(
+                "Column family {:?} belongs to an index, which must be dropped first",
+                data.name
+            )));
+        }
+        let mut edit = VersionEdit::default();
+        edit.drop_column_family(data.id);
+        self.default.versions.log_and_apply(edit)?;
+
+        data.mark_dropped();
+        self.column_families.write().remove(&data.id);
+        self.memtables.write().active.remove(&data.id);
+        log::info!("Dropped column family {:?} with id {}", data.name, data.id);
+        Ok(())
+    }
+
+    /// Logs and applies one write at the next timestamp
+    fn write(&self, key: Key, value: Value, operation: Operation) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.push(operation, key, value);
+        self.write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Logs and applies a batch so it becomes visible all at once
+    ///
+    /// `validate` runs with the write lock held, after every earlier write
+    /// is visible and before anything of the batch is logged; an error
+    /// from it rejects the batch. Empty batches return without validating.
+    fn write_batch(
+        &self,
+        batch: WriteBatch,
+        options: &WriteOptions,
+        validate: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        // Only a logged write needs to know what it wrote
+        let first = self
+            .slow_log
+            .is_enabled()
+            .then(|| batch.entry(0))
+            .flatten()
+            .map(|entry| (entry.column_family, entry.key.clone()));
+        let cf = first
+            .as_ref()
+            .and_then(|(id, _)| self.column_families.read().get(id).cloned());
+        let key = first.as_ref().map(|(_, key)| key.as_slice());
+        self.timed(SlowOperationKind::Write, cf.as_deref(), key, || {
+            self.apply_batch(batch, options, validate)
+        })
+    }
+
+    /// Body of [`write_batch`](Self::write_batch)
+    fn apply_batch(
+        &self,
+        batch: WriteBatch,
+        options: &WriteOptions,
+        validate: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        self.check_open()?;
+        self.check_writable()?;
+        if self.options.replica {
+            return Err(Error::InvalidOperation(
+                "The engine is a replica and only accepts replicated writes".to_string(),
+            ));
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.write_controller.delay_write(batch.size() as u64)?;
+
+        let mut wal = self.lock_writer()?;
+        self.background_error(&self.background.lock())?;
+        validate()?;
+        let mut entries = self.expand_range_deletes(batch)?;
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let updates = self.index_updates(entries.iter().map(|entry| {
+            (
+                entry.column_family,
+                entry.operation,
+                &entry.key,
+                &entry.value,
+            )
+        }))?;
+        entries.extend(updates);
+        self.stamp_expiry(&mut entries)?;
+        let first = self.oracle.next();
+        let entries = batch::into_wal_entries(entries, first)?;
+        self.log_and_apply(&mut wal, entries, options, None)
+    }
+
+    /// Logs timestamped entries as one record and publishes them
+    ///
+    /// The caller holds the write lock and has checked that the entries
+    /// continue the last timestamp. The record is synced as `options`
+    /// override the configured sync mode; with `disable_wal`, nothing is
+    /// logged. The entries of prepared transaction `prepared` are logged
+    /// as its commit record.
+    fn log_and_apply(
+        &self,
+        wal: &mut WALWriter,
+        entries: Vec<WALEntry>,
+        options: &WriteOptions,
+        prepared: Option<&str>,
+    ) -> Result<()> {
+        let memtables = self.make_room(wal, &entries)?;
+        if !options.disable_wal {
+            let sync_mode = options.sync_mode(self.options.config.wal_sync_mode);
+            match prepared {
+                Some(name) => wal.append_commit(name, &entries, sync_mode)?,
+                None => wal.append_batch_with_sync_mode(&entries, sync_mode)?,
+            }
+            if sync_mode != SyncMode::None {
+                slow_log::note_wal_sync(wal.last_sync_duration());
+            }
+            if let Some(cache) = &self.wal_cache {
+                cache.insert(self.memtables.read().active_wal, &entries);
+            }
+        }
+
+        let watched = self.watch_events(&entries);
+        let mut last = self.oracle.last();
+        for entry in entries {
+            let WALEntry {
+                timestamp,
+                operation,
+                key,
+                value,
+                column_family,
+            } = entry;
+            let memtable = &memtables[&column_family];
+            match operation {
+                Operation::Put => memtable.put(key, value, timestamp)?,
+                Operation::Delete => memtable.delete(key, timestamp)?,
+                Operation::Merge => memtable.merge(key, value, timestamp)?,
+            }
+            last = timestamp;
+        }
+        // Readers see the whole batch from here on, never a part of it
+        self.oracle.publish(last);
+        watch::deliver(watched);
+
+        Ok(())
+    }
+
+    /// Appends the expiry trailer to values written to families with a TTL
+    fn stamp_expiry(&self, entries: &mut [BatchEntry]) -> Result<()> {
+        let families = self.column_families.read();
+        for entry in entries {
+            let cf = families
+                .get(&entry.column_family)
+                .ok_or_else(|| missing_column_family(entry.column_family))?;
+            match &cf.ttl {
+                Some(ttl) if entry.operation != Operation::Delete => {
+                    ttl.stamp(&mut entry.value, entry.ttl)
+                }
+                Some(_) => {}
+                None if entry.ttl.is_some() => {
+                    return Err(Error::InvalidOperation(format!(
+                        "Column family {:?} has no TTL, so writes can't set one",
+                        cf.name
+                    )));
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces each range deletion by deletes of the keys it covers
+    ///
+    /// Must be called with the write lock held, so the covered keys can't
+    /// change before the batch is applied.
+    fn expand_range_deletes(&self, batch: WriteBatch) -> Result<Vec<BatchEntry>> {
+        let pin = self.snapshots.pin(&self.oracle);
+        let mut entries = Vec::new();
+        for op in batch.into_ops() {
+            match op {
+                BatchOp::Write(entry) => entries.push(entry),
+                BatchOp::DeleteRange(column_family, range) => {
+                    let cf = self
+                        .column_families
+                        .read()
+                        .get(&column_family)
+                        .cloned()
+                        .ok_or_else(|| missing_column_family(column_family))?;
+                    let mut keys: Vec<Key> = self
+                        .scan_at(&cf, &range, pin.timestamp())?
+                        .into_iter()
+                        .map(|(key, _)| key)
+                        .collect();
+                    keys.extend(
+                        entries
+                            .iter()
+                            .filter(|entry| {
+                                entry.column_family == column_family
+                                    && range_contains(&*cf.comparator, &range, &entry.key)
+                            })
+                            .map(|entry| entry.key.clone()),
+                    );
+                    keys.sort_by(|a, b| cf.comparator.compare(a, b));
+                    keys.dedup();
+                    entries.extend(keys.into_iter().map(|key| BatchEntry {
+                        column_family,
+                        operation: Operation::Delete,
+                        key,
+                        value: Vec::new(),
+                        ttl: None,
+                    }));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the MemTables the batch goes to, switching if needed
+    ///
+    /// Must be called with the write lock held, so nobody else fills the
+    /// returned MemTables or the WAL segment in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the batch writes to a column
+    /// family that doesn't exist, and `Error::EntrySizeExceeded` if it
+    /// doesn't fit even into empty MemTables.
+    fn make_room(
+        &self,
+        wal: &mut WALWriter,
+        entries: &[WALEntry],
+    ) -> Result<BTreeMap<u32, Arc<MemTable>>> {
+        let size = entries
+            .iter()
+            .map(|entry| entry.key.len() + entry.value.len() + WAL_ENTRY_OVERHEAD)
+            .sum::<usize>()
+            + WAL_ENTRY_OVERHEAD;
+        let wal_size_limit = self.options.config.wal_size_limit as u64;
+        let budget = self.options.config.write_buffer_budget;
+
+        // The active MemTable of each column family in the batch, whether
+        // all of them, the memory limit and the WAL segment have room, and
+        // whether the write buffer budget has
+        let pick = |wal: &WALWriter| -> Result<(BTreeMap<u32, Arc<MemTable>>, bool, bool)> {
+            let memtables = self.memtables.read();
+            let mut picked = BTreeMap::new();
+            for entry in entries {
+                if let btree_map::Entry::Vacant(slot) = picked.entry(entry.column_family) {
+                    let memtable = memtables
+                        .active
+                        .get(&entry.column_family)
+                        .ok_or_else(|| missing_column_family(entry.column_family))?;
+                    slot.insert(Arc::clone(memtable));
+                }
+            }
+            let fits = picked.iter().all(|(id, memtable)| {
+                memtable.has_room_for_all(
+                    entries
+                        .iter()
+                        .filter(|entry| entry.column_family == *id)
+                        .map(|entry| (entry.key.as_slice(), entry.value.as_slice())),
+                )
+            }) && wal.size() + size as u64 <= wal_size_limit
+                && memtables.within_memory_limit(size, &self.options.memory);
+            Ok((picked, fits, memtables.within_budget(size, budget)))
+        };
+
+        let (picked, fits, within_budget) = pick(wal)?;
+        if fits && within_budget {
+            return Ok(picked);
+        }
+
+        // A batch that doesn't fit into empty MemTables never will
+        let max_size = {
+            let families = self.column_families.read();
+            picked
+                .keys()
+                .filter_map(|id| families.get(id))
+                .map(|cf| cf.config.memtable_size)
+                .min()
+                .unwrap_or(usize::MAX)
+                .min(self.options.config.wal_size_limit)
+        };
+        let too_large = Error::EntrySizeExceeded { size, max_size };
+        if !self.memtables.read().has_data() {
+            return Err(too_large);
+        }
+
+        self.wait_for_immutable_slot()?;
+        // Over the budget alone, only the largest MemTables make way
+        let retire = fits.then(|| self.memtables.read().over_budget(size, budget));
+        self.switch_memtable(wal, retire.as_ref())?;
+        match pick(wal)? {
+            (picked, true, true) => Ok(picked),
+            _ => Err(too_large),
+        }
+    }
+
+    /// Blocks while the maximum number of MemTables wait for a flush
+    fn wait_for_immutable_slot(&self) -> Result<()> {
+        let max = self.options.config.max_immutable_memtables.max(1);
+        let mut state = self.background.lock();
+        while self.memtables.read().immutable.len() >= max {
+            self.background_error(&state)?;
+            if state.shutdown {
+                return self.check_open();
+            }
+            self.flushed.wait(&mut state);
+        }
+        Ok(())
+    }
+
+    /// Retires the active MemTables of the column families in `retire`,
+    /// or of all of them, and the WAL segment for flushing
+    ///
+    /// The segment is left behind for a flush while a family that isn't
+    /// retired still has writes in it.
+    fn switch_memtable(&self, wal: &mut WALWriter, retire: Option<&BTreeSet<u32>>) -> Result<()> {
+        let number = self.default.versions.new_file_number();
+        // Writes that skipped the WAL aren't in the segment it continues
+        let next = self.new_wal_segment(number, wal.last_timestamp())?;
+        self.relog_prepared(&next)?;
+        wal.sync()?;
+        let closed = std::mem::replace(wal, next);
+        let rotation = WalRotationInfo {
+            closed_path: closed.path().to_path_buf(),
+            closed_size: closed.size(),
+            path: wal.path().to_path_buf(),
+        };
+        // The segment is synced, so a missing seal only costs recovery
+        // checking it in full
+        if let Err(e) = closed.close() {
+            log::warn!(
+                "Failed to seal WAL segment {}: {}",
+                rotation.closed_path.display(),
+                e
+            );
+        }
+        self.notify_listeners(|listener| listener.on_wal_rotated(&rotation));
+
+        let fresh: BTreeMap<_, _> = self
+            .families()
+            .iter()
+            .filter(|cf| retire.map_or(true, |retire| retire.contains(&cf.id)))
+            .map(|cf| (cf.id, cf.new_memtable()))
+            .collect();
+        {
+            let mut memtables = self.memtables.write();
+            let retired = match retire {
+                Some(_) => fresh
+                    .into_iter()
+                    .filter_map(|(id, memtable)| Some((id, memtables.active.insert(id, memtable)?)))
+                    .collect(),
+                None => std::mem::replace(&mut memtables.active, fresh),
+            };
+            let retired_wal = std::mem::replace(&mut memtables.active_wal, number);
+            memtables.immutable.push_back(ImmutableMemTable {
+                memtables: retired,
+                wal_number: retired_wal,
+                last_timestamp: self.oracle.last(),
+            });
+        }
+
+        self.schedule_background_work();
+        Ok(())
+    }
+
+    /// Starts WAL segment `number` after the write at `previous`, from the
+    /// preallocated file if it is ready
+    ///
+    /// Writes wait for the switch, so a preallocation still in progress
+    /// isn't waited for either.
+    fn new_wal_segment(&self, number: u64, previous: Timestamp) -> Result<WALWriter> {
+        let config = &self.options.config;
+        let vfs = &self.options.vfs;
+        let path = self.wal_path(number);
+        let (sync_mode, size_limit) = (config.wal_sync_mode, config.wal_size_limit as u64);
+        // Renaming under the lock keeps the flush job from recreating the
+        // file until it is gone
+        let mut preallocation = self.wal_preallocated.try_lock();
+        let writer = if let Some(ready) = preallocation.as_deref_mut().filter(|ready| **ready) {
+            *ready = false;
+            let preallocated = config.wal_dir.join(PREALLOCATED_WAL_FILE_NAME);
+            WALWriter::from_preallocated_in(
+                vfs,
+                preallocated,
+                path,
+                sync_mode,
+                size_limit,
+                previous,
+            )?
+        } else {
+            WALWriter::new_in(vfs, path, sync_mode, size_limit, previous)?
+        };
+        Ok(writer.with_metrics(Arc::clone(&self.wal_metrics)))
+    }
+
+    /// Creates the file the next WAL segment is renamed from, unless it
+    /// is ready or preallocation is off
+    ///
+    /// A failure only costs the next rotation its head start, so it is
+    /// logged rather than failing the flush job.
+    fn preallocate_wal(&self) {
+        if !self.options.config.wal_preallocate {
+            return;
+        }
+        let mut ready = self.wal_preallocated.lock();
+        if *ready {
+            return;
+        }
+        let path = self.options.config.wal_dir.join(PREALLOCATED_WAL_FILE_NAME);
+        match WALWriter::preallocate_in(&self.options.vfs, &path) {
+            Ok(()) => *ready = true,
+            Err(e) => log::warn!(
+                "Failed to preallocate WAL segment {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Switches the active MemTables if any has data, then waits for the flush
+    fn flush(&self) -> Result<()> {
+        let target = self.retire_memtables()?;
+        self.wait_for_flush(target)
+    }
+
+    /// Switches the active MemTables for the flush job if any has data,
+    /// returning the WAL segment of the newest MemTable retired, which a
+    /// flush waits for
+    fn retire_memtables(&self) -> Result<u64> {
+        let mut wal = self.lock_writer()?;
+        let (has_data, active_wal) = {
+            let memtables = self.memtables.read();
+            (memtables.has_data(), memtables.active_wal)
+        };
+        if has_data {
+            self.wait_for_immutable_slot()?;
+            self.switch_memtable(&mut wal, None)?;
+        }
+        Ok(active_wal)
+    }
+
+    /// Blocks until every retired MemTable up to WAL segment `target` is flushed
+    fn wait_for_flush(&self, target: u64) -> Result<()> {
+        let mut state = self.background.lock();
+        loop {
+            self.background_error(&state)?;
+            let pending = self
+                .memtables
+                .read()
+                .immutable
+                .front()
+                .is_some_and(|imm| imm.wal_number <= target);
+            if !pending {
+                return Ok(());
+            }
+            if state.shutdown {
+                return Err(Error::StorageEngine(
+                    "Storage engine closed before flush finished".to_string(),
+                ));
+            }
+            self.flushed.wait(&mut state);
+        }
+    }
+
+    /// Spawns the flush and compaction jobs, and the WAL sync and scrub
+    /// jobs if they have an interval
+    ///
+    /// The jobs hold the engine weakly, so dropping its last reference
+    /// isn't held up by them.
+    fn start_background_jobs(inner: &Arc<Self>) -> Result<()> {
+        let wal_sync_interval = inner.options.config.wal_sync_interval_ms;
+        if wal_sync_interval > 0 {
+            let weak = Arc::downgrade(inner);
+            inner.scheduler.spawn(
+                WAL_SYNC_JOB,
+                Schedule::Every(Duration::from_millis(wal_sync_interval)),
+                move || weak.upgrade().map_or(Ok(()), |inner| inner.sync_wal()),
+            )?;
+        }
+        let scrub_interval = inner.options.config.scrub_interval_ms;
+        if scrub_interval > 0 {
+            let weak = Arc::downgrade(inner);
+            inner.scheduler.spawn(
+                SCRUB_JOB,
+                Schedule::Every(Duration::from_millis(scrub_interval)),
+                move || {
+                    weak.upgrade()
+                        .map_or(Ok(()), |inner| inner.scrub_cold_tables())
+                },
+            )?;
+        }
+        if inner.tiered.is_some() {
+            let weak = Arc::downgrade(inner);
+            inner
+                .scheduler
+                .spawn(OFFLOAD_JOB, Schedule::OnDemand, move || {
+                    weak.upgrade()
+                        .map_or(Ok(()), |inner| inner.offload_cold_tables())
+                })?;
+        }
+        let weak = Arc::downgrade(inner);
+        inner
+            .scheduler
+            .spawn(FLUSH_JOB, Schedule::OnDemand, move || {
+                weak.upgrade()
+                    .map_or(Ok(()), |inner| inner.flush_immutables())
+            })?;
+        let weak = Arc::downgrade(inner);
+        inner
+            .scheduler
+            .spawn(COMPACTION_JOB, Schedule::OnDemand, move || {
+                weak.upgrade()
+                    .map_or(Ok(()), |inner| inner.run_compactions())
+            })
+    }
+
+    /// Body of the WAL sync job: syncs the current WAL segment to disk
+    ///
+    /// Retired segments were synced when they were switched away from. A
+    /// failed sync fails the job and with it all further writes, as the
+    /// writes it should have made durable may be lost.
+    fn sync_wal(&self) -> Result<()> {
+        match self.writer.lock().as_ref() {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Wakes the flush job, which wakes the compaction job once done
+    fn schedule_background_work(&self) {
+        self.scheduler.trigger(FLUSH_JOB);
+    }
+
+    /// Makes all further writes fail after a background job failed
+    fn background_failed(&self, error: &Error) {
+        log::warn!("Background work failed, rejecting writes: {}", error);
+        self.check_corruption(error, None, "background");
+        self.background
+            .lock()
+            .error
+            .get_or_insert_with(|| error.to_string());
+        // Release writers stalled on a backlog that won't shrink
+        self.write_controller.close();
+        self.flushed.notify_all();
+    }
+
+    /// Body of the flush job: readies the next WAL segment, then writes
+    /// every waiting MemTable to level 0
+    fn flush_immutables(&self) -> Result<()> {
+        self.preallocate_wal();
+        while let Some(imm) = self.oldest_immutable() {
+            self.flush_memtable(&imm)?;
+        }
+        self.update_write_stall();
+        self.scheduler.trigger(COMPACTION_JOB);
+        Ok(())
+    }
+
+    /// Body of the compaction job: compacts until nothing is due
+    fn run_compactions(&self) -> Result<()> {
+        loop {
+            {
+                let state = self.background.lock();
+                if state.shutdown || state.error.is_some() {
+                    return Ok(());
+                }
+            }
+            {
+                let _compaction = self.compaction.lock();
+                // A family dropped since the list was taken is let go of
+                // here, before the run ends, rather than compacted
+                let picked = self.families().into_iter().find_map(|cf| {
+                    cf.check_live().ok()?;
+                    let task = cf.strategy.pick_compaction(&cf.versions.current())?;
+                    Some((cf, task))
+                });
+                let Some((cf, task)) = picked else {
+                    break;
+                };
+                let snapshots = self.snapshots.timestamps(&self.oracle);
+                let running = self.running_compactions.start(&cf, &task, false);
+                let stats = self.timed(SlowOperationKind::Compaction, Some(&cf), None, || {
+                    cf.compactor
+                        .run_tracked(&task, &snapshots, running.progress())
+                })?;
+                self.counters.record_compaction(&stats);
+                self.compaction_completed(running.job(), stats);
+            }
+            self.update_write_stall();
+        }
+        // A manual compaction may have run since the last update
+        self.update_write_stall();
+        Ok(())
+    }
+
+    /// Flushes, then compacts the tables of `cf` overlapping `range`
+    fn compact_range(&self, cf: &ColumnFamilyData, range: &KeyRange) -> Result<()> {
+        self.flush()?;
+        {
+            let _compaction = self.compaction.lock();
+            let version = cf.versions.current();
+            let range = (
+                range.0.as_ref().map(Vec::as_slice),
+                range.1.as_ref().map(Vec::as_slice),
+            );
+            if let Some(task) = cf.strategy.pick_range_compaction(&version, range) {
+                let snapshots = self.snapshots.timestamps(&self.oracle);
+                let running = self.running_compactions.start(cf, &task, true);
+                let stats = self.timed(SlowOperationKind::Compaction, Some(cf), None, || {
+                    cf.compactor
+                        .run_tracked(&task, &snapshots, running.progress())
+                })?;
+                self.counters.record_compaction(&stats);
+                self.compaction_completed(running.job(), stats);
+            }
+        }
+        // The tree changed shape under the compaction job
+        self.scheduler.trigger(COMPACTION_JOB);
+        Ok(())
+    }
+
+    /// Drops the tables of `cf` wholly inside `range`, then deletes the
+    /// keys of the range still left
+    fn delete_files_in_range(&self, cf: &ColumnFamilyData, range: KeyRange) -> Result<usize> {
+        self.check_writable()?;
+        let dropped = {
+            // No compaction may be reading the tables, or writing them
+            // into new ones
+            let _compaction = self.compaction.lock();
+            let version = cf.versions.current();
+            let mut edit = VersionEdit::default();
+            for level in 0..crate::manifest::NUM_LEVELS {
+                for table in version.files(level) {
+                    let meta = table.meta();
+                    if range_contains(&*cf.comparator, &range, &meta.smallest.user_key)
+                        && range_contains(&*cf.comparator, &range, &meta.largest.user_key)
+                    {
+                        edit.delete_file(level, meta.number);
+                    }
+                }
+            }
+            let dropped = edit.deleted_files.len();
+            if dropped > 0 {
+                cf.versions.log_and_apply(edit)?;
+            }
+            dropped
+        };
+
+        // Older versions in tables reaching past the range show through
+        // where the dropped tables shadowed them
+        let mut batch = WriteBatch::new();
+        batch.push_range_delete(cf.id, range);
+        self.write_batch(batch, &WriteOptions::default(), || Ok(()))?;
+        if dropped > 0 {
+            self.update_write_stall();
+            self.scheduler.trigger(COMPACTION_JOB);
+        }
+        Ok(dropped)
+    }
+
+    fn oldest_immutable(&self) -> Option<ImmutableMemTable> {
+        self.memtables.read().immutable.front().cloned()
+    }
+
+    /// Writes retired MemTables to level 0 and retires their WAL segment
+    ///
+    /// Each column family records its table, the segment's last timestamp
+    /// and the next log number in its own MANIFEST. A crash part way
+    /// through leaves some families behind, and recovery replays the
+    /// segment for just those.
+    fn flush_memtable(&self, imm: &ImmutableMemTable) -> Result<()> {
+        // Every older segment is flushed once this one is
+        let next_wal = {
+            let memtables = self.memtables.read();
+            memtables
+                .immutable
+                .get(1)
+                .map_or(memtables.active_wal, |next| next.wal_number)
+        };
+
+        // Families created after the switch or left active by it have
+        // nothing to flush here, and dropped ones are skipped
+        for cf in self.families() {
+            let Some(memtable) = imm.memtables.get(&cf.id) else {
+                continue;
+            };
+            let started = Instant::now();
+            let mut info = FlushJobInfo {
+                column_family: cf.name.clone(),
+                wal_number: imm.wal_number,
+                entry_count: memtable.entry_count(),
+                table_path: None,
+                file_size: 0,
+                elapsed: Duration::ZERO,
+            };
+            if info.entry_count > 0 {
+                self.notify_listeners(|listener| listener.on_flush_begin(&info));
+            }
+            let table = write_table(&cf, memtable)?;
+
+            let mut edit = VersionEdit::default();
+            if let Some(meta) = &table {
+                edit.add_file(0, meta.clone());
+            }
+            edit.set_last_timestamp(imm.last_timestamp);
+            edit.set_log_number(next_wal);
+
+            if let Err(e) = cf.versions.log_and_apply(edit) {
+                if let Some(meta) = table {
+                    let _ = cf
+                        .versions
+                        .vfs()
+                        .remove_file(&cf.versions.table_path(meta.number));
+                }
+                return Err(e);
+            }
+            if let Some(meta) = &table {
+                info.table_path = Some(cf.versions.table_path(meta.number));
+                info.file_size = meta.file_size;
+                info.elapsed = started.elapsed();
+                self.counters.record_flush(meta.file_size, info.elapsed);
+                self.notify_listeners(|listener| listener.on_flush_completed(&info));
+            }
+        }
+
+        // A segment is retired once every family has flushed its writes
+        // in it, which a family left active by the switch hasn't yet
+        let needed = self
+            .families()
+            .iter()
+            .map(|cf| cf.versions.manifest_state().log_number())
+            .min()
+            .unwrap_or(u64::MAX);
+        // Readers pick up the new table before the MemTable disappears
+        let retired: Vec<_> = {
+            let mut memtables = self.memtables.write();
+            memtables.immutable.pop_front();
+            memtables.flushed_wals.push_back(imm.wal_number);
+            let count = memtables
+                .flushed_wals
+                .iter()
+                .take_while(|number| **number < needed)
+                .count();
+            memtables.flushed_wals.drain(..count).collect()
+        };
+        self.record_memtable_memory();
+        for number in retired {
+            retire_wal_segment(&self.options, &self.wal_path(number));
+        }
+
+        let _state = self.background.lock();
+        self.flushed.notify_all();
+        Ok(())
+    }
+
+    /// Reports the most crowded level 0 and the total compaction debt
+    fn update_write_stall(&self) {
+        let mut level0_files = 0;
+        let mut pending_bytes = 0;
+        for cf in self.families() {
+            let version = cf.versions.current();
+            level0_files = level0_files.max(version.files(0).len());
+            pending_bytes += cf.strategy.pending_compaction_bytes(&version);
+        }
+        // Updates racing between the flush and compaction jobs are
+        // reported in the order the controller saw them
+        let mut reported = self.reported_stall.lock();
+        let info = self.write_controller.update(level0_files, pending_bytes);
+        if info.condition != *reported {
+            let change = WriteStallChange {
+                previous: std::mem::replace(&mut *reported, info.condition),
+                current: info,
+            };
+            self.notify_listeners(|listener| listener.on_write_stall(&change));
+        }
+    }
+
+    /// Tells the event listeners about a compaction that finished
+    fn compaction_completed(&self, job: CompactionJob, stats: CompactionStats) {
+        let info = CompactionJobInfo { job, stats };
+        self.notify_listeners(|listener| listener.on_compaction_completed(&info));
+        // The compaction may have written tables due for offloading
+        self.scheduler.trigger(OFFLOAD_JOB);
+    }
+
+    /// Reads a key of a column family as of `read_ts`
+    fn get_at(
+        &self,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        read_ts: Timestamp,
+    ) -> Result<Option<Value>> {
+        let versions = self.versions_at(cf, key, read_ts)?;
+        self.resolve(cf, key, versions)
+    }
+
+    /// Returns a key's versions visible at `read_ts`, newest first
+    ///
+    /// Tables are skipped when the MemTables hold a put or delete, which
+    /// hides every older version.
+    fn versions_at(
+        &self,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        read_ts: Timestamp,
+    ) -> Result<Vec<(Value, Timestamp, Operation)>> {
+        // MemTables before the version: a flush installs its table before
+        // retiring the MemTable, so this order never misses a version
+        let memtables = self.memtables.read().newest_first(cf.id);
+        let version = cf.versions.current();
+
+        // MemTables hold newer data than any table, newest first
+        let mut versions = Vec::new();
+        for memtable in &memtables {
+            versions.extend(memtable.versions(key, read_ts));
+        }
+
+        let has_base = versions
+            .iter()
+            .any(|(_, _, operation)| *operation != Operation::Merge);
+        if !has_base {
+            let user_key = key.to_vec();
+            for table in version.tables_for_key(key) {
+                let mut reader = cf.open_table(table)?;
+                versions.extend(reader.get_versions(&user_key, read_ts)?);
+                self.counters.record_reads(&reader);
+            }
+            // Tables may be ordered differently from their data, and a
+            // MemTable being flushed may also show up as a table
+            versions.sort_by_key(|(_, timestamp, _)| std::cmp::Reverse(*timestamp));
+            versions.dedup_by_key(|(_, timestamp, _)| *timestamp);
+        }
+
+        Ok(versions)
+    }
+
+    /// Returns the values of several keys visible at `read_ts`, in the
+    /// order of `keys`
+    fn multi_get_at(
+        &self,
+        cf: &ColumnFamilyData,
+        keys: &[&[u8]],
+        read_ts: Timestamp,
+    ) -> Result<Vec<Option<Value>>> {
+        let versions = self.versions_at_many(cf, keys, read_ts)?;
+        keys.iter()
+            .zip(versions)
+            .map(|(key, versions)| self.resolve(cf, key, versions))
+            .collect()
+    }
+
+    /// Like [`versions_at`](Self::versions_at) for several keys, returned in
+    /// the order of `keys`
+    ///
+    /// Keys are looked up in sorted order and each table is opened once, so
+    /// keys landing in the same table share its index and cached blocks.
+    fn versions_at_many(
+        &self,
+        cf: &ColumnFamilyData,
+        keys: &[&[u8]],
+        read_ts: Timestamp,
+    ) -> Result<Vec<Vec<(Value, Timestamp, Operation)>>> {
+        let memtables = self.memtables.read().newest_first(cf.id);
+        let version = cf.versions.current();
+
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| cf.comparator.compare(keys[a], keys[b]));
+
+        let mut results = vec![Vec::new(); keys.len()];
+        let mut readers = HashMap::new();
+        let mut previous: Option<usize> = None;
+        for i in order {
+            // Duplicates sort next to each other and are looked up once
+            if let Some(first) = previous.filter(|&first| {
+                cf.comparator.compare(keys[first], keys[i]) == std::cmp::Ordering::Equal
+            }) {
+                results[i] = results[first].clone();
+                continue;
+            }
+            previous = Some(i);
+
+            let versions = &mut results[i];
+            for memtable in &memtables {
+                versions.extend(memtable.versions(keys[i], read_ts));
+            }
+
+            let has_base = versions
+                .iter()
+                .any(|(_, _, operation)| *operation != Operation::Merge);
+            if !has_base {
+                let user_key = keys[i].to_vec();
+                for table in version.tables_for_key(keys[i]) {
+                    let reader = match readers.entry(table.meta().number) {
+                        hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                        hash_map::Entry::Vacant(entry) => entry.insert(cf.open_table(table)?),
+                    };
+                    versions.extend(reader.get_versions(&user_key, read_ts)?);
+                }
+                versions.sort_by_key(|(_, timestamp, _)| std::cmp::Reverse(*timestamp));
+                versions.dedup_by_key(|(_, timestamp, _)| *timestamp);
+            }
+        }
+
+        for reader in readers.values() {
+            self.counters.record_reads(reader);
+        }
+        Ok(results)
+    }
+
+    /// Returns the timestamp of the newest version of a key, if any
+    fn newest_timestamp(&self, cf: &ColumnFamilyData, key: &[u8]) -> Result<Option<Timestamp>> {
+        let memtables = self.memtables.read().newest_first(cf.id);
+        let version = cf.versions.current();
+
+        for memtable in &memtables {
+            if let Some(&(_, timestamp, _)) = memtable.versions(key, Timestamp::MAX).first() {
+                return Ok(Some(timestamp));
+            }
+        }
+
+        let user_key = key.to_vec();
+        let mut newest = None;
+        for table in version.tables_for_key(key) {
+            let versions = cf
+                .open_table(table)?
+                .get_versions(&user_key, Timestamp::MAX)?;
+            if let Some(&(_, timestamp, _)) = versions.first() {
+                newest = newest.max(Some(timestamp));
+            }
+        }
+        Ok(newest)
+    }
+
+    /// Reads the visible key-value pairs in `range` as of `read_ts`
+    fn scan_at(
+        &self,
+        cf: &ColumnFamilyData,
+        range: &KeyRange,
+        read_ts: Timestamp,
+    ) -> Result<Vec<(Key, Value)>> {
+        self.scan_at_limited(cf, range, read_ts, usize::MAX)
+    }
+
+    /// Reads the first `limit` visible key-value pairs in `range` as of `read_ts`
+    fn scan_at_limited(
+        &self,
+        cf: &ColumnFamilyData,
+        range: &KeyRange,
+        read_ts: Timestamp,
+        limit: usize,
+    ) -> Result<Vec<(Key, Value)>> {
+        let start = match &range.0 {
+            Bound::Included(start) | Bound::Excluded(start) => Some(start.as_slice()),
+            Bound::Unbounded => None,
+        };
+        self.timed(SlowOperationKind::Scan, Some(cf), start, || {
+            self.scan_visible(cf, range, read_ts, limit)
+        })
+    }
+
+    /// Body of [`scan_at_limited`](Self::scan_at_limited)
+    fn scan_visible(
+        &self,
+        cf: &ColumnFamilyData,
+        range: &KeyRange,
+        read_ts: Timestamp,
+        limit: usize,
+    ) -> Result<Vec<(Key, Value)>> {
+        let memtables = self.memtables.read().newest_first(cf.id);
+        let version = cf.versions.current();
+
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<SSTableEntry>> + '_>> = Vec::new();
+        for memtable in &memtables {
+            sources.push(Box::new(memtable.entries(range.clone()).map(Ok)));
+        }
+        for level in 0..crate::manifest::NUM_LEVELS {
+            for table in version.files(level) {
+                if overlaps_range(&*cf.comparator, table, range) {
+                    let entries = table_entries(cf, table, range, &self.counters)?;
+                    sources.push(Box::new(entries.into_iter().map(Ok)));
+                }
+            }
+        }
+
+        let mut entries =
+            MergingIterator::with_comparator(sources, Arc::clone(&cf.comparator)).peekable();
+        let mut results = Vec::new();
+        while results.len() < limit {
+            let Some(entry) = entries.next() else {
+                break;
+            };
+            let entry = entry?;
+            if entry.key.timestamp > read_ts {
+                continue;
+            }
+
+            // Gather the key's remaining visible versions, newest first
+            let user_key = entry.key.user_key;
+            let mut versions = vec![(entry.value, entry.key.timestamp, entry.operation)];
+            while let Some(Ok(next)) = entries.peek() {
+                if next.key.user_key != user_key {
+                    break;
+                }
+                let next = entries.next().expect("peeked entry")?;
+                if next.key.timestamp <= read_ts {
+                    versions.push((next.value, next.key.timestamp, next.operation));
+                }
+            }
+
+            if let Some(value) = self.resolve(cf, &user_key, versions)? {
+                results.push((user_key, value));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Resolves a key's visible versions, newest first, into its value
+    fn resolve(
+        &self,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        versions: Vec<(Value, Timestamp, Operation)>,
+    ) -> Result<Option<Value>> {
+        match (&cf.ttl, Self::resolve_stored(cf, key, versions)?) {
+            (Some(ttl), Some(value)) => ttl.unstamp(value),
+            (_, value) => Ok(value),
+        }
+    }
+
+    /// Resolves a key's visible versions, newest first, into its value and
+    /// the time it has left to live
+    fn resolve_with_ttl(
+        &self,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        versions: Vec<(Value, Timestamp, Operation)>,
+    ) -> Result<Option<(Value, Option<Duration>)>> {
+        match (&cf.ttl, Self::resolve_stored(cf, key, versions)?) {
+            (Some(ttl), Some(value)) => ttl.unstamp_with_ttl(value),
+            (_, value) => Ok(value.map(|value| (value, None))),
+        }
+    }
+
+    /// Resolves a key's visible versions into its value as stored, with
+    /// any expiry trailer
+    fn resolve_stored(
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        versions: Vec<(Value, Timestamp, Operation)>,
+    ) -> Result<Option<Value>> {
+        if let Some(operator) = &cf.merge_operator {
+            return merge::resolve(operator.as_ref(), key, versions);
+        }
+        match versions.into_iter().next() {
+            Some((value, _, Operation::Put)) => Ok(Some(value)),
+            Some((_, _, Operation::Merge)) => Err(missing_merge_operator()),
+            Some((_, _, Operation::Delete)) | None => Ok(None),
+        }
+    }
+}
+
+/// Returns the error for merge operands read without a merge operator
+fn missing_merge_operator() -> Error {
+    Error::InvalidOperation("Found a merge operand but no merge operator is configured".to_string())
+}
+
+/// Returns true if `key` lies within `range` in the comparator's order
+fn range_contains(comparator: &dyn Comparator, range: &KeyRange, key: &[u8]) -> bool {
+    let start = range.0.as_ref().map(Vec::as_slice);
+    let end = range.1.as_ref().map(Vec::as_slice);
+    comparator::contains(comparator, start, end, key)
+}
+
+/// Returns true if a table may hold keys within `range`
+fn overlaps_range(comparator: &dyn Comparator, table: &TableHandle, range: &KeyRange) -> bool {
+    let meta = table.meta();
+    let start = range.0.as_ref().map(Vec::as_slice);
+    let end = range.1.as_ref().map(Vec::as_slice);
+    comparator::above_start(comparator, start, &meta.largest.user_key)
+        && comparator::below_end(comparator, end, &meta.smallest.user_key)
+}
+
+/// Reads every version of the keys in `range` from a table
+fn table_entries(
+    cf: &ColumnFamilyData,
+    table: &TableHandle,
+    range: &KeyRange,
+    counters: &Counters,
+) -> Result<Vec<SSTableEntry>> {
+    let start = match &range.0 {
+        Bound::Included(start) | Bound::Excluded(start) => Some(start),
+        Bound::Unbounded => None,
+    };
+
+    let mut reader = cf.open_table(table)?;
+    let mut entries = Vec::new();
+    for entry in reader.range_iter(start, None)? {
+        let entry = entry?;
+        if matches!(&range.0, Bound::Excluded(start) if *start == entry.key.user_key) {
+            continue;
+        }
+        if !range_contains(&*cf.comparator, range, &entry.key.user_key) {
+            break;
+        }
+        entries.push(entry);
+    }
+    counters.record_reads(&reader);
+    Ok(entries)
+}
+
+/// Writes a MemTable's versions into a new table
+///
+/// Returns the table's metadata, or `None` for an empty MemTable. With
+/// paranoid checks the table is read back before it is returned. A
+/// partially written or damaged table is removed on error.
+fn write_table(cf: &ColumnFamilyData, memtable: &MemTable) -> Result<Option<SSTableMeta>> {
+    if memtable.entry_count() == 0 {
+        return Ok(None);
+    }
+
+    let number = cf.versions.new_file_number();
+    let path = cf.versions.table_path(number);
+    let result = (|| {
+        let mut writer = cf.table_writer(&path, IoPriority::High)?;
+        for entry in memtable.entries::<[u8], _>(..) {
+            writer.add(entry.key, entry.value, entry.operation)?;
+        }
+        let meta = SSTableMeta::new(number, &writer.finish()?);
+        if cf.config.paranoid_checks {
+            cf.versions.verify_table(cf.versions.vfs(), &meta)?;
+        }
+        Ok(meta)
+    })();
+
+    if result.is_err() {
+        let _ = cf.versions.vfs().remove_file(&path);
+    }
+    result.map(Some)
+}
+
+/// Opens every column family registered in the default family's MANIFEST
+///
+/// Directories of families that aren't registered, left behind by a crash
+/// while one was created or before a dropped one was deleted, are removed
+/// unless the default family is read-only.
+fn open_column_families(
+    options: &Options,
+    default: &Arc<ColumnFamilyData>,
+    rate_limiter: &Arc<RateLimiter>,
+) -> Result<BTreeMap<u32, Arc<ColumnFamilyData>>> {
+    let data_dir = &options.config.data_dir;
+    let registered = default.versions.manifest_state().column_families().clone();
+    let read_only = default.versions.is_read_only();
+
+    if !read_only {
+        let vfs = &options.vfs;
+        for path in vfs.read_dir(data_dir)? {
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_column_family_dir);
+            if let Some(id) = id {
+                // Only directories can be listed
+                if !registered.contains_key(&id) && vfs.read_dir(&path).is_ok() {
+                    log::warn!("Removing unregistered column family directory {}", id);
+                    vfs.remove_dir_all(&path)?;
+                }
+            }
+        }
+    }
+
+    let mut families = BTreeMap::new();
+    families.insert(DEFAULT_COLUMN_FAMILY_ID, Arc::clone(default));
+    for (id, name) in registered {
+        let cf = open_column_family(options, id, &name, rate_limiter, read_only)?;
+        families.insert(id, Arc::new(cf));
+    }
+    Ok(families)
+}
+
+/// Opens a registered column family other than the default with the
+/// options given for its name
+fn open_column_family(
+    options: &Options,
+    id: u32,
+    name: &str,
+    rate_limiter: &Arc<RateLimiter>,
+    read_only: bool,
+) -> Result<ColumnFamilyData> {
+    let open = if read_only {
+        ColumnFamilyData::open_read_only
+    } else {
+        ColumnFamilyData::open
+    };
+    open(
+        id,
+        name,
+        &column_family_dir(&options.config.data_dir, id),
+        options,
+        options
+            .column_families
+            .get(name)
+            .cloned()
+            .unwrap_or_default(),
+        rate_limiter,
+    )
+}
+
+/// Creates the data and WAL directories, then locks the data directory,
+/// and the WAL directory if it lives elsewhere, through the engine's
+/// filesystem
+fn lock_dirs(options: &Options) -> Result<Vec<DirLock>> {
+    let config = &options.config;
+    let vfs = options.vfs.as_ref();
+    vfs.create_dir_all(&config.data_dir)?;
+    vfs.create_dir_all(&config.wal_dir)?;
+    let mut dir_locks = vec![DirLock::acquire(vfs, &config.data_dir)?];
+    if !config.wal_dir.starts_with(&config.data_dir) {
+        dir_locks.push(DirLock::acquire(vfs, &config.wal_dir)?);
+    }
+    Ok(dir_locks)
+}
+
+fn missing_column_family(id: u32) -> Error {
+    Error::InvalidOperation(format!("Column family {} does not exist", id))
+}
+
+/// Deletes or archives a WAL segment that is no longer needed for recovery
+fn retire_wal_segment(options: &Options, path: &Path) {
+    let vfs = options.vfs.as_ref();
+    let result = match (&options.config.wal_archive_dir, path.file_name()) {
+        (Some(archive), Some(name)) => {
+            let target = archive.join(name);
+            vfs.rename(path, &target).or_else(|_| {
+                vfs::copy(vfs, path, &target)?;
+                vfs.remove_file(path)
+            })
+        }
+        _ => vfs.remove_file(path),
+    };
+    if let Err(e) = result {
+        log::warn!("Failed to retire flushed WAL {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    fn small_options(dir: &Path) -> Options {
+        Options::new(dir)
+            .with_memtable_size(4 * 1024)
+            .with_max_immutable_memtables(1)
+    }
+
+    fn key(i: usize) -> Key {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_put_get_delete() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"3".to_vec()).unwrap();
+        engine.delete(b"b".to_vec()).unwrap();
+
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+        assert_eq!(engine.get(b"c").unwrap(), None);
+    }
+
+    #[test]
+    fn test_flush_without_waiting_leaves_the_write_to_the_flush_job() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+
+        let no_wait = FlushOptions::new().with_wait(false);
+        engine.flush_with_options(&no_wait).unwrap();
+        assert!(!engine.inner.memtables.read().has_data());
+        engine.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while engine.inner.default.versions.current().file_count() == 0 {
+            assert!(Instant::now() < deadline, "MemTable never flushed");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), Some(b"2".to_vec()));
+
+        // Waiting is the default, so the second write is flushed on return
+        engine.flush_with_options(&FlushOptions::default()).unwrap();
+        assert_eq!(engine.inner.default.versions.current().file_count(), 2);
+        drop(engine);
+        let reader = StorageEngine::open_read_only(Options::new(dir.path())).unwrap();
+        assert!(reader.flush_with_options(&no_wait).is_err());
+        assert!(reader.sync_wal().is_err());
+    }
+
+    #[test]
+    fn test_sync_wal_makes_unsynced_writes_durable() {
+        let sim = Arc::new(vfs::SimVfs::new(7));
+        let options = Options::new("/db")
+            .with_vfs(sim.clone())
+            .with_sync_mode(ferrisdb_core::SyncMode::None);
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..100 {
+            batch.put(key(i), b"v".to_vec());
+        }
+        engine.write(batch, false).unwrap();
+
+        let syncs = engine.wal_metrics().sync_total();
+        engine.sync_wal().unwrap();
+        assert_eq!(engine.wal_metrics().sync_total(), syncs + 1);
+        engine.crash();
+        sim.crash();
+
+        let engine = StorageEngine::open(options).unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 100);
+        assert_eq!(engine.get(&key(99)).unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_reads_span_memtables_and_tables() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(small_options(dir.path())).unwrap();
+
+        for i in 0..500 {
+            engine.put(key(i), format!("v{}", i).into_bytes()).unwrap();
+        }
+        for i in (0..500).step_by(3) {
+            engine.delete(key(i)).unwrap();
+        }
+
+        assert!(engine.inner.default.versions.current().file_count() > 0);
+        for i in 0..500 {
+            let expected = (i % 3 != 0).then(|| format!("v{}", i).into_bytes());
+            assert_eq!(engine.get(&key(i)).unwrap(), expected, "key {}", i);
+        }
+
+        let scanned = engine.scan(key(10).as_slice()..key(20).as_slice()).unwrap();
+        let keys: Vec<_> = scanned.into_iter().map(|(k, _)| k).collect();
+        let expected: Vec<_> = (10..20).filter(|i| i % 3 != 0).map(key).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_multi_get_returns_values_in_input_order() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(small_options(dir.path())).unwrap();
+
+        for i in 0..500 {
+            engine.put(key(i), format!("v{}", i).into_bytes()).unwrap();
+        }
+        for i in (0..500).step_by(3) {
+            engine.delete(key(i)).unwrap();
+        }
+        assert!(engine.inner.default.versions.current().file_count() > 0);
+
+        let keys = vec![
+            key(499),
+            key(7),
+            key(3),
+            b"missing".to_vec(),
+            key(7),
+            key(250),
+        ];
+        let expected: Vec<_> = keys.iter().map(|k| engine.get(k).unwrap()).collect();
+        assert_eq!(engine.multi_get(&keys).unwrap(), expected);
+        assert_eq!(expected[1], Some(b"v7".to_vec()));
+        assert_eq!(expected[2], None);
+
+        let snapshot = engine.snapshot();
+        engine.put(key(7), b"newer".to_vec()).unwrap();
+        assert_eq!(
+            snapshot.multi_get(&[key(7)]).unwrap(),
+            vec![Some(b"v7".to_vec())]
+        );
+        assert_eq!(
+            engine.multi_get(&[key(7)]).unwrap(),
+            vec![Some(b"newer".to_vec())]
+        );
+        assert!(engine.multi_get::<Key>(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_multi_get_looks_keys_up_in_the_family_order() {
+        /// Folds ASCII case, so keys differing only in case compare equal
+        struct CaseInsensitive;
+
+        impl crate::comparator::Comparator for CaseInsensitive {
+            fn name(&self) -> &str {
+                "test.CaseInsensitive"
+            }
+
+            fn compare(&self, a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+                a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let folded = engine
+            .create_column_family(
+                "folded",
+                ColumnFamilyOptions::default().with_comparator(Arc::new(CaseInsensitive)),
+            )
+            .unwrap();
+        for i in 0..50 {
+            engine
+                .put_cf(&folded, key(i), format!("v{}", i).into_bytes())
+                .unwrap();
+        }
+        engine.flush().unwrap();
+        engine.put_cf(&folded, key(7), b"newer".to_vec()).unwrap();
+
+        // Keys equal under the comparator are duplicates even though their
+        // bytes differ, and share the lookup of the first one
+        let upper = |i| {
+            String::from_utf8(key(i))
+                .unwrap()
+                .to_uppercase()
+                .into_bytes()
+        };
+        let keys = vec![
+            key(3),
+            key(49),
+            key(7),
+            b"missing".to_vec(),
+            upper(3),
+            upper(7),
+        ];
+        let values = engine.multi_get_cf(&folded, &keys).unwrap();
+        assert_eq!(values[0], Some(b"v3".to_vec()));
+        assert_eq!(values[1], Some(b"v49".to_vec()));
+        assert_eq!(values[2], Some(b"newer".to_vec()));
+        assert_eq!(values[3], None);
+        assert_eq!(values[4], values[0]);
+        assert_eq!(values[5], values[2]);
+    }
+
+    #[test]
+    fn test_flush_moves_memtable_into_level0() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+
+        engine.put(b"k".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+
+        let version = engine.inner.default.versions.current();
+        assert_eq!(version.files(0).len(), 1);
+        assert!(!engine.inner.memtables.read().has_data());
+        assert_eq!(engine.get(b"k").unwrap(), Some(b"v".to_vec()));
+
+        // Only the fresh WAL segment is left
+        assert_eq!(
+            wal_segments_in(&vfs::os(), &engine.config().wal_dir)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_background_jobs_report_runs_and_failures() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+
+        engine.put(b"k".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let jobs = engine.background_jobs();
+        let names: Vec<_> = jobs.iter().map(|job| job.name.as_str()).collect();
+        assert_eq!(names, [FLUSH_JOB, COMPACTION_JOB]);
+
+        // The flush job counts its run once it has finished triggering
+        // compaction, shortly after the flush itself
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while engine.background_jobs()[0].runs == 0 {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(engine.background_jobs()[0].last_run.is_some());
+
+        // A failed job makes writes fail from then on
+        engine
+            .inner
+            .background_failed(&Error::DiskFull("no space left".to_string()));
+        assert!(engine.put(b"k".to_vec(), b"v2".to_vec()).is_err());
+        let error = engine
+            .inner
+            .background_error(&engine.inner.background.lock())
+            .unwrap_err();
+        assert!(error.to_string().contains("no space left"));
+    }
+
+    #[test]
+    fn test_reopen_recovers_flushed_and_logged_writes() {
+        let dir = TempDir::new().unwrap();
+        {
+            let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+            engine.put(b"flushed".to_vec(), b"1".to_vec()).unwrap();
+            engine.flush().unwrap();
+            engine.put(b"logged".to_vec(), b"2".to_vec()).unwrap();
+            // Skip close, as if the process had crashed
+            engine.crash();
+        }
+
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 1);
+        assert_eq!(engine.get(b"flushed").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get(b"logged").unwrap(), Some(b"2".to_vec()));
+
+        // Timestamps continue after the recovered ones
+        engine.put(b"logged".to_vec(), b"3".to_vec()).unwrap();
+        assert_eq!(engine.get(b"logged").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn test_directory_is_locked_while_open() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let error = StorageEngine::open(Options::new(dir.path())).err().unwrap();
+        assert!(matches!(error, Error::AlreadyLocked(_)));
+
+        // A WAL directory of its own is locked as well
+        let wal_dir = TempDir::new().unwrap();
+        let other = TempDir::new().unwrap();
+        let _owner =
+            StorageEngine::open(Options::new(other.path()).with_wal_dir(wal_dir.path())).unwrap();
+        let error =
+            StorageEngine::open(Options::new(dir.path().join("b")).with_wal_dir(wal_dir.path()))
+                .err()
+                .unwrap();
+        assert!(matches!(error, Error::AlreadyLocked(_)));
+
+        engine.close().unwrap();
+        StorageEngine::open(Options::new(dir.path())).unwrap();
+    }
+
+    #[test]
+    fn test_directory_is_locked_through_the_vfs() {
+        let vfs: Arc<dyn vfs::Vfs> = Arc::new(vfs::SimVfs::new(5));
+        let options = Options::new("/db").with_vfs(Arc::clone(&vfs));
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        let error = StorageEngine::open(options.clone()).err().unwrap();
+        assert!(matches!(error, Error::AlreadyLocked(_)));
+
+        engine.close().unwrap();
+        StorageEngine::open(options).unwrap();
+    }
+
+    #[test]
+    fn test_merge_requires_operator_and_resolves_across_flushes() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert!(matches!(
+            engine.merge(b"n".to_vec(), 1u64.to_le_bytes().to_vec()),
+            Err(Error::InvalidOperation(_))
+        ));
+        engine.close().unwrap();
+
+        let engine = StorageEngine::open(
+            Options::new(dir.path()).with_merge_operator(Arc::new(U64AddOperator)),
+        )
+        .unwrap();
+        engine
+            .put(b"n".to_vec(), 10u64.to_le_bytes().to_vec())
+            .unwrap();
+        engine.flush().unwrap();
+        engine
+            .merge(b"n".to_vec(), 5u64.to_le_bytes().to_vec())
+            .unwrap();
+
+        assert_eq!(
+            engine.get(b"n").unwrap(),
+            Some(15u64.to_le_bytes().to_vec())
+        );
+        assert_eq!(
+            engine.scan::<[u8], _>(..).unwrap(),
+            vec![(b"n".to_vec(), 15u64.to_le_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_write_batch_applies_all_writes_atomically() {
+        let dir = TempDir::new().unwrap();
+        {
+            // A stopped clock, so the batch's timestamps follow the last
+            // put's instead of starting at a later millisecond
+            let clock = Arc::new(ManualClock::new(Duration::ZERO));
+            let engine = StorageEngine::open(Options::new(dir.path()).with_clock(clock)).unwrap();
+            engine.put(b"a".to_vec(), b"old".to_vec()).unwrap();
+            let before = engine.snapshot();
+
+            let mut batch = WriteBatch::new();
+            batch.put(b"a".to_vec(), b"new".to_vec());
+            batch.put(b"b".to_vec(), b"1".to_vec());
+            batch.delete(b"a".to_vec());
+            batch.put(b"c".to_vec(), b"1".to_vec());
+            engine.write(batch, true).unwrap();
+
+            assert_eq!(engine.get(b"a").unwrap(), None);
+            assert_eq!(before.get(b"b").unwrap(), None);
+            assert_eq!(before.timestamp() + 4, engine.snapshot().timestamp());
+            // Skip close, as if the process had crashed
+            engine.crash();
+        }
+
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert_eq!(engine.recovery_report().entries_replayed, 5);
+        assert_eq!(
+            engine.scan::<[u8], _>(..).unwrap(),
+            vec![
+                (b"b".to_vec(), b"1".to_vec()),
+                (b"c".to_vec(), b"1".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_delete_range_covers_existing_and_earlier_batch_keys() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(small_options(dir.path())).unwrap();
+        for i in 0..200 {
+            engine.put(key(i), b"v".to_vec()).unwrap();
+        }
+        assert!(engine.inner.default.versions.current().file_count() > 0);
+
+        let mut batch = WriteBatch::new();
+        batch.put(key(55), b"earlier".to_vec());
+        batch.delete_range(key(50)..key(80));
+        batch.put(key(70), b"later".to_vec());
+        engine.write(batch, false).unwrap();
+
+        assert_eq!(engine.get(&key(49)).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(&key(55)).unwrap(), None);
+        assert_eq!(engine.get(&key(70)).unwrap(), Some(b"later".to_vec()));
+        assert_eq!(engine.get(&key(80)).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.scan(key(50)..key(80)).unwrap().len(), 1);
+
+        // A range covering nothing writes nothing
+        let before = engine.snapshot().timestamp();
+        let mut batch = WriteBatch::new();
+        batch.delete_range(key(2000)..key(3000));
+        engine.write(batch, false).unwrap();
+        assert_eq!(engine.snapshot().timestamp(), before);
+    }
+
+    #[test]
+    fn test_delete_files_in_range_drops_contained_tables() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        // An older version of a1 in a table reaching past the range
+        engine.put(b"a1".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"b1".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+        // A table holding only keys of the range
+        engine.put(b"a1".to_vec(), b"new".to_vec()).unwrap();
+        engine.put(b"a2".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+        // And one of them still in the MemTable
+        engine.put(b"a3".to_vec(), b"v".to_vec()).unwrap();
+
+        let dropped = engine
+            .delete_files_in_range(b"a".as_slice()..b"b".as_slice())
+            .unwrap();
+
+        assert_eq!(dropped, 1);
+        let version = engine.inner.default.versions.current();
+        assert_eq!(version.file_count(), 1);
+        assert_eq!(engine.get(b"a1").unwrap(), None);
+        assert_eq!(engine.get(b"a2").unwrap(), None);
+        assert_eq!(engine.get(b"a3").unwrap(), None);
+        assert_eq!(engine.get(b"b1").unwrap(), Some(b"v".to_vec()));
+
+        // A range holding no whole table only deletes
+        assert_eq!(engine.delete_files_in_range(b"b1".as_slice()..).unwrap(), 0);
+        assert_eq!(engine.get(b"b1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_batch_with_merge_requires_operator() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"a".to_vec(), b"1".to_vec());
+        batch.merge(b"n".to_vec(), 1u64.to_le_bytes().to_vec());
+
+        assert!(matches!(
+            engine.write(batch, false),
+            Err(Error::InvalidOperation(_))
+        ));
+        assert_eq!(engine.get(b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_entry_larger_than_memtable_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(small_options(dir.path())).unwrap();
+
+        let result = engine.put(b"big".to_vec(), vec![0; 8 * 1024]);
+
+        assert!(matches!(result, Err(Error::EntrySizeExceeded { .. })));
+        assert_eq!(engine.get(b"big").unwrap(), None);
+    }
+
+    #[test]
+    fn test_operations_fail_after_close() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"k".to_vec(), b"v".to_vec()).unwrap();
+
+        engine.close().unwrap();
+        engine.close().unwrap();
+
+        assert!(engine.put(b"k".to_vec(), b"v".to_vec()).is_err());
+        assert!(engine.get(b"k").is_err());
+
+        // Closing flushed everything
+        let state = engine.inner.default.versions.manifest_state();
+        assert_eq!(state.file_count(), 1);
+    }
+
+    #[test]
+    fn test_writes_skipping_the_wal_are_lost_in_a_crash_unless_flushed() {
+        let dir = TempDir::new().unwrap();
+        let unlogged = WriteOptions::new().with_disable_wal(true);
+        let batch = |key: &[u8]| {
+            let mut batch = WriteBatch::new();
+            batch.put(key.to_vec(), b"v".to_vec());
+            batch
+        };
+        {
+            let engine = StorageEngine::open(small_options(dir.path())).unwrap();
+            engine
+                .write_with_options(batch(b"flushed"), &unlogged)
+                .unwrap();
+            engine.flush().unwrap();
+            engine.put(b"logged".to_vec(), b"v".to_vec()).unwrap();
+            engine
+                .write_with_options(batch(b"lost"), &unlogged)
+                .unwrap();
+            assert_eq!(engine.get(b"lost").unwrap(), Some(b"v".to_vec()));
+            assert_eq!(
+                engine.wal_metrics().writes_total(),
+                1,
+                "only the logged put reaches the WAL"
+            );
+            engine.crash();
+        }
+
+        let engine = StorageEngine::open(small_options(dir.path())).unwrap();
+        assert_eq!(engine.get(b"flushed").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"logged").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"lost").unwrap(), None);
+
+        let result = engine.write_with_options(batch(b"key"), &unlogged.with_sync(true));
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_write_options_override_the_sync_mode() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path()).with_sync_mode(ferrisdb_core::SyncMode::None),
+        )
+        .unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        assert_eq!(engine.wal_metrics().sync_total(), 0);
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"b".to_vec(), b"2".to_vec());
+        let critical = WriteOptions::new().with_sync(true);
+        engine.write_with_options(batch, &critical).unwrap();
+        assert_eq!(engine.wal_metrics().sync_total(), 1);
+    }
+
+    #[test]
+    fn test_wal_sync_job_syncs_every_interval() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path())
+                .with_sync_mode(ferrisdb_core::SyncMode::None)
+                .with_wal_sync_interval(Duration::from_millis(10)),
+        )
+        .unwrap();
+        engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while engine.wal_metrics().sync_total() == 0 {
+            assert!(std::time::Instant::now() < deadline, "WAL never synced");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(engine
+            .background_jobs()
+            .iter()
+            .any(|job| job.name == WAL_SYNC_JOB));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wal_rotation_renames_the_preallocated_segment() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let preallocated = engine.config().wal_dir.join(PREALLOCATED_WAL_FILE_NAME);
+        // The flush job started on open readies the first one
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while !*engine.inner.wal_preallocated.lock() {
+            assert!(std::time::Instant::now() < deadline, "no WAL preallocated");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let inode = std::fs::metadata(&preallocated).unwrap().ino();
+
+        engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let active = engine
+            .inner
+            .wal_path(engine.inner.memtables.read().active_wal);
+        assert_eq!(std::fs::metadata(&active).unwrap().ino(), inode);
+        // The flush job readied the file of the segment after it
+        assert!(preallocated.exists());
+
+        engine.put(b"other".to_vec(), b"value".to_vec()).unwrap();
+        engine.close().unwrap();
+        assert!(!preallocated.exists());
+        drop(engine);
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert_eq!(engine.get(b"other").unwrap(), Some(b"value".to_vec()));
+
+        let dir = TempDir::new().unwrap();
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_wal_preallocation(false)).unwrap();
+        engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        engine.flush().unwrap();
+        assert!(!engine
+            .config()
+            .wal_dir
+            .join(PREALLOCATED_WAL_FILE_NAME)
+            .exists());
+    }
+
+    #[test]
+    fn test_write_buffer_budget_flushes_the_largest_memtables() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path())
+            .with_memtable_size(1024 * 1024)
+            .with_write_buffer_budget(8 * 1024);
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        let index = engine
+            .create_column_family("index", ColumnFamilyOptions::default())
+            .unwrap();
+        let active_wal = || engine.inner.memtables.read().active_wal;
+        let entries = |id| engine.inner.memtables.read().active[&id].entry_count();
+        let first = active_wal();
+
+        // Each family is far below its own size, together they fill the budget
+        engine.put(key(0), vec![b'v'; 1024]).unwrap();
+        engine.put_cf(&index, key(0), vec![b'i'; 5 * 1024]).unwrap();
+        assert_eq!(active_wal(), first);
+        engine.put_cf(&index, key(1), vec![b'i'; 2 * 1024]).unwrap();
+        assert_ne!(active_wal(), first);
+
+        // Retiring the larger family made enough room, the other stays
+        assert_eq!(entries(DEFAULT_COLUMN_FAMILY_ID), 1);
+        assert_eq!(entries(index.id()), 1);
+        assert!(engine.inner.scheduler.wait_idle(FLUSH_JOB));
+        let level0 = |cf: &ColumnFamilyData| cf.versions.current().files(0).len();
+        assert_eq!(level0(&engine.inner.column_family(&index).unwrap()), 1);
+        assert_eq!(level0(&engine.inner.default), 0);
+        // The segment still holds the default family's write
+        assert!(engine.inner.wal_path(first).exists());
+
+        drop(engine);
+        let engine = StorageEngine::open(options).unwrap();
+        let index = engine.cf_handle("index").unwrap();
+        assert_eq!(engine.get(&key(0)).unwrap().unwrap().len(), 1024);
+        assert_eq!(
+            engine.get_cf(&index, &key(0)).unwrap().unwrap().len(),
+            5 * 1024
+        );
+        assert_eq!(
+            engine.get_cf(&index, &key(1)).unwrap().unwrap().len(),
+            2 * 1024
+        );
+        engine.flush().unwrap();
+        assert!(!engine.inner.wal_path(first).exists());
+
+        // A batch larger than the budget still fits into empty MemTables
+        engine.put(key(2), vec![b'v'; 16 * 1024]).unwrap();
+        assert_eq!(engine.get(&key(2)).unwrap().unwrap().len(), 16 * 1024);
+    }
+
+    #[test]
+    fn test_write_buffer_budget_keeps_segments_until_every_family_flushed() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path())
+                .with_memtable_size(1024 * 1024)
+                .with_write_buffer_budget(8 * 1024),
+        )
+        .unwrap();
+        let index = engine
+            .create_column_family("index", ColumnFamilyOptions::default())
+            .unwrap();
+        let first = engine.inner.memtables.read().active_wal;
+
+        engine.put(key(0), vec![b'v'; 1024]).unwrap();
+        for i in 0..8 {
+            engine.put_cf(&index, key(i), vec![b'i'; 5 * 1024]).unwrap();
+        }
+        assert!(engine.inner.scheduler.wait_idle(FLUSH_JOB));
+        // Every switch retired only the index, whose segments are kept for
+        // the default family's write in the first
+        let kept = wal_segments_in(engine.inner.options.vfs(), &engine.config().wal_dir)
+            .unwrap()
+            .len();
+        assert!(kept > 2, "only {} segments kept", kept);
+        assert_eq!(
+            engine.inner.memtables.read().flushed_wals.front(),
+            Some(&first)
+        );
+
+        engine.flush().unwrap();
+        let segments =
+            wal_segments_in(engine.inner.options.vfs(), &engine.config().wal_dir).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert!(engine.inner.memtables.read().flushed_wals.is_empty());
+    }
+
+    #[test]
+    fn test_max_write_buffer_number_stalls_writes() {
+        /// Holds up every flush until the sender is dropped
+        struct Gate(Mutex<std::sync::mpsc::Receiver<()>>);
+
+        impl EventListener for Gate {
+            fn on_flush_begin(&self, _info: &FlushJobInfo) {
+                let _ = self.0.lock().recv();
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let (open, gate) = std::sync::mpsc::channel();
+        let engine = Arc::new(
+            StorageEngine::open(
+                Options::new(dir.path())
+                    .with_memtable_size(4 * 1024)
+                    .with_max_write_buffer_number(2)
+                    .with_event_listener(Arc::new(Gate(Mutex::new(gate)))),
+            )
+            .unwrap(),
+        );
+
+        // The second write retires the first MemTable, whose flush is held up
+        engine.put(key(0), vec![b'v'; 3 * 1024]).unwrap();
+        engine.put(key(1), vec![b'v'; 3 * 1024]).unwrap();
+        assert_eq!(engine.inner.memtables.read().immutable.len(), 1);
+
+        // With two MemTables in memory, a third has to wait for the flush
+        let (done, finished) = std::sync::mpsc::channel();
+        let writer = {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || {
+                let result = engine.put(key(2), vec![b'v'; 3 * 1024]);
+                done.send(()).unwrap();
+                result
+            })
+        };
+        assert!(finished.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(engine.inner.memtables.read().immutable.len(), 1);
+
+        drop(open);
+        writer.join().unwrap().unwrap();
+        assert!(finished.try_recv().is_ok());
+        for i in 0..3 {
+            assert_eq!(engine.get(&key(i)).unwrap().unwrap().len(), 3 * 1024);
+        }
+    }
+
+    #[test]
+    fn test_in_memory_engine_flushes_and_compacts_without_files() {
+        let engine =
+            StorageEngine::open(Options::in_memory().with_memtable_size(4 * 1024)).unwrap();
+        // Each has a filesystem of its own, so in-memory engines never
+        // conflict
+        let other = StorageEngine::open_in_memory().unwrap();
+        assert_eq!(other.get(b"key0000").unwrap(), None);
+
+        let index = engine
+            .create_column_family("index", ColumnFamilyOptions::default())
+            .unwrap();
+        for i in 0..300 {
+            let key = format!("key{:04}", i).into_bytes();
+            engine.put(key.clone(), vec![b'v'; 64]).unwrap();
+            engine.put_cf(&index, key, b"i".to_vec()).unwrap();
+        }
+        // Holding the lock keeps the compaction job from merging the tables
+        let held = engine.inner.compaction.lock();
+        engine.flush().unwrap();
+        assert!(engine.inner.default.versions.current().file_count() > 1);
+        drop(held);
+        engine.compact_range::<[u8], _>(..).unwrap();
+
+        let version = engine.inner.default.versions.current();
+        assert_eq!(version.files(0).len(), 0);
+        assert!(version.file_count() > 0);
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap().len(), 300);
+        assert_eq!(
+            engine.get_cf(&index, b"key0299").unwrap(),
+            Some(b"i".to_vec())
+        );
+        engine.drop_column_family(&index).unwrap();
+        drop(index);
+
+        let vfs = &engine.inner.options.vfs;
+        assert!(!vfs::is_os(vfs));
+        assert_eq!(wal_segments_in(vfs, Path::new("/wal")).unwrap().len(), 1);
+        assert!(vfs.exists(Path::new("/MANIFEST")));
+        assert!(!Path::new("/MANIFEST").exists());
+
+        // Checkpoints go to the same filesystem, tables linked
+        engine.create_checkpoint(Path::new("/checkpoint")).unwrap();
+        assert!(!Path::new("/checkpoint").exists());
+        let copy =
+            StorageEngine::open(Options::new("/checkpoint").with_vfs(Arc::clone(vfs))).unwrap();
+        assert_eq!(copy.scan::<[u8], _>(..).unwrap().len(), 300);
+        copy.close().unwrap();
+
+        engine.close().unwrap();
+        // Background jobs may hold the dropped family until they stop
+        assert!(!vfs.exists(&column_family_dir(Path::new("/"), 1)));
+    }
+}
@@ -0,0 +1,152 @@
+//! Offloading cold tables to remote storage
+//!
+//! With [`Options::with_remote_storage`](super::Options::with_remote_storage),
+//! the engine reads and writes files through a [`TieredVfs`] over its
+//! filesystem, and an `offload` job moves the tables of levels from
+//! `remote_level` down to the remote storage once compactions install
+//! them there:
+//!
+//! - Tables are written locally as usual, and only uploaded once in a
+//!   level at or below `remote_level`, where compaction rewrites them
+//!   least often
+//! - An offloaded table keeps its path: reads open the remote object, a
+//!   chunk at a time through the cache, and dropping the table deletes it
+//! - The job holds the compaction lock while it uploads, so the tables it
+//!   offloads are never obsolete halfway through
+//! - Failing to upload a table leaves it local, to be tried again after
+//!   the next compaction; the job never fails the engine's writes
+
+use super::EngineInner;
+use crate::manifest::NUM_LEVELS;
+use crate::remote::TieredVfs;
+use ferrisdb_core::Result;
+
+use std::sync::Arc;
+
+/// Name of the background job offloading tables to remote storage
+pub(super) const OFFLOAD_JOB: &str = "offload";
+
+impl EngineInner {
+    /// Returns the filesystem offloaded tables are read through, if the
+    /// engine has remote storage
+    pub(super) fn tiered(&self) -> Option<&Arc<TieredVfs>> {
+        self.tiered.as_ref()
+    }
+
+    /// Body of the offload job: offloads the local tables due for it
+    ///
+    /// Never fails, so remote storage being unreachable doesn't stop the
+    /// engine's writes; the error is logged and the next run tries again.
+    pub(super) fn offload_cold_tables(&self) -> Result<()> {
+        match self.offload_tables() {
+            Ok(0) => {}
+            Ok(count) => log::debug!("Offloaded {} tables", count),
+            Err(e) => log::warn!("Offloading tables failed: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Uploads the local tables of levels from `remote_level` down to
+    /// remote storage, returning how many were offloaded
+    pub(super) fn offload_tables(&self) -> Result<usize> {
+        let Some(tiered) = self.tiered() else {
+            return Ok(0);
+        };
+        let first_level = self.options.config.remote_level.min(NUM_LEVELS);
+        let mut offloaded = 0;
+        for cf in self.families() {
+            let version = cf.versions.current();
+            for table in (first_level..NUM_LEVELS).flat_map(|level| version.files(level)) {
+                if !tiered.is_local(table.path()) {
+                    continue;
+                }
+                let _compaction = self.compaction.lock();
+                // A manual offload may have raced the job to the table
+                if table.is_obsolete()
+                    || !tiered.is_local(table.path())
+                    || self.background.lock().shutdown
+                {
+                    continue;
+                }
+                tiered.offload(table.path())?;
+                offloaded += 1;
+            }
+        }
+        Ok(offloaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use super::*;
+    use crate::remote::{MemoryRemoteStorage, RemoteStorage};
+    use crate::vfs::Vfs;
+
+    fn tiered_options(remote: &Arc<MemoryRemoteStorage>) -> Options {
+        Options::in_memory()
+            .with_remote_storage(remote.clone())
+            .with_remote_level(1)
+    }
+
+    #[test]
+    fn test_compacted_tables_are_offloaded_and_read_back() {
+        let remote = Arc::new(MemoryRemoteStorage::new());
+        let options = tiered_options(&remote);
+        let vfs = Arc::clone(&options.vfs);
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        for i in 0..100u32 {
+            engine
+                .put(format!("key{:03}", i).into_bytes(), vec![b'v'; 100])
+                .unwrap();
+        }
+        engine.flush().unwrap();
+        assert_eq!(engine.offload_tables().unwrap(), 0);
+
+        engine.compact_range::<&[u8], _>(..).unwrap();
+        // The job may have offloaded the tables already
+        engine.offload_tables().unwrap();
+        let version = engine.inner.default.versions.current();
+        let table = (1..NUM_LEVELS)
+            .find_map(|level| version.files(level).first())
+            .unwrap();
+        assert!(!vfs.exists(table.path()));
+        assert!(engine.inner.tiered().unwrap().exists(table.path()));
+        assert!(!remote.list("").unwrap().is_empty());
+        assert_eq!(engine.offload_tables().unwrap(), 0);
+
+        assert_eq!(engine.get(b"key042").unwrap(), Some(vec![b'v'; 100]));
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap().len(), 100);
+        assert!(engine.inner.tiered().unwrap().cache_stats().misses > 0);
+        drop(version);
+        engine.close().unwrap();
+        drop(engine);
+
+        // Remote tables are found again on reopening
+        let engine = StorageEngine::open(options).unwrap();
+        assert_eq!(engine.get(b"key099").unwrap(), Some(vec![b'v'; 100]));
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_obsolete_remote_tables_are_deleted() {
+        let remote = Arc::new(MemoryRemoteStorage::new());
+        let engine = StorageEngine::open(tiered_options(&remote)).unwrap();
+        for round in 0..2 {
+            for i in 0..10u32 {
+                engine
+                    .put(format!("key{}", i).into_bytes(), vec![round])
+                    .unwrap();
+            }
+            engine.flush().unwrap();
+            engine.compact_range::<&[u8], _>(..).unwrap();
+            engine.offload_tables().unwrap();
+        }
+        assert_eq!(engine.get(b"key3").unwrap(), Some(vec![1]));
+
+        // The first round's table was compacted away and its object deleted
+        // once the job, which may still hold its version, stopped
+        engine.close().unwrap();
+        assert_eq!(remote.list("").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,620 @@
+//! Options for opening a storage engine
+
+use super::column_family::ColumnFamilyOptions;
+use super::corruption::CorruptionLog;
+use super::event_listener::EventListener;
+use super::event_log::EventLogWriter;
+use super::recovery::RecoveryObserver;
+use super::scrub::{CorruptTable, CorruptionHandler};
+use crate::clock::{Clock, SystemClock};
+use crate::compaction::CompactionFilter;
+use crate::comparator::{self, Comparator};
+use crate::config::{CompactionStrategyKind, MemTableKind, StorageConfig};
+use crate::merge::MergeOperator;
+use crate::prefix::PrefixExtractor;
+use crate::remote::RemoteStorage;
+use crate::utils::memory::MemoryTracker;
+use crate::vfs::{self, SimVfs, Vfs};
+use ferrisdb_core::SyncMode;
+
+use parking_lot::Mutex;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Everything needed to open a [`StorageEngine`](super::StorageEngine)
+///
+/// Wraps a [`StorageConfig`] together with the user hooks that can't live
+/// in a plain configuration struct, such as the merge operator. Settings
+/// not changed through a `with_*` method keep their defaults.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_core::SyncMode;
+/// use ferrisdb_storage::merge::U64AddOperator;
+/// use ferrisdb_storage::storage_engine::Options;
+/// use std::sync::Arc;
+///
+/// let options = Options::new("./data")
+///     .with_sync_mode(SyncMode::Full)
+///     .with_memtable_size(16 * 1024 * 1024)
+///     .with_merge_operator(Arc::new(U64AddOperator));
+///
+/// assert_eq!(options.config().wal_dir, std::path::Path::new("./data/wal"));
+/// ```
+#[derive(Clone)]
+pub struct Options {
+    pub(super) config: StorageConfig,
+    pub(super) merge_operator: Option<Arc<dyn MergeOperator>>,
+    pub(super) compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    /// Default lifetime of values in the default column family
+    pub(super) ttl: Option<Duration>,
+    pub(super) clock: Arc<dyn Clock>,
+    /// Ordering of keys in the default column family
+    pub(super) comparator: Arc<dyn Comparator>,
+    /// Prefixes of the default column family's bloom filters
+    pub(super) prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Settings of non-default column families, by name
+    pub(super) column_families: BTreeMap<String, ColumnFamilyOptions>,
+    /// Whether only replicated writes are accepted
+    pub(super) replica: bool,
+    /// Called with every table the scrubber finds damaged
+    pub(super) corruption_handler: Option<CorruptionHandler>,
+    /// Told about flushes, compactions, WAL rotations and write stalls
+    pub(super) event_listeners: Vec<Arc<dyn EventListener>>,
+    /// Where lifecycle events are written as JSON lines
+    pub(super) event_log: Option<EventLogWriter>,
+    /// Told how far WAL replay has got while the engine opens
+    pub(super) recovery_observers: Vec<Arc<dyn RecoveryObserver>>,
+    /// Filesystem holding the database
+    pub(super) vfs: Arc<dyn Vfs>,
+    /// Where tables of the deepest levels are offloaded to
+    pub(super) remote_storage: Option<Arc<dyn RemoteStorage>>,
+    /// Accounts the memory of the engine opened with these options, which
+    /// gets a tracker of its own
+    pub(super) memory: Arc<MemoryTracker>,
+    /// Keeps the corrupt data the engine opened with these options finds,
+    /// which gets a log of its own
+    pub(super) corruption: Arc<CorruptionLog>,
+}
+
+impl Options {
+    /// Creates options for a database stored in `path`
+    ///
+    /// Tables and the MANIFEST live directly in `path`, WAL segments in
+    /// its `wal` subdirectory.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        Self::from_config(StorageConfig {
+            data_dir: path.to_path_buf(),
+            wal_dir: path.join("wal"),
+            ..Default::default()
+        })
+    }
+
+    /// Creates options for a database kept entirely in memory
+    ///
+    /// The engine runs against an in-memory [`SimVfs`] instead of the
+    /// operating system's filesystem, through the same code paths as on
+    /// disk: writes are logged, MemTables flushed and tables compacted.
+    /// Nothing touches the disk, and everything is gone once the engine
+    /// is dropped, which suits unit tests and experiments. Checkpoints go
+    /// to the same filesystem, so the engine's [`vfs`](Self::vfs) is
+    /// needed to open them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::storage_engine::{Options, StorageEngine};
+    ///
+    /// let engine = StorageEngine::open(Options::in_memory().with_memtable_size(4096))?;
+    /// engine.put(b"key".to_vec(), b"value".to_vec())?;
+    /// assert_eq!(engine.get(b"key")?, Some(b"value".to_vec()));
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    pub fn in_memory() -> Self {
+        let mut options = Self::new("/");
+        options.vfs = Arc::new(SimVfs::new(0));
+        options
+    }
+
+    /// Creates options from a complete configuration
+    pub fn from_config(config: StorageConfig) -> Self {
+        Self {
+            config,
+            merge_operator: None,
+            compaction_filter: None,
+            ttl: None,
+            clock: Arc::new(SystemClock),
+            comparator: comparator::bytewise(),
+            prefix_extractor: None,
+            column_families: BTreeMap::new(),
+            replica: false,
+            corruption_handler: None,
+            event_listeners: Vec::new(),
+            event_log: None,
+            recovery_observers: Vec::new(),
+            vfs: vfs::os(),
+            remote_storage: None,
+            memory: Arc::default(),
+            corruption: Arc::default(),
+        }
+    }
+
+    /// Returns the configuration the engine will be opened with
+    pub fn config(&self) -> &StorageConfig {
+        &self.config
+    }
+
+    /// Returns the filesystem the engine will be opened on
+    pub fn vfs(&self) -> &Arc<dyn Vfs> {
+        &self.vfs
+    }
+
+    /// Sets the filesystem every file of the engine is read and written
+    /// through, the operating system's by default
+    ///
+    /// WAL segments, tables and MANIFESTs go through `vfs`, as do
+    /// checkpoints and [repair](super::StorageEngine::repair), and the
+    /// lock against the database being opened twice is taken with
+    /// [`Vfs::try_lock_file`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::storage_engine::{Options, StorageEngine};
+    /// use ferrisdb_storage::vfs::SimVfs;
+    /// use std::sync::Arc;
+    ///
+    /// let vfs = Arc::new(SimVfs::new(7));
+    /// let engine = StorageEngine::open(Options::new("/db").with_vfs(vfs.clone()))?;
+    /// engine.put(b"key".to_vec(), b"value".to_vec())?;
+    /// engine.flush()?;
+    /// drop(engine);
+    ///
+    /// let engine = StorageEngine::open(Options::new("/db").with_vfs(vfs))?;
+    /// assert_eq!(engine.get(b"key")?, Some(b"value".to_vec()));
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    pub fn with_vfs(mut self, vfs: Arc<dyn Vfs>) -> Self {
+        self.vfs = vfs;
+        self
+    }
+
+    /// Sets the directory holding WAL segments
+    pub fn with_wal_dir(mut self, wal_dir: impl AsRef<Path>) -> Self {
+        self.config.wal_dir = wal_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Keeps flushed WAL segments in `dir` instead of deleting them
+    ///
+    /// Archived segments let [`BackupEngine::restore_to_timestamp`](super::BackupEngine::restore_to_timestamp)
+    /// replay writes made after a backup. Nothing deletes them; prune the
+    /// directory once the backups needing them are gone.
+    pub fn with_wal_archive_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.config.wal_archive_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Keeps the last `size` bytes of WAL records in memory, for
+    /// [`tail_wal`](super::StorageEngine::tail_wal)
+    ///
+    /// Replicas and change consumers that keep up then read new records
+    /// from memory instead of the segment just written; those that fall
+    /// behind the cache read the files until they catch up. Off by
+    /// default, as it copies every logged write.
+    pub fn with_wal_cache_size(mut self, size: usize) -> Self {
+        self.config.wal_cache_size = size;
+        self
+    }
+
+    /// Sets how durably each write is logged before it is acknowledged
+    pub fn with_sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.config.wal_sync_mode = sync_mode;
+        self
+    }
+
+    /// Syncs the WAL to disk every `interval`
+    ///
+    /// Bounds how much a power loss can take of the writes the sync mode,
+    /// or their [`WriteOptions`](super::WriteOptions), didn't sync. A zero
+    /// interval turns the background sync off, as it is by default.
+    pub fn with_wal_sync_interval(mut self, interval: Duration) -> Self {
+        self.config.wal_sync_interval_ms = interval.as_millis() as u64;
+        self
+    }
+
+    /// Creates the file of the next WAL segment ahead of time, as by default
+    ///
+    /// Rotating the WAL then renames the file and writes its header
+    /// instead of creating and syncing a new one while writes wait.
+    pub fn with_wal_preallocation(mut self, enabled: bool) -> Self {
+        self.config.wal_preallocate = enabled;
+        self
+    }
+
+    /// Sets the size at which the active MemTable is flushed (in bytes)
+    pub fn with_memtable_size(mut self, memtable_size: usize) -> Self {
+        self.config.memtable_size = memtable_size;
+        self
+    }
+
+    /// Sets the in-memory index used by MemTables
+    pub fn with_memtable_kind(mut self, kind: MemTableKind) -> Self {
+        self.config.memtable_kind = kind;
+        self
+    }
+
+    /// Sets how many full MemTables may wait for a flush before writes block
+    pub fn with_max_immutable_memtables(mut self, count: usize) -> Self {
+        self.config.max_immutable_memtables = count;
+        self
+    }
+
+    /// Sets how many MemTables of a column family may be in memory at
+    /// once, the active one included
+    ///
+    /// The same as allowing `count - 1` immutable MemTables, but at least
+    /// one.
+    pub fn with_max_write_buffer_number(self, count: usize) -> Self {
+        self.with_max_immutable_memtables(count.saturating_sub(1).max(1))
+    }
+
+    /// Sets the memory the active MemTables of all column families may
+    /// use together (in bytes)
+    ///
+    /// On top of each family's own MemTable size, a write that would take
+    /// the families past the budget first switches the largest MemTables
+    /// for a flush, just enough of them to make room. The others stay
+    /// active, keeping the WAL segments holding their writes until they
+    /// are flushed too. Zero, the default, sets no budget.
+    pub fn with_write_buffer_budget(mut self, bytes: usize) -> Self {
+        self.config.write_buffer_budget = bytes;
+        self
+    }
+
+    /// Sets the memory the engine may hold altogether (in bytes)
+    ///
+    /// Counts the MemTables, the blocks table readers cache, the indexes
+    /// and bloom filters of open tables, and the data iterators copy out
+    /// of MemTables, as broken down by
+    /// [`memory_usage`](super::StorageEngine::memory_usage). Once a write
+    /// would take the engine past the limit while the active MemTables
+    /// hold at least half of it, they are switched for a flush first; and
+    /// while the engine is over the limit, readers keep no blocks cached
+    /// beyond those in use. Memory pinned by iterators is only released
+    /// when they are dropped, so usage can exceed the limit. Zero, the
+    /// default, sets no limit.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.config.memory_limit = bytes;
+        self
+    }
+
+    /// Sets how SSTables are organized and picked for compaction
+    pub fn with_compaction_strategy(mut self, strategy: CompactionStrategyKind) -> Self {
+        self.config.compaction_strategy = strategy;
+        self
+    }
+
+    /// Caps the combined I/O rate of background work (0 = unlimited)
+    ///
+    /// Flushes and compactions share the budget, with flushes served
+    /// first as writes stall when they fall behind.
+    pub fn with_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.config.rate_limiter_bytes_per_sec = bytes_per_sec;
+        self
+    }
+
+    /// Reads SSTables with direct I/O, bypassing the OS page cache
+    ///
+    /// Only the block cache then keeps data in memory, so it should be
+    /// sized for the working set. Filesystems without direct I/O fall back
+    /// to buffered reads.
+    pub fn with_direct_reads(mut self, enabled: bool) -> Self {
+        self.config.use_direct_reads = enabled;
+        self
+    }
+
+    /// Reads compaction inputs and writes its outputs with direct I/O, so
+    /// compactions don't evict the pages hot reads depend on
+    pub fn with_direct_io_for_compaction(mut self, enabled: bool) -> Self {
+        self.config.use_direct_io_for_compaction = enabled;
+        self
+    }
+
+    /// Only logs the files no MANIFEST refers to on open, instead of
+    /// deleting them
+    ///
+    /// They are listed in the [`RecoveryReport`](super::RecoveryReport)
+    /// either way.
+    pub fn with_orphan_files_dry_run(mut self, dry_run: bool) -> Self {
+        self.config.orphan_files_dry_run = dry_run;
+        self
+    }
+
+    /// Trades CPU for catching corruption early
+    ///
+    /// Every SSTable block read is checked against its checksum, tables
+    /// written by flushes and compactions are read back in full before
+    /// they are installed, and the MANIFEST only records tables found on
+    /// disk with the size they were written with. A check that fails
+    /// returns [`Error::Corruption`](ferrisdb_core::Error::Corruption)
+    /// instead of the damaged data.
+    pub fn with_paranoid_checks(mut self, enabled: bool) -> Self {
+        self.config.paranoid_checks = enabled;
+        self
+    }
+
+    /// Keeps every version written within `retention` of now readable by
+    /// timestamp
+    ///
+    /// Compaction then keeps the versions written in the window and the
+    /// one each key had at its start, so [`get_at`](super::StorageEngine::get_at),
+    /// [`scan_at`](super::StorageEngine::scan_at) and
+    /// [`snapshot_at`](super::StorageEngine::snapshot_at) can read as of
+    /// any timestamp in the window, at the cost of the disk space the old
+    /// versions take. Without a window, as by default, only snapshots
+    /// keep old versions.
+    pub fn with_version_retention(mut self, retention: Duration) -> Self {
+        self.config.version_retention_ms = retention.as_millis() as u64;
+        self
+    }
+
+    /// Keeps the `count` newest versions of every key through compaction,
+    /// whatever their age
+    ///
+    /// Reads by timestamp are still limited to the retention window, as
+    /// other keys may have lost the versions they would need; the extra
+    /// versions show up in iterators with a
+    /// [timestamp range](super::ReadOptions::with_timestamp_range).
+    pub fn with_min_versions_to_keep(mut self, count: usize) -> Self {
+        self.config.min_versions_to_keep = count;
+        self
+    }
+
+    /// Reads cold SSTables back every `interval`, verifying their
+    /// checksums before a user read runs into damage
+    ///
+    /// See the [`scrub`](super::StorageEngine::scrub) method for what a
+    /// scrub does. A zero interval turns the scrubber off, as it is by
+    /// default.
+    pub fn with_scrub_interval(mut self, interval: Duration) -> Self {
+        self.config.scrub_interval_ms = interval.as_millis() as u64;
+        self
+    }
+
+    /// Caps the bytes per second the scrubber reads (0 = unlimited)
+    pub fn with_scrub_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.config.scrub_bytes_per_sec = bytes_per_sec;
+        self
+    }
+
+    /// Calls `handler` with every table a scrub finds damaged, once per
+    /// table
+    ///
+    /// The handler runs on the scrubbing thread, so it should hand
+    /// anything slow off elsewhere.
+    pub fn with_corruption_handler(
+        mut self,
+        handler: impl Fn(&CorruptTable) + Send + Sync + 'static,
+    ) -> Self {
+        self.corruption_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Keeps the operations taking at least `threshold` in the slow log
+    ///
+    /// See [`slow_log`](super::StorageEngine::slow_log) for what is
+    /// recorded. A zero threshold turns the slow log off, as it is by
+    /// default.
+    pub fn with_slow_log_threshold(mut self, threshold: Duration) -> Self {
+        self.config.slow_log_threshold_us = threshold.as_micros() as u64;
+        self
+    }
+
+    /// Sets how many operations the slow log keeps, 128 by default
+    pub fn with_slow_log_capacity(mut self, capacity: usize) -> Self {
+        self.config.slow_log_capacity = capacity;
+        self
+    }
+
+    /// Leaves the keys of operations out of the slow log, keeping only
+    /// their lengths, for databases whose keys are sensitive
+    pub fn with_slow_log_redact_keys(mut self, redact: bool) -> Self {
+        self.config.slow_log_redact_keys = redact;
+        self
+    }
+
+    /// Adds a listener for the engine's background work
+    ///
+    /// Listeners are called in the order they were added. See
+    /// [`EventListener`] for the events and the threads they arrive on.
+    pub fn with_event_listener(mut self, listener: Arc<dyn EventListener>) -> Self {
+        self.event_listeners.push(listener);
+        self
+    }
+
+    /// Writes every flush, compaction, WAL rotation, write stall change
+    /// and detected corruption to `writer` as a line of JSON
+    ///
+    /// Each line is an object holding the event's name in `event`, the
+    /// engine's clock in microseconds in `time_micros`, and the fields of
+    /// the matching [`EventListener`] callback's info, such as
+    /// `{"event":"flush_started","time_micros":..,"column_family":"default",..}`.
+    /// The writer is typically a file of its own, kept apart from the
+    /// application's logs, and is flushed after every event.
+    pub fn with_event_log(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.event_log = Some(Arc::new(Mutex::new(Box::new(writer))));
+        self
+    }
+
+    /// Adds an observer of WAL replay while the engine opens
+    ///
+    /// Observers are called in the order they were added. See
+    /// [`RecoveryObserver`] for when progress is reported.
+    pub fn with_recovery_observer(mut self, observer: Arc<dyn RecoveryObserver>) -> Self {
+        self.recovery_observers.push(observer);
+        self
+    }
+
+    /// Sets how long pessimistic transactions wait for a lock
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.config.lock_timeout_ms = timeout.as_millis() as u64;
+        self
+    }
+
+    /// Sets the operator resolving [`merge`](super::StorageEngine::merge) operands
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    /// Sets the filter applied to versions rewritten by compaction
+    pub fn with_compaction_filter(mut self, filter: Arc<dyn CompactionFilter>) -> Self {
+        self.compaction_filter = Some(filter);
+        self
+    }
+
+    /// Makes values in the default column family expire `ttl` after being written
+    ///
+    /// Values can override it with [`put_with_ttl`](super::StorageEngine::put_with_ttl).
+    /// The setting isn't persisted, and values are stored differently with
+    /// a TTL: a database must always be reopened with one once it has one.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the clock that decides when values with a TTL expire and that
+    /// the physical part of write timestamps follows
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets how keys in the default column family are ordered
+    ///
+    /// Scans, range deletes and compaction follow the comparator's order.
+    /// It is recorded in every SSTable, and a database must always be
+    /// reopened with a comparator of the same name.
+    pub fn with_comparator(mut self, comparator: Arc<dyn Comparator>) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
+    /// Builds the default column family's bloom filters over the prefixes
+    /// `extractor` finds
+    ///
+    /// Iterators reading with [`ReadOptions::with_prefix`](super::ReadOptions::with_prefix)
+    /// skip the tables whose filter excludes their prefix. Each table
+    /// records the extractor's name; tables written with another one, or
+    /// none, are never skipped.
+    pub fn with_prefix_extractor(mut self, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+
+    /// Sets the options an existing column family is opened with
+    ///
+    /// The default column family is configured by these options
+    /// themselves; other families opened without a call to this use
+    /// [`ColumnFamilyOptions::default`].
+    pub fn with_column_family(
+        mut self,
+        name: impl Into<String>,
+        options: ColumnFamilyOptions,
+    ) -> Self {
+        self.column_families.insert(name.into(), options);
+        self
+    }
+
+    /// Opens the engine as a replica of another one
+    ///
+    /// A replica rejects ordinary writes and transactions; its data only
+    /// changes through [`write_replicated`](super::StorageEngine::write_replicated).
+    /// Reads, flushes, compactions and column family management work as
+    /// usual.
+    pub fn with_replica(mut self, replica: bool) -> Self {
+        self.replica = replica;
+        self
+    }
+
+    /// Makes a secondary engine catch up with its primary every `interval`
+    ///
+    /// Only engines opened with
+    /// [`open_as_secondary`](super::StorageEngine::open_as_secondary) use
+    /// it. A zero interval, the default, leaves catching up to
+    /// [`catch_up_with_primary`](super::StorageEngine::catch_up_with_primary).
+    pub fn with_catch_up_interval(mut self, interval: Duration) -> Self {
+        self.config.catch_up_interval_ms = interval.as_millis() as u64;
+        self
+    }
+
+    /// Offloads the tables of the deepest levels to `storage`
+    ///
+    /// Tables reaching a level at or below the
+    /// [remote level](Self::with_remote_level) are uploaded and removed
+    /// from the data directory; reading them fetches their blocks from
+    /// `storage`, keeping recent ones in a local cache. See the
+    /// [`remote`](crate::remote) module for how remote tables are found.
+    ///
+    /// Checkpoints, backups and repair need every table on the engine's
+    /// filesystem and are not supported, and direct reads don't apply to
+    /// remote tables.
+    pub fn with_remote_storage(mut self, storage: Arc<dyn RemoteStorage>) -> Self {
+        self.remote_storage = Some(storage);
+        self
+    }
+
+    /// Sets the shallowest level whose tables are offloaded, the deepest
+    /// one by default
+    pub fn with_remote_level(mut self, level: usize) -> Self {
+        self.config.remote_level = level;
+        self
+    }
+
+    /// Sets the bytes of remote tables cached locally
+    pub fn with_remote_cache_size(mut self, bytes: usize) -> Self {
+        self.config.remote_cache_size = bytes;
+        self
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::from_config(StorageConfig::default())
+    }
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("config", &self.config)
+            .field(
+                "merge_operator",
+                &self.merge_operator.as_ref().map(|op| op.name()),
+            )
+            .field(
+                "compaction_filter",
+                &self.compaction_filter.as_ref().map(|filter| filter.name()),
+            )
+            .field("ttl", &self.ttl)
+            .field("comparator", &self.comparator.name())
+            .field("prefix_extractor", &self.prefix_extractor)
+            .field("column_families", &self.column_families)
+            .field("replica", &self.replica)
+            .field("corruption_handler", &self.corruption_handler.is_some())
+            .field("event_listeners", &self.event_listeners.len())
+            .field("event_log", &self.event_log.is_some())
+            .field("recovery_observers", &self.recovery_observers.len())
+            .field("os_vfs", &vfs::is_os(&self.vfs))
+            .field("remote_storage", &self.remote_storage.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,511 @@
+//! Pessimistic transactions
+//!
+//! A [`PessimisticTransaction`] locks every key before touching it and
+//! holds the locks until it commits or rolls back (two-phase locking, see
+//! [`LockManager`](crate::lock_manager::LockManager)). Reads take shared
+//! locks, writes and [`get_for_update`](PessimisticTransaction::get_for_update)
+//! exclusive ones, and [`scan`](PessimisticTransaction::scan) a shared lock
+//! on the whole range, so no other transaction can insert into it either.
+//!
+//! Conflicts make the later transaction wait instead of failing at commit,
+//! which is what interactive clients used to an RDBMS expect. Waiting fails
+//! with `Error::Transaction` when it would deadlock or exceeds the lock
+//! timeout; the transaction should then be rolled back and retried.
+//!
+//! Because no other transaction can change what a locked read returned,
+//! reads see the latest committed data rather than a snapshot, and commit
+//! never fails with a conflict. Writes made through
+//! [`StorageEngine::put`](super::StorageEngine::put) and friends take no
+//! locks and are not held back by transactions.
+//!
+//! To take part in a distributed transaction, a pessimistic transaction
+//! [`prepare`](PessimisticTransaction::prepare)s under a name the
+//! coordinator picks, and commits or rolls back once told to, even after
+//! a restart. Optimistic transactions can't
+//! prepare, since nothing would stop a conflicting write between the
+//! prepare and the commit.
+
+use super::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use super::indexed_batch::WriteBatchWithIndex;
+use super::snapshot::owned_range;
+use super::write_options::WriteOptions;
+use super::{range_contains, EngineInner, KeyRange};
+use crate::lock_manager::{LockMode, LockOwner};
+use ferrisdb_core::{Error, Key, Result, Timestamp, Value};
+
+use std::fmt;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A transaction that locks what it reads and writes until it finishes
+///
+/// Created by [`StorageEngine::begin_pessimistic_transaction`](super::StorageEngine::begin_pessimistic_transaction).
+/// Writes stay private until [`commit`](Self::commit); dropping the
+/// transaction rolls it back. Either way its locks are released.
+///
+/// A transaction that has been [prepared](Self::prepare) only commits or
+/// rolls back: reading or writing through it fails. Dropping it keeps it
+/// prepared, locks included, to be picked up again through
+/// [`StorageEngine::prepared_transactions`](super::StorageEngine::prepared_transactions).
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// engine.put(b"seats".to_vec(), b"1".to_vec())?;
+///
+/// let mut txn = engine.begin_pessimistic_transaction();
+/// // Nobody else can read or write `seats` until this commits
+/// if txn.get_for_update(b"seats")? == Some(b"1".to_vec()) {
+///     txn.put(b"seats".to_vec(), b"0".to_vec())?;
+///     txn.put(b"booking:alice".to_vec(), b"1A".to_vec())?;
+/// }
+/// txn.commit()?;
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct PessimisticTransaction {
+    inner: Arc<EngineInner>,
+    owner: LockOwner,
+    batch: WriteBatchWithIndex,
+    /// Name the transaction was prepared under, if it was
+    prepared: Option<String>,
+}
+
+impl PessimisticTransaction {
+    pub(super) fn new(inner: Arc<EngineInner>) -> Self {
+        let owner = inner.locks.new_owner();
+        Self {
+            inner,
+            owner,
+            batch: WriteBatchWithIndex::default(),
+            prepared: None,
+        }
+    }
+
+    /// Creates the transaction standing for one prepared under `name`
+    /// that no other stands for
+    pub(super) fn recovered(inner: Arc<EngineInner>, owner: LockOwner, name: String) -> Self {
+        Self {
+            inner,
+            owner,
+            batch: WriteBatchWithIndex::default(),
+            prepared: Some(name),
+        }
+    }
+
+    /// Returns the id under which the transaction holds its locks
+    pub fn id(&self) -> LockOwner {
+        self.owner
+    }
+
+    /// Returns the name the transaction was prepared under, if it was
+    pub fn prepared_name(&self) -> Option<&str> {
+        self.prepared.as_deref()
+    }
+
+    /// Returns the value of a key after taking a shared lock on it
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Transaction` if the lock can't be acquired, or an
+    /// error if the engine was closed or the read fails.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        self.lock_key(key, LockMode::Shared)?;
+        self.read(key)
+    }
+
+    /// Returns the value of a key after taking an exclusive lock on it
+    ///
+    /// Use this for keys that are about to be written based on what was
+    /// read, so two transactions never both read before either writes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get).
+    pub fn get_for_update(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        self.lock_key(key, LockMode::Exclusive)?;
+        self.read(key)
+    }
+
+    /// Returns the key-value pairs in `range` after locking the range
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get`](Self::get).
+    pub fn scan<K, R>(&mut self, range: R) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let range = owned_range(range);
+        self.lock_range(&range, LockMode::Shared)?;
+
+        self.inner.check_open()?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        let read_ts = pin.timestamp();
+        let comparator = &*self.inner.default.comparator;
+        let mut results = self.inner.scan_at(&self.inner.default, &range, read_ts)?;
+
+        // Overlay the transaction's own writes
+        let mut written: Vec<&Key> = self
+            .batch
+            .keys(DEFAULT_COLUMN_FAMILY_ID)
+            .filter(|key| range_contains(comparator, &range, key))
+            .collect();
+        written.sort_by(|a, b| comparator.compare(a, b));
+        for key in written {
+            let position =
+                results.binary_search_by(|(existing, _)| comparator.compare(existing, key));
+            match (self.read_at(key, read_ts)?, position) {
+                (Some(value), Ok(i)) => results[i].1 = value,
+                (Some(value), Err(i)) => results.insert(i, (key.clone(), value)),
+                (None, Ok(i)) => {
+                    results.remove(i);
+                }
+                (None, Err(_)) => {}
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Sets the value of a key when the transaction commits
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Transaction` if the exclusive lock can't be acquired.
+    pub fn put(&mut self, key: Key, value: Value) -> Result<()> {
+        self.lock_key(&key, LockMode::Exclusive)?;
+        self.batch.put(key, value);
+        Ok(())
+    }
+
+    /// Deletes a key when the transaction commits
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Transaction` if the exclusive lock can't be acquired.
+    pub fn delete(&mut self, key: Key) -> Result<()> {
+        self.lock_key(&key, LockMode::Exclusive)?;
+        self.batch.delete(key);
+        Ok(())
+    }
+
+    /// Records a merge operand for a key when the transaction commits
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if no merge operator is configured,
+    /// or `Error::Transaction` if the exclusive lock can't be acquired.
+    pub fn merge(&mut self, key: Key, operand: Value) -> Result<()> {
+        if self.inner.options.merge_operator.is_none() {
+            return Err(Error::InvalidOperation(
+                "Merge requires a merge operator".to_string(),
+            ));
+        }
+        self.lock_key(&key, LockMode::Exclusive)?;
+        self.batch.merge(key, operand);
+        Ok(())
+    }
+
+    /// Durably logs the transaction's writes under `name`, the first
+    /// phase of a two-phase commit
+    ///
+    /// Once this returns, the transaction can be committed or rolled back
+    /// even after a crash: it keeps its locks, and after a restart it is
+    /// returned by [`StorageEngine::prepared_transactions`](super::StorageEngine::prepared_transactions).
+    /// The writes stay invisible until the commit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the transaction is prepared
+    /// already or another transaction is prepared under `name`,
+    /// `Error::InvalidArgument` if `name` is empty or longer than
+    /// [`MAX_TRANSACTION_NAME_SIZE`](crate::wal::MAX_TRANSACTION_NAME_SIZE)
+    /// bytes, or an error for the same reasons as
+    /// [`StorageEngine::put`](super::StorageEngine::put). The transaction
+    /// isn't prepared then.
+    pub fn prepare(&mut self, name: &str) -> Result<()> {
+        self.check_active()?;
+        self.inner
+            .prepare(name, self.owner, self.batch.batch().clone())?;
+        self.batch.clear();
+        self.prepared = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Atomically applies the transaction's writes and releases its locks
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as
+    /// [`StorageEngine::put`](super::StorageEngine::put); nothing is
+    /// written then. The locks are released regardless, unless the
+    /// transaction was prepared: it stays prepared then.
+    pub fn commit(mut self) -> Result<()> {
+        if let Some(name) = &self.prepared {
+            self.inner.commit_prepared(name)?;
+            self.prepared = None;
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch).into_batch();
+        self.inner
+            .write_batch(batch, &WriteOptions::default(), || Ok(()))
+    }
+
+    /// Discards the transaction's writes and releases its locks
+    ///
+    /// Equivalent to dropping the transaction, unless it was prepared:
+    /// rolling back is then logged, so it survives a crash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rollback of a prepared transaction can't be
+    /// logged. It stays prepared then.
+    pub fn rollback(mut self) -> Result<()> {
+        if let Some(name) = &self.prepared {
+            self.inner.rollback_prepared(name)?;
+            self.prepared = None;
+        }
+        Ok(())
+    }
+
+    fn lock_timeout(&self) -> Duration {
+        Duration::from_millis(self.inner.options.config.lock_timeout_ms)
+    }
+
+    /// Fails once the transaction is prepared
+    fn check_active(&self) -> Result<()> {
+        match &self.prepared {
+            Some(name) => Err(Error::InvalidOperation(format!(
+                "Transaction {:?} is prepared and can only commit or roll back",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn lock_key(&self, key: &[u8], mode: LockMode) -> Result<()> {
+        self.check_active()?;
+        self.inner.check_open()?;
+        self.inner
+            .locks
+            .lock_key(self.owner, key, mode, self.lock_timeout())
+    }
+
+    fn lock_range(&self, range: &KeyRange, mode: LockMode) -> Result<()> {
+        self.check_active()?;
+        self.inner.check_open()?;
+        self.inner
+            .locks
+            .lock(self.owner, range.clone(), mode, self.lock_timeout())
+    }
+
+    /// Reads the latest committed value of a locked key
+    fn read(&self, key: &[u8]) -> Result<Option<Value>> {
+        self.inner.check_open()?;
+        let pin = self.inner.snapshots.pin(&self.inner.oracle);
+        self.read_at(key, pin.timestamp())
+    }
+
+    /// Reads a key as of `read_ts` with the transaction's writes on top
+    fn read_at(&self, key: &[u8], read_ts: Timestamp) -> Result<Option<Value>> {
+        self.batch
+            .get_at(&self.inner, &self.inner.default, key, read_ts)
+    }
+}
+
+impl Drop for PessimisticTransaction {
+    fn drop(&mut self) {
+        match &self.prepared {
+            // Prepared transactions keep their locks until they finish
+            Some(name) => self.inner.unclaim_prepared(name),
+            None => self.inner.locks.release_all(self.owner),
+        }
+    }
+}
+
+impl fmt::Debug for PessimisticTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PessimisticTransaction")
+            .field("id", &self.owner)
+            .field("batch", &self.batch)
+            .field("prepared", &self.prepared)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use ferrisdb_core::Error;
+    use tempfile::TempDir;
+
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn open(dir: &TempDir, lock_timeout: Duration) -> Arc<StorageEngine> {
+        Arc::new(
+            StorageEngine::open(Options::new(dir.path()).with_lock_timeout(lock_timeout)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_reads_see_own_writes_and_latest_commits() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, Duration::from_millis(50));
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+
+        let mut txn = engine.begin_pessimistic_transaction();
+        txn.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        txn.delete(b"b".to_vec()).unwrap();
+        txn.put(b"c".to_vec(), b"2".to_vec()).unwrap();
+        engine.put(b"d".to_vec(), b"1".to_vec()).unwrap();
+
+        assert_eq!(txn.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(
+            txn.scan::<[u8], _>(..).unwrap(),
+            vec![
+                (b"a".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"2".to_vec()),
+                (b"d".to_vec(), b"1".to_vec()),
+            ]
+        );
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+
+        txn.commit().unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_conflicting_transaction_waits_for_commit() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, Duration::from_secs(10));
+        engine.put(b"n".to_vec(), b"0".to_vec()).unwrap();
+
+        let mut first = engine.begin_pessimistic_transaction();
+        assert_eq!(first.get_for_update(b"n").unwrap(), Some(b"0".to_vec()));
+
+        let second = {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                let mut txn = engine.begin_pessimistic_transaction();
+                let seen = txn.get_for_update(b"n").unwrap();
+                txn.put(b"n".to_vec(), b"2".to_vec()).unwrap();
+                txn.commit().unwrap();
+                seen
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        first.put(b"n".to_vec(), b"1".to_vec()).unwrap();
+        first.commit().unwrap();
+
+        // The second transaction only read after the first committed
+        assert_eq!(second.join().unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get(b"n").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_lock_wait_times_out_and_rollback_releases_locks() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, Duration::from_millis(20));
+
+        let mut first = engine.begin_pessimistic_transaction();
+        first.scan(b"a".as_slice()..b"m".as_slice()).unwrap();
+
+        let mut second = engine.begin_pessimistic_transaction();
+        assert!(matches!(
+            second.put(b"f".to_vec(), b"1".to_vec()),
+            Err(Error::Transaction(_))
+        ));
+        second.put(b"x".to_vec(), b"1".to_vec()).unwrap();
+
+        first.rollback().unwrap();
+        second.put(b"f".to_vec(), b"1".to_vec()).unwrap();
+        second.commit().unwrap();
+        assert_eq!(engine.get(b"f").unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn test_prepared_transactions_survive_a_restart() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, Duration::from_millis(20));
+        engine.put(b"a".to_vec(), b"0".to_vec()).unwrap();
+
+        let mut first = engine.begin_pessimistic_transaction();
+        first.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        first.prepare("xid-1").unwrap();
+        assert_eq!(first.prepared_name(), Some("xid-1"));
+        assert!(matches!(first.get(b"a"), Err(Error::InvalidOperation(_))));
+        let mut second = engine.begin_pessimistic_transaction();
+        second.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        assert!(second.prepare("xid-1").is_err());
+        second.prepare("xid-2").unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"0".to_vec()));
+        drop((first, second));
+        drop(engine);
+
+        let engine = open(&dir, Duration::from_millis(20));
+        assert_eq!(engine.recovery_report().transactions_prepared, 2);
+        let mut prepared = engine.prepared_transactions();
+        assert_eq!(prepared.len(), 2);
+        assert!(engine.prepared_transactions().is_empty());
+
+        // The recovered transactions hold their locks again
+        let mut other = engine.begin_pessimistic_transaction();
+        assert!(matches!(other.get(b"a"), Err(Error::Transaction(_))));
+        drop(other);
+
+        let second = prepared.pop().unwrap();
+        assert_eq!(second.prepared_name(), Some("xid-2"));
+        second.rollback().unwrap();
+        prepared.pop().unwrap().commit().unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+        let mut other = engine.begin_pessimistic_transaction();
+        other.put(b"a".to_vec(), b"3".to_vec()).unwrap();
+        drop(other);
+
+        drop(engine);
+        let engine = open(&dir, Duration::from_millis(20));
+        assert_eq!(engine.recovery_report().transactions_prepared, 0);
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_prepare_records_outlive_flushed_segments() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir, Duration::from_millis(20));
+        let mut txn = engine.begin_pessimistic_transaction();
+        txn.put(b"key".to_vec(), b"prepared".to_vec()).unwrap();
+        txn.prepare("xid").unwrap();
+        drop(txn);
+
+        // Each flush deletes the segment logged before it
+        for i in 0..3 {
+            engine.put(vec![i], b"v".to_vec()).unwrap();
+            engine.flush().unwrap();
+        }
+        drop(engine);
+
+        let engine = open(&dir, Duration::from_millis(20));
+        let mut tailer = engine.tail_wal(engine.last_timestamp() + 1).unwrap();
+        let txn = engine.prepared_transactions().pop().unwrap();
+        txn.commit().unwrap();
+        assert_eq!(engine.get(b"key").unwrap(), Some(b"prepared".to_vec()));
+
+        // Tailers only see the committed writes
+        let entries = tailer.poll().unwrap().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].timestamp, engine.last_timestamp());
+        assert!(tailer.poll().unwrap().is_none());
+    }
+}
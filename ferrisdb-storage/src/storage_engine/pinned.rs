@@ -0,0 +1,231 @@
+//! Values read without copying
+//!
+//! [`get`](super::StorageEngine::get) copies a value found in a table out
+//! of its decoded block. [`get_pinned`](super::StorageEngine::get_pinned)
+//! instead returns a [`PinnedSlice`] that borrows it from the block,
+//! keeping the block in memory until the slice is dropped:
+//!
+//! ```text
+//!  get_pinned ─▶ MemTables ── found ──▶ PinnedSlice (copied value)
+//!                   │
+//!                   └─ not found ─▶ SSTable block ─▶ PinnedSlice (pins the block)
+//! ```
+//!
+//! Values that have to be computed, from merge operands or by stripping
+//! the expiry of a TTL family, are returned owned. Either way the slice
+//! derefs to the value's bytes.
+
+use super::column_family::ColumnFamilyData;
+use super::EngineInner;
+use crate::sstable::PinnedEntry;
+use ferrisdb_core::{Operation, Result, Timestamp, Value};
+
+use std::fmt;
+use std::ops::Deref;
+
+/// A value that may borrow the SSTable block it was read from
+///
+/// Holding a slice keeps its whole block in memory, so long-lived values
+/// are better copied out with [`to_vec`](slice::to_vec) or
+/// [`into_vec`](Self::into_vec).
+pub struct PinnedSlice {
+    repr: Repr,
+}
+
+enum Repr {
+    Owned(Value),
+    Pinned(PinnedEntry),
+}
+
+impl PinnedSlice {
+    /// Returns true if the value borrows its block instead of owning a copy
+    pub fn is_pinned(&self) -> bool {
+        matches!(self.repr, Repr::Pinned(_))
+    }
+
+    /// Returns the value, copying it only if it is pinned
+    pub fn into_vec(self) -> Value {
+        match self.repr {
+            Repr::Owned(value) => value,
+            Repr::Pinned(entry) => entry.entry().value.clone(),
+        }
+    }
+}
+
+impl From<Value> for PinnedSlice {
+    fn from(value: Value) -> Self {
+        Self {
+            repr: Repr::Owned(value),
+        }
+    }
+}
+
+impl Deref for PinnedSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Owned(value) => value,
+            Repr::Pinned(entry) => &entry.entry().value,
+        }
+    }
+}
+
+impl AsRef<[u8]> for PinnedSlice {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PartialEq<[u8]> for PinnedSlice {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl fmt::Debug for PinnedSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinnedSlice")
+            .field("pinned", &self.is_pinned())
+            .field("value", &&**self)
+            .finish()
+    }
+}
+
+impl EngineInner {
+    /// Returns the value of a key visible at `read_ts`, borrowing it from
+    /// its block when it comes from a table
+    pub(super) fn get_pinned_at(
+        &self,
+        cf: &ColumnFamilyData,
+        key: &[u8],
+        read_ts: Timestamp,
+    ) -> Result<Option<PinnedSlice>> {
+        // Merged and expiring values are computed, not stored
+        if cf.merge_operator.is_some() || cf.ttl.is_some() {
+            return Ok(self.get_at(cf, key, read_ts)?.map(PinnedSlice::from));
+        }
+
+        // MemTables before the version, as in `versions_at`
+        let memtables = self.memtables.read().newest_first(cf.id);
+        let version = cf.versions.current();
+
+        for memtable in &memtables {
+            if let Some((value, operation)) = memtable.get(key, read_ts) {
+                return latest_value(operation, || PinnedSlice::from(value));
+            }
+        }
+
+        // Tables may be ordered differently from their data, so the newest
+        // version is the one with the highest timestamp
+        let mut newest: Option<PinnedEntry> = None;
+        for table in version.tables_for_key(key) {
+            let mut reader = cf.open_table(table)?;
+            let found = reader.get_latest_pinned(key, read_ts)?;
+            self.counters.record_reads(&reader);
+            if let Some(found) = found {
+                let timestamp = found.entry().key.timestamp;
+                if newest
+                    .as_ref()
+                    .map_or(true, |newest| newest.entry().key.timestamp < timestamp)
+                {
+                    newest = Some(found);
+                }
+            }
+        }
+
+        match newest {
+            Some(entry) => latest_value(entry.entry().operation, || PinnedSlice {
+                repr: Repr::Pinned(entry),
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Replaces the contents of `buffer` with `value`, returning whether
+/// there was one
+pub(super) fn copy_into(value: Option<PinnedSlice>, buffer: &mut Vec<u8>) -> bool {
+    buffer.clear();
+    match value {
+        Some(value) => {
+            buffer.extend_from_slice(&value);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the value of a key whose newest version has `operation`, in a
+/// family without a merge operator
+fn latest_value(
+    operation: Operation,
+    value: impl FnOnce() -> PinnedSlice,
+) -> Result<Option<PinnedSlice>> {
+    match operation {
+        Operation::Put => Ok(Some(value())),
+        Operation::Delete => Ok(None),
+        Operation::Merge => Err(super::missing_merge_operator()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_pinned_values_match_get_wherever_they_live() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"flushed".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"gone".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"flushed".to_vec(), b"table".to_vec()).unwrap();
+        engine.delete(b"gone".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"fresh".to_vec(), b"memtable".to_vec()).unwrap();
+
+        let value = engine.get_pinned(b"flushed").unwrap().unwrap();
+        assert!(value.is_pinned());
+        assert_eq!(&*value, b"table");
+        let value = engine.get_pinned(b"fresh").unwrap().unwrap();
+        assert!(!value.is_pinned());
+        assert_eq!(value.into_vec(), b"memtable".to_vec());
+        assert!(engine.get_pinned(b"gone").unwrap().is_none());
+        assert!(engine.get_pinned(b"missing").unwrap().is_none());
+
+        // The block outlives compactions deleting its table
+        let value = engine.get_pinned(b"flushed").unwrap().unwrap();
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(&*value, b"table");
+
+        let mut buffer = b"leftover bytes".to_vec();
+        assert!(engine.get_into(b"flushed", &mut buffer).unwrap());
+        assert_eq!(buffer, b"table");
+        assert!(!engine.get_into(b"gone", &mut buffer).unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_computed_values_are_returned_owned() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path()).with_merge_operator(Arc::new(U64AddOperator)),
+        )
+        .unwrap();
+        for n in [1u64, 2] {
+            engine
+                .merge(b"n".to_vec(), n.to_le_bytes().to_vec())
+                .unwrap();
+        }
+        engine.flush().unwrap();
+
+        let value = engine.get_pinned(b"n").unwrap().unwrap();
+        assert!(!value.is_pinned());
+        assert_eq!(&*value, 3u64.to_le_bytes());
+    }
+}
@@ -0,0 +1,207 @@
+//! Two-phase commit of pessimistic transactions
+//!
+//! An external coordinator running a distributed transaction asks every
+//! participant to [`prepare`](super::PessimisticTransaction::prepare)
+//! first, and only once all of them have does it tell them to commit;
+//! should any fail, it tells them to roll back. A participant that has
+//! prepared must be able to do either, even after a crash, so preparing
+//! logs the transaction's writes under its name and keeps its locks:
+//!
+//! ```text
+//!  txn.put(..) ──▶ prepare("xid") ──▶ WAL: Prepare(xid, writes)      locks held
+//!                        │
+//!          crash, reopen ┼─▶ prepared_transactions() ──▶ txn "xid"   locks taken again
+//!                        ▼
+//!  commit() ──▶ WAL: Commit(xid, writes at ts N..) ──▶ MemTable, locks released
+//!  rollback() ─▶ WAL: Rollback(xid) ─────────────────────────────▶ locks released
+//! ```
+//!
+//! Timestamps are only assigned at commit, so a prepared transaction's
+//! writes are logged once more with them in the commit record, which is
+//! what recovery replays and replicas receive. Prepare records stay in the
+//! segment they were logged in, so every switch to a new segment logs the
+//! records of the transactions still prepared again; flushing the old
+//! segment away then loses none of them. Entries of
+//! [secondary indexes](super::SecondaryIndex) are only added at commit,
+//! from the values current then.
+
+use super::batch::{self, WriteBatch};
+use super::write_options::WriteOptions;
+use super::EngineInner;
+use crate::lock_manager::{LockMode, LockOwner};
+use crate::wal::{WALEntry, WALWriter};
+use ferrisdb_core::{Error, Result, SyncMode};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Writes of prepared transactions by name, timestamped with their
+/// position in the transaction
+pub(super) type PreparedWrites = BTreeMap<String, Vec<WALEntry>>;
+
+/// A transaction prepared but not yet committed or rolled back
+#[derive(Debug)]
+pub(super) struct Prepared {
+    /// The transaction's writes, timestamped with their position
+    entries: Vec<WALEntry>,
+    /// Id under which the transaction holds its locks
+    owner: LockOwner,
+    /// Whether a [`PessimisticTransaction`](super::PessimisticTransaction)
+    /// stands for the transaction
+    claimed: bool,
+}
+
+impl EngineInner {
+    /// Logs the writes of the transaction holding locks as `owner` under
+    /// `name`, to be committed or rolled back later
+    ///
+    /// The prepare record is synced whatever the sync mode.
+    pub(super) fn prepare(&self, name: &str, owner: LockOwner, batch: WriteBatch) -> Result<()> {
+        self.check_open()?;
+        if self.options.replica {
+            return Err(Error::InvalidOperation(
+                "The engine is a replica and only accepts replicated writes".to_string(),
+            ));
+        }
+        let mut wal = self.lock_writer()?;
+        self.background_error(&self.background.lock())?;
+        if self.prepared.lock().contains_key(name) {
+            return Err(Error::InvalidOperation(format!(
+                "A transaction named {:?} is already prepared",
+                name
+            )));
+        }
+        let mut entries = self.expand_range_deletes(batch)?;
+        // Writes indexes can't follow fail here rather than at commit
+        self.index_updates(entries.iter().map(|entry| {
+            (
+                entry.column_family,
+                entry.operation,
+                &entry.key,
+                &entry.value,
+            )
+        }))?;
+        self.stamp_expiry(&mut entries)?;
+        let entries = batch::into_wal_entries(entries, 0)?;
+        // The writes need as much room at commit
+        self.make_room(&mut wal, &entries)?;
+        wal.append_prepare(name, &entries, SyncMode::Full)?;
+        self.prepared.lock().insert(
+            name.to_string(),
+            Prepared {
+                entries,
+                owner,
+                claimed: true,
+            },
+        );
+        Ok(())
+    }
+
+    /// Applies the writes of prepared transaction `name` at the next
+    /// timestamps and forgets it
+    pub(super) fn commit_prepared(&self, name: &str) -> Result<()> {
+        self.check_open()?;
+        let mut wal = self.lock_writer()?;
+        self.background_error(&self.background.lock())?;
+        let mut entries = self.prepared_entries(name)?;
+        // Index entries follow the values current at commit
+        let updates = self.index_updates(entries.iter().map(|entry| {
+            (
+                entry.column_family,
+                entry.operation,
+                &entry.key,
+                &entry.value,
+            )
+        }))?;
+        entries.extend(batch::into_wal_entries(updates, 0)?);
+        let first = self.oracle.next();
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.timestamp = first + i as u64;
+        }
+        self.log_and_apply(&mut wal, entries, &WriteOptions::default(), Some(name))?;
+        self.prepared.lock().remove(name);
+        Ok(())
+    }
+
+    /// Discards the writes of prepared transaction `name` and forgets it
+    pub(super) fn rollback_prepared(&self, name: &str) -> Result<()> {
+        self.check_open()?;
+        let wal = self.lock_writer()?;
+        self.prepared_entries(name)?;
+        wal.append_rollback(name, self.options.config.wal_sync_mode)?;
+        self.prepared.lock().remove(name);
+        Ok(())
+    }
+
+    /// Hands the prepared transactions no
+    /// [`PessimisticTransaction`](super::PessimisticTransaction) stands
+    /// for to new ones, returning their names and lock owners
+    pub(super) fn claim_prepared(&self) -> Vec<(String, LockOwner)> {
+        let mut prepared = self.prepared.lock();
+        prepared
+            .iter_mut()
+            .filter(|(_, prepared)| !prepared.claimed)
+            .map(|(name, prepared)| {
+                prepared.claimed = true;
+                (name.clone(), prepared.owner)
+            })
+            .collect()
+    }
+
+    /// Leaves prepared transaction `name` to be claimed again, once the
+    /// transaction standing for it is dropped
+    pub(super) fn unclaim_prepared(&self, name: &str) {
+        if let Some(prepared) = self.prepared.lock().get_mut(name) {
+            prepared.claimed = false;
+        }
+    }
+
+    /// Logs the prepare records of every prepared transaction in `wal`, a
+    /// segment just started
+    pub(super) fn relog_prepared(&self, wal: &WALWriter) -> Result<()> {
+        let prepared = self.prepared.lock();
+        if prepared.is_empty() {
+            return Ok(());
+        }
+        for (name, prepared) in prepared.iter() {
+            wal.append_prepare(name, &prepared.entries, SyncMode::None)?;
+        }
+        wal.sync()
+    }
+
+    /// Registers the transactions recovery found prepared, each locking
+    /// its keys again under a fresh owner
+    ///
+    /// Recovery has logged them in the segment the engine writes already.
+    pub(super) fn restore_prepared(&self, prepared: PreparedWrites) -> Result<()> {
+        let mut restored = self.prepared.lock();
+        for (name, entries) in prepared {
+            let owner = self.locks.new_owner();
+            for entry in &entries {
+                // Nothing else holds locks yet
+                self.locks
+                    .lock_key(owner, &entry.key, LockMode::Exclusive, Duration::ZERO)?;
+            }
+            restored.insert(
+                name,
+                Prepared {
+                    entries,
+                    owner,
+                    claimed: false,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the writes of prepared transaction `name`
+    fn prepared_entries(&self, name: &str) -> Result<Vec<WALEntry>> {
+        self.prepared
+            .lock()
+            .get(name)
+            .map(|prepared| prepared.entries.clone())
+            .ok_or_else(|| {
+                Error::InvalidOperation(format!("No transaction named {:?} is prepared", name))
+            })
+    }
+}
@@ -0,0 +1,167 @@
+//! Options for reading from a storage engine
+
+use super::snapshot::Snapshot;
+use ferrisdb_core::{Key, Timestamp};
+
+use std::ops::{Bound, RangeBounds};
+use std::time::Duration;
+
+/// Settings for a read through [`StorageEngine::iter`](super::StorageEngine::iter)
+///
+/// By default a read sees every write committed when it starts, across
+/// the whole key space, and may take as long as it needs. Settings not
+/// changed through a `with_*` method keep their defaults.
+///
+/// Limits on the entries an iterator steps over, the bytes it reads and
+/// the time it takes protect a server from scans that would otherwise
+/// wade through millions of deleted or invisible versions. An iterator
+/// that reaches one fails with [`Error::ScanLimitExceeded`] and is no
+/// longer valid. The counts add up over the iterator's whole life, so
+/// seeking again doesn't reset them.
+///
+/// [`Error::ScanLimitExceeded`]: ferrisdb_core::Error::ScanLimitExceeded
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{ReadOptions, StorageEngine, Options};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// let snapshot = engine.snapshot();
+///
+/// let options = ReadOptions::new()
+///     .with_snapshot(&snapshot)
+///     .with_lower_bound(b"user:".to_vec())
+///     .with_upper_bound(b"user;".to_vec());
+/// let iter = engine.iter(options)?;
+/// assert!(!iter.valid());
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions<'a> {
+    pub(super) snapshot: Option<&'a Snapshot>,
+    /// Smallest key the read sees, inclusive
+    pub(super) lower_bound: Option<Key>,
+    /// Key the read stops before, exclusive
+    pub(super) upper_bound: Option<Key>,
+    /// Prefix every key the read sees starts with
+    pub(super) prefix: Option<Key>,
+    /// Smallest and largest timestamp of the versions the read sees, both
+    /// inclusive
+    pub(super) timestamp_range: Option<(Timestamp, Timestamp)>,
+    /// Most entries an iterator may step over, every version and
+    /// tombstone included
+    pub(super) max_keys_scanned: Option<u64>,
+    /// Most bytes of keys and values an iterator may step over
+    pub(super) max_bytes_read: Option<u64>,
+    /// Time on the engine's clock, since the Unix epoch, after which an
+    /// iterator stops
+    pub(super) deadline: Option<Duration>,
+    /// How long an iterator may run from its creation
+    pub(super) timeout: Option<Duration>,
+}
+
+impl<'a> ReadOptions<'a> {
+    /// Creates options reading the latest data across all keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads as of a snapshot instead of the latest committed write
+    pub fn with_snapshot(mut self, snapshot: &'a Snapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Hides keys before `key`; `key` itself is visible
+    pub fn with_lower_bound(mut self, key: Key) -> Self {
+        self.lower_bound = Some(key);
+        self
+    }
+
+    /// Hides `key` and every key after it
+    ///
+    /// Iterators stop at the bound instead of reading on through the
+    /// rest of the tables.
+    pub fn with_upper_bound(mut self, key: Key) -> Self {
+        self.upper_bound = Some(key);
+        self
+    }
+
+    /// Hides every key not starting with `prefix`
+    ///
+    /// Narrows the bounds to the keys with the prefix, so iterators stop
+    /// right after the last of them. When `prefix` is a whole prefix of
+    /// the column family's [`PrefixExtractor`](crate::prefix::PrefixExtractor),
+    /// tables whose bloom filter shows they hold none of it aren't read at
+    /// all, making a scan cost about as much as the keys it returns.
+    ///
+    /// Only supported in column families with the bytewise comparator,
+    /// where the keys with a prefix are contiguous.
+    pub fn with_prefix(mut self, prefix: Key) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Hides every version written outside `range` of timestamps
+    ///
+    /// Each key shows its newest version written within the range, or
+    /// nothing if it has none there or that version is a deletion, which
+    /// answers what a range of writes changed. The read still sees
+    /// nothing newer than its snapshot or start.
+    ///
+    /// Tables record the timestamps of their entries, and tables holding
+    /// none in the range aren't read at all, so a read of recent writes
+    /// skips the bulk of older data. Merge operands within the range are
+    /// resolved without the versions before it.
+    pub fn with_timestamp_range(mut self, range: impl RangeBounds<Timestamp>) -> Self {
+        let min = match range.start_bound() {
+            Bound::Included(&ts) => ts,
+            Bound::Excluded(&ts) => ts.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        // An excluded 0 leaves the range empty, as min exceeds max
+        let (min, max) = match range.end_bound() {
+            Bound::Included(&ts) => (min, ts),
+            Bound::Excluded(&0) => (1, 0),
+            Bound::Excluded(&ts) => (min, ts - 1),
+            Bound::Unbounded => (min, Timestamp::MAX),
+        };
+        self.timestamp_range = Some((min, max));
+        self
+    }
+
+    /// Fails an iterator that steps over more than `count` entries
+    ///
+    /// Every version, tombstone and invisible entry counts, not just the
+    /// keys returned, since those are what make a scan expensive.
+    pub fn with_max_keys_scanned(mut self, count: u64) -> Self {
+        self.max_keys_scanned = Some(count);
+        self
+    }
+
+    /// Fails an iterator that steps over more than `bytes` of keys and
+    /// values
+    pub fn with_max_bytes_read(mut self, bytes: u64) -> Self {
+        self.max_bytes_read = Some(bytes);
+        self
+    }
+
+    /// Fails an iterator that is still moving at `deadline`, the time
+    /// since the Unix epoch on the engine's [`Clock`](crate::clock::Clock)
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fails an iterator that is still moving `timeout` after it was
+    /// created
+    ///
+    /// With a [deadline](Self::with_deadline) as well, the earlier of the
+    /// two applies.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
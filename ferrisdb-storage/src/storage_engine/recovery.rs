@@ -0,0 +1,1005 @@
+//! Crash recovery: replaying unflushed WAL segments on open
+//!
+//! The MANIFEST records which tables are live and the log number: WAL
+//! segments numbered below it were flushed before the engine stopped.
+//! Everything at or above it is replayed, oldest segment first:
+//!
+//! ```text
+//!  MANIFEST: log_number = 12, last_timestamp = 4000
+//!
+//!  000009.wal  (flushed, deleted)
+//!  000012.wal  after 4000, ts 4001..=4730  ──┐
+//!  000015.wal  after 4730, ts 4731..=4802  ──┼─▶ fresh MemTable ──▶ level 0 tables
+//!                             └─ torn tail ──┘    (flushed when full and at the end)
+//! ```
+//!
+//! Timestamps only increase, so the entries of a segment must come after
+//! each other. Entries no newer than the MANIFEST's last timestamp, or
+//! than those replayed from earlier segments, were already applied and
+//! are skipped: replay is idempotent where segments overlap each other or
+//! the tables, as segments copied by a checkpoint during a rotation can.
+//! Timestamps have gaps, so each segment's header records the last write
+//! before it; a segment continuing from a write that wasn't replayed
+//! means one in the middle is missing, and recovery fails instead of
+//! silently losing the writes in between.
+//!
+//! With several column families, each family's MANIFEST has a log number
+//! and last timestamp of its own, since a crash in the middle of a flush
+//! can leave some families behind. Replay starts at the oldest segment
+//! any family still needs, and inserts an entry only into a family that
+//! hasn't persisted its timestamp yet. Entries of dropped families are
+//! skipped.
+//!
+//! A crash can leave the newest segment with a partially written final
+//! entry. Replay stops at the first entry of that segment that cannot be
+//! read and truncates the file there. Damage in any older segment is an
+//! error, since those were synced before the next segment was started.
+//! Either way, entries that fail their checksum or don't decode are added
+//! to the engine's
+//! [corruption reports](super::StorageEngine::corruption_reports).
+//!
+//! Once the replayed tables are installed, tables no MANIFEST refers to
+//! and leftover temporary files are deleted as orphans (see
+//! [`files::collect_garbage`]).
+//!
+//! Transactions prepared for a two-phase commit but neither committed nor
+//! rolled back are collected as replay goes, and their prepare records
+//! are logged again in the segment the engine goes on writing before the
+//! old segments are retired, so they wait for their outcome across any
+//! number of restarts.
+//!
+//! Replaying a large WAL can take a while. A [`RecoveryObserver`]
+//! registered with [`Options::with_recovery_observer`] is told how far
+//! replay got as it goes, so a server can report its progress towards
+//! being ready.
+
+use super::column_family::ColumnFamilyData;
+use super::corruption::CorruptionLog;
+use super::prepared::PreparedWrites;
+use super::{retire_wal_segment, write_table, Options};
+use crate::files::{self, wal_file_name, FileType, GarbageReport};
+use crate::manifest::{SSTableMeta, VersionEdit, NUM_LEVELS};
+use crate::memtable::MemTable;
+use crate::vfs::Vfs;
+pub(super) use crate::wal::wal_segments_in;
+use crate::wal::{TransactionMarker, WALEntry, WALReader, WALWriter};
+use ferrisdb_core::{Error, Operation, Result, SyncMode, Timestamp};
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What [`StorageEngine::open`](super::StorageEngine::open) recovered
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Number of WAL segments replayed
+    pub segments_replayed: usize,
+    /// Replayed segments that were closed cleanly and so read without
+    /// verifying their checksums
+    pub segments_sealed: usize,
+    /// Number of WAL entries replayed
+    pub entries_replayed: u64,
+    /// WAL entries skipped as already applied: persisted by their column
+    /// family according to its MANIFEST, or replayed from an earlier
+    /// segment overlapping theirs
+    pub entries_skipped: u64,
+    /// Number of level 0 tables written from the replayed entries
+    pub tables_written: usize,
+    /// Bytes cut off the newest segment after a torn or corrupted entry
+    pub truncated_bytes: u64,
+    /// Transactions found prepared but neither committed nor rolled back,
+    /// see [`StorageEngine::prepared_transactions`](super::StorageEngine::prepared_transactions)
+    pub transactions_prepared: usize,
+    /// Highest timestamp in use once recovery finished
+    pub last_timestamp: Timestamp,
+    /// Files no MANIFEST refers to, deleted unless
+    /// [`orphan_files_dry_run`](crate::StorageConfig::orphan_files_dry_run)
+    /// is set
+    pub orphans: GarbageReport,
+    /// Time spent replaying and flushing
+    pub duration: Duration,
+}
+
+impl RecoveryReport {
+    /// Returns true if the newest segment ended in a torn or corrupted entry
+    pub fn truncated_tail(&self) -> bool {
+        self.truncated_bytes > 0
+    }
+}
+
+/// How far WAL replay has got
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// Number of the segment being replayed
+    pub segment: u64,
+    /// Path of the segment being replayed
+    pub segment_path: PathBuf,
+    /// Segments replayed in full
+    pub segments_done: usize,
+    /// Segments to replay in all
+    pub segments_total: usize,
+    /// Bytes of the segments read so far
+    pub bytes_processed: u64,
+    /// Size of all segments to replay, as they were when replay started
+    pub bytes_total: u64,
+    /// Entries replayed so far
+    pub entries_replayed: u64,
+}
+
+impl RecoveryProgress {
+    /// Returns the fraction of the WAL bytes read so far, 1 once replay
+    /// is done or if there was nothing to replay
+    pub fn fraction_done(&self) -> f64 {
+        if self.bytes_total == 0 {
+            return 1.0;
+        }
+        (self.bytes_processed as f64 / self.bytes_total as f64).min(1.0)
+    }
+}
+
+/// Receives the progress of WAL replay while an engine opens
+///
+/// Progress is reported as each segment starts and ends, and every
+/// megabyte read in between. The observer runs on the thread opening the
+/// engine, which waits for it.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::storage_engine::{Options, RecoveryObserver, RecoveryProgress, StorageEngine};
+/// use std::sync::Arc;
+///
+/// struct LogProgress;
+///
+/// impl RecoveryObserver for LogProgress {
+///     fn on_progress(&self, progress: &RecoveryProgress) {
+///         println!(
+///             "Replaying WAL segment {} of {}: {:.0}% done",
+///             progress.segments_done + 1,
+///             progress.segments_total,
+///             progress.fraction_done() * 100.0
+///         );
+///     }
+/// }
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()).with_recovery_observer(Arc::new(LogProgress)))?;
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub trait RecoveryObserver: Send + Sync {
+    /// Called with the progress so far
+    fn on_progress(&self, progress: &RecoveryProgress);
+}
+
+/// Bytes read from a segment between progress reports
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// What damage found while replaying is reported as detected by
+const REPLAY: &str = "recovery";
+
+/// Replays unflushed WAL segments into level 0 and retires all segments
+///
+/// Retired segments are deleted, or moved to the WAL archive if configured.
+///
+/// Replayed writes are flushed right away, so the recovered engine starts
+/// with empty MemTables and `wal_number` as the only live segment. It
+/// holds the prepare records of the transactions still prepared, which
+/// are returned with the report.
+pub(super) fn recover(
+    options: &Options,
+    families: &BTreeMap<u32, Arc<ColumnFamilyData>>,
+    segments: &[(u64, PathBuf)],
+    wal_number: u64,
+) -> Result<(RecoveryReport, PreparedWrites)> {
+    let start = Instant::now();
+    let (mut report, _, prepared) = replay(options, families, segments, wal_number, false)?;
+
+    for (_, path) in segments {
+        retire_wal_segment(options, path);
+    }
+
+    report.orphans = collect_orphans(options, families)?;
+    report.duration = start.elapsed();
+    if report.entries_replayed > 0 || report.truncated_tail() {
+        log::info!(
+            "Recovered {} WAL entries from {} segments in {:?}, truncated {} bytes",
+            report.entries_replayed,
+            report.segments_replayed,
+            report.duration,
+            report.truncated_bytes
+        );
+    }
+    Ok((report, prepared))
+}
+
+/// Replays what can still be read of every segment into level 0
+///
+/// Unlike [`recover`], damage in any segment and segments missing in
+/// between are tolerated: a segment is replayed up to its first damaged
+/// entry, and entries older than those already replayed are skipped.
+/// Returns the numbers of the damaged segments along with the report;
+/// retiring the segments is left to the caller.
+pub(super) fn salvage(
+    options: &Options,
+    families: &BTreeMap<u32, Arc<ColumnFamilyData>>,
+    segments: &[(u64, PathBuf)],
+    wal_number: u64,
+) -> Result<(RecoveryReport, Vec<u64>)> {
+    let start = Instant::now();
+    let (mut report, damaged, _) = replay(options, families, segments, wal_number, true)?;
+    report.duration = start.elapsed();
+    Ok((report, damaged))
+}
+
+/// MemTables the WAL was replayed into by an engine that only reads
+pub(super) struct Replayed {
+    pub(super) report: RecoveryReport,
+    /// MemTables that filled up, oldest first, by column family id
+    pub(super) retired: Vec<(u32, Arc<MemTable>)>,
+    /// Each column family's MemTable holding its newest entries
+    pub(super) active: BTreeMap<u32, Arc<MemTable>>,
+}
+
+/// Replays the unflushed segments into MemTables, leaving every file as
+/// it is
+///
+/// For engines reading a database another process may still be writing:
+/// the newest segment may end in an entry that is only partly written,
+/// which is skipped rather than truncated.
+pub(super) fn replay_read_only(
+    options: &Options,
+    families: &BTreeMap<u32, Arc<ColumnFamilyData>>,
+    segments: &[(u64, PathBuf)],
+) -> Result<Replayed> {
+    let start = Instant::now();
+    let mut replay = Replay::new(options, families, false, true);
+    let unflushed = replay.unflushed(segments);
+    replay.start(&unflushed);
+    for (i, (number, path)) in unflushed.iter().enumerate() {
+        replay.segment(*number, path, i + 1 == unflushed.len())?;
+    }
+    let newest_persisted = replay.persisted.values().copied().max().unwrap_or(0);
+    replay.report.last_timestamp = replay.report.last_timestamp.max(newest_persisted);
+    replay.report.duration = start.elapsed();
+    Ok(Replayed {
+        report: replay.report,
+        retired: replay.retired,
+        active: replay.memtables,
+    })
+}
+
+/// Replays the unflushed segments and installs the resulting tables, with
+/// `wal_number` as every family's log number
+///
+/// Transactions still prepared are logged in segment `wal_number` first,
+/// since the segments they were logged in count as flushed from then on.
+fn replay(
+    options: &Options,
+    families: &BTreeMap<u32, Arc<ColumnFamilyData>>,
+    segments: &[(u64, PathBuf)],
+    wal_number: u64,
+    salvage: bool,
+) -> Result<(RecoveryReport, Vec<u64>, PreparedWrites)> {
+    let mut replay = Replay::new(options, families, salvage, false);
+    let unflushed = replay.unflushed(segments);
+    replay.start(&unflushed);
+
+    let result = (|| {
+        for (i, (number, path)) in unflushed.iter().enumerate() {
+            replay.segment(*number, path, i + 1 == unflushed.len())?;
+        }
+        let ids: Vec<_> = replay.memtables.keys().copied().collect();
+        for id in ids {
+            replay.flush_memtable(&families[&id])?;
+        }
+        let newest_persisted = replay.persisted.values().copied().max().unwrap_or(0);
+        replay.report.last_timestamp = replay.report.last_timestamp.max(newest_persisted);
+        replay.report.transactions_prepared = replay.prepared.len();
+        relog_prepared(
+            options,
+            wal_number,
+            replay.report.last_timestamp,
+            &replay.prepared,
+        )?;
+
+        // The default family goes first, persisting the next file number
+        for (id, cf) in families {
+            let tables = replay.tables.remove(id).unwrap_or_default();
+            let mut edit = VersionEdit::default();
+            for meta in &tables {
+                edit.add_file(0, meta.clone());
+            }
+            edit.set_log_number(wal_number);
+            edit.set_last_timestamp(replay.report.last_timestamp);
+            if let Err(e) = cf.versions.log_and_apply(edit) {
+                remove_tables(cf, &tables);
+                return Err(e);
+            }
+            replay.report.tables_written += tables.len();
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        for (id, tables) in &replay.tables {
+            remove_tables(&families[id], tables);
+        }
+        return Err(e);
+    }
+    Ok((replay.report, replay.damaged, replay.prepared))
+}
+
+/// Logs the prepare records of the transactions still prepared in WAL
+/// segment `wal_number`, which continues after the write at `previous`
+fn relog_prepared(
+    options: &Options,
+    wal_number: u64,
+    previous: Timestamp,
+    prepared: &PreparedWrites,
+) -> Result<()> {
+    if prepared.is_empty() {
+        return Ok(());
+    }
+    let config = &options.config;
+    let wal = WALWriter::new_in(
+        &options.vfs,
+        config.wal_dir.join(wal_file_name(wal_number)),
+        config.wal_sync_mode,
+        config.wal_size_limit as u64,
+        previous,
+    )?;
+    for (name, entries) in prepared {
+        wal.append_prepare(name, entries, SyncMode::None)?;
+    }
+    // Left unsealed: the engine appends to the segment once it opens
+    wal.sync()
+}
+
+/// Deletes the files in the families' directories that no MANIFEST
+/// refers to
+fn collect_orphans(
+    options: &Options,
+    families: &BTreeMap<u32, Arc<ColumnFamilyData>>,
+) -> Result<GarbageReport> {
+    let dry_run = options.config.orphan_files_dry_run;
+    let mut report = GarbageReport::default();
+    for cf in families.values() {
+        let version = cf.versions.current();
+        let live: HashSet<u64> = (0..NUM_LEVELS)
+            .flat_map(|level| version.files(level))
+            .map(|table| table.meta().number)
+            .collect();
+        let is_live = |file_type| match file_type {
+            FileType::Table(number) => live.contains(&number),
+            FileType::Temp => false,
+            // Every segment was just replayed and retired, and the WAL
+            // directory may be the default family's
+            FileType::Wal(_) | FileType::Manifest => true,
+        };
+        report.extend(files::collect_garbage_in(
+            &options.vfs,
+            &cf.config.data_dir,
+            is_live,
+            dry_run,
+        )?);
+    }
+
+    if !report.files.is_empty() {
+        log::warn!(
+            "Found {} orphaned files ({} bytes){}",
+            report.files.len(),
+            report.bytes,
+            if dry_run {
+                ", kept for the dry run"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(report)
+}
+
+/// Deletes tables written by a replay that failed to register them
+fn remove_tables(cf: &ColumnFamilyData, tables: &[SSTableMeta]) {
+    for meta in tables {
+        let _ = cf
+            .versions
+            .vfs()
+            .remove_file(&cf.versions.table_path(meta.number));
+    }
+}
+
+/// State of an in-progress replay
+struct Replay<'a> {
+    /// Filesystem holding the segments
+    vfs: &'a Arc<dyn Vfs>,
+    families: &'a BTreeMap<u32, Arc<ColumnFamilyData>>,
+    /// Newest timestamp already persisted by each column family
+    persisted: BTreeMap<u32, Timestamp>,
+    /// Oldest segment any column family still needs
+    log_number: Option<u64>,
+    /// Receive each column family's replayed entries until full
+    memtables: BTreeMap<u32, Arc<MemTable>>,
+    /// Level 0 tables written so far, by column family
+    tables: BTreeMap<u32, Vec<SSTableMeta>>,
+    /// Full MemTables kept instead of written when replaying read-only
+    retired: Vec<(u32, Arc<MemTable>)>,
+    /// Whether to replay around damage instead of failing, see [`salvage`]
+    salvage: bool,
+    /// Whether to leave every file as it is, see [`replay_read_only`]
+    read_only: bool,
+    /// Segments found damaged while salvaging
+    damaged: Vec<u64>,
+    /// Timestamp of the last entry read from the current segment
+    segment_last: Timestamp,
+    /// Writes of the transactions prepared but not yet committed or
+    /// rolled back, by name
+    prepared: PreparedWrites,
+    report: RecoveryReport,
+    /// Told about [`progress`](Self::progress) as replay goes on
+    observers: &'a [Arc<dyn RecoveryObserver>],
+    /// Records the damage replay runs into
+    corruption: &'a CorruptionLog,
+    progress: RecoveryProgress,
+}
+
+impl<'a> Replay<'a> {
+    fn new(
+        options: &'a Options,
+        families: &'a BTreeMap<u32, Arc<ColumnFamilyData>>,
+        salvage: bool,
+        read_only: bool,
+    ) -> Self {
+        let states: Vec<_> = families
+            .values()
+            .map(|cf| cf.versions.manifest_state())
+            .collect();
+        Self {
+            vfs: &options.vfs,
+            families,
+            persisted: families
+                .keys()
+                .zip(&states)
+                .map(|(id, state)| (*id, state.last_timestamp()))
+                .collect(),
+            log_number: states.iter().map(|state| state.log_number()).min(),
+            memtables: BTreeMap::new(),
+            tables: BTreeMap::new(),
+            retired: Vec::new(),
+            salvage,
+            read_only,
+            damaged: Vec::new(),
+            segment_last: 0,
+            prepared: PreparedWrites::new(),
+            report: RecoveryReport {
+                // The family furthest behind has persisted everything
+                // before the oldest unflushed segment
+                last_timestamp: states
+                    .iter()
+                    .map(|state| state.last_timestamp())
+                    .min()
+                    .unwrap_or(0),
+                ..Default::default()
+            },
+            observers: &options.recovery_observers,
+            corruption: &options.corruption,
+            progress: RecoveryProgress::default(),
+        }
+    }
+
+    /// Returns the segments some column family still needs
+    fn unflushed<'s>(&self, segments: &'s [(u64, PathBuf)]) -> Vec<&'s (u64, PathBuf)> {
+        segments
+            .iter()
+            .filter(|(number, _)| *number >= self.log_number.unwrap_or(0))
+            .collect()
+    }
+
+    /// Sets the totals the progress is measured against
+    fn start(&mut self, unflushed: &[&(u64, PathBuf)]) {
+        self.progress.segments_total = unflushed.len();
+        self.progress.bytes_total = unflushed
+            .iter()
+            .map(|(_, path)| self.vfs.file_size(path).unwrap_or(0))
+            .sum();
+    }
+
+    /// Tells the observers how far replay has got
+    fn report_progress(&mut self) {
+        self.progress.entries_replayed = self.report.entries_replayed;
+        for observer in self.observers {
+            observer.on_progress(&self.progress);
+        }
+    }
+
+    /// Replays one segment, reporting progress before and after
+    fn segment(&mut self, number: u64, path: &Path, newest: bool) -> Result<()> {
+        let len = self.vfs.file_size(path).unwrap_or(0);
+        let done = self.progress.bytes_processed;
+        self.progress.segment = number;
+        self.progress.segment_path = path.to_path_buf();
+        self.report_progress();
+
+        self.replay_segment(number, path, newest)?;
+        // Counts the whole segment even if replay stopped at damage
+        self.progress.bytes_processed = done + len;
+        self.progress.segments_done += 1;
+        self.report_progress();
+        Ok(())
+    }
+
+    /// Replays one segment; only the newest may end in a damaged entry,
+    /// unless salvaging
+    fn replay_segment(&mut self, number: u64, path: &Path, newest: bool) -> Result<()> {
+        let done = self.progress.bytes_processed;
+        let mut reader = match WALReader::new_in(self.vfs, path) {
+            Ok(reader) => reader,
+            Err(e) if self.salvage => {
+                log::warn!("Skipping unreadable WAL segment {}: {}", number, e);
+                self.corruption.record(&e, None, REPLAY);
+                self.damaged.push(number);
+                return Ok(());
+            }
+            // Crashed while creating the segment, before any entry was logged
+            Err(e) if newest && is_torn(&e) => {
+                if self.read_only {
+                    return Ok(());
+                }
+                let len = self.vfs.file_size(path)?;
+                log::warn!("Ignoring WAL segment {} with torn header: {}", number, e);
+                self.corruption.record(&e, None, REPLAY);
+                self.report.truncated_bytes += len;
+                return truncate(self.vfs.as_ref(), path, 0);
+            }
+            Err(e) => return Err(e),
+        };
+        let previous = reader.header().previous_timestamp;
+        if previous > self.report.last_timestamp && !self.salvage {
+            return Err(Error::Corruption(format!(
+                "WAL segment {} continues from timestamp {}, but replay only reached {}",
+                number, previous, self.report.last_timestamp
+            )));
+        }
+        self.report.segments_replayed += 1;
+        self.segment_last = 0;
+        if reader.is_sealed() {
+            self.report.segments_sealed += 1;
+        }
+
+        loop {
+            let read = reader.read_entry();
+            self.track_transactions(reader.take_transaction_markers());
+            match read {
+                Ok(Some(entry)) => {
+                    self.apply(number, entry)?;
+                    let read = done + reader.valid_len();
+                    if read >= self.progress.bytes_processed + PROGRESS_INTERVAL {
+                        self.progress.bytes_processed = read;
+                        self.report_progress();
+                    }
+                }
+                Ok(None) => break,
+                Err(e) if self.salvage => {
+                    log::warn!(
+                        "WAL segment {} is damaged at offset {}, salvaging the entries before: {}",
+                        number,
+                        reader.valid_len(),
+                        e
+                    );
+                    self.corruption.record(&e, None, REPLAY);
+                    self.damaged.push(number);
+                    return Ok(());
+                }
+                // A writer may still be appending the rest of the entry
+                Err(e) if newest && is_torn(&e) && self.read_only => break,
+                Err(e) if newest && is_torn(&e) => {
+                    log::warn!(
+                        "WAL segment {} is damaged at offset {}, truncating: {}",
+                        number,
+                        reader.valid_len(),
+                        e
+                    );
+                    self.corruption.record(&e, None, REPLAY);
+                    break;
+                }
+                Err(e) => {
+                    self.corruption.record(&e, None, REPLAY);
+                    return Err(Error::Corruption(format!(
+                        "WAL segment {} is damaged: {}",
+                        number,
+                        e.root()
+                    ))
+                    .with_path(path)
+                    .at_offset(reader.valid_len()));
+                }
+            }
+        }
+
+        // A partial length prefix reads as a clean end of file
+        let len = self.vfs.file_size(path)?;
+        if len > reader.valid_len() && !(newest && self.read_only) {
+            if self.salvage {
+                self.damaged.push(number);
+                return Ok(());
+            }
+            if !newest {
+                let error = Error::Corruption(format!(
+                    "WAL segment {} has {} trailing bytes after its last entry",
+                    number,
+                    len - reader.valid_len()
+                ))
+                .with_path(path)
+                .at_offset(reader.valid_len());
+                self.corruption.record(&error, None, REPLAY);
+                return Err(error);
+            }
+            self.report.truncated_bytes += len - reader.valid_len();
+            truncate(self.vfs.as_ref(), path, reader.valid_len())?;
+        }
+        Ok(())
+    }
+
+    /// Keeps track of which transactions are prepared
+    fn track_transactions(&mut self, markers: Vec<TransactionMarker>) {
+        for marker in markers {
+            match marker {
+                TransactionMarker::Prepare { name, entries } => {
+                    self.prepared.insert(name, entries);
+                }
+                TransactionMarker::Commit { name } | TransactionMarker::Rollback { name } => {
+                    self.prepared.remove(&name);
+                }
+            }
+        }
+    }
+
+    /// Checks an entry comes after the last one of its segment and inserts
+    /// it unless it was already applied
+    ///
+    /// Entries no newer than those replayed so far are skipped, so
+    /// segments overlapping an earlier one, as a checkpoint taken during a
+    /// rotation can leave, replay only once. So are entries their column
+    /// family persisted before the MANIFEST was last written.
+    fn apply(&mut self, segment: u64, entry: WALEntry) -> Result<()> {
+        let WALEntry {
+            timestamp,
+            operation,
+            key,
+            value,
+            column_family,
+        } = entry;
+
+        if timestamp <= self.segment_last {
+            if self.salvage {
+                return Ok(());
+            }
+            return Err(Error::Corruption(format!(
+                "WAL segment {} goes back in time: timestamp {} follows {}",
+                segment, timestamp, self.segment_last
+            )));
+        }
+        self.segment_last = timestamp;
+
+        if timestamp <= self.report.last_timestamp {
+            self.report.entries_skipped += 1;
+            return Ok(());
+        }
+        self.report.last_timestamp = timestamp;
+
+        let Some(cf) = self.families.get(&column_family) else {
+            return Ok(());
+        };
+        if timestamp <= self.persisted[&column_family] {
+            self.report.entries_skipped += 1;
+            return Ok(());
+        }
+
+        let full = self
+            .memtables
+            .get(&column_family)
+            .is_some_and(|memtable| !memtable.has_room_for(&key, &value));
+        if full {
+            self.flush_memtable(cf)?;
+        }
+        let memtable = self
+            .memtables
+            .entry(column_family)
+            .or_insert_with(|| cf.new_memtable());
+        match operation {
+            Operation::Put => memtable.put(key, value, timestamp)?,
+            Operation::Delete => memtable.delete(key, timestamp)?,
+            Operation::Merge => memtable.merge(key, value, timestamp)?,
+        }
+
+        self.report.entries_replayed += 1;
+        Ok(())
+    }
+
+    /// Writes a column family's replayed entries so far into a level 0
+    /// table, or only retires the MemTable when replaying read-only
+    fn flush_memtable(&mut self, cf: &ColumnFamilyData) -> Result<()> {
+        let Some(memtable) = self.memtables.remove(&cf.id) else {
+            return Ok(());
+        };
+        if self.read_only {
+            self.retired.push((cf.id, memtable));
+            return Ok(());
+        }
+        if let Some(meta) = write_table(cf, &memtable)? {
+            self.tables.entry(cf.id).or_default().push(meta);
+        }
+        Ok(())
+    }
+}
+
+/// Returns true for errors a partially written entry or header produces
+fn is_torn(e: &Error) -> bool {
+    match e.root() {
+        Error::Corruption(_) | Error::InvalidFormat(_) => true,
+        Error::Io(e) => e.kind() == ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+/// Cuts a segment back to its last complete entry
+pub(super) fn truncate(vfs: &dyn Vfs, path: &Path, len: u64) -> Result<()> {
+    if !vfs.exists(path) {
+        return Err(Error::NotFound(format!(
+            "{} does not exist",
+            path.display()
+        )));
+    }
+    let file = vfs.open_or_create(path)?;
+    file.set_len(len)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use super::{RecoveryObserver, RecoveryProgress};
+    use crate::clock::ManualClock;
+    use crate::files::wal_file_name;
+    use crate::wal::{WALEntry, WALWriter};
+    use ferrisdb_core::{Error, SyncMode};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// Writes a segment continuing after `previous` that holds puts of
+    /// `key{ts}` at the given timestamps
+    fn write_segment(
+        options: &Options,
+        number: u64,
+        previous: u64,
+        timestamps: &[u64],
+    ) -> std::path::PathBuf {
+        let path = options.config().wal_dir.join(wal_file_name(number));
+        let writer = WALWriter::new_after(&path, SyncMode::Full, 1024 * 1024, previous).unwrap();
+        for &ts in timestamps {
+            let entry = WALEntry::new_put(format!("key{}", ts).into_bytes(), b"v".to_vec(), ts);
+            writer.append(&entry.unwrap()).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn test_replays_segments_in_order_into_level0() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path()).with_memtable_size(1024);
+        write_segment(&options, 3, 0, &[1, 2, 3]);
+        write_segment(&options, 8, 3, &(4..40).collect::<Vec<_>>());
+
+        let engine = StorageEngine::open(options).unwrap();
+        let report = engine.recovery_report();
+
+        assert_eq!(report.segments_replayed, 2);
+        assert_eq!(report.entries_replayed, 39);
+        assert_eq!(report.last_timestamp, 39);
+        assert!(report.tables_written > 1);
+        assert!(!report.truncated_tail());
+        assert_eq!(engine.get(b"key1").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"key39").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_observers_follow_replay_progress() {
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<RecoveryProgress>>);
+
+        impl RecoveryObserver for Recorder {
+            fn on_progress(&self, progress: &RecoveryProgress) {
+                self.0.lock().push(progress.clone());
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let options = Options::new(dir.path()).with_recovery_observer(recorder.clone());
+        let first = write_segment(&options, 3, 0, &[1, 2, 3]);
+        let second = write_segment(&options, 8, 3, &[4, 5]);
+        let sizes = [first, second.clone()].map(|path| std::fs::metadata(path).unwrap().len());
+
+        let engine = StorageEngine::open(options).unwrap();
+
+        let progress = recorder.0.lock();
+        // Each segment reports as it starts and ends
+        assert_eq!(progress.len(), 4);
+        assert!(progress.iter().all(|p| p.segments_total == 2));
+        assert!(progress
+            .iter()
+            .all(|p| p.bytes_total == sizes[0] + sizes[1]));
+        assert_eq!(progress[0].segment, 3);
+        assert_eq!(progress[0].bytes_processed, 0);
+        assert_eq!(progress[0].fraction_done(), 0.0);
+        assert_eq!(progress[1].segments_done, 1);
+        assert_eq!(progress[1].entries_replayed, 3);
+        assert_eq!(progress[2].segment, 8);
+        assert_eq!(progress[2].segment_path, second);
+        assert_eq!(progress[2].bytes_processed, sizes[0]);
+
+        let last = &progress[3];
+        assert_eq!(last.segments_done, 2);
+        assert_eq!(
+            last.entries_replayed,
+            engine.recovery_report().entries_replayed
+        );
+        assert_eq!(last.fraction_done(), 1.0);
+    }
+
+    #[test]
+    fn test_segments_closed_cleanly_are_reported_as_sealed() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path());
+        write_segment(&options, 1, 0, &[1, 2]);
+        let path = options.config().wal_dir.join(wal_file_name(2));
+        let writer = WALWriter::new_after(&path, SyncMode::Full, 1024 * 1024, 2).unwrap();
+        writer
+            .append(&WALEntry::new_put(b"key3".to_vec(), b"v".to_vec(), 3).unwrap())
+            .unwrap();
+        writer.close().unwrap();
+
+        let engine = StorageEngine::open(options).unwrap();
+        let report = engine.recovery_report();
+        assert_eq!(report.segments_replayed, 2);
+        assert_eq!(report.segments_sealed, 1);
+        assert_eq!(report.entries_replayed, 3);
+        assert!(!report.truncated_tail());
+        assert_eq!(engine.get(b"key3").unwrap(), Some(b"v".to_vec()));
+
+        // Closing the engine seals the segment the next open replays
+        engine.put(b"key4".to_vec(), b"v".to_vec()).unwrap();
+        drop(engine);
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let report = engine.recovery_report();
+        assert_eq!(report.segments_sealed, report.segments_replayed);
+        assert!(report.segments_sealed > 0);
+        assert_eq!(engine.get(b"key4").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_torn_tail_of_newest_segment_is_truncated() {
+        let dir = TempDir::new().unwrap();
+        // A stopped clock keeps the oracle from skipping ahead
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let options = Options::new(dir.path()).with_clock(clock.clone());
+        let path = write_segment(&options, 1, 0, &[1, 2]);
+
+        let torn = WALEntry::new_put(b"key3".to_vec(), b"v".to_vec(), 3)
+            .unwrap()
+            .encode()
+            .unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        data.extend_from_slice(&torn[..torn.len() - 3]);
+        std::fs::write(&path, data).unwrap();
+
+        let engine = StorageEngine::open(options).unwrap();
+        let report = engine.recovery_report();
+
+        assert_eq!(report.entries_replayed, 2);
+        assert_eq!(report.truncated_bytes, torn.len() as u64 - 3);
+        assert_eq!(engine.get(b"key2").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"key3").unwrap(), None);
+
+        // The next write continues right after the last complete entry
+        engine.put(b"next".to_vec(), b"v".to_vec()).unwrap();
+        drop(engine);
+        let engine = StorageEngine::open(Options::new(dir.path()).with_clock(clock)).unwrap();
+        assert_eq!(engine.recovery_report().last_timestamp, 3);
+    }
+
+    #[test]
+    fn test_missing_segment_fails_recovery() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path());
+        write_segment(&options, 1, 0, &[1, 2]);
+        // Segment 2 with timestamps 3..=4 went missing
+        write_segment(&options, 3, 4, &[5, 6]);
+
+        let result = StorageEngine::open(options);
+
+        assert!(matches!(result, Err(Error::Corruption(_))));
+        // Nothing was deleted, so the damage can still be inspected
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("wal")).unwrap().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_damage_in_older_segment_fails_recovery() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path());
+        let older = write_segment(&options, 1, 0, &[1, 2]);
+        write_segment(&options, 2, 2, &[3]);
+
+        let mut data = std::fs::read(&older).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(&older, data).unwrap();
+
+        let error = StorageEngine::open(options).err().unwrap();
+        assert!(matches!(error.root(), Error::Corruption(_)));
+        assert_eq!(
+            error.context().unwrap().path.as_deref(),
+            Some(older.as_path())
+        );
+    }
+
+    #[test]
+    fn test_overlapping_segments_replay_each_entry_once() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path());
+        write_segment(&options, 1, 0, &[1, 2, 3]);
+        // Continues from timestamp 1, repeating 2 and 3
+        write_segment(&options, 2, 1, &[2, 3, 4, 5]);
+
+        let engine = StorageEngine::open(options).unwrap();
+        let report = engine.recovery_report();
+        assert_eq!(report.entries_replayed, 5);
+        assert_eq!(report.entries_skipped, 2);
+        assert_eq!(report.last_timestamp, 5);
+        for ts in 1..=5 {
+            let key = format!("key{}", ts);
+            assert_eq!(engine.get(key.as_bytes()).unwrap(), Some(b"v".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_entries_the_manifest_covers_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        // A stopped clock gives the writes timestamps 1 to 3
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let options = Options::new(dir.path()).with_clock(clock);
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        for ts in 1..=3 {
+            let key = format!("key{}", ts);
+            engine.put(key.into_bytes(), b"flushed".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        drop(engine);
+
+        // A segment from before the flush, as an older checkpoint holds
+        write_segment(&options, 100, 1, &[2, 3, 4]);
+        let engine = StorageEngine::open(options).unwrap();
+        let report = engine.recovery_report();
+        assert_eq!(report.entries_replayed, 1);
+        assert_eq!(report.entries_skipped, 2);
+        assert_eq!(engine.get(b"key2").unwrap(), Some(b"flushed".to_vec()));
+        assert_eq!(engine.get(b"key4").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_segment_going_back_in_time_fails_recovery() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path());
+        write_segment(&options, 1, 0, &[1, 3, 2]);
+
+        let error = StorageEngine::open(options).err().unwrap();
+        assert!(matches!(error.root(), Error::Corruption(msg) if msg.contains("back in time")));
+    }
+}
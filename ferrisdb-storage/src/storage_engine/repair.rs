@@ -0,0 +1,473 @@
+//! Rebuilding a database whose MANIFESTs are lost or corrupt
+//!
+//! The MANIFESTs are the only record of which tables are live. When one is
+//! lost or damaged beyond recovery, [`StorageEngine::repair`] rebuilds all
+//! of them from the files still on disk, as a last resort before opening:
+//!
+//! ```text
+//!  1. every table is read in full   ──▶ readable ones go to level 0
+//!                                   └─▶ unreadable ones to lost/
+//!  2. fresh MANIFESTs list the tables, the old ones go to lost/
+//!  3. the WAL is replayed up to the damage in each segment
+//!                                   ──▶ new level 0 tables
+//!  4. replayed segments are retired, damaged ones go to lost/
+//! ```
+//!
+//! Nothing is deleted: files that can't be used are moved to the `lost`
+//! directory in the data directory, where they can be inspected or fed to
+//! other tools. The writes held only by damaged files are lost. A table
+//! that a compaction replaced just before the crash may still be on disk;
+//! it is registered again, bringing back its versions, including those of
+//! keys whose deletions the compaction dropped.
+//!
+//! Column family names live only in the default family's MANIFEST. They
+//! are read from whatever of it is still intact; a family whose name is
+//! lost is named after its directory, such as `cf-3`.
+//!
+//! [`StorageEngine::repair`]: super::StorageEngine::repair
+
+use super::column_family::{
+    parse_column_family_dir, ColumnFamilyData, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY,
+    DEFAULT_COLUMN_FAMILY_ID,
+};
+use super::recovery::{salvage, wal_segments_in};
+use super::{lock_dirs, open_column_families, retire_wal_segment, Options};
+use crate::comparator::{self, Comparator};
+use crate::files::{self, FileType, MANIFEST_FILE_NAME};
+use crate::manifest::{ManifestReader, ManifestWriter, SSTableMeta, VersionEdit};
+use crate::rate_limiter::RateLimiter;
+use crate::sstable::SSTableReader;
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the directory in the data directory unusable files go to
+pub const LOST_DIR_NAME: &str = "lost";
+
+/// What [`StorageEngine::repair`](super::StorageEngine::repair) rebuilt
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Number of column families, including the default one
+    pub column_families: usize,
+    /// Number of readable tables registered in the new MANIFESTs
+    pub tables: usize,
+    /// Number of WAL entries salvaged into new level 0 tables
+    pub entries_salvaged: u64,
+    /// Number of level 0 tables written from the salvaged entries
+    pub tables_written: usize,
+    /// Where the unusable tables, damaged WAL segments and replaced
+    /// MANIFESTs were moved
+    pub quarantined: Vec<PathBuf>,
+}
+
+/// A column family found on disk
+struct Family {
+    id: u32,
+    name: String,
+    dir: PathBuf,
+}
+
+/// Rebuilds the MANIFESTs of the database `options` describes
+pub(super) fn repair(options: &Options) -> Result<RepairReport> {
+    if options.remote_storage.is_some() {
+        return Err(Error::Unsupported(
+            "Databases with remote tables can't be repaired".to_string(),
+        ));
+    }
+    let config = &options.config;
+    let vfs = &options.vfs;
+    let _locks = lock_dirs(options)?;
+    if let Some(archive) = &config.wal_archive_dir {
+        vfs.create_dir_all(archive)?;
+    }
+    let lost_dir = config.data_dir.join(LOST_DIR_NAME);
+    let mut report = RepairReport::default();
+
+    let families = find_families(vfs, &config.data_dir)?;
+    let segments = wal_segments_in(vfs, &config.wal_dir)?;
+    let mut next_file_number = segments.last().map_or(1, |(number, _)| number + 1);
+
+    // Read every table before writing anything, so the file numbers of
+    // the new MANIFESTs are past all of them
+    let mut found = Vec::with_capacity(families.len());
+    for family in &families {
+        let comparator = family_comparator(options, family);
+        let mut tables = Vec::new();
+        let mut last_timestamp = 0;
+        for (file_type, path) in files::list_files_in(vfs, &family.dir)? {
+            let FileType::Table(number) = file_type else {
+                continue;
+            };
+            next_file_number = next_file_number.max(number + 1);
+            match scan_table(vfs, &path, number, &comparator) {
+                Ok((meta, newest)) => {
+                    last_timestamp = last_timestamp.max(newest);
+                    tables.push(meta);
+                }
+                // A table of another comparator means wrong options, not
+                // a damaged table
+                Err(e) if matches!(e.root(), Error::InvalidArgument(_)) => return Err(e),
+                Err(e) => {
+                    log::warn!("Quarantining unreadable table {}: {}", path.display(), e);
+                    report
+                        .quarantined
+                        .push(quarantine(vfs, &config.data_dir, &lost_dir, &path)?);
+                }
+            }
+        }
+        found.push((tables, last_timestamp));
+    }
+
+    // The old MANIFESTs are kept, and replaced by ones listing the tables
+    let next_column_family_id = families.iter().map(|family| family.id + 1).max();
+    for (family, (tables, last_timestamp)) in families.iter().zip(&found) {
+        let path = family.dir.join(MANIFEST_FILE_NAME);
+        if vfs.exists(&path) {
+            report
+                .quarantined
+                .push(quarantine(vfs, &config.data_dir, &lost_dir, &path)?);
+        }
+
+        let mut edit = VersionEdit::default();
+        for meta in tables {
+            edit.add_file(0, meta.clone());
+        }
+        // Every segment is replayed by the salvage below
+        edit.set_log_number(0);
+        edit.set_next_file_number(next_file_number);
+        edit.set_last_timestamp(*last_timestamp);
+        if family.id == DEFAULT_COLUMN_FAMILY_ID {
+            for other in &families[1..] {
+                edit.add_column_family(other.id, other.name.clone());
+            }
+            if let Some(id) = next_column_family_id {
+                edit.set_next_column_family_id(id);
+            }
+        }
+        ManifestWriter::create_in(vfs, &path)?.log_and_apply(edit)?;
+        report.tables += tables.len();
+    }
+    report.column_families = families.len();
+
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limiter_bytes_per_sec));
+    let default = Arc::new(ColumnFamilyData::open(
+        DEFAULT_COLUMN_FAMILY_ID,
+        DEFAULT_COLUMN_FAMILY,
+        &config.data_dir,
+        options,
+        ColumnFamilyOptions::from_options(options),
+        &rate_limiter,
+    )?);
+    let opened = open_column_families(options, &default, &rate_limiter)?;
+    let wal_number = default.versions.new_file_number();
+    let (recovery, damaged) = salvage(options, &opened, &segments, wal_number)?;
+    report.entries_salvaged = recovery.entries_replayed;
+    report.tables_written = recovery.tables_written;
+
+    for (number, path) in &segments {
+        if damaged.contains(number) {
+            report
+                .quarantined
+                .push(quarantine(vfs, &config.data_dir, &lost_dir, path)?);
+        } else {
+            retire_wal_segment(options, path);
+        }
+    }
+
+    log::info!(
+        "Repaired {} column families: {} tables, {} WAL entries salvaged, {} files quarantined",
+        report.column_families,
+        report.tables,
+        report.entries_salvaged,
+        report.quarantined.len()
+    );
+    Ok(report)
+}
+
+/// Lists the default family and the family directories in `data_dir`,
+/// ordered by id
+fn find_families(vfs: &Arc<dyn Vfs>, data_dir: &Path) -> Result<Vec<Family>> {
+    let names = registered_names(vfs, &data_dir.join(MANIFEST_FILE_NAME));
+    let mut families = vec![Family {
+        id: DEFAULT_COLUMN_FAMILY_ID,
+        name: DEFAULT_COLUMN_FAMILY.to_string(),
+        dir: data_dir.to_path_buf(),
+    }];
+    for path in vfs.read_dir(data_dir)? {
+        let id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_column_family_dir);
+        if let Some(id) = id {
+            // Only directories can be listed
+            if id != DEFAULT_COLUMN_FAMILY_ID && vfs.read_dir(&path).is_ok() {
+                families.push(Family {
+                    id,
+                    name: names
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or_else(|| format!("cf-{}", id)),
+                    dir: path,
+                });
+            }
+        }
+    }
+    families.sort_by_key(|family| family.id);
+    Ok(families)
+}
+
+/// Returns the column family names a MANIFEST records, reading its edits
+/// up to the first damaged one
+fn registered_names(vfs: &Arc<dyn Vfs>, path: &Path) -> BTreeMap<u32, String> {
+    let mut names = BTreeMap::new();
+    let Ok(mut reader) = ManifestReader::new_in(vfs, path) else {
+        return names;
+    };
+    while let Ok(Some(edit)) = reader.read_edit() {
+        for id in &edit.dropped_column_families {
+            names.remove(id);
+        }
+        names.extend(edit.added_column_families);
+    }
+    names
+}
+
+/// Returns the comparator a family's tables are sorted by
+fn family_comparator(options: &Options, family: &Family) -> Arc<dyn Comparator> {
+    if family.id == DEFAULT_COLUMN_FAMILY_ID {
+        return Arc::clone(&options.comparator);
+    }
+    options
+        .column_families
+        .get(&family.name)
+        .map(|cf_options| Arc::clone(cf_options.comparator()))
+        .unwrap_or_else(comparator::bytewise)
+}
+
+/// Reads every entry of a table, returning its metadata and the newest
+/// timestamp in it
+fn scan_table(
+    vfs: &Arc<dyn Vfs>,
+    path: &Path,
+    number: u64,
+    comparator: &Arc<dyn Comparator>,
+) -> Result<(SSTableMeta, Timestamp)> {
+    let mut reader = SSTableReader::open_in(vfs, path, Arc::clone(comparator))?;
+    let mut bounds = None;
+    let mut entry_count = 0;
+    let mut newest = 0;
+    for entry in reader.iter()? {
+        let key = entry?.key;
+        newest = newest.max(key.timestamp);
+        entry_count += 1;
+        bounds = match bounds {
+            None => Some((key.clone(), key)),
+            Some((smallest, _)) => Some((smallest, key)),
+        };
+    }
+    let Some((smallest, largest)) = bounds else {
+        return Err(Error::Corruption("Table holds no entries".to_string()).with_path(path));
+    };
+    let meta = SSTableMeta {
+        number,
+        file_size: vfs.file_size(path)?,
+        entry_count,
+        smallest,
+        largest,
+    };
+    Ok((meta, newest))
+}
+
+/// Moves `path` into `lost_dir`, keeping its place relative to the data
+/// directory, and returns where it went
+///
+/// A file quarantined by an earlier repair under the same name is kept;
+/// the new one gets a numbered suffix.
+fn quarantine(
+    vfs: &Arc<dyn Vfs>,
+    data_dir: &Path,
+    lost_dir: &Path,
+    path: &Path,
+) -> Result<PathBuf> {
+    let relative = match path.strip_prefix(data_dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => PathBuf::from(path.file_name().unwrap_or_default()),
+    };
+    let mut target = lost_dir.join(&relative);
+    let mut suffix = 1;
+    while vfs.exists(&target) {
+        let mut name = relative.as_os_str().to_owned();
+        name.push(format!(".{}", suffix));
+        target = lost_dir.join(name);
+        suffix += 1;
+    }
+    if let Some(parent) = target.parent() {
+        vfs.create_dir_all(parent)?;
+    }
+
+    let moved = vfs.rename(path, &target).or_else(|_| {
+        vfs::copy(vfs.as_ref(), path, &target)?;
+        vfs.remove_file(path)
+    });
+    moved.map_err(|e| Error::from(e).with_path(path))?;
+    vfs::sync_parent_dir(vfs.as_ref(), &target)?;
+    vfs::sync_parent_dir(vfs.as_ref(), path)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine};
+    use super::*;
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    fn options(dir: &Path) -> Options {
+        Options::new(dir).with_column_family(
+            "counters",
+            ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+        )
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_repair_rebuilds_lost_manifests() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(options(dir.path())).unwrap();
+        let counters = engine
+            .create_column_family(
+                "counters",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+            )
+            .unwrap();
+        for i in 0..100 {
+            engine.put(key(i), b"flushed".to_vec()).unwrap();
+        }
+        engine
+            .merge_cf(&counters, b"n".to_vec(), 2u64.to_le_bytes().to_vec())
+            .unwrap();
+        engine.flush().unwrap();
+        for i in 0..50 {
+            engine.put(key(i), b"in the wal".to_vec()).unwrap();
+        }
+        engine
+            .merge_cf(&counters, b"n".to_vec(), 3u64.to_le_bytes().to_vec())
+            .unwrap();
+        engine.crash();
+
+        // The flush's edit is damaged, but the family's name comes before
+        let manifest = dir.path().join(MANIFEST_FILE_NAME);
+        let mut bytes = std::fs::read(&manifest).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&manifest, bytes).unwrap();
+        std::fs::remove_file(dir.path().join("cf-1").join(MANIFEST_FILE_NAME)).unwrap();
+        assert!(StorageEngine::open(options(dir.path())).is_err());
+
+        let report = StorageEngine::repair(options(dir.path())).unwrap();
+        assert_eq!(report.column_families, 2);
+        assert_eq!(report.tables, 2);
+        assert_eq!(report.entries_salvaged, 51);
+        assert_eq!(report.quarantined, vec![dir.path().join("lost/MANIFEST")]);
+
+        let engine = StorageEngine::open(options(dir.path())).unwrap();
+        assert_eq!(engine.get(&key(10)).unwrap(), Some(b"in the wal".to_vec()));
+        assert_eq!(engine.get(&key(90)).unwrap(), Some(b"flushed".to_vec()));
+        let counters = engine.cf_handle("counters").unwrap();
+        assert_eq!(
+            engine.get_cf(&counters, b"n").unwrap(),
+            Some(5u64.to_le_bytes().to_vec())
+        );
+        // Repaired tables compact like any others
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_repair_quarantines_unreadable_tables_and_segments() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"kept".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"lost".to_vec(), b"v".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"salvaged".to_vec(), b"v".to_vec()).unwrap();
+        engine.crash();
+
+        let tables: Vec<_> = files::list_files(dir.path())
+            .unwrap()
+            .into_iter()
+            .filter(|(file_type, _)| matches!(file_type, FileType::Table(_)))
+            .collect();
+        let (_, damaged) = &tables[1];
+        std::fs::write(damaged, b"not a table").unwrap();
+        let (_, segment) = wal_segments_in(&vfs::os(), &dir.path().join("wal"))
+            .unwrap()
+            .pop()
+            .unwrap();
+        let mut wal = std::fs::read(&segment).unwrap();
+        wal.extend_from_slice(&[0xff; 20]);
+        std::fs::write(&segment, wal).unwrap();
+
+        let report = StorageEngine::repair(Options::new(dir.path())).unwrap();
+        assert_eq!(report.tables, 1);
+        assert_eq!(report.entries_salvaged, 1);
+        assert_eq!(report.quarantined.len(), 3);
+        assert!(report
+            .quarantined
+            .iter()
+            .all(|path| path.starts_with(dir.path().join(LOST_DIR_NAME)) && path.exists()));
+        assert!(!damaged.exists());
+
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert_eq!(engine.get(b"kept").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"lost").unwrap(), None);
+        assert_eq!(engine.get(b"salvaged").unwrap(), Some(b"v".to_vec()));
+    }
+    #[test]
+    fn test_repair_goes_through_the_engine_vfs() {
+        let options = Options::in_memory().with_column_family(
+            "counters",
+            ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+        );
+        let vfs = Arc::clone(options.vfs());
+        let engine = StorageEngine::open(options.clone()).unwrap();
+        let counters = engine
+            .create_column_family(
+                "counters",
+                ColumnFamilyOptions::default().with_merge_operator(Arc::new(U64AddOperator)),
+            )
+            .unwrap();
+        engine.put(b"flushed".to_vec(), b"v".to_vec()).unwrap();
+        engine
+            .merge_cf(&counters, b"n".to_vec(), 4u64.to_le_bytes().to_vec())
+            .unwrap();
+        engine.flush().unwrap();
+        engine.put(b"in the wal".to_vec(), b"v".to_vec()).unwrap();
+        engine.crash();
+
+        vfs.remove_file(&Path::new("/cf-1").join(MANIFEST_FILE_NAME))
+            .unwrap();
+        let report = StorageEngine::repair(options.clone()).unwrap();
+        assert_eq!(report.column_families, 2);
+        assert_eq!(report.tables, 2);
+        assert_eq!(report.entries_salvaged, 1);
+        assert_eq!(report.quarantined, vec![PathBuf::from("/lost/MANIFEST")]);
+        assert!(vfs.exists(Path::new("/lost/MANIFEST")));
+        assert!(!Path::new("/lost").exists());
+
+        let engine = StorageEngine::open(options).unwrap();
+        assert_eq!(engine.get(b"flushed").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"in the wal").unwrap(), Some(b"v".to_vec()));
+        let counters = engine.cf_handle("counters").unwrap();
+        assert_eq!(
+            engine.get_cf(&counters, b"n").unwrap(),
+            Some(4u64.to_le_bytes().to_vec())
+        );
+    }
+}
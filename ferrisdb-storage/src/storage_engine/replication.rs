@@ -0,0 +1,306 @@
+//! Shipping writes from a primary engine to replicas
+//!
+//! Timestamps only ever increase, so a timestamp doubles as a position in
+//! the write history. A replica starts from a checkpoint of the primary
+//! and asks for everything after its last timestamp; the primary follows
+//! its WAL from there and ships the records unchanged:
+//!
+//! ```text
+//!   primary                                   replica
+//!   write ──▶ WAL ──▶ WALTailer ──records──▶ write_replicated ──▶ WAL ──▶ MemTable
+//!                       ▲                          │
+//!                       └── resume after ts N ◀────┘ last_timestamp() = N
+//! ```
+//!
+//! Replicated entries keep the primary's timestamps and batch boundaries,
+//! so the replica's history is a prefix of the primary's and readers never
+//! see part of a batch. Entries the replica already has are skipped, which
+//! makes resending after a reconnect harmless. Timestamps have gaps, so
+//! the replica can't tell a missing record from a gap; the primary's
+//! tailer checks that no WAL segment between the replica's last timestamp
+//! and the records it ships is missing instead.
+//!
+//! Tailers of a primary with a [WAL cache](super::Options::with_wal_cache_size)
+//! read the records it still holds from memory, so replicas that keep up
+//! cost the primary no reads of the segment it is writing.
+//!
+//! The primary deletes WAL segments once they are flushed, so a replica
+//! that falls behind the oldest segment can't catch up from the WAL; a
+//! WAL archive on the primary keeps the segments around, and the replica
+//! can always be seeded again from a fresh checkpoint. Column families
+//! aren't replicated: a replica needs the primary's families, created in
+//! the same order so they get the same ids.
+
+use super::{EngineInner, WriteOptions};
+use crate::wal::{WALEntry, WALTailer};
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::sync::Arc;
+
+/// Creates a tailer of the engine's WAL and archive from timestamp `from`
+pub(super) fn tail_wal(inner: &EngineInner, from: Timestamp) -> Result<WALTailer> {
+    let last = inner.oracle.last();
+    if from > last + 1 {
+        return Err(Error::InvalidOperation(format!(
+            "Cannot tail from timestamp {}, the newest write is {}",
+            from, last
+        )));
+    }
+    let config = &inner.options.config;
+    let mut dirs = vec![config.wal_dir.clone()];
+    dirs.extend(config.wal_archive_dir.clone());
+    let tailer = WALTailer::new_in(&inner.options.vfs, dirs, from);
+    Ok(match &inner.wal_cache {
+        Some(cache) => tailer.with_cache(Arc::clone(cache)),
+        None => tailer,
+    })
+}
+
+/// Applies entries of a primary's WAL record, returning the new last
+/// timestamp
+pub(super) fn write_replicated(
+    inner: &EngineInner,
+    entries: Vec<WALEntry>,
+    sync: bool,
+) -> Result<Timestamp> {
+    inner.check_open()?;
+    if !inner.options.replica {
+        return Err(Error::InvalidOperation(
+            "Only replicas accept replicated writes".to_string(),
+        ));
+    }
+    let size = entries
+        .iter()
+        .map(|entry| entry.key.len() + entry.value.len())
+        .sum::<usize>();
+    inner.write_controller.delay_write(size as u64)?;
+
+    let mut wal = inner.lock_writer()?;
+    inner.background_error(&inner.background.lock())?;
+    let last = inner.oracle.last();
+    let entries: Vec<_> = entries
+        .into_iter()
+        .filter(|entry| entry.timestamp > last)
+        .collect();
+    if entries
+        .windows(2)
+        .any(|pair| pair[1].timestamp <= pair[0].timestamp)
+    {
+        return Err(Error::InvalidOperation(
+            "Replicated writes must be in timestamp order".to_string(),
+        ));
+    }
+    let Some(newest) = entries.last().map(|entry| entry.timestamp) else {
+        return Ok(last);
+    };
+    let options = WriteOptions {
+        sync: sync.then_some(true),
+        ..WriteOptions::default()
+    };
+    inner.log_and_apply(&mut wal, entries, &options, None)?;
+    Ok(newest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine, WriteBatch};
+    use crate::clock::ManualClock;
+    use crate::wal::WALTailer;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    /// Ships everything the tailer has, returning the number of records
+    fn ship(tailer: &mut WALTailer, replica: &StorageEngine) -> usize {
+        let mut records = 0;
+        while let Some(entries) = tailer.poll().unwrap() {
+            replica.write_replicated(entries, false).unwrap();
+            records += 1;
+        }
+        records
+    }
+
+    #[test]
+    fn test_replica_follows_the_primary_across_segments() {
+        let dir = TempDir::new().unwrap();
+        let primary = StorageEngine::open(
+            Options::new(dir.path().join("primary"))
+                .with_memtable_size(4096)
+                .with_wal_archive_dir(dir.path().join("archive")),
+        )
+        .unwrap();
+        primary.put(key(0), b"seed".to_vec()).unwrap();
+        primary
+            .create_checkpoint(dir.path().join("replica"))
+            .unwrap();
+        let replica =
+            StorageEngine::open(Options::new(dir.path().join("replica")).with_replica(true))
+                .unwrap();
+        assert_eq!(replica.last_timestamp(), primary.last_timestamp());
+
+        let mut tailer = primary.tail_wal(replica.last_timestamp() + 1).unwrap();
+        assert!(tailer.poll().unwrap().is_none());
+
+        // Small MemTables switch WAL segments many times over, and the
+        // archive keeps those flushed before they're shipped
+        for i in 1..500 {
+            primary.put(key(i), vec![b'v'; 32]).unwrap();
+            if i % 100 == 0 {
+                ship(&mut tailer, &replica);
+            }
+        }
+        let mut batch = WriteBatch::new();
+        batch.delete(key(1));
+        batch.put(key(2), b"batched".to_vec());
+        primary.write(batch, false).unwrap();
+        ship(&mut tailer, &replica);
+
+        assert_eq!(replica.last_timestamp(), primary.last_timestamp());
+        assert_eq!(
+            replica.scan::<[u8], _>(..).unwrap(),
+            primary.scan::<[u8], _>(..).unwrap()
+        );
+        assert_eq!(replica.get(&key(0)).unwrap(), Some(b"seed".to_vec()));
+
+        // Replicas only change through replication
+        assert!(replica.put(key(0), b"local".to_vec()).is_err());
+        assert!(primary.write_replicated(Vec::new(), false).is_err());
+    }
+
+    #[test]
+    fn test_replicated_writes_are_idempotent_and_ordered() {
+        let dir = TempDir::new().unwrap();
+        // A stopped clock makes the timestamps 1, 2, 3
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let primary =
+            StorageEngine::open(Options::new(dir.path().join("primary")).with_clock(clock))
+                .unwrap();
+        let replica =
+            StorageEngine::open(Options::new(dir.path().join("replica")).with_replica(true))
+                .unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(key(1), b"a".to_vec());
+        batch.put(key(2), b"b".to_vec());
+        primary.write(batch, false).unwrap();
+        primary.put(key(3), b"c".to_vec()).unwrap();
+
+        let mut tailer = primary.tail_wal(1).unwrap();
+        let first = tailer.poll().unwrap().unwrap();
+        let second = tailer.poll().unwrap().unwrap();
+        assert_eq!((first.len(), second.len()), (2, 1));
+
+        // Writes within a record can't go back in time
+        let reversed = vec![first[1].clone(), first[0].clone()];
+        assert!(replica.write_replicated(reversed, false).is_err());
+        assert_eq!(replica.write_replicated(first.clone(), false).unwrap(), 2);
+        assert_eq!(replica.write_replicated(first, false).unwrap(), 2);
+        assert_eq!(replica.write_replicated(second, false).unwrap(), 3);
+        assert_eq!(replica.get(&key(3)).unwrap(), Some(b"c".to_vec()));
+
+        // Starting in the middle of a batch returns the rest of it
+        let mut tailer = primary.tail_wal(2).unwrap();
+        let rest: Vec<_> = tailer.poll().unwrap().unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].key, key(2));
+        assert!(primary.tail_wal(5).is_err());
+
+        // Replicated writes survive a restart of the replica
+        drop(replica);
+        let replica =
+            StorageEngine::open(Options::new(dir.path().join("replica")).with_replica(true))
+                .unwrap();
+        assert_eq!(replica.last_timestamp(), 3);
+        assert_eq!(replica.get(&key(1)).unwrap(), Some(b"a".to_vec()));
+    }
+
+    #[test]
+    fn test_tailing_fails_once_flushed_segments_are_gone() {
+        let dir = TempDir::new().unwrap();
+        let primary = StorageEngine::open(Options::new(dir.path())).unwrap();
+        primary.put(key(1), b"a".to_vec()).unwrap();
+        primary.flush().unwrap();
+        primary.put(key(2), b"b".to_vec()).unwrap();
+
+        let mut tailer = primary.tail_wal(1).unwrap();
+        assert!(tailer.poll().is_err());
+
+        // An archive keeps the flushed segments available
+        let dir = TempDir::new().unwrap();
+        let primary = StorageEngine::open(
+            Options::new(dir.path()).with_wal_archive_dir(dir.path().join("archive")),
+        )
+        .unwrap();
+        primary.put(key(1), b"a".to_vec()).unwrap();
+        primary.flush().unwrap();
+        primary.put(key(2), b"b".to_vec()).unwrap();
+
+        let mut tailer = primary.tail_wal(1).unwrap();
+        assert_eq!(tailer.poll().unwrap().unwrap()[0].key, key(1));
+        assert_eq!(tailer.poll().unwrap().unwrap()[0].key, key(2));
+        assert!(tailer.poll().unwrap().is_none());
+        assert_eq!(tailer.next_timestamp(), primary.last_timestamp() + 1);
+    }
+
+    #[test]
+    fn test_tailers_read_recent_records_from_the_cache() {
+        let dir = TempDir::new().unwrap();
+        let primary = StorageEngine::open(
+            Options::new(dir.path().join("primary"))
+                .with_memtable_size(4096)
+                .with_wal_archive_dir(dir.path().join("archive"))
+                .with_wal_cache_size(16 * 1024),
+        )
+        .unwrap();
+        let replica =
+            StorageEngine::open(Options::new(dir.path().join("replica")).with_replica(true))
+                .unwrap();
+
+        // A tailer keeping up reads nothing but the cache, across segments
+        let first_segment = primary.inner.memtables.read().active_wal;
+        let mut tailer = primary.tail_wal(1).unwrap();
+        for i in 0..200 {
+            primary.put(key(i), vec![b'v'; 32]).unwrap();
+            if i % 10 == 0 {
+                ship(&mut tailer, &replica);
+            }
+        }
+        assert!(primary.inner.memtables.read().active_wal > first_segment);
+        let stats = primary.wal_cache_stats().unwrap();
+        assert_eq!(stats.misses, 0);
+        assert!(stats.hits > 0);
+
+        // One falling behind the cache reads the files until it catches up
+        for i in 200..1000 {
+            primary.put(key(i), vec![b'v'; 32]).unwrap();
+        }
+        let hits = primary.wal_cache_stats().unwrap().hits;
+        assert_eq!(ship(&mut tailer, &replica), 809);
+        let stats = primary.wal_cache_stats().unwrap();
+        assert!(stats.misses > 0);
+        assert!(stats.hits > hits);
+        assert!(stats.evictions > 0);
+        assert_eq!(replica.last_timestamp(), primary.last_timestamp());
+        assert_eq!(
+            replica.scan::<[u8], _>(..).unwrap(),
+            primary.scan::<[u8], _>(..).unwrap()
+        );
+
+        // Without an archive, evicted records of deleted segments are gone
+        let dir = TempDir::new().unwrap();
+        let primary =
+            StorageEngine::open(Options::new(dir.path()).with_wal_cache_size(1024)).unwrap();
+        let mut tailer = primary.tail_wal(1).unwrap();
+        for i in 0..100 {
+            primary.put(key(i), vec![b'v'; 32]).unwrap();
+        }
+        primary.flush().unwrap();
+        assert!(tailer.poll().is_err());
+        let mut tailer = primary.tail_wal(primary.last_timestamp()).unwrap();
+        assert_eq!(tailer.poll().unwrap().unwrap()[0].key, key(99));
+        assert!(tailer.poll().unwrap().is_none());
+    }
+}
@@ -0,0 +1,323 @@
+//! Background verification of table checksums
+//!
+//! Bit rot in a table nothing reads goes unnoticed until a read or a
+//! compaction finally hits it, by which time the backups from before the
+//! damage may be gone. With [`Options::with_scrub_interval`](super::Options::with_scrub_interval),
+//! a `scrub` job reads cold tables back in full every interval, checking
+//! every block against its checksum and the entries for their order:
+//!
+//! - A table is cold once it was already live at the job's previous run.
+//!   Tables flushed or compacted since are left for the next run, as
+//!   compaction is likely to rewrite them first
+//! - Reads are capped at `scrub_bytes_per_sec` and also charged to the
+//!   shared rate limiter at low priority, so scrubbing yields to flushes
+//! - A damaged table is counted in the [`Statistics`](super::Statistics),
+//!   passed to the [corruption handler](super::Options::with_corruption_handler),
+//!   and quarantined: the job never reads it again, so it is reported
+//!   once. The table stays live, reads of its damaged blocks fail, and
+//!   [`repair`](super::StorageEngine::repair) moves it aside
+//!
+//! [`scrub`](super::StorageEngine::scrub) verifies every table right away,
+//! cold or not.
+
+use super::column_family::ColumnFamilyData;
+use super::{EngineInner, Options};
+use crate::manifest::NUM_LEVELS;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::version::TableHandle;
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::error::ErrorCode;
+use ferrisdb_core::Result;
+
+use parking_lot::Mutex;
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Name of the background job scrubbing cold tables
+pub(super) const SCRUB_JOB: &str = "scrub";
+
+/// Bytes charged to the rate limiters at a time
+const SCRUB_CHUNK: u64 = 64 * 1024;
+
+/// Called with every table a scrub finds damaged
+pub(super) type CorruptionHandler = Arc<dyn Fn(&CorruptTable) + Send + Sync>;
+
+/// A table a scrub found damaged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptTable {
+    /// Name of the column family the table belongs to
+    pub column_family: String,
+    /// Path of the table file
+    pub path: PathBuf,
+    /// What reading the table back failed with
+    pub error: String,
+}
+
+/// Outcome of a scrub
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// Tables read back in full without finding damage
+    pub tables_verified: usize,
+    /// Bytes of the tables read back, damaged ones included
+    pub bytes_verified: u64,
+    /// Tables found damaged
+    pub corrupt: Vec<CorruptTable>,
+}
+
+/// What the scrubber remembers between runs
+pub(super) struct Scrubber {
+    /// Caps the scrubber's own reads
+    limiter: RateLimiter,
+    /// Filesystem tables are read through, with direct I/O where
+    /// compactions use it
+    vfs: Arc<dyn Vfs>,
+    /// Tables live at the job's last run, by family id and number
+    seen: Mutex<HashSet<(u32, u64)>>,
+    /// Tables found damaged, by family id and number
+    quarantined: Mutex<HashSet<(u32, u64)>>,
+    /// Held by a running scrub, so a manual one waits for the job's run
+    running: Mutex<()>,
+}
+
+impl Scrubber {
+    pub(super) fn new(options: &Options) -> Self {
+        let config = &options.config;
+        Self {
+            limiter: RateLimiter::new(config.scrub_bytes_per_sec),
+            vfs: vfs::with_direct_io(&options.vfs, config.use_direct_io_for_compaction),
+            seen: Mutex::new(HashSet::new()),
+            quarantined: Mutex::new(HashSet::new()),
+            running: Mutex::new(()),
+        }
+    }
+}
+
+impl EngineInner {
+    /// Body of the scrub job: verifies the tables that were live at its
+    /// previous run
+    ///
+    /// Never fails, so a table that can't be read doesn't stop the job
+    /// or the engine's writes; the error is logged and the next run tries
+    /// again.
+    pub(super) fn scrub_cold_tables(&self) -> Result<()> {
+        match self.scrub(true) {
+            Ok(report) => log::debug!(
+                "Scrubbed {} tables, {} bytes, {} corrupt",
+                report.tables_verified,
+                report.bytes_verified,
+                report.corrupt.len()
+            ),
+            Err(e) => log::warn!("Scrubbing tables failed: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Reads live tables back in full, only the cold ones if `cold_only`
+    ///
+    /// Stops early, with what was verified so far, once the engine shuts
+    /// down.
+    pub(super) fn scrub(&self, cold_only: bool) -> Result<ScrubReport> {
+        let _running = self.scrubber.running.lock();
+        let mut report = ScrubReport::default();
+
+        // Holding the versions keeps their tables on disk while they are read
+        let families: Vec<_> = self
+            .families()
+            .into_iter()
+            .map(|cf| {
+                let version = cf.versions.current();
+                (cf, version)
+            })
+            .collect();
+        let live: HashSet<_> = families
+            .iter()
+            .flat_map(|(cf, version)| {
+                (0..NUM_LEVELS)
+                    .flat_map(|level| version.files(level))
+                    .map(|table| (cf.id, table.meta().number))
+            })
+            .collect();
+        let seen = if cold_only {
+            std::mem::replace(&mut *self.scrubber.seen.lock(), live)
+        } else {
+            HashSet::new()
+        };
+
+        for (cf, version) in &families {
+            for table in (0..NUM_LEVELS).flat_map(|level| version.files(level)) {
+                let key = (cf.id, table.meta().number);
+                if cold_only
+                    && (!seen.contains(&key) || self.scrubber.quarantined.lock().contains(&key))
+                {
+                    continue;
+                }
+                if !self.throttle_scrub(table.meta().file_size) {
+                    return Ok(report);
+                }
+                self.scrub_table(cf, table, &mut report)?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Reads one table back, recording the outcome in `report`
+    fn scrub_table(
+        &self,
+        cf: &ColumnFamilyData,
+        table: &TableHandle,
+        report: &mut ScrubReport,
+    ) -> Result<()> {
+        let meta = table.meta();
+        report.bytes_verified += meta.file_size;
+        self.counters.record_scrub(meta.file_size);
+        let error = match cf.versions.verify_table(&self.scrubber.vfs, meta) {
+            Ok(()) => {
+                report.tables_verified += 1;
+                return Ok(());
+            }
+            Err(e) if e.code() == ErrorCode::Corruption => e,
+            Err(e) => return Err(e),
+        };
+
+        let corrupt = CorruptTable {
+            column_family: cf.name.clone(),
+            path: table.path().to_path_buf(),
+            error: error.to_string(),
+        };
+        if self
+            .scrubber
+            .quarantined
+            .lock()
+            .insert((cf.id, meta.number))
+        {
+            log::error!("Scrub found a corrupt table: {}", error);
+            self.counters.record_corrupt_table();
+            if let Some(handler) = &self.options.corruption_handler {
+                handler(&corrupt);
+            }
+            self.check_corruption(&error, Some(cf), "scrub");
+        }
+        report.corrupt.push(corrupt);
+        Ok(())
+    }
+
+    /// Waits until `bytes` may be read at the scrub rate, returning false
+    /// if the engine shuts down meanwhile
+    fn throttle_scrub(&self, bytes: u64) -> bool {
+        let mut left = bytes;
+        while left > 0 {
+            if self.background.lock().shutdown {
+                return false;
+            }
+            let chunk = left.min(SCRUB_CHUNK);
+            self.scrubber.limiter.request(chunk, IoPriority::Low);
+            self.rate_limiter.request(chunk, IoPriority::Low);
+            left -= chunk;
+        }
+        !self.background.lock().shutdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use super::*;
+    use tempfile::TempDir;
+
+    use std::time::Duration;
+
+    /// Flips the last byte of the first occurrence of `needle` in `path`
+    fn damage(path: &std::path::Path, needle: &[u8]) {
+        let mut file = std::fs::read(path).unwrap();
+        let at = file
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap();
+        file[at + needle.len() - 1] ^= 0x01;
+        std::fs::write(path, file).unwrap();
+    }
+
+    #[test]
+    fn test_scrub_reports_each_corrupt_table_once() {
+        let dir = TempDir::new().unwrap();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let options = Options::new(dir.path()).with_corruption_handler({
+            let reported = Arc::clone(&reported);
+            move |table| reported.lock().push(table.clone())
+        });
+        let engine = StorageEngine::open(options).unwrap();
+        engine.put(b"a".to_vec(), b"first table".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"b".to_vec(), b"second table".to_vec()).unwrap();
+        engine.flush().unwrap();
+
+        let report = engine.scrub().unwrap();
+        assert_eq!(report.tables_verified, 2);
+        assert!(report.corrupt.is_empty());
+
+        let version = engine.inner.default.versions.current();
+        let damaged = version
+            .files(0)
+            .iter()
+            .find(|table| table.meta().smallest.user_key == b"b")
+            .unwrap()
+            .path()
+            .to_path_buf();
+        damage(&damaged, b"second table");
+
+        for _ in 0..2 {
+            let report = engine.scrub().unwrap();
+            assert_eq!(report.tables_verified, 1);
+            assert_eq!(report.corrupt.len(), 1);
+            assert_eq!(report.corrupt[0].path, damaged);
+            assert_eq!(report.corrupt[0].column_family, "default");
+        }
+        assert_eq!(reported.lock().len(), 1);
+        let stats = engine.statistics();
+        assert_eq!(stats.scrub_corrupt_tables, 1);
+        assert_eq!(stats.scrubbed_tables, 6);
+    }
+
+    #[test]
+    fn test_scrub_job_only_reads_tables_live_since_its_last_run() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"old".to_vec(), b"cold".to_vec()).unwrap();
+        engine.flush().unwrap();
+
+        // The first run only notes which tables are live
+        let report = engine.inner.scrub(true).unwrap();
+        assert_eq!(report.tables_verified, 0);
+
+        engine.put(b"new".to_vec(), b"hot".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let report = engine.inner.scrub(true).unwrap();
+        assert_eq!(report.tables_verified, 1);
+        let report = engine.inner.scrub(true).unwrap();
+        assert_eq!(report.tables_verified, 2);
+    }
+
+    #[test]
+    fn test_scrub_job_runs_every_interval() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(
+            Options::new(dir.path()).with_scrub_interval(Duration::from_millis(10)),
+        )
+        .unwrap();
+        engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+        engine.flush().unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while engine.statistics().scrubbed_tables == 0 {
+            assert!(std::time::Instant::now() < deadline, "scrub never ran");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(engine
+            .background_jobs()
+            .iter()
+            .any(|job| job.name == SCRUB_JOB));
+        engine.close().unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Engines that read a database another engine writes
+//!
+//! An analytics job reading a live database must not take its directory
+//! lock or change its files. Engines opened with
+//! [`open_read_only`](super::StorageEngine::open_read_only) or
+//! [`open_as_secondary`](super::StorageEngine::open_as_secondary) leave
+//! the directory to the primary engine writing it:
+//!
+//! - The MANIFESTs are only read, and dropping a version or a column
+//!   family never deletes a file
+//! - Unflushed WAL segments are replayed into MemTables instead of level 0
+//!   tables, and nothing is truncated or retired; an entry the primary is
+//!   still appending ends the replay
+//! - No flush, compaction or scrub job runs, and every write fails
+//!
+//! A secondary catches up by doing the same again: it re-reads the
+//! MANIFESTs, opens the column families created since and drops the
+//! dropped ones, and replays the WAL into fresh MemTables, which replace
+//! the old ones at once. Listing the WAL and reading the MANIFESTs race
+//! with the primary's flushes, which retire segments once the MANIFEST no
+//! longer needs them, so a replay that finds a segment gone starts over.
+//!
+//! The primary deletes the tables its compactions replace as soon as it
+//! no longer reads them, so a secondary reading an old version may find a
+//! table gone; its next catch-up moves it on to the primary's tables.
+
+use super::column_family::ColumnFamilyData;
+use super::recovery::{replay_read_only, wal_segments_in, RecoveryReport};
+use super::{
+    open_column_family, EngineInner, ImmutableMemTable, MemTables, Options,
+    DEFAULT_COLUMN_FAMILY_ID,
+};
+use crate::scheduler::Schedule;
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::collections::{btree_map, BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Name of the background job catching a secondary up with its primary
+pub(super) const CATCH_UP_JOB: &str = "catch_up";
+
+/// Replays tried before a segment going missing under each is an error
+const REPLAY_ATTEMPTS: usize = 3;
+
+/// How an engine was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum OpenMode {
+    /// Owns the database: locks it, writes, flushes and compacts
+    Primary,
+    /// Reads the database as it was when opened
+    ReadOnly,
+    /// Reads the database and follows the primary writing it
+    Secondary,
+}
+
+/// Replays the unflushed WAL into MemTables without changing any file
+///
+/// If the replay fails, the primary may have retired a segment after the
+/// MANIFESTs were read, so they are read again and the replay retried.
+pub(super) fn replay_wal(
+    options: &Options,
+    families: &BTreeMap<u32, Arc<ColumnFamilyData>>,
+) -> Result<(RecoveryReport, MemTables)> {
+    let mut attempt = 1;
+    loop {
+        let segments = wal_segments_in(&options.vfs, &options.config.wal_dir)?;
+        let error = match replay_read_only(options, families, &segments) {
+            Ok(replayed) => {
+                let mut active = replayed.active;
+                let memtables = MemTables {
+                    active: families
+                        .values()
+                        .map(|cf| {
+                            let memtable = active.remove(&cf.id);
+                            (cf.id, memtable.unwrap_or_else(|| cf.new_memtable()))
+                        })
+                        .collect(),
+                    active_wal: segments.last().map_or(0, |&(number, _)| number),
+                    immutable: replayed
+                        .retired
+                        .into_iter()
+                        .map(|(id, memtable)| ImmutableMemTable {
+                            memtables: BTreeMap::from([(id, memtable)]),
+                            // Never flushed, so never matched against a segment
+                            wal_number: 0,
+                            last_timestamp: 0,
+                        })
+                        .collect::<VecDeque<_>>(),
+                    flushed_wals: VecDeque::new(),
+                };
+                return Ok((replayed.report, memtables));
+            }
+            Err(e) if attempt < REPLAY_ATTEMPTS => e,
+            Err(e) => return Err(e),
+        };
+
+        log::debug!("Replaying the WAL read-only failed, retrying: {}", error);
+        for cf in families.values() {
+            cf.versions.refresh()?;
+        }
+        attempt += 1;
+    }
+}
+
+impl EngineInner {
+    /// Spawns the job catching a secondary up every catch-up interval, if
+    /// it has one
+    pub(super) fn start_catch_up_job(inner: &Arc<Self>) -> Result<()> {
+        let interval = inner.options.config.catch_up_interval_ms;
+        if interval == 0 {
+            return Ok(());
+        }
+        let weak = Arc::downgrade(inner);
+        inner.scheduler.spawn(
+            CATCH_UP_JOB,
+            Schedule::Every(Duration::from_millis(interval)),
+            move || {
+                if let Some(inner) = weak.upgrade() {
+                    // The next run tries again; failing would stop the job
+                    if let Err(e) = inner.catch_up_with_primary() {
+                        log::warn!("Catching up with the primary failed: {}", e);
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Makes everything the primary logged so far visible, returning the
+    /// new last timestamp
+    pub(super) fn catch_up_with_primary(&self) -> Result<Timestamp> {
+        self.check_open()?;
+        if self.mode != OpenMode::Secondary {
+            return Err(Error::InvalidOperation(
+                "Only secondary engines catch up with a primary".to_string(),
+            ));
+        }
+        // Serializes catch-ups as the writer serializes writes
+        let _writer = self.writer.lock();
+
+        self.refresh_column_families()?;
+        let families = self.column_families.read().clone();
+        let (report, memtables) = replay_wal(&self.options, &families)?;
+        // The refreshed tables hold everything the old MemTables did
+        // before these replace them
+        *self.memtables.write() = memtables;
+        self.oracle.publish(report.last_timestamp);
+        Ok(self.oracle.last())
+    }
+
+    /// Re-reads every family's MANIFEST, then opens the families the
+    /// primary created and drops those it dropped
+    fn refresh_column_families(&self) -> Result<()> {
+        for cf in self.families() {
+            cf.versions.refresh()?;
+        }
+        let registered = self
+            .default
+            .versions
+            .manifest_state()
+            .column_families()
+            .clone();
+
+        let mut families = self.column_families.write();
+        families.retain(|id, cf| {
+            let live = *id == DEFAULT_COLUMN_FAMILY_ID || registered.contains_key(id);
+            if !live {
+                log::info!("Column family {:?} was dropped by the primary", cf.name);
+                cf.mark_dropped();
+            }
+            live
+        });
+        for (id, name) in registered {
+            if let btree_map::Entry::Vacant(entry) = families.entry(id) {
+                let cf = open_column_family(&self.options, id, &name, &self.rate_limiter, true)?;
+                log::info!("Column family {:?} was created by the primary", name);
+                entry.insert(Arc::new(cf));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine};
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_only_engine_sees_unflushed_writes_and_rejects_changes() {
+        let dir = TempDir::new().unwrap();
+        let primary = StorageEngine::open(Options::new(dir.path())).unwrap();
+        primary.put(b"flushed".to_vec(), b"1".to_vec()).unwrap();
+        primary.flush().unwrap();
+        primary.put(b"logged".to_vec(), b"2".to_vec()).unwrap();
+
+        let reader = StorageEngine::open_read_only(Options::new(dir.path())).unwrap();
+        assert_eq!(reader.get(b"flushed").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(reader.get(b"logged").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(reader.last_timestamp(), primary.last_timestamp());
+        assert_eq!(reader.recovery_report().entries_replayed, 1);
+
+        for result in [
+            reader.put(b"key".to_vec(), b"value".to_vec()),
+            reader.flush(),
+            reader.compact_range::<[u8], _>(..),
+            reader
+                .create_column_family("index", ColumnFamilyOptions::default())
+                .map(|_| ()),
+        ] {
+            assert!(matches!(result, Err(Error::InvalidOperation(_))));
+        }
+        assert!(matches!(
+            reader.catch_up_with_primary(),
+            Err(Error::InvalidOperation(_))
+        ));
+        assert!(reader.background_jobs().is_empty());
+
+        // The primary keeps its lock and WAL, and loses nothing
+        reader.close().unwrap();
+        primary.put(b"after".to_vec(), b"3".to_vec()).unwrap();
+        drop(primary);
+        let primary = StorageEngine::open(Options::new(dir.path())).unwrap();
+        assert_eq!(primary.get(b"logged").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(primary.get(b"after").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn test_read_only_open_requires_a_database() {
+        let dir = TempDir::new().unwrap();
+        let result = StorageEngine::open_read_only(Options::new(dir.path().join("missing")));
+        assert!(matches!(result.err().unwrap().root(), Error::NotFound(_)));
+        assert!(!dir.path().join("missing").exists());
+    }
+
+    #[test]
+    fn test_secondary_follows_flushes_and_column_families() {
+        let dir = TempDir::new().unwrap();
+        let primary =
+            StorageEngine::open(Options::new(dir.path()).with_memtable_size(4 * 1024)).unwrap();
+        primary.put(b"first".to_vec(), b"1".to_vec()).unwrap();
+        let secondary = StorageEngine::open_as_secondary(Options::new(dir.path())).unwrap();
+
+        let index = primary
+            .create_column_family("index", ColumnFamilyOptions::default())
+            .unwrap();
+        for i in 0..200 {
+            let key = format!("key{:04}", i).into_bytes();
+            primary.put(key.clone(), vec![b'v'; 64]).unwrap();
+            primary.put_cf(&index, key, b"i".to_vec()).unwrap();
+        }
+        primary.flush().unwrap();
+        primary.put(b"last".to_vec(), b"2".to_vec()).unwrap();
+        assert_eq!(secondary.get(b"last").unwrap(), None);
+
+        let last = secondary.catch_up_with_primary().unwrap();
+        assert_eq!(last, primary.last_timestamp());
+        assert_eq!(secondary.get(b"first").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(secondary.get(b"last").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(secondary.scan::<[u8], _>(..).unwrap().len(), 202);
+        let secondary_index = secondary.cf_handle("index").unwrap();
+        assert_eq!(
+            secondary.get_cf(&secondary_index, b"key0199").unwrap(),
+            Some(b"i".to_vec())
+        );
+
+        primary.drop_column_family(&index).unwrap();
+        secondary.catch_up_with_primary().unwrap();
+        assert!(secondary.cf_handle("index").is_none());
+        assert!(secondary.get_cf(&secondary_index, b"key0199").is_err());
+    }
+
+    #[test]
+    fn test_secondary_catches_up_every_interval() {
+        let dir = TempDir::new().unwrap();
+        let primary = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let secondary = StorageEngine::open_as_secondary(
+            Options::new(dir.path()).with_catch_up_interval(Duration::from_millis(10)),
+        )
+        .unwrap();
+        primary.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while secondary.get(b"key").unwrap().is_none() {
+            assert!(std::time::Instant::now() < deadline, "never caught up");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(secondary
+            .background_jobs()
+            .iter()
+            .any(|job| job.name == CATCH_UP_JOB));
+    }
+}
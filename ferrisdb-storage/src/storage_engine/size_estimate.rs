@@ -0,0 +1,231 @@
+//! Estimates of how much data and how many keys a key range holds
+//!
+//! Splitting a database into shards, or a shard in two, needs to know
+//! where the data lies without reading it. The estimates here come from
+//! the tables' index blocks, which every open reader holds in memory, and
+//! the entry counts in their properties:
+//!
+//! - The bytes of a table in a range run from the first data block at or
+//!   after its start to the first at or after its end, so they are off by
+//!   up to a block at either end
+//! - A table's keys in a range are its entry count scaled by the share of
+//!   its data bytes in the range
+//!
+//! Both count every version and tombstone, not just live keys.
+//!
+//! [`split_keys`](super::StorageEngine::split_keys) picks keys dividing a
+//! range into spans of about equal data, for work such as an export to
+//! split over threads. Only the first keys of data blocks are candidates,
+//! so spans are even to within about a block per table.
+
+use super::column_family::ColumnFamilyData;
+use super::{overlaps_range, range_contains, EngineInner, KeyRange};
+use crate::manifest::NUM_LEVELS;
+use crate::sstable::SSTableReader;
+use ferrisdb_core::{Key, Result};
+
+use std::ops::{Bound, Range};
+
+/// Returns roughly the span of a table's data that holds keys in `range`
+fn data_in_range(reader: &SSTableReader, range: &KeyRange) -> Range<u64> {
+    let data = reader.data_range();
+    let start = match &range.0 {
+        Bound::Included(key) | Bound::Excluded(key) => reader.approximate_offset_of(key),
+        Bound::Unbounded => data.start,
+    };
+    let end = match &range.1 {
+        Bound::Included(key) | Bound::Excluded(key) => reader.approximate_offset_of(key),
+        Bound::Unbounded => data.end,
+    };
+    start..end.max(start)
+}
+
+impl EngineInner {
+    /// Returns roughly how many bytes of SSTable data each range holds
+    pub(super) fn approximate_sizes(
+        &self,
+        cf: &ColumnFamilyData,
+        ranges: &[KeyRange],
+    ) -> Result<Vec<u64>> {
+        let mut sizes = vec![0; ranges.len()];
+        let version = cf.versions.current();
+        for level in 0..NUM_LEVELS {
+            for table in version.files(level) {
+                let mut reader = None;
+                for (range, size) in ranges.iter().zip(&mut sizes) {
+                    if !overlaps_range(&*cf.comparator, table, range) {
+                        continue;
+                    }
+                    let reader = match &mut reader {
+                        Some(reader) => reader,
+                        None => reader.insert(cf.open_table(table)?),
+                    };
+                    let bytes = data_in_range(reader, range);
+                    *size += bytes.end - bytes.start;
+                }
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Returns roughly how many entries a range holds in the SSTables,
+    /// plus the exact number in the MemTables
+    pub(super) fn estimate_num_keys(&self, cf: &ColumnFamilyData, range: &KeyRange) -> Result<u64> {
+        let mut keys = 0;
+        let version = cf.versions.current();
+        for level in 0..NUM_LEVELS {
+            for table in version.files(level) {
+                if !overlaps_range(&*cf.comparator, table, range) {
+                    continue;
+                }
+                let reader = cf.open_table(table)?;
+                let data = reader.data_range();
+                let bytes = data_in_range(&reader, range);
+                let entries = table.meta().entry_count;
+                if data.is_empty() || bytes.start == data.start && bytes.end == data.end {
+                    keys += entries;
+                } else {
+                    let share = (bytes.end - bytes.start) as f64 / (data.end - data.start) as f64;
+                    keys += (entries as f64 * share).round() as u64;
+                }
+            }
+        }
+
+        let memtables = self.memtables.read().newest_first(cf.id);
+        for memtable in memtables {
+            keys += memtable.entries(range.clone()).count() as u64;
+        }
+        Ok(keys)
+    }
+
+    /// Returns up to `shards - 1` keys within `range`, in order, splitting
+    /// the tables' data in it into spans of about equal size
+    pub(super) fn split_keys(
+        &self,
+        cf: &ColumnFamilyData,
+        range: &KeyRange,
+        shards: usize,
+    ) -> Result<Vec<Key>> {
+        let comparator = &*cf.comparator;
+        let mut blocks = Vec::new();
+        let version = cf.versions.current();
+        for level in 0..NUM_LEVELS {
+            for table in version.files(level) {
+                if !overlaps_range(comparator, table, range) {
+                    continue;
+                }
+                let reader = cf.open_table(table)?;
+                for (key, size) in reader.data_blocks() {
+                    // Splitting at the start would leave the first span empty
+                    let at_start = matches!(&range.0, Bound::Included(start) if start == key);
+                    if range_contains(comparator, range, key) && !at_start {
+                        blocks.push((key.to_vec(), size));
+                    }
+                }
+            }
+        }
+        blocks.sort_by(|a, b| comparator.compare(&a.0, &b.0));
+
+        let total: u64 = blocks.iter().map(|(_, size)| size).sum();
+        let shards = shards as u64;
+        let mut splits: Vec<Key> = Vec::new();
+        let mut before = 0;
+        for (key, size) in blocks {
+            if splits.len() as u64 + 1 >= shards {
+                break;
+            }
+            // Split once the data before the key fills the next span
+            let due = before * shards >= total * (splits.len() as u64 + 1);
+            if due && before > 0 && splits.last() != Some(&key) {
+                splits.push(key);
+            }
+            before += size;
+        }
+        Ok(splits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use tempfile::TempDir;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    /// Opens an engine holding keys `0..count` in one flushed table
+    fn engine_with_table(dir: &TempDir, count: usize) -> StorageEngine {
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        for i in 0..count {
+            engine.put(key(i), vec![b'v'; 100]).unwrap();
+        }
+        engine.flush().unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_approximate_sizes_split_the_data() {
+        let dir = TempDir::new().unwrap();
+        let engine = engine_with_table(&dir, 2000);
+
+        let sizes = engine
+            .approximate_sizes(&[
+                key(0)..key(1000),
+                key(1000)..key(2000),
+                key(0)..key(2000),
+                key(3000)..key(4000),
+            ])
+            .unwrap();
+        let (first, second, all) = (sizes[0], sizes[1], sizes[2]);
+        assert!(all > 2000 * 100, "{all}");
+        assert_eq!(first + second, all);
+        assert!(first.abs_diff(second) < all / 10, "{first} vs {second}");
+        assert_eq!(sizes[3], 0);
+
+        let everything = engine.approximate_sizes::<&[u8], _>(&[..]).unwrap();
+        assert!(everything[0] >= all);
+    }
+
+    #[test]
+    fn test_split_keys_divide_the_data_evenly() {
+        let dir = TempDir::new().unwrap();
+        let engine = engine_with_table(&dir, 2000);
+
+        let splits = engine.split_keys::<&[u8], _>(.., 4).unwrap();
+        assert_eq!(splits.len(), 3);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]));
+        let mut bounds = vec![key(0)];
+        bounds.extend(splits.iter().cloned());
+        bounds.push(key(2000));
+        let sizes = engine
+            .approximate_sizes(
+                &bounds
+                    .windows(2)
+                    .map(|pair| pair[0].clone()..pair[1].clone())
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+        let (smallest, largest) = (sizes.iter().min().unwrap(), sizes.iter().max().unwrap());
+        assert!(largest - smallest < largest / 5, "{sizes:?}");
+
+        let splits = engine.split_keys(key(500)..key(1000), 2).unwrap();
+        assert_eq!(splits.len(), 1);
+        assert!(key(500) < splits[0] && splits[0] < key(1000));
+        assert!(engine.split_keys::<&[u8], _>(.., 1).unwrap().is_empty());
+        assert!(engine.split_keys(key(3000).., 8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_estimate_num_keys_counts_tables_and_memtables() {
+        let dir = TempDir::new().unwrap();
+        let engine = engine_with_table(&dir, 2000);
+        engine.put(key(2500), b"v".to_vec()).unwrap();
+
+        assert_eq!(engine.estimate_num_keys::<&[u8], _>(..).unwrap(), 2001);
+        let half = engine.estimate_num_keys(key(0)..key(1000)).unwrap();
+        assert!(half.abs_diff(1000) < 100, "{half}");
+        assert_eq!(engine.estimate_num_keys(key(2100)..key(3000)).unwrap(), 1);
+        assert_eq!(engine.estimate_num_keys(key(3000)..).unwrap(), 0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Log of slow operations
+//!
+//! With [`Options::with_slow_log_threshold`](super::Options::with_slow_log_threshold)
+//! set, reads, writes, scans, WAL syncs and compactions taking at least
+//! the threshold are kept in a bounded in-memory log, which
+//! [`StorageEngine::slow_log`](super::StorageEngine::slow_log) returns.
+//! Each entry says where the time went:
+//!
+//! ```text
+//! get        cf=default key=user:42 took 12.4ms (wal sync 0ns, block reads 3, cache hits 1)
+//! write      cf=default key=order:7 took 35.1ms (wal sync 34.8ms, block reads 0, cache hits 0)
+//! ```
+//!
+//! The breakdown is gathered on the thread running the operation: the
+//! block loads of the tables it reads and the sync of its WAL record are
+//! added up while it runs. Only operations that succeed are logged.
+//!
+//! Every operation is timed whether or not the log is on, since the same
+//! measurement feeds the latency histograms of
+//! [`Statistics`](super::Statistics).
+
+use super::column_family::ColumnFamilyData;
+use super::EngineInner;
+use crate::StorageConfig;
+use ferrisdb_core::{Key, Result};
+
+use parking_lot::Mutex;
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// Breakdown of the operation running on this thread
+    static BREAKDOWN: Cell<Breakdown> = const { Cell::new(Breakdown::ZERO) };
+}
+
+/// What a slow operation was
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SlowOperationKind {
+    /// A point read: `get`, `get_pinned` or `multi_get`
+    Get,
+    /// A put, delete, merge or batch of them
+    Write,
+    /// A range scan
+    Scan,
+    /// An explicit sync of the WAL
+    Sync,
+    /// A compaction, background or manual
+    Compaction,
+}
+
+impl SlowOperationKind {
+    /// Returns the lowercase name of the kind
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlowOperationKind::Get => "get",
+            SlowOperationKind::Write => "write",
+            SlowOperationKind::Scan => "scan",
+            SlowOperationKind::Sync => "sync",
+            SlowOperationKind::Compaction => "compaction",
+        }
+    }
+}
+
+impl fmt::Display for SlowOperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// An operation that took at least the slow log threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowOperation {
+    /// What the operation was
+    pub kind: SlowOperationKind,
+    /// Column family it worked on; `None` for syncs
+    pub column_family: Option<String>,
+    /// The key read or written, or the first of several or of a scanned
+    /// range; `None` for syncs and compactions, or when keys are redacted
+    pub key: Option<Key>,
+    /// Length of the key, kept when the key itself is redacted
+    pub key_len: usize,
+    /// When the operation started, since the Unix epoch by the engine's
+    /// clock
+    pub started_at: Duration,
+    /// How long the operation took
+    pub duration: Duration,
+    /// Part of the duration spent syncing the WAL
+    pub wal_sync_time: Duration,
+    /// Table blocks read from their files
+    pub block_reads: u64,
+    /// Table blocks served from the block cache
+    pub block_cache_hits: u64,
+}
+
+impl fmt::Display for SlowOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<10}", self.kind)?;
+        if let Some(cf) = &self.column_family {
+            write!(f, " cf={}", cf)?;
+        }
+        match &self.key {
+            Some(key) => write!(f, " key={}", key.escape_ascii())?,
+            None if self.key_len > 0 => write!(f, " key=<{} bytes>", self.key_len)?,
+            None => {}
+        }
+        write!(
+            f,
+            " took {:?} (wal sync {:?}, block reads {}, cache hits {})",
+            self.duration, self.wal_sync_time, self.block_reads, self.block_cache_hits
+        )
+    }
+}
+
+/// Work done by the operation running on a thread so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Breakdown {
+    wal_sync_time: Duration,
+    block_reads: u64,
+    block_cache_hits: u64,
+}
+
+impl Breakdown {
+    const ZERO: Breakdown = Breakdown {
+        wal_sync_time: Duration::ZERO,
+        block_reads: 0,
+        block_cache_hits: 0,
+    };
+
+    fn add(self, other: Breakdown) -> Breakdown {
+        Breakdown {
+            wal_sync_time: self.wal_sync_time + other.wal_sync_time,
+            block_reads: self.block_reads + other.block_reads,
+            block_cache_hits: self.block_cache_hits + other.block_cache_hits,
+        }
+    }
+}
+
+/// Adds block loads to the breakdown of the operation running on this
+/// thread
+pub(super) fn note_block_loads(hits: u64, misses: u64) {
+    BREAKDOWN.with(|breakdown| {
+        breakdown.set(breakdown.get().add(Breakdown {
+            block_reads: misses,
+            block_cache_hits: hits,
+            ..Breakdown::ZERO
+        }))
+    });
+}
+
+/// Adds a WAL sync to the breakdown of the operation running on this
+/// thread
+pub(super) fn note_wal_sync(duration: Duration) {
+    BREAKDOWN.with(|breakdown| {
+        breakdown.set(breakdown.get().add(Breakdown {
+            wal_sync_time: duration,
+            ..Breakdown::ZERO
+        }))
+    });
+}
+
+/// The slow operations of an engine, newest last
+pub(super) struct SlowLog {
+    threshold: Option<Duration>,
+    capacity: usize,
+    redact_keys: bool,
+    entries: Mutex<VecDeque<SlowOperation>>,
+}
+
+impl SlowLog {
+    pub(super) fn new(config: &StorageConfig) -> Self {
+        let threshold = (config.slow_log_threshold_us > 0 && config.slow_log_capacity > 0)
+            .then(|| Duration::from_micros(config.slow_log_threshold_us));
+        Self {
+            threshold,
+            capacity: config.slow_log_capacity,
+            redact_keys: config.slow_log_redact_keys,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Returns whether operations are logged at all
+    pub(super) fn is_enabled(&self) -> bool {
+        self.threshold.is_some()
+    }
+
+    /// Returns the logged operations, newest first
+    pub(super) fn entries(&self) -> Vec<SlowOperation> {
+        self.entries.lock().iter().rev().cloned().collect()
+    }
+
+    pub(super) fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
+    fn push(&self, entry: SlowOperation) {
+        let mut entries = self.entries.lock();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+impl EngineInner {
+    /// Runs an operation, recording its latency and logging it if it
+    /// succeeds in no less than the slow log threshold
+    ///
+    /// Operations nested in another, such as the reads of a write
+    /// maintaining a secondary index, add their breakdown to the outer
+    /// one's.
+    pub(super) fn timed<T>(
+        &self,
+        kind: SlowOperationKind,
+        cf: Option<&ColumnFamilyData>,
+        key: Option<&[u8]>,
+        operation: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let outer = BREAKDOWN.with(|breakdown| breakdown.replace(Breakdown::ZERO));
+        let started = Instant::now();
+        let result = operation();
+        let elapsed = started.elapsed();
+        let breakdown = BREAKDOWN.with(|current| {
+            let breakdown = current.get();
+            current.set(outer.add(breakdown));
+            breakdown
+        });
+        let value = result.inspect_err(|e| {
+            // Compactions fail the engine, which reports the damage
+            if kind != SlowOperationKind::Compaction {
+                self.check_corruption(e, cf, kind.as_str());
+            }
+        })?;
+
+        self.counters.record_latency(kind, elapsed);
+        if self
+            .slow_log
+            .threshold
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            let key_len = key.map_or(0, <[u8]>::len);
+            self.slow_log.push(SlowOperation {
+                kind,
+                column_family: cf.map(|cf| cf.name.clone()),
+                key: key
+                    .filter(|_| !self.slow_log.redact_keys)
+                    .map(<[u8]>::to_vec),
+                key_len,
+                started_at: self.options.clock.now().saturating_sub(elapsed),
+                duration: elapsed,
+                wal_sync_time: breakdown.wal_sync_time,
+                block_reads: breakdown.block_reads,
+                block_cache_hits: breakdown.block_cache_hits,
+            });
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine, WriteBatch};
+    use super::*;
+    use ferrisdb_core::SyncMode;
+    use tempfile::TempDir;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_slow_operations_are_logged_with_their_breakdown() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path())
+            .with_sync_mode(SyncMode::Full)
+            .with_slow_log_threshold(Duration::from_nanos(1_000))
+            .with_slow_log_capacity(1000);
+        let engine = StorageEngine::open(options).unwrap();
+
+        for i in 0..50 {
+            engine.put(key(i), vec![b'v'; 100]).unwrap();
+        }
+        engine.flush().unwrap();
+        assert!(engine.get(&key(7)).unwrap().is_some());
+        engine.scan(key(10)..key(20)).unwrap();
+        engine.sync_wal().unwrap();
+
+        let log = engine.slow_log();
+        // Newest first
+        assert_eq!(log[0].kind, SlowOperationKind::Sync);
+        assert_eq!(log[0].column_family, None);
+        assert!(log[0].wal_sync_time > Duration::ZERO);
+
+        let scan = log
+            .iter()
+            .find(|op| op.kind == SlowOperationKind::Scan)
+            .unwrap();
+        assert_eq!(scan.key.as_deref(), Some(&key(10)[..]));
+        assert!(scan.block_reads + scan.block_cache_hits > 0);
+
+        let get = log
+            .iter()
+            .find(|op| op.kind == SlowOperationKind::Get)
+            .unwrap();
+        assert_eq!(get.key.as_deref(), Some(&key(7)[..]));
+        assert_eq!(get.column_family.as_deref(), Some("default"));
+        assert!(get.block_reads + get.block_cache_hits > 0);
+        assert!(get
+            .to_string()
+            .starts_with("get        cf=default key=key00007 took"));
+
+        // Fully synced writes spend time syncing the WAL
+        let write = log.last().unwrap();
+        assert_eq!(write.kind, SlowOperationKind::Write);
+        assert_eq!(write.key.as_deref(), Some(&key(0)[..]));
+        assert!(write.wal_sync_time > Duration::ZERO);
+        assert!(write.wal_sync_time <= write.duration);
+        assert_eq!(write.block_reads, 0);
+
+        engine.clear_slow_log();
+        assert!(engine.slow_log().is_empty());
+    }
+
+    #[test]
+    fn test_slow_log_redacts_keys_and_keeps_the_newest() {
+        let dir = TempDir::new().unwrap();
+        let options = Options::new(dir.path())
+            .with_slow_log_threshold(Duration::from_nanos(1_000))
+            .with_slow_log_capacity(2)
+            .with_slow_log_redact_keys(true);
+        let engine = StorageEngine::open(options).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"secret".to_vec(), b"v".to_vec());
+        for _ in 0..3 {
+            engine.write(batch.clone(), true).unwrap();
+        }
+        let log = engine.slow_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].key, None);
+        assert_eq!(log[0].key_len, 6);
+        assert!(log[0].to_string().contains("key=<6 bytes>"));
+        assert!(!log[0].to_string().contains("secret"));
+    }
+
+    #[test]
+    fn test_slow_log_is_off_by_default() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(key(0), b"v".to_vec()).unwrap();
+        engine.sync_wal().unwrap();
+        assert!(engine.slow_log().is_empty());
+        // Latencies are measured all the same
+        assert_eq!(engine.statistics().write_latency.count, 1);
+    }
+}
@@ -0,0 +1,629 @@
+//! Consistent point-in-time reads
+//!
+//! Every version carries the timestamp of the write that created it, so a
+//! read at timestamp `t` sees exactly the writes committed up to `t`. A
+//! [`Snapshot`] keeps such a timestamp for as long as it is held:
+//!
+//! ```text
+//!  ts:  1        2        3         4
+//!       put a=1  put b=1  snapshot  put a=2, delete b
+//!                            │
+//!                            └─ sees a=1, b=1 until dropped
+//! ```
+//!
+//! Compaction normally keeps only the newest version of each key, which
+//! would remove the versions a snapshot still reads. The engine therefore
+//! tracks all pinned timestamps in a [`SnapshotList`] and compacts against
+//! all of them: between two pinned timestamps, only the versions the newer
+//! one reads are kept. A bottommost compaction drops a tombstone once no
+//! pinned timestamp reads an older version of its key, so a long-held
+//! snapshot keeps both the deleted value and the tombstone on disk until
+//! it is dropped.
+//!
+//! # Time Travel
+//!
+//! With a [version retention](super::Options::with_version_retention)
+//! set, compaction also keeps every version written within that window,
+//! and the version each key had at its start. Any timestamp from the
+//! window's start on can then be read at, as with
+//! [`get_at`](super::StorageEngine::get_at) or
+//! [`snapshot_at`](super::StorageEngine::snapshot_at). The list remembers
+//! the latest window start a compaction was told about and refuses to pin
+//! older timestamps, whose versions may be gone, unless a snapshot already
+//! holds them.
+
+use super::column_family::ColumnFamily;
+use super::{EngineInner, KeyRange};
+use crate::oracle::TimestampOracle;
+use ferrisdb_core::{Error, Key, Result, Timestamp, Value};
+
+use parking_lot::Mutex;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Timestamps pinned by snapshots and in-flight reads
+///
+/// Compaction may only drop versions that no reader can see anymore, so it
+/// asks for the [`timestamps`](Self::timestamps) pinned. Pinning reads the
+/// last committed timestamp under the same lock, so a read either shows up
+/// among them or starts at a timestamp no older than what compaction was
+/// told.
+#[derive(Debug)]
+pub(super) struct SnapshotList {
+    /// Pinned timestamp -> number of readers at it, and the oldest
+    /// timestamp compaction has kept every version for
+    pinned: Mutex<(BTreeMap<Timestamp, usize>, Timestamp)>,
+    /// How long versions stay readable by timestamp
+    retention: Duration,
+}
+
+impl SnapshotList {
+    /// Creates a list keeping the versions written within `retention`
+    /// readable, on top of those the snapshots read
+    pub(super) fn new(oracle: &TimestampOracle, retention: Duration) -> Self {
+        let list = Self {
+            pinned: Mutex::new((BTreeMap::new(), 0)),
+            retention,
+        };
+        list.pinned.lock().1 = list.horizon(oracle);
+        list
+    }
+
+    /// Returns the oldest timestamp the retention window keeps readable
+    fn horizon(&self, oracle: &TimestampOracle) -> Timestamp {
+        if self.retention.is_zero() {
+            oracle.last()
+        } else {
+            oracle.issued_within(self.retention)
+        }
+    }
+
+    /// Pins the last committed timestamp until the guard is dropped
+    pub(super) fn pin(&self, oracle: &TimestampOracle) -> PinnedTimestamp<'_> {
+        PinnedTimestamp {
+            list: self,
+            timestamp: self.acquire(oracle),
+        }
+    }
+
+    /// Pins the last committed timestamp until [`release`](Self::release)
+    fn acquire(&self, oracle: &TimestampOracle) -> Timestamp {
+        let (pinned, _) = &mut *self.pinned.lock();
+        let timestamp = oracle.last();
+        *pinned.entry(timestamp).or_insert(0) += 1;
+        timestamp
+    }
+
+    /// Pins `timestamp` until [`release`](Self::release), if compaction
+    /// still keeps every version it reads: it is in the retention window
+    /// or already pinned
+    fn acquire_at(&self, oracle: &TimestampOracle, timestamp: Timestamp) -> Result<Timestamp> {
+        let (pinned, horizon) = &mut *self.pinned.lock();
+        let last = oracle.last();
+        if timestamp > last {
+            return Err(Error::InvalidArgument(format!(
+                "Timestamp {} is newer than the last committed write at {}",
+                timestamp, last
+            )));
+        }
+        if timestamp < *horizon && !pinned.contains_key(&timestamp) {
+            return Err(Error::InvalidArgument(format!(
+                "Timestamp {} is older than the versions kept since {}",
+                timestamp, horizon
+            )));
+        }
+        *pinned.entry(timestamp).or_insert(0) += 1;
+        Ok(timestamp)
+    }
+
+    fn release(&self, timestamp: Timestamp) {
+        let (pinned, _) = &mut *self.pinned.lock();
+        if let Some(count) = pinned.get_mut(&timestamp) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(&timestamp);
+            }
+        }
+    }
+
+    /// Returns every pinned timestamp and the start of the retention
+    /// window, oldest first
+    ///
+    /// Future readers may read at the window's start or anything newer;
+    /// without a retention window, that is the last committed timestamp.
+    /// The start never moves back, even if the clock does.
+    pub(super) fn timestamps(&self, oracle: &TimestampOracle) -> Vec<Timestamp> {
+        let (pinned, horizon) = &mut *self.pinned.lock();
+        *horizon = (*horizon).max(self.horizon(oracle));
+        let mut timestamps: Vec<_> = pinned.keys().copied().filter(|&ts| ts < *horizon).collect();
+        timestamps.push(*horizon);
+        timestamps
+    }
+
+    /// Returns the oldest timestamp a read may still use
+    pub(super) fn oldest_readable(&self) -> Timestamp {
+        self.pinned.lock().1
+    }
+
+    /// Returns the number of pins held, counting each holder separately
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.pinned.lock().0.values().sum()
+    }
+}
+
+/// A read timestamp that stays visible to compaction while held
+pub(super) struct PinnedTimestamp<'a> {
+    list: &'a SnapshotList,
+    timestamp: Timestamp,
+}
+
+impl PinnedTimestamp<'_> {
+    /// Returns the pinned read timestamp
+    pub(super) fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+}
+
+impl Drop for PinnedTimestamp<'_> {
+    fn drop(&mut self) {
+        self.list.release(self.timestamp);
+    }
+}
+
+/// A consistent read-only view of the engine at one point in time
+///
+/// Created by [`StorageEngine::snapshot`](super::StorageEngine::snapshot).
+/// Reads through a snapshot see every write committed before it was taken
+/// and nothing after, while writes, flushes, and compactions continue.
+///
+/// Versions the snapshot can see are kept through compactions until it is
+/// dropped, so long-lived snapshots hold on to disk space.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+///
+/// engine.put(b"k".to_vec(), b"old".to_vec())?;
+/// let snapshot = engine.snapshot();
+/// engine.put(b"k".to_vec(), b"new".to_vec())?;
+///
+/// assert_eq!(snapshot.get(b"k")?, Some(b"old".to_vec()));
+/// assert_eq!(engine.get(b"k")?, Some(b"new".to_vec()));
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct Snapshot {
+    inner: Arc<EngineInner>,
+    timestamp: Timestamp,
+}
+
+impl Snapshot {
+    /// Pins the engine's last committed timestamp
+    pub(super) fn new(inner: Arc<EngineInner>) -> Self {
+        let timestamp = inner.snapshots.acquire(&inner.oracle);
+        Self { inner, timestamp }
+    }
+
+    /// Pins an earlier timestamp of the engine
+    pub(super) fn at(inner: Arc<EngineInner>, timestamp: Timestamp) -> Result<Self> {
+        let timestamp = inner.snapshots.acquire_at(&inner.oracle, timestamp)?;
+        Ok(Self { inner, timestamp })
+    }
+
+    /// Returns the timestamp this snapshot reads at
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    /// Returns the engine the snapshot reads from
+    pub(super) fn engine(&self) -> &Arc<EngineInner> {
+        &self.inner
+    }
+
+    /// Returns the value of a key as of the snapshot
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed or the read fails.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        self.inner.check_open()?;
+        self.inner.get_at(&self.inner.default, key, self.timestamp)
+    }
+
+    /// Returns the value of a key in a column family as of the snapshot
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed, the family was dropped,
+    /// or the read fails.
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Value>> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.get_at(&cf, key, self.timestamp)
+    }
+
+    /// Returns the values of several keys as of the snapshot, in the order
+    /// given
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed or the read fails.
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>> {
+        self.inner.check_open()?;
+        let keys: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        self.inner
+            .multi_get_at(&self.inner.default, &keys, self.timestamp)
+    }
+
+    /// Returns the values of several keys in a column family as of the
+    /// snapshot, in the order given
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed, the family was dropped,
+    /// or the read fails.
+    pub fn multi_get_cf<K: AsRef<[u8]>>(
+        &self,
+        cf: &ColumnFamily,
+        keys: &[K],
+    ) -> Result<Vec<Option<Value>>> {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        let keys: Vec<&[u8]> = keys.iter().map(AsRef::as_ref).collect();
+        self.inner.multi_get_at(&cf, &keys, self.timestamp)
+    }
+
+    /// Returns the key-value pairs in `range` as of the snapshot
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed or the read fails.
+    pub fn scan<K, R>(&self, range: R) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        self.inner
+            .scan_at(&self.inner.default, &owned_range(range), self.timestamp)
+    }
+
+    /// Returns the key-value pairs in `range` of a column family as of the
+    /// snapshot
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn scan_cf<K, R>(&self, cf: &ColumnFamily, range: R) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner.scan_at(&cf, &owned_range(range), self.timestamp)
+    }
+
+    /// Returns the first `limit` key-value pairs in `range` of a column
+    /// family as of the snapshot
+    ///
+    /// Paging through a range with successive calls, each starting after
+    /// the last key returned, sees the same data as a single scan.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`get_cf`](Self::get_cf).
+    pub fn scan_cf_limit<K, R>(
+        &self,
+        cf: &ColumnFamily,
+        range: R,
+        limit: usize,
+    ) -> Result<Vec<(Key, Value)>>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.inner.check_open()?;
+        let cf = self.inner.column_family(cf)?;
+        self.inner
+            .scan_at_limited(&cf, &owned_range(range), self.timestamp, limit)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.inner.snapshots.release(self.timestamp);
+    }
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("timestamp", &self.timestamp)
+            .finish()
+    }
+}
+
+/// Copies the bounds of a caller's range
+pub(super) fn owned_range<K, R>(range: R) -> KeyRange
+where
+    K: AsRef<[u8]> + ?Sized,
+    R: RangeBounds<K>,
+{
+    (
+        range.start_bound().map(|key| key.as_ref().to_vec()),
+        range.end_bound().map(|key| key.as_ref().to_vec()),
+    )
+}
+
+/// Copies the bounds of each of a caller's ranges
+pub(super) fn owned_ranges<K, R>(ranges: &[R]) -> Vec<KeyRange>
+where
+    K: AsRef<[u8]> + ?Sized,
+    R: RangeBounds<K>,
+{
+    ranges
+        .iter()
+        .map(|range| owned_range::<K, _>((range.start_bound(), range.end_bound())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, StorageEngine};
+    use crate::clock::{Clock, ManualClock};
+    use ferrisdb_core::Error;
+    use tempfile::TempDir;
+
+    use std::ops::Bound;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:04}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_writes() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+        let written = engine.last_timestamp();
+
+        let snapshot = engine.snapshot();
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        engine.delete(b"b".to_vec()).unwrap();
+        engine.put(b"c".to_vec(), b"2".to_vec()).unwrap();
+
+        assert_eq!(snapshot.timestamp(), written);
+        assert_eq!(snapshot.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(b"b").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(b"c").unwrap(), None);
+        assert_eq!(
+            snapshot.scan::<[u8], _>(..).unwrap(),
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"1".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limited_scans_page_through_the_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let cf = engine.cf_handle("default").unwrap();
+        for i in 0..10 {
+            engine.put(key(i), b"old".to_vec()).unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        let mut pages = Vec::new();
+        let mut start = Bound::Unbounded;
+        loop {
+            let page = snapshot
+                .scan_cf_limit(&cf, (start.clone(), Bound::Unbounded), 4)
+                .unwrap();
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            start = Bound::Excluded(last.clone());
+            pages.push(page.len());
+            // Writes between pages don't show up in later ones
+            engine.put(key(9), b"new".to_vec()).unwrap();
+            engine.put(b"key0005a".to_vec(), b"new".to_vec()).unwrap();
+        }
+        assert_eq!(pages, vec![4, 4, 2]);
+        assert_eq!(snapshot.get(&key(9)).unwrap(), Some(b"old".to_vec()));
+    }
+
+    #[test]
+    fn test_snapshot_survives_flushes_and_compactions() {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_memtable_size(4 * 1024)).unwrap();
+        for i in 0..100 {
+            engine.put(key(i), b"old".to_vec()).unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        // Overwrite everything several times, forcing compactions
+        for round in 0..5 {
+            for i in 0..100 {
+                engine
+                    .put(key(i), format!("new{}", round).into_bytes())
+                    .unwrap();
+            }
+        }
+        engine.flush().unwrap();
+        // Compactions run in the background; wait until one has finished
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while engine.inner.default.versions.current().files(1).is_empty() {
+            assert!(Instant::now() < deadline, "no compaction ran");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        for i in 0..100 {
+            assert_eq!(snapshot.get(&key(i)).unwrap(), Some(b"old".to_vec()));
+            assert_eq!(engine.get(&key(i)).unwrap(), Some(b"new4".to_vec()));
+        }
+        assert_eq!(snapshot.scan(key(0)..key(100)).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_tombstones_outlive_the_snapshots_reading_below_them() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+        engine.flush().unwrap();
+        let held = engine.snapshot();
+        engine.delete(b"b".to_vec()).unwrap();
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        let between = engine.snapshot();
+        engine.put(b"a".to_vec(), b"3".to_vec()).unwrap();
+        engine.delete(b"a".to_vec()).unwrap();
+
+        // Each snapshot keeps what it reads, the versions in between go
+        let entries = || engine.statistics().estimated_num_keys;
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(entries(), 5);
+        assert_eq!(held.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(held.get(b"b").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(between.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(between.get(b"b").unwrap(), None);
+        assert_eq!(engine.get(b"a").unwrap(), None);
+
+        // Without the long-held snapshot, "b" has nothing left to hide
+        drop(held);
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(entries(), 2);
+        assert_eq!(between.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+
+        drop(between);
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(entries(), 0);
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_dropping_snapshots_releases_their_timestamps() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        let written = engine.last_timestamp();
+
+        let first = engine.snapshot();
+        let second = engine.snapshot();
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        let inner = &engine.inner;
+        let last = engine.last_timestamp();
+        assert_eq!(inner.snapshots.len(), 2);
+        assert_eq!(
+            inner.snapshots.timestamps(&inner.oracle),
+            vec![written, last]
+        );
+
+        drop(first);
+        assert_eq!(
+            inner.snapshots.timestamps(&inner.oracle),
+            vec![written, last]
+        );
+        drop(second);
+        assert_eq!(inner.snapshots.len(), 0);
+        assert_eq!(inner.snapshots.timestamps(&inner.oracle), vec![last]);
+    }
+
+    #[test]
+    fn test_reads_at_timestamps_within_the_retention_window() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000)));
+        let engine = StorageEngine::open(
+            Options::new(dir.path())
+                .with_clock(Arc::clone(&clock) as Arc<dyn Clock>)
+                .with_version_retention(Duration::from_secs(60)),
+        )
+        .unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"1".to_vec()).unwrap();
+        let first = engine.last_timestamp();
+        clock.advance(Duration::from_secs(1));
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+        engine.delete(b"b".to_vec()).unwrap();
+        let second = engine.last_timestamp();
+
+        // Compaction keeps every version written within the window
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(engine.get_at(b"a", first).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get_at(b"b", first).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get_at(b"a", second).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(
+            engine.scan_at::<[u8], _>(.., first).unwrap(),
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"1".to_vec())
+            ]
+        );
+        assert_eq!(
+            engine.scan_at::<[u8], _>(.., second).unwrap(),
+            vec![(b"a".to_vec(), b"2".to_vec())]
+        );
+        assert!(matches!(
+            engine.get_at(b"a", second + 1),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // Once the window moves past them, only what its start reads stays
+        clock.advance(Duration::from_secs(120));
+        engine.put(b"a".to_vec(), b"3".to_vec()).unwrap();
+        engine.compact_range::<[u8], _>(..).unwrap();
+        let oldest = engine.oldest_readable_timestamp();
+        assert!(oldest > second && oldest < engine.last_timestamp());
+        assert!(matches!(
+            engine.get_at(b"a", first),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert_eq!(engine.get_at(b"a", oldest).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(engine.statistics().estimated_num_keys, 2);
+    }
+
+    #[test]
+    fn test_reads_at_timestamps_without_retention_end_at_compaction() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        let first = engine.last_timestamp();
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+
+        // Versions not yet compacted away can still be read
+        assert_eq!(engine.oldest_readable_timestamp(), 0);
+        assert_eq!(engine.get_at(b"a", first).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get_at(b"a", first - 1).unwrap(), None);
+        let held = engine.snapshot_at(first).unwrap();
+
+        engine.compact_range::<[u8], _>(..).unwrap();
+        assert_eq!(engine.oldest_readable_timestamp(), engine.last_timestamp());
+        assert!(matches!(
+            engine.get_at(b"a", first - 1),
+            Err(Error::InvalidArgument(_))
+        ));
+        // The snapshot pinned its timestamp, so it can still be read at
+        // while the snapshot is held
+        assert_eq!(held.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get_at(b"a", first).unwrap(), Some(b"1".to_vec()));
+        drop(held);
+        assert!(engine.get_at(b"a", first).is_err());
+    }
+}
@@ -0,0 +1,576 @@
+//! Introspection of a running engine
+//!
+//! [`StorageEngine::statistics`](super::StorageEngine::statistics) returns
+//! a [`Statistics`] snapshot with typed fields for programs, and
+//! [`StorageEngine::property`](super::StorageEngine::property) returns the
+//! same numbers as strings under stable names for tools and operators:
+//!
+//! ```text
+//! ferrisdb.estimate-num-keys                 entries in MemTables and tables
+//! ferrisdb.num-files-at-level<N>             tables in level N
+//! ferrisdb.total-sst-files-size              bytes of all tables
+//! ferrisdb.estimate-pending-compaction-bytes compaction debt
+//! ferrisdb.num-running-compactions           compactions running
+//! ferrisdb.compaction-pending                families with a compaction due
+//! ferrisdb.cur-size-active-mem-table         bytes in the active MemTable
+//! ferrisdb.cur-size-all-mem-tables           bytes in all MemTables
+//! ferrisdb.num-immutable-mem-table           MemTables waiting for a flush
+//! ferrisdb.block-cache-hit-rate              block loads served from cache
+//! ferrisdb.levelstats                        table of files and bytes per level
+//! ferrisdb.stats                             everything above, one per line
+//! ```
+//!
+//! The numbers are gathered without blocking writers, so they are a
+//! consistent picture of no single moment; they are meant for monitoring,
+//! not for decisions needing exact values.
+
+use super::column_family::ColumnFamilyData;
+use super::compaction_status::CompactionJob;
+use super::slow_log::{self, SlowOperationKind};
+use super::EngineInner;
+use crate::compaction::CompactionStats;
+use crate::histogram::{HistogramSnapshot, LatencyHistogram};
+use crate::manifest::NUM_LEVELS;
+use crate::rate_limiter::IoPriority;
+use crate::sstable::reader::SSTableReader;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Names accepted by [`StorageEngine::property`](super::StorageEngine::property)
+pub mod properties {
+    /// Estimated number of keys, counting every version and tombstone
+    pub const ESTIMATE_NUM_KEYS: &str = "ferrisdb.estimate-num-keys";
+    /// Prefix of the number of tables in a level, followed by the level
+    pub const NUM_FILES_AT_LEVEL_PREFIX: &str = "ferrisdb.num-files-at-level";
+    /// Total size of all tables in bytes
+    pub const TOTAL_SST_FILES_SIZE: &str = "ferrisdb.total-sst-files-size";
+    /// Bytes compaction has to rewrite to reach the strategy's targets
+    pub const ESTIMATE_PENDING_COMPACTION_BYTES: &str =
+        "ferrisdb.estimate-pending-compaction-bytes";
+    /// Number of compactions running
+    pub const NUM_RUNNING_COMPACTIONS: &str = "ferrisdb.num-running-compactions";
+    /// Number of column families with a compaction due that isn't running
+    pub const COMPACTION_PENDING: &str = "ferrisdb.compaction-pending";
+    /// Bytes held by the active MemTable
+    pub const CUR_SIZE_ACTIVE_MEM_TABLE: &str = "ferrisdb.cur-size-active-mem-table";
+    /// Bytes held by the active MemTable and those waiting for a flush
+    pub const CUR_SIZE_ALL_MEM_TABLES: &str = "ferrisdb.cur-size-all-mem-tables";
+    /// Number of MemTables waiting for a flush
+    pub const NUM_IMMUTABLE_MEM_TABLE: &str = "ferrisdb.num-immutable-mem-table";
+    /// Fraction of block loads served from the block cache
+    pub const BLOCK_CACHE_HIT_RATE: &str = "ferrisdb.block-cache-hit-rate";
+    /// Number of corruption reports, from this run and earlier ones
+    pub const CORRUPTION_REPORTS: &str = "ferrisdb.corruption-reports";
+    /// Human-readable table of files and bytes per level
+    pub const LEVELSTATS: &str = "ferrisdb.levelstats";
+    /// Human-readable summary of all statistics
+    pub const STATS: &str = "ferrisdb.stats";
+}
+
+/// Counters of the engine's reads and background work since it opened
+#[derive(Debug, Default)]
+pub(super) struct Counters {
+    block_cache_hits: AtomicU64,
+    block_cache_misses: AtomicU64,
+    flushes: AtomicU64,
+    flush_bytes_written: AtomicU64,
+    flush_time_us: AtomicU64,
+    compactions: AtomicU64,
+    compaction_bytes_read: AtomicU64,
+    compaction_bytes_written: AtomicU64,
+    prefix_filter_skips: AtomicU64,
+    timestamp_filter_skips: AtomicU64,
+    scrubbed_tables: AtomicU64,
+    scrubbed_bytes: AtomicU64,
+    scrub_corrupt_tables: AtomicU64,
+    read_latency: LatencyHistogram,
+    write_latency: LatencyHistogram,
+    flush_latency: LatencyHistogram,
+    compaction_latency: LatencyHistogram,
+}
+
+impl Counters {
+    /// Adds the block loads of a reader that is done
+    pub(super) fn record_reads(&self, reader: &SSTableReader) {
+        let (hits, misses) = (reader.block_cache_hits(), reader.block_cache_misses());
+        self.block_cache_hits.fetch_add(hits, Ordering::Relaxed);
+        self.block_cache_misses.fetch_add(misses, Ordering::Relaxed);
+        slow_log::note_block_loads(hits, misses + reader.blocks_scanned());
+    }
+
+    /// Counts a MemTable flush that wrote a table of `bytes` in `elapsed`
+    pub(super) fn record_flush(&self, bytes: u64, elapsed: Duration) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        self.flush_bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        self.flush_time_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.flush_latency.record(elapsed);
+    }
+
+    /// Records how long a successful operation took
+    pub(super) fn record_latency(&self, kind: SlowOperationKind, elapsed: Duration) {
+        let histogram = match kind {
+            SlowOperationKind::Get => &self.read_latency,
+            SlowOperationKind::Write => &self.write_latency,
+            SlowOperationKind::Compaction => &self.compaction_latency,
+            SlowOperationKind::Scan | SlowOperationKind::Sync => return,
+        };
+        histogram.record(elapsed);
+    }
+
+    /// Counts a table a prefix read skipped by its bloom filter
+    pub(super) fn record_prefix_filter_skip(&self) {
+        self.prefix_filter_skips.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a table a read skipped as its timestamps are outside the
+    /// read's range
+    pub(super) fn record_timestamp_filter_skip(&self) {
+        self.timestamp_filter_skips.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a table of `bytes` the scrubber read back
+    pub(super) fn record_scrub(&self, bytes: u64) {
+        self.scrubbed_tables.fetch_add(1, Ordering::Relaxed);
+        self.scrubbed_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts a table the scrubber found damaged for the first time
+    pub(super) fn record_corrupt_table(&self) {
+        self.scrub_corrupt_tables.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a finished compaction
+    pub(super) fn record_compaction(&self, stats: &CompactionStats) {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        self.compaction_bytes_read
+            .fetch_add(stats.bytes_read, Ordering::Relaxed);
+        self.compaction_bytes_written
+            .fetch_add(stats.bytes_written, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the engine's size, shape and cache effectiveness
+///
+/// Gathered over one column family by
+/// [`statistics_cf`](super::StorageEngine::statistics_cf), or summed over
+/// all of them by [`statistics`](super::StorageEngine::statistics). The
+/// counters of block loads, flushes, compactions, prefix filter skips and
+/// scrubs, the latencies, the running compactions and the corruption
+/// reports, always cover the whole engine.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    /// Entries in MemTables and tables, counting every version and tombstone
+    pub estimated_num_keys: u64,
+    /// Number of tables in each level
+    pub level_files: Vec<usize>,
+    /// Bytes of the tables in each level
+    pub level_bytes: Vec<u64>,
+    /// Bytes compaction has to rewrite to reach the strategy's targets
+    pub pending_compaction_bytes: u64,
+    /// Compactions running in the whole engine
+    pub running_compactions: usize,
+    /// Column families with a compaction due that isn't running (see
+    /// [`compaction_status`](super::StorageEngine::compaction_status))
+    pub pending_compactions: usize,
+    /// Bytes held by the active MemTables
+    pub active_memtable_bytes: usize,
+    /// Bytes held by MemTables waiting for a flush
+    pub immutable_memtable_bytes: usize,
+    /// Number of MemTables waiting for a flush
+    pub immutable_memtables: usize,
+    /// Block loads served from the block cache since the engine opened
+    pub block_cache_hits: u64,
+    /// Block loads that read the table file since the engine opened
+    pub block_cache_misses: u64,
+    /// MemTable flushes since the engine opened, one per column family
+    pub flushes: u64,
+    /// Bytes of the tables written by flushes
+    pub flush_bytes_written: u64,
+    /// Time flushes took, from the start of writing their table until it
+    /// was installed
+    pub flush_time: Duration,
+    /// Time flushes waited on the rate limiter, included in `flush_time`
+    pub flush_throttle_time: Duration,
+    /// Time writes were delayed or stopped waiting for flushes and
+    /// compactions to catch up
+    pub write_stall_time: Duration,
+    /// Compactions finished since the engine opened
+    pub compactions: u64,
+    /// Bytes of the tables read by compactions
+    pub compaction_bytes_read: u64,
+    /// Bytes of the tables written by compactions
+    pub compaction_bytes_written: u64,
+    /// Tables prefix reads skipped because their bloom filter excluded
+    /// the prefix
+    pub prefix_filter_skips: u64,
+    /// Tables reads with a timestamp range skipped because none of their
+    /// entries were written in it
+    pub timestamp_filter_skips: u64,
+    /// Tables the scrubber read back since the engine opened
+    pub scrubbed_tables: u64,
+    /// Bytes of the tables the scrubber read back
+    pub scrubbed_bytes: u64,
+    /// Damaged tables the scrubber found, each counted once
+    pub scrub_corrupt_tables: u64,
+    /// Damage the engine found and reported, from this run and earlier
+    /// ones, see [`corruption_reports`](super::StorageEngine::corruption_reports)
+    pub corruption_reports: u64,
+    /// Time point reads (`get`, `get_pinned`, `multi_get` and their `_cf`
+    /// forms) took
+    pub read_latency: HistogramSnapshot,
+    /// Time applied writes and batches took, including stalls and waiting
+    /// for the WAL
+    pub write_latency: HistogramSnapshot,
+    /// Time flushes took, as in `flush_time`
+    pub flush_latency: HistogramSnapshot,
+    /// Time compactions took, trivial moves included
+    pub compaction_latency: HistogramSnapshot,
+}
+
+impl Statistics {
+    /// Returns the number of tables in level 0
+    pub fn level0_files(&self) -> usize {
+        self.level_files.first().copied().unwrap_or(0)
+    }
+
+    /// Returns the total size of all tables in bytes
+    pub fn total_sst_bytes(&self) -> u64 {
+        self.level_bytes.iter().sum()
+    }
+
+    /// Returns the bytes held by all MemTables
+    pub fn memtable_bytes(&self) -> usize {
+        self.active_memtable_bytes + self.immutable_memtable_bytes
+    }
+
+    /// Returns the fraction of block loads served from the cache (0 without loads)
+    pub fn block_cache_hit_rate(&self) -> f64 {
+        let loads = self.block_cache_hits + self.block_cache_misses;
+        if loads == 0 {
+            return 0.0;
+        }
+        self.block_cache_hits as f64 / loads as f64
+    }
+
+    /// Returns the value of a named property, or `None` for unknown names
+    ///
+    /// See [`properties`] for the names.
+    pub fn property(&self, name: &str) -> Option<String> {
+        if let Some(level) = name.strip_prefix(properties::NUM_FILES_AT_LEVEL_PREFIX) {
+            let level: usize = level.parse().ok()?;
+            return self.level_files.get(level).map(ToString::to_string);
+        }
+        let value = match name {
+            properties::ESTIMATE_NUM_KEYS => self.estimated_num_keys.to_string(),
+            properties::TOTAL_SST_FILES_SIZE => self.total_sst_bytes().to_string(),
+            properties::ESTIMATE_PENDING_COMPACTION_BYTES => {
+                self.pending_compaction_bytes.to_string()
+            }
+            properties::NUM_RUNNING_COMPACTIONS => self.running_compactions.to_string(),
+            properties::COMPACTION_PENDING => self.pending_compactions.to_string(),
+            properties::CUR_SIZE_ACTIVE_MEM_TABLE => self.active_memtable_bytes.to_string(),
+            properties::CUR_SIZE_ALL_MEM_TABLES => self.memtable_bytes().to_string(),
+            properties::NUM_IMMUTABLE_MEM_TABLE => self.immutable_memtables.to_string(),
+            properties::BLOCK_CACHE_HIT_RATE => format!("{:.4}", self.block_cache_hit_rate()),
+            properties::CORRUPTION_REPORTS => self.corruption_reports.to_string(),
+            properties::LEVELSTATS => self.level_stats(),
+            properties::STATS => self.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn level_stats(&self) -> String {
+        let mut out = String::from("Level Files Size(bytes)\n");
+        for (level, (files, bytes)) in self.level_files.iter().zip(&self.level_bytes).enumerate() {
+            out.push_str(&format!("{:>5} {:>5} {:>11}\n", level, files, bytes));
+        }
+        out
+    }
+
+    /// Adds another column family's numbers to these
+    fn add(&mut self, other: &Statistics) {
+        self.estimated_num_keys += other.estimated_num_keys;
+        for (files, other) in self.level_files.iter_mut().zip(&other.level_files) {
+            *files += other;
+        }
+        for (bytes, other) in self.level_bytes.iter_mut().zip(&other.level_bytes) {
+            *bytes += other;
+        }
+        self.pending_compaction_bytes += other.pending_compaction_bytes;
+        self.pending_compactions += other.pending_compactions;
+        self.active_memtable_bytes += other.active_memtable_bytes;
+        self.immutable_memtable_bytes += other.immutable_memtable_bytes;
+        self.immutable_memtables += other.immutable_memtables;
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "estimated keys: {}", self.estimated_num_keys)?;
+        writeln!(f, "level 0 files: {}", self.level0_files())?;
+        writeln!(f, "table bytes: {}", self.total_sst_bytes())?;
+        writeln!(
+            f,
+            "pending compaction bytes: {}",
+            self.pending_compaction_bytes
+        )?;
+        writeln!(
+            f,
+            "compaction jobs: {} running, {} pending",
+            self.running_compactions, self.pending_compactions
+        )?;
+        writeln!(
+            f,
+            "memtable bytes: {} active, {} in {} immutable",
+            self.active_memtable_bytes, self.immutable_memtable_bytes, self.immutable_memtables
+        )?;
+        writeln!(
+            f,
+            "block cache: {} hits, {} misses, hit rate {:.4}",
+            self.block_cache_hits,
+            self.block_cache_misses,
+            self.block_cache_hit_rate()
+        )?;
+        writeln!(
+            f,
+            "flushes: {}, {} bytes written in {:?}, {:?} throttled",
+            self.flushes, self.flush_bytes_written, self.flush_time, self.flush_throttle_time
+        )?;
+        writeln!(f, "write stalls: {:?}", self.write_stall_time)?;
+        writeln!(
+            f,
+            "compactions: {}, {} bytes read, {} bytes written",
+            self.compactions, self.compaction_bytes_read, self.compaction_bytes_written
+        )?;
+        writeln!(
+            f,
+            "prefix filter: {} tables skipped",
+            self.prefix_filter_skips
+        )?;
+        writeln!(
+            f,
+            "timestamp filter: {} tables skipped",
+            self.timestamp_filter_skips
+        )?;
+        writeln!(
+            f,
+            "scrub: {} tables, {} bytes read, {} corrupt",
+            self.scrubbed_tables, self.scrubbed_bytes, self.scrub_corrupt_tables
+        )?;
+        writeln!(f, "corruption reports: {}", self.corruption_reports)?;
+        writeln!(f, "read latency: {}", self.read_latency)?;
+        writeln!(f, "write latency: {}", self.write_latency)?;
+        writeln!(f, "flush latency: {}", self.flush_latency)?;
+        writeln!(f, "compaction latency: {}", self.compaction_latency)?;
+        write!(f, "{}", self.level_stats())
+    }
+}
+
+/// Gathers the statistics of the given column families, summed
+pub(super) fn collect(inner: &EngineInner, families: &[&ColumnFamilyData]) -> Statistics {
+    let counters = &inner.counters;
+    let mut total = Statistics {
+        level_files: vec![0; NUM_LEVELS],
+        level_bytes: vec![0; NUM_LEVELS],
+        block_cache_hits: counters.block_cache_hits.load(Ordering::Relaxed),
+        block_cache_misses: counters.block_cache_misses.load(Ordering::Relaxed),
+        flushes: counters.flushes.load(Ordering::Relaxed),
+        flush_bytes_written: counters.flush_bytes_written.load(Ordering::Relaxed),
+        flush_time: Duration::from_micros(counters.flush_time_us.load(Ordering::Relaxed)),
+        flush_throttle_time: inner.rate_limiter.metrics().throttle_time(IoPriority::High),
+        write_stall_time: inner.write_controller.metrics().stall_time(),
+        compactions: counters.compactions.load(Ordering::Relaxed),
+        compaction_bytes_read: counters.compaction_bytes_read.load(Ordering::Relaxed),
+        compaction_bytes_written: counters.compaction_bytes_written.load(Ordering::Relaxed),
+        prefix_filter_skips: counters.prefix_filter_skips.load(Ordering::Relaxed),
+        timestamp_filter_skips: counters.timestamp_filter_skips.load(Ordering::Relaxed),
+        scrubbed_tables: counters.scrubbed_tables.load(Ordering::Relaxed),
+        scrubbed_bytes: counters.scrubbed_bytes.load(Ordering::Relaxed),
+        scrub_corrupt_tables: counters.scrub_corrupt_tables.load(Ordering::Relaxed),
+        corruption_reports: inner.options.corruption.len() as u64,
+        read_latency: counters.read_latency.snapshot(),
+        write_latency: counters.write_latency.snapshot(),
+        flush_latency: counters.flush_latency.snapshot(),
+        compaction_latency: counters.compaction_latency.snapshot(),
+        ..Default::default()
+    };
+    let running = inner.running_compactions.jobs();
+    total.running_compactions = running.len();
+    for cf in families {
+        total.add(&collect_family(inner, cf, &running));
+    }
+    total
+}
+
+fn collect_family(
+    inner: &EngineInner,
+    cf: &ColumnFamilyData,
+    running: &[CompactionJob],
+) -> Statistics {
+    let mut stats = Statistics::default();
+    {
+        let memtables = inner.memtables.read();
+        if let Some(active) = memtables.active.get(&cf.id) {
+            stats.active_memtable_bytes = active.memory_usage();
+            stats.estimated_num_keys += active.entry_count() as u64;
+        }
+        for imm in &memtables.immutable {
+            if let Some(memtable) = imm.memtables.get(&cf.id) {
+                stats.immutable_memtable_bytes += memtable.memory_usage();
+                stats.immutable_memtables += 1;
+                stats.estimated_num_keys += memtable.entry_count() as u64;
+            }
+        }
+    }
+
+    let version = cf.versions.current();
+    for level in 0..NUM_LEVELS {
+        let tables = version.files(level);
+        stats.level_files.push(tables.len());
+        stats
+            .level_bytes
+            .push(tables.iter().map(|table| table.meta().file_size).sum());
+        stats.estimated_num_keys += tables
+            .iter()
+            .map(|table| table.meta().entry_count)
+            .sum::<u64>();
+    }
+    stats.pending_compaction_bytes = cf.strategy.pending_compaction_bytes(&version);
+    stats.pending_compactions = usize::from(inner.pending_compaction(cf, running).is_some());
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine};
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_statistics_follow_writes_and_flushes() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let other = engine
+            .create_column_family("other", ColumnFamilyOptions::default())
+            .unwrap();
+
+        for i in 0..100 {
+            engine.put(key(i), vec![b'v'; 32]).unwrap();
+        }
+        engine.put_cf(&other, key(0), b"v".to_vec()).unwrap();
+        let stats = engine
+            .statistics_cf(&engine.cf_handle("default").unwrap())
+            .unwrap();
+        assert_eq!(stats.estimated_num_keys, 100);
+        assert!(stats.active_memtable_bytes > 100 * 32);
+        assert_eq!(stats.write_latency.count, 101);
+        assert!(stats.write_latency.p50 > Duration::ZERO);
+        assert!(stats.write_latency.p999 <= stats.write_latency.max);
+        assert_eq!(stats.total_sst_bytes(), 0);
+        assert_eq!(engine.statistics().estimated_num_keys, 101);
+
+        engine.flush().unwrap();
+        let stats = engine.statistics();
+        assert_eq!(stats.estimated_num_keys, 101);
+        assert_eq!(stats.level0_files(), 2);
+        assert!(stats.total_sst_bytes() > 100 * 32);
+        assert_eq!(stats.immutable_memtables, 0);
+        assert_eq!(
+            engine.property(properties::ESTIMATE_NUM_KEYS),
+            Some("100".to_string())
+        );
+        assert_eq!(
+            engine.property("ferrisdb.num-files-at-level0"),
+            Some("1".to_string())
+        );
+        assert_eq!(
+            engine
+                .property_cf(&other, "ferrisdb.num-files-at-level0")
+                .unwrap(),
+            Some("1".to_string())
+        );
+
+        assert_eq!(stats.flushes, 2);
+        assert_eq!(stats.flush_bytes_written, stats.total_sst_bytes());
+        assert!(stats.flush_time > Duration::ZERO);
+        assert_eq!(stats.flush_latency.count, 2);
+        assert!(stats.flush_latency.max <= stats.flush_time);
+        assert_eq!(stats.flush_throttle_time, Duration::ZERO);
+        assert_eq!(stats.write_stall_time, Duration::ZERO);
+
+        // Reads from tables count block loads
+        for i in 0..100 {
+            assert!(engine.get(&key(i)).unwrap().is_some());
+        }
+        assert_eq!(engine.multi_get(&[key(0), key(1)]).unwrap().len(), 2);
+        let stats = engine.statistics();
+        assert!(stats.block_cache_misses >= 100);
+        assert_eq!(stats.read_latency.count, 101);
+        assert!(stats.read_latency.p99 > Duration::ZERO);
+        assert_eq!(stats.compaction_latency.count, stats.compactions);
+        assert!(engine
+            .property(properties::STATS)
+            .unwrap()
+            .contains("estimated keys: 100"));
+        assert!(engine
+            .property(properties::STATS)
+            .unwrap()
+            .contains("read latency: 101 ops"));
+    }
+
+    #[test]
+    fn test_rate_limited_flushes_report_their_throttling() {
+        let dir = TempDir::new().unwrap();
+        // About 6KB may go through at once, less than the table
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_rate_limit(64 * 1024)).unwrap();
+        for i in 0..200 {
+            engine.put(key(i), vec![b'v'; 64]).unwrap();
+        }
+        engine.flush().unwrap();
+
+        let stats = engine.statistics();
+        assert_eq!(stats.flushes, 1);
+        assert!(stats.flush_bytes_written > 200 * 64);
+        assert!(stats.flush_throttle_time > Duration::ZERO);
+        assert!(stats.flush_time >= stats.flush_throttle_time);
+    }
+
+    #[test]
+    fn test_unknown_properties_are_none() {
+        let stats = Statistics {
+            level_files: vec![3, 1],
+            level_bytes: vec![300, 100],
+            block_cache_hits: 3,
+            block_cache_misses: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            stats.property("ferrisdb.num-files-at-level1"),
+            Some("1".to_string())
+        );
+        assert_eq!(stats.property("ferrisdb.num-files-at-level9"), None);
+        assert_eq!(stats.property("ferrisdb.num-files-at-levelx"), None);
+        assert_eq!(stats.property("ferrisdb.unknown"), None);
+        assert_eq!(
+            stats.property(properties::TOTAL_SST_FILES_SIZE),
+            Some("400".to_string())
+        );
+        assert_eq!(
+            stats.property(properties::BLOCK_CACHE_HIT_RATE),
+            Some("0.7500".to_string())
+        );
+        assert!(stats
+            .property(properties::LEVELSTATS)
+            .unwrap()
+            .contains("    0     3         300"));
+    }
+}
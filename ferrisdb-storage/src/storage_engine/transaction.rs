@@ -0,0 +1,373 @@
+//! Optimistic transactions
+//!
+//! A [`Transaction`] reads from a [`Snapshot`] taken when it begins and
+//! buffers its writes in a [`WriteBatchWithIndex`], which its reads see on
+//! top of the snapshot. Nothing is locked while it runs;
+//! instead, commit checks under the engine's write lock that no key the
+//! transaction read or overwrote has a newer version than the snapshot:
+//!
+//! ```text
+//!  T1: begin(ts=5)  get(a)  put(b)                  commit ─▶ conflict,
+//!  T2:    begin(ts=5)  put(a)  commit ─▶ Ok (ts=6)             a changed at 6
+//!  T3:       begin(ts=5)  get(c)  put(c)               commit ─▶ Ok
+//! ```
+//!
+//! The check and the write of the batch happen without another writer in
+//! between, so committed transactions are serializable for the keys they
+//! read. Merges are not checked: operands commute, so concurrent merges to
+//! the same key never conflict.
+
+use super::indexed_batch::{self, WriteBatchWithIndex};
+use super::iterator::EngineIterator;
+use super::read_options::ReadOptions;
+use super::snapshot::Snapshot;
+use super::write_options::WriteOptions;
+use super::EngineInner;
+use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// A unit of reads and writes that commits atomically or not at all
+///
+/// Created by [`StorageEngine::begin_transaction`](super::StorageEngine::begin_transaction).
+/// Reads see the engine as of the transaction's start plus the
+/// transaction's own writes. Writes stay private until
+/// [`commit`](Self::commit); dropping the transaction discards them.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, StorageEngine};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+/// engine.put(b"alice".to_vec(), b"100".to_vec())?;
+///
+/// let mut txn = engine.begin_transaction();
+/// assert_eq!(txn.get(b"alice")?, Some(b"100".to_vec()));
+/// txn.put(b"alice".to_vec(), b"50".to_vec());
+/// txn.put(b"bob".to_vec(), b"50".to_vec());
+///
+/// // A concurrent write to a key the transaction read makes it fail
+/// engine.put(b"alice".to_vec(), b"200".to_vec())?;
+/// assert!(txn.commit().is_err());
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Transaction {
+    snapshot: Snapshot,
+    batch: WriteBatchWithIndex,
+    /// Keys whose committed value was read
+    read_set: BTreeSet<Key>,
+}
+
+impl Transaction {
+    pub(super) fn new(inner: Arc<EngineInner>) -> Self {
+        Self {
+            snapshot: Snapshot::new(inner),
+            batch: WriteBatchWithIndex::default(),
+            read_set: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the timestamp the transaction reads at
+    pub fn timestamp(&self) -> Timestamp {
+        self.snapshot.timestamp()
+    }
+
+    /// Returns the value of a key, including the transaction's own writes
+    ///
+    /// Unless the transaction already put or deleted the key, the key is
+    /// added to the read set checked on commit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed, the read fails, or the
+    /// merge operator fails.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        let inner = self.snapshot.engine();
+        inner.check_open()?;
+
+        // Own writes are newer than anything the snapshot sees
+        let mut versions = self.batch.versions(&inner.default, key);
+        if !indexed_batch::has_base(&versions) {
+            versions.extend(inner.versions_at(&inner.default, key, self.timestamp())?);
+            self.read_set.insert(key.to_vec());
+        }
+        inner.resolve(&inner.default, key, versions)
+    }
+
+    /// Returns an iterator over the snapshot with the transaction's writes
+    /// applied, within the bounds of `options`
+    ///
+    /// The iterator reads at the transaction's timestamp, whatever snapshot
+    /// `options` names, and sees the writes made before it was created.
+    /// Keys it reads are not added to the read set: read keys whose change
+    /// must fail the commit with [`get`](Self::get).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the engine was closed or an SSTable cannot be
+    /// opened.
+    pub fn iter(&self, options: ReadOptions<'_>) -> Result<EngineIterator> {
+        let inner = self.snapshot.engine();
+        inner.check_open()?;
+        EngineIterator::new(
+            Arc::clone(inner),
+            Arc::clone(&inner.default),
+            options.with_snapshot(&self.snapshot),
+            Some(&self.batch),
+        )
+    }
+
+    /// Sets the value of a key when the transaction commits
+    pub fn put(&mut self, key: Key, value: Value) {
+        self.batch.put(key, value);
+    }
+
+    /// Deletes a key when the transaction commits
+    pub fn delete(&mut self, key: Key) {
+        self.batch.delete(key);
+    }
+
+    /// Records a merge operand for a key when the transaction commits
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if no merge operator is configured.
+    pub fn merge(&mut self, key: Key, operand: Value) -> Result<()> {
+        if self.snapshot.engine().options.merge_operator.is_none() {
+            return Err(Error::InvalidOperation(
+                "Merge requires a merge operator".to_string(),
+            ));
+        }
+        self.batch.merge(key, operand);
+        Ok(())
+    }
+
+    /// Atomically applies the transaction's writes
+    ///
+    /// A transaction without writes commits without checking anything:
+    /// all of its reads came from one consistent snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Transaction` if a key that was read, put, or deleted
+    /// was written by someone else after the transaction began; nothing is
+    /// written then, and the transaction may be retried from the start.
+    /// Otherwise errors for the same reasons as
+    /// [`StorageEngine::put`](super::StorageEngine::put).
+    pub fn commit(self) -> Result<()> {
+        let Self {
+            snapshot,
+            batch,
+            mut read_set,
+        } = self;
+        let inner = snapshot.engine();
+        let read_ts = snapshot.timestamp();
+        let batch = batch.into_batch();
+
+        read_set.extend(
+            batch
+                .entries()
+                .filter(|entry| entry.operation != Operation::Merge)
+                .map(|entry| entry.key.clone()),
+        );
+
+        inner.write_batch(batch, &WriteOptions::default(), || {
+            for key in &read_set {
+                if let Some(timestamp) = inner.newest_timestamp(&inner.default, key)? {
+                    if timestamp > read_ts {
+                        return Err(Error::Transaction(format!(
+                            "Conflict on key {:?}: written at {} after the transaction began at {}",
+                            String::from_utf8_lossy(key),
+                            timestamp,
+                            read_ts
+                        )));
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Discards the transaction's writes
+    ///
+    /// Equivalent to dropping the transaction.
+    pub fn rollback(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Options, ReadOptions, StorageEngine};
+    use crate::merge::U64AddOperator;
+    use ferrisdb_core::Error;
+    use tempfile::TempDir;
+
+    use std::sync::Arc;
+
+    fn open(dir: &TempDir) -> StorageEngine {
+        StorageEngine::open(Options::new(dir.path()).with_merge_operator(Arc::new(U64AddOperator)))
+            .unwrap()
+    }
+
+    fn counter(n: u64) -> Vec<u8> {
+        n.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_transaction_reads_own_writes_and_commits_atomically() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.put(b"a".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"n".to_vec(), counter(1)).unwrap();
+
+        let mut txn = engine.begin_transaction();
+        txn.put(b"a".to_vec(), b"new".to_vec());
+        txn.delete(b"b".to_vec());
+        txn.merge(b"n".to_vec(), counter(2)).unwrap();
+        txn.merge(b"n".to_vec(), counter(3)).unwrap();
+
+        assert_eq!(txn.get(b"a").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(txn.get(b"b").unwrap(), None);
+        assert_eq!(txn.get(b"n").unwrap(), Some(counter(6)));
+        // Nothing is visible outside before commit
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(engine.get(b"n").unwrap(), Some(counter(1)));
+
+        let before = engine.snapshot();
+        txn.commit().unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(engine.get(b"n").unwrap(), Some(counter(6)));
+        assert_eq!(before.get(b"a").unwrap(), Some(b"old".to_vec()));
+    }
+
+    #[test]
+    fn test_iterator_sees_own_writes_over_the_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.put(b"a".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"b".to_vec(), b"old".to_vec()).unwrap();
+        engine.put(b"n".to_vec(), counter(1)).unwrap();
+
+        let mut txn = engine.begin_transaction();
+        txn.put(b"c".to_vec(), b"new".to_vec());
+        txn.delete(b"a".to_vec());
+        txn.merge(b"n".to_vec(), counter(2)).unwrap();
+        // Committed after the transaction began, so not visible to it
+        engine.put(b"d".to_vec(), b"later".to_vec()).unwrap();
+
+        let mut iter = txn.iter(ReadOptions::new()).unwrap();
+        let mut pairs = Vec::new();
+        iter.seek_to_first().unwrap();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            pairs.push((key.to_vec(), value.to_vec()));
+            iter.next().unwrap();
+        }
+        assert_eq!(
+            pairs,
+            vec![
+                (b"b".to_vec(), b"old".to_vec()),
+                (b"c".to_vec(), b"new".to_vec()),
+                (b"n".to_vec(), counter(3)),
+            ]
+        );
+        drop(iter);
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_commit_fails_when_read_key_changed() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+
+        let mut txn = engine.begin_transaction();
+        assert_eq!(txn.get(b"a").unwrap(), Some(b"1".to_vec()));
+        txn.put(b"b".to_vec(), b"from txn".to_vec());
+        engine.put(b"a".to_vec(), b"2".to_vec()).unwrap();
+
+        assert!(matches!(txn.commit(), Err(Error::Transaction(_))));
+        assert_eq!(engine.get(b"b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_commit_fails_when_written_key_changed() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+
+        let mut txn = engine.begin_transaction();
+        txn.delete(b"a".to_vec());
+        engine.put(b"a".to_vec(), b"concurrent".to_vec()).unwrap();
+
+        assert!(matches!(txn.commit(), Err(Error::Transaction(_))));
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"concurrent".to_vec()));
+    }
+
+    #[test]
+    fn test_unrelated_writes_and_merges_do_not_conflict() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+
+        let mut txn = engine.begin_transaction();
+        assert_eq!(txn.get(b"a").unwrap(), None);
+        txn.put(b"b".to_vec(), b"1".to_vec());
+        txn.merge(b"n".to_vec(), counter(1)).unwrap();
+        engine.put(b"c".to_vec(), b"1".to_vec()).unwrap();
+        engine.merge(b"n".to_vec(), counter(1)).unwrap();
+
+        txn.commit().unwrap();
+        assert_eq!(engine.get(b"n").unwrap(), Some(counter(2)));
+    }
+
+    #[test]
+    fn test_conflict_detected_after_flush() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+
+        let mut txn = engine.begin_transaction();
+        assert_eq!(txn.get(b"a").unwrap(), None);
+        engine.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        engine.flush().unwrap();
+        txn.put(b"a".to_vec(), b"2".to_vec());
+
+        assert!(matches!(txn.commit(), Err(Error::Transaction(_))));
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn test_rollback_discards_writes() {
+        let dir = TempDir::new().unwrap();
+        let engine = open(&dir);
+
+        let mut txn = engine.begin_transaction();
+        txn.put(b"a".to_vec(), b"1".to_vec());
+        txn.rollback();
+
+        assert_eq!(engine.get(b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_committed_transaction_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+        {
+            let engine = open(&dir);
+            let mut txn = engine.begin_transaction();
+            for i in 0..10u8 {
+                txn.put(vec![i], vec![i]);
+            }
+            txn.commit().unwrap();
+            engine.put(b"after".to_vec(), b"1".to_vec()).unwrap();
+            // Leave the batch in the WAL instead of flushing it on close
+            engine.crash();
+        }
+
+        let engine = open(&dir);
+        assert_eq!(engine.recovery_report().entries_replayed, 11);
+        for i in 0..10u8 {
+            assert_eq!(engine.get(&[i]).unwrap(), Some(vec![i]));
+        }
+        assert_eq!(engine.get(b"after").unwrap(), Some(b"1".to_vec()));
+    }
+}
@@ -0,0 +1,431 @@
+//! Expiring values in column families with a TTL
+//!
+//! In a family configured with [`ColumnFamilyOptions::with_ttl`](super::ColumnFamilyOptions::with_ttl),
+//! every value and merge operand is stored with a trailer holding the time
+//! it expires, in milliseconds since the Unix epoch:
+//!
+//! ```text
+//! [user value][expiry: 8 bytes LE]      u64::MAX = never expires
+//! ```
+//!
+//! Expiry is judged against the engine's [`Clock`] whenever a version is
+//! looked at:
+//!
+//! - Reads strip the trailer and treat an expired value as deleted and an
+//!   expired operand as never written
+//! - Compaction removes expired versions through a [`CompactionFilter`]
+//!   wrapped around the family's own filter, which sees values without
+//!   their trailer
+//! - Merges go through a wrapper of the family's merge operator that drops
+//!   expired inputs and stamps the result with the expiry of the newest
+//!   operand
+//!
+//! Whether a family has a TTL is not persisted: a family must always be
+//! opened with a TTL once its values were written with one, and never
+//! with one otherwise.
+
+use crate::clock::Clock;
+use crate::compaction::{CompactionFilter, FilterDecision};
+use crate::merge::MergeOperator;
+use ferrisdb_core::{Error, Operation, Result, Timestamp, Value};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Size of the expiry trailer of each value
+const TRAILER_LEN: usize = 8;
+
+/// Expiry of versions that never expire
+const NEVER: u64 = u64::MAX;
+
+/// Expiry stamped on merge results whose inputs all expired
+const EXPIRED: u64 = 0;
+
+/// The TTL settings of one column family
+#[derive(Clone)]
+pub(super) struct Ttl {
+    /// Lifetime of values written without a TTL of their own
+    default: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Ttl {
+    pub(super) fn new(default: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self { default, clock }
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.now().as_millis() as u64
+    }
+
+    /// Appends the trailer of a value written now that lives for `ttl`
+    ///
+    /// Without `ttl`, the family's default applies.
+    pub(super) fn stamp(&self, value: &mut Value, ttl: Option<Duration>) {
+        let ttl = ttl.unwrap_or(self.default);
+        let expiry = u64::try_from(ttl.as_millis())
+            .ok()
+            .and_then(|millis| self.now().checked_add(millis))
+            .unwrap_or(NEVER);
+        value.extend_from_slice(&expiry.to_le_bytes());
+    }
+
+    /// Strips the trailer of a value, returning `None` once it has expired
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the value is too short to have a trailer.
+    pub(super) fn unstamp(&self, value: Value) -> Result<Option<Value>> {
+        Ok(self.unstamp_with_ttl(value)?.map(|(value, _)| value))
+    }
+
+    /// Strips the trailer of a value, returning it with the time it has
+    /// left to live, `None` if it never expires
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the value is too short to have a trailer.
+    pub(super) fn unstamp_with_ttl(
+        &self,
+        mut value: Value,
+    ) -> Result<Option<(Value, Option<Duration>)>> {
+        let (_, expiry) = split(&value)?;
+        let now = self.now();
+        if expiry <= now {
+            return Ok(None);
+        }
+        value.truncate(value.len() - TRAILER_LEN);
+        let remaining = (expiry != NEVER).then(|| Duration::from_millis(expiry - now));
+        Ok(Some((value, remaining)))
+    }
+}
+
+/// Splits a stored value into the user's value and its expiry
+fn split(value: &[u8]) -> Result<(&[u8], u64)> {
+    if value.len() < TRAILER_LEN {
+        return Err(Error::Corruption(format!(
+            "Value of {} bytes in a TTL column family has no expiry",
+            value.len()
+        )));
+    }
+    let (user_value, trailer) = value.split_at(value.len() - TRAILER_LEN);
+    Ok((user_value, u64::from_le_bytes(trailer.try_into().unwrap())))
+}
+
+/// Returns the user's part of a stamped value, all of it if it is too
+/// short to have a trailer
+pub(super) fn strip_expiry(value: &[u8]) -> &[u8] {
+    split(value).map_or(value, |(user_value, _)| user_value)
+}
+
+fn with_expiry(mut value: Value, expiry: u64) -> Value {
+    value.extend_from_slice(&expiry.to_le_bytes());
+    value
+}
+
+/// Applies a family's merge operator to values with expiry trailers
+pub(super) struct TtlMergeOperator {
+    inner: Arc<dyn MergeOperator>,
+    ttl: Ttl,
+}
+
+impl TtlMergeOperator {
+    pub(super) fn new(inner: Arc<dyn MergeOperator>, ttl: Ttl) -> Self {
+        Self { inner, ttl }
+    }
+
+    /// Returns the operands that haven't expired and the newest one's expiry
+    fn live_operands<'a>(&self, operands: &[&'a [u8]]) -> Result<(Vec<&'a [u8]>, u64)> {
+        let now = self.ttl.now();
+        let mut live = Vec::with_capacity(operands.len());
+        let mut expiry = EXPIRED;
+        for operand in operands {
+            let (value, operand_expiry) = split(operand)?;
+            if operand_expiry > now {
+                live.push(value);
+                expiry = operand_expiry;
+            }
+        }
+        Ok((live, expiry))
+    }
+}
+
+impl MergeOperator for TtlMergeOperator {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Value> {
+        let existing = match existing.map(split).transpose()? {
+            Some((value, expiry)) if expiry > self.ttl.now() => Some((value, expiry)),
+            _ => None,
+        };
+        let (live, expiry) = self.live_operands(operands)?;
+        if live.is_empty() {
+            return Ok(match existing {
+                Some((value, expiry)) => with_expiry(value.to_vec(), expiry),
+                None => with_expiry(Vec::new(), EXPIRED),
+            });
+        }
+
+        let merged = self
+            .inner
+            .full_merge(key, existing.map(|(value, _)| value), &live)?;
+        Ok(with_expiry(merged, expiry))
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Value> {
+        let (live, expiry) = self.live_operands(operands).ok()?;
+        if live.is_empty() {
+            return Some(with_expiry(Vec::new(), EXPIRED));
+        }
+        let merged = self.inner.partial_merge(key, &live)?;
+        Some(with_expiry(merged, expiry))
+    }
+}
+
+/// Removes expired versions, then applies the family's own filter
+pub(super) struct TtlFilter {
+    inner: Option<Arc<dyn CompactionFilter>>,
+    ttl: Ttl,
+}
+
+impl TtlFilter {
+    pub(super) fn new(inner: Option<Arc<dyn CompactionFilter>>, ttl: Ttl) -> Self {
+        Self { inner, ttl }
+    }
+}
+
+impl CompactionFilter for TtlFilter {
+    fn name(&self) -> &str {
+        match &self.inner {
+            Some(inner) => inner.name(),
+            None => "ttl",
+        }
+    }
+
+    fn filter(
+        &self,
+        key: &[u8],
+        timestamp: Timestamp,
+        operation: Operation,
+        value: &[u8],
+    ) -> FilterDecision {
+        // Malformed values are left for reads to report
+        let Ok((value, expiry)) = split(value) else {
+            return FilterDecision::Keep;
+        };
+        if expiry <= self.ttl.now() {
+            return FilterDecision::Remove;
+        }
+        match &self.inner {
+            Some(inner) => match inner.filter(key, timestamp, operation, value) {
+                FilterDecision::ChangeValue(value) => {
+                    FilterDecision::ChangeValue(with_expiry(value, expiry))
+                }
+                decision => decision,
+            },
+            None => FilterDecision::Keep,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine, WriteBatch};
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::merge::U64AddOperator;
+    use tempfile::TempDir;
+
+    use std::thread;
+    use std::time::Instant;
+
+    const START: Duration = Duration::from_secs(1_700_000_000);
+
+    fn ttl(clock: &Arc<ManualClock>) -> Ttl {
+        Ttl::new(Duration::from_secs(10), Arc::clone(clock) as Arc<dyn Clock>)
+    }
+
+    fn counter(n: u64) -> Vec<u8> {
+        n.to_le_bytes().to_vec()
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:05}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_values_expire_after_their_ttl() {
+        let clock = Arc::new(ManualClock::new(START));
+        let ttl = ttl(&clock);
+
+        let mut default = b"v".to_vec();
+        ttl.stamp(&mut default, None);
+        let mut short = b"v".to_vec();
+        ttl.stamp(&mut short, Some(Duration::from_secs(1)));
+        let mut forever = b"v".to_vec();
+        ttl.stamp(&mut forever, Some(Duration::MAX));
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(ttl.unstamp(default.clone()).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(
+            ttl.unstamp_with_ttl(default.clone()).unwrap(),
+            Some((b"v".to_vec(), Some(Duration::from_secs(5))))
+        );
+        assert_eq!(
+            ttl.unstamp_with_ttl(forever.clone()).unwrap(),
+            Some((b"v".to_vec(), None))
+        );
+        assert_eq!(ttl.unstamp(short).unwrap(), None);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(ttl.unstamp(default).unwrap(), None);
+        assert_eq!(ttl.unstamp(forever).unwrap(), Some(b"v".to_vec()));
+        assert!(matches!(
+            ttl.unstamp(b"short".to_vec()),
+            Err(Error::Corruption(_))
+        ));
+    }
+
+    #[test]
+    fn test_merge_skips_expired_inputs() {
+        let clock = Arc::new(ManualClock::new(START));
+        let ttl = ttl(&clock);
+        let operator = TtlMergeOperator::new(Arc::new(U64AddOperator), ttl.clone());
+        let stamped = |n: u64, secs: u64| {
+            let mut value = counter(n);
+            ttl.stamp(&mut value, Some(Duration::from_secs(secs)));
+            value
+        };
+        let base = stamped(100, 1);
+        let old = stamped(1, 2);
+        let new = stamped(2, 20);
+
+        clock.advance(Duration::from_secs(5));
+        let merged = operator
+            .full_merge(b"k", Some(&base), &[&old, &new])
+            .unwrap();
+        assert_eq!(ttl.unstamp(merged).unwrap(), Some(counter(2)));
+
+        let partial = operator.partial_merge(b"k", &[&old, &new]).unwrap();
+        assert_eq!(ttl.unstamp(partial).unwrap(), Some(counter(2)));
+
+        // With every input expired the result is expired too
+        let merged = operator.full_merge(b"k", Some(&base), &[&old]).unwrap();
+        assert_eq!(ttl.unstamp(merged).unwrap(), None);
+    }
+
+    #[test]
+    fn test_engine_reads_treat_expired_values_as_absent() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(START));
+        let options = Options::new(dir.path())
+            .with_clock(Arc::clone(&clock) as Arc<dyn Clock>)
+            .with_ttl(Duration::from_secs(60))
+            .with_merge_operator(Arc::new(U64AddOperator));
+        let engine = StorageEngine::open(options).unwrap();
+        let plain = engine
+            .create_column_family("plain", ColumnFamilyOptions::default())
+            .unwrap();
+
+        engine.put(b"default".to_vec(), b"v".to_vec()).unwrap();
+        engine
+            .put_with_ttl(b"short".to_vec(), b"v".to_vec(), Duration::from_secs(5))
+            .unwrap();
+        engine.merge(b"n".to_vec(), counter(1)).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl(b"batch".to_vec(), b"v".to_vec(), Duration::from_secs(5));
+        engine.write(batch, false).unwrap();
+        engine.put_cf(&plain, b"k".to_vec(), b"v".to_vec()).unwrap();
+        assert!(matches!(
+            engine.put_cf_with_ttl(&plain, b"k".to_vec(), b"v".to_vec(), Duration::from_secs(1)),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        let snapshot = engine.snapshot();
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(engine.get(b"short").unwrap(), None);
+        assert_eq!(engine.get(b"batch").unwrap(), None);
+        assert_eq!(snapshot.get(b"short").unwrap(), None);
+        assert_eq!(engine.get(b"default").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(engine.get(b"n").unwrap(), Some(counter(1)));
+        assert_eq!(
+            engine.scan::<[u8], _>(..).unwrap(),
+            vec![
+                (b"default".to_vec(), b"v".to_vec()),
+                (b"n".to_vec(), counter(1))
+            ]
+        );
+        let default = engine.cf_handle("default").unwrap();
+        assert_eq!(
+            engine.get_cf_with_ttl(&default, b"default").unwrap(),
+            Some((b"v".to_vec(), Some(Duration::from_secs(50))))
+        );
+        assert_eq!(engine.get_cf_with_ttl(&default, b"short").unwrap(), None);
+        assert_eq!(
+            engine.get_cf_with_ttl(&plain, b"k").unwrap(),
+            Some((b"v".to_vec(), None))
+        );
+
+        // Own writes of a transaction carry the default TTL as well
+        let mut txn = engine.begin_transaction();
+        txn.put(b"txn".to_vec(), b"v".to_vec());
+        assert_eq!(txn.get(b"txn").unwrap(), Some(b"v".to_vec()));
+        txn.commit().unwrap();
+
+        engine.flush().unwrap();
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(engine.get(b"default").unwrap(), None);
+        assert_eq!(engine.get(b"txn").unwrap(), None);
+        assert_eq!(engine.get(b"n").unwrap(), None);
+        // Families without a TTL never expire
+        assert_eq!(engine.get_cf(&plain, b"k").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_compaction_drops_expired_values() {
+        let dir = TempDir::new().unwrap();
+        let clock = Arc::new(ManualClock::new(START));
+        let options = Options::new(dir.path())
+            .with_clock(Arc::clone(&clock) as Arc<dyn Clock>)
+            .with_ttl(Duration::MAX);
+        let engine = StorageEngine::open(options).unwrap();
+
+        // Four level 0 tables start a compaction once the last is flushed
+        for round in 0..4 {
+            for i in 0..100 {
+                let ttl = if i % 2 == 0 { 10 } else { 1_000 };
+                engine
+                    .put_with_ttl(key(i), vec![round; 32], Duration::from_secs(ttl))
+                    .unwrap();
+            }
+            if round == 3 {
+                clock.advance(Duration::from_secs(60));
+            }
+            engine.flush().unwrap();
+        }
+
+        let versions = &engine.inner.default.versions;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !versions.current().files(0).is_empty() {
+            assert!(Instant::now() < deadline, "no compaction ran");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let current = versions.current();
+        let mut live = 0;
+        for level in 1..crate::manifest::NUM_LEVELS {
+            for table in current.files(level) {
+                for entry in table.open_reader().unwrap().iter().unwrap() {
+                    let entry = entry.unwrap();
+                    if entry.operation == Operation::Put {
+                        live += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(live, 50);
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap().len(), 50);
+    }
+}
@@ -0,0 +1,397 @@
+//! Watching keys for changes
+//!
+//! [`StorageEngine::watch`](super::StorageEngine::watch) returns a
+//! [`Watcher`] receiving every change committed to the keys under a
+//! prefix, which is what invalidating a cache or reloading a setting the
+//! moment it changes takes. The commit pipeline hands out the events
+//! itself: once a batch is applied and published, its writes under each
+//! watcher's prefix are queued on that watcher. Nothing polls, and no
+//! watcher hears of a write before readers can see it.
+//!
+//! ```text
+//!  write ──▶ WAL ──▶ MemTable ──▶ publish ──▶ queue of each matching Watcher ──▶ recv()
+//! ```
+//!
+//! Events arrive in commit order, those of a batch one after another.
+//! Deletes carry no value and merges their operand; range deletions come
+//! as deletes of the keys they covered. Replicas report the writes they
+//! replicate. Engines opened read-only or as secondaries apply no writes
+//! and can't be watched.
+//!
+//! # Falling Behind
+//!
+//! A watcher's queue holds at most [`DEFAULT_WATCH_CAPACITY`] events,
+//! or the capacity set with [`Watcher::with_capacity`]. Events that don't
+//! fit are dropped rather than holding up writers, and once the watcher
+//! has received the events queued before them, receiving fails with the
+//! number missed, so the caller can re-read the keys instead of acting on
+//! stale state.
+
+use super::ttl;
+use super::EngineInner;
+use crate::wal::WALEntry;
+use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
+
+use parking_lot::{Condvar, Mutex};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// Events a watcher queues unless given another capacity
+pub const DEFAULT_WATCH_CAPACITY: usize = 4096;
+
+/// A change committed to a watched key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The key changed
+    pub key: Key,
+    /// What changed it
+    pub operation: Operation,
+    /// The value put or the merge operand, empty for a delete
+    pub value: Value,
+    /// Timestamp of the write
+    pub timestamp: Timestamp,
+}
+
+/// Receives the changes to the keys under a prefix of one column family
+///
+/// Dropping the watcher stops its events. Receiving returns `None` once
+/// the engine is closed and every event queued before has been received.
+/// As an [`Iterator`], a watcher waits for each event.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_core::Operation;
+/// use ferrisdb_storage::StorageEngine;
+///
+/// let engine = StorageEngine::open_in_memory()?;
+/// let config = engine.watch(b"config/")?;
+///
+/// engine.put(b"config/timeout".to_vec(), b"30".to_vec())?;
+/// engine.put(b"users/1".to_vec(), b"alice".to_vec())?;
+/// engine.delete(b"config/timeout".to_vec())?;
+///
+/// let event = config.recv()?.unwrap();
+/// assert_eq!((event.key.as_slice(), event.value.as_slice()), (&b"config/timeout"[..], &b"30"[..]));
+/// assert_eq!(config.recv()?.unwrap().operation, Operation::Delete);
+/// assert_eq!(config.try_recv()?, None);
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct Watcher {
+    queue: Arc<WatchQueue>,
+}
+
+impl Watcher {
+    /// Sets how many events the watcher queues before it misses some
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.queue.state.lock().capacity = capacity;
+        self
+    }
+
+    /// Returns the prefix of the keys watched
+    pub fn prefix(&self) -> &[u8] {
+        &self.queue.prefix
+    }
+
+    /// Waits for the next event
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the queue overflowed and
+    /// events were missed.
+    pub fn recv(&self) -> Result<Option<WatchEvent>> {
+        self.receive(Some(None))
+    }
+
+    /// Waits up to `timeout` for the next event, returning `None` if none
+    /// arrives in time
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the queue overflowed and
+    /// events were missed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<WatchEvent>> {
+        self.receive(Some(Some(Instant::now() + timeout)))
+    }
+
+    /// Returns the next event if one is queued
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the queue overflowed and
+    /// events were missed.
+    pub fn try_recv(&self) -> Result<Option<WatchEvent>> {
+        self.receive(None)
+    }
+
+    /// Takes the next event, waiting until the deadline, if any, when
+    /// `wait` is given
+    fn receive(&self, mut wait: Option<Option<Instant>>) -> Result<Option<WatchEvent>> {
+        let mut state = self.queue.state.lock();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if state.missed > 0 {
+                let missed = std::mem::take(&mut state.missed);
+                return Err(Error::InvalidOperation(format!(
+                    "The watcher fell behind and missed {} changes",
+                    missed
+                )));
+            }
+            match wait {
+                _ if state.closed => return Ok(None),
+                None => return Ok(None),
+                Some(None) => self.queue.ready.wait(&mut state),
+                Some(Some(deadline)) => {
+                    if self
+                        .queue
+                        .ready
+                        .wait_until(&mut state, deadline)
+                        .timed_out()
+                    {
+                        // Only take what arrived along with the timeout
+                        wait = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<WatchEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv().transpose()
+    }
+}
+
+impl std::fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher")
+            .field("column_family", &self.queue.column_family)
+            .field("prefix", &self.queue.prefix)
+            .finish()
+    }
+}
+
+/// Events waiting for one watcher
+pub(super) struct WatchQueue {
+    column_family: u32,
+    prefix: Vec<u8>,
+    state: Mutex<QueueState>,
+    /// Signalled when events are queued or the engine closes
+    ready: Condvar,
+}
+
+struct QueueState {
+    events: VecDeque<WatchEvent>,
+    capacity: usize,
+    /// Events dropped since the watcher last heard of it
+    missed: u64,
+    closed: bool,
+}
+
+impl WatchQueue {
+    fn push(&self, events: Vec<WatchEvent>) {
+        let mut state = self.state.lock();
+        for event in events {
+            if state.events.len() < state.capacity {
+                state.events.push_back(event);
+            } else {
+                state.missed += 1;
+            }
+        }
+        drop(state);
+        self.ready.notify_all();
+    }
+}
+
+/// Events of one batch, with the watcher each goes to
+pub(super) type PendingEvents = Vec<(Arc<WatchQueue>, Vec<WatchEvent>)>;
+
+impl EngineInner {
+    /// Starts watching the keys of column family `column_family` under
+    /// `prefix`
+    pub(super) fn watch(&self, column_family: u32, prefix: &[u8]) -> Result<Watcher> {
+        self.check_writable()?;
+        let queue = Arc::new(WatchQueue {
+            column_family,
+            prefix: prefix.to_vec(),
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                capacity: DEFAULT_WATCH_CAPACITY,
+                missed: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        });
+        let mut watchers = self.watchers.lock();
+        watchers.retain(|watcher| watcher.strong_count() > 0);
+        watchers.push(Arc::downgrade(&queue));
+        Ok(Watcher { queue })
+    }
+
+    /// Returns the events `entries` make for each watcher, to be delivered
+    /// with [`deliver`] once they are published
+    pub(super) fn watch_events(&self, entries: &[WALEntry]) -> PendingEvents {
+        let watchers = self.watchers.lock();
+        if watchers.is_empty() {
+            return Vec::new();
+        }
+        let families = self.column_families.read();
+        watchers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter_map(|queue| {
+                let has_ttl = families
+                    .get(&queue.column_family)
+                    .is_some_and(|cf| cf.ttl.is_some());
+                let events: Vec<WatchEvent> = entries
+                    .iter()
+                    .filter(|entry| {
+                        entry.column_family == queue.column_family
+                            && entry.key.starts_with(&queue.prefix)
+                    })
+                    .map(|entry| {
+                        let value = match entry.operation {
+                            // Watchers get the value as written, without its expiry
+                            Operation::Put | Operation::Merge if has_ttl => {
+                                ttl::strip_expiry(&entry.value)
+                            }
+                            _ => &entry.value,
+                        };
+                        WatchEvent {
+                            key: entry.key.clone(),
+                            operation: entry.operation,
+                            value: value.to_vec(),
+                            timestamp: entry.timestamp,
+                        }
+                    })
+                    .collect();
+                (!events.is_empty()).then_some((queue, events))
+            })
+            .collect()
+    }
+
+    /// Ends every watcher once it has received the events queued
+    pub(super) fn close_watchers(&self) {
+        for queue in self.watchers.lock().drain(..).filter_map(|w| w.upgrade()) {
+            queue.state.lock().closed = true;
+            queue.ready.notify_all();
+        }
+    }
+}
+
+/// Queues the events of a published batch on their watchers
+pub(super) fn deliver(pending: PendingEvents) {
+    for (queue, events) in pending {
+        queue.push(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ColumnFamilyOptions, Options, StorageEngine, WriteBatch};
+    use super::*;
+    use tempfile::TempDir;
+
+    use std::thread;
+
+    fn keys(watcher: &Watcher) -> Vec<(Key, Operation)> {
+        std::iter::from_fn(|| watcher.try_recv().unwrap())
+            .map(|event| (event.key, event.operation))
+            .collect()
+    }
+
+    #[test]
+    fn test_watchers_receive_committed_changes_under_their_prefix() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let cache = engine
+            .create_column_family("cache", ColumnFamilyOptions::default())
+            .unwrap();
+        let users = engine.watch(b"user/").unwrap();
+        let everything = engine.watch(b"").unwrap();
+        let cached = engine.watch_cf(&cache, b"user/").unwrap();
+
+        engine.put(b"user/1".to_vec(), b"alice".to_vec()).unwrap();
+        engine.put(b"group/1".to_vec(), b"admins".to_vec()).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"user/2".to_vec(), b"bob".to_vec());
+        batch.put_cf(&cache, b"user/2".to_vec(), b"bob".to_vec());
+        batch.delete_range(b"user/".as_slice()..b"user0".as_slice());
+        engine.write(batch, false).unwrap();
+        let mut txn = engine.begin_pessimistic_transaction();
+        txn.put(b"user/3".to_vec(), b"carol".to_vec()).unwrap();
+        txn.prepare("xid").unwrap();
+        assert_eq!(users.try_recv().unwrap().unwrap().key, b"user/1");
+        txn.commit().unwrap();
+
+        assert_eq!(
+            keys(&users),
+            vec![
+                (b"user/2".to_vec(), Operation::Put),
+                (b"user/1".to_vec(), Operation::Delete),
+                (b"user/2".to_vec(), Operation::Delete),
+                (b"user/3".to_vec(), Operation::Put),
+            ]
+        );
+        assert_eq!(keys(&everything).len(), 6);
+        assert_eq!(keys(&cached), vec![(b"user/2".to_vec(), Operation::Put)]);
+
+        // A watcher hears of a write once it is visible
+        let watcher = engine.watch(b"ready").unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| engine.put(b"ready".to_vec(), b"yes".to_vec()).unwrap());
+            let event = watcher.recv().unwrap().unwrap();
+            assert_eq!(engine.get(&event.key).unwrap(), Some(event.value));
+        });
+        assert_eq!(
+            watcher.recv_timeout(Duration::from_millis(10)).unwrap(),
+            None
+        );
+
+        engine.close().unwrap();
+        assert_eq!(users.recv().unwrap(), None);
+        assert_eq!(users.count(), 0);
+    }
+
+    #[test]
+    fn test_watchers_falling_behind_learn_how_much_they_missed() {
+        let engine = StorageEngine::open_in_memory().unwrap();
+        let ttl = engine
+            .create_column_family(
+                "sessions",
+                ColumnFamilyOptions::default().with_ttl(Duration::from_secs(60)),
+            )
+            .unwrap();
+        let watcher = engine.watch(b"").unwrap().with_capacity(2);
+        let sessions = engine.watch_cf(&ttl, b"").unwrap();
+        for i in 0..5u8 {
+            engine.put(vec![i], vec![i]).unwrap();
+        }
+        engine
+            .put_cf(&ttl, b"s1".to_vec(), b"token".to_vec())
+            .unwrap();
+
+        assert_eq!(watcher.try_recv().unwrap().unwrap().key, vec![0]);
+        assert_eq!(watcher.try_recv().unwrap().unwrap().key, vec![1]);
+        assert!(matches!(
+            watcher.try_recv(),
+            Err(Error::InvalidOperation(msg)) if msg.contains("missed 3")
+        ));
+        engine.put(b"next".to_vec(), Vec::new()).unwrap();
+        assert_eq!(watcher.try_recv().unwrap().unwrap().key, b"next");
+        assert_eq!(sessions.try_recv().unwrap().unwrap().value, b"token");
+
+        drop(watcher);
+        engine.put(b"after".to_vec(), Vec::new()).unwrap();
+        assert_eq!(engine.inner.watchers.lock().len(), 2);
+        engine.watch(b"again").unwrap();
+        assert_eq!(engine.inner.watchers.lock().len(), 2);
+    }
+}
@@ -0,0 +1,108 @@
+//! Options for writing to a storage engine
+
+use ferrisdb_core::SyncMode;
+
+/// Settings for a write through [`StorageEngine::write_with_options`](super::StorageEngine::write_with_options)
+///
+/// By default a write is logged to the WAL before it is applied, and
+/// synced as the configured sync mode says. Settings not changed through
+/// a `with_*` method keep their defaults.
+///
+/// Passing options per write lets an engine run with a lax sync mode and a
+/// [background WAL sync](super::Options::with_wal_sync_interval) while the
+/// few writes that must survive a power loss ask for a sync of their own,
+/// or the other way round.
+///
+/// # Example
+///
+/// ```
+/// use ferrisdb_storage::{Options, StorageEngine, WriteBatch, WriteOptions};
+///
+/// let dir = tempfile::tempdir()?;
+/// let engine = StorageEngine::open(Options::new(dir.path()))?;
+///
+/// // A bulk load made durable by the flush at its end
+/// let options = WriteOptions::new().with_disable_wal(true);
+/// for chunk in 0..10u32 {
+///     let mut batch = WriteBatch::new();
+///     for i in 0..100u32 {
+///         batch.put((chunk * 100 + i).to_be_bytes().to_vec(), b"row".to_vec());
+///     }
+///     engine.write_with_options(batch, &options)?;
+/// }
+/// engine.flush()?;
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Whether the WAL is synced to disk before the write returns, or
+    /// `None` to follow the configured sync mode
+    pub(super) sync: Option<bool>,
+    /// Whether the write skips the WAL
+    pub(super) disable_wal: bool,
+}
+
+impl WriteOptions {
+    /// Creates options for a logged write
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the configured sync mode for these writes
+    ///
+    /// With `true`, the WAL is synced to disk before the write returns,
+    /// which also makes every earlier write durable. With `false`, the
+    /// write is only handed to the OS, so it survives the process
+    /// crashing but not the machine; it becomes durable with the next
+    /// synced write, [`sync_wal`](super::StorageEngine::sync_wal),
+    /// background WAL sync, segment switch or close.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = Some(sync);
+        self
+    }
+
+    /// Applies writes to the MemTables without logging them to the WAL
+    ///
+    /// Meant for bulk loads that are made durable by a
+    /// [`flush`](super::StorageEngine::flush) once they finish, which
+    /// saves encoding every entry into the WAL and syncing it. Until the
+    /// MemTables holding them are flushed, such writes live only in
+    /// memory: a crash loses them, while logged writes made before and
+    /// after are recovered as usual. Closing the engine flushes them.
+    ///
+    /// Unlogged writes are also invisible to everything following the
+    /// WAL instead of the tables, such as [`tail_wal`](super::StorageEngine::tail_wal)
+    /// and secondary engines, until they are flushed.
+    pub fn with_disable_wal(mut self, disable_wal: bool) -> Self {
+        self.disable_wal = disable_wal;
+        self
+    }
+
+    /// Returns how the WAL record of a write is synced, given the
+    /// configured sync mode
+    pub(super) fn sync_mode(&self, configured: SyncMode) -> SyncMode {
+        match (self.sync, configured) {
+            (Some(true), _) => SyncMode::Full,
+            (Some(false), SyncMode::Full) => SyncMode::Normal,
+            (_, configured) => configured,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_overrides_the_configured_sync_mode() {
+        let default = WriteOptions::new();
+        let synced = WriteOptions::new().with_sync(true);
+        let unsynced = WriteOptions::new().with_sync(false);
+        for mode in [SyncMode::None, SyncMode::Normal, SyncMode::Full] {
+            assert_eq!(default.sync_mode(mode), mode);
+            assert_eq!(synced.sync_mode(mode), SyncMode::Full);
+        }
+        assert_eq!(unsynced.sync_mode(SyncMode::Full), SyncMode::Normal);
+        assert_eq!(unsynced.sync_mode(SyncMode::None), SyncMode::None);
+    }
+}
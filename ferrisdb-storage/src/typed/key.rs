@@ -0,0 +1,887 @@
+//! Order-preserving encoding of typed keys
+//!
+//! The engine orders keys bytewise, so a typed key is only useful in
+//! scans if its encoding sorts like the key itself. Every value is
+//! encoded so that it compares like the value and is never a prefix of
+//! another, which lets tuples and structs concatenate their fields:
+//!
+//! | Type                  | Encoding                                            |
+//! |-----------------------|-----------------------------------------------------|
+//! | `bool`, unsigned ints | big-endian                                          |
+//! | signed ints           | big-endian with the sign bit flipped                |
+//! | floats                | bits flipped so negatives sort first (total order)  |
+//! | `char`                | as `u32`                                            |
+//! | strings, bytes        | `0x00` escaped to `0x00 0xFF`, ended by `0x00 0x01` |
+//! | `Option`              | `0x00` for `None`, `0x01` then the value for `Some` |
+//! | sequences, maps       | `0x01` before each element, `0x00` at the end       |
+//! | tuples, structs       | fields one after another                            |
+//! | enums                 | variant index as `u32`, then the variant's fields   |
+//!
+//! Encoded keys therefore order the way `#[derive(Ord)]` orders the
+//! types: structs by their fields in declaration order, enums by variant
+//! first. Types with a hand-written `Ord` may sort differently.
+//!
+//! The encoding is not self-describing, so keys decode only into the type
+//! they were encoded from.
+
+use ferrisdb_core::{Error, Result};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+use std::fmt;
+
+/// Escaped form of a zero byte inside a string
+const ESCAPED_ZERO: [u8; 2] = [0x00, 0xFF];
+
+/// Ends a string; sorts before every escaped zero and every other byte
+const TERMINATOR: [u8; 2] = [0x00, 0x01];
+
+/// Marks an element of a sequence or map, or a present `Option`
+const MORE: u8 = 0x01;
+
+/// Ends a sequence or map, or marks an absent `Option`
+const END: u8 = 0x00;
+
+/// Encodes `key` into bytes ordering like the key
+pub fn encode_key<K: Serialize + ?Sized>(key: &K) -> Result<Vec<u8>> {
+    let mut serializer = KeySerializer { output: Vec::new() };
+    key.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+/// Decodes a key encoded by [`encode_key`]
+pub fn decode_key<K: DeserializeOwned>(bytes: &[u8]) -> Result<K> {
+    let mut deserializer = KeyDeserializer { input: bytes };
+    let key = K::deserialize(&mut deserializer)?;
+    if !deserializer.input.is_empty() {
+        return Err(Error::Serialization(format!(
+            "{} bytes left after the key",
+            deserializer.input.len()
+        )));
+    }
+    Ok(key)
+}
+
+/// Failure to encode or decode a key, as serde needs one of our own
+#[derive(Debug)]
+struct KeyError(String);
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl ser::Error for KeyError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        KeyError(message.to_string())
+    }
+}
+
+impl de::Error for KeyError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        KeyError(message.to_string())
+    }
+}
+
+impl From<KeyError> for Error {
+    fn from(error: KeyError) -> Self {
+        Error::Serialization(error.0)
+    }
+}
+
+type KeyResult<T> = std::result::Result<T, KeyError>;
+
+struct KeySerializer {
+    output: Vec<u8>,
+}
+
+impl KeySerializer {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                0 => self.output.extend_from_slice(&ESCAPED_ZERO),
+                byte => self.output.push(byte),
+            }
+        }
+        self.output.extend_from_slice(&TERMINATOR);
+    }
+
+    fn write_variant(&mut self, index: u32) {
+        self.output.extend_from_slice(&index.to_be_bytes());
+    }
+}
+
+impl ser::Serializer for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> KeyResult<()> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> KeyResult<()> {
+        self.serialize_u8(v as u8 ^ 0x80)
+    }
+
+    fn serialize_i16(self, v: i16) -> KeyResult<()> {
+        self.serialize_u16(v as u16 ^ (1 << 15))
+    }
+
+    fn serialize_i32(self, v: i32) -> KeyResult<()> {
+        self.serialize_u32(v as u32 ^ (1 << 31))
+    }
+
+    fn serialize_i64(self, v: i64) -> KeyResult<()> {
+        self.serialize_u64(v as u64 ^ (1 << 63))
+    }
+
+    fn serialize_i128(self, v: i128) -> KeyResult<()> {
+        self.serialize_u128(v as u128 ^ (1 << 127))
+    }
+
+    fn serialize_u8(self, v: u8) -> KeyResult<()> {
+        self.output.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> KeyResult<()> {
+        self.output.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> KeyResult<()> {
+        self.output.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> KeyResult<()> {
+        self.output.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> KeyResult<()> {
+        self.output.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> KeyResult<()> {
+        let bits = v.to_bits();
+        // Negatives reverse their order, positives go above them
+        let bits = if bits >> 31 == 1 {
+            !bits
+        } else {
+            bits | 1 << 31
+        };
+        self.serialize_u32(bits)
+    }
+
+    fn serialize_f64(self, v: f64) -> KeyResult<()> {
+        let bits = v.to_bits();
+        let bits = if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits | 1 << 63
+        };
+        self.serialize_u64(bits)
+    }
+
+    fn serialize_char(self, v: char) -> KeyResult<()> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> KeyResult<()> {
+        self.write_bytes(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> KeyResult<()> {
+        self.write_bytes(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> KeyResult<()> {
+        self.output.push(END);
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> KeyResult<()> {
+        self.output.push(MORE);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> KeyResult<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> KeyResult<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> KeyResult<()> {
+        self.write_variant(variant_index);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> KeyResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> KeyResult<()> {
+        self.write_variant(variant_index);
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> KeyResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> KeyResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> KeyResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> KeyResult<Self> {
+        self.write_variant(variant_index);
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> KeyResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> KeyResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> KeyResult<Self> {
+        self.write_variant(variant_index);
+        Ok(self)
+    }
+}
+
+impl ser::SerializeSeq for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> KeyResult<()> {
+        self.output.push(MORE);
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> KeyResult<()> {
+        self.output.push(MORE);
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> KeyResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> KeyResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> KeyResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> KeyResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> KeyResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut KeySerializer {
+    type Ok = ();
+    type Error = KeyError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> KeyResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> KeyResult<()> {
+        Ok(())
+    }
+}
+
+struct KeyDeserializer<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> KeyDeserializer<'de> {
+    fn read<const N: usize>(&mut self) -> KeyResult<[u8; N]> {
+        if self.input.len() < N {
+            return Err(KeyError("Key ends too early".to_string()));
+        }
+        let (bytes, rest) = self.input.split_at(N);
+        self.input = rest;
+        Ok(bytes.try_into().expect("split at N"))
+    }
+
+    fn read_u8(&mut self) -> KeyResult<u8> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn read_u32(&mut self) -> KeyResult<u32> {
+        Ok(u32::from_be_bytes(self.read()?))
+    }
+
+    fn read_u64(&mut self) -> KeyResult<u64> {
+        Ok(u64::from_be_bytes(self.read()?))
+    }
+
+    /// Reads a marker, returning whether another element follows
+    fn read_more(&mut self) -> KeyResult<bool> {
+        match self.read_u8()? {
+            END => Ok(false),
+            MORE => Ok(true),
+            marker => Err(KeyError(format!("Invalid marker {:#04x}", marker))),
+        }
+    }
+
+    /// Reads an escaped, terminated string of bytes
+    fn read_bytes(&mut self) -> KeyResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.read_u8()? {
+                0 => match self.read_u8()? {
+                    0x01 => return Ok(bytes),
+                    0xFF => bytes.push(0),
+                    byte => return Err(KeyError(format!("Invalid escape {:#04x}", byte))),
+                },
+                byte => bytes.push(byte),
+            }
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut KeyDeserializer<'de> {
+    type Error = KeyError;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> KeyResult<V::Value> {
+        Err(KeyError(
+            "Keys are not self-describing; decode them into the type they were encoded from"
+                .to_string(),
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            byte => Err(KeyError(format!("Invalid bool {:#04x}", byte))),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_i8((self.read_u8()? ^ 0x80) as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_i16((u16::from_be_bytes(self.read()?) ^ (1 << 15)) as i16)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_i32((self.read_u32()? ^ (1 << 31)) as i32)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_i64((self.read_u64()? ^ (1 << 63)) as i64)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_i128((u128::from_be_bytes(self.read()?) ^ (1 << 127)) as i128)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_u8(self.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_u16(u16::from_be_bytes(self.read()?))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_u32(self.read_u32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_u64(self.read_u64()?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_u128(u128::from_be_bytes(self.read()?))
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        let bits = self.read_u32()?;
+        let bits = if bits >> 31 == 1 {
+            bits & !(1 << 31)
+        } else {
+            !bits
+        };
+        visitor.visit_f32(f32::from_bits(bits))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        let bits = self.read_u64()?;
+        let bits = if bits >> 63 == 1 {
+            bits & !(1 << 63)
+        } else {
+            !bits
+        };
+        visitor.visit_f64(f64::from_bits(bits))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        let code = self.read_u32()?;
+        let c = char::from_u32(code).ok_or_else(|| KeyError(format!("Invalid char {}", code)))?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        let string = String::from_utf8(self.read_bytes()?)
+            .map_err(|e| KeyError(format!("Invalid string: {}", e)))?;
+        visitor.visit_string(string)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_byte_buf(self.read_bytes()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        if self.read_more()? {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> KeyResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> KeyResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_seq(Elements { de: self })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_seq(Fields {
+            de: self,
+            remaining: len,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> KeyResult<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        visitor.visit_map(Elements { de: self })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> KeyResult<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> KeyResult<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> KeyResult<V::Value> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// Elements of a sequence or map, each behind a marker
+struct Elements<'a, 'de> {
+    de: &'a mut KeyDeserializer<'de>,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = KeyError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> KeyResult<Option<T::Value>> {
+        if !self.de.read_more()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'_, 'de> {
+    type Error = KeyError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> KeyResult<Option<K::Value>> {
+        if !self.de.read_more()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> KeyResult<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// A known number of fields of a tuple or struct
+struct Fields<'a, 'de> {
+    de: &'a mut KeyDeserializer<'de>,
+    remaining: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Fields<'_, 'de> {
+    type Error = KeyError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> KeyResult<Option<T::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut KeyDeserializer<'de> {
+    type Error = KeyError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> KeyResult<(V::Value, Self)> {
+        let index = self.read_u32()?;
+        let variant = seed.deserialize(IntoDeserializer::<KeyError>::into_deserializer(index))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut KeyDeserializer<'de> {
+    type Error = KeyError;
+
+    fn unit_variant(self) -> KeyResult<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> KeyResult<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> KeyResult<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> KeyResult<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    use std::collections::BTreeMap;
+    use std::fmt::Debug;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    enum Event {
+        Created,
+        Renamed(String),
+        Moved { from: u32, to: u32 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct Id {
+        tenant: i16,
+        path: Vec<String>,
+        version: Option<u64>,
+    }
+
+    /// Checks that `keys`, given in order, encode in order and decode back
+    fn assert_ordered<K>(keys: &[K])
+    where
+        K: Serialize + DeserializeOwned + Ord + Debug,
+    {
+        let encoded: Vec<Vec<u8>> = keys.iter().map(|key| encode_key(key).unwrap()).collect();
+        for (key, bytes) in keys.iter().zip(&encoded) {
+            assert_eq!(&decode_key::<K>(bytes).unwrap(), key);
+        }
+        for (i, pair) in encoded.windows(2).enumerate() {
+            assert!(keys[i] < keys[i + 1], "test keys out of order");
+            assert!(pair[0] < pair[1], "{:?} !< {:?}", keys[i], keys[i + 1]);
+        }
+    }
+
+    #[test]
+    fn test_integers_and_strings_order_like_their_values() {
+        assert_ordered(&[i64::MIN, -300, -1, 0, 1, 256, i64::MAX]);
+        assert_ordered(&[0u16, 1, 255, 256, u16::MAX]);
+        assert_ordered(&[-128i8, -1, 0, 127]);
+        assert_ordered(&[i128::MIN, -1, 0, i128::MAX]);
+        assert_ordered(&['\0', 'a', 'é', '🦀']);
+        assert_ordered(&[
+            String::new(),
+            "\0".to_string(),
+            "\0\0".to_string(),
+            "\0a".to_string(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_floats_sort_in_total_order() {
+        let floats = [
+            f64::NEG_INFINITY,
+            -2.5,
+            -0.0,
+            0.0,
+            1e-300,
+            3.0,
+            f64::INFINITY,
+        ];
+        let encoded: Vec<Vec<u8>> = floats.iter().map(|f| encode_key(f).unwrap()).collect();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (float, bytes) in floats.iter().zip(&encoded) {
+            assert_eq!(decode_key::<f64>(bytes).unwrap().to_bits(), float.to_bits());
+        }
+        assert_eq!(
+            decode_key::<f32>(&encode_key(&-1.5f32).unwrap()).unwrap(),
+            -1.5
+        );
+    }
+
+    #[test]
+    fn test_composite_keys_order_like_derived_ord() {
+        assert_ordered(&[
+            (1u8, "b".to_string()),
+            (2, String::new()),
+            (2, "a".to_string()),
+        ]);
+        assert_ordered(&[None, Some(0u32), Some(7)]);
+        assert_ordered(&[vec![], vec![0u8], vec![0, 0], vec![1]]);
+        assert_ordered(&[
+            Event::Created,
+            Event::Renamed(String::new()),
+            Event::Renamed("x".to_string()),
+            Event::Moved { from: 1, to: 9 },
+            Event::Moved { from: 2, to: 0 },
+        ]);
+        let path = |parts: &[&str]| parts.iter().map(|part| part.to_string()).collect();
+        assert_ordered(&[
+            Id {
+                tenant: -1,
+                path: path(&["z"]),
+                version: Some(3),
+            },
+            Id {
+                tenant: 0,
+                path: path(&[]),
+                version: Some(1),
+            },
+            Id {
+                tenant: 0,
+                path: path(&["a"]),
+                version: None,
+            },
+            Id {
+                tenant: 0,
+                path: path(&["a", ""]),
+                version: None,
+            },
+            Id {
+                tenant: 0,
+                path: path(&["ab"]),
+                version: None,
+            },
+        ]);
+
+        let map: BTreeMap<String, (bool, ())> = [("a".to_string(), (true, ()))].into();
+        assert_eq!(
+            decode_key::<BTreeMap<String, (bool, ())>>(&encode_key(&map).unwrap()).unwrap(),
+            map
+        );
+    }
+
+    #[test]
+    fn test_malformed_keys_fail_to_decode() {
+        let encoded = encode_key(&("a".to_string(), 7u32)).unwrap();
+        assert!(decode_key::<(String, u32)>(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_key::<String>(&encoded).is_err());
+        assert!(decode_key::<bool>(&[2]).is_err());
+        assert!(decode_key::<String>(&[b'a', 0x00, 0x02]).is_err());
+        assert!(decode_key::<Event>(&[0, 0, 0, 9]).is_err());
+        assert!(matches!(
+            decode_key::<u8>(&[1, 2]),
+            Err(Error::Serialization(_))
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+//! Typed tables over the byte-oriented engine
+//!
+//! The engine stores byte strings. A [`Table`] stores Rust values
+//! instead: it keeps records with keys of type `K` and values of type `V`
+//! in a column family of its own, encoding keys with [`encode_key`] and
+//! values with bincode. Encoded keys order like the keys, so scans take
+//! ranges of `K` and return records in `K`'s order.
+//!
+//! The encodings are not versioned: changing the definition of `K` or `V`
+//! makes records written before undecodable, unless serde reads the old
+//! layout as the new one.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::typed::Table;
+//! use ferrisdb_storage::StorageEngine;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Order {
+//!     item: String,
+//!     quantity: u32,
+//! }
+//!
+//! let engine = StorageEngine::open_in_memory()?;
+//! // Orders by customer, then order number
+//! let orders: Table<(String, u64), Order> = Table::open(&engine, "orders")?;
+//!
+//! let order = Order { item: "ferris plush".to_string(), quantity: 2 };
+//! orders.put(&("alice".to_string(), 1), &order)?;
+//! orders.put(&("alice".to_string(), 2), &order)?;
+//! orders.put(&("bob".to_string(), 1), &order)?;
+//!
+//! assert_eq!(orders.get(&("alice".to_string(), 1))?, Some(order));
+//! let of_alice = orders.scan(("alice".to_string(), 0)..("alice".to_string(), u64::MAX))?;
+//! assert_eq!(of_alice.len(), 2);
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+mod key;
+
+pub use key::{decode_key, encode_key};
+
+use crate::storage_engine::{ColumnFamily, ColumnFamilyOptions, StorageEngine, WriteBatch};
+use bincode::Options as _;
+use ferrisdb_core::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+/// Records with keys of type `K` and values of type `V`, kept in one
+/// column family
+///
+/// Tables borrow the engine and are cheap to create; any number may
+/// share a family, as long as they agree on its types.
+pub struct Table<'a, K, V> {
+    engine: &'a StorageEngine,
+    column_family: ColumnFamily,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'a, K, V> Table<'a, K, V>
+where
+    K: Serialize + DeserializeOwned + Ord,
+    V: Serialize + DeserializeOwned,
+{
+    /// Opens the table stored in column family `name`, creating the
+    /// family with default options if it doesn't exist
+    pub fn open(engine: &'a StorageEngine, name: &str) -> Result<Self> {
+        let column_family = match engine.cf_handle(name) {
+            Some(column_family) => column_family,
+            None => engine.create_column_family(name, ColumnFamilyOptions::default())?,
+        };
+        Ok(Self::new(engine, column_family))
+    }
+
+    /// Creates a table over an existing column family
+    pub fn new(engine: &'a StorageEngine, column_family: ColumnFamily) -> Self {
+        Self {
+            engine,
+            column_family,
+            types: PhantomData,
+        }
+    }
+
+    /// Returns the column family holding the table
+    pub fn column_family(&self) -> &ColumnFamily {
+        &self.column_family
+    }
+
+    /// Returns the value of `key`
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.engine
+            .get_cf(&self.column_family, &encode_key(key)?)?
+            .map(|value| decode_value(&value))
+            .transpose()
+    }
+
+    /// Returns whether the table holds `key`
+    pub fn contains_key(&self, key: &K) -> Result<bool> {
+        Ok(self
+            .engine
+            .get_cf(&self.column_family, &encode_key(key)?)?
+            .is_some())
+    }
+
+    /// Sets the value of `key`
+    pub fn put(&self, key: &K, value: &V) -> Result<()> {
+        self.engine
+            .put_cf(&self.column_family, encode_key(key)?, encode_value(value)?)
+    }
+
+    /// Deletes `key`
+    pub fn delete(&self, key: &K) -> Result<()> {
+        self.engine.delete_cf(&self.column_family, encode_key(key)?)
+    }
+
+    /// Returns the records with keys in `range`, in key order
+    pub fn scan<R: RangeBounds<K>>(&self, range: R) -> Result<Vec<(K, V)>> {
+        let range = (
+            encode_bound(range.start_bound())?,
+            encode_bound(range.end_bound())?,
+        );
+        self.engine
+            .scan_cf(&self.column_family, range)?
+            .into_iter()
+            .map(|(key, value)| Ok((decode_key(&key)?, decode_value(&value)?)))
+            .collect()
+    }
+
+    /// Adds setting the value of `key` to `batch`, so that it commits
+    /// atomically with the batch's other writes
+    pub fn batch_put(&self, batch: &mut WriteBatch, key: &K, value: &V) -> Result<()> {
+        batch.put_cf(&self.column_family, encode_key(key)?, encode_value(value)?);
+        Ok(())
+    }
+
+    /// Adds deleting `key` to `batch`
+    pub fn batch_delete(&self, batch: &mut WriteBatch, key: &K) -> Result<()> {
+        batch.delete_cf(&self.column_family, encode_key(key)?);
+        Ok(())
+    }
+}
+
+impl<K, V> fmt::Debug for Table<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("column_family", &self.column_family.name())
+            .field("key", &std::any::type_name::<K>())
+            .field("value", &std::any::type_name::<V>())
+            .finish()
+    }
+}
+
+/// Fixed-width integers as `bincode::serialize` writes them, but values
+/// must use up all their bytes to decode
+fn value_codec() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_fixint_encoding()
+}
+
+fn encode_value<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+    value_codec()
+        .serialize(value)
+        .map_err(|e| Error::Serialization(e.to_string()))
+}
+
+fn decode_value<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+    value_codec()
+        .deserialize(bytes)
+        .map_err(|e| Error::Serialization(e.to_string()))
+}
+
+fn encode_bound<K: Serialize>(bound: Bound<&K>) -> Result<Bound<Vec<u8>>> {
+    Ok(match bound {
+        Bound::Included(key) => Bound::Included(encode_key(key)?),
+        Bound::Excluded(key) => Bound::Excluded(encode_key(key)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage_engine::Options;
+    use serde::Deserialize;
+    use tempfile::TempDir;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Reading {
+        sensor: String,
+        celsius: f64,
+    }
+
+    fn reading(sensor: &str, celsius: f64) -> Reading {
+        Reading {
+            sensor: sensor.to_string(),
+            celsius,
+        }
+    }
+
+    #[test]
+    fn test_tables_store_typed_records_in_key_order() {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let readings: Table<(i32, u64), Reading> = Table::open(&engine, "readings").unwrap();
+
+        for (zone, time) in [(3, 10), (-2, 5), (3, 2), (0, 7), (-2, 1)] {
+            let value = reading(&format!("s{}", zone), time as f64 / 2.0);
+            readings.put(&(zone, time), &value).unwrap();
+        }
+        assert_eq!(readings.get(&(3, 2)).unwrap(), Some(reading("s3", 1.0)));
+        assert_eq!(readings.get(&(3, 3)).unwrap(), None);
+        assert!(readings.contains_key(&(-2, 1)).unwrap());
+
+        let keys = |records: Vec<((i32, u64), Reading)>| -> Vec<(i32, u64)> {
+            records.into_iter().map(|(key, _)| key).collect()
+        };
+        assert_eq!(
+            keys(readings.scan(..).unwrap()),
+            vec![(-2, 1), (-2, 5), (0, 7), (3, 2), (3, 10)]
+        );
+        assert_eq!(
+            keys(readings.scan((-2, 5)..=(3, 2)).unwrap()),
+            vec![(-2, 5), (0, 7), (3, 2)]
+        );
+        assert_eq!(
+            keys(readings.scan((0, u64::MAX)..).unwrap()),
+            vec![(3, 2), (3, 10)]
+        );
+
+        let mut batch = WriteBatch::new();
+        readings.batch_delete(&mut batch, &(3, 10)).unwrap();
+        readings
+            .batch_put(&mut batch, &(9, 0), &reading("s9", -4.5))
+            .unwrap();
+        engine.write(batch, false).unwrap();
+        readings.delete(&(-2, 1)).unwrap();
+        assert_eq!(
+            keys(readings.scan(..).unwrap()),
+            vec![(-2, 5), (0, 7), (3, 2), (9, 0)]
+        );
+        drop(readings);
+        drop(engine);
+
+        let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+        let readings: Table<(i32, u64), Reading> = Table::open(&engine, "readings").unwrap();
+        assert_eq!(readings.get(&(9, 0)).unwrap(), Some(reading("s9", -4.5)));
+        assert!(engine
+            .get_cf(readings.column_family(), b"raw")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_records_of_another_type_fail_to_decode() {
+        let engine = StorageEngine::open_in_memory().unwrap();
+        let names: Table<String, String> = Table::open(&engine, "names").unwrap();
+        names.put(&"a".to_string(), &"x".to_string()).unwrap();
+
+        let cf = names.column_family().clone();
+        engine.put_cf(&cf, b"junk".to_vec(), vec![0xFF]).unwrap();
+        assert!(matches!(names.scan(..), Err(Error::Serialization(_))));
+
+        let counts: Table<String, u64> = Table::new(&engine, cf);
+        assert!(matches!(
+            counts.get(&"a".to_string()),
+            Err(Error::Serialization(_))
+        ));
+    }
+}
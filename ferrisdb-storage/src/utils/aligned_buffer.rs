@@ -0,0 +1,178 @@
+//! Buffers for direct I/O
+//!
+//! Files opened for direct I/O bypass the OS page cache, and the kernel
+//! moves their data straight between the disk and the caller's memory.
+//! That only works in whole sectors: the buffer's address, the file
+//! offset and the length must all be multiples of the device's logical
+//! block size. An [`AlignedBuffer`] keeps its bytes at an address aligned
+//! to [`DIRECT_IO_ALIGNMENT`], which covers the block sizes of common
+//! devices, and pads its contents to that alignment for writes.
+//!
+//! The alignment is found without unsafe code: the buffer over-allocates
+//! by one alignment and starts its bytes at the first aligned address of
+//! the allocation, which never moves because the allocation never grows.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::aligned_buffer::{AlignedBuffer, DIRECT_IO_ALIGNMENT};
+//!
+//! let mut buffer = AlignedBuffer::with_capacity(8192);
+//! assert_eq!(buffer.extend_from_slice(b"record"), 6);
+//! assert_eq!(buffer.as_slice().as_ptr() as usize % DIRECT_IO_ALIGNMENT, 0);
+//!
+//! // Padded with zeros to a whole block for writing
+//! let padded = buffer.padded();
+//! assert_eq!(padded.len(), DIRECT_IO_ALIGNMENT);
+//! assert_eq!(&padded[..6], b"record");
+//! ```
+
+use std::fmt;
+
+/// Alignment of direct I/O buffers, offsets and lengths (in bytes)
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Rounds `n` down to a multiple of [`DIRECT_IO_ALIGNMENT`]
+pub fn align_down(n: u64) -> u64 {
+    n & !(DIRECT_IO_ALIGNMENT as u64 - 1)
+}
+
+/// Rounds `n` up to a multiple of [`DIRECT_IO_ALIGNMENT`]
+pub fn align_up(n: usize) -> usize {
+    n.next_multiple_of(DIRECT_IO_ALIGNMENT)
+}
+
+/// A fixed-capacity byte buffer whose start is aligned for direct I/O
+pub struct AlignedBuffer {
+    /// Allocation holding the aligned bytes; never resized
+    storage: Vec<u8>,
+    /// Offset of the first aligned byte in `storage`
+    start: usize,
+    capacity: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    /// Creates an empty buffer holding at least `capacity` bytes
+    ///
+    /// The capacity is rounded up to a multiple of the alignment.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = align_up(capacity.max(1));
+        let storage = vec![0; capacity + DIRECT_IO_ALIGNMENT];
+        let start = storage.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+        Self {
+            storage,
+            start,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of bytes the buffer holds
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the buffer holds no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes the buffer can hold
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns true if the buffer can't take another byte
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity
+    }
+
+    /// Removes every byte, keeping the allocation
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends as much of `data` as fits and returns how much that was
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(self.capacity - self.len);
+        let at = self.start + self.len;
+        self.storage[at..at + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        n
+    }
+
+    /// Returns the bytes the buffer holds
+    pub fn as_slice(&self) -> &[u8] {
+        &self.storage[self.start..self.start + self.len]
+    }
+
+    /// Returns the bytes the buffer holds, zero-padded to a multiple of
+    /// the alignment
+    pub fn padded(&mut self) -> &[u8] {
+        let end = self.start + align_up(self.len);
+        self.storage[self.start + self.len..end].fill(0);
+        &self.storage[self.start..end]
+    }
+
+    /// Sets the buffer's length to `len` and returns its bytes for
+    /// reading into
+    ///
+    /// Bytes past the previous length keep whatever they held before.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` exceeds the capacity.
+    pub fn resize_for_read(&mut self, len: usize) -> &mut [u8] {
+        assert!(len <= self.capacity, "{} exceeds capacity", len);
+        self.len = len;
+        &mut self.storage[self.start..self.start + len]
+    }
+}
+
+impl fmt::Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlignedBuffer")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_stays_aligned_and_bounded() {
+        for capacity in [1, 100, 4096, 5000] {
+            let mut buffer = AlignedBuffer::with_capacity(capacity);
+            assert_eq!(buffer.capacity() % DIRECT_IO_ALIGNMENT, 0);
+            assert!(buffer.capacity() >= capacity);
+            assert_eq!(buffer.as_slice().as_ptr() as usize % DIRECT_IO_ALIGNMENT, 0);
+
+            let data = vec![7u8; buffer.capacity() + 10];
+            assert_eq!(buffer.extend_from_slice(&data), buffer.capacity());
+            assert!(buffer.is_full());
+            assert_eq!(buffer.extend_from_slice(b"more"), 0);
+        }
+    }
+
+    #[test]
+    fn test_padding_is_zeroed_after_reuse() {
+        let mut buffer = AlignedBuffer::with_capacity(8192);
+        buffer.extend_from_slice(&[0xff; 5000]);
+        buffer.clear();
+        buffer.extend_from_slice(b"abc");
+
+        let padded = buffer.padded();
+        assert_eq!(padded.len(), DIRECT_IO_ALIGNMENT);
+        assert_eq!(&padded[..3], b"abc");
+        assert!(padded[3..].iter().all(|&byte| byte == 0));
+        assert_eq!(buffer.as_slice(), b"abc");
+
+        assert_eq!(align_down(5000), 4096);
+        assert_eq!(align_up(4097), 8192);
+        assert_eq!(align_up(0), 0);
+    }
+}
@@ -0,0 +1,172 @@
+//! Crash-safe file creation
+//!
+//! Writing a file in place leaves a window in which a crash exposes it
+//! half-written, and even a fully written and synced file can vanish after
+//! a crash if the directory entry pointing at it was never synced.
+//! [`write_atomic`] closes both gaps for small metadata files: the bytes go
+//! to a temporary file next to the target, which is synced and then renamed
+//! over it, and the directory is synced last. After a crash, readers see
+//! either the old file or the complete new one.
+//!
+//! Files written incrementally, such as SSTables and MANIFESTs, are synced
+//! by their writers; they only need their directory synced with
+//! [`sync_dir`] or [`sync_parent_dir`] once created.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::atomic_file;
+//!
+//! let dir = tempfile::TempDir::new()?;
+//! let path = dir.path().join("CURRENT");
+//!
+//! atomic_file::write_atomic(&path, b"MANIFEST-000002\n")?;
+//! atomic_file::write_atomic(&path, b"MANIFEST-000007\n")?;
+//! assert_eq!(std::fs::read(&path)?, b"MANIFEST-000007\n");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::{Error, Result};
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the temporary file a new version is written to
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Replaces the file at `path` with `bytes`, atomically and durably
+///
+/// Any existing file is replaced as a whole. A temporary file left behind
+/// by a crash in the middle of the write is overwritten by the next call.
+///
+/// # Errors
+///
+/// Returns an I/O error, with the failing path attached, if the temporary
+/// file cannot be written or renamed, or the directory cannot be synced.
+/// The target is untouched unless the rename succeeded.
+pub fn write_atomic(path: impl AsRef<Path>, bytes: &[u8]) -> Result<()> {
+    write_atomic_in(vfs::os().as_ref(), path, bytes)
+}
+
+/// Replaces the file at `path` on `vfs` with `bytes`, atomically and
+/// durably
+///
+/// # Errors
+///
+/// Returns the errors of [`write_atomic`].
+pub fn write_atomic_in(vfs: &dyn Vfs, path: impl AsRef<Path>, bytes: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let temp = temp_path(path);
+
+    let written = (|| {
+        let mut file = vfs.create(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()
+    })();
+    if let Err(e) = written.and_then(|()| vfs.rename(&temp, path)) {
+        let _ = vfs.remove_file(&temp);
+        return Err(Error::from(e).with_path(path));
+    }
+
+    vfs::sync_parent_dir(vfs, path).map_err(|e| {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Error::from(e).with_path(dir)
+    })
+}
+
+/// Makes the creation, removal and renaming of entries in `dir` durable
+///
+/// A no-op on platforms where directories can't be synced.
+///
+/// # Errors
+///
+/// Returns an I/O error, with `dir` attached, if the directory cannot be
+/// opened or synced.
+pub fn sync_dir(dir: impl AsRef<Path>) -> Result<()> {
+    let dir = dir.as_ref();
+    if cfg!(unix) {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| Error::from(e).with_path(dir))?;
+    }
+    Ok(())
+}
+
+/// Syncs the directory holding `path`, making its entry durable
+///
+/// # Errors
+///
+/// Returns an I/O error if the directory cannot be synced.
+pub fn sync_parent_dir(path: impl AsRef<Path>) -> Result<()> {
+    match path.as_ref().parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent),
+        _ => sync_dir("."),
+    }
+}
+
+/// Returns the temporary path a new version of `path` is written to
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(TEMP_SUFFIX);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_atomic_replaces_whole_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("DESCRIPTION");
+
+        write_atomic(&path, b"first version, rather long").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_write_atomic_overwrites_leftover_temp_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("DESCRIPTION");
+        std::fs::write(temp_path(&path), b"torn by a crash").unwrap();
+
+        write_atomic(&path, b"complete").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"complete");
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_failed_write_leaves_target_untouched() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("target");
+        // Renaming a file over a non-empty directory fails
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("inner"), b"kept").unwrap();
+
+        let error = write_atomic(&path, b"new").unwrap_err();
+        assert_eq!(
+            error.context().and_then(|c| c.path.as_deref()),
+            Some(path.as_path())
+        );
+        assert!(!temp_path(&path).exists());
+        assert_eq!(std::fs::read(path.join("inner")).unwrap(), b"kept");
+    }
+
+    #[test]
+    fn test_sync_dir_reports_missing_directory() {
+        let dir = TempDir::new().unwrap();
+        sync_dir(dir.path()).unwrap();
+        sync_parent_dir(dir.path().join("not-yet-created")).unwrap();
+
+        if cfg!(unix) {
+            assert!(sync_dir(dir.path().join("missing")).is_err());
+        }
+    }
+}
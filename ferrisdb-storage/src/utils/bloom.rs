@@ -0,0 +1,217 @@
+//! Bloom filters over byte strings
+//!
+//! A [`BloomFilter`] answers "might this key be in the set?" with no false
+//! negatives and a false positive rate set by the bits spent per key: 10
+//! bits give about 1%. SSTables store one over the prefixes of their keys,
+//! so prefix scans can skip tables that hold none of the prefix.
+//!
+//! Each key sets `k` bits chosen by double hashing, as in LevelDB: one
+//! 32-bit hash `h` and its rotation `delta` give the probes `h`,
+//! `h + delta`, `h + 2 * delta` and so on.
+//!
+//! # Format
+//!
+//! ```text
+//! ┌─────────────────┬─────────────────┬─────────────┐
+//! │   Bit Array     │   Hash Count    │  Checksum   │
+//! │   (variable)    │    (4 bytes)    │  (4 bytes)  │
+//! └─────────────────┴─────────────────┴─────────────┘
+//! ```
+//!
+//! The checksum is the CRC32C of the bit array and hash count. A hash
+//! count of 0 marks an empty filter that matches every key; tables
+//! written without a prefix extractor store one with a zero checksum.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::bloom::BloomFilter;
+//!
+//! let filter = BloomFilter::build([&b"user:1:"[..], b"user:2:"], 10);
+//! assert!(filter.may_contain(b"user:1:"));
+//!
+//! let decoded = BloomFilter::decode(&filter.encode())?;
+//! assert!(decoded.may_contain(b"user:2:"));
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use super::{coding, crc};
+use ferrisdb_core::{Error, Result};
+
+/// Fewest bits in a filter, so tiny sets don't see a high false positive
+/// rate
+const MIN_BITS: usize = 64;
+
+/// A set of byte strings that may report keys it doesn't hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    /// Probes per key; 0 for the empty filter matching everything
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    /// Returns a filter that matches every key
+    pub fn empty() -> Self {
+        Self {
+            bits: vec![0; 8],
+            hash_count: 0,
+        }
+    }
+
+    /// Builds a filter holding `keys`, spending `bits_per_key` bits on each
+    pub fn build<'a>(keys: impl IntoIterator<Item = &'a [u8]>, bits_per_key: usize) -> Self {
+        Self::from_hashes(keys.into_iter().map(hash), bits_per_key)
+    }
+
+    /// Builds a filter from the [`hash`]es of its keys
+    pub fn from_hashes(hashes: impl IntoIterator<Item = u32>, bits_per_key: usize) -> Self {
+        let hashes: Vec<u32> = hashes.into_iter().collect();
+        let bits_per_key = bits_per_key.max(1);
+        // ln(2) * bits per key minimizes the false positive rate
+        let hash_count = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        let bytes = (hashes.len() * bits_per_key).max(MIN_BITS).div_ceil(8);
+        let mut filter = Self {
+            bits: vec![0; bytes],
+            hash_count,
+        };
+        for hash in hashes {
+            let probes: Vec<usize> = filter.probes(hash).collect();
+            for bit in probes {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// Returns false only if `key` was not added to the filter
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.hash_count == 0
+            || self
+                .probes(hash(key))
+                .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Serializes the filter, including its checksum
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.bits.len() + 8);
+        bytes.extend_from_slice(&self.bits);
+        coding::put_fixed32(&mut bytes, self.hash_count);
+        let checksum = if self.hash_count == 0 {
+            0
+        } else {
+            crc::crc32c(&bytes)
+        };
+        coding::put_fixed32(&mut bytes, checksum);
+        bytes
+    }
+
+    /// Deserializes a filter written by [`encode`](Self::encode)
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the filter is truncated or its
+    /// checksum doesn't match.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let Some(body_len) = bytes.len().checked_sub(4) else {
+            return Err(Error::Corruption("Bloom filter is truncated".to_string()));
+        };
+        let (body, mut checksum) = bytes.split_at(body_len);
+        let checksum = coding::get_fixed32(&mut checksum)?;
+        let Some(bits_len) = body.len().checked_sub(4) else {
+            return Err(Error::Corruption("Bloom filter is truncated".to_string()));
+        };
+        let (bits, mut hash_count) = body.split_at(bits_len);
+        let hash_count = coding::get_fixed32(&mut hash_count)?;
+
+        if hash_count == 0 {
+            return Ok(Self::empty());
+        }
+        if bits.is_empty() || checksum != crc::crc32c(body) {
+            return Err(Error::Corruption(
+                "Bloom filter checksum mismatch".to_string(),
+            ));
+        }
+        Ok(Self {
+            bits: bits.to_vec(),
+            hash_count,
+        })
+    }
+
+    /// Returns the bit positions probed for a key with `hash`
+    fn probes(&self, mut hash: u32) -> impl Iterator<Item = usize> + '_ {
+        let bit_count = self.bits.len() * 8;
+        let delta = hash.rotate_right(17);
+        (0..self.hash_count).map(move |_| {
+            let bit = hash as usize % bit_count;
+            hash = hash.wrapping_add(delta);
+            bit
+        })
+    }
+}
+
+/// Hashes a key for a filter, with the Murmur-like hash of LevelDB
+pub fn hash(key: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f_1d34;
+    const M: u32 = 0xc6a4_a793;
+
+    let mut h = SEED ^ (key.len() as u32).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        let word = u32::from_le_bytes(chunk.try_into().expect("4-byte chunk"));
+        h = h.wrapping_add(word).wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, &byte) in rest.iter().enumerate() {
+            h = h.wrapping_add(u32::from(byte) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:06}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_filter_has_no_false_negatives_and_few_false_positives() {
+        let keys: Vec<_> = (0..10_000).map(key).collect();
+        let filter = BloomFilter::build(keys.iter().map(Vec::as_slice), 10);
+        assert!(keys.iter().all(|key| filter.may_contain(key)));
+
+        let false_positives = (10_000..20_000)
+            .filter(|&i| filter.may_contain(&key(i)))
+            .count();
+        // About 1% at 10 bits per key
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_encoding_round_trips_and_detects_corruption() {
+        let filter = BloomFilter::build([&b"a"[..], b"b", b"c"], 10);
+        let mut bytes = filter.encode();
+        assert_eq!(BloomFilter::decode(&bytes).unwrap(), filter);
+
+        bytes[3] ^= 1;
+        assert!(matches!(
+            BloomFilter::decode(&bytes),
+            Err(Error::Corruption(_))
+        ));
+        assert!(BloomFilter::decode(&bytes[..3]).is_err());
+
+        // The empty filter is the placeholder of tables without one
+        let empty = BloomFilter::empty().encode();
+        assert_eq!(empty, [0; 16]);
+        assert!(BloomFilter::decode(&empty)
+            .unwrap()
+            .may_contain(b"anything"));
+    }
+}
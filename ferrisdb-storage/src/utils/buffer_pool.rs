@@ -0,0 +1,318 @@
+//! Reusable read buffers
+//!
+//! Readers need a buffer to read records and blocks into before decoding
+//! them. Allocating one per reader, and growing it to the largest record
+//! it meets, is wasted work when readers are short-lived, as when many
+//! threads open the same files. A [`BufferPool`] keeps buffers that readers
+//! are done with and hands them to the next reader instead.
+//!
+//! Buffers are kept in size classes, powers of two from
+//! [`MIN_CLASS_SIZE`] to [`MAX_CLASS_SIZE`], each holding at most a fixed
+//! number of buffers, so an idle pool stays bounded. A checked-out buffer
+//! can grow like any `BytesMut`; when its [`PooledBuffer`] is dropped it
+//! is cleared and filed under the largest class its capacity covers, or
+//! freed if it outgrew the largest class or its class is full.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::BufferPool;
+//!
+//! let pool = BufferPool::new(4);
+//! {
+//!     let mut buffer = pool.checkout(1000);
+//!     assert!(buffer.capacity() >= 1000);
+//!     buffer.extend_from_slice(b"record");
+//! }
+//!
+//! // The buffer came back, empty, and is reused
+//! let buffer = pool.checkout(1000);
+//! assert!(buffer.is_empty());
+//! assert_eq!(pool.stats().hits, 1);
+//! ```
+
+use bytes::BytesMut;
+use parking_lot::Mutex;
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Capacity of the smallest size class
+pub const MIN_CLASS_SIZE: usize = 64;
+
+/// Capacity of the largest size class
+///
+/// Larger buffers are allocated for the request and freed afterwards.
+pub const MAX_CLASS_SIZE: usize = 1024 * 1024;
+
+/// Idle buffers kept per size class by the shared pool
+pub const DEFAULT_BUFFERS_PER_CLASS: usize = 16;
+
+const NUM_CLASSES: usize =
+    (MAX_CLASS_SIZE.trailing_zeros() - MIN_CLASS_SIZE.trailing_zeros()) as usize + 1;
+
+/// Counters describing how well a pool is being reused
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Checkouts served with an idle buffer
+    pub hits: u64,
+    /// Checkouts that had to allocate
+    pub misses: u64,
+    /// Returned buffers freed because they were too large or their class
+    /// was full
+    pub discarded: u64,
+    /// Buffers currently idle in the pool
+    pub idle_buffers: usize,
+}
+
+/// A thread-safe pool of reusable `BytesMut` buffers
+pub struct BufferPool {
+    /// Idle buffers by size class, smallest first
+    classes: [Mutex<Vec<BytesMut>>; NUM_CLASSES],
+    buffers_per_class: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl BufferPool {
+    /// Creates an empty pool keeping up to `buffers_per_class` idle buffers
+    /// in each size class
+    pub fn new(buffers_per_class: usize) -> Arc<Self> {
+        Arc::new(Self {
+            classes: std::array::from_fn(|_| Mutex::new(Vec::new())),
+            buffers_per_class,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the pool shared by all readers in the process
+    pub fn shared() -> Arc<Self> {
+        static SHARED: OnceLock<Arc<BufferPool>> = OnceLock::new();
+        Arc::clone(SHARED.get_or_init(|| Self::new(DEFAULT_BUFFERS_PER_CLASS)))
+    }
+
+    /// Checks out an empty buffer with room for at least `min_capacity`
+    /// bytes
+    ///
+    /// The buffer goes back to the pool when the returned guard is dropped.
+    pub fn checkout(self: &Arc<Self>, min_capacity: usize) -> PooledBuffer {
+        let buffer = match class_for_request(min_capacity) {
+            Some(class) => match self.classes[class].lock().pop() {
+                Some(buffer) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    buffer
+                }
+                None => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    BytesMut::with_capacity(class_size(class))
+                }
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                BytesMut::with_capacity(min_capacity)
+            }
+        };
+        PooledBuffer {
+            buffer: Some(buffer),
+            pool: Arc::clone(self),
+        }
+    }
+
+    /// Returns the pool's counters
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            discarded: self.discarded.load(Ordering::Relaxed),
+            idle_buffers: self.classes.iter().map(|class| class.lock().len()).sum(),
+        }
+    }
+
+    /// Takes back a buffer, or frees it if the pool has no room for it
+    fn release(&self, mut buffer: BytesMut) {
+        buffer.clear();
+        if let Some(class) = class_for_capacity(buffer.capacity()) {
+            let mut idle = self.classes[class].lock();
+            if idle.len() < self.buffers_per_class {
+                idle.push(buffer);
+                return;
+            }
+        }
+        self.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffers_per_class", &self.buffers_per_class)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// Capacity of the buffers in size class `class`
+fn class_size(class: usize) -> usize {
+    MIN_CLASS_SIZE << class
+}
+
+/// Returns the smallest class whose buffers hold `min_capacity` bytes, or
+/// `None` if no class is large enough
+fn class_for_request(min_capacity: usize) -> Option<usize> {
+    let size = min_capacity
+        .max(MIN_CLASS_SIZE)
+        .checked_next_power_of_two()?;
+    (size <= MAX_CLASS_SIZE).then(|| (size / MIN_CLASS_SIZE).trailing_zeros() as usize)
+}
+
+/// Returns the largest class a buffer of `capacity` bytes can serve, or
+/// `None` if it is smaller than the smallest class or larger than the
+/// largest
+fn class_for_capacity(capacity: usize) -> Option<usize> {
+    if !(MIN_CLASS_SIZE..=MAX_CLASS_SIZE).contains(&capacity) {
+        return None;
+    }
+    Some((capacity / MIN_CLASS_SIZE).ilog2() as usize)
+}
+
+/// A buffer checked out of a [`BufferPool`], returned to it when dropped
+pub struct PooledBuffer {
+    /// Always `Some` until dropped
+    buffer: Option<BytesMut>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        self.buffer.as_ref().expect("buffer present until dropped")
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        self.buffer.as_mut().expect("buffer present until dropped")
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.release(buffer);
+        }
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_requests_round_up_to_a_size_class() {
+        assert_eq!(class_for_request(0), Some(0));
+        assert_eq!(class_for_request(MIN_CLASS_SIZE), Some(0));
+        assert_eq!(class_for_request(MIN_CLASS_SIZE + 1), Some(1));
+        assert_eq!(class_for_request(MAX_CLASS_SIZE), Some(NUM_CLASSES - 1));
+        assert_eq!(class_for_request(MAX_CLASS_SIZE + 1), None);
+        assert_eq!(class_for_request(usize::MAX), None);
+
+        // Returned buffers serve the largest class they cover
+        assert_eq!(class_for_capacity(MIN_CLASS_SIZE - 1), None);
+        assert_eq!(class_for_capacity(3 * MIN_CLASS_SIZE), Some(1));
+        assert_eq!(class_for_capacity(MAX_CLASS_SIZE), Some(NUM_CLASSES - 1));
+        assert_eq!(class_for_capacity(MAX_CLASS_SIZE + 1), None);
+    }
+
+    #[test]
+    fn test_returned_buffers_are_reused_empty() {
+        let pool = BufferPool::new(2);
+        {
+            let mut buffer = pool.checkout(100);
+            assert!(buffer.capacity() >= 100);
+            buffer.extend_from_slice(&[1; 100]);
+        }
+        assert_eq!(pool.stats().idle_buffers, 1);
+
+        let buffer = pool.checkout(128);
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 128);
+        assert_eq!(
+            pool.stats(),
+            BufferPoolStats {
+                hits: 1,
+                misses: 1,
+                discarded: 0,
+                idle_buffers: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_grown_buffers_move_to_a_larger_class() {
+        let pool = BufferPool::new(2);
+        {
+            let mut buffer = pool.checkout(MIN_CLASS_SIZE);
+            buffer.reserve(10_000);
+        }
+
+        // A small request doesn't find it, a large one does
+        drop(pool.checkout(MIN_CLASS_SIZE));
+        assert_eq!(pool.stats().hits, 0);
+        assert!(pool.checkout(8 * 1024).capacity() >= 10_000);
+        assert_eq!(pool.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_pool_stays_bounded() {
+        let pool = BufferPool::new(2);
+        let buffers: Vec<_> = (0..5).map(|_| pool.checkout(4096)).collect();
+        drop(buffers);
+        drop(pool.checkout(2 * MAX_CLASS_SIZE));
+
+        let stats = pool.stats();
+        assert_eq!(stats.idle_buffers, 2);
+        assert_eq!(stats.discarded, 4);
+    }
+
+    #[test]
+    fn test_concurrent_checkouts_share_buffers() {
+        let pool = BufferPool::new(8);
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let mut buffer = pool.checkout(512);
+                        assert!(buffer.is_empty());
+                        buffer.extend_from_slice(&[i; 512]);
+                        assert!(buffer.iter().all(|&b| b == i));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.hits + stats.misses, 800);
+        assert!(stats.misses <= 8);
+        assert_eq!(stats.discarded, 0);
+    }
+}
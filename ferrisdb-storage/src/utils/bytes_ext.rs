@@ -20,7 +20,7 @@
 //! uninitialized buffers (e.g., BorrowedBuf/BorrowedCursor or similar abstractions).
 
 use bytes::BytesMut;
-use std::io::{self, Read};
+use std::io::{self, IoSliceMut, Read};
 
 /// Extension trait for BytesMut providing efficient read operations
 pub trait BytesMutExt {
@@ -58,6 +58,57 @@ pub trait BytesMutExt {
     /// - `Ok(())` if exactly `count` bytes were read
     /// - `Err(e)` if the read failed or EOF was encountered
     fn read_exact_from<R: Read>(&mut self, reader: &mut R, count: usize) -> io::Result<()>;
+
+    /// Reads exactly `head.len()` bytes into `head`, then `count` bytes
+    /// appended to the buffer, with vectored reads.
+    ///
+    /// This lets a caller read a fixed-size prefix, such as a length or
+    /// header, into its own array and the payload behind it into the buffer
+    /// in one `readv` call, where the reader supports it. Readers that
+    /// return less than requested are read from again until both parts are
+    /// filled.
+    ///
+    /// # Error Handling
+    ///
+    /// As with `read_exact_from`, the buffer length is unchanged if the read
+    /// fails. `head` may have been partially overwritten.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(())` if both `head` and `count` bytes were filled
+    /// - `Err(e)` if the read failed or EOF was encountered
+    fn read_vectored_from<R: Read>(
+        &mut self,
+        reader: &mut R,
+        head: &mut [u8],
+        count: usize,
+    ) -> io::Result<()>;
+
+    /// Reads at least `min` and at most `max` bytes from the reader,
+    /// appending them to the buffer without zero-initialization overhead.
+    ///
+    /// Useful when the size of what follows isn't known yet, such as a
+    /// record whose length prefix has to be read first: reading up to
+    /// `max` bytes picks up as much of the payload as is available in the
+    /// same call, instead of one read for the prefix and another for the
+    /// rest.
+    ///
+    /// # Error Handling
+    ///
+    /// If EOF is reached before `min` bytes were read, or the read fails,
+    /// the buffer length is unchanged and no partially read data is
+    /// retained.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(n)` with the number of bytes appended, `min <= n <= max`
+    /// - `Err(e)` with `InvalidInput` if `min > max`, or if the read failed
+    fn read_at_least<R: Read>(
+        &mut self,
+        reader: &mut R,
+        min: usize,
+        max: usize,
+    ) -> io::Result<usize>;
 }
 
 impl BytesMutExt for BytesMut {
@@ -99,6 +150,102 @@ impl BytesMutExt for BytesMut {
             }
         }
     }
+
+    fn read_vectored_from<R: Read>(
+        &mut self,
+        reader: &mut R,
+        head: &mut [u8],
+        count: usize,
+    ) -> io::Result<()> {
+        let start_len = self.len();
+        self.reserve(count);
+
+        let mut head_filled = 0;
+        let mut body_filled = 0;
+        while head_filled < head.len() || body_filled < count {
+            // SAFETY: Same invariants as read_exact_from. The slice covers
+            // reserved capacity past the current length, which is only
+            // updated once every byte of it has been read.
+            let body = unsafe {
+                let dst = self.as_mut_ptr().add(start_len + body_filled);
+                std::slice::from_raw_parts_mut(dst, count - body_filled)
+            };
+            let mut slices = [
+                IoSliceMut::new(&mut head[head_filled..]),
+                IoSliceMut::new(body),
+            ];
+
+            match reader.read_vectored(&mut slices) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    // Bytes land in the head first, then the body
+                    let into_head = n.min(head.len() - head_filled);
+                    head_filled += into_head;
+                    body_filled += n - into_head;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // SAFETY: All `count` bytes past start_len have been read
+        unsafe { self.set_len(start_len + count) };
+        Ok(())
+    }
+
+    fn read_at_least<R: Read>(
+        &mut self,
+        reader: &mut R,
+        min: usize,
+        max: usize,
+    ) -> io::Result<usize> {
+        if min > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("minimum read of {} bytes exceeds maximum {}", min, max),
+            ));
+        }
+
+        let start_len = self.len();
+        self.reserve(max);
+
+        let mut filled = 0;
+        while filled < max {
+            // SAFETY: Same invariants as read_exact_from
+            let dst = unsafe {
+                let dst = self.as_mut_ptr().add(start_len + filled);
+                std::slice::from_raw_parts_mut(dst, max - filled)
+            };
+
+            match reader.read(dst) {
+                Ok(0) => break,
+                Ok(n) => {
+                    filled += n;
+                    if filled >= min {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if filled < min {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("expected at least {} bytes but read {}", min, filled),
+            ));
+        }
+
+        // SAFETY: The first `filled` bytes past start_len have been read
+        unsafe { self.set_len(start_len + filled) };
+        Ok(filled)
+    }
 }
 
 #[cfg(test)]
@@ -267,9 +414,7 @@ mod tests {
                     ));
                 }
                 let to_read = self.fail_after.min(buf.len());
-                for i in 0..to_read {
-                    buf[i] = 42;
-                }
+                buf[..to_read].fill(42);
                 self.fail_after -= to_read;
                 Ok(to_read)
             }
@@ -343,7 +488,7 @@ mod tests {
                     && self.position >= self.fail_at_position
                     && self.fail_at_position != usize::MAX
                 {
-                    return Err(io::Error::new(io::ErrorKind::Other, "read failed"));
+                    return Err(io::Error::other("read failed"));
                 }
 
                 let available = self.data.len() - self.position;
@@ -401,7 +546,7 @@ mod tests {
         impl Read for PartialReader {
             fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
                 if self.bytes_read >= self.fail_after {
-                    return Err(io::Error::new(io::ErrorKind::Other, "forced error"));
+                    return Err(io::Error::other("forced error"));
                 }
 
                 let remaining = self.fail_after - self.bytes_read;
@@ -464,6 +609,139 @@ mod tests {
         assert!(buf[..512].iter().all(|&b| b == 1));
         assert!(buf[512..].iter().all(|&b| b == 42));
     }
+
+    /// Reader returning one byte per call, using the default
+    /// `read_vectored` that only fills the first non-empty slice
+    struct ByteAtATimeReader<'a>(&'a [u8]);
+
+    impl Read for ByteAtATimeReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(dst)) => {
+                    *dst = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    /// Tests that read_vectored_from splits the data between head and buffer.
+    ///
+    /// This test verifies that:
+    /// - The head is filled first, then the payload is appended
+    /// - Existing buffer contents are preserved
+    /// - The reader is left positioned after both parts
+    #[test]
+    fn read_vectored_from_fills_head_then_buffer() {
+        let data: Vec<u8> = (0..12).collect();
+        let mut reader = Cursor::new(&data);
+        let mut buf = BytesMut::from(&b"ab"[..]);
+        let mut head = [0u8; 4];
+
+        buf.read_vectored_from(&mut reader, &mut head, 6).unwrap();
+
+        assert_eq!(head, [0, 1, 2, 3]);
+        assert_eq!(&buf[..], &[b'a', b'b', 4, 5, 6, 7, 8, 9]);
+        assert_eq!(reader.position(), 10);
+    }
+
+    /// Tests that read_vectored_from keeps reading after short reads.
+    ///
+    /// This test verifies that:
+    /// - Readers filling one byte at a time still fill both parts
+    /// - Progress carries over the boundary between head and buffer
+    /// - An empty head or empty payload is handled
+    #[test]
+    fn read_vectored_from_handles_short_reads() {
+        let data: Vec<u8> = (0..8).collect();
+        let mut buf = BytesMut::new();
+        let mut head = [0u8; 3];
+
+        buf.read_vectored_from(&mut ByteAtATimeReader(&data), &mut head, 5)
+            .unwrap();
+        assert_eq!(head, [0, 1, 2]);
+        assert_eq!(&buf[..], &[3, 4, 5, 6, 7]);
+
+        let mut reader = ByteAtATimeReader(&data);
+        buf.clear();
+        buf.read_vectored_from(&mut reader, &mut [], 2).unwrap();
+        buf.read_vectored_from(&mut reader, &mut head, 0).unwrap();
+        assert_eq!(&buf[..], &[0, 1]);
+        assert_eq!(head, [2, 3, 4]);
+    }
+
+    /// Tests that read_vectored_from leaves the buffer unchanged on EOF.
+    ///
+    /// This test verifies that:
+    /// - Running out of data in the payload returns UnexpectedEof
+    /// - The buffer length is unchanged
+    #[test]
+    fn read_vectored_from_leaves_buffer_unchanged_on_eof() {
+        let data = [7u8; 6];
+        let mut buf = BytesMut::from(&b"kept"[..]);
+        let mut head = [0u8; 4];
+
+        let error = buf
+            .read_vectored_from(&mut Cursor::new(&data), &mut head, 4)
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(&buf[..], b"kept");
+    }
+
+    /// Tests that read_at_least returns between `min` and `max` bytes.
+    ///
+    /// This test verifies that:
+    /// - A reader with enough data available fills up to `max` in one call
+    /// - Reading stops as soon as `min` is reached on short reads
+    /// - EOF after `min` bytes returns what was read
+    #[test]
+    fn read_at_least_reads_between_min_and_max() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut buf = BytesMut::new();
+
+        let read = buf.read_at_least(&mut Cursor::new(&data), 10, 50).unwrap();
+        assert_eq!(read, 50);
+        assert_eq!(&buf[..], &data[..50]);
+
+        buf.clear();
+        let read = buf
+            .read_at_least(&mut ByteAtATimeReader(&data), 3, 10)
+            .unwrap();
+        assert_eq!(read, 3);
+        assert_eq!(&buf[..], &[0, 1, 2]);
+
+        buf.clear();
+        let read = buf
+            .read_at_least(&mut Cursor::new(&data[..5]), 2, 10)
+            .unwrap();
+        assert_eq!(read, 5);
+    }
+
+    /// Tests that read_at_least fails cleanly without `min` bytes.
+    ///
+    /// This test verifies that:
+    /// - EOF before `min` bytes returns UnexpectedEof
+    /// - No partially read data is retained
+    /// - `min` larger than `max` is rejected as invalid input
+    #[test]
+    fn read_at_least_fails_on_eof_before_min() {
+        let mut buf = BytesMut::from(&b"kept"[..]);
+
+        let error = buf
+            .read_at_least(&mut ByteAtATimeReader(&[1; 5]), 10, 20)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(&buf[..], b"kept");
+
+        let error = buf
+            .read_at_least(&mut Cursor::new([0u8; 8]), 5, 4)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(&buf[..], b"kept");
+    }
 }
 
 #[cfg(all(test, not(miri)))] // Disable proptest under miri
@@ -0,0 +1,621 @@
+//! Sharded caches with LRU or Clock eviction
+//!
+//! A [`Cache`] maps keys to values, each inserted with a *charge*, its
+//! cost in whatever unit the capacity is measured in, typically bytes.
+//! When the total charge exceeds the capacity, entries are evicted
+//! according to the [`EvictionPolicy`]:
+//!
+//! - **LRU** evicts the least recently used entry. Every hit moves its
+//!   entry to the back of the queue.
+//! - **Clock** keeps entries in insertion order and marks them as
+//!   referenced on a hit. Eviction sweeps a hand over them, giving each
+//!   referenced entry a second chance by clearing its mark. Hits are
+//!   cheaper than with LRU, at the cost of approximating recency.
+//!
+//! Lookups and inserts return a [`CacheHandle`] that *pins* its entry:
+//! pinned entries are never evicted, so a caller can use a value without
+//! copying it out. The entry is unpinned when the last handle is dropped,
+//! and evicted then if the cache is over capacity. Pinned entries still
+//! count towards the usage, which can therefore exceed the capacity while
+//! they are held.
+//!
+//! Keys are spread over shards by hash, each with its own lock and an
+//! equal part of the capacity, so concurrent lookups rarely contend.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::cache::{Cache, EvictionPolicy};
+//!
+//! let cache = Cache::new(100).with_policy(EvictionPolicy::Lru);
+//! drop(cache.insert("a", vec![1u8; 60], 60));
+//! drop(cache.insert("b", vec![2u8; 30], 30));
+//! assert_eq!(cache.usage(), 90);
+//!
+//! // Holding a handle keeps "a" from being evicted
+//! let a = cache.get("a").unwrap();
+//! drop(cache.insert("c", vec![3u8; 30], 30));
+//! assert_eq!(a[0], 1);
+//! assert!(cache.get("b").is_none());
+//! ```
+
+use parking_lot::Mutex;
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Most shards a cache is split into unless set with
+/// [`with_shards`](Cache::with_shards)
+pub const DEFAULT_MAX_SHARDS: usize = 16;
+
+/// Capacity below which a cache isn't split further by default, so small
+/// caches aren't divided into shards too small to hold anything
+const MIN_SHARD_CAPACITY: usize = 512 * 1024;
+
+/// Which entry a full cache evicts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// The least recently used entry
+    #[default]
+    Lru,
+    /// The next entry under the clock hand not referenced since the hand
+    /// last passed it
+    Clock,
+}
+
+/// Counters describing a cache's effectiveness
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that found their key
+    pub hits: u64,
+    /// Lookups that didn't
+    pub misses: u64,
+    /// Entries evicted to stay within capacity
+    pub evictions: u64,
+}
+
+/// A shard, shared between its cache and the handles into it
+type SharedShard<K, V> = Arc<Mutex<Shard<K, V>>>;
+
+/// A sharded, thread-safe cache bounded by total charge
+pub struct Cache<K, V> {
+    shards: Box<[SharedShard<K, V>]>,
+    hasher: RandomState,
+    capacity: usize,
+    policy: EvictionPolicy,
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> {
+    /// Creates an LRU cache holding entries with a total charge of up to
+    /// `capacity`
+    ///
+    /// Large caches are split into up to [`DEFAULT_MAX_SHARDS`] shards.
+    pub fn new(capacity: usize) -> Self {
+        let shards = (capacity / MIN_SHARD_CAPACITY).clamp(1, DEFAULT_MAX_SHARDS);
+        Self::build(capacity, shards, EvictionPolicy::default())
+    }
+
+    /// Splits the cache into `shards` shards (at least one)
+    ///
+    /// Entries already inserted are dropped.
+    pub fn with_shards(self, shards: usize) -> Self {
+        Self::build(self.capacity, shards.max(1), self.policy)
+    }
+
+    /// Sets the eviction policy
+    ///
+    /// Entries already inserted are dropped.
+    pub fn with_policy(self, policy: EvictionPolicy) -> Self {
+        Self::build(self.capacity, self.shards.len(), policy)
+    }
+
+    fn build(capacity: usize, shards: usize, policy: EvictionPolicy) -> Self {
+        let shard_capacity = capacity.div_ceil(shards);
+        Self {
+            shards: (0..shards)
+                .map(|_| Arc::new(Mutex::new(Shard::new(shard_capacity, policy))))
+                .collect(),
+            hasher: RandomState::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    /// Inserts `value` under `key` with the given charge, replacing any
+    /// existing entry
+    ///
+    /// Returns a handle pinning the new entry. Entries are evicted to make
+    /// room; if none can be, because they are all pinned, the cache goes
+    /// over capacity until handles are dropped.
+    pub fn insert(&self, key: K, value: V, charge: usize) -> CacheHandle<K, V> {
+        let shard = self.shard(&key);
+        let (value, id) = shard.lock().insert(key.clone(), Arc::new(value), charge);
+        CacheHandle {
+            shard: Arc::clone(shard),
+            key,
+            id,
+            value,
+        }
+    }
+
+    /// Looks up `key`, returning a handle pinning its entry
+    pub fn get<Q>(&self, key: &Q) -> Option<CacheHandle<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let shard = self.shard(key);
+        let (key, value, id) = shard.lock().lookup(key)?;
+        Some(CacheHandle {
+            shard: Arc::clone(shard),
+            key,
+            id,
+            value,
+        })
+    }
+
+    /// Removes the entry for `key`
+    ///
+    /// Handles to it remain valid, but its charge is released at once.
+    pub fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).lock().erase(key);
+    }
+
+    /// Removes every entry
+    ///
+    /// Handles remain valid, but their charges are released at once.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock();
+            shard.entries.clear();
+            shard.order.clear();
+            shard.usage = 0;
+        }
+    }
+
+    /// Returns the capacity the cache was created with
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the eviction policy
+    pub fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    /// Returns the total charge of the cached entries
+    pub fn usage(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().usage).sum()
+    }
+
+    /// Returns the total charge of entries pinned by handles
+    pub fn pinned_usage(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().pinned_usage())
+            .sum()
+    }
+
+    /// Returns the number of cached entries
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().entries.len())
+            .sum()
+    }
+
+    /// Returns true if nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the cache's counters
+    pub fn stats(&self) -> CacheStats {
+        self.shards
+            .iter()
+            .fold(CacheStats::default(), |total, shard| {
+                let stats = shard.lock().stats;
+                CacheStats {
+                    hits: total.hits + stats.hits,
+                    misses: total.misses + stats.misses,
+                    evictions: total.evictions + stats.evictions,
+                }
+            })
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &SharedShard<K, V> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+}
+
+impl<K, V> fmt::Debug for Cache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("capacity", &self.capacity)
+            .field("shards", &self.shards.len())
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A pinned cache entry, unpinned when dropped
+///
+/// Dereferences to the cached value.
+pub struct CacheHandle<K: Hash + Eq + Clone, V> {
+    shard: SharedShard<K, V>,
+    key: K,
+    /// Identifies the entry the handle pins, in case the key is replaced
+    id: u64,
+    value: Arc<V>,
+}
+
+impl<K: Hash + Eq + Clone, V> CacheHandle<K, V> {
+    /// Returns the key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Deref for CacheHandle<K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.value
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Drop for CacheHandle<K, V> {
+    fn drop(&mut self) {
+        self.shard.lock().release(&self.key, self.id);
+    }
+}
+
+impl<K: Hash + Eq + Clone + fmt::Debug, V: fmt::Debug> fmt::Debug for CacheHandle<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheHandle")
+            .field("key", &self.key)
+            .field("value", &*self.value)
+            .finish()
+    }
+}
+
+struct Entry<V> {
+    value: Arc<V>,
+    charge: usize,
+    /// Live handles pinning the entry
+    pins: usize,
+    /// Position in the shard's order: last use for LRU, insertion for Clock
+    position: u64,
+    /// Position at insertion, identifying the entry to its handles
+    id: u64,
+    /// Clock: hit since the hand last passed
+    referenced: bool,
+}
+
+/// One independently locked part of a cache
+struct Shard<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Keys by position, oldest first
+    order: BTreeMap<u64, K>,
+    next_position: u64,
+    /// Clock: the position the next sweep starts from
+    hand: u64,
+    capacity: usize,
+    usage: usize,
+    policy: EvictionPolicy,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V> Shard<K, V> {
+    fn new(capacity: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_position: 0,
+            hand: 0,
+            capacity,
+            usage: 0,
+            policy,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn lookup<Q>(&mut self, key: &Q) -> Option<(K, Arc<V>, u64)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some((key, _)) = self.entries.get_key_value(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        let key = key.clone();
+        self.stats.hits += 1;
+
+        let position = self.next_position;
+        let entry = self.entries.get_mut::<K>(&key).expect("entry just found");
+        entry.pins += 1;
+        match self.policy {
+            EvictionPolicy::Lru => {
+                self.order.remove(&entry.position);
+                entry.position = position;
+                self.order.insert(position, key.clone());
+                self.next_position += 1;
+            }
+            EvictionPolicy::Clock => entry.referenced = true,
+        }
+        Some((key, Arc::clone(&entry.value), entry.id))
+    }
+
+    fn insert(&mut self, key: K, value: Arc<V>, charge: usize) -> (Arc<V>, u64) {
+        self.erase(&key);
+
+        let position = self.next_position;
+        self.next_position += 1;
+        self.order.insert(position, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value: Arc::clone(&value),
+                charge,
+                pins: 1,
+                position,
+                id: position,
+                referenced: false,
+            },
+        );
+        self.usage += charge;
+        self.evict();
+        (value, position)
+    }
+
+    fn release(&mut self, key: &K, id: u64) {
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.id == id => entry.pins -= 1,
+            // The entry was replaced or removed since the handle was taken
+            _ => return,
+        }
+        self.evict();
+    }
+
+    fn erase<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.position);
+            self.usage -= entry.charge;
+        }
+    }
+
+    fn pinned_usage(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|entry| entry.pins > 0)
+            .map(|entry| entry.charge)
+            .sum()
+    }
+
+    /// Evicts unpinned entries until usage is within capacity
+    fn evict(&mut self) {
+        while self.usage > self.capacity {
+            let victim = match self.policy {
+                EvictionPolicy::Lru => self.lru_victim(),
+                EvictionPolicy::Clock => self.clock_victim(),
+            };
+            let Some(key) = victim else {
+                break;
+            };
+            self.erase(&key);
+            self.stats.evictions += 1;
+        }
+    }
+
+    fn lru_victim(&self) -> Option<K> {
+        self.order
+            .values()
+            .find(|key| self.entries[*key].pins == 0)
+            .cloned()
+    }
+
+    fn clock_victim(&mut self) -> Option<K> {
+        // Two full turns: the first may only clear referenced marks
+        for _ in 0..2 * self.order.len() {
+            let (&position, key) = self
+                .order
+                .range(self.hand..)
+                .next()
+                .or_else(|| self.order.iter().next())?;
+            self.hand = position + 1;
+
+            let entry = self.entries.get_mut(key).expect("ordered key is cached");
+            if entry.pins > 0 {
+                continue;
+            }
+            if entry.referenced {
+                entry.referenced = false;
+                continue;
+            }
+            return Some(key.clone());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn single_shard(capacity: usize, policy: EvictionPolicy) -> Cache<&'static str, u32> {
+        Cache::new(capacity).with_shards(1).with_policy(policy)
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let cache = single_shard(3, EvictionPolicy::Lru);
+        for (i, key) in ["a", "b", "c"].into_iter().enumerate() {
+            drop(cache.insert(key, i as u32, 1));
+        }
+
+        drop(cache.get("a"));
+        drop(cache.insert("d", 3, 1));
+
+        assert!(cache.get("b").is_none());
+        assert_eq!(*cache.get("a").unwrap(), 0);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_clock_gives_referenced_entries_a_second_chance() {
+        let cache = single_shard(3, EvictionPolicy::Clock);
+        for (i, key) in ["a", "b", "c"].into_iter().enumerate() {
+            drop(cache.insert(key, i as u32, 1));
+        }
+
+        drop(cache.get("a"));
+        drop(cache.insert("d", 3, 1));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+
+        // Every entry referenced: a full turn clears them, then evicts
+        drop(cache.get("c"));
+        drop(cache.get("d"));
+        drop(cache.insert("e", 4, 1));
+        assert_eq!(cache.len(), 3);
+        assert!(cache.get("e").is_some());
+    }
+
+    #[test]
+    fn test_usage_is_accounted_by_charge() {
+        let cache = single_shard(100, EvictionPolicy::Lru);
+        drop(cache.insert("a", 1, 40));
+        drop(cache.insert("b", 2, 50));
+        assert_eq!(cache.usage(), 90);
+
+        // Replacing an entry swaps its charge
+        drop(cache.insert("a", 3, 10));
+        assert_eq!(cache.usage(), 60);
+
+        // A large entry evicts as many as needed
+        drop(cache.insert("c", 4, 95));
+        assert_eq!(cache.usage(), 95);
+        assert_eq!(cache.len(), 1);
+
+        cache.remove("c");
+        assert!(cache.is_empty());
+        assert_eq!(cache.usage(), 0);
+    }
+
+    #[test]
+    fn test_clear_keeps_handles_valid() {
+        let cache = Cache::new(100).with_shards(4);
+        let a = cache.insert("a", 1, 10);
+        drop(cache.insert("b", 2, 20));
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.usage(), 0);
+        assert_eq!(*a, 1);
+
+        // Releasing a cleared entry's handle leaves the cache alone
+        drop(a);
+        assert_eq!(cache.pinned_usage(), 0);
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn test_pinned_entries_are_not_evicted() {
+        let cache = single_shard(2, EvictionPolicy::Lru);
+        let a = cache.insert("a", 1, 1);
+        let b = cache.insert("b", 2, 1);
+        let c = cache.insert("c", 3, 1);
+
+        // Nothing could be evicted, so the cache is over capacity
+        assert_eq!(cache.usage(), 3);
+        assert_eq!(cache.pinned_usage(), 3);
+
+        drop(b);
+        assert_eq!(cache.usage(), 2);
+        assert!(cache.get("b").is_none());
+        assert_eq!((*a, *c), (1, 3));
+
+        drop((a, c));
+        assert_eq!(cache.pinned_usage(), 0);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_handles_outlive_replaced_entries() {
+        let cache = single_shard(10, EvictionPolicy::Lru);
+        let old = cache.insert("a", 1, 1);
+        let new = cache.insert("a", 2, 1);
+        assert_eq!((*old, *new), (1, 2));
+
+        // Releasing the old handle doesn't unpin the new entry
+        drop(old);
+        assert_eq!(cache.pinned_usage(), 1);
+        drop(new);
+        assert_eq!(cache.pinned_usage(), 0);
+        assert_eq!(*cache.get("a").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_stats_count_hits_and_misses() {
+        let cache = single_shard(10, EvictionPolicy::Lru);
+        drop(cache.insert("a", 1, 1));
+        drop(cache.get("a"));
+        drop(cache.get("a"));
+        drop(cache.get("missing"));
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                evictions: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_concurrent_use_stays_within_capacity() {
+        for policy in [EvictionPolicy::Lru, EvictionPolicy::Clock] {
+            let cache = Arc::new(Cache::new(64).with_shards(4).with_policy(policy));
+            let handles: Vec<_> = (0..8u64)
+                .map(|t| {
+                    let cache = Arc::clone(&cache);
+                    thread::spawn(move || {
+                        for i in 0..1000 {
+                            let key = (t * 7 + i) % 200;
+                            match cache.get(&key) {
+                                Some(value) => assert_eq!(*value, key * 2),
+                                None => drop(cache.insert(key, key * 2, 1)),
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            assert!(cache.usage() <= 64, "{:?}", policy);
+            assert_eq!(cache.pinned_usage(), 0);
+            let stats = cache.stats();
+            assert_eq!(stats.hits + stats.misses, 8000);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Integer and slice encodings shared by the on-disk formats
+//!
+//! Fixed-width integers are little-endian. Varints store an unsigned
+//! integer seven bits per byte, least significant group first, with the
+//! high bit of every byte but the last set:
+//!
+//! ```text
+//!   1     → 01
+//!   300   → AC 02
+//!   2^32  → 80 80 80 80 10
+//! ```
+//!
+//! Lengths of keys and values are nearly always small, so a varint takes
+//! one or two bytes where a fixed `u32` takes four. A length-prefixed slice
+//! is its length as a varint32 followed by the bytes.
+//!
+//! Encoders append to any [`BufMut`], such as a `Vec<u8>` or `BytesMut`.
+//! Decoders read from the front of a `&mut &[u8]` cursor and advance it,
+//! returning `Error::Corruption` if the input ends early or a varint is too
+//! long for its type.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::coding;
+//!
+//! let mut buf = Vec::new();
+//! coding::put_varint32(&mut buf, 300);
+//! coding::put_length_prefixed_slice(&mut buf, b"key");
+//! coding::put_fixed64(&mut buf, 7);
+//! assert_eq!(buf.len(), 2 + 4 + 8);
+//!
+//! let mut input = buf.as_slice();
+//! assert_eq!(coding::get_varint32(&mut input)?, 300);
+//! assert_eq!(coding::get_length_prefixed_slice(&mut input)?, b"key");
+//! assert_eq!(coding::get_fixed64(&mut input)?, 7);
+//! assert!(input.is_empty());
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use ferrisdb_core::{Error, Result};
+
+use bytes::BufMut;
+
+/// Longest encoding of a varint32
+pub const MAX_VARINT32_LEN: usize = 5;
+
+/// Longest encoding of a varint64
+pub const MAX_VARINT64_LEN: usize = 10;
+
+/// Appends `value` as a little-endian `u32`
+pub fn put_fixed32(buf: &mut impl BufMut, value: u32) {
+    buf.put_u32_le(value);
+}
+
+/// Appends `value` as a little-endian `u64`
+pub fn put_fixed64(buf: &mut impl BufMut, value: u64) {
+    buf.put_u64_le(value);
+}
+
+/// Appends `value` as a varint
+pub fn put_varint32(buf: &mut impl BufMut, value: u32) {
+    put_varint64(buf, value.into());
+}
+
+/// Appends `value` as a varint
+pub fn put_varint64(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Appends `bytes` prefixed with their length as a varint32
+///
+/// # Panics
+///
+/// Panics if `bytes` is 4 GiB or longer.
+pub fn put_length_prefixed_slice(buf: &mut impl BufMut, bytes: &[u8]) {
+    let len = u32::try_from(bytes.len()).expect("slice too long for a varint32 length");
+    put_varint32(buf, len);
+    buf.put_slice(bytes);
+}
+
+/// Returns the number of bytes `value` takes as a varint
+pub fn varint_length(value: u64) -> usize {
+    // Every started group of seven significant bits takes a byte
+    let bits = 64 - (value | 1).leading_zeros() as usize;
+    bits.div_ceil(7)
+}
+
+/// Splits `len` bytes off the front of `input`
+///
+/// # Errors
+///
+/// Returns `Error::Corruption` if `input` is shorter than `len`.
+pub fn get_bytes<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    let (head, rest) = input.split_at_checked(len).ok_or_else(|| {
+        Error::Corruption(format!(
+            "Expected {} more bytes but only {} remain",
+            len,
+            input.len()
+        ))
+    })?;
+    *input = rest;
+    Ok(head)
+}
+
+/// Decodes a little-endian `u32` from the front of `input`
+///
+/// # Errors
+///
+/// Returns `Error::Corruption` if fewer than 4 bytes remain.
+pub fn get_fixed32(input: &mut &[u8]) -> Result<u32> {
+    let bytes = get_bytes(input, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+}
+
+/// Decodes a little-endian `u64` from the front of `input`
+///
+/// # Errors
+///
+/// Returns `Error::Corruption` if fewer than 8 bytes remain.
+pub fn get_fixed64(input: &mut &[u8]) -> Result<u64> {
+    let bytes = get_bytes(input, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+}
+
+/// Decodes a varint32 from the front of `input`
+///
+/// # Errors
+///
+/// Returns `Error::Corruption` if the varint is truncated or its value
+/// doesn't fit in a `u32`.
+pub fn get_varint32(input: &mut &[u8]) -> Result<u32> {
+    let value = get_varint(input, MAX_VARINT32_LEN)?;
+    u32::try_from(value).map_err(|_| Error::Corruption(format!("Varint {} exceeds 32 bits", value)))
+}
+
+/// Decodes a varint64 from the front of `input`
+///
+/// # Errors
+///
+/// Returns `Error::Corruption` if the varint is truncated or longer than
+/// 10 bytes.
+pub fn get_varint64(input: &mut &[u8]) -> Result<u64> {
+    get_varint(input, MAX_VARINT64_LEN)
+}
+
+/// Decodes a slice prefixed with its length as a varint32
+///
+/// # Errors
+///
+/// Returns `Error::Corruption` if the length is invalid or the slice is
+/// truncated.
+pub fn get_length_prefixed_slice<'a>(input: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = get_varint32(input)?;
+    get_bytes(input, len as usize)
+}
+
+/// Decodes a varint of at most `max_len` bytes
+fn get_varint(input: &mut &[u8], max_len: usize) -> Result<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in input.iter().take(max_len).enumerate() {
+        let group = u64::from(byte & 0x7F);
+        let shift = 7 * i as u32;
+        if shift == 63 && group > 1 {
+            return Err(Error::Corruption("Varint exceeds 64 bits".to_string()));
+        }
+        value |= group << shift;
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(Error::Corruption(if input.len() < max_len {
+        "Truncated varint".to_string()
+    } else {
+        format!("Varint longer than {} bytes", max_len)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varints_round_trip_at_group_boundaries() {
+        let mut values = vec![0u64, 1, u64::MAX];
+        for bits in [7, 14, 21, 28, 32, 35, 42, 49, 56, 63] {
+            values.extend([(1 << bits) - 1, 1 << bits]);
+        }
+
+        let mut buf = Vec::new();
+        for &value in &values {
+            let before = buf.len();
+            put_varint64(&mut buf, value);
+            assert_eq!(buf.len() - before, varint_length(value), "{}", value);
+        }
+
+        let mut input = buf.as_slice();
+        for &value in &values {
+            assert_eq!(get_varint64(&mut input).unwrap(), value);
+        }
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_varint_encoding_is_little_endian_groups() {
+        let mut buf = Vec::new();
+        put_varint32(&mut buf, 300);
+        put_varint32(&mut buf, u32::MAX);
+        assert_eq!(buf, [0xAC, 0x02, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        assert_eq!(varint_length(0), 1);
+        assert_eq!(varint_length(u64::MAX), MAX_VARINT64_LEN);
+    }
+
+    #[test]
+    fn test_malformed_varints_are_rejected() {
+        // Truncated: the continuation bit promises another byte
+        assert!(get_varint32(&mut [0x80, 0x80].as_slice()).is_err());
+        assert!(get_varint64(&mut [].as_slice()).is_err());
+
+        // Too large for 32 bits, but a valid varint64
+        let mut buf = Vec::new();
+        put_varint64(&mut buf, 1 << 32);
+        assert!(get_varint32(&mut buf.as_slice()).is_err());
+        assert_eq!(get_varint64(&mut buf.as_slice()).unwrap(), 1 << 32);
+
+        // Longer than any varint64, or overflowing its last group
+        assert!(get_varint64(&mut [0xFF; 11].as_slice()).is_err());
+        let mut overflow = vec![0xFF; 9];
+        overflow.push(0x02);
+        assert!(get_varint64(&mut overflow.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_fixed_and_length_prefixed_round_trip() {
+        let mut buf = Vec::new();
+        put_fixed32(&mut buf, 0xDEAD_BEEF);
+        put_fixed64(&mut buf, u64::MAX - 1);
+        put_length_prefixed_slice(&mut buf, b"");
+        put_length_prefixed_slice(&mut buf, &[7; 200]);
+        assert_eq!(&buf[..4], &0xDEAD_BEEFu32.to_le_bytes());
+
+        let mut input = buf.as_slice();
+        assert_eq!(get_fixed32(&mut input).unwrap(), 0xDEAD_BEEF);
+        assert_eq!(get_fixed64(&mut input).unwrap(), u64::MAX - 1);
+        assert_eq!(get_length_prefixed_slice(&mut input).unwrap(), b"");
+        assert_eq!(get_length_prefixed_slice(&mut input).unwrap(), &[7; 200]);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_input_leaves_cursor_unchanged() {
+        let mut buf = Vec::new();
+        put_length_prefixed_slice(&mut buf, b"value");
+        let truncated = &buf[..buf.len() - 1];
+
+        let mut input = truncated;
+        assert!(get_fixed64(&mut input).is_err());
+        assert!(get_bytes(&mut input, 100).is_err());
+        assert_eq!(input, truncated);
+        assert!(get_length_prefixed_slice(&mut input).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Accounting of the memory an engine holds
+//!
+//! A [`MemoryTracker`] sums the bytes held by each [`MemoryKind`] against
+//! an optional limit. Components charge what they hold through a
+//! [`MemoryReservation`], which releases its bytes when dropped, so the
+//! usage follows readers and iterators however long they live. Kinds
+//! whose owner already knows its total, such as MemTables, are
+//! [recorded](MemoryTracker::record) instead.
+//!
+//! The tracker only counts; what to do when usage approaches the limit,
+//! such as flushing or caching less, is up to whoever checks
+//! [`is_over_limit`](MemoryTracker::is_over_limit).
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::utils::memory::{MemoryKind, MemoryTracker};
+//! use std::sync::Arc;
+//!
+//! let tracker = Arc::new(MemoryTracker::new(1024));
+//! let mut index = tracker.reserve(MemoryKind::TableReaders, 600);
+//! assert!(!tracker.is_over_limit());
+//!
+//! index.resize(2048);
+//! assert!(tracker.is_over_limit());
+//!
+//! drop(index);
+//! assert_eq!(tracker.total(), 0);
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// What a tracked allocation is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryKind {
+    /// Active MemTables and those waiting for a flush
+    MemTables,
+    /// Decoded data blocks cached by table readers
+    BlockCache,
+    /// Index blocks and bloom filters of open table readers
+    TableReaders,
+    /// Data iterators copied out of MemTables
+    Iterators,
+}
+
+impl MemoryKind {
+    /// Every kind, in the order of their usage slots
+    pub const ALL: [MemoryKind; 4] = [
+        MemoryKind::MemTables,
+        MemoryKind::BlockCache,
+        MemoryKind::TableReaders,
+        MemoryKind::Iterators,
+    ];
+
+    fn slot(self) -> usize {
+        self as usize
+    }
+}
+
+/// Sums the memory held by each [`MemoryKind`] against a limit
+pub struct MemoryTracker {
+    /// Bytes usage should stay within (0 = no limit)
+    limit: usize,
+    usage: [AtomicUsize; MemoryKind::ALL.len()],
+}
+
+impl MemoryTracker {
+    /// Creates a tracker with a limit of `limit` bytes, or none if 0
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            usage: Default::default(),
+        }
+    }
+
+    /// Returns the limit in bytes, 0 if there is none
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the bytes held for `kind`
+    pub fn usage(&self, kind: MemoryKind) -> usize {
+        self.usage[kind.slot()].load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes held for all kinds together
+    pub fn total(&self) -> usize {
+        self.usage
+            .iter()
+            .map(|usage| usage.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Returns true if there is a limit and usage has reached it
+    pub fn is_over_limit(&self) -> bool {
+        self.limit > 0 && self.total() >= self.limit
+    }
+
+    /// Sets the bytes held for `kind`, for owners that know their total
+    ///
+    /// Don't mix with reservations of the same kind, which this overwrites.
+    pub fn record(&self, kind: MemoryKind, bytes: usize) {
+        self.usage[kind.slot()].store(bytes, Ordering::Relaxed);
+    }
+
+    /// Charges `bytes` to `kind` until the returned reservation is dropped
+    pub fn reserve(self: &Arc<Self>, kind: MemoryKind, bytes: usize) -> MemoryReservation {
+        self.usage[kind.slot()].fetch_add(bytes, Ordering::Relaxed);
+        MemoryReservation {
+            tracker: Arc::clone(self),
+            kind,
+            bytes,
+        }
+    }
+}
+
+impl Default for MemoryTracker {
+    /// A tracker without a limit
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl fmt::Debug for MemoryTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("MemoryTracker");
+        debug.field("limit", &self.limit);
+        for kind in MemoryKind::ALL {
+            debug.field(&format!("{:?}", kind), &self.usage(kind));
+        }
+        debug.finish()
+    }
+}
+
+/// Bytes charged to a [`MemoryTracker`], released when dropped
+pub struct MemoryReservation {
+    tracker: Arc<MemoryTracker>,
+    kind: MemoryKind,
+    bytes: usize,
+}
+
+impl MemoryReservation {
+    /// Returns the bytes reserved
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns the tracker the bytes are charged to
+    pub fn tracker(&self) -> &Arc<MemoryTracker> {
+        &self.tracker
+    }
+
+    /// Changes the reservation to `bytes`
+    pub fn resize(&mut self, bytes: usize) {
+        let usage = &self.tracker.usage[self.kind.slot()];
+        if bytes > self.bytes {
+            usage.fetch_add(bytes - self.bytes, Ordering::Relaxed);
+        } else {
+            usage.fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+        }
+        self.bytes = bytes;
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.resize(0);
+    }
+}
+
+impl fmt::Debug for MemoryReservation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryReservation")
+            .field("kind", &self.kind)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_reservations_are_summed_per_kind() {
+        let tracker = Arc::new(MemoryTracker::default());
+        let blocks = tracker.reserve(MemoryKind::BlockCache, 100);
+        let mut index = tracker.reserve(MemoryKind::TableReaders, 40);
+        tracker.record(MemoryKind::MemTables, 500);
+
+        assert_eq!(tracker.usage(MemoryKind::BlockCache), 100);
+        assert_eq!(tracker.usage(MemoryKind::TableReaders), 40);
+        assert_eq!(tracker.total(), 640);
+
+        index.resize(10);
+        assert_eq!(tracker.usage(MemoryKind::TableReaders), 10);
+        drop((blocks, index));
+        assert_eq!(tracker.total(), 500);
+
+        // Without a limit usage is never too high
+        assert!(!tracker.is_over_limit());
+    }
+
+    #[test]
+    fn test_limit_is_reached_by_all_kinds_together() {
+        let tracker = Arc::new(MemoryTracker::new(1000));
+        tracker.record(MemoryKind::MemTables, 700);
+        let iterator = tracker.reserve(MemoryKind::Iterators, 299);
+        assert!(!tracker.is_over_limit());
+
+        let blocks = tracker.reserve(MemoryKind::BlockCache, 1);
+        assert!(tracker.is_over_limit());
+        drop((iterator, blocks));
+        assert!(!tracker.is_over_limit());
+    }
+
+    #[test]
+    fn test_concurrent_reservations_balance_out() {
+        let tracker = Arc::new(MemoryTracker::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let tracker = Arc::clone(&tracker);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        let mut reservation = tracker.reserve(MemoryKind::Iterators, t + i);
+                        reservation.resize(i);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(tracker.usage(MemoryKind::Iterators), 0);
+    }
+}
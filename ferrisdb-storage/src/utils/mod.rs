@@ -2,6 +2,15 @@
 //!
 //! This module contains shared utilities used across different storage components.
 
+pub mod aligned_buffer;
+pub mod atomic_file;
+pub mod bloom;
+pub mod buffer_pool;
 mod bytes_ext;
+pub mod cache;
+pub mod coding;
+pub mod crc;
+pub mod memory;
 
+pub use buffer_pool::{BufferPool, PooledBuffer};
 pub use bytes_ext::BytesMutExt;
@@ -0,0 +1,966 @@
+//! In-memory versions of the LSM tree's file set
+//!
+//! The [`ManifestWriter`] persists *changes* to the set of live SSTables;
+//! this module turns them into immutable snapshots that readers can hold
+//! on to while flushes and compactions keep changing the tree:
+//!
+//! - A [`Version`] is one snapshot of the live tables in every level
+//! - The [`VersionSet`] owns the MANIFEST and the current version, and
+//!   installs a new version for every edit
+//! - A version set [opened read-only](VersionSet::open_read_only) follows a
+//!   MANIFEST another process writes, through [`refresh`](VersionSet::refresh)
+//! - A [`TableHandle`] is shared by every version containing its table
+//!
+//! ```text
+//!            current
+//!               │
+//!   Version 1   ▼   Version 2           Version 1 is still pinned by a
+//!   ┌──────┐       ┌──────┐             reader, so table 7 stays on disk
+//!   │ L0: 7│       │ L0:  │             even though compaction replaced
+//!   │ L1: 3│       │ L1: 3│ 8           it with table 8
+//!   └──────┘       └──────┘
+//! ```
+//!
+//! # File Lifetime
+//!
+//! Handles are reference counted. When an edit removes a table, its handle
+//! is marked obsolete, and the file is deleted when the last version (and
+//! therefore the last reader) referencing it is dropped. A reader holding
+//! an `Arc<Version>` can thus never have a file deleted underneath it.
+
+use crate::comparator::{self, Comparator};
+pub use crate::files::{table_file_name, wal_file_name, MANIFEST_FILE_NAME};
+use crate::manifest::{
+    ManifestReader, ManifestState, ManifestWriter, SSTableMeta, VersionEdit, NUM_LEVELS,
+};
+use crate::sstable::SSTableReader;
+use crate::vfs::{self, Vfs};
+use ferrisdb_core::{Error, Result};
+
+use parking_lot::{Mutex, RwLock};
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A live SSTable shared by every version that contains it
+///
+/// Dropping the last handle to an obsolete table deletes its file.
+#[derive(Debug)]
+pub struct TableHandle {
+    meta: SSTableMeta,
+    path: PathBuf,
+    /// Ordering of the table's user keys
+    comparator: Arc<dyn Comparator>,
+    /// Filesystem holding the table's file
+    vfs: Arc<dyn Vfs>,
+    /// Set once an installed edit has removed this table
+    obsolete: AtomicBool,
+}
+
+impl TableHandle {
+    fn new(
+        meta: SSTableMeta,
+        dir: &Path,
+        comparator: &Arc<dyn Comparator>,
+        vfs: &Arc<dyn Vfs>,
+    ) -> Self {
+        Self {
+            path: dir.join(table_file_name(meta.number)),
+            meta,
+            comparator: Arc::clone(comparator),
+            vfs: Arc::clone(vfs),
+            obsolete: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the table's metadata as recorded in the MANIFEST
+    pub fn meta(&self) -> &SSTableMeta {
+        &self.meta
+    }
+
+    /// Returns the path to the table's file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true once the table has been removed from the current version
+    pub fn is_obsolete(&self) -> bool {
+        self.obsolete.load(Ordering::Acquire)
+    }
+
+    /// Opens a reader over the table's file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened, is invalid, or was
+    /// written with a different comparator than the version set's.
+    pub fn open_reader(&self) -> Result<SSTableReader> {
+        SSTableReader::open_in(&self.vfs, &self.path, Arc::clone(&self.comparator))
+    }
+
+    /// Opens a reader over the table's file through `vfs`
+    ///
+    /// # Errors
+    ///
+    /// See [`open_reader`](Self::open_reader).
+    pub fn open_reader_in(&self, vfs: &Arc<dyn Vfs>) -> Result<SSTableReader> {
+        SSTableReader::open_in(vfs, &self.path, Arc::clone(&self.comparator))
+    }
+
+    /// Returns true if the table's key range contains `user_key`
+    fn contains_key(&self, user_key: &[u8]) -> bool {
+        self.comparator
+            .compare(&self.meta.smallest.user_key, user_key)
+            .is_le()
+            && self
+                .comparator
+                .compare(user_key, &self.meta.largest.user_key)
+                .is_le()
+    }
+}
+
+impl Drop for TableHandle {
+    fn drop(&mut self) {
+        if self.is_obsolete() {
+            if let Err(e) = self.vfs.remove_file(&self.path) {
+                log::warn!(
+                    "Failed to delete obsolete table {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// An immutable snapshot of the live tables in every level
+///
+/// Level 0 tables come straight from MemTable flushes and may overlap, so
+/// they are ordered newest first. Tables in deeper levels never overlap and
+/// are ordered by smallest key, in the order of the version's comparator.
+#[derive(Debug)]
+pub struct Version {
+    levels: [Vec<Arc<TableHandle>>; NUM_LEVELS],
+    comparator: Arc<dyn Comparator>,
+    /// Filesystem holding the version's tables
+    vfs: Arc<dyn Vfs>,
+}
+
+impl Version {
+    /// Builds a version from a recovered MANIFEST state
+    fn from_state(
+        state: &ManifestState,
+        dir: &Path,
+        comparator: Arc<dyn Comparator>,
+        vfs: Arc<dyn Vfs>,
+    ) -> Self {
+        let mut version = Self {
+            levels: Default::default(),
+            comparator,
+            vfs,
+        };
+        for (level, files) in version.levels.iter_mut().enumerate() {
+            files.extend(state.files(level).map(|meta| {
+                Arc::new(TableHandle::new(
+                    meta.clone(),
+                    dir,
+                    &version.comparator,
+                    &version.vfs,
+                ))
+            }));
+        }
+        version.sort_levels();
+        version
+    }
+
+    /// Builds the version that results from applying `edit` to this one
+    ///
+    /// Tables untouched by the edit keep their handles; removed tables have
+    /// their handles marked obsolete.
+    fn apply(&self, edit: &VersionEdit, dir: &Path) -> Self {
+        let mut next = Self {
+            levels: self.levels.clone(),
+            comparator: Arc::clone(&self.comparator),
+            vfs: Arc::clone(&self.vfs),
+        };
+
+        let mut removed = Vec::new();
+        for &(level, number) in &edit.deleted_files {
+            let files = &mut next.levels[level];
+            if let Some(pos) = files.iter().position(|t| t.meta.number == number) {
+                removed.push(files.remove(pos));
+            }
+        }
+        for (level, meta) in &edit.new_files {
+            // A table moved between levels keeps its file and its handle
+            let handle = match removed.iter().position(|t| t.meta.number == meta.number) {
+                Some(pos) => removed.swap_remove(pos),
+                None => Arc::new(TableHandle::new(
+                    meta.clone(),
+                    dir,
+                    &self.comparator,
+                    &self.vfs,
+                )),
+            };
+            next.levels[*level].push(handle);
+        }
+        for handle in removed {
+            handle.obsolete.store(true, Ordering::Release);
+        }
+
+        next.sort_levels();
+        next
+    }
+
+    /// Builds a version from a MANIFEST state re-read from disk
+    ///
+    /// Tables this version already has keep their handles. Nothing is
+    /// marked obsolete, as the process writing the MANIFEST deletes the
+    /// tables it removes.
+    fn refreshed(&self, state: &ManifestState, dir: &Path) -> Self {
+        let mut next = Self {
+            levels: Default::default(),
+            comparator: Arc::clone(&self.comparator),
+            vfs: Arc::clone(&self.vfs),
+        };
+        for (level, files) in next.levels.iter_mut().enumerate() {
+            files.extend(state.files(level).map(|meta| {
+                self.levels
+                    .iter()
+                    .flatten()
+                    .find(|t| t.meta.number == meta.number)
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        Arc::new(TableHandle::new(
+                            meta.clone(),
+                            dir,
+                            &self.comparator,
+                            &self.vfs,
+                        ))
+                    })
+            }));
+        }
+        next.sort_levels();
+        next
+    }
+
+    /// Restores the per-level ordering after tables were added
+    fn sort_levels(&mut self) {
+        let (level0, deeper) = self.levels.split_at_mut(1);
+        level0[0].sort_by_key(|t| std::cmp::Reverse(t.meta.number));
+        for files in deeper {
+            files.sort_by(|a, b| {
+                self.comparator
+                    .compare(&a.meta.smallest.user_key, &b.meta.smallest.user_key)
+            });
+        }
+    }
+
+    /// Returns the ordering of the version's user keys
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Returns the live tables of a level
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level >= NUM_LEVELS`.
+    pub fn files(&self, level: usize) -> &[Arc<TableHandle>] {
+        &self.levels[level]
+    }
+
+    /// Returns the total number of live tables across all levels
+    pub fn file_count(&self) -> usize {
+        self.levels.iter().map(Vec::len).sum()
+    }
+
+    /// Returns the total size in bytes of the live tables of a level
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.levels[level].iter().map(|t| t.meta.file_size).sum()
+    }
+
+    /// Returns the tables that may contain `user_key`, newest data first
+    ///
+    /// All overlapping level 0 tables come first, by descending file number,
+    /// followed by at most one table from each deeper level. A compaction
+    /// writing back into level 0 can give older data a higher number than a
+    /// table flushed while it ran, so readers must order the versions they
+    /// find by timestamp rather than by table.
+    pub fn tables_for_key(&self, user_key: &[u8]) -> Vec<&Arc<TableHandle>> {
+        let mut tables: Vec<_> = self.levels[0]
+            .iter()
+            .filter(|t| t.contains_key(user_key))
+            .collect();
+
+        for files in &self.levels[1..] {
+            // First table whose largest key is not below user_key
+            let pos = files.partition_point(|t| {
+                self.comparator
+                    .compare(&t.meta.largest.user_key, user_key)
+                    .is_lt()
+            });
+            if let Some(table) = files.get(pos).filter(|t| t.contains_key(user_key)) {
+                tables.push(table);
+            }
+        }
+
+        tables
+    }
+}
+
+/// The MANIFEST together with the current [`Version`]
+///
+/// All changes to the file set go through [`log_and_apply`](Self::log_and_apply),
+/// which persists the edit and then installs a new current version.
+/// Readers call [`current`](Self::current) and keep the returned version
+/// for as long as they read from its tables.
+///
+/// # Thread Safety
+///
+/// `VersionSet` is `Send + Sync`. Edits are serialized by an internal
+/// lock; taking a snapshot only clones an `Arc` and never waits for an
+/// edit's disk sync.
+///
+/// # Example
+///
+/// ```no_run
+/// use ferrisdb_storage::manifest::{SSTableMeta, VersionEdit};
+/// use ferrisdb_storage::sstable::SSTableWriter;
+/// use ferrisdb_storage::version::VersionSet;
+///
+/// let versions = VersionSet::open("data")?;
+///
+/// // Flush a MemTable into a new level 0 table
+/// let number = versions.new_file_number();
+/// let info = SSTableWriter::new(versions.table_path(number))?.finish()?;
+/// let mut edit = VersionEdit::default();
+/// edit.add_file(0, SSTableMeta::new(number, &info));
+/// versions.log_and_apply(edit)?;
+///
+/// let snapshot = versions.current();
+/// for table in snapshot.tables_for_key(b"key") {
+///     let mut reader = table.open_reader()?;
+///     // ...
+/// }
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct VersionSet {
+    dir: PathBuf,
+    /// Filesystem holding the MANIFEST and tables
+    vfs: Arc<dyn Vfs>,
+    /// Serializes edits and owns the MANIFEST file
+    manifest: Mutex<Manifest>,
+    current: RwLock<Arc<Version>>,
+    next_file_number: AtomicU64,
+    /// Whether edits are checked against the files on disk
+    paranoid_checks: bool,
+}
+
+/// The MANIFEST behind a version set
+enum Manifest {
+    /// Logs the version set's edits
+    Writable(ManifestWriter),
+    /// Another process writes the file; holds the state last read from it
+    ReadOnly(ManifestState),
+}
+
+impl Manifest {
+    fn state(&self) -> &ManifestState {
+        match self {
+            Self::Writable(writer) => writer.state(),
+            Self::ReadOnly(state) => state,
+        }
+    }
+}
+
+impl VersionSet {
+    /// Opens the version set stored in `dir`, creating it if needed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or MANIFEST cannot be created, or
+    /// if recovering an existing MANIFEST fails.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_comparator(dir, comparator::bytewise())
+    }
+
+    /// Opens the version set stored in `dir`, its keys ordered by `comparator`
+    ///
+    /// All tables of a version set share one comparator, so only one table
+    /// is checked here; the others are checked when they are first read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or MANIFEST cannot be created, if
+    /// recovering an existing MANIFEST fails, or if the tables were written
+    /// with a different comparator.
+    pub fn open_with_comparator(
+        dir: impl AsRef<Path>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        Self::open_in(&vfs::os(), dir, comparator)
+    }
+
+    /// Opens the version set stored in `dir` on `vfs`, its keys ordered by
+    /// `comparator`
+    ///
+    /// # Errors
+    ///
+    /// See [`open_with_comparator`](Self::open_with_comparator).
+    pub fn open_in(
+        vfs: &Arc<dyn Vfs>,
+        dir: impl AsRef<Path>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        vfs.create_dir_all(&dir)?;
+
+        let manifest_path = dir.join(MANIFEST_FILE_NAME);
+        let manifest = if vfs.exists(&manifest_path) {
+            ManifestWriter::open_in(vfs, &manifest_path)?
+        } else {
+            ManifestWriter::create_in(vfs, &manifest_path)?
+        };
+
+        Self::from_manifest(vfs, dir, Manifest::Writable(manifest), comparator)
+    }
+
+    /// Opens the version set stored in `dir` without ever writing to it
+    ///
+    /// Edits are rejected, and the directory, its MANIFEST and its tables
+    /// are left as they are, so another process may keep writing them;
+    /// [`refresh`](Self::refresh) picks up the edits it made since.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if `dir` has no MANIFEST, or an error if
+    /// recovering it fails or the tables were written with a different
+    /// comparator.
+    pub fn open_read_only(dir: impl AsRef<Path>, comparator: Arc<dyn Comparator>) -> Result<Self> {
+        Self::open_read_only_in(&vfs::os(), dir, comparator)
+    }
+
+    /// Opens the version set stored in `dir` on `vfs` without ever writing
+    /// to it
+    ///
+    /// # Errors
+    ///
+    /// See [`open_read_only`](Self::open_read_only).
+    pub fn open_read_only_in(
+        vfs: &Arc<dyn Vfs>,
+        dir: impl AsRef<Path>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let state = read_manifest(vfs, &dir)?;
+        Self::from_manifest(vfs, dir, Manifest::ReadOnly(state), comparator)
+    }
+
+    fn from_manifest(
+        vfs: &Arc<dyn Vfs>,
+        dir: PathBuf,
+        manifest: Manifest,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let state = manifest.state();
+        let current = Version::from_state(state, &dir, comparator, Arc::clone(vfs));
+        if let Some(table) = current.levels.iter().flatten().next() {
+            table.open_reader()?;
+        }
+        // File number 0 is never handed out
+        let next_file_number = state.next_file_number().max(1);
+
+        Ok(Self {
+            dir,
+            vfs: Arc::clone(vfs),
+            manifest: Mutex::new(manifest),
+            current: RwLock::new(Arc::new(current)),
+            next_file_number: AtomicU64::new(next_file_number),
+            paranoid_checks: false,
+        })
+    }
+
+    /// Returns true if the version set was opened read-only
+    pub fn is_read_only(&self) -> bool {
+        matches!(*self.manifest.lock(), Manifest::ReadOnly(_))
+    }
+
+    /// Re-reads the MANIFEST of a read-only version set, installing the
+    /// version it now describes
+    ///
+    /// Returns false if nothing changed since the last read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the version set isn't
+    /// read-only, or an error if reading the MANIFEST fails; the current
+    /// version is unchanged then.
+    pub fn refresh(&self) -> Result<bool> {
+        let mut manifest = self.manifest.lock();
+        let Manifest::ReadOnly(state) = &mut *manifest else {
+            return Err(Error::InvalidOperation(
+                "Only read-only version sets are refreshed".to_string(),
+            ));
+        };
+        let next = read_manifest(&self.vfs, &self.dir)?;
+        if next == *state {
+            return Ok(false);
+        }
+
+        let version = self.current.read().refreshed(&next, &self.dir);
+        *self.current.write() = Arc::new(version);
+        self.mark_file_number_used(next.next_file_number().saturating_sub(1));
+        *state = next;
+        Ok(true)
+    }
+
+    /// Checks every table an edit adds against the file on disk before
+    /// logging the edit
+    ///
+    /// A table whose file is missing or differs in size from its metadata
+    /// fails the edit with [`Error::Corruption`], so the MANIFEST never
+    /// records it.
+    pub fn with_paranoid_checks(mut self, enabled: bool) -> Self {
+        self.paranoid_checks = enabled;
+        self
+    }
+
+    /// Returns a snapshot of the current file set
+    pub fn current(&self) -> Arc<Version> {
+        Arc::clone(&self.current.read())
+    }
+
+    /// Returns the ordering of user keys in the version set's tables
+    pub fn comparator(&self) -> Arc<dyn Comparator> {
+        Arc::clone(self.current.read().comparator())
+    }
+
+    /// Allocates a new, unique file number
+    ///
+    /// The allocation is persisted by the next [`log_and_apply`](Self::log_and_apply),
+    /// so numbers are never reused after a restart as long as every file
+    /// written with them is registered through an edit.
+    pub fn new_file_number(&self) -> u64 {
+        self.next_file_number.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Ensures `number` and everything below it are never handed out
+    ///
+    /// Used at startup for files, such as WAL segments, whose numbers were
+    /// allocated but not yet persisted by an edit before a restart.
+    pub fn mark_file_number_used(&self, number: u64) {
+        self.next_file_number
+            .fetch_max(number.saturating_add(1), Ordering::Relaxed);
+    }
+
+    /// Returns the path of the SSTable with the given number
+    pub fn table_path(&self, number: u64) -> PathBuf {
+        self.dir.join(table_file_name(number))
+    }
+
+    /// Persists an edit and installs the resulting version as current
+    ///
+    /// The next file number is filled in automatically. Tables removed by
+    /// the edit are deleted from disk once no version references them.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the edit doesn't match the
+    /// current file set or the version set is read-only,
+    /// `Error::Corruption` if paranoid checks find an
+    /// added table missing or of the wrong size, or an I/O error if the
+    /// MANIFEST write fails. The current version is unchanged in all cases.
+    pub fn log_and_apply(&self, mut edit: VersionEdit) -> Result<()> {
+        let mut manifest = self.manifest.lock();
+        let Manifest::Writable(manifest) = &mut *manifest else {
+            return Err(Error::InvalidOperation(format!(
+                "Version set in {} is read-only",
+                self.dir.display()
+            )));
+        };
+        if self.paranoid_checks {
+            self.check_new_files(&edit)?;
+        }
+
+        edit.set_next_file_number(self.next_file_number.load(Ordering::Relaxed));
+        manifest.log_and_apply(edit.clone())?;
+
+        let next = self.current.read().apply(&edit, &self.dir);
+        *self.current.write() = Arc::new(next);
+
+        Ok(())
+    }
+
+    /// Checks that the tables an edit adds exist with their recorded sizes
+    fn check_new_files(&self, edit: &VersionEdit) -> Result<()> {
+        for (_, meta) in &edit.new_files {
+            let path = self.table_path(meta.number);
+            let size = match self.vfs.file_size(&path) {
+                Ok(size) => size,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(Error::Corruption(format!(
+                        "Table {} added by an edit does not exist",
+                        meta.number
+                    ))
+                    .with_path(&path));
+                }
+                Err(e) => return Err(Error::from(e).with_path(&path)),
+            };
+            if size != meta.file_size {
+                return Err(Error::Corruption(format!(
+                    "Table {} is {} bytes, but the edit records {}",
+                    meta.number, size, meta.file_size
+                ))
+                .with_path(&path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a table written for a coming edit back in full through `vfs`
+    ///
+    /// Every block is checked against its checksum and the entries for
+    /// their order, and the table must hold as many entries as `meta`
+    /// says were written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Corruption`] if the table doesn't read back as
+    /// written, or the error of opening or reading it.
+    pub fn verify_table(&self, vfs: &Arc<dyn Vfs>, meta: &SSTableMeta) -> Result<()> {
+        let path = self.table_path(meta.number);
+        let mut reader = SSTableReader::open_in(vfs, &path, self.comparator())?;
+        let count = reader.verify()?;
+        if count != meta.entry_count {
+            return Err(Error::Corruption(format!(
+                "Table {} holds {} entries, but {} were written",
+                meta.number, count, meta.entry_count
+            ))
+            .with_path(&path));
+        }
+        Ok(())
+    }
+
+    /// Returns the persisted counters and file set of the MANIFEST
+    pub fn manifest_state(&self) -> ManifestState {
+        self.manifest.lock().state().clone()
+    }
+
+    /// Returns the data directory holding the MANIFEST and tables
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the filesystem holding the MANIFEST and tables
+    pub fn vfs(&self) -> &Arc<dyn Vfs> {
+        &self.vfs
+    }
+
+    /// Copies the current file set into `dir` as a new version set
+    ///
+    /// Live tables are hard-linked, or copied where the file system can't
+    /// link them, and `dir` gets a fresh MANIFEST holding only the current
+    /// state. Edits wait until the copy is complete, so the MANIFEST and
+    /// the tables always match.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` already has a MANIFEST, or if linking a
+    /// table or writing the MANIFEST fails.
+    pub fn checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        let manifest = self.manifest.lock();
+        let mut copy = ManifestWriter::create_in(&self.vfs, dir.join(MANIFEST_FILE_NAME))?;
+
+        let current = self.current();
+        for level in 0..NUM_LEVELS {
+            for table in current.files(level) {
+                let target = dir.join(table_file_name(table.meta.number));
+                vfs::link_or_copy(self.vfs.as_ref(), table.path(), &target)?;
+            }
+        }
+
+        let mut edit = manifest.state().snapshot();
+        edit.set_next_file_number(self.next_file_number.load(Ordering::Relaxed));
+        copy.log_and_apply(edit)?;
+        self.vfs.sync_dir(dir)?;
+        Ok(())
+    }
+}
+
+/// Reads the state of the MANIFEST in `dir`
+fn read_manifest(vfs: &Arc<dyn Vfs>, dir: &Path) -> Result<ManifestState> {
+    let path = dir.join(MANIFEST_FILE_NAME);
+    if !vfs.exists(&path) {
+        return Err(Error::NotFound(format!("No MANIFEST in {}", dir.display())));
+    }
+    ManifestReader::new_in(vfs, &path)
+        .and_then(|mut reader| reader.recover())
+        .map_err(|e| e.with_path(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::{InternalKey, SSTableWriter};
+    use ferrisdb_core::{Error, Operation};
+    use tempfile::TempDir;
+
+    /// Writes a table spanning `first..=last` and returns its metadata
+    fn write_table(versions: &VersionSet, first: &[u8], last: &[u8]) -> SSTableMeta {
+        let number = versions.new_file_number();
+        let mut writer = SSTableWriter::new(versions.table_path(number)).unwrap();
+        for key in [first, last] {
+            writer
+                .add(
+                    InternalKey::new(key.to_vec(), 1),
+                    b"v".to_vec(),
+                    Operation::Put,
+                )
+                .unwrap();
+        }
+        SSTableMeta::new(number, &writer.finish().unwrap())
+    }
+
+    #[test]
+    fn test_snapshot_keeps_compacted_tables_until_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+
+        let flushed = write_table(&versions, b"a", b"m");
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, flushed.clone());
+        versions.log_and_apply(edit).unwrap();
+
+        let snapshot = versions.current();
+        let flushed_path = versions.table_path(flushed.number);
+
+        // Compact the flushed table into level 1
+        let compacted = write_table(&versions, b"a", b"m");
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, flushed.number);
+        edit.add_file(1, compacted.clone());
+        versions.log_and_apply(edit).unwrap();
+
+        // The old snapshot still reads the flushed table
+        assert!(snapshot.files(0)[0].is_obsolete());
+        assert!(flushed_path.exists());
+        snapshot.files(0)[0].open_reader().unwrap();
+
+        let current = versions.current();
+        assert!(current.files(0).is_empty());
+        assert_eq!(current.files(1)[0].meta(), &compacted);
+
+        drop(snapshot);
+        assert!(!flushed_path.exists());
+        assert!(versions.table_path(compacted.number).exists());
+    }
+
+    #[test]
+    fn test_moving_table_between_levels_keeps_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+
+        let table = write_table(&versions, b"a", b"z");
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, table.clone());
+        versions.log_and_apply(edit).unwrap();
+        let snapshot = versions.current();
+
+        // Trivial move: no overlap below, so the file is relinked as is
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, table.number);
+        edit.add_file(1, table.clone());
+        versions.log_and_apply(edit).unwrap();
+        drop(snapshot);
+
+        let current = versions.current();
+        assert!(!current.files(1)[0].is_obsolete());
+        assert!(versions.table_path(table.number).exists());
+    }
+
+    #[test]
+    fn test_reopen_restores_version_and_file_numbers() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let (table, last_number) = {
+            let versions = VersionSet::open(temp_dir.path()).unwrap();
+            let table = write_table(&versions, b"a", b"z");
+            let mut edit = VersionEdit::default();
+            edit.add_file(2, table.clone());
+            versions.log_and_apply(edit).unwrap();
+            (table, versions.new_file_number())
+        };
+
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+        assert_eq!(versions.current().files(2)[0].meta(), &table);
+        assert_eq!(versions.current().file_count(), 1);
+        // Numbers allocated after the last edit may have been used for
+        // files that were never registered, but earlier ones are never reused
+        assert!(versions.new_file_number() >= last_number);
+        assert!(versions.new_file_number() > table.number);
+    }
+
+    #[test]
+    fn test_checkpoint_outlives_compaction_of_its_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let checkpoint_dir = temp_dir.path().join("checkpoint");
+        let versions = VersionSet::open(temp_dir.path().join("db")).unwrap();
+
+        let table = write_table(&versions, b"a", b"z");
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, table.clone());
+        versions.log_and_apply(edit).unwrap();
+        versions.checkpoint(&checkpoint_dir).unwrap();
+        assert!(versions.checkpoint(&checkpoint_dir).is_err());
+
+        // Compacting the original away leaves the checkpoint's link intact
+        let compacted = write_table(&versions, b"a", b"z");
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, table.number);
+        edit.add_file(1, compacted);
+        versions.log_and_apply(edit).unwrap();
+        assert!(!versions.table_path(table.number).exists());
+
+        let checkpoint = VersionSet::open(&checkpoint_dir).unwrap();
+        assert_eq!(checkpoint.current().files(0)[0].meta(), &table);
+        checkpoint.current().files(0)[0].open_reader().unwrap();
+        assert!(checkpoint.new_file_number() > table.number);
+    }
+
+    #[test]
+    fn test_marked_file_numbers_are_never_handed_out() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+
+        versions.mark_file_number_used(41);
+        assert_eq!(versions.new_file_number(), 42);
+
+        // Marking an older number changes nothing
+        versions.mark_file_number_used(7);
+        assert_eq!(versions.new_file_number(), 43);
+    }
+
+    #[test]
+    fn test_tables_for_key_orders_newest_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+
+        let older_l0 = write_table(&versions, b"a", b"k");
+        let newer_l0 = write_table(&versions, b"c", b"z");
+        let l1_low = write_table(&versions, b"a", b"e");
+        let l1_high = write_table(&versions, b"f", b"p");
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, older_l0.clone());
+        edit.add_file(0, newer_l0.clone());
+        edit.add_file(1, l1_high.clone());
+        edit.add_file(1, l1_low.clone());
+        versions.log_and_apply(edit).unwrap();
+
+        let current = versions.current();
+        let numbers = |key: &[u8]| -> Vec<u64> {
+            current
+                .tables_for_key(key)
+                .iter()
+                .map(|t| t.meta().number)
+                .collect()
+        };
+
+        assert_eq!(
+            numbers(b"d"),
+            vec![newer_l0.number, older_l0.number, l1_low.number]
+        );
+        assert_eq!(numbers(b"m"), vec![newer_l0.number, l1_high.number]);
+        assert_eq!(numbers(b"b"), vec![older_l0.number, l1_low.number]);
+        assert!(numbers(b"zz").is_empty());
+    }
+
+    #[test]
+    fn test_rejected_edit_leaves_current_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+        let before = versions.current();
+
+        let mut edit = VersionEdit::default();
+        edit.delete_file(1, 99);
+        assert!(matches!(
+            versions.log_and_apply(edit),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        assert!(Arc::ptr_eq(&before, &versions.current()));
+    }
+
+    #[test]
+    fn test_paranoid_edits_require_tables_as_written() {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = VersionSet::open(temp_dir.path())
+            .unwrap()
+            .with_paranoid_checks(true);
+        let missing = SSTableMeta {
+            number: versions.new_file_number(),
+            ..write_table(&versions, b"a", b"b")
+        };
+        let mut truncated = write_table(&versions, b"c", b"d");
+        truncated.file_size += 1;
+        let table = write_table(&versions, b"e", b"f");
+        versions.verify_table(&crate::vfs::os(), &table).unwrap();
+
+        for meta in [missing, truncated] {
+            let mut edit = VersionEdit::default();
+            edit.add_file(0, meta);
+            let err = versions.log_and_apply(edit).unwrap_err();
+            assert!(matches!(err.root(), Error::Corruption(_)), "{}", err);
+        }
+        assert!(versions.current().files(0).is_empty());
+
+        let mut short = table.clone();
+        short.entry_count += 1;
+        assert!(versions.verify_table(&crate::vfs::os(), &short).is_err());
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, table);
+        versions.log_and_apply(edit).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_version_set_follows_the_writer() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(matches!(
+            VersionSet::open_read_only(temp_dir.path(), comparator::bytewise()),
+            Err(Error::NotFound(_))
+        ));
+        let versions = VersionSet::open(temp_dir.path()).unwrap();
+        let flushed = write_table(&versions, b"a", b"m");
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, flushed.clone());
+        versions.log_and_apply(edit).unwrap();
+
+        let follower = VersionSet::open_read_only(temp_dir.path(), comparator::bytewise()).unwrap();
+        assert!(follower.is_read_only());
+        assert_eq!(follower.current().files(0)[0].meta(), &flushed);
+        assert!(!follower.refresh().unwrap());
+        assert!(matches!(
+            follower.log_and_apply(VersionEdit::default()),
+            Err(Error::InvalidOperation(_))
+        ));
+
+        let compacted = write_table(&versions, b"a", b"m");
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, flushed.number);
+        edit.add_file(1, compacted.clone());
+        versions.log_and_apply(edit).unwrap();
+        let flushed_path = versions.table_path(flushed.number);
+        assert!(!flushed_path.exists());
+
+        let before = follower.current();
+        assert!(follower.refresh().unwrap());
+        assert!(follower.current().files(0).is_empty());
+        assert_eq!(follower.current().files(1)[0].meta(), &compacted);
+        // Dropping the follower's versions leaves the writer's files alone
+        drop(before);
+        drop(follower);
+        assert!(versions.table_path(compacted.number).exists());
+    }
+}
@@ -0,0 +1,368 @@
+//! Files read and written with `O_DIRECT`
+//!
+//! Direct I/O moves data between the disk and aligned buffers without
+//! going through the page cache, so a large compaction streaming through
+//! gigabytes of tables doesn't evict the pages hot reads depend on. Every
+//! transfer is a whole number of [`DIRECT_IO_ALIGNMENT`] blocks at an
+//! aligned offset:
+//!
+//! - [`DirectReader`] reads the aligned blocks covering each request into
+//!   an [`AlignedBuffer`] and copies out the part asked for
+//! - [`DirectWriter`] collects writes in an aligned buffer and writes it
+//!   out a chunk at a time. The partial chunk at the end is written
+//!   zero-padded on flush and the file truncated back to its real length;
+//!   the chunk stays buffered and is rewritten in full once it fills up
+//!
+//! Filesystems without direct I/O, such as tmpfs, reject `O_DIRECT` when
+//! the file is opened; the file is then opened normally instead.
+
+use crate::utils::aligned_buffer::{align_down, align_up, AlignedBuffer, DIRECT_IO_ALIGNMENT};
+
+use super::VfsFile;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+/// Bytes a writer buffers before writing them out
+const WRITE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Most bytes a reader transfers for one read
+const MAX_READ_SIZE: usize = 1024 * 1024;
+
+/// Opens an existing file for direct reads
+pub(super) fn open(path: &Path) -> io::Result<Box<dyn VfsFile>> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    match open_direct(&options, path)? {
+        Some(file) => Ok(Box::new(DirectReader::new(file)?)),
+        None => Ok(Box::new(options.open(path)?)),
+    }
+}
+
+/// Creates a file for direct writes, truncating any existing one
+pub(super) fn create(path: &Path) -> io::Result<Box<dyn VfsFile>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    match open_direct(&options, path)? {
+        Some(file) => Ok(Box::new(DirectWriter::new(file))),
+        None => Ok(Box::new(options.read(true).open(path)?)),
+    }
+}
+
+/// Opens a file with `O_DIRECT`, or returns `None` if its filesystem
+/// doesn't support direct I/O
+fn open_direct(options: &OpenOptions, path: &Path) -> io::Result<Option<File>> {
+    let mut direct = options.clone();
+    direct.custom_flags(libc::O_DIRECT);
+    match direct.open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            log::debug!(
+                "Direct I/O unsupported for {}, using buffered I/O",
+                path.display()
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads `buf.len()` bytes at `offset`, or fewer at the end of the file
+fn read_full_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read_at(&mut buf[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// A read-only file whose reads bypass the page cache
+///
+/// The file's size is taken when it is opened; it must not change while
+/// the reader is open, as holds for finished SSTables.
+#[derive(Debug)]
+pub(super) struct DirectReader {
+    file: File,
+    size: u64,
+    position: u64,
+    buffer: AlignedBuffer,
+}
+
+impl DirectReader {
+    fn new(file: File) -> io::Result<Self> {
+        Ok(Self {
+            size: file.metadata()?.len(),
+            file,
+            position: 0,
+            buffer: AlignedBuffer::with_capacity(DIRECT_IO_ALIGNMENT),
+        })
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() || self.position >= self.size {
+            return Ok(0);
+        }
+        let wanted = out
+            .len()
+            .min(MAX_READ_SIZE)
+            .min((self.size - self.position) as usize);
+        let start = align_down(self.position);
+        let skip = (self.position - start) as usize;
+        let len = align_up(skip + wanted);
+        if self.buffer.capacity() < len {
+            self.buffer = AlignedBuffer::with_capacity(len);
+        }
+
+        let blocks = self.buffer.resize_for_read(len);
+        let read = read_full_at(&self.file, blocks, start)?;
+        let copied = read.saturating_sub(skip).min(wanted);
+        out[..copied].copy_from_slice(&blocks[skip..skip + copied]);
+        self.position += copied as u64;
+        Ok(copied)
+    }
+}
+
+impl Write for DirectReader {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for DirectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(pos, self.position, self.size)?;
+        Ok(self.position)
+    }
+}
+
+impl VfsFile for DirectReader {
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    fn set_len(&self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// An append-only file whose writes bypass the page cache
+///
+/// Only appends are supported: seeking anywhere but to the current end
+/// fails, and so does reading.
+#[derive(Debug)]
+pub(super) struct DirectWriter {
+    file: File,
+    /// Bytes after `buffer_offset`, not yet written as a full chunk
+    buffer: AlignedBuffer,
+    /// Offset of the buffer's first byte in the file, always aligned
+    buffer_offset: u64,
+    /// Whether the file holds the buffered bytes as they are now
+    tail_written: bool,
+}
+
+impl DirectWriter {
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            buffer: AlignedBuffer::with_capacity(WRITE_CHUNK_SIZE),
+            buffer_offset: 0,
+            tail_written: true,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.buffer_offset + self.buffer.len() as u64
+    }
+
+    /// Writes the buffered bytes padded to whole blocks, and truncates the
+    /// padding off again
+    fn write_tail(&mut self) -> io::Result<()> {
+        if self.tail_written {
+            return Ok(());
+        }
+        self.file
+            .write_all_at(self.buffer.padded(), self.buffer_offset)?;
+        self.file.set_len(self.len())?;
+        self.tail_written = true;
+        Ok(())
+    }
+}
+
+impl Read for DirectWriter {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "File was opened for direct appends only",
+        ))
+    }
+}
+
+impl Write for DirectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full chunk is written out before taking more bytes, so a
+        // failed write leaves none of `buf` behind
+        if self.buffer.is_full() {
+            self.file
+                .write_all_at(self.buffer.as_slice(), self.buffer_offset)?;
+            self.buffer_offset += self.buffer.len() as u64;
+            self.buffer.clear();
+        }
+        self.tail_written = false;
+        Ok(self.buffer.extend_from_slice(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_tail()
+    }
+}
+
+impl Seek for DirectWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.len();
+        if seek_position(pos, len, len)? != len {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Direct writers can only seek to their end",
+            ));
+        }
+        Ok(len)
+    }
+}
+
+impl VfsFile for DirectWriter {
+    fn sync_all(&self) -> io::Result<()> {
+        // Writes reach the device directly, but a tail not flushed yet
+        // still has to be written, from a copy as `self` is shared
+        if !self.tail_written {
+            let mut tail = AlignedBuffer::with_capacity(self.buffer.len());
+            tail.extend_from_slice(self.buffer.as_slice());
+            self.file.write_all_at(tail.padded(), self.buffer_offset)?;
+            self.file.set_len(self.len())?;
+        }
+        self.file.sync_all()
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len())
+    }
+
+    fn set_len(&self, _size: u64) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Direct writers can't change their length",
+        ))
+    }
+}
+
+impl Drop for DirectWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_tail() {
+            log::warn!("Failed to write the end of a direct I/O file: {}", e);
+        }
+    }
+}
+
+/// Returns where a seek from `current` in a file of `size` bytes lands
+fn seek_position(pos: SeekFrom, current: u64, size: u64) -> io::Result<u64> {
+    let target = match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(delta) => size.checked_add_signed(delta),
+        SeekFrom::Current(delta) => current.checked_add_signed(delta),
+    };
+    target.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Seek to a negative or overflowing position",
+        )
+    })
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "File was opened for direct reads only",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Bytes that differ at every offset, to catch misplaced blocks
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_direct_files_round_trip_unaligned_sizes() {
+        let dir = TempDir::new().unwrap();
+        for len in [0, 1, 4095, 4097, WRITE_CHUNK_SIZE + 12_345] {
+            let path = dir.path().join(format!("file-{}", len));
+            let data = pattern(len);
+            {
+                let mut file = create(&path).unwrap();
+                for chunk in data.chunks(1000) {
+                    file.write_all(chunk).unwrap();
+                }
+                // Flushing midway rewrites the tail without corrupting it
+                file.flush().unwrap();
+                file.sync_all().unwrap();
+                assert_eq!(file.size().unwrap(), len as u64);
+                assert_eq!(file.stream_position().unwrap(), len as u64);
+            }
+            assert_eq!(std::fs::read(&path).unwrap(), data);
+
+            let mut file = open(&path).unwrap();
+            let mut read = Vec::new();
+            file.read_to_end(&mut read).unwrap();
+            assert_eq!(read, data);
+
+            if len > 5000 {
+                let mut middle = [0u8; 100];
+                file.seek(SeekFrom::Start(4000)).unwrap();
+                file.read_exact(&mut middle).unwrap();
+                assert_eq!(&middle[..], &data[4000..4100]);
+                file.seek(SeekFrom::End(-3)).unwrap();
+                let mut end = Vec::new();
+                file.read_to_end(&mut end).unwrap();
+                assert_eq!(end, &data[len - 3..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_continues_after_a_flushed_tail() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("appended");
+        let mut file = create(&path).unwrap();
+        file.write_all(b"first").unwrap();
+        file.sync_all().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        file.write_all(b" second").unwrap();
+        assert!(file.seek(SeekFrom::Start(0)).is_err());
+        drop(file);
+        assert_eq!(std::fs::read(&path).unwrap(), b"first second");
+    }
+}
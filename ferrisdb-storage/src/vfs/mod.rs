@@ -0,0 +1,591 @@
+//! Filesystem and clock abstraction
+//!
+//! Components that touch files, such as the WAL and SSTable readers and
+//! writers, can open them through a [`Vfs`] instead of `std::fs`. In
+//! production that is [`OsVfs`], a thin layer over the operating system.
+//! Tests can substitute a [`SimVfs`]: an in-memory filesystem that loses
+//! unsynced data when told to simulate a crash and injects I/O errors,
+//! with every random choice drawn from a seed so a failing run can be
+//! replayed exactly.
+//!
+//! An engine opened with [`Options::with_vfs`](crate::storage_engine::Options::with_vfs)
+//! does all of its file I/O through the given filesystem: the WAL, tables
+//! and MANIFESTs, and also checkpoints, backups taken with a
+//! [`BackupEngine`](crate::BackupEngine) on the same filesystem, and
+//! repair. That is the place for encryption at rest, quotas or a
+//! filesystem of one's own. The lock keeping a second engine out of the
+//! same directory is taken through it too, with
+//! [`try_lock_file`](Vfs::try_lock_file), so a filesystem wrapping the
+//! operating system's must pass that on for the lock to hold.
+//!
+//! A `Vfs` also provides the [`Clock`] its users read the time from, so a
+//! simulation controls time as well as storage.
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::vfs::{SimVfs, Vfs};
+//! use std::io::Write;
+//! use std::path::Path;
+//!
+//! let vfs = SimVfs::new(42);
+//! vfs.create_dir_all(Path::new("/db"))?;
+//!
+//! let mut file = vfs.create(Path::new("/db/synced"))?;
+//! file.write_all(b"durable")?;
+//! file.sync_all()?;
+//! vfs.sync_dir(Path::new("/db"))?;
+//! file.write_all(b" and lost")?;
+//!
+//! // Power loss: only what was synced is guaranteed to survive
+//! vfs.crash();
+//! let contents = vfs.contents(Path::new("/db/synced")).unwrap();
+//! assert!(contents.starts_with(b"durable"));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+#[cfg(target_os = "linux")]
+mod direct;
+mod sim;
+
+pub use sim::{Faults, SimVfs};
+
+use crate::clock::{Clock, SystemClock};
+
+use std::any::Any;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// An open file
+///
+/// Reads and writes start at the file's cursor and advance it, as they
+/// do for a `std::fs::File`.
+pub trait VfsFile: Read + Write + Seek + Send + Sync {
+    /// Makes everything written so far durable
+    fn sync_all(&self) -> io::Result<()>;
+
+    /// Returns the current size of the file
+    fn size(&self) -> io::Result<u64>;
+
+    /// Truncates or extends the file to `size` bytes
+    fn set_len(&self, size: u64) -> io::Result<()>;
+}
+
+/// A filesystem and clock
+///
+/// Creating, removing or renaming a file changes its directory, which
+/// only becomes durable once the directory is synced with
+/// [`sync_dir`](Self::sync_dir).
+///
+/// Paths are those the engine was configured with, joined with the names
+/// of its files and directories. An implementation may map them anywhere,
+/// but must report a missing file as `NotFound` and fail to
+/// [`read_dir`](Self::read_dir) anything that isn't a directory, which is
+/// how directories are told from files.
+pub trait Vfs: Send + Sync {
+    /// Opens an existing file for reading
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Creates a file for reading and writing, truncating any existing one
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Opens a file for reading and writing, creating it if it doesn't
+    /// exist and keeping its contents if it does
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Returns whether a file or directory exists at `path`
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns the size of the file at `path`
+    fn file_size(&self, path: &Path) -> io::Result<u64>;
+
+    /// Creates a directory and any missing parents
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns the paths of the entries in a directory, sorted
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Removes a file
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes a directory and everything in it
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Renames a file, replacing any file at `to`
+    ///
+    /// Directories are renamed too, with everything in them, to a path
+    /// that doesn't exist; backups stage their files in one.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Makes `to` a second name of the file at `from`, failing with
+    /// `AlreadyExists` if there is a file at `to`
+    ///
+    /// Checkpoints link tables where they can, and copy them where this
+    /// fails, as it does by default.
+    fn hard_link(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Hard links are not supported",
+        ))
+    }
+
+    /// Makes the creation, removal and renaming of entries in `dir` durable
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+
+    /// Takes an exclusive lock on the file at `path`, creating the file if
+    /// it doesn't exist, held until the returned [`FileLock`] is dropped
+    ///
+    /// Engines lock their directories with it, so it must keep out every
+    /// other engine that could open the same files, in this process or
+    /// another; a filesystem wrapping another should delegate to it. Fails
+    /// with `WouldBlock` if the file is locked already.
+    fn try_lock_file(&self, path: &Path) -> io::Result<FileLock>;
+
+    /// Returns the source of the current time
+    fn clock(&self) -> &dyn Clock;
+}
+
+/// A lock from [`Vfs::try_lock_file`], released when dropped
+pub struct FileLock {
+    _guard: Box<dyn Any + Send + Sync>,
+}
+
+impl FileLock {
+    /// Wraps `guard`, whatever holds the lock until it is dropped, such as
+    /// the locked file
+    pub fn new(guard: impl Any + Send + Sync) -> Self {
+        Self {
+            _guard: Box::new(guard),
+        }
+    }
+}
+
+impl fmt::Debug for FileLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileLock").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for dyn Vfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vfs").finish_non_exhaustive()
+    }
+}
+
+/// Returns the operating system's filesystem, shared by the process
+pub fn os() -> Arc<dyn Vfs> {
+    static OS: OnceLock<Arc<dyn Vfs>> = OnceLock::new();
+    Arc::clone(OS.get_or_init(|| Arc::new(OsVfs::default())))
+}
+
+/// Returns the operating system's filesystem with direct I/O, shared by
+/// the process
+///
+/// See [`OsVfs::with_direct_io`].
+pub fn os_direct() -> Arc<dyn Vfs> {
+    static OS_DIRECT: OnceLock<Arc<dyn Vfs>> = OnceLock::new();
+    Arc::clone(OS_DIRECT.get_or_init(|| Arc::new(OsVfs::with_direct_io())))
+}
+
+/// Returns the operating system's filesystem with write-through files,
+/// shared by the process
+///
+/// See [`OsVfs::with_write_through`].
+pub fn os_write_through() -> Arc<dyn Vfs> {
+    static OS_WRITE_THROUGH: OnceLock<Arc<dyn Vfs>> = OnceLock::new();
+    Arc::clone(OS_WRITE_THROUGH.get_or_init(|| Arc::new(OsVfs::with_write_through())))
+}
+
+/// Returns the filesystem to open files through, with direct I/O if
+/// `direct_io` is set
+///
+/// Only the operating system's filesystem has a direct I/O mode; any
+/// other `vfs` is returned as it is.
+pub fn with_direct_io(vfs: &Arc<dyn Vfs>, direct_io: bool) -> Arc<dyn Vfs> {
+    if direct_io && is_os(vfs) {
+        os_direct()
+    } else {
+        Arc::clone(vfs)
+    }
+}
+
+/// Returns the filesystem to append to files through, writing through
+/// to the disk if `write_through` is set
+///
+/// Only the operating system's filesystem has a write-through mode; any
+/// other `vfs` is returned as it is.
+pub fn with_write_through(vfs: &Arc<dyn Vfs>, write_through: bool) -> Arc<dyn Vfs> {
+    if write_through && is_os(vfs) {
+        os_write_through()
+    } else {
+        Arc::clone(vfs)
+    }
+}
+
+/// Returns true if `vfs` is the operating system's filesystem, in any of
+/// its modes
+pub fn is_os(vfs: &Arc<dyn Vfs>) -> bool {
+    [os(), os_direct(), os_write_through()]
+        .iter()
+        .any(|os| std::ptr::addr_eq(Arc::as_ptr(os), Arc::as_ptr(vfs)))
+}
+
+/// Returns true if `a` and `b` are the same filesystem
+///
+/// The operating system's filesystem is the same in all of its modes.
+pub fn is_same(a: &Arc<dyn Vfs>, b: &Arc<dyn Vfs>) -> bool {
+    std::ptr::addr_eq(Arc::as_ptr(a), Arc::as_ptr(b)) || (is_os(a) && is_os(b))
+}
+
+/// Links the file at `from` to `to`, or copies it where it can't be
+/// linked, and syncs the copy
+///
+/// # Errors
+///
+/// Returns an error of kind `AlreadyExists` if there is a file at `to`,
+/// or the error of copying the file.
+pub fn link_or_copy(vfs: &dyn Vfs, from: &Path, to: &Path) -> io::Result<()> {
+    match vfs.hard_link(from, to) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            if vfs.exists(to) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", to.display()),
+                ));
+            }
+            copy(vfs, from, to).map(drop)
+        }
+        result => result,
+    }
+}
+
+/// Copies the file at `from` to `to`, replacing any file there, and
+/// syncs the copy
+pub fn copy(vfs: &dyn Vfs, from: &Path, to: &Path) -> io::Result<u64> {
+    let mut source = vfs.open(from)?;
+    let mut target = vfs.create(to)?;
+    let copied = io::copy(&mut source, &mut target)?;
+    target.flush()?;
+    target.sync_all()?;
+    Ok(copied)
+}
+
+/// Syncs the directory holding `path`, making its entry durable
+pub fn sync_parent_dir(vfs: &dyn Vfs, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => vfs.sync_dir(parent),
+        _ => vfs.sync_dir(Path::new(".")),
+    }
+}
+
+/// The operating system's filesystem and wall clock
+///
+/// On Windows, directories can't be synced, as NTFS makes their entries
+/// durable by itself, and renames and removals are retried for a moment
+/// while another program holds the file open.
+#[derive(Debug, Default)]
+pub struct OsVfs {
+    clock: SystemClock,
+    /// Whether `open` and `create` bypass the page cache
+    direct_io: bool,
+    /// Whether `open_or_create` opens files writing through to the disk
+    write_through: bool,
+}
+
+impl OsVfs {
+    /// Creates a filesystem whose [`open`](Vfs::open)ed and
+    /// [`create`](Vfs::create)d files bypass the OS page cache
+    ///
+    /// Such files are read-only or append-only: opened files can't be
+    /// written, and created ones can't be read or seeked back. Files from
+    /// [`open_or_create`](Vfs::open_or_create) are unaffected. Direct I/O
+    /// is used on Linux, where filesystems support it; elsewhere files are
+    /// opened normally.
+    pub fn with_direct_io() -> Self {
+        Self {
+            direct_io: true,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a filesystem whose files from
+    /// [`open_or_create`](Vfs::open_or_create), such as WAL segments, write
+    /// through to the disk
+    ///
+    /// On Windows, files are opened with `FILE_FLAG_WRITE_THROUGH`, so each
+    /// write reaches the disk before returning and syncing has little left
+    /// to flush. Elsewhere files are opened normally, and syncing alone
+    /// makes writes durable.
+    pub fn with_write_through() -> Self {
+        Self {
+            write_through: true,
+            ..Self::default()
+        }
+    }
+}
+
+impl VfsFile for File {
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        File::set_len(self, size)
+    }
+}
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        #[cfg(target_os = "linux")]
+        if self.direct_io {
+            return direct::open(path);
+        }
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        #[cfg(target_os = "linux")]
+        if self.direct_io {
+            return direct::create(path);
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        if self.write_through {
+            set_write_through(&mut options);
+        }
+        Ok(Box::new(options.open(path)?))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        retry_while_open(|| fs::remove_file(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        retry_while_open(|| fs::rename(from, to))
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::hard_link(from, to)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        // Directories can't be opened for syncing on every platform
+        if cfg!(unix) {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn try_lock_file(&self, path: &Path) -> io::Result<FileLock> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        // Called through the trait, as newer toolchains have an inherent
+        // `File::try_lock` that the supported 1.81 lacks. The lock is tied
+        // to the open file, so the OS releases it if the process dies.
+        fs4::FileExt::try_lock(&file)?;
+        Ok(FileLock::new(file))
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        &self.clock
+    }
+}
+
+/// Makes writes to files opened with `options` go through to the disk
+#[cfg(windows)]
+fn set_write_through(options: &mut OpenOptions) {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_FLAG_WRITE_THROUGH: u32 = 0x8000_0000;
+    options.custom_flags(FILE_FLAG_WRITE_THROUGH);
+}
+
+/// Leaves `options` as they are: only Windows has write-through files
+#[cfg(not(windows))]
+fn set_write_through(_options: &mut OpenOptions) {}
+
+/// Times a rename or removal is tried while the file is held open
+const OPEN_FILE_ATTEMPTS: u32 = 10;
+
+/// Runs a rename or removal, retrying on Windows while another program
+/// holds the file open
+///
+/// Windows refuses to rename or remove a file opened without
+/// `FILE_SHARE_DELETE`. The engine's own handles share deletion, but virus
+/// scanners, indexers and backup tools often don't, and only hold files
+/// briefly, so backing off for a moment lets WAL rotation and checkpoints
+/// go ahead.
+fn retry_while_open(mut operation: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(e) if attempt < OPEN_FILE_ATTEMPTS && is_held_open(&e) => {
+                std::thread::sleep(Duration::from_millis(10 * u64::from(attempt)));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns true for the errors Windows reports for a file another handle
+/// doesn't share: `ERROR_ACCESS_DENIED` and `ERROR_SHARING_VIOLATION`
+///
+/// Always false elsewhere, where these codes mean something else.
+pub(crate) fn is_held_open(e: &io::Error) -> bool {
+    cfg!(windows) && matches!(e.raw_os_error(), Some(5 | 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::SeekFrom;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_os_vfs_round_trips_files() {
+        let dir = TempDir::new().unwrap();
+        let vfs = os();
+        let path = dir.path().join("nested/file");
+
+        vfs.create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = vfs.create(&path).unwrap();
+        file.write_all(b"hello world").unwrap();
+        file.sync_all().unwrap();
+        vfs.sync_dir(path.parent().unwrap()).unwrap();
+        assert_eq!(file.size().unwrap(), 11);
+
+        // Reopening keeps the contents; truncation shortens them
+        let mut file = vfs.open_or_create(&path).unwrap();
+        file.set_len(5).unwrap();
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello");
+
+        let renamed = dir.path().join("nested/renamed");
+        vfs.rename(&path, &renamed).unwrap();
+        assert!(!vfs.exists(&path));
+        assert_eq!(vfs.file_size(&renamed).unwrap(), 5);
+        assert_eq!(
+            vfs.read_dir(renamed.parent().unwrap()).unwrap(),
+            vec![renamed.clone()]
+        );
+        vfs.remove_file(&renamed).unwrap();
+        assert!(vfs.open(&renamed).is_err());
+    }
+
+    #[test]
+    fn test_renames_retry_only_while_windows_reports_the_file_open() {
+        let mut calls = 0;
+        let result = retry_while_open(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from_raw_os_error(32))
+            } else {
+                Ok(())
+            }
+        });
+        if cfg!(windows) {
+            assert!(result.is_ok());
+            assert_eq!(calls, 3);
+        } else {
+            assert!(result.is_err());
+            assert_eq!(calls, 1);
+        }
+
+        // Other errors and lasting holds give up
+        let mut calls = 0;
+        let result = retry_while_open(|| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+        let mut calls = 0;
+        let _ = retry_while_open(|| {
+            calls += 1;
+            Err(io::Error::from_raw_os_error(5))
+        });
+        assert_eq!(calls, if cfg!(windows) { OPEN_FILE_ATTEMPTS } else { 1 });
+    }
+
+    #[test]
+    fn test_write_through_files_append_and_are_shared() {
+        let dir = TempDir::new().unwrap();
+        let vfs = with_write_through(&os(), true);
+        assert!(is_os(&vfs));
+        assert!(!std::ptr::addr_eq(Arc::as_ptr(&vfs), Arc::as_ptr(&os())));
+        let path = dir.path().join("000001.wal");
+
+        let mut writer = vfs.open_or_create(&path).unwrap();
+        writer.write_all(b"first").unwrap();
+        writer.sync_all().unwrap();
+        let mut writer = vfs.open_or_create(&path).unwrap();
+        writer.seek(SeekFrom::End(0)).unwrap();
+        writer.write_all(b" second").unwrap();
+
+        // Readers, such as followers tailing the WAL, open it meanwhile,
+        // and retiring the segment doesn't wait for them
+        let mut reader = os().open(&path).unwrap();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "first second");
+        let retired = dir.path().join("archived.wal");
+        vfs.rename(&path, &retired).unwrap();
+        vfs.remove_file(&retired).unwrap();
+        assert!(!vfs.exists(&retired));
+
+        // Other filesystems have no write-through mode
+        let sim: Arc<dyn Vfs> = Arc::new(SimVfs::new(1));
+        assert!(std::ptr::addr_eq(
+            Arc::as_ptr(&with_write_through(&sim, true)),
+            Arc::as_ptr(&sim)
+        ));
+    }
+}
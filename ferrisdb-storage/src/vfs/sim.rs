@@ -0,0 +1,788 @@
+//! In-memory filesystem for deterministic simulations
+//!
+//! [`SimVfs`] tracks, for every file, what has been written and what has
+//! been synced, and for every directory, which entries have been synced.
+//! [`crash`](SimVfs::crash) then simulates power loss: directories revert
+//! to their synced entries and each file keeps its synced contents plus a
+//! random prefix of what was written after, as a disk that persisted part
+//! of the unsynced writes would. Handles opened before the crash fail from
+//! then on, like those of a process that died.
+//!
+//! Every random choice, whether of a torn write or of an injected
+//! [`Faults`] error, comes from a generator seeded at construction, so a
+//! run with the same seed and the same operations behaves identically.
+//!
+//! Directories themselves are simplified: once created they exist, and
+//! survive crashes, without being synced, and once removed they are gone
+//! along with everything in them. File locks are held in memory and
+//! released by a crash, as a dead process's are.
+
+use super::{FileLock, Vfs, VfsFile};
+use crate::clock::{Clock, ManualClock};
+
+use parking_lot::{Mutex, MutexGuard};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time the clock of a new [`SimVfs`] shows, since the Unix epoch
+const START_TIME: Duration = Duration::from_secs(1_700_000_000);
+
+/// OS error number of a full disk, `ENOSPC` on Unix and `ERROR_DISK_FULL`
+/// on Windows, so errors of the simulated disk are categorized as real ones
+#[cfg(unix)]
+const DISK_FULL_OS_ERROR: i32 = 28;
+#[cfg(not(unix))]
+const DISK_FULL_OS_ERROR: i32 = 112;
+
+/// I/O errors a [`SimVfs`] injects, none by default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Faults {
+    /// Probability that a write fails without writing anything
+    pub write_error: f64,
+    /// Probability that a sync fails, leaving the data unsynced
+    pub sync_error: f64,
+    /// Probability that a read fails without reading anything
+    pub read_error: f64,
+    /// Bytes all files together may hold; writes beyond fail as on a full
+    /// disk
+    pub capacity: Option<u64>,
+}
+
+/// Contents of a file, shared by its directory entries and open handles
+type SharedFile = Arc<Mutex<FileData>>;
+
+#[derive(Debug, Default)]
+struct FileData {
+    /// Contents as readers see them
+    data: Vec<u8>,
+    /// Contents as of the last sync, which a crash preserves
+    synced: Vec<u8>,
+}
+
+struct State {
+    /// Entries as they are now
+    files: BTreeMap<PathBuf, SharedFile>,
+    /// Entries as of the last sync of their directories
+    synced_files: BTreeMap<PathBuf, SharedFile>,
+    dirs: BTreeSet<PathBuf>,
+    /// Files locked with `try_lock_file`
+    locks: BTreeSet<PathBuf>,
+    faults: Faults,
+    rng: SimRng,
+    /// Number of crashes so far, which outdates older handles
+    epoch: u64,
+}
+
+impl State {
+    /// Returns a NotFound error unless the directory of `path` exists
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.contains(parent) => {
+                Err(not_found(parent))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves a directory and everything in it to `to`, which must not
+    /// exist, as if its parent had been synced since
+    fn rename_dir(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.dirs.contains(to) || self.files.contains_key(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        let moved = |path: &PathBuf| match path.strip_prefix(from) {
+            Ok(rest) if rest.as_os_str().is_empty() => to.to_path_buf(),
+            Ok(rest) => to.join(rest),
+            Err(_) => path.clone(),
+        };
+        self.dirs = self.dirs.iter().map(moved).collect();
+        for files in [&mut self.files, &mut self.synced_files] {
+            *files = std::mem::take(files)
+                .into_iter()
+                .map(|(path, file)| (moved(&path), file))
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Returns the bytes held by all files, counting shared files once
+    fn used_bytes(&self) -> u64 {
+        let mut seen = BTreeSet::new();
+        self.files
+            .values()
+            .chain(self.synced_files.values())
+            .filter(|file| seen.insert(Arc::as_ptr(file)))
+            .map(|file| file.lock().data.len() as u64)
+            .sum()
+    }
+}
+
+/// A seeded in-memory filesystem that simulates crashes and I/O errors
+pub struct SimVfs {
+    seed: u64,
+    state: Arc<Mutex<State>>,
+    clock: ManualClock,
+}
+
+impl SimVfs {
+    /// Creates an empty filesystem whose random choices derive from `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: Arc::new(Mutex::new(State {
+                files: BTreeMap::new(),
+                synced_files: BTreeMap::new(),
+                dirs: BTreeSet::new(),
+                locks: BTreeSet::new(),
+                faults: Faults::default(),
+                rng: SimRng(seed),
+                epoch: 0,
+            })),
+            clock: ManualClock::new(START_TIME),
+        }
+    }
+
+    /// Injects `faults` into the operations that follow
+    pub fn with_faults(self, faults: Faults) -> Self {
+        self.set_faults(faults);
+        self
+    }
+
+    /// Replaces the faults injected from now on
+    pub fn set_faults(&self, faults: Faults) {
+        self.state.lock().faults = faults;
+    }
+
+    /// Returns the seed the filesystem was created with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Moves the filesystem's clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// Returns the current contents of the file at `path`
+    pub fn contents(&self, path: &Path) -> Option<Vec<u8>> {
+        let state = self.state.lock();
+        state.files.get(path).map(|file| file.lock().data.clone())
+    }
+
+    /// Simulates power loss
+    ///
+    /// Entries created, removed or renamed since their directory was last
+    /// synced revert, and every file loses a random suffix of the data
+    /// written since it was last synced. Open handles fail from now on,
+    /// and file locks are released.
+    pub fn crash(&self) {
+        let mut state = self.state.lock();
+        let State {
+            files,
+            synced_files,
+            locks,
+            rng,
+            epoch,
+            ..
+        } = &mut *state;
+
+        *epoch += 1;
+        locks.clear();
+        for file in synced_files.values() {
+            let mut file = file.lock();
+            let FileData { data, synced } = &mut *file;
+            let unsynced = data.get(synced.len()..).unwrap_or_default();
+            let kept = rng.below(unsynced.len() as u64 + 1) as usize;
+            synced.extend_from_slice(&unsynced[..kept]);
+            data.clone_from(synced);
+        }
+        files.clone_from(synced_files);
+    }
+
+    /// Opens a handle on the file at `path`
+    fn open_with(
+        &self,
+        path: &Path,
+        writable: bool,
+        create: bool,
+        truncate: bool,
+    ) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = self.state.lock();
+        let file = match state.files.get(path) {
+            Some(file) => Arc::clone(file),
+            None if create => {
+                state.check_parent(path)?;
+                let file = SharedFile::default();
+                state.files.insert(path.to_path_buf(), Arc::clone(&file));
+                file
+            }
+            None => return Err(not_found(path)),
+        };
+        if truncate {
+            file.lock().data.clear();
+        }
+        Ok(Box::new(SimFile {
+            state: Arc::clone(&self.state),
+            file,
+            epoch: state.epoch,
+            position: 0,
+            writable,
+        }))
+    }
+}
+
+impl Vfs for SimVfs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.open_with(path, false, false, false)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.open_with(path, true, true, true)
+    }
+
+    fn open_or_create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.open_with(path, true, true, false)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock();
+        state.files.contains_key(path) || state.dirs.contains(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        let state = self.state.lock();
+        let file = state.files.get(path).ok_or_else(|| not_found(path))?;
+        let size = file.lock().data.len() as u64;
+        Ok(size)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            if state.files.contains_key(dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+            state.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state.lock();
+        if !state.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        let files = state.files.keys();
+        let mut entries: Vec<_> = files
+            .chain(&state.dirs)
+            .filter(|entry| entry.parent() == Some(path))
+            .cloned()
+            .collect();
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state
+            .files
+            .remove(path)
+            .map(drop)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if !state.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        state.files.retain(|file, _| !file.starts_with(path));
+        state.synced_files.retain(|file, _| !file.starts_with(path));
+        state.dirs.retain(|dir| !dir.starts_with(path));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check_parent(to)?;
+        if state.dirs.contains(from) {
+            return state.rename_dir(from, to);
+        }
+        let file = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check_parent(to)?;
+        if state.files.contains_key(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        let file = state.files.get(from).ok_or_else(|| not_found(from))?;
+        let file = Arc::clone(file);
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if !state.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        let State {
+            files,
+            synced_files,
+            ..
+        } = &mut *state;
+        synced_files.retain(|path, _| path.parent() != Some(dir));
+        for (path, file) in files.iter() {
+            if path.parent() == Some(dir) {
+                synced_files.insert(path.clone(), Arc::clone(file));
+            }
+        }
+        Ok(())
+    }
+
+    fn try_lock_file(&self, path: &Path) -> io::Result<FileLock> {
+        let mut state = self.state.lock();
+        if !state.files.contains_key(path) {
+            state.check_parent(path)?;
+            state
+                .files
+                .insert(path.to_path_buf(), SharedFile::default());
+        }
+        if !state.locks.insert(path.to_path_buf()) {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("{} is locked", path.display()),
+            ));
+        }
+        Ok(FileLock::new(SimLock {
+            state: Arc::clone(&self.state),
+            path: path.to_path_buf(),
+            epoch: state.epoch,
+        }))
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        &self.clock
+    }
+}
+
+impl fmt::Debug for SimVfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("SimVfs")
+            .field("seed", &self.seed)
+            .field("files", &state.files.len())
+            .field("crashes", &state.epoch)
+            .field("faults", &state.faults)
+            .finish()
+    }
+}
+
+/// A lock on a file of a [`SimVfs`], released when dropped
+struct SimLock {
+    state: Arc<Mutex<State>>,
+    path: PathBuf,
+    /// Crash count when the lock was taken
+    epoch: u64,
+}
+
+impl Drop for SimLock {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        // A crash released the lock, and someone else may hold it now
+        if state.epoch == self.epoch {
+            state.locks.remove(&self.path);
+        }
+    }
+}
+
+/// An open handle on a file of a [`SimVfs`]
+struct SimFile {
+    state: Arc<Mutex<State>>,
+    file: SharedFile,
+    /// Crash count when the handle was opened
+    epoch: u64,
+    position: u64,
+    writable: bool,
+}
+
+impl SimFile {
+    /// Locks the filesystem for an operation, failing it if the handle
+    /// predates a crash or the fault chosen by `probability` strikes
+    fn begin<'a>(
+        state: &'a Mutex<State>,
+        epoch: u64,
+        operation: &str,
+        probability: fn(&Faults) -> f64,
+    ) -> io::Result<MutexGuard<'a, State>> {
+        let mut state = state.lock();
+        if state.epoch != epoch {
+            return Err(io::Error::other("file handle used after a simulated crash"));
+        }
+        let probability = probability(&state.faults);
+        if state.rng.chance(probability) {
+            return Err(io::Error::other(format!("injected {} error", operation)));
+        }
+        Ok(state)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file not opened for writing",
+            ))
+        }
+    }
+}
+
+impl Read for SimFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let _state = Self::begin(&self.state, self.epoch, "read", |faults| faults.read_error)?;
+        let file = self.file.lock();
+        let start = (self.position as usize).min(file.data.len());
+        let n = buf.len().min(file.data.len() - start);
+        buf[..n].copy_from_slice(&file.data[start..start + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for SimFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        let state = Self::begin(&self.state, self.epoch, "write", |faults| {
+            faults.write_error
+        })?;
+        let start = self.position as usize;
+        let end = start + buf.len();
+        if let Some(capacity) = state.faults.capacity {
+            let growth = end.saturating_sub(self.file.lock().data.len()) as u64;
+            if state.used_bytes() + growth > capacity {
+                return Err(io::Error::from_raw_os_error(DISK_FULL_OS_ERROR));
+            }
+        }
+
+        let mut file = self.file.lock();
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[start..end].copy_from_slice(buf);
+        self.position = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SimFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => (self.file.lock().data.len() as u64, offset),
+        };
+        self.position = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl VfsFile for SimFile {
+    fn sync_all(&self) -> io::Result<()> {
+        let _state = Self::begin(&self.state, self.epoch, "sync", |faults| faults.sync_error)?;
+        let mut file = self.file.lock();
+        let FileData { data, synced } = &mut *file;
+        synced.clone_from(data);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let _state = Self::begin(&self.state, self.epoch, "stat", |_| 0.0)?;
+        let size = self.file.lock().data.len() as u64;
+        Ok(size)
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        self.check_writable()?;
+        let _state = Self::begin(&self.state, self.epoch, "write", |faults| {
+            faults.write_error
+        })?;
+        self.file.lock().data.resize(size as usize, 0);
+        Ok(())
+    }
+}
+
+/// SplitMix64, chosen for producing the same sequence on every platform
+/// and version, unlike the generators of the `rand` crate
+struct SimRng(u64);
+
+impl SimRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value below `bound`, which must be positive
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// Returns true with the given probability
+    ///
+    /// Draws nothing for a probability of zero, so disabled faults don't
+    /// shift the choices made for crashes.
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        unit < probability
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_core::error::ErrorCode;
+    use ferrisdb_core::Error;
+
+    fn write_file(vfs: &SimVfs, path: &str, bytes: &[u8], sync: bool) -> Box<dyn VfsFile> {
+        let mut file = vfs.open_or_create(Path::new(path)).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(bytes).unwrap();
+        if sync {
+            file.sync_all().unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn test_crash_keeps_only_synced_state_for_sure() {
+        let vfs = SimVfs::new(1);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+
+        write_file(&vfs, "/db/synced", b"header", true);
+        vfs.sync_dir(Path::new("/db")).unwrap();
+        let _file = write_file(&vfs, "/db/synced", b" and a torn tail", false);
+        write_file(&vfs, "/db/unlinked", b"data", true);
+
+        vfs.crash();
+        let contents = vfs.contents(Path::new("/db/synced")).unwrap();
+        assert!(b"header and a torn tail".starts_with(&contents));
+        assert!(contents.starts_with(b"header"));
+        // Synced contents don't help a file whose entry was never synced
+        assert!(!vfs.exists(Path::new("/db/unlinked")));
+        assert_eq!(
+            vfs.read_dir(Path::new("/db")).unwrap(),
+            vec![PathBuf::from("/db/synced")]
+        );
+    }
+
+    #[test]
+    fn test_crashes_are_deterministic_per_seed() {
+        let run = |seed| {
+            let vfs = SimVfs::new(seed);
+            vfs.create_dir_all(Path::new("/db")).unwrap();
+            write_file(&vfs, "/db/log", b"", true);
+            vfs.sync_dir(Path::new("/db")).unwrap();
+            write_file(&vfs, "/db/log", &[7; 1000], false);
+            vfs.crash();
+            vfs.contents(Path::new("/db/log")).unwrap().len()
+        };
+
+        let lengths: Vec<_> = (0..8).map(run).collect();
+        assert_eq!(lengths, (0..8).map(run).collect::<Vec<_>>());
+        assert!(lengths.iter().any(|&len| len != lengths[0]));
+    }
+
+    #[test]
+    fn test_file_locks_are_exclusive_until_dropped_or_crashed() {
+        let vfs = SimVfs::new(9);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+        let lock = vfs.try_lock_file(Path::new("/db/LOCK")).unwrap();
+        assert!(vfs.exists(Path::new("/db/LOCK")));
+        let error = vfs.try_lock_file(Path::new("/db/LOCK")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        drop(lock);
+
+        // A crash releases the lock, and the stale guard can't take back
+        // a lock taken since
+        let stale = vfs.try_lock_file(Path::new("/db/LOCK")).unwrap();
+        vfs.crash();
+        let lock = vfs.try_lock_file(Path::new("/db/LOCK")).unwrap();
+        drop(stale);
+        assert!(vfs.try_lock_file(Path::new("/db/LOCK")).is_err());
+        drop(lock);
+    }
+
+    #[test]
+    fn test_handles_fail_after_crash() {
+        let vfs = SimVfs::new(2);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+        let mut file = write_file(&vfs, "/db/file", b"data", true);
+        vfs.sync_dir(Path::new("/db")).unwrap();
+
+        vfs.crash();
+        assert!(file.write_all(b"more").is_err());
+        assert!(file.sync_all().is_err());
+        assert_eq!(vfs.contents(Path::new("/db/file")).unwrap(), b"data");
+
+        // Reopening works as after a restart
+        let mut file = vfs.open(Path::new("/db/file")).unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"data");
+        assert!(file.write_all(b"read-only").is_err());
+    }
+
+    #[test]
+    fn test_injected_faults_fail_operations() {
+        let vfs = SimVfs::new(3);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+        let mut file = write_file(&vfs, "/db/file", b"synced", true);
+        vfs.sync_dir(Path::new("/db")).unwrap();
+
+        vfs.set_faults(Faults {
+            write_error: 1.0,
+            ..Faults::default()
+        });
+        assert!(file.write_all(b"never written").is_err());
+        assert_eq!(vfs.contents(Path::new("/db/file")).unwrap(), b"synced");
+
+        // A failed sync leaves the data at the mercy of the next crash
+        vfs.set_faults(Faults {
+            sync_error: 1.0,
+            ..Faults::default()
+        });
+        file.write_all(b" unsynced").unwrap();
+        assert!(file.sync_all().is_err());
+
+        vfs.set_faults(Faults {
+            capacity: Some(20),
+            ..Faults::default()
+        });
+        let error = file.write_all(&[0; 10]).unwrap_err();
+        assert_eq!(Error::from(error).code(), ErrorCode::DiskFull);
+        file.write_all(&[0; 5]).unwrap();
+    }
+
+    #[test]
+    fn test_renames_and_removals_revert_until_dir_synced() {
+        let vfs = SimVfs::new(4);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+        write_file(&vfs, "/db/a", b"a", true);
+        write_file(&vfs, "/db/b", b"b", true);
+        vfs.sync_dir(Path::new("/db")).unwrap();
+
+        vfs.rename(Path::new("/db/a"), Path::new("/db/c")).unwrap();
+        vfs.remove_file(Path::new("/db/b")).unwrap();
+        vfs.crash();
+        assert!(vfs.exists(Path::new("/db/a")));
+        assert!(vfs.exists(Path::new("/db/b")));
+        assert!(!vfs.exists(Path::new("/db/c")));
+
+        vfs.rename(Path::new("/db/a"), Path::new("/db/c")).unwrap();
+        vfs.remove_file(Path::new("/db/b")).unwrap();
+        vfs.sync_dir(Path::new("/db")).unwrap();
+        vfs.crash();
+        assert_eq!(
+            vfs.read_dir(Path::new("/db")).unwrap(),
+            vec![PathBuf::from("/db/c")]
+        );
+        assert_eq!(vfs.contents(Path::new("/db/c")).unwrap(), b"a");
+    }
+
+    #[test]
+    fn test_remove_dir_all_removes_everything_below_for_good() {
+        let vfs = SimVfs::new(5);
+        vfs.create_dir_all(Path::new("/db/cf-1/nested")).unwrap();
+        write_file(&vfs, "/db/cf-1/a", b"a", true);
+        write_file(&vfs, "/db/cf-1/nested/b", b"b", true);
+        write_file(&vfs, "/db/cf-10", b"kept", true);
+        vfs.sync_dir(Path::new("/db")).unwrap();
+        vfs.sync_dir(Path::new("/db/cf-1")).unwrap();
+
+        vfs.remove_dir_all(Path::new("/db/cf-1")).unwrap();
+        vfs.crash();
+        assert!(!vfs.exists(Path::new("/db/cf-1")));
+        assert!(!vfs.exists(Path::new("/db/cf-1/a")));
+        assert!(vfs.exists(Path::new("/db/cf-10")));
+        assert_eq!(
+            vfs.remove_dir_all(Path::new("/db/cf-1"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_directories_rename_with_their_contents() {
+        let vfs = SimVfs::new(6);
+        vfs.create_dir_all(Path::new("/db/tmp/wal")).unwrap();
+        write_file(&vfs, "/db/tmp/a", b"a", true);
+        write_file(&vfs, "/db/tmp/wal/b", b"b", true);
+        write_file(&vfs, "/db/tmp-other", b"kept", true);
+        vfs.hard_link(Path::new("/db/tmp/a"), Path::new("/db/link"))
+            .unwrap();
+
+        vfs.rename(Path::new("/db/tmp"), Path::new("/db/1"))
+            .unwrap();
+        assert!(!vfs.exists(Path::new("/db/tmp")));
+        assert_eq!(vfs.contents(Path::new("/db/1/a")).unwrap(), b"a");
+        assert_eq!(vfs.contents(Path::new("/db/1/wal/b")).unwrap(), b"b");
+        assert_eq!(vfs.contents(Path::new("/db/link")).unwrap(), b"a");
+        assert!(vfs.exists(Path::new("/db/tmp-other")));
+        assert_eq!(
+            vfs.rename(Path::new("/db/1/wal"), Path::new("/db/1"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            vfs.hard_link(Path::new("/db/1/a"), Path::new("/db/link"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+    }
+}
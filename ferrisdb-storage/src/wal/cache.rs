@@ -0,0 +1,222 @@
+use super::WALEntry;
+use crate::utils::cache::CacheStats;
+use ferrisdb_core::Timestamp;
+
+use parking_lot::Mutex;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bytes a cached entry is charged beyond its key and value
+const ENTRY_OVERHEAD: usize = 48;
+
+/// The most recently appended records of a WAL, kept in memory
+///
+/// The engine adds each record to the cache as it appends it to the WAL,
+/// and evicts the oldest once they take more than the capacity. A
+/// [`WALTailer`](super::WALTailer) given the cache reads the records it
+/// holds from memory rather than from the file the writer just wrote,
+/// and goes back to the files when it lags behind what the cache holds.
+///
+/// The cache always holds every record after some timestamp: records only
+/// leave from the front, so a tailer past the newest evicted record finds
+/// everything it hasn't returned yet in memory.
+pub struct WALCache {
+    /// Bytes the records may take together
+    capacity: usize,
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+struct State {
+    /// Records in the order they were appended
+    records: VecDeque<Record>,
+    /// Bytes charged for the records
+    size: usize,
+    /// Timestamp after which every record is held
+    complete_after: Timestamp,
+}
+
+struct Record {
+    /// Number of the WAL segment the record was appended to
+    segment: u64,
+    entries: Vec<WALEntry>,
+    /// Bytes charged for the record
+    size: usize,
+}
+
+/// What the cache holds from a timestamp on
+pub(super) enum Lookup {
+    /// The first record with entries from the timestamp on, with the
+    /// number of its segment
+    Record(u64, Vec<WALEntry>),
+    /// No record has entries from the timestamp on yet
+    CaughtUp,
+    /// Records from the timestamp on were evicted, or appended before the
+    /// cache was created
+    Evicted,
+}
+
+impl WALCache {
+    /// Creates a cache of up to `capacity` bytes of records, holding every
+    /// record appended after timestamp `last`
+    pub fn new(capacity: usize, last: Timestamp) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State {
+                records: VecDeque::new(),
+                size: 0,
+                complete_after: last,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the bytes the records may take together
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the bytes the records take now
+    pub fn size(&self) -> usize {
+        self.state.lock().size
+    }
+
+    /// Adds the entries of a record appended to WAL segment `segment`
+    ///
+    /// Records must be added in the order they were appended, each after
+    /// it is written, evicting the oldest records to stay within capacity.
+    pub fn insert(&self, segment: u64, entries: &[WALEntry]) {
+        let Some(last) = entries.last() else {
+            return;
+        };
+        let size = entries
+            .iter()
+            .map(|entry| entry.key.len() + entry.value.len() + ENTRY_OVERHEAD)
+            .sum::<usize>();
+
+        let mut state = self.state.lock();
+        if size > self.capacity {
+            // Holding nothing keeps the records contiguous
+            self.evictions
+                .fetch_add(state.records.len() as u64 + 1, Ordering::Relaxed);
+            state.records.clear();
+            state.size = 0;
+            state.complete_after = last.timestamp;
+            return;
+        }
+        state.records.push_back(Record {
+            segment,
+            entries: entries.to_vec(),
+            size,
+        });
+        state.size += size;
+        while state.size > self.capacity {
+            let evicted = state.records.pop_front().expect("records take space");
+            state.size -= evicted.size;
+            state.complete_after = evicted.last_timestamp();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the counters of records read from the cache (hits), reads
+    /// that had to go to the files (misses) and records evicted
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the first record with entries from timestamp `next` on,
+    /// keeping only those entries
+    pub(super) fn read(&self, next: Timestamp) -> Lookup {
+        let state = self.state.lock();
+        if next <= state.complete_after {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Lookup::Evicted;
+        }
+        // Records are in timestamp order
+        let first = state
+            .records
+            .partition_point(|record| record.last_timestamp() < next);
+        let Some(record) = state.records.get(first) else {
+            return Lookup::CaughtUp;
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        let entries = record
+            .entries
+            .iter()
+            .filter(|entry| entry.timestamp >= next)
+            .cloned()
+            .collect();
+        Lookup::Record(record.segment, entries)
+    }
+}
+
+impl Record {
+    fn last_timestamp(&self) -> Timestamp {
+        self.entries.last().map_or(0, |entry| entry.timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(timestamp: Timestamp) -> WALEntry {
+        WALEntry::new_put(b"key".to_vec(), vec![b'v'; 100], timestamp).unwrap()
+    }
+
+    fn timestamps(lookup: Lookup) -> Option<(u64, Vec<Timestamp>)> {
+        match lookup {
+            Lookup::Record(segment, entries) => Some((
+                segment,
+                entries.iter().map(|entry| entry.timestamp).collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_records_are_read_from_a_timestamp_on() {
+        let cache = WALCache::new(1024 * 1024, 10);
+        assert!(matches!(cache.read(11), Lookup::CaughtUp));
+        cache.insert(1, &[put(11), put(12), put(13)]);
+        cache.insert(2, &[put(15)]);
+
+        assert!(matches!(cache.read(10), Lookup::Evicted));
+        assert_eq!(timestamps(cache.read(11)), Some((1, vec![11, 12, 13])));
+        // The rest of a batch, then the next record past the gap
+        assert_eq!(timestamps(cache.read(13)), Some((1, vec![13])));
+        assert_eq!(timestamps(cache.read(14)), Some((2, vec![15])));
+        assert!(matches!(cache.read(16), Lookup::CaughtUp));
+        assert_eq!(cache.stats().hits, 3);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_oldest_records_are_evicted_past_capacity() {
+        let record_size = 3 + 100 + ENTRY_OVERHEAD;
+        let cache = WALCache::new(2 * record_size, 0);
+        for timestamp in 1..=4 {
+            cache.insert(1, &[put(timestamp)]);
+        }
+        assert_eq!(cache.size(), 2 * record_size);
+        assert_eq!(cache.stats().evictions, 2);
+        assert!(matches!(cache.read(2), Lookup::Evicted));
+        assert_eq!(timestamps(cache.read(3)), Some((1, vec![3])));
+
+        // A record larger than the cache empties it
+        let batch: Vec<_> = (5..=7).map(put).collect();
+        cache.insert(2, &batch);
+        assert_eq!(cache.size(), 0);
+        assert!(matches!(cache.read(7), Lookup::Evicted));
+        assert!(matches!(cache.read(8), Lookup::CaughtUp));
+    }
+}
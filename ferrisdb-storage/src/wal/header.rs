@@ -4,7 +4,7 @@
 //! WAL file. It provides file identification, versioning, and integrity checking.
 
 use crate::format::{ChecksummedHeader, FileFormat, FileHeader, FileMetadata, ValidateFile};
-use ferrisdb_core::{Error, Result};
+use ferrisdb_core::{Error, Result, Timestamp};
 
 use crc32fast::Hasher;
 
@@ -14,8 +14,20 @@ use std::time::{SystemTime, UNIX_EPOCH};
 /// Format: "FDB_WAL\0" (7 chars + null terminator)
 pub const WAL_MAGIC: &[u8; 8] = b"FDB_WAL\0";
 
-/// Current WAL format version (1.0)
-pub const WAL_CURRENT_VERSION: u16 = 0x0100;
+/// Current WAL format version (2.1)
+pub const WAL_CURRENT_VERSION: u16 = 0x0201;
+
+/// First WAL format version storing entry lengths as varints (2.0)
+///
+/// Version 1.x stored them as fixed 4-byte integers; readers decode
+/// entries according to the version of their segment.
+pub const WAL_VARINT_VERSION: u16 = 0x0200;
+
+/// First WAL format version whose segments may end in a seal record (2.1)
+///
+/// A writer closed cleanly appends the seal, and readers of a sealed
+/// segment skip the checksums of the records before it.
+pub const WAL_SEAL_VERSION: u16 = 0x0201;
 
 /// Size of WAL header in bytes
 pub const WAL_HEADER_SIZE: usize = 64;
@@ -33,14 +45,15 @@ pub const WAL_HEADER_SIZE: usize = 64;
 /// ```text
 /// struct WALHeader {
 ///     magic: [u8; 8],           // offset 0:  "FDB_WAL\0"
-///     version: u16,             // offset 8:  0x0100 (v1.0)
+///     version: u16,             // offset 8:  0x0201 (v2.1)
 ///     flags: u16,               // offset 10: 0x0000 (reserved)
 ///     header_size: u32,         // offset 12: 64
 ///     header_checksum: u32,     // offset 16: CRC32 of bytes 0-15,20-63
 ///     entry_start_offset: u32,  // offset 20: 64
 ///     created_at: u64,          // offset 24: microseconds since epoch
 ///     file_sequence: u64,       // offset 32: unique file ID
-///     reserved: [u8; 24],       // offset 40: zeros (future use)
+///     previous_timestamp: u64,  // offset 40: last write before the file
+///     reserved: [u8; 16],       // offset 48: zeros (future use)
 /// }  // Total: 64 bytes
 /// ```
 ///
@@ -50,7 +63,9 @@ pub const WAL_HEADER_SIZE: usize = 64;
 /// - `MM` = major version (incompatible changes)
 /// - `mm` = minor version (compatible changes)
 ///
-/// Current version: 0x0100 (v1.0)
+/// Current version: 0x0201 (v2.1). The header layout is the same in
+/// every version; 2.0 changed how entries encode their lengths and 2.1
+/// added the seal record a cleanly closed segment ends with.
 ///
 /// ## Checksum Calculation
 ///
@@ -65,18 +80,25 @@ pub struct WALHeader {
     pub version: u16,
     /// Feature flags (currently unused, must be 0)
     pub flags: u16,
-    /// Total size of header (64 for v1.0)
+    /// Total size of header (64 in every version)
     pub header_size: u32,
     /// CRC32 checksum of header (excluding this field)
     pub header_checksum: u32,
-    /// Offset where entries begin (64 for v1.0)
+    /// Offset where entries begin (64 in every version)
     pub entry_start_offset: u32,
     /// Creation timestamp in microseconds since Unix epoch
     pub created_at: u64,
     /// Unique sequence number for this file
     pub file_sequence: u64,
+    /// Timestamp of the last write logged before this file was started,
+    /// or 0 if unknown
+    ///
+    /// Timestamps aren't consecutive, so this is what shows that no file
+    /// is missing between two segments: each one continues from the last
+    /// write of the one before it.
+    pub previous_timestamp: Timestamp,
     /// Reserved for future use (must be zero)
-    pub reserved: [u8; 24],
+    pub reserved: [u8; 16],
 }
 
 impl WALHeader {
@@ -91,20 +113,28 @@ impl WALHeader {
             entry_start_offset: WAL_HEADER_SIZE as u32,
             created_at: current_timestamp_micros(),
             file_sequence,
-            reserved: [0; 24],
+            previous_timestamp: 0,
+            reserved: [0; 16],
         };
 
         // Calculate and set checksum
         header.header_checksum = header.calculate_checksum();
         header
     }
+
+    /// Records the timestamp of the last write before this file
+    pub fn with_previous_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.previous_timestamp = timestamp;
+        self.header_checksum = self.calculate_checksum();
+        self
+    }
 }
 
 impl FileFormat for WALHeader {
     const MAGIC: &'static [u8; 8] = WAL_MAGIC;
     const FORMAT_NAME: &'static str = "WAL";
     const CURRENT_VERSION: u16 = WAL_CURRENT_VERSION;
-    const MIN_SUPPORTED_VERSION: u16 = 0x0100; // v1.0
+    const MIN_SUPPORTED_VERSION: u16 = 0x0100; // v1.0, fixed-width entry lengths
 }
 
 impl FileHeader for WALHeader {
@@ -122,7 +152,8 @@ impl FileHeader for WALHeader {
         buf[20..24].copy_from_slice(&self.entry_start_offset.to_le_bytes());
         buf[24..32].copy_from_slice(&self.created_at.to_le_bytes());
         buf[32..40].copy_from_slice(&self.file_sequence.to_le_bytes());
-        buf[40..64].copy_from_slice(&self.reserved);
+        buf[40..48].copy_from_slice(&self.previous_timestamp.to_le_bytes());
+        buf[48..64].copy_from_slice(&self.reserved);
 
         buf
     }
@@ -151,8 +182,12 @@ impl FileHeader for WALHeader {
             data[32], data[33], data[34], data[35], data[36], data[37], data[38], data[39],
         ]);
 
-        let mut reserved = [0u8; 24];
-        reserved.copy_from_slice(&data[40..64]);
+        let previous_timestamp = u64::from_le_bytes([
+            data[40], data[41], data[42], data[43], data[44], data[45], data[46], data[47],
+        ]);
+
+        let mut reserved = [0u8; 16];
+        reserved.copy_from_slice(&data[48..64]);
 
         let header = Self {
             magic,
@@ -163,6 +198,7 @@ impl FileHeader for WALHeader {
             entry_start_offset,
             created_at,
             file_sequence,
+            previous_timestamp,
             reserved,
         };
 
@@ -201,7 +237,7 @@ impl FileHeader for WALHeader {
             )));
         }
 
-        // Check flags (must be 0 for v1.0)
+        // Check flags (must be 0)
         if self.flags != 0 {
             return Err(Error::Corruption(format!(
                 "Invalid WAL flags: {:#x} (must be 0)",
@@ -239,6 +275,7 @@ impl ChecksummedHeader for WALHeader {
         hasher.update(&self.entry_start_offset.to_le_bytes());
         hasher.update(&self.created_at.to_le_bytes());
         hasher.update(&self.file_sequence.to_le_bytes());
+        hasher.update(&self.previous_timestamp.to_le_bytes());
         hasher.update(&self.reserved);
 
         hasher.finalize()
@@ -291,6 +328,22 @@ mod tests {
         assert_eq!(header, decoded);
     }
 
+    /// Tests that the previous timestamp is covered by the checksum.
+    ///
+    /// This test verifies that:
+    /// - The timestamp survives an encode/decode cycle
+    /// - Changing it without updating the checksum fails validation
+    #[test]
+    fn previous_timestamp_is_checksummed() {
+        let header = WALHeader::new(12345).with_previous_timestamp(77);
+        let decoded = WALHeader::decode(&header.encode()).unwrap();
+        assert_eq!(decoded.previous_timestamp, 77);
+
+        let mut tampered = header;
+        tampered.previous_timestamp = 78;
+        assert!(tampered.validate().is_err());
+    }
+
     /// Tests that header validation rejects incorrect magic numbers.
     ///
     /// This test verifies that:
@@ -337,7 +390,7 @@ mod tests {
     #[test]
     fn validate_returns_error_for_unsupported_version() {
         let mut header = WALHeader::new(12345);
-        header.version = 0x0200; // v2.0 - not supported
+        header.version = 0x0300; // v3.0 - not supported
 
         let result = header.validate();
         assert!(result.is_err());
@@ -1,3 +1,5 @@
+use super::header::{WAL_CURRENT_VERSION, WAL_VARINT_VERSION};
+use crate::utils::coding::{self, MAX_VARINT32_LEN};
 use ferrisdb_core::{Error, Key, Operation, Result, Timestamp, Value};
 
 use bytes::{Buf, BufMut, BytesMut};
@@ -8,17 +10,75 @@ use std::convert::TryFrom;
 // Constants for the binary format
 const OP_PUT: u8 = 1;
 const OP_DELETE: u8 = 2;
+const OP_MERGE: u8 = 3;
+const OP_BATCH: u8 = 4;
+const OP_SEAL: u8 = 5;
+const OP_PREPARE: u8 = 6;
+const OP_COMMIT: u8 = 7;
+const OP_ROLLBACK: u8 = 8;
+/// Set on the operation byte of entries outside the default column family
+const OP_COLUMN_FAMILY_FLAG: u8 = 0x80;
 const HEADER_SIZE: usize = 8; // length + checksum
-const MIN_ENTRY_SIZE: usize = HEADER_SIZE + 8 + 1 + 4 + 4; // header + timestamp + op + key_len + val_len
+const MIN_ENTRY_SIZE: usize = HEADER_SIZE + 8 + 1 + 1 + 1; // header + timestamp + op + key_len + val_len
+/// Largest encoding of everything but the key and value bytes
+const MAX_ENTRY_OVERHEAD: usize = HEADER_SIZE + 8 + 1 + 3 * MAX_VARINT32_LEN; // + column family
 
 // Size limits for DoS protection
 const MAX_KEY_SIZE: usize = 10 * 1024; // 10KB
 const MAX_VALUE_SIZE: usize = 100 * 1024; // 100KB
-pub const MAX_ENTRY_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + MIN_ENTRY_SIZE;
+pub const MAX_ENTRY_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + MAX_ENTRY_OVERHEAD;
+/// Maximum size of a batch record holding several entries
+pub(crate) const MAX_BATCH_SIZE: usize = 256 * 1024 * 1024; // 256MB
+const BATCH_HEADER_SIZE: usize = HEADER_SIZE + 8 + 1 + 1; // header + timestamp + op + count
+/// Size of the seal record ending a cleanly closed segment
+pub(crate) const SEAL_SIZE: usize = HEADER_SIZE + 8 + 1 + 8; // header + timestamp + op + sealed_len
+/// Maximum length of the name of a prepared transaction
+pub const MAX_TRANSACTION_NAME_SIZE: usize = 256;
+const TRANSACTION_HEADER_SIZE: usize = HEADER_SIZE + 8 + 1 + 1 + 1; // + name_len + count
+
+/// A step of a two-phase commit, as logged in the WAL
+///
+/// Preparing a transaction logs its writes without applying them, so they
+/// survive a crash while an external coordinator decides its outcome.
+/// Committing logs the writes again, now timestamped, and
+/// [`WALReader`](super::WALReader) returns them as entries like those of a
+/// batch; rolling back only logs the decision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionMarker {
+    /// A transaction's writes were made durable under `name`
+    ///
+    /// Timestamps are only assigned at commit, so the entries carry their
+    /// position in the transaction instead.
+    Prepare {
+        /// Name the transaction was prepared under
+        name: String,
+        /// The transaction's writes, in the order they were made
+        entries: Vec<WALEntry>,
+    },
+    /// The prepared transaction `name` was committed
+    Commit {
+        /// Name the transaction was prepared under
+        name: String,
+    },
+    /// The prepared transaction `name` was rolled back
+    Rollback {
+        /// Name the transaction was prepared under
+        name: String,
+    },
+}
+
+impl TransactionMarker {
+    /// Returns the name the transaction was prepared under
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Prepare { name, .. } | Self::Commit { name } | Self::Rollback { name } => name,
+        }
+    }
+}
 
 /// An entry in the Write-Ahead Log
 ///
-/// Each entry represents a single operation (Put or Delete) with its
+/// Each entry represents a single operation (Put, Delete or Merge) with its
 /// associated key, value, and timestamp. Entries are encoded in a binary
 /// format with checksums for corruption detection.
 ///
@@ -27,16 +87,24 @@ pub const MAX_ENTRY_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + MIN_ENTRY_SIZE
 /// ```text
 /// Offset  Size  Field         Description
 /// ------  ----  -----         -----------
-/// 0       4     length        Total entry size (including this field)
+/// 0       4     length        Entry size, excluding this field
 /// 4       4     checksum      CRC32 of all following fields
-/// 8       8     timestamp     Operation timestamp (microseconds)
-/// 16      1     operation     1=Put, 2=Delete
-/// 17      4     key_len       Key length in bytes
-/// 21      4     value_len     Value length in bytes (0 for Delete)
-/// 25      var   key           Key data
-/// 25+key  var   value         Value data (empty for Delete)
+/// 8       8     timestamp     Operation timestamp (hybrid logical clock)
+/// 16      1     operation     1=Put, 2=Delete, 3=Merge
+/// 17      1-5   key_len       Key length in bytes (varint)
+/// ...     var   key           Key data
+/// ...     1-5   value_len     Value length in bytes (varint, 0 for Delete)
+/// ...     var   value         Value data (empty for Delete)
 /// ```
 ///
+/// Entries for a column family other than the default set the high bit
+/// of `operation` and store the column family id as a varint right after
+/// it. Default column family entries keep the layout above.
+///
+/// Segments of WAL format version 1.x stored `key_len`, `value_len` and
+/// the column family id as fixed 4-byte integers; they are still decoded
+/// through [`decode_version`](Self::decode_version).
+///
 /// ## Size Limits
 ///
 /// - Maximum key size: 10 KB
@@ -52,7 +120,8 @@ pub const MAX_ENTRY_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + MIN_ENTRY_SIZE
 ///
 /// ## Encoding Details
 ///
-/// - All integers use little-endian format for x86/ARM compatibility
+/// - Fixed-width integers use little-endian format for x86/ARM compatibility
+/// - Lengths and column family ids are varints (see [`coding`](crate::utils::coding))
 /// - Length field does NOT include itself (entry size = length + 4)
 /// - Checksum covers all data after the checksum field
 /// - Delete operations have value_len=0 and empty value
@@ -60,12 +129,14 @@ pub const MAX_ENTRY_SIZE: usize = MAX_KEY_SIZE + MAX_VALUE_SIZE + MIN_ENTRY_SIZE
 pub struct WALEntry {
     /// Timestamp when this operation occurred
     pub timestamp: Timestamp,
-    /// Type of operation (Put or Delete)
+    /// Type of operation (Put, Delete or Merge)
     pub operation: Operation,
     /// The key being operated on
     pub key: Key,
-    /// The value (empty for Delete operations)
+    /// The value (empty for Delete operations, the operand for Merge)
     pub value: Value,
+    /// Column family the operation applies to (0 is the default)
+    pub column_family: u32,
 }
 
 impl WALEntry {
@@ -106,6 +177,7 @@ impl WALEntry {
             operation: Operation::Put,
             key,
             value,
+            column_family: 0,
         })
     }
 
@@ -136,24 +208,65 @@ impl WALEntry {
             operation: Operation::Delete,
             key,
             value: Vec::new(),
+            column_family: 0,
         })
     }
 
+    /// Creates a new Merge entry
+    ///
+    /// The operand is stored in the value field and combined with the
+    /// key's existing value by a [`MergeOperator`](crate::merge::MergeOperator)
+    /// when the key is read or compacted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::wal::WALEntry;
+    ///
+    /// let entry = WALEntry::new_merge(b"counter".to_vec(), 1u64.to_le_bytes().to_vec(), 12347)?;
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the key or operand exceeds size limits
+    pub fn new_merge(key: Key, operand: Value, timestamp: Timestamp) -> Result<Self> {
+        let mut entry = Self::new_put(key, operand, timestamp)?;
+        entry.operation = Operation::Merge;
+        Ok(entry)
+    }
+
+    /// Moves the entry to a column family
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ferrisdb_storage::wal::WALEntry;
+    ///
+    /// let entry = WALEntry::new_delete(b"idx:alice".to_vec(), 12348)?.with_column_family(1);
+    /// assert_eq!(entry.column_family, 1);
+    /// # Ok::<(), ferrisdb_core::Error>(())
+    /// ```
+    pub fn with_column_family(mut self, column_family: u32) -> Self {
+        self.column_family = column_family;
+        self
+    }
+
     /// Encodes the entry into binary format with checksum
     ///
     /// The encoded format is:
     /// ```text
-    /// [length:4][checksum:4][timestamp:8][op:1][key_len:4][val_len:4][key][value]
+    /// [length:4][checksum:4][timestamp:8][op:1][key_len:var][key][val_len:var][value]
     /// ```
     ///
     /// Where:
     /// - `length`: Total size of the encoded entry (excluding length field)
     /// - `checksum`: CRC32 of all fields after checksum
-    /// - `timestamp`: Microseconds since Unix epoch
-    /// - `op`: Operation type (1=Put, 2=Delete)
-    /// - `key_len`: Size of key in bytes
-    /// - `val_len`: Size of value in bytes (0 for Delete)
+    /// - `timestamp`: Hybrid logical clock timestamp
+    /// - `op`: Operation type (1=Put, 2=Delete, 3=Merge)
+    /// - `key_len`: Size of key in bytes, as a varint
     /// - `key`: Raw key bytes
+    /// - `val_len`: Size of value in bytes (0 for Delete), as a varint
     /// - `value`: Raw value bytes (empty for Delete)
     ///
     /// # Errors
@@ -180,35 +293,30 @@ impl WALEntry {
         }
 
         // Pre-calculate size for efficient allocation
-        let size = 4 + 4 + 8 + 1 + 4 + self.key.len() + 4 + self.value.len();
+        let size = MAX_ENTRY_OVERHEAD + self.key.len() + self.value.len();
         let mut buf = BytesMut::with_capacity(size);
 
         // Reserve space for length and checksum
-        buf.put_u32_le(0); // length placeholder
-        buf.put_u32_le(0); // checksum placeholder
+        coding::put_fixed32(&mut buf, 0); // length placeholder
+        coding::put_fixed32(&mut buf, 0); // checksum placeholder
 
         // Encode entry data
-        buf.put_u64_le(self.timestamp);
-        buf.put_u8(match self.operation {
+        coding::put_fixed64(&mut buf, self.timestamp);
+        let op = match self.operation {
             Operation::Put => OP_PUT,
             Operation::Delete => OP_DELETE,
-        });
+            Operation::Merge => OP_MERGE,
+        };
+        if self.column_family == 0 {
+            buf.put_u8(op);
+        } else {
+            buf.put_u8(op | OP_COLUMN_FAMILY_FLAG);
+            coding::put_varint32(&mut buf, self.column_family);
+        }
 
-        // Safe conversion with proper error handling
-        let key_len: u32 = self.key.len().try_into().map_err(|_| {
-            Error::Corruption(format!("Key length {} too large for u32", self.key.len()))
-        })?;
-        buf.put_u32_le(key_len);
-        buf.put_slice(&self.key);
-
-        let value_len: u32 = self.value.len().try_into().map_err(|_| {
-            Error::Corruption(format!(
-                "Value length {} too large for u32",
-                self.value.len()
-            ))
-        })?;
-        buf.put_u32_le(value_len);
-        buf.put_slice(&self.value);
+        // The size checks above keep both lengths well within a varint32
+        coding::put_length_prefixed_slice(&mut buf, &self.key);
+        coding::put_length_prefixed_slice(&mut buf, &self.value);
 
         // Calculate and set length (excluding length field itself)
         let total_len = buf.len() - 4;
@@ -234,10 +342,10 @@ impl WALEntry {
     /// ## Error Conditions
     ///
     /// Returns `Error::Corruption` if:
-    /// - The buffer is too small (< 25 bytes minimum)
+    /// - The buffer is too small (< 19 bytes minimum)
     /// - The length field doesn't match actual size
     /// - The checksum verification fails
-    /// - The operation type is invalid (not 1 or 2)
+    /// - The operation type is invalid (not 1, 2 or 3)
     /// - Key or value sizes exceed limits
     /// - Data is truncated (insufficient bytes for declared lengths)
     /// - Unexpected trailing bytes after the value
@@ -253,11 +361,34 @@ impl WALEntry {
     /// 6. Buffer bounds checking during parsing
     /// 7. Exact size match verification
     pub fn decode(data: &[u8]) -> Result<Self> {
-        if data.len() < MIN_ENTRY_SIZE {
+        Self::decode_version(data, WAL_CURRENT_VERSION)
+    }
+
+    /// Decodes an entry written in WAL format `version`
+    ///
+    /// Readers pass the version from the segment's header, so segments
+    /// written before lengths became varints stay readable.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`decode`](Self::decode).
+    pub fn decode_version(data: &[u8], version: u16) -> Result<Self> {
+        Self::decode_checked(data, version, true)
+    }
+
+    /// Decodes an entry like [`decode_version`](Self::decode_version),
+    /// skipping the checksum unless `verify_checksum` is set
+    pub(crate) fn decode_checked(data: &[u8], version: u16, verify_checksum: bool) -> Result<Self> {
+        let min_size = if version < WAL_VARINT_VERSION {
+            HEADER_SIZE + 8 + 1 + 4 + 4
+        } else {
+            MIN_ENTRY_SIZE
+        };
+        if data.len() < min_size {
             return Err(Error::Corruption(format!(
                 "WAL entry too small: {} bytes (minimum: {})",
                 data.len(),
-                MIN_ENTRY_SIZE
+                min_size
             )));
         }
 
@@ -281,70 +412,48 @@ impl WALEntry {
 
         // Read and verify checksum
         let expected_checksum = cursor.get_u32_le();
-        let mut hasher = Hasher::new();
-        hasher.update(&data[8..]);
-        let actual_checksum = hasher.finalize();
-
-        if expected_checksum != actual_checksum {
-            return Err(Error::Corruption(format!(
-                "WAL entry checksum mismatch: expected {:#x} but got {:#x}",
-                expected_checksum, actual_checksum
-            )));
-        }
-
-        // Ensure we have enough data for fixed fields
-        if cursor.len() < 8 + 1 + 4 {
-            return Err(Error::Corruption(
-                "WAL entry truncated: missing fixed fields".to_string(),
-            ));
+        if verify_checksum {
+            let actual_checksum = checksum(data);
+            if expected_checksum != actual_checksum {
+                return Err(Error::Corruption(format!(
+                    "WAL entry checksum mismatch: expected {:#x} but got {:#x}",
+                    expected_checksum, actual_checksum
+                )));
+            }
         }
 
-        // Decode entry data
+        // Decode entry data; the minimum size covers the timestamp and op
         let timestamp = cursor.get_u64_le();
-        let operation = match cursor.get_u8() {
+        let op = cursor.get_u8();
+        let operation = match op & !OP_COLUMN_FAMILY_FLAG {
             OP_PUT => Operation::Put,
             OP_DELETE => Operation::Delete,
-            op => return Err(Error::Corruption(format!("Invalid operation type: {}", op))),
+            OP_MERGE => Operation::Merge,
+            _ => return Err(Error::Corruption(format!("Invalid operation type: {}", op))),
+        };
+        let column_family = if op & OP_COLUMN_FAMILY_FLAG == 0 {
+            0
+        } else {
+            get_u32(&mut cursor, version)?
         };
 
-        let key_len = cursor.get_u32_le() as usize;
+        let key_len = get_u32(&mut cursor, version)? as usize;
         if key_len > MAX_KEY_SIZE {
             return Err(Error::Corruption(format!(
                 "Key size {} exceeds maximum {}",
                 key_len, MAX_KEY_SIZE
             )));
         }
-        if cursor.len() < key_len + 4 {
-            return Err(Error::Corruption(format!(
-                "WAL entry truncated: expected {} key bytes but only {} available",
-                key_len,
-                cursor.len() - 4
-            )));
-        }
-        let key = cursor[..key_len].to_vec();
-        cursor.advance(key_len);
+        let key = coding::get_bytes(&mut cursor, key_len)?.to_vec();
 
-        if cursor.len() < 4 {
-            return Err(Error::Corruption(
-                "WAL entry truncated: missing value length".to_string(),
-            ));
-        }
-        let value_len = cursor.get_u32_le() as usize;
+        let value_len = get_u32(&mut cursor, version)? as usize;
         if value_len > MAX_VALUE_SIZE {
             return Err(Error::Corruption(format!(
                 "Value size {} exceeds maximum {}",
                 value_len, MAX_VALUE_SIZE
             )));
         }
-        if cursor.len() < value_len {
-            return Err(Error::Corruption(format!(
-                "WAL entry truncated: expected {} value bytes but only {} available",
-                value_len,
-                cursor.len()
-            )));
-        }
-        let value = cursor[..value_len].to_vec();
-        cursor.advance(value_len);
+        let value = coding::get_bytes(&mut cursor, value_len)?.to_vec();
 
         // Verify we consumed exactly the right amount of data
         if !cursor.is_empty() {
@@ -359,10 +468,465 @@ impl WALEntry {
             operation,
             key,
             value,
+            column_family,
         })
     }
 }
 
+impl WALEntry {
+    /// Encodes several entries into one batch record
+    ///
+    /// A batch record is checksummed as a whole, so recovery replays either
+    /// all of its entries or none. The entries must carry consecutive
+    /// timestamps:
+    ///
+    /// ```text
+    /// [length:4][checksum:4][timestamp:8][op=4:1][count:var][entry]...[entry]
+    /// ```
+    ///
+    /// where `timestamp` is the first entry's and each `entry` is encoded as
+    /// by [`encode`](Self::encode).
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the batch is empty, an entry exceeds
+    /// the size limits, the timestamps are not consecutive, or the record
+    /// would exceed `MAX_BATCH_SIZE`.
+    pub fn encode_batch(entries: &[WALEntry]) -> Result<Vec<u8>> {
+        let first = entries
+            .first()
+            .ok_or_else(|| Error::Corruption("WAL batch has no entries".to_string()))?;
+        let count: u32 = entries.len().try_into().map_err(|_| {
+            Error::Corruption(format!(
+                "WAL batch of {} entries is too large",
+                entries.len()
+            ))
+        })?;
+
+        let mut buf = BytesMut::with_capacity(BATCH_HEADER_SIZE + MAX_VARINT32_LEN);
+        coding::put_fixed32(&mut buf, 0); // length placeholder
+        coding::put_fixed32(&mut buf, 0); // checksum placeholder
+        coding::put_fixed64(&mut buf, first.timestamp);
+        buf.put_u8(OP_BATCH);
+        coding::put_varint32(&mut buf, count);
+
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.timestamp != first.timestamp + i as u64 {
+                return Err(Error::Corruption(format!(
+                    "WAL batch timestamps not consecutive: expected {} but got {}",
+                    first.timestamp + i as u64,
+                    entry.timestamp
+                )));
+            }
+            buf.put_slice(&entry.encode()?);
+        }
+
+        let total_len = buf.len() - 4;
+        if total_len > MAX_BATCH_SIZE {
+            return Err(Error::Corruption(format!(
+                "WAL batch size {} exceeds maximum {}",
+                total_len, MAX_BATCH_SIZE
+            )));
+        }
+        buf[0..4].copy_from_slice(&(total_len as u32).to_le_bytes());
+
+        let mut hasher = Hasher::new();
+        hasher.update(&buf[8..]);
+        let checksum = hasher.finalize();
+        buf[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+        Ok(buf.to_vec())
+    }
+
+    /// Decodes a batch record into its entries
+    ///
+    /// The input must be a complete record including the length prefix.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the record is not a batch, fails its
+    /// checksum, or any contained entry is invalid, its timestamp out of
+    /// sequence, or its count doesn't match the record.
+    pub fn decode_batch(data: &[u8]) -> Result<Vec<WALEntry>> {
+        Self::decode_batch_version(data, WAL_CURRENT_VERSION)
+    }
+
+    /// Decodes a batch record written in WAL format `version`
+    ///
+    /// # Errors
+    ///
+    /// The same as [`decode_batch`](Self::decode_batch).
+    pub fn decode_batch_version(data: &[u8], version: u16) -> Result<Vec<WALEntry>> {
+        Self::decode_batch_checked(data, version, true)
+    }
+
+    /// Decodes a batch record like [`decode_batch_version`](Self::decode_batch_version),
+    /// skipping the checksums unless `verify_checksum` is set
+    pub(crate) fn decode_batch_checked(
+        data: &[u8],
+        version: u16,
+        verify_checksum: bool,
+    ) -> Result<Vec<WALEntry>> {
+        if data.len() < BATCH_HEADER_SIZE {
+            return Err(Error::Corruption(format!(
+                "WAL batch too small: {} bytes (minimum: {})",
+                data.len(),
+                BATCH_HEADER_SIZE
+            )));
+        }
+
+        let mut cursor = data;
+        let length = cursor.get_u32_le() as usize;
+        if data.len() != length + 4 {
+            return Err(Error::Corruption(format!(
+                "WAL batch length mismatch: declared {} but got {} bytes",
+                length + 4,
+                data.len()
+            )));
+        }
+
+        let expected_checksum = cursor.get_u32_le();
+        if verify_checksum {
+            let actual_checksum = checksum(data);
+            if expected_checksum != actual_checksum {
+                return Err(Error::Corruption(format!(
+                    "WAL batch checksum mismatch: expected {:#x} but got {:#x}",
+                    expected_checksum, actual_checksum
+                )));
+            }
+        }
+
+        let timestamp = cursor.get_u64_le();
+        let op = cursor.get_u8();
+        if op != OP_BATCH {
+            return Err(Error::Corruption(format!(
+                "Invalid batch operation type: {}",
+                op
+            )));
+        }
+        let count = get_u32(&mut cursor, version)? as usize;
+        decode_entries(cursor, count, Some(timestamp), version, verify_checksum)
+    }
+
+    /// Returns true if an encoded record is a batch of entries
+    pub(crate) fn is_batch_record(data: &[u8]) -> bool {
+        data.get(HEADER_SIZE + 8) == Some(&OP_BATCH)
+    }
+
+    /// Encodes the seal record a cleanly closed segment ends with
+    ///
+    /// ```text
+    /// [length:4][checksum:4][timestamp:8][op=5:1][sealed_len:8]
+    /// ```
+    ///
+    /// where `timestamp` is the newest write in the segment and
+    /// `sealed_len` the offset the seal starts at, which tells a seal at
+    /// the end of the file from one that was appended to.
+    pub(crate) fn encode_seal(last_timestamp: Timestamp, sealed_len: u64) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(SEAL_SIZE);
+        coding::put_fixed32(&mut buf, (SEAL_SIZE - 4) as u32);
+        coding::put_fixed32(&mut buf, 0); // checksum placeholder
+        coding::put_fixed64(&mut buf, last_timestamp);
+        buf.put_u8(OP_SEAL);
+        coding::put_fixed64(&mut buf, sealed_len);
+        let checksum = checksum(&buf);
+        buf[4..8].copy_from_slice(&checksum.to_le_bytes());
+        buf.to_vec()
+    }
+
+    /// Decodes a seal record into the newest timestamp and the offset it
+    /// starts at
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the record is not a seal or fails
+    /// its checksum.
+    pub(crate) fn decode_seal(data: &[u8]) -> Result<(Timestamp, u64)> {
+        let mut cursor = data;
+        if data.len() != SEAL_SIZE
+            || cursor.get_u32_le() as usize != SEAL_SIZE - 4
+            || !Self::is_seal_record(data)
+        {
+            return Err(Error::Corruption("Invalid WAL seal record".to_string()));
+        }
+        let expected_checksum = cursor.get_u32_le();
+        let actual_checksum = checksum(data);
+        if expected_checksum != actual_checksum {
+            return Err(Error::Corruption(format!(
+                "WAL seal checksum mismatch: expected {:#x} but got {:#x}",
+                expected_checksum, actual_checksum
+            )));
+        }
+        let last_timestamp = cursor.get_u64_le();
+        cursor.advance(1);
+        Ok((last_timestamp, cursor.get_u64_le()))
+    }
+
+    /// Returns true if an encoded record is a seal
+    pub(crate) fn is_seal_record(data: &[u8]) -> bool {
+        data.get(HEADER_SIZE + 8) == Some(&OP_SEAL)
+    }
+
+    /// Encodes the record preparing transaction `name` with its writes
+    ///
+    /// Transaction records share one layout:
+    ///
+    /// ```text
+    /// [length:4][checksum:4][timestamp:8][op:1][name_len:var][name][count:var][entry]...[entry]
+    /// ```
+    ///
+    /// where `op` is 6 for a prepare, 7 for a commit and 8 for a rollback.
+    /// A commit's entries are timestamped consecutively from `timestamp`;
+    /// a prepare's carry their position in the transaction, from 0, and
+    /// `timestamp` is the newest write logged before it. A rollback holds
+    /// no entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if the name is empty or longer than
+    /// [`MAX_TRANSACTION_NAME_SIZE`], or `Error::Corruption` for the same
+    /// reasons as [`encode_batch`](Self::encode_batch).
+    pub fn encode_prepare(
+        name: &str,
+        timestamp: Timestamp,
+        entries: &[WALEntry],
+    ) -> Result<Vec<u8>> {
+        encode_transaction(OP_PREPARE, name, timestamp, entries)
+    }
+
+    /// Encodes the record committing prepared transaction `name`, with its
+    /// writes timestamped consecutively from `timestamp`
+    ///
+    /// # Errors
+    ///
+    /// The same as [`encode_prepare`](Self::encode_prepare), or
+    /// `Error::Corruption` if the timestamps are not consecutive.
+    pub fn encode_commit(
+        name: &str,
+        timestamp: Timestamp,
+        entries: &[WALEntry],
+    ) -> Result<Vec<u8>> {
+        encode_transaction(OP_COMMIT, name, timestamp, entries)
+    }
+
+    /// Encodes the record rolling back prepared transaction `name`, logged
+    /// after the write at `timestamp`
+    ///
+    /// # Errors
+    ///
+    /// The same as [`encode_prepare`](Self::encode_prepare).
+    pub fn encode_rollback(name: &str, timestamp: Timestamp) -> Result<Vec<u8>> {
+        encode_transaction(OP_ROLLBACK, name, timestamp, &[])
+    }
+
+    /// Decodes a transaction record into its marker and, for a commit, the
+    /// committed entries
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Corruption` if the record is not a transaction
+    /// record, fails its checksum, or holds an invalid entry or name.
+    pub fn decode_transaction(data: &[u8]) -> Result<(TransactionMarker, Vec<WALEntry>)> {
+        Self::decode_transaction_checked(data, WAL_CURRENT_VERSION, true)
+    }
+
+    /// Decodes a transaction record like [`decode_transaction`](Self::decode_transaction),
+    /// written in WAL format `version` and skipping the checksums unless
+    /// `verify_checksum` is set
+    pub(crate) fn decode_transaction_checked(
+        data: &[u8],
+        version: u16,
+        verify_checksum: bool,
+    ) -> Result<(TransactionMarker, Vec<WALEntry>)> {
+        if data.len() < TRANSACTION_HEADER_SIZE {
+            return Err(Error::Corruption(format!(
+                "WAL transaction record too small: {} bytes (minimum: {})",
+                data.len(),
+                TRANSACTION_HEADER_SIZE
+            )));
+        }
+        let mut cursor = data;
+        let length = cursor.get_u32_le() as usize;
+        if data.len() != length + 4 {
+            return Err(Error::Corruption(format!(
+                "WAL transaction record length mismatch: declared {} but got {} bytes",
+                length + 4,
+                data.len()
+            )));
+        }
+        let expected_checksum = cursor.get_u32_le();
+        if verify_checksum {
+            let actual_checksum = checksum(data);
+            if expected_checksum != actual_checksum {
+                return Err(Error::Corruption(format!(
+                    "WAL transaction record checksum mismatch: expected {:#x} but got {:#x}",
+                    expected_checksum, actual_checksum
+                )));
+            }
+        }
+
+        let timestamp = cursor.get_u64_le();
+        let op = cursor.get_u8();
+        let name = coding::get_length_prefixed_slice(&mut cursor)?;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| {
+            Error::Corruption("WAL transaction name is not valid UTF-8".to_string())
+        })?;
+        let count = coding::get_varint32(&mut cursor)? as usize;
+        match op {
+            OP_PREPARE => {
+                let entries = decode_entries(cursor, count, None, version, verify_checksum)?;
+                Ok((TransactionMarker::Prepare { name, entries }, Vec::new()))
+            }
+            OP_COMMIT => {
+                let entries =
+                    decode_entries(cursor, count, Some(timestamp), version, verify_checksum)?;
+                Ok((TransactionMarker::Commit { name }, entries))
+            }
+            OP_ROLLBACK if count == 0 && cursor.is_empty() => {
+                Ok((TransactionMarker::Rollback { name }, Vec::new()))
+            }
+            _ => Err(Error::Corruption(format!(
+                "Invalid transaction record operation type: {}",
+                op
+            ))),
+        }
+    }
+
+    /// Returns true if an encoded record is a prepare, commit or rollback
+    pub(crate) fn is_transaction_record(data: &[u8]) -> bool {
+        matches!(
+            data.get(HEADER_SIZE + 8),
+            Some(&(OP_PREPARE | OP_COMMIT | OP_ROLLBACK))
+        )
+    }
+}
+
+/// Encodes a transaction record, see [`WALEntry::encode_prepare`]
+fn encode_transaction(
+    op: u8,
+    name: &str,
+    timestamp: Timestamp,
+    entries: &[WALEntry],
+) -> Result<Vec<u8>> {
+    if name.is_empty() || name.len() > MAX_TRANSACTION_NAME_SIZE {
+        return Err(Error::InvalidArgument(format!(
+            "Transaction names must have 1 to {} bytes, not {}",
+            MAX_TRANSACTION_NAME_SIZE,
+            name.len()
+        )));
+    }
+    let count: u32 = entries.len().try_into().map_err(|_| {
+        Error::Corruption(format!(
+            "WAL transaction of {} entries is too large",
+            entries.len()
+        ))
+    })?;
+
+    let mut buf = BytesMut::with_capacity(TRANSACTION_HEADER_SIZE + name.len() + MAX_VARINT32_LEN);
+    coding::put_fixed32(&mut buf, 0); // length placeholder
+    coding::put_fixed32(&mut buf, 0); // checksum placeholder
+    coding::put_fixed64(&mut buf, timestamp);
+    buf.put_u8(op);
+    coding::put_length_prefixed_slice(&mut buf, name.as_bytes());
+    coding::put_varint32(&mut buf, count);
+    for (i, entry) in entries.iter().enumerate() {
+        let expected = if op == OP_COMMIT { timestamp } else { 0 } + i as u64;
+        if entry.timestamp != expected {
+            return Err(Error::Corruption(format!(
+                "WAL transaction timestamps not consecutive: expected {} but got {}",
+                expected, entry.timestamp
+            )));
+        }
+        buf.put_slice(&entry.encode()?);
+    }
+
+    let total_len = buf.len() - 4;
+    if total_len > MAX_BATCH_SIZE {
+        return Err(Error::Corruption(format!(
+            "WAL transaction size {} exceeds maximum {}",
+            total_len, MAX_BATCH_SIZE
+        )));
+    }
+    buf[0..4].copy_from_slice(&(total_len as u32).to_le_bytes());
+    let checksum = checksum(&buf);
+    buf[4..8].copy_from_slice(&checksum.to_le_bytes());
+    Ok(buf.to_vec())
+}
+
+/// Decodes the `count` entries making up the rest of a batch or
+/// transaction record, checking they are timestamped consecutively from
+/// `first`, or from 0 without it
+fn decode_entries(
+    mut cursor: &[u8],
+    count: usize,
+    first: Option<Timestamp>,
+    version: u16,
+    verify_checksum: bool,
+) -> Result<Vec<WALEntry>> {
+    let mut entries = Vec::with_capacity(count.min(cursor.len() / MIN_ENTRY_SIZE));
+    while !cursor.is_empty() {
+        if cursor.len() < 4 {
+            return Err(Error::Corruption(
+                "WAL batch truncated: missing entry length".to_string(),
+            ));
+        }
+        let entry_len = u32::from_le_bytes(cursor[..4].try_into().expect("4 bytes")) as usize;
+        let Some(encoded) = cursor.get(..entry_len + 4) else {
+            return Err(Error::Corruption(format!(
+                "WAL batch truncated: expected {} entry bytes but only {} available",
+                entry_len + 4,
+                cursor.len()
+            )));
+        };
+
+        let entry = WALEntry::decode_checked(encoded, version, verify_checksum)?;
+        let expected = first.unwrap_or(0) + entries.len() as u64;
+        if entry.timestamp != expected {
+            return Err(Error::Corruption(format!(
+                "WAL batch timestamps not consecutive: expected {} but got {}",
+                expected, entry.timestamp
+            )));
+        }
+        entries.push(entry);
+        cursor.advance(entry_len + 4);
+    }
+
+    if entries.len() != count {
+        return Err(Error::Corruption(format!(
+            "WAL batch declares {} entries but holds {}",
+            count,
+            entries.len()
+        )));
+    }
+    Ok(entries)
+}
+
+/// Returns the checksum stored in `record` and the one its bytes have, if
+/// they differ
+pub(crate) fn checksum_mismatch(record: &[u8]) -> Option<(u32, u32)> {
+    let stored = u32::from_le_bytes(record.get(4..HEADER_SIZE)?.try_into().ok()?);
+    let actual = checksum(record);
+    (stored != actual).then_some((stored, actual))
+}
+
+/// Computes the checksum of a record, which covers everything after the
+/// length and checksum fields
+fn checksum(record: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(&record[HEADER_SIZE..]);
+    hasher.finalize()
+}
+
+/// Decodes a length, count or column family id, which WAL format 1.x
+/// stored as a fixed 4-byte integer
+fn get_u32(cursor: &mut &[u8], version: u16) -> Result<u32> {
+    if version < WAL_VARINT_VERSION {
+        coding::get_fixed32(cursor)
+    } else {
+        coding::get_varint32(cursor)
+    }
+}
+
 // Implement TryFrom for ergonomic conversions
 impl TryFrom<&[u8]> for WALEntry {
     type Error = Error;
@@ -422,6 +986,24 @@ mod tests {
         assert_eq!(entry, decoded);
     }
 
+    /// Tests basic Merge entry encoding and decoding.
+    ///
+    /// Ensures:
+    /// - Merge entries use their own operation byte
+    /// - The operand survives the roundtrip in the value field
+    #[test]
+    fn encode_decode_roundtrip_preserves_merge_entry() {
+        let entry = WALEntry::new_merge(b"counter".to_vec(), b"+1".to_vec(), 12345)
+            .expect("Failed to create entry");
+
+        let encoded = entry.encode().expect("Failed to encode");
+        assert_eq!(encoded[16], OP_MERGE);
+
+        let decoded = WALEntry::decode(&encoded).unwrap();
+        assert_eq!(decoded.operation, Operation::Merge);
+        assert_eq!(entry, decoded);
+    }
+
     /// Tests that data corruption is detected during decode.
     ///
     /// Verifies:
@@ -637,9 +1219,12 @@ mod tests {
             .expect("Failed to create entry");
         let mut encoded = entry.encode().expect("Failed to encode");
 
-        // Set key length to exceed MAX_KEY_SIZE
-        let oversized_len = (MAX_KEY_SIZE + 1000) as u32;
-        encoded[17..21].copy_from_slice(&oversized_len.to_le_bytes());
+        // Replace the one-byte key length with one exceeding MAX_KEY_SIZE
+        let mut oversized_len = Vec::new();
+        coding::put_varint32(&mut oversized_len, (MAX_KEY_SIZE + 1000) as u32);
+        encoded.splice(17..18, oversized_len);
+        let new_len = (encoded.len() - 4) as u32;
+        encoded[0..4].copy_from_slice(&new_len.to_le_bytes());
 
         // Recalculate checksum
         let mut hasher = Hasher::new();
@@ -798,6 +1383,204 @@ mod tests {
             handle.join().unwrap();
         }
     }
+
+    fn batch_entries() -> Vec<WALEntry> {
+        vec![
+            WALEntry::new_put(b"a".to_vec(), b"1".to_vec(), 10).unwrap(),
+            WALEntry::new_delete(b"b".to_vec(), 11).unwrap(),
+            WALEntry::new_merge(b"c".to_vec(), b"+1".to_vec(), 12).unwrap(),
+        ]
+    }
+
+    /// Tests batch record encoding and decoding.
+    ///
+    /// Verifies:
+    /// - All entries come back in order with their operations
+    /// - Batch records are told apart from single entries
+    #[test]
+    fn encode_decode_batch_roundtrip_preserves_entries() {
+        let entries = batch_entries();
+        let encoded = WALEntry::encode_batch(&entries).unwrap();
+
+        assert!(WALEntry::is_batch_record(&encoded));
+        assert!(!WALEntry::is_batch_record(&entries[0].encode().unwrap()));
+        assert_eq!(WALEntry::decode_batch(&encoded).unwrap(), entries);
+        assert!(WALEntry::decode(&encoded).is_err());
+    }
+
+    /// Tests that a damaged batch is rejected as a whole.
+    ///
+    /// Verifies:
+    /// - Any flipped byte fails the batch checksum
+    /// - A truncated batch is rejected
+    #[test]
+    fn decode_batch_rejects_corruption_and_truncation() {
+        let encoded = WALEntry::encode_batch(&batch_entries()).unwrap();
+
+        for i in 8..encoded.len() {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0xFF;
+            assert!(WALEntry::decode_batch(&corrupted).is_err(), "byte {}", i);
+        }
+        assert!(WALEntry::decode_batch(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    /// Tests column family ids in encoded entries.
+    ///
+    /// Verifies:
+    /// - Default column family entries keep the original layout
+    /// - Other column families survive single and batch roundtrips
+    #[test]
+    fn column_family_roundtrips_through_entries_and_batches() {
+        let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 7).unwrap();
+        let plain = entry.encode().unwrap();
+        assert_eq!(plain[16], OP_PUT);
+
+        let entry = entry.with_column_family(3);
+        let encoded = entry.encode().unwrap();
+        assert_eq!(encoded.len(), plain.len() + 1);
+        assert_eq!(WALEntry::decode(&encoded).unwrap(), entry);
+
+        let mut entries = batch_entries();
+        entries[1].column_family = u32::MAX;
+        let encoded = WALEntry::encode_batch(&entries).unwrap();
+        assert_eq!(WALEntry::decode_batch(&encoded).unwrap(), entries);
+    }
+
+    /// Tests seal record encoding and decoding.
+    ///
+    /// Verifies:
+    /// - The timestamp and offset come back, and the size is fixed
+    /// - Seals are told apart from entries and batches
+    /// - Any flipped byte is rejected
+    #[test]
+    fn encode_decode_seal_roundtrip_and_rejects_corruption() {
+        let encoded = WALEntry::encode_seal(42, 4096);
+        assert_eq!(encoded.len(), SEAL_SIZE);
+        assert!(WALEntry::is_seal_record(&encoded));
+        assert!(!WALEntry::is_batch_record(&encoded));
+        assert!(WALEntry::decode(&encoded).is_err());
+        assert_eq!(WALEntry::decode_seal(&encoded).unwrap(), (42, 4096));
+
+        for i in 0..encoded.len() {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0xFF;
+            assert!(WALEntry::decode_seal(&corrupted).is_err(), "byte {}", i);
+        }
+        let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 1).unwrap();
+        assert!(WALEntry::decode_seal(&entry.encode().unwrap()).is_err());
+    }
+
+    /// Tests prepare, commit and rollback record encoding and decoding.
+    ///
+    /// Verifies:
+    /// - Prepares keep their entries, numbered from 0, in the marker
+    /// - Commits return their entries, timestamped from the record's
+    /// - Transaction records are told apart from batches, and any flipped
+    ///   byte is rejected
+    /// - Names must be non-empty and short
+    #[test]
+    fn encode_decode_transaction_records() {
+        let mut prepared = batch_entries();
+        for (i, entry) in prepared.iter_mut().enumerate() {
+            entry.timestamp = i as u64;
+        }
+        let encoded = WALEntry::encode_prepare("xid-1", 9, &prepared).unwrap();
+        assert!(WALEntry::is_transaction_record(&encoded));
+        assert!(!WALEntry::is_batch_record(&encoded));
+        assert_eq!(
+            WALEntry::decode_transaction(&encoded).unwrap(),
+            (
+                TransactionMarker::Prepare {
+                    name: "xid-1".to_string(),
+                    entries: prepared.clone(),
+                },
+                Vec::new()
+            )
+        );
+        for i in 8..encoded.len() {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0xFF;
+            assert!(
+                WALEntry::decode_transaction(&corrupted).is_err(),
+                "byte {}",
+                i
+            );
+        }
+
+        let encoded = WALEntry::encode_commit("xid-1", 10, &batch_entries()).unwrap();
+        let (marker, entries) = WALEntry::decode_transaction(&encoded).unwrap();
+        assert_eq!(marker.name(), "xid-1");
+        assert!(matches!(marker, TransactionMarker::Commit { .. }));
+        assert_eq!(entries, batch_entries());
+        assert!(WALEntry::encode_commit("xid-1", 11, &batch_entries()).is_err());
+
+        let encoded = WALEntry::encode_rollback("xid-1", 12).unwrap();
+        assert_eq!(
+            WALEntry::decode_transaction(&encoded).unwrap().0,
+            TransactionMarker::Rollback {
+                name: "xid-1".to_string()
+            }
+        );
+        assert!(
+            WALEntry::decode_transaction(&WALEntry::encode_batch(&batch_entries()).unwrap())
+                .is_err()
+        );
+
+        assert!(WALEntry::encode_rollback("", 12).is_err());
+        let long = "x".repeat(MAX_TRANSACTION_NAME_SIZE + 1);
+        assert!(WALEntry::encode_rollback(&long, 12).is_err());
+    }
+
+    /// Encodes an entry the way WAL format 1.x did, with fixed-width lengths
+    fn encode_v1(entry: &WALEntry) -> Vec<u8> {
+        let mut body = Vec::new();
+        coding::put_fixed64(&mut body, entry.timestamp);
+        if entry.column_family == 0 {
+            body.push(OP_PUT);
+        } else {
+            body.push(OP_PUT | OP_COLUMN_FAMILY_FLAG);
+            coding::put_fixed32(&mut body, entry.column_family);
+        }
+        coding::put_fixed32(&mut body, entry.key.len() as u32);
+        body.extend_from_slice(&entry.key);
+        coding::put_fixed32(&mut body, entry.value.len() as u32);
+        body.extend_from_slice(&entry.value);
+
+        let mut record = Vec::new();
+        coding::put_fixed32(&mut record, (body.len() + 4) as u32);
+        coding::put_fixed32(&mut record, crc32fast::hash(&body));
+        record.extend_from_slice(&body);
+        record
+    }
+
+    /// Tests that varint lengths shrink entries and old segments still decode.
+    ///
+    /// Verifies:
+    /// - Small keys and values take one length byte each instead of four
+    /// - Entries in the fixed-width 1.x format decode with their version
+    /// - Decoding a 1.x entry as the current format fails
+    #[test]
+    fn version_1_entries_decode_with_fixed_lengths() {
+        let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 7)
+            .unwrap()
+            .with_column_family(2);
+        let legacy = encode_v1(&entry);
+        assert_eq!(entry.encode().unwrap().len(), legacy.len() - 9);
+
+        assert_eq!(WALEntry::decode_version(&legacy, 0x0100).unwrap(), entry);
+        assert!(WALEntry::decode(&legacy).is_err());
+    }
+
+    /// Tests that batches must be non-empty with consecutive timestamps.
+    #[test]
+    fn encode_batch_rejects_empty_and_out_of_sequence_entries() {
+        assert!(WALEntry::encode_batch(&[]).is_err());
+
+        let mut entries = batch_entries();
+        entries[2].timestamp = 20;
+        assert!(WALEntry::encode_batch(&entries).is_err());
+    }
 }
 
 // Property-based tests
@@ -848,7 +1631,9 @@ mod property_tests {
                 .expect("Failed to create entry");
             let encoded = entry.encode().expect("Failed to encode");
 
-            let expected_size = 4 + 4 + 8 + 1 + 4 + key.len() + 4 + value.len();
+            let expected_size = 4 + 4 + 8 + 1
+                + coding::varint_length(key.len() as u64) + key.len()
+                + coding::varint_length(value.len() as u64) + value.len();
             assert_eq!(encoded.len(), expected_size);
         }
     }
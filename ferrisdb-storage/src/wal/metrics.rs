@@ -3,8 +3,10 @@
 //! This module provides comprehensive metrics tracking for both WAL reader and writer
 //! operations, enabling performance monitoring and debugging.
 
+use crate::histogram::{HistogramSnapshot, LatencyHistogram};
+
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 /// WAL operation metrics
 ///
@@ -23,6 +25,7 @@ use std::time::Instant;
 /// // After some operations...
 /// println!("Total writes: {}", metrics.writes_total());
 /// println!("Bytes written: {}", metrics.bytes_written());
+/// println!("p99 sync: {:?}", metrics.sync_latency().p99);
 /// # Ok::<(), ferrisdb_core::Error>(())
 /// ```
 #[derive(Debug, Default)]
@@ -48,6 +51,10 @@ pub struct WALMetrics {
     // File metrics
     current_file_size: AtomicU64,
     files_opened: AtomicU64,
+
+    // Latency distributions
+    write_latency: LatencyHistogram,
+    sync_latency: LatencyHistogram,
 }
 
 impl WALMetrics {
@@ -70,9 +77,20 @@ impl WALMetrics {
 
     /// Records a sync operation with its duration
     pub fn record_sync(&self, duration_ms: u64) {
+        self.record_sync_duration(Duration::from_millis(duration_ms));
+    }
+
+    /// Records a sync operation with its exact duration
+    pub fn record_sync_duration(&self, duration: Duration) {
         self.sync_total.fetch_add(1, Ordering::Relaxed);
         self.sync_duration_ms
-            .fetch_add(duration_ms, Ordering::Relaxed);
+            .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+        self.sync_latency.record(duration);
+    }
+
+    /// Records how long writing a record took, its sync included
+    pub fn record_write_duration(&self, duration: Duration) {
+        self.write_latency.record(duration);
     }
 
     /// Records a read operation
@@ -184,6 +202,8 @@ impl WALMetrics {
         self.max_entry_size.store(0, Ordering::Relaxed);
         self.current_file_size.store(0, Ordering::Relaxed);
         self.files_opened.store(0, Ordering::Relaxed);
+        self.write_latency.reset();
+        self.sync_latency.reset();
     }
 
     // Accessor methods for encapsulated fields
@@ -257,6 +277,16 @@ impl WALMetrics {
     pub fn files_opened(&self) -> u64 {
         self.files_opened.load(Ordering::Relaxed)
     }
+
+    /// Gets the distribution of record write times, syncs included
+    pub fn write_latency(&self) -> HistogramSnapshot {
+        self.write_latency.snapshot()
+    }
+
+    /// Gets the distribution of sync times
+    pub fn sync_latency(&self) -> HistogramSnapshot {
+        self.sync_latency.snapshot()
+    }
 }
 
 /// Helper struct for timing operations
@@ -276,6 +306,11 @@ impl TimedOperation {
     pub fn complete(self) -> u64 {
         self.start.elapsed().as_millis() as u64
     }
+
+    /// Completes the timing and returns the exact duration
+    pub fn elapsed(self) -> Duration {
+        self.start.elapsed()
+    }
 }
 
 #[cfg(test)]
@@ -354,6 +389,11 @@ mod tests {
         assert_eq!(metrics.sync_total(), 3);
         assert_eq!(metrics.sync_duration_ms(), 60);
         assert_eq!(metrics.avg_sync_duration_ms(), 20.0);
+
+        let latency = metrics.sync_latency();
+        assert_eq!(latency.count, 3);
+        assert_eq!(latency.max, Duration::from_millis(30));
+        assert_eq!(latency.sum, Duration::from_millis(60));
     }
 
     /// Tests that metrics reset clears all counters to initial state.
@@ -379,6 +419,7 @@ mod tests {
         assert_eq!(metrics.writes_total(), 0);
         assert_eq!(metrics.reads_total(), 0);
         assert_eq!(metrics.sync_total(), 0);
+        assert_eq!(metrics.sync_latency(), HistogramSnapshot::default());
     }
 
     /// Tests that TimedOperation helper measures elapsed time accurately.
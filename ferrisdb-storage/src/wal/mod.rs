@@ -37,7 +37,8 @@
 //! 20      4     entry_start_offset Where entries begin (64)
 //! 24      8     created_at         Creation time (µs since Unix epoch)
 //! 32      8     file_sequence      Unique file identifier
-//! 40      24    reserved           Reserved for future use (zeros)
+//! 40      8     previous_timestamp Last write before this segment (0 if unknown)
+//! 48      16    reserved           Reserved for future use (zeros)
 //! ```
 //!
 //! ## Entry Format (Variable size)
@@ -47,16 +48,82 @@
 //! ```text
 //! Offset  Size  Field         Description
 //! ------  ----  -----         -----------
-//! 0       4     length        Total entry size (including this field)
+//! 0       4     length        Entry size (excluding this field)
 //! 4       4     checksum      CRC32 of all following fields
-//! 8       8     timestamp     Operation timestamp (microseconds)
-//! 16      1     operation     1=Put, 2=Delete
-//! 17      4     key_len       Key length in bytes
-//! 21      4     value_len     Value length in bytes (0 for Delete)
-//! 25      var   key           Key data
-//! 25+key  var   value         Value data (empty for Delete)
+//! 8       8     timestamp     Operation timestamp (hybrid logical clock)
+//! 16      1     operation     1=Put, 2=Delete, 3=Merge
+//! 17      1-5   key_len       Key length in bytes (varint)
+//! ...     var   key           Key data
+//! ...     1-5   value_len     Value length in bytes (varint, 0 for Delete)
+//! ...     var   value         Value data (empty for Delete)
 //! ```
 //!
+//! Entries for a non-default column family set bit `0x80` of `operation`
+//! and insert the column family id as a varint after it. Segments of
+//! format version 1.x store lengths and ids as fixed 4-byte integers and
+//! remain readable.
+//!
+//! ## Batch Format (Variable size)
+//!
+//! Writes that must be applied together are logged as one batch record
+//! whose checksum covers every contained entry, so recovery replays either
+//! the whole batch or none of it:
+//!
+//! ```text
+//! Offset  Size  Field         Description
+//! ------  ----  -----         -----------
+//! 0       4     length        Total record size (excluding this field)
+//! 4       4     checksum      CRC32 of all following fields
+//! 8       8     timestamp     Timestamp of the first entry
+//! 16      1     operation     4=Batch
+//! 17      1-5   count         Number of entries (varint)
+//! ...     var   entries       Encoded entries with consecutive timestamps
+//! ```
+//!
+//! [`WALReader`] unpacks batches, returning their entries one at a time.
+//!
+//! ## Seal Record (25 bytes)
+//!
+//! [`WALWriter::close`] syncs the segment and appends a seal, marking a
+//! clean shutdown. Segments of format version 2.1 and later may end in one:
+//!
+//! ```text
+//! Offset  Size  Field         Description
+//! ------  ----  -----         -----------
+//! 0       4     length        21
+//! 4       4     checksum      CRC32 of all following fields
+//! 8       8     timestamp     Newest write in the segment
+//! 16      1     operation     5=Seal
+//! 17      8     sealed_len    Offset the seal starts at
+//! ```
+//!
+//! A reader that finds a seal at the very end, starting where it says it
+//! does, knows everything before it was synced and skips verifying the
+//! checksums of the records. A segment without one, because its writer was
+//! dropped or crashed, is checked record by record.
+//!
+//! ## Transaction Records (Variable size)
+//!
+//! Two-phase commits log a record per step, named by the transaction:
+//!
+//! ```text
+//! Offset  Size  Field         Description
+//! ------  ----  -----         -----------
+//! 0       4     length        Total record size (excluding this field)
+//! 4       4     checksum      CRC32 of all following fields
+//! 8       8     timestamp     First committed entry, else the newest write before
+//! 16      1     operation     6=Prepare, 7=Commit, 8=Rollback
+//! 17      1-5   name_len      Length of the transaction's name (varint)
+//! ...     var   name          Transaction name (UTF-8)
+//! ...     1-5   count         Number of entries (varint, 0 for Rollback)
+//! ...     var   entries       Encoded entries
+//! ```
+//!
+//! A prepare's entries are numbered from 0, since timestamps are assigned
+//! at commit; a commit's are timestamped consecutively. [`WALReader`]
+//! returns a commit's entries like a batch's and collects a
+//! [`TransactionMarker`] for every transaction record.
+//!
 //! ## Design Rationale
 //!
 //! - **64-byte header**: Fits exactly in one CPU cache line
@@ -75,8 +142,17 @@
 //!
 //! ## File Rotation
 //!
-//! WAL files have a size limit. When reached, a new file should be created.
-//! The file sequence number in the header prevents accidental file mixing.
+//! WAL files have a size limit. When reached, a new file should be created
+//! and the old writer closed, sealing its file. The file sequence number in
+//! the header prevents accidental file mixing.
+//!
+//! ## Following a Live WAL
+//!
+//! [`WALTailer`] reads segments while they are written, returning each
+//! record once it is complete and moving to the next segment after the
+//! writer rotates. Replication ships what it returns to replicas. A
+//! [`WALCache`] keeps the most recent records in memory, so tailers that
+//! keep up read them from there instead of the files.
 //!
 //! # Examples
 //!
@@ -135,6 +211,9 @@
 //!         Operation::Delete => {
 //!             println!("Delete: {:?}", entry.key);
 //!         }
+//!         Operation::Merge => {
+//!             println!("Merge: {:?} += {:?}", entry.key, entry.value);
+//!         }
 //!     }
 //! }
 //! # Ok::<(), ferrisdb_core::Error>(())
@@ -163,15 +242,58 @@
 //! }
 //! # Ok::<(), ferrisdb_core::Error>(())
 //! ```
+//!
+//! ## Reading a File Many Times
+//!
+//! Every [`WALReader::new`] opens the file and validates its header. A
+//! [`WALFile`] does that once and hands out readers sharing its handle,
+//! each at a position of its own:
+//!
+//! ```no_run
+//! use ferrisdb_storage::wal::WALFile;
+//!
+//! let file = WALFile::open("path/to/wal.log")?;
+//! let entries = file.reader().read_all()?;
+//! let again = file.reader().read_all()?;
+//! assert_eq!(entries, again);
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
 
+mod cache;
 mod header;
 mod log_entry;
 mod metrics;
 mod reader;
+mod tailer;
 mod writer;
 
-pub use header::{WALHeader, WAL_CURRENT_VERSION, WAL_HEADER_SIZE, WAL_MAGIC};
-pub use log_entry::WALEntry;
+pub use cache::WALCache;
+pub use header::{
+    WALHeader, WAL_CURRENT_VERSION, WAL_HEADER_SIZE, WAL_MAGIC, WAL_SEAL_VERSION,
+    WAL_VARINT_VERSION,
+};
+pub use log_entry::{TransactionMarker, WALEntry, MAX_TRANSACTION_NAME_SIZE};
 pub use metrics::{TimedOperation, WALMetrics};
-pub use reader::WALReader;
+pub use reader::{WALFile, WALReader};
+pub use tailer::WALTailer;
 pub use writer::WALWriter;
+
+use crate::files::{self, FileType};
+use crate::vfs::Vfs;
+use ferrisdb_core::Result;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Lists the WAL segments in `dir` on `vfs` as (number, path), oldest first
+pub(crate) fn wal_segments_in(vfs: &Arc<dyn Vfs>, dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments: Vec<_> = files::list_files_in(vfs, dir)?
+        .into_iter()
+        .filter_map(|(file_type, path)| match file_type {
+            FileType::Wal(number) => Some((number, path)),
+            _ => None,
+        })
+        .collect();
+    segments.sort();
+    Ok(segments)
+}
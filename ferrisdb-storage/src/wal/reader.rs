@@ -1,11 +1,13 @@
-use super::{WALEntry, WALHeader, WALMetrics};
+use super::log_entry::{checksum_mismatch, MAX_BATCH_SIZE, SEAL_SIZE};
+use super::{TransactionMarker, WALEntry, WALHeader, WALMetrics, WAL_SEAL_VERSION};
 use crate::format::FileHeader;
-use crate::utils::BytesMutExt;
-use bytes::BytesMut;
-use ferrisdb_core::Result;
-use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::path::Path;
+use crate::utils::{BufferPool, BytesMutExt, PooledBuffer};
+use crate::vfs::{self, Vfs, VfsFile};
+use ferrisdb_core::{Error, Result};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /// Statistics for the WAL reader buffer management
@@ -26,6 +28,22 @@ pub struct ReaderStats {
 /// checksums and handles partial entries at the end of the file (which may
 /// occur if the process crashed during a write).
 ///
+/// A segment ending in the seal record of [`WALWriter::close`](super::WALWriter::close)
+/// was synced in full before it was closed, so its records are read without
+/// verifying their checksums. Reading ends at the seal.
+///
+/// Records of two-phase commits don't read as entries, apart from the
+/// writes a commit record carries; the reader collects their
+/// [`TransactionMarker`]s for [`take_transaction_markers`](Self::take_transaction_markers)
+/// instead.
+///
+/// Errors carry the path of the file and, once past the header, the offset
+/// of the record that could not be read.
+///
+/// Each `new` opens the file and validates its header again. To read a
+/// file many times, open it once as a [`WALFile`] and take readers from
+/// it.
+///
 /// # Example
 ///
 /// ```no_run
@@ -46,11 +64,17 @@ pub struct ReaderStats {
 /// # Ok::<(), ferrisdb_core::Error>(())
 /// ```
 pub struct WALReader {
-    reader: BufReader<File>,
-    header: WALHeader,
-    buffer: BytesMut,
-    metrics: Arc<WALMetrics>,
+    /// The file read, with its validated header
+    file: WALFile,
+    reader: BufReader<SharedCursor>,
+    buffer: PooledBuffer,
     stats: ReaderStats,
+    /// Offset just past the last complete record read so far
+    valid_len: u64,
+    /// Entries of the current batch record not yet returned
+    pending: VecDeque<WALEntry>,
+    /// Transaction records read since the markers were last taken
+    markers: Vec<TransactionMarker>,
 }
 
 impl WALReader {
@@ -69,8 +93,20 @@ impl WALReader {
         Self::with_initial_capacity(path, Self::DEFAULT_BUFFER_CAPACITY)
     }
 
+    /// Creates a WAL reader for a file opened through `vfs`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`new`](Self::new).
+    pub fn new_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(WALFile::open_in(vfs, path)?.reader())
+    }
+
     /// Creates a new WAL reader with specified initial buffer capacity
     ///
+    /// The buffer is checked out of the shared [`BufferPool`], rounded up to
+    /// its size class, and returned to it when the reader is dropped.
+    ///
     /// This allows tuning memory usage for different workloads:
     /// - Small capacity for memory-constrained environments
     /// - Large capacity for high-throughput reading
@@ -82,37 +118,33 @@ impl WALReader {
     /// - The header is missing or invalid
     /// - The file is corrupted
     pub fn with_initial_capacity(path: impl AsRef<Path>, initial_capacity: usize) -> Result<Self> {
-        let mut file = File::open(path)?;
-
-        // Read and validate header
-        let mut header_data = vec![0u8; crate::wal::WAL_HEADER_SIZE];
-        file.read_exact(&mut header_data)?;
-
-        let header = WALHeader::decode(&header_data)?;
-        // validate() is already called in decode()
-
-        // Seek to where entries begin
-        file.seek(SeekFrom::Start(header.entry_start_offset as u64))?;
+        let file = WALFile::open(path)?;
+        Ok(file.spawn(file.entry_start(), initial_capacity))
+    }
 
-        let metrics = Arc::new(WALMetrics::new());
-        metrics.record_file_opened();
+    /// Returns the file read, to take more readers from
+    pub fn file(&self) -> &WALFile {
+        &self.file
+    }
 
-        Ok(Self {
-            reader: BufReader::new(file),
-            header,
-            buffer: BytesMut::with_capacity(initial_capacity),
-            metrics,
-            stats: ReaderStats {
-                peak_buffer_size: 0,
-                buffer_resizes: 0,
-                initial_capacity,
-            },
-        })
+    /// Returns a reader of the same file starting at `offset`, with a
+    /// buffer of the same initial capacity
+    ///
+    /// `offset` must be where a record starts, such as the
+    /// [`valid_len`](Self::valid_len) of this reader. Entries of a batch
+    /// record this reader returned only some of are not read again.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `offset` lies within the header.
+    pub fn clone_at(&self, offset: u64) -> Result<Self> {
+        self.file.check_offset(offset)?;
+        Ok(self.file.spawn(offset, self.stats.initial_capacity))
     }
 
     /// Get the WAL file header
     pub fn header(&self) -> &WALHeader {
-        &self.header
+        &self.file.header
     }
 
     /// Get reader statistics for buffer management
@@ -122,7 +154,30 @@ impl WALReader {
 
     /// Get metrics for WAL operations
     pub fn metrics(&self) -> Arc<WALMetrics> {
-        Arc::clone(&self.metrics)
+        Arc::clone(&self.file.metrics)
+    }
+
+    /// Returns true if the file ends in a seal, having been closed cleanly
+    pub fn is_sealed(&self) -> bool {
+        self.file.is_sealed()
+    }
+
+    /// Returns the offset just past the last complete record read
+    ///
+    /// After reading up to an error or the end of the file, anything beyond
+    /// this offset is a torn or corrupted tail. A batch record counts as
+    /// read as soon as its first entry is returned.
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
+
+    /// Returns the markers of the transaction records read since the last
+    /// call, in the order they were logged
+    ///
+    /// A commit's marker is taken along with the first of its entries, or
+    /// with the entry after it if it has none.
+    pub fn take_transaction_markers(&mut self) -> Vec<TransactionMarker> {
+        std::mem::take(&mut self.markers)
     }
 
     /// Reads the next entry from the WAL using efficient buffer management
@@ -136,19 +191,50 @@ impl WALReader {
     /// - Corruption is detected (checksum mismatch)
     /// - The entry format is invalid
     pub fn read_entry(&mut self) -> Result<Option<WALEntry>> {
+        self.read_next()
+            .map_err(|e| e.with_path(&self.file.path).at_offset(self.valid_len))
+    }
+
+    fn read_next(&mut self) -> Result<Option<WALEntry>> {
+        if let Some(entry) = self.pending.pop_front() {
+            return Ok(Some(entry));
+        }
+        if let Some(sealed_len) = self.file.sealed_len {
+            if self.valid_len >= sealed_len {
+                self.valid_len = sealed_len + SEAL_SIZE as u64;
+                return Ok(None);
+            }
+        }
+
         // Read length
         let mut length_buf = [0u8; 4];
         match self.reader.read_exact(&mut length_buf) {
             Ok(_) => {}
             Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
             Err(e) => {
-                self.metrics.record_read(0, false);
+                self.file.metrics.record_read(0, false);
                 return Err(e.into());
             }
         }
 
         let length = u32::from_le_bytes(length_buf) as usize;
         let total_size = length + 4; // Include the length field
+        if length > MAX_BATCH_SIZE {
+            self.file.metrics.record_read(0, false);
+            return Err(Error::Corruption(format!(
+                "WAL record size {} exceeds maximum {}",
+                length, MAX_BATCH_SIZE
+            )));
+        }
+        if let Some(sealed_len) = self.file.sealed_len {
+            if self.valid_len + total_size as u64 > sealed_len {
+                self.file.metrics.record_read(0, false);
+                return Err(Error::Corruption(format!(
+                    "WAL record of {} bytes runs into the seal at offset {}",
+                    total_size, sealed_len
+                )));
+            }
+        }
 
         // Track buffer capacity before potential resize
         let capacity_before = self.buffer.capacity();
@@ -170,14 +256,40 @@ impl WALReader {
                 }
 
                 // Record successful read
-                self.metrics.record_read(total_size as u64, true);
-
-                // Decode the entry
-                let entry = WALEntry::decode(&self.buffer)?;
+                self.file.metrics.record_read(total_size as u64, true);
+
+                // Decode the entry, or all entries of a batch at once
+                let (version, verify) = (self.file.header.version, self.file.sealed_len.is_none());
+                if WALEntry::is_batch_record(&self.buffer) {
+                    self.pending = WALEntry::decode_batch_checked(&self.buffer, version, verify)
+                        .map_err(|e| with_checksums(e, &self.buffer))?
+                        .into();
+                    self.valid_len += total_size as u64;
+                    return Ok(self.pending.pop_front());
+                }
+                // The seal of a writer that was reopened and appended to
+                if WALEntry::is_seal_record(&self.buffer) {
+                    WALEntry::decode_seal(&self.buffer)
+                        .map_err(|e| with_checksums(e, &self.buffer))?;
+                    self.valid_len += total_size as u64;
+                    return self.read_next();
+                }
+                if WALEntry::is_transaction_record(&self.buffer) {
+                    let (marker, entries) =
+                        WALEntry::decode_transaction_checked(&self.buffer, version, verify)
+                            .map_err(|e| with_checksums(e, &self.buffer))?;
+                    self.markers.push(marker);
+                    self.pending = entries.into();
+                    self.valid_len += total_size as u64;
+                    return self.read_next();
+                }
+                let entry = WALEntry::decode_checked(&self.buffer, version, verify)
+                    .map_err(|e| with_checksums(e, &self.buffer))?;
+                self.valid_len += total_size as u64;
                 Ok(Some(entry))
             }
             Err(e) => {
-                self.metrics.record_read(total_size as u64, false);
+                self.file.metrics.record_read(total_size as u64, false);
                 Err(e.into())
             }
         }
@@ -196,6 +308,35 @@ impl WALReader {
     }
 }
 
+/// Adds the checksums of `record` to the error decoding it if they
+/// don't match
+fn with_checksums(error: Error, record: &[u8]) -> Error {
+    match checksum_mismatch(record) {
+        Some((expected, actual)) => error.with_checksums(expected, actual),
+        None => error,
+    }
+}
+
+/// Returns the offset of the seal `file` ends with, if it has one
+///
+/// A seal only counts at the very end, starting where it says it does.
+fn read_seal(file: &mut dyn VfsFile, entry_start: u64) -> Result<Option<u64>> {
+    let len = file.size()?;
+    let Some(sealed_len) = len.checked_sub(SEAL_SIZE as u64) else {
+        return Ok(None);
+    };
+    if sealed_len < entry_start {
+        return Ok(None);
+    }
+    let mut seal = [0u8; SEAL_SIZE];
+    file.seek(SeekFrom::Start(sealed_len))?;
+    file.read_exact(&mut seal)?;
+    Ok(match WALEntry::decode_seal(&seal) {
+        Ok((_, offset)) if offset == sealed_len => Some(sealed_len),
+        _ => None,
+    })
+}
+
 impl Iterator for WALReader {
     type Item = Result<WALEntry>;
 
@@ -208,6 +349,173 @@ impl Iterator for WALReader {
     }
 }
 
+/// An open WAL file whose header was validated, to take readers from
+///
+/// Opening reads and checks the header and looks for the seal once;
+/// [`reader`](Self::reader) then returns a reader without touching the
+/// file. Clones and readers share the file handle, header and
+/// [metrics](WALReader::metrics), while each reader keeps its own
+/// position, so any number of them can read the file at once.
+///
+/// Whether the file is sealed is decided when it is opened: readers of a
+/// segment still being written keep verifying checksums after it is
+/// closed.
+///
+/// # Example
+///
+/// ```no_run
+/// use ferrisdb_storage::wal::WALFile;
+///
+/// let file = WALFile::open("path/to/wal.log")?;
+/// let mut reader = file.reader();
+/// let first = reader.read_entry()?;
+///
+/// // Reads the rest from where the first reader is, leaving it there
+/// let rest = reader.clone_at(reader.valid_len())?.read_all()?;
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct WALFile {
+    path: PathBuf,
+    file: Arc<Mutex<Box<dyn VfsFile>>>,
+    header: WALHeader,
+    /// Offset of the seal the file ends with, if it was closed cleanly
+    sealed_len: Option<u64>,
+    metrics: Arc<WALMetrics>,
+}
+
+impl WALFile {
+    /// Opens the WAL file at `path` and validates its header
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`WALReader::new`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_in(&vfs::os(), path)
+    }
+
+    /// Opens the WAL file at `path` through `vfs` and validates its header
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`WALReader::new`].
+    pub fn open_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let open = || -> Result<(Box<dyn VfsFile>, WALHeader, Option<u64>)> {
+            let mut file = vfs.open(path)?;
+
+            // Read and validate header
+            let mut header_data = vec![0u8; crate::wal::WAL_HEADER_SIZE];
+            file.read_exact(&mut header_data)?;
+
+            let header = WALHeader::decode(&header_data)?;
+            // validate() is already called in decode()
+
+            let sealed_len = if header.version >= WAL_SEAL_VERSION {
+                read_seal(file.as_mut(), header.entry_start_offset as u64)?
+            } else {
+                None
+            };
+            Ok((file, header, sealed_len))
+        };
+        let (file, header, sealed_len) = open().map_err(|e| e.with_path(path))?;
+
+        let metrics = Arc::new(WALMetrics::new());
+        metrics.record_file_opened();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
+            header,
+            sealed_len,
+            metrics,
+        })
+    }
+
+    /// Returns the path the file was opened at
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the file's header
+    pub fn header(&self) -> &WALHeader {
+        &self.header
+    }
+
+    /// Returns true if the file ended in a seal when it was opened
+    pub fn is_sealed(&self) -> bool {
+        self.sealed_len.is_some()
+    }
+
+    /// Returns a reader starting at the first entry
+    pub fn reader(&self) -> WALReader {
+        self.spawn(self.entry_start(), WALReader::DEFAULT_BUFFER_CAPACITY)
+    }
+
+    /// Returns a reader starting at `offset`, which must be where a record
+    /// starts, such as another reader's [`valid_len`](WALReader::valid_len)
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `offset` lies within the header.
+    pub fn reader_at(&self, offset: u64) -> Result<WALReader> {
+        self.check_offset(offset)?;
+        Ok(self.spawn(offset, WALReader::DEFAULT_BUFFER_CAPACITY))
+    }
+
+    /// Returns the offset of the first entry
+    fn entry_start(&self) -> u64 {
+        self.header.entry_start_offset as u64
+    }
+
+    fn check_offset(&self, offset: u64) -> Result<()> {
+        if offset < self.entry_start() {
+            return Err(Error::InvalidArgument(format!(
+                "Offset {} of {} lies within its {}-byte header",
+                offset,
+                self.path.display(),
+                self.entry_start()
+            )));
+        }
+        Ok(())
+    }
+
+    fn spawn(&self, offset: u64, initial_capacity: usize) -> WALReader {
+        WALReader {
+            file: self.clone(),
+            reader: BufReader::new(SharedCursor {
+                file: Arc::clone(&self.file),
+                position: offset,
+            }),
+            buffer: BufferPool::shared().checkout(initial_capacity),
+            stats: ReaderStats {
+                peak_buffer_size: 0,
+                buffer_resizes: 0,
+                initial_capacity,
+            },
+            valid_len: offset,
+            pending: VecDeque::new(),
+            markers: Vec::new(),
+        }
+    }
+}
+
+/// A position of its own in a file shared by several readers
+struct SharedCursor {
+    file: Arc<Mutex<Box<dyn VfsFile>>>,
+    position: u64,
+}
+
+impl Read for SharedCursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.file.lock();
+        file.seek(SeekFrom::Start(self.position))?;
+        let read = file.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -256,6 +564,194 @@ mod tests {
         }
     }
 
+    /// Tests that valid_len stops at the last complete entry.
+    ///
+    /// This test verifies that:
+    /// - valid_len starts at the first entry and advances per entry read
+    /// - A torn final entry is not counted, so the file can be cut there
+    #[test]
+    fn valid_len_excludes_torn_final_entry() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let writer = WALWriter::new(&wal_path, SyncMode::Full, 1024 * 1024).unwrap();
+        for i in 0..2 {
+            let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), i).unwrap();
+            writer.append(&entry).unwrap();
+        }
+        let complete_len = writer.size();
+        drop(writer);
+
+        // Append half of another entry
+        let torn = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 2)
+            .unwrap()
+            .encode()
+            .unwrap();
+        let mut data = std::fs::read(&wal_path).unwrap();
+        data.extend_from_slice(&torn[..torn.len() / 2]);
+        std::fs::write(&wal_path, data).unwrap();
+
+        let mut reader = WALReader::new(&wal_path).unwrap();
+        assert_eq!(reader.valid_len(), crate::wal::WAL_HEADER_SIZE as u64);
+        assert!(reader.read_entry().unwrap().is_some());
+        assert!(reader.read_entry().unwrap().is_some());
+        assert!(reader.read_entry().is_err());
+        assert_eq!(reader.valid_len(), complete_len);
+    }
+
+    /// Tests that batch records are read back as their entries.
+    ///
+    /// This test verifies that:
+    /// - Entries of a batch are returned one at a time, in order
+    /// - A torn batch yields none of its entries, and valid_len stops
+    ///   before it
+    #[test]
+    fn batch_records_are_read_whole_or_not_at_all() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        let batch = |first: u64| -> Vec<WALEntry> {
+            (first..first + 3)
+                .map(|ts| WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), ts).unwrap())
+                .collect()
+        };
+
+        let writer = WALWriter::new(&wal_path, SyncMode::Full, 1024 * 1024).unwrap();
+        writer.append_batch(&batch(1)).unwrap();
+        let complete_len = writer.size();
+        drop(writer);
+
+        let torn = WALEntry::encode_batch(&batch(4)).unwrap();
+        let mut data = std::fs::read(&wal_path).unwrap();
+        data.extend_from_slice(&torn[..torn.len() - 10]);
+        std::fs::write(&wal_path, data).unwrap();
+
+        let mut reader = WALReader::new(&wal_path).unwrap();
+        for ts in 1..=3 {
+            assert_eq!(reader.read_entry().unwrap().unwrap().timestamp, ts);
+        }
+        assert!(reader.read_entry().is_err());
+        assert_eq!(reader.valid_len(), complete_len);
+    }
+
+    /// Tests that sealed files are read without verifying checksums.
+    ///
+    /// This test verifies that:
+    /// - A damaged checksum in a sealed file goes unnoticed, while the same
+    ///   damage fails an unsealed file
+    /// - Reading stops at the seal, and valid_len covers the seal
+    #[test]
+    fn sealed_file_skips_checksums_and_ends_at_the_seal() {
+        let temp_dir = TempDir::new().unwrap();
+        let write = |name: &str, seal: bool| {
+            let wal_path = temp_dir.path().join(name);
+            let writer = WALWriter::new(&wal_path, SyncMode::None, 1024 * 1024).unwrap();
+            for ts in 1..=3 {
+                let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), ts).unwrap();
+                writer.append(&entry).unwrap();
+            }
+            if seal {
+                writer.close().unwrap();
+            } else {
+                drop(writer);
+            }
+            // Damage the checksum of the first entry
+            let mut data = std::fs::read(&wal_path).unwrap();
+            data[crate::wal::WAL_HEADER_SIZE + 4] ^= 0xFF;
+            std::fs::write(&wal_path, &data).unwrap();
+            (wal_path, data.len() as u64)
+        };
+
+        let (sealed, len) = write("sealed.wal", true);
+        let mut reader = WALReader::new(&sealed).unwrap();
+        assert!(reader.is_sealed());
+        assert_eq!(reader.read_all().unwrap().len(), 3);
+        assert_eq!(reader.valid_len(), len);
+
+        let (unsealed, _) = write("unsealed.wal", false);
+        let mut reader = WALReader::new(&unsealed).unwrap();
+        assert!(!reader.is_sealed());
+        assert!(reader.read_entry().is_err());
+    }
+
+    /// Tests that a seal followed by more records no longer counts.
+    ///
+    /// This test verifies that:
+    /// - A file reopened and appended to after its seal is read in full
+    /// - The old seal in the middle is skipped, and valid_len covers it
+    #[test]
+    fn seal_in_the_middle_is_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        for ts in 1..=2 {
+            let writer = WALWriter::new(&wal_path, SyncMode::None, 1024 * 1024).unwrap();
+            let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), ts).unwrap();
+            writer.append(&entry).unwrap();
+            if ts == 1 {
+                writer.close().unwrap();
+            }
+        }
+
+        let mut reader = WALReader::new(&wal_path).unwrap();
+        assert!(!reader.is_sealed());
+        let timestamps: Vec<_> = reader
+            .read_all()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect();
+        assert_eq!(timestamps, [1, 2]);
+        assert_eq!(
+            reader.valid_len(),
+            std::fs::metadata(&wal_path).unwrap().len()
+        );
+    }
+
+    /// Tests that transaction records are read as markers.
+    ///
+    /// This test verifies that:
+    /// - Prepares and rollbacks return no entries, only their markers
+    /// - A commit returns its writes as entries, and its marker with them
+    /// - Markers are only returned once
+    #[test]
+    fn transaction_records_are_read_as_markers() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        let writer = WALWriter::new(&wal_path, SyncMode::None, 1024 * 1024).unwrap();
+        let put = |key: &[u8], timestamp| {
+            WALEntry::new_put(key.to_vec(), b"value".to_vec(), timestamp).unwrap()
+        };
+        writer.append(&put(b"a", 1)).unwrap();
+        writer
+            .append_prepare("t1", &[put(b"b", 0), put(b"c", 1)], SyncMode::None)
+            .unwrap();
+        writer
+            .append_prepare("t2", &[put(b"d", 0)], SyncMode::None)
+            .unwrap();
+        writer.append_rollback("t2", SyncMode::None).unwrap();
+        writer
+            .append_commit("t1", &[put(b"b", 2), put(b"c", 3)], SyncMode::None)
+            .unwrap();
+        assert_eq!(writer.last_timestamp(), 3);
+        writer.close().unwrap();
+
+        let mut reader = WALReader::new(&wal_path).unwrap();
+        assert_eq!(reader.read_entry().unwrap().unwrap().key, b"a");
+        assert!(reader.take_transaction_markers().is_empty());
+        assert_eq!(reader.read_entry().unwrap().unwrap().key, b"b");
+        let markers = reader.take_transaction_markers();
+        let names: Vec<_> = markers.iter().map(|marker| marker.name()).collect();
+        assert_eq!(names, ["t1", "t2", "t2", "t1"]);
+        assert!(
+            matches!(&markers[0], TransactionMarker::Prepare { entries, .. } if entries.len() == 2)
+        );
+        assert!(matches!(markers[2], TransactionMarker::Rollback { .. }));
+        assert!(matches!(markers[3], TransactionMarker::Commit { .. }));
+
+        assert_eq!(reader.read_entry().unwrap().unwrap().timestamp, 3);
+        assert!(reader.read_entry().unwrap().is_none());
+        assert!(reader.take_transaction_markers().is_empty());
+    }
+
     /// Tests that iterator interface yields entries in correct write order.
     ///
     /// This test verifies that:
@@ -369,5 +865,57 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         assert!(err.to_string().contains("Invalid WAL magic"));
+        assert_eq!(err.code(), ferrisdb_core::error::ErrorCode::Corruption);
+        assert_eq!(
+            err.context().unwrap().path.as_deref(),
+            Some(wal_path.as_path())
+        );
+    }
+
+    /// Tests that readers taken from one open file read independently.
+    ///
+    /// This test verifies that:
+    /// - A reader cloned at another's valid_len reads on from there
+    /// - Readers on several threads each read every entry
+    /// - Offsets within the header are rejected
+    #[test]
+    fn readers_of_one_file_keep_positions_of_their_own() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        let writer = WALWriter::new(&wal_path, SyncMode::Full, 1024 * 1024).unwrap();
+        for i in 0..10u64 {
+            let entry = WALEntry::new_put(vec![i as u8], b"value".to_vec(), i + 1).unwrap();
+            writer.append(&entry).unwrap();
+        }
+        writer.close().unwrap();
+
+        let file = WALFile::open(&wal_path).unwrap();
+        assert!(file.is_sealed());
+        let mut first = file.reader();
+        for i in 0..3 {
+            assert_eq!(first.read_entry().unwrap().unwrap().key, [i]);
+        }
+        let mut rest = first.clone_at(first.valid_len()).unwrap();
+        assert_eq!(rest.read_entry().unwrap().unwrap().key, [3]);
+        assert_eq!(first.read_entry().unwrap().unwrap().key, [3]);
+        assert_eq!(rest.read_all().unwrap().len(), 6);
+        assert_eq!(first.read_all().unwrap().len(), 6);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let file = file.clone();
+                std::thread::spawn(move || file.reader().read_all().unwrap())
+            })
+            .collect();
+        for handle in handles {
+            let entries = handle.join().unwrap();
+            let keys: Vec<_> = entries.iter().map(|entry| entry.key[0]).collect();
+            assert_eq!(keys, (0..10).collect::<Vec<u8>>());
+        }
+        // Opened once, however many readers were taken
+        assert_eq!(file.reader().metrics().files_opened(), 1);
+
+        let err = file.reader_at(1).err().unwrap();
+        assert!(matches!(err, Error::InvalidArgument(_)));
     }
 }
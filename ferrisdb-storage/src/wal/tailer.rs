@@ -0,0 +1,280 @@
+use super::cache::{Lookup, WALCache};
+use super::{WALEntry, WALHeader, WAL_CURRENT_VERSION, WAL_HEADER_SIZE};
+use crate::format::FileHeader;
+use crate::vfs::{self, Vfs, VfsFile};
+use crate::wal::log_entry::MAX_BATCH_SIZE;
+use ferrisdb_core::{Error, Result, Timestamp};
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Follows the WAL of a live engine, returning records as they are written
+///
+/// A tailer starts at a timestamp and reads the segments of one or more
+/// directories (typically the WAL directory and the WAL archive) in
+/// segment order. Unlike [`WALReader`](super::WALReader), reaching the end
+/// of the newest segment isn't the end: [`poll`](Self::poll) returns
+/// `None` and picks up where it stopped on the next call, moving on to the
+/// next segment once the writer has rotated to it.
+///
+/// Records come back whole, so the entries of a batch are never split
+/// over two polls. Every segment records the last write before it, and
+/// one continuing from a write the tailer hasn't returned means the
+/// segments holding the missing writes were deleted; polling fails instead
+/// of skipping them. Of a two-phase commit, only the commit record
+/// returns entries, the transaction's writes. With `SyncMode::None` the
+/// writer buffers records, and the tailer sees them only once the buffer
+/// is flushed.
+///
+/// A tailer [given](Self::with_cache) the engine's [`WALCache`] reads the
+/// records it holds from memory, as soon as they are appended, and only
+/// reads the files while it lags behind them.
+///
+/// # Example
+///
+/// ```no_run
+/// use ferrisdb_storage::wal::WALTailer;
+///
+/// let mut tailer = WALTailer::new(vec!["./data/wal".into()], 1);
+/// while let Some(entries) = tailer.poll()? {
+///     println!("{} entries up to {}", entries.len(), tailer.next_timestamp() - 1);
+/// }
+/// # Ok::<(), ferrisdb_core::Error>(())
+/// ```
+pub struct WALTailer {
+    /// Filesystem holding the segments
+    vfs: Arc<dyn Vfs>,
+    dirs: Vec<PathBuf>,
+    /// The segment being read
+    segment: Option<Box<dyn VfsFile>>,
+    /// Number of the segment opened last
+    segment_number: Option<u64>,
+    /// Offset of the next record in the segment
+    offset: u64,
+    /// Format version of the segment, which decides how records decode
+    version: u16,
+    /// Timestamp of the next entry to return
+    next: Timestamp,
+    /// Recent records, read instead of the files where they are held
+    cache: Option<Arc<WALCache>>,
+}
+
+impl WALTailer {
+    /// Creates a tailer returning entries from timestamp `from` on
+    ///
+    /// Nothing is opened until the first poll.
+    pub fn new(dirs: Vec<PathBuf>, from: Timestamp) -> Self {
+        Self::new_in(&vfs::os(), dirs, from)
+    }
+
+    /// Creates a tailer returning entries from timestamp `from` on, with
+    /// the segments read through `vfs`
+    pub fn new_in(vfs: &Arc<dyn Vfs>, dirs: Vec<PathBuf>, from: Timestamp) -> Self {
+        Self {
+            vfs: Arc::clone(vfs),
+            dirs,
+            segment: None,
+            segment_number: None,
+            offset: 0,
+            version: WAL_CURRENT_VERSION,
+            next: from.max(1),
+            cache: None,
+        }
+    }
+
+    /// Reads the records `cache` holds from it rather than from the files
+    ///
+    /// The cache must be that of the WAL in the tailer's directories.
+    pub fn with_cache(mut self, cache: Arc<WALCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Returns the timestamp the next entry returned is at or after
+    pub fn next_timestamp(&self) -> Timestamp {
+        self.next
+    }
+
+    /// Returns the entries of the next record, or `None` if there is none
+    /// yet
+    ///
+    /// Entries before the start timestamp are skipped, so the first record
+    /// returned may be the tail of a batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidOperation` if the WAL no longer holds the
+    /// next timestamp, `Error::Corruption` for a damaged record or one cut
+    /// short in a segment the writer has moved on from, or an I/O error.
+    pub fn poll(&mut self) -> Result<Option<Vec<WALEntry>>> {
+        if let Some(cache) = &self.cache {
+            match cache.read(self.next) {
+                Lookup::Record(segment, entries) => {
+                    // Should the tailer fall behind the cache, it goes on
+                    // from the segment of this record
+                    self.segment = None;
+                    self.segment_number = segment.checked_sub(1);
+                    if let Some(last) = entries.last() {
+                        self.next = last.timestamp + 1;
+                    }
+                    return Ok(Some(entries));
+                }
+                Lookup::CaughtUp => return Ok(None),
+                Lookup::Evicted => {}
+            }
+        }
+        loop {
+            if self.segment.is_none() && !self.open_next_segment()? {
+                return Ok(None);
+            }
+            let record = match self.read_record()? {
+                Some(record) => record,
+                None => {
+                    if self.next_segment()?.is_none() {
+                        return Ok(None);
+                    }
+                    // The writer finishes a segment before starting the
+                    // next, so whatever this one holds now is all of it
+                    match self.read_record()? {
+                        Some(record) => record,
+                        None => {
+                            self.finish_segment()?;
+                            continue;
+                        }
+                    }
+                }
+            };
+            let entries = self.accept(&record)?;
+            if !entries.is_empty() {
+                return Ok(Some(entries));
+            }
+        }
+    }
+
+    /// Returns the oldest segment after the one opened last
+    fn next_segment(&self) -> Result<Option<(u64, PathBuf)>> {
+        let mut next: Option<(u64, PathBuf)> = None;
+        for dir in &self.dirs {
+            let segments = match super::wal_segments_in(&self.vfs, dir) {
+                Ok(segments) => segments,
+                Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let newer = segments
+                .into_iter()
+                .find(|(number, _)| self.segment_number.map_or(true, |last| *number > last));
+            if let Some(segment) = newer {
+                if next
+                    .as_ref()
+                    .map_or(true, |(number, _)| segment.0 < *number)
+                {
+                    next = Some(segment);
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    /// Opens the next segment, returning false if there is none yet
+    fn open_next_segment(&mut self) -> Result<bool> {
+        let (number, mut file) = loop {
+            let Some((number, path)) = self.next_segment()? else {
+                return Ok(false);
+            };
+            match self.vfs.open(&path) {
+                Ok(file) => break (number, file),
+                // Retired between listing and opening; list again
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        if !read_full(&mut file, &mut header)? {
+            // Just created, the header isn't written yet
+            return Ok(false);
+        }
+        let header = WALHeader::decode(&header)?;
+        if header.previous_timestamp >= self.next {
+            return Err(Error::InvalidOperation(format!(
+                "WAL entries from timestamp {} on are no longer available",
+                self.next
+            )));
+        }
+        self.offset = header.entry_start_offset as u64;
+        self.version = header.version;
+        self.segment = Some(file);
+        self.segment_number = Some(number);
+        Ok(true)
+    }
+
+    /// Reads the record at the current offset if it is complete
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
+        let file = self.segment.as_mut().expect("a segment is open");
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut length = [0u8; 4];
+        if !read_full(file, &mut length)? {
+            return Ok(None);
+        }
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_BATCH_SIZE {
+            return Err(Error::Corruption(format!(
+                "WAL record size {} exceeds maximum {}",
+                length, MAX_BATCH_SIZE
+            )));
+        }
+        let mut record = vec![0u8; length + 4];
+        record[..4].copy_from_slice(&(length as u32).to_le_bytes());
+        if !read_full(file, &mut record[4..])? {
+            return Ok(None);
+        }
+        self.offset += record.len() as u64;
+        Ok(Some(record))
+    }
+
+    /// Closes a segment the writer has moved on from
+    fn finish_segment(&mut self) -> Result<()> {
+        let file = self.segment.take().expect("a segment is open");
+        let len = file.size()?;
+        if len > self.offset {
+            return Err(Error::Corruption(format!(
+                "WAL segment {} ends in a partial record at offset {}",
+                self.segment_number.unwrap_or_default(),
+                self.offset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decodes a record, keeping the entries from the next timestamp on
+    fn accept(&mut self, record: &[u8]) -> Result<Vec<WALEntry>> {
+        let entries = if WALEntry::is_batch_record(record) {
+            WALEntry::decode_batch_version(record, self.version)?
+        } else if WALEntry::is_seal_record(record) {
+            WALEntry::decode_seal(record)?;
+            Vec::new()
+        } else if WALEntry::is_transaction_record(record) {
+            // Only a commit's writes happened; a prepare's are placeholders
+            WALEntry::decode_transaction_checked(record, self.version, true)?.1
+        } else {
+            vec![WALEntry::decode_version(record, self.version)?]
+        };
+        let entries: Vec<_> = entries
+            .into_iter()
+            .filter(|entry| entry.timestamp >= self.next)
+            .collect();
+        if let Some(last) = entries.last() {
+            self.next = last.timestamp + 1;
+        }
+        Ok(entries)
+    }
+}
+
+/// Fills `buf`, returning false if the file ends first
+fn read_full(file: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,15 +1,15 @@
 use super::{TimedOperation, WALEntry, WALHeader, WALMetrics};
 use crate::format::FileHeader;
-use ferrisdb_core::{Error, Result, SyncMode};
+use crate::vfs::{self, Vfs, VfsFile};
+use ferrisdb_core::{Error, Result, SyncMode, Timestamp};
 
 use parking_lot::Mutex;
 
-use std::fs::{File, OpenOptions};
 use std::io::{BufWriter, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
 /// Writer for the Write-Ahead Log
 ///
@@ -40,11 +40,16 @@ use std::time::{SystemTime, UNIX_EPOCH};
 /// # Ok::<(), ferrisdb_core::Error>(())
 /// ```
 pub struct WALWriter {
-    file: Arc<Mutex<BufWriter<File>>>,
+    file: Arc<Mutex<BufWriter<Box<dyn VfsFile>>>>,
     path: PathBuf,
     size: AtomicU64,
     sync_mode: SyncMode,
     size_limit: u64,
+    /// Timestamp of the newest entry appended, or the write the segment
+    /// continues from before the first
+    last_timestamp: AtomicU64,
+    /// How long the latest sync took, in nanoseconds
+    last_sync_ns: AtomicU64,
     metrics: Arc<WALMetrics>,
 }
 
@@ -61,41 +66,71 @@ impl WALWriter {
     ///
     /// Returns an error if the file cannot be created or opened.
     pub fn new(path: impl AsRef<Path>, sync_mode: SyncMode, size_limit: u64) -> Result<Self> {
+        Self::new_after(path, sync_mode, size_limit, 0)
+    }
+
+    /// Creates a WAL writer for a segment continuing after the write at
+    /// timestamp `previous`
+    ///
+    /// A new file records `previous` in its header, so readers can tell
+    /// that no segment in between is missing; an existing file keeps its
+    /// header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or opened.
+    pub fn new_after(
+        path: impl AsRef<Path>,
+        sync_mode: SyncMode,
+        size_limit: u64,
+        previous: Timestamp,
+    ) -> Result<Self> {
+        Self::new_in(&vfs::os(), path, sync_mode, size_limit, previous)
+    }
+
+    /// Creates a WAL writer like [`new_after`](Self::new_after), with the
+    /// file and the clock for its sequence number provided by `vfs`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or opened.
+    pub fn new_in(
+        vfs: &Arc<dyn Vfs>,
+        path: impl AsRef<Path>,
+        sync_mode: SyncMode,
+        size_limit: u64,
+        previous: Timestamp,
+    ) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
 
         // Create parent directories if they exist
         if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+            vfs.create_dir_all(parent)?;
         }
 
         // Check if this is a new file that needs a header
-        let needs_header = !path.exists() || std::fs::metadata(&path)?.len() == 0;
+        let needs_header = !vfs.exists(&path) || vfs.file_size(&path)? == 0;
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .truncate(false) // Don't truncate existing files
-            .read(true)
-            .write(true)
-            .open(&path)?;
+        // Full syncs write through where the platform can, rather than
+        // leave each sync the whole write to flush
+        let full = sync_mode == SyncMode::Full;
+        let mut file = vfs::with_write_through(vfs, full).open_or_create(&path)?;
 
-        let mut size = file.metadata()?.len();
+        let mut size = file.size()?;
 
         // Write header to new/empty files
         if needs_header {
             // Generate file sequence based on timestamp
-            let file_sequence = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_else(|_| {
-                    // Fallback: use a random number for uniqueness
-                    std::time::Duration::from_nanos(rand::random::<u64>())
-                })
-                .as_micros() as u64;
-
-            let header = WALHeader::new(file_sequence);
+            let file_sequence = vfs.clock().now().as_micros() as u64;
+
+            let header = WALHeader::new(file_sequence).with_previous_timestamp(previous);
             let encoded = header.encode();
 
             file.write_all(&encoded)?;
             file.sync_all()?;
+            // Writes synced to the file are lost with it if a crash
+            // forgets its directory entry
+            vfs::sync_parent_dir(vfs.as_ref(), &path)?;
 
             size = crate::wal::WAL_HEADER_SIZE as u64;
         }
@@ -113,10 +148,69 @@ impl WALWriter {
             size: AtomicU64::new(size),
             sync_mode,
             size_limit,
+            last_timestamp: AtomicU64::new(previous),
+            last_sync_ns: AtomicU64::new(0),
             metrics,
         })
     }
 
+    /// Creates an empty file to become a WAL segment later, synced along
+    /// with its directory entry
+    ///
+    /// Creating and syncing a file is the slow part of starting a segment;
+    /// doing it ahead of time leaves [`from_preallocated_in`](Self::from_preallocated_in)
+    /// only a rename and the header to write. An existing file at `path`
+    /// is truncated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or synced.
+    pub fn preallocate_in(vfs: &Arc<dyn Vfs>, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            vfs.create_dir_all(parent)?;
+        }
+        vfs.create(path)?.sync_all()?;
+        vfs::sync_parent_dir(vfs.as_ref(), path)?;
+        Ok(())
+    }
+
+    /// Creates a WAL writer like [`new_in`](Self::new_in), for a segment
+    /// renamed into place from a file made by [`preallocate_in`](Self::preallocate_in)
+    ///
+    /// If Windows refuses the rename because another program holds the
+    /// file open, the segment is created in its place instead and the
+    /// preallocated file is left to be recreated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rename fails for any other reason, if the
+    /// segment can't be created in its place, or if its header cannot be
+    /// written.
+    pub fn from_preallocated_in(
+        vfs: &Arc<dyn Vfs>,
+        preallocated: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        sync_mode: SyncMode,
+        size_limit: u64,
+        previous: Timestamp,
+    ) -> Result<Self> {
+        let (preallocated, path) = (preallocated.as_ref(), path.as_ref());
+        match vfs.rename(preallocated, path) {
+            Ok(()) => {}
+            Err(e) if vfs::is_held_open(&e) => {
+                log::warn!(
+                    "Preallocated WAL segment {} is held open, creating {}: {}",
+                    preallocated.display(),
+                    path.display(),
+                    e
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Self::new_in(vfs, path, sync_mode, size_limit, previous)
+    }
+
     /// Appends an entry to the WAL
     ///
     /// The entry is encoded and written to the file. Depending on the
@@ -128,39 +222,141 @@ impl WALWriter {
     /// - The entry would exceed the size limit
     /// - An I/O error occurs during write
     pub fn append(&self, entry: &WALEntry) -> Result<()> {
-        let encoded = entry.encode()?;
+        self.write_record(&entry.encode()?, self.sync_mode)?;
+        self.last_timestamp
+            .fetch_max(entry.timestamp, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Appends several entries as one atomic batch record
+    ///
+    /// Recovery replays either all of the entries or none of them. The
+    /// entries must carry consecutive timestamps. A single entry is
+    /// appended as a plain entry, and an empty batch writes nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`append`](Self::append),
+    /// or if the timestamps are not consecutive.
+    pub fn append_batch(&self, entries: &[WALEntry]) -> Result<()> {
+        self.append_batch_with_sync_mode(entries, self.sync_mode)
+    }
+
+    /// Appends several entries as one atomic batch record, syncing it as
+    /// `sync_mode` says instead of the writer's own sync mode
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`append_batch`](Self::append_batch).
+    pub fn append_batch_with_sync_mode(
+        &self,
+        entries: &[WALEntry],
+        sync_mode: SyncMode,
+    ) -> Result<()> {
+        let encoded = match entries {
+            [] => return Ok(()),
+            [entry] => entry.encode()?,
+            entries => WALEntry::encode_batch(entries)?,
+        };
+        self.write_record(&encoded, sync_mode)?;
+        let newest = entries.iter().map(|entry| entry.timestamp).max();
+        self.last_timestamp
+            .fetch_max(newest.unwrap_or_default(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Appends the record preparing transaction `name`, whose writes carry
+    /// their position in the transaction, from 0, as their timestamp
+    ///
+    /// The writes aren't committed by this: readers collect them as a
+    /// [`TransactionMarker::Prepare`](super::TransactionMarker::Prepare)
+    /// until a commit or rollback record for the same name follows.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`append`](Self::append),
+    /// or if the name is empty or too long.
+    pub fn append_prepare(
+        &self,
+        name: &str,
+        entries: &[WALEntry],
+        sync_mode: SyncMode,
+    ) -> Result<()> {
+        let encoded = WALEntry::encode_prepare(name, self.last_timestamp(), entries)?;
+        self.write_record(&encoded, sync_mode)
+    }
+
+    /// Appends the record committing prepared transaction `name` with its
+    /// writes, which must carry consecutive timestamps
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`append_prepare`](Self::append_prepare),
+    /// or if the timestamps are not consecutive.
+    pub fn append_commit(
+        &self,
+        name: &str,
+        entries: &[WALEntry],
+        sync_mode: SyncMode,
+    ) -> Result<()> {
+        let first = entries
+            .first()
+            .map_or(self.last_timestamp(), |entry| entry.timestamp);
+        self.write_record(&WALEntry::encode_commit(name, first, entries)?, sync_mode)?;
+        if let Some(last) = entries.last() {
+            self.last_timestamp
+                .fetch_max(last.timestamp, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Appends the record rolling back prepared transaction `name`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for the same reasons as [`append_prepare`](Self::append_prepare).
+    pub fn append_rollback(&self, name: &str, sync_mode: SyncMode) -> Result<()> {
+        let encoded = WALEntry::encode_rollback(name, self.last_timestamp())?;
+        self.write_record(&encoded, sync_mode)
+    }
+
+    /// Writes an encoded record and syncs it according to `sync_mode`
+    fn write_record(&self, encoded: &[u8], sync_mode: SyncMode) -> Result<()> {
         let entry_size = encoded.len() as u64;
 
         // Check if we need to rotate
         if self.size.load(Ordering::Relaxed) + entry_size > self.size_limit {
             self.metrics.record_write(entry_size, false);
-            return Err(Error::StorageEngine(
-                "WAL file size limit reached".to_string(),
-            ));
+            return Err(Error::SizeLimitExceeded(format!(
+                "WAL file would exceed {} bytes",
+                self.size_limit
+            )));
         }
 
+        let write_timer = TimedOperation::start();
         let mut file = self.file.lock();
-        match file.write_all(&encoded) {
+        match file.write_all(encoded) {
             Ok(_) => {
                 // Handle sync with timing
-                match self.sync_mode {
+                match sync_mode {
                     SyncMode::None => {}
                     SyncMode::Normal => {
                         let timer = TimedOperation::start();
                         file.flush()?;
-                        self.metrics.record_sync(timer.complete());
+                        self.record_sync(timer.elapsed());
                     }
                     SyncMode::Full => {
                         let timer = TimedOperation::start();
                         file.flush()?;
                         file.get_ref().sync_all()?;
-                        self.metrics.record_sync(timer.complete());
+                        self.record_sync(timer.elapsed());
                     }
                 }
 
                 let new_size = self.size.fetch_add(entry_size, Ordering::Relaxed) + entry_size;
                 self.metrics.record_write(entry_size, true);
                 self.metrics.update_file_size(new_size);
+                self.metrics.record_write_duration(write_timer.elapsed());
                 Ok(())
             }
             Err(e) => {
@@ -179,15 +375,67 @@ impl WALWriter {
         let mut file = self.file.lock();
         file.flush()?;
         file.get_ref().sync_all()?;
-        self.metrics.record_sync(timer.complete());
+        self.record_sync(timer.elapsed());
         Ok(())
     }
 
+    /// Syncs the segment and ends it with a seal record, recording that
+    /// it was closed cleanly
+    ///
+    /// Readers of a sealed segment know every record before the seal was
+    /// synced, so they skip checking the records' checksums. A writer that
+    /// is just dropped leaves no seal, and its segment is read in full.
+    /// Nothing may be appended to a sealed segment, which is why closing
+    /// consumes the writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment cannot be written or synced. It
+    /// is left unsealed then, or sealed without the seal synced.
+    pub fn close(self) -> Result<()> {
+        let timer = TimedOperation::start();
+        let mut file = self.file.lock();
+        // The seal vouches for what comes before it, so that is synced first
+        file.flush()?;
+        file.get_ref().sync_all()?;
+
+        let seal = WALEntry::encode_seal(self.last_timestamp(), self.size());
+        file.write_all(&seal)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        self.record_sync(timer.elapsed());
+        Ok(())
+    }
+
+    /// Returns how long the latest sync of the segment took, or zero
+    /// before the first
+    ///
+    /// A caller that appends with syncing while it keeps others from
+    /// writing learns how long its own record took to sync.
+    pub fn last_sync_duration(&self) -> Duration {
+        Duration::from_nanos(self.last_sync_ns.load(Ordering::Relaxed))
+    }
+
+    fn record_sync(&self, duration: Duration) {
+        self.last_sync_ns
+            .store(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.metrics.record_sync_duration(duration);
+    }
+
     /// Returns the current size of the WAL file
     pub fn size(&self) -> u64 {
         self.size.load(Ordering::Relaxed)
     }
 
+    /// Returns the timestamp of the newest entry appended, or the one the
+    /// segment continues from if none was
+    ///
+    /// For an existing file opened to append to, only entries appended
+    /// through this writer count.
+    pub fn last_timestamp(&self) -> Timestamp {
+        self.last_timestamp.load(Ordering::Relaxed)
+    }
+
     /// Returns the path to the WAL file
     pub fn path(&self) -> &Path {
         &self.path
@@ -197,11 +445,23 @@ impl WALWriter {
     pub fn metrics(&self) -> &WALMetrics {
         &self.metrics
     }
+
+    /// Records into `metrics` instead of metrics of its own
+    ///
+    /// Lets the writers of successive segments add up into one set of
+    /// metrics; the current file size is that of the newest writer.
+    pub fn with_metrics(mut self, metrics: Arc<WALMetrics>) -> Self {
+        metrics.record_file_opened();
+        metrics.update_file_size(self.size());
+        self.metrics = metrics;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ferrisdb_core::error::ErrorCode;
     use tempfile::TempDir;
 
     /// Tests that creating a new WAL writer properly initializes the file.
@@ -272,6 +532,149 @@ mod tests {
         assert_eq!(&data[0..8], crate::wal::WAL_MAGIC);
     }
 
+    /// Tests that a preallocated file becomes a segment in place.
+    ///
+    /// Verifies:
+    /// - The preallocated file is empty until it is used
+    /// - It is renamed to the segment's path and given a header
+    /// - Entries appended afterwards are read back
+    #[test]
+    fn from_preallocated_renames_the_file_and_writes_its_header() {
+        let temp_dir = TempDir::new().unwrap();
+        let vfs = vfs::os();
+        let preallocated = temp_dir.path().join("wal").join("next.tmp");
+        let wal_path = temp_dir.path().join("wal").join("000007.wal");
+
+        WALWriter::preallocate_in(&vfs, &preallocated).unwrap();
+        assert_eq!(std::fs::metadata(&preallocated).unwrap().len(), 0);
+
+        let writer = WALWriter::from_preallocated_in(
+            &vfs,
+            &preallocated,
+            &wal_path,
+            SyncMode::Full,
+            1024 * 1024,
+            41,
+        )
+        .unwrap();
+        assert!(!preallocated.exists());
+        assert_eq!(writer.size(), crate::wal::WAL_HEADER_SIZE as u64);
+        assert_eq!(writer.last_timestamp(), 41);
+        writer
+            .append(&WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 42).unwrap())
+            .unwrap();
+
+        let mut reader = crate::wal::WALReader::new(&wal_path).unwrap();
+        assert_eq!(reader.header().previous_timestamp, 41);
+        let entries = reader.read_all().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].timestamp, 42);
+    }
+
+    /// Tests that a rename failing for other reasons than the file being
+    /// held open fails the rotation.
+    ///
+    /// Verifies:
+    /// - A missing preallocated file is reported rather than papered over
+    /// - No segment is created in its place
+    #[test]
+    fn from_preallocated_reports_a_missing_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let vfs = vfs::os();
+        let preallocated = temp_dir.path().join("wal").join("next.tmp");
+        let wal_path = temp_dir.path().join("wal").join("000008.wal");
+        std::fs::create_dir_all(wal_path.parent().unwrap()).unwrap();
+
+        let result = WALWriter::from_preallocated_in(
+            &vfs,
+            &preallocated,
+            &wal_path,
+            SyncMode::Full,
+            1024 * 1024,
+            7,
+        );
+        assert!(matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound));
+        assert!(!wal_path.exists());
+    }
+
+    /// Tests that a segment is still started when another program holds
+    /// its preallocated file open.
+    ///
+    /// Verifies:
+    /// - Windows refusing the rename doesn't fail the rotation
+    /// - The segment is created in place with its header
+    #[cfg(windows)]
+    #[test]
+    fn from_preallocated_creates_the_segment_while_the_file_is_held_open() {
+        let temp_dir = TempDir::new().unwrap();
+        let vfs = vfs::os();
+        let preallocated = temp_dir.path().join("wal").join("next.tmp");
+        let wal_path = temp_dir.path().join("wal").join("000008.wal");
+        WALWriter::preallocate_in(&vfs, &preallocated).unwrap();
+        // Opened without FILE_SHARE_DELETE, as a virus scanner might
+        let _held = {
+            use std::os::windows::fs::OpenOptionsExt;
+            std::fs::OpenOptions::new()
+                .read(true)
+                .share_mode(0)
+                .open(&preallocated)
+                .unwrap()
+        };
+
+        let writer = WALWriter::from_preallocated_in(
+            &vfs,
+            &preallocated,
+            &wal_path,
+            SyncMode::Full,
+            1024 * 1024,
+            7,
+        )
+        .unwrap();
+        assert_eq!(writer.size(), crate::wal::WAL_HEADER_SIZE as u64);
+        assert!(preallocated.exists());
+        let reader = crate::wal::WALReader::new(&wal_path).unwrap();
+        assert_eq!(reader.header().previous_timestamp, 7);
+    }
+
+    /// Tests that closing a writer seals its file.
+    ///
+    /// Verifies:
+    /// - Close appends the seal after the entries, recording the newest
+    ///   timestamp and where the seal starts
+    /// - Readers see the file as sealed and read back every entry
+    /// - A writer that is only dropped leaves no seal
+    #[test]
+    fn close_appends_seal_after_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+
+        let writer = WALWriter::new(&wal_path, SyncMode::None, 1024 * 1024).unwrap();
+        for ts in 1..=3 {
+            let entry = WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), ts).unwrap();
+            writer.append(&entry).unwrap();
+        }
+        let size = writer.size();
+        writer.close().unwrap();
+
+        let data = std::fs::read(&wal_path).unwrap();
+        assert_eq!(
+            data[size as usize..],
+            WALEntry::encode_seal(3, size)[..],
+            "the seal follows the last entry"
+        );
+        let mut reader = crate::wal::WALReader::new(&wal_path).unwrap();
+        assert!(reader.is_sealed());
+        assert_eq!(reader.read_all().unwrap().len(), 3);
+
+        let dropped = temp_dir.path().join("dropped.wal");
+        let writer = WALWriter::new(&dropped, SyncMode::Full, 1024 * 1024).unwrap();
+        writer
+            .append(&WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 1).unwrap())
+            .unwrap();
+        drop(writer);
+        assert!(!crate::wal::WALReader::new(&dropped).unwrap().is_sealed());
+    }
+
     /// Tests that initial file size tracking starts at header size.
     ///
     /// This ensures:
@@ -372,32 +775,34 @@ mod tests {
     /// - Respects file system permissions
     #[test]
     fn new_returns_error_when_file_exists_but_not_writable() {
-        use std::fs::{self, File};
-        // Skip when running as root because permissions checks won't fail
-        #[cfg(unix)]
-        {
-            if unsafe { libc::geteuid() } == 0 {
-                eprintln!("Skipping test as root user");
-                return;
-            }
-        }
+        use std::fs::{self, File, OpenOptions};
 
         let temp_dir = TempDir::new().unwrap();
         let wal_path = temp_dir.path().join("readonly.wal");
 
-        // Create a read-only file using cross-platform API
+        // Create a read-only file using cross-platform API: the read-only
+        // attribute on Windows, the permission bits elsewhere
         File::create(&wal_path).unwrap();
         let mut perms = fs::metadata(&wal_path).unwrap().permissions();
         perms.set_readonly(true);
         fs::set_permissions(&wal_path, perms).unwrap();
 
+        // Privileged users, such as root on Unix, can write it anyway
+        let enforced = OpenOptions::new().write(true).open(&wal_path).is_err();
         let result = WALWriter::new(&wal_path, SyncMode::Full, 1024 * 1024);
 
         // Restore permissions for cleanup
-        let mut perms = fs::metadata(&wal_path).unwrap().permissions();
-        perms.set_readonly(false);
-        fs::set_permissions(&wal_path, perms).unwrap();
+        #[allow(clippy::permissions_set_readonly_false)]
+        {
+            let mut perms = fs::metadata(&wal_path).unwrap().permissions();
+            perms.set_readonly(false);
+            fs::set_permissions(&wal_path, perms).unwrap();
+        }
 
+        if !enforced {
+            eprintln!("Skipping test as permissions aren't enforced for this user");
+            return;
+        }
         assert!(result.is_err());
     }
 
@@ -470,7 +875,7 @@ mod tests {
                     last_successful_size = writer.size();
                 }
                 Err(e) => {
-                    assert!(e.to_string().contains("size limit"));
+                    assert!(matches!(e, Error::SizeLimitExceeded(_)), "{}", e);
                     break;
                 }
             }
@@ -532,6 +937,30 @@ mod tests {
         assert_eq!(actual_size, expected_size);
     }
 
+    /// Tests that the last timestamp follows appends, starting from the
+    /// timestamp the segment continues after.
+    ///
+    /// This ensures:
+    /// - The next segment's header can name the newest logged write
+    /// - Appending an older timestamp never moves it back
+    #[test]
+    fn last_timestamp_starts_at_previous_and_follows_appends() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_path = temp_dir.path().join("last.wal");
+
+        let writer = WALWriter::new_after(&wal_path, SyncMode::None, 1024 * 1024, 7).unwrap();
+        assert_eq!(writer.last_timestamp(), 7);
+
+        let entries: Vec<_> = [8, 9]
+            .into_iter()
+            .map(|ts| WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), ts).unwrap())
+            .collect();
+        writer.append_batch(&entries).unwrap();
+        assert_eq!(writer.last_timestamp(), 9);
+        writer.append(&entries[0]).unwrap();
+        assert_eq!(writer.last_timestamp(), 9);
+    }
+
     // ==================== Concurrent Error Scenarios ====================
 
     /// Tests that concurrent writes respect size limits without data corruption.
@@ -693,6 +1122,6 @@ mod tests {
         let result = writer.append(&entry);
 
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("size limit"));
+        assert_eq!(result.unwrap_err().code(), ErrorCode::LimitExceeded);
     }
 }
@@ -0,0 +1,401 @@
+//! Backpressure on foreground writes while compaction falls behind
+//!
+//! Writes only ever cost a WAL append and a MemTable insert, so a busy
+//! client can produce data much faster than compaction can merge it. Left
+//! alone, level 0 fills with overlapping tables, every read has to consult
+//! all of them, and compaction debt grows without bound.
+//!
+//! The [`WriteController`] watches the number of level 0 tables and the
+//! compaction debt estimated by the
+//! [`CompactionStrategy`](crate::compaction::CompactionStrategy), and
+//! applies backpressure in two stages:
+//!
+//! ```text
+//! level 0 files      0 ──── slowdown trigger ──── stop trigger ────▶
+//! pending bytes      0 ──── soft limit ────────── hard limit ──────▶
+//!                    │      │                     │
+//! condition          Normal Delayed               Stopped
+//!                           writes paced at       writes wait until
+//!                           delayed_write_rate    compaction catches up
+//! ```
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_storage::write_stall::{WriteController, WriteStallCondition};
+//! use ferrisdb_storage::StorageConfig;
+//!
+//! let controller = WriteController::new(&StorageConfig {
+//!     level0_slowdown_writes_trigger: 8,
+//!     level0_stop_writes_trigger: 12,
+//!     ..Default::default()
+//! });
+//!
+//! // After every flush or compaction
+//! let info = controller.update(9, 0);
+//! assert_eq!(info.condition, WriteStallCondition::Delayed);
+//!
+//! // Before every write
+//! controller.delay_write(4096)?;
+//! # Ok::<(), ferrisdb_core::Error>(())
+//! ```
+
+use crate::config::StorageConfig;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use ferrisdb_core::{Error, Result};
+
+use parking_lot::{Condvar, Mutex};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// How strongly foreground writes are held back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum WriteStallCondition {
+    /// Writes proceed at full speed
+    #[default]
+    Normal,
+    /// Writes are paced at the delayed write rate
+    Delayed,
+    /// Writes block until compaction catches up
+    Stopped,
+}
+
+/// What pushed writes into a stall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCause {
+    /// Too many level 0 tables, each of which every read must consult
+    Level0FileCount,
+    /// Too many bytes waiting to be compacted
+    PendingCompactionBytes,
+}
+
+/// Snapshot of the write stall state and the inputs it was derived from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteStallInfo {
+    /// Current condition
+    pub condition: WriteStallCondition,
+    /// Trigger responsible for the condition; `None` when writes are normal
+    pub cause: Option<WriteStallCause>,
+    /// Number of level 0 tables at the last update
+    pub level0_files: usize,
+    /// Estimated compaction debt in bytes at the last update
+    pub pending_compaction_bytes: u64,
+}
+
+/// Counters describing how often and how long writes were held back
+#[derive(Debug, Default)]
+pub struct WriteStallMetrics {
+    delayed_writes: AtomicU64,
+    stopped_writes: AtomicU64,
+    stall_time_us: AtomicU64,
+}
+
+impl WriteStallMetrics {
+    /// Gets the number of writes paced while writes were delayed
+    pub fn delayed_writes(&self) -> u64 {
+        self.delayed_writes.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of writes that had to wait while writes were stopped
+    pub fn stopped_writes(&self) -> u64 {
+        self.stopped_writes.load(Ordering::Relaxed)
+    }
+
+    /// Gets the total time writes spent held back
+    pub fn stall_time(&self) -> Duration {
+        Duration::from_micros(self.stall_time_us.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    info: WriteStallInfo,
+    closed: bool,
+}
+
+/// Decides and enforces backpressure on foreground writes
+///
+/// The engine calls [`update`](Self::update) whenever the shape of the
+/// tree changes, and [`delay_write`](Self::delay_write) before every write.
+///
+/// A hard or soft pending compaction bytes limit of 0 disables that
+/// trigger.
+///
+/// # Thread Safety
+///
+/// `WriteController` is `Send + Sync`; writers blocked in `delay_write`
+/// are woken by the `update` that ends the stop, or by `close`.
+#[derive(Debug)]
+pub struct WriteController {
+    level0_slowdown_trigger: usize,
+    level0_stop_trigger: usize,
+    soft_pending_bytes_limit: u64,
+    hard_pending_bytes_limit: u64,
+    state: Mutex<State>,
+    /// Signalled when writes may resume
+    resumed: Condvar,
+    /// Paces writes while they are delayed
+    delay_limiter: RateLimiter,
+    metrics: WriteStallMetrics,
+}
+
+impl WriteController {
+    /// Creates a controller using the stall triggers of `config`
+    pub fn new(config: &StorageConfig) -> Self {
+        Self {
+            level0_slowdown_trigger: config.level0_slowdown_writes_trigger.max(1) as usize,
+            level0_stop_trigger: config.level0_stop_writes_trigger.max(1) as usize,
+            soft_pending_bytes_limit: config.soft_pending_compaction_bytes_limit,
+            hard_pending_bytes_limit: config.hard_pending_compaction_bytes_limit,
+            state: Mutex::new(State::default()),
+            resumed: Condvar::new(),
+            delay_limiter: RateLimiter::new(config.delayed_write_rate.max(1)),
+            metrics: WriteStallMetrics::default(),
+        }
+    }
+
+    /// Recomputes the stall condition from the current shape of the tree
+    ///
+    /// Returns the new state. Writers waiting on a stop are released as
+    /// soon as the condition drops below [`WriteStallCondition::Stopped`].
+    pub fn update(&self, level0_files: usize, pending_compaction_bytes: u64) -> WriteStallInfo {
+        let exceeds = |value: u64, limit: u64| limit > 0 && value >= limit;
+        let level0 = level0_files as u64;
+
+        let (condition, cause) = if exceeds(level0, self.level0_stop_trigger as u64) {
+            (
+                WriteStallCondition::Stopped,
+                Some(WriteStallCause::Level0FileCount),
+            )
+        } else if exceeds(pending_compaction_bytes, self.hard_pending_bytes_limit) {
+            (
+                WriteStallCondition::Stopped,
+                Some(WriteStallCause::PendingCompactionBytes),
+            )
+        } else if exceeds(level0, self.level0_slowdown_trigger as u64) {
+            (
+                WriteStallCondition::Delayed,
+                Some(WriteStallCause::Level0FileCount),
+            )
+        } else if exceeds(pending_compaction_bytes, self.soft_pending_bytes_limit) {
+            (
+                WriteStallCondition::Delayed,
+                Some(WriteStallCause::PendingCompactionBytes),
+            )
+        } else {
+            (WriteStallCondition::Normal, None)
+        };
+
+        let info = WriteStallInfo {
+            condition,
+            cause,
+            level0_files,
+            pending_compaction_bytes,
+        };
+
+        let mut state = self.state.lock();
+        if state.info.condition != condition {
+            log::warn!(
+                "Write stall condition changed from {:?} to {:?} ({:?}, {} level 0 files, {} pending compaction bytes)",
+                state.info.condition,
+                condition,
+                cause,
+                level0_files,
+                pending_compaction_bytes
+            );
+        }
+        let was_stopped = state.info.condition == WriteStallCondition::Stopped;
+        state.info = info.clone();
+        if was_stopped && condition != WriteStallCondition::Stopped {
+            self.resumed.notify_all();
+        }
+
+        info
+    }
+
+    /// Returns the current stall state
+    pub fn info(&self) -> WriteStallInfo {
+        self.state.lock().info.clone()
+    }
+
+    /// Returns how often and how long writes were held back
+    pub fn metrics(&self) -> &WriteStallMetrics {
+        &self.metrics
+    }
+
+    /// Holds back a write of `bytes` according to the current condition
+    ///
+    /// Returns immediately when writes are normal, paces the write when
+    /// they are delayed, and blocks while they are stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::StorageEngine` if the controller was closed, either
+    /// before the call or while the write was waiting.
+    pub fn delay_write(&self, bytes: u64) -> Result<()> {
+        let start = Instant::now();
+        let mut state = self.state.lock();
+
+        let stopped = state.info.condition == WriteStallCondition::Stopped;
+        while state.info.condition == WriteStallCondition::Stopped && !state.closed {
+            self.resumed.wait(&mut state);
+        }
+        if state.closed {
+            return Err(Error::StorageEngine(
+                "Write rejected: storage engine is closed".to_string(),
+            ));
+        }
+        let delayed = state.info.condition == WriteStallCondition::Delayed;
+        drop(state);
+
+        if delayed {
+            self.delay_limiter.request(bytes, IoPriority::High);
+            self.metrics.delayed_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        if stopped {
+            self.metrics.stopped_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        if stopped || delayed {
+            let elapsed = start.elapsed().as_micros() as u64;
+            self.metrics
+                .stall_time_us
+                .fetch_add(elapsed, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /// Rejects all further writes and releases writers waiting on a stop
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.resumed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn controller() -> WriteController {
+        WriteController::new(&StorageConfig {
+            level0_slowdown_writes_trigger: 4,
+            level0_stop_writes_trigger: 8,
+            soft_pending_compaction_bytes_limit: 1000,
+            hard_pending_compaction_bytes_limit: 2000,
+            delayed_write_rate: 100 * 1024,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_update_picks_strongest_triggered_condition() {
+        let controller = controller();
+        let state = |l0, pending| {
+            let info = controller.update(l0, pending);
+            (info.condition, info.cause)
+        };
+
+        assert_eq!(state(3, 999), (WriteStallCondition::Normal, None));
+        assert_eq!(
+            state(4, 0),
+            (
+                WriteStallCondition::Delayed,
+                Some(WriteStallCause::Level0FileCount)
+            )
+        );
+        assert_eq!(
+            state(0, 1000),
+            (
+                WriteStallCondition::Delayed,
+                Some(WriteStallCause::PendingCompactionBytes)
+            )
+        );
+        assert_eq!(
+            state(5, 2000),
+            (
+                WriteStallCondition::Stopped,
+                Some(WriteStallCause::PendingCompactionBytes)
+            )
+        );
+        assert_eq!(
+            state(8, 2000),
+            (
+                WriteStallCondition::Stopped,
+                Some(WriteStallCause::Level0FileCount)
+            )
+        );
+        assert_eq!(controller.info().level0_files, 8);
+    }
+
+    #[test]
+    fn test_zero_pending_limits_disable_byte_triggers() {
+        let controller = WriteController::new(&StorageConfig {
+            soft_pending_compaction_bytes_limit: 0,
+            hard_pending_compaction_bytes_limit: 0,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            controller.update(0, u64::MAX).condition,
+            WriteStallCondition::Normal
+        );
+    }
+
+    #[test]
+    fn test_stopped_writes_resume_after_update() {
+        let controller = Arc::new(controller());
+        controller.update(8, 0);
+
+        let writer = {
+            let controller = Arc::clone(&controller);
+            thread::spawn(move || controller.delay_write(10))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!writer.is_finished());
+
+        controller.update(2, 0);
+        writer.join().unwrap().unwrap();
+
+        let metrics = controller.metrics();
+        assert_eq!(metrics.stopped_writes(), 1);
+        assert!(metrics.stall_time() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_delayed_writes_are_paced() {
+        let controller = controller();
+        controller.update(5, 0);
+        let start = Instant::now();
+
+        // 10KB burst, then another 20KB at 100KB/s
+        for _ in 0..3 {
+            controller.delay_write(10 * 1024).unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(controller.metrics().delayed_writes(), 3);
+        assert_eq!(controller.metrics().stopped_writes(), 0);
+    }
+
+    #[test]
+    fn test_close_releases_stopped_writers_with_error() {
+        let controller = Arc::new(controller());
+        controller.update(8, 0);
+
+        let writer = {
+            let controller = Arc::clone(&controller);
+            thread::spawn(move || controller.delay_write(10))
+        };
+        thread::sleep(Duration::from_millis(20));
+        controller.close();
+
+        assert!(matches!(
+            writer.join().unwrap(),
+            Err(Error::StorageEngine(_))
+        ));
+        assert!(controller.delay_write(10).is_err());
+    }
+}
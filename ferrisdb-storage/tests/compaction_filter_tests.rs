@@ -0,0 +1,198 @@
+//! Integration tests for compaction filters
+
+use ferrisdb_core::{Operation, Timestamp};
+use ferrisdb_storage::compaction::{
+    CompactionFilter, CompactionStrategy, Compactor, FilterDecision, LeveledStrategy,
+};
+use ferrisdb_storage::manifest::{SSTableMeta, VersionEdit};
+use ferrisdb_storage::sstable::{InternalKey, SSTableWriter};
+use ferrisdb_storage::version::VersionSet;
+use ferrisdb_storage::StorageConfig;
+
+use tempfile::TempDir;
+
+use std::sync::Arc;
+
+/// Expires `[expiry:8][payload]` values, purges one user's keys and
+/// upgrades `v1:` values to `v2:`
+struct Housekeeping {
+    now: u64,
+    purged_prefix: &'static [u8],
+}
+
+impl CompactionFilter for Housekeeping {
+    fn name(&self) -> &str {
+        "housekeeping"
+    }
+
+    fn filter(
+        &self,
+        key: &[u8],
+        _timestamp: Timestamp,
+        _operation: Operation,
+        value: &[u8],
+    ) -> FilterDecision {
+        if key.starts_with(self.purged_prefix) {
+            return FilterDecision::Remove;
+        }
+        let (expiry, payload) = value.split_at(8);
+        if u64::from_le_bytes(expiry.try_into().unwrap()) < self.now {
+            return FilterDecision::Remove;
+        }
+        match payload.strip_prefix(b"v1:") {
+            Some(rest) => {
+                let mut migrated = expiry.to_vec();
+                migrated.extend_from_slice(b"v2:");
+                migrated.extend_from_slice(rest);
+                FilterDecision::ChangeValue(migrated)
+            }
+            None => FilterDecision::Keep,
+        }
+    }
+}
+
+fn value(expiry: u64, payload: &str) -> Vec<u8> {
+    let mut value = expiry.to_le_bytes().to_vec();
+    value.extend_from_slice(payload.as_bytes());
+    value
+}
+
+/// Flushes `(key, timestamp, value)` entries into a new table in `level`
+fn add_table(versions: &VersionSet, level: usize, entries: &[(&str, u64, Vec<u8>)]) {
+    let number = versions.new_file_number();
+    let mut writer = SSTableWriter::new(versions.table_path(number)).unwrap();
+    for (key, timestamp, value) in entries {
+        writer
+            .add(
+                InternalKey::new(key.as_bytes().to_vec(), *timestamp),
+                value.clone(),
+                Operation::Put,
+            )
+            .unwrap();
+    }
+    let mut edit = VersionEdit::default();
+    edit.add_file(level, SSTableMeta::new(number, &writer.finish().unwrap()));
+    versions.log_and_apply(edit).unwrap();
+}
+
+/// Compacts level 0 into level 1; callers flush at least two tables so the
+/// compaction rewrites them rather than moving a single table down
+fn compact_level0(versions: &Arc<VersionSet>, filter: Housekeeping, oldest_snapshot: Timestamp) {
+    let task = LeveledStrategy::new(1, u64::MAX, 10.0)
+        .pick_compaction(&versions.current())
+        .unwrap();
+    Compactor::new(Arc::clone(versions), &StorageConfig::default())
+        .with_compaction_filter(Arc::new(filter))
+        .run(&task, oldest_snapshot)
+        .unwrap();
+}
+
+/// Returns `(key, timestamp, operation, payload)` for every entry in level 1
+fn level1(versions: &VersionSet) -> Vec<(String, u64, Operation, String)> {
+    let mut entries = Vec::new();
+    for table in versions.current().files(1) {
+        for entry in table.open_reader().unwrap() {
+            let entry = entry.unwrap();
+            let payload = entry.value.get(8..).unwrap_or_default();
+            entries.push((
+                String::from_utf8(entry.key.user_key).unwrap(),
+                entry.key.timestamp,
+                entry.operation,
+                String::from_utf8(payload.to_vec()).unwrap(),
+            ));
+        }
+    }
+    entries
+}
+
+/// Tests TTL expiry, purges and value migration in one compaction.
+///
+/// This test verifies that:
+/// - Expired values and purged keys are removed
+/// - Removed values leave tombstones while older levels may hold the key
+/// - Values are migrated to the new format in place
+/// - Versions newer than the oldest snapshot are left untouched
+#[test]
+fn compactor_applies_filter_to_versions_below_oldest_snapshot() {
+    let temp_dir = TempDir::new().unwrap();
+    let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+
+    // Level 2 holds old data outside this compaction
+    add_table(&versions, 2, &[("expired", 1, value(u64::MAX, "ancient"))]);
+    add_table(
+        &versions,
+        0,
+        &[
+            ("expired", 5, value(50, "stale")),
+            ("fresh", 6, value(500, "v1:profile")),
+        ],
+    );
+    add_table(
+        &versions,
+        0,
+        &[
+            ("pinned", 30, value(50, "v1:snapshot")),
+            ("user42/email", 7, value(500, "a@b.c")),
+        ],
+    );
+
+    compact_level0(
+        &versions,
+        Housekeeping {
+            now: 100,
+            purged_prefix: b"user42/",
+        },
+        20,
+    );
+
+    assert_eq!(
+        level1(&versions),
+        vec![
+            ("expired".to_string(), 5, Operation::Delete, String::new()),
+            (
+                "fresh".to_string(),
+                6,
+                Operation::Put,
+                "v2:profile".to_string()
+            ),
+            (
+                "pinned".to_string(),
+                30,
+                Operation::Put,
+                "v1:snapshot".to_string()
+            ),
+            (
+                "user42/email".to_string(),
+                7,
+                Operation::Delete,
+                String::new()
+            ),
+        ]
+    );
+}
+
+/// Tests that removal needs no tombstone when nothing older can exist.
+///
+/// This test verifies that:
+/// - A bottommost compaction drops removed versions entirely
+#[test]
+fn compactor_drops_removed_versions_in_bottommost_compaction() {
+    let temp_dir = TempDir::new().unwrap();
+    let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+    add_table(&versions, 0, &[("expired", 5, value(50, "stale"))]);
+    add_table(&versions, 0, &[("fresh", 6, value(500, "kept"))]);
+
+    compact_level0(
+        &versions,
+        Housekeeping {
+            now: 100,
+            purged_prefix: b"user42/",
+        },
+        Timestamp::MAX,
+    );
+
+    assert_eq!(
+        level1(&versions),
+        vec![("fresh".to_string(), 6, Operation::Put, "kept".to_string())]
+    );
+}
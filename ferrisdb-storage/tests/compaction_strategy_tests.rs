@@ -0,0 +1,148 @@
+//! Integration tests for compaction strategies and the compactor
+
+use ferrisdb_core::{Key, Operation, Timestamp, Value};
+use ferrisdb_storage::compaction::{
+    strategy_from_config, CompactionStrategy, Compactor, LeveledStrategy, SizeTieredStrategy,
+};
+use ferrisdb_storage::manifest::{SSTableMeta, VersionEdit, NUM_LEVELS};
+use ferrisdb_storage::sstable::{InternalKey, SSTableWriter};
+use ferrisdb_storage::version::VersionSet;
+use ferrisdb_storage::{CompactionStrategyKind, StorageConfig};
+
+use tempfile::TempDir;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+const KEYS_PER_FLUSH: u64 = 100;
+
+/// Flushes a level 0 table whose keys interleave with every other flush
+fn flush(versions: &VersionSet, flush_number: u64, distinct: u64) {
+    let number = versions.new_file_number();
+    let mut writer = SSTableWriter::new(versions.table_path(number)).unwrap();
+    for i in 0..KEYS_PER_FLUSH {
+        let key = format!("key{:06}", i * distinct + flush_number % distinct);
+        let timestamp = flush_number * KEYS_PER_FLUSH + i + 1;
+        writer
+            .add(
+                InternalKey::new(key.into_bytes(), timestamp),
+                timestamp.to_le_bytes().to_vec(),
+                Operation::Put,
+            )
+            .unwrap();
+    }
+    let mut edit = VersionEdit::default();
+    edit.add_file(0, SSTableMeta::new(number, &writer.finish().unwrap()));
+    versions.log_and_apply(edit).unwrap();
+}
+
+/// Runs compactions until the strategy is satisfied, returning bytes written
+fn compact(versions: &Arc<VersionSet>, strategy: &dyn CompactionStrategy) -> u64 {
+    let compactor = Compactor::new(Arc::clone(versions), &StorageConfig::default());
+    let mut bytes_written = 0;
+    while let Some(task) = strategy.pick_compaction(&versions.current()) {
+        bytes_written += compactor.run(&task, Timestamp::MAX).unwrap().bytes_written;
+    }
+    bytes_written
+}
+
+/// Returns the newest version of every key across all live tables
+fn newest_versions(versions: &VersionSet) -> BTreeMap<Key, (Timestamp, Value)> {
+    let mut newest = BTreeMap::new();
+    let current = versions.current();
+    for level in 0..NUM_LEVELS {
+        for table in current.files(level) {
+            for entry in table.open_reader().unwrap() {
+                let entry = entry.unwrap();
+                let slot = newest.entry(entry.key.user_key).or_insert((0, Vec::new()));
+                if entry.key.timestamp > slot.0 {
+                    *slot = (entry.key.timestamp, entry.value);
+                }
+            }
+        }
+    }
+    newest
+}
+
+/// Tests that both configurable strategies compact without losing updates.
+///
+/// This test verifies that:
+/// - `strategy_from_config` selects the strategy named in the config
+/// - Overwritten keys keep their newest value after compaction
+/// - Leveled compaction empties level 0; size-tiered keeps tables in it
+#[test]
+fn strategy_from_config_compacts_without_losing_newest_versions() {
+    for kind in [
+        CompactionStrategyKind::Leveled,
+        CompactionStrategyKind::SizeTiered,
+    ] {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        let config = StorageConfig {
+            compaction_strategy: kind,
+            level0_file_num_compaction_trigger: 4,
+            ..Default::default()
+        };
+        let strategy = strategy_from_config(&config);
+
+        // Every key is rewritten by several flushes
+        for flush_number in 0..12 {
+            flush(&versions, flush_number, 3);
+            compact(&versions, strategy.as_ref());
+        }
+        let newest = newest_versions(&versions);
+
+        assert_eq!(newest.len() as u64, 3 * KEYS_PER_FLUSH, "{:?}", kind);
+        for (timestamp, value) in newest.values() {
+            assert_eq!(value, &timestamp.to_le_bytes().to_vec());
+            // Only flushes 9..12 hold the newest version of each key
+            assert!(*timestamp > 9 * KEYS_PER_FLUSH, "{:?}", kind);
+        }
+
+        let current = versions.current();
+        match kind {
+            CompactionStrategyKind::Leveled => {
+                assert_eq!(strategy.name(), "leveled");
+                assert!(current.files(0).len() < 4);
+                assert!(!current.files(1).is_empty());
+            }
+            CompactionStrategyKind::SizeTiered => {
+                assert_eq!(strategy.name(), "size-tiered");
+                assert_eq!(current.file_count(), current.files(0).len());
+                assert!(current.files(0).len() < 12);
+            }
+        }
+    }
+}
+
+/// Tests the write amplification trade-off between the strategies.
+///
+/// This test verifies that:
+/// - Leveled compaction rewrites level 1 every time level 0 fills up
+/// - Size-tiered compaction merges each table into larger tiers only once
+///   per tier, writing fewer bytes for the same flushes
+#[test]
+fn size_tiered_writes_fewer_bytes_than_leveled_for_write_heavy_load() {
+    let run = |strategy: &dyn CompactionStrategy| {
+        let temp_dir = TempDir::new().unwrap();
+        let versions = Arc::new(VersionSet::open(temp_dir.path()).unwrap());
+        let mut bytes_written = 0;
+        for flush_number in 0..64 {
+            flush(&versions, flush_number, 64);
+            bytes_written += compact(&versions, strategy);
+        }
+        assert_eq!(newest_versions(&versions).len() as u64, 64 * KEYS_PER_FLUSH);
+        bytes_written
+    };
+
+    // Level 1 never overflows, so leveled only ever compacts level 0
+    let leveled = run(&LeveledStrategy::new(4, u64::MAX, 10.0));
+    let size_tiered = run(&SizeTieredStrategy::new(4).with_min_table_size(0));
+
+    assert!(
+        size_tiered < leveled,
+        "size-tiered wrote {} bytes, leveled {}",
+        size_tiered,
+        leveled
+    );
+}
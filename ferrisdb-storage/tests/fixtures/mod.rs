@@ -0,0 +1,410 @@
+//! Canonical contents of the format compatibility fixtures
+//!
+//! Every WAL and SSTable format version has a fixture file in this
+//! directory holding the same canonical entries. The `generate_fixtures`
+//! binary writes the missing ones and `format_compatibility_tests.rs`
+//! checks that current readers still parse all of them, so an
+//! incompatible format change fails the tests instead of old files.
+//!
+//! Fixtures are written once and never regenerated. When a format version
+//! is bumped, the writer of the previous version moves into a frozen
+//! encoder here, like the 1.x ones below, and the new version is added to
+//! [`WAL_VERSIONS`] or [`SSTABLE_VERSIONS`].
+
+use ferrisdb_core::{Error, Operation, Result, SyncMode};
+use ferrisdb_storage::format::{ChecksummedHeader, FileHeader};
+use ferrisdb_storage::sstable::{
+    encode_data_block, encode_index_block, Footer, IndexEntry, InternalKey, SSTableEntry,
+    SSTableWriter, TableProperties, FORMAT_VERSION,
+};
+use ferrisdb_storage::utils::bloom::BloomFilter;
+use ferrisdb_storage::utils::coding;
+use ferrisdb_storage::vfs::{SimVfs, Vfs};
+use ferrisdb_storage::wal::{WALEntry, WALHeader, WALWriter, WAL_CURRENT_VERSION, WAL_HEADER_SIZE};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// WAL format versions with a fixture
+pub const WAL_VERSIONS: &[u16] = &[0x0100, 0x0200, 0x0201];
+
+/// SSTable format versions with a fixture
+pub const SSTABLE_VERSIONS: &[u32] = &[1, 2, 3, 4];
+
+/// Creation time and file sequence of every WAL fixture header: the
+/// simulated clock's start, in microseconds
+const CREATED_AT: u64 = 1_700_000_000_000_000;
+
+/// Entries per data block of the 1.x SSTable fixture
+const LEGACY_BLOCK_ENTRIES: usize = 32;
+
+/// Encoded entry bytes after which the version 2 and 3 SSTable writers
+/// started a new data block
+const V2_BLOCK_SIZE: usize = 4096;
+
+/// Returns the directory holding the fixtures
+pub fn dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Returns the fixture of WAL format `version`, such as `wal/v2.1.log`
+pub fn wal_fixture(version: u16) -> PathBuf {
+    dir()
+        .join("wal")
+        .join(format!("v{}.{}.log", version >> 8, version & 0xff))
+}
+
+/// Returns the fixture of SSTable format `version`, such as `sstable/v2.sst`
+pub fn sstable_fixture(version: u32) -> PathBuf {
+    dir().join("sstable").join(format!("v{}.sst", version))
+}
+
+/// Returns the records of the WAL fixtures, each a single entry or a batch
+///
+/// They cover every operation, a column family, an empty value and a
+/// value long enough for multi-byte varint lengths.
+pub fn wal_records() -> Result<Vec<Vec<WALEntry>>> {
+    Ok(vec![
+        vec![WALEntry::new_put(b"alpha".to_vec(), b"one".to_vec(), 1)?],
+        vec![WALEntry::new_delete(b"alpha".to_vec(), 2)?],
+        vec![WALEntry::new_merge(
+            b"counter".to_vec(),
+            5u64.to_le_bytes().to_vec(),
+            3,
+        )?],
+        vec![WALEntry::new_put(b"cf-key".to_vec(), b"cf-value".to_vec(), 4)?.with_column_family(2)],
+        vec![
+            WALEntry::new_put(b"batch-a".to_vec(), vec![b'a'; 300], 5)?,
+            WALEntry::new_delete(b"batch-b".to_vec(), 6)?.with_column_family(7),
+            WALEntry::new_merge(b"counter".to_vec(), 2u64.to_le_bytes().to_vec(), 7)?,
+        ],
+        vec![WALEntry::new_put(b"empty".to_vec(), Vec::new(), 8)?],
+    ])
+}
+
+/// Returns the entries of the SSTable fixtures, in table order
+///
+/// Keys have several versions of every operation, and values vary in
+/// length so the table spans several data blocks.
+pub fn sstable_entries() -> Vec<SSTableEntry> {
+    (0..150u64)
+        .map(|i| {
+            let operation = match i % 3 {
+                0 => Operation::Put,
+                1 => Operation::Delete,
+                _ => Operation::Merge,
+            };
+            let value = match operation {
+                Operation::Delete => Vec::new(),
+                _ => vec![i as u8; (i * 7 % 200) as usize],
+            };
+            let key = InternalKey::new(format!("key{:04}", i / 3).into_bytes(), 1000 - i % 3);
+            SSTableEntry::new(key, value, operation)
+        })
+        .collect()
+}
+
+/// Encodes the WAL fixture of format `version`
+///
+/// # Errors
+///
+/// Returns `Error::Unsupported` for a version without an encoder.
+pub fn encode_wal(version: u16) -> Result<Vec<u8>> {
+    let records = wal_records()?;
+    if version == WAL_CURRENT_VERSION {
+        let (sim, vfs) = sim();
+        let path = Path::new("/fixture.log");
+        let writer = WALWriter::new_in(&vfs, path, SyncMode::Full, 1 << 20, 0)?;
+        for record in &records {
+            match record.as_slice() {
+                [entry] => writer.append(entry)?,
+                batch => writer.append_batch(batch)?,
+            }
+        }
+        writer.close()?;
+
+        // The header records when the file was created; pin it
+        let mut file = sim.contents(path).expect("WAL fixture written");
+        let mut header = WALHeader::decode(&file[..WAL_HEADER_SIZE])?;
+        header.created_at = CREATED_AT;
+        header.header_checksum = header.calculate_checksum();
+        file[..WAL_HEADER_SIZE].copy_from_slice(&header.encode());
+        return Ok(file);
+    }
+
+    match version {
+        0x0100 | 0x0200 => {
+            let mut header = WALHeader::new(CREATED_AT);
+            header.version = version;
+            header.created_at = CREATED_AT;
+            header.header_checksum = header.calculate_checksum();
+            let mut file = header.encode();
+            for record in &records {
+                file.extend(match (version, record.as_slice()) {
+                    (0x0100, [entry]) => encode_wal_entry_v1(entry),
+                    (0x0100, batch) => encode_wal_batch_v1(batch),
+                    (_, [entry]) => encode_wal_entry_v2(entry),
+                    (_, batch) => encode_wal_batch_v2(batch),
+                });
+            }
+            Ok(file)
+        }
+        _ => Err(unsupported("WAL", u32::from(version))),
+    }
+}
+
+/// Encodes the SSTable fixture of format `version`
+///
+/// # Errors
+///
+/// Returns `Error::Unsupported` for a version without an encoder.
+pub fn encode_sstable(version: u32) -> Result<Vec<u8>> {
+    let entries = sstable_entries();
+    if version == FORMAT_VERSION {
+        let (sim, vfs) = sim();
+        let path = Path::new("/fixture.sst");
+        let mut writer = SSTableWriter::new_in(&vfs, path)?;
+        for entry in &entries {
+            writer.add(entry.key.clone(), entry.value.clone(), entry.operation)?;
+        }
+        writer.finish()?;
+        return Ok(sim.contents(path).expect("SSTable fixture written"));
+    }
+
+    match version {
+        1 => Ok(encode_sstable_v1(&entries)),
+        2 => Ok(encode_sstable_v2(&entries)),
+        3 => Ok(encode_sstable_v3(&entries)),
+        _ => Err(unsupported("SSTable", version)),
+    }
+}
+
+fn sim() -> (Arc<SimVfs>, Arc<dyn Vfs>) {
+    let sim = Arc::new(SimVfs::new(0));
+    let vfs: Arc<dyn Vfs> = sim.clone();
+    vfs.create_dir_all(Path::new("/")).expect("simulated root");
+    (sim, vfs)
+}
+
+fn unsupported(format: &str, version: u32) -> Error {
+    Error::Unsupported(format!(
+        "No encoder for {} format version {:#x}",
+        format, version
+    ))
+}
+
+fn wal_op(entry: &WALEntry) -> u8 {
+    match entry.operation {
+        Operation::Put => 1,
+        Operation::Delete => 2,
+        Operation::Merge => 3,
+    }
+}
+
+/// Encodes an entry as WAL format 1.x did, with fixed-width lengths
+fn encode_wal_entry_v1(entry: &WALEntry) -> Vec<u8> {
+    let mut body = Vec::new();
+    coding::put_fixed64(&mut body, entry.timestamp);
+    if entry.column_family == 0 {
+        body.push(wal_op(entry));
+    } else {
+        body.push(wal_op(entry) | 0x80);
+        coding::put_fixed32(&mut body, entry.column_family);
+    }
+    coding::put_fixed32(&mut body, entry.key.len() as u32);
+    body.extend_from_slice(&entry.key);
+    coding::put_fixed32(&mut body, entry.value.len() as u32);
+    body.extend_from_slice(&entry.value);
+    checksummed_wal_record(body)
+}
+
+/// Encodes a batch as WAL format 1.x did, with a fixed-width count
+fn encode_wal_batch_v1(entries: &[WALEntry]) -> Vec<u8> {
+    let mut body = Vec::new();
+    coding::put_fixed64(&mut body, entries[0].timestamp);
+    body.push(4);
+    coding::put_fixed32(&mut body, entries.len() as u32);
+    for entry in entries {
+        body.extend(encode_wal_entry_v1(entry));
+    }
+    checksummed_wal_record(body)
+}
+
+/// Encodes an entry as WAL format 2.0 did, with varint lengths
+fn encode_wal_entry_v2(entry: &WALEntry) -> Vec<u8> {
+    let mut body = Vec::new();
+    coding::put_fixed64(&mut body, entry.timestamp);
+    if entry.column_family == 0 {
+        body.push(wal_op(entry));
+    } else {
+        body.push(wal_op(entry) | 0x80);
+        coding::put_varint32(&mut body, entry.column_family);
+    }
+    coding::put_length_prefixed_slice(&mut body, &entry.key);
+    coding::put_length_prefixed_slice(&mut body, &entry.value);
+    checksummed_wal_record(body)
+}
+
+/// Encodes a batch as WAL format 2.0 did, with a varint count
+fn encode_wal_batch_v2(entries: &[WALEntry]) -> Vec<u8> {
+    let mut body = Vec::new();
+    coding::put_fixed64(&mut body, entries[0].timestamp);
+    body.push(4);
+    coding::put_varint32(&mut body, entries.len() as u32);
+    for entry in entries {
+        body.extend(encode_wal_entry_v2(entry));
+    }
+    checksummed_wal_record(body)
+}
+
+/// Frames a record body with its length and checksum, as every WAL
+/// format version has
+fn checksummed_wal_record(body: Vec<u8>) -> Vec<u8> {
+    let mut record = Vec::with_capacity(body.len() + 8);
+    coding::put_fixed32(&mut record, (body.len() + 4) as u32);
+    coding::put_fixed32(&mut record, crc32fast::hash(&body));
+    record.extend(body);
+    record
+}
+
+/// Encodes a table as SSTable format 1 did: fixed-width lengths and
+/// offsets, and no properties block
+fn encode_sstable_v1(entries: &[SSTableEntry]) -> Vec<u8> {
+    let mut file = Vec::new();
+    let mut index = Vec::new();
+    for block in entries.chunks(LEGACY_BLOCK_ENTRIES) {
+        index.push((file.len() as u64, &block[0].key.user_key));
+        coding::put_fixed32(&mut file, block.len() as u32);
+        for entry in block {
+            coding::put_fixed32(&mut file, entry.key.user_key.len() as u32);
+            coding::put_fixed32(&mut file, entry.value.len() as u32);
+            coding::put_fixed64(&mut file, entry.key.timestamp);
+            file.push(match entry.operation {
+                Operation::Put => 0,
+                Operation::Delete => 1,
+                Operation::Merge => 2,
+            });
+            file.extend_from_slice(&entry.key.user_key);
+            file.extend_from_slice(&entry.value);
+        }
+        coding::put_fixed32(&mut file, 0);
+    }
+
+    let index_offset = file.len() as u64;
+    coding::put_fixed32(&mut file, index.len() as u32);
+    for (block_offset, first_key) in index {
+        coding::put_fixed64(&mut file, block_offset);
+        coding::put_fixed32(&mut file, first_key.len() as u32);
+        file.extend_from_slice(first_key);
+    }
+    coding::put_fixed32(&mut file, 0);
+
+    let bloom_offset = file.len() as u64;
+    file.extend_from_slice(&[0; 16]);
+    let footer = Footer::new(index_offset, bloom_offset - index_offset, bloom_offset, 16);
+    file.extend_from_slice(&footer.to_bytes());
+    file
+}
+
+/// Encodes a table as SSTable format 2 did: varint lengths and offsets,
+/// and checksums left 0
+fn encode_sstable_v2(entries: &[SSTableEntry]) -> Vec<u8> {
+    let mut file = Vec::new();
+    let mut index = Vec::new();
+    for block in blocks_v2(entries) {
+        index.push((file.len() as u64, &block[0].key.user_key));
+        coding::put_fixed32(&mut file, block.len() as u32);
+        for entry in block {
+            coding::put_varint32(&mut file, entry.key.user_key.len() as u32);
+            coding::put_varint32(&mut file, entry.value.len() as u32);
+            coding::put_fixed64(&mut file, entry.key.timestamp);
+            file.push(match entry.operation {
+                Operation::Put => 0,
+                Operation::Delete => 1,
+                Operation::Merge => 2,
+            });
+            file.extend_from_slice(&entry.key.user_key);
+            file.extend_from_slice(&entry.value);
+        }
+        coding::put_fixed32(&mut file, 0);
+    }
+
+    let index_offset = file.len() as u64;
+    coding::put_fixed32(&mut file, index.len() as u32);
+    for (block_offset, first_key) in index {
+        coding::put_varint64(&mut file, block_offset);
+        coding::put_length_prefixed_slice(&mut file, first_key);
+    }
+    coding::put_fixed32(&mut file, 0);
+
+    let bloom_offset = file.len() as u64;
+    file.extend_from_slice(&[0; 16]);
+
+    let properties: [(&str, &[u8]); 2] = [
+        ("ferrisdb.comparator", b"ferrisdb.BytewiseComparator"),
+        ("ferrisdb.format_version", &2u32.to_le_bytes()),
+    ];
+    coding::put_fixed32(&mut file, properties.len() as u32);
+    for (name, value) in properties {
+        coding::put_fixed32(&mut file, name.len() as u32);
+        file.extend_from_slice(name.as_bytes());
+        coding::put_fixed32(&mut file, value.len() as u32);
+        file.extend_from_slice(value);
+    }
+    coding::put_fixed32(&mut file, 0);
+
+    let footer = Footer::new(index_offset, bloom_offset - index_offset, bloom_offset, 16);
+    file.extend_from_slice(&footer.to_bytes());
+    file
+}
+
+/// Splits entries into data blocks as the version 2 and 3 writers did
+fn blocks_v2(entries: &[SSTableEntry]) -> Vec<&[SSTableEntry]> {
+    let mut blocks: Vec<&[SSTableEntry]> = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (i, entry) in entries.iter().enumerate() {
+        let entry_size = entry.serialized_size();
+        if i > start && size + entry_size > V2_BLOCK_SIZE {
+            blocks.push(&entries[start..i]);
+            (start, size) = (i, 0);
+        }
+        size += entry_size;
+    }
+    blocks.push(&entries[start..]);
+    blocks
+}
+
+/// Encodes a table as SSTable format 3 did: checksummed blocks, and no
+/// timestamp range among the properties
+fn encode_sstable_v3(entries: &[SSTableEntry]) -> Vec<u8> {
+    let mut file = Vec::new();
+    let mut index = Vec::new();
+    for block in blocks_v2(entries) {
+        index.push(IndexEntry::new(
+            file.len() as u64,
+            block[0].key.user_key.clone(),
+        ));
+        file.extend(encode_data_block(block));
+    }
+
+    let index_offset = file.len() as u64;
+    file.extend(encode_index_block(&index));
+    let bloom_offset = file.len() as u64;
+    let bloom = BloomFilter::empty().encode();
+    file.extend_from_slice(&bloom);
+
+    let properties = TableProperties {
+        format_version: 3,
+        ..Default::default()
+    };
+    file.extend(properties.to_bytes());
+
+    let footer = Footer::new(
+        index_offset,
+        bloom_offset - index_offset,
+        bloom_offset,
+        bloom.len() as u64,
+    );
+    file.extend_from_slice(&footer.to_bytes());
+    file
+}
@@ -0,0 +1,181 @@
+//! Integration tests for MANIFEST writing and recovery
+
+use ferrisdb_core::{Error, Operation};
+use ferrisdb_storage::manifest::{
+    ManifestReader, ManifestWriter, SSTableMeta, VersionEdit, MANIFEST_HEADER_SIZE,
+};
+use ferrisdb_storage::sstable::{InternalKey, SSTableWriter};
+
+use tempfile::TempDir;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+fn write_table(dir: &Path, number: u64, keys: &[&[u8]]) -> SSTableMeta {
+    let mut writer = SSTableWriter::new(dir.join(format!("{:06}.sst", number))).unwrap();
+    for key in keys {
+        writer
+            .add(
+                InternalKey::new(key.to_vec(), 1),
+                b"v".to_vec(),
+                Operation::Put,
+            )
+            .unwrap();
+    }
+    SSTableMeta::new(number, &writer.finish().unwrap())
+}
+
+/// Tests that reopening a MANIFEST reconstructs the live file set.
+///
+/// This test verifies that:
+/// - Flush and compaction edits are replayed in order
+/// - Tables removed by compaction are no longer live after recovery
+/// - Counters such as the next file number survive a restart
+#[test]
+fn open_recovers_live_files_after_flushes_and_compaction() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("MANIFEST");
+
+    let table1 = write_table(temp_dir.path(), 1, &[b"a", b"c"]);
+    let table2 = write_table(temp_dir.path(), 2, &[b"b", b"d"]);
+    let table3 = write_table(temp_dir.path(), 3, &[b"a", b"b", b"c", b"d"]);
+
+    {
+        let mut manifest = ManifestWriter::create(&path).unwrap();
+        for meta in [&table1, &table2] {
+            let mut flush = VersionEdit::default();
+            flush.add_file(0, meta.clone());
+            flush.set_next_file_number(meta.number + 1);
+            flush.set_last_timestamp(meta.number * 10);
+            manifest.log_and_apply(flush).unwrap();
+        }
+
+        let mut compaction = VersionEdit::default();
+        compaction.delete_file(0, 1);
+        compaction.delete_file(0, 2);
+        compaction.add_file(1, table3.clone());
+        compaction.set_next_file_number(4);
+        manifest.log_and_apply(compaction).unwrap();
+    }
+
+    let manifest = ManifestWriter::open(&path).unwrap();
+    let state = manifest.state();
+
+    assert_eq!(state.file_count(), 1);
+    assert_eq!(state.files(0).count(), 0);
+    assert_eq!(state.files(1).collect::<Vec<_>>(), vec![&table3]);
+    assert_eq!(state.next_file_number(), 4);
+    assert_eq!(state.last_timestamp(), 20);
+    assert_eq!(table3.smallest.user_key, b"a");
+    assert_eq!(table3.largest.user_key, b"d");
+}
+
+/// Tests recovery after a crash in the middle of writing a record.
+///
+/// This test verifies that:
+/// - A torn final record is ignored during recovery
+/// - Reopening truncates the torn record
+/// - New edits appended afterwards are recovered on the next open
+#[test]
+fn open_truncates_torn_record_and_keeps_appending() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("MANIFEST");
+
+    {
+        let mut manifest = ManifestWriter::create(&path).unwrap();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, write_table(temp_dir.path(), 1, &[b"a"]));
+        manifest.log_and_apply(edit).unwrap();
+    }
+    let complete_len = std::fs::metadata(&path).unwrap().len();
+
+    // Simulate a crash halfway through the next record
+    {
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, write_table(temp_dir.path(), 2, &[b"b"]));
+        let record = edit.encode();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record[..record.len() / 2]).unwrap();
+    }
+
+    {
+        let mut manifest = ManifestWriter::open(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), complete_len);
+        assert_eq!(manifest.state().file_count(), 1);
+
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, write_table(temp_dir.path(), 3, &[b"c"]));
+        manifest.log_and_apply(edit).unwrap();
+    }
+
+    let state = ManifestReader::new(&path).unwrap().recover().unwrap();
+    let numbers: Vec<_> = state.files(0).map(|meta| meta.number).collect();
+    assert_eq!(numbers, vec![1, 3]);
+}
+
+/// Tests that corruption of a complete record is reported.
+///
+/// This test verifies that:
+/// - A checksum mismatch in a complete record fails recovery
+/// - The corruption is not mistaken for a torn write
+#[test]
+fn recover_detects_corrupted_record() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("MANIFEST");
+
+    {
+        let mut manifest = ManifestWriter::create(&path).unwrap();
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, write_table(temp_dir.path(), 1, &[b"a"]));
+        manifest.log_and_apply(edit).unwrap();
+    }
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[MANIFEST_HEADER_SIZE + 10] ^= 0xFF;
+    std::fs::write(&path, bytes).unwrap();
+
+    let err = ManifestReader::new(&path).unwrap().recover().unwrap_err();
+    assert!(matches!(err, Error::Corruption(msg) if msg.contains("checksum")));
+    assert!(ManifestWriter::open(&path).is_err());
+}
+
+/// Tests that edits contradicting the current file set are rejected.
+///
+/// This test verifies that:
+/// - Deleting a table that isn't live fails without writing a record
+/// - The MANIFEST remains usable afterwards
+#[test]
+fn log_and_apply_rejects_inconsistent_edit_without_writing() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("MANIFEST");
+
+    let mut manifest = ManifestWriter::create(&path).unwrap();
+    let len_before = std::fs::metadata(&path).unwrap().len();
+
+    let mut edit = VersionEdit::default();
+    edit.delete_file(0, 42);
+    assert!(matches!(
+        manifest.log_and_apply(edit),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len_before);
+
+    let mut edit = VersionEdit::default();
+    edit.set_next_file_number(5);
+    manifest.log_and_apply(edit).unwrap();
+    drop(manifest);
+
+    let manifest = ManifestWriter::open(&path).unwrap();
+    assert_eq!(manifest.state().next_file_number(), 5);
+}
+
+/// Tests that creating a MANIFEST never clobbers an existing one.
+#[test]
+fn create_refuses_to_overwrite_existing_manifest() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("MANIFEST");
+
+    ManifestWriter::create(&path).unwrap();
+    assert!(matches!(ManifestWriter::create(&path), Err(Error::Io(_))));
+}
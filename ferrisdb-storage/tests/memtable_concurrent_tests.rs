@@ -0,0 +1,240 @@
+//! Concurrent access tests for MemTable
+
+use ferrisdb_core::{Error, Operation};
+use ferrisdb_storage::memtable::MemTable;
+use ferrisdb_storage::MemTableKind;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+/// Tests that concurrent writers never lose an insert.
+///
+/// This test verifies that:
+/// - Many threads can insert into the same MemTable at once
+/// - Every key written by every thread is readable afterwards
+/// - The entry count matches the number of inserts exactly
+#[test]
+fn put_keeps_every_entry_during_concurrent_writes() {
+    let memtable = Arc::new(MemTable::new(64 * 1024 * 1024));
+    let barrier = Arc::new(Barrier::new(8));
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let memtable = Arc::clone(&memtable);
+            let barrier = Arc::clone(&barrier);
+
+            thread::spawn(move || {
+                barrier.wait();
+                for i in 0..500 {
+                    let key = format!("key_{:04}_{}", i, thread_id).into_bytes();
+                    let value = format!("value_{}_{}", thread_id, i).into_bytes();
+                    memtable
+                        .put(key, value, (thread_id * 500 + i) as u64)
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(memtable.entry_count(), 8 * 500);
+    for thread_id in 0..8 {
+        for i in 0..500 {
+            let key = format!("key_{:04}_{}", i, thread_id).into_bytes();
+            let (value, op) = memtable.get(&key, u64::MAX).unwrap();
+            assert_eq!(value, format!("value_{}_{}", thread_id, i).into_bytes());
+            assert_eq!(op, Operation::Put);
+        }
+    }
+}
+
+/// Tests that concurrent versions of one key stay ordered newest first.
+///
+/// This test verifies that:
+/// - Threads racing on the same user key each get their own version
+/// - Reads at every timestamp return exactly the version written at it
+/// - A full scan still reports the key only once
+#[test]
+fn put_orders_versions_of_one_key_during_concurrent_writes() {
+    let memtable = Arc::new(MemTable::new(64 * 1024 * 1024));
+    let barrier = Arc::new(Barrier::new(4));
+
+    let handles: Vec<_> = (0..4u64)
+        .map(|thread_id| {
+            let memtable = Arc::clone(&memtable);
+            let barrier = Arc::clone(&barrier);
+
+            thread::spawn(move || {
+                barrier.wait();
+                // Thread t writes timestamps t+1, t+5, t+9, ...
+                for round in 0..250u64 {
+                    let ts = round * 4 + thread_id + 1;
+                    memtable
+                        .put(b"hot".to_vec(), ts.to_le_bytes().to_vec(), ts)
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    for ts in 1..=1000u64 {
+        let (value, _) = memtable.get(b"hot", ts).unwrap();
+        assert_eq!(value, ts.to_le_bytes().to_vec());
+    }
+
+    let results = memtable.scan(b"a", b"z", u64::MAX);
+    assert_eq!(
+        results,
+        vec![(b"hot".to_vec(), 1000u64.to_le_bytes().to_vec())]
+    );
+}
+
+/// Tests that readers never observe a partially inserted entry.
+///
+/// This test verifies that:
+/// - Readers running alongside writers see either nothing or the full value
+/// - Scans observe keys in strictly ascending order at all times
+#[test]
+fn get_and_scan_see_consistent_entries_during_concurrent_writes() {
+    let memtable = Arc::new(MemTable::new(64 * 1024 * 1024));
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let writers: Vec<_> = (0..4)
+        .map(|thread_id| {
+            let memtable = Arc::clone(&memtable);
+            let done = Arc::clone(&done);
+
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    let key = format!("k{:05}", i * 4 + thread_id).into_bytes();
+                    memtable.put(key.clone(), key, 1).unwrap();
+                }
+                done.fetch_add(1, Ordering::Release);
+            })
+        })
+        .collect();
+
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let memtable = Arc::clone(&memtable);
+            let done = Arc::clone(&done);
+
+            thread::spawn(move || {
+                while done.load(Ordering::Acquire) < 4 {
+                    for i in (0..4000).step_by(97) {
+                        let key = format!("k{:05}", i).into_bytes();
+                        if let Some((value, _)) = memtable.get(&key, 1) {
+                            assert_eq!(value, key);
+                        }
+                    }
+
+                    let results = memtable.scan(b"k", b"l", 1);
+                    assert!(results.windows(2).all(|pair| pair[0].0 < pair[1].0));
+                    assert!(results.iter().all(|(key, value)| key == value));
+                }
+            })
+        })
+        .collect();
+
+    for handle in writers.into_iter().chain(readers) {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(memtable.scan(b"k", b"l", 1).len(), 4000);
+}
+
+/// Tests that concurrent writers cannot overshoot the capacity together.
+///
+/// This test verifies that:
+/// - Capacity is reserved atomically across threads
+/// - Memory usage never exceeds the configured maximum
+/// - Every successful put is present and every rejected one is absent
+#[test]
+fn put_never_exceeds_capacity_during_concurrent_writes() {
+    let max_size = 10_000;
+    let memtable = Arc::new(MemTable::new(max_size));
+    let barrier = Arc::new(Barrier::new(8));
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let memtable = Arc::clone(&memtable);
+            let barrier = Arc::clone(&barrier);
+            let accepted = Arc::clone(&accepted);
+
+            thread::spawn(move || {
+                barrier.wait();
+                for i in 0..100 {
+                    let key = format!("t{}_{:03}", thread_id, i).into_bytes();
+                    match memtable.put(key, vec![0; 16], 1) {
+                        Ok(()) => {
+                            accepted.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(Error::MemTableFull) => {}
+                        Err(e) => panic!("Unexpected error: {:?}", e),
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert!(memtable.memory_usage() <= max_size);
+    assert_eq!(memtable.entry_count(), accepted.load(Ordering::Relaxed));
+}
+
+/// Tests that the hash-indexed MemTable keeps every concurrent insert.
+///
+/// This test verifies that:
+/// - Writers spread over the hash index shards never lose entries
+/// - Gets see every key and a scan sorts them correctly
+#[test]
+fn hash_index_put_keeps_every_entry_during_concurrent_writes() {
+    let memtable = Arc::new(MemTable::with_kind(
+        64 * 1024 * 1024,
+        MemTableKind::HashIndex,
+    ));
+    let barrier = Arc::new(Barrier::new(8));
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let memtable = Arc::clone(&memtable);
+            let barrier = Arc::clone(&barrier);
+
+            thread::spawn(move || {
+                barrier.wait();
+                for i in 0..500 {
+                    let key = format!("key_{:04}_{}", i, thread_id).into_bytes();
+                    memtable.put(key.clone(), key, 1).unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(memtable.entry_count(), 8 * 500);
+    for thread_id in 0..8 {
+        for i in 0..500 {
+            let key = format!("key_{:04}_{}", i, thread_id).into_bytes();
+            assert_eq!(memtable.get(&key, 1).unwrap().0, key);
+        }
+    }
+
+    let results = memtable.scan(b"key", b"kez", 1);
+    assert_eq!(results.len(), 8 * 500);
+    assert!(results.windows(2).all(|pair| pair[0].0 < pair[1].0));
+}
@@ -0,0 +1,123 @@
+//! Loom model tests for the MemTable insert path
+//!
+//! These tests exhaustively explore thread interleavings of the lock-free
+//! skip list insert and the capacity reservation. They only build with the
+//! `loom` cfg enabled:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test -p ferrisdb-storage --release --test memtable_loom_tests
+//! ```
+
+#![cfg(loom)]
+
+use ferrisdb_core::{Error, Operation};
+use ferrisdb_storage::memtable::MemTable;
+
+use loom::sync::Arc;
+use loom::thread;
+
+/// Tests that two racing inserts of different keys are both linked.
+///
+/// Verifies that in every interleaving:
+/// - Neither level-0 compare-and-swap loses the other's node
+/// - Both keys are readable and scanned in order afterwards
+#[test]
+fn concurrent_puts_of_different_keys_are_both_visible() {
+    loom::model(|| {
+        let memtable = Arc::new(MemTable::new(1024));
+
+        let writer = {
+            let memtable = Arc::clone(&memtable);
+            thread::spawn(move || memtable.put(b"a".to_vec(), b"1".to_vec(), 1).unwrap())
+        };
+        memtable.put(b"b".to_vec(), b"2".to_vec(), 1).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(memtable.entry_count(), 2);
+        assert_eq!(
+            memtable.scan(b"a", b"c", 1),
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec())
+            ]
+        );
+    });
+}
+
+/// Tests that racing versions of the same key keep newest-first order.
+///
+/// Verifies that in every interleaving:
+/// - Both versions are inserted
+/// - A read at each timestamp returns the version written at it
+#[test]
+fn concurrent_versions_of_same_key_stay_ordered() {
+    loom::model(|| {
+        let memtable = Arc::new(MemTable::new(1024));
+
+        let writer = {
+            let memtable = Arc::clone(&memtable);
+            thread::spawn(move || memtable.put(b"k".to_vec(), b"old".to_vec(), 1).unwrap())
+        };
+        memtable.delete(b"k".to_vec(), 2).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(memtable.entry_count(), 2);
+        assert_eq!(
+            memtable.get(b"k", 1),
+            Some((b"old".to_vec(), Operation::Put))
+        );
+        assert_eq!(memtable.get(b"k", 2), Some((Vec::new(), Operation::Delete)));
+    });
+}
+
+/// Tests that a reader racing an insert sees nothing or the whole entry.
+///
+/// Verifies that in every interleaving:
+/// - The node's key and value are published before it becomes reachable
+#[test]
+fn get_during_put_sees_none_or_complete_value() {
+    loom::model(|| {
+        let memtable = Arc::new(MemTable::new(1024));
+
+        let writer = {
+            let memtable = Arc::clone(&memtable);
+            thread::spawn(move || memtable.put(b"k".to_vec(), b"value".to_vec(), 1).unwrap())
+        };
+
+        if let Some((value, op)) = memtable.get(b"k", 1) {
+            assert_eq!(value, b"value".to_vec());
+            assert_eq!(op, Operation::Put);
+        }
+
+        writer.join().unwrap();
+        assert!(memtable.get(b"k", 1).is_some());
+    });
+}
+
+/// Tests that two writers racing for the last capacity cannot both win.
+///
+/// Verifies that in every interleaving:
+/// - Exactly one put succeeds and the other reports MemTableFull
+/// - Memory usage stays within the limit
+#[test]
+fn concurrent_puts_cannot_both_take_last_capacity() {
+    loom::model(|| {
+        // Each put reserves 1 + 1 + 64 = 66 bytes; only one fits
+        let memtable = Arc::new(MemTable::new(100));
+
+        let writer = {
+            let memtable = Arc::clone(&memtable);
+            thread::spawn(move || memtable.put(b"a".to_vec(), b"1".to_vec(), 1))
+        };
+        let local = memtable.put(b"b".to_vec(), b"2".to_vec(), 1);
+        let remote = writer.join().unwrap();
+
+        let full = [&local, &remote]
+            .iter()
+            .filter(|result| matches!(result, Err(Error::MemTableFull)))
+            .count();
+        assert_eq!(full, 1);
+        assert_eq!(memtable.entry_count(), 1);
+        assert!(memtable.memory_usage() <= 100);
+    });
+}
@@ -0,0 +1,163 @@
+//! Integration tests for merge operands flowing through WAL, MemTable, and SSTables
+
+use ferrisdb_core::{Operation, SyncMode};
+use ferrisdb_storage::compaction::CompactionIterator;
+use ferrisdb_storage::memtable::MemTable;
+use ferrisdb_storage::merge::{self, U64AddOperator};
+use ferrisdb_storage::sstable::{InternalKey, SSTableReader, SSTableWriter};
+use ferrisdb_storage::wal::{WALEntry, WALReader, WALWriter};
+use ferrisdb_storage::MemTableKind;
+
+use tempfile::TempDir;
+
+use std::sync::Arc;
+
+fn counter(n: u64) -> Vec<u8> {
+    n.to_le_bytes().to_vec()
+}
+
+/// Tests that merge operands survive WAL replay into a MemTable.
+///
+/// This test verifies that:
+/// - Merge entries are written to and read back from the WAL as merges
+/// - Replaying them into a MemTable resolves to the merged value
+#[test]
+fn wal_replay_preserves_merge_operands() {
+    let temp_dir = TempDir::new().unwrap();
+    let wal_path = temp_dir.path().join("merge.wal");
+
+    {
+        let writer = WALWriter::new(&wal_path, SyncMode::Full, 1024 * 1024).unwrap();
+        writer
+            .append(&WALEntry::new_put(b"hits".to_vec(), counter(10), 1).unwrap())
+            .unwrap();
+        for ts in 2..=4 {
+            writer
+                .append(&WALEntry::new_merge(b"hits".to_vec(), counter(1), ts).unwrap())
+                .unwrap();
+        }
+        writer.sync().unwrap();
+    }
+
+    let memtable = MemTable::new(1024 * 1024);
+    for entry in WALReader::new(&wal_path).unwrap().read_all().unwrap() {
+        match entry.operation {
+            Operation::Put => memtable.put(entry.key, entry.value, entry.timestamp),
+            Operation::Delete => memtable.delete(entry.key, entry.timestamp),
+            Operation::Merge => memtable.merge(entry.key, entry.value, entry.timestamp),
+        }
+        .unwrap();
+    }
+
+    let value = merge::resolve(&U64AddOperator, b"hits", memtable.versions(b"hits", 10)).unwrap();
+    assert_eq!(value, Some(counter(13)));
+
+    // Reads at older timestamps only apply the operands visible to them
+    let value = merge::resolve(&U64AddOperator, b"hits", memtable.versions(b"hits", 2)).unwrap();
+    assert_eq!(value, Some(counter(11)));
+}
+
+/// Tests read-time resolution across a MemTable and an older SSTable.
+///
+/// This test verifies that:
+/// - Operands in the MemTable are applied on top of a base in an SSTable
+/// - Both MemTable kinds expose versions in the same order
+/// - Merge operations roundtrip through the SSTable format
+#[test]
+fn resolve_applies_memtable_operands_to_sstable_base() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("base.sst");
+
+    let mut writer = SSTableWriter::new(&path).unwrap();
+    writer
+        .add(
+            InternalKey::new(b"hits".to_vec(), 2),
+            counter(5),
+            Operation::Merge,
+        )
+        .unwrap();
+    writer
+        .add(
+            InternalKey::new(b"hits".to_vec(), 1),
+            counter(100),
+            Operation::Put,
+        )
+        .unwrap();
+    writer.finish().unwrap();
+    let mut reader = SSTableReader::open(&path).unwrap();
+
+    for kind in [MemTableKind::SkipList, MemTableKind::HashIndex] {
+        let memtable = MemTable::with_kind(1024, kind);
+        memtable.merge(b"hits".to_vec(), counter(3), 3).unwrap();
+        memtable.merge(b"hits".to_vec(), counter(7), 4).unwrap();
+
+        // Newest source first: the MemTable, then the SSTable
+        let versions = memtable
+            .versions(b"hits", 10)
+            .into_iter()
+            .chain(reader.get_versions(&b"hits".to_vec(), 10).unwrap());
+
+        let value = merge::resolve(&U64AddOperator, b"hits", versions).unwrap();
+        assert_eq!(value, Some(counter(115)), "{:?}", kind);
+    }
+}
+
+/// Tests that compaction folds operand chains and readers see the same value.
+///
+/// This test verifies that:
+/// - Compacting an SSTable folds operands at or below the oldest snapshot
+/// - Versions newer than the oldest snapshot are kept unchanged
+/// - Resolving the compacted output gives the same value as before
+#[test]
+fn compaction_folds_merge_chain_without_changing_reads() {
+    let temp_dir = TempDir::new().unwrap();
+    let input_path = temp_dir.path().join("input.sst");
+    let output_path = temp_dir.path().join("output.sst");
+
+    let mut writer = SSTableWriter::new(&input_path).unwrap();
+    for ts in (2..=10u64).rev() {
+        writer
+            .add(
+                InternalKey::new(b"hits".to_vec(), ts),
+                counter(1),
+                Operation::Merge,
+            )
+            .unwrap();
+    }
+    writer
+        .add(
+            InternalKey::new(b"hits".to_vec(), 1),
+            counter(0),
+            Operation::Put,
+        )
+        .unwrap();
+    writer.finish().unwrap();
+
+    let mut input = SSTableReader::open(&input_path).unwrap();
+    let before = merge::resolve(
+        &U64AddOperator,
+        b"hits",
+        input.get_versions(&b"hits".to_vec(), 10).unwrap(),
+    )
+    .unwrap();
+
+    let mut writer = SSTableWriter::new(&output_path).unwrap();
+    let compaction = CompactionIterator::new(input.iter().unwrap(), 8)
+        .with_merge_operator(Arc::new(U64AddOperator));
+    for entry in compaction {
+        let entry = entry.unwrap();
+        writer.add(entry.key, entry.value, entry.operation).unwrap();
+    }
+    let info = writer.finish().unwrap();
+
+    // ts 10 and 9 are kept; ts 8 down to 1 collapse into one Put
+    assert_eq!(info.entry_count, 3);
+
+    let mut output = SSTableReader::open(&output_path).unwrap();
+    let versions = output.get_versions(&b"hits".to_vec(), 10).unwrap();
+    assert_eq!(versions[2], (counter(7), 8, Operation::Put));
+
+    let after = merge::resolve(&U64AddOperator, b"hits", versions).unwrap();
+    assert_eq!(after, before);
+    assert_eq!(after, Some(counter(9)));
+}
@@ -0,0 +1,171 @@
+//! Deterministic simulations of crashes and I/O errors
+//!
+//! Each test runs a workload against a `SimVfs` for many seeds. A failure
+//! names its seed, and rerunning with that seed replays the same torn
+//! writes and injected errors.
+
+use ferrisdb_core::{Operation, SyncMode};
+use ferrisdb_storage::comparator;
+use ferrisdb_storage::sstable::{InternalKey, SSTableReader, SSTableWriter};
+use ferrisdb_storage::vfs::{Faults, SimVfs, Vfs};
+use ferrisdb_storage::wal::{WALEntry, WALReader, WALWriter};
+use ferrisdb_test_utils::entries::wal_put;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const SEEDS: u64 = 200;
+const WAL_PATH: &str = "/db/wal/000001.log";
+
+/// Returns a filesystem for `seed`, and the `SimVfs` handle the test
+/// crashes it through
+fn sim(seed: u64) -> (Arc<SimVfs>, Arc<dyn Vfs>) {
+    let sim = Arc::new(SimVfs::new(seed));
+    let vfs: Arc<dyn Vfs> = sim.clone();
+    (sim, vfs)
+}
+
+/// Reads entries until the end of the log or the first unreadable record
+fn read_surviving(vfs: &Arc<dyn Vfs>) -> Vec<WALEntry> {
+    let reader = WALReader::new_in(vfs, WAL_PATH).unwrap();
+    reader.map_while(|entry| entry.ok()).collect()
+}
+
+/// Tests WAL recovery after power loss at arbitrary points.
+///
+/// This test verifies that:
+/// - Every entry written before a successful `sync()` survives the crash
+/// - Unsynced entries survive only as a prefix, never torn or reordered
+/// - The same seed reproduces the same surviving log
+#[test]
+fn wal_recovery_keeps_synced_entries_after_simulated_crashes() {
+    for seed in 0..SEEDS {
+        let run = || {
+            let (sim, vfs) = sim(seed);
+            let writer = WALWriter::new_in(&vfs, WAL_PATH, SyncMode::None, 1 << 20, 0).unwrap();
+
+            let sync_every = seed % 7 + 1;
+            let written = 20 + seed % 30;
+            let mut synced = 0;
+            for i in 0..written {
+                writer.append(&wal_put(i)).unwrap();
+                if (i + 1) % sync_every == 0 {
+                    writer.sync().unwrap();
+                    synced = i + 1;
+                }
+            }
+            sim.crash();
+            (synced, written, read_surviving(&vfs))
+        };
+
+        let (synced, written, survivors) = run();
+        assert!(
+            survivors.len() as u64 >= synced,
+            "seed {}: {} of {} synced entries survived",
+            seed,
+            survivors.len(),
+            synced
+        );
+        assert!(survivors.len() as u64 <= written, "seed {}", seed);
+        for (i, survivor) in survivors.iter().enumerate() {
+            assert_eq!(*survivor, wal_put(i as u64), "seed {}", seed);
+        }
+        assert_eq!(run().2, survivors, "seed {} is not reproducible", seed);
+    }
+}
+
+/// Tests that injected write and sync errors never lose acknowledged
+/// entries.
+///
+/// This test verifies that:
+/// - Writers stopping at the first error keep every entry appended before it
+/// - Entries whose append failed survive at most as a complete record
+/// - Errors surface as errors rather than panics or corrupt logs
+#[test]
+fn wal_keeps_acknowledged_entries_despite_injected_errors() {
+    let faults = Faults {
+        write_error: 0.05,
+        sync_error: 0.05,
+        ..Faults::default()
+    };
+    let mut failures = 0;
+    for seed in 0..SEEDS {
+        let (sim, vfs) = sim(seed);
+        let writer = WALWriter::new_in(&vfs, WAL_PATH, SyncMode::Full, 1 << 20, 0).unwrap();
+        sim.set_faults(faults);
+
+        let mut acknowledged = 0;
+        for i in 0..50 {
+            if writer.append(&wal_put(i)).is_err() {
+                failures += 1;
+                break;
+            }
+            acknowledged = i + 1;
+        }
+        sim.set_faults(Faults::default());
+        sim.crash();
+
+        let survivors = read_surviving(&vfs);
+        assert!(
+            survivors.len() as u64 >= acknowledged,
+            "seed {}: lost acknowledged entries",
+            seed
+        );
+        assert!(survivors.len() as u64 <= acknowledged + 1, "seed {}", seed);
+        for (i, survivor) in survivors.iter().enumerate() {
+            assert_eq!(*survivor, wal_put(i as u64), "seed {}", seed);
+        }
+    }
+    assert!(failures > 0, "no seed injected an error");
+}
+
+/// Tests that an SSTable is either complete after a crash or absent.
+///
+/// This test verifies that:
+/// - A finished table, synced with its directory entry, reads back fully
+/// - A table abandoned before `finish()` leaves no file to misread
+#[test]
+fn sstable_survives_crash_only_once_finished() {
+    for seed in 0..SEEDS / 4 {
+        let (sim, vfs) = sim(seed);
+        vfs.create_dir_all(Path::new("/db")).unwrap();
+
+        let count = 100 + seed * 10;
+        let write = |path: &str, finish: bool| {
+            let mut writer = SSTableWriter::new_in(&vfs, path).unwrap();
+            for i in 0..count {
+                let key = InternalKey::new(format!("key{:06}", i).into_bytes(), 1);
+                writer.add(key, vec![7; 32], Operation::Put).unwrap();
+            }
+            if finish {
+                writer.finish().unwrap();
+            }
+        };
+        write("/db/finished.sst", true);
+        write("/db/abandoned.sst", false);
+        sim.crash();
+
+        assert!(!vfs.exists(Path::new("/db/abandoned.sst")), "seed {}", seed);
+        let mut reader =
+            SSTableReader::open_in(&vfs, "/db/finished.sst", comparator::bytewise()).unwrap();
+        let entries: Vec<_> = reader.iter().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(entries.len() as u64, count, "seed {}", seed);
+    }
+}
+
+/// Tests that WAL files take their sequence number from the VFS clock.
+///
+/// This test verifies that:
+/// - The header's file sequence is the simulated time in microseconds
+/// - Advancing the simulated clock changes it deterministically
+#[test]
+fn wal_header_sequence_follows_simulated_clock() {
+    let (sim, vfs) = sim(0);
+    sim.advance(Duration::from_secs(5));
+    WALWriter::new_in(&vfs, WAL_PATH, SyncMode::Full, 1 << 20, 0).unwrap();
+
+    let reader = WALReader::new_in(&vfs, WAL_PATH).unwrap();
+    let expected = vfs.clock().now().as_micros() as u64;
+    assert_eq!(reader.header().file_sequence, expected);
+}
@@ -0,0 +1,689 @@
+//! Integration tests for the storage engine facade
+
+use ferrisdb_core::error::ErrorCode;
+use ferrisdb_storage::comparator::ReverseBytewiseComparator;
+use ferrisdb_storage::merge::U64AddOperator;
+use ferrisdb_storage::prefix::{DelimitedPrefix, FixedPrefix, PrefixExtractor};
+use ferrisdb_storage::{
+    CompactionStrategyKind, EngineIterator, Options, ReadOptions, StorageConfig, StorageEngine,
+    WriteBatch,
+};
+use ferrisdb_test_utils::entries::{key, value};
+
+use proptest::prelude::*;
+use tempfile::TempDir;
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Options with tiny MemTables so a few hundred writes flush and compact
+fn small_options(dir: &Path, strategy: CompactionStrategyKind) -> Options {
+    Options::new(dir)
+        .with_memtable_size(8 * 1024)
+        .with_compaction_strategy(strategy)
+}
+
+/// Tests the engine against a model across flushes, compactions and reopens.
+///
+/// This test verifies that:
+/// - Gets and scans match a `BTreeMap` model with either compaction strategy
+/// - Overwrites and deletes shadow older versions in lower levels
+/// - Everything written survives close and reopen
+#[test]
+fn engine_matches_model_across_compactions_and_reopen() {
+    for strategy in [
+        CompactionStrategyKind::Leveled,
+        CompactionStrategyKind::SizeTiered,
+    ] {
+        let dir = TempDir::new().unwrap();
+        let mut model = BTreeMap::new();
+
+        let engine = StorageEngine::open(small_options(dir.path(), strategy)).unwrap();
+        for round in 0..4 {
+            for i in 0..300 {
+                let k = key((i * 7 + round * 13) % 400);
+                if (i + round) % 5 == 0 {
+                    engine.delete(k.clone()).unwrap();
+                    model.remove(&k);
+                } else {
+                    let v = format!("r{}-{}", round, i).into_bytes();
+                    engine.put(k.clone(), v.clone()).unwrap();
+                    model.insert(k, v);
+                }
+            }
+        }
+
+        let check = |engine: &StorageEngine| {
+            for i in 0..400 {
+                assert_eq!(engine.get(&key(i)).unwrap(), model.get(&key(i)).cloned());
+            }
+            let expected: Vec<_> = model
+                .range(key(100)..key(300))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            assert_eq!(engine.scan(key(100)..key(300)).unwrap(), expected);
+        };
+
+        check(&engine);
+        engine.close().unwrap();
+
+        let engine = StorageEngine::open(small_options(dir.path(), strategy)).unwrap();
+        check(&engine);
+    }
+}
+
+/// Tests concurrent writers and readers on one engine.
+///
+/// This test verifies that:
+/// - Writes from several threads are all applied
+/// - Merge operands from several threads are all counted
+/// - Readers never fail while flushes and compactions run
+#[test]
+fn engine_handles_concurrent_writers_and_readers() {
+    let dir = TempDir::new().unwrap();
+    let engine = Arc::new(
+        StorageEngine::open(
+            small_options(dir.path(), CompactionStrategyKind::Leveled)
+                .with_merge_operator(Arc::new(U64AddOperator)),
+        )
+        .unwrap(),
+    );
+
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for i in 0..250 {
+                    engine
+                        .put(key(t * 1000 + i), format!("{}", i).into_bytes())
+                        .unwrap();
+                    engine
+                        .merge(b"counter".to_vec(), 1u64.to_le_bytes().to_vec())
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    let reader = {
+        let engine = Arc::clone(&engine);
+        thread::spawn(move || {
+            for i in 0..250 {
+                engine.get(&key(i)).unwrap();
+                engine.scan(key(0)..key(100)).unwrap();
+            }
+        })
+    };
+
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    reader.join().unwrap();
+
+    assert_eq!(
+        engine.get(b"counter").unwrap(),
+        Some(1000u64.to_le_bytes().to_vec())
+    );
+    assert_eq!(engine.scan(key(0)..key(4000)).unwrap().len(), 1000);
+}
+
+/// Tests manual range compaction through the engine.
+///
+/// This test verifies that:
+/// - `compact_range` flushes the MemTables and merges every table of the
+///   range into a single run, with either compaction strategy
+/// - Versions shadowed by newer puts and deletes are dropped, and so are
+///   tombstones once a compaction of the whole database reaches them
+/// - Keys outside a partial range keep their tables
+#[test]
+fn compact_range_merges_the_range_into_one_run() {
+    for strategy in [
+        CompactionStrategyKind::Leveled,
+        CompactionStrategyKind::SizeTiered,
+    ] {
+        let dir = TempDir::new().unwrap();
+        let engine =
+            StorageEngine::open(Options::new(dir.path()).with_compaction_strategy(strategy))
+                .unwrap();
+
+        for i in 0..100 {
+            engine.put(key(i), b"old".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        for i in 0..100 {
+            if i % 2 == 0 {
+                engine.delete(key(i)).unwrap();
+            } else {
+                engine.put(key(i), b"new".to_vec()).unwrap();
+            }
+        }
+        engine.put(b"other".to_vec(), b"x".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put(b"other".to_vec(), b"y".to_vec()).unwrap();
+
+        // "other" sorts after the range, so its newest version stays put
+        engine.compact_range(key(0)..key(50)).unwrap();
+        let stats = engine.statistics();
+        assert_eq!(stats.compactions, 1);
+        assert_eq!(engine.get(b"other").unwrap(), Some(b"y".to_vec()));
+
+        engine.compact_range::<[u8], _>(..).unwrap();
+        let stats = engine.statistics();
+        assert_eq!(
+            stats.level_files.iter().filter(|&&files| files > 0).count(),
+            1,
+            "{:?}",
+            stats.level_files
+        );
+        // One version per live key, as the tombstones hid nothing left
+        // below them: 50 puts and "other"
+        assert_eq!(stats.estimated_num_keys, 51);
+
+        let pairs = engine.scan::<[u8], _>(..).unwrap();
+        assert_eq!(pairs.len(), 51);
+        assert!(pairs[..50].iter().all(|(_, value)| value == b"new"));
+    }
+}
+
+/// Tests an engine whose keys are ordered by a custom comparator.
+///
+/// This test verifies that:
+/// - Scans return keys in the comparator's order across the MemTable,
+///   flushed tables and compacted tables
+/// - Range bounds, range deletes and pessimistic scans follow that order
+/// - The order survives reopening, and reopening with a comparator of
+///   another name fails
+#[test]
+fn engine_orders_keys_by_its_comparator() {
+    let dir = TempDir::new().unwrap();
+    let options = || {
+        small_options(dir.path(), CompactionStrategyKind::Leveled)
+            .with_comparator(Arc::new(ReverseBytewiseComparator))
+    };
+    let descending =
+        |range: std::ops::RangeInclusive<usize>| -> Vec<Vec<u8>> { range.rev().map(key).collect() };
+    let keys = |pairs: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+        pairs.into_iter().map(|(key, _)| key).collect()
+    };
+
+    {
+        let engine = StorageEngine::open(options()).unwrap();
+        for i in 0..300 {
+            engine.put(key(i), b"old".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.compact_range::<[u8], _>(..).unwrap();
+        for i in (0..300).step_by(3) {
+            engine.put(key(i), b"new".to_vec()).unwrap();
+        }
+
+        assert_eq!(
+            keys(engine.scan::<[u8], _>(..).unwrap()),
+            descending(0..=299)
+        );
+        // In descending order the range starts at the larger key
+        assert_eq!(
+            keys(engine.scan(key(200)..key(100)).unwrap()),
+            descending(101..=200)
+        );
+        assert_eq!(engine.get(&key(42)).unwrap(), Some(b"new".to_vec()));
+
+        let mut batch = WriteBatch::new();
+        batch.delete_range(key(50)..key(40));
+        engine.write(batch, false).unwrap();
+
+        let mut txn = engine.begin_pessimistic_transaction();
+        txn.put(key(45), b"txn".to_vec()).unwrap();
+        let pairs = txn.scan(key(52)..key(38)).unwrap();
+        assert_eq!(
+            keys(pairs),
+            vec![key(52), key(51), key(45), key(40), key(39)]
+        );
+        txn.rollback().unwrap();
+    }
+
+    let engine = StorageEngine::open(options()).unwrap();
+    let pairs = engine.scan::<[u8], _>(..).unwrap();
+    assert_eq!(pairs.len(), 290);
+    assert!(pairs.windows(2).all(|pair| pair[0].0 > pair[1].0));
+    drop(engine);
+
+    let err = StorageEngine::open(small_options(dir.path(), CompactionStrategyKind::Leveled))
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), ErrorCode::InvalidArgument);
+}
+
+/// A step of an iterator under test, with key numbers for seeks
+#[derive(Debug, Clone)]
+enum Move {
+    SeekToFirst,
+    SeekToLast,
+    Seek(usize),
+    SeekForPrev(usize),
+    Next,
+    Prev,
+}
+
+fn iterator_move() -> impl Strategy<Value = Move> {
+    prop_oneof![
+        1 => Just(Move::SeekToFirst),
+        1 => Just(Move::SeekToLast),
+        1 => (0usize..70).prop_map(Move::Seek),
+        1 => (0usize..70).prop_map(Move::SeekForPrev),
+        4 => Just(Move::Next),
+        4 => Just(Move::Prev),
+    ]
+}
+
+/// Applies a move to a model iterator over `model`, positioned at `at`
+fn model_move(
+    model: &BTreeMap<Vec<u8>, Vec<u8>>,
+    at: Option<Vec<u8>>,
+    step: &Move,
+) -> Option<Vec<u8>> {
+    let first =
+        |range: (Bound<Vec<u8>>, Bound<Vec<u8>>)| model.range(range).next().map(|(k, _)| k.clone());
+    let last = |range: (Bound<Vec<u8>>, Bound<Vec<u8>>)| {
+        model.range(range).next_back().map(|(k, _)| k.clone())
+    };
+    match step {
+        Move::SeekToFirst => first((Bound::Unbounded, Bound::Unbounded)),
+        Move::SeekToLast => last((Bound::Unbounded, Bound::Unbounded)),
+        Move::Seek(i) => first((Bound::Included(key(*i)), Bound::Unbounded)),
+        Move::SeekForPrev(i) => last((Bound::Unbounded, Bound::Included(key(*i)))),
+        Move::Next => first((Bound::Excluded(at?), Bound::Unbounded)),
+        Move::Prev => last((Bound::Unbounded, Bound::Excluded(at?))),
+    }
+}
+
+fn engine_move(iter: &mut EngineIterator, step: &Move) {
+    match step {
+        Move::SeekToFirst => iter.seek_to_first().unwrap(),
+        Move::SeekToLast => iter.seek_to_last().unwrap(),
+        Move::Seek(i) => iter.seek(&key(*i)).unwrap(),
+        Move::SeekForPrev(i) => iter.seek_for_prev(&key(*i)).unwrap(),
+        Move::Next | Move::Prev if !iter.valid() => {}
+        Move::Next => iter.next().unwrap(),
+        Move::Prev => iter.prev().unwrap(),
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    /// Tests engine iterators against a model iterator.
+    ///
+    /// This test verifies that:
+    /// - Seeks in both directions land on the same key as in a `BTreeMap`
+    /// - Steps and changes of direction visit the same neighbours
+    /// - Keys with versions in the MemTable and several tables resolve
+    ///   to their newest value, and deleted keys are skipped
+    #[test]
+    fn iter_moves_like_a_model_iterator(
+        writes in prop::collection::vec((0usize..60, prop::option::of(0u8..8)), 1..400),
+        moves in prop::collection::vec(iterator_move(), 1..80),
+    ) {
+        let dir = TempDir::new().unwrap();
+        let engine = StorageEngine::open(Options::new(dir.path()).with_memtable_size(2048)).unwrap();
+        let mut model = BTreeMap::new();
+        for (i, value) in writes {
+            match value {
+                Some(value) => {
+                    let value = vec![value; 40];
+                    engine.put(key(i), value.clone()).unwrap();
+                    model.insert(key(i), value);
+                }
+                None => {
+                    engine.delete(key(i)).unwrap();
+                    model.remove(&key(i));
+                }
+            }
+        }
+
+        let mut iter = engine.iter(ReadOptions::new()).unwrap();
+        let mut at = None;
+        for step in &moves {
+            if matches!(step, Move::Next | Move::Prev) && at.is_none() {
+                continue;
+            }
+            at = model_move(&model, at.take(), step);
+            engine_move(&mut iter, step);
+            prop_assert_eq!(iter.key(), at.as_deref(), "after {:?}", step);
+            if let Some(at) = &at {
+                prop_assert_eq!(iter.value(), model.get(at).map(Vec::as_slice));
+            }
+        }
+    }
+}
+
+/// Tests that an iterator keeps reading the tables it started with.
+///
+/// This test verifies that:
+/// - An iterator created before overwrites and compactions sees none of
+///   them, in either direction
+/// - The tables it reads stay on disk until it is dropped
+#[test]
+fn iter_pins_its_version_through_compactions() {
+    let dir = TempDir::new().unwrap();
+    let engine =
+        StorageEngine::open(small_options(dir.path(), CompactionStrategyKind::Leveled)).unwrap();
+    for i in 0..300 {
+        engine.put(key(i), b"old".to_vec()).unwrap();
+    }
+    engine.flush().unwrap();
+    let table_files = || {
+        std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let path = entry.as_ref().unwrap().path();
+                path.extension().is_some_and(|ext| ext == "sst")
+            })
+            .count()
+    };
+
+    let mut iter = engine.iter(ReadOptions::new()).unwrap();
+    for i in 0..300 {
+        engine.put(key(i), b"new".to_vec()).unwrap();
+    }
+    engine.compact_range::<[u8], _>(..).unwrap();
+    let pinned_files = table_files();
+
+    iter.seek_to_first().unwrap();
+    let mut count = 0;
+    while iter.valid() {
+        assert_eq!(iter.value(), Some(&b"old"[..]));
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 300);
+    iter.seek_for_prev(&key(150)).unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), Some(&key(149)[..]));
+
+    drop(iter);
+    assert!(table_files() < pinned_files);
+    let mut iter = engine.iter(ReadOptions::new()).unwrap();
+    iter.seek(&key(7)).unwrap();
+    assert_eq!(iter.value(), Some(&b"new"[..]));
+}
+
+/// Tests prefix reads over tables that each hold some of every prefix range.
+///
+/// This test verifies that:
+/// - A prefix read returns exactly the model's keys with the prefix
+/// - Tables whose bloom filter excludes the prefix are skipped and counted
+/// - Tables written with another extractor, or none, are never skipped
+#[test]
+fn iter_with_prefix_skips_tables_its_filter_excludes() {
+    let dir = TempDir::new().unwrap();
+    let options = |extractor: Option<Arc<dyn PrefixExtractor>>| {
+        // Keep every flushed table in level 0
+        let options = Options::from_config(StorageConfig {
+            data_dir: dir.path().to_path_buf(),
+            wal_dir: dir.path().join("wal"),
+            level0_file_num_compaction_trigger: 100,
+            level0_slowdown_writes_trigger: 200,
+            level0_stop_writes_trigger: 300,
+            ..Default::default()
+        });
+        match extractor {
+            Some(extractor) => options.with_prefix_extractor(extractor),
+            None => options,
+        }
+    };
+    let user = |i: usize| format!("user:{:03}:", i).into_bytes();
+    let prefix_keys = |engine: &StorageEngine, prefix: Vec<u8>| {
+        let mut iter = engine.iter(ReadOptions::new().with_prefix(prefix)).unwrap();
+        iter.seek_to_first().unwrap();
+        let mut keys = Vec::new();
+        while let Some(key) = iter.key() {
+            keys.push(key.to_vec());
+            iter.next().unwrap();
+        }
+        keys
+    };
+
+    let engine =
+        StorageEngine::open(options(Some(Arc::new(DelimitedPrefix::new(b':', 2))))).unwrap();
+    let mut model = BTreeMap::new();
+    // Every table spans all users but holds only one in eight
+    for table in 0..8 {
+        engine.put(b"a".to_vec(), b"first".to_vec()).unwrap();
+        engine.put(b"z".to_vec(), b"last".to_vec()).unwrap();
+        for i in (table..64).step_by(8) {
+            for field in ["age", "email", "name"] {
+                let key = [user(i), field.as_bytes().to_vec()].concat();
+                engine.put(key.clone(), b"v".to_vec()).unwrap();
+                model.insert(key, ());
+            }
+        }
+        engine.flush().unwrap();
+    }
+    assert_eq!(engine.statistics().level0_files(), 8);
+
+    for i in 0..64 {
+        let expected: Vec<_> = model
+            .keys()
+            .filter(|key| key.starts_with(&user(i)))
+            .cloned()
+            .collect();
+        assert_eq!(prefix_keys(&engine, user(i)), expected);
+    }
+    assert!(prefix_keys(&engine, user(999)).is_empty());
+    // 7 of 8 tables hold none of each user, all 8 none of user 999
+    let skips = engine.statistics().prefix_filter_skips;
+    assert!(skips >= 420, "only {} tables skipped", skips);
+
+    // A partial prefix can't use the filters, but is still exact
+    assert_eq!(prefix_keys(&engine, b"user:".to_vec()).len(), model.len());
+    assert_eq!(engine.statistics().prefix_filter_skips, skips);
+    drop(engine);
+
+    let engine = StorageEngine::open(options(Some(Arc::new(FixedPrefix::new(9))))).unwrap();
+    assert_eq!(prefix_keys(&engine, user(5)).len(), 3);
+    drop(engine);
+    let engine = StorageEngine::open(options(None)).unwrap();
+    assert_eq!(prefix_keys(&engine, user(5)).len(), 3);
+    assert_eq!(engine.statistics().prefix_filter_skips, 0);
+}
+
+/// Tests an engine reading and compacting with direct I/O.
+///
+/// This test verifies that:
+/// - Gets and scans match a model when tables are read with direct I/O
+/// - Compactions read and write tables correctly with direct I/O
+/// - Tables written with direct I/O reopen with buffered I/O
+#[test]
+fn engine_with_direct_io_matches_model_across_compactions() {
+    let dir = TempDir::new().unwrap();
+    let options = |direct: bool| {
+        small_options(dir.path(), CompactionStrategyKind::Leveled)
+            .with_direct_reads(direct)
+            .with_direct_io_for_compaction(direct)
+    };
+    let mut model = BTreeMap::new();
+
+    let engine = StorageEngine::open(options(true)).unwrap();
+    for round in 0..3 {
+        for i in 0..300 {
+            let k = key((i * 11 + round * 7) % 500);
+            if (i + round) % 6 == 0 {
+                engine.delete(k.clone()).unwrap();
+                model.remove(&k);
+            } else {
+                // Values of odd lengths leave tables unaligned
+                let v = vec![b'v'; 100 + (i * 37 + round) % 900];
+                engine.put(k.clone(), v.clone()).unwrap();
+                model.insert(k, v);
+            }
+        }
+    }
+    engine.compact_range::<[u8], _>(..).unwrap();
+
+    let check = |engine: &StorageEngine| {
+        for i in 0..500 {
+            assert_eq!(engine.get(&key(i)).unwrap(), model.get(&key(i)).cloned());
+        }
+        let expected: Vec<_> = model.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        assert_eq!(engine.scan::<[u8], _>(..).unwrap(), expected);
+    };
+    check(&engine);
+    engine.close().unwrap();
+
+    let engine = StorageEngine::open(options(false)).unwrap();
+    check(&engine);
+}
+
+/// Tests removing files left behind by a crash when the engine opens.
+///
+/// This test verifies that:
+/// - Tables no MANIFEST refers to and temporary files are reported
+/// - A dry run keeps them, and a normal open deletes them
+/// - Live tables, the MANIFEST and unknown files are never touched
+#[test]
+fn open_deletes_orphaned_files_unless_dry_run() {
+    let dir = TempDir::new().unwrap();
+    let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+    engine.put(b"key".to_vec(), b"value".to_vec()).unwrap();
+    engine.flush().unwrap();
+    engine.close().unwrap();
+
+    // What a crash in the middle of a compaction and a metadata update
+    // leaves behind
+    let orphan = dir.path().join("999999.sst");
+    let temp = dir.path().join("COLUMN_FAMILIES.tmp");
+    let unknown = dir.path().join("notes.txt");
+    for path in [&orphan, &temp, &unknown] {
+        std::fs::write(path, b"leftover").unwrap();
+    }
+
+    let engine =
+        StorageEngine::open(Options::new(dir.path()).with_orphan_files_dry_run(true)).unwrap();
+    let orphans = &engine.recovery_report().orphans;
+    assert_eq!(orphans.files, vec![orphan.clone(), temp.clone()]);
+    assert_eq!(orphans.bytes, 16);
+    assert!(orphan.exists() && temp.exists());
+    engine.close().unwrap();
+
+    let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+    assert_eq!(engine.recovery_report().orphans.files.len(), 2);
+    assert!(!orphan.exists() && !temp.exists());
+    assert!(unknown.exists());
+    assert_eq!(engine.get(b"key").unwrap(), Some(b"value".to_vec()));
+    engine.close().unwrap();
+
+    let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+    assert!(engine.recovery_report().orphans.files.is_empty());
+}
+
+/// Tests catching damaged tables with paranoid checks.
+///
+/// This test verifies that:
+/// - Flushes and compactions with paranoid checks install their tables
+/// - Without paranoid checks a flipped byte in a data block goes unnoticed
+/// - With them the read fails as corruption instead of returning it
+#[test]
+fn paranoid_checks_catch_damaged_data_blocks() {
+    let dir = TempDir::new().unwrap();
+    let options = |paranoid| Options::new(dir.path()).with_paranoid_checks(paranoid);
+    let engine = StorageEngine::open(options(true)).unwrap();
+    for i in 0..1000 {
+        engine.put(key(i), value(i)).unwrap();
+        if i % 250 == 249 {
+            engine.flush().unwrap();
+        }
+    }
+    engine.compact_range::<[u8], _>(..).unwrap();
+    assert_eq!(engine.get(&key(42)).unwrap(), Some(value(42)));
+    engine.close().unwrap();
+
+    let tables: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+        .collect();
+    assert_eq!(tables.len(), 1);
+    let mut file = std::fs::read(&tables[0]).unwrap();
+    let at = file
+        .windows(value(42).len())
+        .position(|window| window == value(42))
+        .unwrap();
+    file[at + value(42).len() - 1] = b'3';
+    std::fs::write(&tables[0], &file).unwrap();
+
+    let engine = StorageEngine::open(options(false)).unwrap();
+    assert_eq!(engine.get(&key(42)).unwrap(), Some(value(43)));
+    engine.close().unwrap();
+
+    let engine = StorageEngine::open(options(true)).unwrap();
+    let err = engine.get(&key(42)).unwrap_err();
+    assert_eq!(err.code(), ErrorCode::Corruption, "{}", err);
+    // Blocks other than the damaged one still read
+    assert_eq!(engine.get(&key(999)).unwrap(), Some(value(999)));
+}
+
+/// Tests the scrub job finding bit rot in a table nothing reads.
+///
+/// This test verifies that:
+/// - The job reads cold tables back on its own, without any user read
+/// - A damaged table reaches the corruption handler exactly once
+/// - The statistics count it, while intact tables keep being verified
+#[test]
+fn scrub_job_reports_bit_rot_in_cold_tables() {
+    let dir = TempDir::new().unwrap();
+    let engine = StorageEngine::open(Options::new(dir.path())).unwrap();
+    for i in 0..2 {
+        engine.put(key(i), value(i)).unwrap();
+        engine.flush().unwrap();
+    }
+    engine.close().unwrap();
+
+    let tables: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+        .collect();
+    assert_eq!(tables.len(), 2);
+    let damaged = tables
+        .iter()
+        .find(|path| {
+            let file = std::fs::read(path).unwrap();
+            file.windows(value(1).len())
+                .any(|window| window == value(1))
+        })
+        .unwrap()
+        .clone();
+    let mut file = std::fs::read(&damaged).unwrap();
+    let at = file
+        .windows(value(1).len())
+        .position(|window| window == value(1))
+        .unwrap();
+    file[at] ^= 0x80;
+    std::fs::write(&damaged, &file).unwrap();
+
+    let (sender, reports) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    let engine = StorageEngine::open(
+        Options::new(dir.path())
+            .with_scrub_interval(Duration::from_millis(10))
+            .with_corruption_handler(move |table| {
+                sender.lock().unwrap().send(table.clone()).unwrap();
+            }),
+    )
+    .unwrap();
+
+    let report = reports.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(report.path, damaged);
+    assert_eq!(report.column_family, "default");
+    while engine.statistics().scrubbed_tables < 5 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    let stats = engine.statistics();
+    assert_eq!(stats.scrub_corrupt_tables, 1);
+    assert!(reports.try_recv().is_err());
+    assert_eq!(engine.get(&key(0)).unwrap(), Some(value(0)));
+}
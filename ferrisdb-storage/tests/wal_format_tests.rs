@@ -282,7 +282,10 @@ fn detects_corrupted_entry_checksum() {
     // Should succeed reading header but fail on entry
     assert!(result.is_err());
     let err = result.unwrap_err();
-    assert!(matches!(err, Error::Corruption(msg) if msg.contains("checksum")));
+    assert!(matches!(err.root(), Error::Corruption(msg) if msg.contains("checksum")));
+    let context = err.context().unwrap();
+    assert_eq!(context.path.as_deref(), Some(wal_path.as_path()));
+    assert_eq!(context.offset, Some(64));
 }
 
 /// Tests detection of corrupted length fields.
@@ -366,7 +369,7 @@ fn detects_corrupted_operation_type() {
     assert!(result.is_err());
     let err = result.unwrap_err();
     // Checksum catches the corruption before operation type validation
-    assert!(matches!(err, Error::Corruption(msg) if msg.contains("checksum")));
+    assert!(matches!(err.root(), Error::Corruption(msg) if msg.contains("checksum")));
 }
 
 // ==================== Truncation Tests ====================
@@ -419,9 +422,9 @@ fn detects_truncated_entry_at_critical_points() {
         ("checksum_field", 6),                   // Middle of checksum
         ("timestamp_field", 12),                 // Middle of timestamp
         ("operation_field", 16),                 // At operation byte
-        ("key_length_field", 18),                // Middle of key length
-        ("value_length_field", 22),              // Middle of value length
-        ("key_data", 26),                        // Middle of key
+        ("key_length_field", 17),                // At key length varint
+        ("key_data", 22),                        // Middle of key
+        ("value_length_field", 26),              // At value length varint
         ("value_data", encoded_entry.len() - 3), // Near end of value
     ];
 
@@ -444,14 +447,13 @@ fn detects_truncated_entry_at_critical_points() {
         let result = reader.read_all();
 
         // Should either return empty (if truncation detected) or error
-        match result {
-            Ok(entries) => assert_eq!(
+        if let Ok(entries) = result {
+            assert_eq!(
                 entries.len(),
                 0,
                 "Truncation at {} should be detected",
                 name
-            ),
-            Err(_) => {} // Also acceptable
+            );
         }
     }
 }
@@ -544,7 +546,7 @@ fn rejects_future_version_with_clear_error() {
 
     // Create header with future version
     let mut header = WALHeader::new(12345);
-    header.version = 0x0200; // v2.0
+    header.version = 0x0300; // v3.0
 
     std::fs::write(&wal_path, header.encode()).unwrap();
 
@@ -555,7 +557,7 @@ fn rejects_future_version_with_clear_error() {
 /// Tests that all compatible WAL versions are accepted.
 ///
 /// Verifies:
-/// - Current version (v1.0) is accepted
+/// - Current version (v2.1) and the older v2.0 and v1.0 are accepted
 /// - Future minor versions would be accepted
 /// - Version checking is not too restrictive
 /// - Backward compatibility maintained
@@ -563,10 +565,11 @@ fn rejects_future_version_with_clear_error() {
 fn accepts_all_compatible_versions() {
     let temp_dir = TempDir::new().unwrap();
 
-    // Currently only v1.0 is supported, but test the range
     let compatible_versions = vec![
-        0x0100, // v1.0 - current
-               // Future: 0x0101, 0x0102, etc. would be compatible minor versions
+        0x0100, // v1.0 - fixed-width entry lengths
+        0x0200, // v2.0 - varint entry lengths
+        0x0201, // v2.1 - current
+        0x0202, // v2.2 - a future compatible minor version
     ];
 
     for version in compatible_versions {
@@ -587,19 +590,19 @@ fn accepts_all_compatible_versions() {
 /// Tests that headers are created with the correct version.
 ///
 /// Ensures:
-/// - New files use current version (0x0100)
+/// - New files use current version (0x0201)
 /// - Version field preserved through encoding
 /// - Consistent version across operations
 /// - Version metadata is accurate
 #[test]
 fn header_version_field_is_current_version() {
     let header = WALHeader::new(12345);
-    assert_eq!(header.version, 0x0100); // v1.0
+    assert_eq!(header.version, 0x0201); // v2.1
 
     // Verify version is preserved through encoding
     let encoded = header.encode();
     let decoded = WALHeader::decode(&encoded).unwrap();
-    assert_eq!(decoded.version, 0x0100);
+    assert_eq!(decoded.version, 0x0201);
 }
 
 // ==================== Additional Format Validation Tests ====================
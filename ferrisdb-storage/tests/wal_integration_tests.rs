@@ -1,5 +1,6 @@
 //! Integration tests for WAL components working together
 
+use ferrisdb_core::error::ErrorCode;
 use ferrisdb_core::SyncMode;
 use ferrisdb_storage::wal::{WALEntry, WALReader, WALWriter};
 
@@ -100,7 +101,7 @@ fn append_and_read_handle_entries_up_to_size_limits() {
     let wal_path = temp_dir.path().join("large.wal");
 
     // Test with various sizes
-    let test_sizes = vec![
+    let test_sizes = [
         (100, 1000),     // Small (100B key, 1KB value)
         (1024, 10240),   // Medium (1KB key, 10KB value)
         (5120, 51200),   // Large (5KB key, 50KB value)
@@ -245,7 +246,7 @@ fn append_returns_error_when_file_size_limit_exceeded() {
         match writer.append(&entry) {
             Ok(_) => written += 1,
             Err(e) => {
-                assert!(e.to_string().contains("size limit"));
+                assert_eq!(e.code(), ErrorCode::LimitExceeded, "{}", e);
                 break;
             }
         }
@@ -262,5 +263,5 @@ fn append_returns_error_when_file_size_limit_exceeded() {
     // The number of entries read should match what was successfully written
     // Note: If the last write partially succeeded, we might read fewer entries
     assert!(entries.len() <= written);
-    assert!(entries.len() > 0); // Should have at least some entries
+    assert!(!entries.is_empty()); // Should have at least some entries
 }
@@ -6,37 +6,11 @@
 use ferrisdb_core::{Error, Operation, SyncMode};
 use ferrisdb_storage::format::FileHeader;
 use ferrisdb_storage::wal::{WALEntry, WALHeader, WALReader, WALWriter};
+use ferrisdb_test_utils::strategies::{arbitrary_bytes, valid_key, valid_value};
 
 use proptest::prelude::*;
 use tempfile::TempDir;
 
-// ==================== Strategies ====================
-
-// Generate valid keys (0 to 10KB)
-prop_compose! {
-    fn valid_key()(size in 0usize..=10*1024) -> Vec<u8> {
-        vec![b'k'; size]
-    }
-}
-
-// Generate valid values (0 to 100KB)
-prop_compose! {
-    fn valid_value()(size in 0usize..=100*1024) -> Vec<u8> {
-        vec![b'v'; size]
-    }
-}
-
-// Generate arbitrary byte vectors for corruption testing
-prop_compose! {
-    fn arbitrary_bytes()(size in 0usize..10000) -> Vec<u8> {
-        let mut bytes = vec![0u8; size];
-        for (i, byte) in bytes.iter_mut().enumerate().take(size) {
-            *byte = (i % 256) as u8;
-        }
-        bytes
-    }
-}
-
 // ==================== Roundtrip Tests ====================
 
 proptest! {
@@ -0,0 +1,117 @@
+//! Storage engines in temporary directories
+
+use ferrisdb_storage::{Options, StorageEngine};
+
+use tempfile::TempDir;
+
+use std::fmt;
+use std::ops::Deref;
+use std::path::Path;
+
+/// A [`StorageEngine`] in a temporary directory
+///
+/// Dereferences to the engine, and deletes the directory when dropped,
+/// after closing the engine. [`reopen`](Self::reopen) closes the engine
+/// and opens the directory again with the same options, as a restart
+/// would.
+pub struct TempEngine {
+    // Declared first, so the engine closes before its directory goes
+    engine: Option<StorageEngine>,
+    options: Options,
+    dir: TempDir,
+}
+
+impl TempEngine {
+    /// Opens an engine with default options
+    ///
+    /// # Panics
+    ///
+    /// Panics if the directory cannot be created or the engine opened.
+    pub fn new() -> Self {
+        Self::with_options(|options| options)
+    }
+
+    /// Opens an engine with the options `configure` returns, given the
+    /// defaults for the temporary directory
+    ///
+    /// # Panics
+    ///
+    /// Panics if the directory cannot be created or the engine opened.
+    pub fn with_options(configure: impl FnOnce(Options) -> Options) -> Self {
+        let dir = TempDir::new().expect("create temporary directory");
+        let options = configure(Options::new(dir.path()));
+        let engine = StorageEngine::open(options.clone()).expect("open storage engine");
+        Self {
+            engine: Some(engine),
+            options,
+            dir,
+        }
+    }
+
+    /// Returns the directory the engine lives in
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Returns the options the engine is opened with
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Closes the engine and opens it again
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine fails to close or to open again.
+    pub fn reopen(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            engine.close().expect("close storage engine");
+        }
+        self.engine =
+            Some(StorageEngine::open(self.options.clone()).expect("reopen storage engine"));
+    }
+}
+
+impl Default for TempEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for TempEngine {
+    type Target = StorageEngine;
+
+    fn deref(&self) -> &StorageEngine {
+        self.engine.as_ref().expect("engine is open")
+    }
+}
+
+impl fmt::Debug for TempEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TempEngine")
+            .field("path", &self.dir.path())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entries::{key, value};
+
+    #[test]
+    fn test_reopen_keeps_data_and_options() {
+        let mut engine = TempEngine::with_options(|options| options.with_memtable_size(4096));
+        for i in 0..200 {
+            engine.put(key(i), value(i)).unwrap();
+        }
+        engine.reopen();
+        for i in 0..200 {
+            assert_eq!(engine.get(&key(i)).unwrap(), Some(value(i)));
+        }
+
+        let path = engine.path().to_path_buf();
+        drop(engine);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,78 @@
+//! Factories for keys, values and log and table entries
+//!
+//! Numbered keys are zero-padded, so they sort in the same order as their
+//! numbers, and every factory derives its output from its arguments alone:
+//! two calls with the same number always build equal data.
+
+use ferrisdb_core::{Key, Operation, Timestamp, Value};
+use ferrisdb_storage::sstable::{InternalKey, SSTableEntry};
+use ferrisdb_storage::wal::WALEntry;
+
+/// Returns the key numbered `i`, such as `key00042`
+pub fn key(i: usize) -> Key {
+    format!("key{:05}", i).into_bytes()
+}
+
+/// Returns the value numbered `i`, such as `value00042`
+pub fn value(i: usize) -> Value {
+    format!("value{:05}", i).into_bytes()
+}
+
+/// Returns a put of key `timestamp` with a value of a length that varies
+/// with it, up to 96 bytes
+pub fn wal_put(timestamp: Timestamp) -> WALEntry {
+    let size = (timestamp % 97) as usize;
+    WALEntry::new_put(
+        key(timestamp as usize),
+        vec![timestamp as u8; size],
+        timestamp,
+    )
+    .expect("entry within size limits")
+}
+
+/// Returns puts for each timestamp in `timestamps`, as built by
+/// [`wal_put`]
+pub fn wal_puts(timestamps: impl IntoIterator<Item = Timestamp>) -> Vec<WALEntry> {
+    timestamps.into_iter().map(wal_put).collect()
+}
+
+/// Returns an SSTable entry for version `timestamp` of `key`
+pub fn sstable_entry(
+    key: &[u8],
+    timestamp: Timestamp,
+    value: &[u8],
+    operation: Operation,
+) -> SSTableEntry {
+    SSTableEntry::new(
+        InternalKey::new(key.to_vec(), timestamp),
+        value.to_vec(),
+        operation,
+    )
+}
+
+/// Returns puts of the keys numbered `0..count` at timestamp 1, in table
+/// order
+pub fn sstable_puts(count: usize) -> Vec<SSTableEntry> {
+    (0..count)
+        .map(|i| sstable_entry(&key(i), 1, &value(i), Operation::Put))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factories_are_deterministic_and_sorted() {
+        assert_eq!(key(42), b"key00042");
+        assert!(key(9) < key(10));
+        assert_eq!(wal_put(5), wal_put(5));
+        assert_eq!(wal_puts(1..=3)[2].timestamp, 3);
+
+        let entries = sstable_puts(20);
+        assert!(entries
+            .windows(2)
+            .all(|pair| pair[0].key.user_key < pair[1].key.user_key));
+        assert_eq!(entries[3].value, value(3));
+    }
+}
@@ -0,0 +1,39 @@
+//! Shared helpers for testing FerrisDB
+//!
+//! Test files across the workspace kept copies of the same strategies and
+//! fixtures; this crate holds one of each for them, downstream crates and
+//! the tutorials:
+//!
+//! - [`strategies`]: proptest strategies for keys, values, WAL entries and
+//!   corruption of encoded data
+//! - [`engine`]: [`TempEngine`], a storage engine in a temporary directory
+//!   that can be reopened and is deleted when dropped
+//! - [`entries`]: factories for numbered keys and values, and WAL and
+//!   SSTable entries
+//!
+//! Add it as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! ferrisdb-test-utils = { path = "../ferrisdb-test-utils" }
+//! ```
+//!
+//! # Example
+//!
+//! ```
+//! use ferrisdb_test_utils::entries::{key, value};
+//! use ferrisdb_test_utils::TempEngine;
+//!
+//! let mut engine = TempEngine::with_options(|options| options.with_memtable_size(4096));
+//! for i in 0..100 {
+//!     engine.put(key(i), value(i)).unwrap();
+//! }
+//! engine.reopen();
+//! assert_eq!(engine.get(&key(7)).unwrap(), Some(value(7)));
+//! ```
+
+pub mod engine;
+pub mod entries;
+pub mod strategies;
+
+pub use engine::TempEngine;
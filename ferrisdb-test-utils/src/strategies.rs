@@ -0,0 +1,173 @@
+//! Proptest strategies for FerrisDB data
+//!
+//! Keys and values span the full range the WAL accepts, and
+//! [`corruption`] damages encoded data the ways disks and torn writes do,
+//! for checking that decoders reject it instead of panicking.
+//!
+//! ```
+//! use ferrisdb_storage::wal::WALEntry;
+//! use ferrisdb_test_utils::strategies::{corruption, wal_entry};
+//! use proptest::prelude::*;
+//!
+//! proptest!(|(entry in wal_entry(), damage in corruption())| {
+//!     let mut encoded = entry.encode().unwrap();
+//!     damage.apply(&mut encoded);
+//!     let _ = WALEntry::decode(&encoded);
+//! });
+//! ```
+
+use ferrisdb_core::{Operation, Timestamp};
+use ferrisdb_storage::wal::WALEntry;
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+/// Largest key a WAL entry accepts
+pub const MAX_KEY_SIZE: usize = 10 * 1024;
+
+/// Largest value a WAL entry accepts
+pub const MAX_VALUE_SIZE: usize = 100 * 1024;
+
+prop_compose! {
+    /// Generates keys of every valid size, from empty to [`MAX_KEY_SIZE`]
+    pub fn valid_key()(size in 0usize..=MAX_KEY_SIZE) -> Vec<u8> {
+        vec![b'k'; size]
+    }
+}
+
+prop_compose! {
+    /// Generates values of every valid size, from empty to
+    /// [`MAX_VALUE_SIZE`]
+    pub fn valid_value()(size in 0usize..=MAX_VALUE_SIZE) -> Vec<u8> {
+        vec![b'v'; size]
+    }
+}
+
+prop_compose! {
+    /// Generates byte strings of up to 10,000 bytes for decoders to reject
+    pub fn arbitrary_bytes()(size in 0usize..10000) -> Vec<u8> {
+        (0..size).map(|i| (i % 256) as u8).collect()
+    }
+}
+
+/// Generates every operation
+pub fn operation() -> impl Strategy<Value = Operation> {
+    prop_oneof![
+        Just(Operation::Put),
+        Just(Operation::Delete),
+        Just(Operation::Merge),
+    ]
+}
+
+prop_compose! {
+    /// Generates WAL entries of every operation, with short arbitrary keys
+    /// and values, and a column family other than the default in a
+    /// quarter of them
+    pub fn wal_entry()(
+        operation in operation(),
+        key in prop::collection::vec(any::<u8>(), 0..64),
+        value in prop::collection::vec(any::<u8>(), 0..256),
+        timestamp in any::<Timestamp>(),
+        column_family in prop_oneof![3 => Just(0u32), 1 => 1u32..16],
+    ) -> WALEntry {
+        let entry = match operation {
+            Operation::Put => WALEntry::new_put(key, value, timestamp),
+            Operation::Delete => WALEntry::new_delete(key, timestamp),
+            Operation::Merge => WALEntry::new_merge(key, value, timestamp),
+        };
+        entry.expect("entry within size limits").with_column_family(column_family)
+    }
+}
+
+/// A way to damage encoded data
+///
+/// Positions are drawn as [`Index`]es, so the same corruption applies to
+/// data of any length.
+#[derive(Debug, Clone)]
+pub enum Corruption {
+    /// Flips one bit, as a failing disk might
+    FlipBit { at: Index, bit: u8 },
+    /// Sets one byte to an arbitrary value
+    Overwrite { at: Index, byte: u8 },
+    /// Zeroes up to `len` bytes, as a lost sector reads back
+    Zero { at: Index, len: usize },
+    /// Cuts the data short, as a torn write leaves it
+    Truncate { at: Index },
+    /// Appends bytes, as garbage after a crash would
+    Append(Vec<u8>),
+}
+
+impl Corruption {
+    /// Applies the corruption to `data`
+    ///
+    /// Flipping a bit and truncating always change non-empty data; the
+    /// others may happen to write what was already there.
+    pub fn apply(&self, data: &mut Vec<u8>) {
+        if data.is_empty() {
+            if let Self::Append(bytes) = self {
+                data.extend_from_slice(bytes);
+            }
+            return;
+        }
+        let len = data.len();
+        match self {
+            Self::FlipBit { at, bit } => data[at.index(len)] ^= 1 << (bit % 8),
+            Self::Overwrite { at, byte } => data[at.index(len)] = *byte,
+            Self::Zero { at, len: zeroed } => {
+                let start = at.index(len);
+                data[start..(start + zeroed).min(len)].fill(0);
+            }
+            Self::Truncate { at } => data.truncate(at.index(len)),
+            Self::Append(bytes) => data.extend_from_slice(bytes),
+        }
+    }
+}
+
+/// Generates every kind of [`Corruption`]
+pub fn corruption() -> impl Strategy<Value = Corruption> {
+    prop_oneof![
+        (any::<Index>(), 0u8..8).prop_map(|(at, bit)| Corruption::FlipBit { at, bit }),
+        (any::<Index>(), any::<u8>()).prop_map(|(at, byte)| Corruption::Overwrite { at, byte }),
+        (any::<Index>(), 1usize..512).prop_map(|(at, len)| Corruption::Zero { at, len }),
+        any::<Index>().prop_map(|at| Corruption::Truncate { at }),
+        prop::collection::vec(any::<u8>(), 1..64).prop_map(Corruption::Append),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ferrisdb_core::Error;
+
+    proptest! {
+        #[test]
+        fn test_corrupted_wal_entries_fail_to_decode(
+            entry in wal_entry(),
+            damage in corruption(),
+        ) {
+            let original = entry.encode().unwrap();
+            let mut encoded = original.clone();
+            damage.apply(&mut encoded);
+            match WALEntry::decode(&encoded) {
+                Ok(decoded) => {
+                    prop_assert_eq!(&encoded, &original);
+                    prop_assert_eq!(decoded, entry);
+                }
+                Err(e) => prop_assert!(matches!(e, Error::Corruption(_))),
+            }
+        }
+
+        #[test]
+        fn test_flip_and_truncate_always_change_data(
+            data in prop::collection::vec(any::<u8>(), 1..100),
+            at in any::<Index>(),
+            bit in 0u8..8,
+        ) {
+            for damage in [Corruption::FlipBit { at, bit }, Corruption::Truncate { at }] {
+                let mut damaged = data.clone();
+                damage.apply(&mut damaged);
+                prop_assert_ne!(&damaged, &data, "{:?}", damage);
+            }
+        }
+    }
+}
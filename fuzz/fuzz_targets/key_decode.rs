@@ -0,0 +1,23 @@
+//! Decodes arbitrary bytes as order-preserving keys of every type
+//!
+//! A key that decodes must encode back to the same bytes, since the
+//! encoding is canonical.
+
+#![no_main]
+
+use ferrisdb_core::keys::{self, KeyDecode, KeyEncode};
+use libfuzzer_sys::fuzz_target;
+
+fn round_trip<T: KeyDecode + KeyEncode>(data: &[u8]) {
+    if let Ok(value) = keys::decode::<T>(data) {
+        assert_eq!(keys::encode(&value), data);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    round_trip::<u64>(data);
+    round_trip::<i64>(data);
+    round_trip::<f64>(data);
+    round_trip::<Vec<u8>>(data);
+    round_trip::<String>(data);
+});
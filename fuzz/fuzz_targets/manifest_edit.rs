@@ -0,0 +1,12 @@
+//! Decodes arbitrary bytes as a MANIFEST version edit
+
+#![no_main]
+
+use ferrisdb_storage::manifest::VersionEdit;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(edit) = VersionEdit::decode(data) {
+        assert_eq!(VersionEdit::decode(&edit.encode()).unwrap(), edit);
+    }
+});
@@ -0,0 +1,13 @@
+//! Decodes arbitrary bytes as a MANIFEST file header
+
+#![no_main]
+
+use ferrisdb_storage::format::FileHeader;
+use ferrisdb_storage::manifest::ManifestHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = ManifestHeader::decode(data) {
+        assert_eq!(ManifestHeader::decode(&header.encode()).unwrap(), header);
+    }
+});
@@ -0,0 +1,24 @@
+//! Decodes arbitrary bytes as SSTable data and index blocks
+//!
+//! The first byte picks the table format version the block is decoded
+//! for; the rest is the block.
+
+#![no_main]
+
+use ferrisdb_storage::sstable::{
+    decode_data_block, decode_index_block, encode_data_block, FORMAT_VERSION,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, block)) = data.split_first() else {
+        return;
+    };
+    let format_version = u32::from(selector) % FORMAT_VERSION + 1;
+
+    if let Ok(entries) = decode_data_block(block, format_version) {
+        let encoded = encode_data_block(&entries);
+        assert_eq!(decode_data_block(&encoded, FORMAT_VERSION).unwrap(), entries);
+    }
+    let _ = decode_index_block(block, format_version);
+});
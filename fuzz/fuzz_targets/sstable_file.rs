@@ -0,0 +1,38 @@
+//! Opens arbitrary bytes as an SSTable and reads everything in it
+//!
+//! The file lives in a simulated filesystem, so the whole reader runs
+//! without touching the disk.
+
+#![no_main]
+
+use ferrisdb_storage::comparator;
+use ferrisdb_storage::sstable::SSTableReader;
+use ferrisdb_storage::vfs::{SimVfs, Vfs};
+use libfuzzer_sys::fuzz_target;
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+fuzz_target!(|data: &[u8]| {
+    let vfs: Arc<dyn Vfs> = Arc::new(SimVfs::new(0));
+    let path = Path::new("/db/000001.sst");
+    vfs.create_dir_all(Path::new("/db")).unwrap();
+    vfs.create(path).unwrap().write_all(data).unwrap();
+
+    let Ok(mut reader) = SSTableReader::open_in(&vfs, path, comparator::bytewise()) else {
+        return;
+    };
+    let mut keys = Vec::new();
+    if let Ok(iter) = reader.iter() {
+        for entry in iter {
+            match entry {
+                Ok(entry) => keys.push(entry.key.user_key),
+                Err(_) => break,
+            }
+        }
+    }
+    for key in keys.iter().take(16) {
+        let _ = reader.get_latest(key, u64::MAX);
+    }
+});
@@ -0,0 +1,13 @@
+//! Decodes arbitrary bytes as an SSTable footer
+
+#![no_main]
+
+use ferrisdb_storage::sstable::Footer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(footer) = Footer::from_bytes(data) {
+        let encoded = footer.to_bytes();
+        assert_eq!(Footer::from_bytes(&encoded).unwrap().to_bytes(), encoded);
+    }
+});
@@ -0,0 +1,13 @@
+//! Decodes arbitrary bytes as an SSTable properties block
+
+#![no_main]
+
+use ferrisdb_storage::sstable::TableProperties;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(properties) = TableProperties::from_bytes(data) {
+        let encoded = properties.to_bytes();
+        assert_eq!(TableProperties::from_bytes(&encoded).unwrap(), properties);
+    }
+});
@@ -0,0 +1,24 @@
+//! Decodes arbitrary bytes as a WAL batch record in every format version
+//!
+//! A batch that decodes must hold consecutive timestamps and survive a
+//! round trip through the current encoding.
+
+#![no_main]
+
+use ferrisdb_storage::wal::{WALEntry, WAL_CURRENT_VERSION};
+use libfuzzer_sys::fuzz_target;
+
+/// Format versions readers still accept
+const VERSIONS: [u16; 2] = [0x0100, WAL_CURRENT_VERSION];
+
+fuzz_target!(|data: &[u8]| {
+    for version in VERSIONS {
+        if let Ok(entries) = WALEntry::decode_batch_version(data, version) {
+            if entries.len() < 2 {
+                continue;
+            }
+            let encoded = WALEntry::encode_batch(&entries).expect("decoded batch re-encodes");
+            assert_eq!(WALEntry::decode_batch(&encoded).unwrap(), entries);
+        }
+    }
+});
@@ -0,0 +1,27 @@
+//! Reads arbitrary bytes as a WAL segment, as recovery would
+//!
+//! The file lives in a simulated filesystem, so the whole reader runs
+//! without touching the disk.
+
+#![no_main]
+
+use ferrisdb_storage::vfs::{SimVfs, Vfs};
+use ferrisdb_storage::wal::WALReader;
+use libfuzzer_sys::fuzz_target;
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+fuzz_target!(|data: &[u8]| {
+    let vfs: Arc<dyn Vfs> = Arc::new(SimVfs::new(0));
+    let path = Path::new("/db/000001.log");
+    vfs.create_dir_all(Path::new("/db")).unwrap();
+    vfs.create(path).unwrap().write_all(data).unwrap();
+
+    let Ok(mut reader) = WALReader::new_in(&vfs, path) else {
+        return;
+    };
+    while let Ok(Some(_)) = reader.read_entry() {}
+    assert!(reader.valid_len() <= data.len() as u64);
+});
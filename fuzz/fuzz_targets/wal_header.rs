@@ -0,0 +1,13 @@
+//! Decodes arbitrary bytes as a WAL file header
+
+#![no_main]
+
+use ferrisdb_storage::format::FileHeader;
+use ferrisdb_storage::wal::WALHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = WALHeader::decode(data) {
+        assert_eq!(WALHeader::decode(&header.encode()).unwrap(), header);
+    }
+});
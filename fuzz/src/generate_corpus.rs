@@ -0,0 +1,146 @@
+//! Writes seed inputs for every fuzz target into `corpus/<target>/`
+//!
+//! Seeds are produced by the encoders themselves, so the fuzzer starts
+//! from valid files and records and mutates its way into corrupted ones
+//! instead of having to discover the formats from scratch. Rerun after a
+//! format change:
+//!
+//! ```text
+//! cargo run --bin generate_corpus
+//! ```
+
+use ferrisdb_core::keys;
+use ferrisdb_core::{Operation, Result, SyncMode};
+use ferrisdb_storage::comparator;
+use ferrisdb_storage::format::FileHeader;
+use ferrisdb_storage::manifest::{ManifestHeader, SSTableMeta, VersionEdit};
+use ferrisdb_storage::sstable::{
+    encode_data_block, encode_index_block, Footer, IndexEntry, InternalKey, SSTableEntry,
+    SSTableWriter, TableProperties,
+};
+use ferrisdb_storage::vfs::{SimVfs, Vfs};
+use ferrisdb_storage::wal::{WALEntry, WALHeader, WALWriter};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn main() -> Result<()> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+    let mut seeds = Seeds {
+        corpus,
+        per_target: BTreeMap::new(),
+    };
+
+    let entries = [
+        WALEntry::new_put(b"key".to_vec(), b"value".to_vec(), 1)?,
+        WALEntry::new_delete(b"key".to_vec(), 2)?,
+        WALEntry::new_merge(b"counter".to_vec(), 5u64.to_le_bytes().to_vec(), 3)?,
+        WALEntry::new_put(vec![0; 200], vec![0xAB; 1000], 4)?,
+    ];
+    for entry in &entries {
+        seeds.add("wal_entry", entry.encode()?)?;
+    }
+    seeds.add("wal_batch", WALEntry::encode_batch(&entries[..2])?)?;
+    seeds.add("wal_batch", WALEntry::encode_batch(&entries)?)?;
+    seeds.add(
+        "wal_header",
+        WALHeader::new(7).with_previous_timestamp(3).encode(),
+    )?;
+
+    let sim = Arc::new(SimVfs::new(0));
+    let vfs: Arc<dyn Vfs> = sim.clone();
+    let wal_path = Path::new("/000001.log");
+    let writer = WALWriter::new_in(&vfs, wal_path, SyncMode::Full, 1 << 20, 0)?;
+    writer.append(&entries[0])?;
+    writer.append_batch(&entries[1..])?;
+    drop(writer);
+    seeds.add("wal_file", sim.contents(wal_path).expect("WAL written"))?;
+
+    let table: Vec<_> = (0..50u64)
+        .map(|i| {
+            let operation = match i % 3 {
+                0 => Operation::Put,
+                1 => Operation::Delete,
+                _ => Operation::Merge,
+            };
+            let key = InternalKey::new(format!("key{:03}", i / 2).into_bytes(), 100 - i);
+            SSTableEntry::new(key, vec![i as u8; i as usize], operation)
+        })
+        .collect();
+    let table_path = Path::new("/000002.sst");
+    let mut writer = SSTableWriter::new_in(&vfs, table_path)?;
+    for entry in &table {
+        writer.add(entry.key.clone(), entry.value.clone(), entry.operation)?;
+    }
+    let info = writer.finish()?;
+    seeds.add("sstable_file", sim.contents(table_path).expect("table written"))?;
+
+    // The block target reads its format version from the first byte
+    for selector in [0u8, 1] {
+        let mut data_block = vec![selector];
+        data_block.extend(encode_data_block(&table[..10]));
+        seeds.add("sstable_block", data_block)?;
+    }
+    let mut index_block = vec![1];
+    index_block.extend(encode_index_block(&[
+        IndexEntry::new(0, b"key000".to_vec()),
+        IndexEntry::new(4096, b"key013".to_vec()),
+    ]));
+    seeds.add("sstable_block", index_block)?;
+    seeds.add(
+        "sstable_footer",
+        Footer::new(4096, 120, 4216, 0).to_bytes().to_vec(),
+    )?;
+    seeds.add(
+        "sstable_properties",
+        TableProperties::new(&*comparator::bytewise()).to_bytes(),
+    )?;
+
+    seeds.add("manifest_header", ManifestHeader::new(1).encode())?;
+    let mut edit = VersionEdit::default();
+    edit.set_log_number(3);
+    edit.set_next_file_number(9);
+    edit.set_last_timestamp(100);
+    edit.add_file(0, SSTableMeta::new(2, &info));
+    edit.delete_file(1, 1);
+    edit.add_column_family(1, "users");
+    edit.drop_column_family(2);
+    edit.set_next_column_family_id(3);
+    seeds.add("manifest_edit", edit.encode())?;
+    seeds.add("manifest_edit", VersionEdit::default().encode())?;
+
+    seeds.add("key_decode", keys::encode(&42u64))?;
+    seeds.add("key_decode", keys::encode(&-1.5f64))?;
+    seeds.add("key_decode", keys::encode(&b"with\0zero".to_vec()))?;
+    seeds.add("key_decode", keys::encode("text"))?;
+
+    println!("Wrote {} seeds to {}", seeds.count(), seeds.corpus.display());
+    Ok(())
+}
+
+/// Writes seeds, each into its target's corpus directory
+///
+/// Seeds are numbered per target in the order they are generated, so
+/// rerunning the generator overwrites its own seeds and nothing the fuzzer
+/// added.
+struct Seeds {
+    corpus: PathBuf,
+    per_target: BTreeMap<&'static str, usize>,
+}
+
+impl Seeds {
+    fn add(&mut self, target: &'static str, bytes: Vec<u8>) -> Result<()> {
+        let dir = self.corpus.join(target);
+        fs::create_dir_all(&dir)?;
+        let index = self.per_target.entry(target).or_default();
+        fs::write(dir.join(format!("seed-{:02}", index)), bytes)?;
+        *index += 1;
+        Ok(())
+    }
+
+    fn count(&self) -> usize {
+        self.per_target.values().sum()
+    }
+}